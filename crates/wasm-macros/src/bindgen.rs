@@ -0,0 +1,392 @@
+//! `#[wasm::bindgen]`: wasm-bindgen's attribute on top of `#[wasm::import]`
+//!
+//! Extern blocks are expanded item by item: types become newtypes of
+//! `wasm::host::bindgen::JsValue`, and functions become imports of the
+//! `wasmrust` calls the JS glue implements, wrapped in associated
+//! functions for methods, constructors, and static methods. Exported
+//! functions go through `#[wasm::export]`. Both classify their types with
+//! `Interop::classify_object`, so every other type is taken for a
+//! JavaScript object and must implement `JsCast`.
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, ToTokens};
+use syn::ext::IdentExt;
+use syn::meta::ParseNestedMeta;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    bracketed, Attribute, Error, FnArg, ForeignItem, ForeignItemFn, ForeignItemType, Ident, Item, ItemFn,
+    ItemForeignMod, LitStr, Meta, Pat, Path, ReturnType, Token, Type,
+};
+
+use crate::{expand_export, expand_import, Interop};
+
+/// Import module of the calls the JS glue implements, as in `wasm::wasmir::JS_IMPORT_MODULE`
+const JS_IMPORT_MODULE: &str = "wasmrust";
+
+/// Options of one `#[wasm_bindgen(...)]`, merged with those of its extern block
+#[derive(Default, Clone)]
+struct Options {
+    js_namespace: Vec<String>,
+    js_name: Option<String>,
+    js_class: Option<String>,
+    module: Option<String>,
+    extends: Vec<Path>,
+    static_method_of: Option<Path>,
+    method: bool,
+    constructor: bool,
+    getter: Option<Option<String>>,
+    setter: Option<Option<String>>,
+}
+
+impl Options {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        let Some(key) = meta.path.get_ident().map(Ident::to_string) else {
+            return Err(meta.error("unknown wasm_bindgen option"));
+        };
+        match key.as_str() {
+            "js_namespace" => {
+                let value = meta.value()?;
+                self.js_namespace = if value.peek(syn::token::Bracket) {
+                    let content;
+                    bracketed!(content in value);
+                    let names = Punctuated::<JsName, Token![,]>::parse_terminated(&content)?;
+                    names.into_iter().map(|name| name.0).collect()
+                } else {
+                    vec![value.parse::<JsName>()?.0]
+                };
+            }
+            "js_name" => self.js_name = Some(meta.value()?.parse::<JsName>()?.0),
+            "js_class" => self.js_class = Some(meta.value()?.parse::<JsName>()?.0),
+            "module" => self.module = Some(meta.value()?.parse::<LitStr>()?.value()),
+            "extends" => self.extends.push(meta.value()?.parse()?),
+            "static_method_of" => self.static_method_of = Some(meta.value()?.parse()?),
+            "method" => self.method = true,
+            "constructor" => self.constructor = true,
+            "getter" | "setter" => {
+                let name = if meta.input.peek(Token![=]) { Some(meta.value()?.parse::<JsName>()?.0) } else { None };
+                if key == "getter" {
+                    self.getter = Some(name);
+                } else {
+                    self.setter = Some(name);
+                }
+            }
+            // Hints for wasm-bindgen's own glue and TypeScript output
+            "structural" | "final" | "skip_typescript" | "skip_jsdoc" => {}
+            "typescript_type" => {
+                meta.value()?.parse::<LitStr>()?;
+            }
+            "catch" | "variadic" | "start" | "inline_js" | "raw_module" | "thread_local" | "thread_local_v2"
+            | "indexing_getter" | "indexing_setter" | "indexing_deleter" | "vendor_prefix" | "is_type_of" => {
+                return Err(meta.error(format!("`{}` is not supported by #[wasm::bindgen]", key)));
+            }
+            _ => return Err(meta.error(format!("unknown wasm_bindgen option `{}`", key))),
+        }
+        Ok(())
+    }
+
+    /// Takes the options out of `#[wasm_bindgen]` attributes, leaving the others
+    fn take(&self, attrs: &mut Vec<Attribute>) -> syn::Result<Options> {
+        let mut options = self.clone();
+        let mut kept = Vec::new();
+        for attr in attrs.drain(..) {
+            let name = attr.path().segments.last().map(|segment| segment.ident.to_string());
+            if !matches!(name.as_deref(), Some("wasm_bindgen" | "bindgen")) {
+                kept.push(attr);
+                continue;
+            }
+            if let Meta::List(_) = attr.meta {
+                attr.parse_nested_meta(|meta| options.parse(meta))?;
+            }
+        }
+        *attrs = kept;
+        Ok(options)
+    }
+
+    /// Dotted path of a global under `js_namespace`
+    fn global(&self, name: &str) -> String {
+        self.js_namespace.iter().map(String::as_str).chain([name]).collect::<Vec<_>>().join(".")
+    }
+}
+
+/// JavaScript name written as an identifier or a string
+struct JsName(String);
+
+impl syn::parse::Parse for JsName {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        if input.peek(LitStr) {
+            Ok(JsName(input.parse::<LitStr>()?.value()))
+        } else {
+            Ok(JsName(Ident::parse_any(input)?.unraw().to_string()))
+        }
+    }
+}
+
+pub(crate) fn expand_bindgen(attr: TokenStream2, item: Item) -> syn::Result<TokenStream2> {
+    let mut options = Options::default();
+    syn::meta::parser(|meta| options.parse(meta)).parse2(attr)?;
+    match item {
+        Item::ForeignMod(block) => expand_block(&options, block),
+        Item::Fn(function) => expand_function(options, function),
+        Item::Struct(_) | Item::Enum(_) | Item::Impl(_) => {
+            Err(Error::new_spanned(item, "exported types are not supported; export functions instead"))
+        }
+        other => Err(Error::new_spanned(other, "expected an extern block or a function")),
+    }
+}
+
+/// Exports a function under its `js_name`
+fn expand_function(options: Options, mut function: ItemFn) -> syn::Result<TokenStream2> {
+    let options = options.take(&mut function.attrs)?;
+    if options.method || options.constructor || options.module.is_some() || !options.js_namespace.is_empty() {
+        return Err(Error::new_spanned(&function.sig.ident, "exported functions only take `js_name`"));
+    }
+    let name = options.js_name.map(|name| LitStr::new(&name, function.sig.ident.span()));
+    expand_export(name, &function, Interop::classify_object)
+}
+
+fn expand_block(options: &Options, mut block: ItemForeignMod) -> syn::Result<TokenStream2> {
+    let options = options.take(&mut block.attrs)?;
+    if let Some(attr) = block.attrs.first() {
+        return Err(Error::new_spanned(attr, "extern blocks only take #[wasm_bindgen] attributes"));
+    }
+    let mut out = TokenStream2::new();
+    for item in block.items {
+        let expanded = match item {
+            ForeignItem::Type(ty) => expand_type(&options, ty)?,
+            ForeignItem::Fn(function) => expand_import_fn(&options, function)?,
+            other => return Err(Error::new_spanned(other, "only types and functions can be imported")),
+        };
+        out.extend(expanded);
+    }
+    Ok(out)
+}
+
+/// Newtype of `JsValue` dereferencing to the type it extends
+fn expand_type(options: &Options, mut ty: ForeignItemType) -> syn::Result<TokenStream2> {
+    let options = options.take(&mut ty.attrs)?;
+    if !ty.generics.params.is_empty() {
+        return Err(Error::new_spanned(&ty.generics, "imported types cannot be generic"));
+    }
+    let ForeignItemType { attrs, vis, ident, .. } = &ty;
+    let js_value = quote!(::wasm::host::bindgen::JsValue);
+    let js_cast = quote!(::wasm::host::bindgen::JsCast);
+    let extends: Vec<&Path> = options.extends.iter().filter(|path| !path.is_ident("JsValue")).collect();
+    let target = extends.first().map_or_else(|| js_value.clone(), |path| quote!(#path));
+    Ok(quote! {
+        #(#attrs)*
+        #[repr(transparent)]
+        #[derive(Debug, Clone)]
+        #vis struct #ident {
+            obj: #js_value,
+        }
+
+        impl #js_cast for #ident {
+            fn unchecked_from_js(value: #js_value) -> Self {
+                #ident { obj: value }
+            }
+
+            fn unchecked_from_js_ref(value: &#js_value) -> &Self {
+                // SAFETY: the type is a transparent wrapper of `JsValue`
+                unsafe { &*(value as *const #js_value as *const #ident) }
+            }
+        }
+
+        impl ::core::convert::AsRef<#js_value> for #ident {
+            fn as_ref(&self) -> &#js_value {
+                &self.obj
+            }
+        }
+
+        impl ::core::convert::From<#ident> for #js_value {
+            fn from(value: #ident) -> Self {
+                value.obj
+            }
+        }
+
+        impl ::core::ops::Deref for #ident {
+            type Target = #target;
+
+            fn deref(&self) -> &#target {
+                #js_cast::unchecked_ref(&self.obj)
+            }
+        }
+
+        #(
+            impl ::core::convert::AsRef<#extends> for #ident {
+                fn as_ref(&self) -> &#extends {
+                    #js_cast::unchecked_ref(&self.obj)
+                }
+            }
+
+            impl ::core::convert::From<#ident> for #extends {
+                fn from(value: #ident) -> Self {
+                    #js_cast::unchecked_into(value.obj)
+                }
+            }
+        )*
+    })
+}
+
+/// How an imported function is called and where it lives in Rust
+enum Kind {
+    /// Free function
+    Free,
+    /// Method of the type of `this`, taking it as `&self` or `self`
+    Method { class: Type, borrowed: bool },
+    /// Constructor or static method of the type
+    Associated { class: Path },
+}
+
+fn expand_import_fn(options: &Options, mut function: ForeignItemFn) -> syn::Result<TokenStream2> {
+    let options = options.take(&mut function.attrs)?;
+    let ident = &function.sig.ident;
+    let js_name = options.js_name.clone().unwrap_or_else(|| ident.unraw().to_string());
+    let in_module = |what: &str| {
+        let message = format!("{} of `module` imports are not supported; import a function instead", what);
+        Error::new_spanned(ident, message)
+    };
+
+    let (module, name, kind) = if options.method {
+        let (class, borrowed) = receiver(&function)?;
+        let name = if let Some(getter) = &options.getter {
+            format!("js_get:{}", getter.clone().unwrap_or(js_name))
+        } else if let Some(setter) = &options.setter {
+            let field = ident.unraw().to_string();
+            let field = field.strip_prefix("set_").map(str::to_string).unwrap_or(field);
+            format!("js_set:{}", setter.clone().or_else(|| options.js_name.clone()).unwrap_or(field))
+        } else {
+            format!("js_call:{}", js_name)
+        };
+        (JS_IMPORT_MODULE.to_string(), name, Kind::Method { class, borrowed })
+    } else if options.constructor {
+        if options.module.is_some() {
+            return Err(in_module("constructors"));
+        }
+        let class = constructed(&function)?;
+        let js_class = options.js_class.clone().unwrap_or_else(|| class.segments.last().unwrap().ident.to_string());
+        (JS_IMPORT_MODULE.to_string(), format!("js_new:{}", options.global(&js_class)), Kind::Associated { class })
+    } else if let Some(class) = options.static_method_of.clone() {
+        if options.module.is_some() {
+            return Err(in_module("static methods"));
+        }
+        let js_class = options.js_class.clone().unwrap_or_else(|| class.segments.last().unwrap().ident.to_string());
+        let path = options.global(&format!("{}.{}", js_class, js_name));
+        (JS_IMPORT_MODULE.to_string(), format!("js_global:{}", path), Kind::Associated { class })
+    } else if let Some(module) = &options.module {
+        (module.clone(), options.global(&js_name), Kind::Free)
+    } else {
+        (JS_IMPORT_MODULE.to_string(), format!("js_global:{}", options.global(&js_name)), Kind::Free)
+    };
+    let names: Punctuated<LitStr, Token![,]> =
+        [module, name].iter().map(|name| LitStr::new(name, Span::call_site())).collect();
+
+    let class = match &kind {
+        Kind::Free => return expand_import(&names, &function, Interop::classify_object),
+        Kind::Method { class, .. } => quote!(#class),
+        Kind::Associated { class } => quote!(#class),
+    };
+    let class_name = class.to_string().rsplit(' ').next().unwrap_or_default().to_lowercase();
+    let wrapper = format_ident!("__wasm_bindgen_{}_{}", class_name, ident.unraw());
+    let mut raw = function.clone();
+    raw.attrs = vec![syn::parse_quote!(#[doc(hidden)])];
+    raw.vis = syn::Visibility::Inherited;
+    raw.sig.ident = wrapper.clone();
+    let import = expand_import(&names, &raw, Interop::classify_object)?;
+
+    let mut signature = function.sig.clone();
+    let mut arguments = Vec::new();
+    for input in &function.sig.inputs {
+        let FnArg::Typed(param) = input else {
+            return Err(Error::new_spanned(input, "imported functions take `this` rather than `self`"));
+        };
+        match &*param.pat {
+            Pat::Ident(pat) => arguments.push(pat.ident.to_token_stream()),
+            pat => return Err(Error::new_spanned(pat, "imported function parameters must be plain names")),
+        }
+    }
+    if let Kind::Method { borrowed, .. } = kind {
+        let mut inputs = signature.inputs.into_iter().skip(1).collect::<Punctuated<FnArg, Token![,]>>();
+        inputs.insert(0, if borrowed { syn::parse_quote!(&self) } else { syn::parse_quote!(self) });
+        signature.inputs = inputs;
+        arguments[0] = quote!(self);
+    }
+    let attrs = &function.attrs;
+    let vis = &function.vis;
+    Ok(quote! {
+        #import
+
+        impl #class {
+            #(#attrs)*
+            #vis #signature {
+                #wrapper(#(#arguments),*)
+            }
+        }
+    })
+}
+
+/// Type of a method's `this`, and whether it is borrowed
+fn receiver(function: &ForeignItemFn) -> syn::Result<(Type, bool)> {
+    let missing = || Error::new_spanned(&function.sig, "methods take the object as their first parameter, `this`");
+    let Some(FnArg::Typed(this)) = function.sig.inputs.first() else { return Err(missing()) };
+    match &*this.ty {
+        Type::Reference(reference) if reference.mutability.is_none() => Ok(((*reference.elem).clone(), true)),
+        Type::Path(_) => Ok(((*this.ty).clone(), false)),
+        _ => Err(missing()),
+    }
+}
+
+/// Type a constructor returns
+fn constructed(function: &ForeignItemFn) -> syn::Result<Path> {
+    match &function.sig.output {
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Path(path) if path.qself.is_none() => Ok(path.path.clone()),
+            ty => Err(Error::new(ty.span(), "constructors return the type they construct")),
+        },
+        ReturnType::Default => Err(Error::new_spanned(&function.sig, "constructors return the type they construct")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn expand(attr: TokenStream2, item: Item) -> String {
+        expand_bindgen(attr, item).map_or_else(|err| err.to_string(), |expanded| expanded.to_string())
+    }
+
+    #[test]
+    fn test_imports_map_to_glue_calls() {
+        let block: Item = parse_quote! {
+            extern "C" {
+                #[wasm_bindgen(js_namespace = ["window", "console"], js_name = warn)]
+                fn log(message: &str);
+
+                #[wasm_bindgen(method, getter)]
+                fn width(this: &Canvas) -> u32;
+
+                #[wasm_bindgen(module = "./draw.js")]
+                fn draw(canvas: Canvas);
+            }
+        };
+        let expanded = expand(quote!(), block);
+        assert!(expanded.contains("link_name = \"js_global:window.console.warn\""));
+        assert!(expanded.contains("fn __wasm_import___wasm_bindgen_canvas_width (this : u32) -> u32"));
+        assert!(expanded.contains("impl Canvas { fn width (& self) -> u32 { __wasm_bindgen_canvas_width (self) } }"));
+        assert!(expanded.contains("wasm_import_module = \"./draw.js\""));
+        assert!(expanded.contains("link_name = \"draw\""));
+    }
+
+    #[test]
+    fn test_unsupported_items_are_rejected() {
+        let catch: Item = parse_quote!(extern "C" { #[wasm_bindgen(catch)] fn parse(text: &str) -> JsValue; });
+        assert_eq!(expand(quote!(), catch), "`catch` is not supported by #[wasm::bindgen]");
+        let constructor: Item = parse_quote!(extern "C" { #[wasm_bindgen(constructor)] fn new(); });
+        assert_eq!(expand(quote!(), constructor), "constructors return the type they construct");
+        let exported: Item = parse_quote!(pub struct Counter { count: u32 });
+        assert_eq!(expand(quote!(), exported), "exported types are not supported; export functions instead");
+        assert!(expand(quote!(module = "./draw.js"), parse_quote!(pub fn draw() {})).contains("only take `js_name`"));
+    }
+}
@@ -0,0 +1,638 @@
+//! `#[wasm::component]`: guest bindings for one WIT interface
+//!
+//! `wasm` depends on this crate, so the macro cannot call
+//! `wasm::component::GuestBindings`. It parses the interface itself and
+//! supports the types whose canonical ABI `wasm::component::abi`
+//! implements generically: numbers, `bool`, `char`, `string`, and `list`,
+//! `option`, and `result` of those. Records, variants, and resources need
+//! the bindings a build script generates with `GuestBindings`.
+//!
+//! The entry points follow `GuestBindings`: parameters are lifted from
+//! their flattened core values, or loaded from memory when they spill past
+//! `MAX_FLAT_PARAMS`, and results wider than one core value are returned
+//! by address and kept until the host calls the `cabi_post_` export.
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned};
+use syn::{DeriveInput, Error, Ident, LitStr};
+
+/// Flat parameters passed directly, as in `wasm::component::canon_async`
+const MAX_FLAT_PARAMS: usize = 16;
+
+/// Flat results returned directly, as in `wasm::component::introspect`
+const MAX_FLAT_RESULTS: usize = 1;
+
+/// WIT type supported by the macro
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum WitType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    S8,
+    S16,
+    S32,
+    S64,
+    F32,
+    F64,
+    Char,
+    String,
+    List(Box<WitType>),
+    Option(Box<WitType>),
+    Result { ok: Option<Box<WitType>>, err: Option<Box<WitType>> },
+}
+
+/// Core value type of the canonical ABI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Core {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+/// Function of a WIT interface
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WitFunction {
+    pub name: String,
+    pub params: Vec<(String, WitType)>,
+    pub result: Option<WitType>,
+}
+
+/// Interface read from a WIT document
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WitInterface {
+    /// Name exports are qualified with, such as `example:plugin/greeter@1.0.0`
+    pub qualified: String,
+    pub name: String,
+    pub functions: Vec<WitFunction>,
+}
+
+/// Expands `#[wasm::component(interface = "...")]` on a type
+///
+/// The WIT path is relative to the crate's manifest, and may select one of
+/// several interfaces in the document as `path#name`.
+pub(crate) fn expand_component(path: &LitStr, input: &DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "component implementors cannot be generic"));
+    }
+    let value = path.value();
+    let (file, selected) = match value.split_once('#') {
+        Some((file, name)) => (file, Some(name)),
+        None => (value.as_str(), None),
+    };
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let full = std::path::Path::new(&root).join(file);
+    let source = std::fs::read_to_string(&full)
+        .map_err(|err| Error::new_spanned(path, format!("cannot read `{}`: {}", full.display(), err)))?;
+    let interface = parse_interface(&source, selected).map_err(|err| Error::new_spanned(path, err))?;
+
+    let full = full.to_string_lossy();
+    let bindings = bindings(&interface, &input.ident, &input.vis);
+    Ok(quote! {
+        #input
+
+        // Rebuilds the crate when the WIT changes
+        const _: &str = include_str!(#full);
+
+        #bindings
+    })
+}
+
+fn bindings(interface: &WitInterface, implementor: &Ident, vis: &syn::Visibility) -> TokenStream2 {
+    let trait_ident = Ident::new(&pascal_case(&interface.name), implementor.span());
+    let trait_doc = format!(" Functions of the `{}` WIT interface, exported by `{}`", interface.qualified, implementor);
+    let methods = interface.functions.iter().map(|function| {
+        let ident = rust_ident(&function.name);
+        let params = function.params.iter().map(|(name, ty)| {
+            let name = rust_ident(name);
+            let ty = rust_type(ty);
+            quote!(#name: #ty)
+        });
+        let returns = function.result.as_ref().map(|ty| {
+            let ty = rust_type(ty);
+            quote!(-> #ty)
+        });
+        quote! { fn #ident(#(#params),*) #returns; }
+    });
+    let entry_points = interface.functions.iter().map(|function| entry_point(interface, function, implementor, &trait_ident));
+
+    // Points at the type when it does not implement the interface
+    let check = quote_spanned! {implementor.span()=>
+        const _: () = {
+            fn implements<T: #trait_ident>() {}
+            let _ = implements::<#implementor>;
+        };
+    };
+    quote! {
+        #[doc = #trait_doc]
+        #vis trait #trait_ident {
+            #(#methods)*
+        }
+
+        #check
+
+        #(#entry_points)*
+    }
+}
+
+fn entry_point(interface: &WitInterface, function: &WitFunction, implementor: &Ident, trait_ident: &Ident) -> TokenStream2 {
+    let abi = quote!(::wasm::component::abi);
+    let export_name = format!("{}#{}", interface.qualified, function.name);
+    let symbol = format_ident!("__export_{}_{}", snake_case(&interface.name), snake_case(&function.name));
+    let names: Vec<Ident> = function.params.iter().map(|(name, _)| rust_ident(name)).collect();
+    let types: Vec<TokenStream2> = function.params.iter().map(|(_, ty)| rust_type(ty)).collect();
+
+    let mut flat = Vec::new();
+    for (_, ty) in &function.params {
+        flatten(ty, &mut flat);
+    }
+    let (params, lift) = if flat.len() > MAX_FLAT_PARAMS {
+        let loads = names.iter().zip(&types).enumerate().map(|(index, (name, ty))| {
+            quote!(let #name: #ty = #abi::ComponentValue::load(params.add(offsets[#index]));)
+        });
+        let lift = quote! {
+            let params = arg0 as usize as *mut u8;
+            let fields = [#(#abi::layout::<#types>()),*];
+            let offsets = #abi::field_offsets(fields);
+            #(#loads)*
+            #abi::free(params, #abi::record_size(&fields), #abi::record_align(&fields));
+        };
+        (quote!(arg0: i32), lift)
+    } else {
+        let args: Vec<Ident> = (0..flat.len()).map(|index| format_ident!("arg{}", index)).collect();
+        let params = args.iter().zip(&flat).map(|(arg, core)| {
+            let ty = core.rust_type();
+            quote!(#arg: #ty)
+        });
+        let bits = args.iter().zip(&flat).map(|(arg, core)| core.to_bits(quote!(#arg)));
+        let lift = if names.is_empty() {
+            quote!()
+        } else {
+            quote! {
+                let flat = [#(#bits),*];
+                let flat = &mut flat.iter();
+                #(let #names: #types = #abi::ComponentValue::lift(flat);)*
+            }
+        };
+        (quote!(#(#params),*), lift)
+    };
+
+    let method = rust_ident(&function.name);
+    let call = quote!(<#implementor as #trait_ident>::#method(#(#names),*));
+    let mut results = Vec::new();
+    if let Some(result) = &function.result {
+        flatten(result, &mut results);
+    }
+    let attrs = quote! {
+        #[doc(hidden)]
+        #[allow(dead_code, clippy::missing_safety_doc)]
+    };
+    if results.len() > MAX_FLAT_RESULTS {
+        let post_name = format!("cabi_post_{}", export_name);
+        let post_symbol = format_ident!("__post_return_{}_{}", snake_case(&interface.name), snake_case(&function.name));
+        quote! {
+            #attrs
+            #[cfg_attr(target_arch = "wasm32", export_name = #export_name)]
+            unsafe extern "C" fn #symbol(#params) -> i32 {
+                #lift
+                #abi::return_area(#call) as i32
+            }
+
+            #attrs
+            #[cfg_attr(target_arch = "wasm32", export_name = #post_name)]
+            unsafe extern "C" fn #post_symbol(_: i32) {
+                #abi::post_return();
+            }
+        }
+    } else if let Some(core) = results.first() {
+        let ty = core.rust_type();
+        let value = core.core_value(quote!(flat[0]));
+        quote! {
+            #attrs
+            #[cfg_attr(target_arch = "wasm32", export_name = #export_name)]
+            unsafe extern "C" fn #symbol(#params) -> #ty {
+                #lift
+                let result = #call;
+                let mut flat = #abi::Vec::new();
+                #abi::ComponentValue::lower(&result, &mut flat, &mut #abi::Allocations::new());
+                #value
+            }
+        }
+    } else {
+        quote! {
+            #attrs
+            #[cfg_attr(target_arch = "wasm32", export_name = #export_name)]
+            unsafe extern "C" fn #symbol(#params) {
+                #lift
+                #call;
+            }
+        }
+    }
+}
+
+impl Core {
+    fn rust_type(self) -> TokenStream2 {
+        match self {
+            Core::I32 => quote!(i32),
+            Core::I64 => quote!(i64),
+            Core::F32 => quote!(f32),
+            Core::F64 => quote!(f64),
+        }
+    }
+
+    /// Converts a core value to the raw bits `ComponentValue` lifts from
+    fn to_bits(self, value: TokenStream2) -> TokenStream2 {
+        match self {
+            Core::I32 => quote!(#value as u32 as u64),
+            Core::I64 => quote!(#value as u64),
+            Core::F32 => quote!(u64::from(#value.to_bits())),
+            Core::F64 => quote!(#value.to_bits()),
+        }
+    }
+
+    /// Converts raw lowered bits to a core value
+    fn core_value(self, value: TokenStream2) -> TokenStream2 {
+        match self {
+            Core::I32 => quote!(#value as i32),
+            Core::I64 => quote!(#value as i64),
+            Core::F32 => quote!(f32::from_bits(#value as u32)),
+            Core::F64 => quote!(f64::from_bits(#value)),
+        }
+    }
+}
+
+/// Appends the core types a WIT type flattens to
+fn flatten(ty: &WitType, out: &mut Vec<Core>) {
+    match ty {
+        WitType::U64 | WitType::S64 => out.push(Core::I64),
+        WitType::F32 => out.push(Core::F32),
+        WitType::F64 => out.push(Core::F64),
+        WitType::String | WitType::List(_) => out.extend([Core::I32, Core::I32]),
+        WitType::Option(inner) => flatten_variant(&[Some(inner)], out),
+        WitType::Result { ok, err } => flatten_variant(&[ok.as_deref(), err.as_deref()], out),
+        _ => out.push(Core::I32),
+    }
+}
+
+/// Flattens a discriminant followed by the join of every case's payload
+fn flatten_variant(cases: &[Option<&WitType>], out: &mut Vec<Core>) {
+    out.push(Core::I32);
+    let mut joined: Vec<Core> = Vec::new();
+    for case in cases.iter().flatten() {
+        let mut flat = Vec::new();
+        flatten(case, &mut flat);
+        for (index, core) in flat.into_iter().enumerate() {
+            match joined.get_mut(index) {
+                None => joined.push(core),
+                Some(existing) if *existing == core => {}
+                Some(existing) => {
+                    *existing = match (*existing, core) {
+                        (Core::I32, Core::F32) | (Core::F32, Core::I32) => Core::I32,
+                        _ => Core::I64,
+                    };
+                }
+            }
+        }
+    }
+    out.extend(joined);
+}
+
+fn rust_type(ty: &WitType) -> TokenStream2 {
+    let abi = quote!(::wasm::component::abi);
+    let optional = |ty: &Option<Box<WitType>>| ty.as_deref().map_or_else(|| quote!(()), rust_type);
+    match ty {
+        WitType::Bool => quote!(bool),
+        WitType::U8 => quote!(u8),
+        WitType::U16 => quote!(u16),
+        WitType::U32 => quote!(u32),
+        WitType::U64 => quote!(u64),
+        WitType::S8 => quote!(i8),
+        WitType::S16 => quote!(i16),
+        WitType::S32 => quote!(i32),
+        WitType::S64 => quote!(i64),
+        WitType::F32 => quote!(f32),
+        WitType::F64 => quote!(f64),
+        WitType::Char => quote!(char),
+        WitType::String => quote!(#abi::String),
+        WitType::List(inner) => {
+            let inner = rust_type(inner);
+            quote!(#abi::Vec<#inner>)
+        }
+        WitType::Option(inner) => {
+            let inner = rust_type(inner);
+            quote!(::core::option::Option<#inner>)
+        }
+        WitType::Result { ok, err } => {
+            let (ok, err) = (optional(ok), optional(err));
+            quote!(::core::result::Result<#ok, #err>)
+        }
+    }
+}
+
+fn rust_ident(name: &str) -> Ident {
+    let name = snake_case(name);
+    syn::parse_str::<Ident>(&name).unwrap_or_else(|_| Ident::new_raw(&name, Span::call_site()))
+}
+
+fn snake_case(name: &str) -> String {
+    name.trim_start_matches('%').replace('-', "_")
+}
+
+fn pascal_case(name: &str) -> String {
+    name.split(['-', '_'])
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars.next().map_or_else(String::new, |first| first.to_ascii_uppercase().to_string() + chars.as_str())
+        })
+        .collect()
+}
+
+/// Reads the interface named `selected`, or the document's only one
+pub(crate) fn parse_interface(source: &str, selected: Option<&str>) -> Result<WitInterface, String> {
+    let mut parser = Parser { tokens: tokenize(source), position: 0 };
+    let mut package = None;
+    let mut interfaces = Vec::new();
+    while let Some(token) = parser.next() {
+        match token.as_str() {
+            "package" => {
+                let mut name = String::new();
+                while let Some(token) = parser.next().filter(|token| token != ";") {
+                    name.push_str(&token);
+                }
+                package = Some(name);
+            }
+            "interface" => {
+                let name = parser.expect_name()?;
+                parser.expect("{")?;
+                interfaces.push(parser.interface(&name)?);
+            }
+            // Worlds and nested items are skipped
+            "{" => parser.skip_block()?,
+            _ => {}
+        }
+    }
+
+    let names: Vec<&str> = interfaces.iter().map(|interface: &WitInterface| interface.name.as_str()).collect();
+    let position = match selected {
+        Some(selected) => names.iter().position(|name| *name == selected)
+            .ok_or_else(|| format!("the WIT document has no interface `{}`", selected))?,
+        None if names.len() == 1 => 0,
+        None if names.is_empty() => return Err("the WIT document declares no interface".to_string()),
+        None => return Err(format!("the WIT document declares {}; select one as `path#name`", names.join(", "))),
+    };
+    let mut interface = interfaces.swap_remove(position);
+    interface.qualified = match package {
+        Some(package) => match package.split_once('@') {
+            Some((name, version)) => format!("{}/{}@{}", name, interface.name, version),
+            None => format!("{}/{}", package, interface.name),
+        },
+        None => interface.name.clone(),
+    };
+    Ok(interface)
+}
+
+/// Splits WIT into names and punctuation, dropping comments
+fn tokenize(source: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push("->".to_string());
+            }
+            c if c.is_alphanumeric() || c == '%' || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push(name);
+            }
+            c => tokens.push(c.to_string()),
+        }
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected `{}` in WIT, found `{}`", expected, token)),
+            None => Err(format!("expected `{}` in WIT, found the end", expected)),
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(token) if token.starts_with(|c: char| c.is_alphabetic() || c == '%') => Ok(token),
+            Some(token) => Err(format!("expected a name in WIT, found `{}`", token)),
+            None => Err("expected a name in WIT, found the end".to_string()),
+        }
+    }
+
+    /// Skips to the `}` closing a block whose `{` was read
+    fn skip_block(&mut self) -> Result<(), String> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next().as_deref() {
+                Some("{") => depth += 1,
+                Some("}") => depth -= 1,
+                Some(_) => {}
+                None => return Err("unclosed `{` in WIT".to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the items of an interface whose `{` was read
+    fn interface(&mut self, name: &str) -> Result<WitInterface, String> {
+        let mut functions = Vec::new();
+        loop {
+            let item = self.next().ok_or_else(|| "unclosed `{` in WIT".to_string())?;
+            match item.as_str() {
+                "}" => break,
+                "record" | "variant" | "enum" | "flags" | "resource" | "type" | "use" => {
+                    // Type definitions are only rejected where they are used
+                    while !matches!(self.peek(), Some(";" | "{") | None) {
+                        self.next();
+                    }
+                    if self.next().as_deref() == Some("{") {
+                        self.skip_block()?;
+                    }
+                }
+                _ => {
+                    self.expect(":")?;
+                    if self.peek() == Some("async") {
+                        return Err(format!("`{}` is async, which #[wasm::component] does not support", item));
+                    }
+                    self.expect("func")?;
+                    functions.push(self.function(item)?);
+                }
+            }
+        }
+        Ok(WitInterface { qualified: String::new(), name: name.to_string(), functions })
+    }
+
+    /// Reads a function's parameters and result after `func`
+    fn function(&mut self, name: String) -> Result<WitFunction, String> {
+        self.expect("(")?;
+        let mut params = Vec::new();
+        while self.peek() != Some(")") {
+            let param = self.expect_name()?;
+            self.expect(":")?;
+            params.push((param, self.ty()?));
+            if self.peek() == Some(",") {
+                self.next();
+            }
+        }
+        self.expect(")")?;
+        let result = if self.peek() == Some("->") {
+            self.next();
+            Some(self.ty()?)
+        } else {
+            None
+        };
+        self.expect(";")?;
+        Ok(WitFunction { name, params, result })
+    }
+
+    fn ty(&mut self) -> Result<WitType, String> {
+        let name = self.expect_name()?;
+        Ok(match name.as_str() {
+            "bool" => WitType::Bool,
+            "u8" => WitType::U8,
+            "u16" => WitType::U16,
+            "u32" => WitType::U32,
+            "u64" => WitType::U64,
+            "s8" => WitType::S8,
+            "s16" => WitType::S16,
+            "s32" => WitType::S32,
+            "s64" => WitType::S64,
+            "f32" | "float32" => WitType::F32,
+            "f64" | "float64" => WitType::F64,
+            "char" => WitType::Char,
+            "string" => WitType::String,
+            "list" | "option" => {
+                self.expect("<")?;
+                let inner = Box::new(self.ty()?);
+                self.expect(">")?;
+                if name == "list" {
+                    WitType::List(inner)
+                } else {
+                    WitType::Option(inner)
+                }
+            }
+            "result" => {
+                if self.peek() != Some("<") {
+                    return Ok(WitType::Result { ok: None, err: None });
+                }
+                self.next();
+                let ok = if self.peek() == Some("_") {
+                    self.next();
+                    None
+                } else {
+                    Some(Box::new(self.ty()?))
+                };
+                let err = if self.peek() == Some(",") {
+                    self.next();
+                    Some(Box::new(self.ty()?))
+                } else {
+                    None
+                };
+                self.expect(">")?;
+                WitType::Result { ok, err }
+            }
+            other => {
+                return Err(format!(
+                    "type `{}` is not supported by #[wasm::component]; generate bindings for it with \
+                     `wasm::component::GuestBindings`",
+                    other
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREETER: &str = r#"
+        package example:plugin@1.0.0;
+
+        /// Greets people
+        interface greeter {
+            record entry { message: string }
+            greet: func(name: string, times: u8) -> string;
+            score: func(weight: option<f64>, flags: result<_, u32>) -> f32;
+            reset: func();
+        }
+
+        world plugin { export greeter; }
+    "#;
+
+    #[test]
+    fn test_interfaces_are_parsed_and_flattened() {
+        let interface = parse_interface(GREETER, None).unwrap();
+        assert_eq!(interface.qualified, "example:plugin/greeter@1.0.0");
+        let names: Vec<&str> = interface.functions.iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(names, ["greet", "score", "reset"]);
+        assert_eq!(interface.functions[0].result, Some(WitType::String));
+
+        let mut flat = Vec::new();
+        for (_, ty) in &interface.functions[1].params {
+            flatten(ty, &mut flat);
+        }
+        assert_eq!(flat, [Core::I32, Core::F64, Core::I32, Core::I32]);
+
+        let unsupported = parse_interface("interface a { f: func(entry: entry); }", None).unwrap_err();
+        assert!(unsupported.starts_with("type `entry` is not supported"));
+        assert!(parse_interface("interface a {} interface b {}", None).unwrap_err().contains("a, b"));
+    }
+
+    #[test]
+    fn test_entry_points_call_the_implementor() {
+        let interface = parse_interface(GREETER, Some("greeter")).unwrap();
+        let implementor = Ident::new("Plugin", Span::call_site());
+        let expanded = bindings(&interface, &implementor, &syn::Visibility::Inherited).to_string();
+        assert!(expanded.contains("trait Greeter { fn greet (name : :: wasm :: component :: abi :: String , times : u8)"));
+        assert!(expanded.contains("export_name = \"example:plugin/greeter@1.0.0#greet\""));
+        assert!(expanded.contains("unsafe extern \"C\" fn __export_greeter_greet (arg0 : i32 , arg1 : i32 , arg2 : i32) -> i32"));
+        assert!(expanded.contains("export_name = \"cabi_post_example:plugin/greeter@1.0.0#greet\""));
+        assert!(expanded.contains("fn implements < T : Greeter > () { } let _ = implements :: < Plugin > ;"));
+    }
+}
@@ -0,0 +1,948 @@
+//! Procedural macros for the `wasm` crate
+//!
+//! `wasm` re-exports them with its `macros` feature, so they are written
+//! `#[wasm::gc]`, `#[wasm::export]`, `#[wasm::import]`, `#[wasm::bench]`,
+//! `#[wasm::linear]`, `#[wasm::component]`, `#[wasm::bindgen]`, and
+//! `wasm::conditional_type_alias!`. Generated code names the runtime
+//! through `::wasm`.
+
+mod bindgen;
+mod component;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Field, Fields, FnArg, ForeignItemFn,
+    GenericArgument, Generics, Ident, Index, Item, ItemFn, LitStr, Pat, Path, PathArguments, ReturnType, Signature,
+    Token, Type, Visibility,
+};
+
+/// Manages a struct or enum on the garbage-collected heap
+///
+/// Implements `wasm::memory::gc::GcManaged` by marking every field that
+/// may hold a `Gc`: fields of primitive types are skipped, and the others
+/// must implement `GcManaged` themselves. Type parameters get a
+/// `GcManaged` bound. Values are then moved onto a `GcHeap` with `alloc`
+/// and refer to each other through `Gc`.
+#[proc_macro_attribute]
+pub fn gc(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(proc_macro2::Span::call_site(), "#[wasm::gc] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let input = parse_macro_input!(item as DeriveInput);
+    expand_gc(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Types that never hold a `Gc`, by their last path segment
+const PRIMITIVES: &[&str] = &[
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64", "bool",
+    "char", "str", "String",
+];
+
+fn expand_gc(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let marks = traced_fields(&data.fields).map(|(index, field)| {
+                let member = match &field.ident {
+                    Some(ident) => quote!(#ident),
+                    None => {
+                        let index = Index::from(index);
+                        quote!(#index)
+                    }
+                };
+                mark(quote!(&self.#member))
+            });
+            quote! { #(#marks)* }
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let name = &variant.ident;
+                let bindings: Vec<_> = (0..variant.fields.len()).map(|index| format_ident!("field{}", index)).collect();
+                let marks = traced_fields(&variant.fields).map(|(index, _)| {
+                    let binding = &bindings[index];
+                    mark(quote!(#binding))
+                });
+                let pattern = match &variant.fields {
+                    Fields::Named(fields) => {
+                        let names = fields.named.iter().map(|field| &field.ident);
+                        quote! { Self::#name { #(#names: ref #bindings),* } }
+                    }
+                    Fields::Unnamed(_) => quote! { Self::#name(#(ref #bindings),*) },
+                    Fields::Unit => quote! { Self::#name },
+                };
+                quote! {
+                    #[allow(unused_variables)]
+                    #pattern => { #(#marks)* }
+                }
+            });
+            quote! {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(data.union_token, "#[wasm::gc] cannot trace unions"));
+        }
+    };
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::wasm::memory::gc::GcManaged));
+    }
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+    let name = &input.ident;
+    Ok(quote! {
+        #input
+
+        // SAFETY: every field that may hold a `Gc` is marked
+        unsafe impl #impl_generics ::wasm::memory::gc::GcManaged for #name #type_generics #where_clause {
+            #[allow(unused_variables)]
+            fn gc_trace(&self, tracer: &mut ::wasm::memory::gc::Tracer) {
+                #body
+            }
+        }
+    })
+}
+
+/// Fields that may hold a `Gc`, with their positions
+fn traced_fields(fields: &Fields) -> impl Iterator<Item = (usize, &Field)> {
+    fields.iter().enumerate().filter(|(_, field)| !is_primitive(&field.ty))
+}
+
+fn mark(value: TokenStream2) -> TokenStream2 {
+    quote! { ::wasm::memory::gc::GcManaged::gc_mark(#value, tracer); }
+}
+
+/// Checks whether a field type never holds a `Gc`
+///
+/// References and tuples are primitive when what they hold is; a type
+/// parameter or a user type may always hold one.
+fn is_primitive(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last().is_some_and(|segment| {
+            segment.arguments.is_empty() && PRIMITIVES.contains(&segment.ident.to_string().as_str())
+        }),
+        Type::Reference(reference) => is_primitive(&reference.elem),
+        Type::Tuple(tuple) => tuple.elems.iter().all(is_primitive),
+        Type::Array(array) => is_primitive(&array.elem),
+        Type::Paren(paren) => is_primitive(&paren.elem),
+        Type::Group(group) => is_primitive(&group.elem),
+        _ => false,
+    }
+}
+
+/// Exports a function to the host
+///
+/// The function is kept as written and wrapped in an `extern "C"` shim
+/// exported as the function's name, or as `name = "..."`. Parameters may
+/// be numbers, `bool`, `&str`, `String`, or `&[T]`, `&mut [T]`, and
+/// `Vec<T>` of numbers other than `u64`; results may be `()`, a number,
+/// `bool`, `String`, or `Vec<T>`. Each export is recorded in the
+/// `wasm::host::export::EXPORTS_SECTION` custom section, from which the
+/// JS glue and `.d.ts` generators learn its signature.
+#[proc_macro_attribute]
+pub fn export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name = None;
+    let arguments = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse::<LitStr>()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `name = \"...\"`"))
+        }
+    });
+    parse_macro_input!(attr with arguments);
+    let function = parse_macro_input!(item as ItemFn);
+    expand_export(name, &function, Interop::classify).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Element types of slices and vectors, in `wasm::wasmir::ElementType` order
+const ELEMENTS: &[&str] = &["u8", "i8", "u16", "i16", "u32", "i32", "i64", "f32", "f64"];
+
+/// How a parameter or result crosses the host boundary
+enum Interop {
+    /// A number passed as its ABI type; `bool` and narrow integers widen
+    Value { abi: TokenStream2, widened: bool, is_bool: bool },
+    /// UTF-8 bytes in linear memory, borrowed or owned
+    Str { owned: bool },
+    /// `&[T]`, `&mut [T]`, or `Vec<T>`, with the code of the interop type
+    Memory { code: u8, element: usize },
+    /// Object of a `#[wasm::bindgen]` type, passed as an externref table handle
+    Object { ty: Type, owned: bool },
+}
+
+impl Interop {
+    fn classify(ty: &Type) -> syn::Result<Interop> {
+        let unsupported = || Error::new_spanned(ty, "type cannot cross the wasm boundary");
+        match ty {
+            Type::Reference(reference) => match &*reference.elem {
+                Type::Path(path) if reference.mutability.is_none() && path.path.is_ident("str") => {
+                    Ok(Interop::Str { owned: false })
+                }
+                Type::Slice(slice) => Ok(Interop::Memory {
+                    code: if reference.mutability.is_some() { 3 } else { 2 },
+                    element: element(&slice.elem).ok_or_else(unsupported)?,
+                }),
+                _ => Err(unsupported()),
+            },
+            Type::Path(path) if path.qself.is_none() => {
+                let segment = path.path.segments.last().ok_or_else(unsupported)?;
+                let name = segment.ident.to_string();
+                if let PathArguments::AngleBracketed(arguments) = &segment.arguments {
+                    return match (name.as_str(), arguments.args.first()) {
+                        ("Vec", Some(GenericArgument::Type(element_type))) if arguments.args.len() == 1 => {
+                            Ok(Interop::Memory { code: 4, element: element(element_type).ok_or_else(unsupported)? })
+                        }
+                        _ => Err(unsupported()),
+                    };
+                }
+                let (abi, widened) = match name.as_str() {
+                    "String" => return Ok(Interop::Str { owned: true }),
+                    "i32" | "u32" | "i64" | "u64" | "f32" | "f64" | "usize" | "isize" => {
+                        let ident = &segment.ident;
+                        (quote!(#ident), false)
+                    },
+                    "bool" | "u8" | "u16" => (quote!(u32), true),
+                    "i8" | "i16" => (quote!(i32), true),
+                    _ => return Err(unsupported()),
+                };
+                Ok(Interop::Value { abi, widened, is_bool: name == "bool" })
+            }
+            Type::Paren(paren) => Interop::classify(&paren.elem),
+            Type::Group(group) => Interop::classify(&group.elem),
+            _ => Err(unsupported()),
+        }
+    }
+
+    /// Like `classify`, taking other types without generic arguments as objects
+    fn classify_object(ty: &Type) -> syn::Result<Interop> {
+        let is_object = |ty: &Type| match ty {
+            Type::Path(path) => {
+                path.qself.is_none() && path.path.segments.iter().all(|segment| segment.arguments.is_none())
+            }
+            _ => false,
+        };
+        Interop::classify(ty).or_else(|err| match ty {
+            Type::Reference(reference) if reference.mutability.is_none() && is_object(&reference.elem) => {
+                Ok(Interop::Object { ty: (*reference.elem).clone(), owned: false })
+            }
+            ty if is_object(ty) => Ok(Interop::Object { ty: ty.clone(), owned: true }),
+            _ => Err(err),
+        })
+    }
+
+    /// Type code and element code in the export record
+    fn codes(&self) -> Vec<u8> {
+        match self {
+            Interop::Value { .. } => vec![0],
+            Interop::Str { .. } => vec![1],
+            Interop::Memory { code, element } => vec![*code, *element as u8],
+            Interop::Object { .. } => vec![5],
+        }
+    }
+}
+
+/// Position of a slice or vector element type in `ELEMENTS`
+fn element(ty: &Type) -> Option<usize> {
+    match ty {
+        Type::Path(path) => ELEMENTS.iter().position(|element| path.path.is_ident(element)),
+        _ => None,
+    }
+}
+
+/// Rejects signatures that cannot cross the boundary as a whole
+fn check_signature(signature: &Signature, what: &str) -> syn::Result<()> {
+    if !signature.generics.params.is_empty() {
+        return Err(Error::new_spanned(&signature.generics, format!("{} cannot be generic", what)));
+    }
+    if let Some(asyncness) = signature.asyncness {
+        return Err(Error::new_spanned(asyncness, format!("{} cannot be async", what)));
+    }
+    if let Some(variadic) = &signature.variadic {
+        return Err(Error::new_spanned(variadic, format!("{} cannot be variadic", what)));
+    }
+    if let Some(receiver) = signature.receiver() {
+        return Err(Error::new_spanned(receiver, format!("{} cannot take self", what)));
+    }
+    Ok(())
+}
+
+/// Result of a signature, unless it is `()`
+fn returned_type(signature: &Signature) -> Option<&Type> {
+    match &signature.output {
+        ReturnType::Type(_, ty) if !matches!(&**ty, Type::Tuple(tuple) if tuple.elems.is_empty()) => Some(ty),
+        _ => None,
+    }
+}
+
+fn expand_export(
+    name: Option<LitStr>,
+    function: &ItemFn,
+    classify: fn(&Type) -> syn::Result<Interop>,
+) -> syn::Result<TokenStream2> {
+    let signature = &function.sig;
+    check_signature(signature, "exported functions")?;
+
+    let ident = &signature.ident;
+    let export_name = name.map_or_else(|| ident.to_string(), |name| name.value());
+    let mut shim_params = Vec::new();
+    let mut arguments = Vec::new();
+    let mut codes = Vec::new();
+    for (index, input) in signature.inputs.iter().enumerate() {
+        let FnArg::Typed(param) = input else { unreachable!("receivers are rejected") };
+        let interop = classify(&param.ty)?;
+        codes.extend(interop.codes());
+        let arg = format_ident!("arg{}", index);
+        let (ptr, len) = (format_ident!("arg{}_ptr", index), format_ident!("arg{}_len", index));
+        let ty = &param.ty;
+        match interop {
+            Interop::Value { abi, widened, is_bool } => {
+                shim_params.push(quote!(#arg: #abi));
+                arguments.push(if is_bool {
+                    quote!(#arg != 0)
+                } else if widened {
+                    quote!(#arg as #ty)
+                } else {
+                    quote!(#arg)
+                });
+            }
+            Interop::Str { owned } => {
+                shim_params.push(quote!(#ptr: *const u8, #len: usize));
+                let lift = if owned { quote!(lift_string) } else { quote!(lift_str) };
+                arguments.push(quote!(::wasm::host::export::#lift(#ptr, #len)));
+            }
+            Interop::Memory { code, element } => {
+                let element = format_ident!("{}", ELEMENTS[element]);
+                let (pointer, lift) = match code {
+                    2 => (quote!(*const #element), quote!(lift_slice)),
+                    3 => (quote!(*mut #element), quote!(lift_slice_mut)),
+                    _ => (quote!(*mut #element), quote!(lift_vec)),
+                };
+                shim_params.push(quote!(#ptr: #pointer, #len: usize));
+                arguments.push(quote!(::wasm::host::export::#lift(#ptr, #len)));
+            }
+            Interop::Object { ty, owned } => {
+                shim_params.push(quote!(#arg: u32));
+                let object = quote!(::wasm::host::bindgen::take_object::<#ty>(#arg));
+                arguments.push(if owned { object } else { quote!(&#object) });
+            }
+        }
+    }
+
+    let call = quote!(#ident(#(#arguments),*));
+    let returns = match returned_type(signature) {
+        Some(ty) => Some((ty, classify(ty)?)),
+        None => None,
+    };
+    let (result, body) = match &returns {
+        None => (quote!(), quote!(#call;)),
+        Some((_, Interop::Value { abi, widened, .. })) => {
+            let body = if *widened { quote!(#call as #abi) } else { call };
+            (quote!(-> #abi), body)
+        }
+        Some((_, Interop::Str { owned: true })) => {
+            shim_params.insert(0, quote!(retptr: *mut usize));
+            (quote!(), quote!(::wasm::host::export::return_string(retptr, #call);))
+        }
+        Some((_, Interop::Memory { code: 4, .. })) => {
+            shim_params.insert(0, quote!(retptr: *mut usize));
+            (quote!(), quote!(::wasm::host::export::return_vec(retptr, #call);))
+        }
+        Some((_, Interop::Object { owned: true, .. })) => {
+            (quote!(-> u32), quote!(::wasm::host::bindgen::into_handle(#call)))
+        }
+        Some((ty, _)) => return Err(Error::new(ty.span(), "borrowed results cannot cross the wasm boundary")),
+    };
+    codes.extend(returns.map_or_else(|| vec![0], |(_, interop)| interop.codes()));
+
+    let mut record = Vec::new();
+    write_name(&mut record, &export_name);
+    write_leb(&mut record, signature.inputs.len());
+    record.extend(codes);
+    let record_len = record.len();
+
+    let vis = &function.vis;
+    let shim = format_ident!("__wasm_export_{}", ident);
+    let metadata = format_ident!("__WASM_EXPORT_{}", ident.to_string().to_uppercase());
+    Ok(quote! {
+        #function
+
+        #[doc(hidden)]
+        #[allow(dead_code, clippy::missing_safety_doc)]
+        #[cfg_attr(target_arch = "wasm32", export_name = #export_name)]
+        #vis unsafe extern "C" fn #shim(#(#shim_params),*) #result {
+            #body
+        }
+
+        #[doc(hidden)]
+        #[used]
+        #[cfg_attr(target_arch = "wasm32", link_section = "wasmrust.exports")]
+        #vis static #metadata: [u8; #record_len] = [#(#record),*];
+    })
+}
+
+/// Imports a host function
+///
+/// Takes a function declaration without a body and the import module,
+/// optionally followed by the field name, which defaults to the function's
+/// name. The declaration becomes a wrapper lowering its arguments to the
+/// import. Parameters may be numbers, `bool`, `&str`, `&[T]`, or
+/// `&mut [T]`, with the element types allowed by `#[wasm::export]`;
+/// results may be `()`, a number, `bool`, `String`, or `Vec<T>`. Each
+/// import is recorded in the `wasm::host::import::IMPORTS_SECTION` custom
+/// section, from which the JS glue builds the stubs adapting host
+/// functions to the memory ABI.
+///
+/// ```ignore
+/// #[wasm::import("console", "log")]
+/// fn log(message: &str);
+/// ```
+#[proc_macro_attribute]
+pub fn import(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let names = parse_macro_input!(attr with Punctuated::<LitStr, Token![,]>::parse_terminated);
+    let function = parse_macro_input!(item as ForeignItemFn);
+    expand_import(&names, &function, Interop::classify).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand_import(
+    names: &Punctuated<LitStr, Token![,]>,
+    function: &ForeignItemFn,
+    classify: fn(&Type) -> syn::Result<Interop>,
+) -> syn::Result<TokenStream2> {
+    let signature = &function.sig;
+    let ident = &signature.ident;
+    let (module, name) = match names.iter().collect::<Vec<_>>()[..] {
+        [module] => (module.value(), ident.to_string()),
+        [module, name] => (module.value(), name.value()),
+        _ => {
+            let usage = "expected #[wasm::import(\"module\")] or #[wasm::import(\"module\", \"name\")]";
+            return Err(Error::new(proc_macro2::Span::call_site(), usage));
+        }
+    };
+    check_signature(signature, "imported functions")?;
+
+    let mut raw_params = Vec::new();
+    let mut arguments = Vec::new();
+    let mut codes = Vec::new();
+    for input in &signature.inputs {
+        let FnArg::Typed(param) = input else { unreachable!("receivers are rejected") };
+        let arg = match &*param.pat {
+            Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => &pat.ident,
+            pat => return Err(Error::new_spanned(pat, "imported function parameters must be plain names")),
+        };
+        let interop = classify(&param.ty)?;
+        codes.extend(interop.codes());
+        let (ptr, len) = (format_ident!("{}_ptr", arg), format_ident!("{}_len", arg));
+        match interop {
+            Interop::Value { abi, widened, .. } => {
+                raw_params.push(quote!(#arg: #abi));
+                arguments.push(if widened { quote!(#arg as #abi) } else { quote!(#arg) });
+            }
+            Interop::Str { owned: false } => {
+                raw_params.push(quote!(#ptr: *const u8, #len: usize));
+                arguments.push(quote!(#arg.as_ptr(), #arg.len()));
+            }
+            Interop::Memory { code: code @ (2 | 3), element } => {
+                let element = format_ident!("{}", ELEMENTS[element]);
+                if code == 2 {
+                    raw_params.push(quote!(#ptr: *const #element, #len: usize));
+                    arguments.push(quote!(#arg.as_ptr(), #arg.len()));
+                } else {
+                    raw_params.push(quote!(#ptr: *mut #element, #len: usize));
+                    arguments.push(quote!(#arg.as_mut_ptr(), #arg.len()));
+                }
+            }
+            Interop::Object { owned, .. } => {
+                raw_params.push(quote!(#arg: u32));
+                let object = if owned { quote!(&#arg) } else { quote!(#arg) };
+                arguments.push(quote!(::wasm::host::bindgen::handle_of(#object)));
+            }
+            _ => return Err(Error::new_spanned(&param.ty, "imports borrow their arguments; pass a reference")),
+        }
+    }
+
+    let raw = format_ident!("__wasm_import_{}", ident);
+    let call = quote!(#raw(#(#arguments),*));
+    let (result, body) = match returned_type(signature) {
+        None => (quote!(), quote!(unsafe { #call })),
+        Some(ty) => {
+            let interop = classify(ty)?;
+            codes.extend(interop.codes());
+            match interop {
+                Interop::Value { abi, widened, is_bool } => {
+                    let lift = if is_bool {
+                        quote!(result != 0)
+                    } else if widened {
+                        quote!(result as #ty)
+                    } else {
+                        quote!(result)
+                    };
+                    (quote!(-> #abi), quote! {
+                        let result = unsafe { #call };
+                        #lift
+                    })
+                }
+                Interop::Str { owned: true } | Interop::Memory { code: 4, .. } => {
+                    raw_params.insert(0, quote!(retptr: *mut usize));
+                    let take = if matches!(interop, Interop::Str { .. }) { quote!(take_string) } else { quote!(take_vec) };
+                    let call = quote!(#raw(area.as_mut_ptr(), #(#arguments),*));
+                    (quote!(), quote! {
+                        let mut area = [0usize; 2];
+                        unsafe {
+                            #call;
+                            ::wasm::host::import::#take(&area)
+                        }
+                    })
+                }
+                Interop::Object { owned: true, .. } => (quote!(-> u32), quote! {
+                    let result = unsafe { #call };
+                    // SAFETY: the glue roots returned objects for the module to own
+                    unsafe { ::wasm::host::bindgen::take_object(result) }
+                }),
+                _ => return Err(Error::new_spanned(ty, "borrowed results cannot cross the wasm boundary")),
+            }
+        }
+    };
+    if returned_type(signature).is_none() {
+        codes.push(0);
+    }
+
+    let mut record = Vec::new();
+    write_name(&mut record, &module);
+    write_name(&mut record, &name);
+    write_leb(&mut record, signature.inputs.len());
+    record.extend(codes);
+    let record_len = record.len();
+
+    let attrs = &function.attrs;
+    let vis = &function.vis;
+    let metadata = format_ident!("__WASM_IMPORT_{}", ident.to_string().to_uppercase());
+    Ok(quote! {
+        #[cfg_attr(target_arch = "wasm32", link(wasm_import_module = #module))]
+        extern "C" {
+            #[link_name = #name]
+            fn #raw(#(#raw_params),*) #result;
+        }
+
+        #(#attrs)*
+        #vis #signature {
+            #body
+        }
+
+        #[doc(hidden)]
+        #[used]
+        #[cfg_attr(target_arch = "wasm32", link_section = "wasmrust.imports")]
+        #vis static #metadata: [u8; #record_len] = [#(#record),*];
+    })
+}
+
+/// Runs a function as a benchmark inside the compiled module
+///
+/// The function takes a `&mut wasm::bench::Bencher` and passes the routine
+/// to time to its `iter`. It is exported as
+/// `wasm::bench::BENCH_EXPORT_PREFIX` followed by its name, through which
+/// benchmark drivers run it with their warmup and iteration counts and
+/// read back the samples.
+///
+/// ```ignore
+/// #[wasm::bench]
+/// fn parse_small(bencher: &mut wasm::bench::Bencher) {
+///     bencher.iter(|| parse("{\"a\": [1, 2, 3]}"));
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn bench(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(proc_macro2::Span::call_site(), "#[wasm::bench] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let function = parse_macro_input!(item as ItemFn);
+    expand_bench(&function).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand_bench(function: &ItemFn) -> syn::Result<TokenStream2> {
+    let signature = &function.sig;
+    check_signature(signature, "benchmarks")?;
+    if signature.inputs.len() != 1 {
+        return Err(Error::new_spanned(&signature.inputs, "benchmarks take one `&mut wasm::bench::Bencher`"));
+    }
+    if let Some(ty) = returned_type(signature) {
+        return Err(Error::new_spanned(ty, "benchmarks cannot return a value"));
+    }
+
+    let ident = &signature.ident;
+    let export_name = format!("__wasmrust_bench_{}", ident);
+    let vis = &function.vis;
+    let shim = format_ident!("__wasm_bench_{}", ident);
+    Ok(quote! {
+        #function
+
+        #[doc(hidden)]
+        #[allow(dead_code)]
+        #[cfg_attr(target_arch = "wasm32", export_name = #export_name)]
+        #vis extern "C" fn #shim(warmup: u32, iterations: u32) -> u32 {
+            ::wasm::bench::run(#ident, warmup, iterations)
+        }
+    })
+}
+
+/// Makes a struct linear, so each value is used exactly once
+///
+/// `Clone` and `Copy` are removed from the struct's derives, and it
+/// implements `wasm::linear::Linear`, by which the WasmRust compiler
+/// rejects implicit drops at compile time. Other compilers get a drop bomb
+/// instead: in debug builds, dropping a value panics. A value is used up by
+/// moving it into a function that takes ownership, or by the generated
+/// `consume` method, which takes it apart and returns its field, a tuple
+/// of its fields, or `()`.
+///
+/// ```ignore
+/// #[wasm::linear]
+/// struct Canvas(wasm::component::resource::Own<Surface>);
+///
+/// impl Canvas {
+///     fn into_bitmap(self) -> Bitmap {
+///         snapshot(self.consume())
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn linear(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(proc_macro2::Span::call_site(), "#[wasm::linear] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let input = parse_macro_input!(item as DeriveInput);
+    expand_linear(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand_linear(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => data.fields.clone(),
+        Data::Enum(data) => return Err(Error::new_spanned(data.enum_token, "#[wasm::linear] supports only structs")),
+        Data::Union(data) => return Err(Error::new_spanned(data.union_token, "#[wasm::linear] supports only structs")),
+    };
+    strip_duplication(&mut input.attrs)?;
+
+    let members: Vec<_> = fields.iter()
+        .enumerate()
+        .map(|(index, field)| match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(index);
+                quote!(#index)
+            }
+        })
+        .collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let (output, body) = match &types[..] {
+        [] => (quote!(()), quote!(::core::mem::forget(self))),
+        [ty] => {
+            let member = &members[0];
+            (quote!(#ty), quote! {
+                let this = ::core::mem::ManuallyDrop::new(self);
+                // SAFETY: the field is read once, and `this` is never dropped
+                unsafe { ::core::ptr::read(&this.#member) }
+            })
+        }
+        _ => (quote!((#(#types),*)), quote! {
+            let this = ::core::mem::ManuallyDrop::new(self);
+            // SAFETY: each field is read once, and `this` is never dropped
+            unsafe { (#(::core::ptr::read(&this.#members)),*) }
+        }),
+    };
+
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let name = &input.ident;
+    let vis = &input.vis;
+    Ok(quote! {
+        #input
+
+        impl #impl_generics ::wasm::linear::Linear for #name #type_generics #where_clause {}
+
+        impl #impl_generics ::core::ops::Drop for #name #type_generics #where_clause {
+            fn drop(&mut self) {
+                if cfg!(debug_assertions) {
+                    ::wasm::linear::implicit_drop(::core::any::type_name::<Self>());
+                }
+            }
+        }
+
+        impl #impl_generics #name #type_generics #where_clause {
+            /// Uses the value up, returning its fields without dropping it
+            #[allow(dead_code)]
+            #vis fn consume(self) -> #output {
+                #body
+            }
+        }
+    })
+}
+
+/// Removes `Clone` and `Copy` from `#[derive]` attributes, dropping any
+/// left empty
+fn strip_duplication(attrs: &mut Vec<Attribute>) -> syn::Result<()> {
+    let mut kept = Vec::new();
+    for mut attr in attrs.drain(..) {
+        if attr.path().is_ident("derive") {
+            let derives = attr.parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated)?;
+            let derives: Vec<_> = derives.into_iter()
+                .filter(|path| !path.segments.last().is_some_and(|segment| segment.ident == "Clone" || segment.ident == "Copy"))
+                .collect();
+            if derives.is_empty() {
+                continue;
+            }
+            attr = parse_quote!(#[derive(#(#derives),*)]);
+        }
+        kept.push(attr);
+    }
+    *attrs = kept;
+    Ok(())
+}
+
+/// Declares type aliases with one definition on wasm and another natively
+///
+/// Each alias names its type on `wasm` targets and on `native` ones, in
+/// either order, so crates compiled for both can swap representations, such
+/// as a host reference for an owned value. Aliases may carry attributes,
+/// visibility, and generics, and are separated by semicolons.
+///
+/// ```ignore
+/// wasm::conditional_type_alias! {
+///     pub Node: wasm => wasm::ExternRef<DomNode>, native => Box<DomNode>;
+///     Index<T>: wasm => u32, native => usize;
+/// }
+/// ```
+#[proc_macro]
+pub fn conditional_type_alias(input: TokenStream) -> TokenStream {
+    let aliases = parse_macro_input!(input with Punctuated::<ConditionalAlias, Token![;]>::parse_terminated);
+    aliases.iter().map(ConditionalAlias::expand).collect::<TokenStream2>().into()
+}
+
+/// One alias of `conditional_type_alias!`
+struct ConditionalAlias {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    generics: Generics,
+    wasm: Type,
+    native: Type,
+}
+
+impl Parse for ConditionalAlias {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let ident = input.parse()?;
+        let mut generics: Generics = input.parse()?;
+        generics.where_clause = input.parse()?;
+        input.parse::<Token![:]>()?;
+
+        let (mut wasm, mut native) = (None, None);
+        while !input.is_empty() && !input.peek(Token![;]) {
+            let target: Ident = input.parse()?;
+            input.parse::<Token![=>]>()?;
+            let slot = match target.to_string().as_str() {
+                "wasm" => &mut wasm,
+                "native" => &mut native,
+                _ => return Err(Error::new_spanned(target, "expected `wasm` or `native`")),
+            };
+            if slot.replace(input.parse()?).is_some() {
+                return Err(Error::new_spanned(&target, format!("`{}` is given twice", target)));
+            }
+            if !input.peek(Token![,]) {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        match (wasm, native) {
+            (Some(wasm), Some(native)) => Ok(ConditionalAlias { attrs, vis, ident, generics, wasm, native }),
+            _ => Err(Error::new_spanned(&ident, format!("`{}` needs both a `wasm` and a `native` type", ident))),
+        }
+    }
+}
+
+impl ConditionalAlias {
+    fn expand(&self) -> TokenStream2 {
+        let ConditionalAlias { attrs, vis, ident, generics, wasm, native } = self;
+        let where_clause = &generics.where_clause;
+        quote! {
+            #[cfg(target_arch = "wasm32")]
+            #(#attrs)*
+            #vis type #ident #generics #where_clause = #wasm;
+            #[cfg(not(target_arch = "wasm32"))]
+            #(#attrs)*
+            #vis type #ident #generics #where_clause = #native;
+        }
+    }
+}
+
+/// Exports a WIT interface implemented by the annotated type
+///
+/// `interface` is the path of a WIT document relative to the crate's
+/// manifest, followed by `#name` if it declares several interfaces. The
+/// macro emits a trait named after the interface, whose associated
+/// functions the type must implement, and the canonical ABI entry points
+/// the component exports, which call them. A type that does not implement
+/// the trait fails to compile. Interfaces may only use numbers, `bool`,
+/// `char`, `string`, and `list`, `option`, and `result` of those.
+///
+/// ```ignore
+/// #[wasm::component(interface = "wit/plugin.wit#greeter")]
+/// struct Plugin;
+///
+/// impl Greeter for Plugin {
+///     fn greet(name: String) -> String {
+///         format!("hello {}", name)
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn component(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut interface = None;
+    let arguments = syn::meta::parser(|meta| {
+        if meta.path.is_ident("interface") {
+            interface = Some(meta.value()?.parse::<LitStr>()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `interface = \"...\"`"))
+        }
+    });
+    parse_macro_input!(attr with arguments);
+    let input = parse_macro_input!(item as DeriveInput);
+    let Some(interface) = interface else {
+        return Error::new(proc_macro2::Span::call_site(), "expected #[wasm::component(interface = \"...\")]")
+            .to_compile_error()
+            .into();
+    };
+    component::expand_component(&interface, &input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Accepts wasm-bindgen's `#[wasm_bindgen]` on extern blocks and functions
+///
+/// Imported types, functions, methods, and constructors become
+/// `#[wasm::import]`s of the calls the JS glue implements, and functions
+/// become `#[wasm::export]`s; see `wasm::host::bindgen`, whose prelude
+/// re-exports the macro as `wasm_bindgen`.
+///
+/// ```ignore
+/// #[wasm::bindgen]
+/// extern "C" {
+///     #[wasm_bindgen(js_namespace = console)]
+///     fn log(message: &str);
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn bindgen(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as Item);
+    bindgen::expand_bindgen(attr.into(), item).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Same encoding as `wasm::host::export`, which reads the records back
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_leb(out, name.len());
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_leb(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_signatures_are_rejected() {
+        let cases: [ItemFn; 4] = [
+            parse_quote!(fn f(values: &[bool]) {}),
+            parse_quote!(fn f() -> &'static str { "" }),
+            parse_quote!(fn f<T>(value: T) {}),
+            parse_quote!(async fn f() {}),
+        ];
+        for function in &cases {
+            assert!(expand_export(None, function, Interop::classify).is_err());
+        }
+    }
+
+    #[test]
+    fn test_export_records_name_and_codes() {
+        let function: ItemFn = parse_quote!(fn sum(values: &[f64], scratch: String) -> Vec<u16> { Vec::new() });
+        let name = LitStr::new("total", proc_macro2::Span::call_site());
+        let expanded = expand_export(Some(name), &function, Interop::classify).unwrap();
+        let expanded = expanded.to_string();
+        // "total", two params, a f64 slice, a string, and a u16 vector
+        assert!(expanded.contains("[5u8 , 116u8 , 111u8 , 116u8 , 97u8 , 108u8 , 2u8 , 2u8 , 8u8 , 1u8 , 4u8 , 2u8]"));
+        assert!(expanded.contains("retptr : * mut usize"));
+    }
+
+    #[test]
+    fn test_imports_borrow_arguments() {
+        let module: Punctuated<LitStr, Token![,]> = parse_quote!("env");
+        let owned: ForeignItemFn = parse_quote!(fn log(message: String););
+        assert!(expand_import(&module, &owned, Interop::classify).is_err());
+        let borrowed: ForeignItemFn = parse_quote!(fn log(message: &str, level: u8););
+        let expanded = expand_import(&module, &borrowed, Interop::classify).unwrap().to_string();
+        assert!(expanded.contains("fn __wasm_import_log (message_ptr : * const u8 , message_len : usize , level : u32)"));
+        assert!(expand_import(&Punctuated::new(), &borrowed, Interop::classify).is_err());
+    }
+
+    #[test]
+    fn test_benches_are_exported_with_the_prefix() {
+        let function: ItemFn = parse_quote!(fn parse(bencher: &mut Bencher) { bencher.iter(|| 1) });
+        let expanded = expand_bench(&function).unwrap().to_string();
+        assert!(expanded.contains("export_name = \"__wasmrust_bench_parse\""));
+        assert!(expanded.contains("extern \"C\" fn __wasm_bench_parse (warmup : u32 , iterations : u32) -> u32"));
+        assert!(expand_bench(&parse_quote!(fn parse() {})).is_err());
+        assert!(expand_bench(&parse_quote!(fn parse(bencher: &mut Bencher) -> u32 { 0 })).is_err());
+    }
+
+    #[test]
+    fn test_linear_structs_lose_clone_and_copy() {
+        let input: DeriveInput = parse_quote! {
+            #[derive(Clone, Copy, Debug)]
+            #[derive(core::clone::Clone)]
+            struct Canvas { handle: u32, scale: f32 }
+        };
+        let expanded = expand_linear(input).unwrap().to_string();
+        assert!(expanded.contains("# [derive (Debug)] struct Canvas"));
+        assert!(!expanded.contains("Clone"));
+        assert!(expanded.contains("fn consume (self) -> (u32 , f32)"));
+        assert!(expand_linear(parse_quote!(enum Handle { Open(u32) })).is_err());
+    }
+
+    #[test]
+    fn test_conditional_aliases_are_cfg_gated() {
+        let aliases: Punctuated<ConditionalAlias, Token![;]> = parse_quote! {
+            /// Host node
+            pub Node: native => Box<DomNode>, wasm => wasm::ExternRef<DomNode>;
+            Index<T>: wasm => u32, native => usize,
+        };
+        let expanded: TokenStream2 = aliases.iter().map(ConditionalAlias::expand).collect();
+        let expanded = expanded.to_string();
+        assert!(expanded.contains(
+            "# [cfg (target_arch = \"wasm32\")] # [doc = r\" Host node\"] pub type Node = wasm :: ExternRef < DomNode > ;"
+        ));
+        assert!(expanded.contains("# [cfg (not (target_arch = \"wasm32\"))] type Index < T > = usize ;"));
+
+        let missing = syn::parse2::<ConditionalAlias>(quote!(Node: wasm => u32));
+        assert_eq!(missing.err().unwrap().to_string(), "`Node` needs both a `wasm` and a `native` type");
+        assert!(syn::parse2::<ConditionalAlias>(quote!(Node: web => u32, native => u64)).is_err());
+    }
+}
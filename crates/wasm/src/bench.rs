@@ -0,0 +1,184 @@
+//! Benchmarks run inside the compiled module, declared with `#[wasm::bench]`
+//!
+//! A benchmark is a function taking a `Bencher`, which times the routine
+//! given to `Bencher::iter` with the module's own clock: `performance.now`
+//! through the `NOW_IMPORT` the JS glue provides, `clock_time_get` under
+//! WASI, and `std::time::Instant` off wasm with the `std` feature. Routines
+//! shorter than the clock's resolution are timed in batches, grown during
+//! warmup until a batch takes `MIN_SAMPLE_NS`.
+//!
+//! The macro exports each benchmark as `BENCH_EXPORT_PREFIX` followed by its
+//! name, taking the warmup and measured iteration counts and returning the
+//! number of samples. The host then reads the per-call latencies one at a
+//! time from `SAMPLE_EXPORT`, so drivers only pass numbers across the
+//! boundary, and turns them into the registry's performance records.
+
+use crate::threading::Mutex;
+use alloc::vec::Vec;
+use core::hint::black_box;
+
+/// Prefix of the export running a benchmark
+pub const BENCH_EXPORT_PREFIX: &str = "__wasmrust_bench_";
+
+/// Export returning a sample of the last benchmark run, in nanoseconds
+pub const SAMPLE_EXPORT: &str = "__wasmrust_sample";
+
+/// `wasmrust` import returning `performance.now()` in milliseconds
+pub const NOW_IMPORT: &str = "__wasmrust_now";
+
+/// Shortest batch that calibration aims for
+pub const MIN_SAMPLE_NS: u64 = 1_000_000;
+
+/// Upper bound on calls per batch, reached when the clock does not advance
+const MAX_BATCH: u64 = 1 << 16;
+
+/// Samples of the last `run`, read back through `SAMPLE_EXPORT`
+static SAMPLES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+/// Times a benchmark routine
+#[derive(Debug, Clone)]
+pub struct Bencher {
+    warmup: u32,
+    iterations: u32,
+    samples: Vec<u64>,
+}
+
+impl Bencher {
+    pub fn new(warmup: u32, iterations: u32) -> Self {
+        Self { warmup, iterations, samples: Vec::new() }
+    }
+
+    /// Runs `routine` through the warmup, then records one sample per iteration
+    ///
+    /// Each sample is the mean latency of one call over its batch. Results
+    /// go through `black_box` so the routine is not optimized out.
+    pub fn iter<T, F: FnMut() -> T>(&mut self, mut routine: F) {
+        let mut batch = 1;
+        for _ in 0..self.warmup {
+            if time(batch, &mut routine) < MIN_SAMPLE_NS && batch < MAX_BATCH {
+                batch *= 2;
+            }
+        }
+        self.samples = (0..self.iterations).map(|_| time(batch, &mut routine) / batch).collect();
+    }
+
+    /// Per-call latencies in nanoseconds, empty until `iter` is called
+    pub fn samples(&self) -> &[u64] {
+        &self.samples
+    }
+}
+
+/// Calls `routine` `batch` times, returning the elapsed nanoseconds
+fn time<T>(batch: u64, routine: &mut impl FnMut() -> T) -> u64 {
+    let start = now_ns();
+    for _ in 0..batch {
+        black_box(routine());
+    }
+    now_ns().saturating_sub(start)
+}
+
+/// Runs a benchmark and keeps its samples for `sample`, returning their count
+pub fn run(bench: fn(&mut Bencher), warmup: u32, iterations: u32) -> u32 {
+    let mut bencher = Bencher::new(warmup, iterations);
+    bench(&mut bencher);
+    let count = bencher.samples.len() as u32;
+    *SAMPLES.lock() = bencher.samples;
+    count
+}
+
+/// Sample of the last `run`
+pub fn sample(index: u32) -> Option<u64> {
+    SAMPLES.lock().get(index as usize).copied()
+}
+
+/// Export the host reads samples through, as `f64` so JavaScript gets a number
+#[cfg(all(target_arch = "wasm32", feature = "macros"))]
+#[no_mangle]
+pub extern "C" fn __wasmrust_sample(index: u32) -> f64 {
+    sample(index).unwrap_or_default() as f64
+}
+
+/// Monotonic time in nanoseconds
+///
+/// Off wasm without the `std` feature there is no clock and this returns 0.
+pub fn now_ns() -> u64 {
+    imp::now_ns()
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "wasi"))]
+mod imp {
+    /// `CLOCKID_MONOTONIC`
+    const MONOTONIC: u32 = 1;
+
+    #[link(wasm_import_module = "wasi_snapshot_preview1")]
+    extern "C" {
+        fn clock_time_get(id: u32, precision: u64, time: *mut u64) -> u16;
+    }
+
+    pub fn now_ns() -> u64 {
+        let mut time = 0;
+        // SAFETY: `time` is a writable u64
+        match unsafe { clock_time_get(MONOTONIC, 1, &mut time) } {
+            0 => time,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+mod imp {
+    #[link(wasm_import_module = "wasmrust")]
+    extern "C" {
+        fn __wasmrust_now() -> f64;
+    }
+
+    pub fn now_ns() -> u64 {
+        // SAFETY: the import takes no arguments
+        (unsafe { __wasmrust_now() } * 1e6) as u64
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "std"))]
+mod imp {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    pub fn now_ns() -> u64 {
+        START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "std")))]
+mod imp {
+    pub fn now_ns() -> u64 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iterations_record_samples() {
+        let mut calls = 0u64;
+        let mut bencher = Bencher::new(0, 5);
+        bencher.iter(|| calls += 1);
+        assert_eq!((bencher.samples().len(), calls), (5, 5));
+    }
+
+    #[test]
+    fn test_warmup_grows_batches_below_the_clock_resolution() {
+        let mut calls = 0u64;
+        let mut bencher = Bencher::new(3, 2);
+        bencher.iter(|| calls += 1);
+        // Batches of 1, 2, and 4 warm up unless a call takes a millisecond
+        assert_eq!(calls, 1 + 2 + 4 + 2 * 8);
+
+        fn empty(_: &mut Bencher) {}
+        assert_eq!(run(empty, 1, 1), 0);
+        assert_eq!(sample(0), None);
+    }
+}
@@ -4,20 +4,214 @@
 //! This is a minimal implementation to support the core wasm crate functionality.
 
 use crate::host::{get_host_capabilities};
+use alloc::boxed::Box;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
+pub mod abi;
+pub mod adapter;
+pub mod canon_async;
+pub mod encoder;
+pub mod guest_bindings;
+pub mod host_bindings;
+pub mod introspect;
+pub mod resource;
+pub mod wit;
+pub mod wit_deps;
+
+pub use crate::host_bindings;
+pub use adapter::{Adapter, AdapterError, ComponentAdapter};
+pub use encoder::{ComponentEncoder, EncodeError};
+pub use guest_bindings::{GuestBindings, GuestBindingsError};
+pub use host_bindings::{HostBindings, HostBindingsError};
+pub use introspect::{inspect, ComponentInfo, InspectError};
+
 /// Simple signature representation
 #[derive(Debug, Clone)]
 pub struct Signature {
     pub name: String,
 }
 
+/// WIT value type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    S8,
+    S16,
+    S32,
+    S64,
+    F32,
+    F64,
+    Char,
+    String,
+    List(Box<WitType>),
+    Option(Box<WitType>),
+    Result {
+        ok: Option<Box<WitType>>,
+        err: Option<Box<WitType>>,
+    },
+    /// `future<T>`, or a bare `future` signalling completion only
+    Future(Option<Box<WitType>>),
+    /// `stream<T>`, or a bare `stream` carrying no payload
+    Stream(Option<Box<WitType>>),
+    /// `own<r>`, a handle owning resource `r`
+    ///
+    /// A resource named without `own` or `borrow` is `Named`, which also owns it.
+    Own(String),
+    /// `borrow<r>`, a handle lent for the duration of a call
+    Borrow(String),
+    /// Record, variant, enum, or resource defined by name
+    Named(String),
+}
+
+impl WitType {
+    /// Creates `future<T>`
+    pub fn future(payload: WitType) -> Self {
+        WitType::Future(Some(Box::new(payload)))
+    }
+
+    /// Creates `stream<T>`
+    pub fn stream(payload: WitType) -> Self {
+        WitType::Stream(Some(Box::new(payload)))
+    }
+
+    /// Checks whether the type is or contains a `future` or `stream`
+    pub fn is_async(&self) -> bool {
+        match self {
+            WitType::Future(_) | WitType::Stream(_) => true,
+            WitType::List(inner) | WitType::Option(inner) => inner.is_async(),
+            WitType::Result { ok, err } => {
+                ok.as_deref().is_some_and(WitType::is_async) || err.as_deref().is_some_and(WitType::is_async)
+            }
+            _ => false,
+        }
+    }
+
+    /// Visits this type and every nested type, outermost first
+    pub fn visit<'a>(&'a self, visitor: &mut impl FnMut(&'a WitType)) {
+        visitor(self);
+        match self {
+            WitType::List(inner) | WitType::Option(inner) => inner.visit(visitor),
+            WitType::Result { ok, err } => {
+                for inner in ok.iter().chain(err) {
+                    inner.visit(visitor);
+                }
+            }
+            WitType::Future(Some(inner)) | WitType::Stream(Some(inner)) => inner.visit(visitor),
+            _ => {}
+        }
+    }
+}
+
+impl core::fmt::Display for WitType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WitType::Bool => write!(f, "bool"),
+            WitType::U8 => write!(f, "u8"),
+            WitType::U16 => write!(f, "u16"),
+            WitType::U32 => write!(f, "u32"),
+            WitType::U64 => write!(f, "u64"),
+            WitType::S8 => write!(f, "s8"),
+            WitType::S16 => write!(f, "s16"),
+            WitType::S32 => write!(f, "s32"),
+            WitType::S64 => write!(f, "s64"),
+            WitType::F32 => write!(f, "f32"),
+            WitType::F64 => write!(f, "f64"),
+            WitType::Char => write!(f, "char"),
+            WitType::String => write!(f, "string"),
+            WitType::List(inner) => write!(f, "list<{}>", inner),
+            WitType::Option(inner) => write!(f, "option<{}>", inner),
+            WitType::Result { ok: None, err: None } => write!(f, "result"),
+            WitType::Result { ok: Some(ok), err: None } => write!(f, "result<{}>", ok),
+            WitType::Result { ok: None, err: Some(err) } => write!(f, "result<_, {}>", err),
+            WitType::Result { ok: Some(ok), err: Some(err) } => write!(f, "result<{}, {}>", ok, err),
+            WitType::Future(None) => write!(f, "future"),
+            WitType::Future(Some(inner)) => write!(f, "future<{}>", inner),
+            WitType::Stream(None) => write!(f, "stream"),
+            WitType::Stream(Some(inner)) => write!(f, "stream<{}>", inner),
+            WitType::Own(name) => write!(f, "own<{}>", name),
+            WitType::Borrow(name) => write!(f, "borrow<{}>", name),
+            WitType::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Function declared by a component interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentFunction {
+    pub name: String,
+    pub params: Vec<(String, WitType)>,
+    pub result: Option<WitType>,
+    /// Declared `async func`, lifted with the callback ABI
+    pub is_async: bool,
+}
+
+impl ComponentFunction {
+    /// Creates a synchronous function with no parameters or result
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            params: Vec::new(),
+            result: None,
+            is_async: false,
+        }
+    }
+
+    /// Adds a parameter
+    pub fn param(mut self, name: impl Into<String>, ty: WitType) -> Self {
+        self.params.push((name.into(), ty));
+        self
+    }
+
+    /// Sets the result type
+    pub fn result(mut self, ty: WitType) -> Self {
+        self.result = Some(ty);
+        self
+    }
+
+    /// Marks the function `async`
+    pub fn asynchronous(mut self) -> Self {
+        self.is_async = true;
+        self
+    }
+
+    /// Parameter and result types in declaration order
+    pub fn types(&self) -> impl Iterator<Item = &WitType> {
+        self.params.iter().map(|(_, ty)| ty).chain(self.result.as_ref())
+    }
+}
+
+impl core::fmt::Display for ComponentFunction {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}: ", self.name)?;
+        if self.is_async {
+            write!(f, "async ")?;
+        }
+        write!(f, "func(")?;
+        for (i, (name, ty)) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", name, ty)?;
+        }
+        write!(f, ")")?;
+        if let Some(result) = &self.result {
+            write!(f, " -> {}", result)?;
+        }
+        Ok(())
+    }
+}
+
 /// Component interface definition
 #[derive(Debug, Clone)]
 pub struct ComponentInterface {
     pub name: String,
     pub version: String,
+    pub functions: Vec<ComponentFunction>,
 }
 
 impl ComponentInterface {
@@ -25,7 +219,56 @@ impl ComponentInterface {
         Self {
             name,
             version: "1.0.0".to_string(),
+            functions: Vec::new(),
+        }
+    }
+
+    /// Adds a function to the interface
+    pub fn add_function(&mut self, function: ComponentFunction) -> Result<(), ComponentError> {
+        if self.functions.iter().any(|existing| existing.name == function.name) {
+            return Err(ComponentError::ValidationFailed(alloc::format!(
+                "duplicate function {} in interface {}",
+                function.name, self.name
+            )));
         }
+        self.functions.push(function);
+        Ok(())
+    }
+
+    /// Checks whether any function is async or passes futures or streams
+    pub fn uses_async(&self) -> bool {
+        self.functions.iter().any(|function| function.is_async || function.types().any(WitType::is_async))
+    }
+}
+
+/// World: the interfaces a component imports from and exports to its host
+#[derive(Debug, Clone)]
+pub struct ComponentWorld {
+    pub name: String,
+    pub imports: Vec<ComponentInterface>,
+    pub exports: Vec<ComponentInterface>,
+}
+
+impl ComponentWorld {
+    /// Creates a world with no imports or exports
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            imports: Vec::new(),
+            exports: Vec::new(),
+        }
+    }
+
+    /// Adds an imported interface
+    pub fn import(mut self, interface: ComponentInterface) -> Self {
+        self.imports.push(interface);
+        self
+    }
+
+    /// Adds an exported interface
+    pub fn export(mut self, interface: ComponentInterface) -> Self {
+        self.exports.push(interface);
+        self
     }
 }
 
@@ -0,0 +1,690 @@
+//! Canonical ABI lifting and lowering for generated component bindings
+//!
+//! `ComponentValue` describes how a Rust value crosses a component
+//! boundary: flattened into core values for parameters and small results,
+//! or stored in linear memory for lists, spilled parameters, and larger
+//! results. Flat values travel as raw `u64` bits so a variant's joined
+//! payload slots can carry any case; conversions follow the canonical ABI's
+//! zero-extending bitcasts.
+//!
+//! Lowering borrows the value's own buffers where the layout allows, so the
+//! value must outlive the call it is lowered for. Lifting takes ownership of
+//! buffers the other side allocated through `cabi_realloc`.
+
+use alloc::alloc::{dealloc, Layout};
+use alloc::boxed::Box;
+use core::slice::Iter;
+
+// Re-exported for generated bindings in `no_std` crates
+pub use alloc::string::String;
+pub use alloc::vec::Vec;
+
+use crate::threading::Mutex;
+
+/// Value with a canonical ABI representation
+///
+/// # Safety
+///
+/// `SIZE`, `ALIGN`, and `FLAT_COUNT` must match the WIT type the value is
+/// bound to, and `store` must write no more than `SIZE` bytes.
+pub unsafe trait ComponentValue: Sized {
+    /// Bytes the value occupies in linear memory
+    const SIZE: usize;
+    /// Alignment of the value in linear memory
+    const ALIGN: usize;
+    /// Number of core values the value flattens to
+    const FLAT_COUNT: usize;
+
+    /// Appends the flattened value, recording any buffers it had to allocate
+    fn lower(&self, flat: &mut Vec<u64>, allocs: &mut Allocations);
+
+    /// Reads a flattened value, taking ownership of the buffers it refers to
+    ///
+    /// # Safety
+    ///
+    /// The values must have been lowered for this type by the other side.
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self;
+
+    /// Writes the value at `ptr`, recording any buffers it had to allocate
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for `SIZE` bytes and aligned to `ALIGN`.
+    unsafe fn store(&self, ptr: *mut u8, allocs: &mut Allocations);
+
+    /// Reads a value at `ptr`, taking ownership of the buffers it refers to
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must hold a value stored for this type by the other side.
+    unsafe fn load(ptr: *const u8) -> Self;
+}
+
+/// Buffers allocated while lowering, freed on drop
+#[derive(Debug, Default)]
+pub struct Allocations(Vec<(*mut u8, Layout)>);
+
+impl Allocations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates a zeroed buffer freed with the others
+    pub fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        if size == 0 {
+            return align as *mut u8;
+        }
+        let layout = Layout::from_size_align(size, align).expect("invalid canonical ABI layout");
+        // SAFETY: the layout has a non-zero size
+        let ptr = unsafe { alloc::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
+        self.0.push((ptr, layout));
+        ptr
+    }
+}
+
+impl Drop for Allocations {
+    fn drop(&mut self) {
+        for (ptr, layout) in self.0.drain(..) {
+            // SAFETY: every entry came from `alloc` with this layout
+            unsafe { dealloc(ptr, layout) }
+        }
+    }
+}
+
+/// Export result kept alive until the host calls the post-return function
+struct Retained {
+    value: *mut (),
+    drop: unsafe fn(*mut ()),
+    _allocs: Allocations,
+}
+
+// SAFETY: components run on a single thread; the mutex only serialises
+// access between an export and its post-return
+unsafe impl Send for Retained {}
+
+impl Drop for Retained {
+    fn drop(&mut self) {
+        // SAFETY: `value` came from `Box::into_raw` of the type `drop` frees
+        unsafe { (self.drop)(self.value) }
+    }
+}
+
+static RETAINED: Mutex<Option<Retained>> = Mutex::new(None);
+
+/// Stores an export's result in memory and returns its address
+///
+/// The result and its buffers stay alive until `post_return`.
+pub fn return_area<T: ComponentValue>(value: T) -> *mut u8 {
+    unsafe fn drop_boxed<T>(value: *mut ()) {
+        drop(Box::from_raw(value as *mut T));
+    }
+
+    let value = Box::new(value);
+    let mut allocs = Allocations::new();
+    let ptr = allocs.alloc(T::SIZE, T::ALIGN);
+    // SAFETY: the area was allocated for `T`
+    unsafe { value.store(ptr, &mut allocs) };
+    *RETAINED.lock() = Some(Retained {
+        value: Box::into_raw(value) as *mut (),
+        drop: drop_boxed::<T>,
+        _allocs: allocs,
+    });
+    ptr
+}
+
+/// Releases the result kept by the last `return_area`
+pub fn post_return() {
+    let retained = RETAINED.lock().take();
+    drop(retained);
+}
+
+/// Allocator the host calls to pass strings, lists, and spilled values
+///
+/// # Safety
+///
+/// Called by the host with the canonical ABI's `realloc` contract.
+#[cfg(all(target_arch = "wasm32", feature = "component-model"))]
+#[no_mangle]
+pub unsafe extern "C" fn cabi_realloc(old: *mut u8, old_size: usize, align: usize, new_size: usize) -> *mut u8 {
+    use alloc::alloc::{alloc, realloc};
+
+    if new_size == 0 {
+        return align as *mut u8;
+    }
+    let ptr = if old_size == 0 {
+        alloc(Layout::from_size_align_unchecked(new_size, align))
+    } else {
+        realloc(old, Layout::from_size_align_unchecked(old_size, align), new_size)
+    };
+    if ptr.is_null() {
+        core::arch::wasm32::unreachable();
+    }
+    ptr
+}
+
+/// Frees spilled parameters the host allocated with `cabi_realloc`
+///
+/// # Safety
+///
+/// `ptr` must have been allocated with this size and alignment.
+pub unsafe fn free(ptr: *mut u8, size: usize, align: usize) {
+    if size > 0 {
+        dealloc(ptr, Layout::from_size_align_unchecked(size, align));
+    }
+}
+
+/// Memory layout of `T` as `(size, align)`
+pub const fn layout<T: ComponentValue>() -> (usize, usize) {
+    (T::SIZE, T::ALIGN)
+}
+
+/// Layout and flat count of a variant case's payload
+pub const fn case<T: ComponentValue>() -> (usize, usize, usize) {
+    (T::SIZE, T::ALIGN, T::FLAT_COUNT)
+}
+
+/// Rounds `offset` up to a multiple of `align`
+pub const fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Offset of each field of a record
+pub const fn field_offsets<const N: usize>(fields: [(usize, usize); N]) -> [usize; N] {
+    let mut offsets = [0; N];
+    let mut offset = 0;
+    let mut i = 0;
+    while i < N {
+        offset = align_to(offset, fields[i].1);
+        offsets[i] = offset;
+        offset += fields[i].0;
+        i += 1;
+    }
+    offsets
+}
+
+/// Alignment of a record, the largest of its fields
+pub const fn record_align(fields: &[(usize, usize)]) -> usize {
+    let mut align = 1;
+    let mut i = 0;
+    while i < fields.len() {
+        if fields[i].1 > align {
+            align = fields[i].1;
+        }
+        i += 1;
+    }
+    align
+}
+
+/// Size of a record, padded to its alignment
+pub const fn record_size(fields: &[(usize, usize)]) -> usize {
+    let mut offset = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        offset = align_to(offset, fields[i].1) + fields[i].0;
+        i += 1;
+    }
+    align_to(offset, record_align(fields))
+}
+
+/// Bytes of the discriminant of a variant with `cases` cases
+pub const fn discriminant_size(cases: usize) -> usize {
+    if cases <= 1 << 8 {
+        1
+    } else if cases <= 1 << 16 {
+        2
+    } else {
+        4
+    }
+}
+
+/// Alignment of a variant, covering its discriminant and every payload
+pub const fn variant_align(cases: &[(usize, usize, usize)]) -> usize {
+    let mut align = discriminant_size(cases.len());
+    let mut i = 0;
+    while i < cases.len() {
+        if cases[i].1 > align {
+            align = cases[i].1;
+        }
+        i += 1;
+    }
+    align
+}
+
+/// Offset of a variant's payload, after the discriminant
+pub const fn variant_payload_offset(cases: &[(usize, usize, usize)]) -> usize {
+    let mut align = 1;
+    let mut i = 0;
+    while i < cases.len() {
+        if cases[i].1 > align {
+            align = cases[i].1;
+        }
+        i += 1;
+    }
+    align_to(discriminant_size(cases.len()), align)
+}
+
+/// Size of a variant, padded to its alignment
+pub const fn variant_size(cases: &[(usize, usize, usize)]) -> usize {
+    let mut size = 0;
+    let mut i = 0;
+    while i < cases.len() {
+        if cases[i].0 > size {
+            size = cases[i].0;
+        }
+        i += 1;
+    }
+    align_to(variant_payload_offset(cases) + size, variant_align(cases))
+}
+
+/// Flat count of a variant: the discriminant and the widest payload
+pub const fn variant_flat_count(cases: &[(usize, usize, usize)]) -> usize {
+    let mut width = 0;
+    let mut i = 0;
+    while i < cases.len() {
+        if cases[i].2 > width {
+            width = cases[i].2;
+        }
+        i += 1;
+    }
+    1 + width
+}
+
+/// Lowers a case payload, padding the joined slots it does not use
+pub fn lower_case<T: ComponentValue>(value: &T, width: usize, flat: &mut Vec<u64>, allocs: &mut Allocations) {
+    let end = flat.len() + width;
+    value.lower(flat, allocs);
+    flat.resize(end, 0);
+}
+
+/// Lifts a case payload, skipping the joined slots it does not use
+///
+/// # Safety
+///
+/// As for `ComponentValue::lift`.
+pub unsafe fn lift_case<T: ComponentValue>(flat: &mut Iter<'_, u64>, width: usize) -> T {
+    let value = T::lift(flat);
+    for _ in T::FLAT_COUNT..width {
+        flat.next();
+    }
+    value
+}
+
+/// Reads the next flat value
+pub fn next_flat(flat: &mut Iter<'_, u64>) -> u64 {
+    flat.next().copied().expect("missing canonical ABI value")
+}
+
+/// Writes a variant discriminant
+///
+/// # Safety
+///
+/// `ptr` must be valid for the discriminant's size.
+pub unsafe fn store_discriminant(ptr: *mut u8, cases: usize, discriminant: u32) {
+    match discriminant_size(cases) {
+        1 => ptr.write(discriminant as u8),
+        2 => (ptr as *mut u16).write_unaligned(discriminant as u16),
+        _ => (ptr as *mut u32).write_unaligned(discriminant),
+    }
+}
+
+/// Reads a variant discriminant
+///
+/// # Safety
+///
+/// `ptr` must be valid for the discriminant's size.
+pub unsafe fn load_discriminant(ptr: *const u8, cases: usize) -> u32 {
+    match discriminant_size(cases) {
+        1 => u32::from(ptr.read()),
+        2 => u32::from((ptr as *const u16).read_unaligned()),
+        _ => (ptr as *const u32).read_unaligned(),
+    }
+}
+
+/// Traps on a discriminant the type does not define
+pub fn invalid_discriminant(discriminant: u32) -> ! {
+    panic!("invalid canonical ABI discriminant {}", discriminant)
+}
+
+macro_rules! impl_scalar {
+    ($($ty:ty => $size:literal, $lower:expr, $lift:expr;)*) => {$(
+        unsafe impl ComponentValue for $ty {
+            const SIZE: usize = $size;
+            const ALIGN: usize = $size;
+            const FLAT_COUNT: usize = 1;
+
+            fn lower(&self, flat: &mut Vec<u64>, _: &mut Allocations) {
+                let lower: fn($ty) -> u64 = $lower;
+                flat.push(lower(*self));
+            }
+
+            unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+                let lift: fn(u64) -> $ty = $lift;
+                lift(next_flat(flat))
+            }
+
+            unsafe fn store(&self, ptr: *mut u8, _: &mut Allocations) {
+                (ptr as *mut $ty).write_unaligned(*self);
+            }
+
+            unsafe fn load(ptr: *const u8) -> Self {
+                (ptr as *const $ty).read_unaligned()
+            }
+        }
+    )*};
+}
+
+impl_scalar! {
+    u8 => 1, u64::from, |bits| bits as u8;
+    i8 => 1, |v| u64::from(v as i32 as u32), |bits| bits as i8;
+    u16 => 2, u64::from, |bits| bits as u16;
+    i16 => 2, |v| u64::from(v as i32 as u32), |bits| bits as i16;
+    u32 => 4, u64::from, |bits| bits as u32;
+    i32 => 4, |v| u64::from(v as u32), |bits| bits as i32;
+    u64 => 8, |v| v, |bits| bits;
+    i64 => 8, |v| v as u64, |bits| bits as i64;
+    f32 => 4, |v| u64::from(v.to_bits()), |bits| f32::from_bits(bits as u32);
+    f64 => 8, |v| v.to_bits(), f64::from_bits;
+}
+
+unsafe impl ComponentValue for bool {
+    const SIZE: usize = 1;
+    const ALIGN: usize = 1;
+    const FLAT_COUNT: usize = 1;
+
+    fn lower(&self, flat: &mut Vec<u64>, _: &mut Allocations) {
+        flat.push(u64::from(*self));
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        next_flat(flat) as u32 != 0
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, _: &mut Allocations) {
+        ptr.write(u8::from(*self));
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        ptr.read() != 0
+    }
+}
+
+unsafe impl ComponentValue for char {
+    const SIZE: usize = 4;
+    const ALIGN: usize = 4;
+    const FLAT_COUNT: usize = 1;
+
+    fn lower(&self, flat: &mut Vec<u64>, _: &mut Allocations) {
+        flat.push(u64::from(u32::from(*self)));
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        char::from_u32(next_flat(flat) as u32).expect("invalid canonical ABI char")
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, _: &mut Allocations) {
+        (ptr as *mut u32).write_unaligned(u32::from(*self));
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        char::from_u32((ptr as *const u32).read_unaligned()).expect("invalid canonical ABI char")
+    }
+}
+
+/// Payload of `result` cases and functions without a value
+unsafe impl ComponentValue for () {
+    const SIZE: usize = 0;
+    const ALIGN: usize = 1;
+    const FLAT_COUNT: usize = 0;
+
+    fn lower(&self, _: &mut Vec<u64>, _: &mut Allocations) {}
+
+    unsafe fn lift(_: &mut Iter<'_, u64>) -> Self {}
+
+    unsafe fn store(&self, _: *mut u8, _: &mut Allocations) {}
+
+    unsafe fn load(_: *const u8) -> Self {}
+}
+
+unsafe impl ComponentValue for String {
+    const SIZE: usize = 8;
+    const ALIGN: usize = 4;
+    const FLAT_COUNT: usize = 2;
+
+    fn lower(&self, flat: &mut Vec<u64>, _: &mut Allocations) {
+        flat.push(self.as_ptr() as usize as u64);
+        flat.push(self.len() as u64);
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        let ptr = next_flat(flat) as usize as *mut u8;
+        let len = next_flat(flat) as usize;
+        take_string(ptr, len)
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, _: &mut Allocations) {
+        (ptr as *mut u32).write_unaligned(self.as_ptr() as u32);
+        (ptr.add(4) as *mut u32).write_unaligned(self.len() as u32);
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        let data = (ptr as *const u32).read_unaligned() as usize as *mut u8;
+        let len = (ptr.add(4) as *const u32).read_unaligned() as usize;
+        take_string(data, len)
+    }
+}
+
+/// Takes a UTF-8 buffer the host allocated with `cabi_realloc`
+unsafe fn take_string(ptr: *mut u8, len: usize) -> String {
+    if len == 0 {
+        return String::new();
+    }
+    let bytes = Vec::from_raw_parts(ptr, len, len);
+    String::from_utf8(bytes).expect("invalid canonical ABI string")
+}
+
+unsafe impl<T: ComponentValue> ComponentValue for Vec<T> {
+    const SIZE: usize = 8;
+    const ALIGN: usize = 4;
+    const FLAT_COUNT: usize = 2;
+
+    fn lower(&self, flat: &mut Vec<u64>, allocs: &mut Allocations) {
+        let ptr = store_elements(self, allocs);
+        flat.push(ptr as usize as u64);
+        flat.push(self.len() as u64);
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        let ptr = next_flat(flat) as usize as *mut u8;
+        let len = next_flat(flat) as usize;
+        take_elements(ptr, len)
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, allocs: &mut Allocations) {
+        let elements = store_elements(self, allocs);
+        (ptr as *mut u32).write_unaligned(elements as u32);
+        (ptr.add(4) as *mut u32).write_unaligned(self.len() as u32);
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        let elements = (ptr as *const u32).read_unaligned() as usize as *mut u8;
+        let len = (ptr.add(4) as *const u32).read_unaligned() as usize;
+        take_elements(elements, len)
+    }
+}
+
+/// Copies list elements into a canonical buffer
+fn store_elements<T: ComponentValue>(elements: &[T], allocs: &mut Allocations) -> *mut u8 {
+    let stride = align_to(T::SIZE, T::ALIGN);
+    let ptr = allocs.alloc(stride * elements.len(), T::ALIGN);
+    for (i, element) in elements.iter().enumerate() {
+        // SAFETY: the buffer holds `elements.len()` strides
+        unsafe { element.store(ptr.add(i * stride), allocs) };
+    }
+    ptr
+}
+
+/// Loads list elements from a buffer the host allocated, then frees it
+unsafe fn take_elements<T: ComponentValue>(ptr: *mut u8, len: usize) -> Vec<T> {
+    let stride = align_to(T::SIZE, T::ALIGN);
+    let elements = (0..len).map(|i| T::load(ptr.add(i * stride))).collect();
+    if stride * len > 0 {
+        dealloc(ptr, Layout::from_size_align_unchecked(stride * len, T::ALIGN));
+    }
+    elements
+}
+
+unsafe impl<T: ComponentValue> ComponentValue for Option<T> {
+    const SIZE: usize = variant_size(&[case::<()>(), case::<T>()]);
+    const ALIGN: usize = variant_align(&[case::<()>(), case::<T>()]);
+    const FLAT_COUNT: usize = variant_flat_count(&[case::<()>(), case::<T>()]);
+
+    fn lower(&self, flat: &mut Vec<u64>, allocs: &mut Allocations) {
+        match self {
+            None => {
+                flat.push(0);
+                lower_case(&(), Self::FLAT_COUNT - 1, flat, allocs);
+            }
+            Some(value) => {
+                flat.push(1);
+                lower_case(value, Self::FLAT_COUNT - 1, flat, allocs);
+            }
+        }
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        match next_flat(flat) as u32 {
+            0 => {
+                lift_case::<()>(flat, Self::FLAT_COUNT - 1);
+                None
+            }
+            1 => Some(lift_case(flat, Self::FLAT_COUNT - 1)),
+            other => invalid_discriminant(other),
+        }
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, allocs: &mut Allocations) {
+        let payload = ptr.add(variant_payload_offset(&[case::<()>(), case::<T>()]));
+        match self {
+            None => store_discriminant(ptr, 2, 0),
+            Some(value) => {
+                store_discriminant(ptr, 2, 1);
+                value.store(payload, allocs);
+            }
+        }
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        let payload = ptr.add(variant_payload_offset(&[case::<()>(), case::<T>()]));
+        match load_discriminant(ptr, 2) {
+            0 => None,
+            1 => Some(T::load(payload)),
+            other => invalid_discriminant(other),
+        }
+    }
+}
+
+unsafe impl<T: ComponentValue, E: ComponentValue> ComponentValue for Result<T, E> {
+    const SIZE: usize = variant_size(&[case::<T>(), case::<E>()]);
+    const ALIGN: usize = variant_align(&[case::<T>(), case::<E>()]);
+    const FLAT_COUNT: usize = variant_flat_count(&[case::<T>(), case::<E>()]);
+
+    fn lower(&self, flat: &mut Vec<u64>, allocs: &mut Allocations) {
+        match self {
+            Ok(value) => {
+                flat.push(0);
+                lower_case(value, Self::FLAT_COUNT - 1, flat, allocs);
+            }
+            Err(error) => {
+                flat.push(1);
+                lower_case(error, Self::FLAT_COUNT - 1, flat, allocs);
+            }
+        }
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        match next_flat(flat) as u32 {
+            0 => Ok(lift_case(flat, Self::FLAT_COUNT - 1)),
+            1 => Err(lift_case(flat, Self::FLAT_COUNT - 1)),
+            other => invalid_discriminant(other),
+        }
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, allocs: &mut Allocations) {
+        let payload = ptr.add(variant_payload_offset(&[case::<T>(), case::<E>()]));
+        match self {
+            Ok(value) => {
+                store_discriminant(ptr, 2, 0);
+                value.store(payload, allocs);
+            }
+            Err(error) => {
+                store_discriminant(ptr, 2, 1);
+                error.store(payload, allocs);
+            }
+        }
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        let payload = ptr.add(variant_payload_offset(&[case::<T>(), case::<E>()]));
+        match load_discriminant(ptr, 2) {
+            0 => Ok(T::load(payload)),
+            1 => Err(E::load(payload)),
+            other => invalid_discriminant(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_flat<T: ComponentValue>(value: &T) -> (Vec<u64>, T) {
+        let mut flat = Vec::new();
+        value.lower(&mut flat, &mut Allocations::new());
+        assert_eq!(flat.len(), T::FLAT_COUNT);
+        // SAFETY: the values were lowered for `T` just above
+        let lifted = unsafe { T::lift(&mut flat.iter()) };
+        (flat, lifted)
+    }
+
+    #[test]
+    fn test_variant_flattening_joins_payloads() {
+        assert_eq!(round_trip_flat(&-1i8), (alloc::vec![0xffff_ffff], -1));
+        assert_eq!(round_trip_flat(&Some(2.5f32)).1, Some(2.5));
+
+        // `f64` and `u8` share one i64 slot; `None` pads it with zero
+        let value: Result<f64, u8> = Err(7);
+        assert_eq!(round_trip_flat(&value), (alloc::vec![1, 7], Err(7)));
+        assert_eq!(round_trip_flat(&None::<Result<f64, u8>>).0, alloc::vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_layouts_follow_canonical_abi() {
+        assert_eq!(field_offsets([layout::<u8>(), layout::<u32>(), layout::<u16>()]), [0, 4, 8]);
+        assert_eq!(record_size(&[layout::<u8>(), layout::<u32>(), layout::<u16>()]), 12);
+        assert_eq!((<Option<u64>>::SIZE, <Option<u64>>::ALIGN), (16, 8));
+        assert_eq!((<Result<(), u8>>::SIZE, <Result<(), u8>>::ALIGN), (2, 1));
+        assert_eq!(<Vec<String>>::FLAT_COUNT, 2);
+        assert_eq!(discriminant_size(300), 2);
+    }
+
+    #[test]
+    fn test_store_and_load_in_memory() {
+        let value: Option<Result<u32, char>> = Some(Err('λ'));
+        let mut allocs = Allocations::new();
+        let ptr = allocs.alloc(<Option<Result<u32, char>>>::SIZE, <Option<Result<u32, char>>>::ALIGN);
+        // SAFETY: the area was allocated for the type
+        let loaded = unsafe {
+            value.store(ptr, &mut allocs);
+            <Option<Result<u32, char>>>::load(ptr)
+        };
+        assert_eq!(loaded, value);
+
+        let area = return_area(0x1234_5678u64);
+        // SAFETY: the area holds a stored u64 until `post_return`
+        assert_eq!(unsafe { u64::load(area) }, 0x1234_5678);
+        post_return();
+    }
+}
@@ -0,0 +1,697 @@
+//! Adapting core modules into components
+//!
+//! Modules built by other toolchains export plain core functions and import
+//! plain core functions. `Adapter::adapt` matches them against the
+//! functions of a WIT world, checks each core signature against the
+//! canonical ABI flattening of the WIT signature, and picks the memory,
+//! `realloc`, and post-return functions the canonical adapters need. The
+//! resulting `ComponentAdapter` lists one `canon lift` per exported
+//! function and one `canon lower` per imported one.
+//!
+//! Core exports are found under `<interface>#<func>`, the unqualified
+//! interface name, or the bare function name with `-` or `_`; core imports
+//! match when their module names the interface. Both can be overridden.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use super::canon_async::MAX_FLAT_PARAMS;
+use super::introspect::{InspectError, Reader, StringEncoding, MAX_FLAT_RESULTS};
+use super::wit::{WitError, WitInterface, WitPackage, WitTypeDefKind};
+use super::{ComponentFunction, WitType};
+use crate::wasmir::Type;
+
+/// Exports tried, in order, for the `realloc` the canonical ABI allocates with
+const REALLOC_EXPORTS: [&str; 2] = ["cabi_realloc", "canonical_abi_realloc"];
+
+/// Adapter generation errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterError {
+    /// The core module could not be parsed
+    Inspect(InspectError),
+    /// The world could not be resolved
+    Wit(WitError),
+    /// No core export implements an exported function
+    MissingExport(String),
+    /// A core import matches no function the world imports
+    UnresolvedImport { module: String, name: String },
+    /// A core function's signature is not the canonical ABI flattening
+    SignatureMismatch { function: String, expected: CoreSignature, found: CoreSignature },
+    /// A function passes values through memory but the module exports none
+    MissingMemory(String),
+    /// A function receives strings or lists but the module exports no `realloc`
+    MissingRealloc(String),
+    /// The module or world uses something adapters cannot express
+    Unsupported(String),
+}
+
+impl core::fmt::Display for AdapterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AdapterError::Inspect(err) => write!(f, "{}", err),
+            AdapterError::Wit(err) => write!(f, "{}", err),
+            AdapterError::MissingExport(function) => write!(f, "No core export implements {}", function),
+            AdapterError::UnresolvedImport { module, name } => {
+                write!(f, "Core import {}.{} matches no imported function", module, name)
+            }
+            AdapterError::SignatureMismatch { function, expected, found } => {
+                write!(f, "Core signature of {} is {}, expected {}", function, found, expected)
+            }
+            AdapterError::MissingMemory(function) => write!(f, "{} needs an exported memory", function),
+            AdapterError::MissingRealloc(function) => write!(f, "{} needs an exported cabi_realloc", function),
+            AdapterError::Unsupported(what) => write!(f, "Unsupported by adapters: {}", what),
+        }
+    }
+}
+
+impl From<InspectError> for AdapterError {
+    fn from(err: InspectError) -> Self {
+        AdapterError::Inspect(err)
+    }
+}
+
+impl From<WitError> for AdapterError {
+    fn from(err: WitError) -> Self {
+        AdapterError::Wit(err)
+    }
+}
+
+/// Core function signature
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoreSignature {
+    pub params: Vec<Type>,
+    pub results: Vec<Type>,
+}
+
+impl core::fmt::Display for CoreSignature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let names = |types: &[Type]| types.iter().map(core_type_name).collect::<Vec<_>>().join(", ");
+        write!(f, "({}) -> ({})", names(&self.params), names(&self.results))
+    }
+}
+
+/// Exported function lifted from a core export
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiftedExport {
+    /// Qualified name of the exported interface
+    pub interface: String,
+    pub function: ComponentFunction,
+    pub core_export: String,
+    pub signature: CoreSignature,
+    /// Core export that frees the function's results
+    pub post_return: Option<String>,
+    /// Whether the adapter needs the memory and `realloc` options
+    pub uses_memory: bool,
+    pub uses_realloc: bool,
+}
+
+/// Imported function lowered into a core import
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoweredImport {
+    /// Qualified name of the imported interface
+    pub interface: String,
+    pub function: ComponentFunction,
+    pub core_module: String,
+    pub core_name: String,
+    pub signature: CoreSignature,
+    /// Whether the adapter needs the memory and `realloc` options
+    pub uses_memory: bool,
+    pub uses_realloc: bool,
+}
+
+/// Canonical adapters wrapping a core module into a component
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentAdapter {
+    pub world: String,
+    /// Core export of the memory the adapters read and write
+    pub memory: Option<String>,
+    /// Core export of `realloc`
+    pub realloc: Option<String>,
+    pub string_encoding: StringEncoding,
+    /// In core import order
+    pub imports: Vec<LoweredImport>,
+    pub exports: Vec<LiftedExport>,
+}
+
+/// Builder matching a core module against a world
+#[derive(Debug, Clone)]
+pub struct Adapter<'a> {
+    package: &'a WitPackage,
+    world: &'a str,
+    string_encoding: StringEncoding,
+    /// `(interface#func, core export)` overrides
+    export_names: Vec<(String, String)>,
+    /// `(core module, interface)` overrides
+    import_modules: Vec<(String, String)>,
+}
+
+impl<'a> Adapter<'a> {
+    pub fn new(package: &'a WitPackage, world: &'a str) -> Self {
+        Self {
+            package,
+            world,
+            string_encoding: StringEncoding::Utf8,
+            export_names: Vec::new(),
+            import_modules: Vec::new(),
+        }
+    }
+
+    /// Sets the encoding of strings in the module's memory
+    pub fn string_encoding(mut self, encoding: StringEncoding) -> Self {
+        self.string_encoding = encoding;
+        self
+    }
+
+    /// Implements `interface#func` with the core export `core_export`
+    pub fn export(mut self, function: impl Into<String>, core_export: impl Into<String>) -> Self {
+        self.export_names.push((function.into(), core_export.into()));
+        self
+    }
+
+    /// Satisfies the core imports from `core_module` with `interface`
+    pub fn import_module(mut self, core_module: impl Into<String>, interface: impl Into<String>) -> Self {
+        self.import_modules.push((core_module.into(), interface.into()));
+        self
+    }
+
+    /// Matches a core module binary against the world
+    pub fn adapt(&self, module: &[u8]) -> Result<ComponentAdapter, AdapterError> {
+        let core = parse_module(module)?;
+        let interfaces = self.package.world_interfaces(self.world)?;
+        let mut memory_user = None;
+        let mut realloc_user = None;
+
+        let mut imports = Vec::new();
+        for (module, name, signature) in &core.imports {
+            let unresolved = || AdapterError::UnresolvedImport { module: module.clone(), name: name.clone() };
+            let target = self.import_modules.iter()
+                .find(|(core_module, _)| core_module == module)
+                .map(|(_, interface)| interface.as_str())
+                .unwrap_or(module);
+            let (qualified, interface) = interfaces.imports.iter()
+                .find(|(qualified, interface)| qualified == target || interface.name == target)
+                .ok_or_else(unresolved)?;
+            let function = interface.functions.iter()
+                .find(|function| function.name == *name || function.name.replace('-', "_") == *name)
+                .ok_or_else(unresolved)?;
+            let display = format!("{}#{}", qualified, function.name);
+            let expected = self.core_signature(interface, function, false)?;
+            check_signature(&display, &expected, signature)?;
+
+            let (memory, realloc) = self.memory_needs(interface, function, false)?;
+            if memory {
+                memory_user.get_or_insert_with(|| display.clone());
+            }
+            if realloc {
+                realloc_user.get_or_insert(display);
+            }
+            imports.push(LoweredImport {
+                interface: qualified.clone(),
+                function: function.clone(),
+                core_module: module.clone(),
+                core_name: name.clone(),
+                signature: expected,
+                uses_memory: memory,
+                uses_realloc: realloc,
+            });
+        }
+
+        let mut exports = Vec::new();
+        for (qualified, interface) in &interfaces.exports {
+            for function in &interface.functions {
+                let display = format!("{}#{}", qualified, function.name);
+                let core_export = self.find_export(&core, qualified, interface, function)
+                    .ok_or_else(|| AdapterError::MissingExport(display.clone()))?;
+                let expected = self.core_signature(interface, function, true)?;
+                check_signature(&display, &expected, core.func_export(&core_export).expect("found above"))?;
+
+                let post_return = format!("cabi_post_{}", core_export);
+                let post_return = match core.func_export(&post_return) {
+                    Some(signature) => {
+                        let expected = CoreSignature { params: expected.results.clone(), results: Vec::new() };
+                        check_signature(&post_return, &expected, signature)?;
+                        Some(post_return)
+                    }
+                    None => None,
+                };
+
+                let (memory, realloc) = self.memory_needs(interface, function, true)?;
+                if memory {
+                    memory_user.get_or_insert_with(|| display.clone());
+                }
+                if realloc {
+                    realloc_user.get_or_insert(display);
+                }
+                exports.push(LiftedExport {
+                    interface: qualified.clone(),
+                    function: function.clone(),
+                    core_export,
+                    signature: expected,
+                    post_return,
+                    uses_memory: memory,
+                    uses_realloc: realloc,
+                });
+            }
+        }
+
+        let memory = match memory_user {
+            Some(function) => Some(core.memory.clone().ok_or(AdapterError::MissingMemory(function))?),
+            None => None,
+        };
+        let realloc = match realloc_user {
+            Some(function) => {
+                let name = REALLOC_EXPORTS.iter()
+                    .find(|name| core.func_export(name).is_some())
+                    .ok_or(AdapterError::MissingRealloc(function))?;
+                let expected = CoreSignature {
+                    params: alloc::vec![Type::I32; 4],
+                    results: alloc::vec![Type::I32],
+                };
+                check_signature(name, &expected, core.func_export(name).expect("found above"))?;
+                Some(name.to_string())
+            }
+            None => None,
+        };
+
+        Ok(ComponentAdapter {
+            world: self.world.to_string(),
+            memory,
+            realloc,
+            string_encoding: self.string_encoding,
+            imports,
+            exports,
+        })
+    }
+
+    fn find_export(
+        &self,
+        core: &CoreModule,
+        qualified: &str,
+        interface: &WitInterface,
+        function: &ComponentFunction,
+    ) -> Option<String> {
+        let qualified_func = format!("{}#{}", qualified, function.name);
+        if let Some((_, name)) = self.export_names.iter()
+            .find(|(func, _)| *func == qualified_func || *func == format!("{}#{}", interface.name, function.name))
+        {
+            return core.func_export(name).map(|_| name.clone());
+        }
+        [
+            qualified_func,
+            format!("{}#{}", interface.name, function.name),
+            function.name.clone(),
+            function.name.replace('-', "_"),
+        ]
+        .into_iter()
+        .find(|name| core.func_export(name).is_some())
+    }
+
+    /// Canonical ABI core signature of a lifted or lowered function
+    ///
+    /// Functions using resources or async are rejected.
+    pub fn core_signature(
+        &self,
+        interface: &WitInterface,
+        function: &ComponentFunction,
+        lift: bool,
+    ) -> Result<CoreSignature, AdapterError> {
+        let display = || format!("{}.{}", interface.name, function.name);
+        if function.is_async {
+            return Err(AdapterError::Unsupported(format!("async function {}", display())));
+        }
+        for ty in function.types() {
+            self.check_type(interface, ty, &display())?;
+        }
+
+        let mut params = Vec::new();
+        for (_, ty) in &function.params {
+            self.package.flatten(interface, ty, &mut params)?;
+        }
+        if params.len() > MAX_FLAT_PARAMS {
+            params = alloc::vec![Type::I32];
+        }
+        let mut results = Vec::new();
+        if let Some(result) = &function.result {
+            self.package.flatten(interface, result, &mut results)?;
+        }
+        if results.len() > MAX_FLAT_RESULTS {
+            // Lifted functions return a pointer; lowered ones take one to fill
+            results = Vec::new();
+            if lift {
+                results.push(Type::I32);
+            } else {
+                params.push(Type::I32);
+            }
+        }
+        Ok(CoreSignature { params, results })
+    }
+
+    /// Whether a function needs the memory, and whether it needs `realloc`
+    fn memory_needs(
+        &self,
+        interface: &WitInterface,
+        function: &ComponentFunction,
+        lift: bool,
+    ) -> Result<(bool, bool), AdapterError> {
+        let mut params_in_memory = false;
+        let mut flat_params = Vec::new();
+        for (_, ty) in &function.params {
+            params_in_memory |= self.package.uses_memory(interface, ty)?;
+            self.package.flatten(interface, ty, &mut flat_params)?;
+        }
+        let mut results_in_memory = false;
+        let mut flat_results = Vec::new();
+        if let Some(result) = &function.result {
+            results_in_memory = self.package.uses_memory(interface, result)?;
+            self.package.flatten(interface, result, &mut flat_results)?;
+        }
+        let spills = flat_params.len() > MAX_FLAT_PARAMS || flat_results.len() > MAX_FLAT_RESULTS;
+        let memory = params_in_memory || results_in_memory || spills;
+        // The side receiving allocated values places them with realloc
+        let realloc = if lift {
+            params_in_memory || flat_params.len() > MAX_FLAT_PARAMS
+        } else {
+            results_in_memory
+        };
+        Ok((memory, realloc))
+    }
+
+    /// Rejects types that need resource or async support
+    fn check_type(&self, interface: &WitInterface, ty: &WitType, function: &str) -> Result<(), AdapterError> {
+        let mut result = Ok(());
+        ty.visit(&mut |ty| {
+            if result.is_err() {
+                return;
+            }
+            result = match ty {
+                WitType::Future(_) | WitType::Stream(_) => {
+                    Err(AdapterError::Unsupported(format!("{} in {}", ty, function)))
+                }
+                WitType::Own(name) | WitType::Borrow(name) => {
+                    Err(AdapterError::Unsupported(format!("resource {} in {}", name, function)))
+                }
+                WitType::Named(name) => match self.package.resolve_type(interface, name) {
+                    Ok((_, def)) if def.kind == WitTypeDefKind::Resource => {
+                        Err(AdapterError::Unsupported(format!("resource {} in {}", name, function)))
+                    }
+                    Ok((owner, def)) => {
+                        let members: Vec<&WitType> = match &def.kind {
+                            WitTypeDefKind::Alias(ty) => alloc::vec![ty],
+                            WitTypeDefKind::Record(fields) => fields.iter().map(|(_, ty)| ty).collect(),
+                            WitTypeDefKind::Variant(cases) => cases.iter().filter_map(|(_, ty)| ty.as_ref()).collect(),
+                            _ => Vec::new(),
+                        };
+                        members.into_iter().try_for_each(|member| self.check_type(owner, member, function))
+                    }
+                    Err(err) => Err(err.into()),
+                },
+                _ => Ok(()),
+            };
+        });
+        result
+    }
+}
+
+fn check_signature(function: &str, expected: &CoreSignature, found: &CoreSignature) -> Result<(), AdapterError> {
+    if expected != found {
+        return Err(AdapterError::SignatureMismatch {
+            function: function.to_string(),
+            expected: expected.clone(),
+            found: found.clone(),
+        });
+    }
+    Ok(())
+}
+
+fn core_type_name(ty: &Type) -> &'static str {
+    match ty {
+        Type::I64 => "i64",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        _ => "i32",
+    }
+}
+
+/// Function imports and exports of a core module
+struct CoreModule {
+    /// `(module, name, signature)` of each function import
+    imports: Vec<(String, String, CoreSignature)>,
+    /// Exported functions and their signatures
+    exports: Vec<(String, CoreSignature)>,
+    /// Name of the exported memory
+    memory: Option<String>,
+}
+
+impl CoreModule {
+    fn func_export(&self, name: &str) -> Option<&CoreSignature> {
+        self.exports.iter().find(|(export, _)| export == name).map(|(_, signature)| signature)
+    }
+}
+
+fn parse_module(bytes: &[u8]) -> Result<CoreModule, AdapterError> {
+    let mut reader = Reader::new(bytes, 1);
+    if reader.take(4)? != b"\0asm" {
+        return Err(InspectError::BadMagic.into());
+    }
+    if reader.take(4)? != [1, 0, 0, 0] {
+        return Err(reader.error("not a core module").into());
+    }
+
+    let mut types = Vec::new();
+    // Type index of every function, imports first
+    let mut funcs = Vec::new();
+    let mut module = CoreModule { imports: Vec::new(), exports: Vec::new(), memory: None };
+    let mut export_indices = Vec::new();
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut section = reader.sub(size, 1)?;
+        match id {
+            1 => {
+                for _ in 0..section.u32()? {
+                    if section.u8()? != 0x60 {
+                        return Err(AdapterError::Unsupported("core types other than functions".to_string()));
+                    }
+                    let mut signature = CoreSignature::default();
+                    for list in [&mut signature.params, &mut signature.results] {
+                        for _ in 0..section.u32()? {
+                            list.push(match section.u8()? {
+                                0x7f => Type::I32,
+                                0x7e => Type::I64,
+                                0x7d => Type::F32,
+                                0x7c => Type::F64,
+                                _ => return Err(AdapterError::Unsupported("core value types other than numbers".to_string())),
+                            });
+                        }
+                    }
+                    types.push(signature);
+                }
+            }
+            2 => {
+                for _ in 0..section.u32()? {
+                    let from = section.name()?;
+                    let name = section.name()?;
+                    if section.u8()? != 0x00 {
+                        return Err(AdapterError::Unsupported(format!("core import {}.{} is not a function", from, name)));
+                    }
+                    let ty = section.u32()?;
+                    let signature = types.get(ty as usize).cloned().ok_or_else(|| section.error("type index out of bounds"))?;
+                    module.imports.push((from, name, signature));
+                    funcs.push(ty);
+                }
+            }
+            3 => {
+                for _ in 0..section.u32()? {
+                    funcs.push(section.u32()?);
+                }
+            }
+            7 => {
+                for _ in 0..section.u32()? {
+                    let name = section.name()?;
+                    let kind = section.u8()?;
+                    let index = section.u32()?;
+                    match kind {
+                        0x00 => export_indices.push((name, index)),
+                        0x02 => {
+                            module.memory.get_or_insert(name);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (name, index) in export_indices {
+        let signature = funcs.get(index as usize)
+            .and_then(|ty| types.get(*ty as usize))
+            .cloned()
+            .ok_or_else(|| reader.error("exported function index out of bounds"))?;
+        module.exports.push((name, signature));
+    }
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORLD: &str = r#"
+        package example:plugin@1.0.0;
+
+        interface logger {
+            log: func(message: string);
+        }
+
+        interface greeter {
+            greet: func(name: string) -> string;
+            add: func(a: s32, b: s64) -> s64;
+        }
+
+        world plugin {
+            import logger;
+            export greeter;
+        }
+    "#;
+
+    fn leb(value: u32, out: &mut Vec<u8>) {
+        let mut value = value;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn name(text: &str, out: &mut Vec<u8>) {
+        leb(text.len() as u32, out);
+        out.extend_from_slice(text.as_bytes());
+    }
+
+    fn section(id: u8, contents: &[u8], out: &mut Vec<u8>) {
+        out.push(id);
+        leb(contents.len() as u32, out);
+        out.extend_from_slice(contents);
+    }
+
+    /// Core module importing `log` from `import_module` and exporting the
+    /// named functions, each with a type index from the table below
+    fn core_module(import_module: &str, exports: &[(&str, u8)]) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // 0: (i32, i32) -> ()   1: (i32, i32) -> i32   2: (i32, i64) -> i64
+        // 3: (i32) -> ()        4: (i32, i32, i32, i32) -> i32
+        let types: [&[u8]; 5] = [
+            &[0x60, 2, 0x7f, 0x7f, 0],
+            &[0x60, 2, 0x7f, 0x7f, 1, 0x7f],
+            &[0x60, 2, 0x7f, 0x7e, 1, 0x7e],
+            &[0x60, 1, 0x7f, 0],
+            &[0x60, 4, 0x7f, 0x7f, 0x7f, 0x7f, 1, 0x7f],
+        ];
+        let mut contents = alloc::vec![types.len() as u8];
+        for ty in types {
+            contents.extend_from_slice(ty);
+        }
+        section(1, &contents, &mut module);
+
+        let mut imports = alloc::vec![1];
+        name(import_module, &mut imports);
+        name("log", &mut imports);
+        imports.extend([0x00, 0x00]);
+        section(2, &imports, &mut module);
+
+        let mut funcs = alloc::vec![exports.len() as u8];
+        funcs.extend(exports.iter().map(|(_, ty)| *ty));
+        section(3, &funcs, &mut module);
+
+        let mut contents = alloc::vec![exports.len() as u8 + 1];
+        name("memory", &mut contents);
+        contents.extend([0x02, 0x00]);
+        for (i, (export, _)) in exports.iter().enumerate() {
+            name(export, &mut contents);
+            contents.extend([0x00, i as u8 + 1]);
+        }
+        section(7, &contents, &mut module);
+        module
+    }
+
+    #[test]
+    fn test_adapt_matches_exports_and_imports() {
+        let package = WitPackage::parse(WORLD).unwrap();
+        let module = core_module("example:plugin/logger@1.0.0", &[
+            ("example:plugin/greeter@1.0.0#greet", 1),
+            ("cabi_post_example:plugin/greeter@1.0.0#greet", 3),
+            ("add", 2),
+            ("cabi_realloc", 4),
+        ]);
+        let adapter = Adapter::new(&package, "plugin").adapt(&module).unwrap();
+        assert_eq!(adapter.memory.as_deref(), Some("memory"));
+        assert_eq!(adapter.realloc.as_deref(), Some("cabi_realloc"));
+        assert_eq!(adapter.imports.len(), 1);
+        assert_eq!(adapter.imports[0].interface, "example:plugin/logger@1.0.0");
+        assert_eq!(adapter.imports[0].signature.to_string(), "(i32, i32) -> ()");
+
+        let greet = &adapter.exports[0];
+        assert_eq!(greet.core_export, "example:plugin/greeter@1.0.0#greet");
+        assert_eq!(greet.post_return.as_deref(), Some("cabi_post_example:plugin/greeter@1.0.0#greet"));
+        // Strings do not fit in one result, so greet returns a pointer
+        assert_eq!(greet.signature.to_string(), "(i32, i32) -> (i32)");
+        assert_eq!(adapter.exports[1].core_export, "add");
+        assert_eq!(adapter.exports[1].post_return, None);
+    }
+
+    #[test]
+    fn test_adapt_overrides_names() {
+        let package = WitPackage::parse(WORLD).unwrap();
+        let module = core_module("env", &[("say_hello", 1), ("greeter#add", 2), ("cabi_realloc", 4)]);
+        assert_eq!(
+            Adapter::new(&package, "plugin").adapt(&module).unwrap_err(),
+            AdapterError::UnresolvedImport { module: "env".to_string(), name: "log".to_string() }
+        );
+
+        let adapter = Adapter::new(&package, "plugin")
+            .import_module("env", "logger")
+            .export("greeter#greet", "say_hello")
+            .adapt(&module)
+            .unwrap();
+        assert_eq!(adapter.imports[0].core_module, "env");
+        assert_eq!(adapter.exports[0].core_export, "say_hello");
+        assert_eq!(adapter.exports[1].core_export, "greeter#add");
+    }
+
+    #[test]
+    fn test_adapt_errors() {
+        let package = WitPackage::parse(WORLD).unwrap();
+        let missing = core_module("logger", &[("greet", 1), ("cabi_realloc", 4)]);
+        assert_eq!(
+            Adapter::new(&package, "plugin").adapt(&missing).unwrap_err(),
+            AdapterError::MissingExport("example:plugin/greeter@1.0.0#add".to_string())
+        );
+
+        let mismatched = core_module("logger", &[("greet", 1), ("add", 1), ("cabi_realloc", 4)]);
+        let err = Adapter::new(&package, "plugin").adapt(&mismatched).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Core signature of example:plugin/greeter@1.0.0#add is (i32, i32) -> (i32), expected (i32, i64) -> (i64)"
+        );
+
+        let no_realloc = core_module("logger", &[("greet", 1), ("add", 2)]);
+        assert_eq!(
+            Adapter::new(&package, "plugin").adapt(&no_realloc).unwrap_err(),
+            AdapterError::MissingRealloc("example:plugin/greeter@1.0.0#greet".to_string())
+        );
+        assert_eq!(
+            Adapter::new(&package, "plugin").adapt(b"\0asm\x0d\0\x01\0").unwrap_err(),
+            AdapterError::Inspect(InspectError::MalformedSection {
+                id: 1,
+                offset: 8,
+                message: "not a core module".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,819 @@
+//! Component Model async ABI
+//!
+//! Tracks the async proposal: `future<T>` and `stream<T>` values are
+//! handles whose reads and writes go through per-type canonical built-ins,
+//! and blocked operations complete later as events on the task's waitable
+//! set. This module derives those built-ins (plus the task and waitable
+//! intrinsics) for an interface, and bridges them to Rust futures driven by
+//! `LocalExecutor`: the async export callback hands each event to `deliver`,
+//! which wakes the task waiting on that waitable.
+
+use super::{ComponentFunction, ComponentInterface, WitType};
+use crate::threading::Mutex;
+use crate::wasmir::{Signature, Type, WasmModule};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll, Waker};
+
+/// Import module of intrinsics that are not tied to an interface
+pub const ROOT_IMPORT_MODULE: &str = "$root";
+
+/// Return code of a copy that has not completed yet
+pub const BLOCKED: u32 = 0xffff_ffff;
+
+/// Core parameters beyond which `task.return` takes a pointer instead
+pub const MAX_FLAT_PARAMS: usize = 16;
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "$root")]
+extern "C" {
+    #[link_name = "[waitable-join]"]
+    fn waitable_join(waitable: u32, set: u32);
+}
+
+/// Outcome of a future or stream copy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyStatus {
+    /// The copy finished; the other end remains open
+    Completed,
+    /// The other end was dropped
+    Dropped,
+    /// The copy was cancelled before it finished
+    Cancelled,
+}
+
+/// Decoded return code of a read or write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyResult {
+    pub status: CopyStatus,
+    /// Number of values copied
+    pub count: u32,
+}
+
+impl CopyResult {
+    /// Decodes a return or event code, or `None` if the copy blocked
+    pub fn decode(code: u32) -> Option<Self> {
+        if code == BLOCKED {
+            return None;
+        }
+        let status = match code & 0xf {
+            0 => CopyStatus::Completed,
+            1 => CopyStatus::Dropped,
+            _ => CopyStatus::Cancelled,
+        };
+        Some(Self { status, count: code >> 4 })
+    }
+}
+
+/// Kind of event reported by `waitable-set.wait` or `poll`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    None,
+    Subtask,
+    StreamRead,
+    StreamWrite,
+    FutureRead,
+    FutureWrite,
+    TaskCancelled,
+}
+
+impl EventKind {
+    /// Decodes an event code
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code {
+            0 => EventKind::None,
+            1 => EventKind::Subtask,
+            2 => EventKind::StreamRead,
+            3 => EventKind::StreamWrite,
+            4 => EventKind::FutureRead,
+            5 => EventKind::FutureWrite,
+            6 => EventKind::TaskCancelled,
+            _ => return None,
+        })
+    }
+}
+
+/// Event delivered to an async export's callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Waitable the event concerns
+    pub waitable: u32,
+    /// Kind-specific payload, e.g. an encoded `CopyResult`
+    pub code: u32,
+}
+
+/// Whether a payload type is carried by a future or a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PayloadKind {
+    Future,
+    Stream,
+}
+
+impl PayloadKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            PayloadKind::Future => "future",
+            PayloadKind::Stream => "stream",
+        }
+    }
+}
+
+/// Per-type operation on a future or stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PayloadOp {
+    New,
+    Read,
+    Write,
+    CancelRead,
+    CancelWrite,
+    DropReadable,
+    DropWritable,
+}
+
+impl PayloadOp {
+    /// Every operation, in declaration order
+    pub const ALL: [PayloadOp; 7] = [
+        PayloadOp::New,
+        PayloadOp::Read,
+        PayloadOp::Write,
+        PayloadOp::CancelRead,
+        PayloadOp::CancelWrite,
+        PayloadOp::DropReadable,
+        PayloadOp::DropWritable,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            PayloadOp::New => "new",
+            PayloadOp::Read => "read",
+            PayloadOp::Write => "write",
+            PayloadOp::CancelRead => "cancel-read",
+            PayloadOp::CancelWrite => "cancel-write",
+            PayloadOp::DropReadable => "drop-readable",
+            PayloadOp::DropWritable => "drop-writable",
+        }
+    }
+}
+
+/// Canonical built-in imported by components using async
+#[derive(Debug, Clone, PartialEq)]
+pub enum CanonicalBuiltin {
+    /// `task.return` for an async export, taking its flattened result
+    TaskReturn { function: String, results: Vec<Type> },
+    ContextGet,
+    ContextSet,
+    Yield,
+    WaitableSetNew,
+    WaitableSetWait,
+    WaitableSetPoll,
+    WaitableSetDrop,
+    WaitableJoin,
+    SubtaskDrop,
+    /// Operation on the future or stream type with the given index
+    Payload { kind: PayloadKind, op: PayloadOp, index: u32 },
+}
+
+impl CanonicalBuiltin {
+    /// Intrinsics every async component imports
+    pub const TASK_INTRINSICS: [CanonicalBuiltin; 9] = [
+        CanonicalBuiltin::ContextGet,
+        CanonicalBuiltin::ContextSet,
+        CanonicalBuiltin::Yield,
+        CanonicalBuiltin::WaitableSetNew,
+        CanonicalBuiltin::WaitableSetWait,
+        CanonicalBuiltin::WaitableSetPoll,
+        CanonicalBuiltin::WaitableSetDrop,
+        CanonicalBuiltin::WaitableJoin,
+        CanonicalBuiltin::SubtaskDrop,
+    ];
+
+    /// Import field name
+    pub fn import_name(&self) -> String {
+        match self {
+            CanonicalBuiltin::TaskReturn { function, .. } => format!("[task-return]{}", function),
+            CanonicalBuiltin::ContextGet => "[context-get-0]".into(),
+            CanonicalBuiltin::ContextSet => "[context-set-0]".into(),
+            CanonicalBuiltin::Yield => "[yield]".into(),
+            CanonicalBuiltin::WaitableSetNew => "[waitable-set-new]".into(),
+            CanonicalBuiltin::WaitableSetWait => "[waitable-set-wait]".into(),
+            CanonicalBuiltin::WaitableSetPoll => "[waitable-set-poll]".into(),
+            CanonicalBuiltin::WaitableSetDrop => "[waitable-set-drop]".into(),
+            CanonicalBuiltin::WaitableJoin => "[waitable-join]".into(),
+            CanonicalBuiltin::SubtaskDrop => "[subtask-drop]".into(),
+            CanonicalBuiltin::Payload { kind, op, index } => {
+                format!("[{}-{}-{}]", kind.as_str(), op.as_str(), index)
+            }
+        }
+    }
+
+    /// Core wasm signature of the import
+    pub fn signature(&self) -> Signature {
+        let (params, returns) = match self {
+            CanonicalBuiltin::TaskReturn { results, .. } => (results.clone(), None),
+            CanonicalBuiltin::ContextGet
+            | CanonicalBuiltin::Yield
+            | CanonicalBuiltin::WaitableSetNew => (Vec::new(), Some(Type::I32)),
+            CanonicalBuiltin::ContextSet
+            | CanonicalBuiltin::WaitableSetDrop
+            | CanonicalBuiltin::SubtaskDrop => (alloc::vec![Type::I32], None),
+            // (set, event payload pointer) -> event code
+            CanonicalBuiltin::WaitableSetWait | CanonicalBuiltin::WaitableSetPoll => {
+                (alloc::vec![Type::I32, Type::I32], Some(Type::I32))
+            }
+            CanonicalBuiltin::WaitableJoin => (alloc::vec![Type::I32, Type::I32], None),
+            CanonicalBuiltin::Payload { kind, op, .. } => match op {
+                // Readable end in the low half, writable end in the high half
+                PayloadOp::New => (Vec::new(), Some(Type::I64)),
+                PayloadOp::Read | PayloadOp::Write => match kind {
+                    PayloadKind::Future => (alloc::vec![Type::I32, Type::I32], Some(Type::I32)),
+                    PayloadKind::Stream => (alloc::vec![Type::I32; 3], Some(Type::I32)),
+                },
+                PayloadOp::CancelRead | PayloadOp::CancelWrite => (alloc::vec![Type::I32], Some(Type::I32)),
+                PayloadOp::DropReadable | PayloadOp::DropWritable => (alloc::vec![Type::I32], None),
+            },
+        };
+        Signature { params, returns }
+    }
+}
+
+/// Canonical built-ins required by an interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsyncIntrinsics {
+    interface: String,
+    /// Distinct future and stream types, indexed by position
+    pub payloads: Vec<(PayloadKind, Option<WitType>)>,
+    pub builtins: Vec<CanonicalBuiltin>,
+}
+
+impl AsyncIntrinsics {
+    /// Collects the built-ins used by the interface's functions
+    pub fn for_interface(interface: &ComponentInterface) -> Self {
+        let mut payloads: Vec<(PayloadKind, Option<WitType>)> = Vec::new();
+        for ty in interface.functions.iter().flat_map(ComponentFunction::types) {
+            ty.visit(&mut |ty| {
+                let payload = match ty {
+                    WitType::Future(inner) => (PayloadKind::Future, inner.as_deref().cloned()),
+                    WitType::Stream(inner) => (PayloadKind::Stream, inner.as_deref().cloned()),
+                    _ => return,
+                };
+                if !payloads.contains(&payload) {
+                    payloads.push(payload);
+                }
+            });
+        }
+
+        let mut builtins = Vec::new();
+        if interface.uses_async() {
+            builtins.extend(CanonicalBuiltin::TASK_INTRINSICS.iter().cloned());
+        }
+        for function in interface.functions.iter().filter(|function| function.is_async) {
+            builtins.push(CanonicalBuiltin::TaskReturn {
+                function: function.name.clone(),
+                results: task_return_params(function.result.as_ref()),
+            });
+        }
+        for (index, (kind, _)) in payloads.iter().enumerate() {
+            builtins.extend(PayloadOp::ALL.iter().map(|&op| CanonicalBuiltin::Payload {
+                kind: *kind,
+                op,
+                index: index as u32,
+            }));
+        }
+
+        Self { interface: interface.name.clone(), payloads, builtins }
+    }
+
+    /// Import module of a built-in
+    pub fn import_module(&self, builtin: &CanonicalBuiltin) -> &str {
+        match builtin {
+            CanonicalBuiltin::Payload { .. } | CanonicalBuiltin::TaskReturn { .. } => &self.interface,
+            _ => ROOT_IMPORT_MODULE,
+        }
+    }
+
+    /// Declares every built-in as an import and returns their indices
+    pub fn declare(&self, module: &mut WasmModule) -> Vec<u32> {
+        self.builtins.iter()
+            .map(|builtin| module.add_import(self.import_module(builtin), builtin.import_name(), builtin.signature()))
+            .collect()
+    }
+}
+
+/// Core parameters `task.return` takes for a result type
+fn task_return_params(result: Option<&WitType>) -> Vec<Type> {
+    let mut flat = Vec::new();
+    if let Some(result) = result {
+        flatten(result, &mut flat);
+    }
+    if flat.len() > MAX_FLAT_PARAMS {
+        flat = alloc::vec![Type::I32];
+    }
+    flat
+}
+
+/// Canonical ABI flattening of a WIT type into core types
+fn flatten(ty: &WitType, out: &mut Vec<Type>) {
+    match ty {
+        WitType::U64 | WitType::S64 => out.push(Type::I64),
+        WitType::F32 => out.push(Type::F32),
+        WitType::F64 => out.push(Type::F64),
+        WitType::String | WitType::List(_) => out.extend([Type::I32, Type::I32]),
+        WitType::Option(inner) => flatten_variant(&[Some(inner)], out),
+        WitType::Result { ok, err } => flatten_variant(&[ok.as_ref(), err.as_ref()], out),
+        // Scalars, handles, and named types passed by handle or discriminant
+        _ => out.push(Type::I32),
+    }
+}
+
+/// Flattens a discriminant followed by the join of every case's payload
+fn flatten_variant(cases: &[Option<&alloc::boxed::Box<WitType>>], out: &mut Vec<Type>) {
+    out.push(Type::I32);
+    let mut joined: Vec<Type> = Vec::new();
+    for case in cases.iter().flatten() {
+        let mut flat = Vec::new();
+        flatten(case, &mut flat);
+        for (i, ty) in flat.into_iter().enumerate() {
+            match joined.get_mut(i) {
+                None => joined.push(ty),
+                Some(existing) if *existing == ty => {}
+                Some(existing) => {
+                    *existing = if matches!((&*existing, &ty), (Type::I32, Type::F32) | (Type::F32, Type::I32)) {
+                        Type::I32
+                    } else {
+                        Type::I64
+                    };
+                }
+            }
+        }
+    }
+    out.extend(joined);
+}
+
+struct WaitableSlot {
+    waker: Option<Waker>,
+    event: Option<Event>,
+}
+
+static WAITABLES: Mutex<BTreeMap<u32, WaitableSlot>> = Mutex::new(BTreeMap::new());
+static WAITABLE_SET: AtomicU32 = AtomicU32::new(0);
+
+/// Sets the waitable set blocked operations are joined to
+///
+/// Async export entry points create one set per task and install it here
+/// before polling the task.
+pub fn set_waitable_set(set: u32) {
+    WAITABLE_SET.store(set, Ordering::Release);
+}
+
+/// Hands an event from the async export callback to the waiting task
+///
+/// Returns false if no task is waiting on the event's waitable. Run the
+/// executor afterwards to resume the woken task.
+pub fn deliver(event: Event) -> bool {
+    let waker = {
+        let mut waitables = WAITABLES.lock();
+        let Some(slot) = waitables.get_mut(&event.waitable) else {
+            return false;
+        };
+        slot.event = Some(event);
+        slot.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+    true
+}
+
+/// Waits for the next event on a waitable
+pub fn wait_for(waitable: u32) -> WaitFor {
+    WaitFor { waitable, registered: false }
+}
+
+/// Future returned by `wait_for`
+pub struct WaitFor {
+    waitable: u32,
+    registered: bool,
+}
+
+impl Future for WaitFor {
+    type Output = Event;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Event> {
+        let waitable = self.waitable;
+        let mut waitables = WAITABLES.lock();
+        if !self.registered {
+            self.registered = true;
+            waitables.insert(waitable, WaitableSlot { waker: None, event: None });
+            #[cfg(target_arch = "wasm32")]
+            {
+                let set = WAITABLE_SET.load(Ordering::Acquire);
+                if set != 0 {
+                    // SAFETY: joining only affects where the host reports events
+                    unsafe { waitable_join(waitable, set) };
+                }
+            }
+        }
+
+        let slot = waitables.get_mut(&waitable).expect("registered waitable");
+        if let Some(event) = slot.event.take() {
+            waitables.remove(&waitable);
+            self.registered = false;
+            return Poll::Ready(event);
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitFor {
+    fn drop(&mut self) {
+        if self.registered {
+            WAITABLES.lock().remove(&self.waitable);
+        }
+    }
+}
+
+/// Cancels a blocked copy if the awaiting future is dropped first
+///
+/// The host may still be writing into the copy buffer, so cancellation
+/// must finish before the buffer is freed.
+struct CancelGuard {
+    handle: u32,
+    cancel: Option<unsafe extern "C" fn(u32) -> u32>,
+}
+
+impl CancelGuard {
+    fn disarm(mut self) {
+        self.cancel = None;
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel {
+            // SAFETY: the copy on `handle` is still in flight
+            unsafe { cancel(self.handle) };
+        }
+    }
+}
+
+/// Waits for a copy that may have blocked and decodes its result
+async fn complete(handle: u32, code: u32, cancel: unsafe extern "C" fn(u32) -> u32) -> CopyResult {
+    if let Some(result) = CopyResult::decode(code) {
+        return result;
+    }
+    let guard = CancelGuard { handle, cancel: Some(cancel) };
+    let event = wait_for(handle).await;
+    guard.disarm();
+    CopyResult::decode(event.code).unwrap_or(CopyResult { status: CopyStatus::Cancelled, count: 0 })
+}
+
+/// Canonical built-ins for one `future<T>` type
+pub struct FutureVtable<T> {
+    pub new: unsafe extern "C" fn() -> u64,
+    pub read: unsafe extern "C" fn(u32, *mut T) -> u32,
+    pub write: unsafe extern "C" fn(u32, *const T) -> u32,
+    pub cancel_read: unsafe extern "C" fn(u32) -> u32,
+    pub cancel_write: unsafe extern "C" fn(u32) -> u32,
+    pub drop_readable: unsafe extern "C" fn(u32),
+    pub drop_writable: unsafe extern "C" fn(u32),
+}
+
+/// Creates a future, returning its writable and readable ends
+pub fn future<T: Copy>(vtable: &'static FutureVtable<T>) -> (FutureWriter<T>, FutureReader<T>) {
+    // SAFETY: `new` takes no arguments and returns two fresh handles
+    let handles = unsafe { (vtable.new)() };
+    (
+        FutureWriter { handle: (handles >> 32) as u32, vtable },
+        FutureReader { handle: handles as u32, vtable },
+    )
+}
+
+/// Readable end of a `future<T>`
+pub struct FutureReader<T: 'static> {
+    handle: u32,
+    vtable: &'static FutureVtable<T>,
+}
+
+impl<T: Copy> FutureReader<T> {
+    /// Wraps a handle received from the host
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be an owned readable end of the future type `vtable`
+    /// was generated for.
+    pub unsafe fn from_handle(handle: u32, vtable: &'static FutureVtable<T>) -> Self {
+        Self { handle, vtable }
+    }
+
+    /// Gets the handle of this end
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Reads the value, or `None` if the writer was dropped without writing
+    pub async fn read(self) -> Option<T> {
+        let mut value = MaybeUninit::<T>::uninit();
+        // SAFETY: `value` outlives the copy; `complete` cancels it if dropped early
+        let code = unsafe { (self.vtable.read)(self.handle, value.as_mut_ptr()) };
+        let result = complete(self.handle, code, self.vtable.cancel_read).await;
+        match result.status {
+            // SAFETY: a completed read stored one value
+            CopyStatus::Completed => Some(unsafe { value.assume_init() }),
+            CopyStatus::Dropped | CopyStatus::Cancelled => None,
+        }
+    }
+}
+
+impl<T> Drop for FutureReader<T> {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned and dropped exactly once
+        unsafe { (self.vtable.drop_readable)(self.handle) };
+    }
+}
+
+/// Writable end of a `future<T>`
+pub struct FutureWriter<T: 'static> {
+    handle: u32,
+    vtable: &'static FutureVtable<T>,
+}
+
+impl<T: Copy> FutureWriter<T> {
+    /// Gets the handle of this end
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Writes the value, returning false if the reader was dropped
+    pub async fn write(self, value: T) -> bool {
+        // SAFETY: `value` lives in this frame until the copy completes
+        let code = unsafe { (self.vtable.write)(self.handle, &value) };
+        complete(self.handle, code, self.vtable.cancel_write).await.status == CopyStatus::Completed
+    }
+}
+
+impl<T> Drop for FutureWriter<T> {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned and dropped exactly once
+        unsafe { (self.vtable.drop_writable)(self.handle) };
+    }
+}
+
+/// Canonical built-ins for one `stream<T>` type
+pub struct StreamVtable<T> {
+    pub new: unsafe extern "C" fn() -> u64,
+    pub read: unsafe extern "C" fn(u32, *mut T, u32) -> u32,
+    pub write: unsafe extern "C" fn(u32, *const T, u32) -> u32,
+    pub cancel_read: unsafe extern "C" fn(u32) -> u32,
+    pub cancel_write: unsafe extern "C" fn(u32) -> u32,
+    pub drop_readable: unsafe extern "C" fn(u32),
+    pub drop_writable: unsafe extern "C" fn(u32),
+}
+
+/// Creates a stream, returning its writable and readable ends
+pub fn stream<T: Copy>(vtable: &'static StreamVtable<T>) -> (StreamWriter<T>, StreamReader<T>) {
+    // SAFETY: `new` takes no arguments and returns two fresh handles
+    let handles = unsafe { (vtable.new)() };
+    (
+        StreamWriter { handle: (handles >> 32) as u32, vtable, closed: false },
+        StreamReader { handle: handles as u32, vtable, closed: false },
+    )
+}
+
+/// Readable end of a `stream<T>`
+pub struct StreamReader<T: 'static> {
+    handle: u32,
+    vtable: &'static StreamVtable<T>,
+    closed: bool,
+}
+
+impl<T: Copy> StreamReader<T> {
+    /// Wraps a handle received from the host
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be an owned readable end of the stream type `vtable`
+    /// was generated for.
+    pub unsafe fn from_handle(handle: u32, vtable: &'static StreamVtable<T>) -> Self {
+        Self { handle, vtable, closed: false }
+    }
+
+    /// Gets the handle of this end
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Reads up to `max` values, or `None` once the writer is dropped
+    pub async fn next(&mut self, max: usize) -> Option<Vec<T>> {
+        while !self.closed {
+            let mut values = Vec::with_capacity(max);
+            // SAFETY: `values` outlives the copy; `complete` cancels it if dropped early
+            let code = unsafe { (self.vtable.read)(self.handle, values.as_mut_ptr(), max as u32) };
+            let result = complete(self.handle, code, self.vtable.cancel_read).await;
+            // SAFETY: the host initialized `count` values, at most `max`
+            unsafe { values.set_len((result.count as usize).min(max)) };
+            self.closed = result.status == CopyStatus::Dropped;
+            if !values.is_empty() {
+                return Some(values);
+            }
+        }
+        None
+    }
+}
+
+impl<T> Drop for StreamReader<T> {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned and dropped exactly once
+        unsafe { (self.vtable.drop_readable)(self.handle) };
+    }
+}
+
+/// Writable end of a `stream<T>`
+pub struct StreamWriter<T: 'static> {
+    handle: u32,
+    vtable: &'static StreamVtable<T>,
+    closed: bool,
+}
+
+impl<T: Copy> StreamWriter<T> {
+    /// Gets the handle of this end
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Writes all values, returning how many the reader accepted before
+    /// it was dropped
+    pub async fn write_all(&mut self, mut values: &[T]) -> usize {
+        let mut written = 0;
+        while !values.is_empty() && !self.closed {
+            // SAFETY: `values` is borrowed until the copy completes
+            let code = unsafe { (self.vtable.write)(self.handle, values.as_ptr(), values.len() as u32) };
+            let result = complete(self.handle, code, self.vtable.cancel_write).await;
+            let count = (result.count as usize).min(values.len());
+            written += count;
+            values = &values[count..];
+            self.closed = result.status == CopyStatus::Dropped;
+        }
+        written
+    }
+}
+
+impl<T> Drop for StreamWriter<T> {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned and dropped exactly once
+        unsafe { (self.vtable.drop_writable)(self.handle) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threading::executor::LocalExecutor;
+    use alloc::rc::Rc;
+    use alloc::string::ToString;
+    use core::cell::Cell;
+
+    // Simulated host: future handles 10/11, stream handles 20/21
+    static PENDING_READ: Mutex<Option<usize>> = Mutex::new(None);
+    static STREAM_READS: AtomicU32 = AtomicU32::new(0);
+
+    unsafe extern "C" fn future_new() -> u64 {
+        (11 << 32) | 10
+    }
+    unsafe extern "C" fn future_read(_: u32, ptr: *mut u32) -> u32 {
+        *PENDING_READ.lock() = Some(ptr as usize);
+        BLOCKED
+    }
+    unsafe extern "C" fn future_write(_: u32, _: *const u32) -> u32 {
+        0
+    }
+    unsafe extern "C" fn stream_new() -> u64 {
+        (21 << 32) | 20
+    }
+    unsafe extern "C" fn stream_read(_: u32, ptr: *mut u8, len: u32) -> u32 {
+        if STREAM_READS.fetch_add(1, Ordering::SeqCst) > 0 {
+            return 1;
+        }
+        let data = [1u8, 2, 3];
+        let count = data.len().min(len as usize);
+        core::ptr::copy_nonoverlapping(data.as_ptr(), ptr, count);
+        (count as u32) << 4
+    }
+    unsafe extern "C" fn stream_write(_: u32, _: *const u8, len: u32) -> u32 {
+        len.min(2) << 4
+    }
+    unsafe extern "C" fn cancel(_: u32) -> u32 {
+        2
+    }
+    unsafe extern "C" fn drop_end(_: u32) {}
+
+    static FUTURE_U32: FutureVtable<u32> = FutureVtable {
+        new: future_new,
+        read: future_read,
+        write: future_write,
+        cancel_read: cancel,
+        cancel_write: cancel,
+        drop_readable: drop_end,
+        drop_writable: drop_end,
+    };
+
+    static STREAM_U8: StreamVtable<u8> = StreamVtable {
+        new: stream_new,
+        read: stream_read,
+        write: stream_write,
+        cancel_read: cancel,
+        cancel_write: cancel,
+        drop_readable: drop_end,
+        drop_writable: drop_end,
+    };
+
+    #[test]
+    fn test_blocked_future_read_resumes_on_event() {
+        let (writer, reader) = future(&FUTURE_U32);
+        assert_eq!((writer.handle(), reader.handle()), (11, 10));
+
+        let result = Rc::new(Cell::new(None));
+        let sink = result.clone();
+        let mut executor = LocalExecutor::new();
+        executor.spawn(async move { sink.set(Some(reader.read().await)) });
+        executor.run_until_stalled();
+        assert_eq!(result.get(), None);
+
+        // The host completes the copy, then reports it to the callback
+        let ptr = PENDING_READ.lock().take().unwrap();
+        unsafe { *(ptr as *mut u32) = 42 };
+        assert!(deliver(Event { kind: EventKind::FutureRead, waitable: 10, code: 1 << 4 }));
+        executor.run_until_stalled();
+        assert_eq!(result.get(), Some(Some(42)));
+        assert!(!deliver(Event { kind: EventKind::FutureRead, waitable: 10, code: 0 }));
+
+        let mut executor = LocalExecutor::new();
+        executor.spawn(async move { assert!(writer.write(7).await) });
+        assert_eq!(executor.run_until_stalled(), 1);
+        assert_eq!(executor.pending_tasks(), 0);
+    }
+
+    #[test]
+    fn test_stream_reads_until_writer_dropped() {
+        let (mut writer, mut reader) = stream(&STREAM_U8);
+        let chunks = Rc::new(core::cell::RefCell::new(Vec::new()));
+        let sink = chunks.clone();
+        let mut executor = LocalExecutor::new();
+        executor.spawn(async move {
+            while let Some(chunk) = reader.next(8).await {
+                sink.borrow_mut().push(chunk);
+            }
+            assert_eq!(writer.write_all(&[9, 8, 7]).await, 3);
+        });
+        executor.run_until_stalled();
+        assert_eq!(*chunks.borrow(), alloc::vec![alloc::vec![1u8, 2, 3]]);
+        assert_eq!(executor.pending_tasks(), 0);
+    }
+
+    #[test]
+    fn test_async_intrinsics_for_interface() {
+        let mut interface = ComponentInterface::new("wasi:http/handler".to_string());
+        interface.add_function(
+            ComponentFunction::new("handle")
+                .param("body", WitType::stream(WitType::U8))
+                .result(WitType::Result {
+                    ok: Some(alloc::boxed::Box::new(WitType::future(WitType::String))),
+                    err: Some(alloc::boxed::Box::new(WitType::U64)),
+                })
+                .asynchronous(),
+        ).unwrap();
+        assert_eq!(
+            interface.functions[0].to_string(),
+            "handle: async func(body: stream<u8>) -> result<future<string>, u64>"
+        );
+
+        let intrinsics = AsyncIntrinsics::for_interface(&interface);
+        assert_eq!(intrinsics.payloads, alloc::vec![
+            (PayloadKind::Stream, Some(WitType::U8)),
+            (PayloadKind::Future, Some(WitType::String)),
+        ]);
+        let names: Vec<String> = intrinsics.builtins.iter().map(CanonicalBuiltin::import_name).collect();
+        assert!(names.contains(&"[waitable-set-wait]".to_string()));
+        assert!(names.contains(&"[stream-read-0]".to_string()));
+        assert!(names.contains(&"[future-drop-writable-1]".to_string()));
+
+        // Discriminant plus the join of the future handle and the u64 error
+        let task_return = &intrinsics.builtins[9];
+        assert_eq!(task_return.import_name(), "[task-return]handle");
+        assert_eq!(task_return.signature().params, alloc::vec![Type::I32, Type::I64]);
+
+        let mut module = WasmModule::new();
+        let indices = intrinsics.declare(&mut module);
+        assert_eq!(indices.len(), intrinsics.builtins.len());
+        let stream_read = module.find_import("wasi:http/handler", "[stream-read-0]").unwrap();
+        assert_eq!(module.imports[stream_read as usize].signature().params.len(), 3);
+        assert!(module.find_import(ROOT_IMPORT_MODULE, "[waitable-join]").is_some());
+    }
+}
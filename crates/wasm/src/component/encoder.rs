@@ -0,0 +1,923 @@
+//! Component Model binary emission
+//!
+//! `ComponentEncoder` wraps a core module into a component using the
+//! canonical adapters chosen by `Adapter::adapt`. Each imported interface
+//! becomes an instance import whose type declares the lowered functions and
+//! the named types they use; each exported interface becomes an instance of
+//! lifted functions and their named types, exported under the interface's
+//! qualified name.
+//!
+//! Imports lowered with memory options need the instance's memory before
+//! the instance exists. As in other component toolchains, the core module
+//! is instantiated against trampolines in a small shim module that call
+//! through a table, and a fixup module fills the table with the lowered
+//! functions once the memory is available.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use super::adapter::{ComponentAdapter, CoreSignature};
+use super::introspect::StringEncoding;
+use super::wit::{WitError, WitInterface, WitPackage, WitTypeDefKind};
+use super::{ComponentFunction, WitType};
+use crate::wasmir::Type;
+
+/// Component binary version and layer
+const COMPONENT_HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x0d, 0x00, 0x01, 0x00];
+
+/// Core module binary header
+const MODULE_HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+/// Export of the shim table the fixup module fills
+const SHIM_TABLE: &str = "$imports";
+
+/// Component section identifiers
+mod section {
+    pub const CORE_MODULE: u8 = 1;
+    pub const CORE_INSTANCE: u8 = 2;
+    pub const INSTANCE: u8 = 5;
+    pub const ALIAS: u8 = 6;
+    pub const TYPE: u8 = 7;
+    pub const CANON: u8 = 8;
+    pub const IMPORT: u8 = 10;
+    pub const EXPORT: u8 = 11;
+}
+
+/// Core and component sort codes
+mod sort {
+    pub const CORE_FUNC: u8 = 0x00;
+    pub const CORE_TABLE: u8 = 0x01;
+    pub const CORE_MEMORY: u8 = 0x02;
+    pub const CORE_INSTANCE: u8 = 0x12;
+    pub const FUNC: u8 = 0x01;
+    pub const TYPE: u8 = 0x03;
+    pub const INSTANCE: u8 = 0x05;
+}
+
+/// Component encoding errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The world could not be resolved
+    Wit(WitError),
+    /// The adapter names an interface or function the world does not have
+    UnknownFunction(String),
+}
+
+impl core::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EncodeError::Wit(err) => write!(f, "{}", err),
+            EncodeError::UnknownFunction(name) => write!(f, "Adapter names unknown function {}", name),
+        }
+    }
+}
+
+impl From<WitError> for EncodeError {
+    fn from(err: WitError) -> Self {
+        EncodeError::Wit(err)
+    }
+}
+
+/// Encoder wrapping a core module into a component
+#[derive(Debug, Clone)]
+pub struct ComponentEncoder<'a> {
+    package: &'a WitPackage,
+    adapter: &'a ComponentAdapter,
+}
+
+impl<'a> ComponentEncoder<'a> {
+    pub fn new(package: &'a WitPackage, adapter: &'a ComponentAdapter) -> Self {
+        Self { package, adapter }
+    }
+
+    /// Encodes the component around the core module the adapter was built for
+    pub fn encode(&self, module: &[u8]) -> Result<Vec<u8>, EncodeError> {
+        let interfaces = self.package.world_interfaces(&self.adapter.world)?;
+        let find = |list: &[(String, &'a WitInterface)], name: &str| {
+            list.iter()
+                .find(|(qualified, _)| qualified == name)
+                .map(|(_, interface)| *interface)
+                .ok_or_else(|| EncodeError::UnknownFunction(name.to_string()))
+        };
+
+        let mut out = COMPONENT_HEADER.to_vec();
+        let mut spaces = Spaces::default();
+
+        // Imported interfaces, each declaring the functions the module lowers
+        let mut imported: Vec<(&str, &WitInterface, Vec<&ComponentFunction>)> = Vec::new();
+        for import in &self.adapter.imports {
+            match imported.iter_mut().find(|(name, _, _)| *name == import.interface) {
+                Some((_, _, functions)) => functions.push(&import.function),
+                None => {
+                    let interface = find(&interfaces.imports, &import.interface)?;
+                    imported.push((&import.interface, interface, alloc::vec![&import.function]));
+                }
+            }
+        }
+        let mut types = Vec::new();
+        for (_, interface, functions) in &imported {
+            types.push(self.instance_type(interface, functions)?);
+        }
+        write_vec_section(&mut out, section::TYPE, &types);
+        let mut entries = Vec::new();
+        for (i, (name, _, _)) in imported.iter().enumerate() {
+            let mut entry = extern_name(name);
+            entry.push(sort::INSTANCE);
+            write_u32(&mut entry, spaces.types + i as u32);
+            entries.push(entry);
+        }
+        spaces.types += imported.len() as u32;
+        write_vec_section(&mut out, section::IMPORT, &entries);
+        let instance_of = |interface: &str| {
+            imported.iter().position(|(name, _, _)| *name == interface).expect("imported above") as u32
+        };
+        spaces.instances += imported.len() as u32;
+
+        // Component functions of every import, in adapter order
+        let aliases: Vec<Vec<u8>> = self.adapter.imports.iter()
+            .map(|import| {
+                let mut alias = alloc::vec![sort::FUNC, 0x00];
+                write_u32(&mut alias, instance_of(&import.interface));
+                alias.extend(name(&import.function.name));
+                alias
+            })
+            .collect();
+        write_vec_section(&mut out, section::ALIAS, &aliases);
+        let import_funcs = spaces.funcs;
+        spaces.funcs += aliases.len() as u32;
+
+        // Imports without memory options are lowered directly; the rest go
+        // through the shim until the memory exists
+        let shimmed: Vec<usize> = (0..self.adapter.imports.len())
+            .filter(|i| self.adapter.imports[*i].uses_memory)
+            .collect();
+        let mut core_func_of_import = alloc::vec![0; self.adapter.imports.len()];
+        let mut lowers = Vec::new();
+        for (i, import) in self.adapter.imports.iter().enumerate() {
+            if !import.uses_memory {
+                lowers.push(lower(import_funcs + i as u32, &[]));
+                core_func_of_import[i] = spaces.core_funcs;
+                spaces.core_funcs += 1;
+            }
+        }
+        write_vec_section(&mut out, section::CANON, &lowers);
+
+        let main_module = spaces.core_modules;
+        write_section(&mut out, section::CORE_MODULE, module);
+        spaces.core_modules += 1;
+
+        let mut shim_table = None;
+        if !shimmed.is_empty() {
+            let signatures: Vec<&CoreSignature> = shimmed.iter().map(|i| &self.adapter.imports[*i].signature).collect();
+            write_section(&mut out, section::CORE_MODULE, &shim_module(&signatures));
+            let shim = spaces.core_modules;
+            spaces.core_modules += 1;
+            write_vec_section(&mut out, section::CORE_INSTANCE, &[instantiate(shim, &[])]);
+            let shim_instance = spaces.core_instances;
+            spaces.core_instances += 1;
+
+            let mut aliases = Vec::new();
+            for (slot, i) in shimmed.iter().enumerate() {
+                aliases.push(core_alias(sort::CORE_FUNC, shim_instance, &slot.to_string()));
+                core_func_of_import[*i] = spaces.core_funcs;
+                spaces.core_funcs += 1;
+            }
+            aliases.push(core_alias(sort::CORE_TABLE, shim_instance, SHIM_TABLE));
+            write_vec_section(&mut out, section::ALIAS, &aliases);
+            shim_table = Some(spaces.core_tables);
+            spaces.core_tables += 1;
+        }
+
+        // One argument instance per core import module, then the module itself
+        let mut modules: Vec<(&str, Vec<(&str, u32)>)> = Vec::new();
+        for (i, import) in self.adapter.imports.iter().enumerate() {
+            let entry = (import.core_name.as_str(), core_func_of_import[i]);
+            match modules.iter_mut().find(|(module, _)| *module == import.core_module) {
+                Some((_, items)) => items.push(entry),
+                None => modules.push((&import.core_module, alloc::vec![entry])),
+            }
+        }
+        let mut instances = Vec::new();
+        let mut args = Vec::new();
+        for (module, items) in &modules {
+            let items: Vec<(&str, u8, u32)> = items.iter().map(|(name, func)| (*name, sort::CORE_FUNC, *func)).collect();
+            instances.push(core_exports(&items));
+            args.push((*module, spaces.core_instances));
+            spaces.core_instances += 1;
+        }
+        instances.push(instantiate(main_module, &args));
+        let main = spaces.core_instances;
+        spaces.core_instances += 1;
+        write_vec_section(&mut out, section::CORE_INSTANCE, &instances);
+
+        // Core exports the adapters refer to
+        let mut aliases = Vec::new();
+        let mut memory = None;
+        if let Some(name) = &self.adapter.memory {
+            aliases.push(core_alias(sort::CORE_MEMORY, main, name));
+            memory = Some(spaces.core_memories);
+            spaces.core_memories += 1;
+        }
+        let mut core_func = |aliases: &mut Vec<Vec<u8>>, name: &str| {
+            aliases.push(core_alias(sort::CORE_FUNC, main, name));
+            spaces.core_funcs += 1;
+            spaces.core_funcs - 1
+        };
+        let realloc = self.adapter.realloc.as_ref().map(|name| core_func(&mut aliases, name));
+        let mut exported = Vec::new();
+        for export in &self.adapter.exports {
+            let func = core_func(&mut aliases, &export.core_export);
+            let post_return = export.post_return.as_ref().map(|name| core_func(&mut aliases, name));
+            exported.push((func, post_return));
+        }
+        write_vec_section(&mut out, section::ALIAS, &aliases);
+
+        let options = |uses_memory: bool, uses_realloc: bool, post_return: Option<u32>| {
+            let mut options = Vec::new();
+            if uses_memory {
+                options.push(alloc::vec![encoding_option(self.adapter.string_encoding)]);
+                if let Some(memory) = memory {
+                    let mut option = alloc::vec![0x03];
+                    write_u32(&mut option, memory);
+                    options.push(option);
+                }
+            }
+            if let Some(realloc) = realloc.filter(|_| uses_realloc) {
+                let mut option = alloc::vec![0x04];
+                write_u32(&mut option, realloc);
+                options.push(option);
+            }
+            if let Some(post_return) = post_return {
+                let mut option = alloc::vec![0x05];
+                write_u32(&mut option, post_return);
+                options.push(option);
+            }
+            options
+        };
+
+        // Lowered imports for the fixup module
+        let mut lowers = Vec::new();
+        let mut fixups = Vec::new();
+        for (slot, i) in shimmed.iter().enumerate() {
+            let import = &self.adapter.imports[*i];
+            lowers.push(lower(import_funcs + *i as u32, &options(true, import.uses_realloc, None)));
+            fixups.push((slot.to_string(), spaces.core_funcs));
+            spaces.core_funcs += 1;
+        }
+        write_vec_section(&mut out, section::CANON, &lowers);
+        if let Some(table) = shim_table {
+            let signatures: Vec<&CoreSignature> = shimmed.iter().map(|i| &self.adapter.imports[*i].signature).collect();
+            write_section(&mut out, section::CORE_MODULE, &fixup_module(&signatures));
+            let fixup = spaces.core_modules;
+            spaces.core_modules += 1;
+            let mut items: Vec<(&str, u8, u32)> = alloc::vec![(SHIM_TABLE, sort::CORE_TABLE, table)];
+            items.extend(fixups.iter().map(|(name, func)| (name.as_str(), sort::CORE_FUNC, *func)));
+            let args = spaces.core_instances;
+            write_vec_section(&mut out, section::CORE_INSTANCE, &[core_exports(&items), instantiate(fixup, &[("", args)])]);
+            spaces.core_instances += 2;
+        }
+
+        // Lifted exports, grouped into one instance per interface
+        let mut scope = TypeScope::new(self.package, false, spaces.types);
+        let mut lifts = Vec::new();
+        let mut groups: Vec<ExportGroup<'_>> = Vec::new();
+        for (export, (core_func, post_return)) in self.adapter.exports.iter().zip(&exported) {
+            let interface = find(&interfaces.exports, &export.interface)?;
+            let ty = scope.func_type(interface, &export.function)?;
+            let mut lift = alloc::vec![0x00, 0x00];
+            write_u32(&mut lift, *core_func);
+            write_vec(&mut lift, &options(export.uses_memory, export.uses_realloc, *post_return));
+            write_u32(&mut lift, ty);
+            lifts.push(lift);
+
+            let entry = (export.function.name.as_str(), spaces.funcs);
+            match groups.iter_mut().find(|(name, _, _)| *name == export.interface) {
+                Some((_, _, funcs)) => funcs.push(entry),
+                None => groups.push((&export.interface, interface, alloc::vec![entry])),
+            }
+            spaces.funcs += 1;
+        }
+        write_vec_section(&mut out, section::TYPE, &scope.items);
+        write_vec_section(&mut out, section::CANON, &lifts);
+
+        let mut instances = Vec::new();
+        let mut exports = Vec::new();
+        for (name, interface, funcs) in &groups {
+            // Exported functions may only refer to named types
+            let mut reachable = Vec::new();
+            for (func_name, _) in funcs {
+                let function = interface.functions.iter()
+                    .find(|function| function.name == *func_name)
+                    .expect("lifted above");
+                for ty in function.types() {
+                    scope.reachable(interface, ty, &mut reachable)?;
+                }
+            }
+            let mut items = Vec::new();
+            for ((_, type_name), index) in scope.named.iter().filter(|(key, _)| reachable.contains(key)) {
+                let mut item = extern_name(type_name);
+                item.push(sort::TYPE);
+                write_u32(&mut item, *index);
+                items.push(item);
+            }
+            for (func_name, index) in funcs {
+                let mut item = extern_name(func_name);
+                item.push(sort::FUNC);
+                write_u32(&mut item, *index);
+                items.push(item);
+            }
+            let mut instance = alloc::vec![0x01];
+            write_vec(&mut instance, &items);
+            instances.push(instance);
+
+            let mut export = extern_name(name);
+            export.push(sort::INSTANCE);
+            write_u32(&mut export, spaces.instances);
+            export.push(0x00);
+            exports.push(export);
+            spaces.instances += 1;
+        }
+        write_vec_section(&mut out, section::INSTANCE, &instances);
+        write_vec_section(&mut out, section::EXPORT, &exports);
+        Ok(out)
+    }
+
+    /// Instance type declaring functions and the named types they use
+    fn instance_type(&self, interface: &WitInterface, functions: &[&ComponentFunction]) -> Result<Vec<u8>, EncodeError> {
+        let mut scope = TypeScope::new(self.package, true, 0);
+        let mut decls = Vec::new();
+        for function in functions {
+            let ty = scope.func_type(interface, function)?;
+            let mut decl = alloc::vec![0x04];
+            decl.extend(extern_name(&function.name));
+            decl.push(0x01);
+            write_u32(&mut decl, ty);
+            decls.push(decl);
+        }
+        let mut out = alloc::vec![0x42];
+        write_vec(&mut out, &scope.items.into_iter().chain(decls).collect::<Vec<_>>());
+        Ok(out)
+    }
+}
+
+/// Exported interface with its lifted functions and their indices
+type ExportGroup<'a> = (&'a str, &'a WitInterface, Vec<(&'a str, u32)>);
+
+/// Component types being defined in a type section or instance type
+struct TypeScope<'p> {
+    package: &'p WitPackage,
+    /// Whether named types are exported as they are defined
+    exports_types: bool,
+    /// Type definitions, or declarations in an instance type
+    items: Vec<Vec<u8>>,
+    next: u32,
+    /// Type index of each named type, keyed by interface and name
+    named: Vec<((String, String), u32)>,
+}
+
+impl<'p> TypeScope<'p> {
+    fn new(package: &'p WitPackage, exports_types: bool, next: u32) -> Self {
+        Self { package, exports_types, items: Vec::new(), next, named: Vec::new() }
+    }
+
+    fn define(&mut self, def: Vec<u8>) -> u32 {
+        if self.exports_types {
+            let mut decl = alloc::vec![0x01];
+            decl.extend(def);
+            self.items.push(decl);
+        } else {
+            self.items.push(def);
+        }
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn func_type(&mut self, interface: &WitInterface, function: &ComponentFunction) -> Result<u32, EncodeError> {
+        let mut params = Vec::new();
+        for (param, ty) in &function.params {
+            let mut entry = name(param);
+            entry.extend(self.val_type(interface, ty)?);
+            params.push(entry);
+        }
+        let result = match &function.result {
+            Some(ty) => Some(self.val_type(interface, ty)?),
+            None => None,
+        };
+        let mut def = alloc::vec![0x40];
+        write_vec(&mut def, &params);
+        match result {
+            Some(result) => {
+                def.push(0x00);
+                def.extend(result);
+            }
+            None => def.extend([0x01, 0x00]),
+        }
+        Ok(self.define(def))
+    }
+
+    /// Encodes a value type, defining the types it refers to
+    fn val_type(&mut self, interface: &WitInterface, ty: &WitType) -> Result<Vec<u8>, EncodeError> {
+        let primitive = match ty {
+            WitType::Bool => 0x7f,
+            WitType::S8 => 0x7e,
+            WitType::U8 => 0x7d,
+            WitType::S16 => 0x7c,
+            WitType::U16 => 0x7b,
+            WitType::S32 => 0x7a,
+            WitType::U32 => 0x79,
+            WitType::S64 => 0x78,
+            WitType::U64 => 0x77,
+            WitType::F32 => 0x76,
+            WitType::F64 => 0x75,
+            WitType::Char => 0x74,
+            WitType::String => 0x73,
+            WitType::Named(type_name) => {
+                let (owner, def) = self.package.resolve_type(interface, type_name)?;
+                if let WitTypeDefKind::Alias(aliased) = &def.kind {
+                    return self.val_type(owner, aliased);
+                }
+                let index = self.named_type(interface, type_name)?;
+                let mut out = Vec::new();
+                write_s33(&mut out, index);
+                return Ok(out);
+            }
+            _ => {
+                let index = self.type_index(interface, ty)?;
+                let mut out = Vec::new();
+                write_s33(&mut out, index);
+                return Ok(out);
+            }
+        };
+        Ok(alloc::vec![primitive])
+    }
+
+    fn type_index(&mut self, interface: &WitInterface, ty: &WitType) -> Result<u32, EncodeError> {
+        let optional = |scope: &mut Self, ty: &Option<alloc::boxed::Box<WitType>>| -> Result<Vec<u8>, EncodeError> {
+            Ok(match ty {
+                Some(ty) => {
+                    let mut out = alloc::vec![0x01];
+                    out.extend(scope.val_type(interface, ty)?);
+                    out
+                }
+                None => alloc::vec![0x00],
+            })
+        };
+        let def = match ty {
+            WitType::List(inner) => {
+                let mut def = alloc::vec![0x70];
+                def.extend(self.val_type(interface, inner)?);
+                def
+            }
+            WitType::Option(inner) => {
+                let mut def = alloc::vec![0x6b];
+                def.extend(self.val_type(interface, inner)?);
+                def
+            }
+            WitType::Result { ok, err } => {
+                let mut def = alloc::vec![0x6a];
+                def.extend(optional(self, ok)?);
+                def.extend(optional(self, err)?);
+                def
+            }
+            // Adapters reject futures and streams; primitives and named
+            // types never get here
+            _ => return Err(EncodeError::UnknownFunction(format!("type {}", ty))),
+        };
+        Ok(self.define(def))
+    }
+
+    fn named_type(&mut self, interface: &WitInterface, type_name: &str) -> Result<u32, EncodeError> {
+        let (owner, def) = self.package.resolve_type(interface, type_name)?;
+        let key = (owner.name.clone(), def.name.clone());
+        if let Some((_, index)) = self.named.iter().find(|(named, _)| *named == key) {
+            return Ok(*index);
+        }
+        let encoded = match &def.kind {
+            WitTypeDefKind::Alias(ty) => {
+                let ty = ty.clone();
+                return self.type_index(owner, &ty);
+            }
+            WitTypeDefKind::Record(fields) => {
+                let mut entries = Vec::new();
+                for (field, ty) in fields {
+                    let mut entry = name(field);
+                    entry.extend(self.val_type(owner, ty)?);
+                    entries.push(entry);
+                }
+                let mut out = alloc::vec![0x72];
+                write_vec(&mut out, &entries);
+                out
+            }
+            WitTypeDefKind::Variant(cases) => {
+                let mut entries = Vec::new();
+                for (case, ty) in cases {
+                    let mut entry = name(case);
+                    match ty {
+                        Some(ty) => {
+                            entry.push(0x01);
+                            entry.extend(self.val_type(owner, ty)?);
+                        }
+                        None => entry.push(0x00),
+                    }
+                    entry.push(0x00);
+                    entries.push(entry);
+                }
+                let mut out = alloc::vec![0x71];
+                write_vec(&mut out, &entries);
+                out
+            }
+            WitTypeDefKind::Enum(cases) | WitTypeDefKind::Flags(cases) => {
+                let code = if matches!(def.kind, WitTypeDefKind::Enum(_)) { 0x6d } else { 0x6e };
+                let mut out = alloc::vec![code];
+                write_vec(&mut out, &cases.iter().map(|case| name(case)).collect::<Vec<_>>());
+                out
+            }
+            WitTypeDefKind::Resource => {
+                return Err(EncodeError::UnknownFunction(format!("resource {}", type_name)));
+            }
+        };
+        let mut index = self.define(encoded);
+        if self.exports_types {
+            // Functions must refer to the exported, named type
+            let mut decl = alloc::vec![0x04];
+            decl.extend(extern_name(&def.name));
+            decl.extend([sort::TYPE, 0x00]);
+            write_u32(&mut decl, index);
+            self.items.push(decl);
+            index = self.next;
+            self.next += 1;
+        }
+        self.named.push((key, index));
+        Ok(index)
+    }
+
+    /// Collects the named types a WIT type refers to, directly or through other types
+    fn reachable(&self, interface: &WitInterface, ty: &WitType, out: &mut Vec<(String, String)>) -> Result<(), WitError> {
+        let mut names = Vec::new();
+        ty.visit(&mut |ty| {
+            if let WitType::Named(type_name) = ty {
+                names.push(type_name);
+            }
+        });
+        for type_name in names {
+            let (owner, def) = self.package.resolve_type(interface, type_name)?;
+            let key = (owner.name.clone(), def.name.clone());
+            if out.contains(&key) {
+                continue;
+            }
+            let members: Vec<&WitType> = match &def.kind {
+                WitTypeDefKind::Alias(ty) => alloc::vec![ty],
+                WitTypeDefKind::Record(fields) => fields.iter().map(|(_, ty)| ty).collect(),
+                WitTypeDefKind::Variant(cases) => cases.iter().filter_map(|(_, ty)| ty.as_ref()).collect(),
+                _ => Vec::new(),
+            };
+            if !matches!(def.kind, WitTypeDefKind::Alias(_)) {
+                out.push(key);
+            }
+            for member in members {
+                self.reachable(owner, member, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Index spaces of the component being encoded
+#[derive(Default)]
+struct Spaces {
+    types: u32,
+    funcs: u32,
+    instances: u32,
+    core_modules: u32,
+    core_instances: u32,
+    core_funcs: u32,
+    core_tables: u32,
+    core_memories: u32,
+}
+
+fn encoding_option(encoding: StringEncoding) -> u8 {
+    match encoding {
+        StringEncoding::Utf8 => 0x00,
+        StringEncoding::Utf16 => 0x01,
+        StringEncoding::CompactUtf16 => 0x02,
+    }
+}
+
+fn lower(func: u32, options: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = alloc::vec![0x01, 0x00];
+    write_u32(&mut out, func);
+    write_vec(&mut out, options);
+    out
+}
+
+fn instantiate(module: u32, args: &[(&str, u32)]) -> Vec<u8> {
+    let mut out = alloc::vec![0x00];
+    write_u32(&mut out, module);
+    let args: Vec<Vec<u8>> = args.iter()
+        .map(|(arg, instance)| {
+            let mut entry = name(arg);
+            entry.push(sort::CORE_INSTANCE);
+            write_u32(&mut entry, *instance);
+            entry
+        })
+        .collect();
+    write_vec(&mut out, &args);
+    out
+}
+
+/// Core instance made of existing core items
+fn core_exports(items: &[(&str, u8, u32)]) -> Vec<u8> {
+    let mut out = alloc::vec![0x01];
+    let items: Vec<Vec<u8>> = items.iter()
+        .map(|(item, kind, index)| {
+            let mut entry = name(item);
+            entry.push(*kind);
+            write_u32(&mut entry, *index);
+            entry
+        })
+        .collect();
+    write_vec(&mut out, &items);
+    out
+}
+
+fn core_alias(kind: u8, instance: u32, export: &str) -> Vec<u8> {
+    let mut out = alloc::vec![0x00, kind, 0x01];
+    write_u32(&mut out, instance);
+    out.extend(name(export));
+    out
+}
+
+/// Module of trampolines calling through an exported table
+fn shim_module(signatures: &[&CoreSignature]) -> Vec<u8> {
+    let mut out = MODULE_HEADER.to_vec();
+    write_vec_section(&mut out, 1, &signatures.iter().map(|signature| core_func_type(signature)).collect::<Vec<_>>());
+    let functions: Vec<Vec<u8>> = (0..signatures.len() as u32)
+        .map(|i| {
+            let mut entry = Vec::new();
+            write_u32(&mut entry, i);
+            entry
+        })
+        .collect();
+    write_vec_section(&mut out, 3, &functions);
+    let mut table = alloc::vec![0x70, 0x01];
+    write_u32(&mut table, signatures.len() as u32);
+    write_u32(&mut table, signatures.len() as u32);
+    write_vec_section(&mut out, 4, &[table]);
+
+    let mut exports = Vec::new();
+    let mut entry = name(SHIM_TABLE);
+    entry.extend([0x01, 0x00]);
+    exports.push(entry);
+    for i in 0..signatures.len() as u32 {
+        let mut entry = name(&i.to_string());
+        entry.push(0x00);
+        write_u32(&mut entry, i);
+        exports.push(entry);
+    }
+    write_vec_section(&mut out, 7, &exports);
+
+    let bodies: Vec<Vec<u8>> = signatures.iter().enumerate()
+        .map(|(i, signature)| {
+            let mut code = alloc::vec![0x00];
+            for param in 0..signature.params.len() as u32 {
+                code.push(0x20);
+                write_u32(&mut code, param);
+            }
+            code.push(0x41);
+            write_u32(&mut code, i as u32);
+            code.push(0x11);
+            write_u32(&mut code, i as u32);
+            code.extend([0x00, 0x0b]);
+            let mut body = Vec::new();
+            write_u32(&mut body, code.len() as u32);
+            body.extend(code);
+            body
+        })
+        .collect();
+    write_vec_section(&mut out, 10, &bodies);
+    out
+}
+
+/// Module filling the shim table with the lowered imports
+fn fixup_module(signatures: &[&CoreSignature]) -> Vec<u8> {
+    let mut out = MODULE_HEADER.to_vec();
+    write_vec_section(&mut out, 1, &signatures.iter().map(|signature| core_func_type(signature)).collect::<Vec<_>>());
+    let mut imports = Vec::new();
+    let mut table = name("");
+    table.extend(name(SHIM_TABLE));
+    table.extend([0x01, 0x70, 0x01]);
+    write_u32(&mut table, signatures.len() as u32);
+    write_u32(&mut table, signatures.len() as u32);
+    imports.push(table);
+    for i in 0..signatures.len() as u32 {
+        let mut entry = name("");
+        entry.extend(name(&i.to_string()));
+        entry.push(0x00);
+        write_u32(&mut entry, i);
+        imports.push(entry);
+    }
+    write_vec_section(&mut out, 2, &imports);
+
+    // One active segment at offset 0 listing every import
+    let mut segment = alloc::vec![0x00, 0x41, 0x00, 0x0b];
+    let funcs: Vec<Vec<u8>> = (0..signatures.len() as u32)
+        .map(|i| {
+            let mut entry = Vec::new();
+            write_u32(&mut entry, i);
+            entry
+        })
+        .collect();
+    write_vec(&mut segment, &funcs);
+    write_vec_section(&mut out, 9, &[segment]);
+    out
+}
+
+fn core_func_type(signature: &CoreSignature) -> Vec<u8> {
+    let code = |ty: &Type| match ty {
+        Type::I64 => 0x7e,
+        Type::F32 => 0x7d,
+        Type::F64 => 0x7c,
+        _ => 0x7f,
+    };
+    let mut out = alloc::vec![0x60];
+    write_u32(&mut out, signature.params.len() as u32);
+    out.extend(signature.params.iter().map(code));
+    write_u32(&mut out, signature.results.len() as u32);
+    out.extend(signature.results.iter().map(code));
+    out
+}
+
+fn name(text: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_u32(&mut out, text.len() as u32);
+    out.extend_from_slice(text.as_bytes());
+    out
+}
+
+/// Plain import or export name
+fn extern_name(text: &str) -> Vec<u8> {
+    let mut out = alloc::vec![0x00];
+    out.extend(name(text));
+    out
+}
+
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Type index as a non-negative signed LEB128, as value types encode it
+fn write_s33(out: &mut Vec<u8>, value: u32) {
+    let mut value = u64::from(value);
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 && byte & 0x40 == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_vec(out: &mut Vec<u8>, items: &[Vec<u8>]) {
+    write_u32(out, items.len() as u32);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_u32(out, contents.len() as u32);
+    out.extend_from_slice(contents);
+}
+
+/// Writes a section holding a vector of items, unless it is empty
+fn write_vec_section(out: &mut Vec<u8>, id: u8, items: &[Vec<u8>]) {
+    if items.is_empty() {
+        return;
+    }
+    let mut contents = Vec::new();
+    write_vec(&mut contents, items);
+    write_section(out, id, &contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::introspect::{inspect, CanonAdapter, ComponentItem, ExternKind};
+    use crate::component::Adapter;
+
+    const WORLD: &str = r#"
+        package example:plugin@1.0.0;
+
+        interface logger {
+            record entry { message: string, level: u8 }
+            log: func(entry: entry);
+            ticks: func() -> u64;
+        }
+
+        interface greeter {
+            greet: func(name: string) -> string;
+            add: func(a: s32, b: s32) -> s32;
+        }
+
+        world plugin {
+            import logger;
+            export greeter;
+        }
+    "#;
+
+    fn section(id: u8, items: &[Vec<u8>], out: &mut Vec<u8>) {
+        let mut contents = Vec::new();
+        write_vec(&mut contents, items);
+        write_section(out, id, &contents);
+    }
+
+    /// Core module importing the functions named in `imports` from the
+    /// logger and exporting a memory, `cabi_realloc`, `greet`, and `add`
+    fn core_module(imports: &[&str]) -> Vec<u8> {
+        let signature = |params: &[Type], results: &[Type]| CoreSignature { params: params.to_vec(), results: results.to_vec() };
+        let types = [
+            signature(&[Type::I32, Type::I32, Type::I32], &[]),
+            signature(&[], &[Type::I64]),
+            signature(&[Type::I32, Type::I32, Type::I32, Type::I32], &[Type::I32]),
+            signature(&[Type::I32, Type::I32], &[Type::I32]),
+        ];
+        let mut module = MODULE_HEADER.to_vec();
+        section(1, &types.iter().map(core_func_type).collect::<Vec<_>>(), &mut module);
+        let imports: Vec<Vec<u8>> = imports.iter()
+            .map(|import| {
+                let mut entry = name("example:plugin/logger@1.0.0");
+                entry.extend(name(import));
+                entry.extend([0x00, if *import == "log" { 0 } else { 1 }]);
+                entry
+            })
+            .collect();
+        let count = imports.len() as u8;
+        section(2, &imports, &mut module);
+        section(3, &[alloc::vec![2], alloc::vec![3], alloc::vec![3]], &mut module);
+        section(5, &[alloc::vec![0x00, 0x01]], &mut module);
+        let mut exports = Vec::new();
+        for (i, export) in ["cabi_realloc", "greet", "add"].iter().enumerate() {
+            let mut entry = name(export);
+            entry.extend([0x00, count + i as u8]);
+            exports.push(entry);
+        }
+        let mut memory = name("memory");
+        memory.extend([0x02, 0x00]);
+        exports.push(memory);
+        section(7, &exports, &mut module);
+        let bodies = alloc::vec![alloc::vec![0x04, 0x00, 0x41, 0x00, 0x0b]; 3];
+        section(10, &bodies, &mut module);
+        module
+    }
+
+    fn encode(module: &[u8]) -> Vec<u8> {
+        let package = WitPackage::parse(WORLD).unwrap();
+        let adapter = Adapter::new(&package, "plugin").adapt(module).unwrap();
+        ComponentEncoder::new(&package, &adapter).encode(module).unwrap()
+    }
+
+    #[test]
+    fn test_encode_lifts_exports_into_instance() {
+        let info = inspect(&encode(&core_module(&["ticks"]))).unwrap();
+        assert!(info.is_valid(), "{:?}", info.issues);
+        assert_eq!(info.imports, [ComponentItem { name: "example:plugin/logger@1.0.0".to_string(), kind: ExternKind::Instance }]);
+        assert_eq!(info.exports, [ComponentItem { name: "example:plugin/greeter@1.0.0".to_string(), kind: ExternKind::Instance }]);
+        // Without memory options the import is lowered before instantiation
+        assert_eq!(info.modules.len(), 1);
+        assert!(matches!(&info.adapters[0], CanonAdapter::Lower { func: 0, options } if options.memory.is_none()));
+        assert!(matches!(
+            &info.adapters[1],
+            CanonAdapter::Lift { options, .. } if options.memory == Some(0) && options.realloc == Some(1)
+        ));
+        assert!(matches!(&info.adapters[2], CanonAdapter::Lift { options, .. } if options.memory.is_none()));
+    }
+
+    #[test]
+    fn test_encode_shims_imports_needing_memory() {
+        let info = inspect(&encode(&core_module(&["log", "ticks"]))).unwrap();
+        assert!(info.is_valid(), "{:?}", info.issues);
+        // Main module, then the shim and fixup modules
+        assert_eq!(info.modules.len(), 3);
+        assert_eq!(info.modules[1].exports.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["$imports", "0"]);
+        assert_eq!(info.modules[2].imports.len(), 2);
+        let lowers: Vec<Option<u32>> = info.adapters.iter()
+            .filter_map(|adapter| match adapter {
+                CanonAdapter::Lower { options, .. } => Some(options.memory),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lowers, [None, Some(0)]);
+    }
+}
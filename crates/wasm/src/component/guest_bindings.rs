@@ -0,0 +1,1131 @@
+//! Guest-side bindings for implementing and calling component interfaces
+//!
+//! A build script parses the component's WIT with `WitPackage::parse` and
+//! writes `GuestBindings::generate` to a file the crate includes. Imported
+//! interfaces become `imports::<name>` modules of functions that lower
+//! their arguments and call the host; exported interfaces become
+//! `exports::<name>::Guest` traits. Given an implementor, the bindings also
+//! emit the canonical ABI entry points that lift arguments, call the
+//! implementation, and lower its result. Records, variants, enums, and
+//! flags become Rust types implementing `abi::ComponentValue`.
+//!
+//! Imported resources become marker types used through `resource::Own`
+//! and `resource::Borrow` handles, with their constructors, methods, and
+//! static functions as associated functions. Exported resources become
+//! `Guest<Name>` traits; the entry points keep their values in a
+//! `resource::ResourceTable` and export the `[dtor]` that drops them.
+//! Handles to exported resources are only supported as the `self` of
+//! methods and the result of constructors.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::canon_async::MAX_FLAT_PARAMS;
+use super::host_bindings::{module_name, pascal_case, rust_ident};
+use super::introspect::MAX_FLAT_RESULTS;
+use super::wit::{ResourceFunction, WitError, WitInterface, WitPackage, WitTypeDef, WitTypeDefKind};
+use super::{ComponentFunction, WitType};
+use crate::wasmir::Type;
+
+/// Guest binding generation errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuestBindingsError {
+    /// The world could not be resolved
+    Wit(WitError),
+    /// A type the bindings cannot represent, with where it is used
+    UnsupportedType { context: String, ty: String },
+    /// A function is `async`
+    AsyncFunction(String),
+    /// Two interfaces map to the same Rust module
+    DuplicateName(String),
+}
+
+impl core::fmt::Display for GuestBindingsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            GuestBindingsError::Wit(err) => write!(f, "{}", err),
+            GuestBindingsError::UnsupportedType { context, ty } => {
+                write!(f, "{} uses unsupported type {}", context, ty)
+            }
+            GuestBindingsError::AsyncFunction(name) => write!(f, "Async function {} is not supported", name),
+            GuestBindingsError::DuplicateName(name) => write!(f, "Duplicate binding name: {}", name),
+        }
+    }
+}
+
+impl From<WitError> for GuestBindingsError {
+    fn from(err: WitError) -> Self {
+        GuestBindingsError::Wit(err)
+    }
+}
+
+/// Generator for a world's guest bindings
+#[derive(Debug, Clone)]
+pub struct GuestBindings<'a> {
+    package: &'a WitPackage,
+    world: &'a str,
+    implementor: Option<String>,
+}
+
+impl<'a> GuestBindings<'a> {
+    pub fn new(package: &'a WitPackage, world: &'a str) -> Self {
+        Self { package, world, implementor: None }
+    }
+
+    /// Sets the path of the type implementing every exported `Guest` trait
+    ///
+    /// Without one only the traits are generated, not the entry points.
+    pub fn implementor(mut self, path: impl Into<String>) -> Self {
+        self.implementor = Some(path.into());
+        self
+    }
+
+    /// Generates the bindings source
+    pub fn generate(&self) -> Result<String, GuestBindingsError> {
+        let interfaces = self.package.world_interfaces(self.world)?;
+        let mut modules = BTreeMap::new();
+        for (name, _) in &interfaces.imports {
+            let module = module_name(name);
+            if modules.values().any(|existing| *existing == module) {
+                return Err(GuestBindingsError::DuplicateName(name.clone()));
+            }
+            modules.insert(name.clone(), module);
+        }
+        let context = Context { package: self.package, imports: &modules };
+
+        let mut out = String::new();
+        let _ = writeln!(out, "// Generated by WasmRust from the `{}` world. Do not edit.\n", self.world);
+
+        out.push_str("pub mod imports {\n");
+        for (name, interface) in &interfaces.imports {
+            context.write_module(&mut out, name, &modules[name], interface, false)?;
+        }
+        out.push_str("}\n\n");
+
+        out.push_str("pub mod exports {\n");
+        let mut exported = Vec::new();
+        for (name, interface) in &interfaces.exports {
+            let module = module_name(name);
+            if exported.contains(&module) {
+                return Err(GuestBindingsError::DuplicateName(name.clone()));
+            }
+            context.write_module(&mut out, name, &module, interface, true)?;
+            exported.push(module);
+        }
+        out.push_str("}\n");
+
+        if let Some(implementor) = &self.implementor {
+            out.push_str("\n#[allow(unused_imports)]\n");
+            out.push_str("use wasm::component::abi::{self, ComponentValue, Vec};\n");
+            out.push_str("#[allow(unused_imports)]\n");
+            out.push_str("use wasm::component::resource;\n");
+            for ((name, interface), module) in interfaces.exports.iter().zip(&exported) {
+                for def in interface.types.iter().filter(|def| def.kind == WitTypeDefKind::Resource) {
+                    write_resource_table(&mut out, implementor, name, module, &def.name);
+                }
+                for function in &interface.functions {
+                    context.write_entry_point(&mut out, implementor, name, module, interface, function)?;
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+struct Context<'a> {
+    package: &'a WitPackage,
+    /// Module of each imported interface, by qualified name
+    imports: &'a BTreeMap<String, String>,
+}
+
+impl Context<'_> {
+    fn write_module(
+        &self,
+        out: &mut String,
+        name: &str,
+        module: &str,
+        interface: &WitInterface,
+        export: bool,
+    ) -> Result<(), GuestBindingsError> {
+        let _ = writeln!(out, "    /// Bindings for `{}`", name);
+        let _ = writeln!(out, "    pub mod {} {{", module);
+        out.push_str("        #[allow(unused_imports)]\n");
+        out.push_str("        use wasm::component::abi::{self, ComponentValue, String, Vec};\n");
+        out.push_str("        #[allow(unused_imports)]\n");
+        out.push_str("        use wasm::component::resource;\n\n");
+
+        for used in &interface.uses {
+            let target = self.package.qualified_name(&used.interface);
+            let target = self.imports.get(&target)
+                .ok_or_else(|| WitError::UnknownInterface(used.interface.clone()))?;
+            for (original, local) in &used.names {
+                if original == local {
+                    let _ = writeln!(out, "        pub use super::super::imports::{}::{};", target, pascal_case(original));
+                } else {
+                    let _ = writeln!(
+                        out,
+                        "        pub use super::super::imports::{}::{} as {};",
+                        target,
+                        pascal_case(original),
+                        pascal_case(local)
+                    );
+                }
+            }
+        }
+        if !interface.uses.is_empty() {
+            out.push('\n');
+        }
+
+        for def in &interface.types {
+            self.write_type(out, interface, def, export)?;
+        }
+
+        if export {
+            let resources: Vec<&str> = interface.types.iter()
+                .filter(|def| def.kind == WitTypeDefKind::Resource)
+                .map(|def| def.name.as_str())
+                .collect();
+            let _ = writeln!(out, "        /// Implemented by the component to export `{}`", name);
+            out.push_str("        pub trait Guest {\n");
+            for resource in &resources {
+                let _ = writeln!(out, "            type {0}: Guest{0};", pascal_case(resource));
+            }
+            for function in interface.functions.iter().filter(|function| ResourceFunction::parse(&function.name).is_none()) {
+                let _ = writeln!(out, "            {};", self.trait_method(interface, function)?);
+            }
+            out.push_str("        }\n");
+            for resource in &resources {
+                let _ = writeln!(out, "\n        /// Implemented by the component's `{}` resource", resource);
+                let _ = writeln!(out, "        pub trait Guest{}: Sized {{", pascal_case(resource));
+                let members = interface.functions.iter()
+                    .filter(|function| ResourceFunction::parse(&function.name).is_some_and(|f| f.resource() == *resource));
+                for function in members {
+                    let _ = writeln!(out, "            {};", self.trait_method(interface, function)?);
+                }
+                out.push_str("        }\n");
+            }
+        } else {
+            self.write_imports(out, name, interface)?;
+        }
+        out.push_str("    }\n\n");
+        Ok(())
+    }
+
+    /// Declaration of a `Guest` or `Guest<Resource>` trait method
+    fn trait_method(&self, interface: &WitInterface, function: &ComponentFunction) -> Result<String, GuestBindingsError> {
+        self.check_function(interface, function)?;
+        let kind = ResourceFunction::parse(&function.name);
+        let mut params = Vec::new();
+        for (i, (param, ty)) in function.params.iter().enumerate() {
+            if i == 0 && matches!(kind, Some(ResourceFunction::Method { .. })) {
+                params.push("&self".to_string());
+                continue;
+            }
+            params.push(format!("{}: {}", param_ident(param), self.rust_type(interface, ty, &function.name)?));
+        }
+        let returns = if matches!(kind, Some(ResourceFunction::Constructor { .. })) {
+            " -> Self".to_string()
+        } else {
+            self.returns(interface, function)?
+        };
+        Ok(format!("fn {}({}){}", function_ident(function), params.join(", "), returns))
+    }
+
+    fn write_type(
+        &self,
+        out: &mut String,
+        interface: &WitInterface,
+        def: &WitTypeDef,
+        export: bool,
+    ) -> Result<(), GuestBindingsError> {
+        let name = pascal_case(&def.name);
+        let context = format!("{}.{}", interface.name, def.name);
+        let empty = || GuestBindingsError::UnsupportedType { context: context.clone(), ty: "empty type".to_string() };
+        let members: Vec<&WitType> = match &def.kind {
+            WitTypeDefKind::Alias(ty) => alloc::vec![ty],
+            WitTypeDefKind::Record(fields) => fields.iter().map(|(_, ty)| ty).collect(),
+            WitTypeDefKind::Variant(cases) => cases.iter().filter_map(|(_, ty)| ty.as_ref()).collect(),
+            _ => Vec::new(),
+        };
+        for member in members {
+            if self.has_handle(interface, member)? {
+                return Err(GuestBindingsError::UnsupportedType { context, ty: format!("resource handle {}", member) });
+            }
+        }
+        match &def.kind {
+            // Exported resources are implemented through `Guest<Name>` traits
+            WitTypeDefKind::Resource if export => {}
+            WitTypeDefKind::Resource => {
+                let _ = writeln!(out, "        /// Resource `{}`, held through `resource::Own` and `resource::Borrow`", def.name);
+                out.push_str("        #[derive(Debug)]\n");
+                let _ = writeln!(out, "        pub struct {} {{\n            _private: (),\n        }}\n", name);
+                let _ = writeln!(out, "        impl resource::Resource for {} {{", name);
+                out.push_str("            fn drop_handle(handle: u32) {\n");
+                out.push_str("                // SAFETY: `Own` drops each handle it owns once\n");
+                let _ = writeln!(out, "                unsafe {{ import_drop_{}(handle as i32) }}", snake_case(&def.name));
+                out.push_str("            }\n");
+                out.push_str("        }\n\n");
+            }
+            WitTypeDefKind::Alias(ty) => {
+                let _ = writeln!(out, "        pub type {} = {};\n", name, self.rust_type(interface, ty, &context)?);
+            }
+            WitTypeDefKind::Record(fields) => {
+                if fields.is_empty() {
+                    return Err(empty());
+                }
+                let types = fields.iter()
+                    .map(|(_, ty)| self.rust_type(interface, ty, &context))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let idents: Vec<String> = fields.iter().map(|(field, _)| rust_ident(field)).collect();
+
+                out.push_str("        #[derive(Debug, Clone, PartialEq)]\n");
+                let _ = writeln!(out, "        pub struct {} {{", name);
+                for (ident, ty) in idents.iter().zip(&types) {
+                    let _ = writeln!(out, "            pub {}: {},", ident, ty);
+                }
+                out.push_str("        }\n\n");
+
+                let layouts: Vec<String> = types.iter().map(|ty| format!("abi::layout::<{}>()", ty)).collect();
+                let _ = writeln!(out, "        impl {} {{", name);
+                let _ = writeln!(
+                    out,
+                    "            const FIELDS: [(usize, usize); {}] = [{}];",
+                    fields.len(),
+                    layouts.join(", ")
+                );
+                out.push_str("        }\n\n");
+
+                let flat: Vec<String> = types.iter()
+                    .map(|ty| format!("<{} as ComponentValue>::FLAT_COUNT", ty))
+                    .collect();
+                write_impl_header(out, &name);
+                out.push_str("            const SIZE: usize = abi::record_size(&Self::FIELDS);\n");
+                out.push_str("            const ALIGN: usize = abi::record_align(&Self::FIELDS);\n");
+                let _ = writeln!(out, "            const FLAT_COUNT: usize = {};\n", flat.join(" + "));
+                out.push_str("            fn lower(&self, flat: &mut Vec<u64>, allocs: &mut abi::Allocations) {\n");
+                for ident in &idents {
+                    let _ = writeln!(out, "                self.{}.lower(flat, allocs);", ident);
+                }
+                out.push_str("            }\n\n");
+                out.push_str("            unsafe fn lift(flat: &mut core::slice::Iter<'_, u64>) -> Self {\n");
+                out.push_str("                Self {\n");
+                for ident in &idents {
+                    let _ = writeln!(out, "                    {}: ComponentValue::lift(flat),", ident);
+                }
+                out.push_str("                }\n");
+                out.push_str("            }\n\n");
+                out.push_str("            unsafe fn store(&self, ptr: *mut u8, allocs: &mut abi::Allocations) {\n");
+                out.push_str("                let offsets = abi::field_offsets(Self::FIELDS);\n");
+                for (i, ident) in idents.iter().enumerate() {
+                    let _ = writeln!(out, "                self.{}.store(ptr.add(offsets[{}]), allocs);", ident, i);
+                }
+                out.push_str("            }\n\n");
+                out.push_str("            unsafe fn load(ptr: *const u8) -> Self {\n");
+                out.push_str("                let offsets = abi::field_offsets(Self::FIELDS);\n");
+                out.push_str("                Self {\n");
+                for (i, ident) in idents.iter().enumerate() {
+                    let _ = writeln!(out, "                    {}: ComponentValue::load(ptr.add(offsets[{}])),", ident, i);
+                }
+                out.push_str("                }\n");
+                out.push_str("            }\n");
+                out.push_str("        }\n\n");
+            }
+            WitTypeDefKind::Variant(cases) => {
+                let cases = cases.iter()
+                    .map(|(case, ty)| match ty {
+                        Some(ty) => Ok((pascal_case(case), Some(self.rust_type(interface, ty, &context)?))),
+                        None => Ok((pascal_case(case), None)),
+                    })
+                    .collect::<Result<Vec<_>, GuestBindingsError>>()?;
+                if cases.is_empty() {
+                    return Err(empty());
+                }
+                write_variant(out, &name, &cases, "#[derive(Debug, Clone, PartialEq)]");
+            }
+            WitTypeDefKind::Enum(cases) => {
+                if cases.is_empty() {
+                    return Err(empty());
+                }
+                let cases: Vec<(String, Option<String>)> = cases.iter().map(|case| (pascal_case(case), None)).collect();
+                write_variant(out, &name, &cases, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]");
+            }
+            WitTypeDefKind::Flags(flags) => {
+                let repr = match flags.len() {
+                    0 => return Err(empty()),
+                    1..=8 => "u8",
+                    9..=16 => "u16",
+                    17..=32 => "u32",
+                    _ => {
+                        return Err(GuestBindingsError::UnsupportedType {
+                            context,
+                            ty: "flags with more than 32 members".to_string(),
+                        })
+                    }
+                };
+                out.push_str("        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]\n");
+                let _ = writeln!(out, "        pub struct {}(pub {});\n", name, repr);
+                let _ = writeln!(out, "        impl {} {{", name);
+                for (i, flag) in flags.iter().enumerate() {
+                    let _ = writeln!(
+                        out,
+                        "            pub const {}: Self = Self(1 << {});",
+                        rust_ident(flag).trim_start_matches("r#").to_ascii_uppercase(),
+                        i
+                    );
+                }
+                out.push_str("\n            /// Checks whether every flag in `other` is set\n");
+                out.push_str("            pub fn contains(self, other: Self) -> bool {\n");
+                out.push_str("                self.0 & other.0 == other.0\n");
+                out.push_str("            }\n");
+                out.push_str("        }\n\n");
+                let _ = writeln!(out, "        impl core::ops::BitOr for {} {{", name);
+                out.push_str("            type Output = Self;\n\n");
+                out.push_str("            fn bitor(self, other: Self) -> Self {\n");
+                out.push_str("                Self(self.0 | other.0)\n");
+                out.push_str("            }\n");
+                out.push_str("        }\n\n");
+                write_impl_header(out, &name);
+                let _ = writeln!(out, "            const SIZE: usize = <{} as ComponentValue>::SIZE;", repr);
+                let _ = writeln!(out, "            const ALIGN: usize = <{} as ComponentValue>::ALIGN;", repr);
+                out.push_str("            const FLAT_COUNT: usize = 1;\n\n");
+                out.push_str("            fn lower(&self, flat: &mut Vec<u64>, allocs: &mut abi::Allocations) {\n");
+                out.push_str("                self.0.lower(flat, allocs);\n");
+                out.push_str("            }\n\n");
+                out.push_str("            unsafe fn lift(flat: &mut core::slice::Iter<'_, u64>) -> Self {\n");
+                out.push_str("                Self(ComponentValue::lift(flat))\n");
+                out.push_str("            }\n\n");
+                out.push_str("            unsafe fn store(&self, ptr: *mut u8, allocs: &mut abi::Allocations) {\n");
+                out.push_str("                self.0.store(ptr, allocs);\n");
+                out.push_str("            }\n\n");
+                out.push_str("            unsafe fn load(ptr: *const u8) -> Self {\n");
+                out.push_str("                Self(ComponentValue::load(ptr))\n");
+                out.push_str("            }\n");
+                out.push_str("        }\n\n");
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the host calls of an imported interface
+    fn write_imports(&self, outer: &mut String, name: &str, interface: &WitInterface) -> Result<(), GuestBindingsError> {
+        let mut externs = Vec::new();
+        let mut methods: BTreeMap<&str, String> = BTreeMap::new();
+        for def in interface.types.iter().filter(|def| def.kind == WitTypeDefKind::Resource) {
+            let declaration = format!("fn import_drop_{}(arg0: i32);", snake_case(&def.name));
+            externs.push((format!("[resource-drop]{}", def.name), declaration));
+        }
+        for function in &interface.functions {
+            self.check_function(interface, function)?;
+            let ident = function_ident(function);
+            let raw = format!("import_{}", symbol_name(function));
+            let resource = ResourceFunction::parse(&function.name).map(ResourceFunction::resource);
+            let out = &mut String::new();
+            let (core_params, core_result) = self.core_signature(interface, function)?;
+            let spilled = self.flat_params(interface, function)? > MAX_FLAT_PARAMS;
+            let indirect = core_result.is_none() && function.result.is_some();
+
+            let raw_params: Vec<String> = core_params.iter().enumerate()
+                .map(|(i, ty)| format!("arg{}: {}", i, core_type(ty)))
+                .collect();
+            let raw_result = core_result.as_ref().map(|ty| format!(" -> {}", core_type(ty))).unwrap_or_default();
+            externs.push((function.name.clone(), format!("fn {}({}){};", raw, raw_params.join(", "), raw_result)));
+
+            let mut params = Vec::new();
+            let mut lowers = Vec::new();
+            let mut types = Vec::new();
+            for (param, ty) in &function.params {
+                let rust = self.rust_type(interface, ty, &function.name)?;
+                let ident = param_ident(param);
+                if is_scalar(ty) || self.is_handle(interface, ty)? {
+                    params.push(format!("{}: {}", ident, rust));
+                    lowers.push(format!("&{}", ident));
+                } else {
+                    params.push(format!("{}: &{}", ident, rust));
+                    lowers.push(ident);
+                }
+                types.push(rust);
+            }
+
+            let _ = writeln!(out, "        /// Calls `{}`", function.name);
+            let _ = writeln!(
+                out,
+                "        pub fn {}({}){} {{",
+                ident,
+                params.join(", "),
+                self.returns(interface, function)?
+            );
+            if !function.params.is_empty() || indirect {
+                out.push_str("            let mut allocs = abi::Allocations::new();\n");
+            }
+            let mut args = Vec::new();
+            if spilled {
+                let layouts: Vec<String> = types.iter().map(|ty| format!("abi::layout::<{}>()", ty)).collect();
+                let _ = writeln!(out, "            let fields = [{}];", layouts.join(", "));
+                out.push_str("            let offsets = abi::field_offsets(fields);\n");
+                out.push_str("            let params = allocs.alloc(abi::record_size(&fields), abi::record_align(&fields));\n");
+                out.push_str("            // SAFETY: the area was allocated for the parameters' record layout\n");
+                out.push_str("            unsafe {\n");
+                for (i, lower) in lowers.iter().enumerate() {
+                    let _ = writeln!(
+                        out,
+                        "                ComponentValue::store({}, params.add(offsets[{}]), &mut allocs);",
+                        lower, i
+                    );
+                }
+                out.push_str("            }\n");
+                args.push("params as i32".to_string());
+            } else if !function.params.is_empty() {
+                out.push_str("            let mut flat = Vec::new();\n");
+                for lower in &lowers {
+                    let _ = writeln!(out, "            ComponentValue::lower({}, &mut flat, &mut allocs);", lower);
+                }
+                // The return area pointer, if any, is the last core parameter
+                let lowered = &core_params[..core_params.len() - usize::from(indirect)];
+                args.extend(lowered.iter().enumerate().map(|(i, ty)| from_bits(&format!("flat[{}]", i), ty)));
+            }
+            if indirect {
+                let result = self.rust_type(interface, function.result.as_ref().expect("indirect result"), &function.name)?;
+                let _ = writeln!(
+                    out,
+                    "            let ret_area = allocs.alloc(<{0} as ComponentValue>::SIZE, <{0} as ComponentValue>::ALIGN);",
+                    result
+                );
+                args.push("ret_area as i32".to_string());
+            }
+
+            out.push_str("            // SAFETY: the arguments were lowered for the import's core signature\n");
+            let call = format!("unsafe {{ {}({}) }}", raw, args.join(", "));
+            match (&core_result, indirect) {
+                (Some(ty), _) => {
+                    let _ = writeln!(out, "            let ret = {};", call);
+                    out.push_str("            // SAFETY: the host lowered the result for its WIT type\n");
+                    let _ = writeln!(out, "            unsafe {{ ComponentValue::lift(&mut [{}].iter()) }}", to_bits("ret", ty));
+                }
+                (None, true) => {
+                    let _ = writeln!(out, "            {};", call);
+                    out.push_str("            // SAFETY: the host stored the result in the return area\n");
+                    out.push_str("            unsafe { ComponentValue::load(ret_area) }\n");
+                }
+                (None, false) => {
+                    let _ = writeln!(out, "            {}", call);
+                }
+            }
+            out.push_str("        }\n\n");
+            match resource {
+                Some(resource) => {
+                    let block = methods.entry(resource).or_default();
+                    for line in out.lines() {
+                        if !line.is_empty() {
+                            block.push_str("    ");
+                        }
+                        block.push_str(line);
+                        block.push('\n');
+                    }
+                }
+                None => outer.push_str(out),
+            }
+        }
+        let out = outer;
+        for (resource, block) in &methods {
+            let _ = writeln!(out, "        impl {} {{", pascal_case(resource));
+            out.push_str(block.trim_end());
+            out.push_str("\n        }\n\n");
+        }
+
+        if externs.is_empty() {
+            return Ok(());
+        }
+        let _ = writeln!(out, "        #[link(wasm_import_module = {:?})]", name);
+        out.push_str("        extern \"C\" {\n");
+        for (link_name, declaration) in &externs {
+            let _ = writeln!(out, "            #[link_name = {:?}]", link_name);
+            let _ = writeln!(out, "            {}", declaration);
+        }
+        out.push_str("        }\n");
+        Ok(())
+    }
+
+    /// Writes the canonical ABI export and post-return of one function
+    fn write_entry_point(
+        &self,
+        out: &mut String,
+        implementor: &str,
+        name: &str,
+        module: &str,
+        interface: &WitInterface,
+        function: &ComponentFunction,
+    ) -> Result<(), GuestBindingsError> {
+        let symbol = format!("{}_{}", module.trim_start_matches("r#"), symbol_name(function));
+        let kind = ResourceFunction::parse(&function.name);
+        let export_name = format!("{}#{}", name, function.name);
+        let (mut core_params, core_result) = self.core_signature(interface, function)?;
+        let spilled = self.flat_params(interface, function)? > MAX_FLAT_PARAMS;
+        // Results that do not fit in one core value are returned by address
+        // instead of written through a caller-provided pointer
+        let indirect = core_result.is_none() && function.result.is_some();
+        if indirect {
+            core_params.pop();
+        }
+
+        let raw_params: Vec<String> = core_params.iter().enumerate()
+            .map(|(i, ty)| format!("arg{}: {}", i, core_type(ty)))
+            .collect();
+        let raw_result = match (&core_result, indirect) {
+            (Some(ty), _) => format!(" -> {}", core_type(ty)),
+            (None, true) => " -> i32".to_string(),
+            (None, false) => String::new(),
+        };
+
+        let _ = writeln!(out, "\n#[export_name = {:?}]", export_name);
+        let _ = writeln!(out, "unsafe extern \"C\" fn __export_{}({}){} {{", symbol, raw_params.join(", "), raw_result);
+        let names: Vec<String> = function.params.iter().map(|(param, _)| param_ident(param)).collect();
+        // A method's `self` arrives as the representation of its value
+        let this = match kind {
+            Some(ResourceFunction::Method { resource, .. }) => Some(resource),
+            _ => None,
+        };
+        let types = function.params.iter().enumerate()
+            .map(|(i, (_, ty))| match this {
+                Some(_) if i == 0 => Ok("u32".to_string()),
+                _ => self.rust_type(interface, ty, &function.name),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let declare = |i: usize, param: &str| match this {
+            Some(_) if i == 0 => format!("{}: u32", param),
+            _ => param.to_string(),
+        };
+        if spilled {
+            let layouts: Vec<String> = types.iter().map(|ty| format!("abi::layout::<{}>()", ty)).collect();
+            out.push_str("    let params = arg0 as usize as *mut u8;\n");
+            let _ = writeln!(out, "    let fields = [{}];", layouts.join(", "));
+            out.push_str("    let offsets = abi::field_offsets(fields);\n");
+            for (i, param) in names.iter().enumerate() {
+                let _ = writeln!(out, "    let {} = ComponentValue::load(params.add(offsets[{}]));", declare(i, param), i);
+            }
+            out.push_str("    abi::free(params, abi::record_size(&fields), abi::record_align(&fields));\n");
+        } else if !function.params.is_empty() {
+            let bits: Vec<String> = core_params.iter().enumerate()
+                .map(|(i, ty)| to_bits(&format!("arg{}", i), ty))
+                .collect();
+            let _ = writeln!(out, "    let flat = [{}];", bits.join(", "));
+            out.push_str("    let flat = &mut flat.iter();\n");
+            for (i, param) in names.iter().enumerate() {
+                let _ = writeln!(out, "    let {} = ComponentValue::lift(flat);", declare(i, param));
+            }
+        }
+        if let Some(resource) = this {
+            let _ = writeln!(
+                out,
+                "    let {0} = {1}.get({0}).expect(\"invalid `{2}` handle\");",
+                names[0],
+                table_name(module, resource),
+                resource
+            );
+        }
+        let target = match &kind {
+            Some(kind) => format!(
+                "<<{0} as exports::{1}::Guest>::{2} as exports::{1}::Guest{2}>",
+                implementor,
+                module,
+                pascal_case(kind.resource())
+            ),
+            None => format!("<{} as exports::{}::Guest>", implementor, module),
+        };
+        let call = format!("{}::{}({})", target, function_ident(function), names.join(", "));
+        match (&core_result, indirect) {
+            (Some(_), _) if matches!(kind, Some(ResourceFunction::Constructor { .. })) => {
+                let resource = kind.map(ResourceFunction::resource).unwrap_or_default();
+                let _ = writeln!(out, "    let rep = {}.insert({});", table_name(module, resource), call);
+                let _ = writeln!(out, "    {}(rep as i32)", resource_new_name(module, resource));
+                out.push_str("}\n");
+            }
+            (Some(ty), _) => {
+                let _ = writeln!(out, "    let result = {};", call);
+                out.push_str("    let mut flat = Vec::new();\n");
+                out.push_str("    ComponentValue::lower(&result, &mut flat, &mut abi::Allocations::new());\n");
+                let _ = writeln!(out, "    {}", from_bits("flat[0]", ty));
+                out.push_str("}\n");
+            }
+            (None, true) => {
+                let _ = writeln!(out, "    abi::return_area({}) as i32", call);
+                out.push_str("}\n\n");
+                let _ = writeln!(out, "#[export_name = {:?}]", format!("cabi_post_{}", export_name));
+                let _ = writeln!(out, "unsafe extern \"C\" fn __post_return_{}(_: i32) {{", symbol);
+                out.push_str("    abi::post_return();\n");
+                out.push_str("}\n");
+            }
+            (None, false) => {
+                let _ = writeln!(out, "    {};", call);
+                out.push_str("}\n");
+            }
+        }
+        Ok(())
+    }
+
+    fn check_function(&self, interface: &WitInterface, function: &ComponentFunction) -> Result<(), GuestBindingsError> {
+        if function.is_async {
+            return Err(GuestBindingsError::AsyncFunction(format!("{}.{}", interface.name, function.name)));
+        }
+        Ok(())
+    }
+
+    /// Rust result clause of a function, empty without a result
+    fn returns(&self, interface: &WitInterface, function: &ComponentFunction) -> Result<String, GuestBindingsError> {
+        match &function.result {
+            Some(ty) => Ok(format!(" -> {}", self.rust_type(interface, ty, &function.name)?)),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Rust type of a WIT type as seen from `interface`
+    fn rust_type(&self, interface: &WitInterface, ty: &WitType, context: &str) -> Result<String, GuestBindingsError> {
+        let optional = |ty: &Option<alloc::boxed::Box<WitType>>| match ty {
+            Some(ty) => self.rust_type(interface, ty, context),
+            None => Ok("()".to_string()),
+        };
+        Ok(match ty {
+            WitType::Bool => "bool".to_string(),
+            WitType::U8 => "u8".to_string(),
+            WitType::U16 => "u16".to_string(),
+            WitType::U32 => "u32".to_string(),
+            WitType::U64 => "u64".to_string(),
+            WitType::S8 => "i8".to_string(),
+            WitType::S16 => "i16".to_string(),
+            WitType::S32 => "i32".to_string(),
+            WitType::S64 => "i64".to_string(),
+            WitType::F32 => "f32".to_string(),
+            WitType::F64 => "f64".to_string(),
+            WitType::Char => "char".to_string(),
+            WitType::String => "String".to_string(),
+            WitType::List(inner) => format!("Vec<{}>", self.rust_type(interface, inner, context)?),
+            WitType::Option(inner) => format!("Option<{}>", self.rust_type(interface, inner, context)?),
+            WitType::Result { ok, err } => format!("Result<{}, {}>", optional(ok)?, optional(err)?),
+            WitType::Named(name) | WitType::Own(name) | WitType::Borrow(name) => {
+                let (owner, def) = self.package.resolve_type(interface, name)?;
+                if def.kind != WitTypeDefKind::Resource {
+                    return Ok(pascal_case(name));
+                }
+                // Values of exported resources live in the entry points' tables,
+                // so only their constructors and methods can take handles
+                if !self.imports.contains_key(&self.package.qualified_name(&owner.name)) {
+                    return Err(GuestBindingsError::UnsupportedType {
+                        context: context.to_string(),
+                        ty: format!("handle to exported resource {}", name),
+                    });
+                }
+                match ty {
+                    WitType::Borrow(_) => format!("resource::Borrow<'_, {}>", pascal_case(name)),
+                    _ => format!("resource::Own<{}>", pascal_case(name)),
+                }
+            }
+            WitType::Future(_) | WitType::Stream(_) => {
+                return Err(GuestBindingsError::UnsupportedType { context: context.to_string(), ty: ty.to_string() })
+            }
+        })
+    }
+
+    /// Whether a type is a handle, passed by value like a scalar
+    fn is_handle(&self, interface: &WitInterface, ty: &WitType) -> Result<bool, GuestBindingsError> {
+        Ok(match ty {
+            WitType::Own(_) | WitType::Borrow(_) => true,
+            WitType::Named(name) => self.package.resolve_type(interface, name)?.1.kind == WitTypeDefKind::Resource,
+            _ => false,
+        })
+    }
+
+    /// Whether a type holds a handle, which type definitions cannot
+    fn has_handle(&self, interface: &WitInterface, ty: &WitType) -> Result<bool, GuestBindingsError> {
+        let mut types = Vec::new();
+        ty.visit(&mut |ty| types.push(ty));
+        for ty in types {
+            if self.is_handle(interface, ty)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn flat_params(&self, interface: &WitInterface, function: &ComponentFunction) -> Result<usize, GuestBindingsError> {
+        let mut flat = Vec::new();
+        for (_, ty) in &function.params {
+            self.package.flatten(interface, ty, &mut flat)?;
+        }
+        Ok(flat.len())
+    }
+
+    /// Core parameters, plus the core result if it is returned directly
+    ///
+    /// Imports take an extra return-area pointer when the result is not.
+    fn core_signature(
+        &self,
+        interface: &WitInterface,
+        function: &ComponentFunction,
+    ) -> Result<(Vec<Type>, Option<Type>), GuestBindingsError> {
+        let mut params = Vec::new();
+        for (_, ty) in &function.params {
+            self.package.flatten(interface, ty, &mut params)?;
+        }
+        if params.len() > MAX_FLAT_PARAMS {
+            params = alloc::vec![Type::I32];
+        }
+        let mut results = Vec::new();
+        if let Some(result) = &function.result {
+            self.package.flatten(interface, result, &mut results)?;
+        }
+        if results.len() > MAX_FLAT_RESULTS {
+            params.push(Type::I32);
+            return Ok((params, None));
+        }
+        Ok((params, results.pop()))
+    }
+}
+
+/// Writes the table, `[resource-new]` import, and `[dtor]` export of an exported resource
+fn write_resource_table(out: &mut String, implementor: &str, name: &str, module: &str, resource: &str) {
+    let table = table_name(module, resource);
+    let _ = writeln!(
+        out,
+        "\nstatic {}: resource::ResourceTable<<{} as exports::{}::Guest>::{}> = resource::ResourceTable::new();",
+        table,
+        implementor,
+        module,
+        pascal_case(resource)
+    );
+    let _ = writeln!(out, "\n#[link(wasm_import_module = {:?})]", format!("[export]{}", name));
+    out.push_str("extern \"C\" {\n");
+    let _ = writeln!(out, "    #[link_name = {:?}]", format!("[resource-new]{}", resource));
+    let _ = writeln!(out, "    fn {}(rep: i32) -> i32;", resource_new_name(module, resource));
+    out.push_str("}\n");
+    let _ = writeln!(out, "\n#[export_name = {:?}]", format!("{}#[dtor]{}", name, resource));
+    let symbol = format!("{}_{}", module.trim_start_matches("r#"), snake_case(resource));
+    let _ = writeln!(out, "unsafe extern \"C\" fn __dtor_{}(rep: i32) {{", symbol);
+    let _ = writeln!(out, "    drop({}.remove(rep as u32));", table);
+    out.push_str("}\n");
+}
+
+fn table_name(module: &str, resource: &str) -> String {
+    format!("{}_{}_TABLE", module.trim_start_matches("r#"), snake_case(resource)).to_ascii_uppercase()
+}
+
+fn resource_new_name(module: &str, resource: &str) -> String {
+    format!("import_new_{}_{}", module.trim_start_matches("r#"), snake_case(resource))
+}
+
+/// Rust name of a function, with resource functions named within their resource
+fn function_ident(function: &ComponentFunction) -> String {
+    match ResourceFunction::parse(&function.name) {
+        Some(ResourceFunction::Constructor { .. }) => "new".to_string(),
+        Some(ResourceFunction::Method { name, .. } | ResourceFunction::Static { name, .. }) => rust_ident(name),
+        None => rust_ident(&function.name),
+    }
+}
+
+/// Unique identifier fragment for a function's raw symbols
+fn symbol_name(function: &ComponentFunction) -> String {
+    match ResourceFunction::parse(&function.name) {
+        Some(ResourceFunction::Constructor { resource }) => format!("constructor_{}", snake_case(resource)),
+        Some(ResourceFunction::Method { resource, name }) => format!("method_{}_{}", snake_case(resource), snake_case(name)),
+        Some(ResourceFunction::Static { resource, name }) => format!("static_{}_{}", snake_case(resource), snake_case(name)),
+        None => snake_case(&function.name),
+    }
+}
+
+/// Rust name of a parameter, renaming a method's `self`
+fn param_ident(name: &str) -> String {
+    if name == "self" {
+        "this".to_string()
+    } else {
+        rust_ident(name)
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    name.trim_start_matches('%').replace('-', "_")
+}
+
+/// Writes the opening of a `ComponentValue` impl
+fn write_impl_header(out: &mut String, name: &str) {
+    out.push_str("        // SAFETY: the layout constants follow the canonical ABI for the WIT type\n");
+    let _ = writeln!(out, "        unsafe impl ComponentValue for {} {{", name);
+}
+
+/// Writes an enum over variant cases with its `ComponentValue` impl
+fn write_variant(out: &mut String, name: &str, cases: &[(String, Option<String>)], derive: &str) {
+    let _ = writeln!(out, "        {}", derive);
+    let _ = writeln!(out, "        pub enum {} {{", name);
+    for (case, payload) in cases {
+        match payload {
+            Some(ty) => {
+                let _ = writeln!(out, "            {}({}),", case, ty);
+            }
+            None => {
+                let _ = writeln!(out, "            {},", case);
+            }
+        }
+    }
+    out.push_str("        }\n\n");
+
+    let layouts: Vec<String> = cases.iter()
+        .map(|(_, payload)| format!("abi::case::<{}>()", payload.as_deref().unwrap_or("()")))
+        .collect();
+    let _ = writeln!(out, "        impl {} {{", name);
+    let _ = writeln!(out, "            const CASES: [(usize, usize, usize); {}] = [{}];", cases.len(), layouts.join(", "));
+    out.push_str("        }\n\n");
+
+    let has_payload = cases.iter().any(|(_, payload)| payload.is_some());
+    write_impl_header(out, name);
+    out.push_str("            const SIZE: usize = abi::variant_size(&Self::CASES);\n");
+    out.push_str("            const ALIGN: usize = abi::variant_align(&Self::CASES);\n");
+    out.push_str("            const FLAT_COUNT: usize = abi::variant_flat_count(&Self::CASES);\n\n");
+
+    out.push_str("            fn lower(&self, flat: &mut Vec<u64>, allocs: &mut abi::Allocations) {\n");
+    out.push_str("                let width = Self::FLAT_COUNT - 1;\n");
+    out.push_str("                match self {\n");
+    for (i, (case, payload)) in cases.iter().enumerate() {
+        let (pattern, value) = if payload.is_some() { ("(payload)", "payload") } else { ("", "&()") };
+        let _ = writeln!(out, "                    {}::{}{} => {{", name, case, pattern);
+        let _ = writeln!(out, "                        flat.push({});", i);
+        let _ = writeln!(out, "                        abi::lower_case({}, width, flat, allocs);", value);
+        out.push_str("                    }\n");
+    }
+    out.push_str("                }\n");
+    out.push_str("            }\n\n");
+
+    out.push_str("            unsafe fn lift(flat: &mut core::slice::Iter<'_, u64>) -> Self {\n");
+    out.push_str("                let width = Self::FLAT_COUNT - 1;\n");
+    out.push_str("                match abi::next_flat(flat) as u32 {\n");
+    for (i, (case, payload)) in cases.iter().enumerate() {
+        if payload.is_some() {
+            let _ = writeln!(out, "                    {} => {}::{}(abi::lift_case(flat, width)),", i, name, case);
+        } else {
+            let _ = writeln!(out, "                    {} => {{", i);
+            out.push_str("                        abi::lift_case::<()>(flat, width);\n");
+            let _ = writeln!(out, "                        {}::{}", name, case);
+            out.push_str("                    }\n");
+        }
+    }
+    out.push_str("                    other => abi::invalid_discriminant(other),\n");
+    out.push_str("                }\n");
+    out.push_str("            }\n\n");
+
+    out.push_str("            unsafe fn store(&self, ptr: *mut u8, allocs: &mut abi::Allocations) {\n");
+    if has_payload {
+        out.push_str("                let payload = ptr.add(abi::variant_payload_offset(&Self::CASES));\n");
+    } else {
+        out.push_str("                let _ = allocs;\n");
+    }
+    out.push_str("                match self {\n");
+    for (i, (case, payload)) in cases.iter().enumerate() {
+        if payload.is_some() {
+            let _ = writeln!(out, "                    {}::{}(value) => {{", name, case);
+            let _ = writeln!(out, "                        abi::store_discriminant(ptr, {}, {});", cases.len(), i);
+            out.push_str("                        value.store(payload, allocs);\n");
+            out.push_str("                    }\n");
+        } else {
+            let _ = writeln!(
+                out,
+                "                    {}::{} => abi::store_discriminant(ptr, {}, {}),",
+                name,
+                case,
+                cases.len(),
+                i
+            );
+        }
+    }
+    out.push_str("                }\n");
+    out.push_str("            }\n\n");
+
+    out.push_str("            unsafe fn load(ptr: *const u8) -> Self {\n");
+    if has_payload {
+        out.push_str("                let payload = ptr.add(abi::variant_payload_offset(&Self::CASES));\n");
+    }
+    let _ = writeln!(out, "                match abi::load_discriminant(ptr, {}) {{", cases.len());
+    for (i, (case, payload)) in cases.iter().enumerate() {
+        if payload.is_some() {
+            let _ = writeln!(out, "                    {} => {}::{}(ComponentValue::load(payload)),", i, name, case);
+        } else {
+            let _ = writeln!(out, "                    {} => {}::{},", i, name, case);
+        }
+    }
+    out.push_str("                    other => abi::invalid_discriminant(other),\n");
+    out.push_str("                }\n");
+    out.push_str("            }\n");
+    out.push_str("        }\n\n");
+}
+
+/// Whether a type is passed by value rather than by reference
+fn is_scalar(ty: &WitType) -> bool {
+    !matches!(
+        ty,
+        WitType::String | WitType::List(_) | WitType::Option(_) | WitType::Result { .. } | WitType::Named(_)
+    )
+}
+
+fn core_type(ty: &Type) -> &'static str {
+    match ty {
+        Type::I64 => "i64",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        _ => "i32",
+    }
+}
+
+/// Converts a core value to the raw bits `ComponentValue` lifts from
+fn to_bits(value: &str, ty: &Type) -> String {
+    match ty {
+        Type::I64 => format!("{} as u64", value),
+        Type::F32 => format!("u64::from({}.to_bits())", value),
+        Type::F64 => format!("{}.to_bits()", value),
+        _ => format!("{} as u32 as u64", value),
+    }
+}
+
+/// Converts raw lowered bits to a core value
+fn from_bits(value: &str, ty: &Type) -> String {
+    match ty {
+        Type::I64 => format!("{} as i64", value),
+        Type::F32 => format!("f32::from_bits({} as u32)", value),
+        Type::F64 => format!("f64::from_bits({})", value),
+        _ => format!("{} as i32", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUGIN: &str = r#"
+        package example:plugin@1.0.0;
+
+        interface types {
+            enum level { debug, info }
+            record entry { level: level, message: string }
+        }
+
+        interface logger {
+            use types.{entry};
+            log: func(entry: entry, retries: u8);
+            history: func() -> list<entry>;
+        }
+
+        interface greeter {
+            variant reply { text(string), code(u32), silent }
+            greet: func(name: string) -> reply;
+            score: func(weight: f32) -> f32;
+        }
+
+        world plugin {
+            import logger;
+            export greeter;
+        }
+    "#;
+
+    fn generate(implementor: Option<&str>) -> String {
+        let package = WitPackage::parse(PLUGIN).unwrap();
+        let mut bindings = GuestBindings::new(&package, "plugin");
+        if let Some(implementor) = implementor {
+            bindings = bindings.implementor(implementor);
+        }
+        bindings.generate().unwrap()
+    }
+
+    #[test]
+    fn test_types_implement_component_value() {
+        let rust = generate(None);
+        assert!(rust.contains("    pub mod types {"));
+        assert!(rust.contains("        pub use super::super::imports::types::Entry;"));
+        assert!(rust.contains("        pub struct Entry {\n            pub level: Level,\n            pub message: String,\n        }"));
+        assert!(rust.contains("            const FIELDS: [(usize, usize); 2] = [abi::layout::<Level>(), abi::layout::<String>()];"));
+        assert!(rust.contains("        #[derive(Debug, Clone, Copy, PartialEq, Eq)]\n        pub enum Level {"));
+        assert!(rust.contains("                    0 => Reply::Text(abi::lift_case(flat, width)),"));
+        assert!(rust.contains("            fn greet(name: String) -> Reply;"));
+        assert!(!rust.contains("#[export_name"));
+    }
+
+    #[test]
+    fn test_imports_lower_arguments_and_lift_results() {
+        let rust = generate(None);
+        assert!(rust.contains("        pub fn log(entry: &Entry, retries: u8) {"));
+        assert!(rust.contains("            ComponentValue::lower(&retries, &mut flat, &mut allocs);"));
+        assert!(rust.contains("            unsafe { import_log(flat[0] as i32, flat[1] as i32, flat[2] as i32, flat[3] as i32) }"));
+        // A list result does not fit in one core value
+        assert!(rust.contains("            fn import_history(arg0: i32);"));
+        assert!(rust.contains("            unsafe { ComponentValue::load(ret_area) }"));
+        assert!(rust.contains("        #[link(wasm_import_module = \"example:plugin/logger@1.0.0\")]"));
+    }
+
+    #[test]
+    fn test_entry_points_forward_to_implementor() {
+        let rust = generate(Some("crate::Plugin"));
+        assert!(rust.contains("#[export_name = \"example:plugin/greeter@1.0.0#greet\"]"));
+        assert!(rust.contains("unsafe extern \"C\" fn __export_greeter_greet(arg0: i32, arg1: i32) -> i32 {"));
+        assert!(rust.contains("    abi::return_area(<crate::Plugin as exports::greeter::Guest>::greet(name)) as i32"));
+        assert!(rust.contains("#[export_name = \"cabi_post_example:plugin/greeter@1.0.0#greet\"]"));
+        assert!(rust.contains("unsafe extern \"C\" fn __export_greeter_score(arg0: f32) -> f32 {"));
+        assert!(rust.contains("    f32::from_bits(flat[0] as u32)"));
+
+        let package = WitPackage::parse("interface a { f: async func(); }\nworld w { export a; }").unwrap();
+        assert_eq!(
+            GuestBindings::new(&package, "w").generate().unwrap_err(),
+            GuestBindingsError::AsyncFunction("a.f".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resources_use_handles_and_tables() {
+        let package = WitPackage::parse(
+            r#"
+            package example:store;
+            interface files {
+                resource blob {
+                    constructor(init: list<u8>);
+                    read: func(n: u32) -> list<u8>;
+                    merge: static func(a: own<blob>, b: borrow<blob>) -> own<blob>;
+                }
+            }
+            interface cache {
+                resource entry {
+                    constructor(key: string);
+                    key: func() -> string;
+                }
+                record slot { entry: own<entry> }
+            }
+            interface keys {
+                resource key {
+                    constructor(name: string);
+                    name: func() -> string;
+                }
+            }
+            world store { import files; export keys; }
+            world bad { export cache; }
+            "#,
+        )
+        .unwrap();
+        let rust = GuestBindings::new(&package, "store").implementor("crate::Store").generate().unwrap();
+        assert!(rust.contains("        impl resource::Resource for Blob {"));
+        assert!(rust.contains("                unsafe { import_drop_blob(handle as i32) }"));
+        assert!(rust.contains("            #[link_name = \"[resource-drop]blob\"]"));
+        assert!(rust.contains("        impl Blob {\n            /// Calls `[constructor]blob`\n            pub fn new(init: &Vec<u8>) -> resource::Own<Blob> {"));
+        assert!(rust.contains("            pub fn read(this: resource::Borrow<'_, Blob>, n: u32) -> Vec<u8> {"));
+        assert!(rust.contains("            pub fn merge(a: resource::Own<Blob>, b: resource::Borrow<'_, Blob>) -> resource::Own<Blob> {"));
+        assert!(rust.contains("            fn import_method_blob_read(arg0: i32, arg1: i32, arg2: i32);"));
+        assert!(rust.contains("import_method_blob_read(flat[0] as i32, flat[1] as i32, ret_area as i32) };"));
+
+        assert!(rust.contains("            type Key: GuestKey;"));
+        assert!(rust.contains("        pub trait GuestKey: Sized {\n            fn new(name: String) -> Self;\n            fn name(&self) -> String;\n        }"));
+        assert!(rust.contains("static KEYS_KEY_TABLE: resource::ResourceTable<<crate::Store as exports::keys::Guest>::Key> = resource::ResourceTable::new();"));
+        assert!(rust.contains("#[link(wasm_import_module = \"[export]example:store/keys\")]"));
+        assert!(rust.contains("#[export_name = \"example:store/keys#[dtor]key\"]\nunsafe extern \"C\" fn __dtor_keys_key(rep: i32) {"));
+        assert!(rust.contains("    let rep = KEYS_KEY_TABLE.insert(<<crate::Store as exports::keys::Guest>::Key as exports::keys::GuestKey>::new(name));"));
+        assert!(rust.contains("    let this = KEYS_KEY_TABLE.get(this).expect(\"invalid `key` handle\");"));
+
+        let err = GuestBindings::new(&package, "bad").generate().unwrap_err();
+        assert_eq!(
+            err,
+            GuestBindingsError::UnsupportedType { context: "cache.slot".to_string(), ty: "resource handle own<entry>".to_string() }
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! Host-side bindings for embedding components with wasmtime
+//!
+//! A host's build script describes the world its plugins target and writes
+//! `HostBindings::generate` to `OUT_DIR`, then pulls it in with
+//! `host_bindings!`. Each imported interface becomes a `Host` trait with an
+//! `add_to_linker` function, each exported interface a `Guest` struct of
+//! typed functions, and the world a struct that instantiates a component
+//! and binds its exports. The generated code targets the wasmtime 29+
+//! component API.
+//!
+//! Interfaces are linked under their `name` verbatim, so it should be the
+//! fully qualified name, e.g. `example:plugin/logger@1.0.0`. Functions may
+//! pass primitives, strings, lists, options, and results; named types and
+//! async functions are not supported yet.
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::{ComponentFunction, ComponentInterface, ComponentWorld, WitType};
+
+/// Includes host bindings a build script wrote to `$OUT_DIR/<name>.rs`
+#[macro_export]
+macro_rules! host_bindings {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $name, ".rs"));
+    };
+}
+
+/// Host binding generation errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostBindingsError {
+    /// A function passes a type the bindings cannot represent
+    UnsupportedType { function: String, ty: WitType },
+    /// A function is `async`
+    AsyncFunction(String),
+    /// Two interfaces or functions map to the same Rust name
+    DuplicateName(String),
+}
+
+impl core::fmt::Display for HostBindingsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HostBindingsError::UnsupportedType { function, ty } => {
+                write!(f, "Function {} uses unsupported type {}", function, ty)
+            }
+            HostBindingsError::AsyncFunction(name) => write!(f, "Async function {} is not supported", name),
+            HostBindingsError::DuplicateName(name) => write!(f, "Duplicate binding name: {}", name),
+        }
+    }
+}
+
+/// Generator for a world's host bindings
+#[derive(Debug, Clone)]
+pub struct HostBindings<'a> {
+    world: &'a ComponentWorld,
+}
+
+impl<'a> HostBindings<'a> {
+    pub fn new(world: &'a ComponentWorld) -> Self {
+        Self { world }
+    }
+
+    /// Generates the bindings source
+    pub fn generate(&self) -> Result<String, HostBindingsError> {
+        let imports = modules(&self.world.imports)?;
+        let exports = modules(&self.world.exports)?;
+        let world = pascal_case(&self.world.name);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "// Generated by WasmRust from the `{}` world. Do not edit.\n", self.world.name);
+
+        out.push_str("pub mod imports {\n");
+        for (module, interface) in &imports {
+            write_import(&mut out, module, interface)?;
+        }
+        out.push_str("}\n\n");
+
+        out.push_str("pub mod exports {\n");
+        for (module, interface) in &exports {
+            write_export(&mut out, module, interface)?;
+        }
+        out.push_str("}\n\n");
+
+        let bounds: Vec<String> = imports.iter().map(|(module, _)| format!("imports::{}::Host", module)).collect();
+        let _ = writeln!(out, "/// Functions the host provides to the `{}` world", self.world.name);
+        if bounds.is_empty() {
+            let _ = writeln!(out, "pub trait {}Imports {{}}\n", world);
+            let _ = writeln!(out, "impl<U> {}Imports for U {{}}\n", world);
+        } else {
+            let _ = writeln!(out, "pub trait {}Imports: {} {{}}\n", world, bounds.join(" + "));
+            let _ = writeln!(out, "impl<U: {}> {}Imports for U {{}}\n", bounds.join(" + "), world);
+        }
+
+        let _ = writeln!(out, "/// Instance of the `{}` world", self.world.name);
+        let _ = writeln!(out, "pub struct {} {{", world);
+        for (module, _) in &exports {
+            let _ = writeln!(out, "    {}: exports::{}::Guest,", module, module);
+        }
+        out.push_str("}\n\n");
+
+        let _ = writeln!(out, "impl {} {{", world);
+        out.push_str("    /// Registers every import of the world in `linker`\n");
+        let _ = writeln!(out, "    pub fn add_to_linker<T: 'static, U: {}Imports + 'static>(", world);
+        out.push_str("        linker: &mut wasmtime::component::Linker<T>,\n");
+        out.push_str("        get: fn(&mut T) -> &mut U,\n");
+        out.push_str("    ) -> wasmtime::Result<()> {\n");
+        if imports.is_empty() {
+            out.push_str("        let _ = (linker, get);\n");
+        }
+        for (module, _) in &imports {
+            let _ = writeln!(out, "        imports::{}::add_to_linker(linker, get)?;", module);
+        }
+        out.push_str("        Ok(())\n");
+        out.push_str("    }\n\n");
+
+        out.push_str("    /// Instantiates `component` and binds its exports\n");
+        out.push_str("    pub fn instantiate<T: 'static>(\n");
+        out.push_str("        mut store: impl wasmtime::AsContextMut<Data = T>,\n");
+        out.push_str("        component: &wasmtime::component::Component,\n");
+        out.push_str("        linker: &wasmtime::component::Linker<T>,\n");
+        out.push_str("    ) -> wasmtime::Result<(Self, wasmtime::component::Instance)> {\n");
+        out.push_str("        let instance = linker.instantiate(&mut store, component)?;\n");
+        out.push_str("        Ok((Self::new(&mut store, &instance)?, instance))\n");
+        out.push_str("    }\n\n");
+
+        out.push_str("    /// Binds the exports of an existing instance\n");
+        out.push_str("    pub fn new(\n");
+        out.push_str("        mut store: impl wasmtime::AsContextMut,\n");
+        out.push_str("        instance: &wasmtime::component::Instance,\n");
+        out.push_str("    ) -> wasmtime::Result<Self> {\n");
+        if exports.is_empty() {
+            out.push_str("        let _ = (&mut store, instance);\n");
+        }
+        out.push_str("        Ok(Self {\n");
+        for (module, _) in &exports {
+            let _ = writeln!(out, "            {}: exports::{}::Guest::new(&mut store, instance)?,", module, module);
+        }
+        out.push_str("        })\n");
+        out.push_str("    }\n");
+        for (module, interface) in &exports {
+            let _ = writeln!(out, "\n    /// Gets the `{}` export", interface.name);
+            let _ = writeln!(out, "    pub fn {0}(&self) -> &exports::{0}::Guest {{", module);
+            let _ = writeln!(out, "        &self.{}", module);
+            out.push_str("    }\n");
+        }
+        out.push_str("}\n");
+        Ok(out)
+    }
+}
+
+/// Writes the `Host` trait and linker registration of an imported interface
+fn write_import(out: &mut String, module: &str, interface: &ComponentInterface) -> Result<(), HostBindingsError> {
+    let functions = functions(interface)?;
+
+    let _ = writeln!(out, "    /// Host implementation of `{}`", interface.name);
+    let _ = writeln!(out, "    pub mod {} {{", module);
+    let _ = writeln!(out, "        /// Functions the component imports from `{}`", interface.name);
+    out.push_str("        pub trait Host {\n");
+    for (ident, function) in &functions {
+        let mut params = alloc::vec!["&mut self".to_string()];
+        for (name, ty) in &function.params {
+            params.push(format!("{}: {}", rust_ident(name), rust_type(function, ty)?));
+        }
+        let _ = writeln!(
+            out,
+            "            fn {}({}) -> wasmtime::Result<{}>;",
+            ident,
+            params.join(", "),
+            result_type(function)?
+        );
+    }
+    out.push_str("        }\n\n");
+
+    out.push_str("        /// Registers the interface's functions in `linker`\n");
+    out.push_str("        pub fn add_to_linker<T: 'static, U: Host + 'static>(\n");
+    out.push_str("            linker: &mut wasmtime::component::Linker<T>,\n");
+    out.push_str("            get: fn(&mut T) -> &mut U,\n");
+    out.push_str("        ) -> wasmtime::Result<()> {\n");
+    if functions.is_empty() {
+        out.push_str("            let _ = get;\n");
+    }
+    let _ = writeln!(out, "            let mut instance = linker.instance({:?})?;", interface.name);
+    for (ident, function) in &functions {
+        let names: Vec<String> = function.params.iter().map(|(name, _)| rust_ident(name)).collect();
+        let types = function.params.iter()
+            .map(|(_, ty)| rust_type(function, ty))
+            .collect::<Result<Vec<_>, _>>()?;
+        let _ = writeln!(out, "            instance.func_wrap(");
+        let _ = writeln!(out, "                {:?},", function.name);
+        let _ = writeln!(
+            out,
+            "                move |mut caller: wasmtime::StoreContextMut<'_, T>, {}: {}| {{",
+            tuple(&names),
+            tuple(&types)
+        );
+        let mut call = format!("Host::{}(get(caller.data_mut())", ident);
+        for name in &names {
+            let _ = write!(call, ", {}", name);
+        }
+        call.push(')');
+        if function.result.is_some() {
+            let _ = writeln!(out, "                    Ok(({}?,))", call);
+        } else {
+            let _ = writeln!(out, "                    {}", call);
+        }
+        out.push_str("                },\n");
+        out.push_str("            )?;\n");
+    }
+    if functions.is_empty() {
+        out.push_str("            let _ = &mut instance;\n");
+    }
+    out.push_str("            Ok(())\n");
+    out.push_str("        }\n");
+    out.push_str("    }\n\n");
+    Ok(())
+}
+
+/// Writes the `Guest` struct calling into an exported interface
+fn write_export(out: &mut String, module: &str, interface: &ComponentInterface) -> Result<(), HostBindingsError> {
+    let functions = functions(interface)?;
+
+    let _ = writeln!(out, "    /// Typed calls into the component's `{}` export", interface.name);
+    let _ = writeln!(out, "    pub mod {} {{", module);
+    out.push_str("        pub struct Guest {\n");
+    for (ident, function) in &functions {
+        let params = function.params.iter()
+            .map(|(_, ty)| rust_type(function, ty))
+            .collect::<Result<Vec<_>, _>>()?;
+        let results = match &function.result {
+            Some(ty) => alloc::vec![rust_type(function, ty)?],
+            None => Vec::new(),
+        };
+        let _ = writeln!(
+            out,
+            "            {}: wasmtime::component::TypedFunc<{}, {}>,",
+            ident,
+            tuple(&params),
+            tuple(&results)
+        );
+    }
+    out.push_str("        }\n\n");
+
+    out.push_str("        impl Guest {\n");
+    out.push_str("            /// Looks up the interface's functions in an instance\n");
+    out.push_str("            pub fn new(\n");
+    out.push_str("                mut store: impl wasmtime::AsContextMut,\n");
+    out.push_str("                instance: &wasmtime::component::Instance,\n");
+    out.push_str("            ) -> wasmtime::Result<Self> {\n");
+    let _ = writeln!(out, "                let interface = instance.get_export_index(&mut store, None, {:?})", interface.name);
+    let _ = writeln!(
+        out,
+        "                    .ok_or_else(|| wasmtime::Error::msg({:?}))?;",
+        format!("missing export `{}`", interface.name)
+    );
+    for (ident, function) in &functions {
+        let _ = writeln!(
+            out,
+            "                let {} = instance.get_export_index(&mut store, Some(&interface), {:?})",
+            ident, function.name
+        );
+        let _ = writeln!(
+            out,
+            "                    .ok_or_else(|| wasmtime::Error::msg({:?}))?;",
+            format!("missing export `{}#{}`", interface.name, function.name)
+        );
+    }
+    out.push_str("                Ok(Self {\n");
+    for (ident, _) in &functions {
+        let _ = writeln!(out, "                    {0}: instance.get_typed_func(&mut store, &{0})?,", ident);
+    }
+    out.push_str("                })\n");
+    out.push_str("            }\n");
+
+    for (ident, function) in &functions {
+        let mut params = alloc::vec!["&self".to_string(), "mut store: impl wasmtime::AsContextMut".to_string()];
+        let mut names = Vec::new();
+        for (name, ty) in &function.params {
+            names.push(rust_ident(name));
+            params.push(format!("{}: {}", rust_ident(name), rust_type(function, ty)?));
+        }
+        let _ = writeln!(out, "\n            /// Calls `{}`", function.name);
+        let _ = writeln!(
+            out,
+            "            pub fn call_{}({}) -> wasmtime::Result<{}> {{",
+            ident.trim_start_matches("r#"),
+            params.join(", "),
+            result_type(function)?
+        );
+        let binding = if function.result.is_some() { "(result,)" } else { "()" };
+        let _ = writeln!(out, "                let {} = self.{}.call(&mut store, {})?;", binding, ident, tuple(&names));
+        let _ = writeln!(out, "                self.{}.post_return(&mut store)?;", ident);
+        let _ = writeln!(out, "                Ok({})", if function.result.is_some() { "result" } else { "()" });
+        out.push_str("            }\n");
+    }
+    out.push_str("        }\n");
+    out.push_str("    }\n\n");
+    Ok(())
+}
+
+/// Rust module names of a world's interfaces, checked for collisions
+fn modules(interfaces: &[ComponentInterface]) -> Result<Vec<(String, &ComponentInterface)>, HostBindingsError> {
+    let mut seen = BTreeSet::new();
+    let mut modules = Vec::new();
+    for interface in interfaces {
+        let module = module_name(&interface.name);
+        if !seen.insert(module.clone()) {
+            return Err(HostBindingsError::DuplicateName(interface.name.clone()));
+        }
+        modules.push((module, interface));
+    }
+    Ok(modules)
+}
+
+/// Rust module name of an interface: `example:plugin/logger@1.0.0` becomes `logger`
+pub(super) fn module_name(interface: &str) -> String {
+    let unversioned = interface.split('@').next().unwrap_or_default();
+    rust_ident(unversioned.rsplit(['/', ':']).next().unwrap_or_default())
+}
+
+/// Rust method names of an interface's functions, rejecting async ones
+fn functions(interface: &ComponentInterface) -> Result<Vec<(String, &ComponentFunction)>, HostBindingsError> {
+    let mut seen = BTreeSet::new();
+    let mut functions = Vec::new();
+    for function in &interface.functions {
+        if function.is_async {
+            return Err(HostBindingsError::AsyncFunction(function.name.clone()));
+        }
+        let ident = rust_ident(&function.name);
+        if !seen.insert(ident.clone()) {
+            return Err(HostBindingsError::DuplicateName(format!("{}#{}", interface.name, function.name)));
+        }
+        functions.push((ident, function));
+    }
+    Ok(functions)
+}
+
+/// Host-side Rust type of a WIT type
+fn rust_type(function: &ComponentFunction, ty: &WitType) -> Result<String, HostBindingsError> {
+    let optional = |ty: &Option<alloc::boxed::Box<WitType>>| match ty {
+        Some(ty) => rust_type(function, ty),
+        None => Ok("()".to_string()),
+    };
+    Ok(match ty {
+        WitType::Bool => "bool".to_string(),
+        WitType::U8 => "u8".to_string(),
+        WitType::U16 => "u16".to_string(),
+        WitType::U32 => "u32".to_string(),
+        WitType::U64 => "u64".to_string(),
+        WitType::S8 => "i8".to_string(),
+        WitType::S16 => "i16".to_string(),
+        WitType::S32 => "i32".to_string(),
+        WitType::S64 => "i64".to_string(),
+        WitType::F32 => "f32".to_string(),
+        WitType::F64 => "f64".to_string(),
+        WitType::Char => "char".to_string(),
+        WitType::String => "String".to_string(),
+        WitType::List(inner) => format!("Vec<{}>", rust_type(function, inner)?),
+        WitType::Option(inner) => format!("Option<{}>", rust_type(function, inner)?),
+        WitType::Result { ok, err } => format!("Result<{}, {}>", optional(ok)?, optional(err)?),
+        WitType::Future(_) | WitType::Stream(_) | WitType::Own(_) | WitType::Borrow(_) | WitType::Named(_) => {
+            return Err(HostBindingsError::UnsupportedType {
+                function: function.name.clone(),
+                ty: ty.clone(),
+            })
+        }
+    })
+}
+
+fn result_type(function: &ComponentFunction) -> Result<String, HostBindingsError> {
+    match &function.result {
+        Some(ty) => rust_type(function, ty),
+        None => Ok("()".to_string()),
+    }
+}
+
+/// Formats a Rust tuple, with the trailing comma a single element needs
+fn tuple(items: &[String]) -> String {
+    match items {
+        [item] => format!("({},)", item),
+        items => format!("({})", items.join(", ")),
+    }
+}
+
+/// Converts a kebab-case WIT name to a snake_case Rust identifier
+pub(super) fn rust_ident(name: &str) -> String {
+    let ident = name.trim_start_matches('%').replace('-', "_");
+    if matches!(
+        ident.as_str(),
+        "as" | "break" | "const" | "continue" | "crate" | "else" | "enum" | "extern" | "false" | "fn" | "for"
+            | "if" | "impl" | "in" | "let" | "loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref"
+            | "return" | "static" | "struct" | "trait" | "true" | "type" | "unsafe" | "use" | "where"
+            | "while" | "async" | "await" | "dyn"
+    ) {
+        format!("r#{}", ident)
+    } else {
+        ident
+    }
+}
+
+/// Converts a kebab-case WIT name to a PascalCase type name
+pub(super) fn pascal_case(name: &str) -> String {
+    name.split(['-', '_'])
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars.next().map_or_else(String::new, |first| first.to_ascii_uppercase().to_string() + chars.as_str())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::ComponentFunction;
+
+    fn plugin_world() -> ComponentWorld {
+        let mut logger = ComponentInterface::new("example:plugin/logger@1.0.0".to_string());
+        logger.add_function(
+            ComponentFunction::new("log").param("level", WitType::U32).param("message", WitType::String),
+        ).unwrap();
+        let mut greeter = ComponentInterface::new("example:plugin/greeter".to_string());
+        greeter.add_function(
+            ComponentFunction::new("greet")
+                .param("name", WitType::String)
+                .result(WitType::List(alloc::boxed::Box::new(WitType::U8))),
+        ).unwrap();
+        ComponentWorld::new("text-plugin").import(logger).export(greeter)
+    }
+
+    #[test]
+    fn test_import_traits_register_with_linker() {
+        let rust = HostBindings::new(&plugin_world()).generate().unwrap();
+        assert!(rust.contains("    pub mod logger {"));
+        assert!(rust.contains("            fn log(&mut self, level: u32, message: String) -> wasmtime::Result<()>;"));
+        assert!(rust.contains("            let mut instance = linker.instance(\"example:plugin/logger@1.0.0\")?;"));
+        assert!(rust.contains(
+            "                move |mut caller: wasmtime::StoreContextMut<'_, T>, (level, message): (u32, String)| {"
+        ));
+        assert!(rust.contains("                    Host::log(get(caller.data_mut()), level, message)\n"));
+        assert!(rust.contains("pub trait TextPluginImports: imports::logger::Host {}"));
+        assert!(rust.contains("        imports::logger::add_to_linker(linker, get)?;"));
+    }
+
+    #[test]
+    fn test_export_guests_call_typed_funcs() {
+        let rust = HostBindings::new(&plugin_world()).generate().unwrap();
+        assert!(rust.contains("            greet: wasmtime::component::TypedFunc<(String,), (Vec<u8>,)>,"));
+        assert!(rust.contains(
+            "            pub fn call_greet(&self, mut store: impl wasmtime::AsContextMut, name: String) -> wasmtime::Result<Vec<u8>> {"
+        ));
+        assert!(rust.contains("                let (result,) = self.greet.call(&mut store, (name,))?;"));
+        assert!(rust.contains("pub struct TextPlugin {\n    greeter: exports::greeter::Guest,\n}"));
+        assert!(rust.contains("    pub fn greeter(&self) -> &exports::greeter::Guest {"));
+    }
+
+    #[test]
+    fn test_unsupported_functions_are_rejected() {
+        let mut events = ComponentInterface::new("example:plugin/events".to_string());
+        events.add_function(ComponentFunction::new("next").result(WitType::Named("event".to_string()))).unwrap();
+        let world = ComponentWorld::new("plugin").import(events);
+        assert_eq!(
+            HostBindings::new(&world).generate().unwrap_err(),
+            HostBindingsError::UnsupportedType { function: "next".to_string(), ty: WitType::Named("event".to_string()) }
+        );
+
+        let mut tasks = ComponentInterface::new("example:plugin/tasks".to_string());
+        tasks.add_function(ComponentFunction::new("run").asynchronous()).unwrap();
+        let world = ComponentWorld::new("plugin").export(tasks);
+        assert_eq!(HostBindings::new(&world).generate().unwrap_err(), HostBindingsError::AsyncFunction("run".to_string()));
+
+        let world = ComponentWorld::new("plugin")
+            .import(ComponentInterface::new("a:b/log".to_string()))
+            .import(ComponentInterface::new("c:d/log".to_string()));
+        assert_eq!(HostBindings::new(&world).generate().unwrap_err(), HostBindingsError::DuplicateName("c:d/log".to_string()));
+    }
+}
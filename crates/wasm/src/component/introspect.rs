@@ -0,0 +1,1271 @@
+//! Component binary introspection and adapter validation
+//!
+//! `inspect` parses a component binary and lists its imports, exports,
+//! embedded core modules, nested components, and the canonical ABI adapters
+//! (`canon lift`/`canon lower`) that connect them. Hosts use it before
+//! instantiation and the registry runs it on submissions, so malformed
+//! binaries are errors while adapter problems are collected as issues.
+//!
+//! Worlds are the names of exported component types, which is how WIT
+//! packages are encoded, plus any `component-type` custom sections left by
+//! bindings generators.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use super::canon_async::MAX_FLAT_PARAMS;
+
+/// Most core results a lifted or lowered function returns directly
+pub const MAX_FLAT_RESULTS: usize = 1;
+
+/// Component binary version and layer
+const COMPONENT_VERSION: [u8; 4] = [0x0d, 0x00, 0x01, 0x00];
+
+/// Nesting limit for components and type declarations
+const MAX_DEPTH: usize = 16;
+
+/// Component binary parsing errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InspectError {
+    /// Missing `\0asm` magic
+    BadMagic,
+    /// Binary is a core module, not a component
+    NotAComponent,
+    /// Unknown component encoding version
+    UnsupportedVersion(u32),
+    /// Binary ended early
+    UnexpectedEof { offset: usize },
+    /// Section contents do not match the encoding
+    MalformedSection { id: u8, offset: usize, message: String },
+}
+
+impl core::fmt::Display for InspectError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InspectError::BadMagic => write!(f, "Not a WebAssembly binary"),
+            InspectError::NotAComponent => write!(f, "Binary is a core module, not a component"),
+            InspectError::UnsupportedVersion(version) => write!(f, "Unsupported component version {:#x}", version),
+            InspectError::UnexpectedEof { offset } => write!(f, "Unexpected end of binary at offset {:#x}", offset),
+            InspectError::MalformedSection { id, offset, message } => {
+                write!(f, "Malformed section {} at offset {:#x}: {}", id, offset, message)
+            }
+        }
+    }
+}
+
+/// Kind of a component import or export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternKind {
+    Module,
+    Func,
+    Value,
+    Type,
+    Component,
+    Instance,
+}
+
+impl ExternKind {
+    fn name(self) -> &'static str {
+        match self {
+            ExternKind::Module => "core module",
+            ExternKind::Func => "func",
+            ExternKind::Value => "value",
+            ExternKind::Type => "type",
+            ExternKind::Component => "component",
+            ExternKind::Instance => "instance",
+        }
+    }
+}
+
+/// Component import or export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentItem {
+    pub name: String,
+    pub kind: ExternKind,
+}
+
+/// Kind of a core module import or export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreKind {
+    Func,
+    Table,
+    Memory,
+    Global,
+    Tag,
+}
+
+/// Core module embedded in a component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreModuleInfo {
+    /// Offset of the module within the component binary
+    pub offset: usize,
+    pub size: usize,
+    /// `(module, name, kind)` for each import
+    pub imports: Vec<(String, String, CoreKind)>,
+    pub exports: Vec<(String, CoreKind)>,
+}
+
+/// String encoding named by a canonical option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringEncoding {
+    Utf8,
+    Utf16,
+    CompactUtf16,
+}
+
+/// Canonical ABI options of an adapter
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CanonOptions {
+    pub string_encoding: Option<StringEncoding>,
+    /// Core memory index
+    pub memory: Option<u32>,
+    /// Core function index of `realloc`
+    pub realloc: Option<u32>,
+    /// Core function index of the post-return cleanup
+    pub post_return: Option<u32>,
+    pub is_async: bool,
+    /// Core function index of the async callback
+    pub callback: Option<u32>,
+}
+
+/// Canonical definition from a `canon` section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanonAdapter {
+    /// Lifts a core function into a component function of type `ty`
+    Lift { core_func: u32, ty: u32, options: CanonOptions },
+    /// Lowers a component function into a core function
+    Lower { func: u32, options: CanonOptions },
+    ResourceNew(u32),
+    ResourceDrop(u32),
+    ResourceRep(u32),
+}
+
+/// Problem found while validating canonical adapters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterIssue {
+    /// Index of the adapter in `ComponentInfo::adapters`
+    pub adapter: usize,
+    pub message: String,
+}
+
+impl core::fmt::Display for AdapterIssue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "canonical adapter {}: {}", self.adapter, self.message)
+    }
+}
+
+/// Summary of a component binary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComponentInfo {
+    pub worlds: Vec<String>,
+    pub imports: Vec<ComponentItem>,
+    pub exports: Vec<ComponentItem>,
+    pub modules: Vec<CoreModuleInfo>,
+    pub components: Vec<ComponentInfo>,
+    pub adapters: Vec<CanonAdapter>,
+    pub custom_sections: Vec<String>,
+    pub issues: Vec<AdapterIssue>,
+}
+
+impl ComponentInfo {
+    /// Checks that every adapter, including nested components', is valid
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty() && self.components.iter().all(ComponentInfo::is_valid)
+    }
+}
+
+impl core::fmt::Display for ComponentInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for world in &self.worlds {
+            writeln!(f, "world {}", world)?;
+        }
+        for import in &self.imports {
+            writeln!(f, "import {}: {}", import.name, import.kind.name())?;
+        }
+        for export in &self.exports {
+            writeln!(f, "export {}: {}", export.name, export.kind.name())?;
+        }
+        for (i, module) in self.modules.iter().enumerate() {
+            writeln!(
+                f,
+                "core module {} ({} bytes, {} imports, {} exports)",
+                i,
+                module.size,
+                module.imports.len(),
+                module.exports.len()
+            )?;
+        }
+        for (i, component) in self.components.iter().enumerate() {
+            writeln!(f, "component {} ({} imports, {} exports)", i, component.imports.len(), component.exports.len())?;
+        }
+        writeln!(f, "{} canonical adapters", self.adapters.len())?;
+        for issue in &self.issues {
+            writeln!(f, "error: {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a component binary and validates its canonical adapters
+pub fn inspect(bytes: &[u8]) -> Result<ComponentInfo, InspectError> {
+    inspect_nested(bytes, 0, 0)
+}
+
+/// Component value type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValType {
+    Primitive(u8),
+    Index(u32),
+}
+
+const STRING: u8 = 0x73;
+
+/// Component type definition, as far as validation needs it
+#[derive(Debug, Clone)]
+enum TypeDef {
+    Func { params: Vec<ValType>, results: Vec<ValType> },
+    /// Aggregate whose flattening is the sum of its members
+    Record(Vec<ValType>),
+    /// Discriminant plus the widest case
+    Variant(Vec<Option<ValType>>),
+    List,
+    /// Flattens to `count` i32s
+    Scalar(usize),
+    Component,
+    Other,
+}
+
+/// Index spaces needed to check adapter references
+#[derive(Default)]
+struct IndexSpaces {
+    core_funcs: u32,
+    core_memories: u32,
+    core_modules: u32,
+    core_instances: u32,
+    /// Type index of each component function, when known
+    funcs: Vec<Option<u32>>,
+    types: Vec<TypeDef>,
+    instances: u32,
+    components: u32,
+    values: u32,
+}
+
+impl IndexSpaces {
+    /// Adds an item of a component sort
+    fn push(&mut self, kind: ExternKind, func_type: Option<u32>, type_def: TypeDef) {
+        match kind {
+            ExternKind::Module => self.core_modules += 1,
+            ExternKind::Func => self.funcs.push(func_type),
+            ExternKind::Value => self.values += 1,
+            ExternKind::Type => self.types.push(type_def),
+            ExternKind::Component => self.components += 1,
+            ExternKind::Instance => self.instances += 1,
+        }
+    }
+
+    /// Adds an item of a core sort
+    fn push_core(&mut self, sort: u8) {
+        match sort {
+            0x00 => self.core_funcs += 1,
+            0x02 => self.core_memories += 1,
+            0x11 => self.core_modules += 1,
+            0x12 => self.core_instances += 1,
+            _ => {}
+        }
+    }
+}
+
+fn inspect_nested(bytes: &[u8], base: usize, depth: usize) -> Result<ComponentInfo, InspectError> {
+    let mut reader = Reader { bytes, pos: 0, base, section: 0 };
+    if reader.take(4)? != b"\0asm" {
+        return Err(InspectError::BadMagic);
+    }
+    let version = reader.take(4)?;
+    if version == [1, 0, 0, 0] {
+        return Err(InspectError::NotAComponent);
+    }
+    if version != COMPONENT_VERSION {
+        return Err(InspectError::UnsupportedVersion(u32::from_le_bytes([version[0], version[1], version[2], version[3]])));
+    }
+
+    let mut info = ComponentInfo::default();
+    let mut spaces = IndexSpaces::default();
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut section = reader.sub(size, id)?;
+        parse_section(id, &mut section, &mut info, &mut spaces, depth).map_err(|err| match err {
+            InspectError::UnexpectedEof { offset } => {
+                InspectError::MalformedSection { id, offset, message: "truncated section".to_string() }
+            }
+            err => err,
+        })?;
+        if !section.is_empty() {
+            return Err(section.error("trailing bytes"));
+        }
+    }
+    Ok(info)
+}
+
+fn parse_section(
+    id: u8,
+    reader: &mut Reader<'_>,
+    info: &mut ComponentInfo,
+    spaces: &mut IndexSpaces,
+    depth: usize,
+) -> Result<(), InspectError> {
+    match id {
+        0 => {
+            let name = reader.name()?;
+            if let Some(world) = name.strip_prefix("component-type:") {
+                info.worlds.push(world.to_string());
+            }
+            info.custom_sections.push(name);
+            reader.pos = reader.bytes.len();
+        }
+        1 => {
+            let offset = reader.offset();
+            let module = reader.take(reader.bytes.len())?;
+            info.modules.push(parse_core_module(module, offset)?);
+            spaces.core_modules += 1;
+        }
+        2 => {
+            for _ in 0..reader.u32()? {
+                match reader.u8()? {
+                    0x00 => {
+                        reader.u32()?;
+                        for _ in 0..reader.u32()? {
+                            reader.name()?;
+                            if reader.u8()? != 0x12 {
+                                return Err(reader.error("core instantiation arguments must be instances"));
+                            }
+                            reader.u32()?;
+                        }
+                    }
+                    0x01 => {
+                        for _ in 0..reader.u32()? {
+                            reader.name()?;
+                            reader.u8()?;
+                            reader.u32()?;
+                        }
+                    }
+                    _ => return Err(reader.error("unknown core instance form")),
+                }
+                spaces.core_instances += 1;
+            }
+        }
+        3 => {
+            for _ in 0..reader.u32()? {
+                skip_core_type(reader, depth)?;
+            }
+        }
+        4 => {
+            if depth >= MAX_DEPTH {
+                return Err(reader.error("components nested too deeply"));
+            }
+            let offset = reader.offset();
+            let nested = reader.take(reader.bytes.len())?;
+            info.components.push(inspect_nested(nested, offset, depth + 1)?);
+            spaces.components += 1;
+        }
+        5 => {
+            for _ in 0..reader.u32()? {
+                match reader.u8()? {
+                    0x00 => {
+                        reader.u32()?;
+                        for _ in 0..reader.u32()? {
+                            reader.name()?;
+                            sort(reader)?;
+                            reader.u32()?;
+                        }
+                    }
+                    0x01 => {
+                        for _ in 0..reader.u32()? {
+                            extern_name(reader)?;
+                            sort(reader)?;
+                            reader.u32()?;
+                        }
+                    }
+                    _ => return Err(reader.error("unknown instance form")),
+                }
+                spaces.instances += 1;
+            }
+        }
+        6 => {
+            for _ in 0..reader.u32()? {
+                let sort = sort(reader)?;
+                match reader.u8()? {
+                    0x00 | 0x01 => {
+                        reader.u32()?;
+                        reader.name()?;
+                    }
+                    0x02 => {
+                        reader.u32()?;
+                        reader.u32()?;
+                    }
+                    _ => return Err(reader.error("unknown alias target")),
+                }
+                match sort {
+                    Sort::Core(core) => spaces.push_core(core),
+                    Sort::Component(kind) => spaces.push(kind, None, TypeDef::Other),
+                }
+            }
+        }
+        7 => {
+            for _ in 0..reader.u32()? {
+                let def = type_def(reader, depth)?;
+                spaces.types.push(def);
+            }
+        }
+        8 => parse_canon(reader, info, spaces)?,
+        9 => {
+            reader.u32()?;
+            for _ in 0..reader.u32()? {
+                reader.u32()?;
+            }
+            spaces.values += reader.u32()?;
+        }
+        10 => {
+            for _ in 0..reader.u32()? {
+                let name = extern_name(reader)?;
+                let (kind, index) = extern_desc(reader)?;
+                spaces.push(kind, (kind == ExternKind::Func).then_some(index), TypeDef::Other);
+                info.imports.push(ComponentItem { name, kind });
+            }
+        }
+        11 => {
+            for _ in 0..reader.u32()? {
+                let name = extern_name(reader)?;
+                let sort = sort(reader)?;
+                let index = reader.u32()?;
+                let ascribed = match reader.u8()? {
+                    0x00 => None,
+                    0x01 => Some(extern_desc(reader)?),
+                    _ => return Err(reader.error("malformed export type ascription")),
+                };
+                let Sort::Component(kind) = sort else {
+                    return Err(reader.error("components cannot export core items"));
+                };
+                if kind == ExternKind::Type
+                    && matches!(spaces.types.get(index as usize), Some(TypeDef::Component))
+                {
+                    info.worlds.push(name.clone());
+                }
+                let func_type = match (kind, ascribed) {
+                    (ExternKind::Func, Some((_, ty))) => Some(ty),
+                    (ExternKind::Func, None) => spaces.funcs.get(index as usize).copied().flatten(),
+                    _ => None,
+                };
+                let def = match kind {
+                    ExternKind::Type => spaces.types.get(index as usize).cloned().unwrap_or(TypeDef::Other),
+                    _ => TypeDef::Other,
+                };
+                spaces.push(kind, func_type, def);
+                info.exports.push(ComponentItem { name, kind });
+            }
+        }
+        12 => {
+            // Values are rare and carry no adapter information
+            spaces.values += reader.u32()?;
+            reader.pos = reader.bytes.len();
+        }
+        _ => return Err(reader.error("unknown section id")),
+    }
+    Ok(())
+}
+
+fn parse_canon(reader: &mut Reader<'_>, info: &mut ComponentInfo, spaces: &mut IndexSpaces) -> Result<(), InspectError> {
+    for _ in 0..reader.u32()? {
+        let mut problems = Vec::new();
+        let adapter = match reader.u8()? {
+            0x00 => {
+                let sub = reader.u8()?;
+                let core_func = reader.u32()?;
+                let options = canon_options(reader, &mut problems)?;
+                let ty = reader.u32()?;
+                if sub != 0x00 {
+                    return Err(reader.error("malformed canon lift"));
+                }
+                CanonAdapter::Lift { core_func, ty, options }
+            }
+            0x01 => {
+                let sub = reader.u8()?;
+                let func = reader.u32()?;
+                let options = canon_options(reader, &mut problems)?;
+                if sub != 0x00 {
+                    return Err(reader.error("malformed canon lower"));
+                }
+                CanonAdapter::Lower { func, options }
+            }
+            0x02 => CanonAdapter::ResourceNew(reader.u32()?),
+            0x03 => CanonAdapter::ResourceDrop(reader.u32()?),
+            0x04 => CanonAdapter::ResourceRep(reader.u32()?),
+            opcode => {
+                // Later definitions cannot be located without knowing this one
+                info.issues.push(AdapterIssue {
+                    adapter: info.adapters.len(),
+                    message: format!("unsupported canonical definition {:#04x}; rest of section skipped", opcode),
+                });
+                reader.pos = reader.bytes.len();
+                return Ok(());
+            }
+        };
+
+        problems.extend(validate_adapter(&adapter, spaces));
+        for message in problems {
+            info.issues.push(AdapterIssue { adapter: info.adapters.len(), message });
+        }
+        match adapter {
+            CanonAdapter::Lift { ty, .. } => spaces.funcs.push(Some(ty)),
+            _ => spaces.core_funcs += 1,
+        }
+        info.adapters.push(adapter);
+    }
+    Ok(())
+}
+
+/// Reads canonical options, collecting repeated or conflicting ones
+fn canon_options(reader: &mut Reader<'_>, problems: &mut Vec<String>) -> Result<CanonOptions, InspectError> {
+    let mut options = CanonOptions::default();
+    let mut duplicate = false;
+    let set = |slot: &mut Option<u32>, value: u32| slot.replace(value).is_some();
+    let mut encodings = 0;
+    for _ in 0..reader.u32()? {
+        let encoding = match reader.u8()? {
+            0x00 => Some(StringEncoding::Utf8),
+            0x01 => Some(StringEncoding::Utf16),
+            0x02 => Some(StringEncoding::CompactUtf16),
+            0x03 => {
+                duplicate |= set(&mut options.memory, reader.u32()?);
+                None
+            }
+            0x04 => {
+                duplicate |= set(&mut options.realloc, reader.u32()?);
+                None
+            }
+            0x05 => {
+                duplicate |= set(&mut options.post_return, reader.u32()?);
+                None
+            }
+            0x06 => {
+                duplicate |= options.is_async;
+                options.is_async = true;
+                None
+            }
+            0x07 => {
+                duplicate |= set(&mut options.callback, reader.u32()?);
+                None
+            }
+            _ => return Err(reader.error("unknown canonical option")),
+        };
+        if encoding.is_some() {
+            encodings += 1;
+            options.string_encoding = encoding;
+        }
+    }
+    if encodings > 1 {
+        problems.push("more than one string encoding".to_string());
+    }
+    if duplicate {
+        problems.push("canonical option given more than once".to_string());
+    }
+    Ok(options)
+}
+
+/// Checks an adapter's references and canonical ABI requirements
+fn validate_adapter(adapter: &CanonAdapter, spaces: &IndexSpaces) -> Vec<String> {
+    let mut issues = Vec::new();
+    let (options, func_type, lifting) = match adapter {
+        CanonAdapter::Lift { core_func, ty, options } => {
+            if *core_func >= spaces.core_funcs {
+                issues.push(format!("core function {} is out of bounds", core_func));
+            }
+            match spaces.types.get(*ty as usize) {
+                Some(TypeDef::Func { .. }) => {}
+                Some(_) => issues.push(format!("type {} is not a function type", ty)),
+                None => issues.push(format!("type {} is out of bounds", ty)),
+            }
+            (options, Some(*ty), true)
+        }
+        CanonAdapter::Lower { func, options } => match spaces.funcs.get(*func as usize) {
+            Some(ty) => (options, *ty, false),
+            None => {
+                issues.push(format!("function {} is out of bounds", func));
+                (options, None, false)
+            }
+        },
+        CanonAdapter::ResourceNew(ty) | CanonAdapter::ResourceDrop(ty) | CanonAdapter::ResourceRep(ty) => {
+            if *ty as usize >= spaces.types.len() {
+                issues.push(format!("resource type {} is out of bounds", ty));
+            }
+            return issues;
+        }
+    };
+
+    if options.memory.is_some_and(|memory| memory >= spaces.core_memories) {
+        issues.push("memory is out of bounds".to_string());
+    }
+    for (name, func) in [("realloc", options.realloc), ("post-return", options.post_return), ("callback", options.callback)] {
+        if func.is_some_and(|func| func >= spaces.core_funcs) {
+            issues.push(format!("{} function is out of bounds", name));
+        }
+    }
+    if options.realloc.is_some() && options.memory.is_none() {
+        issues.push("realloc requires a memory".to_string());
+    }
+    if !lifting && options.post_return.is_some() {
+        issues.push("post-return is only allowed on lift".to_string());
+    }
+    if options.callback.is_some() && !(lifting && options.is_async) {
+        issues.push("callback requires an async lift".to_string());
+    }
+
+    let Some(TypeDef::Func { params, results }) = func_type.and_then(|ty| spaces.types.get(ty as usize)) else {
+        return issues;
+    };
+    let flat_params: usize = params.iter().map(|ty| flat_count(*ty, spaces, 0)).sum();
+    let flat_results: usize = results.iter().map(|ty| flat_count(*ty, spaces, 0)).sum();
+    let params_in_memory = params.iter().any(|ty| uses_memory(*ty, spaces, 0));
+    let results_in_memory = results.iter().any(|ty| uses_memory(*ty, spaces, 0));
+    let spills = flat_params > MAX_FLAT_PARAMS || flat_results > MAX_FLAT_RESULTS;
+
+    if (params_in_memory || results_in_memory || spills) && options.memory.is_none() {
+        issues.push("function passes values through memory but no memory is given".to_string());
+    }
+    // The side receiving allocated values needs realloc to place them
+    let allocates = if lifting {
+        params_in_memory || flat_params > MAX_FLAT_PARAMS
+    } else {
+        results_in_memory
+    };
+    if allocates && options.realloc.is_none() {
+        issues.push("function receives strings or lists but no realloc is given".to_string());
+    }
+    issues
+}
+
+/// Number of core values a component value flattens to
+fn flat_count(ty: ValType, spaces: &IndexSpaces, depth: usize) -> usize {
+    let index = match ty {
+        ValType::Primitive(STRING) => return 2,
+        ValType::Primitive(_) => return 1,
+        ValType::Index(index) => index,
+    };
+    if depth > MAX_DEPTH {
+        return 1;
+    }
+    match spaces.types.get(index as usize) {
+        Some(TypeDef::Record(fields)) => fields.iter().map(|field| flat_count(*field, spaces, depth + 1)).sum(),
+        Some(TypeDef::Variant(cases)) => {
+            1 + cases.iter().flatten().map(|case| flat_count(*case, spaces, depth + 1)).max().unwrap_or(0)
+        }
+        Some(TypeDef::List) => 2,
+        Some(TypeDef::Scalar(count)) => *count,
+        _ => 1,
+    }
+}
+
+/// Checks whether a value holds strings or lists
+fn uses_memory(ty: ValType, spaces: &IndexSpaces, depth: usize) -> bool {
+    let index = match ty {
+        ValType::Primitive(code) => return code == STRING,
+        ValType::Index(index) => index,
+    };
+    if depth > MAX_DEPTH {
+        return false;
+    }
+    match spaces.types.get(index as usize) {
+        Some(TypeDef::Record(fields)) => fields.iter().any(|field| uses_memory(*field, spaces, depth + 1)),
+        Some(TypeDef::Variant(cases)) => cases.iter().flatten().any(|case| uses_memory(*case, spaces, depth + 1)),
+        Some(TypeDef::List) => true,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Sort {
+    Core(u8),
+    Component(ExternKind),
+}
+
+fn sort(reader: &mut Reader<'_>) -> Result<Sort, InspectError> {
+    Ok(match reader.u8()? {
+        0x00 => Sort::Core(reader.u8()?),
+        0x01 => Sort::Component(ExternKind::Func),
+        0x02 => Sort::Component(ExternKind::Value),
+        0x03 => Sort::Component(ExternKind::Type),
+        0x04 => Sort::Component(ExternKind::Component),
+        0x05 => Sort::Component(ExternKind::Instance),
+        _ => return Err(reader.error("unknown sort")),
+    })
+}
+
+/// Reads an import or export name
+///
+/// The `0x01` form carries a trailing version suffix, which is dropped.
+fn extern_name(reader: &mut Reader<'_>) -> Result<String, InspectError> {
+    match reader.u8()? {
+        0x00 => reader.name(),
+        0x01 => {
+            let name = reader.name()?;
+            reader.name()?;
+            Ok(name)
+        }
+        _ => Err(reader.error("unknown name form")),
+    }
+}
+
+/// Reads an extern descriptor, returning its kind and type index
+fn extern_desc(reader: &mut Reader<'_>) -> Result<(ExternKind, u32), InspectError> {
+    Ok(match reader.u8()? {
+        0x00 => {
+            if reader.u8()? != 0x11 {
+                return Err(reader.error("malformed core module descriptor"));
+            }
+            (ExternKind::Module, reader.u32()?)
+        }
+        0x01 => (ExternKind::Func, reader.u32()?),
+        0x02 => {
+            match reader.u8()? {
+                0x00 => {
+                    reader.u32()?;
+                }
+                0x01 => {
+                    val_type(reader)?;
+                }
+                _ => return Err(reader.error("malformed value bound")),
+            }
+            (ExternKind::Value, 0)
+        }
+        0x03 => match reader.u8()? {
+            0x00 => (ExternKind::Type, reader.u32()?),
+            0x01 => (ExternKind::Type, 0),
+            _ => return Err(reader.error("malformed type bound")),
+        },
+        0x04 => (ExternKind::Component, reader.u32()?),
+        0x05 => (ExternKind::Instance, reader.u32()?),
+        _ => return Err(reader.error("unknown extern descriptor")),
+    })
+}
+
+fn val_type(reader: &mut Reader<'_>) -> Result<ValType, InspectError> {
+    let value = reader.s33()?;
+    Ok(if value < 0 { ValType::Primitive((value + 0x80) as u8) } else { ValType::Index(value as u32) })
+}
+
+fn optional_val_type(reader: &mut Reader<'_>) -> Result<Option<ValType>, InspectError> {
+    match reader.u8()? {
+        0x00 => Ok(None),
+        0x01 => val_type(reader).map(Some),
+        _ => Err(reader.error("malformed optional type")),
+    }
+}
+
+/// Reads a component type definition
+fn type_def(reader: &mut Reader<'_>, depth: usize) -> Result<TypeDef, InspectError> {
+    if depth >= MAX_DEPTH {
+        return Err(reader.error("types nested too deeply"));
+    }
+    Ok(match reader.u8()? {
+        0x40 => {
+            let mut params = Vec::new();
+            for _ in 0..reader.u32()? {
+                reader.name()?;
+                params.push(val_type(reader)?);
+            }
+            let results = match reader.u8()? {
+                0x00 => alloc::vec![val_type(reader)?],
+                0x01 if reader.u8()? == 0x00 => Vec::new(),
+                _ => return Err(reader.error("malformed function results")),
+            };
+            TypeDef::Func { params, results }
+        }
+        0x41 => {
+            for _ in 0..reader.u32()? {
+                match reader.u8()? {
+                    0x03 => {
+                        extern_name(reader)?;
+                        extern_desc(reader)?;
+                    }
+                    tag => instance_decl(reader, tag, depth)?,
+                }
+            }
+            TypeDef::Component
+        }
+        0x42 => {
+            for _ in 0..reader.u32()? {
+                let tag = reader.u8()?;
+                instance_decl(reader, tag, depth)?;
+            }
+            TypeDef::Other
+        }
+        0x3f => {
+            if reader.u8()? != 0x7f {
+                return Err(reader.error("resource representation must be i32"));
+            }
+            if reader.u8()? == 0x01 {
+                reader.u32()?;
+            }
+            TypeDef::Scalar(1)
+        }
+        0x72 => {
+            let mut fields = Vec::new();
+            for _ in 0..reader.u32()? {
+                reader.name()?;
+                fields.push(val_type(reader)?);
+            }
+            TypeDef::Record(fields)
+        }
+        0x71 => {
+            let mut cases = Vec::new();
+            for _ in 0..reader.u32()? {
+                reader.name()?;
+                cases.push(optional_val_type(reader)?);
+                if reader.u8()? != 0x00 {
+                    return Err(reader.error("variant refinements are not supported"));
+                }
+            }
+            TypeDef::Variant(cases)
+        }
+        0x70 => {
+            val_type(reader)?;
+            TypeDef::List
+        }
+        0x67 => {
+            let element = val_type(reader)?;
+            let length = reader.u32()?;
+            TypeDef::Record(alloc::vec![element; length as usize])
+        }
+        0x6f => {
+            let mut fields = Vec::new();
+            for _ in 0..reader.u32()? {
+                fields.push(val_type(reader)?);
+            }
+            TypeDef::Record(fields)
+        }
+        0x6e => {
+            let count = reader.u32()?;
+            for _ in 0..count {
+                reader.name()?;
+            }
+            TypeDef::Scalar((count as usize).div_ceil(32))
+        }
+        0x6d => {
+            for _ in 0..reader.u32()? {
+                reader.name()?;
+            }
+            TypeDef::Scalar(1)
+        }
+        0x6b => TypeDef::Variant(alloc::vec![None, Some(val_type(reader)?)]),
+        0x6a => TypeDef::Variant(alloc::vec![optional_val_type(reader)?, optional_val_type(reader)?]),
+        0x69 | 0x68 => {
+            reader.u32()?;
+            TypeDef::Scalar(1)
+        }
+        0x66 | 0x65 => {
+            optional_val_type(reader)?;
+            TypeDef::Scalar(1)
+        }
+        STRING => TypeDef::List,
+        0x64 | 0x74..=0x7f => TypeDef::Scalar(1),
+        _ => return Err(reader.error("unknown type constructor")),
+    })
+}
+
+fn instance_decl(reader: &mut Reader<'_>, tag: u8, depth: usize) -> Result<(), InspectError> {
+    match tag {
+        0x00 => skip_core_type(reader, depth + 1),
+        0x01 => type_def(reader, depth + 1).map(drop),
+        0x02 => {
+            sort(reader)?;
+            match reader.u8()? {
+                0x00 | 0x01 => {
+                    reader.u32()?;
+                    reader.name()?;
+                }
+                _ => {
+                    reader.u32()?;
+                    reader.u32()?;
+                }
+            }
+            Ok(())
+        }
+        0x04 => {
+            extern_name(reader)?;
+            extern_desc(reader).map(drop)
+        }
+        _ => Err(reader.error("unknown declaration")),
+    }
+}
+
+/// Skips a core function or module type
+fn skip_core_type(reader: &mut Reader<'_>, depth: usize) -> Result<(), InspectError> {
+    if depth >= MAX_DEPTH {
+        return Err(reader.error("types nested too deeply"));
+    }
+    match reader.u8()? {
+        0x60 => {
+            for _ in 0..2 {
+                for _ in 0..reader.u32()? {
+                    core_val_type(reader)?;
+                }
+            }
+        }
+        0x50 => {
+            for _ in 0..reader.u32()? {
+                match reader.u8()? {
+                    0x00 => {
+                        reader.name()?;
+                        reader.name()?;
+                        core_import_desc(reader)?;
+                    }
+                    0x01 => skip_core_type(reader, depth + 1)?,
+                    0x02 => {
+                        reader.u8()?;
+                        reader.u8()?;
+                        reader.u32()?;
+                        reader.u32()?;
+                    }
+                    0x03 => {
+                        reader.name()?;
+                        core_import_desc(reader)?;
+                    }
+                    _ => return Err(reader.error("unknown module declaration")),
+                }
+            }
+        }
+        _ => return Err(reader.error("unsupported core type")),
+    }
+    Ok(())
+}
+
+fn core_val_type(reader: &mut Reader<'_>) -> Result<(), InspectError> {
+    // Nullable and non-null references carry a heap type
+    if matches!(reader.u8()?, 0x63 | 0x64) {
+        reader.s33()?;
+    }
+    Ok(())
+}
+
+fn core_limits(reader: &mut Reader<'_>) -> Result<(), InspectError> {
+    let flags = reader.u8()?;
+    reader.u64()?;
+    if flags & 0x01 != 0 {
+        reader.u64()?;
+    }
+    Ok(())
+}
+
+fn core_import_desc(reader: &mut Reader<'_>) -> Result<CoreKind, InspectError> {
+    Ok(match reader.u8()? {
+        0x00 => {
+            reader.u32()?;
+            CoreKind::Func
+        }
+        0x01 => {
+            core_val_type(reader)?;
+            core_limits(reader)?;
+            CoreKind::Table
+        }
+        0x02 => {
+            core_limits(reader)?;
+            CoreKind::Memory
+        }
+        0x03 => {
+            core_val_type(reader)?;
+            reader.u8()?;
+            CoreKind::Global
+        }
+        0x04 => {
+            reader.u8()?;
+            reader.u32()?;
+            CoreKind::Tag
+        }
+        _ => {
+            return Err(reader.error("unknown import kind"))
+        }
+    })
+}
+
+/// Reads the imports and exports of an embedded core module
+fn parse_core_module(bytes: &[u8], offset: usize) -> Result<CoreModuleInfo, InspectError> {
+    let mut reader = Reader { bytes, pos: 0, base: offset, section: 1 };
+    if reader.take(4)? != b"\0asm" || reader.take(4)? != [1, 0, 0, 0] {
+        return Err(reader.error("invalid core module"));
+    }
+    let mut module = CoreModuleInfo { offset, size: bytes.len(), imports: Vec::new(), exports: Vec::new() };
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut section = reader.sub(size, 1)?;
+        match id {
+            2 => {
+                for _ in 0..section.u32()? {
+                    let from = section.name()?;
+                    let name = section.name()?;
+                    module.imports.push((from, name, core_import_desc(&mut section)?));
+                }
+            }
+            7 => {
+                for _ in 0..section.u32()? {
+                    let name = section.name()?;
+                    let kind = match section.u8()? {
+                        0x00 => CoreKind::Func,
+                        0x01 => CoreKind::Table,
+                        0x02 => CoreKind::Memory,
+                        0x03 => CoreKind::Global,
+                        _ => CoreKind::Tag,
+                    };
+                    section.u32()?;
+                    module.exports.push((name, kind));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(module)
+}
+
+/// Cursor over a byte slice that reports absolute offsets
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    base: usize,
+    /// Id of the component section being read, for errors
+    section: u8,
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a standalone binary, reporting errors against `section`
+    pub(super) fn new(bytes: &'a [u8], section: u8) -> Self {
+        Reader { bytes, pos: 0, base: 0, section }
+    }
+
+    fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+
+    pub(super) fn error(&self, message: &str) -> InspectError {
+        InspectError::MalformedSection { id: self.section, offset: self.offset(), message: message.to_string() }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8], InspectError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or(InspectError::UnexpectedEof { offset: self.base + self.bytes.len() })?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(super) fn sub(&mut self, len: usize, section: u8) -> Result<Reader<'a>, InspectError> {
+        let base = self.offset();
+        Ok(Reader { bytes: self.take(len)?, pos: 0, base, section })
+    }
+
+    pub(super) fn u8(&mut self) -> Result<u8, InspectError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, InspectError> {
+        let mut result = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(self.error("LEB128 too long"))
+    }
+
+    pub(super) fn u32(&mut self) -> Result<u32, InspectError> {
+        let value = self.u64()?;
+        u32::try_from(value).map_err(|_| self.error("integer out of range"))
+    }
+
+    fn s33(&mut self) -> Result<i64, InspectError> {
+        let mut result = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            result |= i64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                return Ok(result);
+            }
+            if shift >= 35 {
+                return Err(self.error("LEB128 too long"));
+            }
+        }
+    }
+
+    pub(super) fn name(&mut self) -> Result<String, InspectError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        core::str::from_utf8(bytes).map(ToString::to_string).map_err(|_| self.error("name is not UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leb(mut value: u32, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn name(text: &str, out: &mut Vec<u8>) {
+        leb(text.len() as u32, out);
+        out.extend_from_slice(text.as_bytes());
+    }
+
+    fn section(id: u8, contents: &[u8], out: &mut Vec<u8>) {
+        out.push(id);
+        leb(contents.len() as u32, out);
+        out.extend_from_slice(contents);
+    }
+
+    /// Core module exporting `memory`, `realloc`, and `run`
+    fn core_module() -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        let mut imports = alloc::vec![1];
+        name("env", &mut imports);
+        name("log", &mut imports);
+        imports.extend([0x00, 0x00]);
+        section(2, &imports, &mut module);
+        let mut exports = alloc::vec![3];
+        for (export, kind) in [("memory", 0x02), ("realloc", 0x00), ("run", 0x00)] {
+            name(export, &mut exports);
+            exports.extend([kind, 0x00]);
+        }
+        section(7, &exports, &mut module);
+        module
+    }
+
+    /// Component importing `host-log: func(msg: string)` and exporting
+    /// `run: func(input: string) -> u32`, with the given lower and lift options
+    fn component(lower_options: &[u8], lift_options: &[u8]) -> Vec<u8> {
+        let mut out = b"\0asm\x0d\0\x01\0".to_vec();
+        section(0, &{
+            let mut custom = Vec::new();
+            name("component-type:example:demo/app", &mut custom);
+            custom.extend_from_slice(b"payload");
+            custom
+        }, &mut out);
+        section(1, &core_module(), &mut out);
+        // Core instance 0 instantiates module 0 without arguments
+        section(2, &[1, 0x00, 0x00, 0x00], &mut out);
+
+        let mut types = alloc::vec![2];
+        // Type 0: func(msg: string)
+        types.extend([0x40, 1]);
+        name("msg", &mut types);
+        types.extend([STRING, 0x01, 0x00]);
+        // Type 1: func(input: string) -> u32
+        types.extend([0x40, 1]);
+        name("input", &mut types);
+        types.extend([STRING, 0x00, 0x79]);
+        section(7, &types, &mut out);
+
+        let mut import = alloc::vec![1, 0x00];
+        name("host-log", &mut import);
+        import.extend([0x01, 0x00]);
+        section(10, &import, &mut out);
+
+        // Core memory 0, core funcs 0 (realloc) and 1 (run)
+        let mut aliases = alloc::vec![3];
+        for (sort, export) in [(0x02, "memory"), (0x00, "realloc"), (0x00, "run")] {
+            aliases.extend([0x00, sort, 0x01, 0x00]);
+            name(export, &mut aliases);
+        }
+        section(6, &aliases, &mut out);
+
+        let mut canon = alloc::vec![2, 0x01, 0x00, 0x00];
+        canon.extend_from_slice(lower_options);
+        canon.extend([0x00, 0x00, 0x01]);
+        canon.extend_from_slice(lift_options);
+        canon.push(0x01);
+        section(8, &canon, &mut out);
+
+        let mut export = alloc::vec![1, 0x00];
+        name("run", &mut export);
+        export.extend([0x01, 0x01, 0x00]);
+        section(11, &export, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_inspect_lists_items() {
+        let info = inspect(&component(&[2, 0x00, 0x03, 0x00], &[2, 0x03, 0x00, 0x04, 0x00])).unwrap();
+        assert_eq!(info.worlds, ["example:demo/app"]);
+        assert_eq!(info.imports, [ComponentItem { name: "host-log".to_string(), kind: ExternKind::Func }]);
+        assert_eq!(info.exports, [ComponentItem { name: "run".to_string(), kind: ExternKind::Func }]);
+        assert_eq!(info.modules.len(), 1);
+        assert_eq!(info.modules[0].imports, [("env".to_string(), "log".to_string(), CoreKind::Func)]);
+        assert_eq!(info.modules[0].exports[0], ("memory".to_string(), CoreKind::Memory));
+        assert!(matches!(
+            &info.adapters[1],
+            CanonAdapter::Lift { core_func: 1, ty: 1, options } if options.realloc == Some(0)
+        ));
+        assert!(info.is_valid(), "{:?}", info.issues);
+        assert!(info.to_string().contains("export run: func\n"));
+
+        // Nested components are inspected recursively
+        let mut outer = b"\0asm\x0d\0\x01\0".to_vec();
+        section(4, &component(&[0], &[0]), &mut outer);
+        let outer = inspect(&outer).unwrap();
+        assert_eq!(outer.components.len(), 1);
+        assert!(!outer.is_valid());
+    }
+
+    #[test]
+    fn test_adapter_validation() {
+        // Lowering a string parameter without a memory, and lifting one without realloc
+        let info = inspect(&component(&[0], &[1, 0x03, 0x00])).unwrap();
+        let messages: Vec<(usize, &str)> = info.issues.iter().map(|issue| (issue.adapter, issue.message.as_str())).collect();
+        assert_eq!(messages, [
+            (0, "function passes values through memory but no memory is given"),
+            (1, "function receives strings or lists but no realloc is given"),
+        ]);
+
+        // Conflicting encodings, an unknown memory, and post-return on lower
+        let info = inspect(&component(&[4, 0x00, 0x01, 0x03, 0x05, 0x05, 0x00], &[2, 0x03, 0x00, 0x04, 0x00])).unwrap();
+        let messages: Vec<&str> = info.issues.iter().map(|issue| issue.message.as_str()).collect();
+        assert_eq!(messages, [
+            "more than one string encoding",
+            "memory is out of bounds",
+            "post-return is only allowed on lift",
+        ]);
+    }
+
+    #[test]
+    fn test_malformed_binaries() {
+        assert_eq!(inspect(b"\0wasm"), Err(InspectError::BadMagic));
+        assert_eq!(inspect(&core_module()), Err(InspectError::NotAComponent));
+        assert_eq!(inspect(b"\0asm\x0c\0\x01\0"), Err(InspectError::UnsupportedVersion(0x0001_000c)));
+
+        let mut truncated = component(&[0], &[0]);
+        truncated.truncate(truncated.len() - 2);
+        assert!(matches!(inspect(&truncated), Err(InspectError::UnexpectedEof { .. })));
+
+        let mut unknown = b"\0asm\x0d\0\x01\0".to_vec();
+        section(13, &[], &mut unknown);
+        assert!(matches!(inspect(&unknown), Err(InspectError::MalformedSection { id: 13, .. })));
+    }
+}
@@ -0,0 +1,339 @@
+//! Resource handles for the Component Model
+//!
+//! A resource crosses a component boundary as an `i32` handle into the
+//! receiving side's handle table, never as its contents. `Own<T>` owns a
+//! handle and drops the resource through `Resource::drop_handle` unless
+//! lowering moved it to the other side; `Borrow<'a, T>` lends one for a
+//! call and cannot outlive the `Own` it came from. The compiler's ownership
+//! tracker treats owned handles as linear and borrowed ones as borrowed.
+//!
+//! Resources a component exports are represented by an index into a
+//! `HandleTable`, kept in a `ResourceTable` static by the generated
+//! bindings. The host passes that representation to methods and to the
+//! `[dtor]` export, which removes and drops the value.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
+use core::slice::Iter;
+
+use super::abi::{next_flat, Allocations, ComponentValue};
+
+/// Resource handle errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceError {
+    /// The handle or representation names no live resource
+    InvalidHandle(u32),
+}
+
+impl core::fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ResourceError::InvalidHandle(handle) => write!(f, "Invalid resource handle {}", handle),
+        }
+    }
+}
+
+/// Resource type whose handles the other side of the boundary owns
+pub trait Resource {
+    /// Releases an owned handle, calling `[resource-drop]`
+    fn drop_handle(handle: u32);
+}
+
+/// Slots of resource values, indexed from 1 so 0 is never a valid handle
+#[derive(Debug)]
+pub struct HandleTable<T> {
+    slots: Vec<Option<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> HandleTable<T> {
+    pub const fn new() -> Self {
+        Self { slots: Vec::new(), free: Vec::new() }
+    }
+
+    /// Stores a value, reusing a freed slot if there is one
+    pub fn insert(&mut self, value: T) -> u32 {
+        match self.free.pop() {
+            Some(handle) => {
+                self.slots[handle as usize - 1] = Some(value);
+                handle
+            }
+            None => {
+                self.slots.push(Some(value));
+                self.slots.len() as u32
+            }
+        }
+    }
+
+    pub fn get(&self, handle: u32) -> Result<&T, ResourceError> {
+        self.slots.get((handle as usize).wrapping_sub(1))
+            .and_then(Option::as_ref)
+            .ok_or(ResourceError::InvalidHandle(handle))
+    }
+
+    pub fn get_mut(&mut self, handle: u32) -> Result<&mut T, ResourceError> {
+        self.slots.get_mut((handle as usize).wrapping_sub(1))
+            .and_then(Option::as_mut)
+            .ok_or(ResourceError::InvalidHandle(handle))
+    }
+
+    /// Takes a value out, freeing its slot
+    pub fn remove(&mut self, handle: u32) -> Result<T, ResourceError> {
+        let value = self.slots.get_mut((handle as usize).wrapping_sub(1))
+            .and_then(Option::take)
+            .ok_or(ResourceError::InvalidHandle(handle))?;
+        self.free.push(handle);
+        Ok(value)
+    }
+
+    /// Number of live values
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Table of an exported resource's values, for use in a static
+///
+/// Values are boxed so references handed to methods stay valid while the
+/// method creates or drops other values of the same resource.
+pub struct ResourceTable<T>(UnsafeCell<HandleTable<Box<T>>>);
+
+// SAFETY: components run on a single thread, and every access completes
+// before control returns to the host or to guest code
+unsafe impl<T> Sync for ResourceTable<T> {}
+
+impl<T> ResourceTable<T> {
+    pub const fn new() -> Self {
+        Self(UnsafeCell::new(HandleTable::new()))
+    }
+
+    /// Stores a new value, returning its representation
+    pub fn insert(&self, value: T) -> u32 {
+        // SAFETY: see the `Sync` impl; no reference into the table escapes
+        unsafe { (*self.0.get()).insert(Box::new(value)) }
+    }
+
+    /// Gets the value for a representation the host passed
+    ///
+    /// # Safety
+    ///
+    /// The value must not be removed while the reference is alive, which
+    /// holds for the duration of a call borrowing it.
+    pub unsafe fn get<'a>(&self, rep: u32) -> Result<&'a T, ResourceError> {
+        let value: *const T = &**(*self.0.get()).get(rep)?;
+        Ok(&*value)
+    }
+
+    /// Removes a value for the resource's destructor
+    pub fn remove(&self, rep: u32) -> Result<T, ResourceError> {
+        // SAFETY: see the `Sync` impl; no reference into the table escapes
+        unsafe { (*self.0.get()).remove(rep).map(|value| *value) }
+    }
+}
+
+impl<T> Default for ResourceTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Owned handle to a resource
+///
+/// Dropping it drops the resource. Lowering it, as an argument or inside
+/// a result, moves the handle to the other side and leaves it empty.
+#[must_use = "dropping an owned handle drops the resource"]
+pub struct Own<T: Resource> {
+    handle: Cell<u32>,
+    _resource: PhantomData<T>,
+}
+
+impl<T: Resource> Own<T> {
+    /// Takes ownership of a handle
+    ///
+    /// # Safety
+    ///
+    /// The handle must be owned by the caller and not wrapped elsewhere.
+    pub unsafe fn from_handle(handle: u32) -> Self {
+        Self { handle: Cell::new(handle), _resource: PhantomData }
+    }
+
+    /// Gets the handle, or 0 once it was moved out by lowering
+    pub fn handle(&self) -> u32 {
+        self.handle.get()
+    }
+
+    /// Lends the handle for a call
+    pub fn borrow(&self) -> Borrow<'_, T> {
+        Borrow { handle: self.handle.get(), _owner: PhantomData }
+    }
+
+    /// Releases ownership without dropping the resource
+    pub fn into_handle(self) -> u32 {
+        self.handle.replace(0)
+    }
+}
+
+impl<T: Resource> Drop for Own<T> {
+    fn drop(&mut self) {
+        let handle = self.handle.replace(0);
+        if handle != 0 {
+            T::drop_handle(handle);
+        }
+    }
+}
+
+impl<T: Resource> core::fmt::Debug for Own<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Own({})", self.handle.get())
+    }
+}
+
+/// Borrowed handle to a resource, valid while its owner is
+pub struct Borrow<'a, T: Resource> {
+    handle: u32,
+    _owner: PhantomData<&'a Own<T>>,
+}
+
+impl<T: Resource> Borrow<'_, T> {
+    /// Lends a handle received from the other side
+    ///
+    /// # Safety
+    ///
+    /// The handle must stay valid for the borrow's lifetime.
+    pub unsafe fn from_handle(handle: u32) -> Self {
+        Self { handle, _owner: PhantomData }
+    }
+
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+}
+
+impl<T: Resource> Clone for Borrow<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Resource> Copy for Borrow<'_, T> {}
+
+impl<T: Resource> core::fmt::Debug for Borrow<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Borrow({})", self.handle)
+    }
+}
+
+// SAFETY: handles are `i32` in the canonical ABI
+unsafe impl<T: Resource> ComponentValue for Own<T> {
+    const SIZE: usize = 4;
+    const ALIGN: usize = 4;
+    const FLAT_COUNT: usize = 1;
+
+    fn lower(&self, flat: &mut Vec<u64>, _: &mut Allocations) {
+        flat.push(u64::from(self.handle.replace(0)));
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        Own::from_handle(next_flat(flat) as u32)
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, _: &mut Allocations) {
+        (ptr as *mut u32).write(self.handle.replace(0));
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        Own::from_handle((ptr as *const u32).read())
+    }
+}
+
+// SAFETY: handles are `i32` in the canonical ABI
+unsafe impl<T: Resource> ComponentValue for Borrow<'_, T> {
+    const SIZE: usize = 4;
+    const ALIGN: usize = 4;
+    const FLAT_COUNT: usize = 1;
+
+    fn lower(&self, flat: &mut Vec<u64>, _: &mut Allocations) {
+        flat.push(u64::from(self.handle));
+    }
+
+    unsafe fn lift(flat: &mut Iter<'_, u64>) -> Self {
+        Borrow::from_handle(next_flat(flat) as u32)
+    }
+
+    unsafe fn store(&self, ptr: *mut u8, _: &mut Allocations) {
+        (ptr as *mut u32).write(self.handle);
+    }
+
+    unsafe fn load(ptr: *const u8) -> Self {
+        Borrow::from_handle((ptr as *const u32).read())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+    struct Blob;
+
+    impl Resource for Blob {
+        fn drop_handle(handle: u32) {
+            DROPPED.fetch_add(handle, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_handle_table_reuses_slots() {
+        let mut table = HandleTable::new();
+        assert_eq!((table.insert("a"), table.insert("b")), (1, 2));
+        assert_eq!(table.remove(1), Ok("a"));
+        assert_eq!(table.remove(1), Err(ResourceError::InvalidHandle(1)));
+        assert_eq!(table.get(0), Err(ResourceError::InvalidHandle(0)));
+        assert_eq!(table.insert("c"), 1);
+        *table.get_mut(2).unwrap() = "d";
+        assert_eq!((table.get(1), table.get(2), table.len()), (Ok(&"c"), Ok(&"d"), 2));
+
+        static COUNTERS: ResourceTable<u32> = ResourceTable::new();
+        let rep = COUNTERS.insert(7);
+        // SAFETY: the value is not removed while borrowed
+        assert_eq!(unsafe { COUNTERS.get(rep) }, Ok(&7));
+        assert_eq!(COUNTERS.remove(rep), Ok(7));
+    }
+
+    #[test]
+    fn test_lowering_moves_owned_handles() {
+        // SAFETY: the handles are not owned elsewhere
+        let (kept, moved, stored) = unsafe { (Own::<Blob>::from_handle(100), Own::<Blob>::from_handle(20), Own::<Blob>::from_handle(3)) };
+        let mut flat = Vec::new();
+        kept.borrow().lower(&mut flat, &mut Allocations::new());
+        moved.lower(&mut flat, &mut Allocations::new());
+        let mut slot = 0u32;
+        // SAFETY: the slot holds one handle
+        unsafe { stored.store(&mut slot as *mut u32 as *mut u8, &mut Allocations::new()) };
+        assert_eq!((flat, slot, moved.handle(), kept.handle()), (alloc::vec![100, 20], 3, 0, 100));
+
+        drop((moved, stored));
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+        let handle = kept.into_handle();
+        assert_eq!((handle, DROPPED.load(Ordering::SeqCst)), (100, 0));
+
+        // SAFETY: the lifted handle is owned by this test
+        let lifted: Own<Blob> = unsafe { ComponentValue::lift(&mut [5u64].iter()) };
+        drop(lifted);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 5);
+    }
+}
@@ -0,0 +1,1094 @@
+//! WIT (`.wit`) parser
+//!
+//! Parses a single package's interfaces and worlds into the component
+//! types the binding generators consume. Supported: `package`, `use` of
+//! types from the package's own interfaces, `type` aliases, `record`,
+//! `variant`, `enum`, `flags`, `resource` with `own`/`borrow` handles,
+//! functions including `async func`, and worlds importing and exporting
+//! named or inline interfaces. A resource's constructor, methods, and static
+//! functions join the interface's functions under the component model's
+//! `[constructor]r`, `[method]r.name`, and `[static]r.name` names; methods
+//! take a leading `self: borrow<r>`. `@since`/`@unstable` gates are ignored.
+//! Tuples, handles, world-level functions and types, and `include` are
+//! rejected with `WitError::Unsupported`. `diff` classifies the changes
+//! between two versions of a package.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use super::wit_deps::PackageId;
+use super::{ComponentFunction, ComponentInterface, ComponentWorld, WitType};
+use crate::wasmir::Type;
+
+pub mod diff;
+
+/// WIT parsing and resolution errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitError {
+    /// Malformed source
+    Parse { line: usize, message: String },
+    /// A type name that is neither defined nor `use`d
+    UnknownType { interface: String, name: String },
+    /// An interface the package does not define
+    UnknownInterface(String),
+    /// A world the package does not define
+    UnknownWorld(String),
+    /// Two definitions share a name
+    DuplicateName(String),
+    /// `own` or `borrow` of a type that is not a resource
+    NotAResource(String),
+    /// Valid WIT this parser does not handle yet
+    Unsupported { line: usize, feature: String },
+}
+
+impl core::fmt::Display for WitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WitError::Parse { line, message } => write!(f, "WIT parse error on line {}: {}", line, message),
+            WitError::UnknownType { interface, name } => write!(f, "Unknown type {} in interface {}", name, interface),
+            WitError::UnknownInterface(name) => write!(f, "Unknown interface: {}", name),
+            WitError::UnknownWorld(name) => write!(f, "Unknown world: {}", name),
+            WitError::DuplicateName(name) => write!(f, "Duplicate WIT definition: {}", name),
+            WitError::NotAResource(name) => write!(f, "Handle to {}, which is not a resource", name),
+            WitError::Unsupported { line, feature } => write!(f, "Unsupported WIT on line {}: {}", line, feature),
+        }
+    }
+}
+
+/// Shape of a named type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitTypeDefKind {
+    /// `type name = ty;`
+    Alias(WitType),
+    Record(Vec<(String, WitType)>),
+    Variant(Vec<(String, Option<WitType>)>),
+    Enum(Vec<String>),
+    Flags(Vec<String>),
+    Resource,
+}
+
+/// Function declared in a `resource` body, recovered from its mangled name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceFunction<'a> {
+    /// `[constructor]r`, returning `own<r>`
+    Constructor { resource: &'a str },
+    /// `[method]r.name`, taking `self: borrow<r>` first
+    Method { resource: &'a str, name: &'a str },
+    /// `[static]r.name`
+    Static { resource: &'a str, name: &'a str },
+}
+
+impl<'a> ResourceFunction<'a> {
+    /// Classifies a function name, or returns `None` for free functions
+    pub fn parse(function: &'a str) -> Option<Self> {
+        if let Some(resource) = function.strip_prefix("[constructor]") {
+            return Some(ResourceFunction::Constructor { resource });
+        }
+        let (resource, name) = function.strip_prefix("[method]")
+            .or_else(|| function.strip_prefix("[static]"))?
+            .split_once('.')?;
+        if function.starts_with("[method]") {
+            Some(ResourceFunction::Method { resource, name })
+        } else {
+            Some(ResourceFunction::Static { resource, name })
+        }
+    }
+
+    pub fn resource(self) -> &'a str {
+        match self {
+            ResourceFunction::Constructor { resource }
+            | ResourceFunction::Method { resource, .. }
+            | ResourceFunction::Static { resource, .. } => resource,
+        }
+    }
+}
+
+/// Named type defined by an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitTypeDef {
+    pub name: String,
+    pub kind: WitTypeDefKind,
+}
+
+/// `use interface.{name, name as alias};`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitUse {
+    pub interface: String,
+    /// Each used type as `(name, local name)`
+    pub names: Vec<(String, String)>,
+}
+
+/// Interface: types and functions under one name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WitInterface {
+    pub name: String,
+    pub uses: Vec<WitUse>,
+    pub types: Vec<WitTypeDef>,
+    pub functions: Vec<ComponentFunction>,
+}
+
+impl WitInterface {
+    /// Finds a type defined by this interface
+    pub fn type_def(&self, name: &str) -> Option<&WitTypeDef> {
+        self.types.iter().find(|def| def.name == name)
+    }
+
+    /// Finds the `use` that brings `name` into scope, with the original name
+    pub fn used_type(&self, name: &str) -> Option<(&str, &str)> {
+        self.uses.iter().find_map(|used| {
+            used.names.iter()
+                .find(|(_, local)| local == name)
+                .map(|(original, _)| (used.interface.as_str(), original.as_str()))
+        })
+    }
+}
+
+/// Interface a world imports or exports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorldItem {
+    /// Interface named by a path such as `logger` or `wasi:cli/run@0.2.0`
+    Interface(String),
+    /// `import name: interface { ... }`
+    Inline(WitInterface),
+}
+
+/// World declared by a package
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WitWorld {
+    pub name: String,
+    pub imports: Vec<WorldItem>,
+    pub exports: Vec<WorldItem>,
+}
+
+/// Interfaces of a world, keyed by their fully qualified names
+#[derive(Debug, Clone)]
+pub struct WorldInterfaces<'a> {
+    /// Explicit imports followed by interfaces imported implicitly through `use`
+    pub imports: Vec<(String, &'a WitInterface)>,
+    pub exports: Vec<(String, &'a WitInterface)>,
+}
+
+/// Parsed WIT package
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WitPackage {
+    pub id: Option<PackageId>,
+    pub interfaces: Vec<WitInterface>,
+    pub worlds: Vec<WitWorld>,
+}
+
+impl WitPackage {
+    /// Parses a package from WIT source
+    pub fn parse(source: &str) -> Result<Self, WitError> {
+        let mut package = WitPackage::default();
+        package.extend(source)?;
+        Ok(package)
+    }
+
+    /// Adds the definitions of another file of the same package
+    pub fn extend(&mut self, source: &str) -> Result<(), WitError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        while !parser.at_end() {
+            parser.skip_gates()?;
+            let line = parser.line();
+            match parser.ident()?.as_str() {
+                "package" => {
+                    let id = parser.package_path()?;
+                    parser.expect(';')?;
+                    let id = PackageId::parse(&id).map_err(|_| WitError::Parse {
+                        line,
+                        message: format!("invalid package id {}", id),
+                    })?;
+                    if self.id.as_ref().is_some_and(|existing| *existing != id) {
+                        return Err(WitError::Parse { line, message: format!("conflicting package {}", id) });
+                    }
+                    self.id = Some(id);
+                }
+                "interface" => {
+                    let name = parser.ident()?;
+                    let interface = parser.interface_body(name)?;
+                    if self.interface(&interface.name).is_some() {
+                        return Err(WitError::DuplicateName(interface.name));
+                    }
+                    self.interfaces.push(interface);
+                }
+                "world" => {
+                    let name = parser.ident()?;
+                    let world = parser.world_body(name)?;
+                    if self.world_def(&world.name).is_some() {
+                        return Err(WitError::DuplicateName(world.name));
+                    }
+                    self.worlds.push(world);
+                }
+                "use" => return Err(WitError::Unsupported { line, feature: "top-level use".to_string() }),
+                other => return Err(WitError::Parse { line, message: format!("unexpected {}", other) }),
+            }
+        }
+        self.check_types()
+    }
+
+    /// Finds an interface by name
+    pub fn interface(&self, name: &str) -> Option<&WitInterface> {
+        self.interfaces.iter().find(|interface| interface.name == name)
+    }
+
+    /// Finds a world by name
+    pub fn world_def(&self, name: &str) -> Option<&WitWorld> {
+        self.worlds.iter().find(|world| world.name == name)
+    }
+
+    /// Fully qualified name of one of the package's interfaces
+    pub fn qualified_name(&self, interface: &str) -> String {
+        match &self.id {
+            Some(id) => {
+                let version = id.version.as_ref().map(|v| format!("@{}", v)).unwrap_or_default();
+                format!("{}/{}{}", id.unversioned(), interface, version)
+            }
+            None => interface.to_string(),
+        }
+    }
+
+    /// Resolves the interfaces a world imports and exports
+    pub fn world_interfaces(&self, world: &str) -> Result<WorldInterfaces<'_>, WitError> {
+        let def = self.world_def(world).ok_or_else(|| WitError::UnknownWorld(world.to_string()))?;
+        let mut imports = self.resolve_items(&def.imports)?;
+        let exports = self.resolve_items(&def.exports)?;
+
+        // Interfaces whose types are `use`d are imported along with their users
+        let mut pending: Vec<&WitInterface> = imports.iter().chain(&exports).map(|(_, interface)| *interface).collect();
+        while let Some(interface) = pending.pop() {
+            for used in &interface.uses {
+                let name = self.qualified_name(&used.interface);
+                if imports.iter().any(|(existing, _)| *existing == name) {
+                    continue;
+                }
+                let target = self.interface(&used.interface)
+                    .ok_or_else(|| WitError::UnknownInterface(used.interface.clone()))?;
+                imports.push((name, target));
+                pending.push(target);
+            }
+        }
+        Ok(WorldInterfaces { imports, exports })
+    }
+
+    /// Resolves world items to qualified names and definitions
+    fn resolve_items<'p>(&'p self, items: &'p [WorldItem]) -> Result<Vec<(String, &'p WitInterface)>, WitError> {
+        let mut resolved: Vec<(String, &WitInterface)> = Vec::new();
+        for item in items {
+            let entry = match item {
+                WorldItem::Interface(path) => {
+                    let name = self.local_interface(path)?;
+                    (self.qualified_name(name), self.interface(name).expect("checked by local_interface"))
+                }
+                WorldItem::Inline(interface) => (interface.name.clone(), interface),
+            };
+            if resolved.iter().any(|(name, _)| *name == entry.0) {
+                return Err(WitError::DuplicateName(entry.0));
+            }
+            resolved.push(entry);
+        }
+        Ok(resolved)
+    }
+
+    /// Maps an interface path to the name of one of the package's interfaces
+    fn local_interface<'p>(&self, path: &'p str) -> Result<&'p str, WitError> {
+        let unknown = || WitError::UnknownInterface(path.to_string());
+        let name = match path.split_once('/') {
+            Some((package, interface)) => {
+                let own = self.id.as_ref().ok_or_else(unknown)?;
+                let (interface, version) = match interface.split_once('@') {
+                    Some((interface, version)) => (interface, Some(version)),
+                    None => (interface, None),
+                };
+                if package != own.unversioned() || version != own.version.as_deref() {
+                    return Err(unknown());
+                }
+                interface
+            }
+            None => path,
+        };
+        self.interface(name).map(|_| name).ok_or_else(unknown)
+    }
+
+    /// Resolves a world to the interface descriptions the host bindings use
+    pub fn world(&self, world: &str) -> Result<ComponentWorld, WitError> {
+        let interfaces = self.world_interfaces(world)?;
+        let describe = |(name, interface): &(String, &WitInterface)| {
+            let mut described = ComponentInterface::new(name.clone());
+            if let Some(version) = self.id.as_ref().and_then(|id| id.version.clone()) {
+                described.version = version;
+            }
+            described.functions = interface.functions.clone();
+            described
+        };
+        let mut component = ComponentWorld::new(world);
+        component.imports = interfaces.imports.iter().map(describe).collect();
+        component.exports = interfaces.exports.iter().map(describe).collect();
+        Ok(component)
+    }
+
+    /// Finds the definition of a named type, following `use`s
+    pub fn resolve_type<'p>(&'p self, interface: &'p WitInterface, name: &str) -> Result<(&'p WitInterface, &'p WitTypeDef), WitError> {
+        if let Some(def) = interface.type_def(name) {
+            return Ok((interface, def));
+        }
+        let unknown = || WitError::UnknownType { interface: interface.name.clone(), name: name.to_string() };
+        let (target, original) = interface.used_type(name).ok_or_else(unknown)?;
+        let target = self.interface(target).ok_or_else(|| WitError::UnknownInterface(target.to_string()))?;
+        self.resolve_type(target, original)
+    }
+
+    /// Appends the core types a WIT type flattens to under the canonical ABI
+    pub fn flatten(&self, interface: &WitInterface, ty: &WitType, out: &mut Vec<Type>) -> Result<(), WitError> {
+        match ty {
+            WitType::U64 | WitType::S64 => out.push(Type::I64),
+            WitType::F32 => out.push(Type::F32),
+            WitType::F64 => out.push(Type::F64),
+            WitType::String | WitType::List(_) => out.extend([Type::I32, Type::I32]),
+            WitType::Option(inner) => self.flatten_variant(interface, &[None, Some(inner)], out)?,
+            WitType::Result { ok, err } => {
+                self.flatten_variant(interface, &[ok.as_deref(), err.as_deref()], out)?
+            }
+            WitType::Named(name) => {
+                let (owner, def) = self.resolve_type(interface, name)?;
+                match &def.kind {
+                    WitTypeDefKind::Alias(ty) => self.flatten(owner, ty, out)?,
+                    WitTypeDefKind::Record(fields) => {
+                        for (_, field) in fields {
+                            self.flatten(owner, field, out)?;
+                        }
+                    }
+                    WitTypeDefKind::Variant(cases) => {
+                        let cases: Vec<Option<&WitType>> = cases.iter().map(|(_, ty)| ty.as_ref()).collect();
+                        self.flatten_variant(owner, &cases, out)?;
+                    }
+                    _ => out.push(Type::I32),
+                }
+            }
+            _ => out.push(Type::I32),
+        }
+        Ok(())
+    }
+
+    /// Flattens a discriminant followed by the join of every case's payload
+    fn flatten_variant(
+        &self,
+        interface: &WitInterface,
+        cases: &[Option<&WitType>],
+        out: &mut Vec<Type>,
+    ) -> Result<(), WitError> {
+        out.push(Type::I32);
+        let mut joined: Vec<Type> = Vec::new();
+        for case in cases.iter().flatten() {
+            let mut flat = Vec::new();
+            self.flatten(interface, case, &mut flat)?;
+            for (i, ty) in flat.into_iter().enumerate() {
+                match joined.get_mut(i) {
+                    None => joined.push(ty),
+                    Some(existing) if *existing == ty => {}
+                    Some(existing) => {
+                        *existing = if matches!((&*existing, &ty), (Type::I32, Type::F32) | (Type::F32, Type::I32)) {
+                            Type::I32
+                        } else {
+                            Type::I64
+                        };
+                    }
+                }
+            }
+        }
+        out.extend(joined);
+        Ok(())
+    }
+
+    /// Checks whether a WIT type holds strings or lists, which live in linear memory
+    pub fn uses_memory(&self, interface: &WitInterface, ty: &WitType) -> Result<bool, WitError> {
+        let mut direct = false;
+        let mut named = Vec::new();
+        ty.visit(&mut |ty| match ty {
+            WitType::String | WitType::List(_) => direct = true,
+            WitType::Named(name) => named.push(name),
+            _ => {}
+        });
+        if direct {
+            return Ok(true);
+        }
+        for name in named {
+            let (owner, def) = self.resolve_type(interface, name)?;
+            let members: Vec<&WitType> = match &def.kind {
+                WitTypeDefKind::Alias(ty) => alloc::vec![ty],
+                WitTypeDefKind::Record(fields) => fields.iter().map(|(_, ty)| ty).collect(),
+                WitTypeDefKind::Variant(cases) => cases.iter().filter_map(|(_, ty)| ty.as_ref()).collect(),
+                _ => Vec::new(),
+            };
+            for member in members {
+                if self.uses_memory(owner, member)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Checks that every named type resolves
+    fn check_types(&self) -> Result<(), WitError> {
+        let inline = self.worlds.iter()
+            .flat_map(|world| world.imports.iter().chain(&world.exports))
+            .filter_map(|item| match item {
+                WorldItem::Inline(interface) => Some(interface),
+                WorldItem::Interface(_) => None,
+            });
+        for interface in self.interfaces.iter().chain(inline) {
+            for used in &interface.uses {
+                let target = self.interface(&used.interface)
+                    .ok_or_else(|| WitError::UnknownInterface(used.interface.clone()))?;
+                for (name, _) in &used.names {
+                    if target.type_def(name).is_none() && target.used_type(name).is_none() {
+                        return Err(WitError::UnknownType { interface: used.interface.clone(), name: name.clone() });
+                    }
+                }
+            }
+
+            let mut names = Vec::new();
+            for def in &interface.types {
+                match &def.kind {
+                    WitTypeDefKind::Alias(ty) => names.push(ty),
+                    WitTypeDefKind::Record(fields) => names.extend(fields.iter().map(|(_, ty)| ty)),
+                    WitTypeDefKind::Variant(cases) => names.extend(cases.iter().filter_map(|(_, ty)| ty.as_ref())),
+                    _ => {}
+                }
+            }
+            names.extend(interface.functions.iter().flat_map(ComponentFunction::types));
+            for ty in names {
+                let mut unknown = None;
+                let mut handles = Vec::new();
+                ty.visit(&mut |ty| {
+                    if let WitType::Named(name) | WitType::Own(name) | WitType::Borrow(name) = ty {
+                        if interface.type_def(name).is_none() && interface.used_type(name).is_none() {
+                            unknown.get_or_insert_with(|| name.clone());
+                        }
+                    }
+                    if let WitType::Own(name) | WitType::Borrow(name) = ty {
+                        handles.push(name);
+                    }
+                });
+                if let Some(name) = unknown {
+                    return Err(WitError::UnknownType { interface: interface.name.clone(), name });
+                }
+                for name in handles {
+                    if self.resolve_type(interface, name)?.1.kind != WitTypeDefKind::Resource {
+                        return Err(WitError::NotAResource(name.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Version(String),
+    Punct(char),
+    Arrow,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, WitError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            prev = c;
+                        }
+                        None => return Err(WitError::Parse { line, message: "unterminated comment".to_string() }),
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push((Token::Arrow, line));
+            }
+            // Versions follow `@` in package paths; gates like `@since` do not
+            '@' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let mut version = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')) {
+                        break;
+                    }
+                    version.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Version(version), line));
+            }
+            c if c.is_ascii_alphabetic() || c == '%' => {
+                let mut ident = String::new();
+                if c != '%' {
+                    ident.push(c);
+                }
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '-') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Ident(number), line));
+            }
+            '{' | '}' | '(' | ')' | '<' | '>' | ',' | ';' | ':' | '=' | '.' | '/' | '@' | '_' | '*' => {
+                tokens.push((Token::Punct(c), line));
+            }
+            other => return Err(WitError::Parse { line, message: format!("unexpected character {:?}", other) }),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: impl Into<String>) -> WitError {
+        WitError::Parse { line: self.line(), message: message.into() }
+    }
+
+    fn unsupported(&self, feature: &str) -> WitError {
+        WitError::Unsupported { line: self.line(), feature: feature.to_string() }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), WitError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", c)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, WitError> {
+        match self.next() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected identifier"))
+            }
+        }
+    }
+
+    fn peek_ident(&self, expected: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if ident == expected)
+    }
+
+    /// Skips `@since(...)`, `@unstable(...)`, and `@deprecated(...)` gates
+    fn skip_gates(&mut self) -> Result<(), WitError> {
+        while self.eat('@') {
+            self.ident()?;
+            self.expect('(')?;
+            while !self.eat(')') {
+                if self.next().is_none() {
+                    return Err(self.error("unterminated feature gate"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses `name`, `ns:pkg`, or `ns:pkg/name`, each with an optional `@version`
+    fn package_path(&mut self) -> Result<String, WitError> {
+        let mut path = self.ident()?;
+        if self.eat(':') {
+            path.push(':');
+            path.push_str(&self.ident()?);
+            if self.eat('/') {
+                path.push('/');
+                path.push_str(&self.ident()?);
+            }
+        }
+        if let Some(Token::Version(version)) = self.peek() {
+            path.push('@');
+            path.push_str(version);
+            self.pos += 1;
+        }
+        Ok(path)
+    }
+
+    fn interface_body(&mut self, name: String) -> Result<WitInterface, WitError> {
+        let mut interface = WitInterface { name, ..WitInterface::default() };
+        self.expect('{')?;
+        while !self.eat('}') {
+            self.skip_gates()?;
+            let keyword = self.ident()?;
+            let defined = match keyword.as_str() {
+                "use" => {
+                    let used = self.use_statement()?;
+                    interface.uses.push(used);
+                    continue;
+                }
+                "type" => {
+                    let name = self.ident()?;
+                    self.expect('=')?;
+                    let ty = self.ty()?;
+                    self.expect(';')?;
+                    WitTypeDef { name, kind: WitTypeDefKind::Alias(ty) }
+                }
+                "record" => {
+                    let name = self.ident()?;
+                    let fields = self.list('{', '}', |parser| {
+                        let field = parser.ident()?;
+                        parser.expect(':')?;
+                        Ok((field, parser.ty()?))
+                    })?;
+                    WitTypeDef { name, kind: WitTypeDefKind::Record(fields) }
+                }
+                "variant" => {
+                    let name = self.ident()?;
+                    let cases = self.list('{', '}', |parser| {
+                        let case = parser.ident()?;
+                        let payload = if parser.eat('(') {
+                            let ty = parser.ty()?;
+                            parser.expect(')')?;
+                            Some(ty)
+                        } else {
+                            None
+                        };
+                        Ok((case, payload))
+                    })?;
+                    WitTypeDef { name, kind: WitTypeDefKind::Variant(cases) }
+                }
+                "enum" => {
+                    let name = self.ident()?;
+                    WitTypeDef { name, kind: WitTypeDefKind::Enum(self.list('{', '}', Parser::ident)?) }
+                }
+                "flags" => {
+                    let name = self.ident()?;
+                    WitTypeDef { name, kind: WitTypeDefKind::Flags(self.list('{', '}', Parser::ident)?) }
+                }
+                "resource" => {
+                    let name = self.ident()?;
+                    if !self.eat(';') {
+                        for function in self.resource_body(&name)? {
+                            if interface.functions.iter().any(|existing| existing.name == function.name) {
+                                return Err(WitError::DuplicateName(format!("{}.{}", interface.name, function.name)));
+                            }
+                            interface.functions.push(function);
+                        }
+                    }
+                    WitTypeDef { name, kind: WitTypeDefKind::Resource }
+                }
+                _ => {
+                    self.expect(':')?;
+                    let function = self.function(keyword)?;
+                    if interface.functions.iter().any(|existing| existing.name == function.name) {
+                        return Err(WitError::DuplicateName(format!("{}.{}", interface.name, function.name)));
+                    }
+                    interface.functions.push(function);
+                    continue;
+                }
+            };
+            if interface.type_def(&defined.name).is_some() {
+                return Err(WitError::DuplicateName(format!("{}.{}", interface.name, defined.name)));
+            }
+            interface.types.push(defined);
+        }
+        Ok(interface)
+    }
+
+    fn world_body(&mut self, name: String) -> Result<WitWorld, WitError> {
+        let mut world = WitWorld { name, ..WitWorld::default() };
+        self.expect('{')?;
+        while !self.eat('}') {
+            self.skip_gates()?;
+            let keyword = self.ident()?;
+            let export = match keyword.as_str() {
+                "import" => false,
+                "export" => true,
+                "include" => return Err(self.unsupported("world include")),
+                "use" | "type" | "record" | "variant" | "enum" | "flags" | "resource" => {
+                    return Err(self.unsupported("types declared in a world"))
+                }
+                other => return Err(self.error(format!("unexpected {} in world", other))),
+            };
+            // `name: interface { ... }` and `name: func(...)` rather than `ns:pkg/name`
+            let inline = match (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+                (Some((Token::Punct(':'), _)), Some((Token::Ident(next), _))) => {
+                    matches!(next.as_str(), "interface" | "func" | "async")
+                }
+                _ => false,
+            };
+            let item = if inline {
+                let name = self.ident()?;
+                self.expect(':')?;
+                if !self.peek_ident("interface") {
+                    return Err(self.unsupported("world-level functions"));
+                }
+                self.pos += 1;
+                WorldItem::Inline(self.interface_body(name)?)
+            } else {
+                let path = self.package_path()?;
+                self.expect(';')?;
+                WorldItem::Interface(path)
+            };
+            if export {
+                world.exports.push(item);
+            } else {
+                world.imports.push(item);
+            }
+        }
+        Ok(world)
+    }
+
+    /// Parses the rest of `use path.{a, b as c};`
+    fn use_statement(&mut self) -> Result<WitUse, WitError> {
+        let interface = self.package_path()?;
+        self.expect('.')?;
+        let names = self.list('{', '}', |parser| {
+            let name = parser.ident()?;
+            if parser.peek_ident("as") {
+                parser.pos += 1;
+                Ok((name, parser.ident()?))
+            } else {
+                Ok((name.clone(), name))
+            }
+        })?;
+        self.expect(';')?;
+        Ok(WitUse { interface, names })
+    }
+
+    /// Parses the `{ ... }` of a resource into its mangled functions
+    fn resource_body(&mut self, resource: &str) -> Result<Vec<ComponentFunction>, WitError> {
+        let mut functions = Vec::new();
+        self.expect('{')?;
+        while !self.eat('}') {
+            self.skip_gates()?;
+            let name = self.ident()?;
+            if name == "constructor" {
+                let mut constructor = ComponentFunction::new(format!("[constructor]{}", resource));
+                constructor.params = self.params()?;
+                if self.peek() == Some(&Token::Arrow) {
+                    return Err(self.unsupported("fallible constructors"));
+                }
+                self.expect(';')?;
+                functions.push(constructor.result(WitType::Own(resource.to_string())));
+                continue;
+            }
+            self.expect(':')?;
+            let function = if self.peek_ident("static") {
+                self.pos += 1;
+                let mut function = self.function(name)?;
+                function.name = format!("[static]{}.{}", resource, function.name);
+                function
+            } else {
+                let mut function = self.function(name)?;
+                function.name = format!("[method]{}.{}", resource, function.name);
+                function.params.insert(0, ("self".to_string(), WitType::Borrow(resource.to_string())));
+                function
+            };
+            functions.push(function);
+        }
+        Ok(functions)
+    }
+
+    /// Parses `[async] func(params) [-> ty];` after the name and colon
+    fn function(&mut self, name: String) -> Result<ComponentFunction, WitError> {
+        let mut function = ComponentFunction::new(name);
+        if self.peek_ident("async") {
+            self.pos += 1;
+            function.is_async = true;
+        }
+        if self.ident()? != "func" {
+            self.pos -= 1;
+            return Err(self.error("expected `func`"));
+        }
+        function.params = self.params()?;
+        if self.next() == Some(Token::Arrow) {
+            if self.peek() == Some(&Token::Punct('(')) {
+                return Err(self.unsupported("named results"));
+            }
+            function.result = Some(self.ty()?);
+        } else {
+            self.pos -= 1;
+        }
+        self.expect(';')?;
+        Ok(function)
+    }
+
+    /// Parses `(name: ty, ...)`
+    fn params(&mut self) -> Result<Vec<(String, WitType)>, WitError> {
+        self.list('(', ')', |parser| {
+            let param = parser.ident()?;
+            parser.expect(':')?;
+            Ok((param, parser.ty()?))
+        })
+    }
+
+    /// Parses a delimited, comma-separated list allowing a trailing comma
+    fn list<T>(
+        &mut self,
+        open: char,
+        close: char,
+        mut item: impl FnMut(&mut Parser) -> Result<T, WitError>,
+    ) -> Result<Vec<T>, WitError> {
+        self.expect(open)?;
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            self.skip_gates()?;
+            items.push(item(self)?);
+            if !self.eat(',') {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn ty(&mut self) -> Result<WitType, WitError> {
+        let name = self.ident()?;
+        let boxed = |parser: &mut Parser| -> Result<Box<WitType>, WitError> {
+            parser.expect('<')?;
+            let inner = parser.ty()?;
+            parser.expect('>')?;
+            Ok(Box::new(inner))
+        };
+        Ok(match name.as_str() {
+            "bool" => WitType::Bool,
+            "u8" => WitType::U8,
+            "u16" => WitType::U16,
+            "u32" => WitType::U32,
+            "u64" => WitType::U64,
+            "s8" => WitType::S8,
+            "s16" => WitType::S16,
+            "s32" => WitType::S32,
+            "s64" => WitType::S64,
+            "f32" | "float32" => WitType::F32,
+            "f64" | "float64" => WitType::F64,
+            "char" => WitType::Char,
+            "string" => WitType::String,
+            "list" => {
+                let inner = boxed(self)?;
+                WitType::List(inner)
+            }
+            "option" => WitType::Option(boxed(self)?),
+            "result" => {
+                if !self.eat('<') {
+                    return Ok(WitType::Result { ok: None, err: None });
+                }
+                let ok = if self.eat('_') { None } else { Some(Box::new(self.ty()?)) };
+                let err = if self.eat(',') { Some(Box::new(self.ty()?)) } else { None };
+                self.expect('>')?;
+                WitType::Result { ok, err }
+            }
+            "future" | "stream" => {
+                let payload = if self.peek() == Some(&Token::Punct('<')) { Some(boxed(self)?) } else { None };
+                if name == "future" {
+                    WitType::Future(payload)
+                } else {
+                    WitType::Stream(payload)
+                }
+            }
+            "tuple" => return Err(self.unsupported("tuple types")),
+            "own" | "borrow" => {
+                self.expect('<')?;
+                let resource = self.ident()?;
+                self.expect('>')?;
+                if name == "own" {
+                    WitType::Own(resource)
+                } else {
+                    WitType::Borrow(resource)
+                }
+            }
+            _ => WitType::Named(name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUGIN: &str = r#"
+        package example:plugin@1.0.0;
+
+        interface types {
+            /// Severity of a log line
+            enum level { debug, info, warn }
+            record entry { level: level, message: string, tags: list<string> }
+        }
+
+        interface logger {
+            use types.{entry, level as severity};
+            flags channels { stdout, stderr }
+            log: func(entry: entry, to: channels);
+            threshold: func() -> severity;
+        }
+
+        interface greeter {
+            variant reply { text(string), silent, code(u32) }
+            greet: func(name: string, times: option<u8>) -> result<reply, string>;
+            @since(version = 1.0.0)
+            ping: async func();
+        }
+
+        world plugin {
+            import logger;
+            export greeter;
+            export host: interface {
+                stats: func() -> list<f64>;
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_parse_interfaces_and_types() {
+        let package = WitPackage::parse(PLUGIN).unwrap();
+        assert_eq!(package.id.as_ref().unwrap().to_string(), "example:plugin@1.0.0");
+        assert_eq!(package.qualified_name("greeter"), "example:plugin/greeter@1.0.0");
+
+        let logger = package.interface("logger").unwrap();
+        assert_eq!(logger.used_type("severity"), Some(("types", "level")));
+        assert_eq!(logger.type_def("channels").unwrap().kind, WitTypeDefKind::Flags(alloc::vec![
+            "stdout".to_string(),
+            "stderr".to_string(),
+        ]));
+        let greeter = package.interface("greeter").unwrap();
+        assert_eq!(
+            greeter.functions[0].to_string(),
+            "greet: func(name: string, times: option<u8>) -> result<reply, string>"
+        );
+        assert!(greeter.functions[1].is_async);
+        assert!(matches!(
+            &package.interface("types").unwrap().type_def("entry").unwrap().kind,
+            WitTypeDefKind::Record(fields) if fields[2].1 == WitType::List(Box::new(WitType::String))
+        ));
+    }
+
+    #[test]
+    fn test_world_resolves_implicit_imports() {
+        let package = WitPackage::parse(PLUGIN).unwrap();
+        let interfaces = package.world_interfaces("plugin").unwrap();
+        let imports: Vec<&str> = interfaces.imports.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(imports, ["example:plugin/logger@1.0.0", "example:plugin/types@1.0.0"]);
+        let exports: Vec<&str> = interfaces.exports.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(exports, ["example:plugin/greeter@1.0.0", "host"]);
+
+        let world = package.world("plugin").unwrap();
+        assert_eq!(world.exports[1].functions[0].to_string(), "stats: func() -> list<f64>");
+        assert_eq!(package.world("missing").unwrap_err(), WitError::UnknownWorld("missing".to_string()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            WitPackage::parse("interface a {\n  f: func() -> missing;\n}").unwrap_err(),
+            WitError::UnknownType { interface: "a".to_string(), name: "missing".to_string() }
+        );
+        assert_eq!(
+            WitPackage::parse("interface a {\n  f: func(x: tuple<u8, u8>);\n}").unwrap_err(),
+            WitError::Unsupported { line: 2, feature: "tuple types".to_string() }
+        );
+        assert!(matches!(
+            WitPackage::parse("interface a {\n  f: func(;\n}").unwrap_err(),
+            WitError::Parse { line: 2, .. }
+        ));
+        assert_eq!(
+            WitPackage::parse("interface a {}\ninterface a {}").unwrap_err(),
+            WitError::DuplicateName("a".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_resources() {
+        let package = WitPackage::parse(
+            "interface store {\n\
+               resource blob {\n\
+                 constructor(init: list<u8>);\n\
+                 read: func(n: u32) -> list<u8>;\n\
+                 merge: static func(lhs: blob, rhs: borrow<blob>) -> own<blob>;\n\
+               }\n\
+               open: func(name: string) -> blob;\n\
+             }",
+        )
+        .unwrap();
+        let store = package.interface("store").unwrap();
+        assert_eq!(store.type_def("blob").unwrap().kind, WitTypeDefKind::Resource);
+        let functions: Vec<String> = store.functions.iter().map(ToString::to_string).collect();
+        assert_eq!(functions, [
+            "[constructor]blob: func(init: list<u8>) -> own<blob>",
+            "[method]blob.read: func(self: borrow<blob>, n: u32) -> list<u8>",
+            "[static]blob.merge: func(lhs: blob, rhs: borrow<blob>) -> own<blob>",
+            "open: func(name: string) -> blob",
+        ]);
+        assert_eq!(
+            ResourceFunction::parse(&store.functions[1].name),
+            Some(ResourceFunction::Method { resource: "blob", name: "read" })
+        );
+        assert_eq!(ResourceFunction::parse("open"), None);
+
+        assert_eq!(
+            WitPackage::parse("interface a {\n  record r { x: u8 }\n  f: func(x: borrow<r>);\n}").unwrap_err(),
+            WitError::NotAResource("r".to_string())
+        );
+    }
+}
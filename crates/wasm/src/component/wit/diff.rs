@@ -0,0 +1,519 @@
+//! Interface evolution checks
+//!
+//! `diff` compares two versions of a package and classifies each change by
+//! its effect on existing users under the Component Model's subtyping
+//! rules. A component type is a subtype of another when it imports no more
+//! and exports no less, and an instance type when it exports no less;
+//! function and value types must match exactly. Changes keeping the new
+//! version a subtype of the old one are additive and the rest are breaking.
+//! Adding to an interface is additive for its callers but breaking for the
+//! hosts of worlds that import it, so those imports are reported too. A
+//! function, type, interface, or world removed while an identical one
+//! appears under a new name is reported as a rename.
+//!
+//! `check_evolution` holds the changes against the package versions:
+//! breaking changes and renames need a semver-incompatible bump (major, or
+//! minor before 1.0), additive ones a compatible bump.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use super::{WitInterface, WitPackage, WitTypeDef, WitTypeDefKind, WitWorld, WorldItem};
+use crate::component::ComponentFunction;
+
+/// Effect of a change on existing users
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    /// Existing users keep working
+    Additive,
+    /// An item moved to a new name without changing
+    Renamed,
+    /// Existing users may fail to link or type-check
+    Breaking,
+}
+
+impl core::fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ChangeKind::Additive => write!(f, "additive"),
+            ChangeKind::Renamed => write!(f, "renamed"),
+            ChangeKind::Breaking => write!(f, "breaking"),
+        }
+    }
+}
+
+/// One classified change between two package versions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitChange {
+    pub kind: ChangeKind,
+    /// `interface`, `interface#item`, or `world` the change applies to
+    pub item: String,
+    pub description: String,
+}
+
+impl core::fmt::Display for WitChange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} change to {}: {}", self.kind, self.item, self.description)
+    }
+}
+
+/// Changes between two versions of a package
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WitDiff {
+    pub changes: Vec<WitChange>,
+}
+
+impl WitDiff {
+    /// Most severe change, if anything changed
+    pub fn severity(&self) -> Option<ChangeKind> {
+        self.changes.iter().map(|change| change.kind).max()
+    }
+
+    /// Checks whether existing users may break
+    pub fn is_breaking(&self) -> bool {
+        self.severity().is_some_and(|kind| kind > ChangeKind::Additive)
+    }
+
+    fn push(&mut self, kind: ChangeKind, item: impl Into<String>, description: impl Into<String>) {
+        self.changes.push(WitChange { kind, item: item.into(), description: description.into() });
+    }
+}
+
+/// Part of a version a release increments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VersionBump {
+    None,
+    Patch,
+    Minor,
+    Major,
+}
+
+/// Interface evolution errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvolutionError {
+    /// A package declares no versioned id, or the two ids name different packages
+    PackageMismatch { old: String, new: String },
+    /// A version is not `major.minor.patch`
+    InvalidVersion(String),
+    /// The new version does not increment enough for its changes
+    InsufficientBump { from: String, to: String, required: VersionBump, changes: Vec<WitChange> },
+}
+
+impl core::fmt::Display for EvolutionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EvolutionError::PackageMismatch { old, new } => {
+                write!(f, "Cannot compare package {} with {}", old, new)
+            }
+            EvolutionError::InvalidVersion(version) => write!(f, "Invalid package version: {}", version),
+            EvolutionError::InsufficientBump { from, to, required, changes } => {
+                write!(f, "{} -> {} needs a {:?} version bump:", from, to, required)?;
+                for change in changes {
+                    write!(f, "\n  {}", change)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Classifies the changes from `old` to `new`
+pub fn diff(old: &WitPackage, new: &WitPackage) -> WitDiff {
+    let mut diff = WitDiff::default();
+    let same_interface = |a: &WitInterface, b: &WitInterface| {
+        a.uses == b.uses && a.types == b.types && a.functions == b.functions
+    };
+    for pairing in pair(&old.interfaces, &new.interfaces, |interface| &interface.name, same_interface) {
+        match pairing {
+            Pairing::Changed(old, new) => diff_interface(&mut diff, &old.name, old, new, false),
+            Pairing::Renamed(old, new) => diff.push(ChangeKind::Renamed, old.name.clone(), format!("renamed to {}", new.name)),
+            Pairing::Removed(old) => diff.push(ChangeKind::Breaking, old.name.clone(), "interface removed"),
+            Pairing::Added(new) => diff.push(ChangeKind::Additive, new.name.clone(), "interface added"),
+        }
+    }
+
+    // Hosts of an imported interface must provide whatever it gained
+    for world in new.worlds.iter().filter(|world| old.world_def(&world.name).is_some()) {
+        for item in &world.imports {
+            let WorldItem::Interface(name) = item else { continue };
+            let additions: Vec<&str> = diff.changes.iter()
+                .filter(|change| change.kind == ChangeKind::Additive)
+                .filter_map(|change| change.item.strip_prefix(name.as_str())?.strip_prefix('#'))
+                .collect();
+            if !additions.is_empty() {
+                let description = format!("hosts importing {} must now provide {}", name, additions.join(", "));
+                diff.push(ChangeKind::Breaking, world.name.clone(), description);
+            }
+        }
+    }
+
+    let same_world = |a: &WitWorld, b: &WitWorld| a.imports == b.imports && a.exports == b.exports;
+    for pairing in pair(&old.worlds, &new.worlds, |world| &world.name, same_world) {
+        match pairing {
+            Pairing::Changed(old, new) => diff_world(&mut diff, old, new),
+            Pairing::Renamed(old, new) => diff.push(ChangeKind::Renamed, old.name.clone(), format!("renamed to {}", new.name)),
+            Pairing::Removed(old) => diff.push(ChangeKind::Breaking, old.name.clone(), "world removed"),
+            Pairing::Added(new) => diff.push(ChangeKind::Additive, new.name.clone(), "world added"),
+        }
+    }
+    diff
+}
+
+/// Diffs the package versions and checks that their versions allow the changes
+pub fn check_evolution(old: &WitPackage, new: &WitPackage) -> Result<WitDiff, EvolutionError> {
+    let describe = |package: &WitPackage| package.id.as_ref().map_or_else(|| "an unnamed package".to_string(), ToString::to_string);
+    let (from, to) = match (&old.id, &new.id) {
+        (Some(old_id), Some(new_id)) if old_id.unversioned() == new_id.unversioned() => {
+            match (&old_id.version, &new_id.version) {
+                (Some(from), Some(to)) => (from.clone(), to.clone()),
+                _ => return Err(EvolutionError::PackageMismatch { old: describe(old), new: describe(new) }),
+            }
+        }
+        _ => return Err(EvolutionError::PackageMismatch { old: describe(old), new: describe(new) }),
+    };
+    let old_version = SemVer::parse(&from)?;
+    let new_version = SemVer::parse(&to)?;
+
+    let diff = diff(old, new);
+    let required = match diff.severity() {
+        None => VersionBump::None,
+        Some(ChangeKind::Additive) => old_version.compatible_bump(),
+        Some(_) => old_version.incompatible_bump(),
+    };
+    let bump = old_version.bump_to(&new_version);
+    if bump < required {
+        let severity = diff.severity();
+        return Err(EvolutionError::InsufficientBump {
+            from,
+            to,
+            required,
+            changes: diff.changes.into_iter().filter(|change| Some(change.kind) == severity).collect(),
+        });
+    }
+    Ok(diff)
+}
+
+/// Item of the old version matched against the new one
+enum Pairing<'a, T> {
+    /// Present in both under the same name
+    Changed(&'a T, &'a T),
+    /// Removed while an identical item was added
+    Renamed(&'a T, &'a T),
+    Removed(&'a T),
+    Added(&'a T),
+}
+
+/// Pairs items by name, then pairs leftover removals and additions that are identical
+fn pair<'a, T>(
+    old: &'a [T],
+    new: &'a [T],
+    name: impl Fn(&T) -> &String,
+    same: impl Fn(&T, &T) -> bool,
+) -> Vec<Pairing<'a, T>> {
+    let mut additions: Vec<&T> = new.iter().filter(|item| !old.iter().any(|o| name(o) == name(item))).collect();
+    let mut pairings = Vec::new();
+    for item in old {
+        let pairing = match new.iter().find(|n| name(n) == name(item)) {
+            Some(current) => Pairing::Changed(item, current),
+            None => match additions.iter().position(|addition| same(item, addition)) {
+                Some(index) => Pairing::Renamed(item, additions.remove(index)),
+                None => Pairing::Removed(item),
+            },
+        };
+        pairings.push(pairing);
+    }
+    pairings.extend(additions.into_iter().map(Pairing::Added));
+    pairings
+}
+
+/// Diffs an interface, flipping additions and removals for imported ones
+fn diff_interface(diff: &mut WitDiff, item: &str, old: &WitInterface, new: &WitInterface, imported: bool) {
+    let (gained, lost) = if imported {
+        (ChangeKind::Breaking, ChangeKind::Additive)
+    } else {
+        (ChangeKind::Additive, ChangeKind::Breaking)
+    };
+    let path = |name: &str| format!("{}#{}", item, name);
+
+    let used = |interface: &WitInterface| -> Vec<(String, String, String)> {
+        interface.uses.iter()
+            .flat_map(|used| used.names.iter().map(|(original, local)| (local.clone(), used.interface.clone(), original.clone())))
+            .collect()
+    };
+    let (old_used, new_used) = (used(old), used(new));
+    for (local, interface, original) in &old_used {
+        match new_used.iter().find(|(name, ..)| name == local) {
+            Some((_, i, o)) if i == interface && o == original => {}
+            Some((_, i, o)) => diff.push(ChangeKind::Breaking, path(local), format!("now uses {}.{{{}}}", i, o)),
+            None => diff.push(lost, path(local), "no longer used"),
+        }
+    }
+    for (local, interface, _) in new_used.iter().filter(|(local, ..)| !old_used.iter().any(|(name, ..)| name == local)) {
+        diff.push(gained, path(local), format!("uses a type from {}", interface));
+    }
+
+    for pairing in pair(&old.types, &new.types, |def| &def.name, |a, b| a.kind == b.kind) {
+        match pairing {
+            Pairing::Changed(old, new) if old.kind != new.kind => {
+                diff.push(ChangeKind::Breaking, path(&old.name), describe_type_change(old, new));
+            }
+            Pairing::Changed(..) => {}
+            Pairing::Renamed(old, new) => diff.push(ChangeKind::Renamed, path(&old.name), format!("renamed to {}", new.name)),
+            Pairing::Removed(old) => diff.push(lost, path(&old.name), "type removed"),
+            Pairing::Added(new) => diff.push(gained, path(&new.name), "type added"),
+        }
+    }
+
+    let same_signature = |a: &ComponentFunction, b: &ComponentFunction| {
+        a.params == b.params && a.result == b.result && a.is_async == b.is_async
+    };
+    for pairing in pair(&old.functions, &new.functions, |function| &function.name, same_signature) {
+        match pairing {
+            Pairing::Changed(old, new) if old != new => {
+                let description = format!("signature changed from {} to {}", signature(old), signature(new));
+                diff.push(ChangeKind::Breaking, path(&old.name), description);
+            }
+            Pairing::Changed(..) => {}
+            Pairing::Renamed(old, new) => diff.push(ChangeKind::Renamed, path(&old.name), format!("renamed to {}", new.name)),
+            Pairing::Removed(old) => diff.push(lost, path(&old.name), "function removed"),
+            Pairing::Added(new) => diff.push(gained, path(&new.name), "function added"),
+        }
+    }
+}
+
+fn diff_world(diff: &mut WitDiff, old: &WitWorld, new: &WitWorld) {
+    let key = |item: &WorldItem| match item {
+        WorldItem::Interface(name) => name.clone(),
+        WorldItem::Inline(interface) => interface.name.clone(),
+    };
+    for (old_items, new_items, imported) in [(&old.imports, &new.imports, true), (&old.exports, &new.exports, false)] {
+        let verb = if imported { "imports" } else { "exports" };
+        // A component importing less or exporting more is a subtype
+        let (gained, lost) = if imported {
+            (ChangeKind::Breaking, ChangeKind::Additive)
+        } else {
+            (ChangeKind::Additive, ChangeKind::Breaking)
+        };
+        for item in old_items {
+            match new_items.iter().find(|current| key(current) == key(item)) {
+                Some(WorldItem::Inline(current)) => match item {
+                    WorldItem::Inline(previous) => {
+                        diff_interface(diff, &format!("{}/{}", old.name, previous.name), previous, current, imported);
+                    }
+                    WorldItem::Interface(_) => {
+                        diff.push(ChangeKind::Breaking, old.name.clone(), format!("{} {} inline", verb, current.name));
+                    }
+                },
+                Some(WorldItem::Interface(current)) => {
+                    if matches!(item, WorldItem::Inline(_)) {
+                        diff.push(ChangeKind::Breaking, old.name.clone(), format!("{} interface {}", verb, current));
+                    }
+                }
+                None => diff.push(lost, old.name.clone(), format!("no longer {} {}", verb, key(item))),
+            }
+        }
+        for item in new_items.iter().filter(|item| !old_items.iter().any(|previous| key(previous) == key(item))) {
+            diff.push(gained, old.name.clone(), format!("{} {}", verb, key(item)));
+        }
+    }
+}
+
+/// Describes how a type definition changed
+fn describe_type_change(old: &WitTypeDef, new: &WitTypeDef) -> String {
+    let members = |def: &WitTypeDef| -> Option<(&'static str, Vec<String>)> {
+        match &def.kind {
+            WitTypeDefKind::Record(fields) => Some(("field", fields.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect())),
+            WitTypeDefKind::Variant(cases) => Some(("case", cases.iter().map(|(name, ty)| match ty {
+                Some(ty) => format!("{}({})", name, ty),
+                None => name.clone(),
+            }).collect())),
+            WitTypeDefKind::Enum(cases) => Some(("case", cases.clone())),
+            WitTypeDefKind::Flags(flags) => Some(("flag", flags.clone())),
+            _ => None,
+        }
+    };
+    match (members(old), members(new)) {
+        (Some((noun, before)), Some((_, after))) if core::mem::discriminant(&old.kind) == core::mem::discriminant(&new.kind) => {
+            let mut parts = Vec::new();
+            for member in after.iter().filter(|member| !before.contains(member)) {
+                parts.push(format!("adds {} {}", noun, member));
+            }
+            for member in before.iter().filter(|member| !after.contains(member)) {
+                parts.push(format!("removes {} {}", noun, member));
+            }
+            if parts.is_empty() {
+                parts.push(format!("reorders {}s", noun));
+            }
+            parts.join(", ")
+        }
+        _ => "definition changed".to_string(),
+    }
+}
+
+fn signature(function: &ComponentFunction) -> String {
+    let params: Vec<String> = function.params.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+    let prefix = if function.is_async { "async func" } else { "func" };
+    match &function.result {
+        Some(result) => format!("{}({}) -> {}", prefix, params.join(", "), result),
+        None => format!("{}({})", prefix, params.join(", ")),
+    }
+}
+
+/// `major.minor.patch`, ignoring pre-release and build suffixes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SemVer {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl SemVer {
+    fn parse(version: &str) -> Result<Self, EvolutionError> {
+        let invalid = || EvolutionError::InvalidVersion(version.to_string());
+        let core = version.split(['-', '+']).next().unwrap_or_default();
+        let mut parts = core.split('.').map(|part| part.parse::<u64>().map_err(|_| invalid()));
+        let version = Self {
+            major: parts.next().ok_or_else(invalid)??,
+            minor: parts.next().ok_or_else(invalid)??,
+            patch: parts.next().ok_or_else(invalid)??,
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(version)
+    }
+
+    /// Smallest bump that stays semver-compatible with this version
+    fn compatible_bump(&self) -> VersionBump {
+        if self.major == 0 {
+            VersionBump::Patch
+        } else {
+            VersionBump::Minor
+        }
+    }
+
+    /// Smallest bump that is semver-incompatible with this version
+    fn incompatible_bump(&self) -> VersionBump {
+        match (self.major, self.minor) {
+            (0, 0) => VersionBump::Patch,
+            (0, _) => VersionBump::Minor,
+            _ => VersionBump::Major,
+        }
+    }
+
+    /// Largest component `next` increments over this version, in the
+    /// terms of this version's compatibility rules
+    fn bump_to(&self, next: &SemVer) -> VersionBump {
+        let raised = if next.major != self.major {
+            (next.major > self.major, VersionBump::Major)
+        } else if next.minor != self.minor {
+            (next.minor > self.minor, VersionBump::Minor)
+        } else if next.patch != self.patch {
+            (next.patch > self.patch, VersionBump::Patch)
+        } else {
+            return VersionBump::None;
+        };
+        match raised {
+            (false, _) => VersionBump::None,
+            (true, bump) => bump,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = "package example:plugin@1.2.0;\n\
+        interface types {\n\
+            record point { x: s32, y: s32 }\n\
+            enum level { info, warn }\n\
+        }\n\
+        interface greeter {\n\
+            use types.{point};\n\
+            greet: func(name: string) -> string;\n\
+            locate: func(p: point) -> u32;\n\
+        }\n\
+        interface logger { log: func(message: string); }\n\
+        world plugin { import logger; export greeter; }";
+
+    fn evolve(version: &str, from: &str, to: &str) -> WitPackage {
+        WitPackage::parse(&V1.replace("1.2.0", version).replace(from, to)).unwrap()
+    }
+
+    fn kinds(diff: &WitDiff) -> Vec<(ChangeKind, &str)> {
+        diff.changes.iter().map(|change| (change.kind, change.item.as_str())).collect()
+    }
+
+    #[test]
+    fn test_diff_classifies_changes() {
+        let old = WitPackage::parse(V1).unwrap();
+        assert!(diff(&old, &old).changes.is_empty());
+
+        let added = evolve("1.3.0", "locate: func", "wave: func();\nlocate: func");
+        assert_eq!(kinds(&diff(&old, &added)), [(ChangeKind::Additive, "greeter#wave")]);
+
+        let changed = evolve("1.3.0", "record point { x: s32, y: s32 }", "record point { x: s32, y: s32, z: s32 }");
+        let changes = diff(&old, &changed);
+        assert_eq!(kinds(&changes), [(ChangeKind::Breaking, "types#point")]);
+        assert_eq!(changes.changes[0].description, "adds field z: s32");
+
+        let renamed = evolve("2.0.0", "greet: func", "hello: func");
+        assert_eq!(kinds(&diff(&old, &renamed)), [(ChangeKind::Renamed, "greeter#greet")]);
+
+        let retyped = evolve("2.0.0", "greet: func(name: string)", "greet: func(name: string, loud: bool)");
+        let changes = diff(&old, &retyped);
+        assert!(changes.is_breaking());
+        assert!(changes.changes[0].description.contains("to func(name: string, loud: bool) -> string"));
+    }
+
+    #[test]
+    fn test_diff_follows_world_subtyping() {
+        let old = WitPackage::parse(V1).unwrap();
+
+        // Exporting more is additive, importing more is not
+        let exports = evolve("1.3.0", "export greeter;", "export greeter; export types;");
+        assert_eq!(kinds(&diff(&old, &exports)), [(ChangeKind::Additive, "plugin")]);
+        let imports = evolve("2.0.0", "import logger;", "import logger; import types;");
+        assert_eq!(kinds(&diff(&old, &imports)), [(ChangeKind::Breaking, "plugin")]);
+        let fewer = evolve("1.3.0", "import logger; ", "");
+        assert_eq!(kinds(&diff(&old, &fewer)), [(ChangeKind::Additive, "plugin")]);
+
+        // Growing an imported interface obliges its hosts
+        let grown = evolve("1.3.0", "log: func(message: string);", "log: func(message: string); flush: func();");
+        let changes = diff(&old, &grown);
+        assert_eq!(kinds(&changes), [(ChangeKind::Additive, "logger#flush"), (ChangeKind::Breaking, "plugin")]);
+        assert_eq!(changes.changes[1].description, "hosts importing logger must now provide flush");
+    }
+
+    #[test]
+    fn test_check_evolution_enforces_semver() {
+        let old = WitPackage::parse(V1).unwrap();
+        let added = |version| evolve(version, "locate: func", "wave: func();\nlocate: func");
+        assert!(check_evolution(&old, &added("1.3.0")).is_ok());
+        assert!(check_evolution(&old, &evolve("1.2.1", "", "")).is_ok());
+        assert!(matches!(
+            check_evolution(&old, &added("1.2.1")),
+            Err(EvolutionError::InsufficientBump { required: VersionBump::Minor, .. })
+        ));
+
+        let removed = |version| evolve(version, "locate: func(p: point) -> u32;", "");
+        assert!(check_evolution(&old, &removed("2.0.0")).is_ok());
+        let err = check_evolution(&old, &removed("1.9.0")).unwrap_err();
+        assert!(err.to_string().contains("needs a Major version bump:\n  breaking change to greeter#locate: function removed"));
+
+        // Before 1.0 the minor version is the compatibility boundary
+        let zero = evolve("0.2.0", "", "");
+        assert!(check_evolution(&zero, &removed("0.3.0")).is_ok());
+        assert!(check_evolution(&zero, &added("0.2.1")).is_ok());
+        assert!(check_evolution(&zero, &removed("0.2.1")).is_err());
+
+        let unversioned = WitPackage::parse(&V1.replace("@1.2.0", "")).unwrap();
+        assert!(matches!(check_evolution(&unversioned, &old), Err(EvolutionError::PackageMismatch { .. })));
+        assert_eq!(
+            check_evolution(&old, &evolve("1.3", "", "")).unwrap_err(),
+            EvolutionError::InvalidVersion("1.3".to_string())
+        );
+    }
+}
@@ -0,0 +1,997 @@
+//! WIT package dependency resolution
+//!
+//! Build scripts describe the packages a component's WIT depends on in a
+//! `deps.toml` manifest, resolve them through a `PackageSource` that knows
+//! how to read local paths and talk to registries, and write the resulting
+//! `wit.lock` pins next to the manifest. Later builds pass the lockfile back
+//! so registry versions stay pinned and fetched contents are checked against
+//! their recorded digests. Resolved packages are kept in a `PackageCache`
+//! keyed by digest, so pinned packages are not fetched again. When a
+//! dependency moves to a new pin, `Resolver::check_upgrades` diffs the old
+//! and new WIT and rejects changes the version bump does not allow.
+//!
+//! The manifest is a TOML subset:
+//!
+//! ```toml
+//! [dependencies]
+//! "wasi:io" = { path = "wit/deps/io" }
+//! "wasi:http" = "0.2.0"
+//! "acme:kv" = { version = "1.2.0", registry = "registry.acme.dev" }
+//! "acme:store" = { oci = "ghcr.io/acme/store-wit:1.0.0" }
+//! ```
+//!
+//! A bare version string resolves against the curation registry.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::wit::diff::{check_evolution, WitDiff};
+use super::wit::WitPackage;
+
+/// Registry that bare version requirements resolve against
+pub const CURATION_REGISTRY: &str = "wa.dev";
+
+/// Name of the manifest listing a package's dependencies
+pub const MANIFEST_FILE: &str = "deps.toml";
+
+/// Name of the lockfile written next to the manifest
+pub const LOCKFILE_FILE: &str = "wit.lock";
+
+/// WIT dependency resolution errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitDepsError {
+    /// Malformed `deps.toml`
+    Manifest { line: usize, message: String },
+    /// Malformed `wit.lock`
+    Lockfile { line: usize, message: String },
+    /// Package name not of the form `namespace:name[@version]`
+    InvalidPackageId(String),
+    /// Source could not provide a package
+    Fetch { package: String, message: String },
+    /// Fetched WIT declares no package, or a different one
+    PackageMismatch { expected: String, found: String },
+    /// Package references another that no manifest provides
+    MissingDependency { package: String, dependency: String },
+    /// Fetched contents differ from the pinned digest
+    DigestMismatch { package: String, expected: String, found: String },
+    /// One package resolved to two different versions
+    VersionConflict { package: String, versions: (String, String) },
+    /// A package upgrade makes changes its version bump does not allow
+    Evolution { package: String, message: String },
+}
+
+impl core::fmt::Display for WitDepsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WitDepsError::Manifest { line, message } => write!(f, "{} line {}: {}", MANIFEST_FILE, line, message),
+            WitDepsError::Lockfile { line, message } => write!(f, "{} line {}: {}", LOCKFILE_FILE, line, message),
+            WitDepsError::InvalidPackageId(id) => write!(f, "Invalid WIT package id: {}", id),
+            WitDepsError::Fetch { package, message } => write!(f, "Failed to fetch {}: {}", package, message),
+            WitDepsError::PackageMismatch { expected, found } => {
+                write!(f, "Expected package {}, found {}", expected, found)
+            }
+            WitDepsError::MissingDependency { package, dependency } => {
+                write!(f, "{} depends on {}, which is not in any manifest", package, dependency)
+            }
+            WitDepsError::DigestMismatch { package, expected, found } => {
+                write!(f, "Digest mismatch for {}: locked {}, fetched {}", package, expected, found)
+            }
+            WitDepsError::VersionConflict { package, versions } => {
+                write!(f, "{} resolved to both {} and {}", package, versions.0, versions.1)
+            }
+            WitDepsError::Evolution { package, message } => write!(f, "Unsafe upgrade of {}: {}", package, message),
+        }
+    }
+}
+
+/// Package identifier, `namespace:name[@version]`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PackageId {
+    pub namespace: String,
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl PackageId {
+    /// Parses `namespace:name` with an optional `@version`
+    pub fn parse(id: &str) -> Result<Self, WitDepsError> {
+        let invalid = || WitDepsError::InvalidPackageId(id.to_string());
+        let (path, version) = match id.split_once('@') {
+            Some((path, version)) if !version.is_empty() => (path, Some(version.to_string())),
+            Some(_) => return Err(invalid()),
+            None => (id, None),
+        };
+        let (namespace, name) = path.split_once(':').ok_or_else(invalid)?;
+        let valid = |part: &str| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        };
+        if !valid(namespace) || !valid(name) {
+            return Err(invalid());
+        }
+        Ok(Self { namespace: namespace.to_string(), name: name.to_string(), version })
+    }
+
+    /// Gets `namespace:name` without the version
+    pub fn unversioned(&self) -> String {
+        format!("{}:{}", self.namespace, self.name)
+    }
+}
+
+impl core::fmt::Display for PackageId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.name)?;
+        if let Some(version) = &self.version {
+            write!(f, "@{}", version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a dependency comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencySource {
+    /// Directory of WIT files, relative to the manifest
+    Path(String),
+    /// Package registry, with a version requirement
+    Registry { registry: String, version: String },
+    /// OCI artifact reference
+    Oci(String),
+}
+
+impl DependencySource {
+    /// Gets the lockfile form of the source
+    ///
+    /// Registry sources drop the requirement, since the pin is the
+    /// resolved version in the package id.
+    pub fn lock_key(&self) -> String {
+        match self {
+            DependencySource::Path(path) => format!("path+{}", path),
+            DependencySource::Registry { registry, .. } => format!("registry+{}", registry),
+            DependencySource::Oci(reference) => format!("oci+{}", reference),
+        }
+    }
+}
+
+impl core::fmt::Display for DependencySource {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DependencySource::Registry { registry, version } => write!(f, "registry+{}@{}", registry, version),
+            other => f.write_str(&other.lock_key()),
+        }
+    }
+}
+
+/// Dependency declared in a manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    /// Package the dependency provides, without a version
+    pub package: PackageId,
+    pub source: DependencySource,
+}
+
+/// Parsed `deps.toml`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepsManifest {
+    pub dependencies: Vec<Dependency>,
+}
+
+impl DepsManifest {
+    /// Parses a `deps.toml` manifest
+    pub fn parse(source: &str) -> Result<Self, WitDepsError> {
+        let mut manifest = Self::default();
+        let mut in_dependencies = false;
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: &str| WitDepsError::Manifest { line: index + 1, message: message.to_string() };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_dependencies = line == "[dependencies]";
+                continue;
+            }
+            if !in_dependencies {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected `name = source`"))?;
+            let package = PackageId::parse(unquote(key.trim()).ok_or_else(|| error("dependency names must be quoted"))?)
+                .map_err(|_| error("dependency names are `namespace:name`"))?;
+            if package.version.is_some() {
+                return Err(error("put the version in the source, not the name"));
+            }
+            if manifest.dependencies.iter().any(|dep| dep.package == package) {
+                return Err(error("duplicate dependency"));
+            }
+
+            let value = value.trim();
+            let source = if value.starts_with('{') {
+                let table = parse_inline_table(value).ok_or_else(|| error("malformed inline table"))?;
+                match (table.get("path"), table.get("version"), table.get("oci")) {
+                    (Some(path), None, None) => DependencySource::Path(path.clone()),
+                    (None, Some(version), None) => DependencySource::Registry {
+                        registry: table.get("registry").cloned().unwrap_or_else(|| CURATION_REGISTRY.to_string()),
+                        version: version.clone(),
+                    },
+                    (None, None, Some(reference)) => DependencySource::Oci(reference.clone()),
+                    _ => return Err(error("expected exactly one of `path`, `version`, or `oci`")),
+                }
+            } else {
+                DependencySource::Registry {
+                    registry: CURATION_REGISTRY.to_string(),
+                    version: unquote(value).ok_or_else(|| error("expected a version string"))?.to_string(),
+                }
+            };
+            manifest.dependencies.push(Dependency { package, source });
+        }
+        Ok(manifest)
+    }
+}
+
+/// WIT source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitFile {
+    pub name: String,
+    pub contents: String,
+}
+
+impl WitFile {
+    pub fn new(name: impl Into<String>, contents: impl Into<String>) -> Self {
+        Self { name: name.into(), contents: contents.into() }
+    }
+}
+
+/// Fetches packages for the resolver
+///
+/// Build scripts implement this over the filesystem, registry HTTP APIs,
+/// and OCI pulls; the resolver only sees the returned files. A package may
+/// include its own `deps.toml` to declare transitive dependencies.
+pub trait PackageSource {
+    fn fetch(&mut self, package: &PackageId, source: &DependencySource) -> Result<Vec<WitFile>, WitDepsError>;
+}
+
+/// Resolved package contents, keyed by digest
+#[derive(Debug, Clone, Default)]
+pub struct PackageCache {
+    packages: BTreeMap<String, Vec<WitFile>>,
+}
+
+impl PackageCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets cached files by digest
+    pub fn get(&self, digest: &str) -> Option<&[WitFile]> {
+        self.packages.get(digest).map(Vec::as_slice)
+    }
+
+    /// Caches files, returning their digest
+    pub fn insert(&mut self, files: Vec<WitFile>) -> String {
+        let digest = package_digest(&files);
+        self.packages.insert(digest.clone(), files);
+        digest
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+}
+
+/// Pinned package in a lockfile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedPackage {
+    /// Package id including the resolved version
+    pub id: PackageId,
+    /// Lockfile form of the source, see `DependencySource::lock_key`
+    pub source: String,
+    /// `sha256:` digest of the package files
+    pub digest: String,
+}
+
+/// Parsed or generated `wit.lock`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lockfile {
+    pub packages: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+    /// Parses a lockfile written by `Lockfile`'s `Display`
+    pub fn parse(source: &str) -> Result<Self, WitDepsError> {
+        let mut lockfile = Self::default();
+        let mut current: Option<(usize, BTreeMap<String, String>)> = None;
+        for (index, line) in source.lines().enumerate().chain(core::iter::once((usize::MAX, "[[package]]"))) {
+            let error = |line: usize, message: &str| WitDepsError::Lockfile { line: line + 1, message: message.to_string() };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[[package]]" {
+                if let Some((start, fields)) = current.take() {
+                    let field = |key: &str| fields.get(key).cloned().ok_or_else(|| error(start, "incomplete package entry"));
+                    let id = PackageId::parse(&field("name")?).map_err(|_| error(start, "invalid package name"))?;
+                    if id.version.is_none() && !field("source")?.starts_with("path+") {
+                        return Err(error(start, "registry packages must be pinned to a version"));
+                    }
+                    lockfile.packages.push(LockedPackage { id, source: field("source")?, digest: field("digest")? });
+                }
+                current = Some((index, BTreeMap::new()));
+                continue;
+            }
+            let (_, fields) = current.as_mut().ok_or_else(|| error(index, "expected `[[package]]`"))?;
+            let (key, value) = line.split_once('=').ok_or_else(|| error(index, "expected `key = value`"))?;
+            let value = unquote(value.trim()).ok_or_else(|| error(index, "expected a string"))?;
+            fields.insert(key.trim().to_string(), value.to_string());
+        }
+        Ok(lockfile)
+    }
+
+    /// Finds the pin for a package by namespace and name
+    pub fn find(&self, package: &PackageId) -> Option<&LockedPackage> {
+        self.packages.iter()
+            .find(|locked| locked.id.namespace == package.namespace && locked.id.name == package.name)
+    }
+}
+
+impl core::fmt::Display for Lockfile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "# Generated by WasmRust. Do not edit.")?;
+        for package in &self.packages {
+            writeln!(f)?;
+            writeln!(f, "[[package]]")?;
+            writeln!(f, "name = \"{}\"", package.id)?;
+            writeln!(f, "source = \"{}\"", package.source)?;
+            writeln!(f, "digest = \"{}\"", package.digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Package after resolution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedPackage {
+    pub id: PackageId,
+    pub source: DependencySource,
+    pub digest: String,
+    pub files: Vec<WitFile>,
+    /// Packages the WIT references, without versions
+    pub dependencies: Vec<PackageId>,
+}
+
+/// Every resolved package, dependencies before dependents
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    pub packages: Vec<ResolvedPackage>,
+}
+
+impl Resolution {
+    /// Gets a resolved package by namespace and name
+    pub fn package(&self, namespace: &str, name: &str) -> Option<&ResolvedPackage> {
+        self.packages.iter().find(|package| package.id.namespace == namespace && package.id.name == name)
+    }
+
+    /// Produces the lockfile pinning this resolution
+    pub fn lockfile(&self) -> Lockfile {
+        let mut packages: Vec<LockedPackage> = self.packages.iter()
+            .map(|package| LockedPackage {
+                id: package.id.clone(),
+                source: package.source.lock_key(),
+                digest: package.digest.clone(),
+            })
+            .collect();
+        packages.sort_by(|a, b| a.id.cmp(&b.id));
+        Lockfile { packages }
+    }
+}
+
+/// Resolves WIT dependencies through a `PackageSource`
+pub struct Resolver<S> {
+    source: S,
+    cache: PackageCache,
+    lockfile: Option<Lockfile>,
+}
+
+impl<S: PackageSource> Resolver<S> {
+    pub fn new(source: S) -> Self {
+        Self { source, cache: PackageCache::new(), lockfile: None }
+    }
+
+    /// Seeds the resolver with previously cached packages
+    pub fn with_cache(mut self, cache: PackageCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Pins registry versions and digests to an existing lockfile
+    pub fn with_lockfile(mut self, lockfile: Lockfile) -> Self {
+        self.lockfile = Some(lockfile);
+        self
+    }
+
+    /// Gets the package cache, for persisting between builds
+    pub fn cache(&self) -> &PackageCache {
+        &self.cache
+    }
+
+    /// Resolves a root manifest and the WIT that depends on it
+    ///
+    /// `root` is the component's own WIT, checked for references to
+    /// packages the manifests do not provide.
+    pub fn resolve(&mut self, manifest: &DepsManifest, root: &[WitFile]) -> Result<Resolution, WitDepsError> {
+        let mut resolved: BTreeMap<String, ResolvedPackage> = BTreeMap::new();
+        let mut requested: BTreeMap<String, DependencySource> = BTreeMap::new();
+        let mut order = Vec::new();
+        let mut pending: Vec<(String, Dependency)> = manifest.dependencies.iter()
+            .rev()
+            .map(|dep| ("root".to_string(), dep.clone()))
+            .collect();
+
+        while let Some((requester, dependency)) = pending.pop() {
+            let key = dependency.package.unversioned();
+            if let Some(existing) = requested.get(&key) {
+                if *existing != dependency.source {
+                    return Err(WitDepsError::VersionConflict {
+                        package: key,
+                        versions: (existing.to_string(), dependency.source.to_string()),
+                    });
+                }
+                continue;
+            }
+            requested.insert(key.clone(), dependency.source.clone());
+
+            let package = self.resolve_one(&dependency).map_err(|err| match err {
+                WitDepsError::Fetch { package, message } => {
+                    WitDepsError::Fetch { package, message: format!("{} (required by {})", message, requester) }
+                }
+                err => err,
+            })?;
+            if let Some(nested) = package.files.iter().find(|file| file.name == MANIFEST_FILE) {
+                let nested = DepsManifest::parse(&nested.contents)?;
+                for dep in nested.dependencies.into_iter().rev() {
+                    pending.push((package.id.to_string(), dep));
+                }
+            }
+            order.push(key.clone());
+            resolved.insert(key, package);
+        }
+
+        for package in resolved.values() {
+            check_references(&package.id.to_string(), &package.dependencies, &resolved)?;
+        }
+        let root_refs = wit_references(root, None);
+        check_references("root", &root_refs, &resolved)?;
+
+        // Dependencies before dependents, so packages can be loaded in order
+        let mut packages = Vec::with_capacity(order.len());
+        let mut placed = BTreeMap::new();
+        for key in &order {
+            place(key, &resolved, &mut placed, &mut packages);
+        }
+        Ok(Resolution { packages })
+    }
+
+    /// Checks packages re-pinned since `previous` against their old contents
+    ///
+    /// Each registry or OCI package whose digest changed is diffed against
+    /// the pinned contents, taken from the cache or, for registries,
+    /// fetched again at the pinned version. Path dependencies are not
+    /// versioned and are skipped.
+    pub fn check_upgrades(
+        &mut self,
+        resolution: &Resolution,
+        previous: &Lockfile,
+    ) -> Result<Vec<(PackageId, WitDiff)>, WitDepsError> {
+        let mut upgrades = Vec::new();
+        for package in &resolution.packages {
+            let Some(locked) = previous.find(&package.id) else { continue };
+            if matches!(package.source, DependencySource::Path(_))
+                || locked.source != package.source.lock_key()
+                || locked.digest == package.digest
+            {
+                continue;
+            }
+            let evolution = |message: String| WitDepsError::Evolution { package: package.id.to_string(), message };
+
+            let old_files = match (self.cache.get(&locked.digest), &package.source) {
+                (Some(files), _) => files.to_vec(),
+                (None, DependencySource::Registry { registry, .. }) => {
+                    let version = locked.id.version.clone().unwrap_or_default();
+                    let source = DependencySource::Registry { registry: registry.clone(), version };
+                    let files = self.source.fetch(&locked.id, &source)?;
+                    let digest = package_digest(&files);
+                    if digest != locked.digest {
+                        return Err(WitDepsError::DigestMismatch {
+                            package: locked.id.to_string(),
+                            expected: locked.digest.clone(),
+                            found: digest,
+                        });
+                    }
+                    self.cache.insert(files.clone());
+                    files
+                }
+                (None, _) => return Err(evolution(format!("pinned contents {} are not cached", locked.digest))),
+            };
+
+            let old = parse_package(&old_files).map_err(|e| evolution(format!("{}: {}", locked.id, e)))?;
+            let new = parse_package(&package.files).map_err(|e| evolution(format!("{}: {}", package.id, e)))?;
+            let diff = check_evolution(&old, &new).map_err(|e| evolution(e.to_string()))?;
+            upgrades.push((package.id.clone(), diff));
+        }
+        Ok(upgrades)
+    }
+
+    fn resolve_one(&mut self, dependency: &Dependency) -> Result<ResolvedPackage, WitDepsError> {
+        let locked = self.lockfile.as_ref()
+            .and_then(|lockfile| lockfile.find(&dependency.package))
+            .filter(|locked| locked.source == dependency.source.lock_key())
+            .cloned();
+
+        let mut source = dependency.source.clone();
+        let mut request = dependency.package.clone();
+        if let (Some(locked), DependencySource::Registry { version, .. }) = (&locked, &mut source) {
+            *version = locked.id.version.clone().unwrap_or_default();
+            request.version = locked.id.version.clone();
+        }
+
+        // Path dependencies are read each build so local edits are picked up
+        let cached = match (&locked, &source) {
+            (Some(locked), DependencySource::Registry { .. } | DependencySource::Oci(_)) => {
+                self.cache.get(&locked.digest).map(<[WitFile]>::to_vec)
+            }
+            _ => None,
+        };
+        let files = match cached {
+            Some(files) => files,
+            None => self.source.fetch(&request, &source)?,
+        };
+
+        let digest = package_digest(&files);
+        if let Some(locked) = &locked {
+            if !matches!(source, DependencySource::Path(_)) && locked.digest != digest {
+                return Err(WitDepsError::DigestMismatch {
+                    package: locked.id.to_string(),
+                    expected: locked.digest.clone(),
+                    found: digest,
+                });
+            }
+        }
+
+        let declared = declared_package(&files).ok_or_else(|| WitDepsError::PackageMismatch {
+            expected: dependency.package.to_string(),
+            found: "no package declaration".to_string(),
+        })?;
+        if declared.namespace != dependency.package.namespace || declared.name != dependency.package.name {
+            return Err(WitDepsError::PackageMismatch {
+                expected: dependency.package.to_string(),
+                found: declared.to_string(),
+            });
+        }
+
+        let dependencies = wit_references(&files, Some(&declared));
+        self.cache.insert(files.clone());
+        Ok(ResolvedPackage { id: declared, source, digest, files, dependencies })
+    }
+}
+
+fn check_references(
+    package: &str,
+    references: &[PackageId],
+    resolved: &BTreeMap<String, ResolvedPackage>,
+) -> Result<(), WitDepsError> {
+    for reference in references {
+        let Some(target) = resolved.get(&reference.unversioned()) else {
+            return Err(WitDepsError::MissingDependency { package: package.to_string(), dependency: reference.to_string() });
+        };
+        if let (Some(wanted), Some(found)) = (&reference.version, &target.id.version) {
+            if wanted != found {
+                return Err(WitDepsError::VersionConflict {
+                    package: reference.unversioned(),
+                    versions: (wanted.clone(), found.clone()),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn place(
+    key: &str,
+    resolved: &BTreeMap<String, ResolvedPackage>,
+    placed: &mut BTreeMap<String, bool>,
+    out: &mut Vec<ResolvedPackage>,
+) {
+    // Marking before recursing also stops at dependency cycles
+    if placed.insert(key.to_string(), true).is_some() {
+        return;
+    }
+    let package = &resolved[key];
+    for dependency in &package.dependencies {
+        place(&dependency.unversioned(), resolved, placed, out);
+    }
+    out.push(package.clone());
+}
+
+/// Parses package files in name order
+fn parse_package(files: &[WitFile]) -> Result<WitPackage, super::wit::WitError> {
+    let mut sorted: Vec<&WitFile> = files.iter().filter(|file| file.name.ends_with(".wit")).collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut package = WitPackage::default();
+    for file in sorted {
+        package.extend(&file.contents)?;
+    }
+    Ok(package)
+}
+
+/// Gets the `package` declaration of a set of WIT files
+pub fn declared_package(files: &[WitFile]) -> Option<PackageId> {
+    files.iter()
+        .filter(|file| file.name.ends_with(".wit"))
+        .flat_map(|file| file.contents.lines())
+        .find_map(|line| {
+            let rest = strip_comment(line).trim().strip_prefix("package ")?;
+            PackageId::parse(rest.trim_end_matches(';').trim()).ok()
+        })
+}
+
+/// Finds packages referenced by `use`, `import`, `export`, and `include`
+///
+/// References to `own`, the files' own package, are skipped.
+pub fn wit_references(files: &[WitFile], own: Option<&PackageId>) -> Vec<PackageId> {
+    let mut references: Vec<PackageId> = Vec::new();
+    let statements = files.iter()
+        .filter(|file| file.name.ends_with(".wit"))
+        .flat_map(|file| file.contents.lines())
+        .flat_map(|line| strip_comment(line).split([';', '{', '}']));
+    for statement in statements {
+        let statement = statement.trim();
+        let Some(path) = ["use ", "import ", "export ", "include "].iter().find_map(|kw| statement.strip_prefix(kw)) else {
+            continue;
+        };
+        // `import name: interface` names a path after the colon
+        let path = path.split_once(": ").map_or(path, |(_, path)| path).trim();
+        let Some((package, rest)) = path.split_once('/') else { continue };
+        let version = rest.split_once('@').map(|(_, version)| {
+            let end = version.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')));
+            version[..end.unwrap_or(version.len())].trim_end_matches('.')
+        });
+        let id = match version {
+            Some(version) => format!("{}@{}", package, version),
+            None => package.to_string(),
+        };
+        let Ok(id) = PackageId::parse(&id) else { continue };
+        let is_own = own.is_some_and(|own| own.namespace == id.namespace && own.name == id.name);
+        if !is_own && !references.contains(&id) {
+            references.push(id);
+        }
+    }
+    references
+}
+
+/// Digest of package files, independent of file order
+pub fn package_digest(files: &[WitFile]) -> String {
+    let mut sorted: Vec<&WitFile> = files.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut hasher = Sha256::new();
+    for file in sorted {
+        hasher.update(file.name.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(&(file.contents.len() as u64).to_le_bytes());
+        hasher.update(file.contents.as_bytes());
+    }
+    let mut digest = String::from("sha256:");
+    for byte in hasher.finish() {
+        let _ = write!(digest, "{:02x}", byte);
+    }
+    digest
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            '/' if !in_string && line[i..].starts_with("//") => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> Option<&str> {
+    value.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn parse_inline_table(value: &str) -> Option<BTreeMap<String, String>> {
+    let inner = value.strip_prefix('{')?.strip_suffix('}')?;
+    let mut table = BTreeMap::new();
+    for entry in inner.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (key, value) = entry.split_once('=')?;
+        table.insert(key.trim().to_string(), unquote(value.trim())?.to_string());
+    }
+    Some(table)
+}
+
+/// Minimal SHA-256 for package digests
+struct Sha256 {
+    state: [u32; 8],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        for &byte in data {
+            self.buffer.push(byte);
+            if self.buffer.len() == 64 {
+                let block = core::mem::take(&mut self.buffer);
+                self.compress(&block);
+                self.buffer = block;
+                self.buffer.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.buffer.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// In-memory source keyed by lock key and requested version
+    #[derive(Default)]
+    struct MemorySource {
+        packages: BTreeMap<String, Vec<WitFile>>,
+        fetches: usize,
+    }
+
+    impl MemorySource {
+        fn with(mut self, key: &str, files: Vec<WitFile>) -> Self {
+            self.packages.insert(key.to_string(), files);
+            self
+        }
+    }
+
+    impl PackageSource for &mut MemorySource {
+        fn fetch(&mut self, package: &PackageId, source: &DependencySource) -> Result<Vec<WitFile>, WitDepsError> {
+            self.fetches += 1;
+            let key = match source {
+                DependencySource::Registry { registry, version } => format!("{}/{}@{}", registry, package.unversioned(), version),
+                other => other.lock_key(),
+            };
+            self.packages.get(&key).cloned().ok_or_else(|| WitDepsError::Fetch {
+                package: package.to_string(),
+                message: "not found".to_string(),
+            })
+        }
+    }
+
+    fn sources() -> MemorySource {
+        MemorySource::default()
+            .with("wa.dev/wasi:io@0.2.0", alloc::vec![WitFile::new(
+                "streams.wit",
+                "package wasi:io@0.2.0;\ninterface streams {}\n",
+            )])
+            .with("wa.dev/wasi:io@0.2.1", alloc::vec![WitFile::new(
+                "streams.wit",
+                "package wasi:io@0.2.1;\ninterface streams {}\n",
+            )])
+            .with("oci+ghcr.io/acme/store:1.0", alloc::vec![
+                WitFile::new("store.wit", "package acme:store@1.0.0;\n\ninterface kv {\n  use wasi:io/streams@0.2.0.{input-stream};\n}\n"),
+                WitFile::new(MANIFEST_FILE, "[dependencies]\n\"wasi:io\" = \"0.2.0\"\n"),
+            ])
+            .with("path+wit/deps/local", alloc::vec![WitFile::new("local.wit", "package local:util;\ninterface log {}\n")])
+    }
+
+    fn manifest() -> DepsManifest {
+        DepsManifest::parse(
+            "# component dependencies\n[package]\nname = \"ignored\"\n\n[dependencies]\n\
+             \"acme:store\" = { oci = \"ghcr.io/acme/store:1.0\" }\n\
+             \"local:util\" = { path = \"wit/deps/local\" } # vendored\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_manifest_and_references() {
+        let manifest = manifest();
+        assert_eq!(manifest.dependencies.len(), 2);
+        assert_eq!(manifest.dependencies[0].source, DependencySource::Oci("ghcr.io/acme/store:1.0".to_string()));
+
+        let registry = DepsManifest::parse("[dependencies]\n\"acme:kv\" = { version = \"1.2.0\", registry = \"registry.acme.dev\" }").unwrap();
+        assert_eq!(registry.dependencies[0].source, DependencySource::Registry {
+            registry: "registry.acme.dev".to_string(),
+            version: "1.2.0".to_string(),
+        });
+        assert!(matches!(
+            DepsManifest::parse("[dependencies]\n\"wasi:io@0.2.0\" = \"0.2.0\""),
+            Err(WitDepsError::Manifest { line: 2, .. })
+        ));
+        assert!(matches!(
+            DepsManifest::parse("[dependencies]\n\"wasi:io\" = { path = \"a\", oci = \"b\" }"),
+            Err(WitDepsError::Manifest { line: 2, .. })
+        ));
+
+        let root = [WitFile::new("world.wit", "package my:app;\nworld app {\n  import wasi:io/streams@0.2.0;\n  export my:app/run;\n  import store: acme:store/kv;\n}\n")];
+        let own = declared_package(&root).unwrap();
+        let refs: Vec<String> = wit_references(&root, Some(&own)).iter().map(ToString::to_string).collect();
+        assert_eq!(refs, ["wasi:io@0.2.0", "acme:store"]);
+
+        // FIPS 180-2 test vector
+        let mut hasher = Sha256::new();
+        hasher.update(b"abc");
+        assert_eq!(hasher.finish()[..4], [0xba, 0x78, 0x16, 0xbf]);
+    }
+
+    #[test]
+    fn test_resolve_transitive_and_lock() {
+        let mut source = sources();
+        let root = [WitFile::new("world.wit", "package my:app;\nworld app { import acme:store/kv; import local:util/log; }\n")];
+        let mut resolver = Resolver::new(&mut source);
+        let resolution = resolver.resolve(&manifest(), &root).unwrap();
+
+        let order: Vec<String> = resolution.packages.iter().map(|p| p.id.to_string()).collect();
+        assert_eq!(order, ["wasi:io@0.2.0", "acme:store@1.0.0", "local:util"]);
+
+        let lockfile = resolution.lockfile();
+        let text = lockfile.to_string();
+        assert!(text.contains("[[package]]\nname = \"wasi:io@0.2.0\"\nsource = \"registry+wa.dev\"\ndigest = \"sha256:"));
+        assert_eq!(Lockfile::parse(&text).unwrap(), lockfile);
+
+        let conflicting = DepsManifest::parse("[dependencies]\n\"acme:store\" = { oci = \"ghcr.io/acme/store:1.0\" }\n\"wasi:io\" = \"0.2.1\"\n").unwrap();
+        assert!(matches!(
+            Resolver::new(&mut source).resolve(&conflicting, &[]),
+            Err(WitDepsError::VersionConflict { package, .. }) if package == "wasi:io"
+        ));
+
+        // Unknown references are reported against the package that made them
+        let missing = [WitFile::new("world.wit", "package my:app;\nworld app { import wasi:http/handler; }\n")];
+        assert_eq!(
+            Resolver::new(&mut source).resolve(&manifest(), &missing).unwrap_err(),
+            WitDepsError::MissingDependency { package: "root".to_string(), dependency: "wasi:http".to_string() }
+        );
+    }
+
+    #[test]
+    fn test_lockfile_pins_versions_and_digests() {
+        let mut source = sources();
+        let requirement = DepsManifest::parse("[dependencies]\n\"wasi:io\" = \"0.2.1\"\n").unwrap();
+        let (lockfile, cache) = {
+            let mut resolver = Resolver::new(&mut source);
+            let lockfile = resolver.resolve(&requirement, &[]).unwrap().lockfile();
+            (lockfile, resolver.cache().clone())
+        };
+
+        // A pinned, cached package is served without fetching
+        let before = source.fetches;
+        let resolution = Resolver::new(&mut source)
+            .with_cache(cache)
+            .with_lockfile(lockfile.clone())
+            .resolve(&requirement, &[])
+            .unwrap();
+        assert_eq!(source.fetches, before);
+        assert_eq!(resolution.package("wasi", "io").unwrap().id.version.as_deref(), Some("0.2.1"));
+
+        // Upstream contents changed under the same version
+        source.packages.insert(
+            "wa.dev/wasi:io@0.2.1".to_string(),
+            alloc::vec![WitFile::new("streams.wit", "package wasi:io@0.2.1;\ninterface streams { }\n")],
+        );
+        let err = Resolver::new(&mut source).with_lockfile(lockfile).resolve(&requirement, &[]).unwrap_err();
+        assert!(matches!(err, WitDepsError::DigestMismatch { package, .. } if package == "wasi:io@0.2.1"));
+    }
+
+    #[test]
+    fn test_check_upgrades_against_previous_pins() {
+        let kv = |version: &str, functions: &str| alloc::vec![WitFile::new(
+            "kv.wit",
+            format!("package acme:kv@{};
+interface store {{
+  get: func(key: string) -> string;
+{}}}
+", version, functions),
+        )];
+        let mut source = MemorySource::default()
+            .with("wa.dev/acme:kv@1.0.0", kv("1.0.0", ""))
+            .with("wa.dev/acme:kv@1.1.0", kv("1.1.0", "  set: func(key: string, value: string);
+"))
+            .with("wa.dev/acme:kv@1.1.1", kv("1.1.1", "  set: func(key: string, value: string);
+  clear: func();
+"));
+        let requirement = |version: &str| DepsManifest::parse(&format!("[dependencies]\n\"acme:kv\" = \"{}\"\n", version)).unwrap();
+        let previous = Resolver::new(&mut source).resolve(&requirement("1.0.0"), &[]).unwrap().lockfile();
+
+        // The old pin is fetched again since the resolver's cache is empty
+        let mut resolver = Resolver::new(&mut source);
+        let resolution = resolver.resolve(&requirement("1.1.0"), &[]).unwrap();
+        let upgrades = resolver.check_upgrades(&resolution, &previous).unwrap();
+        assert_eq!(upgrades.len(), 1);
+        assert_eq!(upgrades[0].0.to_string(), "acme:kv@1.1.0");
+        assert!(!upgrades[0].1.is_breaking());
+        assert!(resolver.check_upgrades(&resolution, &resolution.lockfile()).unwrap().is_empty());
+
+        let mut resolver = Resolver::new(&mut source);
+        let patched = resolver.resolve(&requirement("1.1.1"), &[]).unwrap();
+        let err = resolver.check_upgrades(&patched, &resolution.lockfile()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsafe upgrade of acme:kv@1.1.1: 1.1.0 -> 1.1.1 needs a Minor version bump:\n  additive change to store#clear: function added"
+        );
+    }
+}
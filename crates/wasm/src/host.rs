@@ -8,6 +8,15 @@ use alloc::string::{String, ToString};
 use alloc::boxed::Box;
 use core::any::Any;
 
+pub mod bindgen;
+pub mod closure;
+pub mod export;
+pub mod externref;
+pub mod import;
+pub mod promise;
+pub mod ts_bindings;
+pub mod webidl;
+
 /// JavaScript interop errors
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InteropError {
@@ -418,6 +427,106 @@ unsafe fn wasmtime_remove_reference(handle: u32) {
     // Wasmtime-specific reference removal
     panic!("Wasmtime reference removal not implemented")
 }
+// Host ArrayBuffer bridge
+//
+// Large buffers can live in host-owned ArrayBuffers instead of linear memory.
+// The glue module provides these imports under the `wasmrust` namespace and
+// keeps the buffers alive in its externref table until they are released.
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "wasmrust")]
+extern "C" {
+    fn __wasmrust_array_buffer_new(len: u32) -> u32;
+    fn __wasmrust_array_buffer_read(handle: u32, offset: u32, dst: *mut u8, len: u32);
+    fn __wasmrust_array_buffer_write(handle: u32, offset: u32, src: *const u8, len: u32);
+    fn __wasmrust_array_buffer_release(handle: u32);
+}
+
+/// Allocates a zeroed host ArrayBuffer of `len` bytes
+///
+/// Returns the externref table handle of the new buffer.
+pub fn array_buffer_new(len: usize) -> Result<u32, InteropError> {
+    if !get_host_capabilities().js_interop {
+        return Err(InteropError::UnsupportedOperation);
+    }
+
+    let len = u32::try_from(len)
+        .map_err(|_| InteropError::HostError("ArrayBuffer length exceeds u32".to_string()))?;
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        // SAFETY: the import only reads its scalar argument
+        let handle = unsafe { __wasmrust_array_buffer_new(len) };
+        if handle == 0 {
+            return Err(InteropError::HostError("ArrayBuffer allocation failed".to_string()));
+        }
+        Ok(handle)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = len;
+        Err(InteropError::UnsupportedOperation)
+    }
+}
+
+/// Copies bytes out of a host ArrayBuffer into linear memory
+///
+/// # Safety
+///
+/// `handle` must refer to a live ArrayBuffer and `offset + dst.len()`
+/// must not exceed its byte length.
+pub unsafe fn array_buffer_read(handle: u32, offset: usize, dst: &mut [u8]) -> Result<(), InteropError> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        __wasmrust_array_buffer_read(handle, offset as u32, dst.as_mut_ptr(), dst.len() as u32);
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (handle, offset, dst);
+        Err(InteropError::UnsupportedOperation)
+    }
+}
+
+/// Copies bytes from linear memory into a host ArrayBuffer
+///
+/// # Safety
+///
+/// `handle` must refer to a live ArrayBuffer and `offset + src.len()`
+/// must not exceed its byte length.
+pub unsafe fn array_buffer_write(handle: u32, offset: usize, src: &[u8]) -> Result<(), InteropError> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        __wasmrust_array_buffer_write(handle, offset as u32, src.as_ptr(), src.len() as u32);
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (handle, offset, src);
+        Err(InteropError::UnsupportedOperation)
+    }
+}
+
+/// Releases a host ArrayBuffer so the host GC can reclaim it
+///
+/// # Safety
+///
+/// `handle` must refer to a live ArrayBuffer and must not be used afterwards.
+pub unsafe fn array_buffer_release(handle: u32) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        __wasmrust_array_buffer_release(handle);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = handle;
+    }
+}
+
 fn convert_result<T>(_result: Box<dyn Any>) -> Result<T, InteropError> {
     // Convert to host result to expected type
     // In a real implementation, this would handle type conversion
@@ -0,0 +1,165 @@
+//! Compatibility with crates written against wasm-bindgen
+//!
+//! `#[wasm::bindgen]` accepts wasm-bindgen's `#[wasm_bindgen]` items and
+//! maps them onto the externref table and the `wasmrust` imports the JS
+//! glue implements, so binding code keeps its extern blocks and
+//! attributes. Only the `use` changes, to this module's `prelude`:
+//!
+//! ```ignore
+//! use wasm::host::bindgen::prelude::*;
+//!
+//! #[wasm_bindgen]
+//! extern "C" {
+//!     type Element;
+//!
+//!     #[wasm_bindgen(js_namespace = console)]
+//!     fn log(message: &str);
+//!
+//!     #[wasm_bindgen(method, setter = textContent)]
+//!     fn set_text_content(this: &Element, text: &str);
+//! }
+//!
+//! #[wasm_bindgen]
+//! pub fn greet(name: &str) -> String {
+//!     format!("hello {}", name)
+//! }
+//! ```
+//!
+//! Imported types become newtypes of `JsValue`, dereferencing to the
+//! first type they `extends`. Functions are declared with
+//! `#[wasm::import]` from the `wasmrust` module:
+//!
+//! | wasm-bindgen                           | import                         |
+//! |----------------------------------------|--------------------------------|
+//! | `fn f()`, `js_namespace = ns`          | `js_global:ns.f`               |
+//! | `static_method_of = C`                 | `js_global:C.f`                |
+//! | `constructor`, returning `C`           | `js_new:C`                     |
+//! | `method`                               | `js_call:f`, on `this`         |
+//! | `method, getter` / `method, setter`    | `js_get:f` / `js_set:f`        |
+//! | `module = "m"`                         | `f` from `m`, as a user import |
+//!
+//! Methods, constructors, and static methods are associated functions of
+//! their type, as with wasm-bindgen. Exported functions go through
+//! `#[wasm::export]`, named by `js_name` if given. Objects are passed as
+//! `InteropType::ExternRef`, which is how the glue of `wasmrust glue`
+//! learns to root and borrow them.
+//!
+//! Exported structs and impls, `catch`, `variadic`, statics, and `start`
+//! are rejected at compile time, and `JsValue` has none of the
+//! conversions to and from Rust values that need wasm-bindgen's own glue,
+//! so js-sys and web-sys do not build on the shim. `cargo wasmrust` does
+//! not run proc macros yet; build with `cargo build --target
+//! wasm32-unknown-unknown` and generate the glue with `wasmrust glue`.
+
+use super::externref::OwnedExternRef;
+use crate::ExternRef;
+
+/// JavaScript value rooted in the glue's externref table
+///
+/// `null` and `undefined` are both the null handle.
+#[repr(transparent)]
+#[derive(Debug, Clone)]
+pub struct JsValue(OwnedExternRef<JsValue>);
+
+impl JsValue {
+    /// The `null` value, which needs no slot
+    pub fn null() -> JsValue {
+        // SAFETY: null handles are never rooted or released
+        unsafe { JsValue(OwnedExternRef::from_raw(ExternRef::null())) }
+    }
+
+    /// Checks whether the value is `null` or `undefined`
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+}
+
+impl AsRef<JsValue> for JsValue {
+    fn as_ref(&self) -> &JsValue {
+        self
+    }
+}
+
+/// Conversions between `JsValue` and the types imported with `#[wasm::bindgen]`
+///
+/// Nothing is checked at run time; a value converted to the wrong type
+/// fails when JavaScript uses it.
+pub trait JsCast: AsRef<JsValue> + Into<JsValue> {
+    /// Views a value as this type
+    fn unchecked_from_js(value: JsValue) -> Self;
+
+    /// Views a borrowed value as this type
+    fn unchecked_from_js_ref(value: &JsValue) -> &Self;
+
+    /// Converts into another JavaScript type
+    fn unchecked_into<T: JsCast>(self) -> T {
+        T::unchecked_from_js(self.into())
+    }
+
+    /// Borrows as another JavaScript type
+    fn unchecked_ref<T: JsCast>(&self) -> &T {
+        T::unchecked_from_js_ref(self.as_ref())
+    }
+}
+
+impl JsCast for JsValue {
+    fn unchecked_from_js(value: JsValue) -> Self {
+        value
+    }
+
+    fn unchecked_from_js_ref(value: &JsValue) -> &Self {
+        value
+    }
+}
+
+/// Handle of an object lent to an import for the call
+#[doc(hidden)]
+pub fn handle_of<T: JsCast>(value: &T) -> u32 {
+    value.as_ref().0.handle()
+}
+
+/// Takes ownership of an object the glue rooted, as an import result or export argument
+///
+/// # Safety
+///
+/// The handle must be null or a rooted slot that nothing else releases.
+#[doc(hidden)]
+pub unsafe fn take_object<T: JsCast>(handle: u32) -> T {
+    T::unchecked_from_js(JsValue(OwnedExternRef::from_raw(ExternRef::from_handle(handle))))
+}
+
+/// Hands an exported function's result to the glue, which unroots it
+#[doc(hidden)]
+pub fn into_handle<T: JsCast>(value: T) -> u32 {
+    let value: JsValue = value.into();
+    value.0.into_raw().handle()
+}
+
+/// What `use wasm_bindgen::prelude::*` brings into scope
+pub mod prelude {
+    pub use super::{JsCast, JsValue};
+
+    #[cfg(feature = "macros")]
+    pub use crate::bindgen as wasm_bindgen;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_null_values_hold_no_slot() {
+        let value = JsValue::null();
+        assert!(value.is_null());
+        assert_eq!(handle_of(&value), 0);
+        assert_eq!(into_handle(value.clone()), 0);
+    }
+
+    #[test]
+    fn test_objects_round_trip_through_handles() {
+        // SAFETY: the handle is rooted for this value alone
+        let value: JsValue = unsafe { take_object(7) };
+        assert_eq!(handle_of(&value), 7);
+        assert_eq!(into_handle(value.unchecked_into::<JsValue>()), 7);
+    }
+}
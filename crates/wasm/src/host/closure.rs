@@ -0,0 +1,193 @@
+//! Rust closures callable from JavaScript
+//!
+//! A closure is boxed on the heap and described to the host by three
+//! numbers: the table index of a monomorphized invoke trampoline, a data
+//! pointer to the boxed closure, and the table index of a drop trampoline.
+//! The glue turns these into a JavaScript function that calls
+//! `table.get(invoke)(data, ...args)`, and runs the drop trampoline when
+//! the closure is released.
+//!
+//! The boxed closure sits in a `RefCell`, so a closure that calls back into
+//! itself through JavaScript panics instead of aliasing its captures.
+
+use alloc::boxed::Box;
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+use super::{get_host_capabilities, InteropError};
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "wasmrust")]
+extern "C" {
+    fn __wasmrust_closure_new(invoke: u32, data: u32, drop: u32) -> u32;
+    fn __wasmrust_closure_release(handle: u32);
+}
+
+/// Values that cross the boundary as closure arguments or results
+pub trait ClosureValue: 'static {}
+
+impl ClosureValue for () {}
+impl ClosureValue for i32 {}
+impl ClosureValue for u32 {}
+impl ClosureValue for i64 {}
+impl ClosureValue for f32 {}
+impl ClosureValue for f64 {}
+
+/// Heap-allocated closure split into function-table entries and its data
+///
+/// On wasm32 a function pointer is its index in the indirect function
+/// table, so `invoke` and `drop` can be handed to the host directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawClosure {
+    /// `extern "C" fn(data, args...) -> Ret`
+    pub invoke: usize,
+    /// Pointer to the boxed `RefCell` holding the closure
+    pub data: usize,
+    /// `extern "C" fn(data)`, freeing the boxed closure
+    pub drop: usize,
+}
+
+/// Closures that can be exported with the argument tuple `Args`
+pub trait IntoRawClosure<Args, Ret>: 'static {
+    /// Boxes the closure and returns its trampolines
+    fn into_raw(self) -> RawClosure;
+}
+
+extern "C" fn drop_closure<F>(data: *mut RefCell<F>) {
+    // SAFETY: `data` came from `Box::into_raw` in `into_raw`, and the glue
+    // runs the drop trampoline at most once
+    drop(unsafe { Box::from_raw(data) });
+}
+
+macro_rules! impl_into_raw_closure {
+    ($($arg:ident $name:ident),*) => {
+        impl<F, Ret, $($arg),*> IntoRawClosure<($($arg,)*), Ret> for F
+        where
+            F: FnMut($($arg),*) -> Ret + 'static,
+            Ret: ClosureValue,
+            $($arg: ClosureValue,)*
+        {
+            fn into_raw(self) -> RawClosure {
+                extern "C" fn invoke<F, Ret, $($arg),*>(data: *const RefCell<F>, $($name: $arg),*) -> Ret
+                where
+                    F: FnMut($($arg),*) -> Ret,
+                {
+                    // SAFETY: the glue only invokes closures it has not released
+                    let cell = unsafe { &*data };
+                    let mut closure = cell.try_borrow_mut().expect("closure called again while it is running");
+                    (*closure)($($name),*)
+                }
+
+                RawClosure {
+                    invoke: invoke::<F, Ret, $($arg),*> as *const () as usize,
+                    data: Box::into_raw(Box::new(RefCell::new(self))) as usize,
+                    drop: drop_closure::<F> as *const () as usize,
+                }
+            }
+        }
+    };
+}
+
+impl_into_raw_closure!();
+impl_into_raw_closure!(A a);
+impl_into_raw_closure!(A a, B b);
+impl_into_raw_closure!(A a, B b, C c);
+impl_into_raw_closure!(A a, B b, C c, D d);
+
+/// Rust closure registered with the host as a JavaScript function
+///
+/// Dropping the handle releases the JavaScript function and frees the
+/// closure. Use `into_handle` to hand ownership to JavaScript instead;
+/// the glue then frees it when `free()` is called on the function.
+pub struct JsClosure<Args, Ret> {
+    handle: u32,
+    _phantom: PhantomData<fn(Args) -> Ret>,
+}
+
+impl<Args, Ret> JsClosure<Args, Ret> {
+    /// Boxes `closure` and registers it with the host
+    pub fn new<F: IntoRawClosure<Args, Ret>>(closure: F) -> Result<Self, InteropError> {
+        if !get_host_capabilities().js_interop {
+            return Err(InteropError::UnsupportedOperation);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let raw = closure.into_raw();
+            // SAFETY: the trampolines match the closure's type and `data`
+            // stays valid until the glue runs the drop trampoline
+            let handle = unsafe { __wasmrust_closure_new(raw.invoke as u32, raw.data as u32, raw.drop as u32) };
+            Ok(Self { handle, _phantom: PhantomData })
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            drop(closure);
+            Err(InteropError::UnsupportedOperation)
+        }
+    }
+
+    /// Gets the host handle of the JavaScript function
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Transfers ownership of the closure to JavaScript
+    pub fn into_handle(self) -> u32 {
+        let handle = self.handle;
+        core::mem::forget(self);
+        handle
+    }
+}
+
+impl<Args, Ret> Drop for JsClosure<Args, Ret> {
+    fn drop(&mut self) {
+        #[cfg(target_arch = "wasm32")]
+        // SAFETY: the handle was returned by `__wasmrust_closure_new` and is
+        // released exactly once
+        unsafe {
+            __wasmrust_closure_release(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    #[test]
+    fn test_invoke_trampoline_calls_closure() {
+        let mut total = 0;
+        let raw = IntoRawClosure::<(i32, i32), i32>::into_raw(move |a: i32, b: i32| {
+            total += a * b;
+            total
+        });
+
+        // SAFETY: the trampolines were created for this signature
+        let invoke: extern "C" fn(usize, i32, i32) -> i32 = unsafe { core::mem::transmute(raw.invoke) };
+        assert_eq!(invoke(raw.data, 2, 3), 6);
+        assert_eq!(invoke(raw.data, 4, 1), 10);
+
+        let release: extern "C" fn(usize) = unsafe { core::mem::transmute(raw.drop) };
+        release(raw.data);
+    }
+
+    #[test]
+    fn test_drop_trampoline_frees_captures() {
+        let captured = Rc::new(Cell::new(0));
+        let inner = captured.clone();
+        let raw = IntoRawClosure::<(), ()>::into_raw(move || inner.set(inner.get() + 1));
+        assert_eq!(Rc::strong_count(&captured), 2);
+
+        // SAFETY: the trampolines were created for this signature
+        let invoke: extern "C" fn(usize) = unsafe { core::mem::transmute(raw.invoke) };
+        invoke(raw.data);
+        assert_eq!(captured.get(), 1);
+
+        let release: extern "C" fn(usize) = unsafe { core::mem::transmute(raw.drop) };
+        release(raw.data);
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+}
@@ -0,0 +1,305 @@
+//! Exports declared with `#[wasm::export]`
+//!
+//! The macro wraps the function in an `extern "C"` shim following the
+//! memory ABI of `InteropSignature`, and records the export in the
+//! `EXPORTS_SECTION` custom section, which the linker concatenates across
+//! crates. Reading the section back with `read_exports` gives the JS glue
+//! and `.d.ts` generators the strings, slices, and vectors of modules
+//! built by cargo, whose binaries only say `i32`. The glue allocates the
+//! buffers it passes through `ALLOC_EXPORT` and `FREE_EXPORT`, which this
+//! module defines on top of the global allocator.
+//!
+//! A record is the export name as a LEB128 length and UTF-8 bytes, the
+//! parameter count as LEB128, then one type code per parameter and one for
+//! the result: 0 for a value, 1 for a string, 2 for a slice, 3 for a
+//! mutable slice, 4 for a vector, and 5 for a JavaScript object. Slices
+//! and vectors are followed by the position of their element type in
+//! `ElementType`.
+
+use crate::wasmir::{ElementType, InteropSignature, InteropType};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::slice;
+
+/// Custom section holding one record per export
+pub const EXPORTS_SECTION: &str = "wasmrust.exports";
+
+/// Element codes, in `ElementType` declaration order
+const ELEMENTS: [ElementType; 9] = [
+    ElementType::U8,
+    ElementType::I8,
+    ElementType::U16,
+    ElementType::I16,
+    ElementType::U32,
+    ElementType::I32,
+    ElementType::I64,
+    ElementType::F32,
+    ElementType::F64,
+];
+
+/// Error reading `EXPORTS_SECTION` or `IMPORTS_SECTION`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InteropSectionError {
+    /// The section ends inside a record
+    Truncated,
+    /// A name is not UTF-8
+    InvalidName,
+    /// A type or element code is unknown
+    UnknownCode(u8),
+}
+
+impl fmt::Display for InteropSectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InteropSectionError::Truncated => write!(f, "interop record is truncated"),
+            InteropSectionError::InvalidName => write!(f, "interop record name is not UTF-8"),
+            InteropSectionError::UnknownCode(code) => write!(f, "unknown interop type code {}", code),
+        }
+    }
+}
+
+/// Encodes the record of one export
+pub fn encode_export(name: &str, signature: &InteropSignature) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_name(&mut out, name);
+    write_signature(&mut out, signature);
+    out
+}
+
+/// Decodes every record in the contents of `EXPORTS_SECTION`
+pub fn read_exports(section: &[u8]) -> Result<Vec<(String, InteropSignature)>, InteropSectionError> {
+    let mut bytes = section.iter().copied();
+    let mut exports = Vec::new();
+    while bytes.len() > 0 {
+        let name = read_name(&mut bytes)?;
+        exports.push((name, read_signature(&mut bytes)?));
+    }
+    Ok(exports)
+}
+
+pub(crate) fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_leb(out, name.len());
+    out.extend_from_slice(name.as_bytes());
+}
+
+pub(crate) fn read_name(bytes: &mut impl Iterator<Item = u8>) -> Result<String, InteropSectionError> {
+    let len = read_leb(bytes)?;
+    let name: Vec<u8> = bytes.by_ref().take(len).collect();
+    if name.len() != len {
+        return Err(InteropSectionError::Truncated);
+    }
+    String::from_utf8(name).map_err(|_| InteropSectionError::InvalidName)
+}
+
+/// Writes the parameter count and the codes of the parameters and result
+pub(crate) fn write_signature(out: &mut Vec<u8>, signature: &InteropSignature) {
+    write_leb(out, signature.params.len());
+    for &ty in signature.params.iter().chain([&signature.returns]) {
+        let (code, element) = match ty {
+            InteropType::Value => (0, None),
+            InteropType::Str => (1, None),
+            InteropType::Slice(element) => (2, Some(element)),
+            InteropType::SliceMut(element) => (3, Some(element)),
+            InteropType::Vec(element) => (4, Some(element)),
+            InteropType::ExternRef => (5, None),
+        };
+        out.push(code);
+        if let Some(element) = element {
+            out.push(ELEMENTS.iter().position(|&known| known == element).unwrap_or_default() as u8);
+        }
+    }
+}
+
+pub(crate) fn read_signature(bytes: &mut impl Iterator<Item = u8>) -> Result<InteropSignature, InteropSectionError> {
+    let params = (0..read_leb(bytes)?).map(|_| read_type(bytes)).collect::<Result<_, _>>()?;
+    let returns = read_type(bytes)?;
+    Ok(InteropSignature { params, returns })
+}
+
+fn read_type(bytes: &mut impl Iterator<Item = u8>) -> Result<InteropType, InteropSectionError> {
+    let code = bytes.next().ok_or(InteropSectionError::Truncated)?;
+    let mut element = || {
+        let element = bytes.next().ok_or(InteropSectionError::Truncated)?;
+        ELEMENTS.get(element as usize).copied().ok_or(InteropSectionError::UnknownCode(element))
+    };
+    match code {
+        0 => Ok(InteropType::Value),
+        1 => Ok(InteropType::Str),
+        2 => Ok(InteropType::Slice(element()?)),
+        3 => Ok(InteropType::SliceMut(element()?)),
+        4 => Ok(InteropType::Vec(element()?)),
+        5 => Ok(InteropType::ExternRef),
+        code => Err(InteropSectionError::UnknownCode(code)),
+    }
+}
+
+fn write_leb(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_leb(bytes: &mut impl Iterator<Item = u8>) -> Result<usize, InteropSectionError> {
+    let mut value = 0usize;
+    for shift in (0..35).step_by(7) {
+        let byte = bytes.next().ok_or(InteropSectionError::Truncated)?;
+        value |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(InteropSectionError::Truncated)
+}
+
+/// Borrows a string argument
+///
+/// # Panics
+/// If the bytes are not UTF-8.
+///
+/// # Safety
+/// `ptr` must point at `len` initialized bytes that outlive `'a`.
+pub unsafe fn lift_str<'a>(ptr: *const u8, len: usize) -> &'a str {
+    core::str::from_utf8(lift_slice(ptr, len)).expect("string argument is not UTF-8")
+}
+
+/// Copies a string argument the host frees after the call
+///
+/// # Safety
+/// As for `lift_str`.
+pub unsafe fn lift_string(ptr: *const u8, len: usize) -> String {
+    String::from(lift_str(ptr, len))
+}
+
+/// Borrows a slice argument
+///
+/// # Safety
+/// Unless `len` is 0, `ptr` must point at `len` initialized, aligned
+/// elements that outlive `'a`.
+pub unsafe fn lift_slice<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        return &[];
+    }
+    slice::from_raw_parts(ptr, len)
+}
+
+/// Mutably borrows a slice argument the host copies back after the call
+///
+/// # Safety
+/// As for `lift_slice`, and nothing else may access the elements.
+pub unsafe fn lift_slice_mut<'a, T>(ptr: *mut T, len: usize) -> &'a mut [T] {
+    if len == 0 {
+        return &mut [];
+    }
+    slice::from_raw_parts_mut(ptr, len)
+}
+
+/// Takes ownership of a vector argument
+///
+/// # Safety
+/// Unless `len` is 0, `ptr` must come from `ALLOC_EXPORT` with the size
+/// and alignment of `len` elements, which the host no longer uses.
+pub unsafe fn lift_vec<T>(ptr: *mut T, len: usize) -> Vec<T> {
+    if len == 0 {
+        return Vec::new();
+    }
+    Vec::from_raw_parts(ptr, len, len)
+}
+
+/// Writes a string result to the return area, passing ownership to the host
+///
+/// # Safety
+/// `retptr` must point at two writable, aligned `usize`s.
+pub unsafe fn return_string(retptr: *mut usize, value: String) {
+    return_vec(retptr, value.into_bytes());
+}
+
+/// Writes a vector result to the return area, passing ownership to the host
+///
+/// The host frees it with `FREE_EXPORT`, giving the size of its elements
+/// as the alignment.
+///
+/// # Safety
+/// As for `return_string`.
+pub unsafe fn return_vec<T>(retptr: *mut usize, value: Vec<T>) {
+    let value = value.into_boxed_slice();
+    let len = value.len();
+    let ptr = Box::into_raw(value) as *mut T;
+    retptr.write(ptr as usize);
+    retptr.add(1).write(len);
+}
+
+/// Allocator the glue calls to pass strings, slices, and vectors
+///
+/// # Safety
+/// `align` must be a power of two.
+#[cfg(all(target_arch = "wasm32", feature = "macros"))]
+#[no_mangle]
+pub unsafe extern "C" fn __wasm_alloc(size: usize, align: usize) -> *mut u8 {
+    use alloc::alloc::{alloc, Layout};
+
+    if size == 0 {
+        return align as *mut u8;
+    }
+    let ptr = alloc(Layout::from_size_align_unchecked(size, align));
+    if ptr.is_null() {
+        core::arch::wasm32::unreachable();
+    }
+    ptr
+}
+
+/// Frees a buffer from `__wasm_alloc` or a returned string or vector
+///
+/// # Safety
+/// `ptr` must have been allocated with this size and alignment.
+#[cfg(all(target_arch = "wasm32", feature = "macros"))]
+#[no_mangle]
+pub unsafe extern "C" fn __wasm_free(ptr: *mut u8, size: usize, align: usize) {
+    if size > 0 {
+        alloc::alloc::dealloc(ptr, alloc::alloc::Layout::from_size_align_unchecked(size, align));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_records_round_trip() {
+        let greet = InteropSignature { params: vec![InteropType::Str, InteropType::Value], returns: InteropType::Str };
+        let sum = InteropSignature {
+            params: vec![InteropType::Slice(ElementType::F64), InteropType::SliceMut(ElementType::U8)],
+            returns: InteropType::Vec(ElementType::I32),
+        };
+        let mut section = encode_export("greet", &greet);
+        section.extend(encode_export("sum", &sum));
+
+        let exports = read_exports(&section).unwrap();
+        assert_eq!(exports, vec![("greet".into(), greet), ("sum".into(), sum)]);
+        assert_eq!(read_exports(&section[..section.len() - 1]), Err(InteropSectionError::Truncated));
+        assert_eq!(read_exports(&[1, b'f', 0, 9]), Err(InteropSectionError::UnknownCode(9)));
+    }
+
+    #[test]
+    fn test_arguments_and_results_cross_through_memory() {
+        let bytes = b"hello";
+        // SAFETY: the pointers come from live buffers of the given lengths
+        unsafe {
+            assert_eq!(lift_str(bytes.as_ptr(), bytes.len()), "hello");
+            assert!(lift_slice::<f64>(core::ptr::null(), 0).is_empty());
+
+            let mut area = [0usize; 2];
+            return_vec(area.as_mut_ptr(), vec![1u16, 2, 3]);
+            let returned = lift_vec(area[0] as *mut u16, area[1]);
+            assert_eq!(returned, [1, 2, 3]);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Owned JavaScript references rooted in the glue's externref table
+//!
+//! Rust cannot hold a JavaScript object directly, so the glue roots every
+//! object handed to Rust in a table and passes its index as the `u32`
+//! inside `ExternRef<T>`. An `ExternRef<T>` is a borrowed view of a slot;
+//! `OwnedExternRef<T>` owns one, roots a new slot when cloned, and unroots
+//! its slot on drop so the object can be garbage collected.
+//!
+//! With the `externref-leak-check` feature every owned handle is tracked,
+//! releasing a handle twice panics, and `live_handles` reports handles that
+//! were never dropped. The glue's debug mode performs the same checks on the
+//! JavaScript side and records where each handle was rooted.
+
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use crate::ExternRef;
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "wasmrust")]
+extern "C" {
+    fn __wasmrust_externref_clone(handle: u32) -> u32;
+    fn __wasmrust_externref_release(handle: u32);
+}
+
+/// Roots another slot for the object in `handle`
+fn clone_handle(handle: u32) -> u32 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        // SAFETY: the import only reads the table slot
+        unsafe { __wasmrust_externref_clone(handle) }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        // Without a host there is no table; hand out fresh handles so
+        // ownership tracking still behaves as it would under the glue
+        use core::sync::atomic::{AtomicU32, Ordering};
+        static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1 << 24);
+        let _ = handle;
+        NEXT_HANDLE.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+fn release_handle(handle: u32) {
+    #[cfg(target_arch = "wasm32")]
+    // SAFETY: the caller owned the slot and gives it up here
+    unsafe {
+        __wasmrust_externref_release(handle);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = handle;
+}
+
+/// Rooted JavaScript reference, unrooted when dropped
+#[repr(transparent)]
+pub struct OwnedExternRef<T> {
+    raw: ExternRef<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> OwnedExternRef<T> {
+    /// Takes ownership of a rooted handle, such as one returned by an import
+    ///
+    /// Null handles are never rooted and are not released.
+    ///
+    /// # Safety
+    ///
+    /// The handle must be null or a rooted slot that nothing else releases.
+    pub unsafe fn from_raw(raw: ExternRef<T>) -> Self {
+        if !raw.is_null() {
+            leak_check::track(raw.handle());
+        }
+        Self { raw, _not_send: PhantomData }
+    }
+
+    /// Borrows the reference for passing to an import
+    pub fn get(&self) -> ExternRef<T> {
+        self.raw
+    }
+
+    /// Gives up ownership without unrooting, e.g. to return it to JavaScript
+    pub fn into_raw(self) -> ExternRef<T> {
+        let raw = self.raw;
+        if !raw.is_null() {
+            leak_check::untrack(raw.handle());
+        }
+        core::mem::forget(self);
+        raw
+    }
+}
+
+impl<T> Deref for OwnedExternRef<T> {
+    type Target = ExternRef<T>;
+
+    fn deref(&self) -> &ExternRef<T> {
+        &self.raw
+    }
+}
+
+impl<T> Clone for OwnedExternRef<T> {
+    fn clone(&self) -> Self {
+        if self.raw.is_null() {
+            return Self { raw: ExternRef::null(), _not_send: PhantomData };
+        }
+        // SAFETY: the new slot is rooted for us alone
+        unsafe { Self::from_raw(ExternRef::from_handle(clone_handle(self.raw.handle()))) }
+    }
+}
+
+impl<T> Drop for OwnedExternRef<T> {
+    fn drop(&mut self) {
+        if !self.raw.is_null() {
+            leak_check::untrack(self.raw.handle());
+            release_handle(self.raw.handle());
+        }
+    }
+}
+
+impl<T> core::fmt::Debug for OwnedExternRef<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("OwnedExternRef").field(&self.raw.handle()).finish()
+    }
+}
+
+/// Gets the owned handles that have not been dropped, in ascending order
+#[cfg(feature = "externref-leak-check")]
+pub fn live_handles() -> alloc::vec::Vec<u32> {
+    leak_check::LIVE.lock().iter().copied().collect()
+}
+
+#[cfg(feature = "externref-leak-check")]
+mod leak_check {
+    use alloc::collections::BTreeSet;
+
+    use crate::threading::Mutex;
+
+    pub(super) static LIVE: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+    pub(super) fn track(handle: u32) {
+        let inserted = LIVE.lock().insert(handle);
+        assert!(inserted, "externref {} is owned twice", handle);
+    }
+
+    pub(super) fn untrack(handle: u32) {
+        let removed = LIVE.lock().remove(&handle);
+        assert!(removed, "externref {} released twice", handle);
+    }
+}
+
+#[cfg(not(feature = "externref-leak-check"))]
+mod leak_check {
+    pub(super) fn track(_: u32) {}
+    pub(super) fn untrack(_: u32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Node;
+
+    #[test]
+    fn test_clone_roots_a_new_slot() {
+        // SAFETY: test handles are not rooted anywhere else
+        let owned = unsafe { OwnedExternRef::<Node>::from_raw(ExternRef::from_handle(41)) };
+        let copy = owned.clone();
+        assert_ne!(copy.handle(), owned.handle());
+        assert_eq!(owned.get().handle(), 41);
+
+        let null = unsafe { OwnedExternRef::<Node>::from_raw(ExternRef::null()) };
+        assert!(null.clone().is_null());
+        assert_eq!(owned.into_raw().handle(), 41);
+    }
+
+    #[cfg(feature = "externref-leak-check")]
+    #[test]
+    fn test_leak_check_tracks_owned_handles() {
+        // SAFETY: test handles are not rooted anywhere else
+        let owned = unsafe { OwnedExternRef::<Node>::from_raw(ExternRef::from_handle(77)) };
+        let copy = owned.clone();
+        assert!(live_handles().contains(&77));
+        assert!(live_handles().contains(&copy.handle()));
+
+        drop(owned);
+        drop(copy);
+        assert!(!live_handles().contains(&77));
+    }
+
+    #[cfg(feature = "externref-leak-check")]
+    #[test]
+    #[should_panic(expected = "externref 78 is owned twice")]
+    fn test_leak_check_rejects_double_ownership() {
+        // SAFETY: deliberately wrong, to exercise the check
+        let first = unsafe { OwnedExternRef::<Node>::from_raw(ExternRef::from_handle(78)) };
+        let _second = unsafe { OwnedExternRef::<Node>::from_raw(first.get()) };
+    }
+}
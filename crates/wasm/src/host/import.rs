@@ -0,0 +1,116 @@
+//! Host functions declared with `#[wasm::import]`
+//!
+//! The macro turns a function declaration without a body into an
+//! `extern "C"` import from the given module and a safe wrapper lowering
+//! its arguments to the memory ABI of `InteropSignature`. Strings and
+//! slices are lent to the host for the call; string and vector results
+//! come back through a return area, in buffers the host allocated with
+//! `ALLOC_EXPORT` and the wrapper takes ownership of.
+//!
+//! Each import is recorded in the `IMPORTS_SECTION` custom section, from
+//! which the JS glue builds its stub table: the host's plain JS function
+//! is wrapped to read strings and slices out of memory, and a missing one
+//! throws when called rather than failing instantiation. A record is the
+//! import module and field names, each encoded like export names, followed
+//! by the signature as in `super::export`.
+//!
+//! Off wasm the import links against a C symbol of the field name, so
+//! native hosts and tests can provide it with `#[no_mangle]`.
+
+use super::export::{lift_vec, read_name, read_signature, write_name, write_signature, InteropSectionError};
+use crate::wasmir::InteropSignature;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Custom section holding one record per import
+pub const IMPORTS_SECTION: &str = "wasmrust.imports";
+
+/// A host import read from `IMPORTS_SECTION`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportRecord {
+    /// Module the host resolves the import from
+    pub module: String,
+    /// Field name within the module
+    pub name: String,
+    /// Source-level signature
+    pub signature: InteropSignature,
+}
+
+/// Encodes the record of one import
+pub fn encode_import(module: &str, name: &str, signature: &InteropSignature) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_name(&mut out, module);
+    write_name(&mut out, name);
+    write_signature(&mut out, signature);
+    out
+}
+
+/// Decodes every record in the contents of `IMPORTS_SECTION`
+pub fn read_imports(section: &[u8]) -> Result<Vec<ImportRecord>, InteropSectionError> {
+    let mut bytes = section.iter().copied();
+    let mut imports = Vec::new();
+    while bytes.len() > 0 {
+        let module = read_name(&mut bytes)?;
+        let name = read_name(&mut bytes)?;
+        imports.push(ImportRecord { module, name, signature: read_signature(&mut bytes)? });
+    }
+    Ok(imports)
+}
+
+/// Takes ownership of a string result from the return area
+///
+/// # Panics
+/// If the host wrote bytes that are not UTF-8.
+///
+/// # Safety
+/// The host must have written a buffer from `ALLOC_EXPORT` with alignment
+/// 1 and the given length, which it no longer uses.
+pub unsafe fn take_string(area: &[usize; 2]) -> String {
+    String::from_utf8(take_vec(area)).expect("string result is not UTF-8")
+}
+
+/// Takes ownership of a vector result from the return area
+///
+/// # Safety
+/// The host must have written a buffer from `ALLOC_EXPORT` holding the
+/// given number of elements, aligned to their size, which it no longer
+/// uses.
+pub unsafe fn take_vec<T>(area: &[usize; 2]) -> Vec<T> {
+    lift_vec(area[0] as *mut T, area[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::{ElementType, InteropType};
+    use alloc::vec;
+
+    #[test]
+    fn test_records_round_trip() {
+        let signature = InteropSignature {
+            params: vec![InteropType::Str, InteropType::SliceMut(ElementType::I64)],
+            returns: InteropType::Vec(ElementType::U16),
+        };
+        let mut section = encode_import("env", "log", &InteropSignature::default());
+        section.extend(encode_import("canvas", "pixels", &signature));
+
+        let imports = read_imports(&section).unwrap();
+        assert_eq!(imports[0], ImportRecord {
+            module: "env".into(),
+            name: "log".into(),
+            signature: InteropSignature::default(),
+        });
+        assert_eq!((imports[1].module.as_str(), &imports[1].signature), ("canvas", &signature));
+        assert_eq!(read_imports(&section[..4]), Err(InteropSectionError::Truncated));
+    }
+
+    #[test]
+    fn test_results_are_taken_from_the_return_area() {
+        let bytes = String::from("host").into_bytes().into_boxed_slice();
+        let area = [bytes.as_ptr() as usize, bytes.len()];
+        core::mem::forget(bytes);
+        // SAFETY: the area describes a leaked buffer of the global allocator
+        assert_eq!(unsafe { take_string(&area) }, "host");
+        assert!(unsafe { take_vec::<f64>(&[8, 0]) }.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+//! JavaScript Promise interop for the callback lowering
+//!
+//! Under `PromiseLowering::Callback` a promise crosses the boundary as a
+//! handle into the glue's promise table. The glue reports settlement of
+//! promises returned by JS methods through the `__wasmrust_promise_settled`
+//! export, which wakes the task awaiting the matching `JsPromise`; hosts
+//! then run their `LocalExecutor` (see its wake hook). Async exports go the
+//! other way: they return the handle of a `PromiseResolver` and settle it
+//! once their future completes.
+//!
+//! Values travel as `f64`, which represents every supported payload
+//! exactly, matching JavaScript's number type.
+
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use super::InteropError;
+use crate::threading::executor::LocalExecutor;
+use crate::threading::Mutex;
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "wasmrust")]
+extern "C" {
+    fn __wasmrust_promise_new() -> u32;
+    fn __wasmrust_promise_resolve(handle: u32, fulfilled: u32, value: f64);
+}
+
+/// Values a promise can settle to
+pub trait PromiseValue: Sized {
+    /// Converts from the JavaScript number the promise settled to
+    fn from_js(value: f64) -> Self;
+    /// Converts to a JavaScript number
+    fn to_js(self) -> f64;
+}
+
+impl PromiseValue for () {
+    fn from_js(_: f64) -> Self {}
+    fn to_js(self) -> f64 {
+        0.0
+    }
+}
+
+impl PromiseValue for bool {
+    fn from_js(value: f64) -> Self {
+        value != 0.0
+    }
+    fn to_js(self) -> f64 {
+        if self { 1.0 } else { 0.0 }
+    }
+}
+
+macro_rules! impl_numeric_promise_value {
+    ($($ty:ty),*) => {
+        $(
+            impl PromiseValue for $ty {
+                fn from_js(value: f64) -> Self {
+                    value as $ty
+                }
+                fn to_js(self) -> f64 {
+                    self as f64
+                }
+            }
+        )*
+    };
+}
+
+impl_numeric_promise_value!(i32, u32, f32, f64);
+
+#[derive(Default)]
+struct PromiseSlot {
+    waker: Option<Waker>,
+    settled: Option<Result<f64, ()>>,
+}
+
+static PROMISES: Mutex<BTreeMap<u32, PromiseSlot>> = Mutex::new(BTreeMap::new());
+
+/// Records that the promise with `handle` settled and wakes its awaiter
+///
+/// Called by the glue; settlement may arrive before the promise is awaited.
+pub fn promise_settled(handle: u32, fulfilled: bool, value: f64) {
+    let waker = {
+        let mut promises = PROMISES.lock();
+        let slot = promises.entry(handle).or_default();
+        slot.settled = Some(if fulfilled { Ok(value) } else { Err(()) });
+        slot.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+extern "C" fn __wasmrust_promise_settled(handle: u32, fulfilled: u32, value: f64) {
+    promise_settled(handle, fulfilled != 0, value);
+}
+
+/// Promise returned by a JavaScript method, awaited from Rust
+pub struct JsPromise<T> {
+    handle: u32,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: PromiseValue> JsPromise<T> {
+    /// Wraps the handle a promise-returning import produced
+    pub fn from_handle(handle: u32) -> Self {
+        Self { handle, _phantom: PhantomData }
+    }
+
+    /// Gets the glue's handle for the promise
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+}
+
+impl<T: PromiseValue> Future for JsPromise<T> {
+    type Output = Result<T, InteropError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut promises = PROMISES.lock();
+        let slot = promises.entry(self.handle).or_default();
+        match slot.settled.take() {
+            Some(settled) => {
+                promises.remove(&self.handle);
+                Poll::Ready(settled.map(T::from_js).map_err(|()| {
+                    InteropError::HostError("promise rejected".to_string())
+                }))
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for JsPromise<T> {
+    fn drop(&mut self) {
+        PROMISES.lock().remove(&self.handle);
+    }
+}
+
+/// Settles a promise handed to JavaScript by an async export
+pub struct PromiseResolver<T> {
+    handle: u32,
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T: PromiseValue> PromiseResolver<T> {
+    /// Creates a pending promise in the glue's promise table
+    pub fn new() -> Result<Self, InteropError> {
+        #[cfg(target_arch = "wasm32")]
+        {
+            // SAFETY: the import takes no arguments
+            let handle = unsafe { __wasmrust_promise_new() };
+            Ok(Self { handle, _phantom: PhantomData })
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            Err(InteropError::UnsupportedOperation)
+        }
+    }
+
+    /// Gets the handle the async export returns to the glue
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Fulfills the promise with `value`
+    pub fn resolve(self, value: T) {
+        self.settle(true, value.to_js());
+    }
+
+    /// Rejects the promise
+    pub fn reject(self) {
+        self.settle(false, 0.0);
+    }
+
+    fn settle(self, fulfilled: bool, value: f64) {
+        #[cfg(target_arch = "wasm32")]
+        // SAFETY: the handle came from `__wasmrust_promise_new` and the
+        // resolver is consumed, so it is settled once
+        unsafe {
+            __wasmrust_promise_resolve(self.handle, fulfilled as u32, value);
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = (fulfilled, value);
+        }
+    }
+}
+
+/// Runs `future` on `executor` as the body of an async export
+///
+/// Returns the promise handle the export passes back to the glue, which
+/// hands JavaScript a Promise settled with the future's output.
+pub fn spawn_export<T, F>(executor: &mut LocalExecutor, future: F) -> Result<u32, InteropError>
+where
+    T: PromiseValue + 'static,
+    F: Future<Output = Result<T, InteropError>> + 'static,
+{
+    let resolver = PromiseResolver::<T>::new()?;
+    let handle = resolver.handle();
+    executor.spawn(async move {
+        match future.await {
+            Ok(value) => resolver.resolve(value),
+            Err(_) => resolver.reject(),
+        }
+    });
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    #[test]
+    fn test_js_promise_wakes_on_settlement() {
+        let result = Rc::new(Cell::new(None));
+        let sink = result.clone();
+        let mut executor = LocalExecutor::new();
+        executor.spawn(async move {
+            sink.set(Some(JsPromise::<i32>::from_handle(7).await));
+        });
+
+        executor.run_until_stalled();
+        assert_eq!(result.take(), None);
+
+        promise_settled(7, true, 200.0);
+        executor.run_until_stalled();
+        assert_eq!(result.take(), Some(Ok(200)));
+        assert!(PROMISES.lock().get(&7).is_none());
+    }
+
+    #[test]
+    fn test_js_promise_settled_before_await() {
+        promise_settled(8, false, 0.0);
+        let mut executor = LocalExecutor::new();
+        executor.spawn(async {
+            let settled = JsPromise::<f64>::from_handle(8).await;
+            assert!(matches!(settled, Err(InteropError::HostError(_))));
+        });
+        executor.run_until_stalled();
+        assert_eq!(executor.pending_tasks(), 0);
+    }
+
+    #[test]
+    fn test_promise_value_conversions() {
+        assert!(bool::from_js(1.0));
+        assert_eq!(u32::from_js(4_000_000_000.0), 4_000_000_000);
+        assert_eq!((-3i32).to_js(), -3.0);
+        assert!(matches!(PromiseResolver::<u32>::new(), Err(InteropError::UnsupportedOperation)));
+    }
+}
@@ -0,0 +1,396 @@
+//! TypeScript declaration generation for compiled modules
+//!
+//! Produces a `.d.ts` file matching the JavaScript glue emitted by the
+//! backend, so web developers get autocompletion and type checking for
+//! exported functions and `#[wasm::component]` types.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::wasmir::{ElementType, ExportKind, InteropType, Type, WasmModule};
+
+/// Module system the declarations describe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TsModuleKind {
+    /// ES module glue, which also exports an async `init`
+    #[default]
+    EsModule,
+    /// CommonJS glue, instantiated synchronously on load
+    CommonJs,
+}
+
+/// Shape of a component type exposed to TypeScript
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComponentTypeKind {
+    /// Named fields, emitted as an interface
+    Record(Vec<(String, Type)>),
+    /// Unit variants, emitted as a string literal union
+    Enum(Vec<String>),
+}
+
+/// Description of a `#[wasm::component]` type
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentTypeDef {
+    pub name: String,
+    pub kind: ComponentTypeKind,
+}
+
+impl ComponentTypeDef {
+    /// Describes a record type
+    pub fn record(name: impl Into<String>, fields: Vec<(String, Type)>) -> Self {
+        Self { name: name.into(), kind: ComponentTypeKind::Record(fields) }
+    }
+
+    /// Describes an enum type
+    pub fn enumeration(name: impl Into<String>, cases: Vec<String>) -> Self {
+        Self { name: name.into(), kind: ComponentTypeKind::Enum(cases) }
+    }
+}
+
+/// Implemented by `#[wasm::component]` types to describe their shape
+pub trait ComponentTypeInfo {
+    fn component_type() -> ComponentTypeDef;
+}
+
+/// TypeScript binding generation errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TsBindingsError {
+    /// An export refers to a function the module does not define
+    UnknownFunction(String),
+    /// Two component types share a name
+    DuplicateType(String),
+    /// A name cannot be used as a TypeScript identifier
+    InvalidName(String),
+}
+
+impl core::fmt::Display for TsBindingsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TsBindingsError::UnknownFunction(name) => write!(f, "Export of unknown function: {}", name),
+            TsBindingsError::DuplicateType(name) => write!(f, "Duplicate component type: {}", name),
+            TsBindingsError::InvalidName(name) => write!(f, "Invalid TypeScript name: {}", name),
+        }
+    }
+}
+
+/// Generates `.d.ts` declarations for a module
+#[derive(Debug, Clone, Default)]
+pub struct TsBindingsGenerator {
+    kind: TsModuleKind,
+    types: Vec<ComponentTypeDef>,
+}
+
+impl TsBindingsGenerator {
+    /// Creates a generator for ES module glue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the module system of the accompanying glue
+    pub fn module_kind(mut self, kind: TsModuleKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Adds a component type description
+    pub fn component_type(mut self, def: ComponentTypeDef) -> Self {
+        self.types.push(def);
+        self
+    }
+
+    /// Adds a `#[wasm::component]` type
+    pub fn register<T: ComponentTypeInfo>(self) -> Self {
+        self.component_type(T::component_type())
+    }
+
+    /// Generates the declaration file contents
+    pub fn generate(&self, module: &WasmModule) -> Result<String, TsBindingsError> {
+        let mut out = String::new();
+        out.push_str("// Generated by WasmRust. Do not edit.\n\n");
+
+        self.generate_types(&mut out)?;
+        self.generate_init(&mut out);
+        if module.uses_closures() {
+            out.push_str("export function closure(handle: number): ((...args: any[]) => any) & { free(): void };\n\n");
+        }
+        self.generate_functions(&mut out, module)?;
+
+        Ok(out)
+    }
+
+    fn generate_types(&self, out: &mut String) -> Result<(), TsBindingsError> {
+        for (i, def) in self.types.iter().enumerate() {
+            if !is_identifier(&def.name) {
+                return Err(TsBindingsError::InvalidName(def.name.clone()));
+            }
+            if self.types[..i].iter().any(|other| other.name == def.name) {
+                return Err(TsBindingsError::DuplicateType(def.name.clone()));
+            }
+
+            match &def.kind {
+                ComponentTypeKind::Record(fields) => {
+                    let _ = writeln!(out, "export interface {} {{", def.name);
+                    for (field, ty) in fields {
+                        let _ = writeln!(out, "  {}: {};", property_name(field), self.ts_type(ty));
+                    }
+                    out.push_str("}\n\n");
+                }
+                ComponentTypeKind::Enum(cases) => {
+                    let union = if cases.is_empty() {
+                        "never".to_string()
+                    } else {
+                        cases.iter().map(|case| ts_string(case)).collect::<Vec<_>>().join(" | ")
+                    };
+                    let _ = writeln!(out, "export type {} = {};\n", def.name, union);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_init(&self, out: &mut String) {
+        out.push_str("export type InitInput = BufferSource | WebAssembly.Module;\n\n");
+        out.push_str(
+            "export function initSync(bytes: InitInput, userImports?: WebAssembly.Imports): WebAssembly.Exports;\n\n",
+        );
+        if self.kind == TsModuleKind::EsModule {
+            out.push_str(
+                "export function init(source?: string | URL | Response | BufferSource | \
+                 Promise<Response>, userImports?: WebAssembly.Imports): Promise<WebAssembly.Exports>;\n\n",
+            );
+        }
+    }
+
+    fn generate_functions(&self, out: &mut String, module: &WasmModule) -> Result<(), TsBindingsError> {
+        for export in &module.exports {
+            let index = match export.kind {
+                ExportKind::Function(index) if !export.name.starts_with("__") => index,
+                _ => continue,
+            };
+            let function = module.functions.get(index as usize)
+                .ok_or_else(|| TsBindingsError::UnknownFunction(export.name.clone()))?;
+
+            let mut params: Vec<String> = function.signature.params.iter()
+                .map(|ty| self.ts_type(ty))
+                .collect();
+            let mut returns = function.signature.returns.as_ref()
+                .map_or_else(|| "void".to_string(), |ty| self.ts_type(ty));
+
+            // Collapse pointer/length pairs back to strings and typed arrays
+            if let Some(interop) = module.interop_signature(index).filter(|sig| sig.needs_marshaling()) {
+                let mut lowered = params.into_iter().skip(usize::from(interop.has_return_area()));
+                params = interop.params.iter()
+                    .map(|kind| match kind {
+                        InteropType::Value | InteropType::ExternRef => {
+                            lowered.next().unwrap_or_else(|| "unknown".to_string())
+                        }
+                        pair => {
+                            lowered.nth(1);
+                            interop_param_type(*pair)
+                        }
+                    })
+                    .collect();
+                match interop.returns {
+                    InteropType::Str => returns = "string".to_string(),
+                    InteropType::Vec(element) => returns = element.typed_array().to_string(),
+                    _ => {}
+                }
+            }
+            let params: Vec<String> = params.iter()
+                .enumerate()
+                .map(|(i, ty)| format!("arg{}: {}", i, ty))
+                .collect();
+
+            let _ = writeln!(
+                out,
+                "export function {}({}): {};",
+                ts_identifier(&export.name),
+                params.join(", "),
+                returns
+            );
+        }
+        Ok(())
+    }
+
+    /// TypeScript type for a WasmIR type
+    fn ts_type(&self, ty: &Type) -> String {
+        match ty {
+            Type::I32 | Type::F32 | Type::F64 | Type::Pointer(_) => "number".to_string(),
+            Type::I64 | Type::I128 => "bigint".to_string(),
+            Type::FuncRef => "Function".to_string(),
+            Type::Void => "void".to_string(),
+            Type::ExternRef(name) if self.types.iter().any(|def| def.name == *name) => name.clone(),
+            Type::Array { element_type, .. } => format!("Array<{}>", self.ts_type(element_type)),
+            Type::Linear { inner_type } | Type::Capability { inner_type, .. } => self.ts_type(inner_type),
+            Type::Promise(inner) => format!("Promise<{}>", self.ts_type(inner)),
+            Type::ExternRef(_) | Type::Struct { .. } => "unknown".to_string(),
+        }
+    }
+}
+
+/// TypeScript type accepted for a marshaled parameter
+fn interop_param_type(kind: InteropType) -> String {
+    let scalar = |element: ElementType| if element == ElementType::I64 { "bigint" } else { "number" };
+    match kind {
+        InteropType::Str => "string".to_string(),
+        InteropType::Slice(element) | InteropType::Vec(element) => {
+            format!("{} | ArrayLike<{}>", element.typed_array(), scalar(element))
+        }
+        InteropType::SliceMut(element) => format!("{} | {}[]", element.typed_array(), scalar(element)),
+        InteropType::Value | InteropType::ExternRef => "unknown".to_string(),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Quotes a property name unless it is a plain identifier
+fn property_name(name: &str) -> String {
+    if is_identifier(name) { name.to_string() } else { ts_string(name) }
+}
+
+/// Mirrors the identifier mangling used for glue wrappers
+fn ts_identifier(name: &str) -> String {
+    let mut ident: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '$' { c } else { '_' })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) || is_reserved(&ident) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+fn is_reserved(ident: &str) -> bool {
+    matches!(
+        ident,
+        "break" | "case" | "catch" | "class" | "const" | "continue" | "debugger" | "default"
+            | "delete" | "do" | "else" | "export" | "extends" | "finally" | "for" | "function"
+            | "if" | "import" | "in" | "instanceof" | "new" | "return" | "super" | "switch"
+            | "this" | "throw" | "try" | "typeof" | "var" | "void" | "while" | "with" | "yield"
+            | "let" | "static" | "enum" | "await" | "init" | "initSync" | "exports"
+            | "closure"
+    )
+}
+
+fn ts_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::{InteropSignature, Signature, Terminator, WasmIR, CLOSURE_NEW_IMPORT, JS_IMPORT_MODULE};
+    use alloc::vec;
+
+    struct Point;
+
+    impl ComponentTypeInfo for Point {
+        fn component_type() -> ComponentTypeDef {
+            ComponentTypeDef::record("Point", vec![
+                ("x".to_string(), Type::F64),
+                ("y".to_string(), Type::F64),
+            ])
+        }
+    }
+
+    fn exported(name: &str, params: Vec<Type>, returns: Option<Type>) -> WasmModule {
+        let mut function = WasmIR::new(name.to_string(), Signature { params, returns });
+        function.add_basic_block(Vec::new(), Terminator::Return { value: None });
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function(name, index);
+        module
+    }
+
+    #[test]
+    fn test_function_declarations() {
+        let module = exported("add", vec![Type::I32, Type::I64], Some(Type::F64));
+        let dts = TsBindingsGenerator::new().generate(&module).unwrap();
+
+        assert!(dts.contains("export function add(arg0: number, arg1: bigint): number;"));
+        assert!(dts.contains("export function init("));
+        assert!(dts.contains("export function initSync("));
+        assert!(!dts.contains("export function closure("));
+
+        let mut module = module;
+        module.add_import(JS_IMPORT_MODULE, CLOSURE_NEW_IMPORT, Signature {
+            params: vec![Type::I32; 3],
+            returns: Some(Type::I32),
+        });
+        let dts = TsBindingsGenerator::new().generate(&module).unwrap();
+        assert!(dts.contains("export function closure(handle: number): ((...args: any[]) => any) & { free(): void };"));
+    }
+
+    #[test]
+    fn test_async_export_returns_promise() {
+        let module = exported("load", vec![Type::I32], Some(Type::Promise(alloc::boxed::Box::new(Type::F64))));
+        let dts = TsBindingsGenerator::new().generate(&module).unwrap();
+        assert!(dts.contains("export function load(arg0: number): Promise<number>;"));
+    }
+
+    #[test]
+    fn test_component_types() {
+        let module = exported("center", vec![Type::ExternRef("Point".to_string())], None);
+        let dts = TsBindingsGenerator::new()
+            .module_kind(TsModuleKind::CommonJs)
+            .register::<Point>()
+            .component_type(ComponentTypeDef::enumeration("Axis", vec!["x".to_string(), "y".to_string()]))
+            .generate(&module)
+            .unwrap();
+
+        assert!(dts.contains("export interface Point {\n  x: number;\n  y: number;\n}"));
+        assert!(dts.contains("export type Axis = \"x\" | \"y\";"));
+        assert!(dts.contains("export function center(arg0: Point): void;"));
+        assert!(!dts.contains("export function init("));
+    }
+
+    #[test]
+    fn test_string_abi_parameters() {
+        let mut module = exported("greet", vec![Type::I32, Type::I32, Type::I32, Type::F64], None);
+        module.set_interop_signature(0, InteropSignature {
+            params: vec![InteropType::Str, InteropType::Value],
+            returns: InteropType::Str,
+        });
+        let dts = TsBindingsGenerator::new().generate(&module).unwrap();
+
+        assert!(dts.contains("export function greet(arg0: string, arg1: number): string;"));
+
+        let mut module = exported("scale", vec![Type::I32; 5], None);
+        module.set_interop_signature(0, InteropSignature {
+            params: vec![InteropType::Slice(ElementType::F32), InteropType::SliceMut(ElementType::I64)],
+            returns: InteropType::Vec(ElementType::U8),
+        });
+        let dts = TsBindingsGenerator::new().generate(&module).unwrap();
+
+        assert!(dts.contains(
+            "export function scale(arg0: Float32Array | ArrayLike<number>, arg1: BigInt64Array | bigint[]): Uint8Array;"
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_component_type() {
+        let result = TsBindingsGenerator::new()
+            .register::<Point>()
+            .register::<Point>()
+            .generate(&WasmModule::new());
+        assert_eq!(result, Err(TsBindingsError::DuplicateType("Point".to_string())));
+    }
+}
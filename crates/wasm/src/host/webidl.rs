@@ -0,0 +1,837 @@
+//! Typed DOM/WebAPI bindings generated from WebIDL
+//!
+//! Build scripts parse a curated WebIDL subset with `WebIdl::parse` and
+//! write `generate_rust` to a file, giving typed `OwnedExternRef` wrappers
+//! such as `web::Document` whose methods call `wasmrust` imports. Objects
+//! returned by imports are owned and unrooted when the wrapper drops. The compiler
+//! side uses the same definitions to lower calls to `JSMethodCall`,
+//! `ExternRefLoad`, and `ExternRefStore` with the right signatures.
+//!
+//! Supported: `interface` and `partial interface` with attributes and
+//! regular operations over primitives, `DOMString`, interfaces, `any`/
+//! `object`, nullable interfaces, and `Promise<T>` of primitives. Other
+//! definitions, `const`, `static`, and `constructor` members are skipped.
+//! Optional arguments are bound as required ones.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::wasmir::{Import, Instruction, Operand, Signature, Type, WasmModule, JS_IMPORT_MODULE};
+
+/// Curated subset of the DOM used when no WebIDL is supplied
+pub const DOM_SUBSET: &str = r#"
+interface EventTarget {
+  undefined dispatchEvent(Event event);
+};
+
+interface Event {
+  readonly attribute DOMString type;
+  undefined preventDefault();
+};
+
+interface Node : EventTarget {
+  readonly attribute Node? parentNode;
+  attribute DOMString? textContent;
+  Node appendChild(Node node);
+  Node removeChild(Node child);
+};
+
+interface Element : Node {
+  readonly attribute DOMString tagName;
+  attribute DOMString id;
+  DOMString? getAttribute(DOMString qualifiedName);
+  undefined setAttribute(DOMString qualifiedName, DOMString value);
+  Element? querySelector(DOMString selectors);
+};
+
+interface HTMLElement : Element {
+  attribute boolean hidden;
+  undefined click();
+  undefined focus();
+};
+
+interface Document : Node {
+  readonly attribute HTMLElement? body;
+  Element createElement(DOMString localName);
+  Element? getElementById(DOMString elementId);
+  Element? querySelector(DOMString selectors);
+};
+
+interface Window : EventTarget {
+  readonly attribute Document document;
+  readonly attribute double devicePixelRatio;
+  long setTimeout(any handler, long timeout);
+  undefined clearTimeout(long id);
+  long requestAnimationFrame(any callback);
+};
+"#;
+
+/// WebIDL binding errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebIdlError {
+    /// Malformed WebIDL
+    Parse { line: usize, message: String },
+    /// Type outside the supported subset
+    UnsupportedType(String),
+    /// Interface that was never defined
+    UnknownInterface(String),
+    /// Member not found on an interface or its ancestors
+    UnknownMember { interface: String, member: String },
+    /// Two interfaces bind one method or property name with different signatures
+    ConflictingSignature(String),
+}
+
+impl core::fmt::Display for WebIdlError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WebIdlError::Parse { line, message } => write!(f, "WebIDL parse error on line {}: {}", line, message),
+            WebIdlError::UnsupportedType(ty) => write!(f, "Unsupported WebIDL type: {}", ty),
+            WebIdlError::UnknownInterface(name) => write!(f, "Unknown interface: {}", name),
+            WebIdlError::UnknownMember { interface, member } => {
+                write!(f, "Interface {} has no member {}", interface, member)
+            }
+            WebIdlError::ConflictingSignature(name) => write!(f, "Conflicting signatures for {}", name),
+        }
+    }
+}
+
+/// WebIDL type within the supported subset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdlType {
+    Undefined,
+    Boolean,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+    Float,
+    Double,
+    DomString,
+    /// `any` or `object`
+    Any,
+    Interface(String),
+    Nullable(alloc::boxed::Box<IdlType>),
+    Promise(alloc::boxed::Box<IdlType>),
+}
+
+impl IdlType {
+    /// WasmIR type the value crosses the boundary as, `None` for `undefined`
+    pub fn wasm_type(&self) -> Option<Type> {
+        Some(match self {
+            IdlType::Undefined => return None,
+            IdlType::Boolean | IdlType::Long | IdlType::UnsignedLong => Type::I32,
+            IdlType::LongLong | IdlType::UnsignedLongLong => Type::I64,
+            IdlType::Float => Type::F32,
+            IdlType::Double => Type::F64,
+            IdlType::DomString => Type::ExternRef("DOMString".to_string()),
+            IdlType::Any => Type::ExternRef("Object".to_string()),
+            IdlType::Interface(name) => Type::ExternRef(name.clone()),
+            IdlType::Nullable(inner) => return inner.wasm_type(),
+            IdlType::Promise(inner) => Type::Promise(alloc::boxed::Box::new(inner.wasm_type().unwrap_or(Type::I32))),
+        })
+    }
+
+    /// Rust type used in generated bindings
+    fn rust_type(&self) -> Result<String, WebIdlError> {
+        Ok(match self {
+            IdlType::Undefined => "()".to_string(),
+            IdlType::Boolean => "bool".to_string(),
+            IdlType::Long => "i32".to_string(),
+            IdlType::UnsignedLong => "u32".to_string(),
+            IdlType::LongLong => "i64".to_string(),
+            IdlType::UnsignedLongLong => "u64".to_string(),
+            IdlType::Float => "f32".to_string(),
+            IdlType::Double => "f64".to_string(),
+            IdlType::DomString => "DomString".to_string(),
+            IdlType::Any => "JsObject".to_string(),
+            IdlType::Interface(name) => name.clone(),
+            IdlType::Nullable(inner) if inner.is_reference() => format!("Option<{}>", inner.rust_type()?),
+            IdlType::Promise(inner) if !inner.is_reference() && !matches!(**inner, IdlType::Promise(_)) => {
+                format!("JsPromise<{}>", inner.rust_type()?)
+            }
+            other => return Err(WebIdlError::UnsupportedType(format!("{:?}", other))),
+        })
+    }
+
+    /// Rust type of the raw extern parameter or result
+    fn extern_type(&self) -> Result<String, WebIdlError> {
+        Ok(match self {
+            IdlType::DomString => "ExternRef<DomString>".to_string(),
+            IdlType::Any => "ExternRef<JsObject>".to_string(),
+            IdlType::Interface(name) => format!("ExternRef<{}>", name),
+            IdlType::Nullable(inner) => inner.extern_type()?,
+            IdlType::Promise(_) => "u32".to_string(),
+            _ => self.rust_type()?,
+        })
+    }
+
+    /// Converts a raw extern result to the Rust binding type
+    fn wrap_result(&self, raw: &str) -> String {
+        match self {
+            IdlType::DomString => format!("DomString(unsafe {{ OwnedExternRef::from_raw({}) }})", raw),
+            IdlType::Any => format!("JsObject(unsafe {{ OwnedExternRef::from_raw({}) }})", raw),
+            IdlType::Interface(name) => format!("{}(unsafe {{ OwnedExternRef::from_raw({}) }})", name, raw),
+            IdlType::Nullable(inner) => {
+                format!("if {0}.is_null() {{ None }} else {{ Some({1}) }}", raw, inner.wrap_result(raw))
+            }
+            IdlType::Promise(_) => format!("JsPromise::from_handle({})", raw),
+            _ => raw.to_string(),
+        }
+    }
+
+    /// Borrows a Rust binding argument as its raw extern value
+    fn unwrap_arg(&self, name: &str) -> String {
+        match self {
+            IdlType::Nullable(inner) => {
+                format!("{}.as_ref().map_or(ExternRef::null(), |v| {})", name, inner.unwrap_arg("v"))
+            }
+            ty if ty.is_reference() => format!("{}.0.get()", name),
+            _ => name.to_string(),
+        }
+    }
+
+    fn is_reference(&self) -> bool {
+        matches!(self, IdlType::DomString | IdlType::Any | IdlType::Interface(_))
+    }
+}
+
+/// Member of an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdlMember {
+    Attribute { name: String, ty: IdlType, readonly: bool },
+    Operation { name: String, returns: IdlType, args: Vec<(String, IdlType)> },
+}
+
+impl IdlMember {
+    /// Member name as written in WebIDL
+    pub fn name(&self) -> &str {
+        match self {
+            IdlMember::Attribute { name, .. } | IdlMember::Operation { name, .. } => name,
+        }
+    }
+}
+
+/// Interface definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdlInterface {
+    pub name: String,
+    pub parent: Option<String>,
+    pub members: Vec<IdlMember>,
+}
+
+/// Parsed WebIDL definitions
+#[derive(Debug, Clone, Default)]
+pub struct WebIdl {
+    interfaces: BTreeMap<String, IdlInterface>,
+}
+
+impl WebIdl {
+    /// Parses WebIDL source, merging partial interfaces
+    pub fn parse(source: &str) -> Result<Self, WebIdlError> {
+        let mut idl = Self::default();
+        idl.extend(source)?;
+        Ok(idl)
+    }
+
+    /// Parses more WebIDL into these definitions
+    pub fn extend(&mut self, source: &str) -> Result<(), WebIdlError> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+        while let Some(interface) = parser.next_definition()? {
+            match self.interfaces.get_mut(&interface.name) {
+                Some(existing) => {
+                    existing.parent = existing.parent.take().or(interface.parent);
+                    existing.members.extend(interface.members);
+                }
+                None => {
+                    self.interfaces.insert(interface.name.clone(), interface);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets an interface by name
+    pub fn interface(&self, name: &str) -> Option<&IdlInterface> {
+        self.interfaces.get(name)
+    }
+
+    /// Finds a member on an interface or its ancestors
+    pub fn find_member(&self, interface: &str, member: &str) -> Result<&IdlMember, WebIdlError> {
+        let mut current = Some(interface);
+        while let Some(name) = current {
+            let def = self.interfaces.get(name).ok_or_else(|| WebIdlError::UnknownInterface(name.to_string()))?;
+            if let Some(found) = def.members.iter().find(|m| m.name() == member) {
+                return Ok(found);
+            }
+            current = def.parent.as_deref();
+        }
+        Err(WebIdlError::UnknownMember { interface: interface.to_string(), member: member.to_string() })
+    }
+
+    /// Lowers `object.method(args)` to a `JSMethodCall`
+    pub fn lower_call(
+        &self,
+        interface: &str,
+        method: &str,
+        object: Operand,
+        args: Vec<Operand>,
+    ) -> Result<Instruction, WebIdlError> {
+        match self.find_member(interface, method)? {
+            IdlMember::Operation { returns, args: params, .. } if params.len() == args.len() => {
+                Ok(Instruction::JSMethodCall {
+                    object,
+                    method: method.to_string(),
+                    args,
+                    return_type: returns.wasm_type(),
+                })
+            }
+            _ => Err(WebIdlError::UnknownMember { interface: interface.to_string(), member: method.to_string() }),
+        }
+    }
+
+    /// Lowers a read of `object.attribute` to an `ExternRefLoad`
+    pub fn lower_get(&self, interface: &str, attribute: &str, object: Operand) -> Result<Instruction, WebIdlError> {
+        match self.find_member(interface, attribute)? {
+            IdlMember::Attribute { ty, .. } => Ok(Instruction::ExternRefLoad {
+                externref: object,
+                field: attribute.to_string(),
+                field_type: ty.wasm_type().ok_or_else(|| WebIdlError::UnsupportedType(attribute.to_string()))?,
+            }),
+            _ => Err(WebIdlError::UnknownMember { interface: interface.to_string(), member: attribute.to_string() }),
+        }
+    }
+
+    /// Lowers a write of `object.attribute` to an `ExternRefStore`
+    pub fn lower_set(
+        &self,
+        interface: &str,
+        attribute: &str,
+        object: Operand,
+        value: Operand,
+    ) -> Result<Instruction, WebIdlError> {
+        match self.find_member(interface, attribute)? {
+            IdlMember::Attribute { ty, readonly: false, .. } => Ok(Instruction::ExternRefStore {
+                externref: object,
+                field: attribute.to_string(),
+                value,
+                field_type: ty.wasm_type().ok_or_else(|| WebIdlError::UnsupportedType(attribute.to_string()))?,
+            }),
+            _ => Err(WebIdlError::UnknownMember { interface: interface.to_string(), member: attribute.to_string() }),
+        }
+    }
+
+    /// Every `wasmrust` import the bindings use, keyed by import name
+    pub fn imports(&self) -> Result<BTreeMap<String, Signature>, WebIdlError> {
+        let mut imports: BTreeMap<String, Signature> = BTreeMap::new();
+        // Imports are keyed by member name alone, so interfaces sharing a
+        // name share one import whatever the receiver class
+        let mut add = |name: String, signature: Signature| match imports.get(&name) {
+            Some(existing) if existing.params[1..] != signature.params[1..] || existing.returns != signature.returns => {
+                Err(WebIdlError::ConflictingSignature(name))
+            }
+            Some(_) => Ok(()),
+            None => {
+                imports.insert(name, signature);
+                Ok(())
+            }
+        };
+
+        for interface in self.interfaces.values() {
+            let receiver = Type::ExternRef(interface.name.clone());
+            for member in &interface.members {
+                match member {
+                    IdlMember::Attribute { name, ty, readonly } => {
+                        let Some(field_type) = ty.wasm_type() else { continue };
+                        add(Import::js_getter_name(name), Signature {
+                            params: alloc::vec![receiver.clone()],
+                            returns: Some(field_type.clone()),
+                        })?;
+                        if !readonly {
+                            add(Import::js_setter_name(name), Signature {
+                                params: alloc::vec![receiver.clone(), field_type],
+                                returns: None,
+                            })?;
+                        }
+                    }
+                    IdlMember::Operation { name, returns, args } => {
+                        let mut params = alloc::vec![receiver.clone()];
+                        params.extend(args.iter().filter_map(|(_, ty)| ty.wasm_type()));
+                        add(Import::js_method_name(name), Signature { params, returns: returns.wasm_type() })?;
+                    }
+                }
+            }
+        }
+        Ok(imports)
+    }
+
+    /// Declares every binding import on a module
+    pub fn declare_imports(&self, module: &mut WasmModule) -> Result<(), WebIdlError> {
+        for (name, signature) in self.imports()? {
+            module.add_import(JS_IMPORT_MODULE, name, signature);
+        }
+        Ok(())
+    }
+
+    /// Checks whether any member returns or takes a promise
+    fn uses_promises(&self) -> bool {
+        self.interfaces.values().flat_map(|i| &i.members).any(|member| match member {
+            IdlMember::Attribute { ty, .. } => matches!(ty, IdlType::Promise(_)),
+            IdlMember::Operation { returns, args, .. } => {
+                core::iter::once(returns).chain(args.iter().map(|(_, ty)| ty)).any(|ty| matches!(ty, IdlType::Promise(_)))
+            }
+        })
+    }
+
+    /// Generates a Rust `web` module with typed bindings
+    pub fn generate_rust(&self) -> Result<String, WebIdlError> {
+        // Conflicts would otherwise surface as clashing extern declarations
+        self.imports()?;
+
+        let mut out = String::new();
+        out.push_str("// Generated by WasmRust from WebIDL. Do not edit.\n\n");
+        if self.uses_promises() {
+            out.push_str("use wasm::host::promise::JsPromise;\n");
+        }
+        out.push_str("use wasm::host::externref::OwnedExternRef;\n");
+        out.push_str("use wasm::ExternRef;\n\n");
+        write_newtype(&mut out, "DomString", "JavaScript string");
+        write_newtype(&mut out, "JsObject", "Untyped JavaScript value");
+
+        let mut externs = BTreeMap::new();
+        for interface in self.interfaces.values() {
+            write_newtype(&mut out, &interface.name, &format!("`{}` WebIDL interface", interface.name));
+            if let Some(parent) = &interface.parent {
+                self.interface(parent).ok_or_else(|| WebIdlError::UnknownInterface(parent.clone()))?;
+                let _ = writeln!(out, "impl core::ops::Deref for {} {{", interface.name);
+                let _ = writeln!(out, "    type Target = {};\n", parent);
+                let _ = writeln!(out, "    fn deref(&self) -> &{} {{", parent);
+                out.push_str("        // SAFETY: both are transparent wrappers over an owned u32 handle\n");
+                let _ = writeln!(out, "        unsafe {{ &*(self as *const Self as *const {}) }}", parent);
+                out.push_str("    }\n}\n\n");
+            }
+
+            let _ = writeln!(out, "impl {} {{", interface.name);
+            for member in &interface.members {
+                self.write_member(&mut out, &interface.name, member, &mut externs)?;
+            }
+            out.push_str("}\n\n");
+        }
+
+        out.push_str("#[allow(non_snake_case, clashing_extern_declarations)]\n");
+        out.push_str("#[link(wasm_import_module = \"wasmrust\")]\nextern \"C\" {\n");
+        for (ident, (link_name, declaration)) in &externs {
+            let _ = writeln!(out, "    #[link_name = \"{}\"]\n    fn {}{};", link_name, ident, declaration);
+        }
+        out.push_str("}\n");
+        Ok(out)
+    }
+
+    fn write_member(
+        &self,
+        out: &mut String,
+        interface: &str,
+        member: &IdlMember,
+        externs: &mut BTreeMap<String, (String, String)>,
+    ) -> Result<(), WebIdlError> {
+        let receiver = format!("this: ExternRef<{}>", interface);
+        match member {
+            IdlMember::Attribute { name, ty, readonly } => {
+                let getter = format!("{}_get_{}", interface, name);
+                externs.insert(getter.clone(), (
+                    Import::js_getter_name(name),
+                    format!("({}) -> {}", receiver, ty.extern_type()?),
+                ));
+                let _ = writeln!(out, "    /// Gets `{}.{}`", interface, name);
+                let _ = writeln!(out, "    pub fn {}(&self) -> {} {{", snake_case(name), ty.rust_type()?);
+                out.push_str("        // SAFETY: the import reads a property of a live object, rooting any result for us\n");
+                let _ = writeln!(out, "        let raw = unsafe {{ {}(self.0.get()) }};", getter);
+                let _ = writeln!(out, "        {}", ty.wrap_result("raw"));
+                out.push_str("    }\n\n");
+
+                if !readonly {
+                    let setter = format!("{}_set_{}", interface, name);
+                    externs.insert(setter.clone(), (
+                        Import::js_setter_name(name),
+                        format!("({}, value: {})", receiver, ty.extern_type()?),
+                    ));
+                    let value = ty.unwrap_arg("value");
+                    let _ = writeln!(out, "    /// Sets `{}.{}`", interface, name);
+                    let _ = writeln!(out, "    pub fn set_{}(&self, value: {}) {{", snake_case(name), ty.rust_type()?);
+                    out.push_str("        // SAFETY: the import writes a property of a live object\n");
+                    let _ = writeln!(out, "        unsafe {{ {}(self.0.get(), {}) }}", setter, value);
+                    out.push_str("    }\n\n");
+                }
+            }
+            IdlMember::Operation { name, returns, args } => {
+                let ident = format!("{}_{}", interface, name);
+                let mut raw_params = alloc::vec![receiver];
+                let mut params = alloc::vec!["&self".to_string()];
+                let mut call_args = alloc::vec!["self.0.get()".to_string()];
+                for (arg, ty) in args {
+                    let arg = snake_case(arg);
+                    raw_params.push(format!("{}: {}", arg, ty.extern_type()?));
+                    params.push(format!("{}: {}{}", arg, if ty.is_reference() { "&" } else { "" }, ty.rust_type()?));
+                    call_args.push(ty.unwrap_arg(&arg));
+                }
+                let returns_rust = match returns {
+                    IdlType::Undefined => String::new(),
+                    ty => format!(" -> {}", ty.rust_type()?),
+                };
+                let returns_raw = match returns {
+                    IdlType::Undefined => String::new(),
+                    ty => format!(" -> {}", ty.extern_type()?),
+                };
+                externs.insert(ident.clone(), (
+                    Import::js_method_name(name),
+                    format!("({}){}", raw_params.join(", "), returns_raw),
+                ));
+
+                let _ = writeln!(out, "    /// Calls `{}.{}()`", interface, name);
+                let _ = writeln!(out, "    pub fn {}({}){} {{", snake_case(name), params.join(", "), returns_rust);
+                out.push_str("        // SAFETY: the import calls a method of a live object, rooting any result for us\n");
+                let _ = writeln!(out, "        let raw = unsafe {{ {}({}) }};", ident, call_args.join(", "));
+                let _ = writeln!(out, "        {}", returns.wrap_result("raw"));
+                out.push_str("    }\n\n");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_newtype(out: &mut String, name: &str, doc: &str) {
+    let _ = writeln!(out, "/// {}", doc);
+    out.push_str("#[repr(transparent)]\n#[derive(Debug, Clone)]\n");
+    let _ = writeln!(out, "pub struct {0}(pub OwnedExternRef<{0}>);\n", name);
+}
+
+/// Converts a camelCase WebIDL name to snake_case
+fn snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    let chars: Vec<char> = name.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() {
+            let prev_lower = i > 0 && !chars[i - 1].is_ascii_uppercase();
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_ascii_lowercase());
+            if i > 0 && (prev_lower || next_lower) {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    if matches!(out.as_str(), "type" | "fn" | "ref" | "match" | "move" | "loop" | "self") {
+        out.insert_str(0, "r#");
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Punct(char),
+    Other,
+}
+
+/// Splits WebIDL into tokens tagged with their line numbers
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, WebIdlError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            prev = c;
+                        }
+                        None => return Err(WebIdlError::Parse { line, message: "unterminated comment".to_string() }),
+                    }
+                }
+            }
+            '"' => {
+                while chars.next_if(|&c| c != '"').is_some() {}
+                chars.next();
+                tokens.push((Token::Other, line));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = String::from(c);
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    ident.push(c);
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                while chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '.').is_some() {}
+                tokens.push((Token::Other, line));
+            }
+            c => tokens.push((Token::Punct(c), line)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: impl Into<String>) -> WebIdlError {
+        WebIdlError::Parse { line: self.line(), message: message.into() }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(name)) if name == ident) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn eat_punct(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, punct: char) -> Result<(), WebIdlError> {
+        if self.eat_punct(punct) { Ok(()) } else { Err(self.error(format!("expected `{}`", punct))) }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, WebIdlError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected an identifier"))
+            }
+        }
+    }
+
+    /// Skips `[...]` extended attributes
+    fn skip_extended_attributes(&mut self) -> Result<(), WebIdlError> {
+        while self.eat_punct('[') {
+            self.skip_until(']')?;
+        }
+        Ok(())
+    }
+
+    /// Skips past the matching `close`, honouring nested brackets
+    fn skip_until(&mut self, close: char) -> Result<(), WebIdlError> {
+        let mut depth = 0usize;
+        loop {
+            match self.next() {
+                Some(Token::Punct(c)) if c == close && depth == 0 => return Ok(()),
+                Some(Token::Punct('{' | '(' | '[')) => depth += 1,
+                Some(Token::Punct('}' | ')' | ']')) => depth = depth.saturating_sub(1),
+                Some(_) => {}
+                None => return Err(self.error(format!("expected `{}`", close))),
+            }
+        }
+    }
+
+    /// Parses the next interface, skipping unsupported definitions
+    fn next_definition(&mut self) -> Result<Option<IdlInterface>, WebIdlError> {
+        loop {
+            self.skip_extended_attributes()?;
+            if self.peek().is_none() {
+                return Ok(None);
+            }
+            self.eat_ident("partial");
+            if !self.eat_ident("interface") || self.eat_ident("mixin") {
+                self.skip_until(';')?;
+                continue;
+            }
+
+            let name = self.expect_ident()?;
+            let parent = if self.eat_punct(':') { Some(self.expect_ident()?) } else { None };
+            self.expect_punct('{')?;
+            let mut members = Vec::new();
+            while !self.eat_punct('}') {
+                if let Some(member) = self.member()? {
+                    members.push(member);
+                }
+            }
+            self.expect_punct(';')?;
+            return Ok(Some(IdlInterface { name, parent, members }));
+        }
+    }
+
+    fn member(&mut self) -> Result<Option<IdlMember>, WebIdlError> {
+        self.skip_extended_attributes()?;
+        if matches!(
+            self.peek(),
+            Some(Token::Ident(kw)) if matches!(kw.as_str(), "const" | "static" | "constructor" | "stringifier"
+                | "iterable" | "maplike" | "setlike" | "getter" | "setter" | "deleter" | "inherit")
+        ) {
+            self.skip_until(';')?;
+            return Ok(None);
+        }
+
+        let readonly = self.eat_ident("readonly");
+        if self.eat_ident("attribute") {
+            let ty = self.idl_type()?;
+            let name = self.expect_ident()?;
+            self.expect_punct(';')?;
+            return Ok(Some(IdlMember::Attribute { name, ty, readonly }));
+        }
+        if readonly {
+            return Err(self.error("expected `attribute` after `readonly`"));
+        }
+
+        let returns = self.idl_type()?;
+        let name = self.expect_ident()?;
+        self.expect_punct('(')?;
+        let mut args = Vec::new();
+        while !self.eat_punct(')') {
+            self.skip_extended_attributes()?;
+            self.eat_ident("optional");
+            let ty = self.idl_type()?;
+            let arg = self.expect_ident()?;
+            if self.eat_punct('=') {
+                self.next();
+            }
+            args.push((arg, ty));
+            if !self.eat_punct(',') {
+                self.expect_punct(')')?;
+                break;
+            }
+        }
+        self.expect_punct(';')?;
+        Ok(Some(IdlMember::Operation { name, returns, args }))
+    }
+
+    fn idl_type(&mut self) -> Result<IdlType, WebIdlError> {
+        self.eat_ident("unrestricted");
+        let unsigned = self.eat_ident("unsigned");
+        let name = self.expect_ident()?;
+        let mut ty = match name.as_str() {
+            "undefined" | "void" => IdlType::Undefined,
+            "boolean" => IdlType::Boolean,
+            "byte" | "octet" | "short" => IdlType::Long,
+            "long" if self.eat_ident("long") => {
+                if unsigned { IdlType::UnsignedLongLong } else { IdlType::LongLong }
+            }
+            "long" => if unsigned { IdlType::UnsignedLong } else { IdlType::Long },
+            "float" => IdlType::Float,
+            "double" => IdlType::Double,
+            "DOMString" | "USVString" | "ByteString" => IdlType::DomString,
+            "any" | "object" => IdlType::Any,
+            "Promise" => {
+                self.expect_punct('<')?;
+                let inner = self.idl_type()?;
+                self.expect_punct('>')?;
+                IdlType::Promise(alloc::boxed::Box::new(inner))
+            }
+            "sequence" | "record" | "FrozenArray" | "ObservableArray" => {
+                return Err(WebIdlError::UnsupportedType(name));
+            }
+            _ => IdlType::Interface(name),
+        };
+        if self.eat_punct('?') {
+            ty = IdlType::Nullable(alloc::boxed::Box::new(ty));
+        }
+        Ok(ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_curated_dom_subset() {
+        let idl = WebIdl::parse(DOM_SUBSET).unwrap();
+        let document = idl.interface("Document").unwrap();
+        assert_eq!(document.parent.as_deref(), Some("Node"));
+
+        // Inherited from Node
+        assert!(matches!(
+            idl.find_member("Document", "appendChild"),
+            Ok(IdlMember::Operation { returns: IdlType::Interface(name), .. }) if name == "Node"
+        ));
+        assert!(matches!(
+            idl.find_member("Document", "missing"),
+            Err(WebIdlError::UnknownMember { .. })
+        ));
+
+        let err = WebIdl::parse("interface A {\n  attribute sequence<long> values;\n};").unwrap_err();
+        assert_eq!(err, WebIdlError::UnsupportedType("sequence".to_string()));
+        let err = WebIdl::parse("interface A {\n  readonly long x;\n};").unwrap_err();
+        assert!(matches!(err, WebIdlError::Parse { line: 2, .. }));
+    }
+
+    #[test]
+    fn test_calls_lower_to_typed_js_instructions() {
+        let idl = WebIdl::parse(DOM_SUBSET).unwrap();
+        let call = idl.lower_call("Document", "getElementById", Operand::Local(0), alloc::vec![Operand::Local(1)]).unwrap();
+        assert!(matches!(
+            call,
+            Instruction::JSMethodCall { method, return_type: Some(Type::ExternRef(class)), .. }
+                if method == "getElementById" && class == "Element"
+        ));
+        assert!(idl.lower_call("Document", "getElementById", Operand::Local(0), Vec::new()).is_err());
+        assert!(idl.lower_set("Element", "tagName", Operand::Local(0), Operand::Local(1)).is_err());
+
+        let load = idl.lower_get("Window", "devicePixelRatio", Operand::Local(0)).unwrap();
+        assert!(matches!(load, Instruction::ExternRefLoad { field_type: Type::F64, .. }));
+
+        let mut module = WasmModule::new();
+        idl.declare_imports(&mut module).unwrap();
+        let set_timeout = module.find_import(JS_IMPORT_MODULE, "js_call:setTimeout").unwrap();
+        assert_eq!(module.imports[set_timeout as usize].signature().returns, Some(Type::I32));
+        assert!(module.find_import(JS_IMPORT_MODULE, "js_set:hidden").is_some());
+
+        let err = WebIdl::parse("interface A { long f(); };\ninterface B { double f(); };").unwrap().imports();
+        assert_eq!(err.unwrap_err(), WebIdlError::ConflictingSignature("js_call:f".to_string()));
+    }
+
+    #[test]
+    fn test_generated_rust_bindings() {
+        let rust = WebIdl::parse(DOM_SUBSET).unwrap().generate_rust().unwrap();
+        assert!(rust.contains("pub struct Document(pub OwnedExternRef<Document>);"));
+        assert!(rust.contains("impl core::ops::Deref for HTMLElement {\n    type Target = Element;"));
+        assert!(rust.contains("    pub fn get_element_by_id(&self, element_id: &DomString) -> Option<Element> {"));
+        assert!(rust.contains("        let raw = unsafe { Document_getElementById(self.0.get(), element_id.0.get()) };"));
+        assert!(rust.contains("Some(Element(unsafe { OwnedExternRef::from_raw(raw) }))"));
+        assert!(rust.contains("    pub fn set_timeout(&self, handler: &JsObject, timeout: i32) -> i32 {"));
+        assert!(rust.contains("    pub fn set_text_content(&self, value: Option<DomString>) {"));
+        assert!(rust.contains(
+            "    #[link_name = \"js_call:appendChild\"]\n    fn Node_appendChild(this: ExternRef<Node>, node: ExternRef<Node>) -> ExternRef<Node>;"
+        ));
+        assert_eq!(snake_case("HTMLElement"), "html_element");
+        assert_eq!(snake_case("type"), "r#type");
+    }
+}
@@ -5,6 +5,7 @@
 //! zero-cost wrappers, and safe memory abstractions.
 
 #![no_std]
+#![cfg_attr(all(target_arch = "wasm32", target_feature = "atomics"), feature(thread_local))]
 // Remove unstable features for now - will add back when needed
 // #![feature(extern_types)]
 // #![feature(unsize)]
@@ -12,6 +13,8 @@
 // #![feature(min_specialization)]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 use alloc::vec::Vec;
 use alloc::string::{String, ToString};
 use alloc::boxed::Box;
@@ -21,12 +24,18 @@ use core::slice;
 use core::mem;
 use core::ops::{Deref, DerefMut, Index, IndexMut};
 
+pub mod bench;
 pub mod host;
+pub mod linear;
 pub mod memory;
+pub mod panic;
 pub mod threading;
 pub mod component;
 pub mod wasmir;
 
+#[cfg(feature = "macros")]
+pub use wasm_macros::{bench, bindgen, component, conditional_type_alias, export, gc, import, linear};
+
 use host::{HostProfile, HostCapabilities, get_host_capabilities};
 
 /// Error types for WASM operations
@@ -104,7 +113,9 @@ impl core::fmt::Display for WasmError {
 /// - The type contains no pointers or references
 /// - The type has no interior mutability
 /// - The type has no drop glue
-/// - All bit patterns are valid
+/// - All bit patterns are valid, except for `bool` (and arrays of it),
+///   whose bytes must be 0 or 1; APIs that read `T` from bytes they did
+///   not get from a `T` reject such types
 ///
 /// ## Undefined Behavior
 ///
@@ -135,7 +146,10 @@ pub unsafe trait Pod: Copy + Send + Sync + 'static + private::Sealed {
 
 /// Private module to seal the Pod trait
 mod private {
-    pub trait Sealed {}
+    pub trait Sealed {
+        /// Whether every bit pattern is a valid value
+        const ANY_BIT_PATTERN: bool = true;
+    }
 }
 
 // Implement Pod for primitive types with sealed trait
@@ -163,7 +177,9 @@ impl private::Sealed for i32 {}
 impl private::Sealed for i64 {}
 impl private::Sealed for f32 {}
 impl private::Sealed for f64 {}
-impl private::Sealed for bool {}
+impl private::Sealed for bool {
+    const ANY_BIT_PATTERN: bool = false;
+}
 impl private::Sealed for () {}
 
 /// Implement Pod for arrays of Pod types with compile-time verification
@@ -176,7 +192,9 @@ unsafe impl<T: Pod, const N: usize> Pod for [T; N] {
     }
 }
 
-impl<T: Pod, const N: usize> private::Sealed for [T; N] {}
+impl<T: Pod, const N: usize> private::Sealed for [T; N] {
+    const ANY_BIT_PATTERN: bool = T::ANY_BIT_PATTERN;
+}
 
 // Note: Slices [T] cannot implement Pod as they are unsized
 // Users must work with SharedSlice<'a, T> instead
@@ -1018,10 +1036,15 @@ pub fn runtime_version() -> &'static str {
 }
 
 /// Checks if a capability is available in the current host
+///
+/// `atomic_memory` reports whether this build has shared-memory atomics,
+/// which is false in the fallback variant the glue loads on hosts without
+/// `SharedArrayBuffer`.
 pub fn has_capability(cap: &str) -> bool {
     let caps = get_host_capabilities();
     
     match cap {
+        "atomic_memory" => cfg!(any(not(target_arch = "wasm32"), target_feature = "atomics")),
         "threading" => caps.threading,
         "component_model" => caps.component_model,
         "memory_regions" => caps.memory_regions,
@@ -1153,6 +1176,9 @@ mod tests {
         let result = has_capability("threading");
         // Result depends on host environment, but should not panic
         let _ = result;
+
+        // Native builds always have atomics
+        assert!(has_capability("atomic_memory"));
         
         // Unknown capability should return false
         assert!(!has_capability("unknown_capability"));
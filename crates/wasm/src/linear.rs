@@ -0,0 +1,42 @@
+//! Linear types declared with `#[wasm::linear]`
+//!
+//! A linear value is used exactly once: moved into a function that takes
+//! ownership, or taken apart with the `consume` method the macro generates.
+//! The macro strips `Clone` and `Copy` from the type's derives and gives it
+//! a drop bomb, a `Drop` impl that calls `implicit_drop` in debug builds.
+//! The WasmRust compiler recognizes the `Linear` marker and reports such
+//! drops at compile time instead, through its ownership verification.
+
+/// Marker implemented by `#[wasm::linear]` types
+pub trait Linear {}
+
+/// Path of `Linear`, by which the compiler finds linear types
+pub const LINEAR_TRAIT_PATH: &str = "wasm::linear::Linear";
+
+/// Reports a linear value that went out of scope without being consumed
+///
+/// Called by the drop bomb of `#[wasm::linear]` types in debug builds.
+/// With `std`, a value dropped while its thread unwinds from another panic
+/// is ignored, so the process does not abort.
+///
+/// # Panics
+/// Always, otherwise.
+#[cold]
+pub fn implicit_drop(type_name: &str) {
+    #[cfg(feature = "std")]
+    if std::thread::panicking() {
+        return;
+    }
+    panic!("linear value of type `{}` was dropped without being consumed", type_name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "linear value of type `Canvas` was dropped without being consumed")]
+    fn test_implicit_drop_panics() {
+        implicit_drop("Canvas");
+    }
+}
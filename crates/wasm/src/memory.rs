@@ -2,12 +2,24 @@
 //! 
 //! This module provides safe memory management primitives including
 //! SharedSlice for concurrent access, memory regions with intent
-//! validation, and scoped arenas for temporary allocations.
+//! validation, and scoped arenas for temporary allocations. `layout`
+//! computes where the fields of aggregate types live, `allocator` gives
+//! compiled modules a heap, and `stack` a shadow stack. `regions` names
+//! ranges of linear memory and checks their accesses. `gc` is the
+//! garbage-collected heap behind `#[wasm::gc]` for hosts without WasmGC.
+
+pub mod allocator;
+pub mod gc;
+pub mod layout;
+pub mod regions;
+pub mod stack;
 
 use crate::Pod;
 use crate::host::{get_host_capabilities};
+use crate::threading::Mutex;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
 use alloc::alloc::{alloc, dealloc, realloc, Layout};
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
@@ -18,6 +30,9 @@ use core::slice;
 /// Global memory allocation tracking
 static ALLOCATED_MEMORY: AtomicUsize = AtomicUsize::new(0);
 
+/// Bytes currently held in host ArrayBuffers
+static HOST_ALLOCATED_MEMORY: AtomicUsize = AtomicUsize::new(0);
+
 /// Shared memory region with concurrent access protection
 /// 
 /// `SharedMemory<T>` provides a thread-safe wrapper around
@@ -398,6 +413,897 @@ impl<'a> Default for ScopedArena<'a> {
     }
 }
 
+/// Default size above which allocations are moved out of linear memory
+pub const DEFAULT_LARGE_ALLOCATION_THRESHOLD: usize = 1024 * 1024;
+
+/// Marker type for host `ArrayBuffer` objects held through `ExternRef`
+pub enum ArrayBuffer {}
+
+/// Allocation strategy that keeps huge buffers out of linear memory
+///
+/// Linear memory can only grow, so a single large temporary buffer
+/// permanently inflates the instance. Allocations at or above the
+/// threshold are backed by a host ArrayBuffer instead, when the host
+/// supports JS interop; otherwise they fall back to linear memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeAllocationStrategy {
+    threshold: usize,
+}
+
+impl LargeAllocationStrategy {
+    /// Creates a strategy with the default threshold
+    pub fn new() -> Self {
+        Self {
+            threshold: DEFAULT_LARGE_ALLOCATION_THRESHOLD,
+        }
+    }
+
+    /// Sets the size in bytes at which allocations move to the host
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Gets the configured threshold in bytes
+    pub fn get_threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Returns true if an allocation of `size` bytes would use a host buffer
+    pub fn uses_host_buffer(&self, size: usize) -> bool {
+        size >= self.threshold && get_host_capabilities().js_interop
+    }
+
+    /// Allocates `size` zeroed bytes using the configured strategy
+    pub fn allocate(&self, size: usize) -> Result<Allocation, MemoryError> {
+        if size == 0 {
+            return Err(MemoryError::InvalidSize);
+        }
+
+        if self.uses_host_buffer(size) {
+            return HostBuffer::new(size).map(Allocation::Host);
+        }
+
+        let ptr = allocate_shared(size)?;
+        // SAFETY: allocate_shared returned a writable region of `size` bytes
+        unsafe { core::ptr::write_bytes(ptr, 0, size) };
+
+        Ok(Allocation::Linear {
+            ptr: NonNull::new(ptr).ok_or(MemoryError::AllocationFailed)?,
+            size,
+        })
+    }
+}
+
+impl Default for LargeAllocationStrategy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Storage produced by `LargeAllocationStrategy`
+pub enum Allocation {
+    /// Bytes in linear memory
+    Linear { ptr: NonNull<u8>, size: usize },
+    /// Bytes in a host-owned ArrayBuffer
+    Host(HostBuffer),
+}
+
+impl Allocation {
+    /// Returns the size of the allocation in bytes
+    pub fn len(&self) -> usize {
+        match self {
+            Allocation::Linear { size, .. } => *size,
+            Allocation::Host(buffer) => buffer.len(),
+        }
+    }
+
+    /// Returns true if the allocation is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if the allocation lives outside linear memory
+    pub fn is_host(&self) -> bool {
+        matches!(self, Allocation::Host(_))
+    }
+
+    /// Gets the linear-memory bytes, if the allocation lives there
+    pub fn as_linear_slice(&self) -> Option<&[u8]> {
+        match self {
+            // SAFETY: the region is owned by this allocation and initialized
+            Allocation::Linear { ptr, size } => unsafe {
+                Some(slice::from_raw_parts(ptr.as_ptr(), *size))
+            },
+            Allocation::Host(_) => None,
+        }
+    }
+
+    /// Gets a typed view over the allocation, wherever it lives
+    pub fn view<T: Pod>(&self) -> Result<TypedArrayView<'_, T>, MemoryError> {
+        let len = self.len() / element_size::<T>()?;
+        match self {
+            Allocation::Linear { ptr, .. } => {
+                if ptr.as_ptr() as usize & (core::mem::align_of::<T>() - 1) != 0 {
+                    return Err(MemoryError::ValidationFailed("misaligned view".to_string()));
+                }
+                Ok(TypedArrayView {
+                    backing: ViewBacking::Linear(ptr.cast()),
+                    offset: 0,
+                    len,
+                    _marker: PhantomData,
+                })
+            }
+            Allocation::Host(buffer) => buffer.view(),
+        }
+    }
+
+    /// Gets a writable typed view over the allocation, wherever it lives
+    pub fn view_mut<T: Pod>(&mut self) -> Result<TypedArrayViewMut<'_, T>, MemoryError> {
+        self.view().map(|view| TypedArrayViewMut { view, _marker: PhantomData })
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        if let Allocation::Linear { ptr, size } = self {
+            deallocate_shared(ptr.as_ptr(), *size);
+        }
+    }
+}
+
+/// Host ArrayBuffer referenced through an externref handle
+///
+/// The buffer is released back to the host when dropped.
+pub struct HostBuffer {
+    buffer: crate::ExternRef<ArrayBuffer>,
+    len: usize,
+}
+
+impl HostBuffer {
+    /// Allocates a zeroed host ArrayBuffer of `len` bytes
+    pub fn new(len: usize) -> Result<Self, MemoryError> {
+        let handle = crate::host::array_buffer_new(len)
+            .map_err(|_| MemoryError::AllocationFailed)?;
+
+        HOST_ALLOCATED_MEMORY.fetch_add(len, Ordering::Relaxed);
+
+        Ok(Self {
+            // SAFETY: the handle was just produced by the host for an ArrayBuffer
+            buffer: unsafe { crate::ExternRef::from_handle(handle) },
+            len,
+        })
+    }
+
+    /// Returns the byte length of the buffer
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the externref to the underlying ArrayBuffer
+    pub fn as_extern_ref(&self) -> crate::ExternRef<ArrayBuffer> {
+        self.buffer
+    }
+
+    /// Gets a typed-array view covering the whole buffer
+    pub fn view<T: Pod>(&self) -> Result<TypedArrayView<'_, T>, MemoryError> {
+        self.view_range(0, self.len / element_size::<T>()?)
+    }
+
+    /// Gets a writable typed-array view covering the whole buffer
+    pub fn view_mut<T: Pod>(&mut self) -> Result<TypedArrayViewMut<'_, T>, MemoryError> {
+        self.view().map(|view| TypedArrayViewMut { view, _marker: PhantomData })
+    }
+
+    /// Gets a writable typed-array view of `len` elements starting at element `start`
+    pub fn view_range_mut<T: Pod>(&mut self, start: usize, len: usize) -> Result<TypedArrayViewMut<'_, T>, MemoryError> {
+        self.view_range(start, len).map(|view| TypedArrayViewMut { view, _marker: PhantomData })
+    }
+
+    /// Gets a typed-array view of `len` elements starting at element `start`
+    pub fn view_range<T: Pod>(&self, start: usize, len: usize) -> Result<TypedArrayView<'_, T>, MemoryError> {
+        let elem = element_size::<T>()?;
+        let end = start.checked_add(len).ok_or(MemoryError::InvalidSize)?;
+        if end.checked_mul(elem).filter(|&bytes| bytes <= self.len).is_none() {
+            return Err(MemoryError::InvalidSize);
+        }
+
+        Ok(TypedArrayView {
+            backing: ViewBacking::Host(self.buffer.handle()),
+            offset: start * elem,
+            len,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl Drop for HostBuffer {
+    fn drop(&mut self) {
+        HOST_ALLOCATED_MEMORY.fetch_sub(self.len, Ordering::Relaxed);
+        // SAFETY: this buffer owns the handle and never hands it out mutably
+        unsafe { crate::host::array_buffer_release(self.buffer.handle()) };
+    }
+}
+
+/// Gets the size of a view element, rejecting zero-sized types and types
+/// with invalid bit patterns
+fn element_size<T: Pod>() -> Result<usize, MemoryError> {
+    if !T::ANY_BIT_PATTERN {
+        return Err(MemoryError::ValidationFailed("view element has invalid bit patterns".to_string()));
+    }
+    match core::mem::size_of::<T>() {
+        0 => Err(MemoryError::ValidationFailed("zero-sized view element".to_string())),
+        size => Ok(size),
+    }
+}
+
+enum ViewBacking<T> {
+    Linear(NonNull<T>),
+    Host(u32),
+}
+
+/// Read-only typed-array view over linear memory or a host ArrayBuffer
+///
+/// Host-backed reads copy through the host; the view never hands out
+/// references into host memory.
+pub struct TypedArrayView<'a, T: Pod> {
+    backing: ViewBacking<T>,
+    offset: usize,
+    len: usize,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T: Pod> TypedArrayView<'a, T> {
+    /// Returns the number of elements in the view
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the view is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads the element at `index`
+    pub fn get(&self, index: usize) -> Result<T, MemoryError> {
+        // SAFETY: every bit pattern, zero included, is a valid Pod value
+        let mut value = [unsafe { core::mem::zeroed::<T>() }];
+        self.read_into(index, &mut value)?;
+        Ok(value[0])
+    }
+
+    /// Copies `dst.len()` elements starting at `start` into `dst`
+    pub fn read_into(&self, start: usize, dst: &mut [T]) -> Result<(), MemoryError> {
+        self.check_range(start, dst.len())?;
+        match self.backing {
+            // SAFETY: the range was bounds-checked against the view
+            ViewBacking::Linear(ptr) => unsafe {
+                core::ptr::copy_nonoverlapping(ptr.as_ptr().add(start), dst.as_mut_ptr(), dst.len());
+                Ok(())
+            },
+            ViewBacking::Host(handle) => {
+                // SAFETY: T: Pod, so any byte pattern written by the host is valid
+                let bytes = unsafe {
+                    slice::from_raw_parts_mut(dst.as_mut_ptr() as *mut u8, core::mem::size_of_val(dst))
+                };
+                unsafe { crate::host::array_buffer_read(handle, self.byte_offset(start), bytes) }
+                    .map_err(|_| MemoryError::ValidationFailed("host buffer read failed".to_string()))
+            }
+        }
+    }
+
+    fn check_range(&self, start: usize, count: usize) -> Result<(), MemoryError> {
+        match start.checked_add(count) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err(MemoryError::InvalidSize),
+        }
+    }
+
+    fn byte_offset(&self, index: usize) -> usize {
+        self.offset + index * core::mem::size_of::<T>()
+    }
+}
+
+/// Writable typed-array view, borrowing its allocation exclusively
+///
+/// Reads go through the `TypedArrayView` it dereferences to.
+pub struct TypedArrayViewMut<'a, T: Pod> {
+    view: TypedArrayView<'a, T>,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Pod> TypedArrayViewMut<'a, T> {
+    /// Writes `value` at `index`
+    pub fn set(&mut self, index: usize, value: T) -> Result<(), MemoryError> {
+        self.write_from(index, &[value])
+    }
+
+    /// Copies `src` into the view starting at element `start`
+    pub fn write_from(&mut self, start: usize, src: &[T]) -> Result<(), MemoryError> {
+        self.view.check_range(start, src.len())?;
+        match self.view.backing {
+            // SAFETY: the range was bounds-checked against the view, and the
+            // view borrows its allocation mutably, so nothing else aliases it
+            ViewBacking::Linear(ptr) => unsafe {
+                core::ptr::copy_nonoverlapping(src.as_ptr(), ptr.as_ptr().add(start), src.len());
+                Ok(())
+            },
+            ViewBacking::Host(handle) => {
+                // SAFETY: T: Pod has no padding or pointers to leak
+                let bytes = unsafe {
+                    slice::from_raw_parts(src.as_ptr() as *const u8, core::mem::size_of_val(src))
+                };
+                unsafe { crate::host::array_buffer_write(handle, self.view.byte_offset(start), bytes) }
+                    .map_err(|_| MemoryError::ValidationFailed("host buffer write failed".to_string()))
+            }
+        }
+    }
+}
+
+impl<'a, T: Pod> core::ops::Deref for TypedArrayViewMut<'a, T> {
+    type Target = TypedArrayView<'a, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.view
+    }
+}
+
+/// Alignment of objects in the GC heap
+pub const GC_OBJECT_ALIGN: u32 = 8;
+
+/// Pointer layout of a GC-managed type
+///
+/// `pointer_offsets` lists the byte offsets of 32-bit heap pointer fields
+/// within the object, used to trace and fix up references.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcTypeLayout {
+    pub size: u32,
+    pub pointer_offsets: Vec<u32>,
+}
+
+impl GcTypeLayout {
+    /// Creates a layout for an object of `size` bytes
+    pub fn new(size: u32) -> Self {
+        Self { size, pointer_offsets: Vec::new() }
+    }
+
+    /// Adds a pointer field at `offset`
+    pub fn pointer(mut self, offset: u32) -> Self {
+        self.pointer_offsets.push(offset);
+        self
+    }
+}
+
+/// Handle to a root slot on the shadow stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootSlot(usize);
+
+/// Shadow stack of GC roots
+///
+/// Each frame records the heap addresses live in one activation; this is
+/// the stack map the collector walks and rewrites when objects move.
+#[derive(Debug, Default)]
+pub struct ShadowStack {
+    slots: Vec<u32>,
+    frames: Vec<usize>,
+}
+
+impl ShadowStack {
+    /// Creates an empty shadow stack
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enters a new frame
+    pub fn push_frame(&mut self) {
+        self.frames.push(self.slots.len());
+    }
+
+    /// Leaves the current frame, dropping its roots
+    pub fn pop_frame(&mut self) {
+        if let Some(start) = self.frames.pop() {
+            self.slots.truncate(start);
+        }
+    }
+
+    /// Registers a heap address as a root in the current frame
+    pub fn push_root(&mut self, address: u32) -> RootSlot {
+        self.slots.push(address);
+        RootSlot(self.slots.len() - 1)
+    }
+
+    /// Gets the address held in a root slot
+    pub fn get(&self, slot: RootSlot) -> u32 {
+        self.slots[slot.0]
+    }
+
+    /// Updates the address held in a root slot
+    pub fn set(&mut self, slot: RootSlot, address: u32) {
+        self.slots[slot.0] = address;
+    }
+
+    /// Gets every root address, innermost frame last
+    pub fn roots(&self) -> &[u32] {
+        &self.slots
+    }
+
+    fn roots_mut(&mut self) -> &mut [u32] {
+        &mut self.slots
+    }
+}
+
+/// Side-table entry for an object in the GC heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GcObject {
+    type_id: u32,
+    size: u32,
+    pins: u32,
+}
+
+/// Result of a compaction pass
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Objects relocated by the pass
+    pub moved_objects: usize,
+    /// Bytes returned to the bump region
+    pub reclaimed_bytes: u32,
+}
+
+/// Linear-memory heap for GC-managed objects
+///
+/// Objects are bump-allocated; freed objects leave holes that an optional
+/// sliding compaction pass closes. Object metadata lives in a side table so
+/// heap contents keep the exact layout described by `GcTypeLayout`.
+/// Addresses are absolute, starting at `base`; 0 is the null pointer.
+#[derive(Debug)]
+pub struct GcHeap {
+    base: u32,
+    memory: Vec<u8>,
+    top: u32,
+    layouts: Vec<GcTypeLayout>,
+    objects: BTreeMap<u32, GcObject>,
+    compaction_threshold: Option<u32>,
+}
+
+impl GcHeap {
+    /// Creates a heap of `capacity` bytes starting at address `base`
+    pub fn new(base: u32, capacity: u32) -> Result<Self, MemoryError> {
+        if base == 0 || base & (GC_OBJECT_ALIGN - 1) != 0 || base.checked_add(capacity).is_none() {
+            return Err(MemoryError::InvalidSize);
+        }
+        Ok(Self {
+            base,
+            memory: alloc::vec![0; capacity as usize],
+            top: 0,
+            layouts: Vec::new(),
+            objects: BTreeMap::new(),
+            compaction_threshold: None,
+        })
+    }
+
+    /// Enables compaction once fragmentation reaches `percent` of used bytes
+    pub fn compaction_threshold(mut self, percent: u32) -> Self {
+        self.compaction_threshold = Some(percent.min(100));
+        self
+    }
+
+    /// Registers a type layout and returns its type id
+    pub fn register_type(&mut self, layout: GcTypeLayout) -> Result<u32, MemoryError> {
+        if layout.pointer_offsets.iter().any(|&offset| offset.saturating_add(4) > layout.size) {
+            return Err(MemoryError::ValidationFailed("pointer field outside object".to_string()));
+        }
+        self.layouts.push(layout);
+        Ok(self.layouts.len() as u32 - 1)
+    }
+
+    /// Allocates a zeroed object of the given type
+    pub fn alloc(&mut self, type_id: u32) -> Result<u32, MemoryError> {
+        let size = self.layouts.get(type_id as usize)
+            .ok_or_else(|| MemoryError::ValidationFailed("unknown GC type".to_string()))?
+            .size;
+        let aligned = align_up(size.max(1), GC_OBJECT_ALIGN);
+        let end = self.top.checked_add(aligned).ok_or(MemoryError::OutOfMemory)?;
+        if end as usize > self.memory.len() {
+            return Err(MemoryError::OutOfMemory);
+        }
+
+        let address = self.base + self.top;
+        self.memory[self.top as usize..end as usize].fill(0);
+        self.objects.insert(address, GcObject { type_id, size: aligned, pins: 0 });
+        self.top = end;
+        Ok(address)
+    }
+
+    /// Releases an object, leaving a hole until the next compaction
+    pub fn free(&mut self, address: u32) -> Result<(), MemoryError> {
+        match self.objects.get(&address) {
+            Some(object) if object.pins > 0 => {
+                Err(MemoryError::ValidationFailed("cannot free a pinned object".to_string()))
+            }
+            Some(_) => {
+                self.objects.remove(&address);
+                Ok(())
+            }
+            None => Err(MemoryError::ValidationFailed("not a heap object".to_string())),
+        }
+    }
+
+    /// Pins an object so compaction never moves it
+    pub fn pin(&mut self, address: u32) -> Result<(), MemoryError> {
+        let object = self.object_mut(address)?;
+        object.pins += 1;
+        Ok(())
+    }
+
+    /// Releases one pin on an object
+    pub fn unpin(&mut self, address: u32) -> Result<(), MemoryError> {
+        let object = self.object_mut(address)?;
+        object.pins = object.pins.saturating_sub(1);
+        Ok(())
+    }
+
+    /// Returns true if the object is pinned
+    pub fn is_pinned(&self, address: u32) -> bool {
+        self.objects.get(&address).is_some_and(|object| object.pins > 0)
+    }
+
+    /// Gets the type id of an object
+    pub fn type_of(&self, address: u32) -> Option<u32> {
+        self.objects.get(&address).map(|object| object.type_id)
+    }
+
+    /// Number of live objects
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Bytes between the heap base and the bump pointer
+    pub fn used_bytes(&self) -> u32 {
+        self.top
+    }
+
+    /// Bytes below the bump pointer not occupied by objects
+    pub fn fragmented_bytes(&self) -> u32 {
+        self.top - self.objects.values().map(|object| object.size).sum::<u32>()
+    }
+
+    /// Reads a 32-bit field of an object
+    pub fn read_u32(&self, address: u32, offset: u32) -> Result<u32, MemoryError> {
+        let at = self.field_index(address, offset)?;
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.memory[at..at + 4]);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Writes a 32-bit field of an object
+    pub fn write_u32(&mut self, address: u32, offset: u32, value: u32) -> Result<(), MemoryError> {
+        let at = self.field_index(address, offset)?;
+        self.memory[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    /// Compacts when enabled and fragmentation exceeds the threshold
+    pub fn maybe_compact(&mut self, roots: &mut ShadowStack) -> Option<CompactionStats> {
+        let threshold = self.compaction_threshold?;
+        if self.top == 0 || self.fragmented_bytes() as u64 * 100 < threshold as u64 * self.top as u64 {
+            return None;
+        }
+        Some(self.compact(roots))
+    }
+
+    /// Slides live objects toward the heap base, closing holes
+    ///
+    /// Pinned objects stay in place and unpinned objects slide up to them.
+    /// Root slots and every pointer field described by the type layouts
+    /// are rewritten, including interior pointers into moved objects.
+    pub fn compact(&mut self, roots: &mut ShadowStack) -> CompactionStats {
+        // Compute forwarding addresses in address order
+        let mut forwarding = BTreeMap::new();
+        let mut cursor = self.base;
+        for (&address, object) in &self.objects {
+            let target = if object.pins > 0 { address } else { cursor };
+            forwarding.insert(address, target);
+            cursor = target + object.size;
+        }
+
+        // Fix up roots and pointer fields before anything moves
+        for root in roots.roots_mut() {
+            *root = self.forward(&forwarding, *root);
+        }
+        let fields: Vec<usize> = self.objects.iter()
+            .flat_map(|(&address, object)| {
+                let base = (address - self.base) as usize;
+                self.layouts[object.type_id as usize].pointer_offsets.iter()
+                    .map(move |&offset| base + offset as usize)
+            })
+            .collect();
+        for at in fields {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&self.memory[at..at + 4]);
+            let forwarded = self.forward(&forwarding, u32::from_le_bytes(bytes));
+            self.memory[at..at + 4].copy_from_slice(&forwarded.to_le_bytes());
+        }
+
+        // Slide objects; destinations never pass their sources
+        let mut moved_objects = 0;
+        let mut objects = BTreeMap::new();
+        for (address, object) in core::mem::take(&mut self.objects) {
+            let target = forwarding[&address];
+            if target != address {
+                let from = (address - self.base) as usize;
+                let to = (target - self.base) as usize;
+                self.memory.copy_within(from..from + object.size as usize, to);
+                moved_objects += 1;
+            }
+            objects.insert(target, object);
+        }
+        self.objects = objects;
+
+        let new_top = cursor - self.base;
+        let reclaimed_bytes = self.top - new_top;
+        self.memory[new_top as usize..self.top as usize].fill(0);
+        self.top = new_top;
+
+        CompactionStats { moved_objects, reclaimed_bytes }
+    }
+
+    /// Maps a possibly interior heap pointer through the forwarding table
+    fn forward(&self, forwarding: &BTreeMap<u32, u32>, pointer: u32) -> u32 {
+        match self.objects.range(..=pointer).next_back() {
+            Some((&start, object)) if pointer < start + object.size => {
+                forwarding[&start] + (pointer - start)
+            }
+            _ => pointer,
+        }
+    }
+
+    fn object_mut(&mut self, address: u32) -> Result<&mut GcObject, MemoryError> {
+        self.objects.get_mut(&address)
+            .ok_or_else(|| MemoryError::ValidationFailed("not a heap object".to_string()))
+    }
+
+    fn field_index(&self, address: u32, offset: u32) -> Result<usize, MemoryError> {
+        let object = self.objects.get(&address)
+            .ok_or_else(|| MemoryError::ValidationFailed("not a heap object".to_string()))?;
+        match offset.checked_add(4) {
+            Some(end) if end <= object.size => Ok((address - self.base + offset) as usize),
+            _ => Err(MemoryError::InvalidSize),
+        }
+    }
+}
+
+fn align_up(value: u32, align: u32) -> u32 {
+    (value + align - 1) & !(align - 1)
+}
+
+/// Block sizes served by thread arenas; larger requests use the global path
+pub const ARENA_SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+const ARENA_BLOCK_ALIGN: usize = 16;
+
+/// Tuning for per-thread arenas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaConfig {
+    /// Blocks moved from the shared pool per refill
+    pub batch: usize,
+    /// Blocks a thread may cache per size class before rebalancing
+    pub high_water: usize,
+    /// Arena operations between rebalancing passes
+    pub rebalance_interval: usize,
+}
+
+impl ArenaConfig {
+    /// Creates the default configuration
+    pub fn new() -> Self {
+        Self {
+            batch: 32,
+            high_water: 128,
+            rebalance_interval: 1024,
+        }
+    }
+
+    /// Sets the refill batch size
+    pub fn batch(mut self, batch: usize) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+
+    /// Sets the per-class cache limit
+    pub fn high_water(mut self, high_water: usize) -> Self {
+        self.high_water = high_water.max(1);
+        self
+    }
+
+    /// Sets how often arenas rebalance
+    pub fn rebalance_interval(mut self, interval: usize) -> Self {
+        self.rebalance_interval = interval.max(1);
+        self
+    }
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Free blocks and backing chunks shared by all thread arenas
+#[derive(Default)]
+struct SharedFreeLists {
+    lists: [Vec<usize>; ARENA_SIZE_CLASSES.len()],
+    chunks: Vec<(usize, Layout)>,
+}
+
+/// Allocation pool backing per-thread arenas in threaded builds
+///
+/// Each worker takes a `ThreadArena` that serves small allocations from
+/// thread-local free lists. Only refills and rebalancing touch the shared
+/// lists, so parallel workloads do not serialize on a global lock.
+pub struct ArenaPool {
+    config: ArenaConfig,
+    shared: Mutex<SharedFreeLists>,
+}
+
+impl ArenaPool {
+    /// Creates an empty pool
+    pub fn new(config: ArenaConfig) -> Self {
+        Self {
+            config,
+            shared: Mutex::new(SharedFreeLists::default()),
+        }
+    }
+
+    /// Returns true when per-thread arenas should replace the global path
+    pub fn is_enabled() -> bool {
+        get_host_capabilities().threading
+    }
+
+    /// Creates an arena for the calling thread
+    pub fn arena(&self) -> ThreadArena<'_> {
+        ThreadArena {
+            pool: self,
+            lists: Default::default(),
+            operations: 0,
+        }
+    }
+
+    /// Number of free blocks held in the shared lists
+    pub fn shared_blocks(&self) -> usize {
+        self.shared.lock().lists.iter().map(Vec::len).sum()
+    }
+
+    /// Moves up to `batch` blocks of a class into `local`, carving a chunk if needed
+    fn refill(&self, class: usize, local: &mut Vec<NonNull<u8>>) -> Result<(), MemoryError> {
+        let mut shared = self.shared.lock();
+
+        if shared.lists[class].is_empty() {
+            let block = ARENA_SIZE_CLASSES[class];
+            let layout = Layout::from_size_align(block * self.config.batch, ARENA_BLOCK_ALIGN)
+                .map_err(|_| MemoryError::InvalidSize)?;
+            // SAFETY: the layout has a non-zero size
+            let chunk = unsafe { alloc(layout) };
+            if chunk.is_null() {
+                return Err(MemoryError::OutOfMemory);
+            }
+            ALLOCATED_MEMORY.fetch_add(layout.size(), Ordering::Relaxed);
+            shared.chunks.push((chunk as usize, layout));
+            let start = chunk as usize;
+            shared.lists[class].extend((0..self.config.batch).map(|i| start + i * block));
+        }
+
+        let list = &mut shared.lists[class];
+        let take = list.len().min(self.config.batch);
+        let at = list.len() - take;
+        // SAFETY: shared lists only hold non-null block addresses
+        local.extend(list.drain(at..).map(|addr| unsafe { NonNull::new_unchecked(addr as *mut u8) }));
+        Ok(())
+    }
+
+    /// Returns blocks of a class to the shared lists
+    fn release(&self, class: usize, blocks: impl Iterator<Item = NonNull<u8>>) {
+        let mut shared = self.shared.lock();
+        shared.lists[class].extend(blocks.map(|ptr| ptr.as_ptr() as usize));
+    }
+}
+
+impl Drop for ArenaPool {
+    fn drop(&mut self) {
+        for &(chunk, layout) in &self.shared.get_mut().chunks {
+            // SAFETY: chunks were allocated with exactly this layout
+            unsafe { dealloc(chunk as *mut u8, layout) };
+            ALLOCATED_MEMORY.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Thread-local allocation arena
+///
+/// Not `Send`: an arena belongs to the thread that created it. Dropping
+/// the arena returns its cached blocks to the pool.
+pub struct ThreadArena<'a> {
+    pool: &'a ArenaPool,
+    lists: [Vec<NonNull<u8>>; ARENA_SIZE_CLASSES.len()],
+    operations: usize,
+}
+
+impl<'a> ThreadArena<'a> {
+    /// Allocates `size` bytes aligned to 16 (or 8 above the largest class)
+    pub fn allocate(&mut self, size: usize) -> Result<NonNull<u8>, MemoryError> {
+        if size == 0 {
+            return Err(MemoryError::InvalidSize);
+        }
+        let class = match size_class(size) {
+            Some(class) => class,
+            None => return NonNull::new(allocate_shared(size)?).ok_or(MemoryError::OutOfMemory),
+        };
+
+        self.tick();
+        if self.lists[class].is_empty() {
+            self.pool.refill(class, &mut self.lists[class])?;
+        }
+        self.lists[class].pop().ok_or(MemoryError::OutOfMemory)
+    }
+
+    /// Returns a block previously allocated with the same `size`
+    ///
+    /// # Safety
+    /// `ptr` must come from `allocate(size)` on an arena of the same pool
+    /// and must not be used afterwards.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, size: usize) {
+        match size_class(size) {
+            Some(class) => {
+                self.lists[class].push(ptr);
+                self.tick();
+            }
+            None => deallocate_shared(ptr.as_ptr(), size),
+        }
+    }
+
+    /// Trims every size class down to half the high-water mark
+    pub fn rebalance(&mut self) {
+        let keep = self.pool.config.high_water / 2;
+        for class in 0..ARENA_SIZE_CLASSES.len() {
+            if self.lists[class].len() > self.pool.config.high_water {
+                let excess = self.lists[class].split_off(keep);
+                self.pool.release(class, excess.into_iter());
+            }
+        }
+    }
+
+    /// Number of blocks cached by this arena
+    pub fn cached_blocks(&self) -> usize {
+        self.lists.iter().map(Vec::len).sum()
+    }
+
+    fn tick(&mut self) {
+        self.operations += 1;
+        if self.operations >= self.pool.config.rebalance_interval {
+            self.operations = 0;
+            self.rebalance();
+        }
+    }
+}
+
+impl<'a> Drop for ThreadArena<'a> {
+    fn drop(&mut self) {
+        for class in 0..ARENA_SIZE_CLASSES.len() {
+            let blocks = core::mem::take(&mut self.lists[class]);
+            if !blocks.is_empty() {
+                self.pool.release(class, blocks.into_iter());
+            }
+        }
+    }
+}
+
+fn size_class(size: usize) -> Option<usize> {
+    ARENA_SIZE_CLASSES.iter().position(|&block| size <= block)
+}
+
 /// Memory-related errors
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MemoryError {
@@ -434,6 +1340,7 @@ pub fn get_memory_stats() -> MemoryStats {
     MemoryStats {
         allocated_bytes: ALLOCATED_MEMORY.load(Ordering::Acquire),
         peak_allocated_bytes: 0, // Would need additional tracking
+        host_buffer_bytes: HOST_ALLOCATED_MEMORY.load(Ordering::Acquire),
     }
 }
 
@@ -491,6 +1398,8 @@ pub fn validate_memory_intent(intent: &MemoryIntent) -> Result<(), MemoryError>
 pub struct MemoryStats {
     pub allocated_bytes: usize,
     pub peak_allocated_bytes: usize,
+    /// Bytes held outside linear memory in host ArrayBuffers
+    pub host_buffer_bytes: usize,
 }
 
 #[cfg(test)]
@@ -568,4 +1477,176 @@ mod tests {
         let stats_after = get_memory_stats();
         assert!(stats_after.allocated_bytes > stats_before.allocated_bytes);
     }
+
+    #[test]
+    fn test_large_allocation_strategy_threshold() {
+        let strategy = LargeAllocationStrategy::new();
+        assert_eq!(strategy.get_threshold(), DEFAULT_LARGE_ALLOCATION_THRESHOLD);
+
+        let strategy = strategy.threshold(64);
+        assert_eq!(strategy.get_threshold(), 64);
+        assert!(!strategy.uses_host_buffer(32));
+    }
+
+    #[test]
+    fn test_large_allocation_falls_back_to_linear_memory() {
+        // Without JS interop the host buffer path is unavailable
+        let strategy = LargeAllocationStrategy::new().threshold(16);
+        let allocation = strategy.allocate(256).unwrap();
+
+        assert_eq!(allocation.len(), 256);
+        assert_eq!(allocation.is_host(), strategy.uses_host_buffer(256));
+        if !allocation.is_host() {
+            assert!(allocation.as_linear_slice().unwrap().iter().all(|b| *b == 0));
+        }
+
+        assert_eq!(strategy.allocate(0).err(), Some(MemoryError::InvalidSize));
+    }
+
+    #[test]
+    fn test_typed_array_view_bounds() {
+        let mut allocation = LargeAllocationStrategy::new().allocate(16).unwrap();
+        let mut view = allocation.view_mut::<u32>().unwrap();
+        assert_eq!(view.len(), 4);
+
+        view.set(3, 0xdead_beef).unwrap();
+        assert_eq!(view.get(3), Ok(0xdead_beef));
+        assert_eq!(view.get(4), Err(MemoryError::InvalidSize));
+
+        let mut out = [0u32; 2];
+        view.write_from(0, &[7, 9]).unwrap();
+        view.read_into(0, &mut out).unwrap();
+        assert_eq!(out, [7, 9]);
+        assert!(view.write_from(3, &[1, 2]).is_err());
+        assert!(matches!(allocation.view::<()>(), Err(MemoryError::ValidationFailed(_))));
+        assert!(matches!(allocation.view::<[bool; 2]>(), Err(MemoryError::ValidationFailed(_))));
+    }
+
+    #[test]
+    fn test_gc_heap_compaction_fixes_pointers() {
+        let mut heap = GcHeap::new(0x1000, 256).unwrap();
+        let node = heap.register_type(GcTypeLayout::new(16).pointer(0)).unwrap();
+        let mut stack = ShadowStack::new();
+
+        let garbage = heap.alloc(node).unwrap();
+        let head = heap.alloc(node).unwrap();
+        let tail = heap.alloc(node).unwrap();
+        heap.write_u32(head, 0, tail).unwrap();
+        heap.write_u32(tail, 4, 42).unwrap();
+
+        stack.push_frame();
+        let head_root = stack.push_root(head);
+        let interior_root = stack.push_root(tail + 4);
+
+        heap.free(garbage).unwrap();
+        assert_eq!(heap.fragmented_bytes(), 16);
+
+        let stats = heap.compact(&mut stack);
+        assert_eq!(stats, CompactionStats { moved_objects: 2, reclaimed_bytes: 16 });
+        assert_eq!(heap.fragmented_bytes(), 0);
+
+        let new_head = stack.get(head_root);
+        assert_eq!(new_head, 0x1000);
+        let new_tail = heap.read_u32(new_head, 0).unwrap();
+        assert_eq!(new_tail, 0x1010);
+        assert_eq!(stack.get(interior_root), new_tail + 4);
+        assert_eq!(heap.read_u32(new_tail, 4), Ok(42));
+
+        stack.pop_frame();
+        assert!(stack.roots().is_empty());
+    }
+
+    #[test]
+    fn test_gc_heap_compaction_respects_pins() {
+        let mut heap = GcHeap::new(0x2000, 256).unwrap();
+        let cell = heap.register_type(GcTypeLayout::new(8)).unwrap();
+        let mut stack = ShadowStack::new();
+
+        let a = heap.alloc(cell).unwrap();
+        let b = heap.alloc(cell).unwrap();
+        let pinned = heap.alloc(cell).unwrap();
+        let c = heap.alloc(cell).unwrap();
+        let d = heap.alloc(cell).unwrap();
+        heap.pin(pinned).unwrap();
+        assert!(heap.free(pinned).is_err());
+
+        heap.free(a).unwrap();
+        heap.free(c).unwrap();
+        let root_b = stack.push_root(b);
+        let root_d = stack.push_root(d);
+
+        let stats = heap.compact(&mut stack);
+        assert!(heap.is_pinned(pinned));
+        assert_eq!(heap.type_of(pinned), Some(cell));
+        assert_eq!(stack.get(root_b), a);
+        assert_eq!(stack.get(root_d), c);
+        assert_eq!(stats.reclaimed_bytes, 8);
+
+        heap.unpin(pinned).unwrap();
+        assert!(!heap.is_pinned(pinned));
+    }
+
+    #[test]
+    fn test_gc_heap_compaction_is_optional() {
+        let mut stack = ShadowStack::new();
+        let mut heap = GcHeap::new(0x1000, 64).unwrap();
+        let cell = heap.register_type(GcTypeLayout::new(8)).unwrap();
+        let first = heap.alloc(cell).unwrap();
+        heap.alloc(cell).unwrap();
+        heap.free(first).unwrap();
+        assert_eq!(heap.maybe_compact(&mut stack), None);
+
+        let mut heap = GcHeap::new(0x1000, 64).unwrap().compaction_threshold(25);
+        let cell = heap.register_type(GcTypeLayout::new(8)).unwrap();
+        let first = heap.alloc(cell).unwrap();
+        heap.alloc(cell).unwrap();
+        heap.free(first).unwrap();
+        assert!(heap.maybe_compact(&mut stack).is_some());
+        assert_eq!(heap.used_bytes(), 8);
+
+        assert_eq!(heap.register_type(GcTypeLayout::new(4).pointer(2)).err(),
+            Some(MemoryError::ValidationFailed("pointer field outside object".to_string())));
+    }
+
+    #[test]
+    fn test_thread_arena_reuses_blocks() {
+        let pool = ArenaPool::new(ArenaConfig::new().batch(4));
+        let mut arena = pool.arena();
+
+        let first = arena.allocate(24).unwrap();
+        assert_eq!(first.as_ptr() as usize % 16, 0);
+        assert_eq!(arena.cached_blocks(), 3);
+
+        unsafe { arena.deallocate(first, 24) };
+        assert_eq!(arena.allocate(32).unwrap(), first);
+
+        let large = arena.allocate(4096).unwrap();
+        unsafe { arena.deallocate(large, 4096) };
+        assert_eq!(arena.allocate(0), Err(MemoryError::InvalidSize));
+    }
+
+    #[test]
+    fn test_thread_arena_rebalances_into_shared_pool() {
+        let config = ArenaConfig::new().batch(8).high_water(4).rebalance_interval(1000);
+        let pool = ArenaPool::new(config);
+        let mut arena = pool.arena();
+
+        let blocks: Vec<_> = (0..8).map(|_| arena.allocate(64).unwrap()).collect();
+        for block in blocks {
+            unsafe { arena.deallocate(block, 64) };
+        }
+        assert_eq!(arena.cached_blocks(), 8);
+
+        arena.rebalance();
+        assert_eq!(arena.cached_blocks(), 2);
+        assert_eq!(pool.shared_blocks(), 6);
+
+        // Another thread's arena refills from the shared lists
+        let mut other = pool.arena();
+        other.allocate(64).unwrap();
+        assert_eq!(pool.shared_blocks(), 0);
+
+        drop(arena);
+        assert_eq!(pool.shared_blocks(), 2);
+    }
 }
@@ -0,0 +1,556 @@
+//! Heap allocation for compiled modules
+//!
+//! `Instruction::MemoryAlloc` and `MemoryFree` name no allocator.
+//! `lower_allocations` adds one chosen by `AllocatorStrategy`, exports it
+//! as `ALLOC_EXPORT` and `FREE_EXPORT` so hosts can allocate the buffers
+//! they pass in, and rewrites the instructions into calls to it.
+//!
+//! The bundled allocator keeps its heap in pages grown past the module's
+//! initial memory, so it never overlaps data the module placed there. The
+//! first page starts with the heap's state: the bump pointer, then one
+//! free list head per power-of-two size class. Each block carries an
+//! 8-byte header in front of the pointer handed out, holding the distance
+//! back to the block's start and its size class, so blocks are freed
+//! without being told their size. Freed blocks go to the front of their
+//! class's list and are reused before the heap grows. The bump allocator
+//! shares the heap placement but only keeps the bump pointer: frees do
+//! nothing and `RESET_EXPORT` empties the whole heap, which suits
+//! instances that handle one request at a time. Neither is thread-safe,
+//! and modules using them must not grow memory themselves.
+
+use core::fmt;
+
+use crate::wasmir::builder::{FunctionBuilder, Value, Var, I32};
+use crate::wasmir::{
+    BlockId, Constant, ExportKind, Instruction, InteropSignature, MemoryType, Operand, Signature, Type, WasmIR,
+    WasmModule, ALLOC_EXPORT, FREE_EXPORT,
+};
+
+/// Import module of the host allocator
+pub const HOST_ALLOCATOR_MODULE: &str = "env";
+
+/// Host allocator import: `(size, align) -> ptr`, or 0 when out of memory
+pub const HOST_ALLOC_IMPORT: &str = "__wasmrust_alloc";
+
+/// Host allocator import: `(ptr, size, align)`
+///
+/// Frees issued by compiled code pass a size and alignment of 0, so the
+/// host must track the size of the blocks it hands out.
+pub const HOST_FREE_IMPORT: &str = "__wasmrust_free";
+
+/// Export of the bump allocator emptying its heap: `()`
+///
+/// Every pointer it handed out is invalid afterwards.
+pub const RESET_EXPORT: &str = "memory.reset";
+
+/// Alignment of allocations that do not ask for one
+pub const DEFAULT_ALIGN: u32 = 8;
+
+/// Size of a linear memory page
+const PAGE_SIZE: u32 = 65536;
+
+/// Most pages a 32-bit memory can have
+const MAX_PAGES: u32 = 65536;
+
+/// Bytes of bundled allocator state at the start of the heap
+const STATE_SIZE: i32 = 128;
+
+/// Bytes of bump allocator state at the start of the heap
+const BUMP_STATE_SIZE: i32 = 8;
+
+/// Bytes in front of every block handed out
+const HEADER_SIZE: i32 = 8;
+
+/// Smallest size class, as a power of two
+const MIN_CLASS: i32 = 4;
+
+/// Where `MemoryAlloc` and `MemoryFree` get their memory from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllocatorStrategy {
+    /// Size-class free-list allocator compiled into the module
+    #[default]
+    Bundled,
+    /// Bump allocator without per-object frees, emptied through
+    /// `RESET_EXPORT`
+    Bump,
+    /// `HOST_ALLOC_IMPORT` and `HOST_FREE_IMPORT`, imported from the host
+    Host,
+}
+
+impl AllocatorStrategy {
+    /// Names the allocator is exported under
+    pub fn exports(self) -> &'static [&'static str] {
+        match self {
+            AllocatorStrategy::Bump => &[ALLOC_EXPORT, FREE_EXPORT, RESET_EXPORT],
+            AllocatorStrategy::Bundled | AllocatorStrategy::Host => &[ALLOC_EXPORT, FREE_EXPORT],
+        }
+    }
+}
+
+/// Reasons an allocator cannot be added to a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllocatorError {
+    /// The module already exports a function under an allocator export name
+    ConflictingExport(&'static str),
+    /// The initial memory fills the address space, leaving none for a heap
+    NoHeapSpace,
+}
+
+impl fmt::Display for AllocatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocatorError::ConflictingExport(name) => {
+                write!(f, "module allocates but already exports {}", name)
+            }
+            AllocatorError::NoHeapSpace => write!(f, "initial memory leaves no room for a heap"),
+        }
+    }
+}
+
+/// Checks whether the module allocates, or marshals exports without
+/// providing the allocator exports the host needs for them
+pub fn needs_allocator(module: &WasmModule) -> bool {
+    let allocates = module.functions.iter()
+        .flat_map(WasmIR::all_instructions)
+        .any(|instruction| matches!(instruction, Instruction::MemoryAlloc { .. } | Instruction::MemoryFree { .. }));
+    let marshals = module.exports.iter().any(|export| match export.kind {
+        ExportKind::Function(index) => module.interop_signature(index).is_some_and(InteropSignature::needs_marshaling),
+        ExportKind::Memory(_) => false,
+    });
+    let exported = module.exports.iter().any(|export| export.name == ALLOC_EXPORT || export.name == FREE_EXPORT);
+    allocates || (marshals && !exported)
+}
+
+/// Returns a copy of `module` with an allocator added and exported, and
+/// every `MemoryAlloc` and `MemoryFree` calling it
+///
+/// A module without linear memory gets an empty one.
+pub fn lower_allocations(module: &WasmModule, strategy: AllocatorStrategy) -> Result<WasmModule, AllocatorError> {
+    for &name in strategy.exports() {
+        if module.exports.iter().any(|export| export.name == name) {
+            return Err(AllocatorError::ConflictingExport(name));
+        }
+    }
+    let mut lowered = module.clone();
+    if lowered.memory.is_none() {
+        lowered.set_memory(MemoryType { min_pages: 0, max_pages: None });
+    }
+    let heap_pages = lowered.memory.map_or(0, |memory| memory.min_pages);
+    let (alloc, free) = match strategy {
+        AllocatorStrategy::Bundled | AllocatorStrategy::Bump if heap_pages >= MAX_PAGES => {
+            return Err(AllocatorError::NoHeapSpace);
+        }
+        AllocatorStrategy::Bundled => (bundled_alloc(heap_pages), bundled_free(heap_pages)),
+        AllocatorStrategy::Bump => (bump_alloc(heap_pages), bump_free()),
+        AllocatorStrategy::Host => {
+            let alloc = lowered.add_import(HOST_ALLOCATOR_MODULE, HOST_ALLOC_IMPORT, alloc_signature());
+            let free = lowered.add_import(HOST_ALLOCATOR_MODULE, HOST_FREE_IMPORT, free_signature());
+            (host_alloc(alloc), host_free(free))
+        }
+    };
+
+    let alloc_index = lowered.add_function(alloc);
+    let free_index = lowered.add_function(free);
+    for function in &mut lowered.functions {
+        for block in &mut function.basic_blocks {
+            for instruction in &mut block.instructions {
+                rewrite(instruction, alloc_index, free_index);
+            }
+        }
+    }
+    lowered.export_function(ALLOC_EXPORT, alloc_index);
+    lowered.export_function(FREE_EXPORT, free_index);
+    if strategy == AllocatorStrategy::Bump {
+        let reset = lowered.add_function(bump_reset(heap_pages));
+        lowered.export_function(RESET_EXPORT, reset);
+    }
+    Ok(lowered)
+}
+
+fn rewrite(instruction: &mut Instruction, alloc: u32, free: u32) {
+    let call = match instruction {
+        Instruction::MemoryAlloc { size, align } => Instruction::Call {
+            func_ref: alloc,
+            args: alloc::vec![size.clone(), int(align.unwrap_or(DEFAULT_ALIGN) as i32)],
+        },
+        Instruction::MemoryFree { address } => Instruction::Call {
+            func_ref: free,
+            args: alloc::vec![address.clone(), int(0), int(0)],
+        },
+        _ => return,
+    };
+    *instruction = call;
+}
+
+fn int(value: i32) -> Operand {
+    Operand::Constant(Constant::I32(value))
+}
+
+fn alloc_signature() -> Signature {
+    Signature { params: alloc::vec![Type::I32; 2], returns: Some(Type::I32) }
+}
+
+fn free_signature() -> Signature {
+    Signature { params: alloc::vec![Type::I32; 3], returns: None }
+}
+
+/// Address of the heap's state, at the first page past the initial memory
+fn heap_base(heap_pages: u32) -> i32 {
+    (heap_pages * PAGE_SIZE) as i32
+}
+
+/// Builds the entry block shared by the allocators, continuing at `next`
+///
+/// Sizes and alignments of 2 GiB or more never fit. The heap's first page
+/// is grown on the first allocation, with the bump pointer set past the
+/// `state` bytes at its start.
+fn enter_heap(
+    f: &mut FunctionBuilder,
+    (size, align): (Var<I32>, Var<I32>),
+    heap_pages: u32,
+    state: i32,
+    next: BlockId,
+    fail: BlockId,
+) {
+    let heap = heap_base(heap_pages);
+    let (init_check, init, init_state) = (f.new_block(), f.new_block(), f.new_block());
+
+    let mut b = f.block(f.entry());
+    let both = b.or(size, align);
+    let huge = b.lt(both, 0);
+    b.branch(huge, fail, init_check);
+
+    let mut b = f.block(init_check);
+    let pages = b.memory_size();
+    let fresh = b.eq(pages, heap_pages as i32);
+    b.branch(fresh, init, next);
+
+    let mut b = f.block(init);
+    let previous = b.memory_grow(1);
+    let failed = b.eq(previous, -1);
+    b.branch(failed, fail, init_state);
+
+    let mut b = f.block(init_state);
+    b.store::<I32>(heap, heap + state, 0);
+    b.jump(next);
+}
+
+/// Builds `at`, growing memory to cover `len` bytes from `start` before
+/// continuing at `next`
+///
+/// Page counts stay far from the sign bit, unlike addresses, so they are
+/// compared instead.
+fn reserve(f: &mut FunctionBuilder, at: BlockId, start: Value<I32>, len: Value<I32>, next: BlockId, fail: BlockId) {
+    let grow = f.new_block();
+
+    let mut b = f.block(at);
+    let start_page = b.shr(start, 16);
+    let within = b.and(start, 0xFFFF);
+    let reach = b.add(within, len);
+    let rounded = b.add(reach, 0xFFFF);
+    let extra = b.shr(rounded, 16);
+    let end_pages = b.add(start_page, extra);
+    let current = b.memory_size();
+    let short = b.gt(end_pages, current);
+    b.branch(short, grow, next);
+
+    let mut b = f.block(grow);
+    let missing = b.sub(end_pages, current);
+    let previous = b.memory_grow(missing);
+    let failed = b.eq(previous, -1);
+    b.branch(failed, fail, next);
+}
+
+/// `(size, align) -> ptr` of the bundled allocator, returning 0 when
+/// memory cannot grow
+fn bundled_alloc(heap_pages: u32) -> WasmIR {
+    let heap = heap_base(heap_pages);
+    let mut f = FunctionBuilder::new(ALLOC_EXPORT);
+    let size = f.param::<I32>();
+    let align = f.param::<I32>();
+    f.returns::<I32>();
+    let (align_var, class, block) = (f.var::<I32>(), f.var::<I32>(), f.var::<I32>());
+    let (sized, small_align, pick, classify, min_class) =
+        (f.new_block(), f.new_block(), f.new_block(), f.new_block(), f.new_block());
+    let (lookup, reuse, bump, grown, advance) = (f.new_block(), f.new_block(), f.new_block(), f.new_block(), f.new_block());
+    let (finish, fail) = (f.new_block(), f.new_block());
+    enter_heap(&mut f, (size, align), heap_pages, STATE_SIZE, sized, fail);
+
+    // Blocks are at least 8-aligned, which the header needs
+    let mut b = f.block(sized);
+    b.set(align_var, align);
+    let over = b.gt(align, HEADER_SIZE);
+    b.branch(over, pick, small_align);
+
+    let mut b = f.block(small_align);
+    b.set(align_var, HEADER_SIZE);
+    b.jump(pick);
+
+    // The block must fit the header and any padding to the alignment
+    let mut b = f.block(pick);
+    let need = b.add(size, align_var);
+    let wrapped = b.lt(need, 0);
+    b.branch(wrapped, fail, classify);
+
+    let mut b = f.block(classify);
+    let less = b.sub(need, 1);
+    let zeros = b.clz(less);
+    let bits = b.sub(32, zeros);
+    b.set(class, bits);
+    let tiny = b.lt(bits, MIN_CLASS);
+    b.branch(tiny, min_class, lookup);
+
+    let mut b = f.block(min_class);
+    b.set(class, MIN_CLASS);
+    b.jump(lookup);
+
+    let mut b = f.block(lookup);
+    let slot = b.shl(class, 2);
+    let head = b.add(slot, heap);
+    let first = b.load::<I32>(head, 0);
+    b.set(block, first);
+    let empty = b.eq(first, 0);
+    b.branch(empty, bump, reuse);
+
+    let mut b = f.block(reuse);
+    let next = b.load::<I32>(block, 0);
+    b.store::<I32>(head, next, 0);
+    b.jump(finish);
+
+    let mut b = f.block(bump);
+    let top = b.load::<I32>(heap, 0);
+    b.set(block, top);
+    let span = b.shl(1, class);
+    b.jump(grown);
+    reserve(&mut f, grown, top, span, advance, fail);
+
+    let mut b = f.block(advance);
+    let end = b.add(block, span);
+    b.store::<I32>(heap, end, 0);
+    b.jump(finish);
+
+    let mut b = f.block(finish);
+    let padded = b.add(block, align_var);
+    let padded = b.add(padded, HEADER_SIZE - 1);
+    let mask = b.neg(align_var);
+    let payload = b.and(padded, mask);
+    let header = b.sub(payload, HEADER_SIZE);
+    let distance = b.sub(payload, block);
+    b.store::<I32>(header, distance, 0);
+    b.store::<I32>(header, class, 4);
+    b.ret(payload);
+
+    f.block(fail).ret(0);
+    f.finish().expect("bundled allocator is valid")
+}
+
+/// `(ptr, size, align)` of the bundled allocator; the size and alignment
+/// are read from the block's header instead
+fn bundled_free(heap_pages: u32) -> WasmIR {
+    let heap = heap_base(heap_pages);
+    let mut f = FunctionBuilder::new(FREE_EXPORT);
+    let ptr = f.param::<I32>();
+    f.param::<I32>();
+    f.param::<I32>();
+    let (release, done) = (f.new_block(), f.new_block());
+
+    let mut b = f.block(f.entry());
+    let null = b.eq(ptr, 0);
+    b.branch(null, done, release);
+
+    let mut b = f.block(release);
+    let header = b.sub(ptr, HEADER_SIZE);
+    let distance = b.load::<I32>(header, 0);
+    let class = b.load::<I32>(header, 4);
+    let block = b.sub(ptr, distance);
+    let slot = b.shl(class, 2);
+    let head = b.add(slot, heap);
+    let next = b.load::<I32>(head, 0);
+    b.store::<I32>(block, next, 0);
+    b.store::<I32>(head, block, 0);
+    b.ret_void();
+
+    f.block(done).ret_void();
+    f.finish().expect("bundled free is valid")
+}
+
+/// `(size, align) -> ptr` of the bump allocator, returning 0 when memory
+/// cannot grow
+fn bump_alloc(heap_pages: u32) -> WasmIR {
+    let heap = heap_base(heap_pages);
+    let mut f = FunctionBuilder::new(ALLOC_EXPORT);
+    let size = f.param::<I32>();
+    let align = f.param::<I32>();
+    f.returns::<I32>();
+    let (place, grown, advance, fail) = (f.new_block(), f.new_block(), f.new_block(), f.new_block());
+    enter_heap(&mut f, (size, align), heap_pages, BUMP_STATE_SIZE, place, fail);
+
+    // An alignment of 0 means 1
+    let mut b = f.block(place);
+    let zero = b.eq(align, 0);
+    let align = b.or(align, zero);
+    let top = b.load::<I32>(heap, 0);
+    let padded = b.add(top, align);
+    let padded = b.sub(padded, 1);
+    let mask = b.neg(align);
+    let ptr = b.and(padded, mask);
+    b.jump(grown);
+    reserve(&mut f, grown, ptr, size.into(), advance, fail);
+
+    let mut b = f.block(advance);
+    let end = b.add(ptr, size);
+    b.store::<I32>(heap, end, 0);
+    b.ret(ptr);
+
+    f.block(fail).ret(0);
+    f.finish().expect("bump allocator is valid")
+}
+
+/// `(ptr, size, align)` of the bump allocator, which frees nothing
+fn bump_free() -> WasmIR {
+    let mut f = FunctionBuilder::new(FREE_EXPORT);
+    for _ in 0..3 {
+        f.param::<I32>();
+    }
+    f.block(f.entry()).ret_void();
+    f.finish().expect("bump free is valid")
+}
+
+/// `RESET_EXPORT`, moving the bump pointer back to the start of the heap
+fn bump_reset(heap_pages: u32) -> WasmIR {
+    let heap = heap_base(heap_pages);
+    let mut f = FunctionBuilder::new(RESET_EXPORT);
+    let (clear, done) = (f.new_block(), f.new_block());
+
+    // Before the first allocation there is no heap to reset
+    let mut b = f.block(f.entry());
+    let pages = b.memory_size();
+    let used = b.gt(pages, heap_pages as i32);
+    b.branch(used, clear, done);
+
+    let mut b = f.block(clear);
+    b.store::<I32>(heap, heap + BUMP_STATE_SIZE, 0);
+    b.ret_void();
+
+    f.block(done).ret_void();
+    f.finish().expect("bump reset is valid")
+}
+
+/// Forwards `ALLOC_EXPORT` to the host allocator
+fn host_alloc(import: u32) -> WasmIR {
+    let mut f = FunctionBuilder::new(ALLOC_EXPORT);
+    let size = f.param::<I32>();
+    let align = f.param::<I32>();
+    f.returns::<I32>();
+    let mut b = f.block(f.entry());
+    let args = [size, align].map(|var| Operand::Local(var.index()));
+    let ptr = b.call_import::<I32>(import, args);
+    b.ret(ptr);
+    f.finish().expect("host allocator shim is valid")
+}
+
+/// Forwards `FREE_EXPORT` to the host allocator
+fn host_free(import: u32) -> WasmIR {
+    let mut f = FunctionBuilder::new(FREE_EXPORT);
+    let params = [f.param::<I32>(), f.param::<I32>(), f.param::<I32>()];
+    let mut b = f.block(f.entry());
+    b.call_import_void(import, params.map(|var| Operand::Local(var.index())));
+    b.ret_void();
+    f.finish().expect("host free shim is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::Terminator;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn allocating_module() -> WasmModule {
+        let mut function = WasmIR::new("churn".to_string(), Signature { params: vec![], returns: None });
+        function.locals = vec![Type::I32];
+        function.add_basic_block(
+            vec![
+                Instruction::MemoryAlloc { size: int(16), align: None },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+                Instruction::MemoryFree { address: Operand::Local(0) },
+            ],
+            Terminator::Return { value: None },
+        );
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("churn", index);
+        module
+    }
+
+    #[test]
+    fn test_bundles_and_exports_an_allocator() {
+        let module = allocating_module();
+        assert!(needs_allocator(&module));
+        let lowered = lower_allocations(&module, AllocatorStrategy::Bundled).unwrap();
+        assert!(!needs_allocator(&lowered));
+        assert!(lowered.validate().is_ok());
+        assert_eq!(lowered.memory, Some(MemoryType { min_pages: 0, max_pages: None }));
+        assert!(lowered.imports.is_empty());
+
+        let export = |name: &str| lowered.exports.iter().find(|export| export.name == name).map(|export| &export.kind);
+        assert!(matches!(export(ALLOC_EXPORT), Some(ExportKind::Function(1))));
+        assert!(matches!(export(FREE_EXPORT), Some(ExportKind::Function(2))));
+        assert!(matches!(lowered.functions[0].basic_blocks[0].instructions.as_slice(), [
+            Instruction::Call { func_ref: 1, args: alloc_args },
+            Instruction::LocalSet { .. },
+            Instruction::Call { func_ref: 2, args: free_args },
+        ] if matches!(alloc_args.as_slice(), [_, Operand::Constant(Constant::I32(8))]) && free_args.len() == 3));
+        assert!(lowered.functions[1].all_instructions().any(|i| matches!(i, Instruction::MemoryGrow { .. })));
+    }
+
+    #[test]
+    fn test_forwards_to_a_host_allocator() {
+        let lowered = lower_allocations(&allocating_module(), AllocatorStrategy::Host).unwrap();
+        assert!(lowered.validate().is_ok());
+        assert_eq!(lowered.find_import(HOST_ALLOCATOR_MODULE, HOST_ALLOC_IMPORT), Some(0));
+        assert_eq!(lowered.find_import(HOST_ALLOCATOR_MODULE, HOST_FREE_IMPORT), Some(1));
+        assert!(matches!(
+            lowered.functions[2].basic_blocks[0].instructions.as_slice(),
+            [Instruction::CallImport { import: 1, args }] if args.len() == 3
+        ));
+        assert!(matches!(lowered.functions[2].basic_blocks[0].terminator, Terminator::Return { value: None }));
+    }
+
+    #[test]
+    fn test_bump_allocator_is_small_and_resettable() {
+        let bundled = lower_allocations(&allocating_module(), AllocatorStrategy::Bundled).unwrap();
+        let bump = lower_allocations(&allocating_module(), AllocatorStrategy::Bump).unwrap();
+        assert!(bump.validate().is_ok());
+        let size = |module: &WasmModule| module.functions[1..3].iter().map(|f| f.all_instructions().count()).sum::<usize>();
+        assert!(size(&bump) * 2 < size(&bundled), "{} vs {}", size(&bump), size(&bundled));
+
+        let reset = bump.exports.iter().find(|export| export.name == RESET_EXPORT).map(|export| &export.kind);
+        assert!(matches!(reset, Some(ExportKind::Function(3))));
+        assert!(bump.functions[2].all_instructions().next().is_none());
+        assert!(!bundled.exports.iter().any(|export| export.name == RESET_EXPORT));
+    }
+
+    #[test]
+    fn test_rejects_modules_it_cannot_extend() {
+        let mut module = allocating_module();
+        module.export_function(FREE_EXPORT, 0);
+        assert_eq!(
+            lower_allocations(&module, AllocatorStrategy::Bundled).unwrap_err().to_string(),
+            "module allocates but already exports __wasm_free"
+        );
+
+        let mut module = allocating_module();
+        module.export_function(RESET_EXPORT, 0);
+        assert!(lower_allocations(&module, AllocatorStrategy::Bundled).is_ok());
+        assert_eq!(
+            lower_allocations(&module, AllocatorStrategy::Bump).err(),
+            Some(AllocatorError::ConflictingExport(RESET_EXPORT))
+        );
+
+        let mut module = allocating_module();
+        module.set_memory(MemoryType { min_pages: MAX_PAGES, max_pages: None });
+        assert_eq!(lower_allocations(&module, AllocatorStrategy::Bundled).err(), Some(AllocatorError::NoHeapSpace));
+        assert!(lower_allocations(&module, AllocatorStrategy::Host).is_ok());
+    }
+}
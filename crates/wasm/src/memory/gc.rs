@@ -0,0 +1,435 @@
+//! Tracing garbage collector over linear memory
+//!
+//! Hosts with WasmGC keep garbage-collected objects in the engine's heap.
+//! Elsewhere `GcHeap` is the fallback: objects live in linear memory,
+//! taken from the global allocator, and a mark-sweep collector frees the
+//! ones no `Root` reaches. Types opt in with `#[wasm::gc]`, which
+//! implements `GcManaged` by marking every field that may hold a `Gc`.
+//!
+//! Allocation drives collection: once the bytes allocated since the last
+//! collection pass the heap's threshold, the next `alloc` collects first,
+//! and the threshold becomes `GROWTH_FACTOR` times the bytes that survived.
+//!
+//! A `Root` keeps its object, and everything the object reaches, alive.
+//! Objects refer to each other through `Gc`, a copyable pointer that stays
+//! valid while its target is reachable from a root. A `Gc` held outside
+//! the heap must be rooted with `Gc::root` before the heap allocates again.
+//! Roots do not implement `GcManaged`, so objects cannot hold them, and
+//! `Drop` impls of managed types must not dereference their `Gc` fields,
+//! whose targets may be swept first.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use core::mem;
+use core::ops::Deref;
+use core::ptr::NonNull;
+
+/// Bytes allocated before the first collection
+pub const MIN_THRESHOLD: usize = 64 * 1024;
+
+/// Ratio of the next collection threshold to the bytes that survived
+pub const GROWTH_FACTOR: usize = 2;
+
+/// Types the collector can trace
+///
+/// # Safety
+/// `gc_trace` must mark every `Gc` the value holds, directly or inline in
+/// its fields, or the collector frees objects that are still reachable.
+pub unsafe trait GcManaged {
+    /// Marks every `Gc` held by this value
+    fn gc_trace(&self, tracer: &mut Tracer);
+
+    /// Marks this value as part of a reachable object
+    ///
+    /// Values held inline trace their fields; `Gc` marks its target.
+    fn gc_mark(&self, tracer: &mut Tracer) {
+        self.gc_trace(tracer);
+    }
+}
+
+/// Header and value of a managed object
+struct GcBox<T: ?Sized> {
+    marked: Cell<bool>,
+    roots: Cell<u32>,
+    value: T,
+}
+
+type Object = NonNull<GcBox<dyn GcManaged>>;
+
+/// Objects marked but not yet traced during a collection
+pub struct Tracer {
+    gray: Vec<Object>,
+}
+
+impl Tracer {
+    fn visit(&mut self, object: Object) {
+        // SAFETY: marked objects are reachable, so not yet swept
+        let header = unsafe { object.as_ref() };
+        if !header.marked.replace(true) {
+            self.gray.push(object);
+        }
+    }
+}
+
+/// Pointer from one managed object to another
+pub struct Gc<T> {
+    ptr: NonNull<GcBox<T>>,
+}
+
+impl<T: GcManaged + 'static> Gc<T> {
+    /// Roots the target so it survives collections
+    pub fn root(self) -> Root<T> {
+        let header = self.header();
+        header.roots.set(header.roots.get() + 1);
+        Root { ptr: self.ptr }
+    }
+
+    /// Checks whether two pointers have the same target
+    pub fn ptr_eq(this: Self, other: Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    fn header(&self) -> &GcBox<T> {
+        // SAFETY: the target is reachable, as required of every `Gc` in use
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: GcManaged + 'static> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: GcManaged + 'static> Copy for Gc<T> {}
+
+impl<T: GcManaged + 'static> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.header().value
+    }
+}
+
+impl<T: GcManaged + fmt::Debug + 'static> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Gc").field(&**self).finish()
+    }
+}
+
+/// Pointer keeping a managed object alive
+pub struct Root<T> {
+    ptr: NonNull<GcBox<T>>,
+}
+
+impl<T: GcManaged + 'static> Root<T> {
+    /// Gets a pointer to the object for storing in other objects
+    pub fn gc(&self) -> Gc<T> {
+        Gc { ptr: self.ptr }
+    }
+}
+
+impl<T: GcManaged + 'static> Clone for Root<T> {
+    fn clone(&self) -> Self {
+        self.gc().root()
+    }
+}
+
+impl<T: GcManaged + 'static> Deref for Root<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: rooted objects are never swept
+        unsafe { &self.ptr.as_ref().value }
+    }
+}
+
+impl<T> Drop for Root<T> {
+    fn drop(&mut self) {
+        // SAFETY: rooted objects are never swept
+        let header = unsafe { self.ptr.as_ref() };
+        header.roots.set(header.roots.get() - 1);
+    }
+}
+
+impl<T: GcManaged + fmt::Debug + 'static> fmt::Debug for Root<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Root").field(&**self).finish()
+    }
+}
+
+/// Counters describing the heap after its last collection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Collections run so far
+    pub collections: usize,
+    /// Objects on the heap
+    pub live_objects: usize,
+    /// Bytes held by objects on the heap
+    pub live_bytes: usize,
+    /// Objects freed so far
+    pub freed_objects: usize,
+}
+
+/// Mark-sweep heap of managed objects
+pub struct GcHeap {
+    objects: Vec<Object>,
+    allocated: usize,
+    threshold: usize,
+    stats: GcStats,
+}
+
+impl GcHeap {
+    /// Creates an empty heap collecting after `MIN_THRESHOLD` bytes
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            allocated: 0,
+            threshold: MIN_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Sets the bytes allocated before the first collection
+    pub fn threshold(mut self, bytes: usize) -> Self {
+        self.threshold = bytes;
+        self
+    }
+
+    /// Moves `value` onto the heap, collecting first under allocation
+    /// pressure
+    pub fn alloc<T: GcManaged + 'static>(&mut self, value: T) -> Root<T> {
+        let size = mem::size_of::<GcBox<T>>();
+        if self.allocated + size > self.threshold {
+            self.collect();
+        }
+        let object = Box::new(GcBox { marked: Cell::new(false), roots: Cell::new(1), value });
+        let ptr = NonNull::from(Box::leak(object));
+        self.objects.push(ptr);
+        self.allocated += size;
+        self.stats.live_objects += 1;
+        self.stats.live_bytes += size;
+        Root { ptr }
+    }
+
+    /// Frees every object no root reaches, returning how many were freed
+    pub fn collect(&mut self) -> usize {
+        let mut tracer = Tracer { gray: Vec::new() };
+        for &object in &self.objects {
+            // SAFETY: objects on the heap are live until swept below
+            if unsafe { object.as_ref() }.roots.get() > 0 {
+                tracer.visit(object);
+            }
+        }
+        while let Some(object) = tracer.gray.pop() {
+            // SAFETY: as above
+            unsafe { object.as_ref() }.value.gc_trace(&mut tracer);
+        }
+
+        let (mut freed, mut live_bytes) = (0, 0);
+        self.objects.retain(|&object| {
+            // SAFETY: as above
+            let header = unsafe { object.as_ref() };
+            if header.marked.replace(false) {
+                live_bytes += mem::size_of_val(header);
+                true
+            } else {
+                // SAFETY: unmarked objects are unreachable, so nothing uses them
+                drop(unsafe { Box::from_raw(object.as_ptr()) });
+                freed += 1;
+                false
+            }
+        });
+
+        self.allocated = 0;
+        self.threshold = (live_bytes * GROWTH_FACTOR).max(MIN_THRESHOLD);
+        self.stats.collections += 1;
+        self.stats.live_objects = self.objects.len();
+        self.stats.live_bytes = live_bytes;
+        self.stats.freed_objects += freed;
+        freed
+    }
+
+    /// Gets the heap's counters
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+}
+
+impl Default for GcHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for GcHeap {
+    /// Frees every object, unless a root outlives the heap, in which case
+    /// all of them are leaked so the root stays valid
+    fn drop(&mut self) {
+        // SAFETY: objects on the heap are live
+        if self.objects.iter().any(|object| unsafe { object.as_ref() }.roots.get() > 0) {
+            return;
+        }
+        for object in self.objects.drain(..) {
+            // SAFETY: nothing roots the objects, and the heap is gone
+            drop(unsafe { Box::from_raw(object.as_ptr()) });
+        }
+    }
+}
+
+// SAFETY: `Gc` marks its target, which traces the rest
+unsafe impl<T: GcManaged + 'static> GcManaged for Gc<T> {
+    fn gc_trace(&self, tracer: &mut Tracer) {
+        tracer.visit(self.ptr);
+    }
+}
+
+macro_rules! leaf_types {
+    ($($ty:ty),*) => {
+        $(
+            // SAFETY: holds no `Gc`
+            unsafe impl GcManaged for $ty {
+                fn gc_trace(&self, _tracer: &mut Tracer) {}
+            }
+        )*
+    };
+}
+
+leaf_types!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char, (), String, &'static str);
+
+// SAFETY: marks the value if present
+unsafe impl<T: GcManaged> GcManaged for Option<T> {
+    fn gc_trace(&self, tracer: &mut Tracer) {
+        if let Some(value) = self {
+            value.gc_mark(tracer);
+        }
+    }
+}
+
+// SAFETY: marks every element
+unsafe impl<T: GcManaged> GcManaged for Vec<T> {
+    fn gc_trace(&self, tracer: &mut Tracer) {
+        for value in self {
+            value.gc_mark(tracer);
+        }
+    }
+}
+
+// SAFETY: marks every element
+unsafe impl<T: GcManaged, const N: usize> GcManaged for [T; N] {
+    fn gc_trace(&self, tracer: &mut Tracer) {
+        for value in self {
+            value.gc_mark(tracer);
+        }
+    }
+}
+
+// SAFETY: marks the boxed value
+unsafe impl<T: GcManaged + ?Sized> GcManaged for Box<T> {
+    fn gc_trace(&self, tracer: &mut Tracer) {
+        (**self).gc_mark(tracer);
+    }
+}
+
+// SAFETY: marks a copy of the current value, which holds the same pointers
+unsafe impl<T: GcManaged + Copy> GcManaged for Cell<T> {
+    fn gc_trace(&self, tracer: &mut Tracer) {
+        self.get().gc_mark(tracer);
+    }
+}
+
+// SAFETY: marks the current value
+unsafe impl<T: GcManaged> GcManaged for RefCell<T> {
+    /// # Panics
+    /// If the value is mutably borrowed while the heap collects.
+    fn gc_trace(&self, tracer: &mut Tracer) {
+        self.try_borrow()
+            .expect("RefCell in a managed object was mutably borrowed during a collection")
+            .gc_mark(tracer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+
+    struct Node {
+        next: Cell<Option<Gc<Node>>>,
+        drops: Rc<Cell<u32>>,
+    }
+
+    // SAFETY: marks the only `Gc` field
+    unsafe impl GcManaged for Node {
+        fn gc_trace(&self, tracer: &mut Tracer) {
+            self.next.gc_mark(tracer);
+        }
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    fn node(heap: &mut GcHeap, drops: &Rc<Cell<u32>>) -> Root<Node> {
+        heap.alloc(Node { next: Cell::new(None), drops: drops.clone() })
+    }
+
+    #[test]
+    fn test_collects_unreachable_cycles() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = GcHeap::new();
+        let (a, b) = (node(&mut heap, &drops), node(&mut heap, &drops));
+        a.next.set(Some(b.gc()));
+        b.next.set(Some(a.gc()));
+        drop(b);
+
+        // `b` is reachable through the rooted `a`
+        assert_eq!(heap.collect(), 0);
+        assert!(Gc::ptr_eq(a.next.get().unwrap().next.get().unwrap(), a.gc()));
+
+        drop(a);
+        assert_eq!(heap.collect(), 2);
+        assert_eq!(drops.get(), 2);
+        assert_eq!(heap.stats().live_objects, 0);
+    }
+
+    #[test]
+    fn test_allocation_pressure_triggers_collection() {
+        let drops = Rc::new(Cell::new(0));
+        let size = mem::size_of::<GcBox<Node>>();
+        let mut heap = GcHeap::new().threshold(3 * size);
+        let kept = node(&mut heap, &drops);
+        for _ in 0..3 {
+            node(&mut heap, &drops);
+        }
+
+        // The fourth allocation passed the threshold and freed the first
+        // two garbage nodes
+        assert_eq!(heap.stats().collections, 1);
+        assert_eq!(drops.get(), 2);
+        assert_eq!(heap.stats().live_bytes, 2 * size);
+        assert_eq!(kept.drops.get(), 2);
+    }
+
+    #[test]
+    fn test_rooted_gc_survives_and_roots_outliving_the_heap_leak() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = GcHeap::new();
+        let first = node(&mut heap, &drops);
+        let pointer = first.gc();
+        let rooted = pointer.root();
+        drop(first);
+        heap.collect();
+        assert_eq!(drops.get(), 0);
+        assert!(rooted.next.get().is_none());
+
+        drop(heap);
+        assert_eq!(drops.get(), 0);
+        assert!(rooted.next.get().is_none());
+    }
+}
@@ -0,0 +1,474 @@
+//! Layout of aggregate types in linear memory
+//!
+//! Computes size, alignment, field offsets, and enum discriminant
+//! encodings for wasm32, following rustc: `repr(Rust)` structs reorder
+//! fields to reduce padding, enums use the smallest tag that fits, and an
+//! enum whose other variants carry no data hides their discriminants in
+//! invalid values of a field of the dataful one, so `Option<&T>` is a
+//! single pointer. The field order chosen for `repr(Rust)` is an
+//! implementation detail; offsets are always reported in declaration
+//! order.
+//!
+//! ```
+//! use wasm::memory::layout::{Layout, Primitive, Repr, Shape};
+//!
+//! let point = Shape::Struct {
+//!     fields: vec![Shape::Primitive(Primitive::U8), Shape::Primitive(Primitive::I64)],
+//!     repr: Repr::Rust,
+//! };
+//! let layout = Layout::of(&point);
+//! assert_eq!((layout.size, layout.align), (16, 8));
+//! assert_eq!(layout.field_offset(0), Some(8));
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Scalar stored in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    U128,
+    I128,
+    F32,
+    F64,
+    Char,
+    /// Raw pointer, which may be null
+    Pointer,
+    /// Reference or `NonNull`, never null
+    NonNullPointer,
+}
+
+impl Primitive {
+    /// Size in bytes
+    pub fn size(self) -> u32 {
+        match self {
+            Primitive::Bool | Primitive::U8 | Primitive::I8 => 1,
+            Primitive::U16 | Primitive::I16 => 2,
+            Primitive::U32 | Primitive::I32 | Primitive::F32 | Primitive::Char => 4,
+            Primitive::Pointer | Primitive::NonNullPointer => 4,
+            Primitive::U64 | Primitive::I64 | Primitive::F64 => 8,
+            Primitive::U128 | Primitive::I128 => 16,
+        }
+    }
+
+    /// Alignment in bytes; 128-bit integers are aligned like `i64` on wasm32
+    pub fn align(self) -> u32 {
+        self.size().min(8)
+    }
+
+    /// Values outside this inclusive range are invalid and free for niches
+    fn valid_range(self) -> Option<(u64, u64)> {
+        match self {
+            Primitive::Bool => Some((0, 1)),
+            Primitive::Char => Some((0, 0x10FFFF)),
+            Primitive::NonNullPointer => Some((1, u32::MAX as u64)),
+            _ => None,
+        }
+    }
+}
+
+/// Field ordering rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Repr {
+    /// Fields may be reordered, and enums may use niches
+    #[default]
+    Rust,
+    /// Fields in declaration order; enums have an `i32` tag and no niche
+    C,
+}
+
+/// Type whose layout is computed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Primitive(Primitive),
+    /// Fields in declaration order
+    Struct { fields: Vec<Shape>, repr: Repr },
+    /// Laid out like a `repr(Rust)` struct
+    Tuple(Vec<Shape>),
+    Array { element: Box<Shape>, len: u32 },
+    /// Fields of each variant; discriminants count from zero
+    Enum { variants: Vec<Vec<Shape>>, repr: Repr },
+}
+
+/// Where the fields of a type are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fields {
+    Primitive,
+    /// Element `i` is at `i * stride`
+    Array { stride: u32, len: u32 },
+    /// Offset of each field, in declaration order
+    Offsets(Vec<u32>),
+}
+
+/// Invalid values of a scalar inside a type, usable to encode other data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Niche {
+    pub offset: u32,
+    /// Size of the scalar in bytes
+    pub size: u32,
+    /// Valid values, inclusive and possibly wrapping around
+    pub valid_start: u64,
+    pub valid_end: u64,
+}
+
+impl Niche {
+    fn modulus(self) -> u128 {
+        1u128 << (self.size * 8)
+    }
+
+    /// Number of invalid values
+    pub fn available(self) -> u128 {
+        let valid = (self.valid_end as u128 + self.modulus() - self.valid_start as u128) % self.modulus() + 1;
+        self.modulus() - valid
+    }
+
+    /// Reserves `count` invalid values just past the valid range
+    ///
+    /// Returns the first reserved value and the niche that remains.
+    fn reserve(self, count: u128) -> Option<(u64, Niche)> {
+        if count == 0 || count > self.available() {
+            return None;
+        }
+        let start = ((self.valid_end as u128 + 1) % self.modulus()) as u64;
+        let end = ((self.valid_end as u128 + count) % self.modulus()) as u64;
+        Some((start, Niche { valid_end: end, ..self }))
+    }
+}
+
+/// How the variant of an enum value is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagEncoding {
+    /// The tag holds the variant index
+    Direct,
+    /// Variant `untagged` stores its data as usual; variants `first..=last`
+    /// store `start + (variant - first)` in the niche, wrapping at its size
+    Niche { untagged: u32, first: u32, last: u32, start: u64 },
+}
+
+/// Location and encoding of an enum's discriminant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tag {
+    pub offset: u32,
+    /// Size in bytes
+    pub size: u32,
+    pub encoding: TagEncoding,
+}
+
+/// Variants of an enum with more than one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Variants {
+    /// Not an enum, or an enum with at most one variant, whose fields are
+    /// in `Layout::fields`
+    Single,
+    Multiple {
+        tag: Tag,
+        /// Offsets of each variant's fields, in declaration order
+        fields: Vec<Vec<u32>>,
+    },
+}
+
+/// Memory layout of a type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u32,
+    pub align: u32,
+    pub fields: Fields,
+    pub variants: Variants,
+    /// Largest niche, used when this type is a variant's field
+    pub niche: Option<Niche>,
+}
+
+impl Layout {
+    /// Computes the layout of a type
+    pub fn of(shape: &Shape) -> Layout {
+        match shape {
+            Shape::Primitive(primitive) => {
+                let size = primitive.size();
+                Layout {
+                    size,
+                    align: primitive.align(),
+                    fields: Fields::Primitive,
+                    variants: Variants::Single,
+                    niche: primitive.valid_range()
+                        .map(|(valid_start, valid_end)| Niche { offset: 0, size, valid_start, valid_end }),
+                }
+            }
+            Shape::Struct { fields, repr } => record(fields, *repr, 0, false),
+            Shape::Tuple(fields) => record(fields, Repr::Rust, 0, false),
+            Shape::Array { element, len } => {
+                let element = Layout::of(element);
+                Layout {
+                    size: element.size * len,
+                    align: element.align,
+                    fields: Fields::Array { stride: element.size, len: *len },
+                    variants: Variants::Single,
+                    niche: element.niche.filter(|_| *len > 0),
+                }
+            }
+            Shape::Enum { variants, repr } => enumeration(variants, *repr),
+        }
+    }
+
+    /// Offset of a field of a struct, tuple, array, or single-variant enum
+    pub fn field_offset(&self, field: u32) -> Option<u32> {
+        match &self.fields {
+            Fields::Primitive => None,
+            Fields::Array { stride, len } => (field < *len).then(|| field * stride),
+            Fields::Offsets(offsets) => offsets.get(field as usize).copied(),
+        }
+    }
+
+    /// Offset of a field of one variant of an enum
+    pub fn variant_field_offset(&self, variant: u32, field: u32) -> Option<u32> {
+        match &self.variants {
+            Variants::Single if variant == 0 => self.field_offset(field),
+            Variants::Single => None,
+            Variants::Multiple { fields, .. } => fields.get(variant as usize)?.get(field as usize).copied(),
+        }
+    }
+
+    /// Value to write to the tag to select `variant`, as offset, size, and
+    /// value; `None` if nothing is written, as for the untagged variant of
+    /// a niche-encoded enum
+    pub fn discriminant_store(&self, variant: u32) -> Option<(u32, u32, u64)> {
+        let Variants::Multiple { tag, .. } = &self.variants else { return None };
+        match tag.encoding {
+            TagEncoding::Direct => Some((tag.offset, tag.size, variant as u64)),
+            TagEncoding::Niche { untagged, .. } if variant == untagged => None,
+            TagEncoding::Niche { first, start, .. } => {
+                let modulus = 1u128 << (tag.size * 8);
+                let value = (start as u128 + (variant - first) as u128) % modulus;
+                Some((tag.offset, tag.size, value as u64))
+            }
+        }
+    }
+
+    /// Variant selected by a value read from the tag
+    pub fn variant_of(&self, tag_value: u64) -> u32 {
+        let Variants::Multiple { tag, .. } = &self.variants else { return 0 };
+        match tag.encoding {
+            TagEncoding::Direct => tag_value as u32,
+            TagEncoding::Niche { untagged, first, last, start } => {
+                let modulus = 1u128 << (tag.size * 8);
+                let relative = (tag_value as u128 + modulus - start as u128) % modulus;
+                if relative <= (last - first) as u128 {
+                    first + relative as u32
+                } else {
+                    untagged
+                }
+            }
+        }
+    }
+}
+
+fn align_to(offset: u32, align: u32) -> u32 {
+    offset.div_ceil(align) * align
+}
+
+/// Lays out fields after `start` bytes, as a struct or an enum variant
+///
+/// Variants place their smallest fields first, next to the tag.
+fn record(shapes: &[Shape], repr: Repr, start: u32, variant: bool) -> Layout {
+    let layouts: Vec<Layout> = shapes.iter().map(Layout::of).collect();
+    let mut order: Vec<usize> = (0..layouts.len()).collect();
+    if repr == Repr::Rust {
+        if variant {
+            order.sort_by_key(|&field| layouts[field].align);
+        } else {
+            order.sort_by_key(|&field| core::cmp::Reverse(layouts[field].align));
+        }
+    }
+
+    let mut offsets = vec![0; layouts.len()];
+    let mut offset = start;
+    let mut align = 1;
+    let mut niche: Option<Niche> = None;
+    for field in order {
+        let layout = &layouts[field];
+        offset = align_to(offset, layout.align);
+        offsets[field] = offset;
+        if let Some(field_niche) = layout.niche {
+            if !matches!(niche, Some(best) if best.available() >= field_niche.available()) {
+                niche = Some(Niche { offset: offset + field_niche.offset, ..field_niche });
+            }
+        }
+        offset += layout.size;
+        align = align.max(layout.align);
+    }
+    Layout {
+        size: align_to(offset, align),
+        align,
+        fields: Fields::Offsets(offsets),
+        variants: Variants::Single,
+        niche,
+    }
+}
+
+fn enumeration(variants: &[Vec<Shape>], repr: Repr) -> Layout {
+    if variants.len() <= 1 {
+        let fields = variants.first().map(Vec::as_slice).unwrap_or_default();
+        return record(fields, repr, 0, false);
+    }
+
+    let tag_size = match (repr, variants.len() - 1) {
+        (Repr::C, _) => 4,
+        (_, max) if max <= u8::MAX as usize => 1,
+        (_, max) if max <= u16::MAX as usize => 2,
+        _ => 4,
+    };
+    let layouts: Vec<Layout> = variants.iter().map(|fields| record(fields, repr, tag_size, true)).collect();
+    let align = layouts.iter().map(|layout| layout.align).fold(tag_size, u32::max);
+    let size = align_to(layouts.iter().map(|layout| layout.size).fold(tag_size, u32::max), align);
+    let tagged = Layout {
+        size,
+        align,
+        fields: Fields::Offsets(Vec::new()),
+        variants: Variants::Multiple {
+            tag: Tag { offset: 0, size: tag_size, encoding: TagEncoding::Direct },
+            fields: layouts.iter().map(offsets).collect(),
+        },
+        niche: (repr == Repr::Rust).then_some(Niche {
+            offset: 0,
+            size: tag_size,
+            valid_start: 0,
+            valid_end: variants.len() as u64 - 1,
+        }),
+    };
+    if repr == Repr::C {
+        return tagged;
+    }
+
+    match niche_encoded(variants) {
+        Some(niche) if niche.size <= tagged.size => niche,
+        _ => tagged,
+    }
+}
+
+/// Layout storing the discriminant in a niche of the only dataful variant
+fn niche_encoded(variants: &[Vec<Shape>]) -> Option<Layout> {
+    let layouts: Vec<Layout> = variants.iter().map(|fields| record(fields, Repr::Rust, 0, false)).collect();
+    let mut dataful = layouts.iter().enumerate().filter(|(_, layout)| layout.size > 0);
+    let (untagged, data) = dataful.next()?;
+    if dataful.next().is_some() {
+        return None;
+    }
+    // Like rustc, reserve values for the range of the other variants,
+    // including the untagged one if it lies inside
+    let untagged = untagged as u32;
+    let last_variant = variants.len() as u32 - 1;
+    let first = if untagged == 0 { 1 } else { 0 };
+    let last = if untagged == last_variant { last_variant - 1 } else { last_variant };
+    let niche = data.niche?;
+    let (start, remaining) = niche.reserve((last - first + 1) as u128)?;
+    Some(Layout {
+        size: data.size,
+        align: data.align,
+        fields: Fields::Offsets(Vec::new()),
+        variants: Variants::Multiple {
+            tag: Tag {
+                offset: niche.offset,
+                size: niche.size,
+                encoding: TagEncoding::Niche { untagged, first, last, start },
+            },
+            fields: layouts.iter().map(offsets).collect(),
+        },
+        niche: Some(remaining),
+    })
+}
+
+fn offsets(layout: &Layout) -> Vec<u32> {
+    match &layout.fields {
+        Fields::Offsets(offsets) => offsets.clone(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(primitive: Primitive) -> Shape {
+        Shape::Primitive(primitive)
+    }
+
+    #[test]
+    fn test_struct_field_order_and_padding() {
+        let fields = vec![p(Primitive::U8), p(Primitive::I64), p(Primitive::U16)];
+        let rust = Layout::of(&Shape::Struct { fields: fields.clone(), repr: Repr::Rust });
+        assert_eq!((rust.size, rust.align), (16, 8));
+        assert_eq!(rust.fields, Fields::Offsets(vec![10, 0, 8]));
+
+        let c = Layout::of(&Shape::Struct { fields, repr: Repr::C });
+        assert_eq!((c.size, c.align), (24, 8));
+        assert_eq!(c.fields, Fields::Offsets(vec![0, 8, 16]));
+
+        let array = Layout::of(&Shape::Array { element: Box::new(Shape::Tuple(vec![p(Primitive::I32), p(Primitive::Bool)])), len: 3 });
+        assert_eq!(array.size, 24);
+        assert_eq!(array.field_offset(2), Some(16));
+        assert_eq!(array.field_offset(3), None);
+    }
+
+    #[test]
+    fn test_tagged_enum() {
+        // enum Shape { Circle(f32), Rect(u16, u16), Empty }
+        let shape = Shape::Enum {
+            variants: vec![vec![p(Primitive::F32)], vec![p(Primitive::U16), p(Primitive::U16)], vec![]],
+            repr: Repr::Rust,
+        };
+        let layout = Layout::of(&shape);
+        assert_eq!((layout.size, layout.align), (8, 4));
+        assert_eq!(layout.variant_field_offset(0, 0), Some(4));
+        assert_eq!(layout.variant_field_offset(1, 1), Some(4));
+        assert_eq!(layout.discriminant_store(2), Some((0, 1, 2)));
+        assert_eq!(layout.variant_of(1), 1);
+
+        // The unused tag values are a niche for an enclosing `Option`
+        let option = Layout::of(&Shape::Enum { variants: vec![vec![], vec![shape]], repr: Repr::Rust });
+        assert_eq!(option.size, 8);
+        assert_eq!(option.discriminant_store(0), Some((0, 1, 3)));
+        assert_eq!(option.variant_of(3), 0);
+        assert_eq!(option.variant_of(2), 1);
+
+        let c = Layout::of(&Shape::Enum { variants: vec![vec![], vec![p(Primitive::U8)]], repr: Repr::C });
+        assert_eq!((c.size, c.align), (8, 4));
+        assert_eq!(c.niche, None);
+    }
+
+    #[test]
+    fn test_niche_encoding() {
+        // Option<&T> is a nullable pointer
+        let option_ref = Shape::Enum { variants: vec![vec![], vec![p(Primitive::NonNullPointer)]], repr: Repr::Rust };
+        let layout = Layout::of(&option_ref);
+        assert_eq!((layout.size, layout.align), (4, 4));
+        assert_eq!(layout.discriminant_store(0), Some((0, 4, 0)));
+        assert_eq!(layout.discriminant_store(1), None);
+        assert_eq!(layout.variant_of(0), 0);
+        assert_eq!(layout.variant_of(0x1000), 1);
+
+        // Option<Option<bool>> takes 2 and then 3 from the bool's niche
+        let option_bool = Shape::Enum { variants: vec![vec![], vec![p(Primitive::Bool)]], repr: Repr::Rust };
+        let nested = Layout::of(&Shape::Enum { variants: vec![vec![], vec![option_bool]], repr: Repr::Rust });
+        assert_eq!(nested.size, 1);
+        assert_eq!(nested.discriminant_store(0), Some((0, 1, 3)));
+        assert_eq!(nested.variant_of(3), 0);
+        assert_eq!(nested.variant_of(2), 1);
+        assert_eq!(nested.variant_of(1), 1);
+
+        // The dataful variant may come first
+        let result = Layout::of(&Shape::Enum { variants: vec![vec![p(Primitive::Char)], vec![], vec![]], repr: Repr::Rust });
+        assert_eq!(result.size, 4);
+        assert_eq!(result.discriminant_store(2), Some((0, 4, 0x110001)));
+        assert_eq!(result.variant_of(0x110000), 1);
+        assert_eq!(result.variant_of(b'a' as u64), 0);
+    }
+}
@@ -0,0 +1,493 @@
+//! Named regions of linear memory and the accesses they permit
+//!
+//! A module declares its regions with `WasmModule::declare_memory_region`:
+//! read-only data, the heap, the stack, memory shared between threads, or
+//! any other named range. `tag_region_accesses` finds the region of every
+//! load and store whose address is a constant, or a local typed
+//! `Type::Capability` with `Capability::MemoryRegion`, rejects accesses the
+//! region does not permit, and tags the rest with a
+//! `Instruction::CapabilityCheck` in front of them. Tags emit no code.
+//!
+//! Constant addresses are checked entirely at compile time. An address
+//! held in a local may stray out of its region at run time, so debug
+//! builds run `check_region_accesses`, which replaces the tags in front of
+//! such accesses with a check that turns a stray address into one past the
+//! end of memory. The access then traps as out of bounds, without the
+//! check branching. Regions stay below 2 GiB so the signed comparisons of
+//! WasmIR order their addresses.
+
+use core::fmt;
+
+use crate::wasmir::{BinaryOp, Capability, Constant, Instruction, Operand, Type, WasmIR, WasmModule};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Region of constants and string literals, which no store may write
+pub const RO_DATA_REGION: &str = "ro-data";
+
+/// Region the allocator hands out
+pub const HEAP_REGION: &str = "heap";
+
+/// Region of the shadow stack
+pub const STACK_REGION: &str = "stack";
+
+/// Region threads share, which needs shared memory
+pub const SHARED_REGION: &str = "shared";
+
+/// First address regions may not reach
+const SIGNED_LIMIT: u64 = 1 << 31;
+
+/// Accesses a region permits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Permissions {
+    pub const READ_ONLY: Self = Self { read: true, write: false };
+    pub const READ_WRITE: Self = Self { read: true, write: true };
+}
+
+/// Named range of linear memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    /// Address of the first byte
+    pub start: u32,
+    /// Bytes in the region
+    pub size: u32,
+    pub permissions: Permissions,
+    /// Whether threads share the region
+    pub shared: bool,
+}
+
+impl Region {
+    /// Creates a readable and writable region private to a thread
+    pub fn new(name: impl Into<String>, start: u32, size: u32) -> Self {
+        Self { name: name.into(), start, size, permissions: Permissions::READ_WRITE, shared: false }
+    }
+
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn read_only(self) -> Self {
+        self.permissions(Permissions::READ_ONLY)
+    }
+
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    /// First address past the region
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.size as u64
+    }
+
+    /// Checks whether `bytes` bytes at `address` lie within the region
+    pub fn contains(&self, address: u64, bytes: u32) -> bool {
+        address >= self.start as u64 && address + bytes as u64 <= self.end()
+    }
+
+    fn overlaps(&self, other: &Region) -> bool {
+        (self.start as u64) < other.end() && (other.start as u64) < self.end()
+    }
+
+    fn permits(&self, write: bool) -> bool {
+        if write { self.permissions.write } else { self.permissions.read }
+    }
+}
+
+/// Reasons a region cannot be declared or an access is rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegionError {
+    /// Another region already has the name
+    Duplicate(String),
+    /// The region shares addresses with another
+    Overlap { region: String, other: String },
+    /// The region has no bytes
+    Empty(String),
+    /// The region reaches past 2 GiB
+    OutOfRange(String),
+    /// The region is shared but the module's memory is not
+    NotShared(String),
+    /// An access names a region the module does not declare
+    Unknown { function: String, region: String },
+    /// An access the region does not permit
+    Forbidden { function: String, region: String, write: bool },
+    /// An access at a constant address reaching past the end of its region
+    Straddles { function: String, region: String, address: u64 },
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionError::Duplicate(name) => write!(f, "memory region {} is declared twice", name),
+            RegionError::Overlap { region, other } => {
+                write!(f, "memory region {} overlaps memory region {}", region, other)
+            }
+            RegionError::Empty(name) => write!(f, "memory region {} is empty", name),
+            RegionError::OutOfRange(name) => write!(f, "memory region {} reaches past 2 GiB", name),
+            RegionError::NotShared(name) => {
+                write!(f, "memory region {} is shared but the module's memory is not", name)
+            }
+            RegionError::Unknown { function, region } => {
+                write!(f, "{} accesses undeclared memory region {}", function, region)
+            }
+            RegionError::Forbidden { function, region, write: true } => {
+                write!(f, "{} stores to read-only memory region {}", function, region)
+            }
+            RegionError::Forbidden { function, region, write: false } => {
+                write!(f, "{} loads from unreadable memory region {}", function, region)
+            }
+            RegionError::Straddles { function, region, address } => {
+                write!(f, "{} accesses {:#x}, past the end of memory region {}", function, address, region)
+            }
+        }
+    }
+}
+
+/// Checks that `region` can join the regions already declared
+pub(crate) fn check_declaration(regions: &[Region], region: &Region) -> Result<(), RegionError> {
+    if region.size == 0 {
+        return Err(RegionError::Empty(region.name.clone()));
+    }
+    if region.end() > SIGNED_LIMIT {
+        return Err(RegionError::OutOfRange(region.name.clone()));
+    }
+    for other in regions {
+        if other.name == region.name {
+            return Err(RegionError::Duplicate(region.name.clone()));
+        }
+        if other.overlaps(region) {
+            return Err(RegionError::Overlap { region: region.name.clone(), other: other.name.clone() });
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether the module declares any memory region
+pub fn has_memory_regions(module: &WasmModule) -> bool {
+    !module.memory_regions.is_empty()
+}
+
+/// Returns a copy of `module` with every load and store of a known region
+/// tagged with its region's `CapabilityCheck`
+///
+/// Functions with tagged accesses also list the regions among their
+/// capabilities. Accesses already tagged are left alone, so the pass may
+/// run more than once.
+pub fn tag_region_accesses(module: &WasmModule) -> Result<WasmModule, RegionError> {
+    if let Some(region) = module.memory_regions.iter().find(|region| region.shared && !module.shared_memory) {
+        return Err(RegionError::NotShared(region.name.clone()));
+    }
+    let mut tagged = module.clone();
+    for function in &mut tagged.functions {
+        tag_function(function, &module.memory_regions)?;
+    }
+    Ok(tagged)
+}
+
+/// Returns a copy of `module` in which loads and stores through a local
+/// trap when the address leaves the region the local is typed with, and
+/// the number of accesses checked
+///
+/// Run this on the output of `tag_region_accesses`; only accesses tagged
+/// there are checked, and checking consumes their tags.
+pub fn check_region_accesses(module: &WasmModule) -> (WasmModule, usize) {
+    let mut checked = module.clone();
+    let mut count = 0;
+    for function in &mut checked.functions {
+        count += check_function(function, &module.memory_regions);
+    }
+    (checked, count)
+}
+
+/// An access of a region: its address, static offset, and size
+struct Access<'a> {
+    address: &'a Operand,
+    offset: u32,
+    bytes: u32,
+    write: bool,
+}
+
+fn access(instruction: &Instruction) -> Option<Access<'_>> {
+    match instruction {
+        Instruction::MemoryLoad { address, ty, offset, .. } => {
+            Some(Access { address, offset: *offset, bytes: bytes(ty), write: false })
+        }
+        Instruction::MemoryStore { address, ty, offset, .. } => {
+            Some(Access { address, offset: *offset, bytes: bytes(ty), write: true })
+        }
+        _ => None,
+    }
+}
+
+fn bytes(ty: &Type) -> u32 {
+    match ty {
+        Type::I64 | Type::F64 => 8,
+        Type::I128 => 16,
+        _ => 4,
+    }
+}
+
+/// Region a local holding an address is typed with
+fn local_region(function: &WasmIR, local: u32) -> Option<String> {
+    match function.operand_type(&Operand::Local(local))? {
+        Type::Capability { capability: Capability::MemoryRegion(region), .. } => Some(region),
+        _ => None,
+    }
+}
+
+fn tag(region: &str) -> Instruction {
+    Instruction::CapabilityCheck { capability: Capability::MemoryRegion(region.to_string()) }
+}
+
+fn is_tag(instruction: Option<&Instruction>, region: &str) -> bool {
+    matches!(
+        instruction,
+        Some(Instruction::CapabilityCheck { capability: Capability::MemoryRegion(tagged) }) if tagged == region
+    )
+}
+
+fn tag_function(function: &mut WasmIR, regions: &[Region]) -> Result<(), RegionError> {
+    let mut used = Vec::new();
+    for block in 0..function.basic_blocks.len() {
+        let instructions = core::mem::take(&mut function.basic_blocks[block].instructions);
+        let mut rewritten = Vec::with_capacity(instructions.len());
+        for instruction in instructions {
+            if let Some(access) = access(&instruction) {
+                if let Some(region) = region_of(function, regions, &access)? {
+                    if !region.permits(access.write) {
+                        return Err(RegionError::Forbidden {
+                            function: function.name.clone(),
+                            region: region.name.clone(),
+                            write: access.write,
+                        });
+                    }
+                    if !is_tag(rewritten.last(), &region.name) {
+                        rewritten.push(tag(&region.name));
+                    }
+                    if !used.contains(&region.name) {
+                        used.push(region.name.clone());
+                    }
+                }
+            }
+            rewritten.push(instruction);
+        }
+        function.basic_blocks[block].instructions = rewritten;
+    }
+    for region in used {
+        let capability = Capability::MemoryRegion(region);
+        if !function.capabilities.contains(&capability) {
+            function.capabilities.push(capability);
+        }
+    }
+    Ok(())
+}
+
+fn region_of<'r>(function: &WasmIR, regions: &'r [Region], access: &Access) -> Result<Option<&'r Region>, RegionError> {
+    match access.address {
+        Operand::Constant(Constant::I32(address)) => {
+            let address = *address as u32 as u64 + access.offset as u64;
+            let Some(region) = regions.iter().find(|region| region.contains(address, 1)) else {
+                return Ok(None);
+            };
+            if !region.contains(address, access.bytes) {
+                return Err(RegionError::Straddles {
+                    function: function.name.clone(),
+                    region: region.name.clone(),
+                    address,
+                });
+            }
+            Ok(Some(region))
+        }
+        Operand::Local(local) => match local_region(function, *local) {
+            Some(name) => regions.iter().find(|region| region.name == name).map(Some).ok_or_else(|| {
+                RegionError::Unknown { function: function.name.clone(), region: name }
+            }),
+            None => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn check_function(function: &mut WasmIR, regions: &[Region]) -> usize {
+    let checked = function.all_instructions().any(|instruction| {
+        access(instruction).is_some_and(|access| matches!(access.address, Operand::Local(_)))
+    });
+    if !checked {
+        return 0;
+    }
+
+    // Temporaries are allocated after the parameters
+    while function.locals.len() < function.signature.params.len() {
+        let ty = function.signature.params[function.locals.len()].clone();
+        function.locals.push(ty);
+    }
+
+    let mut count = 0;
+    let mut temporary = None;
+    for block in 0..function.basic_blocks.len() {
+        let instructions = core::mem::take(&mut function.basic_blocks[block].instructions);
+        let mut rewritten = Vec::with_capacity(instructions.len());
+        for mut instruction in instructions {
+            let guard = access(&instruction).and_then(|access| {
+                let Operand::Local(local) = *access.address else {
+                    return None;
+                };
+                let region = regions.iter().find(|region| is_tag(rewritten.last(), &region.name))?;
+                Some((local, bounds(region, &access)))
+            });
+            if let Some((local, (low, high))) = guard {
+                rewritten.pop();
+                let checked = *temporary.get_or_insert_with(|| {
+                    function.locals.push(Type::I32);
+                    function.locals.len() as u32 - 1
+                });
+                rewritten.extend(poison(local, low, high, checked));
+                match &mut instruction {
+                    Instruction::MemoryLoad { address, .. } | Instruction::MemoryStore { address, .. } => {
+                        *address = Operand::Local(checked);
+                    }
+                    _ => unreachable!("only loads and stores are checked"),
+                }
+                count += 1;
+            }
+            rewritten.push(instruction);
+        }
+        function.basic_blocks[block].instructions = rewritten;
+    }
+    count
+}
+
+/// Lowest and highest address the access may be made through
+fn bounds(region: &Region, access: &Access) -> (i32, i32) {
+    let clamp = |address: i64| address.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    let low = region.start as i64 - access.offset as i64;
+    let high = region.end() as i64 - access.offset as i64 - access.bytes as i64;
+    (clamp(low.max(0)), clamp(high))
+}
+
+/// Sets `checked` to the address in `local`, or to `u32::MAX` if it lies
+/// outside `low..=high`
+fn poison(local: u32, low: i32, high: i32, checked: u32) -> [Instruction; 6] {
+    let int = |value| Operand::Constant(Constant::I32(value));
+    [
+        Instruction::BinaryOp { op: BinaryOp::Lt, left: Operand::Local(local), right: int(low) },
+        Instruction::BinaryOp { op: BinaryOp::Gt, left: Operand::Local(local), right: int(high) },
+        Instruction::BinaryOp { op: BinaryOp::Or, left: Operand::StackValue(0), right: Operand::StackValue(1) },
+        Instruction::BinaryOp { op: BinaryOp::Mul, left: Operand::StackValue(0), right: int(-1) },
+        Instruction::BinaryOp { op: BinaryOp::Or, left: Operand::StackValue(0), right: Operand::Local(local) },
+        Instruction::LocalSet { index: checked, value: Operand::StackValue(0) },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::{MemoryType, Signature, Terminator};
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    fn pointer(region: &str) -> Type {
+        Type::Capability {
+            inner_type: Box::new(Type::I32),
+            capability: Capability::MemoryRegion(region.to_string()),
+        }
+    }
+
+    /// `(p) -> *p + 1` after storing 1 to the constant address `store`
+    fn module(store: i32) -> WasmModule {
+        let mut function = WasmIR::new(
+            "run".to_string(),
+            Signature { params: vec![pointer(HEAP_REGION)], returns: Some(Type::I32) },
+        );
+        function.add_basic_block(
+            vec![
+                Instruction::MemoryStore {
+                    address: Operand::Constant(Constant::I32(store)),
+                    value: Operand::Constant(Constant::I32(1)),
+                    ty: Type::I32,
+                    align: None,
+                    offset: 0,
+                },
+                Instruction::MemoryLoad { address: Operand::Local(0), ty: Type::I32, align: None, offset: 4 },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        module.declare_memory_region(Region::new(RO_DATA_REGION, 0, 1024).read_only()).unwrap();
+        module.declare_memory_region(Region::new(HEAP_REGION, 1024, 1024)).unwrap();
+        module.add_function(function);
+        module
+    }
+
+    #[test]
+    fn test_declarations_are_checked() {
+        let mut module = module(1024);
+        let errors = [
+            (Region::new(HEAP_REGION, 4096, 16), RegionError::Duplicate(HEAP_REGION.to_string())),
+            (Region::new(STACK_REGION, 2040, 16), RegionError::Overlap {
+                region: STACK_REGION.to_string(),
+                other: HEAP_REGION.to_string(),
+            }),
+            (Region::new(STACK_REGION, 4096, 0), RegionError::Empty(STACK_REGION.to_string())),
+            (Region::new(STACK_REGION, i32::MAX as u32, 2), RegionError::OutOfRange(STACK_REGION.to_string())),
+        ];
+        for (region, error) in errors {
+            assert_eq!(module.declare_memory_region(region), Err(error));
+        }
+        assert_eq!(module.memory_regions.len(), 2);
+
+        module.declare_memory_region(Region::new(SHARED_REGION, 4096, 16).shared(true)).unwrap();
+        assert_eq!(tag_region_accesses(&module).unwrap_err(), RegionError::NotShared(SHARED_REGION.to_string()));
+    }
+
+    #[test]
+    fn test_tags_accesses_and_rejects_forbidden_ones() {
+        let tagged = tag_region_accesses(&module(1024)).unwrap();
+        let function = &tagged.functions[0];
+        assert!(matches!(function.basic_blocks[0].instructions.as_slice(), [
+            Instruction::CapabilityCheck { capability: Capability::MemoryRegion(a) },
+            Instruction::MemoryStore { .. },
+            Instruction::CapabilityCheck { capability: Capability::MemoryRegion(b) },
+            Instruction::MemoryLoad { .. },
+        ] if a == HEAP_REGION && b == HEAP_REGION));
+        assert_eq!(function.capabilities, vec![Capability::MemoryRegion(HEAP_REGION.to_string())]);
+        let again = tag_region_accesses(&tagged).unwrap();
+        assert_eq!(again.functions[0].basic_blocks[0].instructions.len(), 4);
+
+        assert_eq!(
+            tag_region_accesses(&module(16)).unwrap_err().to_string(),
+            "run stores to read-only memory region ro-data"
+        );
+        assert!(matches!(
+            tag_region_accesses(&module(2046)),
+            Err(RegionError::Straddles { address: 2046, .. })
+        ));
+    }
+
+    #[test]
+    fn test_checks_accesses_through_locals() {
+        let tagged = tag_region_accesses(&module(1024)).unwrap();
+        let (checked, count) = check_region_accesses(&tagged);
+        assert_eq!(count, 1);
+        let function = &checked.functions[0];
+        assert_eq!(function.locals, vec![pointer(HEAP_REGION), Type::I32]);
+        let instructions = &function.basic_blocks[0].instructions;
+        assert_eq!(instructions.len(), 9);
+        assert!(matches!(instructions[2], Instruction::BinaryOp {
+            op: BinaryOp::Lt, right: Operand::Constant(Constant::I32(1020)), ..
+        }));
+        assert!(matches!(instructions[3], Instruction::BinaryOp {
+            op: BinaryOp::Gt, right: Operand::Constant(Constant::I32(2040)), ..
+        }));
+        assert!(matches!(instructions[8], Instruction::MemoryLoad { address: Operand::Local(1), .. }));
+        assert_eq!(check_region_accesses(&checked).1, 0);
+    }
+}
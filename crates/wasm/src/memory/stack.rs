@@ -0,0 +1,352 @@
+//! Shadow stack for locals whose address is taken
+//!
+//! Wasm locals have no address, so functions keep such locals in a
+//! `StackFrame` in linear memory instead. `lower_stack_frames` reserves
+//! the stack's pages past the module's initial memory and defines
+//! `STACK_POINTER_GLOBAL`, which starts at their top and moves down by one
+//! frame per active call, and `STACK_LIMIT_GLOBAL` at their bottom. Every
+//! function with a frame gets a prologue that moves the stack pointer
+//! down, traps if it passes the limit, and
+//! copies spilled parameters into their slots, and an epilogue before
+//! each return that moves it back up.
+//!
+//! Rust aborts on stack overflow, so the check traps rather than
+//! panicking. When the module imports the panic hook, the hook first
+//! reports `STACK_OVERFLOW_MESSAGE`, which `WasmModule::panic_messages`
+//! lists first. `lower_stack_frames_unchecked` leaves the check out, for
+//! builds trading that safety for speed: a frame past the limit then
+//! overwrites whatever lies below the stack. A panic unwinding out of a frame leaves the stack pointer
+//! where that frame put it. The stack stays below 2 GiB so the signed
+//! comparisons of WasmIR order its addresses. Run this before
+//! `allocator::lower_allocations`, whose heap then starts past the stack.
+//!
+//! Both globals belong to an instance, so a thread running in its own
+//! instance points them at a stack of its own before calling any code.
+
+use core::fmt;
+
+use crate::wasmir::{
+    BinaryOp, BlockId, Constant, Instruction, MemoryType, Operand, StackFrame, Terminator, Type, WasmIR, WasmModule,
+    JS_IMPORT_MODULE, PANIC_IMPORT, STACK_ALIGN,
+};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Global holding the address of the innermost frame
+pub const STACK_POINTER_GLOBAL: &str = "__stack_pointer";
+
+/// Global holding the lowest address a frame may start at
+pub const STACK_LIMIT_GLOBAL: &str = "__stack_limit";
+
+/// Panic message the hook reports when a frame passes `STACK_LIMIT_GLOBAL`
+pub const STACK_OVERFLOW_MESSAGE: &str = "stack overflow";
+
+/// Bytes reserved for the shadow stack unless configured otherwise
+pub const DEFAULT_STACK_SIZE: u32 = 64 * 1024;
+
+/// Size of a linear memory page
+const PAGE_SIZE: u32 = 65536;
+
+/// Pages below 2 GiB; the stack must end before them
+const SIGNED_PAGES: u32 = 32768;
+
+/// Reasons a shadow stack cannot be added to a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackError {
+    /// The module already defines `STACK_POINTER_GLOBAL` or `STACK_LIMIT_GLOBAL`
+    ConflictingGlobal(&'static str),
+    /// The stack does not fit below 2 GiB and within the memory's maximum
+    NoStackSpace,
+    /// A function's frame is larger than the whole stack
+    FrameTooLarge { function: String, size: u32 },
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::ConflictingGlobal(name) => write!(f, "module already defines {}", name),
+            StackError::NoStackSpace => write!(f, "memory leaves no room for a shadow stack"),
+            StackError::FrameTooLarge { function, size } => {
+                write!(f, "stack frame of {} takes {} bytes, more than the whole stack", function, size)
+            }
+        }
+    }
+}
+
+/// Checks whether any function of the module has a stack frame
+pub fn has_stack_frames(module: &WasmModule) -> bool {
+    module.functions.iter().any(|function| function.frame.is_some())
+}
+
+/// Returns a copy of `module` with a shadow stack of at least `stack_size`
+/// bytes, and every function with a frame reserving it
+///
+/// The stack takes whole pages. A module without linear memory gets one.
+/// The returned functions no longer carry frames.
+pub fn lower_stack_frames(module: &WasmModule, stack_size: u32) -> Result<WasmModule, StackError> {
+    lower(module, stack_size, true)
+}
+
+/// Like `lower_stack_frames`, but prologues do not check the stack limit
+pub fn lower_stack_frames_unchecked(module: &WasmModule, stack_size: u32) -> Result<WasmModule, StackError> {
+    lower(module, stack_size, false)
+}
+
+fn lower(module: &WasmModule, stack_size: u32, checked: bool) -> Result<WasmModule, StackError> {
+    for name in [STACK_POINTER_GLOBAL, STACK_LIMIT_GLOBAL] {
+        if module.global_index(name).is_some() {
+            return Err(StackError::ConflictingGlobal(name));
+        }
+    }
+    let memory = module.memory.unwrap_or(MemoryType { min_pages: 0, max_pages: None });
+    let stack_pages = stack_size.div_ceil(PAGE_SIZE).max(1);
+    let top_pages = memory.min_pages.saturating_add(stack_pages);
+    if top_pages >= SIGNED_PAGES || memory.max_pages.is_some_and(|max| top_pages > max) {
+        return Err(StackError::NoStackSpace);
+    }
+    for function in &module.functions {
+        let size = function.frame.as_ref().map_or(0, frame_size);
+        if size > stack_pages * PAGE_SIZE {
+            return Err(StackError::FrameTooLarge { function: function.name.clone(), size });
+        }
+    }
+
+    let mut lowered = module.clone();
+    lowered.set_memory(MemoryType { min_pages: top_pages, ..memory });
+    let top = Constant::I32((top_pages * PAGE_SIZE) as i32);
+    let stack_pointer = lowered.add_global(STACK_POINTER_GLOBAL, Type::I32, true, top);
+    let bottom = Constant::I32((memory.min_pages * PAGE_SIZE) as i32);
+    let limit = lowered.add_global(STACK_LIMIT_GLOBAL, Type::I32, true, bottom);
+    let check = checked.then(|| {
+        let hook = lowered.find_import(JS_IMPORT_MODULE, PANIC_IMPORT).map(|hook| {
+            let id = lowered.panic_messages().iter().position(|&message| message == STACK_OVERFLOW_MESSAGE);
+            (hook, id.unwrap_or(0) as i32)
+        });
+        StackCheck { limit, hook }
+    });
+    for function in &mut lowered.functions {
+        if let Some(frame) = function.frame.take() {
+            add_prologue(function, &frame, stack_pointer, check);
+            add_epilogues(function, &frame, stack_pointer);
+        }
+    }
+    Ok(lowered)
+}
+
+/// Bytes a frame moves the stack pointer by
+fn frame_size(frame: &StackFrame) -> u32 {
+    frame.size.next_multiple_of(STACK_ALIGN)
+}
+
+/// How prologues check a new frame against the stack limit
+#[derive(Debug, Clone, Copy)]
+struct StackCheck {
+    /// Index of `STACK_LIMIT_GLOBAL`
+    limit: u32,
+    /// Panic hook import and the id of `STACK_OVERFLOW_MESSAGE`, if imported
+    hook: Option<(u32, i32)>,
+}
+
+/// Makes the prologue the entry block, moving the old entry to the end
+fn add_prologue(function: &mut WasmIR, frame: &StackFrame, stack_pointer: u32, check: Option<StackCheck>) {
+    let body = BlockId(function.basic_blocks.len());
+    for block in &mut function.basic_blocks {
+        retarget(&mut block.terminator, BlockId(0), body);
+    }
+    let (overflow, enter) = match check {
+        Some(_) => (BlockId(body.0 + 1), BlockId(body.0 + 2)),
+        None => (body, BlockId(body.0 + 1)),
+    };
+
+    let mut prologue = alloc::vec![
+        Instruction::BinaryOp {
+            op: BinaryOp::Sub,
+            left: Operand::Global(stack_pointer),
+            right: Operand::Constant(Constant::I32(frame_size(frame) as i32)),
+        },
+        Instruction::LocalSet { index: frame.base, value: Operand::StackValue(0) },
+    ];
+    let terminator = match check {
+        Some(check) => {
+            prologue.push(Instruction::BinaryOp {
+                op: BinaryOp::Lt,
+                left: Operand::Local(frame.base),
+                right: Operand::Global(check.limit),
+            });
+            Terminator::Branch { condition: Operand::StackValue(0), then_block: overflow, else_block: enter }
+        }
+        None => Terminator::Jump { target: enter },
+    };
+    let entry = core::mem::replace(&mut function.basic_blocks[0].instructions, prologue);
+    let entry_terminator = core::mem::replace(&mut function.basic_blocks[0].terminator, terminator);
+    function.add_basic_block(entry, entry_terminator);
+    if let Some(check) = check {
+        let report = check.hook.map(|(hook, id)| Instruction::CallImport {
+            import: hook,
+            args: alloc::vec![Operand::Constant(Constant::I32(id))],
+        });
+        function.add_basic_block(report.into_iter().collect(), Terminator::Unreachable);
+    }
+
+    let mut spills = alloc::vec![Instruction::GlobalSet { index: stack_pointer, value: Operand::Local(frame.base) }];
+    for &(local, offset) in &frame.spills {
+        let ty = match function.operand_type(&Operand::Local(local)) {
+            Some(Type::Pointer(_)) | None => Type::I32,
+            Some(ty) => ty,
+        };
+        spills.push(Instruction::MemoryStore {
+            address: Operand::Local(frame.base),
+            value: Operand::Local(local),
+            ty,
+            align: None,
+            offset,
+        });
+    }
+    function.add_basic_block(spills, Terminator::Jump { target: body });
+}
+
+/// Releases the frame before every return
+fn add_epilogues(function: &mut WasmIR, frame: &StackFrame, stack_pointer: u32) {
+    for block in &mut function.basic_blocks {
+        if matches!(block.terminator, Terminator::Return { .. }) {
+            block.instructions.push(Instruction::BinaryOp {
+                op: BinaryOp::Add,
+                left: Operand::Local(frame.base),
+                right: Operand::Constant(Constant::I32(frame_size(frame) as i32)),
+            });
+            block.instructions.push(Instruction::GlobalSet { index: stack_pointer, value: Operand::StackValue(0) });
+        }
+    }
+}
+
+fn retarget(terminator: &mut Terminator, from: BlockId, to: BlockId) {
+    let targets: Vec<&mut BlockId> = match terminator {
+        Terminator::Jump { target } => alloc::vec![target],
+        Terminator::Branch { then_block, else_block, .. } => alloc::vec![then_block, else_block],
+        Terminator::Switch { targets, default_target, .. } => {
+            targets.iter_mut().map(|(_, target)| target).chain(core::iter::once(default_target)).collect()
+        }
+        Terminator::Return { .. } | Terminator::Unreachable | Terminator::Panic { .. } => Vec::new(),
+    };
+    for target in targets {
+        if *target == from {
+            *target = to;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::Signature;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    /// `(x) -> x`, spilling `x` and loading it back after a branch to the
+    /// entry block
+    fn addressed_module() -> WasmModule {
+        let mut function = WasmIR::new("addressed".to_string(), Signature { params: vec![Type::I32], returns: Some(Type::I32) });
+        function.locals = vec![Type::I32];
+        let slot = function.add_stack_slot(4, 4);
+        let frame = function.frame.as_mut().unwrap();
+        frame.spills.push((0, slot));
+        let base = frame.base;
+        function.add_basic_block(
+            vec![],
+            Terminator::Branch { condition: Operand::Constant(Constant::I32(0)), then_block: BlockId(0), else_block: BlockId(1) },
+        );
+        function.add_basic_block(
+            vec![Instruction::MemoryLoad { address: Operand::Local(base), ty: Type::I32, align: None, offset: slot }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("addressed", index);
+        module
+    }
+
+    #[test]
+    fn test_slots_are_aligned_within_the_frame() {
+        let mut function = WasmIR::new("slots".to_string(), Signature { params: vec![], returns: None });
+        assert_eq!(function.add_stack_slot(1, 1), 0);
+        assert_eq!(function.add_stack_slot(8, 8), 8);
+        assert_eq!(function.add_stack_slot(4, 64), 16);
+        assert_eq!(function.frame, Some(StackFrame { base: 0, size: 20, spills: vec![] }));
+        assert_eq!(function.locals, vec![Type::I32]);
+        assert_eq!(frame_size(function.frame.as_ref().unwrap()), 32);
+    }
+
+    #[test]
+    fn test_reserves_the_stack_and_frames() {
+        let module = addressed_module();
+        assert!(has_stack_frames(&module));
+        let lowered = lower_stack_frames(&module, DEFAULT_STACK_SIZE).unwrap();
+        assert!(lowered.validate().is_ok());
+        assert_eq!(lowered.memory, Some(MemoryType { min_pages: 1, max_pages: None }));
+        assert_eq!(lowered.global_index(STACK_POINTER_GLOBAL), Some(0));
+        assert_eq!(lowered.globals[0].init, Constant::I32(65536));
+        assert_eq!(lowered.global_index(STACK_LIMIT_GLOBAL), Some(1));
+        assert_eq!(lowered.globals[1].init, Constant::I32(0));
+        assert!(!has_stack_frames(&lowered));
+
+        let blocks = &lowered.functions[0].basic_blocks;
+        assert_eq!(blocks.len(), 5);
+        assert!(matches!(blocks[0].terminator, Terminator::Branch { then_block: BlockId(3), else_block: BlockId(4), .. }));
+        assert!(matches!(blocks[2].terminator, Terminator::Branch { then_block: BlockId(2), else_block: BlockId(1), .. }));
+        assert!(matches!(blocks[3].terminator, Terminator::Unreachable));
+        assert!(matches!(blocks[4].instructions.as_slice(), [
+            Instruction::GlobalSet { index: 0, value: Operand::Local(1) },
+            Instruction::MemoryStore { value: Operand::Local(0), offset: 0, .. },
+        ]));
+        assert!(matches!(blocks[1].instructions.last(), Some(Instruction::GlobalSet { index: 0, .. })));
+    }
+
+    #[test]
+    fn test_overflow_reports_through_the_panic_hook() {
+        let mut module = addressed_module();
+        let hook = module.declare_panic_hook();
+        module.functions[0].basic_blocks[1].terminator =
+            Terminator::Panic { message: Some(Operand::Constant(Constant::String("boom".to_string()))) };
+        assert_eq!(module.panic_messages(), [STACK_OVERFLOW_MESSAGE, "boom"]);
+
+        let lowered = lower_stack_frames(&module, DEFAULT_STACK_SIZE).unwrap();
+        assert!(lowered.validate().is_ok());
+        assert_eq!(lowered.panic_messages(), module.panic_messages());
+        let overflow = &lowered.functions[0].basic_blocks[3];
+        assert!(matches!(overflow.terminator, Terminator::Unreachable));
+        assert!(matches!(overflow.instructions.as_slice(), [
+            Instruction::CallImport { import, args },
+        ] if *import == hook && matches!(args.as_slice(), [Operand::Constant(Constant::I32(0))])));
+
+        let unchecked = lower_stack_frames_unchecked(&module, DEFAULT_STACK_SIZE).unwrap();
+        assert!(unchecked.validate().is_ok());
+        let blocks = &unchecked.functions[0].basic_blocks;
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].instructions.len(), 2);
+        assert!(matches!(blocks[0].terminator, Terminator::Jump { target: BlockId(3) }));
+        assert!(matches!(blocks[3].terminator, Terminator::Jump { target: BlockId(2) }));
+        assert_eq!(unchecked.global_index(STACK_LIMIT_GLOBAL), Some(1));
+    }
+
+    #[test]
+    fn test_rejects_stacks_that_do_not_fit() {
+        let mut module = addressed_module();
+        module.set_memory(MemoryType { min_pages: 2, max_pages: Some(2) });
+        assert_eq!(lower_stack_frames(&module, 1).err(), Some(StackError::NoStackSpace));
+
+        let mut module = addressed_module();
+        module.functions[0].add_stack_slot(PAGE_SIZE, 8);
+        assert_eq!(
+            lower_stack_frames(&module, 1).unwrap_err().to_string(),
+            "stack frame of addressed takes 65552 bytes, more than the whole stack"
+        );
+
+        let mut module = addressed_module();
+        module.add_global(STACK_POINTER_GLOBAL, Type::I32, true, Constant::I32(0));
+        assert_eq!(lower_stack_frames(&module, 1).err(), Some(StackError::ConflictingGlobal(STACK_POINTER_GLOBAL)));
+
+        let mut module = addressed_module();
+        module.add_global(STACK_LIMIT_GLOBAL, Type::I32, true, Constant::I32(0));
+        assert_eq!(lower_stack_frames(&module, 1).err(), Some(StackError::ConflictingGlobal(STACK_LIMIT_GLOBAL)));
+    }
+}
@@ -0,0 +1,134 @@
+//! Panic reporting without `core::fmt`
+//!
+//! Formatting a panic message with `core::fmt` pulls several kilobytes of
+//! code into a module. A `#[panic_handler]` can call `report` instead: it
+//! writes the location into a `MessageBuffer` by hand, passes the text to the host's `__wasmrust_panic_message` import,
+//! and traps. The `panic-message` feature formats the full message with
+//! `core::fmt`, for modules where the text matters more than the size.
+
+use core::panic::PanicInfo;
+
+/// Capacity of a `MessageBuffer`, in bytes
+pub const MESSAGE_CAPACITY: usize = 256;
+
+/// Panic message in a fixed buffer; text past the capacity is dropped
+pub struct MessageBuffer {
+    bytes: [u8; MESSAGE_CAPACITY],
+    len: usize,
+}
+
+impl Default for MessageBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageBuffer {
+    pub const fn new() -> Self {
+        Self { bytes: [0; MESSAGE_CAPACITY], len: 0 }
+    }
+
+    /// Appends as much of `text` as fits, keeping whole characters
+    pub fn push_str(&mut self, text: &str) {
+        let mut end = text.len().min(MESSAGE_CAPACITY - self.len);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.bytes[self.len..self.len + end].copy_from_slice(&text.as_bytes()[..end]);
+        self.len += end;
+    }
+
+    /// Appends `value` in decimal
+    pub fn push_u32(&mut self, mut value: u32) {
+        let mut digits = [0u8; 10];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        // Digits are ASCII
+        self.push_str(core::str::from_utf8(&digits[start..]).unwrap_or_default());
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are ever appended
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "panic-message")]
+impl core::fmt::Write for MessageBuffer {
+    fn write_str(&mut self, text: &str) -> core::fmt::Result {
+        self.push_str(text);
+        Ok(())
+    }
+}
+
+/// Formats a panic as `panicked at file:line:column`, followed by the
+/// message with the `panic-message` feature
+pub fn format(info: &PanicInfo<'_>) -> MessageBuffer {
+    let mut message = MessageBuffer::new();
+    #[cfg(feature = "panic-message")]
+    {
+        let _ = core::fmt::Write::write_fmt(&mut message, format_args!("{}", info));
+    }
+    #[cfg(not(feature = "panic-message"))]
+    {
+        message.push_str("panicked");
+        if let Some(location) = info.location() {
+            message.push_str(" at ");
+            message.push_str(location.file());
+            message.push_str(":");
+            message.push_u32(location.line());
+            message.push_str(":");
+            message.push_u32(location.column());
+        }
+    }
+    message
+}
+
+/// Reports a panic to the host, then traps
+#[cfg(target_arch = "wasm32")]
+pub fn report(info: &PanicInfo<'_>) -> ! {
+    let message = format(info);
+    let text = message.as_str();
+    unsafe { __wasmrust_panic_message(text.as_ptr(), text.len() as u32) };
+    core::arch::wasm32::unreachable()
+}
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "wasmrust")]
+extern "C" {
+    fn __wasmrust_panic_message(ptr: *const u8, len: u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_numbers_without_fmt() {
+        let mut message = MessageBuffer::new();
+        message.push_str("src/lib.rs:");
+        message.push_u32(0);
+        message.push_str(":");
+        message.push_u32(u32::MAX);
+        assert_eq!(message.as_str(), "src/lib.rs:0:4294967295");
+    }
+
+    #[test]
+    fn test_truncates_at_character_boundaries() {
+        let mut message = MessageBuffer::new();
+        message.push_str(&"a".repeat(MESSAGE_CAPACITY - 1));
+        message.push_str("é");
+        assert_eq!(message.as_str().len(), MESSAGE_CAPACITY - 1);
+        message.push_str("b");
+        assert!(message.as_str().ends_with('b'));
+        message.push_str("c");
+        assert_eq!(message.as_str().len(), MESSAGE_CAPACITY);
+    }
+}
@@ -9,11 +9,21 @@ use alloc::string::{String, ToString};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use alloc::format;
-use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
 use core::ptr::NonNull;
 use core::marker::PhantomData;
 use core::cell::UnsafeCell;
 
+pub mod async_rt;
+pub mod channel;
+pub mod executor;
+pub mod runtime;
+pub mod sync;
+pub mod tls;
+
+pub use sync::{Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
 /// Threading capability detection and initialization
 static THREADING_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
@@ -303,6 +313,159 @@ impl core::fmt::Display for ThreadingError {
     }
 }
 
+/// Scheduling hint for a spawned thread
+///
+/// Workers have no OS priority, so the hint orders threads waiting for a
+/// free worker slot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThreadPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Lifecycle state of a spawned thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Waiting for a worker slot under the max-worker cap
+    Queued,
+    /// Running on a worker
+    Running,
+}
+
+/// Debugging information about a live thread
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub id: u32,
+    pub name: Option<String>,
+    pub priority: ThreadPriority,
+    pub state: ThreadState,
+}
+
+impl ThreadInfo {
+    /// Name given to the backing Worker, shown in browser devtools
+    pub fn worker_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({})", name, self.id),
+            None => format!("wasm-thread-{}", self.id),
+        }
+    }
+}
+
+/// Tracks live threads and enforces the max-worker cap
+#[derive(Debug)]
+pub struct ThreadRegistry {
+    threads: Vec<ThreadInfo>,
+    max_workers: Option<usize>,
+}
+
+impl ThreadRegistry {
+    /// Creates an empty registry with no worker cap
+    pub const fn new() -> Self {
+        Self {
+            threads: Vec::new(),
+            max_workers: None,
+        }
+    }
+
+    /// Registers a spawned thread, queueing it if the cap is reached
+    pub fn register(&mut self, id: u32, name: Option<String>, priority: ThreadPriority) -> ThreadState {
+        let state = if self.has_free_slot() { ThreadState::Running } else { ThreadState::Queued };
+        self.threads.push(ThreadInfo { id, name, priority, state });
+        state
+    }
+
+    /// Removes a finished thread and returns the threads promoted to running
+    pub fn finish(&mut self, id: u32) -> Vec<u32> {
+        self.threads.retain(|thread| thread.id != id);
+        self.promote()
+    }
+
+    /// Sets the worker cap and returns the threads promoted to running
+    ///
+    /// Lowering the cap never stops running threads; it only delays
+    /// queued ones.
+    pub fn set_max_workers(&mut self, max_workers: Option<usize>) -> Vec<u32> {
+        self.max_workers = max_workers;
+        self.promote()
+    }
+
+    /// Gets the worker cap
+    pub fn max_workers(&self) -> Option<usize> {
+        self.max_workers
+    }
+
+    /// Gets information about a live thread
+    pub fn get(&self, id: u32) -> Option<&ThreadInfo> {
+        self.threads.iter().find(|thread| thread.id == id)
+    }
+
+    /// Lists live threads in spawn order
+    pub fn threads(&self) -> &[ThreadInfo] {
+        &self.threads
+    }
+
+    /// Gets the number of threads running on workers
+    pub fn running_count(&self) -> usize {
+        self.threads.iter().filter(|thread| thread.state == ThreadState::Running).count()
+    }
+
+    /// Gets the number of threads waiting for a worker
+    pub fn queued_count(&self) -> usize {
+        self.threads.len() - self.running_count()
+    }
+
+    fn has_free_slot(&self) -> bool {
+        match self.max_workers {
+            Some(max) => self.running_count() < max,
+            None => true,
+        }
+    }
+
+    /// Starts queued threads by priority, then spawn order
+    fn promote(&mut self) -> Vec<u32> {
+        let mut promoted = Vec::new();
+        while self.has_free_slot() {
+            let next = self.threads.iter_mut()
+                .filter(|thread| thread.state == ThreadState::Queued)
+                .fold(None::<&mut ThreadInfo>, |best, thread| match best {
+                    Some(best) if best.priority >= thread.priority => Some(best),
+                    _ => Some(thread),
+                });
+            match next {
+                Some(thread) => {
+                    thread.state = ThreadState::Running;
+                    promoted.push(thread.id);
+                }
+                None => break,
+            }
+        }
+        promoted
+    }
+}
+
+impl Default for ThreadRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static THREAD_REGISTRY: Mutex<ThreadRegistry> = Mutex::new(ThreadRegistry::new());
+
+/// Sets the maximum number of concurrently running workers
+///
+/// Threads spawned beyond the cap are queued until a worker finishes.
+/// `None` removes the cap.
+pub fn set_max_workers(max_workers: Option<usize>) {
+    THREAD_REGISTRY.lock().set_max_workers(max_workers);
+}
+
+/// Lists live threads and their states for debugging
+pub fn live_threads() -> Vec<ThreadInfo> {
+    THREAD_REGISTRY.lock().threads().to_vec()
+}
+
 /// Thread handle for managing WASM threads
 pub struct ThreadHandle {
     thread_id: u32,
@@ -341,13 +504,24 @@ impl ThreadHandle {
         // In a real implementation, this would wait for the thread
         // to complete and clean up resources
         self.join_handle = Some(NonNull::dangling());
+        THREAD_REGISTRY.lock().finish(self.thread_id);
         Ok(())
     }
 
+    /// Gets the thread name
+    pub fn name(&self) -> Option<String> {
+        THREAD_REGISTRY.lock().get(self.thread_id).and_then(|thread| thread.name.clone())
+    }
+
+    /// Gets the thread state, or `None` once it has finished
+    pub fn state(&self) -> Option<ThreadState> {
+        THREAD_REGISTRY.lock().get(self.thread_id).map(|thread| thread.state)
+    }
+
     /// Checks if the thread has finished
     pub fn is_finished(&self) -> bool {
         // In a real implementation, this would check thread status
-        false
+        self.join_handle.is_some()
     }
 
     /// Attempts to cancel the thread
@@ -369,6 +543,7 @@ impl ThreadHandle {
 pub struct ThreadBuilder {
     name: Option<String>,
     stack_size: Option<usize>,
+    priority: ThreadPriority,
 }
 
 impl ThreadBuilder {
@@ -377,10 +552,13 @@ impl ThreadBuilder {
         Self {
             name: None,
             stack_size: None,
+            priority: ThreadPriority::Normal,
         }
     }
 
     /// Sets the thread name
+    ///
+    /// The name is propagated to the backing Worker for devtools.
     pub fn name(mut self, name: impl Into<String>) -> Self {
         self.name = Some(name.into());
         self
@@ -392,6 +570,12 @@ impl ThreadBuilder {
         self
     }
 
+    /// Sets the scheduling priority hint
+    pub fn priority(mut self, priority: ThreadPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
     /// Spawns a new thread with the given function
     /// 
     /// Returns error if threading is not supported or thread creation fails
@@ -410,6 +594,9 @@ impl ThreadBuilder {
         // For now, we'll simulate thread creation
         let thread_id = self.generate_thread_id();
         
+        // Queued threads start once a worker slot frees up
+        THREAD_REGISTRY.lock().register(thread_id, self.name, self.priority);
+
         // Store the function and execute it when the thread starts
         // This is a simplified implementation
         let _function = Box::new(f);
@@ -420,7 +607,6 @@ impl ThreadBuilder {
     /// Generates a unique thread ID
     fn generate_thread_id(&self) -> u32 {
         // In a real implementation, this would generate a unique ID
-        use core::sync::atomic::{AtomicU32, Ordering};
         static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
         
         NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed)
@@ -433,6 +619,180 @@ impl Default for ThreadBuilder {
     }
 }
 
+/// Blocks while `value` equals `current`
+///
+/// Uses `memory.atomic.wait32` when shared-memory atomics are enabled.
+/// Browsers forbid waiting on their main thread, so callers there must
+/// only wait on values another agent is about to change. Without atomics
+/// every thread runs to completion when spawned, so nothing can change the
+/// value and waiting fails with `DeadlockDetected` instead of hanging.
+pub(crate) fn atomic_wait(value: &AtomicU32, current: u32) -> Result<(), ThreadingError> {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    while value.load(Ordering::Acquire) == current {
+        unsafe {
+            core::arch::wasm32::memory_atomic_wait32(value.as_ptr() as *mut i32, current as i32, -1);
+        }
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+    if value.load(Ordering::Acquire) == current {
+        return Err(ThreadingError::DeadlockDetected);
+    }
+    Ok(())
+}
+
+/// Wakes every agent blocked in `atomic_wait` on `value`
+pub(crate) fn atomic_notify_all(value: &AtomicU32) {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    unsafe {
+        core::arch::wasm32::memory_atomic_notify(value.as_ptr() as *mut i32, u32::MAX);
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+    let _ = value;
+}
+
+/// Creates a scope for spawning threads that borrow local data
+///
+/// Mirrors `std::thread::scope`: every thread spawned through the scope is
+/// joined before `scope` returns, so closures may borrow anything that
+/// outlives the call. GC roots held in a `ShadowStack` can be shared with
+/// scoped threads through `&ShadowStack`; compaction needs
+/// `&mut ShadowStack`, so it cannot run until the scope has ended.
+///
+/// With shared-memory atomics each scoped thread runs on a thread of its
+/// own from `runtime`. Without them, scoped threads run one after another
+/// on the calling thread as they are spawned, so a thread must not wait on
+/// a sibling spawned after it; such waits fail with `DeadlockDetected`.
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope {
+        threads: Mutex::new(Vec::new()),
+        _scope: PhantomData,
+        _env: PhantomData,
+    };
+    let result = f(&scope);
+
+    // Join-before-return: wait for every thread still running. Threads end
+    // before these waits could fail, since without atomics they already have.
+    for done in scope.threads.lock().iter() {
+        let _ = atomic_wait(done, 0);
+    }
+    result
+}
+
+/// Scope for spawning threads, created by `scope`
+pub struct Scope<'scope, 'env: 'scope> {
+    /// Completion flags of the threads spawned so far
+    threads: Mutex<Vec<Arc<AtomicU32>>>,
+    _scope: PhantomData<&'scope mut &'scope ()>,
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawns a thread that may borrow data living outside the scope
+    ///
+    /// Without shared-memory atomics, or if the host refuses to start a
+    /// thread, the closure runs to completion on the calling thread before
+    /// `spawn` returns.
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let packet = Packet::new();
+        self.threads.lock().push(packet.done.clone());
+
+        let task_packet = packet.clone();
+        run_task(&packet.done, Box::new(move || task_packet.complete(f)));
+
+        ScopedJoinHandle { packet, _scope: PhantomData }
+    }
+
+    /// Gets the number of scoped threads that have not finished
+    pub fn running_threads(&self) -> u32 {
+        self.threads.lock().iter().filter(|done| done.load(Ordering::Acquire) == 0).count() as u32
+    }
+}
+
+/// Starts a scoped task on a thread of its own
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+fn run_task<'a>(done: &AtomicU32, task: Box<dyn FnOnce() + Send + 'a>) {
+    // SAFETY: `scope` waits for `done` before anything the task borrows
+    // goes out of scope
+    let main = unsafe { core::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Box<dyn FnOnce() + Send>>(task) };
+    let record = runtime::StartRecord::new(crate::memory::stack::DEFAULT_STACK_SIZE, done, main);
+    if let Err(record) = runtime::start(record) {
+        record.run_inline();
+    }
+}
+
+/// Runs a scoped task to completion on the spawning thread
+#[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+fn run_task<'a>(_done: &AtomicU32, task: Box<dyn FnOnce() + Send + 'a>) {
+    task();
+}
+
+/// Result slot shared between a thread and its handle
+///
+/// `done` becomes nonzero once the thread ends. A thread that panicked or
+/// trapped ends without a result, which its handle reports as
+/// `ThreadJoinFailed`.
+struct Packet<T> {
+    result: Mutex<Option<T>>,
+    done: Arc<AtomicU32>,
+}
+
+impl<T> Packet<T> {
+    fn new() -> Arc<Self> {
+        Arc::new(Packet {
+            result: Mutex::new(None),
+            done: Arc::new(AtomicU32::new(0)),
+        })
+    }
+
+    /// Runs `f` and stores its result, marking the packet done even if
+    /// `f` unwinds
+    fn complete(&self, f: impl FnOnce() -> T) {
+        struct Done<'a>(&'a AtomicU32);
+
+        impl Drop for Done<'_> {
+            fn drop(&mut self) {
+                self.0.store(1, Ordering::Release);
+                atomic_notify_all(self.0);
+            }
+        }
+
+        let _done = Done(&self.done);
+        let value = f();
+        *self.result.lock() = Some(value);
+    }
+
+    /// Waits for the thread to end and takes its result
+    fn join(&self) -> Result<T, ThreadingError> {
+        atomic_wait(&self.done, 0)?;
+        self.result.lock().take().ok_or(ThreadingError::ThreadJoinFailed)
+    }
+}
+
+/// Owned permission to join a scoped thread
+pub struct ScopedJoinHandle<'scope, T> {
+    packet: Arc<Packet<T>>,
+    _scope: PhantomData<&'scope ()>,
+}
+
+impl<'scope, T> ScopedJoinHandle<'scope, T> {
+    /// Waits for the thread to finish and returns its result
+    pub fn join(self) -> Result<T, ThreadingError> {
+        self.packet.join()
+    }
+
+    /// Checks if the thread has finished
+    pub fn is_finished(&self) -> bool {
+        self.packet.done.load(Ordering::Acquire) != 0
+    }
+}
+
 /// Initialize threading support
 pub fn initialize_threading_support() -> Result<(), ThreadingError> {
     if THREADING_INITIALIZED.load(Ordering::Acquire) {
@@ -458,14 +818,13 @@ pub fn initialize_threading_support() -> Result<(), ThreadingError> {
 
 /// Gets the current thread ID
 pub fn current_thread_id() -> u32 {
-    // In a real implementation, this would return the actual thread ID
-    0 // Main thread
+    runtime::current_thread_id()
 }
 
 /// Gets the number of active threads
 pub fn active_thread_count() -> u32 {
-    // In a real implementation, this would track active threads
-    1 // Main thread only
+    // Main thread plus threads running on workers
+    1 + THREAD_REGISTRY.lock().running_count() as u32
 }
 
 /// Checks if the current environment supports threading
@@ -532,6 +891,20 @@ impl<'a, T> ThreadGuard<'a, T> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_mutex_lock_and_try_lock() {
+        let mutex = Mutex::new(Vec::new());
+        {
+            let mut guard = mutex.lock();
+            guard.push(1);
+            assert!(mutex.is_locked());
+            assert!(mutex.try_lock().is_none());
+        }
+        assert!(!mutex.is_locked());
+        mutex.try_lock().unwrap().push(2);
+        assert_eq!(mutex.into_inner(), alloc::vec![1, 2]);
+    }
+
     #[test]
     fn test_atomic_ref_count() {
         let ref_count = AtomicRefCount::new();
@@ -601,6 +974,105 @@ mod tests {
         assert_eq!(empty_value, None);
     }
 
+    #[test]
+    fn test_registry_queues_beyond_worker_cap() {
+        let mut registry = ThreadRegistry::new();
+        registry.set_max_workers(Some(1));
+
+        assert_eq!(registry.register(1, Some("io".to_string()), ThreadPriority::Normal), ThreadState::Running);
+        assert_eq!(registry.register(2, None, ThreadPriority::Low), ThreadState::Queued);
+        assert_eq!(registry.register(3, None, ThreadPriority::High), ThreadState::Queued);
+        assert_eq!(registry.running_count(), 1);
+        assert_eq!(registry.queued_count(), 2);
+
+        // The high priority thread overtakes the earlier low priority one
+        assert_eq!(registry.finish(1), alloc::vec![3]);
+        assert_eq!(registry.set_max_workers(None), alloc::vec![2]);
+        assert_eq!(registry.queued_count(), 0);
+    }
+
+    #[test]
+    fn test_thread_info_worker_name() {
+        let mut registry = ThreadRegistry::new();
+        registry.register(7, Some("decoder".to_string()), ThreadPriority::Normal);
+        registry.register(8, None, ThreadPriority::Normal);
+
+        assert_eq!(registry.get(7).unwrap().worker_name(), "decoder (7)");
+        assert_eq!(registry.get(8).unwrap().worker_name(), "wasm-thread-8");
+        assert_eq!(registry.threads().len(), 2);
+    }
+
+    #[test]
+    fn test_scope_borrows_local_data() {
+        let mut data = [1, 2, 3, 4];
+        let total = AtomicU32::new(0);
+
+        let sum = scope(|s| {
+            let (left, right) = data.split_at_mut(2);
+            let a = s.spawn(|| {
+                left.iter_mut().for_each(|x| *x *= 10);
+                total.fetch_add(left.iter().sum::<u32>(), Ordering::Relaxed);
+            });
+            let b = s.spawn(|| right.iter().sum::<u32>());
+            a.join().unwrap();
+            b.join().unwrap()
+        });
+
+        assert_eq!(sum, 7);
+        assert_eq!(total.load(Ordering::Relaxed), 30);
+        assert_eq!(data, [10, 20, 3, 4]);
+    }
+
+    #[test]
+    fn test_scope_joins_before_return() {
+        let finished = AtomicU32::new(0);
+        scope(|s| {
+            for _ in 0..3 {
+                // Handles are dropped without joining
+                s.spawn(|| finished.fetch_add(1, Ordering::Relaxed));
+            }
+        });
+        assert_eq!(finished.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_waits_no_thread_can_end_fail() {
+        assert_eq!(atomic_wait(&AtomicU32::new(1), 0), Ok(()));
+        assert_eq!(atomic_wait(&AtomicU32::new(0), 0), Err(ThreadingError::DeadlockDetected));
+
+        scope(|s| {
+            let first = s.spawn(|| 1);
+            assert!(first.is_finished());
+            assert_eq!(s.running_threads(), 0);
+            assert_eq!(first.join(), Ok(1));
+        });
+    }
+
+    #[test]
+    fn test_scope_shares_gc_roots() {
+        use crate::memory::{GcHeap, GcTypeLayout, ShadowStack};
+
+        let mut heap = GcHeap::new(16, 256).unwrap();
+        let node = heap.register_type(GcTypeLayout::new(8)).unwrap();
+        let mut stack = ShadowStack::new();
+        stack.push_frame();
+        let garbage = heap.alloc(node).unwrap();
+        let live = heap.alloc(node).unwrap();
+        heap.free(garbage).unwrap();
+        let slot = stack.push_root(live);
+
+        // Scoped threads read roots through a shared borrow
+        let seen = scope(|s| {
+            let roots = &stack;
+            s.spawn(move || roots.roots().to_vec()).join().unwrap()
+        });
+        assert_eq!(seen, alloc::vec![live]);
+
+        // Compaction needs the roots exclusively, so it follows the scope
+        heap.compact(&mut stack);
+        assert_eq!(stack.get(slot), garbage);
+    }
+
     #[test]
     fn test_thread_builder() {
         let builder = ThreadBuilder::new()
@@ -0,0 +1,313 @@
+//! Async runtime for the JS main thread
+//!
+//! `spawn_local` runs `async` code on a global `LocalExecutor` owned by the
+//! main thread, without tokio or another runtime. The executor never
+//! blocks: a wake on the main thread calls `SCHEDULE_RUN_IMPORT`, which
+//! the glue implements with `queueMicrotask`, and the microtask enters
+//! `RUN_TASKS_EXPORT` to poll what was woken. Wakes from workers cannot
+//! call into the main thread's JS, so they only bump the executor's wake
+//! signal; the glue of a threaded module watches its address, from
+//! `WAKE_SIGNAL_EXPORT`, with `Atomics.waitAsync` and schedules the run.
+//!
+//! `sleep` bridges to `setTimeout` through `SET_TIMEOUT_IMPORT`; the glue
+//! reports expiry to `TIMER_FIRED_EXPORT`, which wakes the sleeping task.
+//!
+//! Off wasm nothing calls back, so hosts and tests drive the runtime with
+//! `run_tasks` and `timer_fired`.
+
+use super::executor::LocalExecutor;
+use super::Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::{Context, Poll, Waker};
+
+/// Import asking the host to call `RUN_TASKS_EXPORT` soon: `()`
+pub const SCHEDULE_RUN_IMPORT: &str = "__wasmrust_schedule_run";
+
+/// Import starting a host timer: `(id, millis)`
+pub const SET_TIMEOUT_IMPORT: &str = "__wasmrust_set_timeout";
+
+/// Import cancelling a host timer that has not fired: `(id)`
+pub const CLEAR_TIMEOUT_IMPORT: &str = "__wasmrust_clear_timeout";
+
+/// Export polling woken tasks on the main thread: `()`
+pub const RUN_TASKS_EXPORT: &str = "__wasmrust_run_tasks";
+
+/// Export the host calls when a timer fires: `(id)`
+pub const TIMER_FIRED_EXPORT: &str = "__wasmrust_timer_fired";
+
+/// Export giving the address of the executor's wake signal: `() -> address`
+pub const WAKE_SIGNAL_EXPORT: &str = "__wasmrust_wake_signal";
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "wasmrust")]
+extern "C" {
+    fn __wasmrust_schedule_run();
+    fn __wasmrust_set_timeout(id: u32, millis: u32);
+    fn __wasmrust_clear_timeout(id: u32);
+}
+
+type LocalTask = Pin<Box<dyn Future<Output = ()>>>;
+
+/// State only touched on the main thread
+struct MainThread<T>(T);
+
+// SAFETY: `assert_main_thread` guards every access with threads enabled,
+// and without them there is no other thread
+unsafe impl<T> Send for MainThread<T> {}
+
+/// The main thread's executor, created on the first run
+static EXECUTOR: Mutex<Option<MainThread<LocalExecutor>>> = Mutex::new(None);
+
+/// Tasks spawned since the last run; kept apart from `EXECUTOR` so tasks
+/// can spawn while it is locked for polling
+static SPAWNED: Mutex<Vec<MainThread<LocalTask>>> = Mutex::new(Vec::new());
+
+/// Whether a run is scheduled and has not started
+static RUN_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+fn assert_main_thread() {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    assert_eq!(super::runtime::current_thread_id(), 0, "the async runtime only runs on the main thread");
+}
+
+/// Asks the host to run the executor, at most once per run
+///
+/// Installed as the executor's wake hook, so it may run on any thread.
+fn schedule_run() {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    if super::runtime::current_thread_id() != 0 {
+        return;
+    }
+    if !RUN_SCHEDULED.swap(true, Ordering::AcqRel) {
+        #[cfg(target_arch = "wasm32")]
+        // SAFETY: the import takes no arguments
+        unsafe {
+            __wasmrust_schedule_run();
+        }
+    }
+}
+
+/// Spawns a future on the main thread's executor
+///
+/// It is first polled by the next run, which this schedules.
+///
+/// # Panics
+/// With threads enabled, if called off the main thread.
+pub fn spawn_local(future: impl Future<Output = ()> + 'static) {
+    assert_main_thread();
+    SPAWNED.lock().push(MainThread(Box::pin(future)));
+    schedule_run();
+}
+
+/// Polls spawned and woken tasks until none are ready, returning the
+/// number of polls
+///
+/// The glue calls this from a microtask; other hosts call it whenever a
+/// run was scheduled or a timer fired.
+pub fn run_tasks() -> usize {
+    assert_main_thread();
+    RUN_SCHEDULED.store(false, Ordering::Release);
+    let mut executor = EXECUTOR.lock();
+    let executor = main_executor(&mut executor);
+    let mut polls = 0;
+    loop {
+        let spawned = core::mem::take(&mut *SPAWNED.lock());
+        for task in spawned {
+            executor.spawn(task.0);
+        }
+        match executor.run_until_stalled() {
+            0 => return polls,
+            ran => polls += ran,
+        }
+    }
+}
+
+fn main_executor(slot: &mut Option<MainThread<LocalExecutor>>) -> &mut LocalExecutor {
+    &mut slot.get_or_insert_with(|| MainThread(LocalExecutor::new().wake_hook(schedule_run))).0
+}
+
+/// Gets the number of spawned tasks that have not completed
+pub fn pending_tasks() -> usize {
+    let running = EXECUTOR.lock().as_ref().map_or(0, |executor| executor.0.pending_tasks());
+    running + SPAWNED.lock().len()
+}
+
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+extern "C" fn __wasmrust_run_tasks() {
+    run_tasks();
+}
+
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+extern "C" fn __wasmrust_timer_fired(id: u32) {
+    timer_fired(id);
+}
+
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+extern "C" fn __wasmrust_wake_signal() -> u32 {
+    main_executor(&mut EXECUTOR.lock()).wake_signal().as_ptr() as u32
+}
+
+#[derive(Default)]
+struct TimerSlot {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+static TIMERS: Mutex<BTreeMap<u32, TimerSlot>> = Mutex::new(BTreeMap::new());
+
+static NEXT_TIMER: AtomicU32 = AtomicU32::new(1);
+
+/// Records that the timer with `id` fired and wakes its sleeper
+///
+/// Called by the glue; a timer cancelled in the meantime is ignored.
+pub fn timer_fired(id: u32) {
+    let waker = match TIMERS.lock().get_mut(&id) {
+        Some(slot) => {
+            slot.fired = true;
+            slot.waker.take()
+        }
+        None => None,
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Completes after `millis` milliseconds, measured by a host timer
+///
+/// The timer starts on the first poll and is cancelled if the future is
+/// dropped before it fires.
+pub fn sleep(millis: u32) -> Sleep {
+    Sleep { millis, timer: None }
+}
+
+/// Future returned by `sleep`
+pub struct Sleep {
+    millis: u32,
+    timer: Option<u32>,
+}
+
+impl Sleep {
+    /// Gets the requested delay in milliseconds
+    pub fn millis(&self) -> u32 {
+        self.millis
+    }
+
+    /// Gets the id of the host timer, once the first poll started it
+    pub fn timer(&self) -> Option<u32> {
+        self.timer
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let id = match self.timer {
+            Some(id) => id,
+            None => {
+                let id = NEXT_TIMER.fetch_add(1, Ordering::Relaxed);
+                TIMERS.lock().insert(id, TimerSlot::default());
+                #[cfg(target_arch = "wasm32")]
+                // SAFETY: the slot for `id` exists before the timer can fire
+                unsafe {
+                    __wasmrust_set_timeout(id, self.millis);
+                }
+                self.timer = Some(id);
+                id
+            }
+        };
+
+        let mut timers = TIMERS.lock();
+        match timers.get_mut(&id) {
+            Some(slot) if !slot.fired => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            // Fired, or polled again after completing
+            _ => {
+                timers.remove(&id);
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        let Some(id) = self.timer else { return };
+        if TIMERS.lock().remove(&id).is_some_and(|slot| !slot.fired) {
+            #[cfg(target_arch = "wasm32")]
+            // SAFETY: the timer was started by this future and has not fired
+            unsafe {
+                __wasmrust_clear_timeout(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    #[test]
+    fn test_spawned_tasks_sleep_until_their_timer_fires() {
+        let progress = Arc::new(AtomicU32::new(0));
+        let timer = Arc::new(AtomicU32::new(0));
+        let (task_progress, task_timer) = (progress.clone(), timer.clone());
+        spawn_local(async move {
+            let mut nap = sleep(10);
+            core::future::poll_fn(|cx| {
+                let poll = Pin::new(&mut nap).poll(cx);
+                task_timer.store(nap.timer().unwrap(), Ordering::SeqCst);
+                poll
+            })
+            .await;
+            task_progress.store(1, Ordering::SeqCst);
+            // Tasks may spawn more tasks while the executor is running
+            let inner = task_progress.clone();
+            spawn_local(async move {
+                inner.store(2, Ordering::SeqCst);
+            });
+        });
+
+        assert!(RUN_SCHEDULED.load(Ordering::SeqCst));
+        run_tasks();
+        let id = timer.load(Ordering::SeqCst);
+        assert_ne!(id, 0);
+        assert_eq!(progress.load(Ordering::SeqCst), 0);
+
+        timer_fired(id);
+        run_tasks();
+        assert_eq!(progress.load(Ordering::SeqCst), 2);
+        assert!(TIMERS.lock().get(&id).is_none());
+    }
+
+    #[test]
+    fn test_dropped_sleep_cancels_its_timer() {
+        let mut nap = sleep(5);
+        let waker = Waker::from(Arc::new(NoopWake));
+        assert_eq!(Pin::new(&mut nap).poll(&mut Context::from_waker(&waker)), Poll::Pending);
+        let id = nap.timer().unwrap();
+        drop(nap);
+        assert!(TIMERS.lock().get(&id).is_none());
+        // A late expiry of the cancelled timer is ignored
+        timer_fired(id);
+        assert!(TIMERS.lock().get(&id).is_none());
+    }
+
+    struct NoopWake;
+
+    impl alloc::task::Wake for NoopWake {
+        fn wake(self: Arc<Self>) {}
+    }
+}
@@ -0,0 +1,225 @@
+//! Async multi-producer, single-consumer channel
+//!
+//! Senders are `Send` and never block, so worker threads can stream
+//! results to a receiver awaited on the main thread's `LocalExecutor`.
+//! Sending wakes the receiving task, which in turn signals the main
+//! thread; nothing busy-polls.
+
+use super::Mutex;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// Creates a channel with an unbounded queue
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Mutex::new(ChannelState {
+        queue: VecDeque::new(),
+        waker: None,
+        senders: 1,
+        receiver_alive: true,
+    }));
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+struct ChannelState<T> {
+    queue: VecDeque<T>,
+    waker: Option<Waker>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+/// Error returned when the receiver has been dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> core::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Sending on a closed channel")
+    }
+}
+
+/// Errors returned by `Receiver::try_recv`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// No message is queued yet
+    Empty,
+    /// Every sender has been dropped and the queue is drained
+    Disconnected,
+}
+
+impl core::fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "Receiving on an empty channel"),
+            TryRecvError::Disconnected => write!(f, "Receiving on a closed channel"),
+        }
+    }
+}
+
+/// Sending half of a channel
+pub struct Sender<T> {
+    shared: Arc<Mutex<ChannelState<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues a message and wakes the receiver
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let waker = {
+            let mut state = self.shared.lock();
+            if !state.receiver_alive {
+                return Err(SendError(value));
+            }
+            state.queue.push_back(value);
+            state.waker.take()
+        };
+        // Wake outside the lock; the wake hook may call back into the host
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.shared.lock();
+            state.senders -= 1;
+            if state.senders == 0 { state.waker.take() } else { None }
+        };
+        // The last sender wakes the receiver so it observes the disconnect
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Receiving half of a channel
+pub struct Receiver<T> {
+    shared: Arc<Mutex<ChannelState<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next message, or `None` once every sender is gone
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    /// Receives a message without waiting
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        match state.queue.pop_front() {
+            Some(value) => Ok(value),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.queue.clear();
+    }
+}
+
+/// Future returned by `Receiver::recv`
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.receiver.shared.lock();
+        if let Some(value) = state.queue.pop_front() {
+            return Poll::Ready(Some(value));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threading::executor::LocalExecutor;
+    use crate::threading::scope;
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+    use core::sync::atomic::Ordering;
+
+    #[test]
+    fn test_receiver_wakes_executor_task() {
+        let (tx, mut rx) = channel();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+
+        let mut executor = LocalExecutor::new();
+        executor.spawn(async move {
+            while let Some(value) = rx.recv().await {
+                sink.borrow_mut().push(value);
+            }
+        });
+
+        assert_eq!(executor.run_until_stalled(), 1);
+        assert_eq!(executor.pending_tasks(), 1);
+        // Nothing was sent, so another run polls nothing
+        assert_eq!(executor.run_until_stalled(), 0);
+
+        let signal = executor.wake_signal().load(Ordering::Acquire);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(executor.wake_signal().load(Ordering::Acquire), signal + 1);
+
+        executor.run_until_stalled();
+        assert_eq!(*received.borrow(), alloc::vec![1, 2]);
+
+        drop(tx);
+        executor.run_until_stalled();
+        assert_eq!(executor.pending_tasks(), 0);
+    }
+
+    #[test]
+    fn test_senders_from_scoped_threads() {
+        let (tx, mut rx) = channel();
+        scope(|s| {
+            for worker in 0..3 {
+                let tx = tx.clone();
+                s.spawn(move || tx.send(worker).unwrap());
+            }
+        });
+        drop(tx);
+
+        let mut values = Vec::new();
+        while let Ok(value) = rx.try_recv() {
+            values.push(value);
+        }
+        values.sort();
+        assert_eq!(values, alloc::vec![0, 1, 2]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_send_after_receiver_dropped() {
+        let (tx, mut rx) = channel::<u32>();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        drop(rx);
+        assert_eq!(tx.send(7), Err(SendError(7)));
+    }
+}
@@ -0,0 +1,132 @@
+//! Single-threaded executor for the JS main thread
+//!
+//! The main thread cannot block, so the executor never waits: the host
+//! calls `run_until_stalled` whenever work may be ready. Wakers are `Send`,
+//! letting worker threads wake tasks owned by the main thread. Each wake
+//! bumps `wake_signal`, which the glue watches with `Atomics.waitAsync`,
+//! and invokes the optional wake hook for hosts that schedule runs through
+//! `postMessage` instead.
+
+use super::{atomic_notify_all, Mutex};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll, Waker};
+
+type LocalTask = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Tasks woken since the last run, shared with wakers on any thread
+struct WakeQueue {
+    ready: Mutex<VecDeque<usize>>,
+    signal: AtomicU32,
+    hook: Option<fn()>,
+}
+
+struct TaskWaker {
+    task: usize,
+    queue: Arc<WakeQueue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.queue.ready.lock().push_back(self.task);
+        self.queue.signal.fetch_add(1, Ordering::Release);
+        atomic_notify_all(&self.queue.signal);
+        if let Some(hook) = self.queue.hook {
+            hook();
+        }
+    }
+}
+
+/// Executor running `!Send` futures on the current thread
+pub struct LocalExecutor {
+    tasks: Vec<Option<LocalTask>>,
+    queue: Arc<WakeQueue>,
+}
+
+impl LocalExecutor {
+    /// Creates an executor with no tasks
+    pub fn new() -> Self {
+        Self {
+            tasks: Vec::new(),
+            queue: Arc::new(WakeQueue {
+                ready: Mutex::new(VecDeque::new()),
+                signal: AtomicU32::new(0),
+                hook: None,
+            }),
+        }
+    }
+
+    /// Sets a hook invoked on every wake, e.g. to post a message to the
+    /// main thread asking it to run the executor
+    ///
+    /// The hook may run on any thread.
+    pub fn wake_hook(mut self, hook: fn()) -> Self {
+        if let Some(queue) = Arc::get_mut(&mut self.queue) {
+            queue.hook = Some(hook);
+        }
+        self
+    }
+
+    /// Spawns a task; it is first polled by the next run
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+        let task = match self.tasks.iter().position(Option::is_none) {
+            Some(slot) => {
+                self.tasks[slot] = Some(Box::pin(future));
+                slot
+            }
+            None => {
+                self.tasks.push(Some(Box::pin(future)));
+                self.tasks.len() - 1
+            }
+        };
+        self.queue.ready.lock().push_back(task);
+    }
+
+    /// Polls woken tasks until none are ready and returns the number of polls
+    pub fn run_until_stalled(&mut self) -> usize {
+        let mut polls = 0;
+        loop {
+            let next = self.queue.ready.lock().pop_front();
+            let Some(task) = next else {
+                return polls;
+            };
+            let Some(future) = self.tasks.get_mut(task).and_then(Option::as_mut) else {
+                continue;
+            };
+
+            let waker = Waker::from(Arc::new(TaskWaker { task, queue: self.queue.clone() }));
+            polls += 1;
+            if let Poll::Ready(()) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                self.tasks[task] = None;
+            }
+        }
+    }
+
+    /// Gets the number of tasks that have not completed
+    pub fn pending_tasks(&self) -> usize {
+        self.tasks.iter().filter(|task| task.is_some()).count()
+    }
+
+    /// Counter bumped on every wake
+    ///
+    /// Its address is what the glue passes to `Atomics.waitAsync`.
+    pub fn wake_signal(&self) -> &AtomicU32 {
+        &self.queue.signal
+    }
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
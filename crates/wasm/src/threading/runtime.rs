@@ -0,0 +1,382 @@
+//! Threads running on Web Workers or wasi-threads
+//!
+//! `spawn` mirrors `std::thread::spawn` on the wasi-threads ABI. The
+//! module imports `SPAWN_IMPORT` from `SPAWN_IMPORT_MODULE`, which starts
+//! a new instance of the same compiled module on another agent, sharing
+//! the memory, and calls its `THREAD_START_EXPORT` with the new thread's
+//! id and the argument given to the import. Wasmtime implements the import
+//! itself; in browsers the JS glue posts the module and memory to a
+//! Worker and hands out the ids. Workers are pooled: once a thread
+//! returns, its Worker keeps its instance and runs the next thread.
+//!
+//! The argument points at a `StartRecord` in the shared memory. For
+//! modules compiled by WasmRust, `add_thread_start` synthesizes the start
+//! export: it allocates the thread's shadow stack and TLS block, then
+//! calls `THREAD_MAIN_EXPORT`, which runs the record's closure.
+//!
+//! Without shared-memory atomics there is no other agent to run on, and
+//! the closure runs to completion on the calling thread before `spawn`
+//! returns. A panicking thread traps, which ends the whole program under
+//! wasi-threads. In browsers only the thread's Worker ends: the glue marks
+//! the thread done through the record's `done` address, so `join` returns
+//! `ThreadJoinFailed` instead of waiting forever.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::{Packet, ThreadingError};
+use crate::memory::stack::{DEFAULT_STACK_SIZE, STACK_LIMIT_GLOBAL, STACK_POINTER_GLOBAL};
+use crate::threading::tls::{INIT_TLS_EXPORT, MAX_TLS_ALIGN, TLS_SIZE_EXPORT};
+use crate::wasmir::{
+    BinaryOp, ExportKind, Instruction, Operand, Signature, Terminator, Type, WasmIR, WasmModule, STACK_ALIGN,
+};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Import module of the thread spawn function
+pub const SPAWN_IMPORT_MODULE: &str = "wasi";
+
+/// Import starting a thread: `(start_arg) -> id`, negative on failure
+pub const SPAWN_IMPORT: &str = "thread-spawn";
+
+/// Export a new thread's instance starts in: `(id, start_arg)`
+pub const THREAD_START_EXPORT: &str = "wasi_thread_start";
+
+/// Export running a thread's closure once its stack and TLS are set up:
+/// `(id, start_arg)`
+pub const THREAD_MAIN_EXPORT: &str = "__wasmrust_thread_main";
+
+/// What a spawned thread starts from, passed to `SPAWN_IMPORT` by address
+///
+/// The start export reads `stack_size` at offset 0, and the JS glue reads
+/// `done` at offset 4 to fail the thread's handle if the thread traps.
+#[repr(C)]
+pub struct StartRecord {
+    /// Bytes of shadow stack the thread gets
+    pub stack_size: u32,
+    done: *const AtomicU32,
+    main: Box<dyn FnOnce() + Send>,
+}
+
+impl StartRecord {
+    /// Creates a record for a thread running `main`, which sets `done`
+    /// when it ends
+    pub(crate) fn new(stack_size: u32, done: &AtomicU32, main: Box<dyn FnOnce() + Send>) -> Self {
+        Self { stack_size: stack_size.next_multiple_of(STACK_ALIGN), done, main }
+    }
+
+    /// Runs the thread's closure on the calling thread
+    pub(crate) fn run_inline(self) {
+        (self.main)();
+    }
+}
+
+/// Id of the running thread
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+#[thread_local]
+static CURRENT_THREAD: core::cell::Cell<u32> = core::cell::Cell::new(0);
+
+/// Id of the running thread; closures run inline, so one is enough
+#[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+static CURRENT_THREAD: AtomicU32 = AtomicU32::new(0);
+
+/// Makes `id` the running thread's id and returns the previous one
+fn replace_current(id: u32) -> u32 {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    return CURRENT_THREAD.replace(id);
+    #[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+    return CURRENT_THREAD.swap(id, Ordering::AcqRel);
+}
+
+/// Gets the id of the running thread; the main thread is 0
+pub fn current_thread_id() -> u32 {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    return CURRENT_THREAD.get();
+    #[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+    return CURRENT_THREAD.load(Ordering::Acquire);
+}
+
+/// Spawns a thread with the default stack size
+pub fn spawn<F, T>(f: F) -> Result<JoinHandle<T>, ThreadingError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_with_stack_size(DEFAULT_STACK_SIZE, f)
+}
+
+/// Spawns a thread whose shadow stack holds `stack_size` bytes
+///
+/// Fails with `ThreadCreationFailed` if the host refuses to start one.
+pub fn spawn_with_stack_size<F, T>(stack_size: u32, f: F) -> Result<JoinHandle<T>, ThreadingError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Packet::new();
+    let task_packet = packet.clone();
+    let record = StartRecord::new(stack_size, &packet.done, Box::new(move || task_packet.complete(f)));
+    let id = start(record).map_err(|_| ThreadingError::ThreadCreationFailed)?;
+    Ok(JoinHandle { id, packet })
+}
+
+/// Starts a thread running `record`, handing the record back if the host
+/// refuses
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+pub(crate) fn start(record: StartRecord) -> Result<u32, StartRecord> {
+    #[link(wasm_import_module = "wasi")]
+    extern "C" {
+        #[link_name = "thread-spawn"]
+        fn thread_spawn(start_arg: i32) -> i32;
+    }
+
+    let record = Box::into_raw(Box::new(record));
+    // SAFETY: the new thread takes ownership of the record in `thread_main`
+    let id = unsafe { thread_spawn(record as i32) };
+    if id < 0 {
+        // SAFETY: no thread started, so the record is still ours
+        return Err(*unsafe { Box::from_raw(record) });
+    }
+    Ok(id as u32)
+}
+
+/// Runs `record` to completion as a new thread
+#[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+pub(crate) fn start(record: StartRecord) -> Result<u32, StartRecord> {
+    static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
+
+    let id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    run(id, record);
+    Ok(id)
+}
+
+/// Runs a thread's closure as thread `id`
+fn run(id: u32, record: StartRecord) {
+    let parent = replace_current(id);
+    record.run_inline();
+    replace_current(parent);
+}
+
+/// Entry point the synthesized start export calls
+///
+/// # Safety
+/// `record` must come from `spawn_with_stack_size` and not be used again.
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+#[export_name = "__wasmrust_thread_main"]
+unsafe extern "C" fn thread_main(id: i32, record: *mut StartRecord) {
+    run(id as u32, *Box::from_raw(record));
+}
+
+/// Owned permission to join a spawned thread
+pub struct JoinHandle<T> {
+    id: u32,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Gets the thread's id
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Waits for the thread to finish and returns its result
+    ///
+    /// Fails with `ThreadJoinFailed` if the thread panicked. Browsers forbid
+    /// blocking their main thread, where this traps unless `is_finished`
+    /// already returns true.
+    pub fn join(self) -> Result<T, ThreadingError> {
+        self.packet.join()
+    }
+
+    /// Checks if the thread has finished
+    pub fn is_finished(&self) -> bool {
+        self.packet.done.load(Ordering::Acquire) != 0
+    }
+}
+
+/// Reasons a thread start export cannot be added to a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadStartError {
+    /// The module already exports `THREAD_START_EXPORT`
+    ConflictingExport,
+    /// `THREAD_MAIN_EXPORT` is missing or does not take `(i32, i32)`
+    InvalidThreadMain,
+}
+
+impl fmt::Display for ThreadStartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThreadStartError::ConflictingExport => write!(f, "module already exports {}", THREAD_START_EXPORT),
+            ThreadStartError::InvalidThreadMain => {
+                write!(f, "{} must be an exported function taking (i32, i32)", THREAD_MAIN_EXPORT)
+            }
+        }
+    }
+}
+
+/// Checks whether the module runs threads and lacks a start export
+pub fn needs_thread_start(module: &WasmModule) -> bool {
+    let exports = |name: &str| module.exports.iter().any(|export| export.name == name);
+    exports(THREAD_MAIN_EXPORT) && !exports(THREAD_START_EXPORT)
+}
+
+/// Returns a copy of `module` exporting `THREAD_START_EXPORT` and sharing
+/// its memory
+///
+/// Run this after `lower_stack_frames` and `lower_thread_locals`: the start
+/// export gives the thread a stack if the module has a stack pointer and a
+/// TLS block if it has `INIT_TLS_EXPORT`, then frees both once the thread
+/// returns. Both come from `MemoryAlloc`, so the allocator must be shared
+/// between threads too.
+pub fn add_thread_start(module: &WasmModule) -> Result<WasmModule, ThreadStartError> {
+    if module.exports.iter().any(|export| export.name == THREAD_START_EXPORT) {
+        return Err(ThreadStartError::ConflictingExport);
+    }
+    let main = exported_function(module, THREAD_MAIN_EXPORT)
+        .filter(|&index| module.functions[index as usize].signature.params == [Type::I32, Type::I32])
+        .ok_or(ThreadStartError::InvalidThreadMain)?;
+
+    let mut function = WasmIR::new(
+        THREAD_START_EXPORT.into(),
+        Signature { params: alloc::vec![Type::I32, Type::I32], returns: None },
+    );
+    // id, start record, stack, TLS block
+    function.locals = alloc::vec![Type::I32; 4];
+    let mut instructions = Vec::new();
+    let mut cleanup = Vec::new();
+    let stack = module.global_index(STACK_POINTER_GLOBAL).zip(module.global_index(STACK_LIMIT_GLOBAL));
+    if let Some((stack_pointer, limit)) = stack {
+        instructions.extend([
+            Instruction::MemoryLoad { address: Operand::Local(1), ty: Type::I32, align: None, offset: 0 },
+            Instruction::MemoryAlloc { size: Operand::StackValue(0), align: Some(STACK_ALIGN) },
+            Instruction::LocalSet { index: 2, value: Operand::StackValue(0) },
+            Instruction::GlobalSet { index: limit, value: Operand::Local(2) },
+            Instruction::MemoryLoad { address: Operand::Local(1), ty: Type::I32, align: None, offset: 0 },
+            Instruction::BinaryOp {
+                op: BinaryOp::Add,
+                left: Operand::StackValue(0),
+                right: Operand::Local(2),
+            },
+            Instruction::GlobalSet { index: stack_pointer, value: Operand::StackValue(0) },
+        ]);
+        cleanup.push(Instruction::MemoryFree { address: Operand::Local(2) });
+    }
+    let tls = exported_function(module, INIT_TLS_EXPORT).zip(exported_function(module, TLS_SIZE_EXPORT));
+    if let Some((init, size)) = tls {
+        instructions.extend([
+            Instruction::Call { func_ref: size, args: Vec::new() },
+            Instruction::MemoryAlloc { size: Operand::StackValue(0), align: Some(MAX_TLS_ALIGN) },
+            Instruction::LocalSet { index: 3, value: Operand::StackValue(0) },
+            Instruction::Call { func_ref: init, args: alloc::vec![Operand::Local(3)] },
+        ]);
+        cleanup.push(Instruction::MemoryFree { address: Operand::Local(3) });
+    }
+    instructions.push(Instruction::Call { func_ref: main, args: alloc::vec![Operand::Local(0), Operand::Local(1)] });
+    instructions.extend(cleanup.into_iter().rev());
+    function.add_basic_block(instructions, Terminator::Return { value: None });
+
+    let mut threaded = module.clone();
+    let start = threaded.add_function(function);
+    threaded.export_function(THREAD_START_EXPORT, start);
+    threaded.shared_memory = true;
+    Ok(threaded)
+}
+
+fn exported_function(module: &WasmModule, name: &str) -> Option<u32> {
+    module.exports.iter().find_map(|export| match export.kind {
+        ExportKind::Function(index) if export.name == name => Some(index),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::stack::lower_stack_frames;
+    use crate::threading::tls::lower_thread_locals;
+    use crate::wasmir::{Constant, MemoryType};
+    use alloc::string::ToString;
+
+    /// Module exporting an empty thread main
+    fn threaded_module() -> WasmModule {
+        let mut main = WasmIR::new(
+            THREAD_MAIN_EXPORT.to_string(),
+            Signature { params: alloc::vec![Type::I32, Type::I32], returns: None },
+        );
+        main.locals = alloc::vec![Type::I32, Type::I32];
+        main.add_basic_block(Vec::new(), Terminator::Return { value: None });
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let index = module.add_function(main);
+        module.export_function(THREAD_MAIN_EXPORT, index);
+        module
+    }
+
+    #[test]
+    fn test_spawn_runs_closure_and_joins() {
+        let handle = spawn(|| (current_thread_id(), 6 * 7)).unwrap();
+        assert!(handle.is_finished());
+        let id = handle.id();
+        assert_ne!(id, 0);
+        assert_eq!(handle.join(), Ok((id, 42)));
+
+        let second = spawn_with_stack_size(1, || ()).unwrap();
+        assert_ne!(second.id(), id);
+        assert_eq!(second.join(), Ok(()));
+    }
+
+    #[test]
+    fn test_join_fails_after_a_panic() {
+        extern crate std;
+
+        let packet = Packet::<u32>::new();
+        let task_packet = packet.clone();
+        let panicked = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+            task_packet.complete(|| panic!("thread failed"))
+        }));
+        assert!(panicked.is_err());
+
+        let handle = JoinHandle { id: 1, packet };
+        assert!(handle.is_finished());
+        assert_eq!(handle.join(), Err(ThreadingError::ThreadJoinFailed));
+    }
+
+    #[test]
+    fn test_thread_start_sets_up_stack_and_tls() {
+        let mut module = threaded_module();
+        module.add_thread_local("COUNT", Type::I32, Constant::I32(0));
+        module.functions[0].add_stack_slot(4, 4);
+        let module = lower_stack_frames(&lower_thread_locals(&module).unwrap(), DEFAULT_STACK_SIZE).unwrap();
+        assert!(needs_thread_start(&module));
+
+        let threaded = add_thread_start(&module).unwrap();
+        assert!(threaded.shared_memory);
+        assert!(!needs_thread_start(&threaded));
+        let start = exported_function(&threaded, THREAD_START_EXPORT).unwrap();
+        let instructions = &threaded.functions[start as usize].basic_blocks[0].instructions;
+        let stack_pointer = threaded.global_index(STACK_POINTER_GLOBAL).unwrap();
+        assert!(matches!(instructions[6], Instruction::GlobalSet { index, .. } if index == stack_pointer));
+        assert!(matches!(instructions[8], Instruction::MemoryAlloc { align: Some(MAX_TLS_ALIGN), .. }));
+        assert!(matches!(instructions[11], Instruction::Call { func_ref: 0, .. }));
+        assert!(matches!(&instructions[12..], [
+            Instruction::MemoryFree { address: Operand::Local(3) },
+            Instruction::MemoryFree { address: Operand::Local(2) },
+        ]));
+    }
+
+    #[test]
+    fn test_rejects_missing_main_and_existing_start() {
+        let mut module = threaded_module();
+        module.export_function(THREAD_START_EXPORT, 0);
+        assert!(!needs_thread_start(&module));
+        assert_eq!(add_thread_start(&module).err(), Some(ThreadStartError::ConflictingExport));
+
+        let mut module = threaded_module();
+        module.functions[0].signature.params.pop();
+        assert_eq!(
+            add_thread_start(&module).unwrap_err().to_string(),
+            "__wasmrust_thread_main must be an exported function taking (i32, i32)"
+        );
+    }
+}
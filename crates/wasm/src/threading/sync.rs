@@ -0,0 +1,466 @@
+//! Futex-based synchronization primitives
+//!
+//! `Mutex`, `Condvar`, and `RwLock` keep their state in an `AtomicU32` and
+//! block in `memory.atomic.wait32` when contended, waking waiters with
+//! `memory.atomic.notify`. The compiler lowers the same two operations,
+//! which `std::sync` reaches through `core::arch::wasm32`, to
+//! `Instruction::AtomicWait` and `Instruction::AtomicNotify`, so both kinds
+//! of lock end up on one futex implementation.
+//!
+//! Without shared-memory atomics a wasm module runs on a single thread, so
+//! a held lock can never be released by anyone else: contended `lock`s
+//! panic and `Condvar::wait` fails with `DeadlockDetected` instead of
+//! hanging. Other targets run these primitives on host threads and spin.
+
+use super::ThreadingError;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Spins before a contended lock falls back to waiting
+const SPIN_LIMIT: u32 = 100;
+
+/// Blocks while `futex` holds `expected`, or until woken
+///
+/// May return spuriously; callers re-check their condition.
+fn futex_wait(futex: &AtomicU32, expected: u32) -> Result<(), ThreadingError> {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    unsafe {
+        core::arch::wasm32::memory_atomic_wait32(futex.as_ptr() as *mut i32, expected as i32, -1);
+    }
+    #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+    if futex.load(Ordering::Acquire) == expected {
+        return Err(ThreadingError::DeadlockDetected);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if futex.load(Ordering::Relaxed) == expected {
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Wakes up to `count` agents blocked in `futex_wait` on `futex`
+fn futex_wake(futex: &AtomicU32, count: u32) {
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    unsafe {
+        core::arch::wasm32::memory_atomic_notify(futex.as_ptr() as *mut i32, count);
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_feature = "atomics")))]
+    let _ = (futex, count);
+}
+
+/// Spins until `futex` leaves `busy` or the spin limit is reached
+fn spin_while(futex: &AtomicU32, busy: impl Fn(u32) -> bool) -> u32 {
+    let mut spins = 0;
+    loop {
+        let state = futex.load(Ordering::Relaxed);
+        if !busy(state) || spins == SPIN_LIMIT {
+            return state;
+        }
+        core::hint::spin_loop();
+        spins += 1;
+    }
+}
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, with agents possibly waiting for the lock
+const CONTENDED: u32 = 2;
+
+/// Mutual exclusion lock
+///
+/// The lock is a three-state futex: unlocked, locked, and locked with
+/// waiters. Only the last makes unlocking notify, so uncontended locking
+/// costs one compare-exchange and one swap.
+pub struct Mutex<T> {
+    futex: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: access to `value` is serialized by `futex`
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates a new unlocked mutex
+    pub const fn new(value: T) -> Self {
+        Self {
+            futex: AtomicU32::new(UNLOCKED),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the lock, blocking until it is available
+    ///
+    /// # Panics
+    /// Without shared-memory atomics, if the lock is already held.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self.futex.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            self.lock_contended();
+        }
+        MutexGuard { mutex: self }
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        let mut state = spin_while(&self.futex, |state| state == LOCKED);
+        if state == UNLOCKED {
+            match self.futex.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current) => state = current,
+            }
+        }
+        loop {
+            // Taking the lock as contended may notify needlessly once, but
+            // never loses a waiter
+            if state != CONTENDED && self.futex.swap(CONTENDED, Ordering::Acquire) == UNLOCKED {
+                return;
+            }
+            if futex_wait(&self.futex, CONTENDED).is_err() {
+                panic!("deadlock: Mutex locked again on the only thread");
+            }
+            state = spin_while(&self.futex, |state| state == LOCKED);
+        }
+    }
+
+    /// Attempts to acquire the lock without blocking
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.futex
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    /// Returns true if the lock is currently held
+    pub fn is_locked(&self) -> bool {
+        self.futex.load(Ordering::Relaxed) != UNLOCKED
+    }
+
+    /// Gets a mutable reference without locking
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the mutex and returns the inner value
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn unlock(&self) {
+        if self.futex.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.futex, 1);
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// RAII guard releasing a `Mutex` when dropped
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock exclusively
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// Condition variable paired with a `Mutex`
+///
+/// The futex is a sequence number bumped by every notification, so a
+/// notification sent between unlocking the mutex and waiting is not lost.
+/// Waits may wake spuriously; `wait_while` re-checks its condition.
+#[derive(Default)]
+pub struct Condvar {
+    futex: AtomicU32,
+}
+
+impl Condvar {
+    /// Creates a condition variable with no waiters
+    pub const fn new() -> Self {
+        Self { futex: AtomicU32::new(0) }
+    }
+
+    /// Unlocks `guard`'s mutex, waits for a notification, and locks it again
+    ///
+    /// Fails with `DeadlockDetected` without shared-memory atomics, since no
+    /// other thread could send the notification.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> Result<MutexGuard<'a, T>, ThreadingError> {
+        let sequence = self.futex.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        let waited = futex_wait(&self.futex, sequence);
+        let guard = mutex.lock();
+        waited.map(|_| guard)
+    }
+
+    /// Waits until `condition` returns false for the protected value
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> Result<MutexGuard<'a, T>, ThreadingError> {
+        while condition(&mut guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Wakes one waiting thread
+    pub fn notify_one(&self) {
+        self.futex.fetch_add(1, Ordering::Release);
+        futex_wake(&self.futex, 1);
+    }
+
+    /// Wakes every waiting thread
+    pub fn notify_all(&self) {
+        self.futex.fetch_add(1, Ordering::Release);
+        futex_wake(&self.futex, u32::MAX);
+    }
+}
+
+/// Futex state of a write-locked `RwLock`; smaller values count readers
+const WRITE_LOCKED: u32 = u32::MAX;
+
+/// Reader-writer lock
+///
+/// The futex counts readers, or holds `WRITE_LOCKED`. Unlocking notifies
+/// only when `waiters` says someone waits. Readers are admitted while
+/// writers wait, so a steady stream of readers can starve a writer.
+pub struct RwLock<T> {
+    futex: AtomicU32,
+    waiters: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: readers share `&T` and writers are exclusive, as for `std::sync::RwLock`
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new unlocked lock
+    pub const fn new(value: T) -> Self {
+        Self {
+            futex: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires shared access, blocking while a writer holds the lock
+    ///
+    /// # Panics
+    /// Without shared-memory atomics, if the lock is write-locked.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            self.wait(WRITE_LOCKED);
+        }
+    }
+
+    /// Attempts to acquire shared access without blocking
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.futex.load(Ordering::Relaxed);
+        // One below `WRITE_LOCKED` is the reader limit
+        while state < WRITE_LOCKED - 1 {
+            match self.futex.compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Some(RwLockReadGuard { lock: self }),
+                Err(current) => state = current,
+            }
+        }
+        None
+    }
+
+    /// Acquires exclusive access, blocking while anyone holds the lock
+    ///
+    /// # Panics
+    /// Without shared-memory atomics, if the lock is held.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            self.wait(self.futex.load(Ordering::Relaxed));
+        }
+    }
+
+    /// Attempts to acquire exclusive access without blocking
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.futex
+            .compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    /// Gets a mutable reference without locking
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the lock and returns the inner value
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Waits for the futex to leave `state`, if it is still held
+    #[cold]
+    fn wait(&self, state: u32) {
+        if state == 0 || spin_while(&self.futex, |current| current == state) != state {
+            return;
+        }
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+        let waited = futex_wait(&self.futex, state);
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+        if waited.is_err() {
+            panic!("deadlock: RwLock locked again on the only thread");
+        }
+    }
+
+    fn unlock(&self, state: u32) {
+        if self.futex.fetch_sub(state, Ordering::Release) == state && self.waiters.load(Ordering::Relaxed) > 0 {
+            futex_wake(&self.futex, u32::MAX);
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// RAII guard releasing shared access to an `RwLock` when dropped
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: writers are excluded while the guard lives
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.unlock(1);
+    }
+}
+
+/// RAII guard releasing exclusive access to an `RwLock` when dropped
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock exclusively
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock exclusively
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.unlock(WRITE_LOCKED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::sync::Arc;
+
+    #[test]
+    fn test_mutex_and_condvar_hand_off_between_threads() {
+        let shared = Arc::new((Mutex::new(0u32), Condvar::new()));
+        let workers: std::vec::Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        *shared.0.lock() += 1;
+                    }
+                    shared.1.notify_all();
+                })
+            })
+            .collect();
+
+        let (mutex, condvar) = &*shared;
+        let guard = condvar.wait_while(mutex.lock(), |count| *count < 4000).unwrap();
+        assert_eq!(*guard, 4000);
+        drop(guard);
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(!mutex.is_locked());
+    }
+
+    #[test]
+    fn test_rwlock_shares_reads_and_excludes_writes() {
+        let lock = RwLock::new(1);
+        {
+            let first = lock.read();
+            let second = lock.try_read().unwrap();
+            assert_eq!(*first + *second, 2);
+            assert!(lock.try_write().is_none());
+        }
+        *lock.try_write().unwrap() += 1;
+        {
+            let _writer = lock.write();
+            assert!(lock.try_read().is_none());
+            assert!(lock.try_write().is_none());
+        }
+        assert_eq!(*lock.read(), 2);
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_rwlock_writer_waits_for_readers() {
+        let lock = Arc::new(RwLock::new(alloc::vec::Vec::new()));
+        let reader = lock.read();
+        let writer = {
+            let lock = lock.clone();
+            std::thread::spawn(move || lock.write().push(1))
+        };
+        assert!(reader.is_empty());
+        drop(reader);
+        writer.join().unwrap();
+        assert_eq!(*lock.read(), alloc::vec![1]);
+    }
+}
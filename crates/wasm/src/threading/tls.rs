@@ -0,0 +1,298 @@
+//! Thread-local storage for compiled modules
+//!
+//! Each thread keeps its copy of the module's `ThreadLocal`s in a TLS
+//! block of linear memory. `lower_thread_locals` lays the variables out,
+//! keeps the running thread's block in the mutable `TLS_BASE_GLOBAL`, and
+//! relocates every `Instruction::ThreadLocalAddress` to an offset from it.
+//!
+//! The main thread's block is reserved past the module's initial memory
+//! and filled by a constructor running before all others. A worker
+//! allocates a block of `TLS_SIZE_EXPORT` bytes, aligned to
+//! `TLS_ALIGN_EXPORT`, in the shared memory and hands it to
+//! `INIT_TLS_EXPORT` before running thread code. Globals belong to an
+//! instance, so every worker's instance points at its own block; workers
+//! must not rerun the constructors, which would reset the main thread's.
+
+use core::fmt;
+
+use crate::wasmir::{
+    BinaryOp, Constant, Constructor, Instruction, MemoryType, Operand, Signature, Terminator, Type, WasmIR, WasmModule,
+};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Global holding the address of the running thread's TLS block
+pub const TLS_BASE_GLOBAL: &str = "__tls_base";
+
+/// Export filling a TLS block and making it the instance's: `(block)`
+pub const INIT_TLS_EXPORT: &str = "__wasm_init_tls";
+
+/// Export giving the size of a TLS block: `() -> size`
+pub const TLS_SIZE_EXPORT: &str = "__wasm_tls_size";
+
+/// Export giving the alignment of a TLS block: `() -> align`
+pub const TLS_ALIGN_EXPORT: &str = "__wasm_tls_align";
+
+/// Alignment every TLS block satisfies
+pub const MAX_TLS_ALIGN: u32 = 8;
+
+/// Size of a linear memory page
+const PAGE_SIZE: u32 = 65536;
+
+/// Most pages a 32-bit memory can have
+const MAX_PAGES: u32 = 65536;
+
+/// Reasons a module's thread locals cannot be lowered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlsError {
+    /// The module already defines a global or export the lowering adds
+    ConflictingName(&'static str),
+    /// A thread local's type cannot be kept in memory
+    UnsupportedType(String),
+    /// A thread local's initializer does not have its type
+    InitMismatch(String),
+    /// The main thread's block does not fit within the memory's maximum
+    NoTlsSpace,
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::ConflictingName(name) => write!(f, "module already defines {}", name),
+            TlsError::UnsupportedType(name) => write!(f, "thread local {} has a type without a memory layout", name),
+            TlsError::InitMismatch(name) => write!(f, "initializer of thread local {} does not match its type", name),
+            TlsError::NoTlsSpace => write!(f, "memory leaves no room for the main thread's TLS block"),
+        }
+    }
+}
+
+/// Placement of a module's thread locals within a TLS block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsLayout {
+    /// Offset of each thread local, by index
+    pub offsets: Vec<u32>,
+    /// Bytes in a block, a multiple of `align`
+    pub size: u32,
+    /// Alignment a block needs
+    pub align: u32,
+}
+
+/// Checks whether the module defines thread locals
+pub fn uses_thread_locals(module: &WasmModule) -> bool {
+    !module.thread_locals.is_empty()
+}
+
+/// Lays out the module's thread locals in declaration order
+pub fn tls_layout(module: &WasmModule) -> Result<TlsLayout, TlsError> {
+    let mut layout = TlsLayout { offsets: Vec::new(), size: 0, align: 1 };
+    for variable in &module.thread_locals {
+        let width = match storage_type(&variable.ty) {
+            Some(Type::I64 | Type::F64) => 8,
+            Some(_) => 4,
+            None => return Err(TlsError::UnsupportedType(variable.name.clone())),
+        };
+        if constant_type(&variable.init) != storage_type(&variable.ty) {
+            return Err(TlsError::InitMismatch(variable.name.clone()));
+        }
+        let offset = layout.size.next_multiple_of(width);
+        layout.offsets.push(offset);
+        layout.size = offset + width;
+        layout.align = layout.align.max(width);
+    }
+    layout.size = layout.size.next_multiple_of(layout.align);
+    Ok(layout)
+}
+
+/// Returns a copy of `module` keeping its thread locals in per-thread TLS
+/// blocks, with the main thread's reserved and initialized
+///
+/// A module without linear memory gets one. The returned module has no
+/// thread locals left.
+pub fn lower_thread_locals(module: &WasmModule) -> Result<WasmModule, TlsError> {
+    if module.global_index(TLS_BASE_GLOBAL).is_some() {
+        return Err(TlsError::ConflictingName(TLS_BASE_GLOBAL));
+    }
+    for name in [INIT_TLS_EXPORT, TLS_SIZE_EXPORT, TLS_ALIGN_EXPORT] {
+        if module.exports.iter().any(|export| export.name == name) {
+            return Err(TlsError::ConflictingName(name));
+        }
+    }
+    let layout = tls_layout(module)?;
+    let memory = module.memory.unwrap_or(MemoryType { min_pages: 0, max_pages: None });
+    let top_pages = memory.min_pages.saturating_add(layout.size.div_ceil(PAGE_SIZE).max(1));
+    if top_pages > MAX_PAGES || memory.max_pages.is_some_and(|max| top_pages > max) {
+        return Err(TlsError::NoTlsSpace);
+    }
+
+    let mut lowered = module.clone();
+    lowered.set_memory(MemoryType { min_pages: top_pages, ..memory });
+    let main_block = (memory.min_pages * PAGE_SIZE) as i32;
+    let base = lowered.add_global(TLS_BASE_GLOBAL, Type::I32, true, Constant::I32(main_block));
+    for function in &mut lowered.functions {
+        for block in &mut function.basic_blocks {
+            for instruction in &mut block.instructions {
+                if let Instruction::ThreadLocalAddress { index } = instruction {
+                    *instruction = Instruction::BinaryOp {
+                        op: BinaryOp::Add,
+                        left: Operand::Global(base),
+                        right: Operand::Constant(Constant::I32(layout.offsets[*index as usize] as i32)),
+                    };
+                }
+            }
+        }
+    }
+
+    let init = lowered.add_function(init_tls(module, &layout, base));
+    let size = lowered.add_function(constant_function(TLS_SIZE_EXPORT, layout.size));
+    let align = lowered.add_function(constant_function(TLS_ALIGN_EXPORT, layout.align));
+    lowered.export_function(INIT_TLS_EXPORT, init);
+    lowered.export_function(TLS_SIZE_EXPORT, size);
+    lowered.export_function(TLS_ALIGN_EXPORT, align);
+
+    // Constructors run in a stable order, so the first of priority 0 runs first
+    let mut main = WasmIR::new("__wasm_init_main_tls".into(), Signature { params: Vec::new(), returns: None });
+    main.add_basic_block(
+        alloc::vec![Instruction::Call { func_ref: init, args: alloc::vec![Operand::Constant(Constant::I32(main_block))] }],
+        Terminator::Return { value: None },
+    );
+    let main = lowered.add_function(main);
+    lowered.constructors.insert(0, Constructor { function: main, priority: 0 });
+    lowered.thread_locals.clear();
+    Ok(lowered)
+}
+
+/// Builds `INIT_TLS_EXPORT`, storing every initializer into the block
+fn init_tls(module: &WasmModule, layout: &TlsLayout, base: u32) -> WasmIR {
+    let mut function = WasmIR::new(INIT_TLS_EXPORT.into(), Signature { params: alloc::vec![Type::I32], returns: None });
+    function.locals = alloc::vec![Type::I32];
+    let mut instructions = alloc::vec![Instruction::GlobalSet { index: base, value: Operand::Local(0) }];
+    for (variable, &offset) in module.thread_locals.iter().zip(&layout.offsets) {
+        instructions.push(Instruction::MemoryStore {
+            address: Operand::Local(0),
+            value: Operand::Constant(variable.init.clone()),
+            ty: storage_type(&variable.ty).expect("laid out thread locals have a storage type"),
+            align: None,
+            offset,
+        });
+    }
+    function.add_basic_block(instructions, Terminator::Return { value: None });
+    function
+}
+
+fn constant_function(name: &str, value: u32) -> WasmIR {
+    let mut function = WasmIR::new(name.into(), Signature { params: Vec::new(), returns: Some(Type::I32) });
+    function.add_basic_block(
+        Vec::new(),
+        Terminator::Return { value: Some(Operand::Constant(Constant::I32(value as i32))) },
+    );
+    function
+}
+
+/// Type a thread local is loaded and stored as; pointers are addresses
+fn storage_type(ty: &Type) -> Option<Type> {
+    match ty {
+        Type::I32 | Type::Pointer(_) => Some(Type::I32),
+        Type::I64 => Some(Type::I64),
+        Type::F32 => Some(Type::F32),
+        Type::F64 => Some(Type::F64),
+        _ => None,
+    }
+}
+
+fn constant_type(constant: &Constant) -> Option<Type> {
+    match constant {
+        Constant::I32(_) | Constant::Boolean(_) => Some(Type::I32),
+        Constant::I64(_) => Some(Type::I64),
+        Constant::F32(_) => Some(Type::F32),
+        Constant::F64(_) => Some(Type::F64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::ExportKind;
+    use alloc::string::ToString;
+
+    /// Module with an `i32` and an `f64` thread local and a `bump` export
+    /// incrementing the first one and returning it
+    fn counter_module() -> WasmModule {
+        let mut module = WasmModule::new();
+        let count = module.add_thread_local("COUNT", Type::I32, Constant::I32(7));
+        module.add_thread_local("RATIO", Type::F64, Constant::F64(0.5));
+        let mut bump = WasmIR::new("bump".to_string(), Signature { params: Vec::new(), returns: Some(Type::I32) });
+        bump.locals = alloc::vec![Type::I32, Type::I32];
+        bump.add_basic_block(
+            alloc::vec![
+                Instruction::ThreadLocalAddress { index: count },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+                Instruction::MemoryLoad { address: Operand::Local(0), ty: Type::I32, align: None, offset: 0 },
+                Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::StackValue(0), right: Operand::Constant(Constant::I32(1)) },
+                Instruction::LocalSet { index: 1, value: Operand::StackValue(0) },
+                Instruction::MemoryStore { address: Operand::Local(0), value: Operand::Local(1), ty: Type::I32, align: None, offset: 0 },
+            ],
+            Terminator::Return { value: Some(Operand::Local(1)) },
+        );
+        let index = module.add_function(bump);
+        module.export_function("bump", index);
+        module
+    }
+
+    #[test]
+    fn test_lays_out_thread_locals_by_alignment() {
+        let module = counter_module();
+        assert!(uses_thread_locals(&module));
+        assert_eq!(tls_layout(&module), Ok(TlsLayout { offsets: alloc::vec![0, 8], size: 16, align: 8 }));
+
+        let mut module = counter_module();
+        module.add_thread_local("NAME", Type::ExternRef(String::new()), Constant::Null);
+        assert_eq!(tls_layout(&module), Err(TlsError::UnsupportedType("NAME".to_string())));
+
+        let mut module = counter_module();
+        module.add_thread_local("WIDE", Type::I64, Constant::I32(0));
+        assert_eq!(
+            lower_thread_locals(&module).unwrap_err().to_string(),
+            "initializer of thread local WIDE does not match its type"
+        );
+    }
+
+    #[test]
+    fn test_relocates_addresses_against_the_tls_base() {
+        let mut module = counter_module();
+        module.set_memory(MemoryType { min_pages: 2, max_pages: None });
+        let lowered = lower_thread_locals(&module).unwrap();
+        assert!(lowered.validate().is_ok());
+        assert!(!uses_thread_locals(&lowered));
+        assert_eq!(lowered.memory, Some(MemoryType { min_pages: 3, max_pages: None }));
+        assert_eq!(lowered.globals[0].init, Constant::I32(2 * 65536));
+        assert!(matches!(
+            lowered.functions[0].basic_blocks[0].instructions[0],
+            Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::Global(0), right: Operand::Constant(Constant::I32(0)) }
+        ));
+
+        let init = lowered.function_index(INIT_TLS_EXPORT).unwrap();
+        assert!(lowered.exports.iter().any(|export| export.kind == ExportKind::Function(init)));
+        assert!(matches!(lowered.functions[init as usize].basic_blocks[0].instructions.as_slice(), [
+            Instruction::GlobalSet { index: 0, value: Operand::Local(0) },
+            Instruction::MemoryStore { ty: Type::I32, offset: 0, .. },
+            Instruction::MemoryStore { ty: Type::F64, offset: 8, .. },
+        ]));
+        assert_eq!(lowered.constructors_in_order()[0], lowered.functions.len() as u32 - 1);
+    }
+
+    #[test]
+    fn test_rejects_conflicts_and_full_memory() {
+        let mut module = counter_module();
+        module.add_global(TLS_BASE_GLOBAL, Type::I32, true, Constant::I32(0));
+        assert_eq!(lower_thread_locals(&module).err(), Some(TlsError::ConflictingName(TLS_BASE_GLOBAL)));
+
+        let mut module = counter_module();
+        module.export_function(TLS_SIZE_EXPORT, 0);
+        assert_eq!(lower_thread_locals(&module).err(), Some(TlsError::ConflictingName(TLS_SIZE_EXPORT)));
+
+        let mut module = counter_module();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: Some(1) });
+        assert_eq!(lower_thread_locals(&module).err(), Some(TlsError::NoTlsSpace));
+    }
+}
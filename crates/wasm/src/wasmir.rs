@@ -11,6 +11,13 @@ use alloc::string::{String, ToString};
 use alloc::boxed::Box;
 use alloc::format;
 use core::fmt;
+use crate::memory::regions::{self, Region, RegionError};
+
+pub mod analysis;
+pub mod builder;
+pub mod features;
+pub mod legalize;
+pub mod verifier;
 
 /// WasmIR - Stable Intermediate Representation
 /// 
@@ -31,6 +38,26 @@ pub struct WasmIR {
     pub capabilities: Vec<Capability>,
     /// Ownership annotations for linear types
     pub ownership_annotations: Vec<OwnershipAnnotation>,
+    /// Shadow stack frame, if any local's address is taken
+    pub frame: Option<StackFrame>,
+}
+
+/// Alignment of the shadow stack pointer and of every frame
+pub const STACK_ALIGN: u32 = 16;
+
+/// Linear memory a function reserves on the shadow stack
+///
+/// Wasm locals have no address, so locals whose address is taken live in
+/// slots of the frame instead. `memory::stack` reserves the frame on
+/// entry and releases it on return.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Local holding the frame's address while the function runs
+    pub base: u32,
+    /// Bytes taken by the slots
+    pub size: u32,
+    /// Parameters copied into their slot on entry, with the slot's offset
+    pub spills: Vec<(u32, u32)>,
 }
 
 /// Function signature in WasmIR
@@ -68,6 +95,12 @@ pub enum Instruction {
     
     /// Set a local variable
     LocalSet { index: u32, value: Operand },
+
+    /// Set a mutable module global; `Operand::Global` reads one
+    GlobalSet { index: u32, value: Operand },
+
+    /// Address of a module thread local in the current thread's TLS block
+    ThreadLocalAddress { index: u32 },
     
     /// Binary operation
     BinaryOp {
@@ -81,12 +114,24 @@ pub enum Instruction {
         op: UnaryOp,
         value: Operand,
     },
+
+    /// Numeric conversion of `value` to another type
+    Convert {
+        op: ConvertOp,
+        value: Operand,
+    },
     
     /// Function call
     Call {
         func_ref: u32,
         args: Vec<Operand>,
     },
+
+    /// Call to an imported host function
+    CallImport {
+        import: u32,
+        args: Vec<Operand>,
+    },
     
     /// Return from function
     Return { value: Option<Operand> },
@@ -130,6 +175,13 @@ pub enum Instruction {
     
     /// Deallocate memory
     MemoryFree { address: Operand },
+
+    /// Current size of linear memory in pages
+    MemorySize,
+
+    /// Grows linear memory by `pages`, producing the previous size in pages
+    /// or -1 if it cannot grow
+    MemoryGrow { pages: Operand },
     
     /// Create a new object reference
     NewObject { type_id: u32, args: Vec<Operand> },
@@ -229,6 +281,25 @@ pub enum Instruction {
         new_value: Operand,
         order: MemoryOrder,
     },
+
+    /// Blocks while the `ty` at `address` equals `expected`, for at most
+    /// `timeout` nanoseconds (an `i64`, negative for no limit)
+    ///
+    /// Pushes 0 when woken, 1 if the value differed, and 2 on timeout.
+    AtomicWait {
+        address: Operand,
+        expected: Operand,
+        timeout: Operand,
+        ty: Type,
+    },
+
+    /// Wakes up to `count` agents waiting on `address`
+    ///
+    /// Pushes the number of agents woken.
+    AtomicNotify {
+        address: Operand,
+        count: Operand,
+    },
     
     /// Linear type operation
     LinearOp {
@@ -245,6 +316,55 @@ pub enum Instruction {
     Nop,
 }
 
+impl Instruction {
+    /// Short name used in diagnostics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::LocalGet { .. } => "local.get",
+            Instruction::LocalSet { .. } => "local.set",
+            Instruction::GlobalSet { .. } => "global.set",
+            Instruction::ThreadLocalAddress { .. } => "thread_local.address",
+            Instruction::BinaryOp { .. } => "binary_op",
+            Instruction::UnaryOp { .. } => "unary_op",
+            Instruction::Convert { .. } => "convert",
+            Instruction::Call { .. } => "call",
+            Instruction::CallImport { .. } => "call_import",
+            Instruction::Return { .. } => "return",
+            Instruction::Branch { .. } => "branch",
+            Instruction::Jump { .. } => "jump",
+            Instruction::Switch { .. } => "switch",
+            Instruction::MemoryLoad { .. } => "memory_load",
+            Instruction::MemoryStore { .. } => "memory_store",
+            Instruction::MemoryAlloc { .. } => "memory_alloc",
+            Instruction::MemoryFree { .. } => "memory_free",
+            Instruction::MemorySize => "memory_size",
+            Instruction::MemoryGrow { .. } => "memory_grow",
+            Instruction::NewObject { .. } => "new_object",
+            Instruction::DropObject { .. } => "drop_object",
+            Instruction::ExternRefLoad { .. } => "externref_load",
+            Instruction::ExternRefStore { .. } => "externref_store",
+            Instruction::JSMethodCall { .. } => "js_method_call",
+            Instruction::MakeFuncRef { .. } => "make_funcref",
+            Instruction::FuncRefCall { .. } => "funcref_call",
+            Instruction::ExternRefNew { .. } => "externref_new",
+            Instruction::ExternRefCast { .. } => "externref_cast",
+            Instruction::ExternRefIsNull { .. } => "externref_is_null",
+            Instruction::ExternRefEq { .. } => "externref_eq",
+            Instruction::FuncRefNew { .. } => "funcref_new",
+            Instruction::FuncRefIsNull { .. } => "funcref_is_null",
+            Instruction::FuncRefEq { .. } => "funcref_eq",
+            Instruction::CallIndirect { .. } => "call_indirect",
+            Instruction::AtomicOp { .. } => "atomic_op",
+            Instruction::CompareExchange { .. } => "compare_exchange",
+            Instruction::AtomicWait { .. } => "atomic_wait",
+            Instruction::AtomicNotify { .. } => "atomic_notify",
+            Instruction::LinearOp { .. } => "linear_op",
+            Instruction::CapabilityCheck { .. } => "capability_check",
+            Instruction::Nop => "nop",
+        }
+    }
+}
+
 /// Binary operations
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BinaryOp {
@@ -260,6 +380,59 @@ pub enum UnaryOp {
     Neg, Not, Clz, Ctz, Popcnt,
 }
 
+/// Numeric conversions, each mapping to a WebAssembly conversion opcode
+///
+/// The source type is that of the operand; `signed` selects how integers
+/// are interpreted on the integer side of the conversion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvertOp {
+    /// `i64` to `i32`, keeping the low bits
+    Wrap,
+    /// `i32` to `i64`
+    Extend { signed: bool },
+    /// Sign-extends the low `bits` of an integer in place
+    SignExtend { bits: u8 },
+    /// Float to integer, trapping on NaN and out-of-range values
+    Trunc { to: Type, signed: bool },
+    /// Float to integer, saturating at the bounds with NaN becoming zero
+    TruncSat { to: Type, signed: bool },
+    /// Integer to float, rounding to nearest
+    FromInt { to: Type, signed: bool },
+    /// `f32` to `f64`
+    Promote,
+    /// `f64` to `f32`
+    Demote,
+    /// Bit-for-bit between an integer and a float of the same width
+    Reinterpret,
+}
+
+impl ConvertOp {
+    /// Result type of the conversion applied to a value of type `from`, or
+    /// `None` if it does not apply to that type
+    pub fn result(&self, from: &Type) -> Option<Type> {
+        let is_int = |ty: &Type| matches!(ty, Type::I32 | Type::I64);
+        let is_float = |ty: &Type| matches!(ty, Type::F32 | Type::F64);
+        match (self, from) {
+            (ConvertOp::Wrap, Type::I64) => Some(Type::I32),
+            (ConvertOp::Extend { .. }, Type::I32) => Some(Type::I64),
+            (ConvertOp::SignExtend { bits: 8 | 16 }, Type::I32) | (ConvertOp::SignExtend { bits: 8 | 16 | 32 }, Type::I64) => {
+                Some(from.clone())
+            }
+            (ConvertOp::Trunc { to, .. } | ConvertOp::TruncSat { to, .. }, from) if is_float(from) && is_int(to) => {
+                Some(to.clone())
+            }
+            (ConvertOp::FromInt { to, .. }, from) if is_int(from) && is_float(to) => Some(to.clone()),
+            (ConvertOp::Promote, Type::F32) => Some(Type::F64),
+            (ConvertOp::Demote, Type::F64) => Some(Type::F32),
+            (ConvertOp::Reinterpret, Type::I32) => Some(Type::F32),
+            (ConvertOp::Reinterpret, Type::I64) => Some(Type::F64),
+            (ConvertOp::Reinterpret, Type::F32) => Some(Type::I32),
+            (ConvertOp::Reinterpret, Type::F64) => Some(Type::I64),
+            _ => None,
+        }
+    }
+}
+
 /// Atomic operations
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AtomicOp {
@@ -337,6 +510,7 @@ pub enum Operand {
 pub enum Constant {
     I32(i32),
     I64(i64),
+    I128(i128),
     F32(f32),
     F64(f64),
     Null,
@@ -353,6 +527,9 @@ pub enum Type {
     /// 64-bit integer
     I64,
     
+    /// 128-bit integer, legalized into a pair of `I64`s before emission
+    I128,
+    
     /// 32-bit float
     F32,
     
@@ -380,6 +557,11 @@ pub enum Type {
     /// Capability-annotated type
     Capability { inner_type: Box<Type>, capability: Capability },
     
+    /// JavaScript Promise settling to the inner type
+    ///
+    /// Lowered according to `WasmModule::promise_lowering` before codegen.
+    Promise(Box<Type>),
+    
     /// Void type
     Void,
 }
@@ -434,7 +616,7 @@ pub enum OwnershipState {
 }
 
 /// Source location for error reporting
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SourceLocation {
     /// File path
     pub file: String,
@@ -456,6 +638,7 @@ impl WasmIR {
             locals: Vec::new(),
             capabilities: Vec::new(),
             ownership_annotations: Vec::new(),
+            frame: None,
         }
     }
 
@@ -478,6 +661,22 @@ impl WasmIR {
         index
     }
 
+    /// Reserves a slot in the function's stack frame, returning its offset
+    /// from `StackFrame::base`
+    ///
+    /// The first slot adds the frame and its base local. Alignments above
+    /// `STACK_ALIGN` are lowered to it; wasm tolerates misaligned accesses.
+    pub fn add_stack_slot(&mut self, size: u32, align: u32) -> u32 {
+        if self.frame.is_none() {
+            let base = self.add_local(Type::I32);
+            self.frame = Some(StackFrame { base, size: 0, spills: Vec::new() });
+        }
+        let frame = self.frame.as_mut().expect("frame was just added");
+        let offset = frame.size.next_multiple_of(align.clamp(1, STACK_ALIGN));
+        frame.size = offset + size;
+        offset
+    }
+
     /// Adds a capability annotation to the function
     pub fn add_capability(&mut self, capability: Capability) {
         self.capabilities.push(capability);
@@ -488,6 +687,12 @@ impl WasmIR {
         self.ownership_annotations.push(annotation);
     }
 
+    /// Runs the full verifier, collecting every problem instead of the first
+    pub fn verify(&self) -> Result<(), Vec<verifier::Diagnostic>> {
+        let diagnostics = verifier::verify_function(self);
+        if diagnostics.is_empty() { Ok(()) } else { Err(diagnostics) }
+    }
+
     /// Validates the WasmIR function
     pub fn validate(&self) -> Result<(), ValidationError> {
         // Check that all block IDs are valid
@@ -541,7 +746,7 @@ impl WasmIR {
                     return Err(ValidationError::InvalidLocalIndex(*index));
                 }
             }
-            Instruction::Call { args, .. } => {
+            Instruction::Call { args, .. } | Instruction::CallImport { args, .. } => {
                 for (i, arg) in args.iter().enumerate() {
                     self.validate_operand(arg, "arg")?;
                 }
@@ -594,6 +799,40 @@ impl WasmIR {
         block_id.0 < self.basic_blocks.len()
     }
 
+    /// Gets the static type of an operand, if it can be known without
+    /// simulating the operand stack
+    ///
+    /// Local indices cover the parameters first, then declared locals.
+    pub fn operand_type(&self, operand: &Operand) -> Option<Type> {
+        match operand {
+            Operand::Local(index) => self.signature.params.get(*index as usize)
+                .or_else(|| self.locals.get(*index as usize))
+                .cloned(),
+            Operand::Constant(constant) => match constant {
+                Constant::I32(_) | Constant::Boolean(_) => Some(Type::I32),
+                Constant::I64(_) => Some(Type::I64),
+                Constant::I128(_) => Some(Type::I128),
+                Constant::F32(_) => Some(Type::F32),
+                Constant::F64(_) => Some(Type::F64),
+                Constant::Null => Some(Type::ExternRef(String::new())),
+                Constant::String(_) => None,
+            },
+            Operand::ExternRef(_) => Some(Type::ExternRef(String::new())),
+            Operand::FuncRef(_) | Operand::FunctionRef(_) => Some(Type::FuncRef),
+            Operand::MemoryAddress(_) => Some(Type::I32),
+            Operand::Global(_) | Operand::StackValue(_) => None,
+        }
+    }
+
+    /// Gets the type of a JS interop receiver, defaulting to an untyped externref
+    fn receiver_type(&self, operand: &Operand) -> Result<Type, ValidationError> {
+        match self.operand_type(operand) {
+            Some(ty @ Type::ExternRef(_)) => Ok(ty),
+            None => Ok(Type::ExternRef(String::new())),
+            Some(_) => Err(ValidationError::InvalidImport("JS receiver is not an externref".to_string())),
+        }
+    }
+
     /// Gets the entry block (first basic block)
     pub fn entry_block(&self) -> Option<&BasicBlock> {
         self.basic_blocks.first()
@@ -654,14 +893,872 @@ impl WasmIR {
     }
 }
 
+/// Module-level WasmIR
+///
+/// Groups the functions of a compilation unit together with the
+/// declarations that connect them: exports, linear memory, and
+/// initialization order. Function indices used by `Instruction::Call`
+/// refer to positions in `functions`.
+#[derive(Debug, Clone, Default)]
+pub struct WasmModule {
+    /// Functions defined by this module
+    pub functions: Vec<WasmIR>,
+    /// Host functions imported by this module
+    pub imports: Vec<Import>,
+    /// Exported items
+    pub exports: Vec<Export>,
+    /// Linear memory declaration, if the module defines one
+    pub memory: Option<MemoryType>,
+    /// Whether threads share the linear memory, which is then imported as
+    /// `MEMORY_IMPORT_MODULE::MEMORY_IMPORT` instead of defined
+    pub shared_memory: bool,
+    /// Whether the linear memory is imported like a shared one without
+    /// being shared, as by a module split off another
+    pub import_memory: bool,
+    /// Globals defined by this module, read with `Operand::Global`
+    pub globals: Vec<Global>,
+    /// Variables with one copy per thread, addressed with
+    /// `Instruction::ThreadLocalAddress`
+    pub thread_locals: Vec<ThreadLocal>,
+    /// Function designated to run when the module is instantiated
+    pub start_function: Option<u32>,
+    /// Initializers that must run before any export is called
+    pub constructors: Vec<Constructor>,
+    /// Source-level signatures of functions marshaling strings, slices, or vectors
+    pub interop_signatures: HashMap<u32, InteropSignature>,
+    /// Source-level signatures of imports marshaling strings or slices, by
+    /// import index
+    pub import_interop_signatures: HashMap<u32, InteropSignature>,
+    /// How `Type::Promise` crosses the host boundary
+    pub promise_lowering: PromiseLowering,
+    /// Named ranges of linear memory, declared with `declare_memory_region`
+    pub memory_regions: Vec<Region>,
+    /// Bytes linear memory holds at instantiation
+    pub data_segments: Vec<DataSegment>,
+    /// Functions rarely called, which code splitting may move out of the module
+    pub cold_functions: Vec<u32>,
+}
+
+/// Strategy for JS methods returning promises and async exports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PromiseLowering {
+    /// A promise is an `i32` handle into the glue's promise table; Rust
+    /// awaits it as a `JsPromise` and async exports resolve a
+    /// `PromiseResolver`
+    #[default]
+    Callback,
+    /// JS Promise Integration: promise-returning imports suspend the wasm
+    /// stack and yield the settled value, and async exports are wrapped
+    /// with `WebAssembly.promising`
+    Jspi,
+}
+
+impl PromiseLowering {
+    /// Core type a `Type::Promise` is lowered to
+    pub fn lower(self, ty: &Type) -> Type {
+        match ty {
+            Type::Promise(inner) => match self {
+                PromiseLowering::Callback => Type::I32,
+                PromiseLowering::Jspi => self.lower(inner),
+            },
+            Type::Linear { inner_type } => Type::Linear { inner_type: Box::new(self.lower(inner_type)) },
+            Type::Capability { inner_type, capability } => Type::Capability {
+                inner_type: Box::new(self.lower(inner_type)),
+                capability: capability.clone(),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+/// Import module used for JavaScript interop shims
+pub const JS_IMPORT_MODULE: &str = "wasmrust";
+
+/// Export the host calls to allocate string buffers: `(size, align) -> ptr`
+pub const ALLOC_EXPORT: &str = "__wasm_alloc";
+
+/// Export the host calls to release string buffers: `(ptr, size, align)`
+pub const FREE_EXPORT: &str = "__wasm_free";
+
+/// Runtime import registering a Rust closure: `(invoke, data, drop) -> handle`
+pub const CLOSURE_NEW_IMPORT: &str = "__wasmrust_closure_new";
+
+/// Runtime import rooting another table slot for an object: `(handle) -> handle`
+pub const EXTERNREF_CLONE_IMPORT: &str = "__wasmrust_externref_clone";
+
+/// Runtime import unrooting an externref table slot: `(handle)`
+pub const EXTERNREF_RELEASE_IMPORT: &str = "__wasmrust_externref_release";
+
+/// Panic hook called with the index of a compiled panic's message in
+/// `WasmModule::panic_messages`, or -1: `(message)`
+pub const PANIC_IMPORT: &str = "__wasmrust_panic";
+
+/// Panic hook called by `wasm::panic::report` with a UTF-8 message: `(ptr, len)`
+pub const PANIC_MESSAGE_IMPORT: &str = "__wasmrust_panic_message";
+
+/// Import module of a shared linear memory
+pub const MEMORY_IMPORT_MODULE: &str = "env";
+
+/// Import name of a shared linear memory
+pub const MEMORY_IMPORT: &str = "memory";
+
+/// How a source-level parameter crosses the host boundary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InteropType {
+    /// Passed directly as its WebAssembly type
+    Value,
+    /// `&str`/`String` passed as UTF-8 bytes in linear memory
+    Str,
+    /// `&[T]`, borrowed for the duration of the call
+    Slice(ElementType),
+    /// `&mut [T]`, whose contents are copied back to the host after the call
+    SliceMut(ElementType),
+    /// `Vec<T>`, whose ownership moves across the boundary
+    Vec(ElementType),
+    /// JavaScript object passed as an `i32` handle to the glue's externref table
+    ExternRef,
+}
+
+impl InteropType {
+    /// Checks whether the type is lowered to an `i32` pointer and `i32` length
+    pub fn is_memory_pair(self) -> bool {
+        !matches!(self, InteropType::Value | InteropType::ExternRef)
+    }
+}
+
+/// Element type of a slice or vector crossing the host boundary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ElementType {
+    /// Size of one element in bytes
+    pub fn size(self) -> u32 {
+        match self {
+            ElementType::U8 | ElementType::I8 => 1,
+            ElementType::U16 | ElementType::I16 => 2,
+            ElementType::U32 | ElementType::I32 | ElementType::F32 => 4,
+            ElementType::I64 | ElementType::F64 => 8,
+        }
+    }
+
+    /// JavaScript typed array constructor viewing elements of this type
+    pub fn typed_array(self) -> &'static str {
+        match self {
+            ElementType::U8 => "Uint8Array",
+            ElementType::I8 => "Int8Array",
+            ElementType::U16 => "Uint16Array",
+            ElementType::I16 => "Int16Array",
+            ElementType::U32 => "Uint32Array",
+            ElementType::I32 => "Int32Array",
+            ElementType::I64 => "BigInt64Array",
+            ElementType::F32 => "Float32Array",
+            ElementType::F64 => "Float64Array",
+        }
+    }
+}
+
+/// Source-level view of a lowered function signature
+///
+/// Strings, slices, and vectors are passed as an `i32` pointer followed by
+/// an `i32` length in elements (bytes for strings). Borrowed arguments are
+/// allocated by the host with `ALLOC_EXPORT` and freed after the call;
+/// `Vec` arguments become owned by the callee. A string or `Vec` result is
+/// returned through a leading `i32` parameter pointing at an 8-byte return
+/// area, into which the callee writes the pointer and length of a buffer
+/// allocated with `ALLOC_EXPORT`. Ownership of that buffer passes to the
+/// host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InteropSignature {
+    /// Source-level parameters, in order
+    pub params: Vec<InteropType>,
+    /// Source-level result; anything but `Value` uses a return area
+    pub returns: InteropType,
+}
+
+impl Default for InteropSignature {
+    fn default() -> Self {
+        Self { params: Vec::new(), returns: InteropType::Value }
+    }
+}
+
+impl InteropSignature {
+    /// Checks whether any part of the signature is marshaled through memory
+    pub fn needs_marshaling(&self) -> bool {
+        self.returns.is_memory_pair() || self.params.iter().any(|param| param.is_memory_pair())
+    }
+
+    /// Checks whether the result is written through a return area
+    pub fn has_return_area(&self) -> bool {
+        self.returns.is_memory_pair()
+    }
+
+    /// Number of WebAssembly parameters after lowering
+    pub fn lowered_param_count(&self) -> usize {
+        let pairs = self.params.iter().filter(|param| param.is_memory_pair()).count();
+        self.params.len() + pairs + usize::from(self.has_return_area())
+    }
+}
+
+/// Import declaration
+///
+/// Imports have their own index space: `Instruction::CallImport` refers
+/// to positions in `WasmModule::imports`, while `Instruction::Call`
+/// refers to defined functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// Module name the host resolves the import from
+    pub module: String,
+    /// Field name within the module
+    pub name: String,
+    /// Imported item
+    pub kind: ImportKind,
+}
+
+/// Kinds of importable items
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportKind {
+    /// Host function with the given signature
+    Function(Signature),
+}
+
+impl Import {
+    /// Import name used for `JSMethodCall` of the given method
+    pub fn js_method_name(method: &str) -> String {
+        format!("js_call:{}", method)
+    }
+
+    /// Import name used for `ExternRefLoad` of the given property
+    pub fn js_getter_name(field: &str) -> String {
+        format!("js_get:{}", field)
+    }
+
+    /// Import name used for `ExternRefStore` of the given property
+    pub fn js_setter_name(field: &str) -> String {
+        format!("js_set:{}", field)
+    }
+
+    /// Import name calling a global function, given its dotted path
+    pub fn js_global_name(path: &str) -> String {
+        format!("js_global:{}", path)
+    }
+
+    /// Import name constructing a global class, given its dotted path
+    pub fn js_new_name(class: &str) -> String {
+        format!("js_new:{}", class)
+    }
+
+    /// Gets the signature of a function import
+    pub fn signature(&self) -> &Signature {
+        match &self.kind {
+            ImportKind::Function(signature) => signature,
+        }
+    }
+}
+
+/// Export declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    /// Name visible to the host
+    pub name: String,
+    /// Exported item
+    pub kind: ExportKind,
+}
+
+/// Kinds of exportable items
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    /// Function by index
+    Function(u32),
+    /// Linear memory by index
+    Memory(u32),
+}
+
+/// Linear memory limits in 64KiB pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryType {
+    /// Initial number of pages
+    pub min_pages: u32,
+    /// Maximum number of pages
+    pub max_pages: Option<u32>,
+}
+
+/// Bytes copied into linear memory when the module is instantiated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSegment {
+    /// Address of the first byte
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl DataSegment {
+    /// First address past the segment
+    pub fn end(&self) -> u64 {
+        self.offset as u64 + self.bytes.len() as u64
+    }
+}
+
+/// Global variable defined by a module
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    /// Name the compiler knows the global by; it is not exported
+    pub name: String,
+    /// Value type
+    pub ty: Type,
+    /// Whether `Instruction::GlobalSet` may write it
+    pub mutable: bool,
+    /// Value at instantiation
+    pub init: Constant,
+}
+
+/// `#[thread_local]` variable defined by a module
+///
+/// Every thread gets its own copy, starting at `init`, in a TLS block of
+/// linear memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadLocal {
+    /// Name the compiler knows the variable by
+    pub name: String,
+    /// Value type
+    pub ty: Type,
+    /// Value each thread starts with
+    pub init: Constant,
+}
+
+/// Module initializer (`#[wasm::ctor]`-style function)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Constructor {
+    /// Function index of the initializer
+    pub function: u32,
+    /// Lower priorities run first
+    pub priority: u32,
+}
+
+impl WasmModule {
+    /// Creates an empty module
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a function and returns its index
+    pub fn add_function(&mut self, function: WasmIR) -> u32 {
+        let index = self.functions.len() as u32;
+        self.functions.push(function);
+        index
+    }
+
+    /// Declares a host function import and returns its import index
+    ///
+    /// Declaring the same module and name twice returns the existing index.
+    pub fn add_import(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        signature: Signature,
+    ) -> u32 {
+        let module = module.into();
+        let name = name.into();
+        if let Some(index) = self.find_import(&module, &name) {
+            return index;
+        }
+        let index = self.imports.len() as u32;
+        self.imports.push(Import {
+            module,
+            name,
+            kind: ImportKind::Function(signature),
+        });
+        index
+    }
+
+    /// Finds an import index by module and field name
+    pub fn find_import(&self, module: &str, name: &str) -> Option<u32> {
+        self.imports.iter()
+            .position(|import| import.module == module && import.name == name)
+            .map(|i| i as u32)
+    }
+
+    /// Declares host imports for every JS interop instruction in the module
+    ///
+    /// Signatures are derived from operand types: the receiver is passed as
+    /// an externref followed by the arguments. Existing declarations are
+    /// kept as long as their signatures agree.
+    pub fn declare_js_imports(&mut self) -> Result<(), ValidationError> {
+        let mut required = Vec::new();
+        for function in &self.functions {
+            for instruction in function.all_instructions() {
+                let (name, signature) = match instruction {
+                    Instruction::JSMethodCall { object, method, args, return_type } => {
+                        let mut params = alloc::vec![function.receiver_type(object)?];
+                        for arg in args {
+                            params.push(function.operand_type(arg).ok_or_else(|| {
+                                ValidationError::InvalidImport(format!("untyped argument to JS method {}", method))
+                            })?);
+                        }
+                        (Import::js_method_name(method), Signature { params, returns: return_type.clone() })
+                    }
+                    Instruction::ExternRefLoad { externref, field, field_type } => {
+                        let params = alloc::vec![function.receiver_type(externref)?];
+                        (Import::js_getter_name(field), Signature { params, returns: Some(field_type.clone()) })
+                    }
+                    Instruction::ExternRefStore { externref, field, field_type, .. } => {
+                        let params = alloc::vec![function.receiver_type(externref)?, field_type.clone()];
+                        (Import::js_setter_name(field), Signature { params, returns: None })
+                    }
+                    _ => continue,
+                };
+                required.push((name, signature));
+            }
+        }
+
+        for (name, signature) in required {
+            match self.find_import(JS_IMPORT_MODULE, &name) {
+                Some(index) => {
+                    let existing = self.imports[index as usize].signature();
+                    if existing.params.len() != signature.params.len() || existing.returns != signature.returns {
+                        return Err(ValidationError::InvalidImport(format!("conflicting signatures for {}", name)));
+                    }
+                }
+                None => {
+                    self.add_import(JS_IMPORT_MODULE, name, signature);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Declares the `PANIC_IMPORT` hook so the host can report panics
+    ///
+    /// Modules without the hook still trap or unwind, but silently.
+    pub fn declare_panic_hook(&mut self) -> u32 {
+        let signature = Signature { params: alloc::vec![Type::I32], returns: None };
+        self.add_import(JS_IMPORT_MODULE, PANIC_IMPORT, signature)
+    }
+
+    /// Exports a function under the given name
+    pub fn export_function(&mut self, name: impl Into<String>, function: u32) {
+        self.exports.push(Export {
+            name: name.into(),
+            kind: ExportKind::Function(function),
+        });
+    }
+
+    /// Declares linear memory and exports it as `memory`
+    pub fn set_memory(&mut self, memory: MemoryType) {
+        self.memory = Some(memory);
+        if !self.exports.iter().any(|e| e.kind == ExportKind::Memory(0)) {
+            self.exports.push(Export {
+                name: "memory".to_string(),
+                kind: ExportKind::Memory(0),
+            });
+        }
+    }
+
+    /// Declares a named range of linear memory; see `memory::regions`
+    ///
+    /// Regions must be non-empty, below 2 GiB, and apart from each other.
+    pub fn declare_memory_region(&mut self, region: Region) -> Result<(), RegionError> {
+        regions::check_declaration(&self.memory_regions, &region)?;
+        self.memory_regions.push(region);
+        Ok(())
+    }
+
+    /// Adds bytes linear memory holds at `offset` when instantiated
+    pub fn add_data_segment(&mut self, offset: u32, bytes: Vec<u8>) {
+        self.data_segments.push(DataSegment { offset, bytes });
+    }
+
+    /// Defines a global and returns its index
+    pub fn add_global(&mut self, name: impl Into<String>, ty: Type, mutable: bool, init: Constant) -> u32 {
+        let index = self.globals.len() as u32;
+        self.globals.push(Global { name: name.into(), ty, mutable, init });
+        index
+    }
+
+    /// Gets the index of the global with the given name
+    pub fn global_index(&self, name: &str) -> Option<u32> {
+        self.globals.iter().position(|global| global.name == name).map(|index| index as u32)
+    }
+
+    /// Defines a thread local and returns its index
+    pub fn add_thread_local(&mut self, name: impl Into<String>, ty: Type, init: Constant) -> u32 {
+        let index = self.thread_locals.len() as u32;
+        self.thread_locals.push(ThreadLocal { name: name.into(), ty, init });
+        index
+    }
+
+    /// Designates the function run at instantiation
+    pub fn set_start_function(&mut self, function: u32) {
+        self.start_function = Some(function);
+    }
+
+    /// Marks a function as rarely called, as `#[cold]` does
+    pub fn mark_cold(&mut self, function: u32) {
+        if !self.cold_functions.contains(&function) {
+            self.cold_functions.push(function);
+        }
+    }
+
+    /// Checks whether the linear memory is imported rather than defined
+    pub fn imports_memory(&self) -> bool {
+        self.memory.is_some() && (self.shared_memory || self.import_memory)
+    }
+
+    /// Registers an initializer to run before exports are called
+    pub fn add_constructor(&mut self, function: u32, priority: u32) {
+        self.constructors.push(Constructor { function, priority });
+    }
+
+    /// Gets initializer function indices in execution order
+    ///
+    /// Initializers run by ascending priority; equal priorities keep
+    /// registration order.
+    pub fn constructors_in_order(&self) -> Vec<u32> {
+        let mut ordered = self.constructors.clone();
+        ordered.sort_by_key(|ctor| ctor.priority);
+        ordered.iter().map(|ctor| ctor.function).collect()
+    }
+
+    /// Records the source-level signature of a function that needs marshaling
+    pub fn set_interop_signature(&mut self, function: u32, signature: InteropSignature) {
+        self.interop_signatures.insert(function, signature);
+    }
+
+    /// Gets the source-level signature of a function, if it needs marshaling
+    pub fn interop_signature(&self, function: u32) -> Option<&InteropSignature> {
+        self.interop_signatures.get(&function)
+    }
+
+    /// Records the source-level signature of an import that needs marshaling
+    ///
+    /// The host receives strings and slices borrowed for the call, and
+    /// returns strings and vectors by allocating them with `ALLOC_EXPORT`
+    /// and writing them to the return area; the module then owns them.
+    pub fn set_import_interop_signature(&mut self, import: u32, signature: InteropSignature) {
+        self.import_interop_signatures.insert(import, signature);
+    }
+
+    /// Gets the source-level signature of an import, if it needs marshaling
+    pub fn import_interop_signature(&self, import: u32) -> Option<&InteropSignature> {
+        self.import_interop_signatures.get(&import)
+    }
+
+    /// Messages of the module's panics, in order of first use
+    ///
+    /// Backends identify a panic to the host by its index here, so no
+    /// message text or formatting code has to be in the binary. A module
+    /// with a shadow stack lists `STACK_OVERFLOW_MESSAGE` first, before and
+    /// after `lower_stack_frames`, so both agree on the indices.
+    pub fn panic_messages(&self) -> Vec<&str> {
+        use crate::memory::stack::{STACK_OVERFLOW_MESSAGE, STACK_POINTER_GLOBAL};
+        let mut messages = Vec::new();
+        if self.functions.iter().any(|function| function.frame.is_some())
+            || self.global_index(STACK_POINTER_GLOBAL).is_some()
+        {
+            messages.push(STACK_OVERFLOW_MESSAGE);
+        }
+        let terminators = self.functions.iter()
+            .flat_map(|function| function.basic_blocks.iter())
+            .map(|block| &block.terminator);
+        for terminator in terminators {
+            if let Terminator::Panic { message: Some(Operand::Constant(Constant::String(message))) } = terminator {
+                if !messages.contains(&message.as_str()) {
+                    messages.push(message.as_str());
+                }
+            }
+        }
+        messages
+    }
+
+    /// Checks whether any signature or local uses `Type::Promise`
+    pub fn uses_promises(&self) -> bool {
+        let signatures = self.functions.iter()
+            .map(|function| &function.signature)
+            .chain(self.imports.iter().map(Import::signature));
+        let locals = self.functions.iter().flat_map(|function| function.locals.iter());
+        signatures
+            .flat_map(|signature| signature.params.iter().chain(&signature.returns))
+            .chain(locals)
+            .any(|ty| matches!(ty, Type::Promise(_)))
+    }
+
+    /// Checks whether any signature or local uses `Type::I128`
+    pub fn uses_i128(&self) -> bool {
+        let signatures = self.functions.iter()
+            .map(|function| &function.signature)
+            .chain(self.imports.iter().map(Import::signature));
+        let locals = self.functions.iter().flat_map(|function| function.locals.iter());
+        signatures
+            .flat_map(|signature| signature.params.iter().chain(&signature.returns))
+            .chain(locals)
+            .any(|ty| *ty == Type::I128)
+    }
+
+    /// Returns a copy with every `Type::Promise` lowered to its core type
+    pub fn lower_promises(&self) -> WasmModule {
+        let lowering = self.promise_lowering;
+        self.map_types(|ty| lowering.lower(ty))
+    }
+
+    /// Rewrites `Type::ExternRef` values to the `i32` table handles Rust holds
+    ///
+    /// Only needed when `uses_externref_table` holds; the glue roots and
+    /// resolves the handles at the boundary.
+    pub fn lower_externref_handles(&self) -> WasmModule {
+        fn lower(ty: &Type) -> Type {
+            match ty {
+                Type::ExternRef(_) => Type::I32,
+                Type::Promise(inner) => Type::Promise(Box::new(lower(inner))),
+                Type::Linear { inner_type } => Type::Linear { inner_type: Box::new(lower(inner_type)) },
+                Type::Capability { inner_type, capability } => Type::Capability {
+                    inner_type: Box::new(lower(inner_type)),
+                    capability: capability.clone(),
+                },
+                other => other.clone(),
+            }
+        }
+        self.map_types(lower)
+    }
+
+    /// Copies the module with every signature, local, and call type mapped
+    fn map_types(&self, map: impl Fn(&Type) -> Type) -> WasmModule {
+        let lower_signature = |signature: &mut Signature| {
+            for param in &mut signature.params {
+                *param = map(param);
+            }
+            if let Some(returns) = &mut signature.returns {
+                *returns = map(returns);
+            }
+        };
+
+        let mut module = self.clone();
+        for function in &mut module.functions {
+            lower_signature(&mut function.signature);
+            for local in &mut function.locals {
+                *local = map(local);
+            }
+            for block in &mut function.basic_blocks {
+                for instruction in &mut block.instructions {
+                    if let Instruction::JSMethodCall { return_type: Some(ty), .. } = instruction {
+                        *ty = map(ty);
+                    }
+                }
+            }
+        }
+        for import in &mut module.imports {
+            let ImportKind::Function(signature) = &mut import.kind;
+            lower_signature(signature);
+        }
+        module
+    }
+
+    /// Checks whether the module registers Rust closures with the host
+    pub fn uses_closures(&self) -> bool {
+        self.imports.iter().any(|import| import.module == JS_IMPORT_MODULE && import.name == CLOSURE_NEW_IMPORT)
+    }
+
+    /// Checks whether externrefs cross the boundary as externref table handles
+    ///
+    /// Rust code holds `ExternRef<T>` as an `i32` slot index, so modules
+    /// linking the table's runtime imports pass every `Type::ExternRef`
+    /// value to and from the host as a handle rather than a reference, as
+    /// do modules whose interop signatures pass `InteropType::ExternRef`.
+    pub fn uses_externref_table(&self) -> bool {
+        let passes_objects = |interop: &InteropSignature| {
+            interop.returns == InteropType::ExternRef || interop.params.contains(&InteropType::ExternRef)
+        };
+        self.imports.iter().any(|import| {
+            import.module == JS_IMPORT_MODULE
+                && (import.name == EXTERNREF_CLONE_IMPORT || import.name == EXTERNREF_RELEASE_IMPORT)
+        }) || self.interop_signatures.values().any(passes_objects)
+            || self.import_interop_signatures.values().any(passes_objects)
+    }
+
+    /// Finds a function index by name
+    pub fn function_index(&self, name: &str) -> Option<u32> {
+        self.functions.iter().position(|f| f.name == name).map(|i| i as u32)
+    }
+
+    /// Runs the full verifier on every function, checking calls against callees
+    pub fn verify(&self) -> Result<(), Vec<verifier::Diagnostic>> {
+        let diagnostics = verifier::verify_module(self);
+        if diagnostics.is_empty() { Ok(()) } else { Err(diagnostics) }
+    }
+
+    /// Validates module-level declarations and every function
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for function in &self.functions {
+            function.validate()?;
+            for instruction in function.all_instructions() {
+                match instruction {
+                    Instruction::Call { func_ref, .. } => self.check_function_index(*func_ref)?,
+                    Instruction::CallImport { import, .. } if *import as usize >= self.imports.len() => {
+                        return Err(ValidationError::InvalidImport(format!("unknown import {}", import)));
+                    }
+                    Instruction::GlobalSet { index, .. }
+                        if !self.globals.get(*index as usize).is_some_and(|global| global.mutable) =>
+                    {
+                        return Err(ValidationError::InvalidGlobal(format!("global {} is not mutable", index)));
+                    }
+                    Instruction::ThreadLocalAddress { index } if *index as usize >= self.thread_locals.len() => {
+                        return Err(ValidationError::InvalidThreadLocal(format!("unknown thread local {}", index)));
+                    }
+                    _ => {}
+                }
+            }
+            let operands = function.basic_blocks.iter()
+                .flat_map(|block| block.instructions.iter().flat_map(features::instruction_operands)
+                    .chain(features::terminator_operands(&block.terminator)));
+            for operand in operands {
+                if let Operand::Global(index) = operand {
+                    if *index as usize >= self.globals.len() {
+                        return Err(ValidationError::InvalidGlobal(format!("unknown global {}", index)));
+                    }
+                }
+            }
+        }
+
+        for export in &self.exports {
+            match export.kind {
+                ExportKind::Function(index) => self.check_function_index(index)?,
+                ExportKind::Memory(_) => {
+                    if self.memory.is_none() {
+                        return Err(ValidationError::ControlFlowError("memory export without memory"));
+                    }
+                }
+            }
+        }
+
+        for (&index, interop) in &self.interop_signatures {
+            self.check_function_index(index)?;
+            let signature = &self.functions[index as usize].signature;
+            let borrowed_result = matches!(interop.returns, InteropType::Slice(_) | InteropType::SliceMut(_));
+            if interop.lowered_param_count() != signature.params.len()
+                || (interop.has_return_area() && signature.returns.is_some())
+                || borrowed_result
+            {
+                return Err(ValidationError::InvalidInterop(format!(
+                    "interop signature does not match function {}",
+                    index
+                )));
+            }
+        }
+
+        for (&index, interop) in &self.import_interop_signatures {
+            let signature = self.imports.get(index as usize).map(Import::signature);
+            let signature = signature.ok_or_else(|| ValidationError::InvalidImport(format!("unknown import {}", index)))?;
+            let moves_vector = interop.params.iter().any(|param| matches!(param, InteropType::Vec(_)));
+            let borrowed_result = matches!(interop.returns, InteropType::Slice(_) | InteropType::SliceMut(_));
+            if interop.lowered_param_count() != signature.params.len()
+                || (interop.has_return_area() && signature.returns.is_some())
+                || moves_vector
+                || borrowed_result
+            {
+                return Err(ValidationError::InvalidInterop(format!(
+                    "interop signature does not match import {}",
+                    index
+                )));
+            }
+        }
+
+        let exports_strings = self.exports.iter().any(|export| match export.kind {
+            ExportKind::Function(index) => self.interop_signature(index).is_some_and(InteropSignature::needs_marshaling),
+            ExportKind::Memory(_) => false,
+        });
+        let imports_strings = self.import_interop_signatures.values().any(InteropSignature::needs_marshaling);
+        if exports_strings || imports_strings {
+            if self.memory.is_none() {
+                return Err(ValidationError::InvalidInterop("marshaling requires linear memory".to_string()));
+            }
+            for name in [ALLOC_EXPORT, FREE_EXPORT] {
+                if !self.exports.iter().any(|export| export.name == name) {
+                    return Err(ValidationError::InvalidInterop(format!("marshaling requires {}", name)));
+                }
+            }
+        }
+
+        if !self.data_segments.is_empty() {
+            // Active segments would be copied again into memory every thread's instance shares
+            if self.shared_memory {
+                return Err(ValidationError::InvalidData("data segments in shared memory".to_string()));
+            }
+            // or over memory the module it is imported from already uses
+            if self.import_memory {
+                return Err(ValidationError::InvalidData("data segments in imported memory".to_string()));
+            }
+            let size = self.memory.map_or(0, |memory| memory.min_pages as u64 * 65536);
+            if let Some(segment) = self.data_segments.iter().find(|segment| segment.end() > size) {
+                return Err(ValidationError::InvalidData(format!(
+                    "segment at {} ends past the initial memory of {} bytes",
+                    segment.offset, size
+                )));
+            }
+        }
+
+        for &index in &self.cold_functions {
+            self.check_function_index(index)?;
+        }
+
+        let initializers = self.start_function.iter()
+            .chain(self.constructors.iter().map(|ctor| &ctor.function));
+        for &index in initializers {
+            self.check_function_index(index)?;
+            let signature = &self.functions[index as usize].signature;
+            if !signature.params.is_empty() || signature.returns.is_some() {
+                return Err(ValidationError::InvalidInitializer(index));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_function_index(&self, index: u32) -> Result<(), ValidationError> {
+        if (index as usize) < self.functions.len() {
+            Ok(())
+        } else {
+            Err(ValidationError::InvalidFunctionIndex(index))
+        }
+    }
+}
+
 /// Validation errors for WasmIR
 #[derive(Debug, Clone, PartialEq)]
 pub enum ValidationError {
     /// Invalid local variable index
     InvalidLocalIndex(u32),
-    
+
     /// Invalid basic block ID
     InvalidBlockId(&'static str),
+
+    /// Function index outside the module
+    InvalidFunctionIndex(u32),
+
+    /// Start function or constructor with a non-`() -> ()` signature
+    InvalidInitializer(u32),
+
+    /// Missing or inconsistent host import
+    InvalidImport(String),
+
+    /// Interop signature inconsistent with the module
+    InvalidInterop(String),
+
+    /// Unknown global, or a write to an immutable one
+    InvalidGlobal(String),
+
+    /// Unknown thread local
+    InvalidThreadLocal(String),
+
+    /// Data segment outside the initial memory
+    InvalidData(String),
     
     /// Type mismatch error
     TypeMismatch { expected: Type, actual: Type },
@@ -671,6 +1768,18 @@ pub enum ValidationError {
     
     /// Capability violation
     CapabilityViolation(Capability),
+
+    /// Local read on some path before any write to it
+    UseBeforeDef(u32),
+
+    /// Linear local read again on some path after being consumed
+    LinearReuse(u32),
+
+    /// Call with a different number of arguments than the callee's parameters
+    ArgumentCount { expected: usize, actual: usize },
+
+    /// Operation applied to a type it is not defined for
+    UnsupportedOperandType { operation: String, ty: Type },
 }
 
 impl fmt::Display for ValidationError {
@@ -678,11 +1787,28 @@ impl fmt::Display for ValidationError {
         match self {
             ValidationError::InvalidLocalIndex(idx) => write!(f, "Invalid local index: {}", idx),
             ValidationError::InvalidBlockId(desc) => write!(f, "Invalid block ID: {}", desc),
+            ValidationError::InvalidFunctionIndex(idx) => write!(f, "Invalid function index: {}", idx),
+            ValidationError::InvalidImport(msg) => write!(f, "Invalid import: {}", msg),
+            ValidationError::InvalidInterop(msg) => write!(f, "Invalid interop signature: {}", msg),
+            ValidationError::InvalidGlobal(msg) => write!(f, "Invalid global: {}", msg),
+            ValidationError::InvalidThreadLocal(msg) => write!(f, "Invalid thread local: {}", msg),
+            ValidationError::InvalidData(msg) => write!(f, "Invalid data segment: {}", msg),
+            ValidationError::InvalidInitializer(idx) => {
+                write!(f, "Initializer function {} must take no parameters and return nothing", idx)
+            }
             ValidationError::TypeMismatch { expected, actual } => {
                 write!(f, "Type mismatch: expected {:?}, got {:?}", expected, actual)
             }
             ValidationError::ControlFlowError(msg) => write!(f, "Control flow error: {}", msg),
             ValidationError::CapabilityViolation(cap) => write!(f, "Capability violation: {:?}", cap),
+            ValidationError::UseBeforeDef(idx) => write!(f, "Local {} may be read before it is written", idx),
+            ValidationError::LinearReuse(idx) => write!(f, "Linear local {} may be used after it was consumed", idx),
+            ValidationError::ArgumentCount { expected, actual } => {
+                write!(f, "Expected {} arguments, got {}", expected, actual)
+            }
+            ValidationError::UnsupportedOperandType { operation, ty } => {
+                write!(f, "{} does not accept {:?}", operation, ty)
+            }
         }
     }
 }
@@ -862,4 +1988,199 @@ mod tests {
         assert!(used_locals.contains_key(&local2));
         assert!(!used_locals.contains_key(&local3)); // local3 is never used
     }
+
+    #[test]
+    fn test_module_constructor_order() {
+        let mut module = WasmModule::new();
+        let void = Signature { params: vec![], returns: None };
+        let first = module.add_function(WasmIR::new("init_a".to_string(), void.clone()));
+        let second = module.add_function(WasmIR::new("init_b".to_string(), void.clone()));
+        let third = module.add_function(WasmIR::new("init_c".to_string(), void));
+
+        module.add_constructor(first, 100);
+        module.add_constructor(second, 10);
+        module.add_constructor(third, 100);
+
+        assert_eq!(module.constructors_in_order(), vec![second, first, third]);
+        assert_eq!(module.function_index("init_c"), Some(third));
+        assert!(module.validate().is_ok());
+    }
+
+    #[test]
+    fn test_module_rejects_invalid_initializer() {
+        let mut module = WasmModule::new();
+        let index = module.add_function(WasmIR::new("takes_arg".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: None,
+        }));
+
+        module.set_start_function(index);
+        assert_eq!(module.validate(), Err(ValidationError::InvalidInitializer(index)));
+
+        module.start_function = Some(7);
+        assert_eq!(module.validate(), Err(ValidationError::InvalidFunctionIndex(7)));
+    }
+
+    #[test]
+    fn test_declare_js_imports() {
+        let mut function = WasmIR::new("greet".to_string(), Signature {
+            params: vec![Type::ExternRef("Console".to_string()), Type::I32],
+            returns: None,
+        });
+        function.add_basic_block(vec![
+            Instruction::JSMethodCall {
+                object: Operand::Local(0),
+                method: "log".to_string(),
+                args: vec![Operand::Local(1)],
+                return_type: None,
+            },
+            Instruction::ExternRefLoad {
+                externref: Operand::Local(0),
+                field: "length".to_string(),
+                field_type: Type::I32,
+            },
+        ], Terminator::Return { value: None });
+
+        let mut module = WasmModule::new();
+        module.add_function(function);
+        module.declare_js_imports().unwrap();
+
+        let log = module.find_import(JS_IMPORT_MODULE, &Import::js_method_name("log")).unwrap();
+        assert_eq!(module.imports[log as usize].signature().params, vec![
+            Type::ExternRef("Console".to_string()),
+            Type::I32,
+        ]);
+        assert!(module.find_import(JS_IMPORT_MODULE, "js_get:length").is_some());
+
+        // Declaring again is idempotent
+        module.declare_js_imports().unwrap();
+        assert_eq!(module.imports.len(), 2);
+    }
+
+    #[test]
+    fn test_string_exports_require_allocator() {
+        let mut module = WasmModule::new();
+        let greet = module.add_function(WasmIR::new("greet".to_string(), Signature {
+            params: vec![Type::I32, Type::I32, Type::I32],
+            returns: None,
+        }));
+        module.export_function("greet", greet);
+        module.set_interop_signature(greet, InteropSignature {
+            params: vec![InteropType::Str],
+            returns: InteropType::Str,
+        });
+        assert_eq!(module.interop_signature(greet).unwrap().lowered_param_count(), 3);
+        assert!(matches!(module.validate(), Err(ValidationError::InvalidInterop(_))));
+
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        for name in [ALLOC_EXPORT, FREE_EXPORT] {
+            let index = module.add_function(WasmIR::new(name.to_string(), Signature {
+                params: vec![Type::I32, Type::I32],
+                returns: Some(Type::I32),
+            }));
+            module.export_function(name, index);
+        }
+        assert!(module.validate().is_ok());
+    }
+
+    #[test]
+    fn test_slice_interop_signature() {
+        let signature = InteropSignature {
+            params: vec![InteropType::Slice(ElementType::F32), InteropType::Value],
+            returns: InteropType::Vec(ElementType::U8),
+        };
+        assert!(signature.needs_marshaling());
+        assert_eq!(signature.lowered_param_count(), 4);
+        assert_eq!(ElementType::F32.typed_array(), "Float32Array");
+
+        // Borrowed slices cannot outlive the call that returns them
+        let mut module = WasmModule::new();
+        let index = module.add_function(WasmIR::new("view".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: None,
+        }));
+        module.set_interop_signature(index, InteropSignature {
+            params: Vec::new(),
+            returns: InteropType::Slice(ElementType::U8),
+        });
+        assert!(matches!(module.validate(), Err(ValidationError::InvalidInterop(_))));
+    }
+
+    #[test]
+    fn test_import_interop_signatures() {
+        let mut module = WasmModule::new();
+        let log = module.add_import("env", "log", Signature { params: vec![Type::I32, Type::I32, Type::I32], returns: None });
+        module.set_import_interop_signature(log, InteropSignature {
+            params: vec![InteropType::Slice(ElementType::U8), InteropType::Value],
+            returns: InteropType::Value,
+        });
+        // Imports with strings need the allocator like exports do
+        assert!(matches!(module.validate(), Err(ValidationError::InvalidInterop(_))));
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        for name in [ALLOC_EXPORT, FREE_EXPORT] {
+            let index = module.add_function(WasmIR::new(name.to_string(), Signature {
+                params: vec![Type::I32, Type::I32],
+                returns: Some(Type::I32),
+            }));
+            module.export_function(name, index);
+        }
+        assert!(module.validate().is_ok());
+
+        // Vectors cannot move into the host
+        module.set_import_interop_signature(log, InteropSignature {
+            params: vec![InteropType::Vec(ElementType::U8), InteropType::Value],
+            returns: InteropType::Value,
+        });
+        assert!(matches!(module.validate(), Err(ValidationError::InvalidInterop(_))));
+        module.set_import_interop_signature(7, InteropSignature::default());
+        assert!(module.validate().is_err());
+    }
+
+    #[test]
+    fn test_lower_promises() {
+        let promise = Type::Promise(Box::new(Type::F64));
+        let mut module = WasmModule::new();
+        module.add_import(JS_IMPORT_MODULE, Import::js_method_name("fetch"), Signature {
+            params: vec![Type::ExternRef(String::new())],
+            returns: Some(promise.clone()),
+        });
+        module.add_function(WasmIR::new("load".to_string(), Signature {
+            params: vec![],
+            returns: Some(promise.clone()),
+        }));
+        assert!(module.uses_promises());
+
+        let lowered = module.lower_promises();
+        assert!(!lowered.uses_promises());
+        assert_eq!(lowered.imports[0].signature().returns, Some(Type::I32));
+        assert_eq!(lowered.functions[0].signature.returns, Some(Type::I32));
+
+        module.promise_lowering = PromiseLowering::Jspi;
+        assert_eq!(module.lower_promises().imports[0].signature().returns, Some(Type::F64));
+        assert_eq!(
+            PromiseLowering::Jspi.lower(&Type::Linear { inner_type: Box::new(promise) }),
+            Type::Linear { inner_type: Box::new(Type::F64) }
+        );
+    }
+
+    #[test]
+    fn test_lower_externref_handles() {
+        let node = Type::ExternRef("Node".to_string());
+        let mut module = WasmModule::new();
+        module.add_import(JS_IMPORT_MODULE, Import::js_getter_name("firstChild"), Signature {
+            params: vec![node.clone()],
+            returns: Some(node.clone()),
+        });
+        assert!(!module.uses_externref_table());
+
+        module.add_import(JS_IMPORT_MODULE, EXTERNREF_RELEASE_IMPORT, Signature {
+            params: vec![Type::I32],
+            returns: None,
+        });
+        assert!(module.uses_externref_table());
+
+        let lowered = module.lower_externref_handles();
+        assert_eq!(lowered.imports[0].signature().params, vec![Type::I32]);
+        assert_eq!(lowered.imports[0].signature().returns, Some(Type::I32));
+    }
 }
@@ -0,0 +1,434 @@
+//! Control-flow analyses over basic blocks
+//!
+//! Block 0 is the entry. Edges to blocks that do not exist are ignored
+//! here, since `WasmIR::validate` reports them; blocks unreachable from the
+//! entry have no dominator and are in no loop, but still get liveness sets.
+//!
+//! ```
+//! use wasm::wasmir::analysis::{natural_loops, Cfg, DominatorTree, Liveness};
+//! # use wasm::wasmir::{BlockId, Signature, Terminator, WasmIR};
+//! # let mut function = WasmIR::new("f".into(), Signature { params: vec![], returns: None });
+//! # function.add_basic_block(vec![], Terminator::Return { value: None });
+//! let cfg = Cfg::new(&function);
+//! let dominators = DominatorTree::new(&cfg);
+//! let liveness = Liveness::new(&function, &cfg);
+//! let loops = natural_loops(&cfg, &dominators);
+//! # assert!(dominators.dominates(BlockId(0), BlockId(0)));
+//! # assert!(liveness.live_in(BlockId(0)).is_empty() && loops.is_empty());
+//! ```
+
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::features::{instruction_operands, operand_locals, terminator_operands};
+use super::{BlockId, Instruction, Terminator, WasmIR};
+
+/// Blocks a terminator can transfer control to, in order and with repeats
+pub fn successors(terminator: &Terminator) -> Vec<BlockId> {
+    match terminator {
+        Terminator::Jump { target } => vec![*target],
+        Terminator::Branch { then_block, else_block, .. } => vec![*then_block, *else_block],
+        Terminator::Switch { targets, default_target, .. } => {
+            targets.iter().map(|(_, target)| *target).chain(core::iter::once(*default_target)).collect()
+        }
+        Terminator::Return { .. } | Terminator::Unreachable | Terminator::Panic { .. } => Vec::new(),
+    }
+}
+
+/// Successor and predecessor edges of a function
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    successors: Vec<Vec<BlockId>>,
+    predecessors: Vec<Vec<BlockId>>,
+    /// Reachable blocks in reverse postorder from the entry
+    order: Vec<BlockId>,
+    /// Position of each block in `order`
+    position: Vec<Option<usize>>,
+}
+
+impl Cfg {
+    pub fn new(function: &WasmIR) -> Self {
+        let count = function.basic_blocks.len();
+        let mut successors = vec![Vec::new(); count];
+        let mut predecessors = vec![Vec::new(); count];
+        for (index, block) in function.basic_blocks.iter().enumerate() {
+            for target in successors_of(&block.terminator, count) {
+                if !successors[index].contains(&target) {
+                    successors[index].push(target);
+                    predecessors[target.0].push(BlockId(index));
+                }
+            }
+        }
+
+        // Iterative depth-first search, recording blocks once all their
+        // successors are done
+        let mut order = Vec::with_capacity(count);
+        let mut visited = vec![false; count];
+        let mut stack = Vec::new();
+        if count > 0 {
+            visited[0] = true;
+            stack.push((BlockId(0), 0));
+        }
+        while let Some((block, next)) = stack.last_mut() {
+            match successors[block.0].get(*next) {
+                Some(&target) => {
+                    *next += 1;
+                    if !visited[target.0] {
+                        visited[target.0] = true;
+                        stack.push((target, 0));
+                    }
+                }
+                None => {
+                    order.push(*block);
+                    stack.pop();
+                }
+            }
+        }
+        order.reverse();
+
+        let mut position = vec![None; count];
+        for (index, block) in order.iter().enumerate() {
+            position[block.0] = Some(index);
+        }
+        Self { successors, predecessors, order, position }
+    }
+
+    /// Number of blocks, reachable or not
+    pub fn len(&self) -> usize {
+        self.successors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.successors.is_empty()
+    }
+
+    /// Distinct successors of a block
+    pub fn successors(&self, block: BlockId) -> &[BlockId] {
+        &self.successors[block.0]
+    }
+
+    /// Distinct predecessors of a block, including unreachable ones
+    pub fn predecessors(&self, block: BlockId) -> &[BlockId] {
+        &self.predecessors[block.0]
+    }
+
+    /// Reachable blocks, each after all its predecessors except along back edges
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.order
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.position.get(block.0).is_some_and(Option::is_some)
+    }
+}
+
+fn successors_of(terminator: &Terminator, count: usize) -> impl Iterator<Item = BlockId> {
+    successors(terminator).into_iter().filter(move |target| target.0 < count)
+}
+
+/// Immediate dominators of the reachable blocks
+///
+/// Computed with the iterative algorithm of Cooper, Harvey, and Kennedy,
+/// which converges in a couple of passes over the reverse postorder.
+#[derive(Debug, Clone)]
+pub struct DominatorTree {
+    /// Immediate dominator of each block; the entry is its own
+    idom: Vec<Option<BlockId>>,
+}
+
+impl DominatorTree {
+    pub fn new(cfg: &Cfg) -> Self {
+        let mut idom = vec![None; cfg.len()];
+        let Some(&entry) = cfg.reverse_postorder().first() else {
+            return Self { idom };
+        };
+        idom[entry.0] = Some(entry);
+
+        let position = |block: BlockId| cfg.position[block.0].unwrap_or(usize::MAX);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &cfg.reverse_postorder()[1..] {
+                let mut processed = cfg.predecessors(block).iter().filter(|pred| idom[pred.0].is_some());
+                let Some(&first) = processed.next() else { continue };
+                let mut dominator = first;
+                for &pred in processed {
+                    // Walk both fingers up the tree until they meet
+                    let mut other = pred;
+                    while dominator != other {
+                        while position(dominator) > position(other) {
+                            dominator = idom[dominator.0].unwrap_or(entry);
+                        }
+                        while position(other) > position(dominator) {
+                            other = idom[other.0].unwrap_or(entry);
+                        }
+                    }
+                }
+                if idom[block.0] != Some(dominator) {
+                    idom[block.0] = Some(dominator);
+                    changed = true;
+                }
+            }
+        }
+        Self { idom }
+    }
+
+    /// Immediate dominator, or `None` for the entry and unreachable blocks
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.idom.get(block.0).copied().flatten().filter(|&idom| idom != block)
+    }
+
+    /// Whether every path from the entry to `block` passes through `dominator`
+    ///
+    /// A block dominates itself; nothing dominates an unreachable block.
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        if self.idom.get(block.0).copied().flatten().is_none() {
+            return false;
+        }
+        let mut current = block;
+        loop {
+            if current == dominator {
+                return true;
+            }
+            match self.immediate_dominator(current) {
+                Some(idom) => current = idom,
+                None => return false,
+            }
+        }
+    }
+
+    /// Blocks immediately dominated by `block`, in index order
+    pub fn children(&self, block: BlockId) -> Vec<BlockId> {
+        (0..self.idom.len())
+            .map(BlockId)
+            .filter(|&child| self.immediate_dominator(child) == Some(block))
+            .collect()
+    }
+}
+
+/// Locals live on entry to and exit from each block
+///
+/// A local is live if some path reads it before writing it. Only
+/// `LocalSet` writes a local, so a local written by a call result through
+/// the stack is still read-before-write until that `LocalSet`.
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    live_in: Vec<BTreeSet<u32>>,
+    live_out: Vec<BTreeSet<u32>>,
+}
+
+impl Liveness {
+    pub fn new(function: &WasmIR, cfg: &Cfg) -> Self {
+        let count = function.basic_blocks.len();
+        let mut uses = vec![BTreeSet::new(); count];
+        let mut defs = vec![BTreeSet::new(); count];
+        for (index, block) in function.basic_blocks.iter().enumerate() {
+            let (uses, defs) = (&mut uses[index], &mut defs[index]);
+            for instruction in &block.instructions {
+                let mut read = operand_locals(&instruction_operands(instruction));
+                if let Instruction::LocalGet { index } = instruction {
+                    read.push(*index);
+                }
+                uses.extend(read.into_iter().filter(|local| !defs.contains(local)));
+                if let Instruction::LocalSet { index, .. } = instruction {
+                    defs.insert(*index);
+                }
+            }
+            let read = operand_locals(&terminator_operands(&block.terminator));
+            uses.extend(read.into_iter().filter(|local| !defs.contains(local)));
+        }
+
+        let mut live_in = uses.clone();
+        let mut live_out = vec![BTreeSet::new(); count];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            // Postorder first, so most successors are done before their predecessors
+            let order = cfg.reverse_postorder().iter().rev().map(|block| block.0);
+            let unreachable = (0..count).filter(|&index| !cfg.is_reachable(BlockId(index)));
+            for index in order.chain(unreachable) {
+                let out: BTreeSet<u32> = cfg.successors(BlockId(index)).iter()
+                    .flat_map(|succ| live_in[succ.0].iter().copied())
+                    .collect();
+                let mut inn = uses[index].clone();
+                inn.extend(out.difference(&defs[index]).copied());
+                if inn != live_in[index] {
+                    live_in[index] = inn;
+                    changed = true;
+                }
+                live_out[index] = out;
+            }
+        }
+        Self { live_in, live_out }
+    }
+
+    pub fn live_in(&self, block: BlockId) -> &BTreeSet<u32> {
+        &self.live_in[block.0]
+    }
+
+    pub fn live_out(&self, block: BlockId) -> &BTreeSet<u32> {
+        &self.live_out[block.0]
+    }
+}
+
+/// Natural loop: a header and the blocks reaching its back edges without passing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub header: BlockId,
+    /// Sources of the back edges to the header
+    pub latches: Vec<BlockId>,
+    /// Blocks of the loop, header included, in index order
+    pub blocks: Vec<BlockId>,
+}
+
+impl Loop {
+    pub fn contains(&self, block: BlockId) -> bool {
+        self.blocks.binary_search_by_key(&block.0, |b| b.0).is_ok()
+    }
+}
+
+/// Natural loops of a function, one per header, ordered by header index
+///
+/// A back edge is an edge to a block dominating its source. Cycles entered
+/// at more than one block have no such edge and are not reported.
+pub fn natural_loops(cfg: &Cfg, dominators: &DominatorTree) -> Vec<Loop> {
+    let mut loops: Vec<Loop> = Vec::new();
+    for &latch in cfg.reverse_postorder() {
+        for &header in cfg.successors(latch) {
+            if !dominators.dominates(header, latch) {
+                continue;
+            }
+            match loops.iter_mut().find(|l| l.header == header) {
+                Some(existing) => existing.latches.push(latch),
+                None => loops.push(Loop { header, latches: vec![latch], blocks: Vec::new() }),
+            }
+        }
+    }
+
+    for l in &mut loops {
+        let mut body = BTreeSet::from([l.header.0]);
+        let mut work: Vec<BlockId> = l.latches.clone();
+        while let Some(block) = work.pop() {
+            if body.insert(block.0) {
+                work.extend(cfg.predecessors(block).iter().filter(|pred| cfg.is_reachable(**pred)));
+            }
+        }
+        l.latches.sort_by_key(|b| b.0);
+        l.blocks = body.into_iter().map(BlockId).collect();
+    }
+    loops.sort_by_key(|l| l.header.0);
+    loops
+}
+
+/// Number of loops containing a block
+pub fn loop_depth(loops: &[Loop], block: BlockId) -> usize {
+    loops.iter().filter(|l| l.contains(block)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::{BinaryOp, Constant, Operand, Signature, Type};
+    use alloc::string::ToString;
+
+    fn jump(target: usize) -> Terminator {
+        Terminator::Jump { target: BlockId(target) }
+    }
+
+    fn branch(condition: Operand, then_block: usize, else_block: usize) -> Terminator {
+        Terminator::Branch { condition, then_block: BlockId(then_block), else_block: BlockId(else_block) }
+    }
+
+    fn function(blocks: Vec<(Vec<Instruction>, Terminator)>) -> WasmIR {
+        let mut f = WasmIR::new("f".to_string(), Signature { params: vec![Type::I32], returns: None });
+        f.locals = vec![Type::I32; 3];
+        for (instructions, terminator) in blocks {
+            f.add_basic_block(instructions, terminator);
+        }
+        f
+    }
+
+    fn ids(blocks: &[usize]) -> Vec<BlockId> {
+        blocks.iter().copied().map(BlockId).collect()
+    }
+
+    #[test]
+    fn test_dominators_of_a_diamond() {
+        // 0 -> {1, 2} -> 3, with 4 unreachable and jumping into 3
+        let f = function(vec![
+            (vec![], branch(Operand::Local(0), 1, 2)),
+            (vec![], jump(3)),
+            (vec![], jump(3)),
+            (vec![], Terminator::Return { value: None }),
+            (vec![], jump(3)),
+        ]);
+        let cfg = Cfg::new(&f);
+        assert_eq!(cfg.reverse_postorder()[0], BlockId(0));
+        assert_eq!(cfg.reverse_postorder().last(), Some(&BlockId(3)));
+        assert_eq!(cfg.predecessors(BlockId(3)), ids(&[1, 2, 4]).as_slice());
+        assert!(!cfg.is_reachable(BlockId(4)));
+
+        let dom = DominatorTree::new(&cfg);
+        assert_eq!(dom.immediate_dominator(BlockId(0)), None);
+        assert_eq!(dom.immediate_dominator(BlockId(3)), Some(BlockId(0)));
+        assert_eq!(dom.immediate_dominator(BlockId(4)), None);
+        assert!(dom.dominates(BlockId(0), BlockId(3)));
+        assert!(!dom.dominates(BlockId(1), BlockId(3)));
+        assert!(!dom.dominates(BlockId(4), BlockId(4)));
+        assert_eq!(dom.children(BlockId(0)), ids(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn test_nested_loops_and_depth() {
+        // 0 -> 1 (outer header) -> 2 (inner header) <-> 3, 2 -> 4 -> 1, 1 -> 5
+        let f = function(vec![
+            (vec![], jump(1)),
+            (vec![], branch(Operand::Local(0), 2, 5)),
+            (vec![], branch(Operand::Local(0), 3, 4)),
+            (vec![], jump(2)),
+            (vec![], jump(1)),
+            (vec![], Terminator::Return { value: None }),
+        ]);
+        let cfg = Cfg::new(&f);
+        let loops = natural_loops(&cfg, &DominatorTree::new(&cfg));
+        assert_eq!(loops, vec![
+            Loop { header: BlockId(1), latches: ids(&[4]), blocks: ids(&[1, 2, 3, 4]) },
+            Loop { header: BlockId(2), latches: ids(&[3]), blocks: ids(&[2, 3]) },
+        ]);
+        assert_eq!(loop_depth(&loops, BlockId(3)), 2);
+        assert_eq!(loop_depth(&loops, BlockId(4)), 1);
+        assert_eq!(loop_depth(&loops, BlockId(5)), 0);
+    }
+
+    #[test]
+    fn test_liveness_around_a_loop() {
+        // local 1 is a counter carried around the loop, local 2 a temporary
+        let f = function(vec![
+            (vec![Instruction::LocalSet { index: 1, value: Operand::Constant(Constant::I32(0)) }], jump(1)),
+            (
+                vec![
+                    Instruction::BinaryOp { op: BinaryOp::Lt, left: Operand::Local(1), right: Operand::Local(0) },
+                    Instruction::LocalSet { index: 2, value: Operand::StackValue(0) },
+                ],
+                branch(Operand::Local(2), 2, 3),
+            ),
+            (
+                vec![
+                    Instruction::LocalGet { index: 1 },
+                    Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::StackValue(0), right: Operand::Constant(Constant::I32(1)) },
+                    Instruction::LocalSet { index: 1, value: Operand::StackValue(0) },
+                ],
+                jump(1),
+            ),
+            (vec![], Terminator::Return { value: None }),
+        ]);
+        let cfg = Cfg::new(&f);
+        let liveness = Liveness::new(&f, &cfg);
+        assert_eq!(liveness.live_in(BlockId(0)), &BTreeSet::from([0]));
+        assert_eq!(liveness.live_in(BlockId(1)), &BTreeSet::from([0, 1]));
+        assert_eq!(liveness.live_out(BlockId(1)), &BTreeSet::from([0, 1]));
+        assert_eq!(liveness.live_in(BlockId(2)), &BTreeSet::from([0, 1]));
+        assert!(liveness.live_in(BlockId(3)).is_empty());
+    }
+}
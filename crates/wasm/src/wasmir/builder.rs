@@ -0,0 +1,607 @@
+//! Structured construction of WasmIR functions
+//!
+//! `FunctionBuilder` keeps every intermediate result in a local of its own
+//! and only ever uses a stack value as the operand of the `local.set` right
+//! after the instruction producing it, so built code is stack-valid for any
+//! backend. Values are typed handles: adding an `i32` to an `i64`, or
+//! branching on a float, does not compile. A block is built through a
+//! `BlockBuilder` whose terminator methods consume it, so no block ends
+//! twice; `finish` reports blocks never built and type errors the handles
+//! cannot catch, such as a `ret` of the wrong type.
+//!
+//! ```
+//! use wasm::wasmir::builder::{FunctionBuilder, I64};
+//!
+//! let mut f = FunctionBuilder::new("fact");
+//! let n = f.param::<I64>();
+//! f.returns::<I64>();
+//! let acc = f.var::<I64>();
+//! let (head, body, exit) = (f.new_block(), f.new_block(), f.new_block());
+//!
+//! let mut b = f.block(f.entry());
+//! b.set(acc, 1i64);
+//! b.jump(head);
+//!
+//! let mut b = f.block(head);
+//! let more = b.gt(n, 1i64);
+//! b.branch(more, body, exit);
+//!
+//! let mut b = f.block(body);
+//! let product = b.mul(acc, n);
+//! b.set(acc, product);
+//! let next = b.sub(n, 1i64);
+//! b.set(n, next);
+//! b.jump(head);
+//!
+//! f.block(exit).ret(acc);
+//! assert!(f.finish().is_ok());
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use super::{
+    BinaryOp, BlockId, Constant, Instruction, Operand, Signature, Terminator, Type, UnaryOp, ValidationError,
+    WasmIR,
+};
+
+/// Marker for a numeric value type
+pub trait Numeric: Copy {
+    /// Rust type of constants
+    type Native: Copy;
+
+    fn ty() -> Type;
+
+    fn constant(value: Self::Native) -> Constant;
+}
+
+/// Marker for an integer value type
+pub trait Integer: Numeric {}
+
+macro_rules! numeric {
+    ($marker:ident, $native:ty, $integer:tt) => {
+        #[doc = concat!("`", stringify!($native), "` values")]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct $marker;
+
+        impl Numeric for $marker {
+            type Native = $native;
+
+            fn ty() -> Type {
+                Type::$marker
+            }
+
+            fn constant(value: $native) -> Constant {
+                Constant::$marker(value)
+            }
+        }
+
+        impl From<$native> for Value<$marker> {
+            fn from(value: $native) -> Self {
+                Value::new(Slot::$marker(value))
+            }
+        }
+
+        numeric!(@integer $marker $integer);
+    };
+    (@integer $marker:ident integer) => {
+        impl Integer for $marker {}
+    };
+    (@integer $marker:ident float) => {};
+}
+
+numeric!(I32, i32, integer);
+numeric!(I64, i64, integer);
+numeric!(F32, f32, float);
+numeric!(F64, f64, float);
+
+/// Local or constant behind a value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    Local(u32),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+/// Typed handle to a value computed by the function
+///
+/// Constants convert from their Rust type: `Value::<I32>::from(1)`, or just
+/// `1i32` wherever an `impl Into<Value<I32>>` is expected.
+#[derive(Debug, PartialEq)]
+pub struct Value<T> {
+    slot: Slot,
+    ty: PhantomData<T>,
+}
+
+impl<T> Clone for Value<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Value<T> {}
+
+impl<T> Value<T> {
+    fn new(slot: Slot) -> Self {
+        Self { slot, ty: PhantomData }
+    }
+
+    /// Operand reading the value
+    pub fn operand(&self) -> Operand {
+        match self.slot {
+            Slot::Local(index) => Operand::Local(index),
+            Slot::I32(value) => Operand::Constant(Constant::I32(value)),
+            Slot::I64(value) => Operand::Constant(Constant::I64(value)),
+            Slot::F32(value) => Operand::Constant(Constant::F32(value)),
+            Slot::F64(value) => Operand::Constant(Constant::F64(value)),
+        }
+    }
+}
+
+/// Typed handle to a mutable local
+#[derive(Debug, PartialEq, Eq)]
+pub struct Var<T> {
+    index: u32,
+    ty: PhantomData<T>,
+}
+
+impl<T> Clone for Var<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Var<T> {}
+
+impl<T> Var<T> {
+    /// Local index of the variable
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<T> From<Var<T>> for Value<T> {
+    fn from(var: Var<T>) -> Self {
+        Value::new(Slot::Local(var.index))
+    }
+}
+
+/// Errors reported by `FunctionBuilder::finish`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// A parameter was declared after a local was allocated
+    ParamAfterLocal,
+    /// A block was created but never terminated
+    UnterminatedBlock(BlockId),
+    /// A block was built more than once
+    BlockBuiltTwice(BlockId),
+    /// A return does not match the declared result
+    ReturnType { block: BlockId, expected: Option<Type>, actual: Option<Type> },
+    /// The built function failed validation
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ParamAfterLocal => write!(f, "parameter declared after a local"),
+            BuildError::UnterminatedBlock(block) => write!(f, "block {} is never terminated", block.0),
+            BuildError::BlockBuiltTwice(block) => write!(f, "block {} is built twice", block.0),
+            BuildError::ReturnType { block, expected, actual } => write!(
+                f,
+                "block {} returns {:?}, expected {:?}",
+                block.0, actual, expected
+            ),
+            BuildError::Invalid(e) => write!(f, "invalid function: {}", e),
+        }
+    }
+}
+
+/// Builder for one WasmIR function
+pub struct FunctionBuilder {
+    name: String,
+    params: Vec<Type>,
+    returns: Option<Type>,
+    locals: Vec<Type>,
+    blocks: Vec<Option<(Vec<Instruction>, Terminator)>>,
+    errors: Vec<BuildError>,
+}
+
+impl FunctionBuilder {
+    /// Creates a function with no parameters, no result, and an entry block
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            params: Vec::new(),
+            returns: None,
+            locals: Vec::new(),
+            blocks: alloc::vec![None],
+            errors: Vec::new(),
+        }
+    }
+
+    /// Declares the next parameter
+    ///
+    /// Parameters come before every other local, so all of them must be
+    /// declared before the first `var` or block.
+    pub fn param<T: Numeric>(&mut self) -> Var<T> {
+        if self.locals.len() > self.params.len() {
+            self.errors.push(BuildError::ParamAfterLocal);
+        }
+        self.params.push(T::ty());
+        self.allocate(T::ty())
+    }
+
+    /// Declares the result type
+    pub fn returns<T: Numeric>(&mut self) {
+        self.returns = Some(T::ty());
+    }
+
+    /// Allocates a mutable local, zero until set
+    pub fn var<T: Numeric>(&mut self) -> Var<T> {
+        self.allocate(T::ty())
+    }
+
+    /// Block execution starts in
+    pub fn entry(&self) -> BlockId {
+        BlockId(0)
+    }
+
+    /// Creates an empty block, to be built with `block`
+    pub fn new_block(&mut self) -> BlockId {
+        self.blocks.push(None);
+        BlockId(self.blocks.len() - 1)
+    }
+
+    /// Starts building the instructions of a block
+    pub fn block(&mut self, block: BlockId) -> BlockBuilder<'_> {
+        BlockBuilder { function: self, block, instructions: Vec::new() }
+    }
+
+    /// Assembles and validates the function
+    pub fn finish(self) -> Result<WasmIR, BuildError> {
+        if let Some(error) = self.errors.into_iter().next() {
+            return Err(error);
+        }
+        let mut function = WasmIR::new(self.name, Signature { params: self.params, returns: self.returns });
+        function.locals = self.locals;
+        for (index, block) in self.blocks.into_iter().enumerate() {
+            let (instructions, terminator) = block.ok_or(BuildError::UnterminatedBlock(BlockId(index)))?;
+            function.add_basic_block(instructions, terminator);
+        }
+        function.validate().map_err(BuildError::Invalid)?;
+        Ok(function)
+    }
+
+    fn allocate<T>(&mut self, ty: Type) -> Var<T> {
+        self.locals.push(ty);
+        Var { index: self.locals.len() as u32 - 1, ty: PhantomData }
+    }
+}
+
+/// Builder for the instructions of one block, consumed by its terminator
+pub struct BlockBuilder<'f> {
+    function: &'f mut FunctionBuilder,
+    block: BlockId,
+    instructions: Vec<Instruction>,
+}
+
+impl BlockBuilder<'_> {
+    /// Assigns a variable
+    pub fn set<T: Numeric>(&mut self, var: Var<T>, value: impl Into<Value<T>>) {
+        self.instructions.push(Instruction::LocalSet { index: var.index, value: value.into().operand() });
+    }
+
+    pub fn add<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Add, left.into(), right.into())
+    }
+
+    pub fn sub<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Sub, left.into(), right.into())
+    }
+
+    pub fn mul<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Mul, left.into(), right.into())
+    }
+
+    /// Divides, signed for integers
+    pub fn div<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Div, left.into(), right.into())
+    }
+
+    /// Signed remainder
+    pub fn rem<T: Integer>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Mod, left.into(), right.into())
+    }
+
+    pub fn and<T: Integer>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::And, left.into(), right.into())
+    }
+
+    pub fn or<T: Integer>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Or, left.into(), right.into())
+    }
+
+    pub fn xor<T: Integer>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Xor, left.into(), right.into())
+    }
+
+    pub fn shl<T: Integer>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Shl, left.into(), right.into())
+    }
+
+    /// Logical shift right
+    pub fn shr<T: Integer>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Shr, left.into(), right.into())
+    }
+
+    /// Arithmetic shift right
+    pub fn sar<T: Integer>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<T> {
+        self.binary(BinaryOp::Sar, left.into(), right.into())
+    }
+
+    pub fn eq<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<I32> {
+        self.compare(BinaryOp::Eq, left.into(), right.into())
+    }
+
+    pub fn ne<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<I32> {
+        self.compare(BinaryOp::Ne, left.into(), right.into())
+    }
+
+    /// Signed less than for integers
+    pub fn lt<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<I32> {
+        self.compare(BinaryOp::Lt, left.into(), right.into())
+    }
+
+    pub fn le<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<I32> {
+        self.compare(BinaryOp::Le, left.into(), right.into())
+    }
+
+    pub fn gt<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<I32> {
+        self.compare(BinaryOp::Gt, left.into(), right.into())
+    }
+
+    pub fn ge<T: Numeric>(&mut self, left: impl Into<Value<T>>, right: impl Into<Value<T>>) -> Value<I32> {
+        self.compare(BinaryOp::Ge, left.into(), right.into())
+    }
+
+    pub fn neg<T: Numeric>(&mut self, value: impl Into<Value<T>>) -> Value<T> {
+        self.unary(UnaryOp::Neg, value.into())
+    }
+
+    /// Bitwise complement
+    pub fn not<T: Integer>(&mut self, value: impl Into<Value<T>>) -> Value<T> {
+        self.unary(UnaryOp::Not, value.into())
+    }
+
+    pub fn clz<T: Integer>(&mut self, value: impl Into<Value<T>>) -> Value<T> {
+        self.unary(UnaryOp::Clz, value.into())
+    }
+
+    pub fn ctz<T: Integer>(&mut self, value: impl Into<Value<T>>) -> Value<T> {
+        self.unary(UnaryOp::Ctz, value.into())
+    }
+
+    pub fn popcnt<T: Integer>(&mut self, value: impl Into<Value<T>>) -> Value<T> {
+        self.unary(UnaryOp::Popcnt, value.into())
+    }
+
+    /// Loads from linear memory, which the module must declare
+    pub fn load<T: Numeric>(&mut self, address: impl Into<Value<I32>>, offset: u32) -> Value<T> {
+        self.instructions.push(Instruction::MemoryLoad {
+            address: address.into().operand(),
+            ty: T::ty(),
+            align: None,
+            offset,
+        });
+        self.result(T::ty())
+    }
+
+    /// Stores to linear memory, which the module must declare
+    pub fn store<T: Numeric>(&mut self, address: impl Into<Value<I32>>, value: impl Into<Value<T>>, offset: u32) {
+        self.instructions.push(Instruction::MemoryStore {
+            address: address.into().operand(),
+            value: value.into().operand(),
+            ty: T::ty(),
+            align: None,
+            offset,
+        });
+    }
+
+    /// Size of linear memory in pages
+    pub fn memory_size(&mut self) -> Value<I32> {
+        self.instructions.push(Instruction::MemorySize);
+        self.result(Type::I32)
+    }
+
+    /// Address of thread local `index` in the current thread's TLS block
+    pub fn thread_local_address(&mut self, index: u32) -> Value<I32> {
+        self.instructions.push(Instruction::ThreadLocalAddress { index });
+        self.result(Type::I32)
+    }
+
+    /// Grows linear memory by `pages`, producing the previous size or -1
+    pub fn memory_grow(&mut self, pages: impl Into<Value<I32>>) -> Value<I32> {
+        self.instructions.push(Instruction::MemoryGrow { pages: pages.into().operand() });
+        self.result(Type::I32)
+    }
+
+    /// Calls a function of the module returning a `T`
+    ///
+    /// The arguments must match the callee's parameters; the builder has no
+    /// view of the module to check them against.
+    pub fn call<T: Numeric>(&mut self, function: u32, args: impl IntoIterator<Item = Operand>) -> Value<T> {
+        self.call_void(function, args);
+        self.result(T::ty())
+    }
+
+    /// Calls a function of the module without a result
+    pub fn call_void(&mut self, function: u32, args: impl IntoIterator<Item = Operand>) {
+        self.instructions.push(Instruction::Call { func_ref: function, args: args.into_iter().collect() });
+    }
+
+    /// Calls an import returning a `T`
+    pub fn call_import<T: Numeric>(&mut self, import: u32, args: impl IntoIterator<Item = Operand>) -> Value<T> {
+        self.call_import_void(import, args);
+        self.result(T::ty())
+    }
+
+    /// Calls an import without a result
+    pub fn call_import_void(&mut self, import: u32, args: impl IntoIterator<Item = Operand>) {
+        self.instructions.push(Instruction::CallImport { import, args: args.into_iter().collect() });
+    }
+
+    /// Ends the block with a jump
+    pub fn jump(self, target: BlockId) {
+        self.terminate(Terminator::Jump { target });
+    }
+
+    /// Ends the block with a branch on a nonzero integer
+    pub fn branch<T: Integer>(self, condition: impl Into<Value<T>>, then_block: BlockId, else_block: BlockId) {
+        let condition = condition.into().operand();
+        self.terminate(Terminator::Branch { condition, then_block, else_block });
+    }
+
+    /// Ends the block with a jump to the first case equal to `value`
+    pub fn switch<T: Numeric>(
+        self,
+        value: impl Into<Value<T>>,
+        cases: impl IntoIterator<Item = (T::Native, BlockId)>,
+        default_target: BlockId,
+    ) {
+        let value = value.into().operand();
+        let targets = cases.into_iter()
+            .map(|(case, target)| (Operand::Constant(T::constant(case)), target))
+            .collect();
+        self.terminate(Terminator::Switch { value, targets, default_target });
+    }
+
+    /// Ends the block by returning a value
+    pub fn ret<T: Numeric>(mut self, value: impl Into<Value<T>>) {
+        let value = value.into().operand();
+        self.check_return(Some(T::ty()));
+        self.terminate(Terminator::Return { value: Some(value) });
+    }
+
+    /// Ends the block by returning without a value
+    pub fn ret_void(mut self) {
+        self.check_return(None);
+        self.terminate(Terminator::Return { value: None });
+    }
+
+    /// Ends the block with a trap
+    pub fn unreachable(self) {
+        self.terminate(Terminator::Unreachable);
+    }
+
+    fn binary<T: Numeric>(&mut self, op: BinaryOp, left: Value<T>, right: Value<T>) -> Value<T> {
+        self.instructions.push(Instruction::BinaryOp { op, left: left.operand(), right: right.operand() });
+        self.result(T::ty())
+    }
+
+    fn compare<T: Numeric>(&mut self, op: BinaryOp, left: Value<T>, right: Value<T>) -> Value<I32> {
+        self.instructions.push(Instruction::BinaryOp { op, left: left.operand(), right: right.operand() });
+        self.result(Type::I32)
+    }
+
+    fn unary<T: Numeric>(&mut self, op: UnaryOp, value: Value<T>) -> Value<T> {
+        self.instructions.push(Instruction::UnaryOp { op, value: value.operand() });
+        self.result(T::ty())
+    }
+
+    /// Moves the value the last instruction produced into a fresh local
+    fn result<T>(&mut self, ty: Type) -> Value<T> {
+        let var = self.function.allocate::<T>(ty);
+        self.instructions.push(Instruction::LocalSet { index: var.index, value: Operand::StackValue(0) });
+        var.into()
+    }
+
+    fn check_return(&mut self, actual: Option<Type>) {
+        if actual != self.function.returns {
+            self.function.errors.push(BuildError::ReturnType {
+                block: self.block,
+                expected: self.function.returns.clone(),
+                actual,
+            });
+        }
+    }
+
+    fn terminate(self, terminator: Terminator) {
+        let slot = &mut self.function.blocks[self.block.0];
+        if slot.is_some() {
+            self.function.errors.push(BuildError::BlockBuiltTwice(self.block));
+            return;
+        }
+        *slot = Some((self.instructions, terminator));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_builds_valid_functions() {
+        let mut f = FunctionBuilder::new("clamp");
+        let x = f.param::<I32>();
+        let limit = f.param::<I32>();
+        f.returns::<I32>();
+        let (over, under) = (f.new_block(), f.new_block());
+
+        let mut b = f.block(f.entry());
+        let doubled = b.mul(x, 2);
+        let above = b.gt(doubled, limit);
+        b.branch(above, over, under);
+        f.block(over).ret(limit);
+        let mut b = f.block(under);
+        let sum = b.add(doubled, 1);
+        b.ret(sum);
+
+        let function = f.finish().unwrap();
+        assert_eq!(function.signature, Signature { params: vec![Type::I32, Type::I32], returns: Some(Type::I32) });
+        assert_eq!(function.locals, vec![Type::I32; 5]);
+        assert!(matches!(function.basic_blocks[0].instructions.as_slice(), [
+            Instruction::BinaryOp { op: BinaryOp::Mul, left: Operand::Local(0), right: Operand::Constant(Constant::I32(2)) },
+            Instruction::LocalSet { index: 2, value: Operand::StackValue(0) },
+            Instruction::BinaryOp { op: BinaryOp::Gt, left: Operand::Local(2), right: Operand::Local(1) },
+            Instruction::LocalSet { index: 3, value: Operand::StackValue(0) },
+        ]));
+        assert!(matches!(
+            function.basic_blocks[0].terminator,
+            Terminator::Branch { condition: Operand::Local(3), then_block: BlockId(1), else_block: BlockId(2) }
+        ));
+    }
+
+    #[test]
+    fn test_reports_structural_errors() {
+        let mut f = FunctionBuilder::new("unfinished");
+        let exit = f.new_block();
+        f.block(f.entry()).jump(exit);
+        assert_eq!(f.finish().err(), Some(BuildError::UnterminatedBlock(BlockId(1))));
+
+        let mut f = FunctionBuilder::new("wrong_result");
+        f.returns::<F64>();
+        f.block(f.entry()).ret(1i32);
+        assert_eq!(
+            f.finish().err().unwrap().to_string(),
+            "block 0 returns Some(I32), expected Some(F64)"
+        );
+
+        let mut f = FunctionBuilder::new("late_param");
+        f.var::<I64>();
+        f.param::<I32>();
+        f.block(f.entry()).ret_void();
+        assert_eq!(f.finish().err(), Some(BuildError::ParamAfterLocal));
+
+        let mut f = FunctionBuilder::new("twice");
+        f.block(f.entry()).ret_void();
+        f.block(f.entry()).unreachable();
+        assert_eq!(f.finish().err(), Some(BuildError::BlockBuiltTwice(BlockId(0))));
+    }
+}
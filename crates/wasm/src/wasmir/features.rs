@@ -0,0 +1,443 @@
+//! WebAssembly proposal gating
+//!
+//! Engines reject modules using a proposal they do not enable, at load time
+//! and with errors naming a byte offset. Backends consult a `FeatureSet`
+//! before emission instead, so a function needing a disabled proposal is
+//! rejected with the function, block, and instruction that need it, and
+//! the source location when an ownership annotation records one.
+
+use alloc::string::String;
+use core::fmt;
+
+use super::{
+    ConvertOp, Instruction, Operand, Signature, SourceLocation, Terminator, Type, WasmIR, WasmModule,
+};
+
+/// Post-MVP proposal that WasmIR can require
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Proposal {
+    /// `externref` and `funcref` values, `ref.null`, and reference instructions
+    ReferenceTypes,
+    /// Functions returning more than one value
+    MultiValue,
+    /// Atomic memory accesses
+    Threads,
+    /// Exception tags and `throw`, used to unwind panics
+    ExceptionHandling,
+    /// `extend8_s` and friends, sign-extending within an integer
+    SignExtension,
+    /// Saturating float-to-int conversions, which Rust's `as` casts need
+    SaturatingFloatToInt,
+    /// `memory.copy` and `memory.fill`, used by the component model's `cabi_realloc`
+    BulkMemory,
+    /// 128-bit vectors, which code under `cfg(target_feature = "simd128")` uses
+    Simd,
+}
+
+impl Proposal {
+    pub const ALL: [Proposal; 8] = [
+        Proposal::ReferenceTypes,
+        Proposal::MultiValue,
+        Proposal::Threads,
+        Proposal::ExceptionHandling,
+        Proposal::SignExtension,
+        Proposal::SaturatingFloatToInt,
+        Proposal::BulkMemory,
+        Proposal::Simd,
+    ];
+
+    /// Name of the proposal, as used by engines' feature flags
+    pub fn name(self) -> &'static str {
+        match self {
+            Proposal::ReferenceTypes => "reference-types",
+            Proposal::MultiValue => "multi-value",
+            Proposal::Threads => "threads",
+            Proposal::ExceptionHandling => "exception-handling",
+            Proposal::SignExtension => "sign-ext",
+            Proposal::SaturatingFloatToInt => "nontrapping-float-to-int",
+            Proposal::BulkMemory => "bulk-memory",
+            Proposal::Simd => "simd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|proposal| proposal.name() == name)
+    }
+
+    /// Name of the proposal as a rustc and LLVM target feature, as in `-C target-feature=+simd128`
+    pub fn target_feature(self) -> &'static str {
+        match self {
+            Proposal::ReferenceTypes => "reference-types",
+            Proposal::MultiValue => "multivalue",
+            Proposal::Threads => "atomics",
+            Proposal::ExceptionHandling => "exception-handling",
+            Proposal::SignExtension => "sign-ext",
+            Proposal::SaturatingFloatToInt => "nontrapping-fptoint",
+            Proposal::BulkMemory => "bulk-memory",
+            Proposal::Simd => "simd128",
+        }
+    }
+
+    pub fn from_target_feature(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|proposal| proposal.target_feature() == name)
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Proposals a target engine enables
+///
+/// The default is WebAssembly 2.0: reference types, multi-value, sign
+/// extension, saturating conversions, and bulk memory. Threads need shared
+/// memory and are opt-in, as are exceptions and, as with rustc, SIMD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FeatureSet {
+    bits: u8,
+}
+
+impl Default for FeatureSet {
+    fn default() -> Self {
+        Self::mvp()
+            .with(Proposal::ReferenceTypes)
+            .with(Proposal::MultiValue)
+            .with(Proposal::SignExtension)
+            .with(Proposal::SaturatingFloatToInt)
+            .with(Proposal::BulkMemory)
+    }
+}
+
+impl FeatureSet {
+    /// No proposals beyond the MVP
+    pub const fn mvp() -> Self {
+        Self { bits: 0 }
+    }
+
+    pub fn all() -> Self {
+        Proposal::ALL.into_iter().fold(Self::mvp(), Self::with)
+    }
+
+    pub fn with(self, proposal: Proposal) -> Self {
+        Self { bits: self.bits | proposal.bit() }
+    }
+
+    pub fn without(self, proposal: Proposal) -> Self {
+        Self { bits: self.bits & !proposal.bit() }
+    }
+
+    pub fn contains(self, proposal: Proposal) -> bool {
+        self.bits & proposal.bit() != 0
+    }
+
+    /// Checks the memory and every function and import signature of a module
+    pub fn check_module(self, module: &WasmModule) -> Result<(), FeatureViolation> {
+        if module.shared_memory {
+            self.check(Some(Proposal::Threads), || FeatureViolation {
+                proposal: Proposal::Threads,
+                function: "module".into(),
+                site: Site::Memory,
+                location: None,
+            })?;
+        }
+        for import in &module.imports {
+            self.check(signature_proposal(import.signature()), || FeatureViolation {
+                proposal: Proposal::ReferenceTypes,
+                function: alloc::format!("{}::{}", import.module, import.name),
+                site: Site::Signature,
+                location: None,
+            })?;
+        }
+        module.functions.iter().try_for_each(|function| self.check_function(function))
+    }
+
+    /// Checks a function's signature, locals, and instructions
+    pub fn check_function(self, function: &WasmIR) -> Result<(), FeatureViolation> {
+        let violation = |site: Site, locals: &[u32]| FeatureViolation {
+            proposal: Proposal::ReferenceTypes,
+            function: function.name.clone(),
+            site,
+            location: locals.iter().find_map(|local| {
+                function.ownership_annotations.iter()
+                    .find(|annotation| annotation.variable == *local)
+                    .map(|annotation| annotation.source_location.clone())
+            }),
+        };
+
+        self.check(signature_proposal(&function.signature), || violation(Site::Signature, &[]))?;
+        for (index, ty) in function.locals.iter().enumerate() {
+            let index = index as u32;
+            self.check(type_proposal(ty), || violation(Site::Local(index), &[index]))?;
+        }
+        for (block, basic_block) in function.basic_blocks.iter().enumerate() {
+            for (index, instruction) in basic_block.instructions.iter().enumerate() {
+                let operands = instruction_operands(instruction);
+                let locals = operand_locals(&operands);
+                let site = || Site::Instruction { block, index, name: instruction.name() };
+                self.check(instruction_proposal(instruction), || violation(site(), &locals))?;
+                for operand in &operands {
+                    self.check(operand_proposal(function, operand), || violation(site(), &locals))?;
+                }
+            }
+            let operands = terminator_operands(&basic_block.terminator);
+            let locals = operand_locals(&operands);
+            for operand in &operands {
+                self.check(operand_proposal(function, operand), || violation(Site::Terminator { block }, &locals))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check(
+        self,
+        required: Option<Proposal>,
+        violation: impl FnOnce() -> FeatureViolation,
+    ) -> Result<(), FeatureViolation> {
+        match required {
+            Some(proposal) if !self.contains(proposal) => Err(FeatureViolation { proposal, ..violation() }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Use of a disabled proposal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureViolation {
+    pub proposal: Proposal,
+    /// Function name, or `module::name` of an import
+    pub function: String,
+    pub site: Site,
+    /// Source location of a local involved, if annotated
+    pub location: Option<SourceLocation>,
+}
+
+/// Part of a function, or the module memory, a diagnostic refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Site {
+    Signature,
+    Local(u32),
+    Instruction { block: usize, index: usize, name: &'static str },
+    Terminator { block: usize },
+    Memory,
+}
+
+impl fmt::Display for FeatureViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}:{}:{}: ", location.file, location.line, location.column)?;
+        }
+        write!(f, "{}: {} requires the disabled {} proposal", self.function, self.site, self.proposal.name())
+    }
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Site::Signature => write!(f, "signature"),
+            Site::Local(index) => write!(f, "local {}", index),
+            Site::Instruction { block, index, name } => write!(f, "{} at block {} instruction {}", name, block, index),
+            Site::Terminator { block } => write!(f, "terminator of block {}", block),
+            Site::Memory => write!(f, "shared memory"),
+        }
+    }
+}
+
+fn signature_proposal(signature: &Signature) -> Option<Proposal> {
+    let returns = match &signature.returns {
+        Some(Type::Struct { fields }) if fields.len() > 1 => return Some(Proposal::MultiValue),
+        Some(ty) => type_proposal(ty),
+        None => None,
+    };
+    signature.params.iter().find_map(type_proposal).or(returns)
+}
+
+fn type_proposal(ty: &Type) -> Option<Proposal> {
+    match ty {
+        Type::ExternRef(_) | Type::FuncRef => Some(Proposal::ReferenceTypes),
+        Type::Linear { inner_type } | Type::Capability { inner_type, .. } | Type::Promise(inner_type) => {
+            type_proposal(inner_type)
+        }
+        Type::Struct { fields } => fields.iter().find_map(type_proposal),
+        _ => None,
+    }
+}
+
+fn instruction_proposal(instruction: &Instruction) -> Option<Proposal> {
+    match instruction {
+        Instruction::AtomicOp { .. }
+        | Instruction::CompareExchange { .. }
+        | Instruction::AtomicWait { .. }
+        | Instruction::AtomicNotify { .. } => Some(Proposal::Threads),
+        Instruction::MakeFuncRef { .. }
+        | Instruction::FuncRefCall { .. }
+        | Instruction::ExternRefNew { .. }
+        | Instruction::ExternRefCast { .. }
+        | Instruction::ExternRefIsNull { .. }
+        | Instruction::ExternRefEq { .. }
+        | Instruction::FuncRefNew { .. }
+        | Instruction::FuncRefIsNull { .. }
+        | Instruction::FuncRefEq { .. } => Some(Proposal::ReferenceTypes),
+        Instruction::MemoryLoad { ty, .. } | Instruction::MemoryStore { ty, .. } => type_proposal(ty),
+        Instruction::Convert { op: ConvertOp::SignExtend { .. }, .. } => Some(Proposal::SignExtension),
+        Instruction::Convert { op: ConvertOp::TruncSat { .. }, .. } => Some(Proposal::SaturatingFloatToInt),
+        _ => None,
+    }
+}
+
+fn operand_proposal(function: &WasmIR, operand: &Operand) -> Option<Proposal> {
+    match operand {
+        Operand::MemoryAddress(inner) => operand_proposal(function, inner),
+        operand => function.operand_type(operand).as_ref().and_then(type_proposal),
+    }
+}
+
+pub(super) fn instruction_operands(instruction: &Instruction) -> alloc::vec::Vec<&Operand> {
+    match instruction {
+        Instruction::LocalGet { .. }
+        | Instruction::Jump { .. }
+        | Instruction::MakeFuncRef { .. }
+        | Instruction::FuncRefNew { .. }
+        | Instruction::CapabilityCheck { .. }
+        | Instruction::MemorySize
+        | Instruction::ThreadLocalAddress { .. }
+        | Instruction::Nop => alloc::vec![],
+        Instruction::LocalSet { value, .. }
+        | Instruction::GlobalSet { value, .. }
+        | Instruction::UnaryOp { value, .. }
+        | Instruction::Convert { value, .. }
+        | Instruction::LinearOp { value, .. }
+        | Instruction::ExternRefNew { value, .. } => alloc::vec![value],
+        Instruction::BinaryOp { left, right, .. }
+        | Instruction::ExternRefEq { left, right }
+        | Instruction::FuncRefEq { left, right } => alloc::vec![left, right],
+        Instruction::Call { args, .. } | Instruction::CallImport { args, .. } | Instruction::NewObject { args, .. } => {
+            args.iter().collect()
+        }
+        Instruction::Return { value } => value.iter().collect(),
+        Instruction::Branch { condition, .. } => alloc::vec![condition],
+        Instruction::Switch { value, .. } => alloc::vec![value],
+        Instruction::MemoryLoad { address, .. } | Instruction::MemoryFree { address } => alloc::vec![address],
+        Instruction::MemoryStore { address, value, .. } | Instruction::AtomicOp { address, value, .. } => {
+            alloc::vec![address, value]
+        }
+        Instruction::MemoryAlloc { size, .. } => alloc::vec![size],
+        Instruction::MemoryGrow { pages } => alloc::vec![pages],
+        Instruction::DropObject { object } => alloc::vec![object],
+        Instruction::ExternRefLoad { externref, .. }
+        | Instruction::ExternRefCast { externref, .. }
+        | Instruction::ExternRefIsNull { externref } => alloc::vec![externref],
+        Instruction::ExternRefStore { externref, value, .. } => alloc::vec![externref, value],
+        Instruction::JSMethodCall { object, args, .. } => core::iter::once(object).chain(args).collect(),
+        Instruction::FuncRefCall { funcref, args, .. } => core::iter::once(funcref).chain(args).collect(),
+        Instruction::FuncRefIsNull { funcref } => alloc::vec![funcref],
+        Instruction::CallIndirect { table_index, function_index, args, .. } => {
+            [table_index, function_index].into_iter().chain(args).collect()
+        }
+        Instruction::CompareExchange { address, expected, new_value, .. } => alloc::vec![address, expected, new_value],
+        Instruction::AtomicWait { address, expected, timeout, .. } => alloc::vec![address, expected, timeout],
+        Instruction::AtomicNotify { address, count } => alloc::vec![address, count],
+    }
+}
+
+pub(super) fn terminator_operands(terminator: &Terminator) -> alloc::vec::Vec<&Operand> {
+    match terminator {
+        Terminator::Return { value } | Terminator::Panic { message: value } => value.iter().collect(),
+        Terminator::Branch { condition, .. } => alloc::vec![condition],
+        Terminator::Switch { value, targets, .. } => {
+            core::iter::once(value).chain(targets.iter().map(|(case, _)| case)).collect()
+        }
+        Terminator::Jump { .. } | Terminator::Unreachable => alloc::vec![],
+    }
+}
+
+pub(super) fn operand_locals(operands: &[&Operand]) -> alloc::vec::Vec<u32> {
+    operands.iter()
+        .filter_map(|operand| match operand {
+            Operand::Local(index) => Some(*index),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::{AtomicOp, Constant, MemoryOrder, OwnershipAnnotation, OwnershipState};
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn function_f(params: vec::Vec<Type>, instructions: vec::Vec<Instruction>) -> WasmIR {
+        let mut f = WasmIR::new("f".to_string(), Signature { params, returns: None });
+        f.add_basic_block(instructions, Terminator::Return { value: None });
+        f
+    }
+
+    #[test]
+    fn test_feature_set_membership() {
+        let features = FeatureSet::default();
+        assert!(features.contains(Proposal::ReferenceTypes) && !features.contains(Proposal::Threads));
+        assert!(FeatureSet::all().contains(Proposal::Threads));
+        assert!(!features.without(Proposal::MultiValue).contains(Proposal::MultiValue));
+        assert_eq!(Proposal::from_name("multi-value"), Some(Proposal::MultiValue));
+        assert_eq!(Proposal::from_name("tail-call"), None);
+        assert_eq!(Proposal::from_name("nontrapping-float-to-int"), Some(Proposal::SaturatingFloatToInt));
+        assert_eq!(Proposal::from_target_feature("simd128"), Some(Proposal::Simd));
+        assert_eq!(Proposal::from_target_feature("atomics"), Some(Proposal::Threads));
+        assert!(features.contains(Proposal::BulkMemory) && !features.contains(Proposal::Simd));
+    }
+
+    #[test]
+    fn test_conversions_need_their_proposals() {
+        let convert = |op| Instruction::Convert { op, value: Operand::Local(0) };
+        let f = function_f(vec![Type::F32], vec![
+            convert(ConvertOp::Trunc { to: Type::I32, signed: true }),
+            convert(ConvertOp::TruncSat { to: Type::I32, signed: true }),
+        ]);
+        let violation = FeatureSet::mvp().check_function(&f).unwrap_err();
+        assert_eq!(violation.proposal, Proposal::SaturatingFloatToInt);
+        assert_eq!(violation.site, Site::Instruction { block: 0, index: 1, name: "convert" });
+        assert!(FeatureSet::default().check_function(&f).is_ok());
+
+        let f = function_f(vec![Type::I64], vec![convert(ConvertOp::SignExtend { bits: 32 })]);
+        assert_eq!(FeatureSet::mvp().check_function(&f).unwrap_err().proposal, Proposal::SignExtension);
+    }
+
+    #[test]
+    fn test_rejects_disabled_instructions_with_attribution() {
+        let atomic = Instruction::AtomicOp {
+            op: AtomicOp::Add,
+            address: Operand::Local(0),
+            value: Operand::Constant(Constant::I32(1)),
+            order: MemoryOrder::SeqCst,
+        };
+        let mut f = function_f(vec![Type::I32], vec![Instruction::Nop, atomic]);
+        f.ownership_annotations.push(OwnershipAnnotation {
+            variable: 0,
+            state: OwnershipState::Owned,
+            source_location: SourceLocation { file: "src/counter.rs".to_string(), line: 12, column: 5 },
+        });
+        let violation = FeatureSet::default().check_function(&f).unwrap_err();
+        assert_eq!(violation.site, Site::Instruction { block: 0, index: 1, name: "atomic_op" });
+        assert_eq!(
+            violation.to_string(),
+            "src/counter.rs:12:5: f: atomic_op at block 0 instruction 1 requires the disabled threads proposal"
+        );
+        assert!(FeatureSet::all().check_function(&f).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_reference_types_in_signatures_and_operands() {
+        let mvp = FeatureSet::mvp();
+        let f = function_f(vec![Type::ExternRef("Element".to_string())], vec![]);
+        assert_eq!(mvp.check_function(&f).unwrap_err().site, Site::Signature);
+
+        let mut f = function_f(vec![], vec![]);
+        f.basic_blocks[0].terminator = Terminator::Return { value: Some(Operand::Constant(Constant::Null)) };
+        assert_eq!(mvp.check_function(&f).unwrap_err().to_string(), "f: terminator of block 0 requires the disabled reference-types proposal");
+
+        let mut module = WasmModule::new();
+        module.add_import("env", "log", Signature { params: vec![Type::FuncRef], returns: None });
+        assert_eq!(mvp.check_module(&module).unwrap_err().function, "env::log");
+        assert!(FeatureSet::default().check_module(&module).is_ok());
+    }
+}
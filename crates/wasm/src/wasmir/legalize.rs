@@ -0,0 +1,688 @@
+//! Legalization of 128-bit integers
+//!
+//! Core WebAssembly has no 128-bit values, so backends split every
+//! `Type::I128` parameter and local into a pair of `i64` locals, low half
+//! first, and expand each operation on them into `i64` arithmetic. Carries
+//! and borrows are taken from the sign bit of bitwise combinations, and
+//! shifts pick between the in-half and cross-half results with a mask, so
+//! no blocks are split. Comparisons are signed, like the narrower ones.
+//!
+//! A 128-bit result must be stored to a local by the next instruction.
+//!
+//! Not supported yet, and rejected with a `LegalizeError` naming the
+//! operation: division, remainder, bit counts (`clz`, `ctz`, `popcnt`),
+//! and 128-bit return values, which need multi-value returns.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::features::Site;
+use super::{
+    BinaryOp, Constant, ConvertOp, ImportKind, Instruction, Operand, Signature, Terminator, Type, UnaryOp, WasmIR,
+    WasmModule,
+};
+
+/// 128-bit operation that cannot be legalized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegalizeError {
+    /// Function name, or `module::name` of an import
+    pub function: String,
+    pub site: Site,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for LegalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} {}", self.function, self.site, self.reason)
+    }
+}
+
+/// Returns a copy of `module` with every 128-bit value split into `i64` pairs
+pub fn legalize_i128(module: &WasmModule) -> Result<WasmModule, LegalizeError> {
+    let mut legalized = module.clone();
+    for function in &mut legalized.functions {
+        if uses_i128(function) {
+            *function = Legalizer::new(function).run()?;
+        }
+    }
+    for import in &mut legalized.imports {
+        let ImportKind::Function(signature) = &mut import.kind;
+        *signature = split_signature(signature).map_err(|reason| LegalizeError {
+            function: alloc::format!("{}::{}", import.module, import.name),
+            site: Site::Signature,
+            reason,
+        })?;
+    }
+    Ok(legalized)
+}
+
+fn split_signature(signature: &Signature) -> Result<Signature, &'static str> {
+    if signature.returns == Some(Type::I128) {
+        return Err("returns a 128-bit value, which needs multi-value returns");
+    }
+    let params = signature.params.iter()
+        .flat_map(|ty| match ty {
+            Type::I128 => alloc::vec![Type::I64, Type::I64],
+            ty => alloc::vec![ty.clone()],
+        })
+        .collect();
+    Ok(Signature { params, returns: signature.returns.clone() })
+}
+
+struct Legalizer<'a> {
+    function: &'a WasmIR,
+    /// Legalized index of every local; the low half of 128-bit ones
+    low: Vec<u32>,
+    /// Legalized index of the high half of every 128-bit local
+    high: Vec<Option<u32>>,
+    locals: Vec<Type>,
+    out: Vec<Instruction>,
+    site: Site,
+}
+
+impl<'a> Legalizer<'a> {
+    fn new(function: &'a WasmIR) -> Self {
+        let mut legalizer = Self {
+            function,
+            low: Vec::new(),
+            high: Vec::new(),
+            locals: Vec::new(),
+            out: Vec::new(),
+            site: Site::Signature,
+        };
+        let count = function.locals.len().max(function.signature.params.len());
+        for index in 0..count {
+            let ty = function.signature.params.get(index).or_else(|| function.locals.get(index));
+            match ty {
+                Some(Type::I128) => {
+                    let low = legalizer.temp(Type::I64);
+                    let high = legalizer.temp(Type::I64);
+                    legalizer.low.push(low);
+                    legalizer.high.push(Some(high));
+                }
+                ty => {
+                    let index = legalizer.temp(ty.cloned().unwrap_or(Type::I32));
+                    legalizer.low.push(index);
+                    legalizer.high.push(None);
+                }
+            }
+        }
+        legalizer
+    }
+
+    fn run(mut self) -> Result<WasmIR, LegalizeError> {
+        let mut function = self.function.clone();
+        function.signature = split_signature(&function.signature).map_err(|reason| self.error(reason))?;
+
+        for (block, basic_block) in self.function.basic_blocks.iter().enumerate() {
+            let instructions = &basic_block.instructions;
+            let mut index = 0;
+            while index < instructions.len() {
+                let instruction = &instructions[index];
+                self.site = Site::Instruction { block, index, name: instruction.name() };
+                if self.store_result(instruction, instructions.get(index + 1))? {
+                    index += 2;
+                    continue;
+                }
+                self.instruction(instruction)?;
+                index += 1;
+            }
+            function.basic_blocks[block].instructions = core::mem::take(&mut self.out);
+
+            self.site = Site::Terminator { block };
+            let mut terminator = basic_block.terminator.clone();
+            for operand in terminator_operands_mut(&mut terminator) {
+                *operand = self.narrow(operand)?;
+            }
+            function.basic_blocks[block].terminator = terminator;
+        }
+        function.locals = self.locals;
+        Ok(function)
+    }
+
+    /// Legalizes an instruction producing a 128-bit value together with the
+    /// `LocalSet` storing it; returns false if `instruction` produces none
+    fn store_result(&mut self, instruction: &Instruction, next: Option<&Instruction>) -> Result<bool, LegalizeError> {
+        let wide = match instruction {
+            Instruction::BinaryOp { op, left, right } => !is_comparison(*op) && (self.is_wide(left) || self.is_wide(right)),
+            Instruction::UnaryOp { value, .. } => self.is_wide(value),
+            Instruction::MemoryLoad { ty, .. } => *ty == Type::I128,
+            _ => false,
+        };
+        if !wide {
+            return Ok(false);
+        }
+        let destination = match next {
+            Some(Instruction::LocalSet { index, value: Operand::StackValue(0) }) => self.halves(&Operand::Local(*index))?,
+            _ => return Err(self.error("produces a 128-bit value that is not stored to a 128-bit local")),
+        };
+
+        let (low, high) = match instruction {
+            Instruction::BinaryOp { op, left, right } => self.binary(*op, left, right)?,
+            Instruction::UnaryOp { op: UnaryOp::Not, value } => {
+                let (low, high) = self.halves(value)?;
+                (self.op(BinaryOp::Xor, low, int(-1)), self.op(BinaryOp::Xor, high, int(-1)))
+            }
+            Instruction::UnaryOp { op: UnaryOp::Neg, value } => {
+                let value = self.halves(value)?;
+                self.sub((int(0), int(0)), value)
+            }
+            Instruction::UnaryOp { .. } => {
+                return Err(self.error("counts bits of a 128-bit value, which is not supported yet"));
+            }
+            Instruction::MemoryLoad { address, align, offset, .. } => {
+                let address = self.address(address)?;
+                let mut half = |offset| {
+                    self.out.push(Instruction::MemoryLoad { address: address.clone(), ty: Type::I64, align: *align, offset });
+                    self.spill(Type::I64)
+                };
+                (half(*offset), half(offset + 8))
+            }
+            _ => unreachable!("only value-producing instructions are wide"),
+        };
+        self.set(destination.0, low);
+        self.set(destination.1, high);
+        Ok(true)
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) -> Result<(), LegalizeError> {
+        match instruction {
+            Instruction::LocalSet { index, value } if self.is_wide(&Operand::Local(*index)) => {
+                let (low, high) = self.halves(&Operand::Local(*index))?;
+                let value = self.halves(value)?;
+                self.set(low, value.0);
+                self.set(high, value.1);
+            }
+            Instruction::BinaryOp { op, left, right } if self.is_wide(left) || self.is_wide(right) => {
+                let result = self.compare(*op, left, right)?;
+                self.out.push(result);
+            }
+            Instruction::MemoryStore { address, value, ty: Type::I128, align, offset } => {
+                let (low, high) = self.halves(value)?;
+                let address = self.address(address)?;
+                for (value, offset) in [(low, *offset), (high, offset + 8)] {
+                    self.out.push(Instruction::MemoryStore {
+                        address: address.clone(),
+                        value,
+                        ty: Type::I64,
+                        align: *align,
+                        offset,
+                    });
+                }
+            }
+            Instruction::Call { func_ref, args } => {
+                let args = self.arguments(args)?;
+                self.out.push(Instruction::Call { func_ref: *func_ref, args });
+            }
+            Instruction::CallImport { import, args } => {
+                let args = self.arguments(args)?;
+                self.out.push(Instruction::CallImport { import: *import, args });
+            }
+            instruction => {
+                let mut instruction = instruction.clone();
+                match &mut instruction {
+                    Instruction::LocalGet { index } | Instruction::LocalSet { index, .. } => {
+                        if self.is_wide(&Operand::Local(*index)) {
+                            return Err(self.error("moves a 128-bit value through the operand stack"));
+                        }
+                        *index = self.local(*index);
+                    }
+                    _ => {}
+                }
+                for operand in instruction_operands_mut(&mut instruction) {
+                    *operand = self.narrow(operand)?;
+                }
+                self.out.push(instruction);
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands 128-bit arithmetic into the halves of the result
+    fn binary(&mut self, op: BinaryOp, left: &Operand, right: &Operand) -> Result<(Operand, Operand), LegalizeError> {
+        if matches!(op, BinaryOp::Shl | BinaryOp::Shr | BinaryOp::Sar) {
+            let value = self.halves(left)?;
+            let amount = self.shift_amount(right)?;
+            return Ok(self.shift(op, value, amount));
+        }
+        let a = self.halves(left)?;
+        let b = self.halves(right)?;
+        Ok(match op {
+            BinaryOp::Add => self.add(a, b),
+            BinaryOp::Sub => self.sub(a, b),
+            BinaryOp::Mul => self.mul(a, b),
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => (self.op(op, a.0, b.0), self.op(op, a.1, b.1)),
+            BinaryOp::Mod => {
+                return Err(self.error("takes the remainder of 128-bit integers, which is not supported yet"));
+            }
+            _ => return Err(self.error("divides 128-bit integers, which is not supported yet")),
+        })
+    }
+
+    fn add(&mut self, a: (Operand, Operand), b: (Operand, Operand)) -> (Operand, Operand) {
+        let low = self.op(BinaryOp::Add, a.0.clone(), b.0.clone());
+        // Carry out of the low half: ((a & b) | ((a | b) & !sum)) >> 63
+        let both = self.op(BinaryOp::And, a.0.clone(), b.0.clone());
+        let either = self.op(BinaryOp::Or, a.0, b.0);
+        let not_low = self.op(BinaryOp::Xor, low.clone(), int(-1));
+        let lost = self.op(BinaryOp::And, either, not_low);
+        let carry = self.op(BinaryOp::Or, both, lost);
+        let carry = self.op(BinaryOp::Shr, carry, int(63));
+        let high = self.op(BinaryOp::Add, a.1, b.1);
+        let high = self.op(BinaryOp::Add, high, carry);
+        (low, high)
+    }
+
+    fn sub(&mut self, a: (Operand, Operand), b: (Operand, Operand)) -> (Operand, Operand) {
+        let low = self.op(BinaryOp::Sub, a.0.clone(), b.0.clone());
+        // Borrow out of the low half: ((!a & b) | (!(a ^ b) & diff)) >> 63
+        let not_a = self.op(BinaryOp::Xor, a.0.clone(), int(-1));
+        let taken = self.op(BinaryOp::And, not_a, b.0.clone());
+        let differ = self.op(BinaryOp::Xor, a.0, b.0);
+        let same = self.op(BinaryOp::Xor, differ, int(-1));
+        let passed = self.op(BinaryOp::And, same, low.clone());
+        let borrow = self.op(BinaryOp::Or, taken, passed);
+        let borrow = self.op(BinaryOp::Shr, borrow, int(63));
+        let high = self.op(BinaryOp::Sub, a.1, b.1);
+        let high = self.op(BinaryOp::Sub, high, borrow);
+        (low, high)
+    }
+
+    fn mul(&mut self, a: (Operand, Operand), b: (Operand, Operand)) -> (Operand, Operand) {
+        // The full product of the low halves from 32-bit limbs
+        let mask = int(0xFFFF_FFFF);
+        let a0 = self.op(BinaryOp::And, a.0.clone(), mask.clone());
+        let a1 = self.op(BinaryOp::Shr, a.0.clone(), int(32));
+        let b0 = self.op(BinaryOp::And, b.0.clone(), mask.clone());
+        let b1 = self.op(BinaryOp::Shr, b.0.clone(), int(32));
+        let p00 = self.op(BinaryOp::Mul, a0.clone(), b0.clone());
+        let p01 = self.op(BinaryOp::Mul, a0, b1.clone());
+        let p10 = self.op(BinaryOp::Mul, a1.clone(), b0);
+        let p11 = self.op(BinaryOp::Mul, a1, b1);
+
+        let carry = self.op(BinaryOp::Shr, p00.clone(), int(32));
+        let p01_low = self.op(BinaryOp::And, p01.clone(), mask.clone());
+        let p10_low = self.op(BinaryOp::And, p10.clone(), mask.clone());
+        let middle = self.op(BinaryOp::Add, carry, p01_low);
+        let middle = self.op(BinaryOp::Add, middle, p10_low);
+        let p00_low = self.op(BinaryOp::And, p00, mask);
+        let middle_high = self.op(BinaryOp::Shl, middle.clone(), int(32));
+        let low = self.op(BinaryOp::Or, p00_low, middle_high);
+
+        let p01_high = self.op(BinaryOp::Shr, p01, int(32));
+        let p10_high = self.op(BinaryOp::Shr, p10, int(32));
+        let middle_carry = self.op(BinaryOp::Shr, middle, int(32));
+        let high = self.op(BinaryOp::Add, p11, p01_high);
+        let high = self.op(BinaryOp::Add, high, p10_high);
+        let high = self.op(BinaryOp::Add, high, middle_carry);
+        // Cross terms only reach the high half
+        let cross = self.op(BinaryOp::Mul, a.0, b.1);
+        let high = self.op(BinaryOp::Add, high, cross);
+        let cross = self.op(BinaryOp::Mul, a.1, b.0);
+        let high = self.op(BinaryOp::Add, high, cross);
+        (low, high)
+    }
+
+    /// Shifts by `amount & 127`
+    ///
+    /// `i64` shifts already take their amount modulo 64; bit 6 of the
+    /// amount selects whether the result crosses halves.
+    fn shift(&mut self, op: BinaryOp, value: (Operand, Operand), amount: Operand) -> (Operand, Operand) {
+        let (low, high) = value;
+        let bit = self.op(BinaryOp::Shr, amount.clone(), int(6));
+        let bit = self.op(BinaryOp::And, bit, int(1));
+        let crossed = self.op(BinaryOp::Sub, int(0), bit);
+        let within = self.op(BinaryOp::Xor, crossed.clone(), int(-1));
+        // Moving bits between halves by (amount - 64) without shifting by 64
+        let back = self.op(BinaryOp::Sub, int(63), amount.clone());
+
+        let select = |this: &mut Self, near: Operand, far: Operand| {
+            let near = this.op(BinaryOp::And, near, within.clone());
+            let far = this.op(BinaryOp::And, far, crossed.clone());
+            this.op(BinaryOp::Or, near, far)
+        };
+        match op {
+            BinaryOp::Shl => {
+                let shifted_low = self.op(BinaryOp::Shl, low.clone(), amount.clone());
+                let shifted_high = self.op(BinaryOp::Shl, high, amount);
+                let moved = self.op(BinaryOp::Shr, low, int(1));
+                let moved = self.op(BinaryOp::Shr, moved, back);
+                let near_high = self.op(BinaryOp::Or, shifted_high, moved);
+                let new_low = self.op(BinaryOp::And, shifted_low.clone(), within.clone());
+                (new_low, select(self, near_high, shifted_low))
+            }
+            _ => {
+                let shifted_high = self.op(op, high.clone(), amount.clone());
+                let shifted_low = self.op(BinaryOp::Shr, low, amount);
+                let moved = self.op(BinaryOp::Shl, high.clone(), int(1));
+                let moved = self.op(BinaryOp::Shl, moved, back);
+                let near_low = self.op(BinaryOp::Or, shifted_low, moved);
+                let new_low = select(self, near_low, shifted_high.clone());
+                let fill = match op {
+                    BinaryOp::Sar => self.op(BinaryOp::Sar, high, int(63)),
+                    _ => int(0),
+                };
+                (new_low, select(self, shifted_high, fill))
+            }
+        }
+    }
+
+    /// Comparison leaving its `i32` result on the stack
+    fn compare(&mut self, op: BinaryOp, left: &Operand, right: &Operand) -> Result<Instruction, LegalizeError> {
+        let a = self.halves(left)?;
+        let b = self.halves(right)?;
+        let (op, a, b) = match op {
+            BinaryOp::Gt => (BinaryOp::Lt, b, a),
+            BinaryOp::Ge => (BinaryOp::Le, b, a),
+            op => (op, a, b),
+        };
+        Ok(match op {
+            BinaryOp::Eq | BinaryOp::Ne => {
+                let low = self.op(op, a.0, b.0);
+                let high = self.op(op, a.1, b.1);
+                let join = if op == BinaryOp::Eq { BinaryOp::And } else { BinaryOp::Or };
+                Instruction::BinaryOp { op: join, left: low, right: high }
+            }
+            BinaryOp::Lt | BinaryOp::Le => {
+                let below = self.op(BinaryOp::Lt, a.1.clone(), b.1.clone());
+                let equal = self.op(BinaryOp::Eq, a.1, b.1);
+                // Unsigned comparison of the low halves with the sign bits flipped
+                let a_low = self.op(BinaryOp::Xor, a.0, int(i64::MIN));
+                let b_low = self.op(BinaryOp::Xor, b.0, int(i64::MIN));
+                let low = self.op(op, a_low, b_low);
+                let low = self.op(BinaryOp::And, equal, low);
+                Instruction::BinaryOp { op: BinaryOp::Or, left: below, right: low }
+            }
+            _ => unreachable!("arithmetic is legalized by store_result"),
+        })
+    }
+
+    /// Pushes `left op right` and stores the result in a new local
+    fn op(&mut self, op: BinaryOp, left: Operand, right: Operand) -> Operand {
+        self.out.push(Instruction::BinaryOp { op, left, right });
+        self.spill(if is_comparison(op) { Type::I32 } else { Type::I64 })
+    }
+
+    /// Stores the value on top of the stack in a new local
+    fn spill(&mut self, ty: Type) -> Operand {
+        let index = self.temp(ty);
+        self.out.push(Instruction::LocalSet { index, value: Operand::StackValue(0) });
+        Operand::Local(index)
+    }
+
+    fn set(&mut self, target: Operand, value: Operand) {
+        if let Operand::Local(index) = target {
+            self.out.push(Instruction::LocalSet { index, value });
+        }
+    }
+
+    fn temp(&mut self, ty: Type) -> u32 {
+        self.locals.push(ty);
+        self.locals.len() as u32 - 1
+    }
+
+    /// Address operand usable for both halves of a memory access
+    fn address(&mut self, address: &Operand) -> Result<Operand, LegalizeError> {
+        match address {
+            Operand::StackValue(_) => Ok(self.spill(Type::I32)),
+            address => self.narrow(address),
+        }
+    }
+
+    /// Shift amount as an `i64`, zero-extending `i32` amounts
+    fn shift_amount(&mut self, amount: &Operand) -> Result<Operand, LegalizeError> {
+        if self.is_wide(amount) {
+            return Ok(self.halves(amount)?.0);
+        }
+        match amount {
+            Operand::Constant(Constant::I32(amount)) => Ok(int(*amount as i64)),
+            amount => match self.function.operand_type(amount) {
+                Some(Type::I64) => self.narrow(amount),
+                Some(Type::I32) => {
+                    let value = self.narrow(amount)?;
+                    self.out.push(Instruction::Convert { op: ConvertOp::Extend { signed: false }, value });
+                    Ok(self.spill(Type::I64))
+                }
+                _ => Err(self.error("shifts a 128-bit value by an amount that is not an integer")),
+            },
+        }
+    }
+
+    fn arguments(&self, args: &[Operand]) -> Result<Vec<Operand>, LegalizeError> {
+        let mut legalized = Vec::new();
+        for arg in args {
+            if self.is_wide(arg) {
+                let (low, high) = self.halves(arg)?;
+                legalized.push(low);
+                legalized.push(high);
+            } else {
+                legalized.push(self.narrow(arg)?);
+            }
+        }
+        Ok(legalized)
+    }
+
+    fn is_wide(&self, operand: &Operand) -> bool {
+        match operand {
+            Operand::Local(index) => self.high.get(*index as usize).is_some_and(Option::is_some),
+            Operand::Constant(Constant::I128(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Low and high halves of a 128-bit operand
+    fn halves(&self, operand: &Operand) -> Result<(Operand, Operand), LegalizeError> {
+        match operand {
+            Operand::Local(index) => match self.high.get(*index as usize) {
+                Some(Some(high)) => Ok((Operand::Local(self.low[*index as usize]), Operand::Local(*high))),
+                _ => Err(self.error("mixes 128-bit and narrower operands")),
+            },
+            Operand::Constant(Constant::I128(value)) => Ok((int(*value as i64), int((*value >> 64) as i64))),
+            _ => Err(self.error("mixes 128-bit and narrower operands")),
+        }
+    }
+
+    /// Renumbers an operand that is not 128-bit
+    fn narrow(&self, operand: &Operand) -> Result<Operand, LegalizeError> {
+        if self.is_wide(operand) {
+            return Err(self.error("passes a 128-bit operand where it cannot be split"));
+        }
+        Ok(match operand {
+            Operand::Local(index) => Operand::Local(self.local(*index)),
+            Operand::MemoryAddress(inner) => Operand::MemoryAddress(alloc::boxed::Box::new(self.narrow(inner)?)),
+            operand => operand.clone(),
+        })
+    }
+
+    /// Legalized index of a local; out-of-range indices are left for validation
+    fn local(&self, index: u32) -> u32 {
+        self.low.get(index as usize).copied().unwrap_or(index)
+    }
+
+    fn error(&self, reason: &'static str) -> LegalizeError {
+        LegalizeError { function: self.function.name.clone(), site: self.site.clone(), reason }
+    }
+}
+
+fn uses_i128(function: &WasmIR) -> bool {
+    let signature = &function.signature;
+    signature.params.iter().chain(&signature.returns).chain(&function.locals).any(|ty| *ty == Type::I128)
+}
+
+fn int(value: i64) -> Operand {
+    Operand::Constant(Constant::I64(value))
+}
+
+fn is_comparison(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge)
+}
+
+fn instruction_operands_mut(instruction: &mut Instruction) -> Vec<&mut Operand> {
+    match instruction {
+        Instruction::LocalGet { .. }
+        | Instruction::Jump { .. }
+        | Instruction::MakeFuncRef { .. }
+        | Instruction::FuncRefNew { .. }
+        | Instruction::CapabilityCheck { .. }
+        | Instruction::MemorySize
+        | Instruction::ThreadLocalAddress { .. }
+        | Instruction::Nop => alloc::vec![],
+        Instruction::LocalSet { value, .. }
+        | Instruction::GlobalSet { value, .. }
+        | Instruction::UnaryOp { value, .. }
+        | Instruction::Convert { value, .. }
+        | Instruction::LinearOp { value, .. }
+        | Instruction::ExternRefNew { value, .. } => alloc::vec![value],
+        Instruction::BinaryOp { left, right, .. }
+        | Instruction::ExternRefEq { left, right }
+        | Instruction::FuncRefEq { left, right } => alloc::vec![left, right],
+        Instruction::Call { args, .. } | Instruction::CallImport { args, .. } | Instruction::NewObject { args, .. } => {
+            args.iter_mut().collect()
+        }
+        Instruction::Return { value } => value.iter_mut().collect(),
+        Instruction::Branch { condition, .. } => alloc::vec![condition],
+        Instruction::Switch { value, .. } => alloc::vec![value],
+        Instruction::MemoryLoad { address, .. } | Instruction::MemoryFree { address } => alloc::vec![address],
+        Instruction::MemoryStore { address, value, .. } | Instruction::AtomicOp { address, value, .. } => {
+            alloc::vec![address, value]
+        }
+        Instruction::MemoryAlloc { size, .. } => alloc::vec![size],
+        Instruction::MemoryGrow { pages } => alloc::vec![pages],
+        Instruction::DropObject { object } => alloc::vec![object],
+        Instruction::ExternRefLoad { externref, .. }
+        | Instruction::ExternRefCast { externref, .. }
+        | Instruction::ExternRefIsNull { externref } => alloc::vec![externref],
+        Instruction::ExternRefStore { externref, value, .. } => alloc::vec![externref, value],
+        Instruction::JSMethodCall { object, args, .. } => core::iter::once(object).chain(args).collect(),
+        Instruction::FuncRefCall { funcref, args, .. } => core::iter::once(funcref).chain(args).collect(),
+        Instruction::FuncRefIsNull { funcref } => alloc::vec![funcref],
+        Instruction::CallIndirect { table_index, function_index, args, .. } => {
+            [table_index, function_index].into_iter().chain(args).collect()
+        }
+        Instruction::CompareExchange { address, expected, new_value, .. } => alloc::vec![address, expected, new_value],
+        Instruction::AtomicWait { address, expected, timeout, .. } => alloc::vec![address, expected, timeout],
+        Instruction::AtomicNotify { address, count } => alloc::vec![address, count],
+    }
+}
+
+fn terminator_operands_mut(terminator: &mut Terminator) -> Vec<&mut Operand> {
+    match terminator {
+        Terminator::Return { value } | Terminator::Panic { message: value } => value.iter_mut().collect(),
+        Terminator::Branch { condition, .. } => alloc::vec![condition],
+        Terminator::Switch { value, .. } => alloc::vec![value],
+        Terminator::Jump { .. } | Terminator::Unreachable => alloc::vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::{BlockId, Import};
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn function(params: Vec<Type>, locals: Vec<Type>, instructions: Vec<Instruction>) -> WasmIR {
+        let mut f = WasmIR::new("f".to_string(), Signature { params, returns: None });
+        f.locals = locals;
+        f.add_basic_block(instructions, Terminator::Return { value: None });
+        f
+    }
+
+    #[test]
+    fn test_splits_locals_signatures_and_arguments() {
+        let mut module = WasmModule::new();
+        module.imports.push(Import {
+            module: "env".to_string(),
+            name: "log".to_string(),
+            kind: ImportKind::Function(Signature { params: vec![Type::I32, Type::I128], returns: None }),
+        });
+        module.add_function(function(
+            vec![Type::I32, Type::I128],
+            vec![Type::I32, Type::I128, Type::F64],
+            vec![
+                Instruction::CallImport { import: 0, args: vec![Operand::Local(0), Operand::Local(1)] },
+                Instruction::LocalSet { index: 2, value: Operand::Constant(Constant::F64(1.0)) },
+            ],
+        ));
+
+        let legalized = legalize_i128(&module).unwrap();
+        let ImportKind::Function(signature) = &legalized.imports[0].kind;
+        assert_eq!(signature.params, vec![Type::I32, Type::I64, Type::I64]);
+        let f = &legalized.functions[0];
+        assert_eq!(f.signature.params, vec![Type::I32, Type::I64, Type::I64]);
+        assert_eq!(f.locals, vec![Type::I32, Type::I64, Type::I64, Type::F64]);
+        assert!(matches!(
+            &f.basic_blocks[0].instructions[..],
+            [Instruction::CallImport { args, .. }, Instruction::LocalSet { index: 3, .. }]
+                if matches!(args[..], [Operand::Local(0), Operand::Local(1), Operand::Local(2)])
+        ));
+        assert!(!legalized.uses_i128());
+    }
+
+    #[test]
+    fn test_rejects_what_cannot_be_split() {
+        let error = |f: WasmIR| {
+            let mut module = WasmModule::new();
+            module.add_function(f);
+            legalize_i128(&module).unwrap_err()
+        };
+        let divide = function(vec![Type::I128], vec![Type::I128], vec![
+            Instruction::BinaryOp { op: BinaryOp::Div, left: Operand::Local(0), right: Operand::Constant(Constant::I128(3)) },
+            Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+        ]);
+        assert_eq!(error(divide).to_string(), "f: binary_op at block 0 instruction 0 divides 128-bit integers, which is not supported yet");
+
+        let unstored = function(vec![Type::I128], vec![Type::I128], vec![
+            Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::Local(0), right: Operand::Local(0) },
+            Instruction::Jump { target: BlockId(0) },
+        ]);
+        assert_eq!(error(unstored).reason, "produces a 128-bit value that is not stored to a 128-bit local");
+
+        let remainder = function(vec![Type::I128], vec![Type::I128], vec![
+            Instruction::BinaryOp { op: BinaryOp::Mod, left: Operand::Local(0), right: Operand::Local(0) },
+            Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+        ]);
+        assert_eq!(error(remainder).reason, "takes the remainder of 128-bit integers, which is not supported yet");
+
+        for op in [UnaryOp::Clz, UnaryOp::Ctz, UnaryOp::Popcnt] {
+            let count = function(vec![Type::I128], vec![Type::I128], vec![
+                Instruction::UnaryOp { op, value: Operand::Local(0) },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+            ]);
+            assert_eq!(error(count).reason, "counts bits of a 128-bit value, which is not supported yet");
+        }
+
+        let mut returns = function(vec![], vec![], vec![]);
+        returns.signature.returns = Some(Type::I128);
+        let returns = error(returns);
+        assert_eq!(returns.site, Site::Signature);
+        assert_eq!(returns.reason, "returns a 128-bit value, which needs multi-value returns");
+
+        let mut module = WasmModule::new();
+        module.add_import("env", "wide", Signature { params: vec![], returns: Some(Type::I128) });
+        assert_eq!(
+            legalize_i128(&module).unwrap_err().to_string(),
+            "env::wide: signature returns a 128-bit value, which needs multi-value returns"
+        );
+    }
+
+    #[test]
+    fn test_zero_extends_variable_shift_amounts() {
+        let shift = function(vec![Type::I128, Type::I32], vec![Type::I128, Type::I32], vec![
+            Instruction::BinaryOp { op: BinaryOp::Shl, left: Operand::Local(0), right: Operand::Local(1) },
+            Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+        ]);
+        let mut module = WasmModule::new();
+        module.add_function(shift);
+        let legalized = legalize_i128(&module).unwrap();
+        assert!(matches!(
+            &legalized.functions[0].basic_blocks[0].instructions[..2],
+            [
+                Instruction::Convert { op: ConvertOp::Extend { signed: false }, value: Operand::Local(2) },
+                Instruction::LocalSet { index: 3, value: Operand::StackValue(0) },
+            ]
+        ));
+        assert_eq!(legalized.functions[0].locals[3], Type::I64);
+    }
+}
@@ -0,0 +1,568 @@
+//! Full verification of WasmIR functions
+//!
+//! `WasmIR::validate` stops at the first out-of-range index. The verifier
+//! reports every problem it finds, each with the block and instruction
+//! involved and the source location of an annotated local, and also checks
+//! operand types, terminators, reads of locals no path has written, and
+//! use-once semantics of linear locals. Values taken from the operand stack
+//! have no static type here, so only backends check them.
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::analysis::Cfg;
+use super::features::{instruction_operands, terminator_operands, Site};
+use super::{
+    BinaryOp, BlockId, Instruction, Operand, SourceLocation, Terminator, Type, UnaryOp, ValidationError, WasmIR,
+    WasmModule,
+};
+
+/// Verification failure at a site of a function
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub function: String,
+    pub site: Site,
+    /// Source location of a local involved, if annotated
+    pub location: Option<SourceLocation>,
+    pub error: ValidationError,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}:{}:{}: ", location.file, location.line, location.column)?;
+        }
+        write!(f, "{}: {}: {}", self.function, self.site, self.error)
+    }
+}
+
+/// Verifies a function on its own, without checking its calls
+pub fn verify_function(function: &WasmIR) -> Vec<Diagnostic> {
+    Verifier::new(function, None).run()
+}
+
+/// Verifies every function of a module, checking calls against their callees
+///
+/// Module-level declarations are checked by `WasmModule::validate`.
+pub fn verify_module(module: &WasmModule) -> Vec<Diagnostic> {
+    module.functions.iter()
+        .flat_map(|function| Verifier::new(function, Some(module)).run())
+        .collect()
+}
+
+struct Verifier<'a> {
+    function: &'a WasmIR,
+    module: Option<&'a WasmModule>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(function: &'a WasmIR, module: Option<&'a WasmModule>) -> Self {
+        Self { function, module, diagnostics: Vec::new() }
+    }
+
+    fn run(mut self) -> Vec<Diagnostic> {
+        self.check_declarations();
+        let blocks = &self.function.basic_blocks;
+        if blocks.is_empty() && self.function.signature.returns.is_some() {
+            self.report(Site::Signature, &[], ValidationError::ControlFlowError("function with a result has no blocks"));
+        }
+        for (block, basic_block) in blocks.iter().enumerate() {
+            if basic_block.id != BlockId(block) {
+                self.report(Site::Terminator { block }, &[], ValidationError::InvalidBlockId("id differs from position"));
+            }
+            for (index, instruction) in basic_block.instructions.iter().enumerate() {
+                let site = Site::Instruction { block, index, name: instruction.name() };
+                self.check_instruction(site, instruction);
+            }
+            self.check_terminator(block, &basic_block.terminator);
+        }
+
+        let cfg = Cfg::new(self.function);
+        self.check_definitions(&cfg);
+        self.check_linear_uses(&cfg);
+        self.diagnostics
+    }
+
+    fn report(&mut self, site: Site, locals: &[u32], error: ValidationError) {
+        let location = locals.iter().find_map(|local| self.annotation(*local));
+        self.diagnostics.push(Diagnostic { function: self.function.name.clone(), site, location, error });
+    }
+
+    fn annotation(&self, local: u32) -> Option<SourceLocation> {
+        self.function.ownership_annotations.iter()
+            .find(|annotation| annotation.variable == local)
+            .map(|annotation| annotation.source_location.clone())
+    }
+
+    fn local_count(&self) -> u32 {
+        self.function.locals.len() as u32
+    }
+
+    /// Parameters must lead the locals, and annotations name existing locals
+    fn check_declarations(&mut self) {
+        let params = &self.function.signature.params;
+        for (index, (param, local)) in params.iter().zip(&self.function.locals).enumerate() {
+            if !compatible(param, local) {
+                let error = ValidationError::TypeMismatch { expected: param.clone(), actual: local.clone() };
+                self.report(Site::Local(index as u32), &[index as u32], error);
+            }
+        }
+        for annotation in &self.function.ownership_annotations {
+            if annotation.variable >= self.local_count() {
+                self.diagnostics.push(Diagnostic {
+                    function: self.function.name.clone(),
+                    site: Site::Local(annotation.variable),
+                    location: Some(annotation.source_location.clone()),
+                    error: ValidationError::InvalidLocalIndex(annotation.variable),
+                });
+            }
+        }
+    }
+
+    fn check_instruction(&mut self, site: Site, instruction: &Instruction) {
+        let locals = read_locals(instruction);
+        for &local in &locals {
+            if local >= self.local_count() {
+                self.report(site.clone(), &[], ValidationError::InvalidLocalIndex(local));
+            }
+        }
+        if let Instruction::LocalSet { index, .. } = instruction {
+            if *index >= self.local_count() {
+                self.report(site.clone(), &[], ValidationError::InvalidLocalIndex(*index));
+            }
+        }
+
+        let mut errors = Vec::new();
+        match instruction {
+            Instruction::Branch { .. } | Instruction::Jump { .. } | Instruction::Switch { .. } => {
+                errors.push(ValidationError::ControlFlowError("branch inside a block instead of its terminator"));
+            }
+            Instruction::LocalSet { index, value } => {
+                if let Some(local) = self.function.locals.get(*index as usize) {
+                    self.expect(&mut errors, local, value);
+                }
+            }
+            Instruction::BinaryOp { op, left, right } => {
+                let (left, right) = (self.function.operand_type(left), self.function.operand_type(right));
+                if let (Some(left), Some(right)) = (&left, &right) {
+                    if !compatible(left, right) {
+                        errors.push(ValidationError::TypeMismatch { expected: left.clone(), actual: right.clone() });
+                    }
+                }
+                if let Some(ty) = left.or(right) {
+                    let integer_only = !matches!(
+                        op,
+                        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div
+                            | BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+                    );
+                    check_numeric(&mut errors, format!("{:?}", op), &ty, integer_only);
+                }
+            }
+            Instruction::UnaryOp { op, value } => {
+                if let Some(ty) = self.function.operand_type(value) {
+                    check_numeric(&mut errors, format!("{:?}", op), &ty, *op != UnaryOp::Neg);
+                }
+            }
+            Instruction::Convert { op, value } => {
+                if let Some(ty) = self.function.operand_type(value) {
+                    let source = match representation(&ty) {
+                        Type::Pointer(_) => Type::I32,
+                        source => source.clone(),
+                    };
+                    if op.result(&source).is_none() {
+                        errors.push(ValidationError::UnsupportedOperandType { operation: format!("{:?}", op), ty });
+                    }
+                }
+            }
+            Instruction::MemoryLoad { address, .. } | Instruction::MemoryFree { address } => {
+                self.expect(&mut errors, &Type::I32, address)
+            }
+            Instruction::MemoryAlloc { size: value, .. } | Instruction::MemoryGrow { pages: value } => {
+                self.expect(&mut errors, &Type::I32, value)
+            }
+            Instruction::MemoryStore { address, value, ty, .. } => {
+                self.expect(&mut errors, &Type::I32, address);
+                self.expect(&mut errors, ty, value);
+            }
+            Instruction::Return { value } => self.check_return(&mut errors, value.as_ref()),
+            Instruction::Call { func_ref, args } => {
+                if let Some(module) = self.module {
+                    match module.functions.get(*func_ref as usize) {
+                        Some(callee) => self.check_arguments(&mut errors, &callee.signature.params, args),
+                        None => errors.push(ValidationError::InvalidFunctionIndex(*func_ref)),
+                    }
+                }
+            }
+            Instruction::CallImport { import, args } => {
+                if let Some(module) = self.module {
+                    match module.imports.get(*import as usize) {
+                        Some(import) => self.check_arguments(&mut errors, &import.signature().params, args),
+                        None => errors.push(ValidationError::InvalidImport(format!("unknown import {}", import))),
+                    }
+                }
+            }
+            Instruction::GlobalSet { index, value } => {
+                if let Some(module) = self.module {
+                    match module.globals.get(*index as usize) {
+                        Some(global) if global.mutable => self.expect(&mut errors, &global.ty, value),
+                        Some(_) => errors.push(ValidationError::InvalidGlobal(format!("global {} is not mutable", index))),
+                        None => errors.push(ValidationError::InvalidGlobal(format!("unknown global {}", index))),
+                    }
+                }
+            }
+            Instruction::ThreadLocalAddress { index }
+                if self.module.is_some_and(|module| *index as usize >= module.thread_locals.len()) =>
+            {
+                errors.push(ValidationError::InvalidThreadLocal(format!("unknown thread local {}", index)));
+            }
+            _ => {}
+        }
+        for error in errors {
+            self.report(site.clone(), &locals, error);
+        }
+    }
+
+    fn check_terminator(&mut self, block: usize, terminator: &Terminator) {
+        let site = Site::Terminator { block };
+        let locals = operand_locals(&terminator_operands(terminator));
+        for &local in &locals {
+            if local >= self.local_count() {
+                self.report(site.clone(), &[], ValidationError::InvalidLocalIndex(local));
+            }
+        }
+
+        let mut errors = Vec::new();
+        let count = self.function.basic_blocks.len();
+        let target = |errors: &mut Vec<ValidationError>, id: &BlockId, kind: &'static str| {
+            if id.0 >= count {
+                errors.push(ValidationError::InvalidBlockId(kind));
+            }
+        };
+        match terminator {
+            Terminator::Jump { target: id } => target(&mut errors, id, "jump_target"),
+            Terminator::Branch { condition, then_block, else_block } => {
+                target(&mut errors, then_block, "then_block");
+                target(&mut errors, else_block, "else_block");
+                if let Some(ty) = self.function.operand_type(condition) {
+                    if !matches!(representation(&ty), Type::I32 | Type::I64) {
+                        errors.push(ValidationError::TypeMismatch { expected: Type::I32, actual: ty });
+                    }
+                }
+            }
+            Terminator::Switch { value, targets, default_target } => {
+                target(&mut errors, default_target, "default_target");
+                let ty = self.function.operand_type(value);
+                for (case, id) in targets {
+                    target(&mut errors, id, "switch_target");
+                    if let Some(ty) = &ty {
+                        self.expect(&mut errors, ty, case);
+                    }
+                }
+            }
+            Terminator::Return { value } => self.check_return(&mut errors, value.as_ref()),
+            Terminator::Unreachable | Terminator::Panic { .. } => {}
+        }
+        for error in errors {
+            self.report(site.clone(), &locals, error);
+        }
+    }
+
+    fn expect(&self, errors: &mut Vec<ValidationError>, expected: &Type, operand: &Operand) {
+        if let Some(actual) = self.function.operand_type(operand) {
+            if !compatible(expected, &actual) {
+                errors.push(ValidationError::TypeMismatch { expected: expected.clone(), actual });
+            }
+        }
+    }
+
+    fn check_return(&self, errors: &mut Vec<ValidationError>, value: Option<&Operand>) {
+        match (&self.function.signature.returns, value) {
+            (Some(expected), Some(value)) => self.expect(errors, expected, value),
+            (Some(expected), None) => {
+                errors.push(ValidationError::TypeMismatch { expected: expected.clone(), actual: Type::Void });
+            }
+            (None, Some(_)) => errors.push(ValidationError::ControlFlowError("value returned without a result type")),
+            (None, None) => {}
+        }
+    }
+
+    fn check_arguments(&self, errors: &mut Vec<ValidationError>, params: &[Type], args: &[Operand]) {
+        if params.len() != args.len() {
+            errors.push(ValidationError::ArgumentCount { expected: params.len(), actual: args.len() });
+            return;
+        }
+        for (param, arg) in params.iter().zip(args) {
+            self.expect(errors, param, arg);
+        }
+    }
+
+    /// Reports reads of locals that some path from the entry reaches without
+    /// writing them first
+    ///
+    /// Engines zero such locals, but a read of one is almost always a
+    /// lowering bug.
+    fn check_definitions(&mut self, cfg: &Cfg) {
+        let params: BTreeSet<u32> = (0..self.function.signature.params.len() as u32).collect();
+        // `None` stands for "every local", the start of a must-analysis
+        let mut written_out: Vec<Option<BTreeSet<u32>>> = alloc::vec![None; cfg.len()];
+        let written_in = |written_out: &[Option<BTreeSet<u32>>], block: BlockId| -> BTreeSet<u32> {
+            if block.0 == 0 {
+                return params.clone();
+            }
+            let mut known = cfg.predecessors(block).iter().filter_map(|pred| written_out[pred.0].as_ref());
+            let first = known.next().cloned().unwrap_or_default();
+            known.fold(first, |acc, set| acc.intersection(set).copied().collect())
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in cfg.reverse_postorder() {
+                let mut written = written_in(&written_out, block);
+                for instruction in &self.function.basic_blocks[block.0].instructions {
+                    if let Instruction::LocalSet { index, .. } = instruction {
+                        written.insert(*index);
+                    }
+                }
+                if written_out[block.0].as_ref() != Some(&written) {
+                    written_out[block.0] = Some(written);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut reported = BTreeSet::new();
+        for &block in cfg.reverse_postorder() {
+            let mut written = written_in(&written_out, block);
+            let basic_block = &self.function.basic_blocks[block.0];
+            for (index, instruction) in basic_block.instructions.iter().enumerate() {
+                for local in read_locals(instruction) {
+                    if !written.contains(&local) && local < self.local_count() && reported.insert(local) {
+                        let site = Site::Instruction { block: block.0, index, name: instruction.name() };
+                        self.report(site, &[local], ValidationError::UseBeforeDef(local));
+                    }
+                }
+                if let Instruction::LocalSet { index, .. } = instruction {
+                    written.insert(*index);
+                }
+            }
+            for local in operand_locals(&terminator_operands(&basic_block.terminator)) {
+                if !written.contains(&local) && local < self.local_count() && reported.insert(local) {
+                    self.report(Site::Terminator { block: block.0 }, &[local], ValidationError::UseBeforeDef(local));
+                }
+            }
+        }
+    }
+
+    /// Reports reads of linear locals that may already have been consumed
+    ///
+    /// Every read consumes a linear local, and writing it makes it usable
+    /// again.
+    fn check_linear_uses(&mut self, cfg: &Cfg) {
+        let linear: BTreeSet<u32> = self.function.locals.iter().enumerate()
+            .filter(|(_, ty)| matches!(ty, Type::Linear { .. }))
+            .map(|(index, _)| index as u32)
+            .collect();
+        if linear.is_empty() {
+            return;
+        }
+
+        let mut consumed_out: Vec<BTreeSet<u32>> = alloc::vec![BTreeSet::new(); cfg.len()];
+        let consumed_in = |consumed_out: &[BTreeSet<u32>], block: BlockId| -> BTreeSet<u32> {
+            cfg.predecessors(block).iter().flat_map(|pred| consumed_out[pred.0].iter().copied()).collect()
+        };
+        let mut reported = BTreeSet::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in cfg.reverse_postorder() {
+                let mut consumed = consumed_in(&consumed_out, block);
+                let basic_block = &self.function.basic_blocks[block.0];
+                for (index, instruction) in basic_block.instructions.iter().enumerate() {
+                    for local in read_locals(instruction).into_iter().filter(|local| linear.contains(local)) {
+                        if !consumed.insert(local) && reported.insert(local) {
+                            let site = Site::Instruction { block: block.0, index, name: instruction.name() };
+                            self.report(site, &[local], ValidationError::LinearReuse(local));
+                        }
+                    }
+                    if let Instruction::LocalSet { index, .. } = instruction {
+                        consumed.remove(index);
+                    }
+                }
+                let terminator = operand_locals(&terminator_operands(&basic_block.terminator));
+                for local in terminator.into_iter().filter(|local| linear.contains(local)) {
+                    if !consumed.insert(local) && reported.insert(local) {
+                        self.report(Site::Terminator { block: block.0 }, &[local], ValidationError::LinearReuse(local));
+                    }
+                }
+                if consumed != consumed_out[block.0] {
+                    consumed_out[block.0] = consumed;
+                    changed = true;
+                }
+            }
+        }
+    }
+}
+
+/// Locals an instruction reads, including through memory addresses
+fn read_locals(instruction: &Instruction) -> Vec<u32> {
+    let mut locals = operand_locals(&instruction_operands(instruction));
+    if let Instruction::LocalGet { index } = instruction {
+        locals.push(*index);
+    }
+    locals
+}
+
+fn operand_locals(operands: &[&Operand]) -> Vec<u32> {
+    fn collect(operand: &Operand, locals: &mut Vec<u32>) {
+        match operand {
+            Operand::Local(index) => locals.push(*index),
+            Operand::MemoryAddress(inner) => collect(inner, locals),
+            _ => {}
+        }
+    }
+    let mut locals = Vec::new();
+    for operand in operands {
+        collect(operand, &mut locals);
+    }
+    locals
+}
+
+/// Type a value is represented as, without linearity or capability wrappers
+fn representation(ty: &Type) -> &Type {
+    match ty {
+        Type::Linear { inner_type } | Type::Capability { inner_type, .. } => representation(inner_type),
+        ty => ty,
+    }
+}
+
+/// Whether a value of type `actual` can be used where `expected` is
+///
+/// Pointers are `i32` addresses, and an externref without a class name
+/// matches any other.
+fn compatible(expected: &Type, actual: &Type) -> bool {
+    match (representation(expected), representation(actual)) {
+        (Type::ExternRef(expected), Type::ExternRef(actual)) => {
+            expected.is_empty() || actual.is_empty() || expected == actual
+        }
+        (Type::Pointer(_) | Type::I32, Type::Pointer(_) | Type::I32) => true,
+        (expected, actual) => expected == actual,
+    }
+}
+
+fn check_numeric(errors: &mut Vec<ValidationError>, operation: String, ty: &Type, integer_only: bool) {
+    let valid = match representation(ty) {
+        Type::I32 | Type::I64 | Type::I128 | Type::Pointer(_) => true,
+        Type::F32 | Type::F64 => !integer_only,
+        _ => false,
+    };
+    if !valid {
+        errors.push(ValidationError::UnsupportedOperandType { operation, ty: ty.clone() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasmir::{Constant, Import, ImportKind, OwnershipAnnotation, OwnershipState, Signature};
+    use alloc::string::ToString;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn function(params: Vec<Type>, locals: Vec<Type>, returns: Option<Type>) -> WasmIR {
+        let mut f = WasmIR::new("f".to_string(), Signature { params, returns });
+        f.locals = locals;
+        f
+    }
+
+    fn errors(diagnostics: &[Diagnostic]) -> Vec<(Site, ValidationError)> {
+        diagnostics.iter().map(|d| (d.site.clone(), d.error.clone())).collect()
+    }
+
+    #[test]
+    fn test_reports_every_problem_with_its_site() {
+        let mut f = function(vec![Type::I32], vec![Type::I32, Type::F64], Some(Type::I32));
+        f.add_basic_block(
+            vec![
+                Instruction::BinaryOp { op: BinaryOp::Mod, left: Operand::Local(1), right: Operand::Constant(Constant::F64(2.0)) },
+                Instruction::LocalSet { index: 0, value: Operand::Local(1) },
+                Instruction::Jump { target: BlockId(1) },
+            ],
+            Terminator::Branch { condition: Operand::Local(1), then_block: BlockId(1), else_block: BlockId(7) },
+        );
+        f.add_basic_block(vec![], Terminator::Return { value: None });
+
+        let instruction = |index, name| Site::Instruction { block: 0, index, name };
+        assert_eq!(errors(&verify_function(&f)), vec![
+            (instruction(0, "binary_op"), ValidationError::UnsupportedOperandType { operation: "Mod".to_string(), ty: Type::F64 }),
+            (instruction(1, "local.set"), ValidationError::TypeMismatch { expected: Type::I32, actual: Type::F64 }),
+            (instruction(2, "jump"), ValidationError::ControlFlowError("branch inside a block instead of its terminator")),
+            (Site::Terminator { block: 0 }, ValidationError::InvalidBlockId("else_block")),
+            (Site::Terminator { block: 0 }, ValidationError::TypeMismatch { expected: Type::I32, actual: Type::F64 }),
+            (Site::Terminator { block: 1 }, ValidationError::TypeMismatch { expected: Type::I32, actual: Type::Void }),
+            (instruction(0, "binary_op"), ValidationError::UseBeforeDef(1)),
+        ]);
+    }
+
+    #[test]
+    fn test_reads_before_writes_and_linear_reuse() {
+        // local 1 is only written on one side of the branch; local 2 is linear
+        let linear = Type::Linear { inner_type: alloc::boxed::Box::new(Type::I32) };
+        let mut f = function(vec![Type::I32], vec![Type::I32, Type::I32, linear], None);
+        let set = |index, value| Instruction::LocalSet { index, value: Operand::Constant(Constant::I32(value)) };
+        f.add_basic_block(vec![set(2, 0)], Terminator::Branch {
+            condition: Operand::Local(0),
+            then_block: BlockId(1),
+            else_block: BlockId(2),
+        });
+        f.add_basic_block(vec![set(1, 1), Instruction::LocalGet { index: 2 }], Terminator::Jump { target: BlockId(2) });
+        f.add_basic_block(
+            vec![Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::Local(1), right: Operand::Local(2) }],
+            Terminator::Return { value: None },
+        );
+        let location = SourceLocation { file: "lib.rs".to_string(), line: 3, column: 9 };
+        f.add_ownership_annotation(OwnershipAnnotation { variable: 2, state: OwnershipState::Owned, source_location: location });
+
+        let diagnostics = verify_function(&f);
+        let site = Site::Instruction { block: 2, index: 0, name: "binary_op" };
+        assert_eq!(errors(&diagnostics), vec![
+            (site.clone(), ValidationError::UseBeforeDef(1)),
+            (site, ValidationError::LinearReuse(2)),
+        ]);
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "lib.rs:3:9: f: binary_op at block 2 instruction 0: Linear local 2 may be used after it was consumed"
+        );
+    }
+
+    #[test]
+    fn test_module_checks_calls_against_callees() {
+        let mut module = WasmModule::new();
+        module.imports.push(Import {
+            module: "env".to_string(),
+            name: "log".to_string(),
+            kind: ImportKind::Function(Signature { params: vec![Type::F64], returns: None }),
+        });
+        let mut caller = function(vec![], vec![], None);
+        caller.add_basic_block(
+            vec![
+                Instruction::Call { func_ref: 0, args: vec![Operand::Constant(Constant::I32(1))] },
+                Instruction::CallImport { import: 0, args: vec![Operand::Constant(Constant::I64(1))] },
+                Instruction::Call { func_ref: 3, args: vec![] },
+            ],
+            Terminator::Return { value: None },
+        );
+        module.add_function(caller);
+
+        assert!(verify_function(&module.functions[0]).is_empty());
+        assert_eq!(verify_module(&module).into_iter().map(|d| d.error).collect::<Vec<_>>(), vec![
+            ValidationError::ArgumentCount { expected: 0, actual: 1 },
+            ValidationError::TypeMismatch { expected: Type::F64, actual: Type::I64 },
+            ValidationError::InvalidFunctionIndex(3),
+        ]);
+    }
+}
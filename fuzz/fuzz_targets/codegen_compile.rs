@@ -0,0 +1,22 @@
+//! Fuzzes `WasmCodegen::compile` with generated WasmIR modules
+//!
+//! The emitter may reject a module, but must not panic, and a well-typed
+//! module it accepts must validate.
+
+#![no_main]
+
+use arbitrary::Unstructured;
+use libfuzzer_sys::fuzz_target;
+use wasmrust_compiler::backend::codegen::WasmCodegen;
+use wasmrust_fuzz::{assert_valid_wasm, wasmir};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(generated) = wasmir::module(&mut Unstructured::new(data)) else {
+        return;
+    };
+    if let Ok(code) = WasmCodegen::new().compile(&generated.module) {
+        if generated.well_typed {
+            assert_valid_wasm(&code, &generated);
+        }
+    }
+});
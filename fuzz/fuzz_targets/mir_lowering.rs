@@ -0,0 +1,26 @@
+//! Fuzzes MIR lowering with generated MIR function trees
+//!
+//! Lowering may reject a tree, but must not panic, and the emitter's output
+//! for a well-typed tree it lowers must validate.
+
+#![no_main]
+
+use arbitrary::Unstructured;
+use libfuzzer_sys::fuzz_target;
+use wasmrust_compiler::backend::codegen::WasmCodegen;
+use wasmrust_compiler::backend::cranelift::mir_lowering::MirLoweringContext;
+use wasmrust_fuzz::{assert_valid_wasm, mir};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(generated) = mir::functions(&mut Unstructured::new(data)) else {
+        return;
+    };
+    let Ok(module) = MirLoweringContext::new().lower_module(&generated.functions) else {
+        return;
+    };
+    if let Ok(code) = WasmCodegen::new().compile(&module) {
+        if generated.well_typed {
+            assert_valid_wasm(&code, &generated);
+        }
+    }
+});
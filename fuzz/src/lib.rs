@@ -0,0 +1,27 @@
+//! Input generators for the fuzz targets
+//!
+//! Both generators build programs type by type, so whatever they mark well
+//! typed must either be rejected with an error or compile to a module
+//! `wasmparser` accepts. A small share of operands is deliberately
+//! corrupted to also exercise the error paths; those inputs only have to
+//! not panic.
+
+pub mod mir;
+pub mod wasmir;
+
+use arbitrary::{Result, Unstructured};
+
+/// Chance, out of 64, that a generated operand is corrupted
+const CORRUPTION_RATE: u8 = 1;
+
+/// Decides whether to corrupt the next operand
+fn corrupt(u: &mut Unstructured) -> Result<bool> {
+    u.ratio(CORRUPTION_RATE, 64)
+}
+
+/// Checks an emitted binary, panicking with the input that produced it
+pub fn assert_valid_wasm(code: &[u8], input: &dyn std::fmt::Debug) {
+    if let Err(e) = wasmparser::validate(code) {
+        panic!("emitted an invalid module: {}\ninput: {:#?}", e, input);
+    }
+}
@@ -0,0 +1,236 @@
+//! MIR function trees for the lowering
+//!
+//! Lowering does not yet return a value from `MirTerminator::Return`, so
+//! generated functions return unit; a value-returning function would be
+//! flagged on its first input.
+
+use crate::corrupt;
+use arbitrary::{Result, Unstructured};
+use wasmrust_compiler::backend::cranelift::mir_lowering::{
+    MirAttribute, MirBasicBlock, MirBinOp, MirConstant, MirFunction, MirLocalDecl, MirOperand, MirPlace,
+    MirProjection, MirRvalue, MirSignature, MirSourceInfo, MirSpan, MirStatement, MirTerminator, MirType, MirUnOp,
+};
+
+/// Generated functions and whether every operand was generated well typed
+#[derive(Debug)]
+pub struct GeneratedFunctions {
+    pub functions: Vec<MirFunction>,
+    pub well_typed: bool,
+}
+
+/// Generates one to three functions
+pub fn functions(u: &mut Unstructured) -> Result<GeneratedFunctions> {
+    let mut generator = Generator { well_typed: true, start: false };
+    let mut functions = Vec::new();
+    for index in 0..u.int_in_range(1..=3)? {
+        functions.push(generator.function(u, index)?);
+    }
+    Ok(GeneratedFunctions { functions, well_typed: generator.well_typed })
+}
+
+/// WebAssembly value type a MIR type lowers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lowered {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+fn lowered(ty: &MirType) -> Lowered {
+    match ty {
+        MirType::I64 | MirType::U64 => Lowered::I64,
+        MirType::F32 => Lowered::F32,
+        MirType::F64 => Lowered::F64,
+        _ => Lowered::I32,
+    }
+}
+
+fn scalar(u: &mut Unstructured) -> Result<MirType> {
+    Ok(match u.int_in_range(0..=7)? {
+        0 => MirType::I32,
+        1 => MirType::I64,
+        2 => MirType::F32,
+        3 => MirType::F64,
+        4 => MirType::Bool,
+        5 => MirType::U32,
+        6 => MirType::U64,
+        _ => MirType::U8,
+    })
+}
+
+fn constant(u: &mut Unstructured, ty: Lowered) -> Result<MirConstant> {
+    Ok(match ty {
+        Lowered::I32 if u.arbitrary()? => MirConstant::Bool(u.arbitrary()?),
+        Lowered::I32 => MirConstant::I32(u.arbitrary()?),
+        Lowered::I64 => MirConstant::I64(u.arbitrary()?),
+        Lowered::F32 => MirConstant::F32(u.arbitrary()?),
+        Lowered::F64 => MirConstant::F64(u.arbitrary()?),
+    })
+}
+
+fn source_info(line: u32) -> MirSourceInfo {
+    MirSourceInfo { span: MirSpan { filename: "fuzz.rs".to_string(), line, column: 1 } }
+}
+
+struct Generator {
+    well_typed: bool,
+    start: bool,
+}
+
+impl Generator {
+    fn function(&mut self, u: &mut Unstructured, index: usize) -> Result<MirFunction> {
+        let inputs: Vec<MirType> = (0..u.int_in_range(0..=3)?).map(|_| scalar(u)).collect::<Result<_>>()?;
+        // Locals start with the arguments
+        let mut local_decls: Vec<MirLocalDecl> = inputs.iter()
+            .map(|ty| MirLocalDecl { ty: ty.clone(), source_info: source_info(1) })
+            .collect();
+        for line in 0..u.int_in_range(0..=4)? {
+            local_decls.push(MirLocalDecl { ty: scalar(u)?, source_info: source_info(line + 2) });
+        }
+        let locals: Vec<Lowered> = local_decls.iter().map(|decl| lowered(&decl.ty)).collect();
+
+        let mut attributes = Vec::new();
+        if inputs.is_empty() && u.ratio(1, 4)? {
+            if !self.start && u.arbitrary()? {
+                self.start = true;
+                attributes.push(MirAttribute::Start);
+            } else {
+                attributes.push(MirAttribute::Constructor { priority: u.int_in_range(0..=3)? });
+            }
+        }
+
+        let blocks = u.int_in_range(1..=4)?;
+        let mut basic_blocks = Vec::new();
+        for _ in 0..blocks {
+            let mut statements = Vec::new();
+            for _ in 0..u.int_in_range(0..=6)? {
+                statements.push(self.statement(u, &locals)?);
+            }
+            let terminator = self.terminator(u, &locals, blocks)?;
+            basic_blocks.push(MirBasicBlock { statements, terminator });
+        }
+
+        Ok(MirFunction {
+            name: format!("f{}", index),
+            signature: MirSignature { inputs, output: MirType::Unit },
+            basic_blocks,
+            local_decls,
+            source_info: source_info(0),
+            attributes,
+        })
+    }
+
+    fn place(&mut self, u: &mut Unstructured, local: u32) -> Result<MirPlace> {
+        let place = MirPlace::Local(local);
+        // Every local is a scalar, which the lowering cannot project
+        let projection = match u.int_in_range(0..=7)? {
+            0 => MirProjection::Deref,
+            1 => MirProjection::Field(0),
+            _ => return Ok(place),
+        };
+        self.well_typed = false;
+        Ok(MirPlace::Projection(Box::new(place), Box::new(projection)))
+    }
+
+    /// Picks an operand that lowers to `ty`
+    fn operand(&mut self, u: &mut Unstructured, locals: &[Lowered], ty: Lowered) -> Result<MirOperand> {
+        if corrupt(u)? {
+            self.well_typed = false;
+            let local = u.int_in_range(0..=locals.len() as u32)?;
+            return Ok(MirOperand::Copy(Box::new(MirPlace::Local(local))));
+        }
+        let matching: Vec<u32> = (0..locals.len() as u32).filter(|&local| locals[local as usize] == ty).collect();
+        if !matching.is_empty() && u.arbitrary()? {
+            let local = *u.choose(&matching)?;
+            let place = Box::new(self.place(u, local)?);
+            return Ok(if u.arbitrary()? { MirOperand::Move(place) } else { MirOperand::Copy(place) });
+        }
+        Ok(MirOperand::Constant(constant(u, ty)?))
+    }
+
+    fn statement(&mut self, u: &mut Unstructured, locals: &[Lowered]) -> Result<MirStatement> {
+        if locals.is_empty() || u.ratio(1, 6)? {
+            return Ok(MirStatement::Nop);
+        }
+        let local = u.choose_index(locals.len())? as u32;
+        let ty = locals[local as usize];
+        let rvalue = match u.int_in_range(0..=5)? {
+            0 => return Ok(MirStatement::StorageLive(local)),
+            1 => return Ok(MirStatement::StorageDead(local)),
+            2 => MirRvalue::Use(self.operand(u, locals, ty)?),
+            3 => MirRvalue::UnaryOp(*u.choose(&[MirUnOp::Not, MirUnOp::Neg])?, self.operand(u, locals, ty)?),
+            4 if ty == Lowered::I32 && u.arbitrary()? => {
+                // Comparisons of any operand type produce an i32
+                use MirBinOp::*;
+                let op = *u.choose(&[Eq, Lt, Le, Ne, Ge, Gt])?;
+                let operands = *u.choose(&[Lowered::I32, Lowered::I64, Lowered::F32, Lowered::F64])?;
+                MirRvalue::BinaryOp(op, self.operand(u, locals, operands)?, self.operand(u, locals, operands)?)
+            }
+            4 => {
+                use MirBinOp::*;
+                let op = *u.choose(&[Add, Sub, Mul, Div, Rem, BitXor, BitAnd, BitOr, Shl, Shr])?;
+                MirRvalue::BinaryOp(op, self.operand(u, locals, ty)?, self.operand(u, locals, ty)?)
+            }
+            _ => {
+                // Numeric casts from any scalar; only `bool` cannot be a target
+                let target = match ty {
+                    Lowered::I32 => u.choose(&[MirType::I32, MirType::U32, MirType::U8])?.clone(),
+                    Lowered::I64 => u.choose(&[MirType::I64, MirType::U64])?.clone(),
+                    Lowered::F32 => MirType::F32,
+                    Lowered::F64 => MirType::F64,
+                };
+                let source = *u.choose(&[Lowered::I32, Lowered::I64, Lowered::F32, Lowered::F64])?;
+                MirRvalue::Cast(self.operand(u, locals, source)?, target)
+            }
+        };
+        Ok(MirStatement::Assign(self.place(u, local)?, rvalue))
+    }
+
+    fn terminator(&mut self, u: &mut Unstructured, locals: &[Lowered], blocks: usize) -> Result<MirTerminator> {
+        let mut target = |u: &mut Unstructured| -> Result<u32> {
+            if corrupt(u)? {
+                self.well_typed = false;
+                return Ok(blocks as u32);
+            }
+            Ok(u.choose_index(blocks)? as u32)
+        };
+        let terminator = match u.int_in_range(0..=6)? {
+            0 => MirTerminator::Goto { target: target(u)? },
+            1 => {
+                let mut targets = Vec::new();
+                for _ in 0..u.int_in_range(0..=3)? {
+                    targets.push((u.arbitrary()?, target(u)?));
+                }
+                let otherwise = target(u)?;
+                MirTerminator::SwitchInt { discr: self.operand(u, locals, Lowered::I32)?, targets, otherwise }
+            }
+            2 => {
+                let args = (0..u.int_in_range(0..=2)?)
+                    .map(|_| {
+                        let ty = *u.choose(&[Lowered::I32, Lowered::I64, Lowered::F32, Lowered::F64])?;
+                        self.operand(u, locals, ty)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let destination = match locals.len() {
+                    0 => None,
+                    len => Some((MirPlace::Local(u.choose_index(len)? as u32), u.choose_index(blocks)? as u32)),
+                };
+                MirTerminator::Call { func: MirOperand::Constant(MirConstant::Unit), args, destination }
+            }
+            3 => {
+                let target = target(u)?;
+                MirTerminator::Assert {
+                    cond: self.operand(u, locals, Lowered::I32)?,
+                    expected: u.arbitrary()?,
+                    message: "assertion failed".to_string(),
+                    target,
+                }
+            }
+            4 => MirTerminator::Panic { message: "explicit panic".to_string() },
+            5 => MirTerminator::Unreachable,
+            _ => MirTerminator::Return,
+        };
+        Ok(terminator)
+    }
+}
@@ -0,0 +1,242 @@
+//! WasmIR modules over the instructions the binary emitter encodes
+
+use crate::corrupt;
+use arbitrary::{Result, Unstructured};
+use wasm::wasmir::{
+    BinaryOp, BlockId, Constant, ConvertOp, Instruction, MemoryType, Operand, Signature, Terminator, Type, UnaryOp, WasmIR,
+    WasmModule,
+};
+
+const TYPES: [Type; 4] = [Type::I32, Type::I64, Type::F32, Type::F64];
+
+/// Generated module and whether every operand was generated well typed
+#[derive(Debug)]
+pub struct GeneratedModule {
+    pub module: WasmModule,
+    pub well_typed: bool,
+}
+
+/// Generates a module of one to four exported functions
+pub fn module(u: &mut Unstructured) -> Result<GeneratedModule> {
+    let mut generator = Generator { well_typed: true, signatures: Vec::new(), memory: u.arbitrary()? };
+    for _ in 0..u.int_in_range(1..=4)? {
+        let params = (0..u.int_in_range(0..=3)?).map(|_| numeric(u)).collect::<Result<_>>()?;
+        let returns = if u.arbitrary()? { Some(numeric(u)?) } else { None };
+        generator.signatures.push(Signature { params, returns });
+    }
+
+    let mut module = WasmModule::new();
+    if generator.memory {
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+    }
+    for index in 0..generator.signatures.len() {
+        let function = generator.function(u, index)?;
+        let name = function.name.clone();
+        let index = module.add_function(function);
+        module.export_function(name, index);
+    }
+    Ok(GeneratedModule { module, well_typed: generator.well_typed })
+}
+
+fn numeric(u: &mut Unstructured) -> Result<Type> {
+    u.choose(&TYPES).cloned()
+}
+
+fn constant(u: &mut Unstructured, ty: &Type) -> Result<Constant> {
+    Ok(match ty {
+        Type::I64 => Constant::I64(u.arbitrary()?),
+        Type::F32 => Constant::F32(u.arbitrary()?),
+        Type::F64 => Constant::F64(u.arbitrary()?),
+        _ => Constant::I32(u.arbitrary()?),
+    })
+}
+
+fn binary_ops(ty: &Type) -> &'static [BinaryOp] {
+    use BinaryOp::*;
+    match ty {
+        Type::I32 | Type::I64 => &[Add, Sub, Mul, Div, Mod, And, Or, Xor, Shl, Shr, Sar, Eq, Ne, Lt, Le, Gt, Ge],
+        _ => &[Add, Sub, Mul, Div, Eq, Ne, Lt, Le, Gt, Ge],
+    }
+}
+
+struct Generator {
+    well_typed: bool,
+    signatures: Vec<Signature>,
+    memory: bool,
+}
+
+/// Function being generated and the types its instructions left behind
+struct Body<'a> {
+    locals: &'a [Type],
+    stack: Vec<Type>,
+}
+
+impl Generator {
+    fn function(&mut self, u: &mut Unstructured, index: usize) -> Result<WasmIR> {
+        let signature = self.signatures[index].clone();
+        let mut function = WasmIR::new(format!("f{}", index), signature.clone());
+        function.locals = signature.params.clone();
+        for _ in 0..u.int_in_range(0..=3)? {
+            function.locals.push(numeric(u)?);
+        }
+
+        let blocks = u.int_in_range(1..=4)?;
+        for _ in 0..blocks {
+            let mut body = Body { locals: &function.locals, stack: Vec::new() };
+            let mut instructions = Vec::new();
+            for _ in 0..u.int_in_range(0..=8)? {
+                instructions.push(self.instruction(u, &mut body)?);
+            }
+            let terminator = self.terminator(u, &mut body, &signature, blocks)?;
+            function.add_basic_block(instructions, terminator);
+        }
+        Ok(function)
+    }
+
+    /// Picks an operand of type `ty`, taking it from the stack if allowed
+    fn operand(&mut self, u: &mut Unstructured, body: &mut Body, ty: &Type, from_stack: bool) -> Result<Operand> {
+        if corrupt(u)? {
+            self.well_typed = false;
+            return Ok(match u.int_in_range(0..=3)? {
+                0 => Operand::Local(u.int_in_range(0..=body.locals.len() as u32)?),
+                1 => Operand::StackValue(0),
+                2 => Operand::Global(0),
+                _ => {
+                    let ty = numeric(u)?;
+                    Operand::Constant(constant(u, &ty)?)
+                }
+            });
+        }
+        if from_stack && body.stack.last() == Some(ty) && u.arbitrary()? {
+            body.stack.pop();
+            return Ok(Operand::StackValue(0));
+        }
+        let matching: Vec<u32> = (0..body.locals.len() as u32)
+            .filter(|&index| &body.locals[index as usize] == ty)
+            .collect();
+        if !matching.is_empty() && u.arbitrary()? {
+            return Ok(Operand::Local(*u.choose(&matching)?));
+        }
+        Ok(Operand::Constant(constant(u, ty)?))
+    }
+
+    fn instruction(&mut self, u: &mut Unstructured, body: &mut Body) -> Result<Instruction> {
+        let instruction = match u.int_in_range(0..=8)? {
+            0 if !body.locals.is_empty() => {
+                let index = u.int_in_range(0..=body.locals.len() as u32 - 1)?;
+                body.stack.push(body.locals[index as usize].clone());
+                Instruction::LocalGet { index }
+            }
+            1 if !body.locals.is_empty() => {
+                let index = u.int_in_range(0..=body.locals.len() as u32 - 1)?;
+                let locals = body.locals;
+                let value = self.operand(u, body, &locals[index as usize], true)?;
+                Instruction::LocalSet { index, value }
+            }
+            2 => {
+                let ty = numeric(u)?;
+                let op = *u.choose(binary_ops(&ty))?;
+                let left = self.operand(u, body, &ty, true)?;
+                let right = self.operand(u, body, &ty, matches!(left, Operand::StackValue(_)))?;
+                body.stack.push(match op {
+                    BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => Type::I32,
+                    _ => ty,
+                });
+                Instruction::BinaryOp { op, left, right }
+            }
+            3 => {
+                let ty = numeric(u)?;
+                let op = match ty {
+                    Type::I32 | Type::I64 => *u.choose(&[UnaryOp::Neg, UnaryOp::Not, UnaryOp::Clz, UnaryOp::Ctz, UnaryOp::Popcnt])?,
+                    _ => UnaryOp::Neg,
+                };
+                let value = self.operand(u, body, &ty, true)?;
+                body.stack.push(ty);
+                Instruction::UnaryOp { op, value }
+            }
+            4 => {
+                let func_ref = u.choose_index(self.signatures.len())?;
+                let signature = self.signatures[func_ref].clone();
+                // Stack values may only lead the arguments
+                let mut from_stack = true;
+                let mut args = Vec::new();
+                for param in &signature.params {
+                    let arg = self.operand(u, body, param, from_stack)?;
+                    from_stack &= matches!(arg, Operand::StackValue(_));
+                    args.push(arg);
+                }
+                // Arguments were taken from the top down, but are passed bottom up
+                let leading = args.iter().take_while(|arg| matches!(arg, Operand::StackValue(_))).count();
+                if leading > 1 && signature.params[..leading].iter().any(|ty| ty != &signature.params[0]) {
+                    self.well_typed = false;
+                }
+                body.stack.extend(signature.returns);
+                Instruction::Call { func_ref: func_ref as u32, args }
+            }
+            5 if self.memory => {
+                let address = self.operand(u, body, &Type::I32, true)?;
+                let ty = numeric(u)?;
+                body.stack.push(ty.clone());
+                Instruction::MemoryLoad { address, ty, align: None, offset: u.int_in_range(0..=64)? }
+            }
+            6 if self.memory => {
+                let ty = numeric(u)?;
+                let value = self.operand(u, body, &ty, true)?;
+                let address = self.operand(u, body, &Type::I32, matches!(value, Operand::StackValue(_)))?;
+                Instruction::MemoryStore { address, value, ty, align: None, offset: u.int_in_range(0..=64)? }
+            }
+            7 => {
+                let ty = numeric(u)?;
+                let (to, signed) = (numeric(u)?, u.arbitrary()?);
+                let ops = [
+                    ConvertOp::Wrap,
+                    ConvertOp::Extend { signed },
+                    ConvertOp::SignExtend { bits: *u.choose(&[8, 16, 32])? },
+                    ConvertOp::Trunc { to: to.clone(), signed },
+                    ConvertOp::TruncSat { to: to.clone(), signed },
+                    ConvertOp::FromInt { to, signed },
+                    ConvertOp::Promote,
+                    ConvertOp::Demote,
+                    ConvertOp::Reinterpret,
+                ];
+                // Reinterpret applies to every numeric type
+                let ops: Vec<ConvertOp> = ops.into_iter().filter(|op| op.result(&ty).is_some()).collect();
+                let op = u.choose(&ops)?.clone();
+                let value = self.operand(u, body, &ty, true)?;
+                body.stack.extend(op.result(&ty));
+                Instruction::Convert { op, value }
+            }
+            _ => Instruction::Nop,
+        };
+        Ok(instruction)
+    }
+
+    fn terminator(&mut self, u: &mut Unstructured, body: &mut Body, signature: &Signature, blocks: usize) -> Result<Terminator> {
+        let target = |u: &mut Unstructured| u.choose_index(blocks).map(BlockId);
+        let terminator = match u.int_in_range(0..=4)? {
+            0 => Terminator::Jump { target: target(u)? },
+            1 => {
+                let condition = self.operand(u, body, &Type::I32, body.stack.len() == 1)?;
+                Terminator::Branch { condition, then_block: target(u)?, else_block: target(u)? }
+            }
+            2 => {
+                let ty = numeric(u)?;
+                let value = self.operand(u, body, &ty, false)?;
+                let mut targets = Vec::new();
+                for _ in 0..u.int_in_range(0..=3)? {
+                    targets.push((Operand::Constant(constant(u, &ty)?), target(u)?));
+                }
+                Terminator::Switch { value, targets, default_target: target(u)? }
+            }
+            3 => Terminator::Unreachable,
+            _ => {
+                let value = match &signature.returns {
+                    Some(ty) => Some(self.operand(u, body, ty, body.stack.len() == 1)?),
+                    None => None,
+                };
+                Terminator::Return { value }
+            }
+        };
+        Ok(terminator)
+    }
+}
@@ -0,0 +1,492 @@
+//! ABI compatibility of compiled modules with their host
+//!
+//! Hosts are often versioned separately from the modules they run. A
+//! `HostInterface` declares the functions a host provides for modules to
+//! import and the exports it calls, with their core signatures, and
+//! `HostInterface::check` diffs a compiled module against it. Every import
+//! the host does not provide, export it calls that is missing, and
+//! signature that differs is reported, so they are caught before
+//! deployment rather than when the module is instantiated.
+//!
+//! Interfaces come from a JSON descriptor:
+//!
+//! ```json
+//! {
+//!   "imports": { "env": { "log": { "params": ["i32", "i32"], "results": [] } } },
+//!   "exports": { "run": { "params": [], "results": ["i32"] } }
+//! }
+//! ```
+//!
+//! with value types `i32`, `i64`, `f32`, `f64`, `funcref`, and
+//! `externref`, or from a WIT world, whose functions are flattened under
+//! the canonical ABI and matched under the names `wasm::component::Adapter`
+//! tries. Only functions are compared; memories, tables, and globals a
+//! module imports are left to the host.
+
+use crate::backend::codegen::ValType;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasm::component::adapter::{Adapter, CoreSignature};
+use wasm::component::wit::WitPackage;
+use wasmparser::{BinaryReaderError, ExternalKind, Parser, Payload, RefType, TypeRef};
+
+/// Core signature of a function crossing the boundary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl fmt::Display for FunctionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = |types: &[ValType]| types.iter().map(|&ty| type_name(ty)).collect::<Vec<_>>().join(", ");
+        write!(f, "({}) -> ({})", names(&self.params), names(&self.results))
+    }
+}
+
+/// Function a host provides to modules or calls on them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostFunction {
+    /// Module of an import, empty for exports
+    pub module: String,
+    pub name: String,
+    pub ty: FunctionType,
+    /// Other `(module, name)`s a module may use for the function
+    pub aliases: Vec<(String, String)>,
+}
+
+impl HostFunction {
+    pub fn new(module: impl Into<String>, name: impl Into<String>, ty: FunctionType) -> Self {
+        Self { module: module.into(), name: name.into(), ty, aliases: Vec::new() }
+    }
+
+    /// Also matches the function under `module` and `name`
+    pub fn alias(mut self, module: impl Into<String>, name: impl Into<String>) -> Self {
+        let (module, name) = (module.into(), name.into());
+        if !self.answers_to(&module, &name) {
+            self.aliases.push((module, name));
+        }
+        self
+    }
+
+    /// Whether a module refers to the function as `module` and `name`
+    pub fn answers_to(&self, module: &str, name: &str) -> bool {
+        (self.module == module && self.name == name)
+            || self.aliases.iter().any(|(alias_module, alias)| alias_module == module && alias == name)
+    }
+
+    /// Names of the function, the declared one first
+    fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str()).chain(self.aliases.iter().map(|(_, name)| name.as_str()))
+    }
+
+    fn display(&self) -> String {
+        match self.module.is_empty() {
+            true => self.name.clone(),
+            false => format!("{}.{}", self.module, self.name),
+        }
+    }
+}
+
+/// Functions a host provides to modules and calls on them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostInterface {
+    pub imports: Vec<HostFunction>,
+    pub exports: Vec<HostFunction>,
+}
+
+impl HostInterface {
+    /// Reads the world of a `.wit` file, or a JSON descriptor from any other
+    ///
+    /// `world` may be left out when the package declares only one.
+    pub fn load(path: &Path, world: Option<&str>) -> Result<Self, AbiError> {
+        let source = fs::read_to_string(path).map_err(|err| AbiError::Io(path.to_path_buf(), err.to_string()))?;
+        if path.extension().and_then(|extension| extension.to_str()) != Some("wit") {
+            return Self::from_json(&source);
+        }
+        let package = WitPackage::parse(&source).map_err(|err| AbiError::Wit(err.to_string()))?;
+        let world = match (world, package.worlds.as_slice()) {
+            (Some(world), _) => world.to_string(),
+            (None, [only]) => only.name.clone(),
+            (None, worlds) => {
+                return Err(AbiError::Wit(format!(
+                    "{} declares {} worlds; name the one to check against",
+                    path.display(),
+                    worlds.len()
+                )))
+            }
+        };
+        Self::from_wit(&package, &world)
+    }
+
+    /// Parses a JSON descriptor
+    pub fn from_json(text: &str) -> Result<Self, AbiError> {
+        let value: Value = serde_json::from_str(text).map_err(|err| AbiError::Descriptor(err.to_string()))?;
+        if !value.is_object() {
+            return Err(AbiError::Descriptor("expected an object of `imports` and `exports`".to_string()));
+        }
+        let mut interface = Self::default();
+        for (module, functions) in members(&value["imports"], "imports")? {
+            for (name, ty) in members(functions, &format!("imports.{}", module))? {
+                let ty = function_type(ty, &format!("imports.{}.{}", module, name))?;
+                interface.imports.push(HostFunction::new(module, name, ty));
+            }
+        }
+        for (name, ty) in members(&value["exports"], "exports")? {
+            let ty = function_type(ty, &format!("exports.{}", name))?;
+            interface.exports.push(HostFunction::new("", name, ty));
+        }
+        Ok(interface)
+    }
+
+    /// Interface of a host running components of `world` from core modules
+    ///
+    /// Imports are expected from the interface's qualified or plain name,
+    /// and exports as `<interface>#<func>`, `<func>`, or `<func>` with `_`.
+    pub fn from_wit(package: &WitPackage, world: &str) -> Result<Self, AbiError> {
+        let interfaces = package.world_interfaces(world).map_err(|err| AbiError::Wit(err.to_string()))?;
+        let adapter = Adapter::new(package, world);
+        let mut host = Self::default();
+        for (qualified, interface) in &interfaces.imports {
+            for function in &interface.functions {
+                let signature = adapter.core_signature(interface, function, false);
+                let snake = function.name.replace('-', "_");
+                let import = HostFunction::new(qualified, &function.name, core_type(signature)?)
+                    .alias(qualified, &snake)
+                    .alias(&interface.name, &function.name)
+                    .alias(&interface.name, &snake);
+                host.imports.push(import);
+            }
+        }
+        for (qualified, interface) in &interfaces.exports {
+            for function in &interface.functions {
+                let signature = adapter.core_signature(interface, function, true);
+                let export = HostFunction::new("", format!("{}#{}", qualified, function.name), core_type(signature)?)
+                    .alias("", format!("{}#{}", interface.name, function.name))
+                    .alias("", &function.name)
+                    .alias("", function.name.replace('-', "_"));
+                host.exports.push(export);
+            }
+        }
+        Ok(host)
+    }
+
+    /// Every way the compiled module `wasm` disagrees with the interface
+    pub fn check(&self, wasm: &[u8]) -> Result<Vec<AbiMismatch>, AbiError> {
+        let module = ModuleFunctions::read(wasm)?;
+        let mut mismatches = Vec::new();
+        for (from, name, ty) in &module.imports {
+            match self.imports.iter().find(|host| host.answers_to(from, name)) {
+                None => mismatches.push(AbiMismatch::UnresolvedImport { module: from.clone(), name: name.clone() }),
+                Some(host) if host.ty != *ty => mismatches.push(AbiMismatch::ImportSignature {
+                    module: from.clone(),
+                    name: name.clone(),
+                    expected: host.ty.clone(),
+                    found: ty.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for host in &self.exports {
+            let export = host.names().find_map(|name| module.exports.iter().find(|(export, _)| export == name));
+            match export {
+                None => mismatches.push(AbiMismatch::MissingExport(host.display())),
+                Some((name, ty)) if host.ty != *ty => mismatches.push(AbiMismatch::ExportSignature {
+                    name: name.clone(),
+                    expected: host.ty.clone(),
+                    found: ty.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+/// Way a module disagrees with its host's interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiMismatch {
+    /// The module imports a function the host does not provide
+    UnresolvedImport { module: String, name: String },
+    /// The host provides an imported function with another signature
+    ImportSignature { module: String, name: String, expected: FunctionType, found: FunctionType },
+    /// The host calls a function the module does not export
+    MissingExport(String),
+    /// The module exports a function the host calls with another signature
+    ExportSignature { name: String, expected: FunctionType, found: FunctionType },
+}
+
+impl fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbiMismatch::UnresolvedImport { module, name } => {
+                write!(f, "the module imports `{}.{}`, which the host does not provide", module, name)
+            }
+            AbiMismatch::ImportSignature { module, name, expected, found } => write!(
+                f,
+                "the module imports `{}.{}` as {}, but the host provides {}",
+                module, name, found, expected
+            ),
+            AbiMismatch::MissingExport(name) => {
+                write!(f, "the host calls `{}`, which the module does not export", name)
+            }
+            AbiMismatch::ExportSignature { name, expected, found } => {
+                write!(f, "the host calls `{}` as {}, but the module exports {}", name, expected, found)
+            }
+        }
+    }
+}
+
+/// Reasons a module cannot be checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiError {
+    Io(PathBuf, String),
+    /// The module does not parse, or passes values hosts cannot
+    Read(String),
+    /// The JSON descriptor is malformed
+    Descriptor(String),
+    /// The WIT world does not resolve, or uses what core modules cannot
+    Wit(String),
+}
+
+impl fmt::Display for AbiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbiError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            AbiError::Read(err) => write!(f, "cannot read the module: {}", err),
+            AbiError::Descriptor(err) => write!(f, "malformed host interface: {}", err),
+            AbiError::Wit(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AbiError {}
+
+/// Function imports and exports of a compiled module
+struct ModuleFunctions {
+    imports: Vec<(String, String, FunctionType)>,
+    exports: Vec<(String, FunctionType)>,
+}
+
+impl ModuleFunctions {
+    fn read(wasm: &[u8]) -> Result<Self, AbiError> {
+        let invalid = |err: BinaryReaderError| AbiError::Read(err.to_string());
+        let mut types = Vec::new();
+        // Type index of every function, imports first
+        let mut functions = Vec::new();
+        let mut imports = Vec::new();
+        let mut exports = Vec::new();
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload.map_err(invalid)? {
+                Payload::TypeSection(reader) => {
+                    for ty in reader.into_iter_err_on_gc_types() {
+                        let ty = ty.map_err(invalid)?;
+                        let params = ty.params().iter().map(|&ty| val_type(ty)).collect::<Option<_>>();
+                        let results = ty.results().iter().map(|&ty| val_type(ty)).collect::<Option<_>>();
+                        types.push(params.zip(results).map(|(params, results)| FunctionType { params, results }));
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import.map_err(invalid)?;
+                        if let TypeRef::Func(index) = import.ty {
+                            functions.push(index);
+                            imports.push((import.module.to_string(), import.name.to_string(), index));
+                        }
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    for index in reader {
+                        functions.push(index.map_err(invalid)?);
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        let export = export.map_err(invalid)?;
+                        if export.kind == ExternalKind::Func {
+                            exports.push((export.name.to_string(), export.index));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let type_of = |index: u32, name: &str| match types.get(index as usize) {
+            Some(Some(ty)) => Ok(FunctionType::clone(ty)),
+            Some(None) => Err(AbiError::Read(format!("`{}` passes values other than numbers and references", name))),
+            None => Err(AbiError::Read(format!("`{}` has no type {}", name, index))),
+        };
+        let imports = imports.into_iter()
+            .map(|(module, name, index)| {
+                let ty = type_of(index, &format!("{}.{}", module, name))?;
+                Ok((module, name, ty))
+            })
+            .collect::<Result<_, AbiError>>()?;
+        let exports = exports.into_iter()
+            .map(|(name, function)| {
+                let index = functions.get(function as usize)
+                    .ok_or_else(|| AbiError::Read(format!("export `{}` of unknown function {}", name, function)))?;
+                Ok((name.clone(), type_of(*index, &name)?))
+            })
+            .collect::<Result<_, AbiError>>()?;
+        Ok(Self { imports, exports })
+    }
+}
+
+/// Members of a descriptor object, or none if it is absent
+fn members<'v>(value: &'v Value, path: &str) -> Result<Vec<(&'v str, &'v Value)>, AbiError> {
+    match value {
+        Value::Null => Ok(Vec::new()),
+        Value::Object(map) => Ok(map.iter().map(|(key, value)| (key.as_str(), value)).collect()),
+        _ => Err(AbiError::Descriptor(format!("`{}` must be an object", path))),
+    }
+}
+
+/// Signature of a descriptor function, whose `params` and `results` default to none
+fn function_type(value: &Value, path: &str) -> Result<FunctionType, AbiError> {
+    if !value.is_object() {
+        return Err(AbiError::Descriptor(format!("`{}` must be an object of `params` and `results`", path)));
+    }
+    let list = |key: &str| -> Result<Vec<ValType>, AbiError> {
+        let path = format!("{}.{}", path, key);
+        match &value[key] {
+            Value::Null => Ok(Vec::new()),
+            Value::Array(types) => types.iter()
+                .map(|ty| {
+                    ty.as_str().and_then(parse_type).ok_or_else(|| {
+                        AbiError::Descriptor(format!(
+                            "`{}` has unknown type {}; expected i32, i64, f32, f64, funcref, or externref",
+                            path, ty
+                        ))
+                    })
+                })
+                .collect(),
+            _ => Err(AbiError::Descriptor(format!("`{}` must be an array", path))),
+        }
+    };
+    Ok(FunctionType { params: list("params")?, results: list("results")? })
+}
+
+fn core_type(signature: Result<CoreSignature, wasm::component::AdapterError>) -> Result<FunctionType, AbiError> {
+    let signature = signature.map_err(|err| AbiError::Wit(err.to_string()))?;
+    let types = |types: &[wasm::wasmir::Type]| {
+        types.iter()
+            .map(ValType::from_type)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| AbiError::Wit(err.to_string()))
+    };
+    Ok(FunctionType { params: types(&signature.params)?, results: types(&signature.results)? })
+}
+
+fn val_type(ty: wasmparser::ValType) -> Option<ValType> {
+    match ty {
+        wasmparser::ValType::I32 => Some(ValType::I32),
+        wasmparser::ValType::I64 => Some(ValType::I64),
+        wasmparser::ValType::F32 => Some(ValType::F32),
+        wasmparser::ValType::F64 => Some(ValType::F64),
+        wasmparser::ValType::Ref(RefType::FUNCREF) => Some(ValType::FuncRef),
+        wasmparser::ValType::Ref(RefType::EXTERNREF) => Some(ValType::ExternRef),
+        _ => None,
+    }
+}
+
+fn type_name(ty: ValType) -> &'static str {
+    match ty {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+        ValType::FuncRef => "funcref",
+        ValType::ExternRef => "externref",
+    }
+}
+
+fn parse_type(name: &str) -> Option<ValType> {
+    [ValType::I32, ValType::I64, ValType::F32, ValType::F64, ValType::FuncRef, ValType::ExternRef]
+        .into_iter()
+        .find(|&ty| type_name(ty) == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::WasmCodegen;
+    use wasm::wasmir::{Constant, Operand, Signature, Terminator, Type, WasmIR, WasmModule};
+
+    /// Module importing `from.log(i32, i32)` and exporting `name() -> i32`
+    fn module(from: &str, name: &str) -> Vec<u8> {
+        let mut module = WasmModule::new();
+        module.add_import(from, "log", Signature { params: vec![Type::I32, Type::I32], returns: None });
+        let mut run = WasmIR::new("run".to_string(), Signature { params: vec![], returns: Some(Type::I32) });
+        run.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(Constant::I32(0))) });
+        let index = module.add_function(run);
+        module.export_function(name, index);
+        WasmCodegen::new().compile(&module).unwrap()
+    }
+
+    const HOST: &str = r#"{
+        "imports": { "env": { "log": { "params": ["i32", "i32"] } } },
+        "exports": { "run": { "results": ["i32"] } }
+    }"#;
+
+    #[test]
+    fn test_module_matching_its_descriptor() {
+        let host = HostInterface::from_json(HOST).unwrap();
+        assert_eq!(host.imports, vec![HostFunction::new("env", "log", FunctionType {
+            params: vec![ValType::I32, ValType::I32],
+            results: vec![],
+        })]);
+        assert_eq!(host.exports[0].ty.to_string(), "() -> (i32)");
+        assert_eq!(host.check(&module("env", "run")).unwrap(), vec![]);
+
+        let error = HostInterface::from_json(r#"{ "exports": { "run": { "params": ["v128"] } } }"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "malformed host interface: `exports.run.params` has unknown type \"v128\"; \
+             expected i32, i64, f32, f64, funcref, or externref"
+        );
+    }
+
+    #[test]
+    fn test_reports_every_mismatch() {
+        let host = HostInterface::from_json(
+            r#"{
+                "imports": { "env": { "log": { "params": ["i32"] } } },
+                "exports": { "run": { "results": ["i64"] }, "init": {} }
+            }"#,
+        )
+        .unwrap();
+        let mismatches = host.check(&module("env", "run")).unwrap();
+        let mismatches: Vec<String> = mismatches.iter().map(AbiMismatch::to_string).collect();
+        assert_eq!(mismatches, [
+            "the module imports `env.log` as (i32, i32) -> (), but the host provides (i32) -> ()",
+            "the host calls `init`, which the module does not export",
+            "the host calls `run` as () -> (i64), but the module exports () -> (i32)",
+        ]);
+        let unresolved = HostInterface::from_json(HOST).unwrap().check(&module("wasi", "run")).unwrap();
+        let expected = AbiMismatch::UnresolvedImport { module: "wasi".to_string(), name: "log".to_string() };
+        assert_eq!(unresolved, vec![expected]);
+    }
+
+    #[test]
+    fn test_checks_against_a_wit_world() {
+        let package = WitPackage::parse(
+            "package example:app;\n\
+             interface logger { log: func(message: string); }\n\
+             interface api { run: func() -> s32; }\n\
+             world plugin { import logger; export api; }",
+        )
+        .unwrap();
+        let host = HostInterface::from_wit(&package, "plugin").unwrap();
+        assert!(host.imports[0].answers_to("logger", "log"));
+        assert_eq!(host.exports[0].name, "example:app/api#run");
+        assert_eq!(host.check(&module("example:app/logger", "run")).unwrap(), vec![]);
+        assert_eq!(
+            host.check(&module("example:app/logger", "start")).unwrap(),
+            vec![AbiMismatch::MissingExport("example:app/api#run".to_string())]
+        );
+    }
+}
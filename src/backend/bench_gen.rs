@@ -0,0 +1,608 @@
+//! Per-export micro-benchmark generation
+//!
+//! `BenchmarkGenerator` derives a benchmark for every exported function from
+//! `WasmModule` metadata. Inputs are drawn from a seeded generator and
+//! constrained by each parameter's source-level type: integers and floats
+//! stay within a configurable range, strings and arrays within a maximum
+//! length. Exports taking or returning JS objects, closures, or promises
+//! are skipped with a reason.
+//!
+//! Suites run in two places. `driver` emits an ES module for the browser
+//! driver that calls the exports through the JS glue and logs one record
+//! per export to the console; embedded runtimes implement `BenchRunner` and
+//! are timed by `run`. Both produce `PerformanceRecord`s in the registry's
+//! performance schema, one JSON object per line.
+//!
+//! Functions declared with `#[wasm::bench]` time themselves inside the
+//! module. Suites list them by name, the driver runs them after the
+//! generated benchmarks, and `run_in_module` runs them in embedded
+//! runtimes; their records come from the module's own samples.
+
+use crate::backend::js_glue::{exported_functions, js_identifier, js_string};
+use crate::backend::BackendError;
+use crate::host::runtime::Value;
+use std::fmt::Write;
+use std::time::Instant;
+use wasm::bench::{BENCH_EXPORT_PREFIX, SAMPLE_EXPORT};
+use wasm::wasmir::{ElementType, ExportKind, InteropType, Type, WasmModule};
+
+/// Schema identifier of the records the registry ingests
+pub const PERFORMANCE_SCHEMA: &str = "wasmrust.performance/1";
+
+/// Source-level argument to an exported function
+#[derive(Debug, Clone, PartialEq)]
+pub enum BenchValue {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Str(String),
+    /// Typed array contents; elements are `I32` except for `I64`, `F32`, and `F64` arrays
+    Array(ElementType, Vec<BenchValue>),
+}
+
+impl BenchValue {
+    /// JavaScript expression producing the value
+    fn to_js(&self) -> String {
+        match self {
+            BenchValue::I32(value) => value.to_string(),
+            BenchValue::I64(value) => format!("{}n", value),
+            BenchValue::F32(value) => format!("{:?}", value),
+            BenchValue::F64(value) => format!("{:?}", value),
+            BenchValue::Str(value) => js_string(value),
+            BenchValue::Array(element, values) => {
+                let values: Vec<String> = values.iter().map(BenchValue::to_js).collect();
+                format!("new {}([{}])", element.typed_array(), values.join(", "))
+            }
+        }
+    }
+}
+
+/// Benchmark of one exported function
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportBenchmark {
+    pub export: String,
+    /// Argument lists, each passed to one call per measured pass
+    pub inputs: Vec<Vec<BenchValue>>,
+}
+
+/// Benchmarks generated for a module
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BenchmarkSuite {
+    pub benchmarks: Vec<ExportBenchmark>,
+    /// Exports without a benchmark, with the reason
+    pub skipped: Vec<(String, String)>,
+    /// Names of the module's `#[wasm::bench]` functions
+    pub in_module: Vec<String>,
+}
+
+/// Measured latency and throughput of one export on one runtime
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerformanceRecord {
+    pub export: String,
+    /// Runtime the export ran in, such as `wasmtime` or `chrome`
+    pub runtime: String,
+    /// Measured passes over the inputs
+    pub iterations: usize,
+    /// Calls per pass
+    pub inputs: usize,
+    pub mean_ns: u64,
+    pub median_ns: u64,
+    pub p99_ns: u64,
+    /// Calls per second at the mean latency
+    pub throughput_per_sec: u64,
+}
+
+impl PerformanceRecord {
+    /// Summarizes per-call latencies, one sample per pass
+    fn from_samples(export: &str, runtime: &str, inputs: usize, samples: Vec<u64>) -> Self {
+        let iterations = samples.len();
+        let (mean_ns, median_ns, p99_ns) = summarize(samples);
+        Self {
+            export: export.to_string(),
+            runtime: runtime.to_string(),
+            iterations,
+            inputs,
+            mean_ns,
+            median_ns,
+            p99_ns,
+            throughput_per_sec: 1_000_000_000 / mean_ns.max(1),
+        }
+    }
+
+    /// Renders the record as a JSON line in `PERFORMANCE_SCHEMA`
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"schema\":{},\"export\":{},\"runtime\":{},\"iterations\":{},\"inputs\":{},\"mean_ns\":{},\"median_ns\":{},\"p99_ns\":{},\"throughput_per_sec\":{}}}",
+            js_string(PERFORMANCE_SCHEMA),
+            js_string(&self.export),
+            js_string(&self.runtime),
+            self.iterations,
+            self.inputs,
+            self.mean_ns,
+            self.median_ns,
+            self.p99_ns,
+            self.throughput_per_sec,
+        )
+    }
+}
+
+/// Calls exports in an embedded runtime
+///
+/// Implementations instantiate the module once and marshal source-level
+/// arguments the way the JS glue does.
+pub trait BenchRunner {
+    /// Runtime name recorded with the results
+    fn runtime(&self) -> &str;
+
+    fn call(&mut self, export: &str, args: &[BenchValue]) -> Result<(), BackendError>;
+
+    /// Calls an export with numbers as they are lowered, for in-module benchmarks
+    fn invoke(&mut self, export: &str, _args: &[Value]) -> Result<Option<Value>, BackendError> {
+        Err(BackendError::Unsupported(format!("{} cannot run in-module benchmark {}", self.runtime(), export)))
+    }
+}
+
+/// Generates and runs per-export micro-benchmarks
+#[derive(Debug, Clone)]
+pub struct BenchmarkGenerator {
+    seed: u64,
+    inputs: usize,
+    warmup: usize,
+    iterations: usize,
+    range: (i64, i64),
+    max_len: usize,
+}
+
+impl Default for BenchmarkGenerator {
+    fn default() -> Self {
+        Self { seed: 0x5eed, inputs: 16, warmup: 10, iterations: 100, range: (-1000, 1000), max_len: 64 }
+    }
+}
+
+impl BenchmarkGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seeds input generation, so suites are reproducible
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the number of argument lists per export
+    pub fn inputs(mut self, inputs: usize) -> Self {
+        self.inputs = inputs.max(1);
+        self
+    }
+
+    /// Sets the unmeasured and measured passes over the inputs
+    pub fn iterations(mut self, warmup: usize, iterations: usize) -> Self {
+        self.warmup = warmup;
+        self.iterations = iterations.max(1);
+        self
+    }
+
+    /// Bounds numeric arguments and array elements, inclusive
+    ///
+    /// Exports whose cost grows with an argument, such as a recursive
+    /// `fibonacci`, need a narrow range to finish.
+    pub fn range(mut self, min: i64, max: i64) -> Self {
+        self.range = (min.min(max), min.max(max));
+        self
+    }
+
+    /// Bounds string lengths in characters and array lengths in elements
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Generates a benchmark for every exported function
+    pub fn generate(&self, module: &WasmModule) -> Result<BenchmarkSuite, BackendError> {
+        let mut suite = BenchmarkSuite::default();
+        let mut rng = Rng(self.seed | 1);
+        for (name, index) in exported_functions(module) {
+            let function = module.functions.get(index as usize).ok_or_else(|| {
+                BackendError::CompilationFailed(format!("export {} of unknown function {}", name, index))
+            })?;
+            let params = match source_params(module, index, &function.signature.params, function.signature.returns.as_ref()) {
+                Ok(params) => params,
+                Err(reason) => {
+                    suite.skipped.push((name.to_string(), reason));
+                    continue;
+                }
+            };
+            let inputs = (0..self.inputs)
+                .map(|_| params.iter().map(|param| self.arbitrary(&mut rng, param)).collect())
+                .collect();
+            suite.benchmarks.push(ExportBenchmark { export: name.to_string(), inputs });
+        }
+        suite.in_module = in_module_benchmarks(module).map(str::to_string).collect();
+        Ok(suite)
+    }
+
+    /// Times a benchmark in an embedded runtime
+    pub fn run<R: BenchRunner>(&self, runner: &mut R, benchmark: &ExportBenchmark) -> Result<PerformanceRecord, BackendError> {
+        let pass = |runner: &mut R| -> Result<(), BackendError> {
+            for args in &benchmark.inputs {
+                runner.call(&benchmark.export, args)?;
+            }
+            Ok(())
+        };
+        for _ in 0..self.warmup {
+            pass(runner)?;
+        }
+        let calls = benchmark.inputs.len().max(1) as u64;
+        let mut samples = Vec::with_capacity(self.iterations);
+        for _ in 0..self.iterations {
+            let start = Instant::now();
+            pass(runner)?;
+            samples.push(start.elapsed().as_nanos() as u64 / calls);
+        }
+        Ok(PerformanceRecord::from_samples(&benchmark.export, runner.runtime(), benchmark.inputs.len(), samples))
+    }
+
+    /// Runs a `#[wasm::bench]` function in an embedded runtime
+    ///
+    /// The module times itself; the record has one input per call.
+    pub fn run_in_module<R: BenchRunner>(&self, runner: &mut R, name: &str) -> Result<PerformanceRecord, BackendError> {
+        let export = format!("{}{}", BENCH_EXPORT_PREFIX, name);
+        let counts = [Value::I32(self.warmup as i32), Value::I32(self.iterations as i32)];
+        let count = match runner.invoke(&export, &counts)? {
+            Some(Value::I32(count)) => count as u32,
+            other => return Err(BackendError::CompilationFailed(format!("{} returned {:?}", export, other))),
+        };
+        let samples = (0..count)
+            .map(|index| match runner.invoke(SAMPLE_EXPORT, &[Value::I32(index as i32)])? {
+                Some(Value::F64(ns)) => Ok(ns as u64),
+                other => Err(BackendError::CompilationFailed(format!("{} returned {:?}", SAMPLE_EXPORT, other))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(PerformanceRecord::from_samples(name, runner.runtime(), 1, samples))
+    }
+
+    /// Generates the browser driver for a suite
+    ///
+    /// The module imports the glue from `glue_file` and exports
+    /// `runBenchmarks(runtime)`, which initializes the glue, logs each
+    /// record as a JSON line, and resolves to the records.
+    pub fn driver(&self, suite: &BenchmarkSuite, glue_file: &str) -> String {
+        let mut out = String::new();
+        out.push_str("// Generated by WasmRust. Do not edit.\n\n");
+        let _ = writeln!(out, "import * as glue from {};\n", js_string(glue_file));
+        let _ = writeln!(out, "const WARMUP = {};", self.warmup);
+        let _ = writeln!(out, "const ITERATIONS = {};\n", self.iterations);
+
+        out.push_str("const benchmarks = [\n");
+        for benchmark in &suite.benchmarks {
+            let _ = writeln!(
+                out,
+                "  {{ export: {}, call: glue.{}, inputs: [",
+                js_string(&benchmark.export),
+                js_identifier(&benchmark.export)
+            );
+            for args in &benchmark.inputs {
+                let args: Vec<String> = args.iter().map(BenchValue::to_js).collect();
+                let _ = writeln!(out, "    [{}],", args.join(", "));
+            }
+            out.push_str("  ] },\n");
+        }
+        out.push_str("];\n\n");
+
+        let in_module: Vec<String> = suite.in_module.iter().map(|name| js_string(name)).collect();
+        let _ = writeln!(out, "const inModule = [{}];\n", in_module.join(", "));
+
+        out.push_str("function rank(sorted, p) {\n");
+        out.push_str("  return sorted[Math.max(Math.ceil(sorted.length * p / 100) - 1, 0)];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function record(name, runtime, inputs, samples) {\n");
+        out.push_str("  samples.sort((a, b) => a - b);\n");
+        out.push_str("  const mean = Math.floor(samples.reduce((sum, sample) => sum + sample, 0) / samples.length);\n");
+        out.push_str("  const record = {\n");
+        let _ = writeln!(out, "    schema: {},", js_string(PERFORMANCE_SCHEMA));
+        out.push_str("    export: name,\n");
+        out.push_str("    runtime,\n");
+        out.push_str("    iterations: samples.length,\n");
+        out.push_str("    inputs,\n");
+        out.push_str("    mean_ns: mean,\n");
+        out.push_str("    median_ns: rank(samples, 50),\n");
+        out.push_str("    p99_ns: rank(samples, 99),\n");
+        out.push_str("    throughput_per_sec: Math.floor(1e9 / Math.max(mean, 1)),\n");
+        out.push_str("  };\n");
+        out.push_str("  console.log(JSON.stringify(record));\n");
+        out.push_str("  return record;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("export async function runBenchmarks(runtime = \"browser\") {\n");
+        out.push_str("  const wasm = await glue.init();\n");
+        out.push_str("  const records = [];\n");
+        out.push_str("  for (const bench of benchmarks) {\n");
+        out.push_str("    const pass = () => { for (const args of bench.inputs) bench.call(...args); };\n");
+        out.push_str("    for (let i = 0; i < WARMUP; i++) pass();\n");
+        out.push_str("    const samples = [];\n");
+        out.push_str("    for (let i = 0; i < ITERATIONS; i++) {\n");
+        out.push_str("      const start = performance.now();\n");
+        out.push_str("      pass();\n");
+        out.push_str("      samples.push(Math.floor((performance.now() - start) * 1e6 / bench.inputs.length));\n");
+        out.push_str("    }\n");
+        out.push_str("    records.push(record(bench.export, runtime, bench.inputs.length, samples));\n");
+        out.push_str("  }\n");
+        out.push_str("  for (const name of inModule) {\n");
+        let _ = writeln!(out, "    const count = wasm[{} + name](WARMUP, ITERATIONS);", js_string(BENCH_EXPORT_PREFIX));
+        let _ = writeln!(out, "    const samples = Array.from({{ length: count }}, (_, i) => Math.floor(wasm.{}(i)));", SAMPLE_EXPORT);
+        out.push_str("    records.push(record(name, runtime, 1, samples));\n");
+        out.push_str("  }\n");
+        out.push_str("  return records;\n");
+        out.push_str("}\n");
+        out
+    }
+
+    /// Draws a value of a parameter type within the configured bounds
+    fn arbitrary(&self, rng: &mut Rng, param: &Param) -> BenchValue {
+        match *param {
+            Param::Scalar(ElementType::I64) => BenchValue::I64(rng.within(self.range)),
+            Param::Scalar(ElementType::F32) => BenchValue::F32(rng.float(self.range) as f32),
+            Param::Scalar(ElementType::F64) => BenchValue::F64(rng.float(self.range)),
+            Param::Scalar(element) => BenchValue::I32(rng.within(clamp(self.range, element)) as i32),
+            Param::Str => {
+                const CHARS: &[char] = &['a', 'b', 'z', 'A', 'Q', '0', '7', ' ', '-', 'é', 'λ', '€'];
+                let len = rng.within((0, self.max_len as i64)) as usize;
+                BenchValue::Str((0..len).map(|_| CHARS[rng.within((0, CHARS.len() as i64 - 1)) as usize]).collect())
+            }
+            Param::Array(element) => {
+                let len = rng.within((0, self.max_len as i64)) as usize;
+                let values = (0..len).map(|_| self.arbitrary(rng, &Param::Scalar(element))).collect();
+                BenchValue::Array(element, values)
+            }
+        }
+    }
+}
+
+/// Mean, median, and 99th percentile of latency samples
+pub(crate) fn summarize(mut samples: Vec<u64>) -> (u64, u64, u64) {
+    samples.sort_unstable();
+    let mean = samples.iter().sum::<u64>() / samples.len().max(1) as u64;
+    // Nearest-rank percentiles
+    let rank = |p: usize| samples.get((samples.len() * p).div_ceil(100).saturating_sub(1)).copied().unwrap_or(0);
+    (mean, rank(50), rank(99))
+}
+
+/// Names of the `#[wasm::bench]` functions a module exports
+fn in_module_benchmarks(module: &WasmModule) -> impl Iterator<Item = &str> {
+    module.exports.iter().filter_map(|export| match export.kind {
+        ExportKind::Function(_) => export.name.strip_prefix(BENCH_EXPORT_PREFIX).filter(|name| !name.is_empty()),
+        _ => None,
+    })
+}
+
+/// Source-level parameter shape that inputs are generated for
+enum Param {
+    Scalar(ElementType),
+    Str,
+    Array(ElementType),
+}
+
+/// Source-level parameters of an export, or why it cannot be benchmarked
+fn source_params(module: &WasmModule, index: u32, lowered: &[Type], returns: Option<&Type>) -> Result<Vec<Param>, String> {
+    match returns {
+        Some(Type::Promise(_)) => return Err("returns a promise".to_string()),
+        Some(Type::ExternRef(_) | Type::FuncRef) => return Err("returns a JS object".to_string()),
+        _ => {}
+    }
+    let interop = module.interop_signature(index);
+    let mut lowered = lowered.iter().skip(usize::from(interop.is_some_and(|sig| sig.has_return_area())));
+    let kinds: Vec<InteropType> = match interop {
+        Some(interop) => interop.params.clone(),
+        None => vec![InteropType::Value; lowered.len()],
+    };
+    kinds.into_iter()
+        .map(|kind| {
+            let ty = lowered.next();
+            if kind.is_memory_pair() {
+                lowered.next();
+            }
+            match kind {
+                InteropType::Value => match ty.and_then(scalar) {
+                    Some(element) => Ok(Param::Scalar(element)),
+                    None => match ty {
+                        Some(Type::ExternRef(_) | Type::FuncRef) => Err("takes a JS object".to_string()),
+                        other => Err(format!("takes a {:?} parameter", other.unwrap_or(&Type::Void))),
+                    },
+                },
+                InteropType::Str => Ok(Param::Str),
+                InteropType::ExternRef => Err("takes a JS object".to_string()),
+                InteropType::Slice(element) | InteropType::SliceMut(element) | InteropType::Vec(element) => {
+                    Ok(Param::Array(element))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Numeric element type of a directly passed value
+fn scalar(ty: &Type) -> Option<ElementType> {
+    match ty {
+        Type::I32 => Some(ElementType::I32),
+        Type::I64 => Some(ElementType::I64),
+        Type::F32 => Some(ElementType::F32),
+        Type::F64 => Some(ElementType::F64),
+        Type::Linear { inner_type } | Type::Capability { inner_type, .. } => scalar(inner_type),
+        _ => None,
+    }
+}
+
+/// Narrows a range to the values an integer element type holds
+fn clamp((min, max): (i64, i64), element: ElementType) -> (i64, i64) {
+    let (low, high) = match element {
+        ElementType::U8 => (0, u8::MAX as i64),
+        ElementType::I8 => (i8::MIN as i64, i8::MAX as i64),
+        ElementType::U16 => (0, u16::MAX as i64),
+        ElementType::I16 => (i16::MIN as i64, i16::MAX as i64),
+        ElementType::U32 => (0, u32::MAX as i64),
+        _ => (i32::MIN as i64, i32::MAX as i64),
+    };
+    let min = min.clamp(low, high);
+    (min, max.clamp(min, high))
+}
+
+/// xorshift64* generator for reproducible inputs
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn within(&mut self, (min, max): (i64, i64)) -> i64 {
+        let span = max.wrapping_sub(min) as u64;
+        match span.checked_add(1) {
+            Some(count) => min.wrapping_add((self.next() % count) as i64),
+            None => self.next() as i64,
+        }
+    }
+
+    fn float(&mut self, (min, max): (i64, i64)) -> f64 {
+        let unit = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        min as f64 + unit * (max as f64 - min as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{InteropSignature, Signature, WasmIR};
+
+    fn bench_module() -> WasmModule {
+        let mut module = WasmModule::new();
+        let fib = module.add_function(WasmIR::new("fib".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        }));
+        module.export_function("fib", fib);
+        let count = module.add_function(WasmIR::new("count".to_string(), Signature {
+            params: vec![Type::I32, Type::I32, Type::I32, Type::I32, Type::I64],
+            returns: None,
+        }));
+        module.export_function("count", count);
+        module.set_interop_signature(count, InteropSignature {
+            params: vec![InteropType::Str, InteropType::Slice(ElementType::U8), InteropType::Value],
+            returns: InteropType::Value,
+        });
+        let wrap = module.add_function(WasmIR::new("wrap".to_string(), Signature {
+            params: vec![Type::ExternRef("Element".to_string())],
+            returns: None,
+        }));
+        module.export_function("wrap", wrap);
+        let parse = module.add_function(WasmIR::new("parse".to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        }));
+        module.export_function("__wasmrust_bench_parse", parse);
+        module
+    }
+
+    #[test]
+    fn test_generate_constrained_inputs() {
+        let generator = BenchmarkGenerator::new().seed(7).inputs(32).range(0, 20).max_len(8);
+        let suite = generator.generate(&bench_module()).unwrap();
+        assert_eq!(suite, generator.generate(&bench_module()).unwrap());
+        assert_eq!(suite.skipped, [("wrap".to_string(), "takes a JS object".to_string())]);
+
+        let [fib, count] = &suite.benchmarks[..] else { panic!("expected two benchmarks") };
+        assert_eq!(fib.inputs.len(), 32);
+        assert!(fib.inputs.iter().all(|args| matches!(args[..], [BenchValue::I32(n)] if (0..=20).contains(&n))));
+        for args in &count.inputs {
+            let [BenchValue::Str(text), BenchValue::Array(ElementType::U8, bytes), BenchValue::I64(n)] = &args[..] else {
+                panic!("unexpected arguments {:?}", args);
+            };
+            assert!(text.chars().count() <= 8 && bytes.len() <= 8 && (0..=20).contains(n));
+        }
+    }
+
+    #[test]
+    fn test_browser_driver() {
+        let generator = BenchmarkGenerator::new().inputs(2).iterations(3, 50).range(5, 5).max_len(2);
+        let suite = generator.generate(&bench_module()).unwrap();
+        let driver = generator.driver(&suite, "./app.mjs");
+
+        assert!(driver.contains("import * as glue from \"./app.mjs\";"));
+        assert!(driver.contains("const ITERATIONS = 50;"));
+        assert!(driver.contains("  { export: \"fib\", call: glue.fib, inputs: [\n    [5],\n    [5],\n  ] },"));
+        assert!(driver.contains("new Uint8Array(["));
+        assert!(driver.contains("5n],"));
+        assert!(driver.contains("schema: \"wasmrust.performance/1\""));
+
+        // `#[wasm::bench]` functions time themselves and hand back samples
+        assert_eq!(suite.in_module, ["parse"]);
+        assert!(driver.contains("const inModule = [\"parse\"];"));
+        assert!(driver.contains("    const count = wasm[\"__wasmrust_bench_\" + name](WARMUP, ITERATIONS);\n"));
+        assert!(driver.contains("Math.floor(wasm.__wasmrust_sample(i))"));
+    }
+
+    #[test]
+    fn test_run_records_latency() {
+        struct Counting(usize);
+        impl BenchRunner for Counting {
+            fn runtime(&self) -> &str {
+                "mock"
+            }
+
+            fn call(&mut self, _: &str, args: &[BenchValue]) -> Result<(), BackendError> {
+                assert_eq!(args.len(), 1);
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let generator = BenchmarkGenerator::new().inputs(4).iterations(2, 10);
+        let suite = generator.generate(&bench_module()).unwrap();
+        let mut runner = Counting(0);
+        let record = generator.run(&mut runner, &suite.benchmarks[0]).unwrap();
+        assert_eq!(runner.0, 4 * 12);
+        assert_eq!((record.export.as_str(), record.runtime.as_str(), record.iterations, record.inputs), ("fib", "mock", 10, 4));
+        assert!(record.median_ns <= record.p99_ns && record.throughput_per_sec > 0);
+        assert!(record.to_json().starts_with("{\"schema\":\"wasmrust.performance/1\",\"export\":\"fib\",\"runtime\":\"mock\",\"iterations\":10,"));
+
+        let summary = PerformanceRecord::from_samples("f", "r", 1, (1..=100).collect());
+        assert_eq!((summary.mean_ns, summary.median_ns, summary.p99_ns), (50, 50, 99));
+
+        let unsupported = generator.run_in_module(&mut runner, "parse");
+        assert!(matches!(unsupported, Err(BackendError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_run_in_module_reads_samples() {
+        struct InModule(Vec<(String, Vec<Value>)>);
+        impl BenchRunner for InModule {
+            fn runtime(&self) -> &str {
+                "mock"
+            }
+
+            fn call(&mut self, _: &str, _: &[BenchValue]) -> Result<(), BackendError> {
+                unreachable!("in-module benchmarks are not called with inputs")
+            }
+
+            fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Option<Value>, BackendError> {
+                self.0.push((export.to_string(), args.to_vec()));
+                Ok(Some(match args {
+                    [Value::I32(index)] => Value::F64(f64::from(*index + 1) * 10.0),
+                    _ => Value::I32(3),
+                }))
+            }
+        }
+
+        let generator = BenchmarkGenerator::new().iterations(5, 3);
+        let mut runner = InModule(Vec::new());
+        let record = generator.run_in_module(&mut runner, "parse").unwrap();
+        assert_eq!(runner.0[0], ("__wasmrust_bench_parse".to_string(), vec![Value::I32(5), Value::I32(3)]));
+        assert_eq!(runner.0[3], ("__wasmrust_sample".to_string(), vec![Value::I32(2)]));
+        assert_eq!((record.export.as_str(), record.iterations, record.inputs), ("parse", 3, 1));
+        assert_eq!((record.mean_ns, record.median_ns, record.p99_ns), (20, 20, 30));
+    }
+}
@@ -0,0 +1,376 @@
+//! Size and compile-time budgets, and the size history regressions are caught against
+//!
+//! A `Budget` caps a module's size, its compile time, and how much it may
+//! grow over the last build recorded in its `SizeHistory`. `cargo wasmrust`
+//! reads one per profile from the workspace manifest:
+//!
+//! ```toml
+//! [workspace.metadata.wasmrust.budgets.release]
+//! max-wasm-size = 65536
+//! max-compile-time-ms = 2000
+//! max-size-growth = 5.0
+//! action = "deny"
+//! history = "wasm-sizes.jsonl"
+//! ```
+//!
+//! Committing the history file lets CI compare each build to the last
+//! recorded one. Builds a `deny` budget fails are not recorded, so a
+//! regression stays measured against the same baseline until fixed.
+
+use super::cranelift::CompilationStats;
+use super::size_report::{SectionSize, SizeReport};
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What exceeding a budget does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BudgetAction {
+    /// Print a warning and keep the build
+    #[default]
+    Warn,
+    /// Fail the build
+    Deny,
+}
+
+impl BudgetAction {
+    pub const ALL: [BudgetAction; 2] = [BudgetAction::Warn, BudgetAction::Deny];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BudgetAction::Warn => "warn",
+            BudgetAction::Deny => "deny",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+/// Limits a build is held to; unset ones are not checked
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Budget {
+    /// Bytes of the module
+    pub max_wasm_size: Option<usize>,
+    /// Time to lower and compile the module
+    pub max_compile_time: Option<Duration>,
+    /// Percent the module may grow over its last recorded size
+    pub max_size_growth: Option<f64>,
+    pub action: BudgetAction,
+    /// JSON lines file builds are recorded in
+    pub history: Option<PathBuf>,
+}
+
+impl Budget {
+    /// Reads a budget table of the workspace manifest
+    ///
+    /// A relative `history` path is taken relative to `root`.
+    pub fn from_metadata(table: &Value, root: &Path) -> Result<Self, String> {
+        let Some(table) = table.as_object() else { return Err("expected a table".to_string()) };
+        let mut budget = Budget::default();
+        for (key, value) in table {
+            let invalid = |expected: &str| format!("`{}` must be {}", key, expected);
+            match key.as_str() {
+                "max-wasm-size" => {
+                    budget.max_wasm_size = Some(value.as_u64().ok_or_else(|| invalid("a byte count"))? as usize);
+                }
+                "max-compile-time-ms" => {
+                    let millis = value.as_u64().ok_or_else(|| invalid("a number of milliseconds"))?;
+                    budget.max_compile_time = Some(Duration::from_millis(millis));
+                }
+                "max-size-growth" => {
+                    let percent = value.as_f64().filter(|percent| *percent >= 0.0);
+                    budget.max_size_growth = Some(percent.ok_or_else(|| invalid("a non-negative percentage"))?);
+                }
+                "action" => {
+                    budget.action = value.as_str()
+                        .and_then(BudgetAction::from_name)
+                        .ok_or_else(|| invalid("`warn` or `deny`"))?;
+                }
+                "history" => budget.history = Some(root.join(value.as_str().ok_or_else(|| invalid("a path"))?)),
+                other => return Err(format!("unknown budget `{}`", other)),
+            }
+        }
+        Ok(budget)
+    }
+
+    /// Whether the budget neither limits nor records anything
+    pub fn is_empty(&self) -> bool {
+        self.max_wasm_size.is_none()
+            && self.max_compile_time.is_none()
+            && self.max_size_growth.is_none()
+            && self.history.is_none()
+    }
+
+    /// Limits the build of `module` exceeded
+    ///
+    /// Growth is measured against `previous`, the last recorded build.
+    pub fn check(
+        &self,
+        module: &str,
+        stats: &CompilationStats,
+        report: &SizeReport,
+        previous: Option<&SizeRecord>,
+    ) -> Vec<BudgetViolation> {
+        let violation = |kind| BudgetViolation { module: module.to_string(), kind };
+        let mut violations = Vec::new();
+        if let Some(limit) = self.max_wasm_size.filter(|&limit| stats.wasm_size > limit) {
+            violations.push(violation(ViolationKind::WasmSize { size: stats.wasm_size, limit }));
+        }
+        let time = Duration::from_millis(stats.compilation_time_ms);
+        if let Some(limit) = self.max_compile_time.filter(|&limit| time > limit) {
+            violations.push(violation(ViolationKind::CompileTime { time, limit }));
+        }
+        if let (Some(limit), Some(previous)) = (self.max_size_growth, previous) {
+            let growth = growth(previous.wasm_size, stats.wasm_size);
+            if growth > limit {
+                violations.push(violation(ViolationKind::SizeGrowth {
+                    from: previous.wasm_size,
+                    to: stats.wasm_size,
+                    growth,
+                    limit,
+                    section: largest_growth(&previous.sections, &report.sections),
+                }));
+            }
+        }
+        violations
+    }
+}
+
+/// Percent `to` is over `from`
+fn growth(from: usize, to: usize) -> f64 {
+    match from {
+        0 => 0.0,
+        from => (to as f64 - from as f64) * 100.0 / from as f64,
+    }
+}
+
+/// Section that grew the most, and by how many bytes
+fn largest_growth(before: &[SectionSize], after: &[SectionSize]) -> Option<(String, usize)> {
+    after.iter()
+        .filter_map(|section| {
+            let old = before.iter().find(|old| old.name == section.name).map_or(0, |old| old.size);
+            (section.size > old).then(|| (section.name.clone(), section.size - old))
+        })
+        .max_by_key(|(_, grown)| *grown)
+}
+
+/// A limit a module's build exceeded
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetViolation {
+    pub module: String,
+    pub kind: ViolationKind,
+}
+
+/// Which limit was exceeded, and by how much
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationKind {
+    WasmSize { size: usize, limit: usize },
+    CompileTime { time: Duration, limit: Duration },
+    SizeGrowth {
+        from: usize,
+        to: usize,
+        growth: f64,
+        limit: f64,
+        /// Section that grew the most, and by how many bytes
+        section: Option<(String, usize)>,
+    },
+}
+
+impl fmt::Display for BudgetViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ViolationKind::WasmSize { size, limit } => {
+                write!(f, "`{}` is {} bytes, over its budget of {} bytes", self.module, size, limit)
+            }
+            ViolationKind::CompileTime { time, limit } => write!(
+                f,
+                "`{}` took {}ms to compile, over its budget of {}ms",
+                self.module,
+                time.as_millis(),
+                limit.as_millis()
+            ),
+            ViolationKind::SizeGrowth { from, to, growth, limit, section } => {
+                write!(f, "`{}` grew {:.1}% since its last recorded build ", self.module, growth)?;
+                write!(f, "({} -> {} bytes", from, to)?;
+                if let Some((section, grown)) = section {
+                    write!(f, ", most in `{}` +{}", section, grown)?;
+                }
+                write!(f, "), over its budget of {}%", limit)
+            }
+        }
+    }
+}
+
+/// One build of a module in a `SizeHistory`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeRecord {
+    pub module: String,
+    pub wasm_size: usize,
+    pub compile_time_ms: u64,
+    pub functions: usize,
+    pub sections: Vec<SectionSize>,
+}
+
+impl SizeRecord {
+    pub fn new(module: &str, stats: &CompilationStats, report: &SizeReport) -> Self {
+        Self {
+            module: module.to_string(),
+            wasm_size: stats.wasm_size,
+            compile_time_ms: stats.compilation_time_ms,
+            functions: stats.functions_compiled,
+            sections: report.sections.clone(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let sections: Vec<Value> =
+            self.sections.iter().map(|section| json!({ "name": section.name, "size": section.size })).collect();
+        json!({
+            "module": self.module,
+            "wasm_size": self.wasm_size,
+            "compile_time_ms": self.compile_time_ms,
+            "functions": self.functions,
+            "sections": sections,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let size = |value: &Value| value.as_u64().map(|size| size as usize);
+        let sections = value["sections"].as_array()?.iter()
+            .map(|section| {
+                Some(SectionSize { name: section["name"].as_str()?.to_string(), size: size(&section["size"])? })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            module: value["module"].as_str()?.to_string(),
+            wasm_size: size(&value["wasm_size"])?,
+            compile_time_ms: value["compile_time_ms"].as_u64()?,
+            functions: size(&value["functions"])?,
+            sections,
+        })
+    }
+}
+
+/// Builds recorded in a JSON lines file, oldest first
+#[derive(Debug, Clone)]
+pub struct SizeHistory {
+    path: PathBuf,
+}
+
+impl SizeHistory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Last recorded build of `module`, if the file has one
+    ///
+    /// Lines that do not parse are skipped, so hand edits cannot fail a build.
+    pub fn last(&self, module: &str) -> io::Result<Option<SizeRecord>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        Ok(contents.lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .filter_map(|value| SizeRecord::from_json(&value))
+            .find(|record| record.module == module))
+    }
+
+    /// Appends a build to the file, creating it and its directory if needed
+    pub fn append(&self, record: &SizeRecord) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", record.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(code: usize) -> SizeReport {
+        let sections = vec![
+            SectionSize { name: "header".to_string(), size: 8 },
+            SectionSize { name: "code".to_string(), size: code },
+        ];
+        SizeReport { total: 8 + code, sections, functions: Vec::new(), crates: Vec::new() }
+    }
+
+    fn stats(wasm_size: usize, compilation_time_ms: u64) -> CompilationStats {
+        CompilationStats { wasm_size, compilation_time_ms, functions_compiled: 3, ..CompilationStats::default() }
+    }
+
+    #[test]
+    fn test_budget_from_metadata() {
+        let table = json!({
+            "max-wasm-size": 1024,
+            "max-compile-time-ms": 500,
+            "max-size-growth": 2.5,
+            "action": "deny",
+            "history": "sizes.jsonl",
+        });
+        let budget = Budget::from_metadata(&table, Path::new("/ws")).unwrap();
+        assert_eq!(budget.max_wasm_size, Some(1024));
+        assert_eq!(budget.max_compile_time, Some(Duration::from_millis(500)));
+        assert_eq!(budget.max_size_growth, Some(2.5));
+        assert_eq!(budget.action, BudgetAction::Deny);
+        assert_eq!(budget.history, Some(PathBuf::from("/ws/sizes.jsonl")));
+        assert!(Budget::default().is_empty());
+
+        let error = |table: Value| Budget::from_metadata(&table, Path::new("/ws")).unwrap_err();
+        assert_eq!(error(json!({ "action": "fail" })), "`action` must be `warn` or `deny`");
+        assert_eq!(error(json!({ "max-size": 1 })), "unknown budget `max-size`");
+    }
+
+    #[test]
+    fn test_check_reports_each_exceeded_limit() {
+        let budget = Budget {
+            max_wasm_size: Some(100),
+            max_compile_time: Some(Duration::from_millis(50)),
+            max_size_growth: Some(10.0),
+            ..Budget::default()
+        };
+        let previous = SizeRecord::new("app", &stats(80, 10), &report(72));
+        assert!(budget.check("app", &stats(88, 50), &report(80), Some(&previous)).is_empty());
+
+        let violations = budget.check("app", &stats(120, 75), &report(112), Some(&previous));
+        let messages: Vec<_> = violations.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, [
+            "`app` is 120 bytes, over its budget of 100 bytes",
+            "`app` took 75ms to compile, over its budget of 50ms",
+            "`app` grew 50.0% since its last recorded build (80 -> 120 bytes, most in `code` +40), \
+             over its budget of 10%",
+        ]);
+        // Without history, growth is not checked
+        assert_eq!(budget.check("app", &stats(120, 0), &report(112), None).len(), 1);
+    }
+
+    #[test]
+    fn test_history_keeps_the_last_build_of_each_module() {
+        let path = std::env::temp_dir().join(format!("wasmrust-history-{}/sizes.jsonl", std::process::id()));
+        let history = SizeHistory::new(&path);
+        assert_eq!(history.last("app").unwrap(), None);
+
+        let first = SizeRecord::new("app", &stats(80, 10), &report(72));
+        let second = SizeRecord::new("app", &stats(90, 12), &report(82));
+        history.append(&first).unwrap();
+        history.append(&SizeRecord::new("lib", &stats(40, 5), &report(32))).unwrap();
+        history.append(&second).unwrap();
+        assert_eq!(history.last("app").unwrap(), Some(second));
+        assert_eq!(history.last("lib").unwrap().map(|record| record.wasm_size), Some(40));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,834 @@
+//! Canonical ABI lowering of component exports
+//!
+//! `WasmCodegen::compile_component` lets exported functions work with
+//! source-level values instead of hand-flattened core values. Parameters
+//! and results whose WIT type is a number, `char`, enum, flags value, or
+//! resource handle are passed directly. Any other value (a string, list, record, variant,
+//! option, or result) is passed as an `i32` pointer to the value in linear
+//! memory, laid out the way the canonical ABI stores it: records and
+//! variants as `#[repr(C)]` structs and tagged unions, strings and lists as
+//! a pointer and element count. Such results are returned as a pointer to
+//! a buffer allocated with `ALLOC_EXPORT`.
+//!
+//! For each export using memory the emitter synthesizes a canonical entry
+//! point taking its place, which stores the flattened arguments into
+//! buffers it allocates, calls the implementation, and frees those buffers
+//! afterwards. The strings and lists inside arguments become owned by the
+//! implementation. A `cabi_post_` function frees a result returned through
+//! memory along with every list it owns, and `cabi_realloc` is built on the
+//! module's allocator exports.
+
+use crate::backend::codegen::{group_locals, write_i64, write_u32, FuncType, ValType};
+use crate::backend::BackendError;
+use wasm::component::wit::{WitInterface, WitPackage, WitTypeDefKind};
+use wasm::component::WitType;
+use wasm::wasmir::{ExportKind, WasmModule, ALLOC_EXPORT, FREE_EXPORT};
+
+/// Most core parameters a function takes before they are passed in memory
+const MAX_FLAT_PARAMS: usize = 16;
+
+/// Most core results a function returns before they are passed in memory
+const MAX_FLAT_RESULTS: usize = 1;
+
+/// Export the canonical ABI allocates arguments with
+pub const REALLOC_EXPORT: &str = "cabi_realloc";
+
+/// Memory layout of a resolved WIT type
+#[derive(Debug, Clone, PartialEq)]
+enum AbiType {
+    /// Number stored in `size` bytes and flattened to one core value
+    Scalar { size: u32, signed: bool, flat: ValType },
+    /// `string` or `list<T>`, stored as an `i32` pointer and element count
+    List(Box<AbiType>),
+    Record(Vec<AbiType>),
+    /// Discriminant followed by the payload of the active case
+    Variant(Vec<Option<AbiType>>),
+}
+
+impl AbiType {
+    fn resolve(package: &WitPackage, interface: &WitInterface, ty: &WitType) -> Result<Self, BackendError> {
+        let scalar = |size, signed, flat| AbiType::Scalar { size, signed, flat };
+        let optional = |ty: &Option<Box<WitType>>| {
+            ty.as_deref().map(|ty| Self::resolve(package, interface, ty)).transpose()
+        };
+        Ok(match ty {
+            WitType::Bool | WitType::U8 => scalar(1, false, ValType::I32),
+            WitType::S8 => scalar(1, true, ValType::I32),
+            WitType::U16 => scalar(2, false, ValType::I32),
+            WitType::S16 => scalar(2, true, ValType::I32),
+            WitType::U32 | WitType::S32 | WitType::Char => scalar(4, false, ValType::I32),
+            // Handles are `i32` indices into the receiving side's table
+            WitType::Own(_) | WitType::Borrow(_) => scalar(4, false, ValType::I32),
+            WitType::U64 | WitType::S64 => scalar(8, false, ValType::I64),
+            WitType::F32 => scalar(4, false, ValType::F32),
+            WitType::F64 => scalar(8, false, ValType::F64),
+            WitType::String => AbiType::List(Box::new(scalar(1, false, ValType::I32))),
+            WitType::List(element) => AbiType::List(Box::new(Self::resolve(package, interface, element)?)),
+            WitType::Option(inner) => AbiType::Variant(vec![None, Some(Self::resolve(package, interface, inner)?)]),
+            WitType::Result { ok, err } => AbiType::Variant(vec![optional(ok)?, optional(err)?]),
+            WitType::Future(_) | WitType::Stream(_) => {
+                return Err(BackendError::Unsupported(format!("{} in a lowered export", ty)));
+            }
+            WitType::Named(name) => {
+                let (owner, def) = package.resolve_type(interface, name)
+                    .map_err(|e| BackendError::CompilationFailed(e.to_string()))?;
+                match &def.kind {
+                    WitTypeDefKind::Alias(ty) => Self::resolve(package, owner, ty)?,
+                    WitTypeDefKind::Record(fields) => AbiType::Record(
+                        fields.iter()
+                            .map(|(_, ty)| Self::resolve(package, owner, ty))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    WitTypeDefKind::Variant(cases) => AbiType::Variant(
+                        cases.iter()
+                            .map(|(_, ty)| ty.as_ref().map(|ty| Self::resolve(package, owner, ty)).transpose())
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    WitTypeDefKind::Enum(cases) => scalar(discriminant_size(cases.len()), false, ValType::I32),
+                    WitTypeDefKind::Flags(flags) if flags.len() <= 32 => {
+                        scalar((flags.len() as u32).div_ceil(8).next_power_of_two(), false, ValType::I32)
+                    }
+                    WitTypeDefKind::Flags(_) => {
+                        return Err(BackendError::Unsupported(format!("flags {} with more than 32 members", name)));
+                    }
+                    WitTypeDefKind::Resource => scalar(4, false, ValType::I32),
+                }
+            }
+        })
+    }
+
+    fn size(&self) -> u32 {
+        match self {
+            AbiType::Scalar { size, .. } => *size,
+            AbiType::List(_) => 8,
+            AbiType::Record(fields) => align_to(record_offsets(fields).1, self.align()),
+            AbiType::Variant(cases) => {
+                let payload = cases.iter().flatten().map(AbiType::size).max().unwrap_or(0);
+                align_to(self.payload_offset() + payload, self.align())
+            }
+        }
+    }
+
+    fn align(&self) -> u32 {
+        match self {
+            AbiType::Scalar { size, .. } => (*size).max(1),
+            AbiType::List(_) => 4,
+            AbiType::Record(fields) => fields.iter().map(AbiType::align).max().unwrap_or(1),
+            AbiType::Variant(cases) => cases.iter().flatten()
+                .map(AbiType::align)
+                .fold(discriminant_size(cases.len()), u32::max),
+        }
+    }
+
+    /// Offset of a variant's payload from its discriminant
+    fn payload_offset(&self) -> u32 {
+        match self {
+            AbiType::Variant(cases) => {
+                let align = cases.iter().flatten().map(AbiType::align).max().unwrap_or(1);
+                align_to(discriminant_size(cases.len()), align)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Discriminant of a variant as a scalar
+    fn discriminant(&self) -> AbiType {
+        let cases = match self {
+            AbiType::Variant(cases) => cases.len(),
+            _ => 0,
+        };
+        AbiType::Scalar { size: discriminant_size(cases), signed: false, flat: ValType::I32 }
+    }
+
+    /// Appends the core types the value flattens to
+    fn flatten(&self, out: &mut Vec<ValType>) {
+        match self {
+            AbiType::Scalar { flat, .. } => out.push(*flat),
+            AbiType::List(_) => out.extend([ValType::I32, ValType::I32]),
+            AbiType::Record(fields) => fields.iter().for_each(|field| field.flatten(out)),
+            AbiType::Variant(cases) => {
+                out.push(ValType::I32);
+                let mut joined: Vec<ValType> = Vec::new();
+                for case in cases.iter().flatten() {
+                    let mut flat = Vec::new();
+                    case.flatten(&mut flat);
+                    for (i, ty) in flat.into_iter().enumerate() {
+                        match joined.get_mut(i) {
+                            None => joined.push(ty),
+                            Some(existing) if *existing == ty => {}
+                            Some(existing) => {
+                                *existing = if matches!((*existing, ty), (ValType::I32, ValType::F32) | (ValType::F32, ValType::I32)) {
+                                    ValType::I32
+                                } else {
+                                    ValType::I64
+                                };
+                            }
+                        }
+                    }
+                }
+                out.extend(joined);
+            }
+        }
+    }
+
+    fn flat(&self) -> Vec<ValType> {
+        let mut flat = Vec::new();
+        self.flatten(&mut flat);
+        flat
+    }
+
+    /// Checks whether the value is passed as its single core value
+    fn is_direct(&self) -> bool {
+        matches!(self, AbiType::Scalar { .. })
+    }
+
+    /// Checks whether the value holds lists that must be freed with it
+    fn owns_lists(&self) -> bool {
+        match self {
+            AbiType::Scalar { .. } => false,
+            AbiType::List(_) => true,
+            AbiType::Record(fields) => fields.iter().any(AbiType::owns_lists),
+            AbiType::Variant(cases) => cases.iter().flatten().any(AbiType::owns_lists),
+        }
+    }
+
+    /// Core type of the value as the implementation sees it
+    fn source_type(&self) -> ValType {
+        match self {
+            AbiType::Scalar { flat, .. } => *flat,
+            _ => ValType::I32,
+        }
+    }
+}
+
+fn align_to(offset: u32, align: u32) -> u32 {
+    offset.div_ceil(align) * align
+}
+
+fn discriminant_size(cases: usize) -> u32 {
+    if cases <= 1 << 8 {
+        1
+    } else if cases <= 1 << 16 {
+        2
+    } else {
+        4
+    }
+}
+
+/// Offset of each field and the end of the last one
+fn record_offsets(fields: &[AbiType]) -> (Vec<u32>, u32) {
+    let mut offsets = Vec::with_capacity(fields.len());
+    let mut offset = 0;
+    for field in fields {
+        offset = align_to(offset, field.align());
+        offsets.push(offset);
+        offset += field.size();
+    }
+    (offsets, offset)
+}
+
+/// Function appended after the module's own functions
+#[derive(Debug, Clone)]
+pub(crate) struct SynthesizedFunction {
+    pub export: String,
+    pub func_type: FuncType,
+    pub body: Vec<u8>,
+}
+
+/// Canonical entry points for exports taking source-level values
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CanonicalExports {
+    /// Export names taken over by synthesized functions
+    pub replaced: Vec<String>,
+    pub functions: Vec<SynthesizedFunction>,
+}
+
+impl CanonicalExports {
+    /// Synthesizes entry points for the exports of `world` that use memory
+    ///
+    /// Exports are found under the names `wasm::component::Adapter` tries.
+    /// Functions passing a few numbers are left alone.
+    pub fn new(module: &WasmModule, package: &WitPackage, world: &str) -> Result<Self, BackendError> {
+        let interfaces = package.world_interfaces(world)
+            .map_err(|e| BackendError::CompilationFailed(e.to_string()))?;
+        let import_count = module.imports.len() as u32;
+        let function_export = |name: &str| module.exports.iter().find_map(|export| match export.kind {
+            ExportKind::Function(index) if export.name == name => Some(index),
+            _ => None,
+        });
+
+        let mut lowered = Self::default();
+        let mut allocator = None;
+        let mut needs_realloc = false;
+        for (qualified, interface) in &interfaces.exports {
+            for function in &interface.functions {
+                let export = [
+                    format!("{}#{}", qualified, function.name),
+                    format!("{}#{}", interface.name, function.name),
+                    function.name.clone(),
+                    function.name.replace('-', "_"),
+                ]
+                .into_iter()
+                .find(|name| function_export(name).is_some());
+                let Some(export) = export else { continue };
+
+                let params = function.params.iter()
+                    .map(|(_, ty)| AbiType::resolve(package, interface, ty))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let result = function.result.as_ref()
+                    .map(|ty| AbiType::resolve(package, interface, ty))
+                    .transpose()?;
+                let flat_params: usize = params.iter().map(|param| param.flat().len()).sum();
+                if params.iter().chain(&result).all(AbiType::is_direct) && flat_params <= MAX_FLAT_PARAMS {
+                    continue;
+                }
+
+                let display = format!("{}#{}", qualified, function.name);
+                let implementation = function_export(&export).expect("found above");
+                let expected = FuncType {
+                    params: params.iter().map(AbiType::source_type).collect(),
+                    results: result.iter().map(AbiType::source_type).collect(),
+                };
+                let actual = FuncType::from_signature(&module.functions[implementation as usize].signature)?;
+                if actual != expected {
+                    return Err(BackendError::CompilationFailed(format!(
+                        "{} must have type {:?} -> {:?}",
+                        display, expected.params, expected.results
+                    )));
+                }
+
+                let (alloc, free) = match allocator {
+                    Some(allocator) => allocator,
+                    None => {
+                        let found = function_export(ALLOC_EXPORT).zip(function_export(FREE_EXPORT))
+                            .map(|(alloc, free)| (import_count + alloc, import_count + free))
+                            .ok_or_else(|| BackendError::CompilationFailed(format!(
+                                "{} passes values through memory but the module does not export {} and {}",
+                                display, ALLOC_EXPORT, FREE_EXPORT
+                            )))?;
+                        *allocator.insert(found)
+                    }
+                };
+                let entry = Entry {
+                    implementation: import_count + implementation,
+                    alloc,
+                    free,
+                    params,
+                    result,
+                };
+                needs_realloc |= entry.params_in_memory();
+                lowered.functions.push(SynthesizedFunction {
+                    export: export.clone(),
+                    func_type: entry.func_type(),
+                    body: entry.body(),
+                });
+                if let Some(post_return) = entry.post_return() {
+                    lowered.functions.push(SynthesizedFunction {
+                        export: format!("cabi_post_{}", export),
+                        func_type: FuncType { params: vec![ValType::I32], results: Vec::new() },
+                        body: post_return,
+                    });
+                }
+                lowered.replaced.push(export);
+            }
+        }
+
+        if let (true, Some((alloc, free)), None) = (needs_realloc, allocator, function_export(REALLOC_EXPORT)) {
+            lowered.functions.push(SynthesizedFunction {
+                export: REALLOC_EXPORT.to_string(),
+                func_type: FuncType { params: vec![ValType::I32; 4], results: vec![ValType::I32] },
+                body: realloc_body(alloc, free),
+            });
+        }
+        Ok(lowered)
+    }
+}
+
+/// Export lowered onto a source-level implementation
+struct Entry {
+    implementation: u32,
+    alloc: u32,
+    free: u32,
+    params: Vec<AbiType>,
+    result: Option<AbiType>,
+}
+
+impl Entry {
+    fn flat_params(&self) -> Vec<ValType> {
+        let mut flat = Vec::new();
+        self.params.iter().for_each(|param| param.flatten(&mut flat));
+        flat
+    }
+
+    fn flat_results(&self) -> Vec<ValType> {
+        self.result.as_ref().map(AbiType::flat).unwrap_or_default()
+    }
+
+    /// Checks whether the host places arguments in memory with `realloc`
+    fn params_in_memory(&self) -> bool {
+        self.flat_params().len() > MAX_FLAT_PARAMS || self.params.iter().any(AbiType::owns_lists)
+    }
+
+    /// Canonical ABI core type of the entry point
+    fn func_type(&self) -> FuncType {
+        let mut params = self.flat_params();
+        if params.len() > MAX_FLAT_PARAMS {
+            params = vec![ValType::I32];
+        }
+        let mut results = self.flat_results();
+        if results.len() > MAX_FLAT_RESULTS {
+            results = vec![ValType::I32];
+        }
+        FuncType { params, results }
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let flat = self.flat_params();
+        let mut body = Body::new(&self.func_type().params);
+        let mut slots = Vec::new();
+
+        if flat.len() > MAX_FLAT_PARAMS {
+            // Arguments arrive as a record the callee owns
+            let area = AbiType::Record(self.params.clone());
+            let (offsets, _) = record_offsets(&self.params);
+            for (param, offset) in self.params.iter().zip(offsets) {
+                if param.is_direct() {
+                    body.load(param, 0, offset);
+                } else {
+                    body.get(0);
+                    body.i32_const(offset);
+                    body.op(0x6A);
+                }
+            }
+            slots.push((0, area.size(), area.align()));
+        } else {
+            let mut args = Vec::new();
+            let mut next = 0;
+            for param in &self.params {
+                let count = param.flat().len() as u32;
+                let locals: Vec<(u32, ValType)> = (next..next + count).zip(param.flat()).collect();
+                next += count;
+                if param.is_direct() {
+                    args.push(locals[0].0);
+                    continue;
+                }
+                let slot = body.local(ValType::I32);
+                body.i32_const(param.size());
+                body.i32_const(param.align());
+                body.call(self.alloc);
+                body.set(slot);
+                body.store_flat(param, &locals, slot, 0);
+                slots.push((slot, param.size(), param.align()));
+                args.push(slot);
+            }
+            for arg in args {
+                body.get(arg);
+            }
+        }
+        body.call(self.implementation);
+
+        let result = self.result.as_ref().map(|result| {
+            let local = body.local(result.source_type());
+            body.set(local);
+            (result, local)
+        });
+        for (slot, size, align) in slots {
+            body.free(self.free, slot, size, align);
+        }
+        match result {
+            Some((result, local)) if !result.is_direct() && self.flat_results().len() <= MAX_FLAT_RESULTS => {
+                // Small results are returned by value
+                let value = self.flat_results().first().map(|&ty| {
+                    let value = body.local(ty);
+                    body.load_single(result, local, 0);
+                    body.set(value);
+                    value
+                });
+                body.free(self.free, local, result.size(), result.align());
+                if let Some(value) = value {
+                    body.get(value);
+                }
+            }
+            Some((_, local)) => body.get(local),
+            None => {}
+        }
+        body.finish()
+    }
+
+    /// Body of the post-return function freeing a result returned in memory
+    fn post_return(&self) -> Option<Vec<u8>> {
+        let result = self.result.as_ref()?;
+        if self.flat_results().len() <= MAX_FLAT_RESULTS {
+            return None;
+        }
+        let mut body = Body::new(&[ValType::I32]);
+        body.free_lists(self.free, result, 0, 0);
+        body.free(self.free, 0, result.size(), result.align());
+        Some(body.finish())
+    }
+}
+
+/// Body of `cabi_realloc(old, old_size, align, new_size)`
+fn realloc_body(alloc: u32, free: u32) -> Vec<u8> {
+    let mut body = Body::new(&[ValType::I32; 4]);
+    let new = body.local(ValType::I32);
+    body.get(3);
+    body.get(2);
+    body.call(alloc);
+    body.set(new);
+    body.get(0);
+    body.begin(0x04);
+    // memory.copy(new, old, min(old_size, new_size))
+    body.get(new);
+    body.get(0);
+    body.get(1);
+    body.get(3);
+    body.get(1);
+    body.get(3);
+    body.op(0x49);
+    body.op(0x1B);
+    body.code.extend_from_slice(&[0xFC, 0x0A, 0x00, 0x00]);
+    body.get(0);
+    body.get(1);
+    body.get(2);
+    body.call(free);
+    body.end();
+    body.get(new);
+    body.finish()
+}
+
+/// Function body under construction
+struct Body {
+    params: u32,
+    locals: Vec<ValType>,
+    code: Vec<u8>,
+}
+
+impl Body {
+    fn new(params: &[ValType]) -> Self {
+        Self { params: params.len() as u32, locals: Vec::new(), code: Vec::new() }
+    }
+
+    fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        self.params + self.locals.len() as u32 - 1
+    }
+
+    fn op(&mut self, opcode: u8) {
+        self.code.push(opcode);
+    }
+
+    fn get(&mut self, local: u32) {
+        self.code.push(0x20);
+        write_u32(&mut self.code, local);
+    }
+
+    fn set(&mut self, local: u32) {
+        self.code.push(0x21);
+        write_u32(&mut self.code, local);
+    }
+
+    fn i32_const(&mut self, value: u32) {
+        self.code.push(0x41);
+        write_i64(&mut self.code, value as i32 as i64);
+    }
+
+    fn call(&mut self, function: u32) {
+        self.code.push(0x10);
+        write_u32(&mut self.code, function);
+    }
+
+    /// Opens a `block`, `loop`, or `if` without results
+    fn begin(&mut self, opcode: u8) {
+        self.code.extend_from_slice(&[opcode, 0x40]);
+    }
+
+    fn end(&mut self) {
+        self.code.push(0x0B);
+    }
+
+    fn memarg(&mut self, opcode: u8, size: u32, offset: u32) {
+        self.code.push(opcode);
+        write_u32(&mut self.code, size.max(1).trailing_zeros());
+        write_u32(&mut self.code, offset);
+    }
+
+    /// Pushes a scalar loaded from `addr + offset`
+    fn load(&mut self, ty: &AbiType, addr: u32, offset: u32) {
+        let AbiType::Scalar { size, signed, flat } = *ty else {
+            unreachable!("only scalars are loaded directly");
+        };
+        let opcode = match (size, signed, flat) {
+            (1, false, _) => 0x2D,
+            (1, true, _) => 0x2C,
+            (2, false, _) => 0x2F,
+            (2, true, _) => 0x2E,
+            (_, _, ValType::I64) => 0x29,
+            (_, _, ValType::F32) => 0x2A,
+            (_, _, ValType::F64) => 0x2B,
+            _ => 0x28,
+        };
+        self.get(addr);
+        self.memarg(opcode, size, offset);
+    }
+
+    /// Stores a scalar held in a local of the possibly wider type `from`
+    fn store(&mut self, ty: &AbiType, (value, from): (u32, ValType), addr: u32, offset: u32) {
+        let AbiType::Scalar { size, flat, .. } = *ty else {
+            unreachable!("only scalars are stored directly");
+        };
+        self.get(addr);
+        self.get(value);
+        // Narrow a joined variant payload slot back to the case's type
+        match (from, flat) {
+            (ValType::I64, ValType::I32) => self.op(0xA7),
+            (ValType::I64, ValType::F32) => self.code.extend_from_slice(&[0xA7, 0xBE]),
+            (ValType::I64, ValType::F64) => self.op(0xBF),
+            (ValType::I32, ValType::F32) => self.op(0xBE),
+            _ => {}
+        }
+        let opcode = match (size, flat) {
+            (1, _) => 0x3A,
+            (2, _) => 0x3B,
+            (_, ValType::I64) => 0x37,
+            (_, ValType::F32) => 0x38,
+            (_, ValType::F64) => 0x39,
+            _ => 0x36,
+        };
+        self.memarg(opcode, size, offset);
+    }
+
+    /// Stores a value flattened into `flat` locals at `addr + offset`
+    fn store_flat(&mut self, ty: &AbiType, flat: &[(u32, ValType)], addr: u32, offset: u32) {
+        let word = AbiType::Scalar { size: 4, signed: false, flat: ValType::I32 };
+        match ty {
+            AbiType::Scalar { .. } => self.store(ty, flat[0], addr, offset),
+            AbiType::List(_) => {
+                self.store(&word, flat[0], addr, offset);
+                self.store(&word, flat[1], addr, offset + 4);
+            }
+            AbiType::Record(fields) => {
+                let (offsets, _) = record_offsets(fields);
+                let mut next = 0;
+                for (field, field_offset) in fields.iter().zip(offsets) {
+                    let count = field.flat().len();
+                    self.store_flat(field, &flat[next..next + count], addr, offset + field_offset);
+                    next += count;
+                }
+            }
+            AbiType::Variant(cases) => {
+                self.store(&ty.discriminant(), flat[0], addr, offset);
+                for (i, case) in cases.iter().enumerate() {
+                    let Some(case) = case else { continue };
+                    let count = case.flat().len();
+                    if count == 0 {
+                        continue;
+                    }
+                    self.case(flat[0].0, i as u32);
+                    self.store_flat(case, &flat[1..1 + count], addr, offset + ty.payload_offset());
+                    self.end();
+                }
+            }
+        }
+    }
+
+    /// Pushes the core value of a type flattening to exactly one
+    fn load_single(&mut self, ty: &AbiType, addr: u32, offset: u32) {
+        match ty {
+            AbiType::Scalar { .. } => self.load(ty, addr, offset),
+            AbiType::Record(fields) => {
+                let (offsets, _) = record_offsets(fields);
+                let (field, field_offset) = fields.iter().zip(offsets)
+                    .find(|(field, _)| !field.flat().is_empty())
+                    .expect("one field flattens to a value");
+                self.load_single(field, addr, offset + field_offset);
+            }
+            // Only a variant without payloads flattens to its discriminant alone
+            AbiType::Variant(_) => self.load(&ty.discriminant(), addr, offset),
+            AbiType::List(_) => unreachable!("lists flatten to two values"),
+        }
+    }
+
+    /// Frees every list owned by the value at `addr + offset`
+    fn free_lists(&mut self, free: u32, ty: &AbiType, addr: u32, offset: u32) {
+        if !ty.owns_lists() {
+            return;
+        }
+        let word = AbiType::Scalar { size: 4, signed: false, flat: ValType::I32 };
+        match ty {
+            AbiType::Scalar { .. } => {}
+            AbiType::List(element) => {
+                let (ptr, len) = (self.local(ValType::I32), self.local(ValType::I32));
+                self.load(&word, addr, offset);
+                self.set(ptr);
+                self.load(&word, addr, offset + 4);
+                self.set(len);
+                if element.owns_lists() {
+                    let (cursor, remaining) = (self.local(ValType::I32), self.local(ValType::I32));
+                    self.get(ptr);
+                    self.set(cursor);
+                    self.get(len);
+                    self.set(remaining);
+                    self.begin(0x02);
+                    self.begin(0x03);
+                    self.get(remaining);
+                    self.op(0x45);
+                    self.code.extend_from_slice(&[0x0D, 0x01]);
+                    self.free_lists(free, element, cursor, 0);
+                    self.get(cursor);
+                    self.i32_const(element.size());
+                    self.op(0x6A);
+                    self.set(cursor);
+                    self.get(remaining);
+                    self.i32_const(1);
+                    self.op(0x6B);
+                    self.set(remaining);
+                    self.code.extend_from_slice(&[0x0C, 0x00]);
+                    self.end();
+                    self.end();
+                }
+                // Empty lists never own a buffer
+                self.get(len);
+                self.begin(0x04);
+                self.get(ptr);
+                self.get(len);
+                self.i32_const(element.size());
+                self.op(0x6C);
+                self.i32_const(element.align());
+                self.call(free);
+                self.end();
+            }
+            AbiType::Record(fields) => {
+                let (offsets, _) = record_offsets(fields);
+                for (field, field_offset) in fields.iter().zip(offsets) {
+                    self.free_lists(free, field, addr, offset + field_offset);
+                }
+            }
+            AbiType::Variant(cases) => {
+                let discriminant = self.local(ValType::I32);
+                self.load(&ty.discriminant(), addr, offset);
+                self.set(discriminant);
+                for (i, case) in cases.iter().enumerate() {
+                    let Some(case) = case.as_ref().filter(|case| case.owns_lists()) else { continue };
+                    self.case(discriminant, i as u32);
+                    self.free_lists(free, case, addr, offset + ty.payload_offset());
+                    self.end();
+                }
+            }
+        }
+    }
+
+    /// Opens an `if` taken when `discriminant` selects case `index`
+    fn case(&mut self, discriminant: u32, index: u32) {
+        self.get(discriminant);
+        self.i32_const(index);
+        self.op(0x46);
+        self.begin(0x04);
+    }
+
+    /// Calls `free(ptr, size, align)` on the buffer in `ptr`
+    fn free(&mut self, free: u32, ptr: u32, size: u32, align: u32) {
+        self.get(ptr);
+        self.i32_const(size);
+        self.i32_const(align);
+        self.call(free);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let groups = group_locals(&self.locals);
+        write_u32(&mut body, groups.len() as u32);
+        for (count, ty) in groups {
+            write_u32(&mut body, count);
+            body.push(ty.byte());
+        }
+        body.extend_from_slice(&self.code);
+        body.push(0x0B);
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{Signature, Type, WasmIR};
+
+    const SHAPES: &str = "package example:shapes;\n\
+        interface api {\n\
+            record point { x: u8, y: u64 }\n\
+            variant shape { circle(f32), rect(point), empty }\n\
+            name: func(p: point) -> string;\n\
+            measure: func(s: shape, scale: option<f32>) -> result<u64, f32>;\n\
+            area: func(x: u32, y: u32) -> u32;\n\
+        }\n\
+        world shapes { export api; }";
+
+    fn resolve(package: &WitPackage, ty: &str) -> AbiType {
+        AbiType::resolve(package, package.interface("api").unwrap(), &WitType::Named(ty.to_string())).unwrap()
+    }
+
+    fn function(name: &str, params: Vec<Type>, returns: Option<Type>) -> WasmIR {
+        WasmIR::new(name.to_string(), Signature { params, returns })
+    }
+
+    #[test]
+    fn test_abi_type_layout() {
+        let package = WitPackage::parse(SHAPES).unwrap();
+        let point = resolve(&package, "point");
+        assert_eq!((point.size(), point.align()), (16, 8));
+        assert_eq!(point.flat(), [ValType::I32, ValType::I64]);
+
+        let shape = resolve(&package, "shape");
+        assert_eq!((shape.size(), shape.align(), shape.payload_offset()), (24, 8, 8));
+        assert_eq!(shape.flat(), [ValType::I32, ValType::I32, ValType::I64]);
+        assert!(!shape.owns_lists());
+
+        let interface = package.interface("api").unwrap();
+        let result = AbiType::resolve(&package, interface, &interface.functions[1].result.clone().unwrap()).unwrap();
+        assert_eq!(result.flat(), [ValType::I32, ValType::I64]);
+        assert_eq!((result.size(), result.align()), (16, 8));
+    }
+
+    #[test]
+    fn test_lowers_exports_using_memory() {
+        let package = WitPackage::parse(SHAPES).unwrap();
+        let mut module = WasmModule::new();
+        let name = module.add_function(function("name", vec![Type::I32], Some(Type::I32)));
+        let measure = module.add_function(function("measure", vec![Type::I32, Type::I32], Some(Type::I32)));
+        let area = module.add_function(function("area", vec![Type::I32, Type::I32], Some(Type::I32)));
+        let alloc = module.add_function(function("alloc", vec![Type::I32; 2], Some(Type::I32)));
+        let free = module.add_function(function("free", vec![Type::I32; 3], None));
+        module.export_function("name", name);
+        module.export_function("api#measure", measure);
+        module.export_function("area", area);
+        module.export_function(ALLOC_EXPORT, alloc);
+        module.export_function(FREE_EXPORT, free);
+
+        let lowered = CanonicalExports::new(&module, &package, "shapes").unwrap();
+        assert_eq!(lowered.replaced, ["name", "api#measure"]);
+        let exports: Vec<&str> = lowered.functions.iter().map(|function| function.export.as_str()).collect();
+        assert_eq!(exports, ["name", "cabi_post_name", "api#measure", "cabi_post_api#measure"]);
+        assert_eq!(lowered.functions[0].func_type.params, [ValType::I32, ValType::I64]);
+        assert_eq!(
+            lowered.functions[2].func_type.params,
+            [ValType::I32, ValType::I32, ValType::I64, ValType::I32, ValType::F32],
+        );
+        assert_eq!(lowered.functions[2].func_type.results, [ValType::I32]);
+    }
+
+    #[test]
+    fn test_rejects_mismatched_implementations() {
+        let package = WitPackage::parse(SHAPES).unwrap();
+        let mut module = WasmModule::new();
+        let name = module.add_function(function("name", vec![Type::I32, Type::I64], Some(Type::I32)));
+        module.export_function("name", name);
+        let err = CanonicalExports::new(&module, &package, "shapes").unwrap_err();
+        assert!(matches!(err, BackendError::CompilationFailed(msg) if msg.contains("api#name must have type [I32]")));
+
+        let mut module = WasmModule::new();
+        let name = module.add_function(function("name", vec![Type::I32], Some(Type::I32)));
+        module.export_function("name", name);
+        let err = CanonicalExports::new(&module, &package, "shapes").unwrap_err();
+        assert!(matches!(err, BackendError::CompilationFailed(msg) if msg.contains("does not export __wasm_alloc")));
+    }
+}
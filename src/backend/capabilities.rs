@@ -0,0 +1,413 @@
+//! Host capabilities a compiled module needs, for deployers
+//!
+//! `CapabilityManifest::of` gathers every `Capability` a module requires:
+//! those its functions are annotated with, check with
+//! `Instruction::CapabilityCheck`, or pass as `Type::Capability` values,
+//! and those implied by what it uses. Imports from the JavaScript glue and
+//! JS objects need `JsInterop`, shared memory and spawning threads need
+//! `Threading`, atomic accesses need `AtomicMemory`, and canonical ABI
+//! intrinsics and WIT interfaces need `ComponentModel`.
+//!
+//! Codegen embeds the manifest as JSON in the `CAPABILITIES_SECTION`
+//! custom section, which `CapabilityManifest::from_binary` reads back, and
+//! `wasm-rustc --capabilities` prints it.
+//!
+//! A `CapabilityPolicy` restricts what modules may need, for reviewing
+//! dependencies. Codegen checks the module after lowering, since lowering
+//! adds imports of its own, and fails naming each forbidden capability
+//! with the functions and imports needing it.
+
+use serde_json::{json, Value};
+use std::fmt;
+use wasm::component::canon_async::ROOT_IMPORT_MODULE;
+use wasm::threading::runtime::{SPAWN_IMPORT, SPAWN_IMPORT_MODULE};
+use wasm::wasmir::{Capability, Instruction, Signature, Type, WasmModule, JS_IMPORT_MODULE};
+use wasmparser::{BinaryReaderError, Parser, Payload};
+
+/// Custom section holding a module's manifest
+pub const CAPABILITIES_SECTION: &str = "wasmrust.capabilities";
+
+/// Version of the section's JSON layout
+pub const MANIFEST_VERSION: u64 = 1;
+
+/// What requires the shared memory
+const MEMORY: &str = "memory";
+
+/// Capabilities a module needs from its host, with what needs each
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CapabilityManifest {
+    /// In `Capability` declaration order, regions and custom ones by name
+    pub requirements: Vec<Requirement>,
+}
+
+/// Capability and what needs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    pub capability: Capability,
+    /// Function names, `module::name` of imports, or `memory`, in order of first use
+    pub required_by: Vec<String>,
+}
+
+impl CapabilityManifest {
+    /// Capabilities `module` requires
+    pub fn of(module: &WasmModule) -> Self {
+        let mut manifest = Self::default();
+        if module.shared_memory {
+            manifest.require(Capability::Threading, MEMORY);
+            manifest.require(Capability::AtomicMemory, MEMORY);
+        }
+        for import in &module.imports {
+            let by = format!("{}::{}", import.module, import.name);
+            if import.module == JS_IMPORT_MODULE {
+                manifest.require(Capability::JsInterop, &by);
+            } else if import.module == SPAWN_IMPORT_MODULE && import.name == SPAWN_IMPORT {
+                manifest.require(Capability::Threading, &by);
+            } else if import.module == ROOT_IMPORT_MODULE || import.module.contains(':') {
+                manifest.require(Capability::ComponentModel, &by);
+            }
+            manifest.require_signature(import.signature(), &by);
+        }
+        for function in &module.functions {
+            let by = function.name.as_str();
+            for capability in &function.capabilities {
+                manifest.require(capability.clone(), by);
+            }
+            manifest.require_signature(&function.signature, by);
+            for ty in &function.locals {
+                manifest.require_type(ty, by);
+            }
+            let instructions = function.basic_blocks.iter().flat_map(|block| &block.instructions);
+            for instruction in instructions {
+                match instruction {
+                    Instruction::CapabilityCheck { capability } => manifest.require(capability.clone(), by),
+                    Instruction::AtomicOp { .. } | Instruction::CompareExchange { .. } => {
+                        manifest.require(Capability::AtomicMemory, by)
+                    }
+                    Instruction::AtomicWait { .. } | Instruction::AtomicNotify { .. } => {
+                        manifest.require(Capability::Threading, by);
+                        manifest.require(Capability::AtomicMemory, by);
+                    }
+                    Instruction::NewObject { .. } | Instruction::JSMethodCall { .. } => {
+                        manifest.require(Capability::JsInterop, by)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        manifest
+    }
+
+    /// Records that `by` needs `capability`
+    pub fn require(&mut self, capability: Capability, by: &str) {
+        match self.requirements.iter_mut().find(|requirement| requirement.capability == capability) {
+            Some(requirement) if requirement.required_by.iter().any(|other| other == by) => {}
+            Some(requirement) => requirement.required_by.push(by.to_string()),
+            None => {
+                self.requirements.push(Requirement { capability, required_by: vec![by.to_string()] });
+                self.requirements.sort_by_key(|requirement| order(&requirement.capability));
+            }
+        }
+    }
+
+    fn require_signature(&mut self, signature: &Signature, by: &str) {
+        for ty in signature.params.iter().chain(&signature.returns) {
+            self.require_type(ty, by);
+        }
+    }
+
+    fn require_type(&mut self, ty: &Type, by: &str) {
+        match ty {
+            Type::Capability { inner_type, capability } => {
+                self.require(capability.clone(), by);
+                self.require_type(inner_type, by);
+            }
+            Type::Linear { inner_type } | Type::Promise(inner_type) => self.require_type(inner_type, by),
+            Type::Struct { fields } => fields.iter().for_each(|field| self.require_type(field, by)),
+            _ => {}
+        }
+    }
+
+    pub fn contains(&self, capability: &Capability) -> bool {
+        self.requirements.iter().any(|requirement| &requirement.capability == capability)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let requirements: Vec<Value> = self.requirements.iter()
+            .map(|requirement| {
+                json!({ "capability": name(&requirement.capability), "required_by": requirement.required_by })
+            })
+            .collect();
+        json!({ "version": MANIFEST_VERSION, "capabilities": requirements })
+    }
+
+    pub fn from_json(value: &Value) -> Option<Self> {
+        if value["version"].as_u64()? != MANIFEST_VERSION {
+            return None;
+        }
+        let requirements = value["capabilities"].as_array()?.iter()
+            .map(|requirement| {
+                let required_by = requirement["required_by"].as_array()?.iter()
+                    .map(|by| by.as_str().map(str::to_string))
+                    .collect::<Option<_>>()?;
+                Some(Requirement { capability: from_name(requirement["capability"].as_str()?)?, required_by })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self { requirements })
+    }
+
+    /// Manifest embedded in a compiled module, if codegen recorded one
+    pub fn from_binary(binary: &[u8]) -> Result<Option<Self>, ManifestError> {
+        for payload in Parser::new(0).parse_all(binary) {
+            let Payload::CustomSection(reader) = payload.map_err(ManifestError::Read)? else { continue };
+            if reader.name() != CAPABILITIES_SECTION {
+                continue;
+            }
+            let value: Value = serde_json::from_slice(reader.data()).map_err(|_| ManifestError::Malformed)?;
+            return Self::from_json(&value).map(Some).ok_or(ManifestError::Malformed);
+        }
+        Ok(None)
+    }
+}
+
+/// One capability per line, with what needs it
+impl fmt::Display for CapabilityManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no host capabilities required");
+        }
+        let width = self.requirements.iter().map(|requirement| name(&requirement.capability).len()).max();
+        for requirement in &self.requirements {
+            let name = name(&requirement.capability);
+            writeln!(f, "{:width$}  {}", name, requirement.required_by.join(", "), width = width.unwrap_or(0))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reasons an embedded manifest cannot be read
+#[derive(Debug)]
+pub enum ManifestError {
+    /// The binary does not parse
+    Read(BinaryReaderError),
+    /// The section is not a manifest of `MANIFEST_VERSION`
+    Malformed,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Read(err) => write!(f, "cannot read the module: {}", err),
+            ManifestError::Malformed => write!(f, "malformed {} section", CAPABILITIES_SECTION),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+/// Capabilities modules may need
+///
+/// The default allows every capability.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CapabilityPolicy {
+    /// Capabilities allowed, or every one not denied if `None`
+    allowed: Option<Vec<Capability>>,
+    denied: Vec<Capability>,
+}
+
+impl CapabilityPolicy {
+    /// Allows only the given capabilities
+    pub fn allow_only(capabilities: impl IntoIterator<Item = Capability>) -> Self {
+        Self { allowed: Some(capabilities.into_iter().collect()), denied: Vec::new() }
+    }
+
+    /// Forbids a capability, even if allowed
+    pub fn deny(mut self, capability: Capability) -> Self {
+        if !self.denied.contains(&capability) {
+            self.denied.push(capability);
+        }
+        self
+    }
+
+    /// Whether every capability is allowed
+    pub fn allows_all(&self) -> bool {
+        self.allowed.is_none() && self.denied.is_empty()
+    }
+
+    pub fn permits(&self, capability: &Capability) -> bool {
+        let unlisted = self.allowed.as_ref().is_some_and(|allowed| !allowed.contains(capability));
+        !self.denied.contains(capability) && !unlisted
+    }
+
+    /// Checks that the policy permits everything `manifest` requires
+    pub fn check(&self, manifest: &CapabilityManifest) -> Result<(), PolicyViolation> {
+        let forbidden: Vec<Requirement> = manifest.requirements.iter()
+            .filter(|requirement| !self.permits(&requirement.capability))
+            .cloned()
+            .collect();
+        match forbidden.is_empty() {
+            true => Ok(()),
+            false => Err(PolicyViolation { forbidden }),
+        }
+    }
+}
+
+/// Capabilities a module needs that the policy forbids
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub forbidden: Vec<Requirement>,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "capability policy forbids ")?;
+        for (index, requirement) in self.forbidden.iter().enumerate() {
+            let required_by: Vec<String> = requirement.required_by.iter().map(|by| format!("`{}`", by)).collect();
+            let separator = if index == 0 { "" } else { "; " };
+            write!(f, "{}{}, required by {}", separator, name(&requirement.capability), required_by.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+/// Name of a capability in the manifest, as in `memory-region:heap`
+pub fn name(capability: &Capability) -> String {
+    match capability {
+        Capability::JsInterop => "js-interop".to_string(),
+        Capability::Threading => "threading".to_string(),
+        Capability::AtomicMemory => "atomic-memory".to_string(),
+        Capability::ComponentModel => "component-model".to_string(),
+        Capability::MemoryRegion(region) => format!("memory-region:{}", region),
+        Capability::Custom(custom) => format!("custom:{}", custom),
+    }
+}
+
+/// Parses a capability name, as `name` spells it
+pub fn from_name(name: &str) -> Option<Capability> {
+    match name.split_once(':') {
+        Some(("memory-region", region)) => Some(Capability::MemoryRegion(region.to_string())),
+        Some(("custom", custom)) => Some(Capability::Custom(custom.to_string())),
+        _ => match name {
+            "js-interop" => Some(Capability::JsInterop),
+            "threading" => Some(Capability::Threading),
+            "atomic-memory" => Some(Capability::AtomicMemory),
+            "component-model" => Some(Capability::ComponentModel),
+            _ => None,
+        },
+    }
+}
+
+/// Parses a comma-separated list of capability names
+pub fn from_list(list: &str) -> Result<Vec<Capability>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            from_name(item).ok_or_else(|| {
+                format!(
+                    "unknown capability `{}`; expected js-interop, threading, atomic-memory, component-model, \
+                     memory-region:<name>, or custom:<name>",
+                    item
+                )
+            })
+        })
+        .collect()
+}
+
+fn order(capability: &Capability) -> (u8, String) {
+    match capability {
+        Capability::JsInterop => (0, String::new()),
+        Capability::Threading => (1, String::new()),
+        Capability::AtomicMemory => (2, String::new()),
+        Capability::ComponentModel => (3, String::new()),
+        Capability::MemoryRegion(region) => (4, region.clone()),
+        Capability::Custom(custom) => (5, custom.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{Terminator, WasmIR};
+
+    fn module() -> WasmModule {
+        let mut module = WasmModule::new();
+        module.declare_panic_hook();
+        let signature = Signature { params: vec![], returns: None };
+        module.add_import("wasi:clocks/monotonic-clock", "now", signature.clone());
+        let mut function = WasmIR::new("render".to_string(), signature);
+        function.add_capability(Capability::MemoryRegion("framebuffer".to_string()));
+        function.locals = vec![Type::Capability {
+            inner_type: Box::new(Type::I32),
+            capability: Capability::Custom("gpu".to_string()),
+        }];
+        function.add_basic_block(
+            vec![
+                Instruction::CapabilityCheck { capability: Capability::JsInterop },
+                Instruction::CapabilityCheck { capability: Capability::MemoryRegion("framebuffer".to_string()) },
+            ],
+            Terminator::Return { value: None },
+        );
+        module.add_function(function);
+        module
+    }
+
+    #[test]
+    fn test_aggregates_capabilities() {
+        let mut module = module();
+        module.shared_memory = true;
+        let manifest = CapabilityManifest::of(&module);
+        let names: Vec<String> = manifest.requirements.iter().map(|required| name(&required.capability)).collect();
+        assert_eq!(names, [
+            "js-interop",
+            "threading",
+            "atomic-memory",
+            "component-model",
+            "memory-region:framebuffer",
+            "custom:gpu",
+        ]);
+        assert_eq!(manifest.requirements[0].required_by, ["wasmrust::__wasmrust_panic", "render"]);
+        assert_eq!(manifest.requirements[4].required_by, ["render"]);
+        assert_eq!(
+            manifest.to_string().lines().nth(3),
+            Some("component-model            wasi:clocks/monotonic-clock::now")
+        );
+        assert!(CapabilityManifest::of(&WasmModule::new()).is_empty());
+    }
+
+    #[test]
+    fn test_round_trips_through_json() {
+        let manifest = CapabilityManifest::of(&module());
+        assert_eq!(CapabilityManifest::from_json(&manifest.to_json()), Some(manifest.clone()));
+        assert_eq!(manifest.to_json()["capabilities"][0]["capability"], "js-interop");
+        assert_eq!(CapabilityManifest::from_json(&json!({ "version": 2, "capabilities": [] })), None);
+        assert_eq!(from_name("memory-region:heap"), Some(Capability::MemoryRegion("heap".to_string())));
+        assert_eq!(from_name("network"), None);
+    }
+
+    #[test]
+    fn test_policy_names_what_needs_forbidden_capabilities() {
+        let manifest = CapabilityManifest::of(&module());
+        assert!(CapabilityPolicy::default().check(&manifest).is_ok());
+        let gpu = Capability::Custom("gpu".to_string());
+        let policy = CapabilityPolicy::default().deny(Capability::JsInterop).deny(gpu.clone());
+        assert_eq!(
+            policy.check(&manifest).unwrap_err().to_string(),
+            "capability policy forbids js-interop, required by `wasmrust::__wasmrust_panic`, `render`; \
+             custom:gpu, required by `render`"
+        );
+
+        let allowed = from_list("js-interop, component-model,memory-region:framebuffer").unwrap();
+        let policy = CapabilityPolicy::allow_only(allowed);
+        let violation = policy.check(&manifest).unwrap_err();
+        assert_eq!(violation.forbidden.len(), 1);
+        assert_eq!(violation.forbidden[0].capability, gpu);
+        assert!(policy.clone().deny(Capability::JsInterop).check(&manifest).unwrap_err().forbidden.len() == 2);
+        assert!(from_list("js-interop,network").unwrap_err().starts_with("unknown capability `network`"));
+    }
+}
@@ -0,0 +1,2902 @@
+//! WebAssembly binary emission for WasmRust
+//!
+//! This module encodes a `WasmModule` directly into the WebAssembly binary
+//! format without going through an external code generator. Arbitrary
+//! WasmIR control flow is lowered with a label-dispatch loop: every basic
+//! block becomes one arm of a `br_table`, and branches store the target
+//! block index in a dedicated label local before jumping back to the loop.
+
+use crate::backend::canonical::{CanonicalExports, SynthesizedFunction, REALLOC_EXPORT};
+use crate::backend::capabilities::{CapabilityManifest, CapabilityPolicy, CAPABILITIES_SECTION};
+use crate::backend::demangle::demangle_or_raw;
+use crate::backend::dump::PassDump;
+use crate::backend::dylink::{self, DylinkInfo};
+use crate::backend::freestanding;
+use crate::backend::object::{
+    self, SymbolKind, SymbolTable, LINEAR_MEMORY_IMPORT, START_PRIORITY, SYMBOL_BINDING_LOCAL, SYMBOL_EXPORTED,
+    SYMBOL_UNDEFINED,
+};
+use crate::backend::preinit::{needs_pre_initialization, pre_initialize};
+use crate::backend::target_features::{TargetFeatures, TARGET_FEATURES_SECTION};
+use crate::backend::{BackendError, Relocation, RelocationKind};
+use crate::timings::{self, Phase};
+use std::collections::HashMap;
+use wasm::component::wit::WitPackage;
+use wasm::component::{Adapter, ComponentEncoder};
+use wasm::memory::allocator::{lower_allocations, needs_allocator, AllocatorStrategy};
+use wasm::memory::regions::{check_region_accesses, has_memory_regions, tag_region_accesses};
+use wasm::memory::stack::{
+    has_stack_frames, lower_stack_frames, lower_stack_frames_unchecked, DEFAULT_STACK_SIZE, STACK_POINTER_GLOBAL,
+};
+use wasm::threading::runtime::{add_thread_start, needs_thread_start};
+use wasm::threading::tls::{lower_thread_locals, uses_thread_locals};
+use wasm::wasmir::features::{FeatureSet, FeatureViolation, Proposal, Site};
+use wasm::wasmir::legalize::legalize_i128;
+use wasm::wasmir::{
+    BasicBlock, BinaryOp, Capability, Constant, ConvertOp, ExportKind, Import, Instruction, MemoryType, Operand,
+    Signature, Terminator, Type, UnaryOp, WasmIR, WasmModule, ALLOC_EXPORT, FREE_EXPORT,
+    JS_IMPORT_MODULE, MEMORY_IMPORT, MEMORY_IMPORT_MODULE, PANIC_IMPORT,
+};
+
+/// `\0asm` magic number
+const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
+
+/// Binary format version 1
+const WASM_VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+/// Export name of the synthesized initializer function
+pub const CALL_CTORS_EXPORT: &str = "__wasm_call_ctors";
+
+/// Export name of the indirect function table closures are invoked through
+pub const FUNCTION_TABLE_EXPORT: &str = "__indirect_function_table";
+
+/// Custom section naming the module's functions
+pub const NAME_SECTION: &str = "name";
+
+/// Id of the function names subsection of `NAME_SECTION`
+const NAME_SUBSECTION_FUNCTIONS: u8 = 1;
+
+/// Maximum pages of a shared memory that does not declare one
+const MAX_PAGES: u32 = 65536;
+
+/// What `WasmCodegen::emit` writes a module as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Module,
+    /// Relocatable object; see `backend::object`
+    Object,
+    /// Side module for dynamic linking; see `backend::dylink`
+    Shared,
+}
+
+/// Section identifiers from the core specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SectionId {
+    Custom = 0,
+    Type = 1,
+    Import = 2,
+    Function = 3,
+    Table = 4,
+    Memory = 5,
+    Global = 6,
+    Export = 7,
+    Start = 8,
+    Element = 9,
+    Code = 10,
+    Data = 11,
+    /// Exception tags from the exception-handling proposal
+    Tag = 13,
+}
+
+/// How module initializers are run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InitStrategy {
+    /// Run initializers from the start section during instantiation
+    #[default]
+    StartSection,
+    /// Export `__wasm_call_ctors` for the host glue to call after instantiation
+    ExportedCallCtors,
+}
+
+/// What a `Panic` terminator does at run time
+///
+/// Either way the panic hook is called first when the module imports
+/// `wasmrust::__wasmrust_panic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicStrategy {
+    /// Trap with `unreachable`
+    #[default]
+    Abort,
+    /// Throw the module's panic tag with the message index as payload
+    ///
+    /// Needs the exception-handling proposal.
+    Unwind,
+}
+
+/// Core WebAssembly value types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+    FuncRef,
+    ExternRef,
+}
+
+impl ValType {
+    /// Binary encoding of the value type
+    pub fn byte(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7F,
+            ValType::I64 => 0x7E,
+            ValType::F32 => 0x7D,
+            ValType::F64 => 0x7C,
+            ValType::FuncRef => 0x70,
+            ValType::ExternRef => 0x6F,
+        }
+    }
+
+    /// Maps a WasmIR type to its core value type
+    pub fn from_type(ty: &Type) -> Result<Self, BackendError> {
+        match ty {
+            Type::I32 => Ok(ValType::I32),
+            Type::I64 => Ok(ValType::I64),
+            Type::F32 => Ok(ValType::F32),
+            Type::F64 => Ok(ValType::F64),
+            Type::ExternRef(_) => Ok(ValType::ExternRef),
+            Type::FuncRef => Ok(ValType::FuncRef),
+            Type::Pointer(_) => Ok(ValType::I32),
+            Type::Linear { inner_type } => Self::from_type(inner_type),
+            Type::Capability { inner_type, .. } => Self::from_type(inner_type),
+            Type::Promise(_) => Err(BackendError::Unsupported(
+                "promise types must be lowered with WasmModule::lower_promises".to_string(),
+            )),
+            Type::I128 => Err(BackendError::Unsupported(
+                "128-bit integers must be split with wasmir::legalize::legalize_i128".to_string(),
+            )),
+            Type::Array { .. } | Type::Struct { .. } | Type::Void => Err(BackendError::Unsupported(
+                format!("type {:?} has no core value representation", ty),
+            )),
+        }
+    }
+}
+
+/// Function type as it appears in the type section
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl FuncType {
+    pub(crate) fn from_signature(signature: &Signature) -> Result<Self, BackendError> {
+        let params = signature.params.iter()
+            .map(ValType::from_type)
+            .collect::<Result<Vec<_>, _>>()?;
+        let results = match &signature.returns {
+            Some(Type::Void) | None => Vec::new(),
+            Some(ty) => vec![ValType::from_type(ty)?],
+        };
+        Ok(Self { params, results })
+    }
+}
+
+/// WasmIR to WebAssembly binary code generator
+#[derive(Debug, Clone, Default)]
+pub struct WasmCodegen {
+    init_strategy: InitStrategy,
+    panic_strategy: PanicStrategy,
+    allocator: AllocatorStrategy,
+    /// Shadow stack size in bytes, `DEFAULT_STACK_SIZE` if unset
+    stack_size: Option<u32>,
+    /// Whether prologues leave out the stack limit check
+    unchecked_stack: bool,
+    /// Whether accesses through region-typed locals trap outside their region
+    region_checks: bool,
+    features: FeatureSet,
+    /// Whether a `name` section names the functions
+    debug_info: bool,
+    /// Whether the `name` section demangles Rust symbols
+    demangle: bool,
+    /// Whether a `target_features` section lists the enabled proposals
+    record_features: bool,
+    /// Whether a capabilities section lists what the module needs from its host
+    record_capabilities: bool,
+    /// Capabilities modules may need
+    capability_policy: CapabilityPolicy,
+    /// Whether modules must do without the standard library and runtime
+    freestanding: bool,
+    /// Whether initializers run at build time, baked into the module
+    pre_initialize: bool,
+    /// Where the WasmIR after each lowering pass goes
+    dump: Option<PassDump>,
+}
+
+impl WasmCodegen {
+    /// Creates a new code generator
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how constructors and the start function are run
+    pub fn init_strategy(mut self, strategy: InitStrategy) -> Self {
+        self.init_strategy = strategy;
+        self
+    }
+
+    /// Sets how panics are lowered
+    pub fn panic_strategy(mut self, strategy: PanicStrategy) -> Self {
+        self.panic_strategy = strategy;
+        self
+    }
+
+    /// Sets where heap allocations get their memory from
+    pub fn allocator(mut self, strategy: AllocatorStrategy) -> Self {
+        self.allocator = strategy;
+        self
+    }
+
+    /// Sets the bytes reserved for the shadow stack of address-taken locals
+    ///
+    /// The stack is rounded up to whole pages.
+    pub fn stack_size(mut self, bytes: u32) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Sets whether prologues trap when a frame passes the stack limit
+    ///
+    /// On by default. Without the check, an overflowing frame overwrites
+    /// the memory below the stack.
+    pub fn stack_check(mut self, enabled: bool) -> Self {
+        self.unchecked_stack = !enabled;
+        self
+    }
+
+    /// Sets whether loads and stores through region-typed locals trap outside their region
+    ///
+    /// Off by default; accesses are tagged with their memory regions and
+    /// checked against the regions' permissions either way. See
+    /// `wasm::memory::regions`.
+    pub fn region_checks(mut self, enabled: bool) -> Self {
+        self.region_checks = enabled;
+        self
+    }
+
+    /// Sets the proposals the target engine enables
+    ///
+    /// Modules needing any other proposal are rejected before emission.
+    pub fn features(mut self, features: FeatureSet) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Sets whether the module gets a `name` section naming its functions
+    ///
+    /// Debuggers, profilers, and stack traces show these names instead of
+    /// function indices.
+    pub fn debug_info(mut self, enabled: bool) -> Self {
+        self.debug_info = enabled;
+        self
+    }
+
+    /// Sets whether the `name` section holds Rust symbols demangled, as in
+    /// `core::fmt::write`, rather than as rustc mangled them
+    pub fn demangle(mut self, enabled: bool) -> Self {
+        self.demangle = enabled;
+        self
+    }
+
+    /// Sets whether the module gets a `target_features` section listing the enabled proposals
+    ///
+    /// wasm-ld and wasm-opt read it to keep linked and optimized modules
+    /// within the proposals they were compiled for.
+    pub fn record_features(mut self, enabled: bool) -> Self {
+        self.record_features = enabled;
+        self
+    }
+
+    /// Sets whether the module gets a `CAPABILITIES_SECTION` listing what it needs from its host
+    ///
+    /// Deployers read it with `CapabilityManifest::from_binary`; see
+    /// `backend::capabilities`.
+    pub fn record_capabilities(mut self, enabled: bool) -> Self {
+        self.record_capabilities = enabled;
+        self
+    }
+
+    /// Sets the capabilities modules may need, rejecting modules needing others
+    ///
+    /// The module is checked after lowering, so capabilities the lowering
+    /// passes introduce count too.
+    pub fn capability_policy(mut self, policy: CapabilityPolicy) -> Self {
+        self.capability_policy = policy;
+        self
+    }
+
+    /// Sets whether modules are rejected when they need the standard library or runtime
+    ///
+    /// See `backend::freestanding` for what they may not use.
+    pub fn freestanding(mut self, enabled: bool) -> Self {
+        self.freestanding = enabled;
+        self
+    }
+
+    /// Sets whether constructors and the start function run at build time
+    ///
+    /// The memory and globals they leave are baked into the module, which
+    /// then has no initializers to run when instantiated; see
+    /// `backend::preinit`.
+    pub fn pre_initialize(mut self, enabled: bool) -> Self {
+        self.pre_initialize = enabled;
+        self
+    }
+
+    /// Records the WasmIR after each lowering pass in `dump`
+    pub fn dump_passes(mut self, dump: PassDump) -> Self {
+        self.dump = Some(dump);
+        self
+    }
+
+    /// Compiles a single function into a module exporting it by name
+    pub fn compile_function(&self, function: &WasmIR) -> Result<Vec<u8>, BackendError> {
+        let mut module = WasmModule::new();
+        let index = module.add_function(function.clone());
+        module.export_function(function.name.clone(), index);
+        self.compile(&module)
+    }
+
+    /// Compiles a module to the WebAssembly binary format
+    pub fn compile(&self, module: &WasmModule) -> Result<Vec<u8>, BackendError> {
+        self.emit(module, None, Output::Module)
+    }
+
+    /// Compiles a module to a relocatable object for wasm-ld
+    ///
+    /// The object links next to C and C++ objects; see `backend::object`
+    /// for its conventions and the modules it rejects.
+    pub fn compile_object(&self, module: &WasmModule) -> Result<Vec<u8>, BackendError> {
+        self.emit(module, None, Output::Object)
+    }
+
+    /// Compiles a module to a side module, loaded into a running main module as a plugin
+    ///
+    /// Its imports resolve against the main module's exports; see
+    /// `backend::dylink` for the conventions and the modules it rejects.
+    pub fn compile_shared(&self, module: &WasmModule) -> Result<Vec<u8>, BackendError> {
+        self.emit(module, None, Output::Shared)
+    }
+
+    /// Compiles a module and wraps it into a component implementing `world`
+    ///
+    /// Exports and imports are matched to the world's functions by name; see
+    /// `wasm::component::adapter` for the conventions. Exports passing
+    /// strings, lists, records, or variants take them as source-level values
+    /// and are lowered as described in `backend::canonical`.
+    pub fn compile_component(
+        &self,
+        module: &WasmModule,
+        package: &WitPackage,
+        world: &str,
+    ) -> Result<Vec<u8>, BackendError> {
+        let core = self.emit(module, Some((package, world)), Output::Module)?;
+        let adapter = Adapter::new(package, world)
+            .adapt(&core)
+            .map_err(|e| BackendError::CompilationFailed(format!("component adapter: {}", e)))?;
+        ComponentEncoder::new(package, &adapter)
+            .encode(&core)
+            .map_err(|e| BackendError::CompilationFailed(format!("component encoding: {}", e)))
+    }
+
+    /// Emits a core module as `output`, lowering the exports of `world` if one is given
+    fn emit(
+        &self,
+        module: &WasmModule,
+        world: Option<(&WitPackage, &str)>,
+        output_kind: Output,
+    ) -> Result<Vec<u8>, BackendError> {
+        if output_kind != Output::Module {
+            self.check_relocatable(module, output_kind)?;
+        }
+        let regioned;
+        let module = if has_memory_regions(module) {
+            let _pass = timings::span(Phase::Pass, "tag memory regions");
+            let tagged = tag_region_accesses(module).map_err(|error| BackendError::Unsupported(error.to_string()))?;
+            regioned = if self.region_checks { check_region_accesses(&tagged).0 } else { tagged };
+            self.record_pass("tag memory regions", &regioned);
+            &regioned
+        } else {
+            module
+        };
+        let lowered;
+        let module = if module.uses_promises() {
+            let _pass = timings::span(Phase::Pass, "lower promises");
+            lowered = module.lower_promises();
+            self.record_pass("lower promises", &lowered);
+            &lowered
+        } else {
+            module
+        };
+        let handles;
+        let module = if module.uses_externref_table() {
+            let _pass = timings::span(Phase::Pass, "lower externref handles");
+            handles = module.lower_externref_handles();
+            self.record_pass("lower externref handles", &handles);
+            &handles
+        } else {
+            module
+        };
+        let threaded;
+        let module = if uses_thread_locals(module) {
+            let _pass = timings::span(Phase::Pass, "lower thread locals");
+            threaded = lower_thread_locals(module).map_err(|error| BackendError::Unsupported(error.to_string()))?;
+            self.record_pass("lower thread locals", &threaded);
+            &threaded
+        } else {
+            module
+        };
+        let stacked;
+        let module = if has_stack_frames(module) {
+            let _pass = timings::span(Phase::Pass, "lower stack frames");
+            let unchecked = self.unchecked_stack || output_kind != Output::Module;
+            let lower = if unchecked { lower_stack_frames_unchecked } else { lower_stack_frames };
+            stacked = lower(module, self.stack_size.unwrap_or(DEFAULT_STACK_SIZE))
+                .map_err(|error| BackendError::Unsupported(error.to_string()))?;
+            self.record_pass("lower stack frames", &stacked);
+            &stacked
+        } else {
+            module
+        };
+        let threaded_start;
+        let module = if needs_thread_start(module) {
+            let _pass = timings::span(Phase::Pass, "add thread start");
+            threaded_start = add_thread_start(module).map_err(|error| BackendError::Unsupported(error.to_string()))?;
+            self.record_pass("add thread start", &threaded_start);
+            &threaded_start
+        } else {
+            module
+        };
+        self.check_threads(module)?;
+        if self.freestanding {
+            freestanding::check(module).map_err(|violation| BackendError::Unsupported(violation.to_string()))?;
+        }
+        let allocated;
+        let module = if needs_allocator(module) {
+            let _pass = timings::span(Phase::Pass, "lower allocations");
+            allocated = lower_allocations(module, self.allocator)
+                .map_err(|error| BackendError::Unsupported(error.to_string()))?;
+            self.record_pass("lower allocations", &allocated);
+            &allocated
+        } else {
+            module
+        };
+        let legalized;
+        let module = if module.uses_i128() {
+            let _pass = timings::span(Phase::Pass, "legalize i128");
+            legalized = legalize_i128(module)
+                .map_err(|error| BackendError::Unsupported(error.to_string()))?;
+            self.record_pass("legalize i128", &legalized);
+            &legalized
+        } else {
+            module
+        };
+        let initialized;
+        let module = if self.pre_initialize && needs_pre_initialization(module) {
+            let _pass = timings::span(Phase::Pass, "pre-initialize");
+            initialized = pre_initialize(module).map_err(|error| BackendError::Unsupported(error.to_string()))?;
+            self.record_pass("pre-initialize", &initialized);
+            &initialized
+        } else {
+            module
+        };
+        {
+            let _pass = timings::span(Phase::Pass, "validate");
+            module.validate()
+                .map_err(|e| BackendError::CompilationFailed(format!("invalid module: {}", e)))?;
+            self.features.check_module(module)
+                .map_err(|violation| BackendError::Unsupported(violation.to_string()))?;
+            check_string_abi(module)?;
+            self.check_panic_strategy(module)?;
+        }
+        let manifest = {
+            let mut manifest = CapabilityManifest::of(module);
+            if let Some((_, world)) = world {
+                manifest.require(Capability::ComponentModel, world);
+            }
+            self.capability_policy.check(&manifest)
+                .map_err(|violation| BackendError::Unsupported(violation.to_string()))?;
+            manifest
+        };
+        let canonical = match world {
+            Some((package, world)) => CanonicalExports::new(module, package, world)?,
+            None => CanonicalExports::default(),
+        };
+        self.check_bulk_memory(&canonical)?;
+
+        let layout = {
+            let _layout = timings::span(Phase::Codegen, "layout");
+            // Loaders call the constructors of side modules once they are linked
+            let strategy = match output_kind {
+                Output::Shared => InitStrategy::ExportedCallCtors,
+                _ => self.init_strategy,
+            };
+            ModuleLayout::new(module, strategy, self.panic_strategy, &canonical.functions, output_kind)?
+        };
+
+        let _encode = timings::span(Phase::Encode, "encode");
+        let mut output = Vec::new();
+        output.extend_from_slice(&WASM_MAGIC);
+        output.extend_from_slice(&WASM_VERSION);
+
+        if output_kind == Output::Shared {
+            // Code of shared modules needs no memory or table of its own
+            dylink::write_dylink_section(&mut output, &DylinkInfo::default());
+        }
+        self.generate_type_section(&mut output, &layout);
+        self.generate_import_section(&mut output, module, &layout);
+        self.generate_function_section(&mut output, &layout);
+        self.generate_table_section(&mut output, &layout);
+        self.generate_memory_section(&mut output, module, &layout);
+        self.generate_tag_section(&mut output, &layout);
+        self.generate_global_section(&mut output, module, &layout)?;
+        self.generate_export_section(&mut output, module, &layout, &canonical);
+        self.generate_start_section(&mut output, &layout);
+        self.generate_element_section(&mut output, &layout);
+        let code_section = object::section_count(&output);
+        let relocations = self.generate_code_section(&mut output, module, &layout, &canonical.functions)?;
+        self.generate_data_section(&mut output, module)?;
+        if let Some(symbols) = &layout.symbols {
+            object::write_linking_section(&mut output, symbols, &layout.init_functions)?;
+            object::write_reloc_section(&mut output, "CODE", code_section, &relocations, symbols)?;
+        }
+        if self.debug_info {
+            self.generate_name_section(&mut output, module, &layout, &canonical.functions);
+        }
+        if self.record_features {
+            self.generate_target_features_section(&mut output);
+        }
+        if self.record_capabilities {
+            self.generate_capabilities_section(&mut output, &manifest);
+        }
+
+        Ok(output)
+    }
+
+    fn record_pass(&self, pass: &str, module: &WasmModule) {
+        if let Some(dump) = &self.dump {
+            dump.record(pass, module);
+        }
+    }
+
+    fn generate_type_section(&self, output: &mut Vec<u8>, layout: &ModuleLayout) {
+        let mut content = Vec::new();
+        write_u32(&mut content, layout.types.len() as u32);
+        for func_type in &layout.types {
+            content.push(0x60);
+            write_u32(&mut content, func_type.params.len() as u32);
+            content.extend(func_type.params.iter().map(|ty| ty.byte()));
+            write_u32(&mut content, func_type.results.len() as u32);
+            content.extend(func_type.results.iter().map(|ty| ty.byte()));
+        }
+        write_section(output, SectionId::Type, &content);
+    }
+
+    /// Writes the function imports, then the imported memory and global
+    ///
+    /// Only objects and shared modules import a global, the stack pointer,
+    /// and objects import their memory as `LINEAR_MEMORY_IMPORT`.
+    fn generate_import_section(&self, output: &mut Vec<u8>, module: &WasmModule, layout: &ModuleLayout) {
+        let imported = module.memory.filter(|_| module.imports_memory() || layout.output != Output::Module);
+        let global = layout.imported_global
+            .map(|index| (&module.globals[index as usize], layout.globals[index as usize]));
+        if module.imports.is_empty() && imported.is_none() && global.is_none() {
+            return;
+        }
+
+        let mut content = Vec::new();
+        write_u32(&mut content, module.imports.len() as u32 + imported.is_some() as u32 + global.is_some() as u32);
+        for (import, &type_index) in module.imports.iter().zip(&layout.import_types) {
+            write_name(&mut content, &import.module);
+            write_name(&mut content, &import.name);
+            content.push(0x00);
+            write_u32(&mut content, type_index);
+        }
+        if let Some(memory) = imported {
+            write_name(&mut content, MEMORY_IMPORT_MODULE);
+            let field = if layout.output == Output::Object { LINEAR_MEMORY_IMPORT } else { MEMORY_IMPORT };
+            write_name(&mut content, field);
+            content.push(0x02);
+            write_limits(&mut content, &memory, module.shared_memory);
+        }
+        if let Some((global, ty)) = global {
+            write_name(&mut content, MEMORY_IMPORT_MODULE);
+            write_name(&mut content, &global.name);
+            content.push(0x03);
+            content.push(ty.byte());
+            content.push(global.mutable as u8);
+        }
+        write_section(output, SectionId::Import, &content);
+    }
+
+    fn generate_function_section(&self, output: &mut Vec<u8>, layout: &ModuleLayout) {
+        let mut content = Vec::new();
+        write_u32(&mut content, layout.function_types.len() as u32);
+        for &type_index in &layout.function_types {
+            write_u32(&mut content, type_index);
+        }
+        write_section(output, SectionId::Function, &content);
+    }
+
+    /// Writes the funcref table holding every function of the module after a null slot
+    fn generate_table_section(&self, output: &mut Vec<u8>, layout: &ModuleLayout) {
+        let Some(functions) = layout.function_table else { return };
+
+        let mut content = Vec::new();
+        write_u32(&mut content, 1);
+        content.push(0x70);
+        // Fixed size: min and max
+        content.push(0x01);
+        write_u32(&mut content, functions + 1);
+        write_u32(&mut content, functions + 1);
+        write_section(output, SectionId::Table, &content);
+    }
+
+    /// Fills the function table, so table slot `i + 1` holds function `i`
+    fn generate_element_section(&self, output: &mut Vec<u8>, layout: &ModuleLayout) {
+        let Some(functions) = layout.function_table else { return };
+
+        let mut content = Vec::new();
+        write_u32(&mut content, 1);
+        // Active, in table 0, at offset `i32.const 1`
+        content.extend_from_slice(&[0x00, 0x41, 0x01, 0x0B]);
+        write_u32(&mut content, functions);
+        for index in 0..functions {
+            write_u32(&mut content, layout.defined(index));
+        }
+        write_section(output, SectionId::Element, &content);
+    }
+
+    fn generate_memory_section(&self, output: &mut Vec<u8>, module: &WasmModule, layout: &ModuleLayout) {
+        let memory = match &module.memory {
+            Some(memory) if !module.imports_memory() && layout.output == Output::Module => memory,
+            _ => return,
+        };
+
+        let mut content = Vec::new();
+        write_u32(&mut content, 1);
+        write_limits(&mut content, memory, false);
+        write_section(output, SectionId::Memory, &content);
+    }
+
+    fn generate_data_section(&self, output: &mut Vec<u8>, module: &WasmModule) -> Result<(), BackendError> {
+        if module.data_segments.is_empty() {
+            return Ok(());
+        }
+
+        let mut content = Vec::new();
+        write_u32(&mut content, module.data_segments.len() as u32);
+        for segment in &module.data_segments {
+            // Active, in memory 0
+            content.push(0x00);
+            encode_constant(&mut content, &Constant::I32(segment.offset as i32))?;
+            content.push(0x0B);
+            write_u32(&mut content, segment.bytes.len() as u32);
+            content.extend_from_slice(&segment.bytes);
+        }
+        write_section(output, SectionId::Data, &content);
+        Ok(())
+    }
+
+    fn generate_tag_section(&self, output: &mut Vec<u8>, layout: &ModuleLayout) {
+        let tag_type = match layout.panic_tag {
+            Some(index) => index,
+            None => return,
+        };
+
+        let mut content = Vec::new();
+        write_u32(&mut content, 1);
+        content.push(0x00);
+        write_u32(&mut content, tag_type);
+        write_section(output, SectionId::Tag, &content);
+    }
+
+    fn generate_global_section(
+        &self,
+        output: &mut Vec<u8>,
+        module: &WasmModule,
+        layout: &ModuleLayout,
+    ) -> Result<(), BackendError> {
+        let defined: Vec<_> = module.globals.iter()
+            .enumerate()
+            .filter(|&(index, _)| layout.imported_global != Some(index as u32))
+            .map(|(_, global)| global)
+            .collect();
+        if defined.is_empty() {
+            return Ok(());
+        }
+
+        let mut content = Vec::new();
+        write_u32(&mut content, defined.len() as u32);
+        for global in defined {
+            let ty = ValType::from_type(&global.ty)?;
+            content.push(ty.byte());
+            content.push(global.mutable as u8);
+            if encode_constant(&mut content, &global.init)? != ty {
+                return Err(BackendError::CompilationFailed(
+                    format!("initializer of global {} does not match its type", global.name),
+                ));
+            }
+            content.push(0x0B);
+        }
+        write_section(output, SectionId::Global, &content);
+        Ok(())
+    }
+
+    /// Rejects settings that break once another instance shares the memory
+    fn check_threads(&self, module: &WasmModule) -> Result<(), BackendError> {
+        if !module.shared_memory {
+            return Ok(());
+        }
+        if needs_allocator(module) && self.allocator != AllocatorStrategy::Host {
+            return Err(BackendError::Unsupported(
+                "threads share the heap, which needs the host allocator".to_string(),
+            ));
+        }
+        let has_init = !module.constructors.is_empty() || module.start_function.is_some();
+        if has_init && self.init_strategy == InitStrategy::StartSection {
+            return Err(BackendError::Unsupported(
+                "threads must not rerun initializers; export them with InitStrategy::ExportedCallCtors".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rejects what an object or shared module cannot hold; see `backend::object`
+    fn check_relocatable(&self, module: &WasmModule, output: Output) -> Result<(), BackendError> {
+        let reason = if !module.data_segments.is_empty() {
+            Some("data segments are placed at fixed addresses")
+        } else if !module.memory_regions.is_empty() {
+            Some("memory regions are placed at fixed addresses")
+        } else if module.shared_memory || uses_thread_locals(module) {
+            Some("thread locals and shared memory are not supported")
+        } else if needs_allocator(module) && self.allocator != AllocatorStrategy::Host {
+            Some("the bundled allocators place their heap at a fixed address; use the host allocator")
+        } else if self.pre_initialize && needs_pre_initialization(module) {
+            Some("pre-initialization bakes memory at fixed addresses")
+        } else if self.panic_strategy == PanicStrategy::Unwind && panics(module) {
+            Some("unwinding panics need a tag symbol; use PanicStrategy::Abort")
+        } else if module.uses_closures() {
+            Some("closures need table index relocations")
+        } else {
+            None
+        };
+        let output = if output == Output::Shared { "a shared module" } else { "a relocatable object" };
+        match reason {
+            Some(reason) => Err(BackendError::Unsupported(format!("cannot emit {}: {}", output, reason))),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that synthesized functions copying memory may use `memory.copy`
+    fn check_bulk_memory(&self, canonical: &CanonicalExports) -> Result<(), BackendError> {
+        let copies = canonical.functions.iter().find(|function| function.export == REALLOC_EXPORT);
+        match copies {
+            Some(function) if !self.features.contains(Proposal::BulkMemory) => Err(BackendError::Unsupported(format!(
+                "{}: memory.copy requires the disabled {} proposal",
+                function.export,
+                Proposal::BulkMemory.name()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects unwinding panics when exceptions are disabled
+    fn check_panic_strategy(&self, module: &WasmModule) -> Result<(), BackendError> {
+        if self.panic_strategy == PanicStrategy::Abort
+            || self.features.contains(Proposal::ExceptionHandling)
+        {
+            return Ok(());
+        }
+        for function in &module.functions {
+            let block = function.basic_blocks.iter()
+                .position(|block| matches!(block.terminator, Terminator::Panic { .. }));
+            if let Some(block) = block {
+                let violation = FeatureViolation {
+                    proposal: Proposal::ExceptionHandling,
+                    function: function.name.clone(),
+                    site: Site::Terminator { block },
+                    location: None,
+                };
+                return Err(BackendError::Unsupported(violation.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_export_section(
+        &self,
+        output: &mut Vec<u8>,
+        module: &WasmModule,
+        layout: &ModuleLayout,
+        canonical: &CanonicalExports,
+    ) {
+        if layout.symbols.is_some() {
+            // Objects export through their symbols
+            return;
+        }
+        let mut entries = Vec::new();
+        let mut count = 0u32;
+
+        for export in &module.exports {
+            if canonical.replaced.contains(&export.name) {
+                continue;
+            }
+            write_name(&mut entries, &export.name);
+            match export.kind {
+                ExportKind::Function(index) => {
+                    entries.push(0x00);
+                    write_u32(&mut entries, layout.defined(index));
+                }
+                ExportKind::Memory(index) => {
+                    entries.push(0x02);
+                    write_u32(&mut entries, index);
+                }
+            }
+            count += 1;
+        }
+
+        if layout.function_table.is_some() {
+            write_name(&mut entries, FUNCTION_TABLE_EXPORT);
+            entries.push(0x01);
+            write_u32(&mut entries, 0);
+            count += 1;
+        }
+
+        // Initializers not run by the start section are exported
+        if let Some(init) = layout.init_function.filter(|&init| layout.start_section != Some(init)) {
+            write_name(&mut entries, CALL_CTORS_EXPORT);
+            entries.push(0x00);
+            write_u32(&mut entries, init);
+            count += 1;
+        }
+
+        for (i, function) in canonical.functions.iter().enumerate() {
+            write_name(&mut entries, &function.export);
+            entries.push(0x00);
+            write_u32(&mut entries, layout.synthesized + i as u32);
+            count += 1;
+        }
+
+        if count == 0 {
+            return;
+        }
+
+        let mut content = Vec::new();
+        write_u32(&mut content, count);
+        content.extend_from_slice(&entries);
+        write_section(output, SectionId::Export, &content);
+    }
+
+    fn generate_start_section(&self, output: &mut Vec<u8>, layout: &ModuleLayout) {
+        if let Some(start) = layout.start_section {
+            let mut content = Vec::new();
+            write_u32(&mut content, start);
+            write_section(output, SectionId::Start, &content);
+        }
+    }
+
+    /// Writes the function bodies, returning the relocations of an object
+    /// at their offset in the section's content
+    fn generate_code_section(
+        &self,
+        output: &mut Vec<u8>,
+        module: &WasmModule,
+        layout: &ModuleLayout,
+        synthesized: &[SynthesizedFunction],
+    ) -> Result<Vec<Relocation>, BackendError> {
+        let mut content = Vec::new();
+        let mut relocations = Vec::new();
+        write_u32(&mut content, layout.function_types.len() as u32);
+
+        for function in &module.functions {
+            let _function = timings::span(Phase::Encode, &function.name);
+            let (body, body_relocations) = FunctionEncoder::new(function, layout, module.memory.is_some())
+                .encode()
+                .map_err(|e| match e {
+                    BackendError::Unsupported(msg) => {
+                        BackendError::Unsupported(format!("{}: {}", function.name, msg))
+                    }
+                    BackendError::CompilationFailed(msg) => {
+                        BackendError::CompilationFailed(format!("{}: {}", function.name, msg))
+                    }
+                    other => other,
+                })?;
+            write_u32(&mut content, body.len() as u32);
+            relocations.extend(body_relocations.into_iter().map(|relocation| Relocation {
+                offset: relocation.offset + content.len() as u32,
+                ..relocation
+            }));
+            content.extend_from_slice(&body);
+        }
+
+        if layout.init_function.is_some() {
+            let body = encode_init_body(&layout.init_calls);
+            write_u32(&mut content, body.len() as u32);
+            content.extend_from_slice(&body);
+        }
+
+        for function in synthesized {
+            write_u32(&mut content, function.body.len() as u32);
+            content.extend_from_slice(&function.body);
+        }
+
+        write_section(output, SectionId::Code, &content);
+        Ok(relocations)
+    }
+
+    /// Writes the function names subsection of the `name` custom section
+    ///
+    /// Imports are named by their field, synthesized canonical ABI
+    /// functions by the export they implement. With `demangle`, Rust
+    /// symbols are named by their path.
+    fn generate_name_section(
+        &self,
+        output: &mut Vec<u8>,
+        module: &WasmModule,
+        layout: &ModuleLayout,
+        synthesized: &[SynthesizedFunction],
+    ) {
+        let names: Vec<&str> = module.imports.iter().map(|import| import.name.as_str())
+            .chain(module.functions.iter().map(|function| function.name.as_str()))
+            .chain(layout.init_function.map(|_| CALL_CTORS_EXPORT))
+            .chain(synthesized.iter().map(|function| function.export.as_str()))
+            .collect();
+        let mut function_names = Vec::new();
+        write_u32(&mut function_names, names.len() as u32);
+        for (index, name) in names.iter().enumerate() {
+            write_u32(&mut function_names, index as u32);
+            let name = if self.demangle { demangle_or_raw(name) } else { name.to_string() };
+            write_name(&mut function_names, &name);
+        }
+
+        let mut content = Vec::new();
+        write_name(&mut content, NAME_SECTION);
+        content.push(NAME_SUBSECTION_FUNCTIONS);
+        write_u32(&mut content, function_names.len() as u32);
+        content.extend_from_slice(&function_names);
+        write_section(output, SectionId::Custom, &content);
+    }
+
+    /// Lists each enabled proposal as a `+` feature, in LLVM's layout
+    fn generate_target_features_section(&self, output: &mut Vec<u8>) {
+        let enabled: Vec<Proposal> = TargetFeatures::new(self.features).enabled().collect();
+        let mut content = Vec::new();
+        write_name(&mut content, TARGET_FEATURES_SECTION);
+        write_u32(&mut content, enabled.len() as u32);
+        for proposal in enabled {
+            content.push(b'+');
+            write_name(&mut content, proposal.target_feature());
+        }
+        write_section(output, SectionId::Custom, &content);
+    }
+
+    /// Embeds the manifest as JSON
+    fn generate_capabilities_section(&self, output: &mut Vec<u8>, manifest: &CapabilityManifest) {
+        let mut content = Vec::new();
+        write_name(&mut content, CAPABILITIES_SECTION);
+        content.extend_from_slice(manifest.to_json().to_string().as_bytes());
+        write_section(output, SectionId::Custom, &content);
+    }
+}
+
+/// Index spaces and type assignments for a module being emitted
+///
+/// Imported functions occupy the start of the wasm function index space,
+/// so WasmIR indices of defined functions are shifted by the import count.
+struct ModuleLayout {
+    /// Deduplicated function types
+    types: Vec<FuncType>,
+    /// Type index of every import
+    import_types: Vec<u32>,
+    /// Import indices keyed by module and field name
+    import_names: HashMap<(String, String), u32>,
+    /// Type index of every defined function, including synthesized ones
+    function_types: Vec<u32>,
+    /// Signatures of every callable function by wasm function index
+    signatures: Vec<FuncType>,
+    /// Index of the synthesized initializer function
+    init_function: Option<u32>,
+    /// Functions called by the synthesized initializer, in order
+    init_calls: Vec<u32>,
+    /// Function referenced by the start section
+    start_section: Option<u32>,
+    /// Index of the first synthesized canonical ABI function
+    synthesized: u32,
+    /// Number of the module's functions in the indirect function table, when closures need one
+    function_table: Option<u32>,
+    /// How `Panic` terminators are lowered
+    panic_strategy: PanicStrategy,
+    /// Index of the imported panic hook
+    panic_hook: Option<u32>,
+    /// Type index of the panic tag when panics unwind
+    panic_tag: Option<u32>,
+    /// Indices of compiled panic messages passed to the hook or tag
+    panic_messages: HashMap<String, i32>,
+    /// Value types of the module's globals
+    globals: Vec<ValType>,
+    /// Wasm index of each of the module's globals; an imported one comes first
+    global_indices: Vec<u32>,
+    /// Global imported rather than defined, the stack pointer of an object or shared module
+    imported_global: Option<u32>,
+    /// What the module is written as
+    output: Output,
+    /// Symbols of an object, or `None` for a module
+    symbols: Option<SymbolTable>,
+    /// Symbol of each function by wasm function index, in an object
+    function_symbols: Vec<String>,
+    /// Symbol of each of the module's globals, in an object
+    global_symbols: Vec<String>,
+    /// Priority and symbol of each initializer of an object
+    init_functions: Vec<(u32, String)>,
+}
+
+impl ModuleLayout {
+    fn new(
+        module: &WasmModule,
+        strategy: InitStrategy,
+        panic_strategy: PanicStrategy,
+        synthesized: &[SynthesizedFunction],
+        output: Output,
+    ) -> Result<Self, BackendError> {
+        let mut layout = Self {
+            types: Vec::new(),
+            import_types: Vec::new(),
+            import_names: HashMap::new(),
+            function_types: Vec::new(),
+            signatures: Vec::new(),
+            init_function: None,
+            init_calls: Vec::new(),
+            start_section: None,
+            synthesized: 0,
+            function_table: module.uses_closures().then_some(module.functions.len() as u32),
+            panic_strategy,
+            panic_hook: None,
+            panic_tag: None,
+            panic_messages: HashMap::new(),
+            globals: module.globals.iter()
+                .map(|global| ValType::from_type(&global.ty))
+                .collect::<Result<_, _>>()?,
+            global_indices: (0..module.globals.len() as u32).collect(),
+            imported_global: None,
+            output,
+            symbols: None,
+            function_symbols: Vec::new(),
+            global_symbols: Vec::new(),
+            init_functions: Vec::new(),
+        };
+        let mut type_indices = HashMap::new();
+
+        for (i, import) in module.imports.iter().enumerate() {
+            let func_type = FuncType::from_signature(import.signature())?;
+            let index = layout.intern_type(&mut type_indices, func_type.clone());
+            layout.import_types.push(index);
+            layout.signatures.push(func_type);
+            layout.import_names.insert((import.module.clone(), import.name.clone()), i as u32);
+        }
+
+        for function in &module.functions {
+            let func_type = FuncType::from_signature(&function.signature)?;
+            let index = layout.intern_type(&mut type_indices, func_type.clone());
+            layout.function_types.push(index);
+            layout.signatures.push(func_type);
+        }
+
+        let import_count = module.imports.len() as u32;
+        let init_calls: Vec<u32> = module.constructors_in_order().iter()
+            .chain(module.start_function.iter())
+            .map(|&index| import_count + index)
+            .collect();
+
+        let needs_init_function = match strategy {
+            InitStrategy::StartSection => !module.constructors.is_empty(),
+            InitStrategy::ExportedCallCtors => !init_calls.is_empty(),
+        };
+
+        if output == Output::Object {
+            // The linker runs the initializers of every object
+            layout.add_symbols(module);
+        } else if needs_init_function {
+            let void = FuncType { params: Vec::new(), results: Vec::new() };
+            let index = layout.intern_type(&mut type_indices, void.clone());
+            let init = import_count + layout.function_types.len() as u32;
+            layout.function_types.push(index);
+            layout.signatures.push(void);
+            layout.init_function = Some(init);
+            layout.init_calls = init_calls;
+            if strategy == InitStrategy::StartSection {
+                layout.start_section = Some(init);
+            }
+        } else if strategy == InitStrategy::StartSection {
+            layout.start_section = module.start_function.map(|index| import_count + index);
+        }
+
+        if output == Output::Shared {
+            layout.import_stack_pointer(module);
+        }
+
+        layout.synthesized = import_count + layout.function_types.len() as u32;
+        for function in synthesized {
+            let index = layout.intern_type(&mut type_indices, function.func_type.clone());
+            layout.function_types.push(index);
+            layout.signatures.push(function.func_type.clone());
+        }
+
+        layout.panic_hook = layout.import_names
+            .get(&(JS_IMPORT_MODULE.to_string(), PANIC_IMPORT.to_string()))
+            .copied();
+        layout.panic_messages = module.panic_messages().into_iter()
+            .enumerate()
+            .map(|(i, message)| (message.to_string(), i as i32))
+            .collect();
+        if panic_strategy == PanicStrategy::Unwind && panics(module) {
+            let payload = FuncType { params: vec![ValType::I32], results: Vec::new() };
+            layout.panic_tag = Some(layout.intern_type(&mut type_indices, payload));
+        }
+
+        Ok(layout)
+    }
+
+    /// Builds the symbol table of an object, importing the stack pointer
+    ///
+    /// Imports are undefined, exported functions global under their export
+    /// names, and everything else local.
+    fn add_symbols(&mut self, module: &WasmModule) {
+        let mut symbols = SymbolTable::new();
+        for (index, import) in module.imports.iter().enumerate() {
+            let name = symbols.add(SymbolKind::Function, &import.name, index as u32, SYMBOL_UNDEFINED);
+            self.function_symbols.push(name);
+        }
+        for (index, function) in module.functions.iter().enumerate() {
+            let mut exports = module.exports.iter().filter_map(|export| match export.kind {
+                ExportKind::Function(exported) if exported == index as u32 => Some(export.name.as_str()),
+                _ => None,
+            });
+            let wasm_index = self.defined(index as u32);
+            let name = match exports.next() {
+                Some(export) => symbols.add(SymbolKind::Function, export, wasm_index, SYMBOL_EXPORTED),
+                None => symbols.add(SymbolKind::Function, &function.name, wasm_index, SYMBOL_BINDING_LOCAL),
+            };
+            for alias in exports {
+                symbols.add(SymbolKind::Function, alias, wasm_index, SYMBOL_EXPORTED);
+            }
+            self.function_symbols.push(name);
+        }
+
+        self.import_stack_pointer(module);
+        for (index, global) in module.globals.iter().enumerate() {
+            let imported = self.imported_global == Some(index as u32);
+            let flags = if imported { SYMBOL_UNDEFINED } else { SYMBOL_BINDING_LOCAL };
+            let name = symbols.add(SymbolKind::Global, &global.name, self.global_indices[index], flags);
+            self.global_symbols.push(name);
+        }
+
+        let start = module.start_function.map(|function| (START_PRIORITY, function));
+        self.init_functions = module.constructors.iter()
+            .map(|constructor| (constructor.priority, constructor.function))
+            .chain(start)
+            .map(|(priority, function)| (priority, self.function_symbols[self.defined(function) as usize].clone()))
+            .collect();
+        self.symbols = Some(symbols);
+    }
+
+    /// Imports the module's stack pointer, if it has one, ahead of the globals it defines
+    fn import_stack_pointer(&mut self, module: &WasmModule) {
+        self.imported_global = module.global_index(STACK_POINTER_GLOBAL);
+        let Some(imported) = self.imported_global else { return };
+        for (index, wasm_index) in (0..).zip(&mut self.global_indices) {
+            *wasm_index = match index {
+                _ if index == imported => 0,
+                _ if index < imported => index + 1,
+                _ => index,
+            };
+        }
+    }
+
+    /// Maps a WasmIR defined-function index to the wasm function index
+    fn defined(&self, index: u32) -> u32 {
+        self.import_types.len() as u32 + index
+    }
+
+    /// Resolves a declared JS interop import
+    fn js_import(&self, name: String) -> Result<u32, BackendError> {
+        self.import_names.get(&(JS_IMPORT_MODULE.to_string(), name.clone()))
+            .copied()
+            .ok_or_else(|| BackendError::CompilationFailed(
+                format!("undeclared host import {}::{}", JS_IMPORT_MODULE, name),
+            ))
+    }
+
+    fn intern_type(&mut self, indices: &mut HashMap<FuncType, u32>, func_type: FuncType) -> u32 {
+        if let Some(&index) = indices.get(&func_type) {
+            return index;
+        }
+        let index = self.types.len() as u32;
+        self.types.push(func_type.clone());
+        indices.insert(func_type, index);
+        index
+    }
+}
+
+/// Checks whether any function has a `Panic` terminator
+fn panics(module: &WasmModule) -> bool {
+    module.functions.iter()
+        .flat_map(|function| &function.basic_blocks)
+        .any(|block| matches!(block.terminator, Terminator::Panic { .. }))
+}
+
+/// Checks that the allocator exports used by string glue have the expected types
+fn check_string_abi(module: &WasmModule) -> Result<(), BackendError> {
+    let expected = [
+        (ALLOC_EXPORT, FuncType { params: vec![ValType::I32; 2], results: vec![ValType::I32] }),
+        (FREE_EXPORT, FuncType { params: vec![ValType::I32; 3], results: Vec::new() }),
+    ];
+
+    for (name, func_type) in expected {
+        let index = module.exports.iter().find_map(|export| match export.kind {
+            ExportKind::Function(index) if export.name == name => Some(index),
+            _ => None,
+        });
+        if let Some(index) = index {
+            let actual = FuncType::from_signature(&module.functions[index as usize].signature)?;
+            if actual != func_type {
+                return Err(BackendError::CompilationFailed(format!(
+                    "{} must have type {:?} -> {:?}",
+                    name, func_type.params, func_type.results
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encodes the body of the synthesized `__wasm_call_ctors` function
+fn encode_init_body(calls: &[u32]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_u32(&mut body, 0);
+    for &function in calls {
+        body.push(0x10);
+        write_u32(&mut body, function);
+    }
+    body.push(0x0B);
+    body
+}
+
+/// Encodes a single function body
+///
+/// WasmIR locals include the parameters: the first `params.len()` locals
+/// are the incoming arguments and the rest are declared in the body.
+struct FunctionEncoder<'a> {
+    function: &'a WasmIR,
+    layout: &'a ModuleLayout,
+    has_memory: bool,
+    code: Vec<u8>,
+    /// Types of values currently on the operand stack
+    stack: Vec<ValType>,
+    /// Index of the block label local when dispatch is used
+    label_local: u32,
+    /// Relocations of an object, at their offset in `code`
+    relocations: Vec<Relocation>,
+}
+
+impl<'a> FunctionEncoder<'a> {
+    fn new(function: &'a WasmIR, layout: &'a ModuleLayout, has_memory: bool) -> Self {
+        let label_local = function.locals.len().max(function.signature.params.len()) as u32;
+        Self {
+            function,
+            layout,
+            has_memory,
+            code: Vec::new(),
+            stack: Vec::new(),
+            label_local,
+            relocations: Vec::new(),
+        }
+    }
+
+    /// Encodes the body, returning it and its relocations at their offset in it
+    fn encode(mut self) -> Result<(Vec<u8>, Vec<Relocation>), BackendError> {
+        let blocks = &self.function.basic_blocks;
+        let needs_dispatch = blocks.len() > 1
+            || blocks.iter().any(|block| !matches!(
+                block.terminator,
+                Terminator::Return { .. } | Terminator::Unreachable | Terminator::Panic { .. }
+            ));
+
+        if blocks.is_empty() {
+            if self.function.signature.returns.is_some() {
+                self.code.push(0x00);
+            }
+        } else if needs_dispatch {
+            self.encode_dispatch()?;
+        } else {
+            self.encode_block(&blocks[0], 0)?;
+        }
+
+        let mut body = Vec::new();
+        let declared = self.declared_locals(needs_dispatch)?;
+        let groups = group_locals(&declared);
+        write_u32(&mut body, groups.len() as u32);
+        for (count, ty) in groups {
+            write_u32(&mut body, count);
+            body.push(ty.byte());
+        }
+        let header = body.len() as u32;
+        body.extend_from_slice(&self.code);
+        body.push(0x0B);
+        let relocations = self.relocations.into_iter()
+            .map(|relocation| Relocation { offset: relocation.offset + header, ..relocation })
+            .collect();
+        Ok((body, relocations))
+    }
+
+    /// Lowers the CFG as `loop { block* { br_table } ... }`
+    fn encode_dispatch(&mut self) -> Result<(), BackendError> {
+        let blocks = &self.function.basic_blocks;
+        let count = blocks.len() as u32;
+
+        // loop $dispatch
+        self.code.push(0x03);
+        self.code.push(0x40);
+        for _ in 0..count {
+            self.code.push(0x02);
+            self.code.push(0x40);
+        }
+
+        // br_table on the label local; block k exits to depth k
+        self.code.push(0x20);
+        write_u32(&mut self.code, self.label_local);
+        self.code.push(0x0E);
+        write_u32(&mut self.code, count);
+        for depth in 0..count {
+            write_u32(&mut self.code, depth);
+        }
+        write_u32(&mut self.code, 0);
+
+        for (k, block) in blocks.iter().enumerate() {
+            // Close the block for arm k, then emit its code
+            self.code.push(0x0B);
+            self.encode_block(block, count - 1 - k as u32)?;
+        }
+
+        // end loop
+        self.code.push(0x0B);
+        self.code.push(0x00);
+        Ok(())
+    }
+
+    fn encode_block(&mut self, block: &BasicBlock, loop_depth: u32) -> Result<(), BackendError> {
+        for instruction in &block.instructions {
+            self.encode_instruction(instruction)?;
+        }
+
+        // Values not consumed by the terminator are dropped
+        let consumed = match &block.terminator {
+            Terminator::Return { value: Some(Operand::StackValue(_)) }
+            | Terminator::Branch { condition: Operand::StackValue(_), .. } => 1,
+            _ => 0,
+        };
+        if consumed > 0 && self.stack.len() > consumed {
+            return Err(BackendError::Unsupported(
+                "unused values beneath the terminator operand".to_string(),
+            ));
+        }
+        if consumed == 0 {
+            while self.stack.pop().is_some() {
+                self.code.push(0x1A);
+            }
+        }
+        self.encode_terminator(&block.terminator, loop_depth)
+    }
+
+    fn encode_terminator(&mut self, terminator: &Terminator, loop_depth: u32) -> Result<(), BackendError> {
+        match terminator {
+            Terminator::Return { value } => {
+                if let Some(value) = value {
+                    self.push_operand(value)?;
+                }
+                self.code.push(0x0F);
+                self.stack.clear();
+            }
+            Terminator::Jump { target } => {
+                self.set_label(target.0 as u32);
+                self.code.push(0x0C);
+                write_u32(&mut self.code, loop_depth);
+            }
+            Terminator::Branch { condition, then_block, else_block } => {
+                self.push_condition(condition)?;
+                self.code.push(0x04);
+                self.code.push(0x40);
+                self.set_label(then_block.0 as u32);
+                self.code.push(0x05);
+                self.set_label(else_block.0 as u32);
+                self.code.push(0x0B);
+                self.code.push(0x0C);
+                write_u32(&mut self.code, loop_depth);
+            }
+            Terminator::Switch { value, targets, default_target } => {
+                if matches!(value, Operand::StackValue(_)) {
+                    return Err(BackendError::Unsupported(
+                        "switch on a stack value".to_string(),
+                    ));
+                }
+                for (case, target) in targets {
+                    let ty = self.push_operand(value)?;
+                    self.push_operand(case)?;
+                    self.pop_values(2)?;
+                    self.code.push(match ty {
+                        ValType::I32 => 0x46,
+                        ValType::I64 => 0x51,
+                        ValType::F32 => 0x5B,
+                        ValType::F64 => 0x61,
+                        _ => return Err(BackendError::Unsupported(
+                            "switch on a reference value".to_string(),
+                        )),
+                    });
+                    self.code.push(0x04);
+                    self.code.push(0x40);
+                    self.set_label(target.0 as u32);
+                    self.code.push(0x0C);
+                    write_u32(&mut self.code, loop_depth + 1);
+                    self.code.push(0x0B);
+                }
+                self.set_label(default_target.0 as u32);
+                self.code.push(0x0C);
+                write_u32(&mut self.code, loop_depth);
+            }
+            Terminator::Unreachable => {
+                self.code.push(0x00);
+                self.stack.clear();
+            }
+            Terminator::Panic { message } => {
+                let id = match message {
+                    Some(Operand::Constant(Constant::String(text))) => self.layout.panic_messages[text],
+                    _ => -1,
+                };
+                if let Some(hook) = self.layout.panic_hook {
+                    self.push_integer(false, id as i64);
+                    self.code.push(0x10);
+                    self.write_function_index(hook);
+                }
+                match self.layout.panic_strategy {
+                    PanicStrategy::Abort => self.code.push(0x00),
+                    PanicStrategy::Unwind => {
+                        self.push_integer(false, id as i64);
+                        self.code.push(0x08);
+                        write_u32(&mut self.code, 0);
+                    }
+                }
+                self.stack.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_instruction(&mut self, instruction: &Instruction) -> Result<(), BackendError> {
+        match instruction {
+            Instruction::LocalGet { index } => {
+                let ty = self.local_type(*index)?;
+                self.code.push(0x20);
+                write_u32(&mut self.code, *index);
+                self.stack.push(ty);
+            }
+            Instruction::LocalSet { index, value } => {
+                self.local_type(*index)?;
+                self.push_operand(value)?;
+                self.pop_values(1)?;
+                self.code.push(0x21);
+                write_u32(&mut self.code, *index);
+            }
+            Instruction::GlobalSet { index, value } => {
+                self.global_type(*index)?;
+                self.push_operand(value)?;
+                self.pop_values(1)?;
+                self.code.push(0x24);
+                self.write_global_index(*index);
+            }
+            Instruction::BinaryOp { op, left, right } => {
+                if matches!(right, Operand::StackValue(_)) && !matches!(left, Operand::StackValue(_)) {
+                    return Err(BackendError::Unsupported(
+                        "stack value as right operand of a binary operation".to_string(),
+                    ));
+                }
+                let ty = self.push_operand(left)?;
+                self.push_operand(right)?;
+                self.pop_values(2)?;
+                let (opcode, result) = binary_opcode(*op, ty)?;
+                self.code.push(opcode);
+                self.stack.push(result);
+            }
+            Instruction::UnaryOp { op, value } => {
+                let ty = self.push_operand(value)?;
+                self.encode_unary(*op, ty)?;
+            }
+            Instruction::Convert { op, value } => {
+                let ty = self.push_operand(value)?;
+                self.pop_values(1)?;
+                let result = self.encode_convert(op, ty)?;
+                self.stack.push(result);
+            }
+            Instruction::Call { func_ref, args } => {
+                self.encode_call(self.layout.defined(*func_ref), None, args)?;
+            }
+            Instruction::CallImport { import, args } => {
+                self.encode_call(*import, None, args)?;
+            }
+            Instruction::JSMethodCall { object, method, args, .. } => {
+                let import = self.layout.js_import(Import::js_method_name(method))?;
+                self.encode_call(import, Some(object), args)?;
+            }
+            Instruction::ExternRefLoad { externref, field, .. } => {
+                let import = self.layout.js_import(Import::js_getter_name(field))?;
+                self.encode_call(import, Some(externref), &[])?;
+            }
+            Instruction::ExternRefStore { externref, field, value, .. } => {
+                let import = self.layout.js_import(Import::js_setter_name(field))?;
+                self.encode_call(import, Some(externref), std::slice::from_ref(value))?;
+            }
+            Instruction::Return { value } => {
+                if let Some(value) = value {
+                    self.push_operand(value)?;
+                }
+                self.code.push(0x0F);
+                self.stack.clear();
+            }
+            Instruction::MemoryLoad { address, ty, align, offset } => {
+                self.require_memory()?;
+                self.push_operand(address)?;
+                self.pop_values(1)?;
+                let ty = ValType::from_type(ty)?;
+                let (opcode, natural) = match ty {
+                    ValType::I32 => (0x28, 2),
+                    ValType::I64 => (0x29, 3),
+                    ValType::F32 => (0x2A, 2),
+                    ValType::F64 => (0x2B, 3),
+                    _ => return Err(BackendError::Unsupported(
+                        "load of a reference type".to_string(),
+                    )),
+                };
+                self.code.push(opcode);
+                write_memarg(&mut self.code, *align, natural, *offset);
+                self.stack.push(ty);
+            }
+            Instruction::MemoryStore { address, value, ty, align, offset } => {
+                self.require_memory()?;
+                if !stack_values_lead(&[address, value]) {
+                    return Err(BackendError::Unsupported(
+                        "stack value as stored value after a pushed address".to_string(),
+                    ));
+                }
+                self.push_operand(address)?;
+                self.push_operand(value)?;
+                self.pop_values(2)?;
+                let (opcode, natural) = match ValType::from_type(ty)? {
+                    ValType::I32 => (0x36, 2),
+                    ValType::I64 => (0x37, 3),
+                    ValType::F32 => (0x38, 2),
+                    ValType::F64 => (0x39, 3),
+                    _ => return Err(BackendError::Unsupported(
+                        "store of a reference type".to_string(),
+                    )),
+                };
+                self.code.push(opcode);
+                write_memarg(&mut self.code, *align, natural, *offset);
+            }
+            Instruction::MemorySize => {
+                self.require_memory()?;
+                self.code.extend_from_slice(&[0x3F, 0x00]);
+                self.stack.push(ValType::I32);
+            }
+            Instruction::MemoryGrow { pages } => {
+                self.require_memory()?;
+                self.push_operand(pages)?;
+                self.pop_values(1)?;
+                self.code.extend_from_slice(&[0x40, 0x00]);
+                self.stack.push(ValType::I32);
+            }
+            Instruction::AtomicWait { address, expected, timeout, ty } => {
+                self.require_memory()?;
+                if !stack_values_lead(&[address, expected, timeout]) {
+                    return Err(BackendError::Unsupported(
+                        "stack value as wait operand after a pushed operand".to_string(),
+                    ));
+                }
+                let (opcode, natural) = match ValType::from_type(ty)? {
+                    ValType::I32 => (0x01, 2),
+                    ValType::I64 => (0x02, 3),
+                    _ => return Err(BackendError::Unsupported(
+                        "wait on a value other than i32 or i64".to_string(),
+                    )),
+                };
+                self.push_operand(address)?;
+                self.push_operand(expected)?;
+                self.push_operand(timeout)?;
+                self.pop_values(3)?;
+                self.code.extend_from_slice(&[0xFE, opcode]);
+                write_memarg(&mut self.code, None, natural, 0);
+                self.stack.push(ValType::I32);
+            }
+            Instruction::AtomicNotify { address, count } => {
+                self.require_memory()?;
+                if !stack_values_lead(&[address, count]) {
+                    return Err(BackendError::Unsupported(
+                        "stack value as notify count after a pushed address".to_string(),
+                    ));
+                }
+                self.push_operand(address)?;
+                self.push_operand(count)?;
+                self.pop_values(2)?;
+                self.code.extend_from_slice(&[0xFE, 0x00]);
+                write_memarg(&mut self.code, None, 2, 0);
+                self.stack.push(ValType::I32);
+            }
+            Instruction::Nop => self.code.push(0x01),
+            // Tags of memory regions, checked before emission
+            Instruction::CapabilityCheck { .. } => {}
+            other => {
+                return Err(BackendError::Unsupported(
+                    format!("instruction {} is not supported by the binary emitter", other.name()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits a call with an optional receiver pushed before the arguments
+    fn encode_call(&mut self, index: u32, receiver: Option<&Operand>, args: &[Operand]) -> Result<(), BackendError> {
+        let results = self.layout.signatures.get(index as usize)
+            .ok_or_else(|| BackendError::CompilationFailed(format!("call to unknown function {}", index)))?
+            .results
+            .clone();
+        let operands: Vec<&Operand> = receiver.into_iter().chain(args).collect();
+        if !stack_values_lead(&operands) {
+            return Err(BackendError::Unsupported(
+                "stack value as call argument after a pushed argument".to_string(),
+            ));
+        }
+        for operand in &operands {
+            self.push_operand(operand)?;
+        }
+        self.pop_values(operands.len())?;
+        self.code.push(0x10);
+        self.write_function_index(index);
+        self.stack.extend(results);
+        Ok(())
+    }
+
+    /// Writes a wasm function index, padded and relocated in an object
+    fn write_function_index(&mut self, index: u32) {
+        match self.layout.function_symbols.get(index as usize) {
+            Some(symbol) => self.write_relocated(RelocationKind::FunctionCall, symbol.clone(), index),
+            None => write_u32(&mut self.code, index),
+        }
+    }
+
+    /// Writes the wasm index of one of the module's globals, padded and relocated in an object
+    fn write_global_index(&mut self, index: u32) {
+        let wasm_index = self.layout.global_indices.get(index as usize).copied().unwrap_or(index);
+        match self.layout.global_symbols.get(index as usize) {
+            Some(symbol) => self.write_relocated(RelocationKind::GlobalAccess, symbol.clone(), wasm_index),
+            None => write_u32(&mut self.code, wasm_index),
+        }
+    }
+
+    fn write_relocated(&mut self, kind: RelocationKind, symbol: String, index: u32) {
+        self.relocations.push(Relocation { kind, offset: self.code.len() as u32, symbol, addend: 0 });
+        object::write_padded_u32(&mut self.code, index);
+    }
+
+    fn encode_unary(&mut self, op: UnaryOp, ty: ValType) -> Result<(), BackendError> {
+        let is_i64 = ty == ValType::I64;
+        match (op, ty) {
+            (UnaryOp::Neg, ValType::I32) | (UnaryOp::Neg, ValType::I64) => {
+                // x * -1
+                self.push_integer(is_i64, -1);
+                self.code.push(if is_i64 { 0x7E } else { 0x6C });
+            }
+            (UnaryOp::Not, ValType::I32) | (UnaryOp::Not, ValType::I64) => {
+                // x ^ -1
+                self.push_integer(is_i64, -1);
+                self.code.push(if is_i64 { 0x85 } else { 0x73 });
+            }
+            (UnaryOp::Neg, ValType::F32) => self.code.push(0x8C),
+            (UnaryOp::Neg, ValType::F64) => self.code.push(0x9A),
+            (UnaryOp::Clz, ValType::I32) => self.code.push(0x67),
+            (UnaryOp::Ctz, ValType::I32) => self.code.push(0x68),
+            (UnaryOp::Popcnt, ValType::I32) => self.code.push(0x69),
+            (UnaryOp::Clz, ValType::I64) => self.code.push(0x79),
+            (UnaryOp::Ctz, ValType::I64) => self.code.push(0x7A),
+            (UnaryOp::Popcnt, ValType::I64) => self.code.push(0x7B),
+            _ => {
+                return Err(BackendError::Unsupported(
+                    format!("unary {:?} on {:?}", op, ty),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits a conversion of a `ty` value and returns the result type
+    fn encode_convert(&mut self, op: &ConvertOp, ty: ValType) -> Result<ValType, BackendError> {
+        use ValType::*;
+
+        let unsupported = || BackendError::Unsupported(format!("{:?} of {:?}", op, ty));
+        // Conversion opcodes are grouped by result type, then source type,
+        // with the unsigned variant after the signed one
+        let variant = |wide_source: bool, signed: bool| 2 * wide_source as u8 + !signed as u8;
+        let (opcode, result) = match (op, ty) {
+            (ConvertOp::Wrap, I64) => (0xA7, I32),
+            (ConvertOp::Extend { signed }, I32) => (0xAC + !signed as u8, I64),
+            (ConvertOp::SignExtend { bits: 8 }, I32) => (0xC0, I32),
+            (ConvertOp::SignExtend { bits: 16 }, I32) => (0xC1, I32),
+            (ConvertOp::SignExtend { bits: 8 }, I64) => (0xC2, I64),
+            (ConvertOp::SignExtend { bits: 16 }, I64) => (0xC3, I64),
+            (ConvertOp::SignExtend { bits: 32 }, I64) => (0xC4, I64),
+            (ConvertOp::Trunc { to, signed }, F32 | F64) => match ValType::from_type(to)? {
+                I32 => (0xA8 + variant(ty == F64, *signed), I32),
+                I64 => (0xAE + variant(ty == F64, *signed), I64),
+                _ => return Err(unsupported()),
+            },
+            (ConvertOp::TruncSat { to, signed }, F32 | F64) => {
+                let result = ValType::from_type(to)?;
+                let base = match result {
+                    I32 => 0,
+                    I64 => 4,
+                    _ => return Err(unsupported()),
+                };
+                self.code.push(0xFC);
+                write_u32(&mut self.code, base + variant(ty == F64, *signed) as u32);
+                return Ok(result);
+            }
+            (ConvertOp::FromInt { to, signed }, I32 | I64) => match ValType::from_type(to)? {
+                F32 => (0xB2 + variant(ty == I64, *signed), F32),
+                F64 => (0xB7 + variant(ty == I64, *signed), F64),
+                _ => return Err(unsupported()),
+            },
+            (ConvertOp::Demote, F64) => (0xB6, F32),
+            (ConvertOp::Promote, F32) => (0xBB, F64),
+            (ConvertOp::Reinterpret, F32) => (0xBC, I32),
+            (ConvertOp::Reinterpret, F64) => (0xBD, I64),
+            (ConvertOp::Reinterpret, I32) => (0xBE, F32),
+            (ConvertOp::Reinterpret, I64) => (0xBF, F64),
+            _ => return Err(unsupported()),
+        };
+        self.code.push(opcode);
+        Ok(result)
+    }
+
+    /// Pushes an operand and returns its value type
+    fn push_operand(&mut self, operand: &Operand) -> Result<ValType, BackendError> {
+        let ty = match operand {
+            Operand::Local(index) => {
+                let ty = self.local_type(*index)?;
+                self.code.push(0x20);
+                write_u32(&mut self.code, *index);
+                ty
+            }
+            Operand::Constant(constant) => self.push_constant(constant)?,
+            Operand::MemoryAddress(inner) => self.push_operand(inner)?,
+            Operand::StackValue(_) => {
+                // The value was produced by the previous instruction
+                return self.stack.last().copied().ok_or_else(|| {
+                    BackendError::CompilationFailed("stack value used with an empty stack".to_string())
+                });
+            }
+            Operand::Global(index) => {
+                let ty = self.global_type(*index)?;
+                self.code.push(0x23);
+                self.write_global_index(*index);
+                ty
+            }
+            // Function pointers are table slots, as on wasm32 targets
+            Operand::FunctionRef(index) => {
+                let slot = self.layout.function_table
+                    .filter(|&functions| *index < functions)
+                    .map(|_| index + 1)
+                    .ok_or_else(|| BackendError::Unsupported(format!(
+                        "address of function {} needs the indirect function table, which only closure modules have",
+                        index
+                    )))?;
+                self.push_integer(false, slot as i64);
+                ValType::I32
+            }
+            Operand::ExternRef(_) | Operand::FuncRef(_) => {
+                return Err(BackendError::Unsupported(
+                    format!("operand {:?} is not supported by the binary emitter", operand),
+                ));
+            }
+        };
+        self.stack.push(ty);
+        Ok(ty)
+    }
+
+    fn push_constant(&mut self, constant: &Constant) -> Result<ValType, BackendError> {
+        encode_constant(&mut self.code, constant)
+    }
+
+    fn push_condition(&mut self, condition: &Operand) -> Result<(), BackendError> {
+        match self.push_operand(condition)? {
+            ValType::I32 => {}
+            ValType::I64 => {
+                // i64.const 0; i64.ne
+                self.push_integer(true, 0);
+                self.code.push(0x52);
+            }
+            other => {
+                return Err(BackendError::CompilationFailed(
+                    format!("branch condition of type {:?}", other),
+                ));
+            }
+        }
+        self.pop_values(1)
+    }
+
+    fn push_integer(&mut self, is_i64: bool, value: i64) {
+        self.code.push(if is_i64 { 0x42 } else { 0x41 });
+        write_i64(&mut self.code, value);
+    }
+
+    fn set_label(&mut self, block: u32) {
+        self.code.push(0x41);
+        write_i64(&mut self.code, block as i64);
+        self.code.push(0x21);
+        write_u32(&mut self.code, self.label_local);
+    }
+
+    fn pop_values(&mut self, count: usize) -> Result<(), BackendError> {
+        if self.stack.len() < count {
+            return Err(BackendError::CompilationFailed("operand stack underflow".to_string()));
+        }
+        let len = self.stack.len() - count;
+        self.stack.truncate(len);
+        Ok(())
+    }
+
+    fn require_memory(&self) -> Result<(), BackendError> {
+        if self.has_memory {
+            Ok(())
+        } else {
+            Err(BackendError::CompilationFailed(
+                "memory access in a module without linear memory".to_string(),
+            ))
+        }
+    }
+
+    fn local_type(&self, index: u32) -> Result<ValType, BackendError> {
+        let params = &self.function.signature.params;
+        let ty = params.get(index as usize)
+            .or_else(|| self.function.locals.get(index as usize))
+            .ok_or_else(|| BackendError::CompilationFailed(format!("invalid local index {}", index)))?;
+        ValType::from_type(ty)
+    }
+
+    fn global_type(&self, index: u32) -> Result<ValType, BackendError> {
+        self.layout.globals.get(index as usize)
+            .copied()
+            .ok_or_else(|| BackendError::CompilationFailed(format!("invalid global index {}", index)))
+    }
+
+    /// Locals declared in the body, excluding parameters
+    fn declared_locals(&self, with_label: bool) -> Result<Vec<ValType>, BackendError> {
+        let params = self.function.signature.params.len();
+        let mut locals = self.function.locals.iter()
+            .skip(params)
+            .map(ValType::from_type)
+            .collect::<Result<Vec<_>, _>>()?;
+        if with_label {
+            locals.push(ValType::I32);
+        }
+        Ok(locals)
+    }
+}
+
+/// Selects the opcode for a binary operation on the given operand type
+fn binary_opcode(op: BinaryOp, ty: ValType) -> Result<(u8, ValType), BackendError> {
+    use BinaryOp::*;
+
+    let opcode = match ty {
+        ValType::I32 => match op {
+            Add => 0x6A, Sub => 0x6B, Mul => 0x6C, Div => 0x6D, Mod => 0x6F,
+            And => 0x71, Or => 0x72, Xor => 0x73,
+            Shl => 0x74, Shr => 0x76, Sar => 0x75,
+            Eq => 0x46, Ne => 0x47, Lt => 0x48, Gt => 0x4A, Le => 0x4C, Ge => 0x4E,
+        },
+        ValType::I64 => match op {
+            Add => 0x7C, Sub => 0x7D, Mul => 0x7E, Div => 0x7F, Mod => 0x81,
+            And => 0x83, Or => 0x84, Xor => 0x85,
+            Shl => 0x86, Shr => 0x88, Sar => 0x87,
+            Eq => 0x51, Ne => 0x52, Lt => 0x53, Gt => 0x55, Le => 0x57, Ge => 0x59,
+        },
+        ValType::F32 => match op {
+            Add => 0x92, Sub => 0x93, Mul => 0x94, Div => 0x95,
+            Eq => 0x5B, Ne => 0x5C, Lt => 0x5D, Gt => 0x5E, Le => 0x5F, Ge => 0x60,
+            _ => return Err(BackendError::Unsupported(format!("{:?} on f32", op))),
+        },
+        ValType::F64 => match op {
+            Add => 0xA0, Sub => 0xA1, Mul => 0xA2, Div => 0xA3,
+            Eq => 0x61, Ne => 0x62, Lt => 0x63, Gt => 0x64, Le => 0x65, Ge => 0x66,
+            _ => return Err(BackendError::Unsupported(format!("{:?} on f64", op))),
+        },
+        ValType::FuncRef | ValType::ExternRef => {
+            return Err(BackendError::Unsupported(format!("{:?} on a reference type", op)));
+        }
+    };
+
+    let result = match op {
+        Eq | Ne | Lt | Le | Gt | Ge => ValType::I32,
+        _ => ty,
+    };
+    Ok((opcode, result))
+}
+
+/// Checks that stack values come before every pushed operand
+///
+/// A stack value after a pushed operand would read that operand instead of
+/// the value the previous instruction left.
+fn stack_values_lead(operands: &[&Operand]) -> bool {
+    fn is_stack_value(operand: &Operand) -> bool {
+        match operand {
+            Operand::StackValue(_) => true,
+            Operand::MemoryAddress(inner) => is_stack_value(inner),
+            _ => false,
+        }
+    }
+    operands.iter().skip_while(|operand| is_stack_value(operand)).all(|operand| !is_stack_value(operand))
+}
+
+/// Encodes the `const` instruction producing a constant
+fn encode_constant(code: &mut Vec<u8>, constant: &Constant) -> Result<ValType, BackendError> {
+    match constant {
+        Constant::I32(value) => {
+            code.push(0x41);
+            write_i64(code, *value as i64);
+            Ok(ValType::I32)
+        }
+        Constant::I64(value) => {
+            code.push(0x42);
+            write_i64(code, *value);
+            Ok(ValType::I64)
+        }
+        Constant::F32(value) => {
+            code.push(0x43);
+            code.extend_from_slice(&value.to_le_bytes());
+            Ok(ValType::F32)
+        }
+        Constant::F64(value) => {
+            code.push(0x44);
+            code.extend_from_slice(&value.to_le_bytes());
+            Ok(ValType::F64)
+        }
+        Constant::Boolean(value) => {
+            code.push(0x41);
+            write_i64(code, *value as i64);
+            Ok(ValType::I32)
+        }
+        Constant::Null => {
+            code.push(0xD0);
+            code.push(ValType::ExternRef.byte());
+            Ok(ValType::ExternRef)
+        }
+        Constant::String(_) => Err(BackendError::Unsupported(
+            "string constants require a data segment".to_string(),
+        )),
+        Constant::I128(_) => Err(BackendError::Unsupported(
+            "128-bit constants must be split with wasmir::legalize::legalize_i128".to_string(),
+        )),
+    }
+}
+
+/// Collapses consecutive locals of the same type into `(count, type)` runs
+pub(crate) fn group_locals(locals: &[ValType]) -> Vec<(u32, ValType)> {
+    let mut groups: Vec<(u32, ValType)> = Vec::new();
+    for &ty in locals {
+        match groups.last_mut() {
+            Some((count, last)) if *last == ty => *count += 1,
+            _ => groups.push((1, ty)),
+        }
+    }
+    groups
+}
+
+/// Writes a memory immediate, defaulting to natural alignment
+fn write_memarg(buf: &mut Vec<u8>, align: Option<u32>, natural: u32, offset: u32) {
+    let align_log2 = align.map(|bytes| bytes.max(1).trailing_zeros()).unwrap_or(natural);
+    write_u32(buf, align_log2);
+    write_u32(buf, offset);
+}
+
+/// Writes a section with its id and size prefix
+fn write_section(output: &mut Vec<u8>, id: SectionId, content: &[u8]) {
+    output.push(id as u8);
+    write_u32(output, content.len() as u32);
+    output.extend_from_slice(content);
+}
+
+/// Writes memory limits, with a maximum always present for shared memory
+fn write_limits(buf: &mut Vec<u8>, memory: &MemoryType, shared: bool) {
+    let max = match memory.max_pages {
+        Some(max) => Some(max),
+        None if shared => Some(MAX_PAGES),
+        None => None,
+    };
+    buf.push(match max {
+        Some(_) if shared => 0x03,
+        Some(_) => 0x01,
+        None => 0x00,
+    });
+    write_u32(buf, memory.min_pages);
+    if let Some(max) = max {
+        write_u32(buf, max);
+    }
+}
+
+/// Writes a length-prefixed UTF-8 name
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    write_u32(buf, name.len() as u32);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+/// Writes an unsigned LEB128 integer
+pub fn write_u32(buf: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Writes a signed LEB128 integer
+pub fn write_i64(buf: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::threading::runtime::{THREAD_MAIN_EXPORT, THREAD_START_EXPORT};
+    use wasm::wasmir::{BlockId, PromiseLowering};
+
+    fn void_signature() -> Signature {
+        Signature { params: vec![], returns: None }
+    }
+
+    fn void_function(name: &str) -> WasmIR {
+        let mut function = WasmIR::new(name.to_string(), void_signature());
+        function.add_basic_block(vec![Instruction::Nop], Terminator::Return { value: None });
+        function
+    }
+
+    /// Returns the payload of the first section with the given id
+    fn find_section(binary: &[u8], id: SectionId) -> Option<Vec<u8>> {
+        let mut pos = 8;
+        while pos < binary.len() {
+            let section = binary[pos];
+            pos += 1;
+            let (size, read) = read_u32(&binary[pos..]);
+            pos += read;
+            if section == id as u8 {
+                return Some(binary[pos..pos + size as usize].to_vec());
+            }
+            pos += size as usize;
+        }
+        None
+    }
+
+    fn read_u32(bytes: &[u8]) -> (u32, usize) {
+        let mut result = 0u32;
+        let mut shift = 0;
+        for (i, byte) in bytes.iter().enumerate() {
+            result |= ((byte & 0x7F) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return (result, i + 1);
+            }
+            shift += 7;
+        }
+        (result, bytes.len())
+    }
+
+    #[test]
+    fn test_leb128_encoding() {
+        let mut buf = Vec::new();
+        write_u32(&mut buf, 624485);
+        assert_eq!(buf, vec![0xE5, 0x8E, 0x26]);
+
+        buf.clear();
+        write_i64(&mut buf, -123456);
+        assert_eq!(buf, vec![0xC0, 0xBB, 0x78]);
+    }
+
+    #[test]
+    fn test_compile_add_function() {
+        let mut function = WasmIR::new("add".to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        });
+        function.add_local(Type::I32);
+        function.add_local(Type::I32);
+        function.add_basic_block(
+            vec![Instruction::BinaryOp {
+                op: BinaryOp::Add,
+                left: Operand::Local(0),
+                right: Operand::Local(1),
+            }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+
+        let binary = WasmCodegen::new().compile_function(&function).unwrap();
+        assert_eq!(&binary[0..8], &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        // one body: no locals, local.get 0, local.get 1, i32.add, return, end
+        assert_eq!(code, vec![0x01, 0x08, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6A, 0x0F, 0x0B]);
+        assert!(find_section(&binary, SectionId::Start).is_none());
+    }
+
+    #[test]
+    fn test_debug_info_names_functions() {
+        let mut module = WasmModule::new();
+        module.add_function(void_function("init"));
+        module.add_function(void_function("run"));
+        assert!(find_section(&WasmCodegen::new().compile(&module).unwrap(), SectionId::Custom).is_none());
+
+        let binary = WasmCodegen::new().debug_info(true).compile(&module).unwrap();
+        let names = find_section(&binary, SectionId::Custom).unwrap();
+        assert_eq!(names, [
+            &[4][..], b"name",
+            &[1, 12, 2], &[0, 4], b"init", &[1, 3], b"run",
+        ].concat());
+    }
+
+    #[test]
+    fn test_records_enabled_target_features() {
+        let mut module = WasmModule::new();
+        module.add_function(void_function("run"));
+        let codegen = WasmCodegen::new().features(FeatureSet::mvp().with(Proposal::Simd).with(Proposal::Threads));
+        assert!(find_section(&codegen.clone().compile(&module).unwrap(), SectionId::Custom).is_none());
+
+        let binary = codegen.record_features(true).compile(&module).unwrap();
+        let features = find_section(&binary, SectionId::Custom).unwrap();
+        assert_eq!(features, [&[15][..], b"target_features", &[2], b"+\x07atomics", b"+\x07simd128"].concat());
+    }
+
+    #[test]
+    fn test_records_required_capabilities() {
+        let mut module = WasmModule::new();
+        let mut function = void_function("run");
+        function.add_capability(Capability::MemoryRegion("heap".to_string()));
+        module.add_function(function);
+        module.declare_panic_hook();
+        let binary = WasmCodegen::new().compile(&module).unwrap();
+        assert_eq!(CapabilityManifest::from_binary(&binary).unwrap(), None);
+
+        let binary = WasmCodegen::new().record_capabilities(true).compile(&module).unwrap();
+        let manifest = CapabilityManifest::from_binary(&binary).unwrap().unwrap();
+        assert_eq!(manifest, CapabilityManifest::of(&module));
+        assert!(manifest.contains(&Capability::JsInterop));
+
+        let policy = CapabilityPolicy::allow_only([Capability::JsInterop]);
+        assert_eq!(
+            WasmCodegen::new().capability_policy(policy).compile(&module).unwrap_err().to_string(),
+            "Unsupported: capability policy forbids memory-region:heap, required by `run`"
+        );
+    }
+
+    #[test]
+    fn test_compile_component_lifts_exports() {
+        let mut function = WasmIR::new("add".to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        });
+        function.add_local(Type::I32);
+        function.add_local(Type::I32);
+        function.add_basic_block(
+            vec![Instruction::BinaryOp {
+                op: BinaryOp::Add,
+                left: Operand::Local(0),
+                right: Operand::Local(1),
+            }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("add".to_string(), index);
+
+        let package = WitPackage::parse(
+            "package example:math;\ninterface calc { add: func(a: s32, b: s32) -> s32; }\nworld math { export calc; }",
+        )
+        .unwrap();
+        let binary = WasmCodegen::new().compile_component(&module, &package, "math").unwrap();
+        let info = wasm::component::inspect(&binary).unwrap();
+        assert!(info.is_valid(), "{:?}", info.issues);
+        assert_eq!(info.modules.len(), 1);
+        assert_eq!(info.exports[0].name, "example:math/calc");
+
+        let package = WitPackage::parse(
+            "package example:math;\ninterface calc { add: func(a: s64, b: s64) -> s64; }\nworld math { export calc; }",
+        )
+        .unwrap();
+        let err = WasmCodegen::new().compile_component(&module, &package, "math").unwrap_err();
+        assert!(matches!(err, BackendError::CompilationFailed(msg) if msg.contains("Core signature of example:math/calc#add")));
+    }
+
+    #[test]
+    fn test_compile_component_lowers_source_level_exports() {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let mut alloc = WasmIR::new("alloc".to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        });
+        alloc.add_local(Type::I32);
+        alloc.add_local(Type::I32);
+        alloc.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(Constant::I32(1024))) });
+        let mut free = WasmIR::new("free".to_string(), Signature { params: vec![Type::I32; 3], returns: None });
+        (0..3).for_each(|_| { free.add_local(Type::I32); });
+        free.add_basic_block(vec![], Terminator::Return { value: None });
+        // Hands the argument string back as the result
+        let mut echo = WasmIR::new("echo".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        echo.add_local(Type::I32);
+        echo.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Local(0)) });
+        let alloc = module.add_function(alloc);
+        let free = module.add_function(free);
+        let echo = module.add_function(echo);
+        module.export_function(ALLOC_EXPORT, alloc);
+        module.export_function(FREE_EXPORT, free);
+        module.export_function("echo", echo);
+
+        let package = WitPackage::parse(
+            "package example:text;\ninterface api { echo: func(s: string) -> string; }\nworld text { export api; }",
+        )
+        .unwrap();
+        let binary = WasmCodegen::new().compile_component(&module, &package, "text").unwrap();
+        let info = wasm::component::inspect(&binary).unwrap();
+        assert!(info.is_valid(), "{:?}", info.issues);
+        assert_eq!(info.exports[0].name, "example:text/api");
+
+        // Without the lowering the plain module keeps the source-level signature
+        let core = WasmCodegen::new().compile(&module).unwrap();
+        let err = Adapter::new(&package, "text").adapt(&core).unwrap_err();
+        assert!(err.to_string().contains("Core signature of example:text/api#echo"));
+    }
+
+    #[test]
+    fn test_branching_function_uses_dispatch_loop() {
+        let mut function = WasmIR::new("select".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        function.add_local(Type::I32);
+        function.add_basic_block(vec![], Terminator::Branch {
+            condition: Operand::Local(0),
+            then_block: BlockId(1),
+            else_block: BlockId(2),
+        });
+        function.add_basic_block(vec![], Terminator::Return {
+            value: Some(Operand::Constant(Constant::I32(1))),
+        });
+        function.add_basic_block(vec![], Terminator::Return {
+            value: Some(Operand::Constant(Constant::I32(2))),
+        });
+
+        let binary = WasmCodegen::new().compile_function(&function).unwrap();
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        // label local declared as a single i32
+        assert_eq!(&code[2..5], &[0x01, 0x01, 0x7F]);
+        // loop followed by three nested blocks
+        assert_eq!(&code[5..13], &[0x03, 0x40, 0x02, 0x40, 0x02, 0x40, 0x02, 0x40]);
+        assert!(code.contains(&0x0E));
+    }
+
+    #[test]
+    fn test_constructors_run_from_start_section() {
+        let mut module = WasmModule::new();
+        let late = module.add_function(void_function("init_late"));
+        let early = module.add_function(void_function("init_early"));
+        let start = module.add_function(void_function("main"));
+        module.add_constructor(late, 200);
+        module.add_constructor(early, 100);
+        module.set_start_function(start);
+
+        let binary = WasmCodegen::new().compile(&module).unwrap();
+
+        // synthesized initializer is appended after the user functions
+        assert_eq!(find_section(&binary, SectionId::Start).unwrap(), vec![0x03]);
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        let init_body = [0x08, 0x00, 0x10, early as u8, 0x10, late as u8, 0x10, start as u8, 0x0B];
+        assert!(code.ends_with(&init_body));
+    }
+
+    #[test]
+    fn test_pre_initialization_bakes_constructors_into_data() {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let mut init = void_function("init");
+        init.basic_blocks[0].instructions.push(Instruction::MemoryStore {
+            address: Operand::Constant(Constant::I32(8)),
+            value: Operand::Constant(Constant::I32(0x0102)),
+            ty: Type::I32,
+            align: None,
+            offset: 0,
+        });
+        let init = module.add_function(init);
+        module.add_constructor(init, 0);
+
+        let binary = WasmCodegen::new().pre_initialize(true).compile(&module).unwrap();
+        assert!(find_section(&binary, SectionId::Start).is_none());
+        assert_eq!(find_section(&binary, SectionId::Data).unwrap(), vec![1, 0x00, 0x41, 8, 0x0B, 2, 0x02, 0x01]);
+        assert!(find_section(&WasmCodegen::new().compile(&module).unwrap(), SectionId::Data).is_none());
+
+        let mut trap = void_function("trap");
+        trap.basic_blocks[0].terminator = Terminator::Unreachable;
+        let trap = module.add_function(trap);
+        module.add_constructor(trap, 1);
+        let error = WasmCodegen::new().pre_initialize(true).compile(&module).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported: pre-initialization failed: Trap: unreachable");
+    }
+
+    #[test]
+    fn test_exported_call_ctors() {
+        let mut module = WasmModule::new();
+        let ctor = module.add_function(void_function("init"));
+        let run = module.add_function(void_function("run"));
+        module.add_constructor(ctor, 0);
+        module.export_function("run", run);
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+
+        let binary = WasmCodegen::new()
+            .init_strategy(InitStrategy::ExportedCallCtors)
+            .compile(&module)
+            .unwrap();
+
+        assert!(find_section(&binary, SectionId::Start).is_none());
+        let exports = find_section(&binary, SectionId::Export).unwrap();
+        assert_eq!(exports[0], 3);
+        let name = CALL_CTORS_EXPORT.as_bytes();
+        assert!(exports.windows(name.len()).any(|w| w == name));
+    }
+
+    #[test]
+    fn test_import_section_and_js_calls() {
+        let mut function = WasmIR::new("log_value".to_string(), Signature {
+            params: vec![Type::ExternRef("Console".to_string()), Type::I32],
+            returns: None,
+        });
+        function.add_local(Type::ExternRef("Console".to_string()));
+        function.add_local(Type::I32);
+        function.add_basic_block(vec![Instruction::JSMethodCall {
+            object: Operand::Local(0),
+            method: "log".to_string(),
+            args: vec![Operand::Local(1)],
+            return_type: None,
+        }], Terminator::Return { value: None });
+
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("log_value", index);
+
+        // Undeclared host imports are an error rather than a placeholder
+        assert!(WasmCodegen::new().compile(&module).is_err());
+
+        module.declare_js_imports().unwrap();
+        let binary = WasmCodegen::new().compile(&module).unwrap();
+
+        let imports = find_section(&binary, SectionId::Import).unwrap();
+        assert_eq!(imports[0], 1);
+        assert_eq!(&imports[1..10], b"\x08wasmrust");
+
+        // The defined function is shifted past the import in the export
+        let exports = find_section(&binary, SectionId::Export).unwrap();
+        assert_eq!(exports[exports.len() - 2..], [0x00, 0x01]);
+
+        // local.get 0, local.get 1, call 0
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        assert!(code.windows(6).any(|w| w == [0x20, 0x00, 0x20, 0x01, 0x10, 0x00]));
+    }
+
+    #[test]
+    fn test_rejects_unsupported_i128_operations() {
+        let wide = |op| {
+            let mut function = WasmIR::new("wide".to_string(), Signature { params: vec![Type::I128], returns: None });
+            function.add_local(Type::I128);
+            function.add_basic_block(vec![
+                Instruction::BinaryOp { op, left: Operand::Local(0), right: Operand::Local(0) },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+            ], Terminator::Return { value: None });
+            WasmCodegen::new().compile_function(&function)
+        };
+        assert!(wide(BinaryOp::Mul).is_ok());
+        match wide(BinaryOp::Div) {
+            Err(BackendError::Unsupported(message)) => assert_eq!(
+                message,
+                "wide: binary_op at block 0 instruction 0 divides 128-bit integers, which is not supported yet"
+            ),
+            other => panic!("expected an unsupported division, got {:?}", other),
+        }
+        let remainder = wide(BinaryOp::Mod);
+        assert!(matches!(remainder, Err(BackendError::Unsupported(message)) if message.contains("remainder")));
+    }
+
+    #[test]
+    fn test_closure_modules_export_function_table() {
+        use wasm::wasmir::CLOSURE_NEW_IMPORT;
+        use wasmparser::{Parser, Payload};
+
+        let i32_signature = |params: usize| Signature { params: vec![Type::I32; params], returns: Some(Type::I32) };
+        let mut module = WasmModule::new();
+        let new_closure = module.add_import(JS_IMPORT_MODULE, CLOSURE_NEW_IMPORT, i32_signature(3));
+        let mut invoke = WasmIR::new("invoke".to_string(), i32_signature(2));
+        invoke.add_local(Type::I32);
+        invoke.add_local(Type::I32);
+        invoke.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Local(1)) });
+        let invoke = module.add_function(invoke);
+        let mut make = WasmIR::new("make".to_string(), i32_signature(0));
+        make.add_basic_block(vec![Instruction::CallImport {
+            import: new_closure,
+            args: vec![
+                Operand::FunctionRef(invoke),
+                Operand::Constant(Constant::I32(64)),
+                Operand::FunctionRef(invoke),
+            ],
+        }], Terminator::Return { value: Some(Operand::StackValue(0)) });
+        let make = module.add_function(make);
+        module.export_function("make", make);
+
+        let binary = WasmCodegen::new().compile(&module).unwrap();
+        wasmparser::validate(&binary).unwrap();
+        let mut exports = Vec::new();
+        for payload in Parser::new(0).parse_all(&binary) {
+            match payload.unwrap() {
+                Payload::TableSection(reader) => {
+                    let table = reader.into_iter().next().unwrap().unwrap();
+                    assert_eq!((table.ty.initial, table.ty.maximum), (3, Some(3)));
+                }
+                Payload::ExportSection(reader) => {
+                    exports.extend(reader.into_iter().map(|export| export.unwrap().name.to_string()));
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(exports, ["make", FUNCTION_TABLE_EXPORT]);
+        // Both functions after the null slot, shifted past the import
+        let elements = find_section(&binary, SectionId::Element).unwrap();
+        assert_eq!(elements, [0x01, 0x00, 0x41, 0x01, 0x0B, 0x02, 0x01, 0x02]);
+        // The invoke trampoline's address is its table slot
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        assert!(code.windows(6).any(|w| w == [0x41, 0x01, 0x41, 0xC0, 0x00, 0x41]));
+
+        // Without closures there is no table to take an address in
+        module.imports.clear();
+        module.functions[make as usize].basic_blocks[0].instructions.clear();
+        module.functions[make as usize].basic_blocks[0].terminator =
+            Terminator::Return { value: Some(Operand::FunctionRef(invoke)) };
+        assert!(matches!(WasmCodegen::new().compile(&module), Err(BackendError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_promise_types_are_lowered() {
+        let promise = Type::Promise(Box::new(Type::F64));
+        let mut function = WasmIR::new("fetch_later".to_string(), Signature {
+            params: vec![Type::ExternRef("Window".to_string())],
+            returns: Some(promise.clone()),
+        });
+        function.add_local(Type::ExternRef("Window".to_string()));
+        function.add_basic_block(vec![Instruction::JSMethodCall {
+            object: Operand::Local(0),
+            method: "fetch".to_string(),
+            args: vec![],
+            return_type: Some(promise),
+        }], Terminator::Return { value: Some(Operand::StackValue(0)) });
+
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("fetch_later", index);
+        module.declare_js_imports().unwrap();
+
+        // Callback lowering passes a promise handle: (externref) -> i32
+        let binary = WasmCodegen::new().compile(&module).unwrap();
+        let types = find_section(&binary, SectionId::Type).unwrap();
+        assert!(types.windows(5).any(|w| w == [0x60, 0x01, 0x6F, 0x01, 0x7F]));
+
+        // JSPI suspends until the promise settles: (externref) -> f64
+        module.promise_lowering = PromiseLowering::Jspi;
+        let binary = WasmCodegen::new().compile(&module).unwrap();
+        let types = find_section(&binary, SectionId::Type).unwrap();
+        assert!(types.windows(5).any(|w| w == [0x60, 0x01, 0x6F, 0x01, 0x7C]));
+    }
+
+    #[test]
+    fn test_rejects_non_void_initializer() {
+        let mut module = WasmModule::new();
+        let mut function = WasmIR::new("value".to_string(), Signature {
+            params: vec![],
+            returns: Some(Type::I32),
+        });
+        function.add_basic_block(vec![], Terminator::Return {
+            value: Some(Operand::Constant(Constant::I32(0))),
+        });
+        let index = module.add_function(function);
+        module.set_start_function(index);
+
+        assert!(matches!(
+            WasmCodegen::new().compile(&module),
+            Err(BackendError::CompilationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_stack_values_after_pushed_operands() {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let mut store = WasmIR::new("store".to_string(), Signature { params: vec![Type::I32], returns: None });
+        store.locals = vec![Type::I32];
+        store.add_basic_block(
+            vec![
+                Instruction::LocalGet { index: 0 },
+                Instruction::MemoryStore {
+                    address: Operand::Local(0),
+                    value: Operand::StackValue(0),
+                    ty: Type::I32,
+                    align: None,
+                    offset: 0,
+                },
+            ],
+            Terminator::Return { value: None },
+        );
+        module.add_function(store);
+        assert!(matches!(WasmCodegen::new().compile(&module), Err(BackendError::Unsupported(_))));
+
+        let mut call = WasmIR::new("call".to_string(), Signature { params: vec![Type::I32, Type::I32], returns: None });
+        call.locals = vec![Type::I32, Type::I32];
+        call.add_basic_block(
+            vec![
+                Instruction::LocalGet { index: 0 },
+                Instruction::Call {
+                    func_ref: 0,
+                    args: vec![Operand::Constant(Constant::I32(1)), Operand::StackValue(0)],
+                },
+            ],
+            Terminator::Return { value: None },
+        );
+        module.functions[0] = call;
+        assert!(matches!(WasmCodegen::new().compile(&module), Err(BackendError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_rejects_disabled_proposals_before_emission() {
+        let mut function = WasmIR::new("describe".to_string(), Signature {
+            params: vec![Type::ExternRef("Element".to_string())],
+            returns: None,
+        });
+        function.add_basic_block(vec![], Terminator::Return { value: None });
+        assert!(WasmCodegen::new().compile_function(&function).is_ok());
+
+        let codegen = WasmCodegen::new().features(FeatureSet::mvp());
+        match codegen.compile_function(&function) {
+            Err(BackendError::Unsupported(message)) => assert_eq!(
+                message,
+                "describe: signature requires the disabled reference-types proposal"
+            ),
+            other => panic!("expected a feature error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_threaded_modules_import_shared_memory() {
+        let mut main = WasmIR::new(THREAD_MAIN_EXPORT.to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: None,
+        });
+        main.add_basic_block(vec![], Terminator::Return { value: None });
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let index = module.add_function(main);
+        module.export_function(THREAD_MAIN_EXPORT, index);
+        match WasmCodegen::new().compile(&module) {
+            Err(BackendError::Unsupported(message)) => assert_eq!(
+                message,
+                "module: shared memory requires the disabled threads proposal"
+            ),
+            other => panic!("expected a feature error, got {:?}", other),
+        }
+
+        let codegen = WasmCodegen::new().features(FeatureSet::default().with(Proposal::Threads));
+        let binary = codegen.clone().compile(&module).unwrap();
+        assert!(find_section(&binary, SectionId::Memory).is_none());
+        // env.memory, shared with limits 1..=65536
+        let imports = find_section(&binary, SectionId::Import).unwrap();
+        assert_eq!(imports, b"\x01\x03env\x06memory\x02\x03\x01\x80\x80\x04");
+        let exports = find_section(&binary, SectionId::Export).unwrap();
+        assert!(exports.windows(THREAD_START_EXPORT.len()).any(|w| w == THREAD_START_EXPORT.as_bytes()));
+
+        // Every thread's shadow stack comes from the shared heap
+        module.functions[0].add_stack_slot(4, 4);
+        match codegen.clone().compile(&module) {
+            Err(BackendError::Unsupported(message)) => {
+                assert_eq!(message, "threads share the heap, which needs the host allocator")
+            }
+            other => panic!("expected a threading error, got {:?}", other),
+        }
+        codegen.allocator(AllocatorStrategy::Host).compile(&module).unwrap();
+    }
+
+    #[test]
+    fn test_atomic_wait_and_notify_need_threads() {
+        let mut function = WasmIR::new("park".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        function.add_local(Type::I32);
+        function.add_basic_block(
+            vec![
+                Instruction::AtomicWait {
+                    address: Operand::Local(0),
+                    expected: Operand::Constant(Constant::I32(0)),
+                    timeout: Operand::Constant(Constant::I64(-1)),
+                    ty: Type::I32,
+                },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+                Instruction::AtomicNotify { address: Operand::Local(0), count: Operand::Constant(Constant::I32(1)) },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        module.add_function(function);
+        assert!(matches!(
+            WasmCodegen::new().compile(&module),
+            Err(BackendError::Unsupported(message)) if message.contains("threads")
+        ));
+
+        let binary = WasmCodegen::new()
+            .features(FeatureSet::default().with(Proposal::Threads))
+            .compile(&module)
+            .unwrap();
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        // memory.atomic.wait32 and memory.atomic.notify, both aligned to 4 bytes
+        assert!(code.windows(4).any(|w| w == [0xFE, 0x01, 0x02, 0x00]));
+        assert!(code.windows(4).any(|w| w == [0xFE, 0x00, 0x02, 0x00]));
+    }
+
+    fn panicking_module() -> WasmModule {
+        let mut function = WasmIR::new("fail".to_string(), void_signature());
+        function.add_basic_block(vec![], Terminator::Panic {
+            message: Some(Operand::Constant(Constant::String("boom".to_string()))),
+        });
+        let mut module = WasmModule::new();
+        module.declare_panic_hook();
+        let index = module.add_function(function);
+        module.export_function("fail", index);
+        module
+    }
+
+    #[test]
+    fn test_abort_calls_panic_hook_then_traps() {
+        let binary = WasmCodegen::new().compile(&panicking_module()).unwrap();
+        assert!(find_section(&binary, SectionId::Tag).is_none());
+
+        // i32.const 0, call $hook, unreachable
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        assert!(code.windows(5).any(|w| w == [0x41, 0x00, 0x10, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn test_unwind_throws_panic_tag() {
+        let module = panicking_module();
+        let codegen = WasmCodegen::new().panic_strategy(PanicStrategy::Unwind);
+        match codegen.compile(&module) {
+            Err(BackendError::Unsupported(message)) => assert_eq!(
+                message,
+                "fail: terminator of block 0 requires the disabled exception-handling proposal"
+            ),
+            other => panic!("expected a feature error, got {:?}", other),
+        }
+
+        let binary = codegen
+            .features(FeatureSet::default().with(Proposal::ExceptionHandling))
+            .compile(&module)
+            .unwrap();
+        // One tag of the hook's `[i32] -> []` type
+        assert_eq!(find_section(&binary, SectionId::Tag).unwrap(), vec![0x01, 0x00, 0x00]);
+
+        // i32.const 0, call $hook, i32.const 0, throw 0
+        let code = find_section(&binary, SectionId::Code).unwrap();
+        assert!(code.windows(8).any(|w| w == [0x41, 0x00, 0x10, 0x00, 0x41, 0x00, 0x08, 0x00]));
+    }
+
+    #[test]
+    fn test_freestanding_rejects_runtime_imports() {
+        let codegen = WasmCodegen::new().freestanding(true);
+        match codegen.compile(&panicking_module()) {
+            Err(BackendError::Unsupported(message)) => assert_eq!(
+                message,
+                "freestanding modules have no runtime, but this one imports wasmrust::__wasmrust_panic"
+            ),
+            other => panic!("expected a freestanding error, got {:?}", other),
+        }
+
+        // Without the hook, a panic only traps
+        let mut module = panicking_module();
+        module.imports.clear();
+        let code = find_section(&codegen.compile(&module).unwrap(), SectionId::Code).unwrap();
+        assert!(code.ends_with(&[0x00, 0x0B]));
+    }
+
+    /// Calls an import and a local helper, and reads and writes globals
+    /// including the stack pointer, with a constructor
+    fn linkable_module() -> WasmModule {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let puts = module.add_import("env", "puts", Signature { params: vec![Type::I32], returns: None });
+        let stack_pointer = module.add_global(STACK_POINTER_GLOBAL, Type::I32, true, Constant::I32(0));
+        let counter = module.add_global("counter", Type::I32, true, Constant::I32(0));
+        let mut helper = WasmIR::new("helper".to_string(), void_signature());
+        helper.add_basic_block(
+            vec![Instruction::CallImport { import: puts, args: vec![Operand::Global(counter)] }],
+            Terminator::Return { value: None },
+        );
+        let helper = module.add_function(helper);
+        let mut run = WasmIR::new("run".to_string(), void_signature());
+        run.add_basic_block(
+            vec![
+                Instruction::Call { func_ref: helper, args: vec![] },
+                Instruction::GlobalSet { index: counter, value: Operand::Global(stack_pointer) },
+            ],
+            Terminator::Return { value: None },
+        );
+        let run = module.add_function(run);
+        module.export_function("run", run);
+        let init = module.add_function(void_function("init"));
+        module.add_constructor(init, 5);
+        module
+    }
+
+    #[test]
+    fn test_objects_link_through_symbols_and_relocations() {
+        use wasmparser::{KnownCustom, Linking, Parser, Payload, SymbolInfo};
+
+        let binary = WasmCodegen::new().compile_object(&linkable_module()).unwrap();
+        assert!(find_section(&binary, SectionId::Export).is_none());
+        assert!(find_section(&binary, SectionId::Start).is_none());
+        let code = find_section(&binary, SectionId::Code).unwrap();
+
+        let (mut imports, mut symbols, mut inits, mut relocations) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for payload in Parser::new(0).parse_all(&binary) {
+            match payload.unwrap() {
+                Payload::ImportSection(reader) => {
+                    imports.extend(reader.into_iter().map(|import| import.unwrap().name.to_string()));
+                }
+                Payload::CustomSection(reader) => match reader.as_known() {
+                    KnownCustom::Linking(linking) => {
+                        assert_eq!(linking.version(), object::LINKING_VERSION);
+                        for subsection in linking.subsections() {
+                            match subsection.unwrap() {
+                                Linking::SymbolTable(table) => symbols.extend(table.into_iter().map(|symbol| {
+                                    match symbol.unwrap() {
+                                        SymbolInfo::Func { flags, index, name } => (0, flags.bits(), index, name),
+                                        SymbolInfo::Global { flags, index, name } => (2, flags.bits(), index, name),
+                                        other => panic!("unexpected symbol {:?}", other),
+                                    }
+                                })),
+                                Linking::InitFuncs(funcs) => inits.extend(funcs.into_iter().map(|init| {
+                                    let init = init.unwrap();
+                                    (init.priority, init.symbol_index)
+                                })),
+                                other => panic!("unexpected subsection {:?}", other),
+                            }
+                        }
+                    }
+                    KnownCustom::Reloc(reloc) => {
+                        // Type, import, function, and global sections come first
+                        assert_eq!(reloc.section_index(), 4);
+                        relocations.extend(reloc.entries().into_iter().map(|entry| entry.unwrap()));
+                    }
+                    _ => {}
+                },
+                Payload::GlobalSection(reader) => assert_eq!(reader.count(), 1),
+                _ => {}
+            }
+        }
+        assert_eq!(imports, ["puts", LINEAR_MEMORY_IMPORT, STACK_POINTER_GLOBAL]);
+        assert_eq!(symbols, [
+            (0, SYMBOL_UNDEFINED, 0, None),
+            (0, SYMBOL_BINDING_LOCAL, 1, Some("helper")),
+            (0, SYMBOL_EXPORTED, 2, Some("run")),
+            (0, SYMBOL_BINDING_LOCAL, 3, Some("init")),
+            (2, SYMBOL_UNDEFINED, 0, None),
+            (2, SYMBOL_BINDING_LOCAL, 1, Some("counter")),
+        ]);
+        assert_eq!(inits, [(5, 3)]);
+
+        // Each relocated index is padded to five bytes and holds the symbol's index
+        assert_eq!(relocations.len(), 5);
+        for relocation in &relocations {
+            let (_, _, index, _) = symbols[relocation.index as usize];
+            let offset = relocation.offset as usize;
+            assert_eq!(read_u32(&code[offset..]), (index, 5));
+            assert!(matches!(code[offset - 1], 0x10 | 0x23 | 0x24));
+        }
+        // R_WASM_GLOBAL_INDEX_LEB and R_WASM_FUNCTION_INDEX_LEB
+        let ty = |relocation: &wasmparser::RelocationEntry| relocation.ty as u8;
+        assert_eq!(relocations.iter().map(ty).collect::<Vec<_>>(), [7, 0, 0, 7, 7]);
+    }
+
+    #[test]
+    fn test_objects_reject_fixed_addresses() {
+        let mut module = linkable_module();
+        module.data_segments.push(wasm::wasmir::DataSegment { offset: 16, bytes: vec![1] });
+        match WasmCodegen::new().compile_object(&module) {
+            Err(BackendError::Unsupported(message)) => {
+                assert_eq!(message, "cannot emit a relocatable object: data segments are placed at fixed addresses")
+            }
+            other => panic!("expected an object error, got {:?}", other),
+        }
+        let codegen = WasmCodegen::new().panic_strategy(PanicStrategy::Unwind);
+        assert!(codegen.compile_object(&panicking_module()).is_err());
+        assert!(WasmCodegen::new().compile_object(&panicking_module()).is_ok());
+    }
+
+    #[test]
+    fn test_shared_modules_import_memory_and_stack_pointer() {
+        use wasmparser::{Parser, Payload};
+
+        let binary = WasmCodegen::new().compile_shared(&linkable_module()).unwrap();
+        wasmparser::validate(&binary).unwrap();
+        let dylink = b"\x08dylink.0";
+        assert_eq!(binary[8], 0);
+        assert_eq!(&binary[10..10 + dylink.len()], dylink);
+        assert!(find_section(&binary, SectionId::Memory).is_none());
+        assert!(find_section(&binary, SectionId::Start).is_none());
+
+        let (mut imports, mut exports) = (Vec::new(), Vec::new());
+        for payload in Parser::new(0).parse_all(&binary) {
+            match payload.unwrap() {
+                Payload::ImportSection(reader) => imports.extend(reader.into_iter().map(|import| {
+                    let import = import.unwrap();
+                    format!("{}.{}", import.module, import.name)
+                })),
+                Payload::ExportSection(reader) => {
+                    exports.extend(reader.into_iter().map(|export| export.unwrap().name.to_string()));
+                }
+                Payload::GlobalSection(reader) => assert_eq!(reader.count(), 1),
+                _ => {}
+            }
+        }
+        assert_eq!(imports, ["env.puts", "env.memory", "env.__stack_pointer"]);
+        assert_eq!(exports, [MEMORY_IMPORT, "run", CALL_CTORS_EXPORT]);
+
+        let mut module = linkable_module();
+        module.data_segments.push(wasm::wasmir::DataSegment { offset: 16, bytes: vec![1] });
+        match WasmCodegen::new().compile_shared(&module) {
+            Err(BackendError::Unsupported(message)) => {
+                assert_eq!(message, "cannot emit a shared module: data segments are placed at fixed addresses")
+            }
+            other => panic!("expected a shared module error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Cross-backend conformance checking
+//!
+//! Runs the same `WasmModule` on several engines with identical calls and
+//! reports every call whose outcome differs. The interpreter is the
+//! reference; each in-process runtime runs the binary emitter's output
+//! through its own compiler (Cranelift, for wasmtime). The codegen backends
+//! from `BackendFactory` join through `BackendEngine`, which loads what they
+//! emit on a runtime.
+
+use crate::backend::codegen::WasmCodegen;
+use crate::backend::interpreter::{Interpreter, InterpreterError};
+use crate::backend::{BackendCapabilities, BackendFactory, BuildProfile};
+use crate::host::runtime::{self, Instance, Invocation, Runtime, RuntimeError, Value};
+use crate::host::HostFunctions;
+use std::fmt;
+use wasm::wasmir::WasmModule;
+
+/// What a call produced
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The call returned, with its result if it has one
+    Returned(Option<Value>),
+    /// The call trapped
+    Trapped(String),
+    /// The engine could not make the call, so it is left out of the comparison
+    Failed(String),
+}
+
+impl Outcome {
+    /// Whether two outcomes agree: equal results, with any NaN matching any
+    /// other, or both trapping for whatever reason
+    pub fn agrees_with(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Returned(a), Outcome::Returned(b)) => match (a, b) {
+                (Some(Value::F32(a)), Some(Value::F32(b))) if a.is_nan() && b.is_nan() => true,
+                (Some(Value::F64(a)), Some(Value::F64(b))) if a.is_nan() && b.is_nan() => true,
+                _ => a == b,
+            },
+            (Outcome::Trapped(_), Outcome::Trapped(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Returned(Some(value)) => write!(f, "{}", value),
+            Outcome::Returned(None) => write!(f, "()"),
+            Outcome::Trapped(msg) => write!(f, "trap ({})", msg),
+            Outcome::Failed(msg) => write!(f, "failed ({})", msg),
+        }
+    }
+}
+
+/// Something that can execute a module
+pub trait Engine {
+    /// Name shown in reports
+    fn name(&self) -> String;
+
+    /// Runs the calls in order on one instance of `module`
+    ///
+    /// Fails if the module cannot be compiled or instantiated at all.
+    fn run(&self, module: &WasmModule, host: &HostFunctions, calls: &[Invocation]) -> Result<Vec<Outcome>, String>;
+}
+
+/// Engine backed by the WasmIR interpreter
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterpreterEngine;
+
+impl Engine for InterpreterEngine {
+    fn name(&self) -> String {
+        "interpreter".to_string()
+    }
+
+    fn run(&self, module: &WasmModule, host: &HostFunctions, calls: &[Invocation]) -> Result<Vec<Outcome>, String> {
+        let mut interpreter = Interpreter::new(module, host);
+        Ok(calls.iter()
+            .map(|call| match interpreter.invoke(&call.export, &call.args) {
+                Ok(value) => Outcome::Returned(value),
+                Err(InterpreterError::Trap(msg)) => Outcome::Trapped(msg),
+                Err(InterpreterError::OutOfFuel) => Outcome::Failed("out of fuel".to_string()),
+                Err(e) => Outcome::Failed(e.to_string()),
+            })
+            .collect())
+    }
+}
+
+/// Engine that emits a binary module and runs it on an in-process runtime
+pub struct RuntimeEngine {
+    runtime: Box<dyn Runtime>,
+    codegen: WasmCodegen,
+}
+
+impl RuntimeEngine {
+    pub fn new(runtime: Box<dyn Runtime>) -> Self {
+        Self { runtime, codegen: WasmCodegen::new() }
+    }
+
+    /// Sets the code generator, for instance to change enabled proposals
+    pub fn codegen(mut self, codegen: WasmCodegen) -> Self {
+        self.codegen = codegen;
+        self
+    }
+}
+
+impl Engine for RuntimeEngine {
+    fn name(&self) -> String {
+        self.runtime.name().to_string()
+    }
+
+    fn run(&self, module: &WasmModule, host: &HostFunctions, calls: &[Invocation]) -> Result<Vec<Outcome>, String> {
+        let code = self.codegen.compile(module).map_err(|e| e.to_string())?;
+        let instance = self.runtime.instantiate(&code, host).map_err(|e| e.to_string())?;
+        Ok(invoke_all(instance, calls))
+    }
+}
+
+/// Engine that compiles through a `BackendFactory` backend and runs the
+/// code it emits on an in-process runtime
+///
+/// Backends compile one function at a time, so only modules with a single
+/// function and no imports can run. Other modules, and code that is not a
+/// wasm module, fail the engine instead of being left out.
+pub struct BackendEngine {
+    name: &'static str,
+    profile: BuildProfile,
+    runtime: Box<dyn Runtime>,
+}
+
+impl BackendEngine {
+    /// Engine for the Cranelift backend, used by development builds
+    pub fn cranelift(runtime: Box<dyn Runtime>) -> Self {
+        Self { name: "cranelift", profile: BuildProfile::Development, runtime }
+    }
+
+    /// Engine for the LLVM backend, used by release builds
+    #[cfg(feature = "llvm-backend")]
+    pub fn llvm(runtime: Box<dyn Runtime>) -> Self {
+        Self { name: "llvm", profile: BuildProfile::Release, runtime }
+    }
+}
+
+impl Engine for BackendEngine {
+    fn name(&self) -> String {
+        format!("{} on {}", self.name, self.runtime.name())
+    }
+
+    fn run(&self, module: &WasmModule, host: &HostFunctions, calls: &[Invocation]) -> Result<Vec<Outcome>, String> {
+        let function = match module.functions.as_slice() {
+            [function] if module.imports.is_empty() => function,
+            _ => return Err("backend compiles single functions without imports only".to_string()),
+        };
+        let selection = BackendFactory::create_backend("wasm32", self.profile, &BackendCapabilities::default());
+        let mut backend = selection.map_err(|e| e.to_string())?.backend;
+        let result = backend.compile(function, self.profile).map_err(|e| e.to_string())?;
+        if !result.code.starts_with(b"\0asm") {
+            return Err("backend emitted native code, not a wasm module".to_string());
+        }
+        let instance = self.runtime.instantiate(&result.code, host).map_err(|e| e.to_string())?;
+        Ok(invoke_all(instance, calls))
+    }
+}
+
+/// Makes the calls in order on one instance
+fn invoke_all(mut instance: Box<dyn Instance>, calls: &[Invocation]) -> Vec<Outcome> {
+    calls.iter()
+        .map(|call| match instance.invoke(&call.export, &call.args) {
+            Ok(values) => Outcome::Returned(values.first().copied()),
+            Err(RuntimeError::Trap(msg)) => Outcome::Trapped(msg),
+            Err(e) => Outcome::Failed(e.to_string()),
+        })
+        .collect()
+}
+
+/// Call whose outcome differs between engines
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub call: Invocation,
+    /// Outcome on each engine, the reference first
+    pub outcomes: Vec<(String, Outcome)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.call)?;
+        for (engine, outcome) in &self.outcomes {
+            write!(f, " {} = {};", engine, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of running a module through a suite
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceReport {
+    /// Engines that ran the module, the reference first
+    pub engines: Vec<String>,
+    pub divergences: Vec<Divergence>,
+    /// Engines that could not run the module or one of its calls
+    pub failures: Vec<(String, String)>,
+}
+
+impl ConformanceReport {
+    /// Whether every engine agreed on every call
+    pub fn is_conformant(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "engines: {}", self.engines.join(", "))?;
+        for divergence in &self.divergences {
+            writeln!(f, "divergence: {}", divergence)?;
+        }
+        for (engine, reason) in &self.failures {
+            writeln!(f, "{} failed: {}", engine, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set of engines compared against a reference
+pub struct ConformanceSuite {
+    engines: Vec<Box<dyn Engine>>,
+    host: HostFunctions,
+}
+
+impl Default for ConformanceSuite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConformanceSuite {
+    /// Creates a suite with the interpreter as its reference
+    pub fn new() -> Self {
+        Self {
+            engines: vec![Box::new(InterpreterEngine)],
+            host: HostFunctions::new(),
+        }
+    }
+
+    /// Creates a suite that also runs every runtime compiled in
+    pub fn with_available_runtimes() -> Self {
+        runtime::available_runtimes()
+            .into_iter()
+            .filter_map(runtime::create_runtime)
+            .fold(Self::new(), |suite, runtime| suite.engine(RuntimeEngine::new(runtime)))
+    }
+
+    /// Creates a suite that runs every runtime compiled in and, on the first
+    /// of them, every codegen backend compiled in
+    ///
+    /// Without a runtime the backends' output cannot be loaded, so the suite
+    /// is the same as `with_available_runtimes`.
+    pub fn with_available_backends() -> Self {
+        let suite = Self::with_available_runtimes();
+        let Some(name) = runtime::available_runtimes().into_iter().next() else {
+            return suite;
+        };
+        let suite = match runtime::create_runtime(name) {
+            Some(runtime) => suite.engine(BackendEngine::cranelift(runtime)),
+            None => suite,
+        };
+        #[cfg(feature = "llvm-backend")]
+        let suite = match runtime::create_runtime(name) {
+            Some(runtime) => suite.engine(BackendEngine::llvm(runtime)),
+            None => suite,
+        };
+        suite
+    }
+
+    /// Adds an engine to compare against the reference
+    pub fn engine(mut self, engine: impl Engine + 'static) -> Self {
+        self.engines.push(Box::new(engine));
+        self
+    }
+
+    /// Sets the host functions every engine links imports to
+    pub fn host(mut self, host: HostFunctions) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Runs the calls on every engine and compares the outcomes
+    ///
+    /// Engines that fail a call, or the whole module, are reported as
+    /// failures rather than divergences.
+    pub fn run(&self, module: &WasmModule, calls: &[Invocation]) -> ConformanceReport {
+        let mut report = ConformanceReport::default();
+        let mut runs = Vec::new();
+        for engine in &self.engines {
+            let name = engine.name();
+            match engine.run(module, &self.host, calls) {
+                Ok(outcomes) => {
+                    report.engines.push(name.clone());
+                    runs.push((name, outcomes));
+                }
+                Err(reason) => report.failures.push((name, reason)),
+            }
+        }
+
+        for (index, call) in calls.iter().enumerate() {
+            let outcomes: Vec<(String, Outcome)> = runs.iter()
+                .map(|(name, outcomes)| (name.clone(), outcomes[index].clone()))
+                .collect();
+            for (name, outcome) in &outcomes {
+                if let Outcome::Failed(reason) = outcome {
+                    report.failures.push((name.clone(), format!("{}: {}", call, reason)));
+                }
+            }
+            let mut compared = outcomes.iter().filter(|(_, outcome)| !matches!(outcome, Outcome::Failed(_)));
+            if let Some((_, first)) = compared.next() {
+                if !compared.all(|(_, outcome)| outcome.agrees_with(first)) {
+                    report.divergences.push(Divergence { call: call.clone(), outcomes });
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{BinaryOp, Instruction, Operand, Signature, Terminator, Type, WasmIR};
+
+    /// Engine that returns a fixed outcome for every call
+    struct Fixed(Outcome);
+
+    impl Engine for Fixed {
+        fn name(&self) -> String {
+            "fixed".to_string()
+        }
+
+        fn run(&self, _: &WasmModule, _: &HostFunctions, calls: &[Invocation]) -> Result<Vec<Outcome>, String> {
+            Ok(vec![self.0.clone(); calls.len()])
+        }
+    }
+
+    fn divide() -> WasmModule {
+        let mut module = WasmModule::new();
+        let mut div = WasmIR::new("div".to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        });
+        div.add_basic_block(
+            vec![Instruction::BinaryOp { op: BinaryOp::Div, left: Operand::Local(0), right: Operand::Local(1) }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let index = module.add_function(div);
+        module.export_function("div", index);
+        module
+    }
+
+    #[test]
+    fn test_reports_divergent_calls() {
+        let calls = [
+            Invocation::new("div", vec![Value::I32(-9), Value::I32(2)]),
+            Invocation::new("div", vec![Value::I32(1), Value::I32(0)]),
+            Invocation::new("missing", vec![]),
+        ];
+        let report = ConformanceSuite::new()
+            .engine(Fixed(Outcome::Returned(Some(Value::I32(-4)))))
+            .run(&divide(), &calls);
+
+        assert_eq!(report.engines, vec!["interpreter".to_string(), "fixed".to_string()]);
+        assert_eq!(report.divergences.len(), 1);
+        assert_eq!(
+            report.divergences[0].to_string(),
+            "div(1, 0): interpreter = trap (integer divide by zero); fixed = -4;"
+        );
+        assert_eq!(report.failures, vec![("interpreter".to_string(), "missing(): Missing export: missing".to_string())]);
+        assert!(!report.is_conformant());
+        assert!(Outcome::Returned(Some(Value::F64(f64::NAN))).agrees_with(&Outcome::Returned(Some(Value::F64(-f64::NAN)))));
+    }
+
+    #[test]
+    fn test_available_runtimes_agree_with_interpreter() {
+        let calls = [
+            Invocation::new("div", vec![Value::I32(-9), Value::I32(2)]),
+            Invocation::new("div", vec![Value::I32(i32::MIN), Value::I32(-1)]),
+            Invocation::new("div", vec![Value::I32(1), Value::I32(0)]),
+        ];
+        let report = ConformanceSuite::with_available_runtimes().run(&divide(), &calls);
+        assert_eq!(report.engines.len(), 1 + runtime::available_runtimes().len());
+        assert!(report.is_conformant(), "{}", report);
+        assert!(report.failures.is_empty(), "{}", report);
+    }
+
+    #[test]
+    #[cfg(feature = "wasmtime")]
+    fn test_cranelift_engine_loads_what_it_emits() {
+        let calls = [
+            Invocation::new("div", vec![Value::I32(7), Value::I32(2)]),
+            Invocation::new("div", vec![Value::I32(1), Value::I32(0)]),
+        ];
+        let report = ConformanceSuite::with_available_backends().run(&divide(), &calls);
+        assert!(report.engines.iter().any(|engine| engine == "cranelift on wasmtime"), "{}", report);
+        assert!(report.is_conformant(), "{}", report);
+        assert!(report.failures.is_empty(), "{}", report);
+    }
+
+    #[test]
+    #[cfg(feature = "wasmtime")]
+    fn test_backend_engines_fail_modules_they_cannot_compile() {
+        let mut module = divide();
+        module.add_function(module.functions[0].clone());
+        let report = ConformanceSuite::with_available_backends()
+            .run(&module, &[Invocation::new("div", vec![Value::I32(4), Value::I32(2)])]);
+        assert!(report.failures.iter().any(|(engine, reason)| {
+            engine == "cranelift on wasmtime" && reason.contains("single functions")
+        }), "{}", report);
+        assert!(report.is_conformant(), "{}", report);
+    }
+}
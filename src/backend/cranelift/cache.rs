@@ -0,0 +1,192 @@
+//! Compiled functions shared by every Cranelift backend in the process
+//!
+//! `BackendFactory` creates a backend per compilation, so a cache owned by
+//! the backend was lost with it. The shared `CodegenCache` lives as long as
+//! the process instead, and later `WasmRustCompiler`s reuse the functions
+//! earlier ones compiled. Entries are keyed by the function's contents, the
+//! options shaping its code, and `BACKEND_VERSION`, held in full so a hit
+//! is never a hash collision; once the code held exceeds the capacity, the
+//! least recently used entries go first.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use wasm::wasmir::WasmIR;
+
+/// Version of the code the backend emits, so entries of another never match
+pub const BACKEND_VERSION: &str = crate::VERSION;
+
+/// Bytes of code the shared cache holds unless `set_capacity` says otherwise
+pub const DEFAULT_CAPACITY: usize = 64 << 20;
+
+static SHARED: Mutex<Option<CodegenCache>> = Mutex::new(None);
+
+/// What a compiled function depends on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Debug form of the function, name and body
+    pub function: String,
+    /// Debug form of the options the backend compiled it with
+    pub options: String,
+    pub backend_version: &'static str,
+}
+
+impl CacheKey {
+    pub fn new(function: &WasmIR, options: &impl Debug) -> Self {
+        // The debug form covers every field, and WasmIR has no `Eq` or `Hash`
+        // as its constants may be floats
+        Self {
+            function: format!("{:?}", function),
+            options: format!("{:?}", options),
+            backend_version: BACKEND_VERSION,
+        }
+    }
+}
+
+/// Least recently used cache of compiled functions, bounded in bytes
+#[derive(Debug)]
+pub struct CodegenCache {
+    capacity: usize,
+    size: usize,
+    /// Code of each entry, with when it was last used
+    entries: HashMap<Arc<CacheKey>, (Vec<u8>, u64)>,
+    /// Entries by when they were last used, oldest first
+    recency: BTreeMap<u64, Arc<CacheKey>>,
+    clock: u64,
+}
+
+impl CodegenCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, size: 0, entries: HashMap::new(), recency: BTreeMap::new(), clock: 0 }
+    }
+
+    /// Code compiled for `key`, which becomes the most recently used
+    pub fn get(&mut self, key: &CacheKey) -> Option<Vec<u8>> {
+        self.clock += 1;
+        let (code, used) = self.entries.get_mut(key)?;
+        // Every entry is in `recency`, under the key `entries` shares
+        if let Some(key) = self.recency.remove(used) {
+            self.recency.insert(self.clock, key);
+        }
+        *used = self.clock;
+        Some(code.clone())
+    }
+
+    /// Caches code, evicting the least recently used entries to make room
+    ///
+    /// Code larger than the whole capacity is not cached.
+    pub fn insert(&mut self, key: CacheKey, code: Vec<u8>) {
+        self.remove(&key);
+        if code.len() > self.capacity {
+            return;
+        }
+        self.clock += 1;
+        self.size += code.len();
+        let key = Arc::new(key);
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, (code, self.clock));
+        self.evict();
+    }
+
+    pub fn remove(&mut self, key: &CacheKey) -> Option<Vec<u8>> {
+        let (code, used) = self.entries.remove(key)?;
+        self.recency.remove(&used);
+        self.size -= code.len();
+        Some(code)
+    }
+
+    /// Sets the bytes of code held, evicting entries beyond it
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes of code held
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.size = 0;
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((code, _)) = self.entries.remove(&key) {
+                self.size -= code.len();
+            }
+        }
+    }
+}
+
+/// Runs `f` on the cache shared by the process, created on first use
+pub fn with_shared<R>(f: impl FnOnce(&mut CodegenCache) -> R) -> R {
+    // A panic while holding the lock leaves the entries intact
+    let mut shared = SHARED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(shared.get_or_insert_with(|| CodegenCache::new(DEFAULT_CAPACITY)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{Signature, Terminator};
+
+    fn function(name: &str) -> WasmIR {
+        let mut function = WasmIR::new(name.to_string(), Signature { params: vec![], returns: None });
+        function.add_basic_block(vec![], Terminator::Return { value: None });
+        function
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let key = |name| CacheKey::new(&function(name), &());
+        let mut cache = CodegenCache::new(10);
+        cache.insert(key("a"), vec![0; 4]);
+        cache.insert(key("b"), vec![0; 4]);
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("c"), vec![0; 4]);
+        assert_eq!((cache.len(), cache.size()), (2, 8));
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("a")).is_some() && cache.get(&key("c")).is_some());
+
+        cache.insert(key("huge"), vec![0; 11]);
+        assert!(cache.get(&key("huge")).is_none());
+        cache.set_capacity(4);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&key("c")).is_some());
+    }
+
+    #[test]
+    fn test_keys_cover_body_and_options() {
+        let f = function("f");
+        let mut trapping = f.clone();
+        trapping.basic_blocks[0].terminator = Terminator::Unreachable;
+        assert_eq!(CacheKey::new(&f, &1), CacheKey::new(&f.clone(), &1));
+        assert_ne!(CacheKey::new(&f, &1), CacheKey::new(&trapping, &1));
+        assert_ne!(CacheKey::new(&f, &1), CacheKey::new(&f, &2));
+
+        // Hits compare every part of the key, not a hash of it
+        let mut cache = CodegenCache::new(10);
+        cache.insert(CacheKey::new(&f, &1), vec![1]);
+        let stale = CacheKey { backend_version: "0.0.0", ..CacheKey::new(&f, &1) };
+        assert!(cache.get(&stale).is_none());
+        assert_eq!(cache.get(&CacheKey::new(&f, &1)), Some(vec![1]));
+    }
+}
@@ -7,6 +7,7 @@
 use crate::wasmir::{
     WasmIR, Type, Instruction, Terminator, Operand, Signature, BasicBlock, BlockId
 };
+use crate::backend::pgo::PgoProfile;
 use crate::backend::cranelift::{
     type_descriptor::WasmTypeDescriptor,
     thinning_pass::ThinningResult,
@@ -113,6 +114,17 @@ pub struct PGOData {
     pub call_patterns: HashMap<String, Vec<CallPattern>>,
 }
 
+impl From<&PgoProfile> for PGOData {
+    /// Takes call frequencies from the profile's counts; profiles do not record types
+    fn from(profile: &PgoProfile) -> Self {
+        Self {
+            call_frequencies: profile.call_frequencies(),
+            type_frequencies: HashMap::new(),
+            call_patterns: HashMap::new(),
+        }
+    }
+}
+
 /// Call pattern information
 #[derive(Debug, Clone)]
 pub struct CallPattern {
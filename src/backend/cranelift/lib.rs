@@ -3,20 +3,30 @@
 //! This module provides a Cranelift-based codegen backend for WasmRust,
 //! optimized for fast development compilation. It integrates with rustc's
 //! codegen interface while adding WasmRust-specific optimizations.
+//!
+//! The backend emits wasm modules through `WasmCodegen`, like the rest of
+//! the pipeline, so what it compiles loads on any runtime. Cranelift IR is
+//! built for `--emit=clif` and verified against the host ISA.
 
 use cranelift_codegen::*;
-use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
-use cranelift_codegen::ir::{Function, InstBuilder, Signature, AbiParam, types, Type};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_codegen::ir::{Function, InstBuilder, MemFlags, Signature, AbiParam, types, Type, Value};
 use cranelift_codegen::isa::TargetIsa;
 use cranelift_codegen::settings::{Flags, Configurable};
-use cranelift_codegen::Context as CodegenContext;
-use cranelift_codegen::ir::{condcodes::IntCC, Block};
+use cranelift_codegen::ir::{condcodes::{FloatCC, IntCC}, Block};
 use cranelift_codegen::entity::EntityRef;
-use cranelift_control::ControlPlane;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
-use wasm::wasmir::{WasmIR, Instruction, Terminator, BasicBlock, BlockId, Type as WasmIRType, Signature as WasmIRSignature, Operand, BinaryOp, UnaryOp, Constant, AtomicOp, LinearOp, MemoryOrder, Capability};
+use crate::backend::codegen::WasmCodegen;
+use crate::backend::cranelift::cache::{self, CacheKey};
+use crate::backend::{
+    Backend, BackendCapabilities, BackendError, BuildProfile, CompilationMetadata, CompilationResult, OptimizationLevel,
+};
+use crate::timings::{self, Phase};
+
+use wasm::wasmir::features::{FeatureSet, FeatureViolation};
+use wasm::wasmir::{WasmIR, Instruction, Terminator, BasicBlock, BlockId, Type as WasmIRType, Signature as WasmIRSignature, Operand, BinaryOp, UnaryOp, ConvertOp, Constant, AtomicOp, LinearOp, MemoryOrder, Capability};
 
 pub mod mir_lowering;
 
@@ -26,14 +36,14 @@ pub struct WasmRustCraneliftBackend {
     isa: Arc<dyn TargetIsa>,
     /// WasmRust-specific optimization flags
     optimization_flags: WasmRustOptimizationFlags,
-    /// Function compilation cache
-    function_cache: HashMap<u64, Vec<u8>>,
     /// Compilation statistics
     stats: CompilationStats,
+    /// Proposals the target engine enables
+    features: FeatureSet,
 }
 
 /// WasmRust-specific optimization flags
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct WasmRustOptimizationFlags {
     /// Enable thin monomorphization for code deduplication
     pub thin_monomorphization: bool,
@@ -63,9 +73,38 @@ pub struct CompilationStats {
     pub instructions_generated: usize,
     pub optimization_passes: usize,
     pub compilation_time_ms: u64,
+    /// Bytes of the binary emitted
+    pub wasm_size: usize,
+    /// Functions found in the shared codegen cache
+    pub cache_hits: usize,
+    /// Functions compiled since the cache lacked them
+    pub cache_misses: usize,
+}
+
+impl CompilationStats {
+    /// Adds the counts of `other`, such as those of another backend
+    pub fn merge(&mut self, other: &CompilationStats) {
+        self.functions_compiled += other.functions_compiled;
+        self.instructions_generated += other.instructions_generated;
+        self.optimization_passes += other.optimization_passes;
+        self.compilation_time_ms += other.compilation_time_ms;
+        self.wasm_size += other.wasm_size;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+    }
 }
 
 impl WasmRustCraneliftBackend {
+    /// What the backend offers `BackendFactory`
+    pub const CAPABILITIES: BackendCapabilities = BackendCapabilities {
+        thin_monomorphization: true,
+        streaming_layout: true,
+        pgo_support: false,
+        component_model: false,
+        wasm_optimizations: true,
+        linear_types: true,
+    };
+
     /// Creates a new Cranelift backend for WasmRust
     pub fn new() -> Result<Self, CodegenError> {
         let isa = create_target_isa()?;
@@ -74,49 +113,72 @@ impl WasmRustCraneliftBackend {
         Ok(Self {
             isa,
             optimization_flags,
-            function_cache: HashMap::new(),
             stats: CompilationStats::default(),
+            features: FeatureSet::default(),
         })
     }
 
-    /// Compiles a WasmIR function to machine code
+    /// Sets the proposals the target engine enables
+    pub fn set_features(&mut self, features: FeatureSet) {
+        self.features = features;
+    }
+
+    /// Compiles a WasmIR function into a wasm module exporting it by name
+    ///
+    /// The module is emitted by `WasmCodegen` for the proposals of
+    /// `set_features`, not as code for the host ISA, so runtimes can load it.
     pub fn compile_function(
         &mut self,
         wasmir_func: &WasmIR,
         function_name: &str,
     ) -> Result<Vec<u8>, CodegenError> {
+        let _codegen = timings::span(Phase::Codegen, function_name);
         let start_time = std::time::Instant::now();
 
-        // Convert WasmIR to Cranelift IR
-        let func = self.convert_function_body(wasmir_func)?;
-        
-        // Apply WasmRust-specific optimizations
-        let mut optimized_func = func;
-        self.apply_optimizations(&mut optimized_func)?;
-        
-        // Get instruction count before moving the function
-        let instruction_count = optimized_func.dfg.num_insts();
-        
-        // Compile to machine code
-        let mut code_gen_context = CodegenContext::new();
-        code_gen_context.func = optimized_func;
-        let mut ctrl_plane = ControlPlane::default();
-        let compiled = code_gen_context.compile(&*self.isa, &mut ctrl_plane)?;
+        // Reject proposals the target lacks before lowering anything
+        self.features.check_function(wasmir_func).map_err(CodegenError::FeatureDisabled)?;
 
-        let code = compiled.code_buffer().to_vec();
+        // Backends of every compiler in the process share compiled functions
+        let key = CacheKey::new(wasmir_func, &(&self.optimization_flags, self.features));
+        let code = match cache::with_shared(|cache| cache.get(&key)) {
+            Some(code) => {
+                self.stats.cache_hits += 1;
+                code
+            }
+            None => {
+                self.stats.cache_misses += 1;
+                let code = WasmCodegen::new()
+                    .features(self.features)
+                    .compile_function(wasmir_func)
+                    .map_err(CodegenError::Emission)?;
+                cache::with_shared(|cache| cache.insert(key, code.clone()));
+                code
+            }
+        };
 
         // Update statistics
+        let instruction_count: usize = wasmir_func.basic_blocks.iter()
+            .map(|block| block.instructions.len() + 1)
+            .sum();
         self.stats.functions_compiled += 1;
         self.stats.instructions_generated += instruction_count;
         self.stats.compilation_time_ms += start_time.elapsed().as_millis() as u64;
-
-        // Cache compiled function
-        let function_hash = self.hash_function(wasmir_func);
-        self.function_cache.insert(function_hash, code.clone());
+        self.stats.wasm_size += code.len();
 
         Ok(code)
     }
 
+    /// Cranelift IR of a WasmIR function, after WasmRust's optimizations
+    ///
+    /// The function is printed in the CLIF text format `clif-util` reads,
+    /// once it verifies for the host ISA.
+    pub fn emit_clif(&mut self, wasmir_func: &WasmIR) -> Result<String, CodegenError> {
+        self.features.check_function(wasmir_func).map_err(CodegenError::FeatureDisabled)?;
+        let mut func = self.convert_function_body(wasmir_func)?;
+        self.apply_optimizations(&mut func)?;
+        Ok(func.display().to_string())
+    }
+
     /// Gets compilation statistics
     pub fn get_stats(&self) -> &CompilationStats {
         &self.stats
@@ -129,7 +191,7 @@ impl WasmRustCraneliftBackend {
 
     /// Converts WasmIR signature to Cranelift signature
     fn convert_signature(&self, wasmir_sig: &WasmIRSignature) -> Result<Signature, CodegenError> {
-        let mut signature = Signature::new(cranelift_codegen::isa::CallConv::SystemV);
+        let mut signature = Signature::new(self.isa.default_call_conv());
 
         // Convert parameters
         for param in &wasmir_sig.params {
@@ -147,6 +209,13 @@ impl WasmRustCraneliftBackend {
     }
 
     /// Converts WasmIR function body to Cranelift IR
+    ///
+    /// Each WasmIR local becomes a `Variable`, so `FunctionBuilder` builds
+    /// the SSA form: parameters are defined from the entry block's params
+    /// and the other locals start at zero, as in wasm. The entry block only
+    /// jumps to the first basic block, which may then be a loop header, and
+    /// every block is sealed once its last predecessor has been lowered.
+    /// The function is checked with Cranelift's verifier for `self.isa`.
     fn convert_function_body(&self, wasmir_func: &WasmIR) -> Result<Function, CodegenError> {
         let signature = self.convert_signature(&wasmir_func.signature)?;
         let mut func = Function::with_name_signature(
@@ -158,51 +227,91 @@ impl WasmRustCraneliftBackend {
         let mut builder = FunctionBuilder::new(&mut func, &mut builder_context);
 
         // Create blocks for each basic block
-        let mut block_map = HashMap::new();
-        for (i, _) in wasmir_func.basic_blocks.iter().enumerate() {
-            let block = builder.create_block();
-            block_map.insert(BlockId(i), block);
+        let entry = builder.create_block();
+        let blocks: Vec<Block> = wasmir_func.basic_blocks.iter().map(|_| builder.create_block()).collect();
+        let first = *blocks.first().ok_or(CodegenError::InstructionGeneration("function has no basic blocks"))?;
+        let last_predecessors = last_predecessors(wasmir_func);
+
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let params = builder.block_params(entry).to_vec();
+        let count = wasmir_func.signature.params.len().max(wasmir_func.locals.len());
+        for index in 0..count {
+            let ty = wasmir_func.signature.params.get(index).or_else(|| wasmir_func.locals.get(index))
+                .ok_or(CodegenError::TypeConversion("local without a type"))?;
+            let ty = self.convert_type(ty)?;
+            let var = Variable::new(index);
+            builder.try_declare_var(var, ty)
+                .map_err(|err| CodegenError::Verification(format!("local {}: {:?}", index, err)))?;
+            let value = match params.get(index) {
+                Some(&param) => param,
+                None => zero(&mut builder, ty),
+            };
+            builder.try_def_var(var, value)
+                .map_err(|err| CodegenError::Verification(format!("local {}: {:?}", index, err)))?;
         }
+        builder.ins().jump(first, &[]);
 
-        // Convert basic blocks
-        for (bb_id, bb) in wasmir_func.basic_blocks.iter().enumerate() {
-            let block = block_map[&BlockId(bb_id)];
-            builder.switch_to_block(block);
+        for (index, bb) in wasmir_func.basic_blocks.iter().enumerate() {
+            builder.switch_to_block(blocks[index]);
+            if last_predecessors[index].is_none() {
+                // Only the entry block, if any, branches here
+                builder.seal_block(blocks[index]);
+            }
 
-            // Convert instructions in this basic block
+            // Values instructions leave for those after them
+            let mut stack = Vec::new();
             for instruction in &bb.instructions {
-                self.convert_instruction(&mut builder, instruction)?;
+                if let Some(value) = self.convert_instruction(&mut builder, &mut stack, instruction)? {
+                    stack.push(value);
+                }
             }
+            self.add_block_terminator(&mut builder, &mut stack, &bb.terminator, &blocks)?;
 
-            // Add terminator for this block
-            self.add_block_terminator(&mut builder, &bb.terminator, &block_map)?;
+            for target in (0..blocks.len()).filter(|&target| last_predecessors[target] == Some(index)) {
+                builder.seal_block(blocks[target]);
+            }
         }
 
         builder.finalize();
+        cranelift_codegen::verify_function(&func, &*self.isa)
+            .map_err(|errors| CodegenError::Verification(errors.to_string()))?;
         Ok(func)
     }
 
-    /// Converts a WasmIR instruction to Cranelift IR
+    /// Converts a WasmIR instruction to Cranelift IR, returning the value it produces
     fn convert_instruction(
         &self,
         builder: &mut FunctionBuilder,
+        stack: &mut Vec<Value>,
         instruction: &Instruction,
-    ) -> Result<Option<cranelift_codegen::ir::Value>, CodegenError> {
+    ) -> Result<Option<Value>, CodegenError> {
         match instruction {
             Instruction::LocalGet { index } => {
                 let var = Variable::from_u32(*index);
-                let value = builder.use_var(var);
+                let value = builder.try_use_var(var)
+                    .map_err(|err| CodegenError::Verification(format!("local {}: {:?}", index, err)))?;
                 Ok(Some(value))
             }
             Instruction::LocalSet { index, value } => {
                 let var = Variable::from_u32(*index);
-                let converted_value = self.convert_operand(builder, value)?;
-                builder.def_var(var, converted_value);
+                let converted_value = self.convert_operands(builder, stack, &[value])?[0];
+                builder.try_def_var(var, converted_value)
+                    .map_err(|err| CodegenError::Verification(format!("local {}: {:?}", index, err)))?;
                 Ok(None)
             }
             Instruction::BinaryOp { op, left, right } => {
-                let left_val = self.convert_operand(builder, left)?;
-                let right_val = self.convert_operand(builder, right)?;
+                let operands = self.convert_operands(builder, stack, &[left, right])?;
+                let (left_val, right_val) = (operands[0], operands[1]);
+                if builder.func.dfg.value_type(left_val).is_float() {
+                    return float_binary_op(builder, *op, left_val, right_val).map(Some);
+                }
+                let compare = |builder: &mut FunctionBuilder, cc| {
+                    // Comparisons produce an i32, as in wasm
+                    let flag = builder.ins().icmp(cc, left_val, right_val);
+                    builder.ins().uextend(types::I32, flag)
+                };
                 let result = match op {
                     BinaryOp::Add => builder.ins().iadd(left_val, right_val),
                     BinaryOp::Sub => builder.ins().isub(left_val, right_val),
@@ -213,21 +322,24 @@ impl WasmRustCraneliftBackend {
                     BinaryOp::Or => builder.ins().bor(left_val, right_val),
                     BinaryOp::Xor => builder.ins().bxor(left_val, right_val),
                     BinaryOp::Shl => builder.ins().ishl(left_val, right_val),
-                    BinaryOp::Shr => builder.ins().sshr(left_val, right_val),
+                    BinaryOp::Shr => builder.ins().ushr(left_val, right_val),
                     BinaryOp::Sar => builder.ins().sshr(left_val, right_val),
-                    BinaryOp::Eq => builder.ins().icmp(IntCC::Equal, left_val, right_val),
-                    BinaryOp::Ne => builder.ins().icmp(IntCC::NotEqual, left_val, right_val),
-                    BinaryOp::Lt => builder.ins().icmp(IntCC::SignedLessThan, left_val, right_val),
-                    BinaryOp::Le => builder.ins().icmp(IntCC::SignedLessThanOrEqual, left_val, right_val),
-                    BinaryOp::Gt => builder.ins().icmp(IntCC::SignedGreaterThan, left_val, right_val),
-                    BinaryOp::Ge => builder.ins().icmp(IntCC::SignedGreaterThanOrEqual, left_val, right_val),
+                    BinaryOp::Eq => compare(builder, IntCC::Equal),
+                    BinaryOp::Ne => compare(builder, IntCC::NotEqual),
+                    BinaryOp::Lt => compare(builder, IntCC::SignedLessThan),
+                    BinaryOp::Le => compare(builder, IntCC::SignedLessThanOrEqual),
+                    BinaryOp::Gt => compare(builder, IntCC::SignedGreaterThan),
+                    BinaryOp::Ge => compare(builder, IntCC::SignedGreaterThanOrEqual),
                 };
                 Ok(Some(result))
             }
             Instruction::UnaryOp { op, value } => {
-                let value_val = self.convert_operand(builder, value)?;
+                let value_val = self.convert_operands(builder, stack, &[value])?[0];
+                let is_float = builder.func.dfg.value_type(value_val).is_float();
                 let result = match op {
+                    UnaryOp::Neg if is_float => builder.ins().fneg(value_val),
                     UnaryOp::Neg => builder.ins().ineg(value_val),
+                    _ if is_float => return Err(CodegenError::Unsupported("bitwise operation on a float")),
                     UnaryOp::Not => builder.ins().bnot(value_val),
                     UnaryOp::Clz => builder.ins().clz(value_val),
                     UnaryOp::Ctz => builder.ins().ctz(value_val),
@@ -235,39 +347,100 @@ impl WasmRustCraneliftBackend {
                 };
                 Ok(Some(result))
             }
-            Instruction::Return { value } => {
-                if let Some(val) = value {
-                    let converted_val = self.convert_operand(builder, val)?;
-                    builder.ins().return_(&[converted_val]);
-                } else {
-                    builder.ins().return_(&[]);
-                }
-                Ok(None)
+            Instruction::Convert { op, value } => {
+                let value = self.convert_operands(builder, stack, &[value])?[0];
+                self.convert_numeric(builder, op, value).map(Some)
             }
             Instruction::Nop => Ok(None),
-            _ => {
-                // For now, return Ok(None) for unimplemented instructions
-                // This allows the basic backend to compile
-                Ok(None)
+            Instruction::Return { .. } | Instruction::Branch { .. } | Instruction::Jump { .. }
+            | Instruction::Switch { .. } => {
+                Err(CodegenError::InstructionGeneration("control flow belongs in the block terminator"))
             }
+            other => Err(CodegenError::Unsupported(other.name())),
         }
     }
 
-    /// Converts a WasmIR operand to Cranelift value
+    /// Lowers a numeric conversion
+    fn convert_numeric(
+        &self,
+        builder: &mut FunctionBuilder,
+        op: &ConvertOp,
+        value: Value,
+    ) -> Result<Value, CodegenError> {
+        let from = builder.func.dfg.value_type(value);
+        let converted = match op {
+            ConvertOp::Wrap => builder.ins().ireduce(types::I32, value),
+            ConvertOp::Extend { signed: true } => builder.ins().sextend(types::I64, value),
+            ConvertOp::Extend { signed: false } => builder.ins().uextend(types::I64, value),
+            ConvertOp::SignExtend { bits } => {
+                let narrow = Type::int(u16::from(*bits))
+                    .ok_or(CodegenError::TypeConversion("sign extension width"))?;
+                let low = builder.ins().ireduce(narrow, value);
+                builder.ins().sextend(from, low)
+            }
+            ConvertOp::Trunc { to, signed: true } => builder.ins().fcvt_to_sint(self.convert_type(to)?, value),
+            ConvertOp::Trunc { to, signed: false } => builder.ins().fcvt_to_uint(self.convert_type(to)?, value),
+            ConvertOp::TruncSat { to, signed: true } => {
+                builder.ins().fcvt_to_sint_sat(self.convert_type(to)?, value)
+            }
+            ConvertOp::TruncSat { to, signed: false } => {
+                builder.ins().fcvt_to_uint_sat(self.convert_type(to)?, value)
+            }
+            ConvertOp::FromInt { to, signed: true } => builder.ins().fcvt_from_sint(self.convert_type(to)?, value),
+            ConvertOp::FromInt { to, signed: false } => {
+                builder.ins().fcvt_from_uint(self.convert_type(to)?, value)
+            }
+            ConvertOp::Promote => builder.ins().fpromote(types::F64, value),
+            ConvertOp::Demote => builder.ins().fdemote(types::F32, value),
+            ConvertOp::Reinterpret => {
+                let to = match from {
+                    types::I32 => types::F32,
+                    types::I64 => types::F64,
+                    types::F32 => types::I32,
+                    types::F64 => types::I64,
+                    _ => return Err(CodegenError::TypeConversion("reinterpretation of a non-numeric value")),
+                };
+                builder.ins().bitcast(to, MemFlags::new(), value)
+            }
+        };
+        Ok(converted)
+    }
+
+    /// Converts the operands of one instruction, in order
+    ///
+    /// Stack values come off `stack` in the order the instructions before
+    /// produced them, so the first stack value is the deepest one taken.
+    fn convert_operands(
+        &self,
+        builder: &mut FunctionBuilder,
+        stack: &mut Vec<Value>,
+        operands: &[&Operand],
+    ) -> Result<Vec<Value>, CodegenError> {
+        let taken = operands.iter().filter(|operand| matches!(operand, Operand::StackValue(_))).count();
+        let depth = stack.len().checked_sub(taken)
+            .ok_or(CodegenError::InstructionGeneration("stack value used with an empty stack"))?;
+        let mut stacked = stack.split_off(depth).into_iter();
+        operands.iter()
+            .map(|operand| match operand {
+                Operand::StackValue(_) => Ok(stacked.next().expect("counted above")),
+                other => self.convert_operand(builder, other),
+            })
+            .collect()
+    }
+
+    /// Converts a WasmIR operand other than a stack value to Cranelift value
     fn convert_operand(
         &self,
         builder: &mut FunctionBuilder,
         operand: &Operand,
-    ) -> Result<cranelift_codegen::ir::Value, CodegenError> {
+    ) -> Result<Value, CodegenError> {
         match operand {
             Operand::Local(index) => {
                 let var = Variable::from_u32(*index);
-                Ok(builder.use_var(var))
-            }
-            Operand::Constant(value) => {
-                let const_val = self.convert_constant(value)?;
-                Ok(builder.ins().iconst(types::I32, const_val as i64))
+                builder.try_use_var(var)
+                    .map_err(|err| CodegenError::Verification(format!("local {}: {:?}", index, err)))
             }
+            Operand::Constant(value) => self.convert_constant(builder, value),
             Operand::Global(_global_index) => {
                 // Global variables need special handling in WASM
                 Err(CodegenError::Unsupported("Global variables not yet implemented"))
@@ -281,6 +454,7 @@ impl WasmRustCraneliftBackend {
         match wasmir_ty {
             WasmIRType::I32 => Ok(types::I32),
             WasmIRType::I64 => Ok(types::I64),
+            WasmIRType::I128 => Ok(types::I128),
             WasmIRType::F32 => Ok(types::F32),
             WasmIRType::F64 => Ok(types::F64),
             WasmIRType::ExternRef(_) => Ok(types::R32), // Handle as i32 for now
@@ -289,29 +463,39 @@ impl WasmRustCraneliftBackend {
         }
     }
 
-    /// Converts a constant value to Cranelift-compatible value
-    fn convert_constant(&self, value: &Constant) -> Result<i32, CodegenError> {
+    /// Materializes a constant, typed as WasmIR types it
+    fn convert_constant(&self, builder: &mut FunctionBuilder, value: &Constant) -> Result<Value, CodegenError> {
         match value {
-            Constant::I32(v) => Ok(*v),
-            Constant::I64(v) => Ok(*v as i32),
-            Constant::F32(v) => Ok(v.to_bits() as i32),
-            Constant::F64(v) => Ok(v.to_bits() as i32),
-            Constant::Boolean(b) => Ok(if *b { 1 } else { 0 }),
-            _ => Err(CodegenError::Unsupported("Unsupported constant type")),
+            Constant::I32(v) => Ok(builder.ins().iconst(types::I32, i64::from(*v))),
+            Constant::I64(v) => Ok(builder.ins().iconst(types::I64, *v)),
+            Constant::I128(v) => {
+                // `iconst` takes at most 64 bits
+                let low = builder.ins().iconst(types::I64, *v as i64);
+                let high = builder.ins().iconst(types::I64, (*v >> 64) as i64);
+                Ok(builder.ins().iconcat(low, high))
+            }
+            Constant::F32(v) => Ok(builder.ins().f32const(*v)),
+            Constant::F64(v) => Ok(builder.ins().f64const(*v)),
+            Constant::Boolean(b) => Ok(builder.ins().iconst(types::I32, i64::from(*b))),
+            Constant::Null => Ok(builder.ins().null(types::R32)),
+            Constant::String(_) => Err(CodegenError::Unsupported("Unsupported constant type")),
         }
     }
 
     /// Applies WasmRust-specific optimizations to the function
     fn apply_optimizations(&mut self, func: &mut Function) -> Result<(), CodegenError> {
         if self.optimization_flags.thin_monomorphization {
+            let _pass = timings::span(Phase::Pass, "thin monomorphization");
             self.apply_thin_monomorphization(func)?;
         }
 
         if self.optimization_flags.streaming_layout {
+            let _pass = timings::span(Phase::Pass, "streaming layout");
             self.apply_streaming_layout(func)?;
         }
 
         if self.optimization_flags.wasm_optimizations {
+            let _pass = timings::span(Phase::Pass, "wasm optimizations");
             self.apply_wasm_optimizations(func)?;
         }
 
@@ -354,27 +538,41 @@ impl WasmRustCraneliftBackend {
     fn add_block_terminator(
         &self,
         builder: &mut FunctionBuilder,
+        stack: &mut Vec<Value>,
         terminator: &Terminator,
-        block_map: &HashMap<BlockId, Block>,
+        blocks: &[Block],
     ) -> Result<(), CodegenError> {
+        let block = |id: &BlockId| {
+            blocks.get(id.0).copied().ok_or(CodegenError::InstructionGeneration("branch to a missing block"))
+        };
         match terminator {
             Terminator::Return { value } => {
                 if let Some(val) = value {
-                    let converted_val = self.convert_operand(builder, val)?;
+                    let converted_val = self.convert_operands(builder, stack, &[val])?[0];
                     builder.ins().return_(&[converted_val]);
                 } else {
                     builder.ins().return_(&[]);
                 }
             }
             Terminator::Branch { condition, then_block, else_block } => {
-                let cond_val = self.convert_operand(builder, condition)?;
-                let then_block_ref = block_map[then_block];
-                let else_block_ref = block_map[else_block];
-                builder.ins().brif(cond_val, then_block_ref, &[], else_block_ref, &[]);
+                let cond_val = self.convert_operands(builder, stack, &[condition])?[0];
+                builder.ins().brif(cond_val, block(then_block)?, &[], block(else_block)?, &[]);
+            }
+            Terminator::Switch { value, targets, default_target } => {
+                let value = self.convert_operands(builder, stack, &[value])?[0];
+                let mut switch = Switch::new();
+                for (case, target) in targets {
+                    let case = match case {
+                        Operand::Constant(Constant::I32(case)) => *case as u32 as u128,
+                        Operand::Constant(Constant::I64(case)) => *case as u64 as u128,
+                        _ => return Err(CodegenError::Unsupported("switch case that is not an integer constant")),
+                    };
+                    switch.set_entry(case, block(target)?);
+                }
+                switch.emit(builder, value, block(default_target)?);
             }
             Terminator::Jump { target } => {
-                let target_block = block_map[target];
-                builder.ins().jump(target_block, &[]);
+                builder.ins().jump(block(target)?, &[]);
             }
             Terminator::Unreachable => {
                 builder.ins().trap(cranelift_codegen::ir::TrapCode::UnreachableCodeReached);
@@ -382,24 +580,110 @@ impl WasmRustCraneliftBackend {
             Terminator::Panic { message: _ } => {
                 builder.ins().trap(cranelift_codegen::ir::TrapCode::User(0));
             }
-            _ => {
-                // For now, handle other terminators as unreachable
-                builder.ins().trap(cranelift_codegen::ir::TrapCode::UnreachableCodeReached);
-            }
         }
         Ok(())
     }
+}
 
-    /// Hashes a function for caching purposes
-    fn hash_function(&self, wasmir_func: &WasmIR) -> u64 {
-        use std::hash::{Hash, Hasher};
-        use std::collections::hash_map::DefaultHasher;
-        
-        let mut hasher = DefaultHasher::new();
-        wasmir_func.name.hash(&mut hasher);
-        wasmir_func.signature.params.len().hash(&mut hasher);
-        hasher.finish()
+impl Backend for WasmRustCraneliftBackend {
+    fn compile(
+        &mut self,
+        wasmir: &crate::wasmir::WasmIR,
+        profile: BuildProfile,
+    ) -> Result<CompilationResult, BackendError> {
+        let code = self.compile_function(wasmir, &wasmir.name)?;
+        Ok(CompilationResult {
+            code,
+            // The function is the module's only one
+            symbols: BTreeMap::from([(wasmir.name.clone(), 0)]),
+            relocations: Vec::new(),
+            metadata: CompilationMetadata {
+                target: crate::DEFAULT_TARGET.to_string(),
+                optimization_level: OptimizationLevel::None,
+                build_profile: profile,
+                timestamp: CompilationMetadata::timestamp(false),
+            },
+        })
+    }
+
+    fn supported_optimizations(&self) -> Vec<OptimizationLevel> {
+        vec![OptimizationLevel::None]
     }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        Self::CAPABILITIES
+    }
+
+    fn stats(&self) -> Option<&CompilationStats> {
+        Some(&self.stats)
+    }
+
+    fn reset(&mut self) {
+        // The shared cache outlives the backend, as other backends use it
+        self.clear_stats();
+    }
+}
+
+/// Index of the last basic block branching to each one, if any does
+fn last_predecessors(wasmir_func: &WasmIR) -> Vec<Option<usize>> {
+    let mut last = vec![None; wasmir_func.basic_blocks.len()];
+    for (index, bb) in wasmir_func.basic_blocks.iter().enumerate() {
+        let successors: Vec<BlockId> = match &bb.terminator {
+            Terminator::Branch { then_block, else_block, .. } => vec![*then_block, *else_block],
+            Terminator::Switch { targets, default_target, .. } => {
+                targets.iter().map(|(_, target)| *target).chain([*default_target]).collect()
+            }
+            Terminator::Jump { target } => vec![*target],
+            Terminator::Return { .. } | Terminator::Unreachable | Terminator::Panic { .. } => Vec::new(),
+        };
+        for successor in successors {
+            if let Some(slot) = last.get_mut(successor.0) {
+                *slot = Some(index);
+            }
+        }
+    }
+    last
+}
+
+/// Zero of a type, the value wasm locals start with
+fn zero(builder: &mut FunctionBuilder, ty: Type) -> Value {
+    match ty {
+        types::F32 => builder.ins().f32const(0.0),
+        types::F64 => builder.ins().f64const(0.0),
+        types::I128 => {
+            let zero = builder.ins().iconst(types::I64, 0);
+            builder.ins().uextend(types::I128, zero)
+        }
+        ty if ty.is_ref() => builder.ins().null(ty),
+        ty => builder.ins().iconst(ty, 0),
+    }
+}
+
+/// Lowers a binary operation on floats
+fn float_binary_op(
+    builder: &mut FunctionBuilder,
+    op: BinaryOp,
+    left: Value,
+    right: Value,
+) -> Result<Value, CodegenError> {
+    let compare = |builder: &mut FunctionBuilder, cc| {
+        let flag = builder.ins().fcmp(cc, left, right);
+        builder.ins().uextend(types::I32, flag)
+    };
+    let result = match op {
+        BinaryOp::Add => builder.ins().fadd(left, right),
+        BinaryOp::Sub => builder.ins().fsub(left, right),
+        BinaryOp::Mul => builder.ins().fmul(left, right),
+        BinaryOp::Div => builder.ins().fdiv(left, right),
+        BinaryOp::Eq => compare(builder, FloatCC::Equal),
+        BinaryOp::Ne => compare(builder, FloatCC::NotEqual),
+        BinaryOp::Lt => compare(builder, FloatCC::LessThan),
+        BinaryOp::Le => compare(builder, FloatCC::LessThanOrEqual),
+        BinaryOp::Gt => compare(builder, FloatCC::GreaterThan),
+        BinaryOp::Ge => compare(builder, FloatCC::GreaterThanOrEqual),
+        _ => return Err(CodegenError::Unsupported("integer operation on a float")),
+    };
+    Ok(result)
 }
 
 /// Creates target ISA for compilation
@@ -436,6 +720,12 @@ pub enum CodegenError {
     Optimization(&'static str),
     /// Target configuration error
     TargetConfig(&'static str),
+    /// Use of a proposal the target does not enable
+    FeatureDisabled(FeatureViolation),
+    /// Cranelift IR that Cranelift's verifier rejects
+    Verification(String),
+    /// The wasm emitter cannot encode the function
+    Emission(BackendError),
 }
 
 impl std::fmt::Display for CodegenError {
@@ -446,15 +736,34 @@ impl std::fmt::Display for CodegenError {
             CodegenError::InstructionGeneration(msg) => write!(f, "Instruction generation error: {}", msg),
             CodegenError::Optimization(msg) => write!(f, "Optimization error: {}", msg),
             CodegenError::TargetConfig(msg) => write!(f, "Target configuration error: {}", msg),
+            CodegenError::FeatureDisabled(violation) => write!(f, "Feature disabled: {}", violation),
+            CodegenError::Verification(errors) => write!(f, "Invalid Cranelift IR: {}", errors),
+            CodegenError::Emission(err) => write!(f, "{}", err),
         }
     }
 }
 
 impl std::error::Error for CodegenError {}
 
+impl From<CodegenError> for BackendError {
+    fn from(err: CodegenError) -> Self {
+        match err {
+            CodegenError::Emission(err) => err,
+            CodegenError::TargetConfig(_) => BackendError::UnsupportedTarget(err.to_string()),
+            CodegenError::Unsupported(_) | CodegenError::FeatureDisabled(_) => {
+                BackendError::Unsupported(err.to_string())
+            }
+            _ => BackendError::CompilationFailed(err.to_string()),
+        }
+    }
+}
+
 impl From<cranelift_codegen::CodegenError> for CodegenError {
-    fn from(_err: cranelift_codegen::CodegenError) -> Self {
-        CodegenError::InstructionGeneration("Cranelift codegen error")
+    fn from(err: cranelift_codegen::CodegenError) -> Self {
+        match err {
+            cranelift_codegen::CodegenError::Verifier(errors) => CodegenError::Verification(errors.to_string()),
+            _ => CodegenError::InstructionGeneration("Cranelift codegen error"),
+        }
     }
 }
 
@@ -477,6 +786,121 @@ mod tests {
         assert_eq!(backend.get_stats().functions_compiled, 0);
     }
 
+    #[test]
+    fn test_rejects_disabled_features() {
+        let mut backend = WasmRustCraneliftBackend::new().unwrap();
+        backend.set_features(FeatureSet::default().without(wasm::wasmir::features::Proposal::ReferenceTypes));
+        let mut function = WasmIR::new("f".to_string(), WasmIRSignature { params: vec![WasmIRType::FuncRef], returns: None });
+        function.add_basic_block(vec![], Terminator::Return { value: None });
+
+        let err = backend.compile_function(&function, "f").unwrap_err();
+        assert!(matches!(err, CodegenError::FeatureDisabled(_)));
+        assert_eq!(backend.get_stats().functions_compiled, 0);
+    }
+
+    #[test]
+    fn test_lowers_loops_through_variables() {
+        // let mut acc = 0; while n != 0 { acc += n; n -= 1 } acc
+        let signature = WasmIRSignature { params: vec![WasmIRType::I32], returns: Some(WasmIRType::I32) };
+        let mut function = WasmIR::new("sum".to_string(), signature);
+        let n = function.add_local(WasmIRType::I32);
+        let acc = function.add_local(WasmIRType::I32);
+        let (zero, one) = (Operand::Constant(Constant::I32(0)), Operand::Constant(Constant::I32(1)));
+        function.add_basic_block(vec![], Terminator::Jump { target: BlockId(1) });
+        function.add_basic_block(
+            vec![Instruction::BinaryOp { op: BinaryOp::Ne, left: Operand::Local(n), right: zero }],
+            Terminator::Branch { condition: Operand::StackValue(0), then_block: BlockId(2), else_block: BlockId(3) },
+        );
+        function.add_basic_block(
+            vec![
+                Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::Local(acc), right: Operand::Local(n) },
+                Instruction::LocalSet { index: acc, value: Operand::StackValue(0) },
+                Instruction::BinaryOp { op: BinaryOp::Sub, left: Operand::Local(n), right: one },
+                Instruction::LocalSet { index: n, value: Operand::StackValue(0) },
+            ],
+            Terminator::Jump { target: BlockId(1) },
+        );
+        function.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Local(acc)) });
+
+        let mut backend = WasmRustCraneliftBackend::new().unwrap();
+        let clif = backend.emit_clif(&function).unwrap();
+        // The loop header takes `n` and `acc` as block params
+        assert!(clif.lines().any(|line| line.starts_with("block2(") && line.matches(": i32").count() == 2), "{}", clif);
+        assert!(backend.compile_function(&function, "sum").is_ok());
+    }
+
+    #[test]
+    fn test_lowers_stack_values_in_order() {
+        let params = vec![WasmIRType::F64, WasmIRType::F64];
+        let signature = WasmIRSignature { params, returns: Some(WasmIRType::I64) };
+        let mut function = WasmIR::new("f".to_string(), signature);
+        function.add_local(WasmIRType::F64);
+        function.add_local(WasmIRType::F64);
+        let truncate = ConvertOp::TruncSat { to: WasmIRType::I64, signed: true };
+        let (x, y) = (Operand::StackValue(0), Operand::StackValue(1));
+        function.add_basic_block(
+            vec![
+                Instruction::LocalGet { index: 0 },
+                Instruction::LocalGet { index: 1 },
+                // The first stack value is the deeper one, so this is `x - y`
+                Instruction::BinaryOp { op: BinaryOp::Sub, left: x, right: y },
+                Instruction::Convert { op: truncate, value: Operand::StackValue(0) },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+
+        let mut backend = WasmRustCraneliftBackend::new().unwrap();
+        let clif = backend.emit_clif(&function).unwrap();
+        assert!(clif.contains("fsub") && clif.contains(" v0, v1"), "{}", clif);
+        assert!(clif.contains("fcvt_to_sint_sat.i64"), "{}", clif);
+
+        let mut mismatched = function.clone();
+        mismatched.basic_blocks[0].terminator = Terminator::Return { value: Some(Operand::Local(0)) };
+        assert!(matches!(backend.emit_clif(&mismatched), Err(CodegenError::Verification(_))));
+    }
+
+    #[test]
+    fn test_compiles_to_loadable_wasm() {
+        let signature = WasmIRSignature { params: vec![WasmIRType::I32], returns: Some(WasmIRType::I32) };
+        let mut function = WasmIR::new("double".to_string(), signature);
+        function.add_local(WasmIRType::I32);
+        function.add_basic_block(
+            vec![Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::Local(0), right: Operand::Local(0) }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+
+        let mut backend = WasmRustCraneliftBackend::new().unwrap();
+        let result = backend.compile(&function, BuildProfile::Development).unwrap();
+        assert_eq!(result.code, WasmCodegen::new().compile_function(&function).unwrap());
+        assert_eq!(result.symbols.get("double"), Some(&0));
+        assert_eq!(backend.get_stats().wasm_size, result.code.len());
+
+        backend.reset();
+        assert_eq!(backend.get_stats().functions_compiled, 0);
+    }
+
+    #[test]
+    fn test_backends_share_compiled_functions() {
+        let signature = WasmIRSignature { params: vec![], returns: Some(WasmIRType::I32) };
+        // Named for this test alone, so no other test caches it first
+        let mut function = WasmIR::new("test_backends_share_compiled_functions".to_string(), signature);
+        function.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(Constant::I32(7))) });
+
+        let mut first = WasmRustCraneliftBackend::new().unwrap();
+        let code = first.compile_function(&function, &function.name).unwrap();
+        assert_eq!((first.get_stats().cache_hits, first.get_stats().cache_misses), (0, 1));
+
+        let mut second = WasmRustCraneliftBackend::new().unwrap();
+        assert_eq!(second.compile(&function, BuildProfile::Development).unwrap().code, code);
+        let stats = Backend::stats(&second).unwrap();
+        assert_eq!((stats.cache_hits, stats.cache_misses), (1, 0));
+        assert_eq!(second.get_stats().wasm_size, code.len());
+
+        second.optimization_flags.streaming_layout = false;
+        second.compile_function(&function, &function.name).unwrap();
+        assert_eq!(second.get_stats().cache_misses, 1);
+    }
+
     #[test]
     fn test_optimization_flags() {
         let flags = WasmRustOptimizationFlags::default();
@@ -486,6 +910,13 @@ mod tests {
         assert!(flags.zero_cost_abstractions);
     }
 
+    #[test]
+    fn test_merges_compilation_stats() {
+        let mut total = CompilationStats { cache_hits: 1, wasm_size: 10, ..CompilationStats::default() };
+        total.merge(&CompilationStats { cache_misses: 2, wasm_size: 5, ..CompilationStats::default() });
+        assert_eq!((total.cache_hits, total.cache_misses, total.wasm_size), (1, 2, 15));
+    }
+
     #[test]
     fn test_compilation_stats() {
         let mut stats = CompilationStats::default();
@@ -10,14 +10,20 @@
 //! 4. Enforce the Compiler-Crate Contract for safe optimizations
 
 use wasm::wasmir::{
-    WasmIR, Instruction, Terminator, BasicBlock, BlockId, Type, Signature, Operand, 
+    WasmIR, WasmModule, Instruction, Terminator, BasicBlock, BlockId, Type, Signature, Operand, 
     BinaryOp, UnaryOp, OwnershipState, OwnershipAnnotation, SourceLocation, Capability,
-    Constant, AtomicOp, LinearOp, MemoryOrder, ValidationError
+    Constant, ConvertOp, AtomicOp, LinearOp, MemoryOrder, ValidationError, InteropSignature, InteropType, ElementType
 };
+use super::ownership_check::{elaborate_drops, MirLocation};
+use crate::diagnostics::{codes, Code, Diagnostic, Diagnostics};
+use wasm::memory::layout::{Layout, Primitive, Repr, Shape};
 use std::collections::{HashMap, HashSet};
 
-/// Simulated Rust MIR types for demonstration
-/// In a real implementation, these would come from rustc_middle::mir
+/// Standalone mirror of the MIR subset the lowering handles
+///
+/// Tests and fuzz targets build these by hand; a rustc driver lowers
+/// `rustc_middle::mir::Body` directly with `rustc_lowering`, behind the
+/// `rustc-mir` feature.
 #[derive(Debug, Clone)]
 pub struct MirFunction {
     pub name: String,
@@ -25,6 +31,21 @@ pub struct MirFunction {
     pub basic_blocks: Vec<MirBasicBlock>,
     pub local_decls: Vec<MirLocalDecl>,
     pub source_info: MirSourceInfo,
+    pub attributes: Vec<MirAttribute>,
+}
+
+/// Frontend attributes that affect module-level lowering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirAttribute {
+    /// `#[wasm::ctor]` initializer, run before any export
+    Constructor { priority: u32 },
+    /// Designated module start function
+    Start,
+    /// `Drop::drop` for the type, taking its address; drop glue calls it
+    /// before dropping the fields
+    Destructor(MirType),
+    /// `#[cold]` function, which code splitting may move to a secondary module
+    Cold,
 }
 
 #[derive(Debug, Clone)]
@@ -57,21 +78,97 @@ pub struct MirSpan {
     pub column: u32,
 }
 
-#[derive(Debug, Clone)]
+impl MirSpan {
+    pub fn location(&self) -> SourceLocation {
+        SourceLocation { file: self.filename.clone(), line: self.line, column: self.column }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MirType {
     I32,
     I64,
+    /// `u32` or `usize`, which casts zero-extend
+    U32,
+    U64,
+    /// `i128` or `u128`, split into `i64` halves by the backend
+    I128,
     F32,
     F64,
     Bool,
+    U8,
     Ref(Box<MirType>),
     ExternRef(String),
     FuncRef,
     Array(Box<MirType>, u32),
     Struct(Vec<MirType>),
+    /// `&str`, lowered to a UTF-8 pointer and byte length
+    Str,
+    /// `String`, lowered like `Str`
+    String,
+    /// `&[T]` or `&mut [T]`, lowered to a pointer and element count
+    Slice { element: Box<MirType>, mutable: bool },
+    /// `Vec<T>`, lowered like a slice but owned
+    Vec(Box<MirType>),
+    /// `resource::Own<T>`, an `i32` component resource handle that must be
+    /// dropped or moved exactly once
+    Own(String),
+    /// `resource::Borrow<'_, T>`, a handle lent for the duration of a call
+    Borrow(String),
+    /// A `#[wasm::linear]` type, laid out like the type it wraps but moved
+    /// exactly once and never dropped
+    Linear(Box<MirType>),
     Unit,
 }
 
+impl MirType {
+    /// The type values are laid out as, looking through `Linear`
+    pub fn representation(&self) -> &MirType {
+        match self {
+            MirType::Linear(inner) => inner.representation(),
+            other => other,
+        }
+    }
+
+    /// Checks whether the type is lowered to a pointer and a length
+    pub fn is_memory_pair(&self) -> bool {
+        matches!(self.representation(), MirType::Str | MirType::String | MirType::Slice { .. } | MirType::Vec(_))
+    }
+
+    /// Checks whether the type is stored in linear memory and handled by
+    /// address
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self.representation(), MirType::Struct(_) | MirType::Array(..))
+    }
+
+    /// Checks whether the type is an integer, including `bool`
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            MirType::I32 | MirType::I64 | MirType::I128 | MirType::U8 | MirType::U32 | MirType::U64 | MirType::Bool
+        )
+    }
+
+    /// Checks whether casts from the type sign-extend
+    pub fn is_signed(&self) -> bool {
+        matches!(self, MirType::I32 | MirType::I64 | MirType::I128)
+    }
+
+    /// Checks whether a value of the type can be moved only once
+    pub fn is_linear(&self) -> bool {
+        matches!(
+            self,
+            MirType::ExternRef(_) | MirType::FuncRef | MirType::Vec(_) | MirType::Own(_) | MirType::Linear(_)
+        )
+    }
+
+    /// Checks whether a value of the type must be consumed rather than
+    /// dropped; vectors own only memory, which dropping frees
+    pub fn must_consume(&self) -> bool {
+        self.is_linear() && !matches!(self, MirType::Vec(_))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum MirStatement {
     Assign(MirPlace, MirRvalue),
@@ -96,6 +193,12 @@ pub enum MirTerminator {
     Goto { target: u32 },
     SwitchInt { discr: MirOperand, targets: Vec<(i32, u32)>, otherwise: u32 },
     Call { func: MirOperand, args: Vec<MirOperand>, destination: Option<(MirPlace, u32)> },
+    /// Runs the drop glue of the value in `place`, consuming it
+    Drop { place: MirPlace, target: u32 },
+    /// Continues to `target` if `cond` equals `expected`, panics otherwise
+    Assert { cond: MirOperand, expected: bool, message: String, target: u32 },
+    /// Calls into the panic machinery, e.g. from `panic!`
+    Panic { message: String },
     Unreachable,
 }
 
@@ -123,6 +226,7 @@ pub enum MirOperand {
 pub enum MirConstant {
     I32(i32),
     I64(i64),
+    I128(i128),
     F32(f32),
     F64(f64),
     Bool(bool),
@@ -150,14 +254,59 @@ pub struct MirLoweringContext {
     local_mappings: HashMap<u32, u32>,
     /// Basic block mappings from MIR block index to WasmIR BlockId
     block_mappings: HashMap<u32, BlockId>,
-    /// Error messages collected during lowering
-    error_messages: Vec<String>,
+    /// Diagnostics reported while lowering
+    diagnostics: Diagnostics,
+    /// Source location of the function being lowered
+    function_location: Option<SourceLocation>,
     /// Debug information preservation
     debug_info: HashMap<u32, SourceLocation>,
     /// Ownership tracking for linear types
     ownership_tracker: OwnershipTracker,
     /// Capability requirements detected during lowering
     required_capabilities: HashSet<Capability>,
+    /// WasmIR locals holding string, slice, or vector pointers; the length
+    /// is the next local
+    pair_locals: HashSet<u32>,
+    /// Types of the MIR locals of the function being lowered
+    local_types: Vec<MirType>,
+    /// Locals added for intermediate values, after the declared ones
+    temp_locals: Vec<Type>,
+    /// WasmIR index of the first intermediate local
+    first_temp: u32,
+    /// Frame base local and slot offset of each WasmIR local whose address
+    /// is taken
+    stack_slots: HashMap<u32, (u32, u32)>,
+    /// Messages of the panic blocks appended after the MIR blocks
+    panic_blocks: Vec<String>,
+    /// MIR locals dropped before each statement or terminator, where their
+    /// scope ends
+    scope_drops: HashMap<MirLocation, Vec<u32>>,
+    /// Function index of each type's `Drop::drop`
+    destructors: HashMap<MirType, u32>,
+    /// Types whose drop glue `lower_module` appends after the module's
+    /// functions, in order
+    drop_glue: Vec<MirType>,
+    /// Function index of the first drop glue function
+    glue_base: u32,
+}
+
+/// Where a resolved place is stored
+#[derive(Debug, Clone)]
+enum PlaceAccess {
+    /// A WasmIR local
+    Local(u32),
+    /// Linear memory at `address + offset`
+    Memory { address: Operand, offset: u32 },
+}
+
+impl PlaceAccess {
+    /// Base address and offset of an aggregate; its local holds the address
+    fn address(self) -> (Operand, u32) {
+        match self {
+            PlaceAccess::Local(index) => (Operand::Local(index), 0),
+            PlaceAccess::Memory { address, offset } => (address, offset),
+        }
+    }
 }
 
 /// Tracks ownership states for linear types during MIR lowering
@@ -202,41 +351,88 @@ impl MirLoweringContext {
             current_function: None,
             local_mappings: HashMap::new(),
             block_mappings: HashMap::new(),
-            error_messages: Vec::new(),
+            diagnostics: Diagnostics::new(),
+            function_location: None,
             debug_info: HashMap::new(),
             ownership_tracker: OwnershipTracker::new(),
             required_capabilities: HashSet::new(),
+            pair_locals: HashSet::new(),
+            local_types: Vec::new(),
+            temp_locals: Vec::new(),
+            first_temp: 0,
+            stack_slots: HashMap::new(),
+            panic_blocks: Vec::new(),
+            scope_drops: HashMap::new(),
+            destructors: HashMap::new(),
+            drop_glue: Vec::new(),
+            glue_base: 0,
         }
     }
 
     /// Main entry point for lowering a MIR function to WasmIR
-    pub fn lower_function(&mut self, mir_func: &MirFunction) -> Result<WasmIR, String> {
+    ///
+    /// A failure is also recorded in `diagnostics`.
+    pub fn lower_function(&mut self, mir_func: &MirFunction) -> Result<WasmIR, Diagnostic> {
+        let result = self.try_lower_function(mir_func);
+        if let Err(diagnostic) = &result {
+            self.diagnostics.push(diagnostic.clone());
+        }
+        result
+    }
+
+    fn try_lower_function(&mut self, mir_func: &MirFunction) -> Result<WasmIR, Diagnostic> {
+        self.function_location = Some(mir_func.source_info.span.location());
+
+        // Linear values must be used exactly once; all but the returned
+        // violation are recorded here
+        let (mut violations, drops) = elaborate_drops(mir_func, |ty| self.needs_drop(ty));
+        if let Some(last) = violations.pop() {
+            for diagnostic in violations {
+                self.diagnostics.push(diagnostic);
+            }
+            return Err(last);
+        }
+        for drop in drops {
+            self.scope_drops.entry(drop.at).or_default().push(drop.local);
+        }
+
         // Convert MIR signature to WasmIR signature
         let signature = self.convert_signature(&mir_func.signature)?;
         
         // Create new WasmIR function
         let mut wasmir_func = WasmIR::new(mir_func.name.clone(), signature);
         
+        // A string or vector result is written through a leading return area pointer
+        if mir_func.signature.output.is_memory_pair() {
+            wasmir_func.add_local(Type::I32);
+        }
+        
         // Add local variables
         for (index, local_decl) in mir_func.local_decls.iter().enumerate() {
             let wasmir_type = self.convert_type(&local_decl.ty)?;
             let local_index = wasmir_func.add_local(wasmir_type);
             self.local_mappings.insert(index as u32, local_index);
+            if local_decl.ty.is_memory_pair() {
+                wasmir_func.add_local(Type::I32);
+                self.pair_locals.insert(local_index);
+            }
+            self.local_types.push(local_decl.ty.clone());
             
             // Preserve debug information
-            let source_location = SourceLocation {
-                file: local_decl.source_info.span.filename.clone(),
-                line: local_decl.source_info.span.line,
-                column: local_decl.source_info.span.column,
-            };
+            let source_location = local_decl.source_info.span.location();
             self.debug_info.insert(local_index, source_location.clone());
             
-            // Initialize ownership tracking for linear types
+            // Initialize ownership tracking for linear types; slices and
+            // borrowed handles only borrow what the caller keeps ownership of
             if self.is_linear_type(&local_decl.ty) {
                 self.ownership_tracker.set_ownership(local_index, OwnershipState::Owned, source_location);
+            } else if let MirType::Slice { .. } | MirType::Borrow(_) = local_decl.ty {
+                self.ownership_tracker.set_ownership(local_index, OwnershipState::Borrowed, source_location);
             }
         }
         
+        self.allocate_stack_slots(mir_func, &mut wasmir_func)?;
+        
         // Create block mappings
         for (index, _) in mir_func.basic_blocks.iter().enumerate() {
             let block_id = BlockId(index);
@@ -244,11 +440,20 @@ impl MirLoweringContext {
         }
         
         // Convert basic blocks
+        self.first_temp = wasmir_func.locals.len() as u32;
         for (bb_index, mir_bb) in mir_func.basic_blocks.iter().enumerate() {
-            let instructions = self.convert_statements(&mir_bb.statements)?;
-            let terminator = self.convert_terminator(&mir_bb.terminator)?;
+            let mut instructions = self.convert_statements(bb_index as u32, &mir_bb.statements)?;
+            let at = MirLocation { block: bb_index as u32, statement: mir_bb.statements.len() };
+            self.drop_scope_ends(at, &mut instructions)?;
+            let terminator = self.convert_terminator(&mir_bb.terminator, &mut instructions)?;
             wasmir_func.add_basic_block(instructions, terminator);
         }
+        for message in std::mem::take(&mut self.panic_blocks) {
+            wasmir_func.add_basic_block(Vec::new(), panic(message));
+        }
+        for ty in std::mem::take(&mut self.temp_locals) {
+            wasmir_func.add_local(ty);
+        }
         
         // Add capability annotations
         for capability in &self.required_capabilities {
@@ -262,75 +467,170 @@ impl MirLoweringContext {
         }
         
         // Validate the generated WasmIR
-        wasmir_func.validate().map_err(|e| format!("WasmIR validation failed: {}", e))?;
+        wasmir_func.validate().map_err(|e| {
+            self.error(codes::INVALID_LOWERING, format!("lowered function `{}` is invalid WasmIR: {}", mir_func.name, e))
+                .note("this is a bug in the MIR lowering")
+        })?;
         
         Ok(wasmir_func)
     }
 
+    /// Moves scalar locals whose address is taken into stack frame slots
+    ///
+    /// Their reads and writes then go through memory; parameters are copied
+    /// into their slot on entry. Aggregate locals already hold an address.
+    fn allocate_stack_slots(&mut self, mir_func: &MirFunction, wasmir_func: &mut WasmIR) -> Result<(), Diagnostic> {
+        let taken = address_taken(mir_func);
+        for (local, ty) in self.local_types.clone().iter().enumerate() {
+            if !taken.contains(&(local as u32)) || self.memory_type(ty).is_err() {
+                continue;
+            }
+            let layout = Layout::of(&self.shape(ty)?);
+            // Bytes are stored by rewriting their whole word
+            let offset = wasmir_func.add_stack_slot(layout.size.max(4), layout.align.max(4));
+            let index = self.local_mappings[&(local as u32)];
+            let frame = wasmir_func.frame.as_mut().expect("a slot was just added");
+            if (index as usize) < wasmir_func.signature.params.len() {
+                frame.spills.push((index, offset));
+            }
+            self.stack_slots.insert(index, (frame.base, offset));
+        }
+        Ok(())
+    }
+
     /// Converts MIR signature to WasmIR signature
-    fn convert_signature(&self, mir_sig: &MirSignature) -> Result<Signature, String> {
+    ///
+    /// String, slice, and vector parameters expand to a pointer and a
+    /// length; such a result becomes a leading return area pointer, as
+    /// described by `InteropSignature`.
+    fn convert_signature(&self, mir_sig: &MirSignature) -> Result<Signature, Diagnostic> {
         let mut params = Vec::new();
+        if mir_sig.output.is_memory_pair() {
+            params.push(Type::I32);
+        }
         for input_ty in &mir_sig.inputs {
             params.push(self.convert_type(input_ty)?);
+            if input_ty.is_memory_pair() {
+                params.push(Type::I32);
+            }
         }
         
         let returns = match mir_sig.output {
             MirType::Unit => None,
+            ref output if output.is_memory_pair() => None,
             _ => Some(self.convert_type(&mir_sig.output)?),
         };
         
         Ok(Signature { params, returns })
     }
 
+    /// Describes how a MIR signature crosses the host boundary, if it needs marshaling
+    pub fn interop_signature(&self, mir_sig: &MirSignature) -> Result<Option<InteropSignature>, Diagnostic> {
+        let signature = InteropSignature {
+            params: mir_sig.inputs.iter()
+                .map(|ty| self.interop_type(ty))
+                .collect::<Result<_, _>>()?,
+            returns: self.interop_type(&mir_sig.output)?,
+        };
+        Ok(signature.needs_marshaling().then_some(signature))
+    }
+
+    /// Converts a MIR type to its host boundary representation
+    fn interop_type(&self, mir_ty: &MirType) -> Result<InteropType, Diagnostic> {
+        match mir_ty {
+            MirType::Str | MirType::String => Ok(InteropType::Str),
+            MirType::Slice { element, mutable: false } => Ok(InteropType::Slice(self.element_type(element)?)),
+            MirType::Slice { element, mutable: true } => Ok(InteropType::SliceMut(self.element_type(element)?)),
+            MirType::Vec(element) => Ok(InteropType::Vec(self.element_type(element)?)),
+            _ => Ok(InteropType::Value),
+        }
+    }
+
+    /// Converts a slice or vector element type to a typed array element
+    fn element_type(&self, mir_ty: &MirType) -> Result<ElementType, Diagnostic> {
+        match mir_ty {
+            MirType::U8 | MirType::Bool => Ok(ElementType::U8),
+            MirType::I32 => Ok(ElementType::I32),
+            MirType::U32 => Ok(ElementType::U32),
+            MirType::I64 => Ok(ElementType::I64),
+            MirType::F32 => Ok(ElementType::F32),
+            MirType::F64 => Ok(ElementType::F64),
+            other => Err(
+                self.error(codes::UNSUPPORTED_ELEMENT_TYPE, format!("unsupported slice element type `{:?}`", other))
+                    .help("use `u8`, `bool`, `i32`, `u32`, `i64`, `f32`, or `f64` elements"),
+            ),
+        }
+    }
+
     /// Converts MIR type to WasmIR type
-    fn convert_type(&self, mir_ty: &MirType) -> Result<Type, String> {
+    fn convert_type(&self, mir_ty: &MirType) -> Result<Type, Diagnostic> {
         match mir_ty {
-            MirType::I32 => Ok(Type::I32),
-            MirType::I64 => Ok(Type::I64),
+            MirType::I32 | MirType::U32 => Ok(Type::I32),
+            MirType::I64 | MirType::U64 => Ok(Type::I64),
+            MirType::I128 => Ok(Type::I128),
             MirType::F32 => Ok(Type::F32),
             MirType::F64 => Ok(Type::F64),
-            MirType::Bool => Ok(Type::I32), // Booleans are represented as i32 in WASM
+            MirType::Bool | MirType::U8 => Ok(Type::I32), // Booleans and bytes are represented as i32 in WASM
             MirType::ExternRef(type_name) => Ok(Type::ExternRef(type_name.clone())),
             MirType::FuncRef => Ok(Type::FuncRef),
             MirType::Ref(inner_ty) => {
                 // References become pointers in WASM
                 Ok(Type::Pointer(Box::new(self.convert_type(inner_ty)?)))
             }
+            // Aggregates live in linear memory; their locals hold the address
             MirType::Array(element_ty, size) => {
-                Ok(Type::Array {
+                Ok(Type::Pointer(Box::new(Type::Array {
                     element_type: Box::new(self.convert_type(element_ty)?),
                     size: Some(*size),
-                })
+                })))
             }
             MirType::Struct(field_types) => {
                 let mut fields = Vec::new();
                 for field_ty in field_types {
                     fields.push(self.convert_type(field_ty)?);
                 }
-                Ok(Type::Struct { fields })
+                Ok(Type::Pointer(Box::new(Type::Struct { fields })))
             }
+            // Pointer half of a string, slice, or vector; the length lives
+            // in the next local
+            MirType::Str | MirType::String | MirType::Slice { .. } | MirType::Vec(_) => Ok(Type::I32),
+            MirType::Own(_) | MirType::Borrow(_) => Ok(Type::I32),
+            MirType::Linear(inner) => self.convert_type(inner),
             MirType::Unit => Ok(Type::Void),
         }
     }
 
     /// Checks if a MIR type should be treated as a linear type
     fn is_linear_type(&mut self, mir_ty: &MirType) -> bool {
+        if let MirType::ExternRef(_) = mir_ty {
+            // ExternRef requires JS interop capability
+            self.required_capabilities.insert(Capability::JsInterop);
+        }
+        mir_ty.is_linear()
+    }
+
+    /// Checks whether dropping a value of the type runs any code
+    ///
+    /// Linear values are consumed by their drop, vectors and strings free
+    /// their buffer, and aggregates drop their fields after calling any
+    /// `MirAttribute::Destructor` of theirs.
+    pub fn needs_drop(&self, mir_ty: &MirType) -> bool {
         match mir_ty {
-            MirType::ExternRef(_) => {
-                // ExternRef requires JS interop capability
-                self.required_capabilities.insert(Capability::JsInterop);
-                true
-            },
-            MirType::FuncRef => true,      // FuncRef has linear semantics
-            _ => false,
+            MirType::String => true,
+            MirType::Struct(fields) => {
+                self.destructors.contains_key(mir_ty) || fields.iter().any(|field| self.needs_drop(field))
+            }
+            MirType::Array(element, _) => self.needs_drop(element),
+            other => other.is_linear(),
         }
     }
 
     /// Converts MIR statements to WasmIR instructions
-    fn convert_statements(&mut self, statements: &[MirStatement]) -> Result<Vec<Instruction>, String> {
+    fn convert_statements(&mut self, block: u32, statements: &[MirStatement]) -> Result<Vec<Instruction>, Diagnostic> {
         let mut instructions = Vec::new();
         
-        for statement in statements {
+        for (index, statement) in statements.iter().enumerate() {
+            self.drop_scope_ends(MirLocation { block, statement: index }, &mut instructions)?;
             match statement {
                 MirStatement::Assign(place, rvalue) => {
                     let wasmir_instructions = self.convert_assignment(place, rvalue)?;
@@ -363,17 +663,17 @@ impl MirLoweringContext {
     }
 
     /// Converts a MIR assignment to WasmIR instructions
-    fn convert_assignment(&mut self, place: &MirPlace, rvalue: &MirRvalue) -> Result<Vec<Instruction>, String> {
+    fn convert_assignment(&mut self, place: &MirPlace, rvalue: &MirRvalue) -> Result<Vec<Instruction>, Diagnostic> {
         let mut instructions = Vec::new();
         
         match rvalue {
             MirRvalue::Use(operand) => {
-                let wasmir_operand = self.convert_operand(operand)?;
-                let place_local = self.convert_place_to_local(place)?;
+                let wasmir_operand = self.convert_operand(operand, &mut instructions)?;
+                let place_local = self.place_root(place)?;
                 
                 // Handle ownership transfer for linear types
                 if let MirOperand::Move(moved_place) = operand {
-                    if let Ok(moved_local) = self.convert_place_to_local(moved_place.as_ref()) {
+                    if let Ok(moved_local) = self.place_root(moved_place.as_ref()) {
                         if let Some(debug_info) = self.debug_info.get(&moved_local).cloned() {
                             self.ownership_tracker.set_ownership(moved_local, OwnershipState::Moved, debug_info.clone());
                             self.ownership_tracker.set_ownership(place_local, OwnershipState::Owned, debug_info);
@@ -381,16 +681,22 @@ impl MirLoweringContext {
                     }
                 }
                 
-                instructions.push(Instruction::LocalSet {
-                    index: place_local,
-                    value: wasmir_operand,
-                });
+                let target = self.write_place(place, wasmir_operand.clone(), &mut instructions)?;
+                
+                // Strings, slices, and vectors occupy a pointer and a length local
+                if let (Operand::Local(source), Some(target)) = (wasmir_operand, target) {
+                    if self.pair_locals.contains(&source) && self.pair_locals.contains(&target) {
+                        instructions.push(Instruction::LocalSet {
+                            index: target + 1,
+                            value: Operand::Local(source + 1),
+                        });
+                    }
+                }
             }
             MirRvalue::BinaryOp(op, left, right) => {
                 let wasmir_op = self.convert_binary_op(*op)?;
-                let left_operand = self.convert_operand(left)?;
-                let right_operand = self.convert_operand(right)?;
-                let place_local = self.convert_place_to_local(place)?;
+                let left_operand = self.convert_operand(left, &mut instructions)?;
+                let right_operand = self.convert_operand(right, &mut instructions)?;
                 
                 instructions.push(Instruction::BinaryOp {
                     op: wasmir_op,
@@ -399,66 +705,75 @@ impl MirLoweringContext {
                 });
                 
                 // Store result in place
-                instructions.push(Instruction::LocalSet {
-                    index: place_local,
-                    value: Operand::StackValue(0), // Result of binary op
-                });
+                self.write_place(place, Operand::StackValue(0), &mut instructions)?;
             }
             MirRvalue::UnaryOp(op, operand) => {
                 let wasmir_op = self.convert_unary_op(*op)?;
-                let wasmir_operand = self.convert_operand(operand)?;
-                let place_local = self.convert_place_to_local(place)?;
+                let wasmir_operand = self.convert_operand(operand, &mut instructions)?;
                 
                 instructions.push(Instruction::UnaryOp {
                     op: wasmir_op,
                     value: wasmir_operand,
                 });
                 
-                instructions.push(Instruction::LocalSet {
-                    index: place_local,
-                    value: Operand::StackValue(0),
-                });
+                self.write_place(place, Operand::StackValue(0), &mut instructions)?;
             }
             MirRvalue::Cast(operand, target_ty) => {
-                let wasmir_operand = self.convert_operand(operand)?;
+                let (wasmir_operand, source_ty) = self.typed_operand(operand, &mut instructions)?;
                 let target_type = self.convert_type(target_ty)?;
-                let place_local = self.convert_place_to_local(place)?;
                 
                 // Handle ExternRef casts specially
                 if let Type::ExternRef(_) = target_type {
+                    self.place_root(place)?;
                     self.required_capabilities.insert(Capability::JsInterop);
                     instructions.push(Instruction::ExternRefCast {
                         externref: wasmir_operand,
                         target_type,
                     });
                 } else {
-                    // For now, treat other casts as no-ops or simple moves
-                    instructions.push(Instruction::LocalSet {
-                        index: place_local,
-                        value: wasmir_operand,
-                    });
+                    let value = self.cast(wasmir_operand, &source_ty, target_ty, &mut instructions)?;
+                    self.write_place(place, value, &mut instructions)?;
                 }
             }
             MirRvalue::Ref(operand) => {
-                // Taking a reference - this becomes a pointer in WASM
-                let wasmir_operand = self.convert_operand(operand)?;
-                let place_local = self.convert_place_to_local(place)?;
-                
-                instructions.push(Instruction::LocalSet {
-                    index: place_local,
-                    value: wasmir_operand,
-                });
+                // Taking a reference yields the address of data in linear
+                // memory; locals whose address is taken live in a stack slot
+                let address = match operand {
+                    MirOperand::Copy(borrowed) | MirOperand::Move(borrowed) => {
+                        let (access, _) = self.resolve_place(borrowed, &mut instructions)?;
+                        match access {
+                            PlaceAccess::Memory { address, offset } => self.add_offset(address, offset, &mut instructions),
+                            // Aggregate locals already hold their address;
+                            // references to reference types and pairs pass
+                            // the value
+                            PlaceAccess::Local(local) => Operand::Local(local),
+                        }
+                    }
+                    MirOperand::Constant(_) => self.convert_operand(operand, &mut instructions)?,
+                };
+                self.write_place(place, address, &mut instructions)?;
             }
             MirRvalue::Len(operand) => {
                 // Array/slice length operation
-                let wasmir_operand = self.convert_operand(operand)?;
-                let place_local = self.convert_place_to_local(place)?;
-                
-                // For now, assume length is stored as part of the slice structure
-                instructions.push(Instruction::LocalSet {
-                    index: place_local,
-                    value: wasmir_operand,
-                });
+                let length = match operand {
+                    MirOperand::Copy(borrowed) | MirOperand::Move(borrowed) => {
+                        match self.resolve_place(borrowed, &mut instructions)? {
+                            (_, MirType::Array(_, len)) => Operand::Constant(Constant::I32(len as i32)),
+                            // Pointer/length pairs keep the length in the next local
+                            (PlaceAccess::Local(source), _) if self.pair_locals.contains(&source) => {
+                                Operand::Local(source + 1)
+                            }
+                            (PlaceAccess::Memory { address, offset }, ty) if ty.is_memory_pair() => {
+                                let length = Layout::of(&self.shape(&ty)?).field_offset(1).unwrap_or(4);
+                                self.load(address, offset + length, &MirType::I32, &mut instructions)?
+                            }
+                            // For now, assume other lengths are stored with the value
+                            (access, ty) => self.access_value(access, &ty, &mut instructions)?,
+                        }
+                    }
+                    MirOperand::Constant(_) => self.convert_operand(operand, &mut instructions)?,
+                };
+                self.write_place(place, length, &mut instructions)?;
             }
         }
         
@@ -466,119 +781,571 @@ impl MirLoweringContext {
     }
 
     /// Converts MIR terminator to WasmIR terminator
-    fn convert_terminator(&mut self, terminator: &MirTerminator) -> Result<Terminator, String> {
+    ///
+    /// Instructions computing its operands are appended to `instructions`.
+    fn convert_terminator(&mut self, terminator: &MirTerminator, instructions: &mut Vec<Instruction>) -> Result<Terminator, Diagnostic> {
         match terminator {
             MirTerminator::Return => {
                 Ok(Terminator::Return { value: None })
             }
             MirTerminator::Goto { target } => {
-                let target_block = self.block_mappings.get(target)
-                    .ok_or_else(|| format!("Invalid block target: {}", target))?;
-                Ok(Terminator::Jump { target: *target_block })
+                Ok(Terminator::Jump { target: self.block_target(*target, "jump target")? })
             }
             MirTerminator::SwitchInt { discr, targets, otherwise } => {
-                let condition = self.convert_operand(discr)?;
+                let condition = self.convert_operand(discr, instructions)?;
                 let mut wasmir_targets = Vec::new();
                 
                 for (value, target) in targets {
-                    let target_block = self.block_mappings.get(target)
-                        .ok_or_else(|| format!("Invalid switch target: {}", target))?;
-                    wasmir_targets.push((Operand::Constant(Constant::I32(*value)), *target_block));
+                    let target_block = self.block_target(*target, "switch target")?;
+                    wasmir_targets.push((Operand::Constant(Constant::I32(*value)), target_block));
                 }
                 
-                let default_target = self.block_mappings.get(otherwise)
-                    .ok_or_else(|| format!("Invalid default target: {}", otherwise))?;
+                let default_target = self.block_target(*otherwise, "default target")?;
                 
                 Ok(Terminator::Switch {
                     value: condition,
                     targets: wasmir_targets,
-                    default_target: *default_target,
+                    default_target,
                 })
             }
             MirTerminator::Call { func, args, destination } => {
                 // For now, convert calls to a simplified form
                 // In a real implementation, this would handle function resolution
-                let _func_operand = self.convert_operand(func)?;
+                let _func_operand = self.convert_operand(func, instructions)?;
                 let mut wasmir_args = Vec::new();
                 
                 for arg in args {
-                    wasmir_args.push(self.convert_operand(arg)?);
+                    wasmir_args.push(self.convert_operand(arg, instructions)?);
                 }
                 
                 if let Some((dest_place, target)) = destination {
-                    let _dest_local = self.convert_place_to_local(dest_place)?;
-                    let target_block = self.block_mappings.get(target)
-                        .ok_or_else(|| format!("Invalid call target: {}", target))?;
+                    let _dest_local = self.place_root(dest_place)?;
+                    let target_block = self.block_target(*target, "call return target")?;
                     
                     // For now, just jump to the target block
-                    Ok(Terminator::Jump { target: *target_block })
+                    Ok(Terminator::Jump { target: target_block })
                 } else {
                     Ok(Terminator::Unreachable)
                 }
             }
+            MirTerminator::Drop { place, target } => {
+                let (access, ty) = self.resolve_place(place, instructions)?;
+                self.drop_value(access, &ty, instructions)?;
+                let local = self.place_root(place)?;
+                if let Some(debug_info) = self.debug_info.get(&local).cloned() {
+                    self.ownership_tracker.set_ownership(local, OwnershipState::Consumed, debug_info);
+                }
+                Ok(Terminator::Jump { target: self.block_target(*target, "drop target")? })
+            }
+            MirTerminator::Assert { cond, expected, message, target } => {
+                let condition = self.convert_operand(cond, instructions)?;
+                let target = self.block_target(*target, "assert target")?;
+                let failure = self.panic_block(message);
+                let (then_block, else_block) = if *expected { (target, failure) } else { (failure, target) };
+                Ok(Terminator::Branch { condition, then_block, else_block })
+            }
+            MirTerminator::Panic { message } => {
+                Ok(panic(message.clone()))
+            }
             MirTerminator::Unreachable => {
                 Ok(Terminator::Unreachable)
             }
         }
     }
 
+    /// Block panicking with `message`, shared by all failed asserts with it
+    fn panic_block(&mut self, message: &str) -> BlockId {
+        let position = match self.panic_blocks.iter().position(|existing| existing == message) {
+            Some(position) => position,
+            None => {
+                self.panic_blocks.push(message.to_string());
+                self.panic_blocks.len() - 1
+            }
+        };
+        BlockId(self.block_mappings.len() + position)
+    }
+
     /// Converts MIR operand to WasmIR operand
-    fn convert_operand(&mut self, operand: &MirOperand) -> Result<Operand, String> {
+    ///
+    /// Reads from linear memory are appended to `instructions`.
+    fn convert_operand(&mut self, operand: &MirOperand, instructions: &mut Vec<Instruction>) -> Result<Operand, Diagnostic> {
+        self.typed_operand(operand, instructions).map(|(value, _)| value)
+    }
+
+    /// Converts MIR operand to WasmIR operand along with its MIR type
+    fn typed_operand(&mut self, operand: &MirOperand, instructions: &mut Vec<Instruction>) -> Result<(Operand, MirType), Diagnostic> {
         match operand {
-            MirOperand::Copy(place) => {
-                let local = self.convert_place_to_local(place.as_ref())?;
-                Ok(Operand::Local(local))
-            }
+            MirOperand::Copy(place) => self.read_place(place, instructions),
             MirOperand::Move(place) => {
-                let local = self.convert_place_to_local(place.as_ref())?;
+                let value = self.read_place(place, instructions)?;
+                let local = self.place_root(place.as_ref())?;
                 
                 // Track ownership transfer for linear types
                 if let Some(debug_info) = self.debug_info.get(&local).cloned() {
                     self.ownership_tracker.set_ownership(local, OwnershipState::Moved, debug_info);
                 }
                 
-                Ok(Operand::Local(local))
+                Ok(value)
             }
             MirOperand::Constant(constant) => {
-                let wasmir_constant = self.convert_constant(constant)?;
-                Ok(Operand::Constant(wasmir_constant))
+                let ty = match constant {
+                    MirConstant::I32(_) => MirType::I32,
+                    MirConstant::I64(_) => MirType::I64,
+                    MirConstant::I128(_) => MirType::I128,
+                    MirConstant::F32(_) => MirType::F32,
+                    MirConstant::F64(_) => MirType::F64,
+                    MirConstant::Bool(_) => MirType::Bool,
+                    MirConstant::Unit => MirType::Unit,
+                };
+                Ok((Operand::Constant(self.convert_constant(constant)?), ty))
             }
         }
     }
 
-    /// Converts MIR place to WasmIR local index
-    fn convert_place_to_local(&self, place: &MirPlace) -> Result<u32, String> {
+    /// Lowers an `as` cast between scalar types
+    ///
+    /// Bytes stay zero-extended in their `i32`, and float-to-int casts
+    /// saturate like Rust's, with NaN becoming zero. Other casts between
+    /// types with the same representation, such as pointer casts, are moves.
+    fn cast(&mut self, value: Operand, from: &MirType, to: &MirType, instructions: &mut Vec<Instruction>) -> Result<Operand, Diagnostic> {
+        let is_float = |ty: &MirType| matches!(ty, MirType::F32 | MirType::F64);
+        let source = self.convert_type(from)?;
+        let target = self.convert_type(to)?;
+        let invalid = || {
+            self.error(codes::INVALID_CAST, format!("cannot cast `{:?}` to `{:?}`", from, to))
+        };
+        if !(from.is_integer() || is_float(from)) || !(to.is_integer() || is_float(to)) {
+            return if source == target { Ok(value) } else { Err(invalid()) };
+        }
+        if *to == MirType::Bool && *from != MirType::Bool {
+            return Err(invalid().help("compare with zero instead"));
+        }
+        if source == Type::I128 || target == Type::I128 {
+            return Err(self.error(codes::UNSUPPORTED_MIR, format!("casting `{:?}` to `{:?}` is not supported yet", from, to)));
+        }
+
+        let op = match (is_float(from), is_float(to)) {
+            (false, false) => match (&source, &target) {
+                (Type::I32, Type::I64) => Some(ConvertOp::Extend { signed: from.is_signed() }),
+                (Type::I64, Type::I32) => Some(ConvertOp::Wrap),
+                _ => None,
+            },
+            // Bytes saturate through `u32` and are clamped below
+            (true, false) => Some(ConvertOp::TruncSat { to: target.clone(), signed: to.is_signed() }),
+            (false, true) => Some(ConvertOp::FromInt { to: target.clone(), signed: from.is_signed() }),
+            (true, true) => match (&source, &target) {
+                (Type::F32, Type::F64) => Some(ConvertOp::Promote),
+                (Type::F64, Type::F32) => Some(ConvertOp::Demote),
+                _ => None,
+            },
+        };
+        let value = match op {
+            Some(op) => {
+                let converted = self.temp(target);
+                instructions.push(Instruction::Convert { op, value });
+                instructions.push(Instruction::LocalSet { index: converted, value: Operand::StackValue(0) });
+                Operand::Local(converted)
+            }
+            None => value,
+        };
+        if *to != MirType::U8 || matches!(from, MirType::U8 | MirType::Bool) {
+            return Ok(value);
+        }
+
+        let byte = self.temp(Type::I32);
+        if is_float(from) {
+            // Saturate at 255: set every bit if any above the low byte is
+            instructions.push(Instruction::BinaryOp { op: BinaryOp::Shr, left: value.clone(), right: Operand::Constant(Constant::I32(8)) });
+            instructions.push(Instruction::BinaryOp { op: BinaryOp::Ne, left: Operand::StackValue(0), right: Operand::Constant(Constant::I32(0)) });
+            instructions.push(Instruction::LocalSet { index: byte, value: Operand::StackValue(0) });
+            instructions.push(Instruction::BinaryOp { op: BinaryOp::Sub, left: Operand::Constant(Constant::I32(0)), right: Operand::Local(byte) });
+            instructions.push(Instruction::BinaryOp { op: BinaryOp::Or, left: Operand::StackValue(0), right: value });
+            instructions.push(Instruction::LocalSet { index: byte, value: Operand::StackValue(0) });
+            self.mask(byte, 0xFF, Operand::Local(byte), instructions);
+        } else {
+            self.mask(byte, 0xFF, value, instructions);
+        }
+        Ok(Operand::Local(byte))
+    }
+
+    /// Finds the WasmIR local a place is rooted at
+    fn place_root(&self, place: &MirPlace) -> Result<u32, Diagnostic> {
         match place {
             MirPlace::Local(local) => {
                 self.local_mappings.get(local)
                     .copied()
-                    .ok_or_else(|| format!("Unknown local: {}", local))
-            }
-            MirPlace::Projection(base, projection) => {
-                // For now, handle simple projections
-                match projection.as_ref() {
-                    MirProjection::Deref => {
-                        // Dereference - for now, just return the base
-                        self.convert_place_to_local(base)
-                    }
-                    MirProjection::Field(_field) => {
-                        // Field access - for now, just return the base
-                        self.convert_place_to_local(base)
-                    }
-                    MirProjection::Index(_index) => {
-                        // Array index - for now, just return the base
-                        self.convert_place_to_local(base)
-                    }
+                    .ok_or_else(|| {
+                        self.error(codes::UNKNOWN_LOCAL, format!("unknown local _{}", local))
+                            .note(format!("the function declares {} locals", self.local_mappings.len()))
+                    })
+            }
+            MirPlace::Projection(base, _) => self.place_root(base),
+        }
+    }
+
+    /// Resolves a place to a local or a location in linear memory, along
+    /// with its type
+    ///
+    /// Aggregates live in linear memory, and a local of aggregate type
+    /// holds their address. Field and element offsets come from
+    /// `wasm::memory::layout`; address arithmetic is appended to
+    /// `instructions`.
+    fn resolve_place(&mut self, place: &MirPlace, instructions: &mut Vec<Instruction>) -> Result<(PlaceAccess, MirType), Diagnostic> {
+        let (base, projection) = match place {
+            MirPlace::Local(local) => {
+                let index = self.place_root(place)?;
+                let ty = self.local_types[*local as usize].clone();
+                return Ok(match self.stack_slots.get(&index) {
+                    Some(&(base, offset)) => (PlaceAccess::Memory { address: Operand::Local(base), offset }, ty),
+                    None => (PlaceAccess::Local(index), ty),
+                });
+            }
+            MirPlace::Projection(base, projection) => (base, projection.as_ref()),
+        };
+        let (access, ty) = self.resolve_place(base, instructions)?;
+        let ty = ty.representation().clone();
+        match (projection, &ty) {
+            (MirProjection::Deref, MirType::Ref(pointee)) => {
+                let address = self.access_value(access, &ty, instructions)?;
+                Ok((PlaceAccess::Memory { address, offset: 0 }, pointee.as_ref().clone()))
+            }
+            // Field 1 of a pointer/length pair is its length, in the next local
+            (MirProjection::Field(field), _) if *field <= 1 && ty.is_memory_pair() && matches!(access, PlaceAccess::Local(_)) => {
+                let PlaceAccess::Local(pointer) = access else { unreachable!() };
+                Ok((PlaceAccess::Local(pointer + field), MirType::I32))
+            }
+            (MirProjection::Field(field), _) if ty.is_memory_pair() || matches!(ty, MirType::Struct(_)) => {
+                let layout = Layout::of(&self.shape(&ty)?);
+                let field_offset = layout.field_offset(*field).ok_or_else(|| {
+                    self.error(codes::INVALID_PROJECTION, format!("field {} of `{:?}` does not exist", field, ty))
+                })?;
+                let field_ty = match &ty {
+                    MirType::Struct(fields) => fields[*field as usize].clone(),
+                    _ => MirType::I32,
+                };
+                let (address, offset) = access.address();
+                Ok((PlaceAccess::Memory { address, offset: offset + field_offset }, field_ty))
+            }
+            (MirProjection::Index(index), MirType::Array(element, _)) => {
+                let stride = Layout::of(&self.shape(element)?).size;
+                let (address, offset) = access.address();
+                let index = self.convert_operand(index, instructions)?;
+                let address = self.index_address(address, index, stride, instructions);
+                Ok((PlaceAccess::Memory { address, offset }, element.as_ref().clone()))
+            }
+            (MirProjection::Index(index), MirType::Str | MirType::String | MirType::Slice { .. } | MirType::Vec(_)) => {
+                let element = match &ty {
+                    MirType::Slice { element, .. } | MirType::Vec(element) => element.as_ref().clone(),
+                    _ => MirType::U8,
+                };
+                let stride = Layout::of(&self.shape(&element)?).size;
+                let pointer = match access {
+                    PlaceAccess::Local(pointer) => Operand::Local(pointer),
+                    PlaceAccess::Memory { address, offset } => self.load(address, offset, &MirType::I32, instructions)?,
+                };
+                let index = self.convert_operand(index, instructions)?;
+                let address = self.index_address(pointer, index, stride, instructions);
+                Ok((PlaceAccess::Memory { address, offset: 0 }, element))
+            }
+            _ => Err(self.error(
+                codes::INVALID_PROJECTION,
+                format!("cannot apply `{:?}` to a value of type `{:?}`", projection, ty),
+            )),
+        }
+    }
+
+    /// Reads the value of a place, along with its type
+    fn read_place(&mut self, place: &MirPlace, instructions: &mut Vec<Instruction>) -> Result<(Operand, MirType), Diagnostic> {
+        let (access, ty) = self.resolve_place(place, instructions)?;
+        Ok((self.access_value(access, &ty, instructions)?, ty))
+    }
+
+    /// Writes `value` to a place, returning the local written if it is one
+    fn write_place(&mut self, place: &MirPlace, value: Operand, instructions: &mut Vec<Instruction>) -> Result<Option<u32>, Diagnostic> {
+        match self.resolve_place(place, instructions)? {
+            (PlaceAccess::Local(index), _) => {
+                instructions.push(Instruction::LocalSet { index, value });
+                Ok(Some(index))
+            }
+            (PlaceAccess::Memory { address, offset }, ty) => {
+                self.store(address, offset, &ty, value, instructions)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Value of resolved storage; an aggregate in memory yields its address
+    fn access_value(&mut self, access: PlaceAccess, ty: &MirType, instructions: &mut Vec<Instruction>) -> Result<Operand, Diagnostic> {
+        match access {
+            PlaceAccess::Local(index) => Ok(Operand::Local(index)),
+            PlaceAccess::Memory { address, offset } if ty.is_aggregate() => {
+                Ok(self.add_offset(address, offset, instructions))
+            }
+            PlaceAccess::Memory { address, offset } => self.load(address, offset, ty, instructions),
+        }
+    }
+
+    /// Loads a scalar; bytes are loaded as a word and masked
+    fn load(&mut self, address: Operand, offset: u32, ty: &MirType, instructions: &mut Vec<Instruction>) -> Result<Operand, Diagnostic> {
+        let value_type = self.memory_type(ty)?;
+        instructions.push(Instruction::MemoryLoad { address, ty: value_type.clone(), align: None, offset });
+        let value = self.temp(value_type);
+        instructions.push(Instruction::LocalSet { index: value, value: Operand::StackValue(0) });
+        if matches!(ty.representation(), MirType::Bool | MirType::U8) {
+            self.mask(value, 0xFF, Operand::Local(value), instructions);
+        }
+        Ok(Operand::Local(value))
+    }
+
+    /// Stores a scalar; bytes replace the low byte of the word they are in
+    fn store(&mut self, address: Operand, offset: u32, ty: &MirType, value: Operand, instructions: &mut Vec<Instruction>) -> Result<(), Diagnostic> {
+        if ty.is_aggregate() {
+            return Err(self.error(codes::UNSUPPORTED_MIR, format!("copying a `{:?}` in memory is not supported yet", ty))
+                .help("assign its fields one by one"));
+        }
+        let value_type = self.memory_type(ty)?;
+        let value = match value {
+            // The address must be pushed before the value
+            Operand::StackValue(_) => {
+                let spilled = self.temp(value_type.clone());
+                instructions.push(Instruction::LocalSet { index: spilled, value });
+                Operand::Local(spilled)
+            }
+            value => value,
+        };
+        let value = if matches!(ty.representation(), MirType::Bool | MirType::U8) {
+            let word = self.temp(Type::I32);
+            instructions.push(Instruction::MemoryLoad { address: address.clone(), ty: Type::I32, align: None, offset });
+            instructions.push(Instruction::LocalSet { index: word, value: Operand::StackValue(0) });
+            self.mask(word, !0xFF, Operand::Local(word), instructions);
+            let byte = self.temp(Type::I32);
+            self.mask(byte, 0xFF, value, instructions);
+            instructions.push(Instruction::BinaryOp { op: BinaryOp::Or, left: Operand::Local(word), right: Operand::Local(byte) });
+            instructions.push(Instruction::LocalSet { index: word, value: Operand::StackValue(0) });
+            Operand::Local(word)
+        } else {
+            value
+        };
+        instructions.push(Instruction::MemoryStore { address, value, ty: value_type, align: None, offset });
+        Ok(())
+    }
+
+    /// Sets `target` to `value & mask`
+    fn mask(&self, target: u32, mask: i32, value: Operand, instructions: &mut Vec<Instruction>) {
+        instructions.push(Instruction::BinaryOp { op: BinaryOp::And, left: value, right: Operand::Constant(Constant::I32(mask)) });
+        instructions.push(Instruction::LocalSet { index: target, value: Operand::StackValue(0) });
+    }
+
+    /// Adds a constant offset to an address
+    fn add_offset(&mut self, address: Operand, offset: u32, instructions: &mut Vec<Instruction>) -> Operand {
+        if offset == 0 {
+            return address;
+        }
+        let sum = self.temp(Type::I32);
+        instructions.push(Instruction::BinaryOp { op: BinaryOp::Add, left: address, right: Operand::Constant(Constant::I32(offset as i32)) });
+        instructions.push(Instruction::LocalSet { index: sum, value: Operand::StackValue(0) });
+        Operand::Local(sum)
+    }
+
+    /// Computes `address + index * stride`
+    fn index_address(&mut self, address: Operand, index: Operand, stride: u32, instructions: &mut Vec<Instruction>) -> Operand {
+        let element = self.temp(Type::I32);
+        instructions.push(Instruction::BinaryOp { op: BinaryOp::Mul, left: index, right: Operand::Constant(Constant::I32(stride as i32)) });
+        instructions.push(Instruction::LocalSet { index: element, value: Operand::StackValue(0) });
+        instructions.push(Instruction::BinaryOp { op: BinaryOp::Add, left: address, right: Operand::Local(element) });
+        instructions.push(Instruction::LocalSet { index: element, value: Operand::StackValue(0) });
+        Operand::Local(element)
+    }
+
+    /// Adds a local for an intermediate value
+    fn temp(&mut self, ty: Type) -> u32 {
+        self.temp_locals.push(ty);
+        self.first_temp + self.temp_locals.len() as u32 - 1
+    }
+
+    /// Type a scalar is loaded and stored as
+    fn memory_type(&self, ty: &MirType) -> Result<Type, Diagnostic> {
+        match ty.representation() {
+            MirType::I64 | MirType::U64 => Ok(Type::I64),
+            MirType::I128 => Ok(Type::I128),
+            MirType::F32 => Ok(Type::F32),
+            MirType::F64 => Ok(Type::F64),
+            MirType::I32 | MirType::U32 | MirType::Bool | MirType::U8 | MirType::Ref(_) | MirType::Own(_) | MirType::Borrow(_) => Ok(Type::I32),
+            other => Err(self.error(codes::UNSUPPORTED_MIR, format!("`{:?}` values cannot be stored in linear memory", other))),
+        }
+    }
+
+    /// Memory shape of a type, for layout
+    fn shape(&self, ty: &MirType) -> Result<Shape, Diagnostic> {
+        Ok(match ty {
+            MirType::I32 => Shape::Primitive(Primitive::I32),
+            MirType::I64 => Shape::Primitive(Primitive::I64),
+            MirType::U32 => Shape::Primitive(Primitive::U32),
+            MirType::U64 => Shape::Primitive(Primitive::U64),
+            MirType::I128 => Shape::Primitive(Primitive::I128),
+            MirType::F32 => Shape::Primitive(Primitive::F32),
+            MirType::F64 => Shape::Primitive(Primitive::F64),
+            MirType::Bool => Shape::Primitive(Primitive::Bool),
+            MirType::U8 => Shape::Primitive(Primitive::U8),
+            MirType::Ref(_) => Shape::Primitive(Primitive::NonNullPointer),
+            MirType::Own(_) | MirType::Borrow(_) => Shape::Primitive(Primitive::U32),
+            MirType::Linear(inner) => self.shape(inner)?,
+            MirType::Array(element, len) => Shape::Array { element: Box::new(self.shape(element)?), len: *len },
+            MirType::Struct(fields) => Shape::Struct {
+                fields: fields.iter().map(|field| self.shape(field)).collect::<Result<_, _>>()?,
+                repr: Repr::Rust,
+            },
+            // The pointer, then the length
+            MirType::Str | MirType::String | MirType::Slice { .. } | MirType::Vec(_) => Shape::Struct {
+                fields: vec![Shape::Primitive(Primitive::NonNullPointer), Shape::Primitive(Primitive::U32)],
+                repr: Repr::C,
+            },
+            MirType::Unit => Shape::Tuple(Vec::new()),
+            MirType::ExternRef(_) | MirType::FuncRef => {
+                return Err(self.error(codes::UNSUPPORTED_MIR, format!("`{:?}` values cannot be stored in linear memory", ty)));
+            }
+        })
+    }
+
+    /// Drops the locals whose scope ends at `at`
+    fn drop_scope_ends(&mut self, at: MirLocation, instructions: &mut Vec<Instruction>) -> Result<(), Diagnostic> {
+        for local in self.scope_drops.remove(&at).unwrap_or_default() {
+            let (access, ty) = self.resolve_place(&MirPlace::Local(local), instructions)?;
+            self.drop_value(access, &ty, instructions)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the value in resolved storage
+    fn drop_value(&mut self, access: PlaceAccess, ty: &MirType, instructions: &mut Vec<Instruction>) -> Result<(), Diagnostic> {
+        if !self.needs_drop(ty) {
+            return Ok(());
+        }
+        match ty {
+            MirType::Vec(element) if self.needs_drop(element) => {
+                return Err(self.error(codes::UNSUPPORTED_MIR, format!("dropping the elements of `{:?}` is not supported yet", ty)));
+            }
+            // The buffer pointer comes first
+            MirType::Vec(_) | MirType::String => {
+                let address = match access {
+                    PlaceAccess::Local(pointer) => Operand::Local(pointer),
+                    PlaceAccess::Memory { address, offset } => self.load(address, offset, &MirType::I32, instructions)?,
+                };
+                instructions.push(Instruction::MemoryFree { address });
+            }
+            MirType::Struct(_) | MirType::Array(..) => {
+                let (address, offset) = access.address();
+                let address = self.add_offset(address, offset, instructions);
+                let func_ref = self.glue_index(ty);
+                instructions.push(Instruction::Call { func_ref, args: vec![address] });
+            }
+            _ => {
+                let value = self.access_value(access, ty, instructions)?;
+                instructions.push(Instruction::LinearOp { op: LinearOp::Drop, value });
+            }
+        }
+        Ok(())
+    }
+
+    /// Function index of the drop glue of an aggregate, requesting it on
+    /// first use
+    fn glue_index(&mut self, ty: &MirType) -> u32 {
+        let position = match self.drop_glue.iter().position(|glue| glue == ty) {
+            Some(position) => position,
+            None => {
+                self.drop_glue.push(ty.clone());
+                self.drop_glue.len() - 1
+            }
+        };
+        self.glue_base + position as u32
+    }
+
+    /// Builds the drop glue of an aggregate, which takes its address
+    ///
+    /// Struct glue calls the destructor and then drops each field; array
+    /// glue drops the elements in a loop.
+    fn lower_drop_glue(&mut self, ty: &MirType) -> Result<WasmIR, Diagnostic> {
+        let signature = Signature { params: vec![Type::I32], returns: None };
+        let mut glue = WasmIR::new(format!("drop_in_place::<{:?}>", ty), signature);
+        let address = glue.add_local(Type::I32);
+        match ty {
+            MirType::Array(element, len) => {
+                let index = glue.add_local(Type::I32);
+                self.first_temp = glue.locals.len() as u32;
+                let stride = Layout::of(&self.shape(element)?).size;
+
+                // for index in 0..len { drop(address[index]) }
+                glue.add_basic_block(
+                    vec![Instruction::LocalSet { index, value: Operand::Constant(Constant::I32(0)) }],
+                    Terminator::Jump { target: BlockId(1) },
+                );
+                glue.add_basic_block(
+                    vec![Instruction::BinaryOp {
+                        op: BinaryOp::Lt,
+                        left: Operand::Local(index),
+                        right: Operand::Constant(Constant::I32(*len as i32)),
+                    }],
+                    Terminator::Branch { condition: Operand::StackValue(0), then_block: BlockId(2), else_block: BlockId(3) },
+                );
+                let mut body = Vec::new();
+                let element_address = self.index_address(Operand::Local(address), Operand::Local(index), stride, &mut body);
+                self.drop_value(PlaceAccess::Memory { address: element_address, offset: 0 }, element, &mut body)?;
+                body.push(Instruction::BinaryOp {
+                    op: BinaryOp::Add,
+                    left: Operand::Local(index),
+                    right: Operand::Constant(Constant::I32(1)),
+                });
+                body.push(Instruction::LocalSet { index, value: Operand::StackValue(0) });
+                glue.add_basic_block(body, Terminator::Jump { target: BlockId(1) });
+                glue.add_basic_block(Vec::new(), Terminator::Return { value: None });
+            }
+            MirType::Struct(fields) => {
+                self.first_temp = glue.locals.len() as u32;
+                let mut body = Vec::new();
+                if let Some(&func_ref) = self.destructors.get(ty) {
+                    body.push(Instruction::Call { func_ref, args: vec![Operand::Local(address)] });
                 }
+                let layout = Layout::of(&self.shape(ty)?);
+                for (field, field_ty) in fields.iter().enumerate() {
+                    let offset = layout.field_offset(field as u32).unwrap_or(0);
+                    self.drop_value(PlaceAccess::Memory { address: Operand::Local(address), offset }, field_ty, &mut body)?;
+                }
+                glue.add_basic_block(body, Terminator::Return { value: None });
+            }
+            other => {
+                return Err(self.error(codes::INVALID_LOWERING, format!("`{:?}` has no drop glue", other))
+                    .note("this is a bug in the MIR lowering"));
             }
         }
+        for temp in std::mem::take(&mut self.temp_locals) {
+            glue.add_local(temp);
+        }
+        Ok(glue)
+    }
+
+    /// Resolves a MIR basic block index
+    fn block_target(&self, target: u32, kind: &str) -> Result<BlockId, Diagnostic> {
+        self.block_mappings.get(&target).copied().ok_or_else(|| {
+            self.error(codes::INVALID_BLOCK_TARGET, format!("{} bb{} does not exist", kind, target))
+                .note(format!("the function has {} basic blocks", self.block_mappings.len()))
+        })
+    }
+
+    /// Error located at the function being lowered
+    fn error(&self, code: Code, message: String) -> Diagnostic {
+        let diagnostic = Diagnostic::error(code, message);
+        match &self.function_location {
+            Some(location) => diagnostic.primary(location.clone(), "in this function"),
+            None => diagnostic,
+        }
     }
 
     /// Converts MIR constant to WasmIR constant
-    fn convert_constant(&self, constant: &MirConstant) -> Result<Constant, String> {
+    fn convert_constant(&self, constant: &MirConstant) -> Result<Constant, Diagnostic> {
         match constant {
             MirConstant::I32(value) => Ok(Constant::I32(*value)),
             MirConstant::I64(value) => Ok(Constant::I64(*value)),
+            MirConstant::I128(value) => Ok(Constant::I128(*value)),
             MirConstant::F32(value) => Ok(Constant::F32(*value)),
             MirConstant::F64(value) => Ok(Constant::F64(*value)),
             MirConstant::Bool(value) => Ok(Constant::Boolean(*value)),
@@ -587,7 +1354,7 @@ impl MirLoweringContext {
     }
 
     /// Converts MIR binary operation to WasmIR binary operation
-    fn convert_binary_op(&self, op: MirBinOp) -> Result<BinaryOp, String> {
+    fn convert_binary_op(&self, op: MirBinOp) -> Result<BinaryOp, Diagnostic> {
         match op {
             MirBinOp::Add => Ok(BinaryOp::Add),
             MirBinOp::Sub => Ok(BinaryOp::Sub),
@@ -609,13 +1376,106 @@ impl MirLoweringContext {
     }
 
     /// Converts MIR unary operation to WasmIR unary operation
-    fn convert_unary_op(&self, op: MirUnOp) -> Result<UnaryOp, String> {
+    fn convert_unary_op(&self, op: MirUnOp) -> Result<UnaryOp, Diagnostic> {
         match op {
             MirUnOp::Not => Ok(UnaryOp::Not),
             MirUnOp::Neg => Ok(UnaryOp::Neg),
         }
     }
 
+    /// Lowers a set of MIR functions into a WasmIR module
+    ///
+    /// Functions marked with `MirAttribute::Constructor` are registered as
+    /// module initializers, and `MirAttribute::Start` designates the start
+    /// function. Initializers of `lazy_static`-style globals are expected
+    /// to arrive here as constructors so they run before exports. Drop glue
+    /// for the aggregates the functions drop is appended after them.
+    /// `MirAttribute::Cold` functions are marked cold.
+    ///
+    /// A failure is also recorded in `diagnostics`.
+    pub fn lower_module(&mut self, functions: &[MirFunction]) -> Result<WasmModule, Diagnostic> {
+        let result = self.try_lower_module(functions);
+        if let Err(diagnostic) = &result {
+            self.diagnostics.push(diagnostic.clone());
+        }
+        result
+    }
+
+    fn try_lower_module(&mut self, functions: &[MirFunction]) -> Result<WasmModule, Diagnostic> {
+        let mut module = WasmModule::new();
+        let mut start: Option<&MirFunction> = None;
+
+        // Destructors are called from drop glue, which follows the functions
+        self.drop_glue.clear();
+        self.glue_base = functions.len() as u32;
+        self.destructors = functions.iter()
+            .enumerate()
+            .flat_map(|(index, mir_func)| mir_func.attributes.iter().filter_map(move |attribute| match attribute {
+                MirAttribute::Destructor(ty) => Some((ty.clone(), index as u32)),
+                _ => None,
+            }))
+            .collect();
+
+        for mir_func in functions {
+            self.reset_function_state();
+            let wasmir_func = self.try_lower_function(mir_func)?;
+            let index = module.add_function(wasmir_func);
+            if let Some(interop) = self.interop_signature(&mir_func.signature)? {
+                module.set_interop_signature(index, interop);
+            }
+
+            for attribute in &mir_func.attributes {
+                match attribute {
+                    MirAttribute::Constructor { priority } => module.add_constructor(index, *priority),
+                    MirAttribute::Start => {
+                        if let Some(first) = start {
+                            return Err(Diagnostic::error(codes::MULTIPLE_START_FUNCTIONS, "multiple start functions")
+                                .primary(mir_func.source_info.span.location(), format!("`{}` is also a start function", mir_func.name))
+                                .secondary(first.source_info.span.location(), format!("`{}` is the first", first.name))
+                                .note("a module has at most one start function")
+                                .help("mark the others as constructors instead"));
+                        }
+                        start = Some(mir_func);
+                        module.set_start_function(index);
+                    }
+                    MirAttribute::Cold => module.mark_cold(index),
+                    MirAttribute::Destructor(_) => {}
+                }
+            }
+        }
+
+        // Glue may request the glue of nested aggregates
+        let mut next = 0;
+        while let Some(ty) = self.drop_glue.get(next).cloned() {
+            self.reset_function_state();
+            module.add_function(self.lower_drop_glue(&ty)?);
+            next += 1;
+        }
+
+        module.validate().map_err(|e| {
+            Diagnostic::error(codes::INVALID_LOWERING, format!("lowered module is invalid WasmIR: {}", e))
+                .note("this is a bug in the MIR lowering")
+        })?;
+        Ok(module)
+    }
+
+    /// Clears per-function lowering state
+    fn reset_function_state(&mut self) {
+        self.local_mappings.clear();
+        self.block_mappings.clear();
+        self.debug_info.clear();
+        self.ownership_tracker = OwnershipTracker::new();
+        self.required_capabilities.clear();
+        self.pair_locals.clear();
+        self.local_types.clear();
+        self.temp_locals.clear();
+        self.first_temp = 0;
+        self.stack_slots.clear();
+        self.panic_blocks.clear();
+        self.scope_drops.clear();
+        self.function_location = None;
+    }
+
     /// Creates a simple WasmIR function for testing
     pub fn create_simple_function(&mut self, name: String) -> WasmIR {
         let signature = Signature {
@@ -646,14 +1506,14 @@ impl MirLoweringContext {
         wasm_func
     }
 
-    /// Checks if there are any errors
+    /// Checks if any error was reported
     pub fn has_errors(&self) -> bool {
-        !self.error_messages.is_empty()
+        self.diagnostics.has_errors()
     }
 
-    /// Gets error messages
-    pub fn get_errors(&self) -> &[String] {
-        &self.error_messages
+    /// Gets the diagnostics reported so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
     }
 
     /// Converts the context into a WasmIR function
@@ -688,9 +1548,9 @@ impl MirLoweringContext {
         }
     }
 
-    /// Adds an error message
-    pub fn add_error(&mut self, message: String) {
-        self.error_messages.push(message);
+    /// Reports a diagnostic
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
     }
 
     /// Sets the current function
@@ -699,6 +1559,25 @@ impl MirLoweringContext {
     }
 }
 
+/// Panic terminator carrying a compiled message
+/// MIR locals whose address is taken by a `Ref` of the whole local
+fn address_taken(mir_func: &MirFunction) -> HashSet<u32> {
+    mir_func.basic_blocks.iter()
+        .flat_map(|block| &block.statements)
+        .filter_map(|statement| match statement {
+            MirStatement::Assign(_, MirRvalue::Ref(MirOperand::Copy(place) | MirOperand::Move(place))) => match place.as_ref() {
+                MirPlace::Local(local) => Some(*local),
+                MirPlace::Projection(..) => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn panic(message: String) -> Terminator {
+    Terminator::Panic { message: Some(Operand::Constant(Constant::String(message))) }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -707,7 +1586,7 @@ mod tests {
     fn test_mir_lowering_context_creation() {
         let context = MirLoweringContext::new();
         assert!(!context.has_errors());
-        assert_eq!(context.get_errors().len(), 0);
+        assert!(context.diagnostics().is_empty());
         assert_eq!(context.local_mappings.len(), 0);
         assert_eq!(context.block_mappings.len(), 0);
     }
@@ -739,10 +1618,18 @@ mod tests {
         let mut context = MirLoweringContext::new();
         assert!(!context.has_errors());
         
-        context.add_error("Test error".to_string());
+        context.report(Diagnostic::new(crate::diagnostics::Severity::Warning, "Test warning"));
+        assert!(!context.has_errors());
+
+        // Failed lowerings are recorded with a code and the function's span
+        let mut mir_func = unit_function("broken", Vec::new());
+        mir_func.basic_blocks[0].terminator = MirTerminator::Goto { target: 4 };
+        let error = context.lower_function(&mir_func).unwrap_err();
         assert!(context.has_errors());
-        assert_eq!(context.get_errors().len(), 1);
-        assert_eq!(context.get_errors()[0], "Test error");
+        assert_eq!(context.diagnostics().len(), 2);
+        assert_eq!(error.code, Some(codes::INVALID_BLOCK_TARGET));
+        assert_eq!(error.to_string(), "test.rs:1:1: error[WR0002]: jump target bb4 does not exist");
+        assert_eq!(error.notes, vec!["the function has 1 basic blocks".to_string()]);
     }
 
     #[test]
@@ -830,6 +1717,8 @@ mod tests {
         // Linear types
         assert!(context.is_linear_type(&MirType::ExternRef("JsObject".to_string())));
         assert!(context.is_linear_type(&MirType::FuncRef));
+        let canvas = MirType::Linear(Box::new(MirType::Struct(vec![MirType::U32, MirType::F64])));
+        assert!(context.is_linear_type(&canvas) && canvas.must_consume() && canvas.is_aggregate());
         
         // Non-linear types
         assert!(!context.is_linear_type(&MirType::I32));
@@ -930,6 +1819,7 @@ mod tests {
                     column: 1,
                 },
             },
+            attributes: Vec::new(),
         };
         
         // Lower the MIR function to WasmIR
@@ -966,6 +1856,15 @@ mod tests {
                             MirRvalue::Use(MirOperand::Move(Box::new(MirPlace::Local(0)))), // move the ExternRef
                         ),
                     ],
+                    // Pass the ExternRef on, consuming it
+                    terminator: MirTerminator::Call {
+                        func: MirOperand::Constant(MirConstant::Unit),
+                        args: vec![MirOperand::Move(Box::new(MirPlace::Local(1)))],
+                        destination: Some((MirPlace::Local(2), 1)),
+                    },
+                },
+                MirBasicBlock {
+                    statements: vec![],
                     terminator: MirTerminator::Return,
                 },
             ],
@@ -990,6 +1889,16 @@ mod tests {
                         },
                     },
                 },
+                MirLocalDecl {
+                    ty: MirType::I32,
+                    source_info: MirSourceInfo {
+                        span: MirSpan {
+                            filename: "test.rs".to_string(),
+                            line: 3,
+                            column: 10,
+                        },
+                    },
+                },
             ],
             source_info: MirSourceInfo {
                 span: MirSpan {
@@ -998,6 +1907,7 @@ mod tests {
                     column: 1,
                 },
             },
+            attributes: Vec::new(),
         };
         
         // Lower the MIR function to WasmIR
@@ -1018,4 +1928,396 @@ mod tests {
         // Validate the function
         assert!(wasmir_func.validate().is_ok());
     }
-}
\ No newline at end of file
+
+    fn unit_function(name: &str, attributes: Vec<MirAttribute>) -> MirFunction {
+        let span = MirSpan {
+            filename: "test.rs".to_string(),
+            line: 1,
+            column: 1,
+        };
+        MirFunction {
+            name: name.to_string(),
+            signature: MirSignature {
+                inputs: vec![],
+                output: MirType::Unit,
+            },
+            basic_blocks: vec![MirBasicBlock {
+                statements: vec![MirStatement::Nop],
+                terminator: MirTerminator::Return,
+            }],
+            local_decls: vec![],
+            source_info: MirSourceInfo { span },
+            attributes,
+        }
+    }
+
+    #[test]
+    fn test_lower_module_registers_initializers() {
+        let mut context = MirLoweringContext::new();
+        let functions = vec![
+            unit_function("init_logger", vec![MirAttribute::Constructor { priority: 20 }]),
+            unit_function("init_statics", vec![MirAttribute::Constructor { priority: 10 }]),
+            unit_function("main", vec![MirAttribute::Start]),
+            unit_function("report", vec![MirAttribute::Cold]),
+        ];
+
+        let module = context.lower_module(&functions).unwrap();
+        assert_eq!(module.functions.len(), 4);
+        assert_eq!(module.constructors_in_order(), vec![1, 0]);
+        assert_eq!(module.start_function, Some(2));
+        assert_eq!(module.cold_functions, vec![3]);
+
+        let duplicate = vec![
+            unit_function("a", vec![MirAttribute::Start]),
+            unit_function("b", vec![MirAttribute::Start]),
+        ];
+        let error = context.lower_module(&duplicate).unwrap_err();
+        assert_eq!(error.code, Some(codes::MULTIPLE_START_FUNCTIONS));
+        assert_eq!(error.primary_span().unwrap().label.as_deref(), Some("`b` is also a start function"));
+        assert_eq!(error.secondary_spans().next().unwrap().label.as_deref(), Some("`a` is the first"));
+    }
+
+    #[test]
+    fn test_string_signature_lowering() {
+        let mut context = MirLoweringContext::new();
+        let mut mir_func = unit_function("greet", Vec::new());
+        mir_func.signature = MirSignature {
+            inputs: vec![MirType::Str],
+            output: MirType::String,
+        };
+        let decl = |ty| MirLocalDecl { ty, source_info: mir_func.source_info.clone() };
+        mir_func.local_decls = vec![decl(MirType::Str), decl(MirType::I32)];
+        mir_func.basic_blocks[0].statements = vec![MirStatement::Assign(
+            MirPlace::Local(1),
+            MirRvalue::Len(MirOperand::Copy(Box::new(MirPlace::Local(0)))),
+        )];
+
+        let module = context.lower_module(&[mir_func]).unwrap();
+        let function = &module.functions[0];
+
+        // Return area pointer, then the string pointer and length
+        assert_eq!(function.signature.params, vec![Type::I32, Type::I32, Type::I32]);
+        assert_eq!(function.signature.returns, None);
+        assert_eq!(function.locals.len(), 4);
+        assert!(matches!(
+            function.basic_blocks[0].instructions[0],
+            Instruction::LocalSet { index: 3, value: Operand::Local(2) }
+        ));
+
+        let interop = module.interop_signature(0).unwrap();
+        assert_eq!(interop.params, vec![InteropType::Str]);
+        assert_eq!(interop.returns, InteropType::Str);
+    }
+
+    #[test]
+    fn test_slice_and_vec_ownership() {
+        let mut context = MirLoweringContext::new();
+        let mut mir_func = unit_function("fill", Vec::new());
+        let bytes = MirType::Slice { element: Box::new(MirType::U8), mutable: false };
+        let samples = MirType::Vec(Box::new(MirType::F32));
+        mir_func.signature = MirSignature {
+            inputs: vec![bytes.clone(), samples.clone()],
+            output: MirType::Unit,
+        };
+        let decl = |ty| MirLocalDecl { ty, source_info: mir_func.source_info.clone() };
+        mir_func.local_decls = vec![decl(bytes), decl(samples)];
+
+        let function = context.lower_function(&mir_func).unwrap();
+        assert_eq!(function.signature.params.len(), 4);
+
+        // The slice is borrowed from the caller; the vector is owned
+        let states: Vec<_> = function.ownership_annotations.iter()
+            .map(|annotation| (annotation.variable, annotation.state))
+            .collect();
+        assert_eq!(states, vec![(0, OwnershipState::Borrowed), (2, OwnershipState::Owned)]);
+
+        let interop = context.interop_signature(&mir_func.signature).unwrap().unwrap();
+        assert_eq!(interop.params, vec![
+            InteropType::Slice(ElementType::U8),
+            InteropType::Vec(ElementType::F32),
+        ]);
+
+        let nested = MirSignature {
+            inputs: vec![MirType::Vec(Box::new(MirType::String))],
+            output: MirType::Unit,
+        };
+        assert!(context.interop_signature(&nested).is_err());
+    }
+
+    #[test]
+    fn test_resource_handle_ownership() {
+        let mut context = MirLoweringContext::new();
+        let mut mir_func = unit_function("merge", Vec::new());
+        let owned = MirType::Own("blob".to_string());
+        let borrowed = MirType::Borrow("blob".to_string());
+        mir_func.signature = MirSignature {
+            inputs: vec![owned.clone(), borrowed.clone()],
+            output: MirType::Unit,
+        };
+        let decl = |ty| MirLocalDecl { ty, source_info: mir_func.source_info.clone() };
+        mir_func.local_decls = vec![decl(owned), decl(borrowed), decl(MirType::I32)];
+
+        // The owned handle must be consumed, here by passing it on
+        let unconsumed = context.lower_function(&mir_func).unwrap_err();
+        assert_eq!(unconsumed.code, Some(codes::UNCONSUMED_LINEAR_VALUE));
+        assert_eq!(unconsumed.message, "linear value `_0` is dropped on return at bb0[1] without being consumed");
+        mir_func.basic_blocks.insert(0, MirBasicBlock {
+            statements: vec![],
+            terminator: MirTerminator::Call {
+                func: MirOperand::Constant(MirConstant::Unit),
+                args: vec![MirOperand::Move(Box::new(MirPlace::Local(0)))],
+                destination: Some((MirPlace::Local(2), 1)),
+            },
+        });
+
+        context.reset_function_state();
+        let function = context.lower_function(&mir_func).unwrap();
+        assert_eq!(function.signature.params, vec![Type::I32, Type::I32]);
+        let states: Vec<_> = function.ownership_annotations.iter()
+            .map(|annotation| (annotation.variable, annotation.state))
+            .collect();
+        assert_eq!(states, vec![(0, OwnershipState::Owned), (1, OwnershipState::Borrowed), (0, OwnershipState::Moved)]);
+        assert!(context.interop_signature(&mir_func.signature).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_projections_lower_to_memory_accesses() {
+        let mut context = MirLoweringContext::new();
+        let mut mir_func = unit_function("project", Vec::new());
+        let pair = MirType::Struct(vec![MirType::Bool, MirType::I32]);
+        let array = MirType::Ref(Box::new(MirType::Array(Box::new(MirType::I64), 4)));
+        let decl = |ty| MirLocalDecl { ty, source_info: mir_func.source_info.clone() };
+        mir_func.local_decls = vec![decl(pair), decl(MirType::I32), decl(array), decl(MirType::I64)];
+
+        let field = |local, field| MirPlace::Projection(
+            Box::new(MirPlace::Local(local)),
+            Box::new(MirProjection::Field(field)),
+        );
+        let element = MirPlace::Projection(
+            Box::new(MirPlace::Projection(Box::new(MirPlace::Local(2)), Box::new(MirProjection::Deref))),
+            Box::new(MirProjection::Index(Box::new(MirOperand::Copy(Box::new(MirPlace::Local(1)))))),
+        );
+        mir_func.basic_blocks[0].statements = vec![
+            // The `i32` is laid out first, then the `bool`
+            MirStatement::Assign(MirPlace::Local(1), MirRvalue::Use(MirOperand::Copy(Box::new(field(0, 1))))),
+            MirStatement::Assign(field(0, 0), MirRvalue::Use(MirOperand::Constant(MirConstant::Bool(true)))),
+            MirStatement::Assign(MirPlace::Local(3), MirRvalue::Use(MirOperand::Copy(Box::new(element)))),
+        ];
+
+        let function = context.lower_function(&mir_func).unwrap();
+        assert!(matches!(function.locals[0], Type::Pointer(_)));
+        let accesses: Vec<_> = function.basic_blocks[0].instructions.iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::MemoryLoad { ty, offset, .. } => Some(("load", ty.clone(), *offset)),
+                Instruction::MemoryStore { ty, offset, .. } => Some(("store", ty.clone(), *offset)),
+                _ => None,
+            })
+            .collect();
+        // Storing the `bool` rewrites the low byte of its word
+        assert_eq!(accesses, vec![
+            ("load", Type::I32, 0),
+            ("load", Type::I32, 4),
+            ("store", Type::I32, 4),
+            ("load", Type::I64, 0),
+        ]);
+        let strides = function.basic_blocks[0].instructions.iter()
+            .filter(|instruction| matches!(
+                instruction,
+                Instruction::BinaryOp { op: BinaryOp::Mul, right: Operand::Constant(Constant::I32(8)), .. }
+            ))
+            .count();
+        assert_eq!(strides, 1);
+        assert!(function.locals.len() > mir_func.local_decls.len());
+    }
+
+    #[test]
+    fn test_invalid_projection() {
+        let mut context = MirLoweringContext::new();
+        let mut mir_func = unit_function("project", Vec::new());
+        mir_func.local_decls = vec![MirLocalDecl { ty: MirType::I32, source_info: mir_func.source_info.clone() }];
+        let field = MirPlace::Projection(Box::new(MirPlace::Local(0)), Box::new(MirProjection::Field(0)));
+        mir_func.basic_blocks[0].statements = vec![
+            MirStatement::Assign(field, MirRvalue::Use(MirOperand::Constant(MirConstant::I32(1)))),
+        ];
+
+        let error = context.lower_function(&mir_func).unwrap_err();
+        assert_eq!(error.code, Some(codes::INVALID_PROJECTION));
+        assert_eq!(error.message, "cannot apply `Field(0)` to a value of type `I32`");
+    }
+
+    #[test]
+    fn test_drop_glue_and_scope_drops() {
+        let mut context = MirLoweringContext::new();
+        let guard = MirType::Struct(vec![MirType::I32, MirType::String]);
+        let handle = MirType::Own("blob".to_string());
+
+        let mut destructor = unit_function("drop_guard", vec![MirAttribute::Destructor(guard.clone())]);
+        let reference = MirType::Ref(Box::new(guard.clone()));
+        destructor.signature.inputs = vec![reference.clone()];
+        destructor.local_decls = vec![MirLocalDecl { ty: reference, source_info: destructor.source_info.clone() }];
+
+        // fn f(guard, handle) { drop(handle); } with `guard` dropped on return
+        let mut f = unit_function("f", Vec::new());
+        f.signature.inputs = vec![guard.clone(), handle.clone()];
+        let decl = |ty| MirLocalDecl { ty, source_info: f.source_info.clone() };
+        f.local_decls = vec![decl(guard), decl(handle)];
+        f.basic_blocks = vec![
+            MirBasicBlock {
+                statements: Vec::new(),
+                terminator: MirTerminator::Drop { place: MirPlace::Local(1), target: 1 },
+            },
+            MirBasicBlock { statements: Vec::new(), terminator: MirTerminator::Return },
+        ];
+
+        let module = context.lower_module(&[destructor, f]).unwrap();
+        assert_eq!(module.functions.len(), 3);
+        let f = &module.functions[1];
+        assert!(matches!(
+            f.basic_blocks[0].instructions[..],
+            [Instruction::LinearOp { op: LinearOp::Drop, value: Operand::Local(1) }]
+        ));
+        assert!(matches!(
+            f.basic_blocks[1].instructions[..],
+            [Instruction::Call { func_ref: 2, ref args }] if matches!(args[..], [Operand::Local(0)])
+        ));
+
+        // The glue runs the destructor, then frees the string's buffer
+        let glue = &module.functions[2];
+        let instructions = &glue.basic_blocks[0].instructions;
+        assert!(matches!(instructions[0], Instruction::Call { func_ref: 0, .. }));
+        assert!(matches!(instructions[1], Instruction::MemoryLoad { offset: 4, .. }));
+        assert!(matches!(instructions.last(), Some(Instruction::MemoryFree { .. })));
+    }
+
+    #[test]
+    fn test_asserts_share_panic_blocks() {
+        let mut context = MirLoweringContext::new();
+        let mut f = unit_function("check", Vec::new());
+        f.signature.inputs = vec![MirType::Bool];
+        f.local_decls = vec![MirLocalDecl { ty: MirType::Bool, source_info: f.source_info.clone() }];
+        let assert = |expected, target| MirTerminator::Assert {
+            cond: MirOperand::Copy(Box::new(MirPlace::Local(0))),
+            expected,
+            message: "assertion failed: flag".to_string(),
+            target,
+        };
+        f.basic_blocks = vec![
+            MirBasicBlock { statements: Vec::new(), terminator: assert(true, 1) },
+            MirBasicBlock { statements: Vec::new(), terminator: assert(false, 2) },
+            MirBasicBlock {
+                statements: Vec::new(),
+                terminator: MirTerminator::Panic { message: "explicit panic".to_string() },
+            },
+        ];
+
+        let wasmir = context.lower_function(&f).unwrap();
+        assert_eq!(wasmir.basic_blocks.len(), 4);
+        assert!(matches!(
+            wasmir.basic_blocks[0].terminator,
+            Terminator::Branch { then_block: BlockId(1), else_block: BlockId(3), .. }
+        ));
+        assert!(matches!(
+            wasmir.basic_blocks[1].terminator,
+            Terminator::Branch { then_block: BlockId(3), else_block: BlockId(2), .. }
+        ));
+        assert!(matches!(
+            &wasmir.basic_blocks[3].terminator,
+            Terminator::Panic { message: Some(Operand::Constant(Constant::String(message))) }
+                if message == "assertion failed: flag"
+        ));
+    }
+
+    #[test]
+    fn test_casts_follow_rust_semantics() {
+        use crate::backend::interpreter::Interpreter;
+        use crate::host::runtime::Value;
+        use crate::host::HostFunctions;
+
+        let cases = [
+            (MirType::F64, MirType::U8, Value::F64(300.7), Value::I32(255)),
+            (MirType::F64, MirType::U8, Value::F64(-5.0), Value::I32(0)),
+            (MirType::F64, MirType::U8, Value::F64(42.9), Value::I32(42)),
+            (MirType::F64, MirType::I32, Value::F64(-1e10), Value::I32(i32::MIN)),
+            (MirType::F64, MirType::I32, Value::F64(f64::NAN), Value::I32(0)),
+            (MirType::F32, MirType::U64, Value::F32(1.5e19), Value::I64(1.5e19f32 as u64 as i64)),
+            (MirType::I32, MirType::I64, Value::I32(-1), Value::I64(-1)),
+            (MirType::U32, MirType::I64, Value::I32(-1), Value::I64(u32::MAX as i64)),
+            (MirType::I64, MirType::U8, Value::I64(0x1234), Value::I32(0x34)),
+            (MirType::U64, MirType::F32, Value::I64(-1), Value::F32(u64::MAX as f32)),
+            (MirType::F64, MirType::F32, Value::F64(1e40), Value::F32(f32::INFINITY)),
+            (MirType::Bool, MirType::F64, Value::I32(1), Value::F64(1.0)),
+        ];
+        let host = HostFunctions::new();
+        for (from, to, input, expected) in cases {
+            let mut f = unit_function("cast", Vec::new());
+            f.signature.inputs = vec![from.clone()];
+            f.local_decls = [from.clone(), to.clone()]
+                .into_iter()
+                .map(|ty| MirLocalDecl { ty, source_info: f.source_info.clone() })
+                .collect();
+            f.basic_blocks[0].statements = vec![MirStatement::Assign(
+                MirPlace::Local(1),
+                MirRvalue::Cast(MirOperand::Copy(Box::new(MirPlace::Local(0))), to.clone()),
+            )];
+
+            let mut context = MirLoweringContext::new();
+            let mut wasmir = context.lower_function(&f).unwrap();
+            // MIR returns are unit here; return the cast local instead
+            wasmir.signature.returns = Some(context.convert_type(&to).unwrap());
+            wasmir.basic_blocks[0].terminator = Terminator::Return { value: Some(Operand::Local(1)) };
+            let mut module = WasmModule::new();
+            module.add_function(wasmir);
+            let result = Interpreter::new(&module, &host).call(0, &[input]);
+            assert_eq!(result, Ok(Some(expected)), "{:?} as {:?}", input, to);
+        }
+
+        let mut f = unit_function("cast", Vec::new());
+        f.local_decls = vec![MirLocalDecl { ty: MirType::Bool, source_info: f.source_info.clone() }];
+        f.basic_blocks[0].statements = vec![MirStatement::Assign(
+            MirPlace::Local(0),
+            MirRvalue::Cast(MirOperand::Constant(MirConstant::I32(2)), MirType::Bool),
+        )];
+        let error = MirLoweringContext::new().lower_function(&f).unwrap_err();
+        assert_eq!(error.code, Some(codes::INVALID_CAST));
+    }
+
+    #[test]
+    fn test_address_taken_locals_live_in_the_stack_frame() {
+        use crate::backend::interpreter::Interpreter;
+        use crate::host::runtime::Value;
+        use crate::host::HostFunctions;
+        use wasm::memory::stack::{lower_stack_frames, DEFAULT_STACK_SIZE};
+
+        // fn f(x: i32) -> i32 { let r = &x; *r = x + 1; x }
+        let mut f = unit_function("addressed", Vec::new());
+        f.signature.inputs = vec![MirType::I32];
+        f.local_decls = [MirType::I32, MirType::Ref(Box::new(MirType::I32)), MirType::I32]
+            .into_iter()
+            .map(|ty| MirLocalDecl { ty, source_info: f.source_info.clone() })
+            .collect();
+        let x = || Box::new(MirPlace::Local(0));
+        f.basic_blocks[0].statements = vec![
+            MirStatement::Assign(MirPlace::Local(1), MirRvalue::Ref(MirOperand::Copy(x()))),
+            MirStatement::Assign(
+                MirPlace::Projection(Box::new(MirPlace::Local(1)), Box::new(MirProjection::Deref)),
+                MirRvalue::BinaryOp(MirBinOp::Add, MirOperand::Copy(x()), MirOperand::Constant(MirConstant::I32(1))),
+            ),
+            MirStatement::Assign(MirPlace::Local(2), MirRvalue::Use(MirOperand::Copy(x()))),
+        ];
+
+        let mut wasmir = MirLoweringContext::new().lower_function(&f).unwrap();
+        let frame = wasmir.frame.clone().unwrap();
+        assert_eq!(frame.spills, vec![(0, 0)]);
+        // MIR returns are unit here; return the copy of `x` instead
+        wasmir.signature.returns = Some(Type::I32);
+        wasmir.basic_blocks[0].terminator = Terminator::Return { value: Some(Operand::Local(2)) };
+        let mut module = WasmModule::new();
+        module.add_function(wasmir);
+        let module = lower_stack_frames(&module, DEFAULT_STACK_SIZE).unwrap();
+
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+        assert_eq!(interpreter.call(0, &[Value::I32(41)]), Ok(Some(Value::I32(42))));
+        assert_eq!(interpreter.call(0, &[Value::I32(-1)]), Ok(Some(Value::I32(0))));
+    }
+}
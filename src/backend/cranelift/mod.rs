@@ -4,8 +4,12 @@
 //! optimized for fast development compilation.
 
 pub mod lib;
+pub mod cache;
 pub mod integration;
 pub mod mir_lowering;
+pub mod ownership_check;
+#[cfg(feature = "rustc-mir")]
+pub mod rustc_lowering;
 pub mod thin_monomorphization;
 pub mod type_descriptor;
 pub mod mir_complexity;
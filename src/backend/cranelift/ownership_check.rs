@@ -0,0 +1,508 @@
+//! Ownership verification for linear MIR locals
+//!
+//! Runs before lowering and rejects what the linear-type design rules out:
+//! moving a value twice, using it after it was moved, and letting an
+//! externref, funcref, or owned resource handle go out of scope without
+//! consuming it. Vectors are linear too but own only memory, so dropping
+//! one is allowed. Explicitly dropping a handle consumes it, but a
+//! `#[wasm::linear]` value cannot be dropped at all: its drop would only
+//! run the drop bomb the macro generates.
+//!
+//! The same dataflow elaborates drops: `elaborate_drops` also finds where
+//! values with drop glue go out of scope still owned, so the lowering can
+//! drop them there. Values moved on only some paths would need a runtime
+//! drop flag, which is not supported yet.
+//!
+//! MIR statements carry no spans of their own, so diagnostics point at the
+//! local's declaration and name the statement as `bb1[2]`, the way MIR
+//! dumps do.
+
+use super::mir_lowering::{
+    MirFunction, MirOperand, MirPlace, MirProjection, MirRvalue, MirStatement, MirTerminator, MirType,
+};
+use crate::diagnostics::{codes, Code, Diagnostic};
+use std::fmt;
+
+/// Statement or terminator of a MIR function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MirLocation {
+    pub block: u32,
+    /// Index into the block's statements; the terminator follows the last
+    pub statement: usize,
+}
+
+impl fmt::Display for MirLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}[{}]", self.block, self.statement)
+    }
+}
+
+/// Owned value dropped where its local goes out of scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopeDrop {
+    pub local: u32,
+    /// The statement ending the scope, or the block's `Return`; the drop
+    /// happens before it
+    pub at: MirLocation,
+}
+
+// States a local may be in at a program point, as a bit set so that joins
+// keep every possibility
+const UNINIT: u8 = 1;
+const LIVE: u8 = 2;
+const MOVED: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fact {
+    states: u8,
+    /// Where the value was last moved on some path reaching this point
+    moved_at: Option<MirLocation>,
+}
+
+impl Fact {
+    fn join(self, other: Fact) -> Fact {
+        Fact { states: self.states | other.states, moved_at: self.moved_at.or(other.moved_at) }
+    }
+}
+
+/// Checks that linear locals are used exactly once
+///
+/// Returns every violation, in block order; an empty result means the
+/// function may be lowered.
+pub fn check_ownership(function: &MirFunction) -> Vec<Diagnostic> {
+    elaborate_drops(function, |_| false).0
+}
+
+/// Checks ownership like `check_ownership` and finds the scope ends where
+/// owned values whose type `needs_drop` must be dropped
+///
+/// Values that must be consumed are never dropped implicitly.
+pub fn elaborate_drops(function: &MirFunction, needs_drop: impl Fn(&MirType) -> bool) -> (Vec<Diagnostic>, Vec<ScopeDrop>) {
+    let mut checker = Checker {
+        function,
+        linear: function.local_decls.iter().map(|decl| decl.ty.is_linear()).collect(),
+        must_consume: function.local_decls.iter().map(|decl| decl.ty.must_consume()).collect(),
+        dropped: function.local_decls.iter().map(|decl| !decl.ty.must_consume() && needs_drop(&decl.ty)).collect(),
+        diagnostics: Vec::new(),
+        drops: Vec::new(),
+        reporting: false,
+    };
+
+    // Arguments arrive owned; other locals are assigned before use
+    let params = function.signature.inputs.len();
+    let entry: Vec<Fact> = (0..function.local_decls.len())
+        .map(|local| Fact { states: if local < params { LIVE } else { UNINIT }, moved_at: None })
+        .collect();
+
+    let blocks = function.basic_blocks.len();
+    let mut inputs: Vec<Option<Vec<Fact>>> = vec![None; blocks];
+    if blocks == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    inputs[0] = Some(entry);
+
+    let mut worklist = vec![0usize];
+    while let Some(block) = worklist.pop() {
+        let Some(mut facts) = inputs[block].clone() else { continue };
+        for successor in checker.block(block, &mut facts) {
+            let Some(input) = inputs.get_mut(successor) else { continue };
+            let joined = match input {
+                Some(existing) => existing.iter().zip(&facts).map(|(a, b)| a.join(*b)).collect(),
+                None => facts.clone(),
+            };
+            if input.as_ref() != Some(&joined) {
+                *input = Some(joined);
+                worklist.push(successor);
+            }
+        }
+    }
+
+    // Report once per site, from the fixed point
+    checker.reporting = true;
+    for (block, input) in inputs.into_iter().enumerate() {
+        if let Some(mut facts) = input {
+            checker.block(block, &mut facts);
+        }
+    }
+    (checker.diagnostics, checker.drops)
+}
+
+struct Checker<'a> {
+    function: &'a MirFunction,
+    linear: Vec<bool>,
+    must_consume: Vec<bool>,
+    /// Locals dropped implicitly at the end of their scope
+    dropped: Vec<bool>,
+    diagnostics: Vec<Diagnostic>,
+    drops: Vec<ScopeDrop>,
+    reporting: bool,
+}
+
+impl Checker<'_> {
+    /// Applies a block to `facts` and returns its successors
+    fn block(&mut self, block: usize, facts: &mut [Fact]) -> Vec<usize> {
+        let mir_block = &self.function.basic_blocks[block];
+        for (index, statement) in mir_block.statements.iter().enumerate() {
+            let at = MirLocation { block: block as u32, statement: index };
+            match statement {
+                MirStatement::Assign(place, rvalue) => {
+                    match rvalue {
+                        MirRvalue::Use(operand)
+                        | MirRvalue::UnaryOp(_, operand)
+                        | MirRvalue::Cast(operand, _)
+                        | MirRvalue::Ref(operand)
+                        | MirRvalue::Len(operand) => self.operand(operand, facts, at),
+                        MirRvalue::BinaryOp(_, left, right) => {
+                            self.operand(left, facts, at);
+                            self.operand(right, facts, at);
+                        }
+                    }
+                    self.assign(place, facts, at);
+                }
+                MirStatement::StorageLive(local) | MirStatement::StorageDead(local) => {
+                    self.end_scope(*local, facts, at, "goes out of scope");
+                }
+                MirStatement::Nop => {}
+            }
+        }
+
+        let at = MirLocation { block: block as u32, statement: mir_block.statements.len() };
+        match &mir_block.terminator {
+            MirTerminator::Return => {
+                for local in 0..facts.len() as u32 {
+                    self.end_scope(local, facts, at, "is dropped on return");
+                }
+                Vec::new()
+            }
+            MirTerminator::Goto { target } => vec![*target as usize],
+            MirTerminator::SwitchInt { discr, targets, otherwise } => {
+                self.operand(discr, facts, at);
+                targets.iter().map(|(_, target)| *target as usize).chain([*otherwise as usize]).collect()
+            }
+            MirTerminator::Call { func, args, destination } => {
+                self.operand(func, facts, at);
+                for arg in args {
+                    self.operand(arg, facts, at);
+                }
+                match destination {
+                    Some((place, target)) => {
+                        self.assign(place, facts, at);
+                        vec![*target as usize]
+                    }
+                    None => Vec::new(),
+                }
+            }
+            MirTerminator::Drop { place, target } => {
+                if let MirPlace::Local(local) = place {
+                    self.drop_linear(*local, facts, at);
+                }
+                self.use_place(place, facts, at, true);
+                vec![*target as usize]
+            }
+            MirTerminator::Assert { cond, target, .. } => {
+                self.operand(cond, facts, at);
+                vec![*target as usize]
+            }
+            // Values owned on a trapping path are never observed
+            MirTerminator::Panic { .. } | MirTerminator::Unreachable => Vec::new(),
+        }
+    }
+
+    fn operand(&mut self, operand: &MirOperand, facts: &mut [Fact], at: MirLocation) {
+        match operand {
+            MirOperand::Copy(place) => self.use_place(place, facts, at, false),
+            MirOperand::Move(place) => self.use_place(place, facts, at, true),
+            MirOperand::Constant(_) => {}
+        }
+    }
+
+    /// Reads a place, moving the local it is rooted at if `moves`
+    fn use_place(&mut self, place: &MirPlace, facts: &mut [Fact], at: MirLocation, moves: bool) {
+        let local = self.root(place, facts, at);
+        if !self.is_tracked(local) {
+            return;
+        }
+        let fact = &mut facts[local as usize];
+        if fact.states & MOVED != 0 && self.is_linear(local) {
+            let definitely = fact.states == MOVED;
+            let message = match (moves, definitely) {
+                (true, true) => format!("`_{}` is moved again at {} after it was moved", local, at),
+                (true, false) => format!("`_{}` is moved at {} but may already have been moved", local, at),
+                (false, true) => format!("use of moved value `_{}` at {}", local, at),
+                (false, false) => format!("use of possibly moved value `_{}` at {}", local, at),
+            };
+            let moved_at = fact.moved_at;
+            let mut diagnostic = self.diagnostic(codes::USE_OF_MOVED_VALUE, local, message);
+            if let Some(moved_at) = moved_at {
+                diagnostic = diagnostic.note(format!("`_{}` was moved at {}", local, moved_at));
+            }
+            self.report(diagnostic.note("linear values can be used only once"));
+        }
+        if moves {
+            *fact = Fact { states: MOVED, moved_at: Some(at) };
+        }
+    }
+
+    /// Finds the local a place is rooted at, checking index operands on the way
+    fn root(&mut self, place: &MirPlace, facts: &mut [Fact], at: MirLocation) -> u32 {
+        match place {
+            MirPlace::Local(local) => *local,
+            MirPlace::Projection(base, projection) => {
+                if let MirProjection::Index(index) = projection.as_ref() {
+                    self.operand(index, facts, at);
+                }
+                self.root(base, facts, at)
+            }
+        }
+    }
+
+    fn assign(&mut self, place: &MirPlace, facts: &mut [Fact], at: MirLocation) {
+        let MirPlace::Local(local) = place else {
+            // Writing through a projection needs the value it is rooted at
+            self.use_place(place, facts, at, false);
+            return;
+        };
+        if self.is_tracked(*local) {
+            self.end_scope(*local, facts, at, "is overwritten");
+            facts[*local as usize] = Fact { states: LIVE, moved_at: None };
+        }
+    }
+
+    /// Ends the current value of `local`, which must have been consumed
+    /// unless it is dropped here
+    fn end_scope(&mut self, local: u32, facts: &mut [Fact], at: MirLocation, how: &str) {
+        if !self.is_tracked(local) {
+            return;
+        }
+        let fact = facts[local as usize];
+        if self.must_consume[local as usize] && fact.states & LIVE != 0 {
+            let message = if fact.states == LIVE {
+                format!("linear value `_{}` {} at {} without being consumed", local, how, at)
+            } else {
+                format!("linear value `_{}` {} at {} and is not consumed on every path", local, how, at)
+            };
+            let diagnostic = self.diagnostic(codes::UNCONSUMED_LINEAR_VALUE, local, message)
+                .help("move it into a call that takes ownership, such as its drop function");
+            self.report(diagnostic);
+        } else if self.dropped[local as usize] && fact.states == LIVE {
+            if self.reporting {
+                self.drops.push(ScopeDrop { local, at });
+            }
+        } else if self.dropped[local as usize] && fact.states & LIVE != 0 {
+            let message = format!("`_{}` {} at {} but is moved on only some paths", local, how, at);
+            let diagnostic = self.diagnostic(codes::UNSUPPORTED_MIR, local, message)
+                .note("dropping it would need a runtime drop flag, which is not supported yet")
+                .help("move or drop it on every path");
+            self.report(diagnostic);
+        }
+        facts[local as usize] = Fact { states: UNINIT, moved_at: None };
+    }
+
+    /// Reports a `Drop` of a `#[wasm::linear]` value that may still be owned
+    fn drop_linear(&mut self, local: u32, facts: &[Fact], at: MirLocation) {
+        let Some(decl) = self.function.local_decls.get(local as usize) else { return };
+        if !matches!(decl.ty, MirType::Linear(_)) || facts[local as usize].states & LIVE == 0 {
+            return;
+        }
+        let message = format!("linear value `_{}` is dropped at {} without being consumed", local, at);
+        let diagnostic = self.diagnostic(codes::UNCONSUMED_LINEAR_VALUE, local, message)
+            .note("`#[wasm::linear]` types panic when dropped in debug builds")
+            .help("take it apart with its `consume` method, or move it into a call that takes ownership");
+        self.report(diagnostic);
+    }
+
+    /// Checks whether `local` is a declared linear local; the lowering
+    /// reports undeclared ones
+    fn is_linear(&self, local: u32) -> bool {
+        self.linear.get(local as usize).copied().unwrap_or(false)
+    }
+
+    /// Checks whether moves of `local` are followed
+    fn is_tracked(&self, local: u32) -> bool {
+        self.is_linear(local) || self.dropped.get(local as usize).copied().unwrap_or(false)
+    }
+
+    fn diagnostic(&self, code: Code, local: u32, message: String) -> Diagnostic {
+        let decl = &self.function.local_decls[local as usize];
+        Diagnostic::error(code, message)
+            .primary(decl.source_info.span.location(), format!("`_{}` declared here", local))
+            .secondary(self.function.source_info.span.location(), format!("in `{}`", self.function.name))
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) {
+        if self.reporting {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::cranelift::mir_lowering::{
+        MirBasicBlock, MirConstant, MirLocalDecl, MirSignature, MirSourceInfo, MirSpan, MirType,
+    };
+
+    fn function(inputs: Vec<MirType>, locals: Vec<MirType>, basic_blocks: Vec<MirBasicBlock>) -> MirFunction {
+        let info = |line| MirSourceInfo { span: MirSpan { filename: "lib.rs".to_string(), line, column: 5 } };
+        MirFunction {
+            name: "f".to_string(),
+            signature: MirSignature { inputs, output: MirType::Unit },
+            basic_blocks,
+            local_decls: locals.into_iter()
+                .enumerate()
+                .map(|(index, ty)| MirLocalDecl { ty, source_info: info(index as u32 + 2) })
+                .collect(),
+            source_info: info(1),
+            attributes: Vec::new(),
+        }
+    }
+
+    fn block(statements: Vec<MirStatement>, terminator: MirTerminator) -> MirBasicBlock {
+        MirBasicBlock { statements, terminator }
+    }
+
+    fn moved(local: u32) -> MirOperand {
+        MirOperand::Move(Box::new(MirPlace::Local(local)))
+    }
+
+    /// Calls a function taking `args`, storing its result in `_dest`
+    fn call(args: Vec<MirOperand>, dest: u32, target: u32) -> MirTerminator {
+        MirTerminator::Call {
+            func: MirOperand::Constant(MirConstant::Unit),
+            args,
+            destination: Some((MirPlace::Local(dest), target)),
+        }
+    }
+
+    #[test]
+    fn test_consumed_handles_pass() {
+        let handle = MirType::Own("blob".to_string());
+        // _1 = move _0; drop(move _1); return
+        let f = function(
+            vec![handle.clone()],
+            vec![handle.clone(), handle, MirType::I32, MirType::Vec(Box::new(MirType::U8))],
+            vec![
+                block(
+                    vec![MirStatement::Assign(MirPlace::Local(1), MirRvalue::Use(moved(0)))],
+                    call(vec![moved(1)], 2, 1),
+                ),
+                // Vectors may be dropped
+                block(vec![MirStatement::StorageDead(3)], MirTerminator::Return),
+            ],
+        );
+        assert!(check_ownership(&f).is_empty());
+    }
+
+    #[test]
+    fn test_double_move_and_use_after_move() {
+        let reference = MirType::ExternRef("Node".to_string());
+        let f = function(
+            vec![reference.clone()],
+            vec![reference, MirType::I32],
+            vec![block(
+                vec![MirStatement::Assign(
+                    MirPlace::Local(1),
+                    MirRvalue::Len(MirOperand::Copy(Box::new(MirPlace::Local(0)))),
+                )],
+                call(vec![moved(0), moved(0)], 1, 1),
+            ), block(
+                vec![MirStatement::Assign(
+                    MirPlace::Local(1),
+                    MirRvalue::Len(MirOperand::Copy(Box::new(MirPlace::Local(0)))),
+                )],
+                MirTerminator::Return,
+            )],
+        );
+        let diagnostics = check_ownership(&f);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "`_0` is moved again at bb0[1] after it was moved",
+            "use of moved value `_0` at bb1[0]",
+        ]);
+        let error = &diagnostics[0];
+        assert_eq!(error.code, Some(codes::USE_OF_MOVED_VALUE));
+        assert_eq!(error.primary_span().unwrap().location.line, 2);
+        assert_eq!(error.notes[0], "`_0` was moved at bb0[1]");
+    }
+
+    #[test]
+    fn test_unconsumed_on_some_paths() {
+        let reference = MirType::FuncRef;
+        // if _1 { consume(move _0) } return
+        let f = function(
+            vec![reference.clone(), MirType::Bool],
+            vec![reference, MirType::Bool, MirType::I32],
+            vec![
+                block(Vec::new(), MirTerminator::SwitchInt {
+                    discr: MirOperand::Copy(Box::new(MirPlace::Local(1))),
+                    targets: vec![(0, 2)],
+                    otherwise: 1,
+                }),
+                block(Vec::new(), call(vec![moved(0)], 2, 2)),
+                block(Vec::new(), MirTerminator::Return),
+            ],
+        );
+        let diagnostics = check_ownership(&f);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some(codes::UNCONSUMED_LINEAR_VALUE));
+        assert_eq!(
+            diagnostics[0].message,
+            "linear value `_0` is dropped on return at bb2[0] and is not consumed on every path"
+        );
+    }
+
+    #[test]
+    fn test_elaborates_scope_drops() {
+        let owned = MirType::String;
+        // _2 = move _0; if _1 { consume(move _2) } StorageDead(_2); return
+        let f = function(
+            vec![owned.clone(), MirType::Bool],
+            vec![owned.clone(), MirType::Bool, owned, MirType::I32],
+            vec![
+                block(
+                    vec![MirStatement::Assign(MirPlace::Local(2), MirRvalue::Use(moved(0)))],
+                    MirTerminator::SwitchInt {
+                        discr: MirOperand::Copy(Box::new(MirPlace::Local(1))),
+                        targets: vec![(0, 2)],
+                        otherwise: 1,
+                    },
+                ),
+                block(Vec::new(), call(vec![moved(2)], 3, 2)),
+                block(vec![MirStatement::StorageDead(2)], MirTerminator::Return),
+            ],
+        );
+        let (diagnostics, drops) = elaborate_drops(&f, |ty| matches!(ty, MirType::String));
+        assert!(drops.is_empty());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some(codes::UNSUPPORTED_MIR));
+        assert_eq!(diagnostics[0].message, "`_2` goes out of scope at bb2[0] but is moved on only some paths");
+        assert!(check_ownership(&f).is_empty());
+
+        // Without the branch, the string is still owned where its scope ends
+        let mut f = f;
+        f.basic_blocks[0].terminator = MirTerminator::Goto { target: 2 };
+        let (diagnostics, drops) = elaborate_drops(&f, |ty| matches!(ty, MirType::String));
+        assert!(diagnostics.is_empty());
+        assert_eq!(drops, vec![ScopeDrop { local: 2, at: MirLocation { block: 2, statement: 0 } }]);
+    }
+
+    #[test]
+    fn test_linear_values_cannot_be_dropped() {
+        let canvas = MirType::Linear(Box::new(MirType::Own("canvas".to_string())));
+        // drop(_0); consume(move _1); return
+        let f = function(
+            vec![canvas.clone(), canvas.clone()],
+            vec![canvas.clone(), canvas, MirType::I32],
+            vec![
+                block(Vec::new(), MirTerminator::Drop { place: MirPlace::Local(0), target: 1 }),
+                block(Vec::new(), call(vec![moved(1)], 2, 2)),
+                block(Vec::new(), MirTerminator::Return),
+            ],
+        );
+        let diagnostics = check_ownership(&f);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some(codes::UNCONSUMED_LINEAR_VALUE));
+        assert_eq!(diagnostics[0].message, "linear value `_0` is dropped at bb0[0] without being consumed");
+    }
+}
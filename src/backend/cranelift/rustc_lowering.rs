@@ -0,0 +1,1042 @@
+//! Lowering of rustc MIR bodies to WasmIR
+//!
+//! Built with the `rustc-mir` feature, when the compiler runs as a rustc
+//! driver. It lowers `rustc_middle::mir::Body` directly, so the driver
+//! needs no `mir_lowering::MirFunction` mirror of it. Bodies are expected
+//! to be monomorphic and optimized with overflow checks off; anything else
+//! is reported as `codes::UNSUPPORTED_MIR` at the construct's span.
+//!
+//! Scalars live in WasmIR locals, and references to sized types are `i32`
+//! addresses into linear memory. Places behind a `Deref` are loaded and
+//! stored through those addresses, with field offsets taken from rustc's
+//! layouts. Locals whose address is taken live in a slot of the
+//! function's shadow stack frame instead, which `wasm::memory::stack`
+//! reserves on entry. Rust's ownership rules are already checked by borrowck; what
+//! remains for linear types is that rustc drops values implicitly, so a
+//! `Drop` of an externref, funcref, owned resource handle, or
+//! `#[wasm::linear]` type is reported as `codes::UNCONSUMED_LINEAR_VALUE`,
+//! rather than left to the linear type's drop bomb. Other drops call rustc's
+//! `drop_in_place` shim for the type, lowered after the module's items.
+//!
+//! Casts lower to `Instruction::Convert`s; integers narrower than 32 bits
+//! are kept sign- or zero-extended in their `i32`.
+//!
+//! `Box::new` and the collections reach the global allocator through
+//! `exchange_malloc`, `__rust_alloc`, and `__rust_dealloc`; those calls
+//! become `Instruction::MemoryAlloc` and `MemoryFree`, which the backend
+//! hands to the allocator its `AllocatorStrategy` picks. Their alignment
+//! must be a constant.
+//!
+//! References to `#[thread_local]` statics, which `thread_local!` expands
+//! to on wasm, become `Instruction::ThreadLocalAddress`es of module thread
+//! locals; `wasm::threading::tls` gives each thread its own copy. Their
+//! initializers must be scalars without pointers.
+//!
+//! `std::sync` blocks in the futex intrinsics of `core::arch::wasm32`, as
+//! does `wasm::threading::sync`; calls to them become
+//! `Instruction::AtomicWait` and `AtomicNotify`, and mark the function as
+//! needing `Capability::AtomicMemory`.
+//!
+//! Failed asserts and calls into `core::panicking` become
+//! `Terminator::Panic`s carrying the message and source location; the
+//! backend's `PanicStrategy` decides whether they trap or unwind.
+//!
+//! `crate_functions` lists what a driver such as `wasm-rustc` lowers for a
+//! whole crate, and `export_name` which of them the module exports.
+//! Lowered functions are named by their path, crate first.
+//!
+//! For crates compiled with `--test`, `test_functions` finds the `#[test]`
+//! functions to lower, and `BodyLowering::test_cases` describes them to
+//! `host::test_runner` once they are part of a module.
+//!
+//! # Status
+//!
+//! Unverified. The `rustc_*` dependencies this module builds against are
+//! placeholders, so it has never been compiled against the real
+//! `rustc_middle` API, and no test runs it. That covers every path above:
+//! places and projections, drops, panics, 128-bit integers, casts,
+//! allocation, shadow stack slots, thread locals, and atomics.
+
+use crate::diagnostics::{codes, Code, Diagnostic, Diagnostics};
+use crate::host::test_runner::{ShouldPanic, TestCase};
+use crate::timings::{self, Phase};
+use rustc_hir::def::DefKind;
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
+use rustc_middle::mir::{
+    self, BasicBlock, BinOp, Body, CastKind, Operand as MirOperand, Place, ProjectionElem, Rvalue,
+    StatementKind, TerminatorKind, UnOp,
+};
+use rustc_middle::ty::{self, FloatTy, IntTy, Ty, TyCtxt, UintTy};
+use rustc_span::def_id::DefId;
+use rustc_span::source_map::Spanned;
+use rustc_span::symbol::sym;
+use rustc_span::Span;
+use std::collections::{BTreeSet, HashMap};
+use wasm::wasmir::{
+    BinaryOp, BlockId, Capability, Constant, ConvertOp, Instruction, Operand, OwnershipAnnotation, OwnershipState,
+    Signature, SourceLocation, Terminator, Type, UnaryOp, WasmIR, WasmModule,
+};
+
+/// Path prefixes of the functions `panic!` and friends expand to
+const PANIC_ENTRY_POINTS: &[&str] = &["core::panicking::", "std::panicking::", "std::rt::begin_panic"];
+
+/// Functions taking `(size, align)` and returning a fresh allocation
+const ALLOC_ENTRY_POINTS: &[&str] = &["alloc::alloc::exchange_malloc", "alloc::alloc::__rust_alloc"];
+
+/// Functions taking `(ptr, size, align)` and freeing the allocation
+const DEALLOC_ENTRY_POINTS: &[&str] = &["alloc::alloc::__rust_dealloc"];
+
+/// Functions taking `(ptr, expected, timeout)` and waiting on the futex at
+/// `ptr`, by the type of the value waited on
+const WAIT_ENTRY_POINTS: &[(&str, Type)] = &[
+    ("core::arch::wasm32::memory_atomic_wait32", Type::I32),
+    ("core::core_arch::wasm32::atomic::memory_atomic_wait32", Type::I32),
+    ("core::arch::wasm32::memory_atomic_wait64", Type::I64),
+    ("core::core_arch::wasm32::atomic::memory_atomic_wait64", Type::I64),
+];
+
+/// Functions taking `(ptr, count)` and waking waiters on the futex at `ptr`
+const NOTIFY_ENTRY_POINTS: &[&str] = &[
+    "core::arch::wasm32::memory_atomic_notify",
+    "core::core_arch::wasm32::atomic::memory_atomic_notify",
+];
+
+/// Lowers rustc MIR bodies into WasmIR
+pub struct BodyLowering<'tcx> {
+    tcx: TyCtxt<'tcx>,
+    /// Function index of each item in the module being lowered
+    functions: HashMap<DefId, u32>,
+    /// Types whose drop glue follows the items, in order
+    drop_glue: Vec<Ty<'tcx>>,
+    /// `#[thread_local]` statics referenced so far, by thread local index
+    thread_locals: Vec<DefId>,
+    diagnostics: Diagnostics,
+}
+
+impl<'tcx> BodyLowering<'tcx> {
+    pub fn new(tcx: TyCtxt<'tcx>) -> Self {
+        Self {
+            tcx,
+            functions: HashMap::new(),
+            drop_glue: Vec::new(),
+            thread_locals: Vec::new(),
+            diagnostics: Diagnostics::new(),
+        }
+    }
+
+    /// Diagnostics reported so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Lowers the optimized MIR of `items` into a module, in order
+    ///
+    /// Calls between the items become direct calls, and drop glue is
+    /// appended after them. `#[cold]` items are marked cold. The `#[thread_local]` statics they reference
+    /// become the module's thread locals. A failure is also recorded in
+    /// `diagnostics`.
+    pub fn lower_module(&mut self, items: &[DefId]) -> Result<WasmModule, Diagnostic> {
+        self.functions = items.iter().enumerate().map(|(index, &item)| (item, index as u32)).collect();
+        self.drop_glue.clear();
+        self.thread_locals.clear();
+        let mut module = WasmModule::new();
+        for &item in items {
+            let _lowering = timings::span(Phase::Lowering, self.tcx.def_path_str(item));
+            let body = self.tcx.optimized_mir(item);
+            let index = module.add_function(self.lower_body(body)?);
+            if self.tcx.codegen_fn_attrs(item).flags.contains(CodegenFnAttrFlags::COLD) {
+                module.mark_cold(index);
+            }
+        }
+
+        // Glue may drop fields that need glue of their own
+        let mut next = 0;
+        while let Some(&ty) = self.drop_glue.get(next) {
+            let _lowering = timings::span(Phase::Lowering, format_args!("drop glue of {}", ty));
+            let shim = ty::Instance::resolve_drop_in_place(self.tcx, ty);
+            let mut glue = self.lower_body(self.tcx.instance_mir(shim.def))?;
+            glue.name = format!("core::ptr::drop_in_place::<{}>", ty);
+            module.add_function(glue);
+            next += 1;
+        }
+        for &item in &self.thread_locals {
+            let (ty, init) = self.thread_local(item).inspect_err(|diagnostic| self.diagnostics.push(diagnostic.clone()))?;
+            module.add_thread_local(self.tcx.def_path_str(item), ty, init);
+        }
+        module.validate().map_err(|e| {
+            let diagnostic = Diagnostic::error(codes::INVALID_LOWERING, format!("lowered module is invalid WasmIR: {}", e))
+                .note("this is a bug in the MIR lowering");
+            self.diagnostics.push(diagnostic.clone());
+            diagnostic
+        })?;
+        Ok(module)
+    }
+
+    /// Lowers one body, resolving calls against the current module
+    ///
+    /// A failure is also recorded in `diagnostics`.
+    pub fn lower_body(&mut self, body: &Body<'tcx>) -> Result<WasmIR, Diagnostic> {
+        let result = FunctionLowering::new(self.tcx, body, &self.functions, &mut self.drop_glue, &mut self.thread_locals)
+            .and_then(FunctionLowering::lower);
+        if let Err(diagnostic) = &result {
+            self.diagnostics.push(diagnostic.clone());
+        }
+        result
+    }
+
+    /// Test cases of `tests` lowered by the last `lower_module`
+    ///
+    /// Tests keep `#[should_panic]` and `#[ignore]` through rustc's test
+    /// harness expansion, which only reads them.
+    pub fn test_cases(&self, tests: &[DefId]) -> Vec<TestCase> {
+        tests.iter()
+            .filter_map(|&test| {
+                let function = *self.functions.get(&test)?;
+                let should_panic = match self.tcx.get_attr(test, sym::should_panic) {
+                    None => ShouldPanic::No,
+                    Some(attr) => {
+                        let expected = attr.value_str().or_else(|| {
+                            attr.meta_item_list()?
+                                .iter()
+                                .find(|item| item.has_name(sym::expected))
+                                .and_then(|item| item.value_str())
+                        });
+                        match expected {
+                            Some(message) => ShouldPanic::YesWithMessage(message.to_string()),
+                            None => ShouldPanic::Yes,
+                        }
+                    }
+                };
+                let test_case = TestCase::new(self.tcx.def_path_str(test), function)
+                    .should_panic(should_panic)
+                    .ignored(self.tcx.has_attr(test, sym::ignore));
+                Some(test_case)
+            })
+            .collect()
+    }
+
+    /// Type and initial value of a `#[thread_local]` static
+    ///
+    /// The initializer must be a scalar without pointers into other
+    /// allocations; narrow integers are widened like other `i32`s.
+    fn thread_local(&self, item: DefId) -> Result<(Type, Constant), Diagnostic> {
+        let path = self.tcx.def_path_str(item);
+        let unsupported = |what: String| {
+            Diagnostic::error(
+                codes::UNSUPPORTED_MIR,
+                format!("thread local `{}` {} is not supported by the WasmIR lowering", path, what),
+            )
+        };
+        let allocation = self.tcx.eval_static_initializer(item)
+            .map_err(|_| unsupported("with an initializer that fails to evaluate".to_string()))?;
+        let allocation = allocation.inner();
+        if !allocation.provenance().ptrs().is_empty() {
+            return Err(unsupported("initialized with a pointer".to_string()));
+        }
+        let bytes = allocation.inspect_with_uninit_and_ptr_outside_interpreter(0..allocation.len());
+        let ty = self.tcx.type_of(item).instantiate_identity();
+        Ok(match (ty.kind(), bytes.len()) {
+            (ty::Float(FloatTy::F32), 4) => (Type::F32, Constant::F32(f32::from_le_bytes(bytes.try_into().unwrap()))),
+            (ty::Float(FloatTy::F64), 8) => (Type::F64, Constant::F64(f64::from_le_bytes(bytes.try_into().unwrap()))),
+            (ty::Int(_) | ty::Uint(_), 8) => (Type::I64, Constant::I64(i64::from_le_bytes(bytes.try_into().unwrap()))),
+            (ty::Bool | ty::Char | ty::Int(_) | ty::Uint(_) | ty::RawPtr(..) | ty::Ref(..), len @ (1 | 2 | 4)) => {
+                let mut word = [0; 4];
+                word[..len].copy_from_slice(bytes);
+                let shift = 32 - 8 * len as u32;
+                let value = match ty.kind() {
+                    ty::Int(_) => (i32::from_le_bytes(word) << shift) >> shift,
+                    _ => i32::from_le_bytes(word),
+                };
+                (Type::I32, Constant::I32(value))
+            }
+            _ => return Err(unsupported(format!("of type `{}`", ty))),
+        })
+    }
+}
+
+/// Functions and methods of the crate that have a body and no generics
+///
+/// These are what `wasm-rustc` lowers, in definition order; generic items
+/// only exist as the instances their callers request.
+pub fn crate_functions(tcx: TyCtxt<'_>) -> Vec<DefId> {
+    tcx.hir_crate_items(())
+        .definitions()
+        .map(|item| item.to_def_id())
+        .filter(|&item| matches!(tcx.def_kind(item), DefKind::Fn | DefKind::AssocFn))
+        .filter(|&item| tcx.is_mir_available(item) && tcx.generics_of(item).count() == 0)
+        .collect()
+}
+
+/// Name a `#[no_mangle]` or `#[export_name]` function is exported under
+pub fn export_name(tcx: TyCtxt<'_>, item: DefId) -> Option<String> {
+    let attrs = tcx.codegen_fn_attrs(item);
+    match attrs.export_name {
+        Some(name) => Some(name.to_string()),
+        None => attrs.flags.contains(CodegenFnAttrFlags::NO_MANGLE).then(|| tcx.item_name(item).to_string()),
+    }
+}
+
+/// Path a lowered function is named by, crate first
+///
+/// rustc leaves the local crate out of its paths; `size_report` counts
+/// functions under the crate their path starts with.
+fn qualified_name(tcx: TyCtxt<'_>, item: DefId) -> String {
+    match item.as_local() {
+        Some(_) => format!("{}::{}", tcx.crate_name(item.krate), tcx.def_path_str(item)),
+        None => tcx.def_path_str(item),
+    }
+}
+
+/// `#[test]` functions of a crate compiled with `--test`
+///
+/// rustc's test harness expansion keeps each test function and adds a
+/// `#[rustc_test_marker]` const of the same name next to it.
+pub fn test_functions(tcx: TyCtxt<'_>) -> Vec<DefId> {
+    let items = tcx.hir_crate_items(());
+    let markers: Vec<DefId> = items.definitions()
+        .map(|item| item.to_def_id())
+        .filter(|&item| tcx.has_attr(item, sym::rustc_test_marker))
+        .collect();
+    items.definitions()
+        .map(|item| item.to_def_id())
+        .filter(|&item| tcx.def_kind(item).is_fn_like())
+        .filter(|&item| {
+            markers.iter().any(|&marker| {
+                tcx.parent(marker) == tcx.parent(item) && tcx.opt_item_name(marker) == tcx.opt_item_name(item)
+            })
+        })
+        .collect()
+}
+
+/// Where a MIR place lives after lowering
+enum Slot {
+    Local(u32),
+    /// Linear memory at the address in a local plus an offset
+    Memory { address: u32, offset: u32 },
+    /// Zero-sized value with no storage
+    None,
+}
+
+struct FunctionLowering<'a, 'tcx> {
+    tcx: TyCtxt<'tcx>,
+    body: &'a Body<'tcx>,
+    functions: &'a HashMap<DefId, u32>,
+    /// Drop glue requested so far, numbered after `functions`
+    drop_glue: &'a mut Vec<Ty<'tcx>>,
+    /// `#[thread_local]` statics referenced so far
+    thread_locals: &'a mut Vec<DefId>,
+    function: WasmIR,
+    /// WasmIR local of each MIR local; `None` for zero-sized ones
+    locals: Vec<Option<u32>>,
+    /// Frame base local and slot offset of each WasmIR local whose address
+    /// is taken
+    stack_slots: HashMap<u32, (u32, u32)>,
+    /// Messages of the panic blocks appended after the body's blocks
+    panic_blocks: Vec<String>,
+}
+
+impl<'a, 'tcx> FunctionLowering<'a, 'tcx> {
+    fn new(
+        tcx: TyCtxt<'tcx>,
+        body: &'a Body<'tcx>,
+        functions: &'a HashMap<DefId, u32>,
+        drop_glue: &'a mut Vec<Ty<'tcx>>,
+        thread_locals: &'a mut Vec<DefId>,
+    ) -> Result<Self, Diagnostic> {
+        let mut lowering = Self {
+            tcx,
+            body,
+            functions,
+            drop_glue,
+            thread_locals,
+            function: WasmIR::new(String::new(), Signature { params: Vec::new(), returns: None }),
+            locals: vec![None; body.local_decls.len()],
+            stack_slots: HashMap::new(),
+            panic_blocks: Vec::new(),
+        };
+        let name = qualified_name(tcx, body.source.def_id());
+
+        // Arguments come first among the locals, then the return place
+        // and the rest in MIR order
+        let mut params = Vec::new();
+        let order = body.args_iter().chain([mir::RETURN_PLACE]).chain(body.vars_and_temps_iter());
+        for local in order {
+            let decl = &body.local_decls[local];
+            let Some(ty) = lowering.wasm_type(decl.ty, decl.source_info.span)? else { continue };
+            if let Type::ExternRef(_) = ty {
+                // ExternRef requires JS interop capability
+                if !lowering.function.capabilities.contains(&Capability::JsInterop) {
+                    lowering.function.add_capability(Capability::JsInterop);
+                }
+            }
+            let index = lowering.function.add_local(ty.clone());
+            lowering.locals[local.index()] = Some(index);
+            if local.index() <= body.arg_count && local != mir::RETURN_PLACE {
+                params.push(ty);
+                if is_must_consume(tcx, decl.ty) {
+                    lowering.function.add_ownership_annotation(OwnershipAnnotation {
+                        variable: index,
+                        state: OwnershipState::Owned,
+                        source_location: lowering.location(decl.source_info.span),
+                    });
+                }
+            }
+        }
+        let returns = lowering.wasm_type(body.return_ty(), body.span)?;
+
+        // Reference types cannot be stored, so borrowing them stays unsupported
+        for local in address_taken(body) {
+            let Some(index) = lowering.locals[local.index()] else { continue };
+            let size = match &lowering.function.locals[index as usize] {
+                Type::ExternRef(_) | Type::FuncRef => continue,
+                Type::I64 | Type::F64 => 8,
+                Type::I128 => 16,
+                _ => 4,
+            };
+            let offset = lowering.function.add_stack_slot(size, size);
+            let frame = lowering.function.frame.as_mut().expect("a slot was just added");
+            if (index as usize) < params.len() {
+                frame.spills.push((index, offset));
+            }
+            lowering.stack_slots.insert(index, (frame.base, offset));
+        }
+        lowering.function.name = name;
+        lowering.function.signature = Signature { params, returns };
+        Ok(lowering)
+    }
+
+    fn lower(mut self) -> Result<WasmIR, Diagnostic> {
+        for (block, data) in self.body.basic_blocks.iter_enumerated() {
+            let mut instructions = Vec::new();
+            for statement in &data.statements {
+                let span = statement.source_info.span;
+                match &statement.kind {
+                    StatementKind::Assign(assign) => {
+                        let (place, rvalue) = &**assign;
+                        self.assign(place, rvalue, span, &mut instructions)?;
+                    }
+                    // Storage is not tracked, and the rest only matters to
+                    // borrowck, Miri, or coverage
+                    StatementKind::StorageLive(_)
+                    | StatementKind::StorageDead(_)
+                    | StatementKind::Nop
+                    | StatementKind::FakeRead(_)
+                    | StatementKind::Retag(..)
+                    | StatementKind::PlaceMention(_)
+                    | StatementKind::AscribeUserType(..)
+                    | StatementKind::Coverage(_)
+                    | StatementKind::ConstEvalCounter => {}
+                    other => return Err(self.unsupported(span, format!("statement `{:?}`", other))),
+                }
+            }
+            let terminator = self.terminator(block, data.terminator(), &mut instructions)?;
+            self.function.add_basic_block(instructions, terminator);
+        }
+        for message in std::mem::take(&mut self.panic_blocks) {
+            self.function.add_basic_block(Vec::new(), panic(message));
+        }
+
+        self.function.validate().map_err(|e| {
+            self.error(codes::INVALID_LOWERING, self.body.span, format!(
+                "lowered function `{}` is invalid WasmIR: {}", self.function.name, e
+            ))
+            .note("this is a bug in the MIR lowering")
+        })?;
+        Ok(self.function)
+    }
+
+    fn assign(
+        &mut self,
+        place: &Place<'tcx>,
+        rvalue: &Rvalue<'tcx>,
+        span: Span,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), Diagnostic> {
+        let value = match rvalue {
+            Rvalue::Use(operand) => self.operand(operand, span, out)?,
+            Rvalue::BinaryOp(op, operands) => {
+                let (left, right) = &**operands;
+                let signed = left.ty(&self.body.local_decls, self.tcx).is_signed();
+                let op = self.binary_op(*op, signed, span)?;
+                let left = self.operand(left, span, out)?;
+                let right = self.operand(right, span, out)?;
+                match (left, right) {
+                    (Some(left), Some(right)) => {
+                        out.push(Instruction::BinaryOp { op, left, right });
+                        Some(Operand::StackValue(0))
+                    }
+                    _ => return Err(self.unsupported(span, "arithmetic on zero-sized values".to_string())),
+                }
+            }
+            Rvalue::UnaryOp(op, operand) => {
+                let op = match op {
+                    UnOp::Not => UnaryOp::Not,
+                    UnOp::Neg => UnaryOp::Neg,
+                    other => return Err(self.unsupported(span, format!("unary operator `{:?}`", other))),
+                };
+                let Some(value) = self.operand(operand, span, out)? else {
+                    return Err(self.unsupported(span, "arithmetic on zero-sized values".to_string()));
+                };
+                out.push(Instruction::UnaryOp { op, value });
+                Some(Operand::StackValue(0))
+            }
+            Rvalue::Cast(kind, operand, target) => {
+                let source = operand.ty(&self.body.local_decls, self.tcx);
+                let op = self.cast_op(*kind, source, *target, span)?;
+                let value = match (op, self.operand(operand, span, out)?) {
+                    (Some(op), Some(value)) => {
+                        out.push(Instruction::Convert { op, value });
+                        Some(Operand::StackValue(0))
+                    }
+                    (_, value) => value,
+                };
+                // Integers narrower than their `i32` stay sign- or zero-extended
+                match (*kind, narrow_bits(*target), value) {
+                    (CastKind::IntToInt, Some(bits), Some(value)) if target.is_signed() => {
+                        out.push(Instruction::Convert { op: ConvertOp::SignExtend { bits }, value });
+                        Some(Operand::StackValue(0))
+                    }
+                    (CastKind::IntToInt, Some(bits), Some(value)) => {
+                        out.push(Instruction::BinaryOp {
+                            op: BinaryOp::And,
+                            left: value,
+                            right: Operand::Constant(Constant::I32((1 << bits) - 1)),
+                        });
+                        Some(Operand::StackValue(0))
+                    }
+                    (_, _, value) => value,
+                }
+            }
+            Rvalue::Ref(_, _, borrowed) | Rvalue::RawPtr(_, borrowed) => match self.place(borrowed, span)? {
+                Slot::Memory { address, offset: 0 } => Some(Operand::Local(address)),
+                Slot::Memory { address, offset } => {
+                    out.push(Instruction::BinaryOp {
+                        op: BinaryOp::Add,
+                        left: Operand::Local(address),
+                        right: Operand::Constant(Constant::I32(offset as i32)),
+                    });
+                    Some(Operand::StackValue(0))
+                }
+                // Locals with a storable type were given a stack slot
+                Slot::Local(_) | Slot::None => {
+                    return Err(self.unsupported(span, "borrowing a local".to_string())
+                        .help("borrow data that lives in linear memory instead"));
+                }
+            },
+            Rvalue::ThreadLocalRef(item) => {
+                let index = self.thread_local(*item);
+                out.push(Instruction::ThreadLocalAddress { index });
+                Some(Operand::StackValue(0))
+            }
+            Rvalue::Aggregate(_, fields) if fields.is_empty() => None,
+            other => return Err(self.unsupported(span, format!("rvalue `{:?}`", other))),
+        };
+
+        let ty = place.ty(&self.body.local_decls, self.tcx).ty;
+        self.store(place, ty, value, span, out)
+    }
+
+    /// Writes `value` to `place`, or nothing for zero-sized places
+    fn store(
+        &mut self,
+        place: &Place<'tcx>,
+        ty: Ty<'tcx>,
+        value: Option<Operand>,
+        span: Span,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), Diagnostic> {
+        match (self.place(place, span)?, value) {
+            (Slot::Local(index), Some(value)) => out.push(Instruction::LocalSet { index, value }),
+            (Slot::Memory { address, offset }, Some(value)) => {
+                let Some(ty) = self.wasm_type(ty, span)? else { return Ok(()) };
+                out.push(Instruction::MemoryStore {
+                    address: Operand::Local(address),
+                    value,
+                    ty,
+                    align: None,
+                    offset,
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Lowers an operand, or `None` for zero-sized ones
+    fn operand(&mut self, operand: &MirOperand<'tcx>, span: Span, out: &mut Vec<Instruction>) -> Result<Option<Operand>, Diagnostic> {
+        match operand {
+            MirOperand::Copy(place) | MirOperand::Move(place) => match self.place(place, span)? {
+                Slot::Local(index) => Ok(Some(Operand::Local(index))),
+                Slot::Memory { address, offset } => {
+                    let ty = place.ty(&self.body.local_decls, self.tcx).ty;
+                    let Some(ty) = self.wasm_type(ty, span)? else { return Ok(None) };
+                    out.push(Instruction::MemoryLoad { address: Operand::Local(address), ty, align: None, offset });
+                    Ok(Some(Operand::StackValue(0)))
+                }
+                Slot::None => Ok(None),
+            },
+            MirOperand::Constant(constant) => self.constant(constant.const_, constant.span),
+        }
+    }
+
+    fn constant(&self, constant: mir::Const<'tcx>, span: Span) -> Result<Option<Operand>, Diagnostic> {
+        let ty = constant.ty();
+        if self.wasm_type(ty, span)?.is_none() {
+            return Ok(None);
+        }
+        let Some(scalar) = constant.try_to_scalar_int() else {
+            return Err(self.unsupported(span, format!("constant of type `{}`", ty)));
+        };
+        let bits = scalar.to_bits_unchecked();
+        let value = match ty.kind() {
+            ty::Bool => Constant::Boolean(bits != 0),
+            ty::Int(IntTy::I8) => Constant::I32(bits as i8 as i32),
+            ty::Int(IntTy::I16) => Constant::I32(bits as i16 as i32),
+            ty::Int(IntTy::I64) => Constant::I64(bits as i64),
+            ty::Uint(UintTy::U64) => Constant::I64(bits as u64 as i64),
+            ty::Int(IntTy::I128) | ty::Uint(UintTy::U128) => Constant::I128(bits as i128),
+            ty::Float(FloatTy::F32) => Constant::F32(f32::from_bits(bits as u32)),
+            ty::Float(FloatTy::F64) => Constant::F64(f64::from_bits(bits as u64)),
+            _ => Constant::I32(bits as u32 as i32),
+        };
+        Ok(Some(Operand::Constant(value)))
+    }
+
+    /// Resolves a place to its storage
+    ///
+    /// Projections are supported behind a `Deref` of an address local:
+    /// fields add their layout offset, and nothing else is lowered yet.
+    /// Locals in a stack slot resolve to memory.
+    fn place(&self, place: &Place<'tcx>, span: Span) -> Result<Slot, Diagnostic> {
+        let Some(local) = self.locals[place.local.index()] else {
+            if place.projection.is_empty() {
+                return Ok(Slot::None);
+            }
+            return Err(self.unsupported(span, "projection of a zero-sized local".to_string()));
+        };
+        let mut slot = match self.stack_slots.get(&local) {
+            Some(&(address, offset)) => Slot::Memory { address, offset },
+            None => Slot::Local(local),
+        };
+        let mut ty = self.body.local_decls[place.local].ty;
+        for element in place.projection.iter() {
+            slot = match (slot, element) {
+                (Slot::Local(address), ProjectionElem::Deref) => Slot::Memory { address, offset: 0 },
+                (Slot::Memory { address, offset }, ProjectionElem::Field(field, _)) => {
+                    let layout = self.tcx.layout_of(self.body.typing_env(self.tcx).as_query_input(ty))
+                        .map_err(|e| self.unsupported(span, format!("field of `{}` ({})", ty, e)))?;
+                    Slot::Memory { address, offset: offset + layout.fields.offset(field.index()).bytes() as u32 }
+                }
+                (_, ProjectionElem::Deref) => {
+                    return Err(self.unsupported(span, "dereferencing a pointer stored in memory".to_string()));
+                }
+                (_, other) => return Err(self.unsupported(span, format!("place projection `{:?}`", other))),
+            };
+            ty = ty::PlaceTy::from_ty(ty).projection_ty(self.tcx, element).ty;
+        }
+        Ok(slot)
+    }
+
+    fn terminator(
+        &mut self,
+        block: BasicBlock,
+        terminator: &mir::Terminator<'tcx>,
+        out: &mut Vec<Instruction>,
+    ) -> Result<Terminator, Diagnostic> {
+        let span = terminator.source_info.span;
+        match &terminator.kind {
+            TerminatorKind::Goto { target }
+            | TerminatorKind::FalseEdge { real_target: target, .. }
+            | TerminatorKind::FalseUnwind { real_target: target, .. } => Ok(jump(*target)),
+            TerminatorKind::SwitchInt { discr, targets } => {
+                let wide = matches!(self.wasm_type(discr.ty(&self.body.local_decls, self.tcx), span)?, Some(Type::I64));
+                let Some(value) = self.operand(discr, span, out)? else {
+                    return Err(self.unsupported(span, "switch on a zero-sized value".to_string()));
+                };
+                let cases = targets.iter()
+                    .map(|(value, target)| {
+                        let value = if wide { Constant::I64(value as i64) } else { Constant::I32(value as i32) };
+                        (Operand::Constant(value), BlockId(target.index()))
+                    })
+                    .collect();
+                Ok(Terminator::Switch { value, targets: cases, default_target: BlockId(targets.otherwise().index()) })
+            }
+            TerminatorKind::Return => {
+                let value = self.locals[mir::RETURN_PLACE.index()].map(Operand::Local);
+                Ok(Terminator::Return { value })
+            }
+            // Panics unwind to the host, never to cleanup blocks, so unwinding
+            // never resumes
+            TerminatorKind::Unreachable | TerminatorKind::UnwindResume | TerminatorKind::UnwindTerminate(_) => {
+                Ok(Terminator::Unreachable)
+            }
+            TerminatorKind::Drop { place, target, .. } => {
+                let ty = place.ty(&self.body.local_decls, self.tcx).ty;
+                if is_must_consume(self.tcx, ty) {
+                    let decl = &self.body.local_decls[place.local];
+                    return Err(self.error(
+                        codes::UNCONSUMED_LINEAR_VALUE,
+                        span,
+                        format!("linear value of type `{}` is dropped without being consumed", ty),
+                    )
+                    .secondary(self.location(decl.source_info.span), "declared here")
+                    .help("move it into a call that takes ownership, such as its drop function or `consume` method"));
+                }
+                if ty.needs_drop(self.tcx, self.body.typing_env(self.tcx)) {
+                    // Drop glue takes the address of the value
+                    let address = match self.place(place, span)? {
+                        Slot::Memory { address, offset: 0 } => Operand::Local(address),
+                        Slot::Memory { address, offset } => {
+                            out.push(Instruction::BinaryOp {
+                                op: BinaryOp::Add,
+                                left: Operand::Local(address),
+                                right: Operand::Constant(Constant::I32(offset as i32)),
+                            });
+                            Operand::StackValue(0)
+                        }
+                        Slot::Local(_) | Slot::None => {
+                            return Err(self.unsupported(span, format!("dropping a local of type `{}`", ty)));
+                        }
+                    };
+                    let func_ref = self.drop_glue(ty);
+                    out.push(Instruction::Call { func_ref, args: vec![address] });
+                }
+                Ok(jump(*target))
+            }
+            TerminatorKind::Assert { cond, expected, msg, target, .. } => {
+                let Some(condition) = self.operand(cond, span, out)? else {
+                    return Err(self.unsupported(span, "assertion on a zero-sized value".to_string()));
+                };
+                let failure = self.panic_block(assert_message(msg), span);
+                let (then_block, else_block) = if *expected {
+                    (BlockId(target.index()), failure)
+                } else {
+                    (failure, BlockId(target.index()))
+                };
+                Ok(Terminator::Branch { condition, then_block, else_block })
+            }
+            TerminatorKind::Call { func, args, destination, target, .. } => {
+                let Some((callee, generic_args)) = func.const_fn_def() else {
+                    return Err(self.unsupported(span, "indirect call".to_string()));
+                };
+                // Trait methods, such as `Drop::drop` in drop glue, resolve to their impl
+                let callee = match ty::Instance::try_resolve(self.tcx, self.body.typing_env(self.tcx), callee, generic_args) {
+                    Ok(Some(instance)) => instance.def_id(),
+                    _ => callee,
+                };
+                let path = self.tcx.def_path_str(callee);
+                if PANIC_ENTRY_POINTS.iter().any(|prefix| path.starts_with(prefix)) {
+                    // The formatted message would need `core::fmt`; the
+                    // location identifies the panic well enough
+                    return Ok(panic(self.panic_message("explicit panic", span)));
+                }
+                if ALLOC_ENTRY_POINTS.contains(&path.as_str()) || DEALLOC_ENTRY_POINTS.contains(&path.as_str()) {
+                    self.allocator_call(&path, args, destination, span, out)?;
+                    return Ok(match target {
+                        Some(target) => jump(*target),
+                        None => Terminator::Unreachable,
+                    });
+                }
+                let wait = WAIT_ENTRY_POINTS.iter().find(|(entry, _)| *entry == path).map(|(_, ty)| ty.clone());
+                if wait.is_some() || NOTIFY_ENTRY_POINTS.contains(&path.as_str()) {
+                    self.futex_call(&path, wait, args, destination, span, out)?;
+                    return Ok(match target {
+                        Some(target) => jump(*target),
+                        None => Terminator::Unreachable,
+                    });
+                }
+                let Some(&func_ref) = self.functions.get(&callee) else {
+                    return Err(self.error(
+                        codes::UNSUPPORTED_MIR,
+                        span,
+                        format!("call to `{}`, which is not part of the module", path),
+                    )
+                    .note("only calls between the lowered items are resolved"));
+                };
+                let mut operands = Vec::new();
+                for arg in args.iter() {
+                    if let Some(operand) = self.operand(&arg.node, span, out)? {
+                        operands.push(operand);
+                    }
+                }
+                out.push(Instruction::Call { func_ref, args: operands });
+                let ty = destination.ty(&self.body.local_decls, self.tcx).ty;
+                if self.wasm_type(ty, span)?.is_some() {
+                    self.store(destination, ty, Some(Operand::StackValue(0)), span, out)?;
+                }
+                Ok(match target {
+                    Some(target) => jump(*target),
+                    None => Terminator::Unreachable,
+                })
+            }
+            other => Err(self.unsupported(span, format!("terminator `{:?}` in bb{}", other, block.index()))),
+        }
+    }
+
+    /// Lowers a call into the global allocator
+    fn allocator_call(
+        &mut self,
+        path: &str,
+        args: &[Spanned<MirOperand<'tcx>>],
+        destination: &Place<'tcx>,
+        span: Span,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), Diagnostic> {
+        let Some(first) = args.first() else {
+            return Err(self.unsupported(span, format!("call to `{}` without arguments", path)));
+        };
+        let Some(first) = self.operand(&first.node, span, out)? else {
+            return Err(self.unsupported(span, format!("zero-sized argument to `{}`", path)));
+        };
+        if DEALLOC_ENTRY_POINTS.contains(&path) {
+            out.push(Instruction::MemoryFree { address: first });
+            return Ok(());
+        }
+        let align = match args.get(1).map(|align| self.operand(&align.node, span, out)).transpose()?.flatten() {
+            Some(Operand::Constant(Constant::I32(align))) => Some(align as u32),
+            _ => return Err(self.unsupported(span, format!("call to `{}` with a run-time alignment", path))),
+        };
+        out.push(Instruction::MemoryAlloc { size: first, align });
+        let ty = destination.ty(&self.body.local_decls, self.tcx).ty;
+        self.store(destination, ty, Some(Operand::StackValue(0)), span, out)
+    }
+
+    /// Lowers a call to a futex intrinsic, waiting on a `wait` typed value
+    /// or notifying
+    fn futex_call(
+        &mut self,
+        path: &str,
+        wait: Option<Type>,
+        args: &[Spanned<MirOperand<'tcx>>],
+        destination: &Place<'tcx>,
+        span: Span,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), Diagnostic> {
+        let mut operands = Vec::new();
+        for arg in args {
+            if let Some(operand) = self.operand(&arg.node, span, out)? {
+                operands.push(operand);
+            }
+        }
+        let instruction = match (wait, operands.as_slice()) {
+            (Some(ty), [address, expected, timeout]) => Instruction::AtomicWait {
+                address: address.clone(),
+                expected: expected.clone(),
+                timeout: timeout.clone(),
+                ty,
+            },
+            (None, [address, count]) => Instruction::AtomicNotify { address: address.clone(), count: count.clone() },
+            _ => return Err(self.unsupported(span, format!("call to `{}` with unexpected arguments", path))),
+        };
+        out.push(instruction);
+        if !self.function.capabilities.contains(&Capability::AtomicMemory) {
+            self.function.add_capability(Capability::AtomicMemory);
+        }
+        let ty = destination.ty(&self.body.local_decls, self.tcx).ty;
+        self.store(destination, ty, Some(Operand::StackValue(0)), span, out)
+    }
+
+    /// Function index of the drop glue for `ty`, requesting it on first use
+    fn drop_glue(&mut self, ty: Ty<'tcx>) -> u32 {
+        let position = match self.drop_glue.iter().position(|&glue| glue == ty) {
+            Some(position) => position,
+            None => {
+                self.drop_glue.push(ty);
+                self.drop_glue.len() - 1
+            }
+        };
+        (self.functions.len() + position) as u32
+    }
+
+    /// Thread local index of a `#[thread_local]` static, requesting it on
+    /// first use
+    fn thread_local(&mut self, item: DefId) -> u32 {
+        let position = match self.thread_locals.iter().position(|&existing| existing == item) {
+            Some(position) => position,
+            None => {
+                self.thread_locals.push(item);
+                self.thread_locals.len() - 1
+            }
+        };
+        position as u32
+    }
+
+    /// Block panicking with `message` at `span`, created on first use
+    fn panic_block(&mut self, message: &str, span: Span) -> BlockId {
+        let message = self.panic_message(message, span);
+        let position = match self.panic_blocks.iter().position(|existing| *existing == message) {
+            Some(position) => position,
+            None => {
+                self.panic_blocks.push(message);
+                self.panic_blocks.len() - 1
+            }
+        };
+        BlockId(self.body.basic_blocks.len() + position)
+    }
+
+    fn panic_message(&self, message: &str, span: Span) -> String {
+        let location = self.location(span);
+        format!("{} at {}:{}:{}", message, location.file, location.line, location.column)
+    }
+
+    fn binary_op(&self, op: BinOp, signed: bool, span: Span) -> Result<BinaryOp, Diagnostic> {
+        Ok(match op {
+            BinOp::Add | BinOp::AddUnchecked => BinaryOp::Add,
+            BinOp::Sub | BinOp::SubUnchecked => BinaryOp::Sub,
+            BinOp::Mul | BinOp::MulUnchecked => BinaryOp::Mul,
+            BinOp::Div => BinaryOp::Div,
+            BinOp::Rem => BinaryOp::Mod,
+            BinOp::BitXor => BinaryOp::Xor,
+            BinOp::BitAnd => BinaryOp::And,
+            BinOp::BitOr => BinaryOp::Or,
+            BinOp::Shl | BinOp::ShlUnchecked => BinaryOp::Shl,
+            BinOp::Shr | BinOp::ShrUnchecked if signed => BinaryOp::Sar,
+            BinOp::Shr | BinOp::ShrUnchecked => BinaryOp::Shr,
+            BinOp::Eq => BinaryOp::Eq,
+            BinOp::Ne => BinaryOp::Ne,
+            BinOp::Lt => BinaryOp::Lt,
+            BinOp::Le => BinaryOp::Le,
+            BinOp::Gt => BinaryOp::Gt,
+            BinOp::Ge => BinaryOp::Ge,
+            BinOp::AddWithOverflow | BinOp::SubWithOverflow | BinOp::MulWithOverflow => {
+                return Err(self.unsupported(span, format!("checked arithmetic `{:?}`", op))
+                    .help("build with `-C overflow-checks=off`"));
+            }
+            other => return Err(self.unsupported(span, format!("binary operator `{:?}`", other))),
+        })
+    }
+
+    /// Conversion a cast needs, or `None` if it is a move
+    ///
+    /// Float-to-int casts saturate, as `as` does.
+    fn cast_op(&self, kind: CastKind, source: Ty<'tcx>, target: Ty<'tcx>, span: Span) -> Result<Option<ConvertOp>, Diagnostic> {
+        let from = self.wasm_type(source, span)?;
+        let to = self.wasm_type(target, span)?;
+        Ok(match (kind, from, to) {
+            (CastKind::IntToInt | CastKind::Transmute | CastKind::PtrToPtr, from, to) if from == to => None,
+            (CastKind::IntToInt, Some(Type::I32), Some(Type::I64)) => Some(ConvertOp::Extend { signed: source.is_signed() }),
+            (CastKind::IntToInt, Some(Type::I64), Some(Type::I32)) => Some(ConvertOp::Wrap),
+            (CastKind::FloatToInt, Some(Type::F32 | Type::F64), Some(to @ (Type::I32 | Type::I64)))
+                if narrow_bits(target).is_none() =>
+            {
+                Some(ConvertOp::TruncSat { to, signed: target.is_signed() })
+            }
+            (CastKind::IntToFloat, Some(Type::I32 | Type::I64), Some(to @ (Type::F32 | Type::F64))) => {
+                Some(ConvertOp::FromInt { to, signed: source.is_signed() })
+            }
+            (CastKind::FloatToFloat, Some(Type::F32), Some(Type::F64)) => Some(ConvertOp::Promote),
+            (CastKind::FloatToFloat, Some(Type::F64), Some(Type::F32)) => Some(ConvertOp::Demote),
+            (CastKind::Transmute, Some(from), Some(to))
+                if ConvertOp::Reinterpret.result(&from).as_ref() == Some(&to) =>
+            {
+                Some(ConvertOp::Reinterpret)
+            }
+            _ => return Err(self.unsupported(span, format!("cast from `{}` to `{}`", source, target))),
+        })
+    }
+
+    /// WasmIR type of a MIR type, or `None` if it is zero-sized
+    fn wasm_type(&self, ty: Ty<'tcx>, span: Span) -> Result<Option<Type>, Diagnostic> {
+        Ok(Some(match ty.kind() {
+            ty::Bool | ty::Char => Type::I32,
+            ty::Int(IntTy::I64) | ty::Uint(UintTy::U64) => Type::I64,
+            ty::Int(IntTy::I128) | ty::Uint(UintTy::U128) => Type::I128,
+            ty::Int(_) | ty::Uint(_) => Type::I32,
+            ty::Float(FloatTy::F32) => Type::F32,
+            ty::Float(FloatTy::F64) => Type::F64,
+            ty::Ref(_, pointee, _) | ty::RawPtr(pointee, _) if pointee.is_sized(self.tcx, self.body.typing_env(self.tcx)) => {
+                Type::I32
+            }
+            ty::Tuple(fields) if fields.is_empty() => return Ok(None),
+            ty::Never | ty::FnDef(..) => return Ok(None),
+            ty::Adt(adt, _) => match self.tcx.item_name(adt.did()).as_str() {
+                "ExternRef" => Type::ExternRef(self.tcx.def_path_str(adt.did())),
+                "FuncRef" => Type::FuncRef,
+                "Own" | "Borrow" => Type::I32,
+                _ => return Err(self.unsupported(span, format!("type `{}`", ty))),
+            },
+            _ => return Err(self.unsupported(span, format!("type `{}`", ty))),
+        }))
+    }
+
+    fn location(&self, span: Span) -> SourceLocation {
+        let position = self.tcx.sess.source_map().lookup_char_pos(span.lo());
+        SourceLocation {
+            file: position.file.name.prefer_local().to_string(),
+            line: position.line as u32,
+            column: position.col.0 as u32 + 1,
+        }
+    }
+
+    fn error(&self, code: Code, span: Span, message: String) -> Diagnostic {
+        Diagnostic::error(code, message).primary(self.location(span), "")
+    }
+
+    fn unsupported(&self, span: Span, what: String) -> Diagnostic {
+        self.error(codes::UNSUPPORTED_MIR, span, format!("{} is not supported by the WasmIR lowering", what))
+    }
+}
+
+/// Locals borrowed or pointed to as a whole, in order
+fn address_taken(body: &Body<'_>) -> BTreeSet<mir::Local> {
+    body.basic_blocks.iter()
+        .flat_map(|data| &data.statements)
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::Assign(assign) => match &assign.1 {
+                Rvalue::Ref(_, _, borrowed) | Rvalue::RawPtr(_, borrowed) if borrowed.projection.is_empty() => {
+                    Some(borrowed.local)
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn jump(target: BasicBlock) -> Terminator {
+    Terminator::Jump { target: BlockId(target.index()) }
+}
+
+fn panic(message: String) -> Terminator {
+    Terminator::Panic { message: Some(Operand::Constant(Constant::String(message))) }
+}
+
+/// Width of the integer types narrower than the `i32` they are kept in
+fn narrow_bits(ty: Ty<'_>) -> Option<u8> {
+    match ty.kind() {
+        ty::Int(IntTy::I8) | ty::Uint(UintTy::U8) => Some(8),
+        ty::Int(IntTy::I16) | ty::Uint(UintTy::U16) => Some(16),
+        _ => None,
+    }
+}
+
+/// Message of a failed `Assert`, without its runtime operands
+fn assert_message(msg: &mir::AssertMessage<'_>) -> &'static str {
+    match msg {
+        mir::AssertKind::BoundsCheck { .. } => "index out of bounds",
+        mir::AssertKind::MisalignedPointerDereference { .. } => "misaligned pointer dereference",
+        other => other.description(),
+    }
+}
+
+/// Checks whether values of `ty` must be consumed rather than dropped,
+/// matching `MirType::must_consume`
+fn is_must_consume<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> bool {
+    match ty.kind() {
+        ty::Adt(adt, _) => {
+            matches!(tcx.item_name(adt.did()).as_str(), "ExternRef" | "FuncRef" | "Own") || is_linear_adt(tcx, adt.did())
+        }
+        _ => false,
+    }
+}
+
+/// Checks whether `#[wasm::linear]` implemented `wasm::linear::Linear` for
+/// the ADT
+fn is_linear_adt(tcx: TyCtxt<'_>, adt: DefId) -> bool {
+    let Some(linear) = tcx.all_traits().find(|&def_id| tcx.def_path_str(def_id) == wasm::linear::LINEAR_TRAIT_PATH)
+    else {
+        return false;
+    };
+    tcx.all_impls(linear)
+        .any(|item| tcx.type_of(item).instantiate_identity().ty_adt_def().is_some_and(|def| def.did() == adt))
+}
@@ -376,9 +376,13 @@ impl SizeAnalyzer {
         let base_size = match instruction {
             Instruction::LocalGet { .. } => 2,
             Instruction::LocalSet { .. } => 3,
+            Instruction::GlobalSet { .. } => 3,
+            Instruction::ThreadLocalAddress { .. } => 5,
             Instruction::BinaryOp { .. } => 2,
             Instruction::UnaryOp { .. } => 2,
+            Instruction::Convert { .. } => 1,
             Instruction::Call { args, .. } => 1 + args.len(),
+            Instruction::CallImport { args, .. } => 1 + args.len(),
             Instruction::Return { .. } => 1,
             Instruction::Branch { .. } => 2,
             Instruction::Jump { .. } => 1,
@@ -387,6 +391,8 @@ impl SizeAnalyzer {
             Instruction::MemoryStore { .. } => 3,
             Instruction::MemoryAlloc { .. } => 2,
             Instruction::MemoryFree { .. } => 1,
+            Instruction::MemorySize => 2,
+            Instruction::MemoryGrow { .. } => 2,
             Instruction::NewObject { args, .. } => 2 + args.len(),
             Instruction::DropObject { .. } => 1,
             Instruction::ExternRefLoad { .. } => 4,
@@ -404,6 +410,8 @@ impl SizeAnalyzer {
             Instruction::CallIndirect { args, .. } => 2 + args.len(),
             Instruction::AtomicOp { .. } => 3,
             Instruction::CompareExchange { .. } => 4,
+            Instruction::AtomicWait { .. } => 4,
+            Instruction::AtomicNotify { .. } => 3,
             Instruction::LinearOp { .. } => 2,
             Instruction::CapabilityCheck { .. } => 1,
             Instruction::Nop => 1,
@@ -526,6 +534,7 @@ impl SizeAnalyzer {
         match ty {
             Type::I32 => Ok(4),
             Type::I64 => Ok(8),
+            Type::I128 => Ok(16),
             Type::F32 => Ok(4),
             Type::F64 => Ok(8),
             Type::ExternRef(_) => Ok(4), // Handle
@@ -633,6 +642,7 @@ impl SizeAnalyzer {
         match ty {
             Type::I32 => "i32".to_string(),
             Type::I64 => "i64".to_string(),
+            Type::I128 => "i128".to_string(),
             Type::F32 => "f32".to_string(),
             Type::F64 => "f64".to_string(),
             Type::ExternRef(name) => format!("externref_{}", name),
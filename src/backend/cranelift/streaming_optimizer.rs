@@ -8,6 +8,7 @@ use crate::wasmir::{WasmIR, Instruction, Terminator, Operand, Signature};
 use crate::backend::cranelift::{
     thin_monomorphization::{ThinMonomorphizationContext, StreamingLayout, CodeSegment, SegmentType, RelocationInfo, RelocationType, FunctionId},
 };
+use crate::backend::pgo::PgoProfile;
 use rustc_target::spec::Target;
 use std::collections::{HashMap, HashSet, VecDeque};
 
@@ -23,6 +24,8 @@ pub struct StreamingLayoutOptimizer {
     layout_algorithm: LayoutAlgorithm,
     /// Optimization configuration
     config: StreamingConfig,
+    /// Profile whose counts replace the name-based frequency estimates
+    profile: Option<PgoProfile>,
 }
 
 /// Configuration for streaming optimization
@@ -176,6 +179,7 @@ impl StreamingLayoutOptimizer {
             segmentation_strategy: SegmentationStrategy::new(),
             layout_algorithm: LayoutAlgorithm::new(),
             config: StreamingConfig::default(),
+            profile: None,
         }
     }
 
@@ -187,9 +191,16 @@ impl StreamingLayoutOptimizer {
             segmentation_strategy: SegmentationStrategy::new(),
             layout_algorithm: LayoutAlgorithm::new(),
             config,
+            profile: None,
         }
     }
 
+    /// Orders functions by the counts of an instrumented run
+    pub fn with_profile(mut self, profile: PgoProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
     /// Optimizes the layout of WasmIR functions for streaming
     pub fn optimize_layout(
         &mut self,
@@ -267,9 +278,13 @@ impl StreamingLayoutOptimizer {
         match instruction {
             Instruction::LocalGet { .. } => 2,
             Instruction::LocalSet { .. } => 3,
+            Instruction::GlobalSet { .. } => 3,
+            Instruction::ThreadLocalAddress { .. } => 5,
             Instruction::BinaryOp { .. } => 2,
             Instruction::UnaryOp { .. } => 2,
+            Instruction::Convert { .. } => 1,
             Instruction::Call { args, .. } => 1 + args.len(),
+            Instruction::CallImport { args, .. } => 1 + args.len(),
             Instruction::Return { .. } => 1,
             Instruction::Branch { .. } => 2,
             Instruction::Jump { .. } => 1,
@@ -278,6 +293,8 @@ impl StreamingLayoutOptimizer {
             Instruction::MemoryStore { .. } => 3,
             Instruction::MemoryAlloc { .. } => 2,
             Instruction::MemoryFree { .. } => 1,
+            Instruction::MemorySize => 2,
+            Instruction::MemoryGrow { .. } => 2,
             Instruction::NewObject { args, .. } => 2 + args.len(),
             Instruction::DropObject { .. } => 1,
             Instruction::ExternRefLoad { .. } => 4,
@@ -295,6 +312,8 @@ impl StreamingLayoutOptimizer {
             Instruction::CallIndirect { args, .. } => 2 + args.len(),
             Instruction::AtomicOp { .. } => 3,
             Instruction::CompareExchange { .. } => 4,
+            Instruction::AtomicWait { .. } => 4,
+            Instruction::AtomicNotify { .. } => 3,
             Instruction::LinearOp { .. } => 2,
             Instruction::CapabilityCheck { .. } => 1,
             Instruction::Nop => 1,
@@ -306,6 +325,7 @@ impl StreamingLayoutOptimizer {
         match ty {
             crate::wasmir::Type::I32 => 4,
             crate::wasmir::Type::I64 => 8,
+            crate::wasmir::Type::I128 => 16,
             crate::wasmir::Type::F32 => 4,
             crate::wasmir::Type::F64 => 8,
             crate::wasmir::Type::ExternRef(_) => 4,
@@ -354,6 +374,19 @@ impl StreamingLayoutOptimizer {
     fn estimate_call_frequency(&self, function: &WasmIR) -> CallFrequency {
         let name = &function.name;
 
+        if let Some(profile) = &self.profile {
+            let hotness = profile.hotness(name);
+            return if profile.function_count(name) == 0 {
+                CallFrequency::Rare
+            } else if hotness >= 0.5 {
+                CallFrequency::VeryFrequent
+            } else if hotness >= 0.1 {
+                CallFrequency::Frequent
+            } else {
+                CallFrequency::Occasional
+            };
+        }
+
         if name.starts_with("__wasmrust_") || name.contains("init") {
             CallFrequency::Occasional
         } else if name.contains("panic") || name.contains("error") {
@@ -1336,6 +1369,23 @@ mod tests {
         assert_eq!(app_frequency, CallFrequency::Unknown);
     }
 
+    #[test]
+    fn test_profile_replaces_frequency_estimates() {
+        let target = rustc_target::spec::Target {
+            arch: "wasm32".to_string(),
+            ..Default::default()
+        };
+
+        let profile = PgoProfile::parse("1000\tprocess_data\n20\t__wasmrust_init\n").unwrap();
+        let optimizer = StreamingLayoutOptimizer::new(target).with_profile(profile);
+
+        let function = |name: &str| WasmIR::new(name.to_string(), Signature { params: vec![], returns: None });
+
+        assert_eq!(optimizer.estimate_call_frequency(&function("process_data")), CallFrequency::VeryFrequent);
+        assert_eq!(optimizer.estimate_call_frequency(&function("__wasmrust_init")), CallFrequency::Occasional);
+        assert_eq!(optimizer.estimate_call_frequency(&function("core_util")), CallFrequency::Rare);
+    }
+
     #[test]
     fn test_hotness_estimation() {
         let target = rustc_target::spec::Target {
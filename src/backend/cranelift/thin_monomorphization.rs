@@ -20,6 +20,7 @@ use crate::backend::cranelift::{
     size_analyzer::SizeAnalyzer,
     streaming_optimizer::StreamingLayoutOptimizer,
 };
+use crate::backend::pgo::PgoProfile;
 use rustc_middle::ty::{self, TyS, TyKind, Instance};
 use rustc_middle::mir::{Body, BasicBlock, Terminator};
 use rustc_target::spec::Target;
@@ -366,6 +367,12 @@ impl ThinMonomorphizationContext {
         }
     }
 
+    /// Orders functions by the counts of an instrumented run, when streaming is enabled
+    pub fn with_profile(mut self, profile: PgoProfile) -> Self {
+        self.streaming_optimizer = self.streaming_optimizer.map(|optimizer| optimizer.with_profile(profile));
+        self
+    }
+
     /// Enhanced analyze and optimize with thinning and components
     pub fn analyze_and_optimize(
         &mut self,
@@ -647,7 +654,7 @@ impl ThinMonomorphizationContext {
     /// Checks if a type contains generic type parameters
     fn contains_generic_type(&self, type_ref: &Type) -> bool {
         match type_ref {
-            Type::I32 | Type::I64 | Type::F32 | Type::F64 => false,
+            Type::I32 | Type::I64 | Type::I128 | Type::F32 | Type::F64 => false,
             Type::Ref(ty) => ty.starts_with("T") || ty.contains('_'),
             Type::Array { element_type, .. } => self.contains_generic_type(element_type),
             Type::Struct { fields } => fields.iter().any(|f| self.contains_generic_type(f)),
@@ -756,7 +763,7 @@ impl ThinMonomorphizationContext {
         type_params: &[TypeParam],
     ) -> Result<GenericType, MonomorphizationError> {
         match concrete_type {
-            Type::I32 | Type::I64 | Type::F32 | Type::F64 => {
+            Type::I32 | Type::I64 | Type::I128 | Type::F32 | Type::F64 => {
                 Ok(GenericType::Concrete(concrete_type.clone()))
             }
             Type::Ref(ty) => {
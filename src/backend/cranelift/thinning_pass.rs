@@ -571,6 +571,7 @@ impl ThinningPass {
         match ty {
             Type::I32 => "i32".to_string(),
             Type::I64 => "i64".to_string(),
+            Type::I128 => "i128".to_string(),
             Type::F32 => "f32".to_string(),
             Type::F64 => "f64".to_string(),
             Type::ExternRef(name) => format!("externref_{}", name),
@@ -601,6 +602,7 @@ impl ThinningPass {
         match ty {
             Type::I32 | Type::ExternRef(_) => Ok((4, 4)),
             Type::I64 | Type::F64 => Ok((8, 8)),
+            Type::I128 => Ok((16, 8)),
             Type::F32 => Ok((4, 4)),
             Type::Pointer(_) => Ok((4, 4)), // 32-bit pointers
             Type::Array { element_type, size } => {
@@ -0,0 +1,588 @@
+//! Demangling of Rust symbol names
+//!
+//! rustc mangles symbols in one of two schemes: the legacy one, an
+//! Itanium-like `_ZN` path of length-prefixed segments ending in a hash,
+//! and v0 (`-C symbol-mangling-version=v0`), a `_R` path with generic
+//! arguments, impls, closures, and backreferences to earlier parts of the
+//! symbol. `demangle` turns either into the path rustc would print, as in
+//! `core::fmt::write` or `<alloc::vec::Vec<u8> as core::clone::Clone>::clone`,
+//! leaving off the legacy hash and the v0 instantiating crate.
+//!
+//! Outputs name functions demangled when `CompilerConfig::demangle` is set:
+//! the symbol tables and relocations of `CompilationResult`, the `name`
+//! section, and size reports. Names that are not Rust symbols, and the few
+//! v0 forms this does not read (punycode identifiers and constants other
+//! than integers, `bool`, and `char`), stay raw.
+
+use std::fmt::Write;
+
+/// Nesting of v0 types and paths past which a symbol is left raw
+const MAX_DEPTH: u32 = 64;
+
+/// Demangles a legacy or v0 Rust symbol; `None` for anything else
+pub fn demangle(symbol: &str) -> Option<String> {
+    // Linkers prefix an underscore on some platforms, and LLVM suffixes `.llvm.<hash>`
+    let symbol = symbol.split_once(".llvm.").map_or(symbol, |(symbol, _)| symbol);
+    if let Some(legacy) = ["_ZN", "ZN", "__ZN"].iter().find_map(|prefix| symbol.strip_prefix(prefix)) {
+        demangle_legacy(legacy)
+    } else if let Some(v0) = ["_R", "R", "__R"].iter().find_map(|prefix| symbol.strip_prefix(prefix)) {
+        demangle_v0(v0)
+    } else {
+        None
+    }
+}
+
+/// `symbol` demangled if it is a Rust symbol, as is otherwise
+pub fn demangle_or_raw(symbol: &str) -> String {
+    demangle(symbol).unwrap_or_else(|| symbol.to_string())
+}
+
+/// Demangles the segments of a legacy symbol following `_ZN`
+fn demangle_legacy(mut rest: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = rest[..digits].parse().ok()?;
+        let segment = rest.get(digits..digits + len)?;
+        segments.push(segment);
+        rest = &rest[digits + len..];
+    }
+    let suffix = &rest[1..];
+    if !suffix.is_empty() && !suffix.starts_with(['.', '$']) {
+        return None;
+    }
+    if let [.., hash] = segments[..] {
+        if segments.len() > 1 && is_legacy_hash(hash) {
+            segments.pop();
+        }
+    }
+    if segments.is_empty() {
+        return None;
+    }
+    let segments: Option<Vec<String>> = segments.into_iter().map(unescape_legacy).collect();
+    Some(segments?.join("::"))
+}
+
+fn is_legacy_hash(segment: &str) -> bool {
+    segment.len() == 17 && segment.starts_with('h') && segment[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Undoes the `$..$` escapes and `..` of a legacy segment
+fn unescape_legacy(segment: &str) -> Option<String> {
+    // An escape leading a segment gets an underscore so the segment starts like an identifier
+    let mut rest = match segment.strip_prefix("_$") {
+        Some(_) => &segment[1..],
+        None => segment,
+    };
+    let mut out = String::new();
+    while let Some(c) = rest.chars().next() {
+        if c == '$' {
+            let end = rest[1..].find('$')? + 1;
+            let escape = &rest[1..end];
+            out.push(match escape {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                _ => char::from_u32(u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?)?,
+            });
+            rest = &rest[end + 1..];
+        } else if let Some(after) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = after;
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Some(out)
+}
+
+/// Demangles a v0 symbol following `_R`
+fn demangle_v0(symbol: &str) -> Option<String> {
+    // Only the initial encoding version, which has no number, is defined
+    if symbol.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut parser =
+        V0Parser { symbol: symbol.as_bytes(), next: 0, depth: 0, bound_lifetimes: 0, out: String::new() };
+    parser.path(true)?;
+    let demangled = std::mem::take(&mut parser.out);
+    // The instantiating crate and a vendor suffix follow the path
+    if parser.peek().is_some_and(|c| c.is_ascii_uppercase()) {
+        parser.path(false)?;
+    }
+    match parser.peek() {
+        None | Some(b'.') | Some(b'$') => Some(demangled),
+        Some(_) => None,
+    }
+}
+
+/// Reads a v0 symbol, writing what it reads to `out`
+struct V0Parser<'a> {
+    symbol: &'a [u8],
+    next: usize,
+    depth: u32,
+    /// Lifetimes bound by the `for<..>` binders being read
+    bound_lifetimes: u64,
+    out: String,
+}
+
+impl V0Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.symbol.get(self.next).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.peek() == Some(byte);
+        if matched {
+            self.next += 1;
+        }
+        matched
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.next += 1;
+        Some(byte)
+    }
+
+    /// `_` for 0, or digits of `0-9a-zA-Z` and `_` for one more than their value
+    fn base62(&mut self) -> Option<u64> {
+        if self.eat(b'_') {
+            return Some(0);
+        }
+        let mut value: u64 = 0;
+        loop {
+            let digit = match self.byte()? {
+                b'_' => return value.checked_add(1),
+                c @ b'0'..=b'9' => c - b'0',
+                c @ b'a'..=b'z' => c - b'a' + 10,
+                c @ b'A'..=b'Z' => c - b'A' + 36,
+                _ => return None,
+            };
+            value = value.checked_mul(62)?.checked_add(digit as u64)?;
+        }
+    }
+
+    /// Value of an optional `<tag><base-62-number>`, 0 when absent and one more than the number otherwise
+    fn optional_base62(&mut self, tag: u8) -> Option<u64> {
+        if self.eat(tag) {
+            self.base62()?.checked_add(1)
+        } else {
+            Some(0)
+        }
+    }
+
+    /// Digits without leading zeros, so a `0` stands alone
+    fn decimal(&mut self) -> Option<usize> {
+        if self.eat(b'0') {
+            return Some(0);
+        }
+        let start = self.next;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.next += 1;
+        }
+        std::str::from_utf8(&self.symbol[start..self.next]).ok()?.parse().ok()
+    }
+
+    fn identifier(&mut self) -> Option<&str> {
+        if self.eat(b'u') {
+            // Punycode
+            return None;
+        }
+        let len = self.decimal()?;
+        self.eat(b'_');
+        let start = self.next;
+        self.next = start.checked_add(len).filter(|&end| end <= self.symbol.len())?;
+        std::str::from_utf8(&self.symbol[start..self.next]).ok()
+    }
+
+    /// Jumps back to an earlier part of the symbol for the duration of `read`
+    fn backref(&mut self, read: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        let at = self.next - 1;
+        let target = usize::try_from(self.base62()?).ok().filter(|&target| target < at)?;
+        let resume = std::mem::replace(&mut self.next, target);
+        read(self)?;
+        self.next = resume;
+        Some(())
+    }
+
+    /// Runs `read` one level deeper, failing past `MAX_DEPTH`
+    fn nested(&mut self, read: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return None;
+        }
+        read(self)?;
+        self.depth -= 1;
+        Some(())
+    }
+
+    /// Reads without writing, as for the impl paths printed as their type
+    fn skip(&mut self, read: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        let len = self.out.len();
+        read(self)?;
+        self.out.truncate(len);
+        Some(())
+    }
+
+    /// A path, whose generic arguments take `::<` in value position
+    fn path(&mut self, in_value: bool) -> Option<()> {
+        self.nested(|p| {
+            match p.byte()? {
+                b'C' => {
+                    p.optional_base62(b's')?;
+                    let name = p.identifier()?.to_string();
+                    p.out.push_str(&name);
+                }
+                b'N' => {
+                    let namespace = p.byte()?;
+                    if !namespace.is_ascii_alphabetic() {
+                        return None;
+                    }
+                    p.path(in_value)?;
+                    let disambiguator = p.optional_base62(b's')?;
+                    let name = p.identifier()?.to_string();
+                    if namespace.is_ascii_uppercase() {
+                        let kind = match namespace {
+                            b'C' => "closure".to_string(),
+                            b'S' => "shim".to_string(),
+                            other => (other as char).to_string(),
+                        };
+                        let name = if name.is_empty() { name } else { format!(":{}", name) };
+                        write!(p.out, "::{{{}{}#{}}}", kind, name, disambiguator).ok()?;
+                    } else if !name.is_empty() {
+                        write!(p.out, "::{}", name).ok()?;
+                    }
+                }
+                b'M' => {
+                    p.skip(|p| p.impl_path())?;
+                    p.out.push('<');
+                    p.ty()?;
+                    p.out.push('>');
+                }
+                b'X' => {
+                    p.skip(|p| p.impl_path())?;
+                    p.trait_impl()?;
+                }
+                b'Y' => p.trait_impl()?,
+                b'I' => {
+                    p.path(in_value)?;
+                    p.out.push_str(if in_value { "::<" } else { "<" });
+                    p.list(b'E', ", ", |p| p.generic_arg())?;
+                    p.out.push('>');
+                }
+                b'B' => p.backref(|p| p.path(in_value))?,
+                _ => return None,
+            }
+            Some(())
+        })
+    }
+
+    fn impl_path(&mut self) -> Option<()> {
+        self.optional_base62(b's')?;
+        self.path(false)
+    }
+
+    /// `<Type as Trait>`
+    fn trait_impl(&mut self) -> Option<()> {
+        self.out.push('<');
+        self.ty()?;
+        self.out.push_str(" as ");
+        self.path(false)?;
+        self.out.push('>');
+        Some(())
+    }
+
+    /// Items read by `read` until `end`, separated by `separator`
+    fn list(&mut self, end: u8, separator: &str, mut read: impl FnMut(&mut Self) -> Option<()>) -> Option<usize> {
+        let mut count = 0;
+        while !self.eat(end) {
+            if count > 0 {
+                self.out.push_str(separator);
+            }
+            read(self)?;
+            count += 1;
+        }
+        Some(count)
+    }
+
+    fn generic_arg(&mut self) -> Option<()> {
+        if self.eat(b'L') {
+            self.lifetime()
+        } else if self.eat(b'K') {
+            self.constant()
+        } else {
+            self.ty()
+        }
+    }
+
+    /// A lifetime following `L`: 0 for erased ones, or the binder, innermost first
+    fn lifetime(&mut self) -> Option<()> {
+        let index = self.base62()?;
+        self.lifetime_name(index)
+    }
+
+    fn lifetime_name(&mut self, index: u64) -> Option<()> {
+        if index == 0 {
+            self.out.push_str("'_");
+            return Some(());
+        }
+        match self.bound_lifetimes.checked_sub(index)? {
+            depth @ 0..=25 => write!(self.out, "'{}", (b'a' + depth as u8) as char).ok(),
+            depth => write!(self.out, "'_{}", depth).ok(),
+        }
+    }
+
+    /// Reads an optional `for<..>` binder, then `read` with its lifetimes bound
+    fn binder(&mut self, read: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        let count = self.optional_base62(b'G')?;
+        if count > 0 {
+            self.out.push_str("for<");
+            for lifetime in 1..=count {
+                if lifetime > 1 {
+                    self.out.push_str(", ");
+                }
+                self.bound_lifetimes += 1;
+                self.lifetime_name(1)?;
+            }
+            self.out.push_str("> ");
+        }
+        read(self)?;
+        self.bound_lifetimes -= count;
+        Some(())
+    }
+
+    fn ty(&mut self) -> Option<()> {
+        self.nested(|p| {
+            let tag = p.peek()?;
+            if let Some(basic) = basic_type(tag) {
+                p.next += 1;
+                p.out.push_str(basic);
+                return Some(());
+            }
+            if matches!(tag, b'C' | b'M' | b'X' | b'Y' | b'N' | b'I') {
+                return p.path(false);
+            }
+            p.next += 1;
+            match tag {
+                b'A' => {
+                    p.out.push('[');
+                    p.ty()?;
+                    p.out.push_str("; ");
+                    p.constant()?;
+                    p.out.push(']');
+                }
+                b'S' => {
+                    p.out.push('[');
+                    p.ty()?;
+                    p.out.push(']');
+                }
+                b'T' => {
+                    p.out.push('(');
+                    if p.list(b'E', ", ", |p| p.ty())? == 1 {
+                        p.out.push(',');
+                    }
+                    p.out.push(')');
+                }
+                b'R' | b'Q' => {
+                    p.out.push('&');
+                    if p.eat(b'L') {
+                        let lifetime = p.base62()?;
+                        if lifetime != 0 {
+                            p.lifetime_name(lifetime)?;
+                            p.out.push(' ');
+                        }
+                    }
+                    if tag == b'Q' {
+                        p.out.push_str("mut ");
+                    }
+                    p.ty()?;
+                }
+                b'P' => {
+                    p.out.push_str("*const ");
+                    p.ty()?;
+                }
+                b'O' => {
+                    p.out.push_str("*mut ");
+                    p.ty()?;
+                }
+                b'F' => p.binder(|p| p.fn_sig())?,
+                b'D' => {
+                    p.out.push_str("dyn ");
+                    p.binder(|p| p.list(b'E', " + ", |p| p.dyn_trait()).map(drop))?;
+                    // The object's lifetime bound, which rustc prints only when not erased
+                    if !p.eat(b'L') {
+                        return None;
+                    }
+                    let lifetime = p.base62()?;
+                    if lifetime != 0 {
+                        p.out.push_str(" + ");
+                        p.lifetime_name(lifetime)?;
+                    }
+                }
+                b'B' => p.backref(|p| p.ty())?,
+                _ => return None,
+            }
+            Some(())
+        })
+    }
+
+    /// `fn(A, B) -> R`, with its `unsafe` and `extern` qualifiers
+    fn fn_sig(&mut self) -> Option<()> {
+        if self.eat(b'U') {
+            self.out.push_str("unsafe ");
+        }
+        if self.eat(b'K') {
+            let abi = if self.eat(b'C') { "C".to_string() } else { self.identifier()?.replace('_', "-") };
+            write!(self.out, "extern \"{}\" ", abi).ok()?;
+        }
+        self.out.push_str("fn(");
+        self.list(b'E', ", ", |p| p.ty())?;
+        self.out.push(')');
+        if self.eat(b'u') {
+            return Some(());
+        }
+        self.out.push_str(" -> ");
+        self.ty()
+    }
+
+    /// A trait of a `dyn` type, with its associated type bindings
+    fn dyn_trait(&mut self) -> Option<()> {
+        self.path(false)?;
+        let mut open = false;
+        while self.eat(b'p') {
+            // Bindings join the trait's generic arguments, as in `Trait<A, Assoc = T>`
+            if open {
+                self.out.push_str(", ");
+            } else if self.out.ends_with('>') {
+                self.out.pop();
+                self.out.push_str(", ");
+            } else {
+                self.out.push('<');
+            }
+            open = true;
+            let name = self.identifier()?.to_string();
+            write!(self.out, "{} = ", name).ok()?;
+            self.ty()?;
+        }
+        if open {
+            self.out.push('>');
+        }
+        Some(())
+    }
+
+    /// A constant of an integer type, `bool`, or `char`
+    fn constant(&mut self) -> Option<()> {
+        if self.eat(b'B') {
+            return self.backref(|p| p.constant());
+        }
+        if self.eat(b'p') {
+            self.out.push('_');
+            return Some(());
+        }
+        let ty = self.byte()?;
+        let negative = self.eat(b'n');
+        let start = self.next;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.next += 1;
+        }
+        let hex = std::str::from_utf8(&self.symbol[start..self.next]).ok()?;
+        if !self.eat(b'_') {
+            return None;
+        }
+        let value = if hex.is_empty() { 0 } else { u128::from_str_radix(hex, 16).ok()? };
+        match ty {
+            b'b' if !negative => match value {
+                0 => self.out.push_str("false"),
+                1 => self.out.push_str("true"),
+                _ => return None,
+            },
+            b'c' if !negative => write!(self.out, "{:?}", char::from_u32(u32::try_from(value).ok()?)?).ok()?,
+            b'a' | b's' | b'l' | b'x' | b'n' | b'i' => {
+                write!(self.out, "{}{}", if negative { "-" } else { "" }, value).ok()?
+            }
+            b'h' | b't' | b'm' | b'y' | b'o' | b'j' if !negative => write!(self.out, "{}", value).ok()?,
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+fn basic_type(tag: u8) -> Option<&'static str> {
+    Some(match tag {
+        b'a' => "i8",
+        b'b' => "bool",
+        b'c' => "char",
+        b'd' => "f64",
+        b'e' => "str",
+        b'f' => "f32",
+        b'h' => "u8",
+        b'i' => "isize",
+        b'j' => "usize",
+        b'l' => "i32",
+        b'm' => "u32",
+        b'n' => "i128",
+        b'o' => "u128",
+        b's' => "i16",
+        b't' => "u16",
+        b'u' => "()",
+        b'v' => "...",
+        b'x' => "i64",
+        b'y' => "u64",
+        b'z' => "!",
+        b'p' => "_",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_symbols() {
+        assert_eq!(demangle("_ZN4core3fmt5write17h0123456789abcdefE").unwrap(), "core::fmt::write");
+        assert_eq!(
+            demangle("_ZN63_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..clone..Clone$GT$5clone17h0123456789abcdefE")
+                .unwrap(),
+            "<alloc::vec::Vec<T> as core::clone::Clone>::clone"
+        );
+        assert_eq!(demangle("_ZN3app4main17h0123456789abcdefE.llvm.4242").unwrap(), "app::main");
+        assert_eq!(demangle("__ZN3app4mainE").unwrap(), "app::main");
+        assert_eq!(demangle("_ZN3app4ma"), None);
+        assert_eq!(demangle_or_raw("memcpy"), "memcpy");
+    }
+
+    #[test]
+    fn test_v0_symbols() {
+        let cases = [
+            ("_RNvCsciUMGOH01m6_1b4user", "b::user"),
+            ("_RNCNvCsciUMGOH01m6_1b4users1_0B3_", "b::user::{closure#3}"),
+            (
+                "_RNvMs7_NtCslNYArtu3iFV_5alloc5boxedINtB5_3BoxReE4leakCsciUMGOH01m6_1b",
+                "<alloc::boxed::Box<&str>>::leak",
+            ),
+            ("_RNvXCsciUMGOH01m6_1bhNtB2_2Tr2go", "<u8 as b::Tr>::go"),
+            ("_RINvCsciUMGOH01m6_1b1kKb1_Kc78_Kln5_EB2_", "b::k::<true, 'x', -5>"),
+            ("_RINvCsciUMGOH01m6_1b2idFG_RL0_hERL0_hEB2_", "b::id::<for<'a> fn(&'a u8) -> &'a u8>"),
+            (
+                concat!(
+                    "_RINvCsciUMGOH01m6_1b2idINtNtCsgEmfK2I1SDS_4core6option6Option",
+                    "RDINtB2_3Tr2xEp3OutmNtNtBq_6marker4SendEL_EEB2_",
+                ),
+                "b::id::<core::option::Option<&dyn b::Tr2<i64, Out = u32> + core::marker::Send>>",
+            ),
+        ];
+        for (symbol, demangled) in cases {
+            assert_eq!(demangle(symbol).as_deref(), Some(demangled), "{}", symbol);
+        }
+        assert_eq!(demangle("_RNvCs1234_3appu3foo"), None);
+        assert_eq!(demangle("_RNvCs1234_3app4mainB9_"), None);
+    }
+}
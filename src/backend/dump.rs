@@ -0,0 +1,167 @@
+//! Intermediate artifacts of chosen functions, for isolating backend bugs
+//!
+//! A `FunctionFilter` picks functions by a glob over their names, such as
+//! `app::parse*`. A `PassDump` given to the compiler keeps the WasmIR of
+//! those functions after each pass that changed them, which `wasm-rustc
+//! --emit=wasmir --emit-filter <glob>` writes out one file per function
+//! and pass.
+
+use std::sync::{Arc, Mutex};
+use wasm::wasmir::WasmModule;
+
+/// Name of the snapshot taken before any pass
+pub const INPUT_PASS: &str = "input";
+
+/// Glob over function names, where `*` matches any run of characters and `?` any one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionFilter {
+    pattern: String,
+}
+
+impl FunctionFilter {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self { pattern: pattern.into() }
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern: Vec<char> = self.pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        let (mut p, mut n) = (0, 0);
+        // Where the last `*` was, and how much of the name it has taken
+        let mut star = None;
+        while n < name.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match star {
+                    Some((star_p, star_n)) => {
+                        star = Some((star_p, star_n + 1));
+                        p = star_p + 1;
+                        n = star_n + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
+/// WasmIR of one function after one pass
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassSnapshot {
+    /// Position of the pass in the pipeline, `INPUT_PASS` being 0
+    pub index: usize,
+    pub pass: String,
+    pub function: String,
+    pub wasmir: String,
+}
+
+impl PassSnapshot {
+    /// File the snapshot is written to, such as `app..parse.03-fold-constants.wasmir`
+    pub fn file_name(&self) -> String {
+        format!("{}.{:02}-{}.wasmir", file_stem(&self.function), self.index, self.pass.replace(' ', "-"))
+    }
+}
+
+/// Snapshots of the functions a filter picks, shared with the compiler taking them
+#[derive(Debug, Clone)]
+pub struct PassDump {
+    filter: FunctionFilter,
+    state: Arc<Mutex<DumpState>>,
+}
+
+#[derive(Debug, Default)]
+struct DumpState {
+    passes: usize,
+    snapshots: Vec<PassSnapshot>,
+}
+
+impl PassDump {
+    pub fn new(filter: FunctionFilter) -> Self {
+        Self { filter, state: Arc::default() }
+    }
+
+    pub fn filter(&self) -> &FunctionFilter {
+        &self.filter
+    }
+
+    /// Snapshots the functions of `module` the filter picks, after `pass`
+    ///
+    /// A function the pass left as it was is skipped, so each snapshot is
+    /// a change to look at.
+    pub fn record(&self, pass: &str, module: &WasmModule) {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = state.passes;
+        state.passes += 1;
+        for function in module.functions.iter().filter(|function| self.filter.matches(&function.name)) {
+            let wasmir = format!("{:#?}\n", function);
+            let last = state.snapshots.iter().rev().find(|snapshot| snapshot.function == function.name);
+            if last.is_some_and(|last| last.wasmir == wasmir) {
+                continue;
+            }
+            state.snapshots.push(PassSnapshot { index, pass: pass.to_string(), function: function.name.clone(), wasmir });
+        }
+    }
+
+    /// Takes the snapshots recorded so far, in pipeline order
+    pub fn take(&self) -> Vec<PassSnapshot> {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.passes = 0;
+        std::mem::take(&mut state.snapshots)
+    }
+}
+
+/// A function name as a file name, with path separators and generics spelled out in dots
+pub fn file_stem(function: &str) -> String {
+    function.chars().map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '-') { c } else { '.' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{Signature, Terminator, WasmIR};
+
+    #[test]
+    fn test_filter_globs_function_names() {
+        let filter = FunctionFilter::new("app::parse*");
+        assert!(filter.matches("app::parse"));
+        assert!(filter.matches("app::parse_header::<u8>"));
+        assert!(!filter.matches("app::print"));
+        assert!(FunctionFilter::new("*::drop_in_place::<*>").matches("core::ptr::drop_in_place::<Vec<u8>>"));
+        assert!(FunctionFilter::new("a?c").matches("abc"));
+        assert!(!FunctionFilter::new("a?c").matches("ac"));
+        assert!(FunctionFilter::new("*").matches(""));
+    }
+
+    #[test]
+    fn test_dump_keeps_changed_functions() {
+        let mut module = WasmModule::new();
+        for name in ["app::main", "app::helper"] {
+            let mut function = WasmIR::new(name.to_string(), Signature { params: vec![], returns: None });
+            function.add_basic_block(vec![], Terminator::Return { value: None });
+            module.add_function(function);
+        }
+        let dump = PassDump::new(FunctionFilter::new("app::main"));
+        dump.record(INPUT_PASS, &module);
+        dump.record("fold constants", &module);
+        module.functions[0].add_basic_block(vec![], Terminator::Unreachable);
+        dump.record("lower stack frames", &module);
+
+        let snapshots = dump.take();
+        let files: Vec<_> = snapshots.iter().map(PassSnapshot::file_name).collect();
+        assert_eq!(files, ["app..main.00-input.wasmir", "app..main.02-lower-stack-frames.wasmir"]);
+        assert!(snapshots[1].wasmir.contains("Unreachable"));
+        assert!(dump.take().is_empty());
+    }
+}
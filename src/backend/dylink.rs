@@ -0,0 +1,160 @@
+//! Shared modules for dynamic linking
+//!
+//! `WasmCodegen::compile_shared` writes a module as a side module of the
+//! dynamic linking ABI of LLVM and Emscripten, for the glue's `loadPlugin`
+//! to link into a running main module; see `JsGlueGenerator::plugins`. A
+//! `dylink.0` section, first in the module, states the memory and table
+//! it needs. The loader reserves them in the main module's memory and
+//! table and passes their start as `env.__memory_base` and
+//! `env.__table_base`. Memory is imported as `env.memory` and the stack
+//! pointer as `env.__stack_pointer`; every other `env` import resolves
+//! against the main module's exports and earlier plugins', so code they
+//! share is not duplicated in each plugin.
+//!
+//! Addresses of symbols come from `GOT.mem` and `GOT.func` globals, which
+//! the loader sets once the module is instantiated. It then calls
+//! `__wasm_apply_relocs` to rebase pointers stored in the module's data,
+//! and `__wasm_call_ctors`.
+//!
+//! Modules compiled here address memory by constant addresses, so they
+//! have the restrictions of objects, see `backend::object`, and need no
+//! memory, table, or relocations of their own. The loader follows the
+//! whole ABI, so side modules linked by `wasm-ld -shared` load too.
+
+use crate::backend::codegen::write_u32;
+
+/// Name of the custom section describing a side module
+pub const DYLINK_SECTION: &str = "dylink.0";
+
+/// Import of the address the loader placed the module's memory at
+pub const MEMORY_BASE_IMPORT: &str = "__memory_base";
+
+/// Import of the index the loader placed the module's table entries at
+pub const TABLE_BASE_IMPORT: &str = "__table_base";
+
+/// Import of the table shared with the main module
+pub const TABLE_IMPORT: &str = "__indirect_function_table";
+
+/// Import module of globals holding data symbols' addresses
+pub const GOT_MEM_MODULE: &str = "GOT.mem";
+
+/// Import module of globals holding functions' table indices
+pub const GOT_FUNC_MODULE: &str = "GOT.func";
+
+/// Export rebasing the pointers stored in the module's data
+pub const APPLY_RELOCS_EXPORT: &str = "__wasm_apply_relocs";
+
+/// Export newer wasm-ld versions rebase pointers with instead
+pub const APPLY_DATA_RELOCS_EXPORT: &str = "__wasm_apply_data_relocs";
+
+/// `dylink.0` subsection stating the memory and table
+const SUBSECTION_MEM_INFO: u8 = 1;
+
+/// `dylink.0` subsection naming the shared modules to load first
+const SUBSECTION_NEEDED: u8 = 2;
+
+/// Memory and table a side module needs, with alignments as powers of two
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemInfo {
+    pub memory_size: u32,
+    pub memory_align: u32,
+    pub table_size: u32,
+    pub table_align: u32,
+}
+
+/// Contents of the `dylink.0` section
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DylinkInfo {
+    pub mem_info: MemInfo,
+    /// Shared modules that must be loaded first
+    pub needed: Vec<String>,
+}
+
+/// Writes the `dylink.0` section, which must come before any other
+pub fn write_dylink_section(output: &mut Vec<u8>, info: &DylinkInfo) {
+    let mut content = Vec::new();
+    write_name(&mut content, DYLINK_SECTION);
+
+    let mut mem_info = Vec::new();
+    let MemInfo { memory_size, memory_align, table_size, table_align } = info.mem_info;
+    for value in [memory_size, memory_align, table_size, table_align] {
+        write_u32(&mut mem_info, value);
+    }
+    write_subsection(&mut content, SUBSECTION_MEM_INFO, &mem_info);
+
+    if !info.needed.is_empty() {
+        let mut needed = Vec::new();
+        write_u32(&mut needed, info.needed.len() as u32);
+        for name in &info.needed {
+            write_name(&mut needed, name);
+        }
+        write_subsection(&mut content, SUBSECTION_NEEDED, &needed);
+    }
+
+    output.push(0);
+    write_u32(output, content.len() as u32);
+    output.extend_from_slice(&content);
+}
+
+fn write_subsection(output: &mut Vec<u8>, id: u8, content: &[u8]) {
+    output.push(id);
+    write_u32(output, content.len() as u32);
+    output.extend_from_slice(content);
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    write_u32(buf, name.len() as u32);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmparser::{Dylink0Subsection, KnownCustom, Parser, Payload};
+
+    fn read(output: &[u8]) -> DylinkInfo {
+        let mut module = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        module.extend_from_slice(output);
+        let mut info = DylinkInfo::default();
+        for payload in Parser::new(0).parse_all(&module) {
+            let Payload::CustomSection(reader) = payload.unwrap() else { continue };
+            let KnownCustom::Dylink0(subsections) = reader.as_known() else { panic!("not a dylink.0 section") };
+            for subsection in subsections {
+                match subsection.unwrap() {
+                    Dylink0Subsection::MemInfo(mem) => {
+                        info.mem_info = MemInfo {
+                            memory_size: mem.memory_size,
+                            memory_align: mem.memory_alignment,
+                            table_size: mem.table_size,
+                            table_align: mem.table_alignment,
+                        };
+                    }
+                    Dylink0Subsection::Needed(needed) => info.needed = needed.iter().map(|s| s.to_string()).collect(),
+                    other => panic!("unexpected subsection {:?}", other),
+                }
+            }
+        }
+        info
+    }
+
+    #[test]
+    fn test_dylink_section_round_trips() {
+        let info = DylinkInfo {
+            mem_info: MemInfo { memory_size: 300, memory_align: 3, table_size: 2, table_align: 0 },
+            needed: vec!["libcore.so".to_string()],
+        };
+        let mut output = Vec::new();
+        write_dylink_section(&mut output, &info);
+        assert_eq!(read(&output), info);
+    }
+
+    #[test]
+    fn test_dylink_section_without_needed_modules() {
+        let mut output = Vec::new();
+        write_dylink_section(&mut output, &DylinkInfo::default());
+        let name = b"\x08dylink.0";
+        assert_eq!(&output[2..2 + name.len()], name);
+        assert_eq!(&output[2 + name.len()..], &[1, 4, 0, 0, 0, 0]);
+        assert_eq!(read(&output), DylinkInfo::default());
+    }
+}
@@ -0,0 +1,478 @@
+//! The Emscripten ABI, for `wasm32-unknown-emscripten`
+//!
+//! Modules built for the target follow Emscripten's conventions, so glue
+//! generated with `JsGlueGenerator::emscripten` runs them, and the C code
+//! of legacy Emscripten builds linked into them:
+//!
+//! - `main` takes `argc` and `argv` and returns the exit status. Compiling
+//!   for the target wraps a Rust `main` in one, see `wrap_main`, and the
+//!   glue exports `callMain`, which passes it the arguments it is given.
+//! - System calls of Emscripten's libc are imports the glue implements:
+//!   the `wasi_snapshot_preview1` functions for descriptors, clocks,
+//!   randomness, and the environment, and the `env` helpers for the heap
+//!   and time. Standard output and error go to the console a line at a
+//!   time. There is no file system, so other descriptors fail with
+//!   `EBADF`, and `__syscall_*` imports return `-ENOSYS`. `exit` and
+//!   `proc_exit` end `callMain` with the status.
+//! - `EM_JS` functions are JavaScript stored in the module, which
+//!   `read_em_js` extracts for the glue to define as their `env` imports.
+//!   Their bodies see the `HEAP*` views and `UTF8ToString` of Emscripten's
+//!   runtime, but not the rest of `Module`.
+//!
+//! Host imports the glue does not implement come from `userImports`,
+//! which also override the glue's. `EM_ASM` blocks, whose code is looked
+//! up at run time, and threads are not supported.
+
+use crate::backend::js_glue::js_string;
+use crate::backend::BackendError;
+use std::fmt::Write;
+use wasm::wasmir::{
+    Constant, ExportKind, Import, Instruction, Operand, Signature, Terminator, Type, WasmIR, WasmModule, ALLOC_EXPORT,
+};
+use wasmparser::{BinaryReaderError, ConstExpr, DataKind, ExternalKind, Operator, Parser, Payload, TypeRef};
+
+/// Export running the program, given `argc` and `argv`
+pub const MAIN_EXPORT: &str = "main";
+
+/// Name `wrap_main` gives the `main` it wraps
+pub const ORIGINAL_MAIN: &str = "__original_main";
+
+/// Prefix of the exported addresses of `EM_JS` sources
+pub const EM_JS_PREFIX: &str = "__em_js__";
+
+/// Separates the parameters of an `EM_JS` source from its body
+pub const EM_JS_SEPARATOR: &str = "<::>";
+
+/// Import module of Emscripten's libc helpers and `EM_JS` functions
+pub const ENV_MODULE: &str = "env";
+
+/// Import module of the system calls Emscripten's libc makes through WASI
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// Prefix of Emscripten's system call imports
+const SYSCALL_PREFIX: &str = "__syscall_";
+
+/// Imports the glue implements, by module and name
+const RUNTIME_IMPORTS: &[(&str, &str, &str)] = &[
+    (WASI_MODULE, "fd_write", "fdWrite"),
+    // Standard input is empty
+    (
+        WASI_MODULE,
+        "fd_read",
+        "(fd, iov, iovcnt, pnum) => { new DataView(exports().memory.buffer).setUint32(pnum, 0, true); \
+         return fd === 0 ? 0 : EBADF; }",
+    ),
+    (WASI_MODULE, "fd_close", "(fd) => (fd <= 2 ? 0 : EBADF)"),
+    (WASI_MODULE, "fd_seek", "(fd) => (fd <= 2 ? ESPIPE : EBADF)"),
+    (WASI_MODULE, "fd_sync", "(fd) => (fd <= 2 ? 0 : EBADF)"),
+    (WASI_MODULE, "proc_exit", "(status) => { throw new ExitStatus(status); }"),
+    (
+        WASI_MODULE,
+        "environ_sizes_get",
+        "(pcount, psize) => { const view = new DataView(exports().memory.buffer); view.setUint32(pcount, 0, true); \
+         view.setUint32(psize, 0, true); return 0; }",
+    ),
+    (WASI_MODULE, "environ_get", "() => 0"),
+    (WASI_MODULE, "clock_time_get", "clockTimeGet"),
+    (WASI_MODULE, "random_get", "randomGet"),
+    (ENV_MODULE, "exit", "(status) => { throw new ExitStatus(status); }"),
+    (ENV_MODULE, "abort", "() => { throw new Error(\"abort() called\"); }"),
+    (ENV_MODULE, "_abort_js", "() => { throw new Error(\"abort() called\"); }"),
+    (ENV_MODULE, "emscripten_memcpy_js", "(dest, src, len) => { memoryBytes().copyWithin(dest, src, src + len); }"),
+    (ENV_MODULE, "_emscripten_memcpy_js", "(dest, src, len) => { memoryBytes().copyWithin(dest, src, src + len); }"),
+    (ENV_MODULE, "emscripten_memcpy_big", "(dest, src, len) => { memoryBytes().copyWithin(dest, src, src + len); }"),
+    (ENV_MODULE, "emscripten_resize_heap", "resizeHeap"),
+    (ENV_MODULE, "emscripten_notify_memory_growth", "() => {}"),
+    (ENV_MODULE, "emscripten_get_heap_max", "() => 4294901760"),
+    (ENV_MODULE, "emscripten_date_now", "() => Date.now()"),
+    (ENV_MODULE, "emscripten_get_now", "() => performance.now()"),
+];
+
+/// Gives a Rust `main` exported as `main` Emscripten's signature
+///
+/// The function is renamed `ORIGINAL_MAIN`, as clang names a C `main`
+/// without parameters, and `main` becomes a function of `argc` and
+/// `argv` calling it and returning its `i32` result, or 0. Other `main`
+/// functions, such as those with the signature already, are left as
+/// they are.
+pub fn wrap_main(module: &mut WasmModule) {
+    let Some(export) = module.exports.iter().position(|export| export.name == MAIN_EXPORT) else { return };
+    let ExportKind::Function(original) = module.exports[export].kind else { return };
+    let Some(function) = module.functions.get_mut(original as usize) else { return };
+    if !function.signature.params.is_empty() || !matches!(function.signature.returns, None | Some(Type::I32)) {
+        return;
+    }
+    function.name = ORIGINAL_MAIN.to_string();
+    let status = match function.signature.returns {
+        Some(_) => Operand::StackValue(0),
+        None => Operand::Constant(Constant::I32(0)),
+    };
+
+    let signature = Signature { params: vec![Type::I32, Type::I32], returns: Some(Type::I32) };
+    let mut main = WasmIR::new(MAIN_EXPORT.to_string(), signature);
+    main.add_local(Type::I32);
+    main.add_local(Type::I32);
+    main.add_basic_block(
+        vec![Instruction::Call { func_ref: original, args: vec![] }],
+        Terminator::Return { value: Some(status) },
+    );
+    let main = module.add_function(main);
+    module.exports[export].kind = ExportKind::Function(main);
+}
+
+/// JavaScript function declared with `EM_JS`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmJsFunction {
+    /// Name of the `env` import calling it
+    pub name: String,
+    /// Names of the C parameters
+    pub params: Vec<String>,
+    /// Body, braces included
+    pub body: String,
+}
+
+impl EmJsFunction {
+    /// Reads the `(params)<::>body` source `EM_JS` stores
+    ///
+    /// Parameters are named by the last word of each C declaration.
+    pub fn parse(name: &str, source: &str) -> Option<Self> {
+        let (params, body) = source.split_once(EM_JS_SEPARATOR)?;
+        let params = params.trim().strip_prefix('(')?.strip_suffix(')')?;
+        let params = params
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty() && *param != "void")
+            .map(|param| {
+                let name = param.rsplit(|c: char| c.is_whitespace() || c == '*' || c == '&').next()?;
+                let name = name.trim_end_matches("[]");
+                let valid = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+                valid.then(|| name.to_string())
+            })
+            .collect::<Option<_>>()?;
+        Some(EmJsFunction { name: name.to_string(), params, body: body.trim().to_string() })
+    }
+}
+
+/// Reads the `EM_JS` functions of a module linked by `wasm-ld`
+///
+/// Each has its source in the module's data, at the address of an
+/// exported global named with `EM_JS_PREFIX`.
+pub fn read_em_js(wasm: &[u8]) -> Result<Vec<EmJsFunction>, BackendError> {
+    let invalid = |err: BinaryReaderError| malformed(&err.to_string());
+    let mut imported_globals = 0;
+    // Initial values of the module's own globals, where constant
+    let mut globals = Vec::new();
+    let mut symbols = Vec::new();
+    let mut segments = Vec::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.map_err(invalid)? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if matches!(import.map_err(invalid)?.ty, TypeRef::Global(_)) {
+                        imported_globals += 1;
+                    }
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader {
+                    globals.push(const_i32(&global.map_err(invalid)?.init_expr));
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export.map_err(invalid)?;
+                    match export.name.strip_prefix(EM_JS_PREFIX) {
+                        Some(name) if export.kind == ExternalKind::Global => {
+                            symbols.push((name.to_string(), export.index));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Payload::DataSection(reader) => {
+                for data in reader {
+                    let data = data.map_err(invalid)?;
+                    if let DataKind::Active { offset_expr, .. } = data.kind {
+                        if let Some(offset) = const_i32(&offset_expr) {
+                            segments.push((offset, data.data));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    symbols
+        .into_iter()
+        .map(|(name, index)| {
+            let address = index
+                .checked_sub(imported_globals)
+                .and_then(|index| globals.get(index as usize).copied().flatten())
+                .ok_or_else(|| malformed(&format!("`{}{}` is not a constant address", EM_JS_PREFIX, name)))?;
+            let source = segments
+                .iter()
+                .find_map(|&(offset, bytes)| {
+                    let rest = bytes.get(address.checked_sub(offset)? as usize..)?;
+                    std::str::from_utf8(&rest[..rest.iter().position(|&byte| byte == 0)?]).ok()
+                })
+                .ok_or_else(|| malformed(&format!("no source of `EM_JS` function `{}`", name)))?;
+            EmJsFunction::parse(&name, source)
+                .ok_or_else(|| malformed(&format!("`EM_JS` function `{}` has source `{}`", name, source)))
+        })
+        .collect()
+}
+
+/// Value of an `i32.const` expression
+fn const_i32(expr: &ConstExpr) -> Option<u32> {
+    let mut operators = expr.get_operators_reader();
+    match (operators.read().ok()?, operators.read().ok()?) {
+        (Operator::I32Const { value }, Operator::End) => Some(value as u32),
+        _ => None,
+    }
+}
+
+fn malformed(reason: &str) -> BackendError {
+    BackendError::CompilationFailed(format!("malformed module: {}", reason))
+}
+
+/// The glue's implementation of an import, if it has one
+pub(crate) fn import_body(import: &Import, em_js: &[EmJsFunction]) -> Option<String> {
+    if let Some(&(_, _, body)) = RUNTIME_IMPORTS
+        .iter()
+        .find(|(module, name, _)| *module == import.module && *name == import.name)
+    {
+        return Some(body.to_string());
+    }
+    if import.module != ENV_MODULE {
+        return None;
+    }
+    if em_js.iter().any(|function| function.name == import.name) {
+        let name = js_string(&import.name);
+        return Some(format!("(...args) => {{ updateMemoryViews(); return emJs[{}](...args); }}", name));
+    }
+    import.name.starts_with(SYSCALL_PREFIX).then(|| "() => -ENOSYS".to_string())
+}
+
+/// Exit status, output streams, and the helpers of `RUNTIME_IMPORTS`
+///
+/// With `EM_JS` functions, also the parts of Emscripten's runtime they
+/// use and the functions themselves.
+pub(crate) fn generate_runtime(out: &mut String, em_js: &[EmJsFunction]) {
+    // WASI errno values
+    out.push_str("const EBADF = 8;\n");
+    out.push_str("const ENOSYS = 52;\n");
+    out.push_str("const ESPIPE = 70;\n\n");
+
+    out.push_str("class ExitStatus extends Error {\n");
+    out.push_str("  constructor(status) {\n");
+    out.push_str("    super(`program exited with status ${status}`);\n");
+    out.push_str("    this.status = status;\n");
+    out.push_str("  }\n");
+    out.push_str("}\n\n");
+
+    // Standard output and error, printed a line at a time
+    out.push_str("const outputStreams = [\n");
+    out.push_str("  undefined,\n");
+    out.push_str("  { decoder: new TextDecoder(), line: \"\", print: (line) => console.log(line) },\n");
+    out.push_str("  { decoder: new TextDecoder(), line: \"\", print: (line) => console.error(line) },\n");
+    out.push_str("];\n\n");
+
+    out.push_str("function flushOutput() {\n");
+    out.push_str("  for (const stream of outputStreams.slice(1)) {\n");
+    out.push_str("    if (stream.line) stream.print(stream.line);\n");
+    out.push_str("    stream.line = \"\";\n");
+    out.push_str("  }\n");
+    out.push_str("}\n\n");
+
+    out.push_str("function fdWrite(fd, iov, iovcnt, pnum) {\n");
+    out.push_str("  const stream = outputStreams[fd];\n");
+    out.push_str("  if (!stream) return EBADF;\n");
+    out.push_str("  const view = new DataView(exports().memory.buffer);\n");
+    out.push_str("  let written = 0;\n");
+    out.push_str("  for (let i = 0; i < iovcnt; i++) {\n");
+    out.push_str("    const ptr = view.getUint32(iov + i * 8, true);\n");
+    out.push_str("    const len = view.getUint32(iov + i * 8 + 4, true);\n");
+    out.push_str("    const text = stream.decoder.decode(memoryBytes().slice(ptr, ptr + len), { stream: true });\n");
+    out.push_str("    const lines = (stream.line + text).split(\"\\n\");\n");
+    out.push_str("    stream.line = lines.pop();\n");
+    out.push_str("    for (const line of lines) stream.print(line);\n");
+    out.push_str("    written += len;\n");
+    out.push_str("  }\n");
+    out.push_str("  view.setUint32(pnum, written, true);\n");
+    out.push_str("  return 0;\n");
+    out.push_str("}\n\n");
+
+    // Clock 0 is the realtime clock; the others count from page load
+    out.push_str("function clockTimeGet(clock, precision, ptime) {\n");
+    out.push_str("  const now = clock === 0 ? Date.now() : performance.now();\n");
+    out.push_str("  new DataView(exports().memory.buffer).setBigUint64(ptime, BigInt(Math.round(now * 1e6)), true);\n");
+    out.push_str("  return 0;\n");
+    out.push_str("}\n\n");
+
+    // getRandomValues fills at most 64KiB at a time
+    out.push_str("function randomGet(ptr, len) {\n");
+    out.push_str("  for (let at = 0; at < len; at += 65536) {\n");
+    out.push_str("    crypto.getRandomValues(memoryBytes().subarray(ptr + at, ptr + Math.min(len, at + 65536)));\n");
+    out.push_str("  }\n");
+    out.push_str("  return 0;\n");
+    out.push_str("}\n\n");
+
+    out.push_str("function resizeHeap(size) {\n");
+    out.push_str("  const memory = exports().memory;\n");
+    out.push_str("  const pages = Math.ceil(((size >>> 0) - memory.buffer.byteLength) / 65536);\n");
+    out.push_str("  try {\n");
+    out.push_str("    if (pages > 0) memory.grow(pages);\n");
+    out.push_str("    return 1;\n");
+    out.push_str("  } catch {\n");
+    out.push_str("    return 0;\n");
+    out.push_str("  }\n");
+    out.push_str("}\n\n");
+
+    if em_js.is_empty() {
+        return;
+    }
+    out.push_str("let HEAP8, HEAPU8, HEAP16, HEAPU16, HEAP32, HEAPU32, HEAPF32, HEAPF64, HEAP64, HEAPU64;\n\n");
+    // Views are replaced once memory grows, which detaches the old buffer
+    out.push_str("function updateMemoryViews() {\n");
+    out.push_str("  const buffer = exports().memory.buffer;\n");
+    out.push_str("  if (HEAPU8 && HEAPU8.buffer === buffer) return;\n");
+    out.push_str("  HEAP8 = new Int8Array(buffer);\n");
+    out.push_str("  HEAPU8 = new Uint8Array(buffer);\n");
+    out.push_str("  HEAP16 = new Int16Array(buffer);\n");
+    out.push_str("  HEAPU16 = new Uint16Array(buffer);\n");
+    out.push_str("  HEAP32 = new Int32Array(buffer);\n");
+    out.push_str("  HEAPU32 = new Uint32Array(buffer);\n");
+    out.push_str("  HEAPF32 = new Float32Array(buffer);\n");
+    out.push_str("  HEAPF64 = new Float64Array(buffer);\n");
+    out.push_str("  HEAP64 = new BigInt64Array(buffer);\n");
+    out.push_str("  HEAPU64 = new BigUint64Array(buffer);\n");
+    out.push_str("}\n\n");
+
+    out.push_str("function UTF8ToString(ptr, maxBytesToRead = Infinity) {\n");
+    out.push_str("  const bytes = memoryBytes();\n");
+    out.push_str("  let end = ptr;\n");
+    out.push_str("  while (end - ptr < maxBytesToRead && bytes[end]) end++;\n");
+    out.push_str("  return new TextDecoder().decode(bytes.slice(ptr, end));\n");
+    out.push_str("}\n\n");
+
+    out.push_str("const emJs = {\n");
+    for function in em_js {
+        let _ = writeln!(out, "  {}({}) {},", js_string(&function.name), function.params.join(", "), function.body);
+    }
+    out.push_str("};\n\n");
+}
+
+/// `callMain`, running `main` with the arguments given as strings
+///
+/// `argv` is allocated with the module's `malloc`, or its `__wasm_alloc`
+/// when compiled here; without either, only calls without arguments work.
+/// Returns the exit status, from `main` or `exit`.
+pub(crate) fn generate_call_main(out: &mut String, export: &str) {
+    let _ = writeln!(out, "{}function callMain(args = []) {{", export);
+    out.push_str("  const encoder = new TextEncoder();\n");
+    out.push_str("  const strings = [\"this.program\", ...args].map((arg) => encoder.encode(`${arg}\\0`));\n");
+    let _ = writeln!(out, "  const alloc = exports().malloc ?? exports().{};", ALLOC_EXPORT);
+    out.push_str("  let argv = 0;\n");
+    out.push_str("  if (alloc) {\n");
+    out.push_str("    const start = 4 * (strings.length + 1);\n");
+    out.push_str("    const size = strings.reduce((total, bytes) => total + bytes.length, start);\n");
+    out.push_str("    argv = alloc(size, 4) >>> 0;\n");
+    out.push_str("    const view = new DataView(exports().memory.buffer);\n");
+    out.push_str("    let at = argv + start;\n");
+    out.push_str("    strings.forEach((bytes, i) => {\n");
+    out.push_str("      view.setUint32(argv + 4 * i, at, true);\n");
+    out.push_str("      memoryBytes().set(bytes, at);\n");
+    out.push_str("      at += bytes.length;\n");
+    out.push_str("    });\n");
+    out.push_str("    view.setUint32(argv + 4 * strings.length, 0, true);\n");
+    out.push_str("  } else if (args.length) {\n");
+    out.push_str("    throw new Error(\"passing arguments to main needs an exported malloc\");\n");
+    out.push_str("  }\n");
+    out.push_str("  try {\n");
+    let _ = writeln!(out, "    return exports().{}(argv ? strings.length : 0, argv) ?? 0;", MAIN_EXPORT);
+    out.push_str("  } catch (error) {\n");
+    out.push_str("    if (error instanceof ExitStatus) return error.status;\n");
+    out.push_str("    throw error;\n");
+    out.push_str("  } finally {\n");
+    out.push_str("    flushOutput();\n");
+    out.push_str("  }\n");
+    out.push_str("}\n\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::{write_u32, WasmCodegen};
+
+    fn section(id: u8, content: &[u8]) -> Vec<u8> {
+        let mut section = vec![id];
+        write_u32(&mut section, content.len() as u32);
+        section.extend_from_slice(content);
+        section
+    }
+
+    #[test]
+    fn test_wraps_rust_main_in_emscripten_main() {
+        let mut module = WasmModule::new();
+        let mut rust_main = WasmIR::new("main".to_string(), Signature { params: vec![], returns: None });
+        rust_main.add_basic_block(vec![], Terminator::Return { value: None });
+        let rust_main = module.add_function(rust_main);
+        module.export_function(MAIN_EXPORT, rust_main);
+
+        wrap_main(&mut module);
+        assert_eq!(module.functions[0].name, ORIGINAL_MAIN);
+        let ExportKind::Function(main) = module.exports[0].kind else { panic!("main is not a function") };
+        assert_eq!(module.functions[main as usize].signature, Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        });
+        wasmparser::validate(&WasmCodegen::new().compile(&module).unwrap()).unwrap();
+
+        // Wrapped already
+        wrap_main(&mut module);
+        assert_eq!(module.functions.len(), 2);
+    }
+
+    #[test]
+    fn test_reads_em_js_sources_from_data() {
+        let source = b"(int x, const char *label, double scale[])<::>{ return x * scale; }\0";
+        let mut wasm = vec![0, 97, 115, 109, 1, 0, 0, 0];
+        wasm.extend(section(5, &[1, 0, 1]));
+        // Two globals, the second holding the source's address
+        wasm.extend(section(6, &[2, 127, 1, 65, 0, 11, 127, 0, 65, 16, 11]));
+        let mut exports = vec![1, 12];
+        exports.extend_from_slice(b"__em_js__add");
+        exports.extend([3, 1]);
+        wasm.extend(section(7, &exports));
+        let mut data = vec![1, 0, 65, 8, 11];
+        write_u32(&mut data, 8 + source.len() as u32);
+        data.extend([b'x'; 8]);
+        data.extend_from_slice(source);
+        wasm.extend(section(11, &data));
+
+        assert_eq!(read_em_js(&wasm).unwrap(), [EmJsFunction {
+            name: "add".to_string(),
+            params: vec!["x".to_string(), "label".to_string(), "scale".to_string()],
+            body: "{ return x * scale; }".to_string(),
+        }]);
+        assert_eq!(EmJsFunction::parse("f", "(void)<::>{}").unwrap().params, Vec::<String>::new());
+        assert!(EmJsFunction::parse("f", "(int *)<::>{}").is_none());
+    }
+
+    #[test]
+    fn test_glue_implements_syscalls_and_em_js_imports() {
+        let em_js = [EmJsFunction { name: "add".to_string(), params: vec![], body: "{}".to_string() }];
+        let import = |module: &str, name: &str| Import {
+            module: module.to_string(),
+            name: name.to_string(),
+            kind: wasm::wasmir::ImportKind::Function(Signature { params: vec![], returns: None }),
+        };
+        assert_eq!(import_body(&import(WASI_MODULE, "fd_write"), &em_js).unwrap(), "fdWrite");
+        assert_eq!(import_body(&import(ENV_MODULE, "__syscall_openat"), &em_js).unwrap(), "() => -ENOSYS");
+        assert!(import_body(&import(ENV_MODULE, "add"), &em_js).unwrap().contains("emJs[\"add\"](...args)"));
+        assert!(import_body(&import(ENV_MODULE, "add"), &[]).is_none());
+        assert!(import_body(&import("host", "__syscall_openat"), &em_js).is_none());
+    }
+}
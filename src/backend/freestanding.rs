@@ -0,0 +1,163 @@
+//! Freestanding builds, with no standard library or runtime
+//!
+//! `BuildProfile::Freestanding` is for `#![no_std]` crates that run on the
+//! bare engine, such as plugins and firmware. `FreestandingPass` drops
+//! panic messages, so panics trap without formatting and leave no message
+//! data behind. `check` then asserts that the module needs nothing beyond
+//! the intrinsics the compiler emits inline, such as the shadow stack
+//! sized by `CompilerConfig::stack_size`: no heap allocator, no imports
+//! from the WasmRust runtime or WASI, and no symbols of `std` or `alloc`.
+
+use std::fmt;
+use wasm::memory::allocator::needs_allocator;
+use wasm::threading::runtime::SPAWN_IMPORT_MODULE;
+use wasm::wasmir::{Terminator, WasmModule, JS_IMPORT_MODULE};
+
+/// Crates whose symbols must not reach a freestanding module
+pub const STD_CRATES: [&str; 2] = ["std", "alloc"];
+
+/// Import modules of the runtimes a freestanding module does without
+pub const RUNTIME_IMPORT_MODULES: [&str; 3] = [JS_IMPORT_MODULE, SPAWN_IMPORT_MODULE, "wasi_snapshot_preview1"];
+
+/// Result of running the freestanding pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FreestandingReport {
+    /// Panics that lost their message and now only trap
+    pub dropped_panic_messages: usize,
+}
+
+/// Strips what a module would need the runtime for
+#[derive(Debug, Default)]
+pub struct FreestandingPass {
+    report: FreestandingReport,
+}
+
+impl FreestandingPass {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn run(&mut self, module: &mut WasmModule) -> &FreestandingReport {
+        let terminators = module.functions.iter_mut()
+            .flat_map(|function| function.basic_blocks.iter_mut())
+            .map(|block| &mut block.terminator);
+        for terminator in terminators {
+            if let Terminator::Panic { message: message @ Some(_) } = terminator {
+                *message = None;
+                self.report.dropped_panic_messages += 1;
+            }
+        }
+        &self.report
+    }
+}
+
+/// Checks that a module runs without the standard library or a runtime
+pub fn check(module: &WasmModule) -> Result<(), FreestandingViolation> {
+    if needs_allocator(module) {
+        return Err(FreestandingViolation::Allocator);
+    }
+    for import in &module.imports {
+        let name = format!("{}::{}", import.module, import.name);
+        if RUNTIME_IMPORT_MODULES.contains(&import.module.as_str()) {
+            return Err(FreestandingViolation::RuntimeImport(name));
+        }
+        if names_std(&import.name) {
+            return Err(FreestandingViolation::StdSymbol(name));
+        }
+    }
+    match module.functions.iter().find(|function| names_std(&function.name)) {
+        Some(function) => Err(FreestandingViolation::StdSymbol(function.name.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Whether a symbol has a path into one of `STD_CRATES`, as in `<alloc::vec::Vec<u8> as Drop>::drop`
+fn names_std(symbol: &str) -> bool {
+    STD_CRATES.iter().any(|krate| {
+        symbol.match_indices(krate).any(|(start, _)| {
+            let previous = symbol[..start].chars().next_back();
+            let starts_path = !matches!(previous, Some(c) if c.is_alphanumeric() || c == '_');
+            starts_path && symbol[start + krate.len()..].starts_with("::")
+        })
+    })
+}
+
+/// What keeps a module from being freestanding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FreestandingViolation {
+    /// Heap allocation, or an export marshaling through the heap
+    Allocator,
+    /// Import, as `module::name`, from one of `RUNTIME_IMPORT_MODULES`
+    RuntimeImport(String),
+    /// Function or import from one of `STD_CRATES`
+    StdSymbol(String),
+}
+
+impl fmt::Display for FreestandingViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FreestandingViolation::Allocator => {
+                write!(f, "freestanding modules have no allocator, but this one allocates")
+            }
+            FreestandingViolation::RuntimeImport(name) => {
+                write!(f, "freestanding modules have no runtime, but this one imports {}", name)
+            }
+            FreestandingViolation::StdSymbol(name) => {
+                write!(f, "`{}` leaks the standard library into a freestanding module", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FreestandingViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{Constant, Instruction, Operand, Signature, WasmIR};
+
+    fn function(name: &str, terminator: Terminator) -> WasmIR {
+        let mut function = WasmIR::new(name.to_string(), Signature { params: vec![], returns: None });
+        function.add_basic_block(vec![], terminator);
+        function
+    }
+
+    #[test]
+    fn test_pass_drops_panic_messages() {
+        let mut module = WasmModule::new();
+        let message = Operand::Constant(Constant::String("index out of bounds".to_string()));
+        module.add_function(function("app::get", Terminator::Panic { message: Some(message) }));
+        module.add_function(function("app::fail", Terminator::Panic { message: None }));
+
+        let report = FreestandingPass::new().run(&mut module).clone();
+        assert_eq!(report.dropped_panic_messages, 1);
+        assert!(module.panic_messages().is_empty());
+        assert_eq!(check(&module), Ok(()));
+    }
+
+    #[test]
+    fn test_check_rejects_runtime_and_std() {
+        let mut module = WasmModule::new();
+        module.add_function(function("<core::option::Option<u8> as app::Stdlike>::get", Terminator::Unreachable));
+        module.add_function(function("app::nostd::run", Terminator::Unreachable));
+        assert_eq!(check(&module), Ok(()));
+
+        let mut leaky = module.clone();
+        leaky.add_function(function("<alloc::vec::Vec<u8> as core::ops::Drop>::drop", Terminator::Unreachable));
+        assert_eq!(
+            check(&leaky).unwrap_err().to_string(),
+            "`<alloc::vec::Vec<u8> as core::ops::Drop>::drop` leaks the standard library into a freestanding module"
+        );
+
+        let mut importing = module.clone();
+        importing.declare_panic_hook();
+        assert!(matches!(check(&importing), Err(FreestandingViolation::RuntimeImport(_))));
+
+        let mut allocating = module;
+        let mut boxed = WasmIR::new("app::boxed".to_string(), Signature { params: vec![], returns: None });
+        let alloc = Instruction::MemoryAlloc { size: Operand::Constant(Constant::I32(8)), align: Some(4) };
+        boxed.add_basic_block(vec![alloc], Terminator::Return { value: None });
+        allocating.add_function(boxed);
+        assert_eq!(check(&allocating), Err(FreestandingViolation::Allocator));
+    }
+}
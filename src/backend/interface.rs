@@ -0,0 +1,344 @@
+//! Interfaces of compiled core modules
+//!
+//! `read_interface` recovers what the JS glue needs from a binary built by
+//! another toolchain, such as `cargo build --target wasm32-unknown-unknown`:
+//! function imports, exported functions and their signatures, and the
+//! memory limits. Functions come back without bodies, so the result feeds
+//! `JsGlueGenerator` but cannot be compiled again. Exports declared with
+//! `#[wasm::export]` and imports declared with `#[wasm::import]` also get
+//! back their interop signatures, so the glue passes their strings,
+//! slices, and vectors through memory, and their JavaScript objects as
+//! externref table handles.
+
+use crate::backend::BackendError;
+use wasm::host::export::{read_exports, EXPORTS_SECTION};
+use wasm::host::import::{read_imports, IMPORTS_SECTION};
+use wasm::wasmir::{
+    ExportKind, Export, ImportKind, InteropSignature, InteropType, MemoryType, Signature, Type, WasmIR, WasmModule,
+};
+
+/// Reads the imports, exports, and memory of a core module
+///
+/// Multi-value results, and imports of anything but functions and memory,
+/// are rejected since the glue cannot bind them.
+pub fn read_interface(wasm: &[u8]) -> Result<WasmModule, BackendError> {
+    let mut reader = Reader { bytes: wasm, pos: 0 };
+    if reader.take(4)? != b"\0asm" || reader.take(4)? != [1, 0, 0, 0] {
+        return Err(malformed("not a core module"));
+    }
+
+    let mut module = WasmModule::new();
+    let mut types = Vec::new();
+    let mut imported_functions = 0;
+    let mut interop = Vec::new();
+    let mut import_interop = Vec::new();
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader { bytes: reader.take(size)?, pos: 0 };
+        match id {
+            0 => match section.name()?.as_str() {
+                EXPORTS_SECTION => {
+                    let records = read_exports(section.take(size - section.pos)?);
+                    interop.extend(records.map_err(|err| malformed(&err.to_string()))?);
+                }
+                IMPORTS_SECTION => {
+                    let records = read_imports(section.take(size - section.pos)?);
+                    import_interop.extend(records.map_err(|err| malformed(&err.to_string()))?);
+                }
+                _ => {}
+            },
+            1 => {
+                for _ in 0..section.u32()? {
+                    if section.u8()? != 0x60 {
+                        return Err(unsupported("types other than functions"));
+                    }
+                    let params = (0..section.u32()?).map(|_| section.value_type()).collect::<Result<_, _>>()?;
+                    let returns = match section.u32()? {
+                        0 => None,
+                        1 => Some(section.value_type()?),
+                        _ => return Err(unsupported("multi-value results")),
+                    };
+                    types.push(Signature { params, returns });
+                }
+            }
+            2 => {
+                for _ in 0..section.u32()? {
+                    let from = section.name()?;
+                    let name = section.name()?;
+                    match section.u8()? {
+                        0x00 => {
+                            let signature = section.signature(&types)?;
+                            module.add_import(from, name, signature);
+                            imported_functions += 1;
+                        }
+                        0x02 => section.memory(&mut module)?,
+                        _ => return Err(unsupported(&format!("import {}.{} that is not a function or memory", from, name))),
+                    }
+                }
+            }
+            3 => {
+                for index in 0..section.u32()? {
+                    let signature = section.signature(&types)?;
+                    module.add_function(WasmIR::new(format!("func{}", imported_functions + index), signature));
+                }
+            }
+            5 => {
+                for _ in 0..section.u32()? {
+                    section.memory(&mut module)?;
+                }
+            }
+            7 => {
+                for _ in 0..section.u32()? {
+                    let name = section.name()?;
+                    let kind = section.u8()?;
+                    let index = section.u32()?;
+                    let kind = match kind {
+                        0x00 => match index.checked_sub(imported_functions) {
+                            Some(index) if (index as usize) < module.functions.len() => ExportKind::Function(index),
+                            // Re-exported imports have no wrapper of their own
+                            Some(_) => return Err(malformed("exported function index out of bounds")),
+                            None => continue,
+                        },
+                        0x02 => ExportKind::Memory(index),
+                        _ => continue,
+                    };
+                    module.exports.push(Export { name, kind });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (name, signature) in interop {
+        let Some(index) = module.exports.iter().find_map(|export| match export.kind {
+            ExportKind::Function(index) if export.name == name => Some(index),
+            _ => None,
+        }) else {
+            continue;
+        };
+        if module.functions[index as usize].signature.params.len() != signature.lowered_param_count() {
+            return Err(malformed(&format!("interop signature of {} does not match its parameters", name)));
+        }
+        type_objects(&mut module.functions[index as usize].signature, &signature);
+        module.set_interop_signature(index, signature);
+    }
+    for record in import_interop {
+        let Some(index) = module.find_import(&record.module, &record.name) else {
+            continue;
+        };
+        if module.imports[index as usize].signature().params.len() != record.signature.lowered_param_count() {
+            let name = format!("{}.{}", record.module, record.name);
+            return Err(malformed(&format!("interop signature of {} does not match its parameters", name)));
+        }
+        let ImportKind::Function(signature) = &mut module.imports[index as usize].kind;
+        type_objects(signature, &record.signature);
+        module.set_import_interop_signature(index, record.signature);
+    }
+    Ok(module)
+}
+
+/// Types the `i32`s carrying JavaScript objects as `Type::ExternRef`
+fn type_objects(signature: &mut Signature, interop: &InteropSignature) {
+    let mut lowered = signature.params.iter_mut().skip(usize::from(interop.has_return_area()));
+    for kind in &interop.params {
+        let ty = lowered.next();
+        if kind.is_memory_pair() {
+            lowered.next();
+        }
+        if let (InteropType::ExternRef, Some(ty)) = (kind, ty) {
+            *ty = Type::ExternRef(String::new());
+        }
+    }
+    if interop.returns == InteropType::ExternRef && signature.returns.is_some() {
+        signature.returns = Some(Type::ExternRef(String::new()));
+    }
+}
+
+fn malformed(reason: &str) -> BackendError {
+    BackendError::CompilationFailed(format!("malformed module: {}", reason))
+}
+
+fn unsupported(what: &str) -> BackendError {
+    BackendError::Unsupported(format!("the glue cannot bind {}", what))
+}
+
+/// Cursor over a module's bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BackendError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| malformed("unexpected end"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, BackendError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BackendError> {
+        let mut result = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            result |= u32::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(malformed("LEB128 too long"))
+    }
+
+    fn name(&mut self) -> Result<String, BackendError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| malformed("name is not UTF-8"))
+    }
+
+    fn value_type(&mut self) -> Result<Type, BackendError> {
+        match self.u8()? {
+            0x7f => Ok(Type::I32),
+            0x7e => Ok(Type::I64),
+            0x7d => Ok(Type::F32),
+            0x7c => Ok(Type::F64),
+            _ => Err(unsupported("value types other than numbers")),
+        }
+    }
+
+    fn signature(&mut self, types: &[Signature]) -> Result<Signature, BackendError> {
+        let index = self.u32()? as usize;
+        types.get(index).cloned().ok_or_else(|| malformed("type index out of bounds"))
+    }
+
+    /// Reads memory limits, recording whether the memory is shared
+    fn memory(&mut self, module: &mut WasmModule) -> Result<(), BackendError> {
+        let flags = self.u8()?;
+        let min_pages = self.u32()?;
+        let max_pages = if flags & 0x01 != 0 { Some(self.u32()?) } else { None };
+        module.memory = Some(MemoryType { min_pages, max_pages });
+        module.shared_memory = flags & 0x02 != 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::WasmCodegen;
+    use wasm::host::export::encode_export;
+    use wasm::host::import::encode_import;
+    use wasm::wasmir::{BinaryOp, ElementType, Instruction, Operand, Terminator, JS_IMPORT_MODULE};
+
+    #[test]
+    fn test_reads_back_emitted_interface() {
+        let mut module = WasmModule::new();
+        let signature = Signature { params: vec![Type::I32, Type::I32], returns: Some(Type::I32) };
+        module.add_import("env", "log", Signature { params: vec![Type::F64], returns: None });
+        let mut add = WasmIR::new("add".to_string(), signature.clone());
+        add.add_basic_block(
+            vec![Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::Local(0), right: Operand::Local(1) }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let index = module.add_function(add);
+        module.export_function("add", index);
+        module.set_memory(MemoryType { min_pages: 1, max_pages: Some(4) });
+
+        let interface = read_interface(&WasmCodegen::new().compile(&module).unwrap()).unwrap();
+        assert_eq!(interface.imports, module.imports);
+        assert_eq!(interface.functions.len(), 1);
+        assert_eq!(interface.functions[0].signature, signature);
+        assert!(interface.exports.contains(&Export { name: "add".to_string(), kind: ExportKind::Function(0) }));
+        assert_eq!(interface.memory, module.memory);
+        assert!(!interface.shared_memory);
+    }
+
+    #[test]
+    fn test_rejects_what_the_glue_cannot_bind() {
+        assert!(matches!(read_interface(b"\0asm\x0d\0\x01\0"), Err(BackendError::CompilationFailed(_))));
+        // One function type returning two i32s
+        let multi_value = b"\0asm\x01\0\0\0\x01\x06\x01\x60\x00\x02\x7f\x7f";
+        assert!(matches!(read_interface(multi_value), Err(BackendError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_reads_interop_signatures_of_macro_exports() {
+        let mut module = WasmModule::new();
+        let mut scale = WasmIR::new("scale".to_string(), Signature { params: vec![Type::I32; 3], returns: None });
+        scale.add_basic_block(vec![], Terminator::Return { value: None });
+        let index = module.add_function(scale);
+        module.export_function("scale", index);
+        let wasm = WasmCodegen::new().compile(&module).unwrap();
+        let with_records = |records: &[(&str, &InteropSignature)]| {
+            let mut custom = vec![EXPORTS_SECTION.len() as u8];
+            custom.extend_from_slice(EXPORTS_SECTION.as_bytes());
+            for (name, signature) in records {
+                custom.extend(encode_export(name, signature));
+            }
+            let mut wasm = wasm.clone();
+            wasm.extend([0, custom.len() as u8]);
+            wasm.extend(custom);
+            wasm
+        };
+
+        let interop = InteropSignature {
+            params: vec![InteropType::SliceMut(ElementType::F32), InteropType::Value],
+            returns: InteropType::Value,
+        };
+        // A record for an export the linker dropped is skipped
+        let interface = read_interface(&with_records(&[("scale", &interop), ("gone", &interop)])).unwrap();
+        assert_eq!(interface.interop_signature(index), Some(&interop));
+
+        let mismatched = InteropSignature { params: vec![InteropType::Str], ..interop };
+        let err = read_interface(&with_records(&[("scale", &mismatched)]));
+        assert!(matches!(err, Err(BackendError::CompilationFailed(_))));
+    }
+
+    #[test]
+    fn test_reads_interop_signatures_of_macro_imports() {
+        let mut module = WasmModule::new();
+        let log = module.add_import("console", "log", Signature { params: vec![Type::I32; 2], returns: None });
+        let mut custom = vec![IMPORTS_SECTION.len() as u8];
+        custom.extend_from_slice(IMPORTS_SECTION.as_bytes());
+        let interop = InteropSignature { params: vec![InteropType::Str], returns: InteropType::Value };
+        custom.extend(encode_import("console", "log", &interop));
+        let mut wasm = WasmCodegen::new().compile(&module).unwrap();
+        wasm.extend([0, custom.len() as u8]);
+        wasm.extend(custom);
+
+        let interface = read_interface(&wasm).unwrap();
+        assert_eq!(interface.import_interop_signature(log), Some(&interop));
+    }
+
+    #[test]
+    fn test_types_objects_passed_as_handles() {
+        let mut module = WasmModule::new();
+        let signature = Signature { params: vec![Type::I32; 3], returns: Some(Type::I32) };
+        let append = module.add_import(JS_IMPORT_MODULE, "js_call:append", signature);
+        let mut custom = vec![IMPORTS_SECTION.len() as u8];
+        custom.extend_from_slice(IMPORTS_SECTION.as_bytes());
+        let interop = InteropSignature {
+            params: vec![InteropType::ExternRef, InteropType::Str],
+            returns: InteropType::ExternRef,
+        };
+        custom.extend(encode_import(JS_IMPORT_MODULE, "js_call:append", &interop));
+        let mut wasm = WasmCodegen::new().compile(&module).unwrap();
+        wasm.extend([0, custom.len() as u8]);
+        wasm.extend(custom);
+
+        let interface = read_interface(&wasm).unwrap();
+        let object = Type::ExternRef(String::new());
+        assert_eq!(interface.imports[append as usize].signature(), &Signature {
+            params: vec![object.clone(), Type::I32, Type::I32],
+            returns: Some(object),
+        });
+        assert!(interface.uses_externref_table());
+    }
+}
@@ -0,0 +1,1358 @@
+//! WasmIR interpreter
+//!
+//! Executes a `WasmModule` directly, with the semantics the binary emitter
+//! gives it: operands are evaluated on an operand stack where
+//! `Operand::StackValue` takes the value the previous instruction left,
+//! integer division is signed, `Shr` is logical and `Sar` arithmetic, and
+//! values left beneath a terminator are dropped. It covers the instructions
+//! the emitter supports, so any module the emitter compiles can be run on
+//! both and the results compared. It also evaluates constant functions at
+//! compile time; see `fold_constants`.
+
+use crate::backend::preinit::Snapshot;
+use crate::backend::{
+    Backend, BackendCapabilities, BackendError, BuildProfile, CompilationMetadata, CompilationResult,
+    OptimizationLevel,
+};
+use crate::host::runtime::Value;
+use crate::host::HostFunctions;
+use std::collections::BTreeMap;
+use wasm::wasmir::{
+    BasicBlock, BinaryOp, Constant, ConvertOp, ExportKind, Instruction, Operand, Terminator, Type, UnaryOp, WasmIR,
+    WasmModule,
+};
+
+/// Size of a linear memory page
+const PAGE_SIZE: usize = 65536;
+
+/// Most pages a 32-bit memory can have
+const MAX_PAGES: u32 = 65536;
+
+/// Default fuel of an invocation
+const DEFAULT_FUEL: u64 = 10_000_000;
+
+/// Deepest nesting of calls before the interpreter traps
+///
+/// Calls recurse on the native stack, so this bounds its use.
+const MAX_CALL_DEPTH: u32 = 512;
+
+/// Fuel for constant evaluation
+const CONST_EVAL_FUEL: u64 = 10_000;
+
+/// Interpreter errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterpreterError {
+    /// Execution trapped
+    Trap(String),
+    /// The module uses something the interpreter does not execute
+    Unsupported(String),
+    /// No exported function has the name
+    MissingExport(String),
+    /// No host function satisfies an import
+    MissingImport(String),
+    /// The invocation used up its fuel
+    OutOfFuel,
+}
+
+impl std::fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpreterError::Trap(msg) => write!(f, "Trap: {}", msg),
+            InterpreterError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+            InterpreterError::MissingExport(name) => write!(f, "Missing export: {}", name),
+            InterpreterError::MissingImport(name) => write!(f, "Missing import: {}", name),
+            InterpreterError::OutOfFuel => write!(f, "Out of fuel"),
+        }
+    }
+}
+
+impl std::error::Error for InterpreterError {}
+
+/// Instance of a module being interpreted
+pub struct Interpreter<'a> {
+    module: &'a WasmModule,
+    host: &'a HostFunctions,
+    memory: Vec<u8>,
+    /// Global values; `None` for initializers the interpreter cannot represent
+    globals: Vec<Option<Value>>,
+    fuel: u64,
+    remaining: u64,
+    /// Number of active calls
+    depth: u32,
+    initialized: bool,
+    /// Whether memory and imports are available, off for constant evaluation
+    effects: bool,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Creates an instance whose imports call `host`
+    pub fn new(module: &'a WasmModule, host: &'a HostFunctions) -> Self {
+        let pages = module.memory.map_or(0, |memory| memory.min_pages as usize);
+        let mut memory = vec![0; pages * PAGE_SIZE];
+        for segment in &module.data_segments {
+            let start = segment.offset as usize;
+            if let Some(bytes) = memory.get_mut(start..start + segment.bytes.len()) {
+                bytes.copy_from_slice(&segment.bytes);
+            }
+        }
+        Self {
+            module,
+            host,
+            memory,
+            globals: module.globals.iter().map(|global| constant(&global.init).ok()).collect(),
+            fuel: DEFAULT_FUEL,
+            remaining: DEFAULT_FUEL,
+            depth: 0,
+            initialized: false,
+            effects: true,
+        }
+    }
+
+    /// Sets the fuel of each invocation
+    ///
+    /// Every executed instruction and every block entered costs one unit.
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Gets the linear memory
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Calls an exported function
+    ///
+    /// The first invocation runs the constructors and start function first,
+    /// in the order the emitted start section does.
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Option<Value>, InterpreterError> {
+        let function = self.module.exports.iter()
+            .find_map(|export| match export.kind {
+                ExportKind::Function(index) if export.name == name => Some(index),
+                _ => None,
+            })
+            .ok_or_else(|| InterpreterError::MissingExport(name.to_string()))?;
+        self.initialize()?;
+        self.call(function, args)
+    }
+
+    /// Runs the constructors and start function, unless they already ran
+    pub fn initialize(&mut self) -> Result<(), InterpreterError> {
+        if !self.initialized {
+            self.initialized = true;
+            let init: Vec<u32> = self.module.constructors_in_order().into_iter()
+                .chain(self.module.start_function)
+                .collect();
+            for index in init {
+                self.call(index, &[])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Captures the memory and globals of the instance
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(&self.memory, &self.globals)
+    }
+
+    /// Puts the memory and globals back as they were in `snapshot`
+    ///
+    /// The instance counts as initialized afterwards, since the snapshot
+    /// holds whatever its initializers left.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory = vec![0; snapshot.pages as usize * PAGE_SIZE];
+        for segment in &snapshot.segments {
+            let start = segment.offset as usize;
+            self.memory[start..start + segment.bytes.len()].copy_from_slice(&segment.bytes);
+        }
+        self.globals = snapshot.globals.clone();
+        self.initialized = true;
+    }
+
+    /// Calls a defined function by index
+    pub fn call(&mut self, index: u32, args: &[Value]) -> Result<Option<Value>, InterpreterError> {
+        self.remaining = self.fuel;
+        self.call_function(index, args.to_vec())
+    }
+
+    fn call_function(&mut self, index: u32, args: Vec<Value>) -> Result<Option<Value>, InterpreterError> {
+        let module = self.module;
+        let function = module.functions.get(index as usize)
+            .ok_or_else(|| InterpreterError::Trap(format!("call to unknown function {}", index)))?;
+        let params = &function.signature.params;
+        if args.len() != params.len() || args.iter().zip(params).any(|(arg, ty)| zero(ty).map(|z| z.ty()) != Some(arg.ty())) {
+            return Err(InterpreterError::Trap(format!("arguments do not match the signature of {}", function.name)));
+        }
+
+        let slots = function.locals.len().max(params.len());
+        let mut locals = args;
+        for index in params.len()..slots {
+            let value = zero(&function.locals[index])
+                .ok_or_else(|| InterpreterError::Unsupported(format!("local of type {:?}", function.locals[index])))?;
+            locals.push(value);
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(InterpreterError::Trap("call stack exhausted".to_string()));
+        }
+        self.depth += 1;
+        let result = Frame { interpreter: self, function, locals, stack: Vec::new() }.run();
+        self.depth -= 1;
+        result
+    }
+
+    fn consume_fuel(&mut self) -> Result<(), InterpreterError> {
+        if self.remaining == 0 {
+            return Err(InterpreterError::OutOfFuel);
+        }
+        self.remaining -= 1;
+        Ok(())
+    }
+
+    fn call_import(&mut self, index: u32, args: &[Value]) -> Result<Option<Value>, InterpreterError> {
+        let import = self.module.imports.get(index as usize)
+            .ok_or_else(|| InterpreterError::Trap(format!("call to unknown import {}", index)))?;
+        let name = format!("{}::{}", import.module, import.name);
+        if !self.effects {
+            return Err(InterpreterError::Unsupported(format!("import {} in a constant", name)));
+        }
+        let function = self.host.get(&import.module, &import.name)
+            .ok_or(InterpreterError::MissingImport(name))?;
+        function.call(args).map_err(InterpreterError::Trap)
+    }
+
+    /// Grows memory by `pages`, returning the previous size or -1
+    fn grow_memory(&mut self, pages: Value) -> Result<i32, InterpreterError> {
+        if !self.effects {
+            return Err(InterpreterError::Unsupported("memory.grow in a constant".to_string()));
+        }
+        let Value::I32(pages) = pages else {
+            return Err(InterpreterError::Trap("memory.grow operand is not an i32".to_string()));
+        };
+        let max = self.module.memory.and_then(|memory| memory.max_pages).unwrap_or(MAX_PAGES) as usize;
+        let current = self.memory.len() / PAGE_SIZE;
+        let requested = current + pages as u32 as usize;
+        if requested > max.min(MAX_PAGES as usize) {
+            return Ok(-1);
+        }
+        self.memory.resize(requested * PAGE_SIZE, 0);
+        Ok(current as i32)
+    }
+
+    /// Gets the value of a global
+    fn global(&self, index: u32) -> Result<Value, InterpreterError> {
+        if !self.effects {
+            return Err(InterpreterError::Unsupported("global in a constant".to_string()));
+        }
+        match self.globals.get(index as usize) {
+            Some(Some(value)) => Ok(*value),
+            Some(None) => Err(InterpreterError::Unsupported(format!("initializer of global {}", index))),
+            None => Err(InterpreterError::Trap(format!("invalid global index {}", index))),
+        }
+    }
+
+    /// Gets `len` bytes of memory at an effective address
+    fn memory_range(&mut self, address: Value, offset: u32, len: usize) -> Result<&mut [u8], InterpreterError> {
+        if !self.effects {
+            return Err(InterpreterError::Unsupported("memory access in a constant".to_string()));
+        }
+        let Value::I32(address) = address else {
+            return Err(InterpreterError::Trap("memory address is not an i32".to_string()));
+        };
+        let start = address as u32 as usize + offset as usize;
+        self.memory.get_mut(start..start + len)
+            .ok_or_else(|| InterpreterError::Trap(format!("out of bounds memory access at {}", start)))
+    }
+}
+
+/// Activation of one function
+struct Frame<'i, 'a> {
+    interpreter: &'i mut Interpreter<'a>,
+    function: &'a WasmIR,
+    locals: Vec<Value>,
+    stack: Vec<Value>,
+}
+
+/// Where control goes after a block
+enum Flow {
+    Block(usize),
+    Return(Option<Value>),
+}
+
+impl Frame<'_, '_> {
+    fn run(mut self) -> Result<Option<Value>, InterpreterError> {
+        let blocks = &self.function.basic_blocks;
+        if blocks.is_empty() {
+            return match self.function.signature.returns {
+                Some(_) => Err(InterpreterError::Trap(format!("{} has no body", self.function.name))),
+                None => Ok(None),
+            };
+        }
+        let mut block = 0;
+        loop {
+            // Entering a block costs fuel too, so empty loops still run out
+            self.interpreter.consume_fuel()?;
+            let basic_block = blocks.get(block)
+                .ok_or_else(|| InterpreterError::Trap(format!("branch to unknown block {}", block)))?;
+            match self.run_block(basic_block)? {
+                Flow::Block(next) => block = next,
+                Flow::Return(value) => return Ok(value),
+            }
+        }
+    }
+
+    fn run_block(&mut self, block: &BasicBlock) -> Result<Flow, InterpreterError> {
+        for instruction in &block.instructions {
+            self.interpreter.consume_fuel()?;
+            // Calls bypass `execute` to keep its frame off the native stack
+            if let Instruction::Call { func_ref, args } = instruction {
+                let args = self.operands(&args.iter().collect::<Vec<_>>())?;
+                let result = self.interpreter.call_function(*func_ref, args)?;
+                self.stack.extend(result);
+            } else if let Some(value) = self.execute(instruction)? {
+                return Ok(Flow::Return(value));
+            }
+        }
+
+        // Values the terminator does not consume are dropped
+        let consumed = matches!(
+            block.terminator,
+            Terminator::Return { value: Some(Operand::StackValue(_)) }
+                | Terminator::Branch { condition: Operand::StackValue(_), .. }
+        );
+        if !consumed {
+            self.stack.clear();
+        }
+        match &block.terminator {
+            Terminator::Return { value } => Ok(Flow::Return(self.return_value(value.as_ref())?)),
+            Terminator::Jump { target } => Ok(Flow::Block(target.0)),
+            Terminator::Branch { condition, then_block, else_block } => {
+                let taken = match self.operands(&[condition])?[0] {
+                    Value::I32(v) => v != 0,
+                    Value::I64(v) => v != 0,
+                    other => return Err(InterpreterError::Trap(format!("branch condition {:?}", other))),
+                };
+                Ok(Flow::Block(if taken { then_block.0 } else { else_block.0 }))
+            }
+            Terminator::Switch { value, targets, default_target } => {
+                let value = self.operands(&[value])?[0];
+                for (case, target) in targets {
+                    if self.operands(&[case])?[0] == value {
+                        return Ok(Flow::Block(target.0));
+                    }
+                }
+                Ok(Flow::Block(default_target.0))
+            }
+            Terminator::Unreachable => Err(InterpreterError::Trap("unreachable".to_string())),
+            Terminator::Panic { .. } => Err(InterpreterError::Trap("panic".to_string())),
+        }
+    }
+
+    /// Executes an instruction, returning the function's result if it returns
+    fn execute(&mut self, instruction: &Instruction) -> Result<Option<Option<Value>>, InterpreterError> {
+        match instruction {
+            Instruction::LocalGet { index } => {
+                let value = self.local(*index)?;
+                self.stack.push(value);
+            }
+            Instruction::LocalSet { index, value } => {
+                self.local(*index)?;
+                let value = self.operands(&[value])?[0];
+                self.locals[*index as usize] = value;
+            }
+            Instruction::GlobalSet { index, value } => {
+                self.interpreter.global(*index)?;
+                let value = self.operands(&[value])?[0];
+                self.interpreter.globals[*index as usize] = Some(value);
+            }
+            Instruction::BinaryOp { op, left, right } => {
+                let values = self.operands(&[left, right])?;
+                self.stack.push(binary(*op, values[0], values[1])?);
+            }
+            Instruction::UnaryOp { op, value } => {
+                let value = self.operands(&[value])?[0];
+                self.stack.push(unary(*op, value)?);
+            }
+            Instruction::Convert { op, value } => {
+                let value = self.operands(&[value])?[0];
+                self.stack.push(convert(op, value)?);
+            }
+            Instruction::CallImport { import, args } => {
+                let args = self.operands(&args.iter().collect::<Vec<_>>())?;
+                let result = self.interpreter.call_import(*import, &args)?;
+                self.stack.extend(result);
+            }
+            Instruction::Return { value } => return Ok(Some(self.return_value(value.as_ref())?)),
+            Instruction::MemoryLoad { address, ty, offset, .. } => {
+                let address = self.operands(&[address])?[0];
+                let value = match ty {
+                    Type::I32 => Value::I32(i32::from_le_bytes(self.load(address, *offset)?)),
+                    Type::I64 => Value::I64(i64::from_le_bytes(self.load(address, *offset)?)),
+                    Type::F32 => Value::F32(f32::from_le_bytes(self.load(address, *offset)?)),
+                    Type::F64 => Value::F64(f64::from_le_bytes(self.load(address, *offset)?)),
+                    other => return Err(InterpreterError::Unsupported(format!("load of {:?}", other))),
+                };
+                self.stack.push(value);
+            }
+            Instruction::MemoryStore { address, value, offset, .. } => {
+                let values = self.operands(&[address, value])?;
+                let bytes = match values[1] {
+                    Value::I32(v) => v.to_le_bytes().to_vec(),
+                    Value::I64(v) => v.to_le_bytes().to_vec(),
+                    Value::F32(v) => v.to_le_bytes().to_vec(),
+                    Value::F64(v) => v.to_le_bytes().to_vec(),
+                };
+                self.interpreter.memory_range(values[0], *offset, bytes.len())?.copy_from_slice(&bytes);
+            }
+            Instruction::MemorySize => {
+                if !self.interpreter.effects {
+                    return Err(InterpreterError::Unsupported("memory.size in a constant".to_string()));
+                }
+                self.stack.push(Value::I32((self.interpreter.memory.len() / PAGE_SIZE) as i32));
+            }
+            Instruction::MemoryGrow { pages } => {
+                let pages = self.operands(&[pages])?[0];
+                let previous = self.interpreter.grow_memory(pages)?;
+                self.stack.push(Value::I32(previous));
+            }
+            Instruction::Nop | Instruction::CapabilityCheck { .. } => {}
+            other => {
+                return Err(InterpreterError::Unsupported(format!("instruction {}", other.name())));
+            }
+        }
+        Ok(None)
+    }
+
+    fn load<const N: usize>(&mut self, address: Value, offset: u32) -> Result<[u8; N], InterpreterError> {
+        let bytes = self.interpreter.memory_range(address, offset, N)?;
+        Ok(bytes.try_into().expect("range has N bytes"))
+    }
+
+    fn return_value(&mut self, value: Option<&Operand>) -> Result<Option<Value>, InterpreterError> {
+        value.map(|value| self.operands(&[value]).map(|values| values[0])).transpose()
+    }
+
+    fn local(&self, index: u32) -> Result<Value, InterpreterError> {
+        self.locals.get(index as usize)
+            .copied()
+            .ok_or_else(|| InterpreterError::Trap(format!("invalid local index {}", index)))
+    }
+
+    /// Evaluates operands in order; stack values take the topmost values,
+    /// the first of them the deepest
+    fn operands(&mut self, operands: &[&Operand]) -> Result<Vec<Value>, InterpreterError> {
+        let from_stack = operands.iter().filter(|operand| is_stack_value(operand)).count();
+        if self.stack.len() < from_stack {
+            return Err(InterpreterError::Trap("stack value used with an empty stack".to_string()));
+        }
+        let mut stacked = self.stack.split_off(self.stack.len() - from_stack).into_iter();
+        operands.iter()
+            .map(|operand| self.operand(operand, &mut stacked))
+            .collect()
+    }
+
+    fn operand(&self, operand: &Operand, stacked: &mut impl Iterator<Item = Value>) -> Result<Value, InterpreterError> {
+        match operand {
+            Operand::Local(index) => self.local(*index),
+            Operand::StackValue(_) => Ok(stacked.next().expect("one stacked value per stack operand")),
+            Operand::MemoryAddress(inner) => self.operand(inner, stacked),
+            Operand::Constant(value) => constant(value),
+            Operand::Global(index) => self.interpreter.global(*index),
+            other => Err(InterpreterError::Unsupported(format!("operand {:?}", other))),
+        }
+    }
+}
+
+fn constant(constant: &Constant) -> Result<Value, InterpreterError> {
+    match constant {
+        Constant::I32(v) => Ok(Value::I32(*v)),
+        Constant::I64(v) => Ok(Value::I64(*v)),
+        Constant::F32(v) => Ok(Value::F32(*v)),
+        Constant::F64(v) => Ok(Value::F64(*v)),
+        Constant::Boolean(v) => Ok(Value::I32(*v as i32)),
+        Constant::I128(_) | Constant::Null | Constant::String(_) => {
+            Err(InterpreterError::Unsupported(format!("constant {:?}", constant)))
+        }
+    }
+}
+
+fn is_stack_value(operand: &Operand) -> bool {
+    match operand {
+        Operand::StackValue(_) => true,
+        Operand::MemoryAddress(inner) => is_stack_value(inner),
+        _ => false,
+    }
+}
+
+/// Zero value of a numeric type
+fn zero(ty: &Type) -> Option<Value> {
+    match ty {
+        Type::I32 => Some(Value::I32(0)),
+        Type::I64 => Some(Value::I64(0)),
+        Type::F32 => Some(Value::F32(0.0)),
+        Type::F64 => Some(Value::F64(0.0)),
+        Type::Pointer(_) => Some(Value::I32(0)),
+        Type::Linear { inner_type } | Type::Capability { inner_type, .. } => zero(inner_type),
+        _ => None,
+    }
+}
+
+fn binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, InterpreterError> {
+    use BinaryOp::*;
+
+    let trap = |msg: &str| InterpreterError::Trap(msg.to_string());
+    let bool_value = |b: bool| Value::I32(b as i32);
+    Ok(match (left, right) {
+        (Value::I32(a), Value::I32(b)) => match op {
+            Add => Value::I32(a.wrapping_add(b)),
+            Sub => Value::I32(a.wrapping_sub(b)),
+            Mul => Value::I32(a.wrapping_mul(b)),
+            Div if b == 0 => return Err(trap("integer divide by zero")),
+            Div => Value::I32(a.checked_div(b).ok_or_else(|| trap("integer overflow"))?),
+            Mod if b == 0 => return Err(trap("integer divide by zero")),
+            Mod => Value::I32(a.wrapping_rem(b)),
+            And => Value::I32(a & b),
+            Or => Value::I32(a | b),
+            Xor => Value::I32(a ^ b),
+            Shl => Value::I32(a.wrapping_shl(b as u32)),
+            Shr => Value::I32((a as u32).wrapping_shr(b as u32) as i32),
+            Sar => Value::I32(a.wrapping_shr(b as u32)),
+            Eq => bool_value(a == b),
+            Ne => bool_value(a != b),
+            Lt => bool_value(a < b),
+            Le => bool_value(a <= b),
+            Gt => bool_value(a > b),
+            Ge => bool_value(a >= b),
+        },
+        (Value::I64(a), Value::I64(b)) => match op {
+            Add => Value::I64(a.wrapping_add(b)),
+            Sub => Value::I64(a.wrapping_sub(b)),
+            Mul => Value::I64(a.wrapping_mul(b)),
+            Div if b == 0 => return Err(trap("integer divide by zero")),
+            Div => Value::I64(a.checked_div(b).ok_or_else(|| trap("integer overflow"))?),
+            Mod if b == 0 => return Err(trap("integer divide by zero")),
+            Mod => Value::I64(a.wrapping_rem(b)),
+            And => Value::I64(a & b),
+            Or => Value::I64(a | b),
+            Xor => Value::I64(a ^ b),
+            Shl => Value::I64(a.wrapping_shl(b as u32)),
+            Shr => Value::I64((a as u64).wrapping_shr(b as u32) as i64),
+            Sar => Value::I64(a.wrapping_shr(b as u32)),
+            Eq => bool_value(a == b),
+            Ne => bool_value(a != b),
+            Lt => bool_value(a < b),
+            Le => bool_value(a <= b),
+            Gt => bool_value(a > b),
+            Ge => bool_value(a >= b),
+        },
+        (Value::F32(a), Value::F32(b)) => match op {
+            Add => Value::F32(a + b),
+            Sub => Value::F32(a - b),
+            Mul => Value::F32(a * b),
+            Div => Value::F32(a / b),
+            Eq => bool_value(a == b),
+            Ne => bool_value(a != b),
+            Lt => bool_value(a < b),
+            Le => bool_value(a <= b),
+            Gt => bool_value(a > b),
+            Ge => bool_value(a >= b),
+            _ => return Err(InterpreterError::Unsupported(format!("{:?} on f32", op))),
+        },
+        (Value::F64(a), Value::F64(b)) => match op {
+            Add => Value::F64(a + b),
+            Sub => Value::F64(a - b),
+            Mul => Value::F64(a * b),
+            Div => Value::F64(a / b),
+            Eq => bool_value(a == b),
+            Ne => bool_value(a != b),
+            Lt => bool_value(a < b),
+            Le => bool_value(a <= b),
+            Gt => bool_value(a > b),
+            Ge => bool_value(a >= b),
+            _ => return Err(InterpreterError::Unsupported(format!("{:?} on f64", op))),
+        },
+        (left, right) => {
+            return Err(InterpreterError::Trap(format!("{:?} on {} and {}", op, left.ty().name(), right.ty().name())));
+        }
+    })
+}
+
+fn unary(op: UnaryOp, value: Value) -> Result<Value, InterpreterError> {
+    Ok(match (op, value) {
+        (UnaryOp::Neg, Value::I32(v)) => Value::I32(v.wrapping_neg()),
+        (UnaryOp::Neg, Value::I64(v)) => Value::I64(v.wrapping_neg()),
+        (UnaryOp::Neg, Value::F32(v)) => Value::F32(-v),
+        (UnaryOp::Neg, Value::F64(v)) => Value::F64(-v),
+        (UnaryOp::Not, Value::I32(v)) => Value::I32(!v),
+        (UnaryOp::Not, Value::I64(v)) => Value::I64(!v),
+        (UnaryOp::Clz, Value::I32(v)) => Value::I32(v.leading_zeros() as i32),
+        (UnaryOp::Ctz, Value::I32(v)) => Value::I32(v.trailing_zeros() as i32),
+        (UnaryOp::Popcnt, Value::I32(v)) => Value::I32(v.count_ones() as i32),
+        (UnaryOp::Clz, Value::I64(v)) => Value::I64(v.leading_zeros() as i64),
+        (UnaryOp::Ctz, Value::I64(v)) => Value::I64(v.trailing_zeros() as i64),
+        (UnaryOp::Popcnt, Value::I64(v)) => Value::I64(v.count_ones() as i64),
+        (op, value) => return Err(InterpreterError::Unsupported(format!("unary {:?} on {}", op, value.ty().name()))),
+    })
+}
+
+fn convert(op: &ConvertOp, value: Value) -> Result<Value, InterpreterError> {
+    let unsupported = || InterpreterError::Unsupported(format!("{:?} of {}", op, value.ty().name()));
+    Ok(match (op, value) {
+        (ConvertOp::Wrap, Value::I64(v)) => Value::I32(v as i32),
+        (ConvertOp::Extend { signed: true }, Value::I32(v)) => Value::I64(v as i64),
+        (ConvertOp::Extend { signed: false }, Value::I32(v)) => Value::I64(v as u32 as i64),
+        (ConvertOp::SignExtend { bits: 8 }, Value::I32(v)) => Value::I32(v as i8 as i32),
+        (ConvertOp::SignExtend { bits: 16 }, Value::I32(v)) => Value::I32(v as i16 as i32),
+        (ConvertOp::SignExtend { bits: 8 }, Value::I64(v)) => Value::I64(v as i8 as i64),
+        (ConvertOp::SignExtend { bits: 16 }, Value::I64(v)) => Value::I64(v as i16 as i64),
+        (ConvertOp::SignExtend { bits: 32 }, Value::I64(v)) => Value::I64(v as i32 as i64),
+        (ConvertOp::Trunc { to, signed }, Value::F32(_) | Value::F64(_)) => {
+            let v = float(value);
+            if v.is_nan() {
+                return Err(InterpreterError::Trap("invalid conversion to integer".to_string()));
+            }
+            // Bounds of the truncated value, inclusive below and exclusive above
+            let (low, high) = match (to, signed) {
+                (Type::I32, true) => (-2_147_483_648.0, 2_147_483_648.0),
+                (Type::I32, false) => (0.0, 4_294_967_296.0),
+                (Type::I64, true) => (-9_223_372_036_854_775_808.0, 9_223_372_036_854_775_808.0),
+                _ => (0.0, 18_446_744_073_709_551_616.0),
+            };
+            if v.trunc() < low || v.trunc() >= high {
+                return Err(InterpreterError::Trap("integer overflow".to_string()));
+            }
+            saturate(v, to, *signed).ok_or_else(unsupported)?
+        }
+        (ConvertOp::TruncSat { to, signed }, Value::F32(_) | Value::F64(_)) => {
+            saturate(float(value), to, *signed).ok_or_else(unsupported)?
+        }
+        (ConvertOp::FromInt { to, signed }, Value::I32(_) | Value::I64(_)) => {
+            // Every source fits an i128, which converts with a single rounding
+            let v = match (value, signed) {
+                (Value::I32(v), true) => v as i128,
+                (Value::I32(v), false) => v as u32 as i128,
+                (Value::I64(v), true) => v as i128,
+                (Value::I64(v), _) => v as u64 as i128,
+                _ => unreachable!(),
+            };
+            match to {
+                Type::F32 => Value::F32(v as f32),
+                Type::F64 => Value::F64(v as f64),
+                _ => return Err(unsupported()),
+            }
+        }
+        (ConvertOp::Promote, Value::F32(v)) => Value::F64(v as f64),
+        (ConvertOp::Demote, Value::F64(v)) => Value::F32(v as f32),
+        (ConvertOp::Reinterpret, Value::I32(v)) => Value::F32(f32::from_bits(v as u32)),
+        (ConvertOp::Reinterpret, Value::I64(v)) => Value::F64(f64::from_bits(v as u64)),
+        (ConvertOp::Reinterpret, Value::F32(v)) => Value::I32(v.to_bits() as i32),
+        (ConvertOp::Reinterpret, Value::F64(v)) => Value::I64(v.to_bits() as i64),
+        _ => return Err(unsupported()),
+    })
+}
+
+/// Widens a float operand; `f32` values are exact as `f64`
+fn float(value: Value) -> f64 {
+    match value {
+        Value::F32(v) => v as f64,
+        Value::F64(v) => v,
+        _ => f64::NAN,
+    }
+}
+
+/// Float to integer with the saturating semantics of Rust's `as`
+fn saturate(v: f64, to: &Type, signed: bool) -> Option<Value> {
+    Some(match (to, signed) {
+        (Type::I32, true) => Value::I32(v as i32),
+        (Type::I32, false) => Value::I32(v as u32 as i32),
+        (Type::I64, true) => Value::I64(v as i64),
+        (Type::I64, false) => Value::I64(v as u64 as i64),
+        _ => return None,
+    })
+}
+
+/// Evaluates a function without parameters at compile time
+///
+/// Returns `None` unless the function returns a value without touching
+/// memory, calling imports, trapping, or running out of fuel.
+pub fn evaluate_constant(module: &WasmModule, function: u32) -> Option<Value> {
+    let params = &module.functions.get(function as usize)?.signature.params;
+    if !params.is_empty() {
+        return None;
+    }
+    let host = HostFunctions::new();
+    let mut interpreter = Interpreter::new(module, &host).fuel(CONST_EVAL_FUEL);
+    interpreter.effects = false;
+    interpreter.call(function, &[]).ok().flatten()
+}
+
+/// Replaces the body of every function `evaluate_constant` can evaluate
+/// with a return of its value, returning how many were folded
+pub fn fold_constants(module: &mut WasmModule) -> usize {
+    let folded: Vec<(usize, Constant)> = (0..module.functions.len())
+        .filter_map(|index| {
+            let value = evaluate_constant(module, index as u32)?;
+            Some((index, value_constant(value)))
+        })
+        .collect();
+    for (index, value) in &folded {
+        let function = &mut module.functions[*index];
+        function.locals.clear();
+        function.basic_blocks.clear();
+        function.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(value.clone())) });
+    }
+    folded.len()
+}
+
+/// Constant with the value of `value`
+pub fn value_constant(value: Value) -> Constant {
+    match value {
+        Value::I32(v) => Constant::I32(v),
+        Value::I64(v) => Constant::I64(v),
+        Value::F32(v) => Constant::F32(v),
+        Value::F64(v) => Constant::F64(v),
+    }
+}
+
+/// Backend that runs WasmIR instead of compiling it
+///
+/// An interpreter has no code to emit: `compile` validates the function and
+/// returns an empty result, so `BackendFactory` only falls back to it when no
+/// other backend can be created. Execute modules with `Interpreter`.
+#[derive(Debug, Default)]
+pub struct InterpreterBackend;
+
+impl InterpreterBackend {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Backend for InterpreterBackend {
+    fn compile(&mut self, wasmir: &crate::wasmir::WasmIR, profile: BuildProfile) -> Result<CompilationResult, BackendError> {
+        wasmir.validate()
+            .map_err(|e| BackendError::CompilationFailed(format!("invalid function: {}", e)))?;
+        Ok(CompilationResult {
+            code: Vec::new(),
+            symbols: BTreeMap::from([(wasmir.name.clone(), 0)]),
+            relocations: Vec::new(),
+            metadata: CompilationMetadata {
+                target: crate::DEFAULT_TARGET.to_string(),
+                optimization_level: OptimizationLevel::None,
+                build_profile: profile,
+                timestamp: CompilationMetadata::timestamp(false),
+            },
+        })
+    }
+
+    fn supported_optimizations(&self) -> Vec<OptimizationLevel> {
+        vec![OptimizationLevel::None]
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            thin_monomorphization: false,
+            streaming_layout: false,
+            pgo_support: false,
+            component_model: false,
+            wasm_optimizations: false,
+            linear_types: true,
+        }
+    }
+
+    fn reset(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::runtime::ValueType;
+    use wasm::wasmir::{BlockId, MemoryType, Signature};
+
+    /// `fact(n)`: loops multiplying an accumulator in local 1
+    fn factorial() -> WasmIR {
+        let mut fact = WasmIR::new("fact".to_string(), Signature {
+            params: vec![Type::I64],
+            returns: Some(Type::I64),
+        });
+        fact.locals = vec![Type::I64, Type::I64];
+        fact.add_basic_block(
+            vec![Instruction::LocalSet { index: 1, value: Operand::Constant(Constant::I64(1)) }],
+            Terminator::Jump { target: BlockId(1) },
+        );
+        fact.add_basic_block(
+            vec![Instruction::BinaryOp { op: BinaryOp::Gt, left: Operand::Local(0), right: Operand::Constant(Constant::I64(1)) }],
+            Terminator::Branch { condition: Operand::StackValue(0), then_block: BlockId(2), else_block: BlockId(3) },
+        );
+        fact.add_basic_block(
+            vec![
+                Instruction::BinaryOp { op: BinaryOp::Mul, left: Operand::Local(1), right: Operand::Local(0) },
+                Instruction::LocalSet { index: 1, value: Operand::StackValue(0) },
+                Instruction::BinaryOp { op: BinaryOp::Sub, left: Operand::Local(0), right: Operand::Constant(Constant::I64(1)) },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+            ],
+            Terminator::Jump { target: BlockId(1) },
+        );
+        fact.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Local(1)) });
+        fact
+    }
+
+    /// `boxed(x)`: stores `x` in a fresh allocation, frees it, and returns
+    /// the value read back plus the allocation's address
+    fn boxed() -> WasmIR {
+        let mut boxed = WasmIR::new("boxed".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        boxed.locals = vec![Type::I32; 3];
+        boxed.add_basic_block(
+            vec![
+                Instruction::MemoryAlloc { size: Operand::Constant(Constant::I32(12)), align: Some(4) },
+                Instruction::LocalSet { index: 1, value: Operand::StackValue(0) },
+                Instruction::MemoryStore {
+                    address: Operand::Local(1),
+                    value: Operand::Local(0),
+                    ty: Type::I32,
+                    align: None,
+                    offset: 8,
+                },
+                Instruction::MemoryLoad { address: Operand::Local(1), ty: Type::I32, align: None, offset: 8 },
+                Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::StackValue(0), right: Operand::Local(1) },
+                Instruction::LocalSet { index: 2, value: Operand::StackValue(0) },
+                Instruction::MemoryFree { address: Operand::Local(1) },
+            ],
+            Terminator::Return { value: Some(Operand::Local(2)) },
+        );
+        boxed
+    }
+
+    /// `deep(n)`: keeps `n` in a 16 KiB stack frame and returns
+    /// `n + deep(n - 1)`, reading `n` back after the call; `index` is its
+    /// own function index
+    fn deep(index: u32) -> WasmIR {
+        let mut deep = WasmIR::new("deep".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        deep.locals = vec![Type::I32];
+        let slot = deep.add_stack_slot(16384, 4);
+        let base = Operand::Local(deep.frame.as_ref().unwrap().base);
+        deep.add_basic_block(
+            vec![
+                Instruction::MemoryStore { address: base.clone(), value: Operand::Local(0), ty: Type::I32, align: None, offset: slot },
+                Instruction::BinaryOp { op: BinaryOp::Eq, left: Operand::Local(0), right: Operand::Constant(Constant::I32(0)) },
+            ],
+            Terminator::Branch { condition: Operand::StackValue(0), then_block: BlockId(1), else_block: BlockId(2) },
+        );
+        deep.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(Constant::I32(0))) });
+        deep.add_basic_block(
+            vec![
+                Instruction::BinaryOp { op: BinaryOp::Sub, left: Operand::Local(0), right: Operand::Constant(Constant::I32(1)) },
+                Instruction::Call { func_ref: index, args: vec![Operand::StackValue(0)] },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+                Instruction::MemoryLoad { address: base, ty: Type::I32, align: None, offset: slot },
+                Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::StackValue(0), right: Operand::Local(0) },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        deep
+    }
+
+    /// `bump()`: increments thread local `index` and returns it
+    fn bump(index: u32) -> WasmIR {
+        let mut bump = WasmIR::new("bump".to_string(), Signature { params: vec![], returns: Some(Type::I32) });
+        bump.locals = vec![Type::I32, Type::I32];
+        bump.add_basic_block(
+            vec![
+                Instruction::ThreadLocalAddress { index },
+                Instruction::LocalSet { index: 0, value: Operand::StackValue(0) },
+                Instruction::MemoryLoad { address: Operand::Local(0), ty: Type::I32, align: None, offset: 0 },
+                Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::StackValue(0), right: Operand::Constant(Constant::I32(1)) },
+                Instruction::LocalSet { index: 1, value: Operand::StackValue(0) },
+                Instruction::MemoryStore { address: Operand::Local(0), value: Operand::Local(1), ty: Type::I32, align: None, offset: 0 },
+            ],
+            Terminator::Return { value: Some(Operand::Local(1)) },
+        );
+        bump
+    }
+
+    fn export(module: &mut WasmModule, function: WasmIR) -> u32 {
+        let name = function.name.clone();
+        let index = module.add_function(function);
+        module.export_function(name, index);
+        index
+    }
+
+    #[test]
+    fn test_runs_control_flow_memory_and_imports() {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        export(&mut module, factorial());
+
+        let host = HostFunctions::new()
+            .func("env", "offset", [], Some(ValueType::I32), |_| Ok(Some(Value::I32(16))));
+        host.declare_imports(&mut module).unwrap();
+        let mut store = WasmIR::new("store".to_string(), Signature { params: vec![Type::I32], returns: Some(Type::I32) });
+        store.add_basic_block(
+            vec![
+                Instruction::CallImport { import: 0, args: vec![] },
+                Instruction::MemoryStore {
+                    address: Operand::StackValue(0),
+                    value: Operand::Local(0),
+                    ty: Type::I32,
+                    align: None,
+                    offset: 4,
+                },
+                Instruction::MemoryLoad { address: Operand::Constant(Constant::I32(20)), ty: Type::I32, align: None, offset: 0 },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        export(&mut module, store);
+
+        let mut interpreter = Interpreter::new(&module, &host);
+        assert_eq!(interpreter.invoke("fact", &[Value::I64(10)]), Ok(Some(Value::I64(3_628_800))));
+        assert_eq!(interpreter.invoke("store", &[Value::I32(-7)]), Ok(Some(Value::I32(-7))));
+        assert_eq!(&interpreter.memory()[20..24], &(-7i32).to_le_bytes());
+        assert_eq!(interpreter.invoke("fact", &[Value::I32(1)]), Err(InterpreterError::Trap("arguments do not match the signature of fact".to_string())));
+
+        let mut starved = Interpreter::new(&module, &host).fuel(20);
+        assert_eq!(starved.invoke("fact", &[Value::I64(100)]), Err(InterpreterError::OutOfFuel));
+        let empty = HostFunctions::new();
+        assert_eq!(
+            Interpreter::new(&module, &empty).invoke("store", &[Value::I32(1)]),
+            Err(InterpreterError::MissingImport("env::offset".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_empty_loops_run_out_of_fuel() {
+        let mut module = WasmModule::new();
+        let mut spin = WasmIR::new("spin".to_string(), Signature { params: vec![], returns: None });
+        spin.add_basic_block(vec![], Terminator::Jump { target: BlockId(0) });
+        export(&mut module, spin);
+
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host).fuel(1000);
+        assert_eq!(interpreter.invoke("spin", &[]), Err(InterpreterError::OutOfFuel));
+    }
+
+    #[test]
+    fn test_traps_on_unbounded_recursion() {
+        let mut module = WasmModule::new();
+        let mut recurse = WasmIR::new("recurse".to_string(), Signature { params: vec![], returns: None });
+        recurse.add_basic_block(vec![Instruction::Call { func_ref: 0, args: vec![] }], Terminator::Return { value: None });
+        export(&mut module, recurse);
+
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+        assert_eq!(interpreter.invoke("recurse", &[]), Err(InterpreterError::Trap("call stack exhausted".to_string())));
+        assert_eq!(interpreter.depth, 0);
+    }
+
+    #[test]
+    fn test_evaluates_constants() {
+        let mut module = WasmModule::new();
+        let mut answer = WasmIR::new("answer".to_string(), Signature { params: vec![], returns: Some(Type::I32) });
+        answer.add_basic_block(
+            vec![
+                Instruction::BinaryOp { op: BinaryOp::Mul, left: Operand::Constant(Constant::I32(6)), right: Operand::Constant(Constant::I32(7)) },
+                Instruction::UnaryOp { op: UnaryOp::Neg, value: Operand::StackValue(0) },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let answer = module.add_function(answer);
+        let mut divide = WasmIR::new("divide".to_string(), Signature { params: vec![], returns: Some(Type::I32) });
+        divide.add_basic_block(
+            vec![Instruction::BinaryOp { op: BinaryOp::Div, left: Operand::Constant(Constant::I32(1)), right: Operand::Constant(Constant::I32(0)) }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let divide = module.add_function(divide);
+        let fact = module.add_function(factorial());
+
+        assert_eq!(evaluate_constant(&module, answer), Some(Value::I32(-42)));
+        assert_eq!(evaluate_constant(&module, divide), None);
+        assert_eq!(evaluate_constant(&module, fact), None);
+
+        assert_eq!(fold_constants(&mut module), 1);
+        assert!(matches!(
+            module.functions[answer as usize].basic_blocks[0].terminator,
+            Terminator::Return { value: Some(Operand::Constant(Constant::I32(-42))) }
+        ));
+        assert_eq!(module.functions[divide as usize].basic_blocks[0].instructions.len(), 1);
+    }
+
+    #[test]
+    fn test_runs_legalized_i128_arithmetic() {
+        use wasm::wasmir::legalize::legalize_i128;
+
+        let mut wide = WasmIR::new("wide".to_string(), Signature {
+            params: vec![Type::I128, Type::I128],
+            returns: Some(Type::I32),
+        });
+        wide.locals = vec![Type::I128, Type::I128, Type::I128, Type::I32];
+        let mut instructions = vec![Instruction::LocalSet { index: 3, value: Operand::Constant(Constant::I32(75)) }];
+        let results = [
+            (BinaryOp::Add, Operand::Local(1)),
+            (BinaryOp::Sub, Operand::Local(1)),
+            (BinaryOp::Mul, Operand::Local(1)),
+            (BinaryOp::Shl, Operand::Constant(Constant::I32(70))),
+            (BinaryOp::Shr, Operand::Constant(Constant::I32(3))),
+            (BinaryOp::Sar, Operand::Constant(Constant::I32(100))),
+            (BinaryOp::Sar, Operand::Local(3)),
+        ];
+        for (slot, (op, right)) in results.into_iter().enumerate() {
+            instructions.extend([
+                Instruction::BinaryOp { op, left: Operand::Local(0), right },
+                Instruction::LocalSet { index: 2, value: Operand::StackValue(0) },
+                Instruction::MemoryStore {
+                    address: Operand::Constant(Constant::I32(slot as i32 * 16)),
+                    value: Operand::Local(2),
+                    ty: Type::I128,
+                    align: None,
+                    offset: 0,
+                },
+            ]);
+        }
+        instructions.push(Instruction::BinaryOp { op: BinaryOp::Lt, left: Operand::Local(0), right: Operand::Local(1) });
+        wide.add_basic_block(instructions, Terminator::Return { value: Some(Operand::StackValue(0)) });
+
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        export(&mut module, wide);
+        let module = legalize_i128(&module).unwrap();
+        let host = HostFunctions::new();
+
+        let cases: [(i128, i128); 4] = [
+            (1, 2),
+            (u64::MAX as i128, 1),
+            (-3, i128::MAX),
+            (0x0123_4567_89ab_cdef_fedc_ba98_7654_3210, -0x7777_0000_ffff_1234_5678),
+        ];
+        for (a, b) in cases {
+            let halves = |v: i128| [Value::I64(v as i64), Value::I64((v >> 64) as i64)];
+            let args: Vec<_> = halves(a).into_iter().chain(halves(b)).collect();
+            let mut interpreter = Interpreter::new(&module, &host);
+            assert_eq!(interpreter.invoke("wide", &args), Ok(Some(Value::I32((a < b) as i32))), "{} < {}", a, b);
+            let expected = [
+                a.wrapping_add(b),
+                a.wrapping_sub(b),
+                a.wrapping_mul(b),
+                a << 70,
+                ((a as u128) >> 3) as i128,
+                a >> 100,
+                a >> 75,
+            ];
+            for (slot, expected) in expected.into_iter().enumerate() {
+                let bytes = &interpreter.memory()[slot * 16..slot * 16 + 16];
+                assert_eq!(i128::from_le_bytes(bytes.try_into().unwrap()), expected, "slot {} of {}, {}", slot, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_runs_the_bundled_allocator() {
+        use wasm::memory::allocator::{lower_allocations, AllocatorStrategy};
+        use wasm::wasmir::{ALLOC_EXPORT, FREE_EXPORT};
+
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: Some(4) });
+        export(&mut module, boxed());
+        let module = lower_allocations(&module, AllocatorStrategy::Bundled).unwrap();
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+        let alloc = |interpreter: &mut Interpreter, size: i32, align: i32| {
+            match interpreter.invoke(ALLOC_EXPORT, &[Value::I32(size), Value::I32(align)]).unwrap() {
+                Some(Value::I32(ptr)) => ptr,
+                other => panic!("allocation returned {:?}", other),
+            }
+        };
+
+        // The heap starts in the first page past the initial memory
+        let first = alloc(&mut interpreter, 24, 8);
+        assert!(first >= 65536 && first % 8 == 0);
+        assert_eq!(interpreter.memory().len(), 2 * 65536);
+        let aligned = alloc(&mut interpreter, 100, 64);
+        assert_eq!(aligned % 64, 0);
+        assert!(aligned >= first + 24);
+
+        // A freed block is reused by the next allocation of its class
+        interpreter.invoke(FREE_EXPORT, &[Value::I32(first), Value::I32(24), Value::I32(8)]).unwrap();
+        assert_eq!(alloc(&mut interpreter, 20, 4), first);
+
+        // Compiled allocations go through the same heap
+        let result = interpreter.invoke("boxed", &[Value::I32(5)]).unwrap();
+        let Some(Value::I32(sum)) = result else { panic!("boxed returned {:?}", result) };
+        let address = sum - 5;
+        assert!(address > aligned && address % 8 == 0);
+        assert_eq!(interpreter.invoke("boxed", &[Value::I32(9)]).unwrap(), Some(Value::I32(address + 9)));
+
+        // Large blocks grow memory, up to its maximum
+        let large = alloc(&mut interpreter, 100_000, 8);
+        assert!(large > address && interpreter.memory().len() >= large as usize + 100_000);
+        assert_eq!(alloc(&mut interpreter, 200_000, 8), 0);
+        assert_eq!(alloc(&mut interpreter, -1, 8), 0);
+    }
+
+    #[test]
+    fn test_region_checks_trap_stray_addresses() {
+        use wasm::memory::regions::{check_region_accesses, tag_region_accesses, Region, HEAP_REGION};
+        use wasm::wasmir::Capability;
+
+        let heap = Type::Capability {
+            inner_type: Box::new(Type::I32),
+            capability: Capability::MemoryRegion(HEAP_REGION.to_string()),
+        };
+        let mut peek = WasmIR::new("peek".to_string(), Signature { params: vec![heap], returns: Some(Type::I32) });
+        peek.add_basic_block(
+            vec![Instruction::MemoryLoad { address: Operand::Local(0), ty: Type::I32, align: None, offset: 4 }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        module.declare_memory_region(Region::new(HEAP_REGION, 1024, 1024)).unwrap();
+        export(&mut module, peek);
+        let tagged = tag_region_accesses(&module).unwrap();
+        let (checked, _) = check_region_accesses(&tagged);
+        let host = HostFunctions::new();
+
+        // Unchecked, the tag alone lets the stray load through
+        let mut interpreter = Interpreter::new(&tagged, &host);
+        assert_eq!(interpreter.invoke("peek", &[Value::I32(4000)]), Ok(Some(Value::I32(0))));
+        let mut interpreter = Interpreter::new(&checked, &host);
+        for address in [1020, 2040] {
+            assert_eq!(interpreter.invoke("peek", &[Value::I32(address)]), Ok(Some(Value::I32(0))));
+        }
+        for address in [1019, 2041, 4000, -8] {
+            assert!(matches!(interpreter.invoke("peek", &[Value::I32(address)]), Err(InterpreterError::Trap(_))));
+        }
+    }
+
+    #[test]
+    fn test_resets_the_bump_allocator() {
+        use wasm::memory::allocator::{lower_allocations, AllocatorStrategy, RESET_EXPORT};
+        use wasm::wasmir::{ALLOC_EXPORT, FREE_EXPORT};
+
+        let mut module = WasmModule::new();
+        export(&mut module, boxed());
+        let module = lower_allocations(&module, AllocatorStrategy::Bump).unwrap();
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+        let alloc = |interpreter: &mut Interpreter, size: i32, align: i32| {
+            interpreter.invoke(ALLOC_EXPORT, &[Value::I32(size), Value::I32(align)]).unwrap()
+        };
+
+        assert_eq!(interpreter.invoke(RESET_EXPORT, &[]).unwrap(), None);
+        assert_eq!(alloc(&mut interpreter, 3, 1), Some(Value::I32(8)));
+        assert_eq!(alloc(&mut interpreter, 4, 4), Some(Value::I32(12)));
+        assert_eq!(alloc(&mut interpreter, 1, 0), Some(Value::I32(16)));
+        interpreter.invoke(FREE_EXPORT, &[Value::I32(12), Value::I32(4), Value::I32(4)]).unwrap();
+        assert_eq!(interpreter.invoke("boxed", &[Value::I32(1)]).unwrap(), Some(Value::I32(21)));
+        assert_eq!(alloc(&mut interpreter, 70_000, 16), Some(Value::I32(32)));
+        assert_eq!(interpreter.memory().len(), 2 * 65536);
+
+        interpreter.invoke(RESET_EXPORT, &[]).unwrap();
+        assert_eq!(alloc(&mut interpreter, 8, 8), Some(Value::I32(8)));
+    }
+
+    #[test]
+    fn test_traps_on_shadow_stack_overflow() {
+        use wasm::memory::stack::{lower_stack_frames, DEFAULT_STACK_SIZE};
+
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        export(&mut module, deep(0));
+        let module = lower_stack_frames(&module, DEFAULT_STACK_SIZE).unwrap();
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+        assert_eq!(interpreter.memory().len(), 2 * 65536);
+
+        // Four frames fill the stack exactly; returns release them again
+        assert_eq!(interpreter.invoke("deep", &[Value::I32(3)]), Ok(Some(Value::I32(6))));
+        assert_eq!(interpreter.invoke("deep", &[Value::I32(3)]), Ok(Some(Value::I32(6))));
+        assert_eq!(interpreter.invoke("deep", &[Value::I32(4)]), Err(InterpreterError::Trap("unreachable".to_string())));
+    }
+
+    #[test]
+    fn test_thread_locals_live_in_the_current_block() {
+        use wasm::threading::tls::{lower_thread_locals, INIT_TLS_EXPORT};
+
+        let mut module = WasmModule::new();
+        let count = module.add_thread_local("COUNT", Type::I32, Constant::I32(41));
+        export(&mut module, bump(count));
+        let module = lower_thread_locals(&module).unwrap();
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+
+        // The main thread's block is filled before the first export runs
+        assert_eq!(interpreter.invoke("bump", &[]), Ok(Some(Value::I32(42))));
+        assert_eq!(interpreter.invoke("bump", &[]), Ok(Some(Value::I32(43))));
+        // A worker's block starts over from the initializer
+        assert_eq!(interpreter.invoke(INIT_TLS_EXPORT, &[Value::I32(1024)]), Ok(None));
+        assert_eq!(interpreter.invoke("bump", &[]), Ok(Some(Value::I32(42))));
+        assert_eq!(interpreter.memory()[..4], 43i32.to_le_bytes());
+        assert_eq!(interpreter.memory()[1024..1028], 42i32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_thread_start_gives_threads_their_own_stack_and_tls() {
+        use wasm::memory::allocator::{lower_allocations, AllocatorStrategy};
+        use wasm::memory::stack::lower_stack_frames;
+        use wasm::threading::runtime::{add_thread_start, THREAD_MAIN_EXPORT, THREAD_START_EXPORT};
+        use wasm::threading::tls::lower_thread_locals;
+
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let count = module.add_thread_local("COUNT", Type::I32, Constant::I32(41));
+        let bump = export(&mut module, bump(count));
+        let index = module.functions.len() as u32;
+        let deep = export(&mut module, deep(index));
+        // Stores `bump() + deep(3)` after the start record's stack size
+        let mut main = WasmIR::new(THREAD_MAIN_EXPORT.to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: None,
+        });
+        main.locals = vec![Type::I32; 3];
+        main.add_basic_block(
+            vec![
+                Instruction::Call { func_ref: bump, args: vec![] },
+                Instruction::LocalSet { index: 2, value: Operand::StackValue(0) },
+                Instruction::Call { func_ref: deep, args: vec![Operand::Constant(Constant::I32(3))] },
+                Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::StackValue(0), right: Operand::Local(2) },
+                Instruction::LocalSet { index: 2, value: Operand::StackValue(0) },
+                Instruction::MemoryStore { address: Operand::Local(1), value: Operand::Local(2), ty: Type::I32, align: None, offset: 4 },
+            ],
+            Terminator::Return { value: None },
+        );
+        export(&mut module, main);
+        let mut poke = WasmIR::new("poke".to_string(), Signature { params: vec![Type::I32, Type::I32], returns: None });
+        poke.add_basic_block(
+            vec![Instruction::MemoryStore { address: Operand::Local(0), value: Operand::Local(1), ty: Type::I32, align: None, offset: 0 }],
+            Terminator::Return { value: None },
+        );
+        export(&mut module, poke);
+        let module = lower_stack_frames(&lower_thread_locals(&module).unwrap(), 65536).unwrap();
+        let module = lower_allocations(&add_thread_start(&module).unwrap(), AllocatorStrategy::Bundled).unwrap();
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+
+        // The main thread has used its block and the whole stack
+        assert_eq!(interpreter.invoke("bump", &[]), Ok(Some(Value::I32(42))));
+        assert_eq!(interpreter.invoke("deep", &[Value::I32(3)]), Ok(Some(Value::I32(6))));
+        // A thread with a full stack of its own starts from a fresh block
+        interpreter.invoke("poke", &[Value::I32(16), Value::I32(65536)]).unwrap();
+        assert_eq!(interpreter.invoke(THREAD_START_EXPORT, &[Value::I32(1), Value::I32(16)]), Ok(None));
+        assert_eq!(interpreter.memory()[20..24], 48i32.to_le_bytes());
+        assert_eq!(interpreter.memory()[65536..65540], 42i32.to_le_bytes());
+        // Too small a stack overflows
+        interpreter.invoke("poke", &[Value::I32(16), Value::I32(16384)]).unwrap();
+        assert!(interpreter.invoke(THREAD_START_EXPORT, &[Value::I32(1), Value::I32(16)]).is_err());
+    }
+
+    #[test]
+    fn test_agrees_with_compiled_code() {
+        use crate::backend::codegen::WasmCodegen;
+        use crate::host::runtime::{available_runtimes, create_runtime, load};
+
+        let mut module = WasmModule::new();
+        export(&mut module, factorial());
+        let mut mix = WasmIR::new("mix".to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        });
+        mix.add_basic_block(
+            vec![
+                Instruction::BinaryOp { op: BinaryOp::Shr, left: Operand::Local(0), right: Operand::Local(1) },
+                Instruction::BinaryOp { op: BinaryOp::Sar, left: Operand::Local(0), right: Operand::Local(1) },
+                Instruction::BinaryOp { op: BinaryOp::Xor, left: Operand::StackValue(0), right: Operand::StackValue(1) },
+                Instruction::BinaryOp { op: BinaryOp::Div, left: Operand::Local(0), right: Operand::Local(1) },
+                Instruction::BinaryOp { op: BinaryOp::Add, left: Operand::StackValue(0), right: Operand::StackValue(1) },
+                Instruction::UnaryOp { op: UnaryOp::Clz, value: Operand::StackValue(0) },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        export(&mut module, mix);
+        let conversions = [
+            ("saturate", Type::F64, Type::I32, vec![ConvertOp::TruncSat { to: Type::I32, signed: false }]),
+            ("trunc", Type::F32, Type::I64, vec![ConvertOp::Trunc { to: Type::I64, signed: true }]),
+            ("widen", Type::I32, Type::F32, vec![
+                ConvertOp::SignExtend { bits: 8 },
+                ConvertOp::Extend { signed: false },
+                ConvertOp::FromInt { to: Type::F64, signed: false },
+                ConvertOp::Demote,
+            ]),
+        ];
+        for (name, param, returns, ops) in conversions {
+            let mut convert = WasmIR::new(name.to_string(), Signature { params: vec![param], returns: Some(returns) });
+            let instructions = ops.into_iter().enumerate().map(|(index, op)| {
+                let value = if index == 0 { Operand::Local(0) } else { Operand::StackValue(0) };
+                Instruction::Convert { op, value }
+            });
+            convert.add_basic_block(instructions.collect(), Terminator::Return { value: Some(Operand::StackValue(0)) });
+            export(&mut module, convert);
+        }
+        export(&mut module, boxed());
+        let index = module.functions.len() as u32;
+        export(&mut module, deep(index));
+        let count = module.add_thread_local("COUNT", Type::I32, Constant::I32(41));
+        export(&mut module, bump(count));
+        let module = wasm::threading::tls::lower_thread_locals(&module).unwrap();
+        let module = wasm::memory::stack::lower_stack_frames(&module, 65536).unwrap();
+        let module = wasm::memory::allocator::lower_allocations(&module, Default::default()).unwrap();
+        let code = WasmCodegen::new().compile(&module).unwrap();
+
+        let cases = [
+            ("fact", vec![Value::I64(0)]),
+            ("fact", vec![Value::I64(20)]),
+            ("fact", vec![Value::I64(25)]),
+            ("mix", vec![Value::I32(-100), Value::I32(3)]),
+            ("mix", vec![Value::I32(i32::MIN), Value::I32(33)]),
+            ("mix", vec![Value::I32(7), Value::I32(0)]),
+            ("mix", vec![Value::I32(i32::MIN), Value::I32(-1)]),
+            ("saturate", vec![Value::F64(-1.5)]),
+            ("saturate", vec![Value::F64(3e9)]),
+            ("saturate", vec![Value::F64(1e12)]),
+            ("saturate", vec![Value::F64(f64::NAN)]),
+            ("trunc", vec![Value::F32(-7.9)]),
+            ("trunc", vec![Value::F32(f32::NAN)]),
+            ("trunc", vec![Value::F32(1e19)]),
+            ("widen", vec![Value::I32(0x7F)]),
+            ("widen", vec![Value::I32(0x180)]),
+            ("boxed", vec![Value::I32(3)]),
+            ("__wasm_alloc", vec![Value::I32(300_000), Value::I32(16)]),
+            ("__wasm_alloc", vec![Value::I32(40), Value::I32(128)]),
+            ("boxed", vec![Value::I32(4)]),
+            ("deep", vec![Value::I32(3)]),
+            ("deep", vec![Value::I32(4)]),
+            ("deep", vec![Value::I32(2)]),
+            ("bump", vec![]),
+            ("bump", vec![]),
+            ("__wasm_init_tls", vec![Value::I32(64)]),
+            ("bump", vec![]),
+        ];
+        let host = HostFunctions::new();
+        for name in available_runtimes() {
+            let runtime = create_runtime(name).unwrap();
+            let compiled = CompilationResult {
+                code: code.clone(),
+                symbols: BTreeMap::new(),
+                relocations: Vec::new(),
+                metadata: CompilationMetadata {
+                    target: crate::DEFAULT_TARGET.to_string(),
+                    optimization_level: OptimizationLevel::None,
+                    build_profile: BuildProfile::Development,
+                    timestamp: std::time::SystemTime::now(),
+                },
+            };
+            let mut instance = load(runtime.as_ref(), &compiled, &host).unwrap();
+            let mut interpreter = Interpreter::new(&module, &host);
+            for (export, args) in &cases {
+                let expected = instance.invoke(export, args).map(|values| values.first().copied());
+                let actual = interpreter.invoke(export, args);
+                match (expected, actual) {
+                    (Ok(expected), Ok(actual)) => assert_eq!(expected, actual, "{} {:?} on {}", export, args, name),
+                    (Err(_), Err(InterpreterError::Trap(_))) => {}
+                    (expected, actual) => panic!("{} {:?}: {} gave {:?}, interpreter {:?}", export, args, name, expected, actual),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,2307 @@
+//! JavaScript glue generation for WasmRust
+//!
+//! Produces the companion JavaScript module shipped next to a `.wasm`
+//! file. The glue instantiates the module, implements the `wasmrust`
+//! host imports (JS method calls, property access, and the runtime's
+//! ArrayBuffer and closure bridges), runs constructors, and exports typed wrappers for
+//! every exported function. Everything is derived from `WasmModule`
+//! metadata so the glue always matches the emitted import section.
+//!
+//! Imports from other modules come from the `userImports` passed to
+//! `init`. Those with interop signatures, such as the ones declared with
+//! `#[wasm::import]`, go through a stub table that reads their strings
+//! and slices out of memory and writes their string and array results
+//! back; a missing one throws when called.
+//!
+//! Promise-returning JS methods and async exports follow the module's
+//! `PromiseLowering`: either a handle table settled through the runtime's
+//! `__wasmrust_promise_settled` export, or JS Promise Integration.
+//!
+//! Modules using `wasm::threading::async_rt` get its host side: runs of
+//! the main thread's executor are scheduled as microtasks, and `sleep`
+//! timers map to `setTimeout`. The glue of a threaded module also watches
+//! the executor's wake signal with `Atomics.waitAsync`, so wakes from
+//! workers schedule a run.
+//!
+//! Closures are invoked through the module's exported
+//! `__indirect_function_table`, which `WasmCodegen` emits for modules
+//! importing `__wasmrust_closure_new`.
+//!
+//! Modules linking the externref table imports hold JS objects as `i32`
+//! slots in the glue's `heap`. Objects passed to Rust are rooted there and
+//! unrooted when Rust releases them; debug glue also reports double
+//! releases and lists leaked slots with the stack that rooted them.
+//!
+//! Modules compiled for this glue should use
+//! `InitStrategy::ExportedCallCtors`: constructors then run after the glue
+//! has bound the instance exports, so imports touching memory work.
+//!
+//! Modules spawning threads (see `wasm::threading::runtime`) import a
+//! shared memory, which the glue creates from the limits in the binary,
+//! and the wasi-threads spawn function. Every thread runs on a Worker the
+//! main thread creates from this same ES module, which instantiates the
+//! compiled module against the shared memory without running constructors
+//! and enters the thread's start export. Workers whose thread returned are
+//! pooled and run the next spawned thread on the same instance. Threads
+//! only get the glue's own imports, since user imports cannot be posted to
+//! a Worker.
+//!
+//! Shared memory needs `SharedArrayBuffer`, which browsers only expose to
+//! cross-origin isolated pages. Given a `fallback` module built without
+//! atomics, the glue of a threaded module loads it instead where shared
+//! memory is unavailable; spawning a thread then fails with
+//! `ThreadCreationFailed`, which callers can handle by doing the work on
+//! the calling thread.
+//!
+//! Builds producing several variants of a module, such as with and
+//! without SIMD, give each one to `variant` along with the proposals it
+//! needs. The glue then probes the host with a tiny module per proposal
+//! and loads the first variant it can run, or the last one listed.
+//!
+//! The glue of a module split by `backend::split` forwards its calls of
+//! cold functions to the secondary module. ES module glue exports
+//! `loadSecondary`, which fetches and links it once; calling a cold
+//! function before it settles throws. CommonJS glue reads the secondary
+//! the first time a cold function is called. Both export
+//! `loadSecondarySync`, linking a secondary the caller already has.
+//!
+//! Glue generated with `plugins` exports `loadPlugin` and
+//! `loadPluginSync`, which link side modules into the running instance;
+//! see `backend::dylink`. A plugin's memory and shadow stack are allocated
+//! with the module's `__wasm_alloc`, or in pages grown for it if there is
+//! none, and its table entries are appended to the exported
+//! `__indirect_function_table`. Its `env` imports resolve against the
+//! plugin imports passed in, the module's exports, then the exports of the
+//! plugins loaded before it; the rest of its imports get the module's own.
+//!
+//! Glue generated with `hot_reload` for a dev server also exports
+//! `hotSwap`, which instantiates a rebuilt module and rebinds the wrappers
+//! to it. Asked to, it carries the old instance's linear memory and
+//! exported globals over instead of running constructors, which is only
+//! sound when the rebuild left the memory layout as it was. Threaded
+//! modules cannot be swapped, as their Workers hold the old instance.
+//!
+//! Glue generated with `emscripten` runs modules built for
+//! `wasm32-unknown-emscripten`: it implements the system calls of
+//! Emscripten's libc and the module's `EM_JS` functions, and exports
+//! `callMain`, which runs `main` with the arguments it is given and
+//! returns the exit status; see `backend::emscripten`.
+
+use crate::backend::codegen::CALL_CTORS_EXPORT;
+use crate::backend::dylink::{
+    APPLY_DATA_RELOCS_EXPORT, APPLY_RELOCS_EXPORT, DYLINK_SECTION, GOT_FUNC_MODULE, GOT_MEM_MODULE,
+    MEMORY_BASE_IMPORT, TABLE_BASE_IMPORT, TABLE_IMPORT,
+};
+use crate::backend::emscripten::{self, EmJsFunction};
+use crate::backend::split::{secondary_file_name, PRIMARY_IMPORT_MODULE, SECONDARY_IMPORT_MODULE};
+use crate::backend::BackendError;
+use std::fmt::Write;
+use wasm::bench::NOW_IMPORT;
+use wasm::memory::allocator::{HOST_ALLOC_IMPORT, HOST_FREE_IMPORT};
+use wasm::memory::stack::{DEFAULT_STACK_SIZE, STACK_POINTER_GLOBAL};
+use wasm::threading::async_rt::{
+    CLEAR_TIMEOUT_IMPORT, RUN_TASKS_EXPORT, SCHEDULE_RUN_IMPORT, SET_TIMEOUT_IMPORT, TIMER_FIRED_EXPORT, WAKE_SIGNAL_EXPORT,
+};
+use wasm::threading::runtime::{SPAWN_IMPORT, SPAWN_IMPORT_MODULE, THREAD_MAIN_EXPORT, THREAD_START_EXPORT};
+use wasm::wasmir::{
+    ExportKind, Import, ImportKind, InteropSignature, InteropType, PromiseLowering, Signature, Type, WasmModule,
+    ALLOC_EXPORT, CLOSURE_NEW_IMPORT, EXTERNREF_CLONE_IMPORT, EXTERNREF_RELEASE_IMPORT, FREE_EXPORT, JS_IMPORT_MODULE,
+    MEMORY_IMPORT, MEMORY_IMPORT_MODULE, PANIC_IMPORT, PANIC_MESSAGE_IMPORT,
+};
+use wasm::wasmir::features::Proposal;
+
+/// Runtime imports provided by the glue rather than derived from WasmIR
+const RUNTIME_IMPORTS: &[(&str, &str)] = &[
+    (
+        "__wasmrust_array_buffer_new",
+        "(len) => { buffers.push(new ArrayBuffer(len)); return buffers.length - 1; }",
+    ),
+    (
+        "__wasmrust_array_buffer_read",
+        "(handle, offset, dst, len) => { memoryBytes().set(new Uint8Array(buffers[handle], offset, len), dst); }",
+    ),
+    (
+        "__wasmrust_array_buffer_write",
+        "(handle, offset, src, len) => { new Uint8Array(buffers[handle], offset, len).set(memoryBytes().subarray(src, src + len)); }",
+    ),
+    (
+        "__wasmrust_array_buffer_release",
+        "(handle) => { buffers[handle] = undefined; }",
+    ),
+    (
+        CLOSURE_NEW_IMPORT,
+        "(invoke, data, drop) => newClosure(invoke, data, drop)",
+    ),
+    (
+        "__wasmrust_closure_release",
+        "(handle) => releaseClosure(handle)",
+    ),
+    (
+        PROMISE_NEW_IMPORT,
+        "() => newPromise()",
+    ),
+    (
+        EXTERNREF_CLONE_IMPORT,
+        "(handle) => rootExternRef(getExternRef(handle))",
+    ),
+    (
+        EXTERNREF_RELEASE_IMPORT,
+        "(handle) => unrootExternRef(handle)",
+    ),
+    (
+        "__wasmrust_promise_resolve",
+        "(handle, fulfilled, value) => resolvePromise(handle, fulfilled, value)",
+    ),
+    (
+        SCHEDULE_RUN_IMPORT,
+        "() => scheduleRun()",
+    ),
+    (
+        SET_TIMEOUT_IMPORT,
+        "(id, millis) => setTimer(id, millis)",
+    ),
+    (
+        CLEAR_TIMEOUT_IMPORT,
+        "(id) => clearTimer(id)",
+    ),
+    (
+        NOW_IMPORT,
+        "() => performance.now()",
+    ),
+    (
+        PANIC_IMPORT,
+        "(id) => { throw new Error(panicMessages[id] ?? \"wasm module panicked\"); }",
+    ),
+    (
+        PANIC_MESSAGE_IMPORT,
+        "(ptr, len) => { throw new Error(new TextDecoder().decode(memoryBytes().slice(ptr, ptr + len))); }",
+    ),
+];
+
+/// Runtime import creating a promise an async export settles later
+const PROMISE_NEW_IMPORT: &str = "__wasmrust_promise_new";
+
+/// Runtime export the glue reports settled JS promises to
+const PROMISE_SETTLED_EXPORT: &str = "__wasmrust_promise_settled";
+
+/// Query parameter marking the glue module a thread's Worker runs
+const THREAD_WORKER_PARAM: &str = "wasmrust-thread";
+
+/// Module system used by the generated glue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlueFormat {
+    /// ES module (`.mjs`) with async `init` and `initSync`
+    #[default]
+    EsModule,
+    /// CommonJS (`.js`) that instantiates synchronously on `require`
+    CommonJs,
+}
+
+impl GlueFormat {
+    /// File extension for the glue module
+    pub fn extension(self) -> &'static str {
+        match self {
+            GlueFormat::EsModule => "mjs",
+            GlueFormat::CommonJs => "js",
+        }
+    }
+
+    /// Name used in `[package.metadata.wasm]`
+    pub fn name(self) -> &'static str {
+        match self {
+            GlueFormat::EsModule => "esm",
+            GlueFormat::CommonJs => "cjs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [GlueFormat::EsModule, GlueFormat::CommonJs].into_iter().find(|format| format.name() == name)
+    }
+}
+
+/// Generates JavaScript glue from WasmIR module metadata
+#[derive(Debug, Clone)]
+pub struct JsGlueGenerator {
+    wasm_file: String,
+    fallback: Option<String>,
+    secondary: Option<String>,
+    variants: Vec<GlueVariant>,
+    format: GlueFormat,
+    debug: bool,
+    hot_reload: bool,
+    plugins: bool,
+    emscripten: Option<Vec<EmJsFunction>>,
+}
+
+/// A `.wasm` file of a multi-variant build and the proposals it needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GlueVariant {
+    wasm_file: String,
+    features: Vec<Proposal>,
+}
+
+impl JsGlueGenerator {
+    /// Creates a generator for the given `.wasm` file name
+    pub fn new(wasm_file: impl Into<String>) -> Self {
+        Self {
+            wasm_file: wasm_file.into(),
+            fallback: None,
+            secondary: None,
+            variants: Vec::new(),
+            format: GlueFormat::default(),
+            debug: false,
+            hot_reload: false,
+            plugins: false,
+            emscripten: None,
+        }
+    }
+
+    /// Sets the module system of the generated glue
+    pub fn format(mut self, format: GlueFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Names the `.wasm` file built without atomics, loaded by the glue of
+    /// a threaded module where shared memory is unavailable
+    pub fn fallback(mut self, wasm_file: impl Into<String>) -> Self {
+        self.fallback = Some(wasm_file.into());
+        self
+    }
+
+    /// Names the `.wasm` file of the cold functions of a split module, by
+    /// default its `split::secondary_file_name`
+    pub fn secondary(mut self, wasm_file: impl Into<String>) -> Self {
+        self.secondary = Some(wasm_file.into());
+        self
+    }
+
+    /// Adds a `.wasm` file the glue loads where the host supports `features`
+    ///
+    /// Variants are tried in the order they were added, so the most
+    /// demanding comes first and the last is loaded when none fits.
+    pub fn variant(mut self, wasm_file: impl Into<String>, features: impl IntoIterator<Item = Proposal>) -> Self {
+        self.variants.push(GlueVariant { wasm_file: wasm_file.into(), features: features.into_iter().collect() });
+        self
+    }
+
+    /// Enables runtime checks and leak reporting for externref handles
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Exports `hotSwap` from ES module glue of single-threaded modules
+    pub fn hot_reload(mut self, hot_reload: bool) -> Self {
+        self.hot_reload = hot_reload;
+        self
+    }
+
+    /// Exports `loadPlugin` and `loadPluginSync`, linking side modules into the instance
+    pub fn plugins(mut self, plugins: bool) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    /// Implements the Emscripten ABI, with the module's `EM_JS` functions
+    /// from `emscripten::read_em_js`, and exports `callMain`
+    pub fn emscripten(mut self, em_js: Vec<EmJsFunction>) -> Self {
+        self.emscripten = Some(em_js);
+        self
+    }
+
+    /// Generates the glue source for a module
+    pub fn generate(&self, module: &WasmModule) -> Result<String, BackendError> {
+        let mut out = String::new();
+        out.push_str("// Generated by WasmRust. Do not edit.\n\n");
+        out.push_str("const buffers = [undefined];\n");
+        out.push_str("let wasm;\n");
+        out.push_str("let currentUserImports;\n\n");
+        out.push_str("function memoryBytes() {\n");
+        out.push_str("  return new Uint8Array(wasm.memory.buffer);\n");
+        out.push_str("}\n\n");
+        out.push_str("function exports() {\n");
+        out.push_str("  if (!wasm) throw new Error(\"WasmRust module is not initialized\");\n");
+        out.push_str("  return wasm;\n");
+        out.push_str("}\n\n");
+
+        if !self.variants.is_empty() {
+            self.generate_variant_selection(&mut out);
+        }
+        if needs_marshaling(module) {
+            self.generate_marshaling_helpers(&mut out);
+        }
+        if module.uses_closures() {
+            self.generate_closure_helpers(&mut out);
+        }
+        if module.uses_externref_table() {
+            self.generate_externref_helpers(&mut out);
+        }
+        if uses_promises(module) {
+            self.generate_promise_helpers(&mut out, module.promise_lowering);
+        }
+        if uses_tasks(module) {
+            self.generate_task_helpers(&mut out);
+        }
+        if module.find_import(JS_IMPORT_MODULE, PANIC_IMPORT).is_some() {
+            self.generate_panic_messages(&mut out, module);
+        }
+        let threads = uses_threads(module);
+        if threads {
+            self.generate_thread_helpers(&mut out)?;
+        }
+        let split = is_split(module);
+        if split && self.has_alternatives() {
+            return Err(BackendError::Unsupported(
+                "a split module cannot have a fallback or variants".to_string(),
+            ));
+        }
+        if self.plugins && threads {
+            return Err(BackendError::Unsupported(
+                "plugins cannot be loaded into a threaded module, whose Workers would not see them".to_string(),
+            ));
+        }
+        if let Some(em_js) = &self.emscripten {
+            if threads {
+                return Err(BackendError::Unsupported("Emscripten glue cannot run threaded modules".to_string()));
+            }
+            emscripten::generate_runtime(&mut out, em_js);
+        }
+        self.generate_imports(&mut out, module, threads)?;
+        self.generate_instantiation(&mut out, threads, threads && uses_tasks(module));
+        if split {
+            self.generate_secondary_loader(&mut out);
+        }
+        if self.plugins {
+            self.generate_plugin_loader(&mut out);
+        }
+        if self.hot_reload && !threads && self.format == GlueFormat::EsModule {
+            self.generate_hot_swap(&mut out, split);
+        }
+        self.generate_wrappers(&mut out, module)?;
+        if self.emscripten.is_some() {
+            let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+            emscripten::generate_call_main(&mut out, export);
+        }
+        if threads {
+            self.generate_thread_worker(&mut out);
+        }
+
+        if self.format == GlueFormat::CommonJs {
+            self.generate_commonjs_footer(&mut out, module);
+        }
+
+        Ok(out)
+    }
+
+    /// Whether the module loaded may be one built without threads
+    fn has_alternatives(&self) -> bool {
+        self.fallback.is_some() || !self.variants.is_empty()
+    }
+
+    /// Feature detection and `pickVariant`, naming the variant to load
+    ///
+    /// Only proposals some variant needs are probed. Threads also need
+    /// shared memory, which browsers only give isolated pages.
+    fn generate_variant_selection(&self, out: &mut String) {
+        let mut probed: Vec<Proposal> = Vec::new();
+        for &proposal in self.variants.iter().flat_map(|variant| &variant.features) {
+            if !probed.contains(&proposal) {
+                probed.push(proposal);
+            }
+        }
+        out.push_str("const featureProbes = {\n");
+        for proposal in &probed {
+            let bytes: Vec<String> = feature_probe(*proposal).iter().map(u8::to_string).collect();
+            let _ = writeln!(out, "  {}: [{}],", js_string(proposal.name()), bytes.join(", "));
+        }
+        out.push_str("};\n\n");
+
+        out.push_str("function sharedMemoryAvailable() {\n");
+        out.push_str("  return typeof SharedArrayBuffer === \"function\" && globalThis.crossOriginIsolated !== false;\n");
+        out.push_str("}\n\n");
+        out.push_str("function supportsFeature(feature) {\n");
+        let threads = js_string(Proposal::Threads.name());
+        let _ = writeln!(out, "  if (feature === {} && !sharedMemoryAvailable()) return false;", threads);
+        out.push_str("  return WebAssembly.validate(new Uint8Array(featureProbes[feature]));\n");
+        out.push_str("}\n\n");
+
+        out.push_str("const moduleVariants = [\n");
+        for variant in &self.variants {
+            let features: Vec<String> = variant.features.iter().map(|proposal| js_string(proposal.name())).collect();
+            let file = js_string(&variant.wasm_file);
+            let _ = writeln!(out, "  {{ file: {}, features: [{}] }},", file, features.join(", "));
+        }
+        out.push_str("];\n\n");
+
+        out.push_str("function pickVariant() {\n");
+        out.push_str("  const variant = moduleVariants.find(({ features }) => features.every(supportsFeature));\n");
+        out.push_str("  return (variant ?? moduleVariants[moduleVariants.length - 1]).file;\n");
+        out.push_str("}\n\n");
+    }
+
+    /// Marshaling helpers for the memory ABI described by `InteropSignature`
+    fn generate_marshaling_helpers(&self, out: &mut String) {
+        out.push_str("const textEncoder = new TextEncoder();\n");
+        out.push_str("const textDecoder = new TextDecoder();\n\n");
+
+        out.push_str("function allocate(size, align, allocations) {\n");
+        let _ = writeln!(out, "  const ptr = exports().{}(size, align) >>> 0;", ALLOC_EXPORT);
+        out.push_str("  if (allocations) allocations.push([ptr, size, align]);\n");
+        out.push_str("  return ptr;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function release(allocations) {\n");
+        out.push_str("  for (const [ptr, size, align] of allocations) ");
+        let _ = writeln!(out, "exports().{}(ptr, size, align);", FREE_EXPORT);
+        out.push_str("}\n\n");
+
+        out.push_str("function readReturnArea(retptr) {\n");
+        out.push_str("  const view = new DataView(exports().memory.buffer);\n");
+        out.push_str("  return [view.getUint32(retptr, true), view.getUint32(retptr + 4, true)];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function passString(value, allocations) {\n");
+        out.push_str("  const bytes = textEncoder.encode(value);\n");
+        out.push_str("  const ptr = allocate(bytes.length, 1, allocations);\n");
+        out.push_str("  memoryBytes().set(bytes, ptr);\n");
+        out.push_str("  return [ptr, bytes.length];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function writeReturnArea(retptr, ptr, len) {\n");
+        out.push_str("  const view = new DataView(exports().memory.buffer);\n");
+        out.push_str("  view.setUint32(retptr, ptr, true);\n");
+        out.push_str("  view.setUint32(retptr + 4, len, true);\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function readString(ptr, len) {\n");
+        // Copied first: TextDecoder rejects views of shared memory
+        out.push_str("  return textDecoder.decode(memoryBytes().slice(ptr, ptr + len));\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function takeString(retptr) {\n");
+        out.push_str("  const [ptr, len] = readReturnArea(retptr);\n");
+        out.push_str("  const value = readString(ptr, len);\n");
+        let _ = writeln!(out, "  exports().{}(ptr, len, 1);", FREE_EXPORT);
+        out.push_str("  return value;\n");
+        out.push_str("}\n\n");
+
+        // Borrowed typed arrays that already view linear memory are passed
+        // without copying; everything else is copied into a fresh buffer,
+        // which only the callee frees when ownership moves.
+        out.push_str("function passArray(value, ArrayType, allocations) {\n");
+        out.push_str("  if (allocations && value instanceof ArrayType && value.buffer === exports().memory.buffer) {\n");
+        out.push_str("    return [value.byteOffset, value.length];\n");
+        out.push_str("  }\n");
+        out.push_str("  const size = value.length * ArrayType.BYTES_PER_ELEMENT;\n");
+        out.push_str("  const ptr = allocate(size, ArrayType.BYTES_PER_ELEMENT, allocations);\n");
+        out.push_str("  new ArrayType(exports().memory.buffer, ptr, value.length).set(value);\n");
+        out.push_str("  return [ptr, value.length];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function copyBack(target, ptr, len, ArrayType) {\n");
+        out.push_str("  const view = new ArrayType(exports().memory.buffer, ptr, len);\n");
+        out.push_str("  if (target.buffer === view.buffer && target.byteOffset === ptr) return;\n");
+        out.push_str("  for (let i = 0; i < len; i++) target[i] = view[i];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function takeArray(retptr, ArrayType) {\n");
+        out.push_str("  const [ptr, len] = readReturnArea(retptr);\n");
+        out.push_str("  const value = new ArrayType(exports().memory.buffer, ptr, len).slice();\n");
+        let _ = writeln!(
+            out,
+            "  exports().{}(ptr, len * ArrayType.BYTES_PER_ELEMENT, ArrayType.BYTES_PER_ELEMENT);",
+            FREE_EXPORT
+        );
+        out.push_str("  return value;\n");
+        out.push_str("}\n\n");
+
+        // Results of imports become owned by the module
+        out.push_str("function returnString(retptr, value) {\n");
+        out.push_str("  const [ptr, len] = passString(value, null);\n");
+        out.push_str("  writeReturnArea(retptr, ptr, len);\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function returnArray(retptr, value, ArrayType) {\n");
+        out.push_str("  const [ptr, len] = passArray(value, ArrayType, null);\n");
+        out.push_str("  writeReturnArea(retptr, ptr, len);\n");
+        out.push_str("}\n\n");
+    }
+
+    /// Messages of compiled panics, indexed by the id passed to the panic hook
+    fn generate_panic_messages(&self, out: &mut String, module: &WasmModule) {
+        let messages: Vec<String> = module.panic_messages().into_iter().map(js_string).collect();
+        let _ = writeln!(out, "const panicMessages = [{}];\n", messages.join(", "));
+    }
+
+    /// Table of Rust closures exposed as JavaScript functions
+    ///
+    /// Each entry keeps the drop trampoline and data pointer so the closure
+    /// is freed exactly once, whether Rust drops its `JsClosure` or JS calls
+    /// `free()` on a function whose ownership was transferred.
+    fn generate_closure_helpers(&self, out: &mut String) {
+        out.push_str("const closures = [undefined];\n\n");
+
+        out.push_str("function newClosure(invoke, data, drop) {\n");
+        out.push_str("  const handle = closures.length;\n");
+        out.push_str("  const callback = (...args) => {\n");
+        out.push_str("    if (!closures[handle]) throw new Error(\"closure invoked after it was freed\");\n");
+        out.push_str("    return exports().__indirect_function_table.get(invoke)(data, ...args);\n");
+        out.push_str("  };\n");
+        out.push_str("  callback.free = () => releaseClosure(handle);\n");
+        out.push_str("  closures.push({ callback, drop, data });\n");
+        out.push_str("  return handle;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function releaseClosure(handle) {\n");
+        out.push_str("  const entry = closures[handle];\n");
+        out.push_str("  if (!entry) return;\n");
+        out.push_str("  closures[handle] = undefined;\n");
+        out.push_str("  exports().__indirect_function_table.get(entry.drop)(entry.data);\n");
+        out.push_str("}\n\n");
+
+        let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+        out.push_str("/**\n");
+        out.push_str(" * Gets the JavaScript function for a closure handle returned by an export\n");
+        out.push_str(" * @param {number} handle\n");
+        out.push_str(" * @returns {Function}\n");
+        out.push_str(" */\n");
+        let _ = writeln!(out, "{}function closure(handle) {{", export);
+        out.push_str("  const entry = closures[handle];\n");
+        out.push_str("  if (!entry) throw new Error(`no closure with handle ${handle}`);\n");
+        out.push_str("  return entry.callback;\n");
+        out.push_str("}\n\n");
+    }
+
+    /// Table rooting the JS objects Rust holds as `ExternRef` handles
+    ///
+    /// Slot 0 is the null reference and is never rooted. Freed slots are
+    /// reused, so debug glue checks every access against the rooted set.
+    fn generate_externref_helpers(&self, out: &mut String) {
+        out.push_str("const heap = [undefined];\n");
+        out.push_str("const heapFree = [];\n");
+        if self.debug {
+            out.push_str("const heapSites = new Map();\n");
+        }
+        out.push('\n');
+
+        out.push_str("function rootExternRef(value) {\n");
+        out.push_str("  if (value === null || value === undefined) return 0;\n");
+        out.push_str("  const handle = heapFree.length ? heapFree.pop() : heap.length;\n");
+        out.push_str("  heap[handle] = value;\n");
+        if self.debug {
+            out.push_str("  heapSites.set(handle, new Error(\"externref rooted\").stack);\n");
+        }
+        out.push_str("  return handle;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function getExternRef(handle) {\n");
+        out.push_str("  if (handle === 0) return null;\n");
+        if self.debug {
+            out.push_str("  if (!heapSites.has(handle)) throw new Error(`externref ${handle} is not rooted`);\n");
+        }
+        out.push_str("  return heap[handle];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function unrootExternRef(handle) {\n");
+        if self.debug {
+            out.push_str("  if (handle !== 0 && !heapSites.delete(handle)) {\n");
+            out.push_str("    throw new Error(`externref ${handle} released twice or never rooted`);\n");
+            out.push_str("  }\n");
+        }
+        // Rooted values are never undefined, which guards against freeing a slot twice
+        out.push_str("  if (handle === 0 || heap[handle] === undefined) return;\n");
+        out.push_str("  heap[handle] = undefined;\n");
+        out.push_str("  heapFree.push(handle);\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function takeExternRef(handle) {\n");
+        out.push_str("  const value = getExternRef(handle);\n");
+        out.push_str("  unrootExternRef(handle);\n");
+        out.push_str("  return value;\n");
+        out.push_str("}\n\n");
+
+        if self.debug {
+            let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+            out.push_str("/**\n");
+            out.push_str(" * Lists externref handles still rooted, with the stack that rooted each\n");
+            out.push_str(" * @returns {{handle: number, stack: string}[]}\n");
+            out.push_str(" */\n");
+            let _ = writeln!(out, "{}function externrefLeaks() {{", export);
+            out.push_str("  return Array.from(heapSites, ([handle, stack]) => ({ handle, stack }));\n");
+            out.push_str("}\n\n");
+        }
+    }
+
+    /// Promise bridging for the module's `PromiseLowering`
+    fn generate_promise_helpers(&self, out: &mut String, lowering: PromiseLowering) {
+        if lowering == PromiseLowering::Jspi {
+            out.push_str("const promisingExports = new Map();\n\n");
+            out.push_str("function promising(name) {\n");
+            out.push_str("  let wrapper = promisingExports.get(name);\n");
+            out.push_str("  if (!wrapper) {\n");
+            out.push_str("    wrapper = WebAssembly.promising(exports()[name]);\n");
+            out.push_str("    promisingExports.set(name, wrapper);\n");
+            out.push_str("  }\n");
+            out.push_str("  return wrapper;\n");
+            out.push_str("}\n\n");
+            return;
+        }
+
+        out.push_str("const promises = [undefined];\n\n");
+
+        // JS promises awaited by Rust; settlement may precede the await
+        out.push_str("function registerPromise(promise) {\n");
+        out.push_str("  const handle = promises.length;\n");
+        out.push_str("  promises.push({ promise });\n");
+        out.push_str("  const settle = (fulfilled, value) => {\n");
+        out.push_str("    promises[handle] = undefined;\n");
+        let _ = writeln!(out, "    exports().{}(handle, fulfilled, value);", PROMISE_SETTLED_EXPORT);
+        out.push_str("  };\n");
+        out.push_str("  Promise.resolve(promise).then((value) => settle(1, Number(value)), () => settle(0, 0));\n");
+        out.push_str("  return handle;\n");
+        out.push_str("}\n\n");
+
+        // Promises returned by async exports; the export may settle its
+        // promise before the wrapper takes it, so entries live until both
+        out.push_str("function newPromise() {\n");
+        out.push_str("  const entry = { settled: false, taken: false };\n");
+        out.push_str("  entry.promise = new Promise((resolve, reject) => {\n");
+        out.push_str("    entry.resolve = resolve;\n");
+        out.push_str("    entry.reject = reject;\n");
+        out.push_str("  });\n");
+        out.push_str("  promises.push(entry);\n");
+        out.push_str("  return promises.length - 1;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function resolvePromise(handle, fulfilled, value) {\n");
+        out.push_str("  const entry = promises[handle];\n");
+        out.push_str("  if (fulfilled) entry.resolve(value);\n");
+        out.push_str("  else entry.reject(new Error(\"WasmRust async export failed\"));\n");
+        out.push_str("  entry.settled = true;\n");
+        out.push_str("  if (entry.taken) promises[handle] = undefined;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function takePromise(handle) {\n");
+        out.push_str("  const entry = promises[handle];\n");
+        out.push_str("  entry.taken = true;\n");
+        out.push_str("  if (entry.settled) promises[handle] = undefined;\n");
+        out.push_str("  return entry.promise;\n");
+        out.push_str("}\n\n");
+    }
+
+    /// Host side of `wasm::threading::async_rt`
+    ///
+    /// Runs are coalesced into one microtask. `watchWakes` re-arms an
+    /// `Atomics.waitAsync` on the executor's wake signal after every wake.
+    fn generate_task_helpers(&self, out: &mut String) {
+        out.push_str("let runScheduled = false;\n");
+        out.push_str("const timers = new Map();\n\n");
+
+        out.push_str("function scheduleRun() {\n");
+        out.push_str("  if (runScheduled) return;\n");
+        out.push_str("  runScheduled = true;\n");
+        out.push_str("  queueMicrotask(() => {\n");
+        out.push_str("    runScheduled = false;\n");
+        let _ = writeln!(out, "    exports().{}();", RUN_TASKS_EXPORT);
+        out.push_str("  });\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function setTimer(id, millis) {\n");
+        out.push_str("  timers.set(id, setTimeout(() => {\n");
+        out.push_str("    timers.delete(id);\n");
+        let _ = writeln!(out, "    exports().{}(id);", TIMER_FIRED_EXPORT);
+        out.push_str("  }, millis));\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function clearTimer(id) {\n");
+        out.push_str("  clearTimeout(timers.get(id));\n");
+        out.push_str("  timers.delete(id);\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function watchWakes() {\n");
+        let _ = writeln!(out, "  if (typeof wasm.{} !== \"function\" || !Atomics.waitAsync) return;", WAKE_SIGNAL_EXPORT);
+        let _ = writeln!(out, "  const index = wasm.{}() >>> 2;", WAKE_SIGNAL_EXPORT);
+        out.push_str("  const signal = new Int32Array(wasm.memory.buffer);\n");
+        out.push_str("  const wait = () => {\n");
+        out.push_str("    const result = Atomics.waitAsync(signal, index, Atomics.load(signal, index));\n");
+        out.push_str("    Promise.resolve(result.value).then(() => {\n");
+        out.push_str("      scheduleRun();\n");
+        out.push_str("      wait();\n");
+        out.push_str("    });\n");
+        out.push_str("  };\n");
+        out.push_str("  wait();\n");
+        out.push_str("}\n\n");
+    }
+
+    /// Shared memory setup and the wasi-threads spawn import
+    ///
+    /// Thread ids come from a shared counter so any thread can spawn.
+    /// Workers ask the main thread to start the thread, since a nested
+    /// Worker would end with the thread that created it. The main thread
+    /// keeps up to `hardwareConcurrency` idle Workers for reuse.
+    fn generate_thread_helpers(&self, out: &mut String) -> Result<(), BackendError> {
+        if self.format != GlueFormat::EsModule {
+            return Err(BackendError::Unsupported(
+                "threads need ES module glue to start Workers".to_string(),
+            ));
+        }
+        let _ = writeln!(
+            out,
+            "const isThreadWorker = typeof WorkerGlobalScope !== \"undefined\" && new URL(import.meta.url).searchParams.has({});",
+            js_string(THREAD_WORKER_PARAM)
+        );
+        out.push_str("let wasmModule;\n");
+        out.push_str("let threadMemory;\n");
+        out.push_str("let threadIds;\n");
+        out.push_str("const idleWorkers = [];\n");
+        out.push_str("const maxIdleWorkers = globalThis.navigator?.hardwareConcurrency ?? 4;\n\n");
+        if self.has_alternatives() {
+            // Without isolation browsers hide SharedArrayBuffer or refuse to post it
+            out.push_str("const sharedMemory = typeof SharedArrayBuffer === \"function\" && globalThis.crossOriginIsolated !== false;\n\n");
+            out.push_str("function importsMemory(module) {\n");
+            out.push_str("  return WebAssembly.Module.imports(module).some(({ kind }) => kind === \"memory\");\n");
+            out.push_str("}\n\n");
+        }
+
+        out.push_str("function memoryLimits(bytes) {\n");
+        out.push_str("  if (bytes instanceof WebAssembly.Module) {\n");
+        out.push_str("    throw new Error(\"pass env.memory to instantiate a compiled module that spawns threads\");\n");
+        out.push_str("  }\n");
+        out.push_str("  const view = ArrayBuffer.isView(bytes) ? new Uint8Array(bytes.buffer, bytes.byteOffset, bytes.byteLength) : new Uint8Array(bytes);\n");
+        out.push_str("  let pos = 8;\n");
+        out.push_str("  const leb = () => {\n");
+        out.push_str("    let result = 0, shift = 0, byte;\n");
+        out.push_str("    do { byte = view[pos++]; result |= (byte & 0x7f) << shift; shift += 7; } while (byte & 0x80);\n");
+        out.push_str("    return result >>> 0;\n");
+        out.push_str("  };\n");
+        out.push_str("  const name = () => { const len = leb(); pos += len; return String.fromCharCode(...view.subarray(pos - len, pos)); };\n");
+        out.push_str("  while (pos < view.length) {\n");
+        out.push_str("    const id = view[pos++];\n");
+        out.push_str("    const end = leb() + pos;\n");
+        out.push_str("    for (let count = id === 2 ? leb() : 0; count > 0; count--) {\n");
+        out.push_str("      const module = name(), field = name(), kind = view[pos++];\n");
+        out.push_str("      if (kind === 0) { leb(); continue; }\n");
+        out.push_str("      const flags = view[pos++], initial = leb(), maximum = flags & 1 ? leb() : undefined;\n");
+        let _ = writeln!(
+            out,
+            "      if (kind === 2 && module === {} && field === {}) return {{ initial, maximum }};",
+            js_string(MEMORY_IMPORT_MODULE),
+            js_string(MEMORY_IMPORT)
+        );
+        out.push_str("    }\n");
+        out.push_str("    pos = end;\n");
+        out.push_str("  }\n");
+        out.push_str("  throw new Error(\"module does not import a shared memory\");\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function prepareThreads(module, bytes, userImports) {\n");
+        out.push_str("  wasmModule = module;\n");
+        out.push_str("  threadIds = new Int32Array(new SharedArrayBuffer(4));\n");
+        out.push_str("  threadIds[0] = 1;\n");
+        let _ = writeln!(
+            out,
+            "  threadMemory = userImports?.[{}]?.[{}] ?? new WebAssembly.Memory({{ ...memoryLimits(bytes), shared: true }});",
+            js_string(MEMORY_IMPORT_MODULE),
+            js_string(MEMORY_IMPORT)
+        );
+        out.push_str("}\n\n");
+
+        out.push_str("function startWorker(id, startArg) {\n");
+        out.push_str("  const idle = idleWorkers.pop();\n");
+        out.push_str("  if (idle) {\n");
+        out.push_str("    idle.postMessage({ id, startArg });\n");
+        out.push_str("    return;\n");
+        out.push_str("  }\n");
+        out.push_str("  const url = new URL(import.meta.url);\n");
+        let _ = writeln!(out, "  url.searchParams.set({}, \"\");", js_string(THREAD_WORKER_PARAM));
+        out.push_str("  const worker = new Worker(url, { type: \"module\", name: `wasm-thread-${id}` });\n");
+        out.push_str("  worker.addEventListener(\"message\", ({ data }) => {\n");
+        out.push_str("    if (data?.wasmrustSpawn) startWorker(data.wasmrustSpawn.id, data.wasmrustSpawn.startArg);\n");
+        out.push_str("    if (data?.wasmrustIdle) {\n");
+        out.push_str("      if (idleWorkers.length < maxIdleWorkers) idleWorkers.push(worker);\n");
+        out.push_str("      else worker.terminate();\n");
+        out.push_str("    }\n");
+        out.push_str("  });\n");
+        out.push_str("  worker.postMessage({ module: wasmModule, memory: threadMemory, threadIds, id, startArg });\n");
+        out.push_str("}\n\n");
+
+        // The thread's `StartRecord` holds the address of its done flag at
+        // offset 4; setting it without a result fails the join
+        out.push_str("function failThread(startArg) {\n");
+        out.push_str("  const words = new Int32Array(threadMemory.buffer);\n");
+        out.push_str("  const done = words[(startArg >>> 2) + 1] >>> 2;\n");
+        out.push_str("  Atomics.store(words, done, 1);\n");
+        out.push_str("  Atomics.notify(words, done);\n");
+        out.push_str("}\n\n");
+
+        // wasi-threads ids are positive and fit in 29 bits
+        out.push_str("function spawnThread(startArg) {\n");
+        if self.has_alternatives() {
+            // The fallback module runs on this thread alone
+            out.push_str("  if (!threadMemory) return -1;\n");
+        }
+        out.push_str("  const id = Atomics.add(threadIds, 0, 1);\n");
+        out.push_str("  if (id > 0x1fffffff) return -1;\n");
+        out.push_str("  if (isThreadWorker) {\n");
+        out.push_str("    postMessage({ wasmrustSpawn: { id, startArg } });\n");
+        out.push_str("    return id;\n");
+        out.push_str("  }\n");
+        out.push_str("  try {\n");
+        out.push_str("    startWorker(id, startArg);\n");
+        out.push_str("  } catch {\n");
+        out.push_str("    return -1;\n");
+        out.push_str("  }\n");
+        out.push_str("  return id;\n");
+        out.push_str("}\n\n");
+        Ok(())
+    }
+
+    /// Bootstrap of a thread's Worker, which runs no constructors
+    ///
+    /// The first message instantiates the module; every message then runs
+    /// one thread. A thread that traps fails its join and ends the Worker,
+    /// since its instance may be left inconsistent.
+    fn generate_thread_worker(&self, out: &mut String) {
+        out.push_str("if (isThreadWorker) {\n");
+        out.push_str("  self.addEventListener(\"message\", async ({ data }) => {\n");
+        out.push_str("    if (!wasm) {\n");
+        out.push_str("      wasmModule = data.module;\n");
+        out.push_str("      threadMemory = data.memory;\n");
+        out.push_str("      threadIds = data.threadIds;\n");
+        out.push_str("      wasm = (await WebAssembly.instantiate(wasmModule, buildImports())).exports;\n");
+        out.push_str("    }\n");
+        out.push_str("    try {\n");
+        let _ = writeln!(out, "      wasm.{}(data.id, data.startArg);", THREAD_START_EXPORT);
+        out.push_str("    } catch (error) {\n");
+        out.push_str("      failThread(data.startArg);\n");
+        out.push_str("      close();\n");
+        out.push_str("      throw error;\n");
+        out.push_str("    }\n");
+        out.push_str("    postMessage({ wasmrustIdle: true });\n");
+        out.push_str("  });\n");
+        out.push_str("}\n");
+    }
+
+    fn generate_imports(&self, out: &mut String, module: &WasmModule, threads: bool) -> Result<(), BackendError> {
+        let stubs = self.generate_import_stubs(out, module)?;
+        out.push_str("function buildImports(userImports = {}) {\n");
+        out.push_str("  const imports = {\n");
+        out.push_str(&format!("    {}: {{\n", js_string(JS_IMPORT_MODULE)));
+
+        let handles = module.uses_externref_table();
+        for (index, import) in module.imports.iter().enumerate() {
+            if import.module != JS_IMPORT_MODULE {
+                continue;
+            }
+            let body = match module.import_interop_signature(index as u32) {
+                // The stub reads the values the body takes out of memory and the table
+                Some(interop) => {
+                    let params = vec![Type::I32; interop.params.len()];
+                    let source = Import {
+                        module: import.module.clone(),
+                        name: import.name.clone(),
+                        kind: ImportKind::Function(Signature { params, returns: None }),
+                    };
+                    let body = import_body(&source, module.promise_lowering, false)?;
+                    format!("((host) => {})({})", import_stub(interop, handles)?, body)
+                }
+                None => import_body(import, module.promise_lowering, handles)?,
+            };
+            let _ = writeln!(out, "      {}: {},", js_string(&import.name), body);
+        }
+
+        out.push_str("    },\n");
+        let cold: Vec<&Import> = module.imports.iter()
+            .filter(|import| import.module == SECONDARY_IMPORT_MODULE)
+            .collect();
+        if !cold.is_empty() {
+            let _ = writeln!(out, "    {}: {{", js_string(SECONDARY_IMPORT_MODULE));
+            for import in cold {
+                let name = js_string(&import.name);
+                let _ = writeln!(out, "      {0}: (...args) => secondaryFunction({0})(...args),", name);
+            }
+            out.push_str("    },\n");
+        }
+        if threads {
+            let _ = writeln!(
+                out,
+                "    {}: {{ {}: spawnThread }},",
+                js_string(SPAWN_IMPORT_MODULE),
+                js_string(SPAWN_IMPORT)
+            );
+            let _ = writeln!(out, "    {}: {{ {}: threadMemory }},", js_string(MEMORY_IMPORT_MODULE), js_string(MEMORY_IMPORT));
+        }
+        if let Some(em_js) = &self.emscripten {
+            // Imports with interop signatures are the user's, through the stubs
+            for from in [emscripten::ENV_MODULE, emscripten::WASI_MODULE] {
+                let bodies: Vec<(&str, String)> = module.imports.iter()
+                    .enumerate()
+                    .filter(|&(index, import)| {
+                        import.module == from && module.import_interop_signature(index as u32).is_none()
+                    })
+                    .filter_map(|(_, import)| Some((import.name.as_str(), emscripten::import_body(import, em_js)?)))
+                    .collect();
+                if bodies.is_empty() {
+                    continue;
+                }
+                let _ = writeln!(out, "    {}: {{", js_string(from));
+                for (name, body) in bodies {
+                    let _ = writeln!(out, "      {}: {},", js_string(name), body);
+                }
+                out.push_str("    },
+");
+            }
+        }
+        out.push_str("  };\n");
+        out.push_str("  for (const [name, members] of Object.entries(userImports)) {\n");
+        out.push_str("    imports[name] = Object.assign(imports[name] || {}, members);\n");
+        out.push_str("  }\n");
+        if stubs {
+            out.push_str("  for (const [module, stubs] of Object.entries(importStubs)) {\n");
+            out.push_str("    const members = imports[module] = Object.assign({}, imports[module]);\n");
+            out.push_str("    for (const [name, stub] of Object.entries(stubs)) {\n");
+            out.push_str("      members[name] = stub(members[name] || missingImport(module, name));\n");
+            out.push_str("    }\n");
+            out.push_str("  }\n");
+        }
+        out.push_str("  return imports;\n");
+        out.push_str("}\n\n");
+        Ok(())
+    }
+
+    /// Stub table adapting host functions to imports with interop signatures
+    ///
+    /// Each stub takes the function the host provided and returns the
+    /// import the module calls, which reads strings and slices out of
+    /// memory and writes string and array results to the return area.
+    /// Slices are passed as views of memory, valid for the call. Returns
+    /// whether the module has any such imports.
+    fn generate_import_stubs(&self, out: &mut String, module: &WasmModule) -> Result<bool, BackendError> {
+        let handles = module.uses_externref_table();
+        let mut modules: Vec<(&str, Vec<String>)> = Vec::new();
+        for (index, import) in module.imports.iter().enumerate() {
+            let Some(interop) = module.import_interop_signature(index as u32) else { continue };
+            if import.module == JS_IMPORT_MODULE {
+                continue;
+            }
+            let stub = format!("    {}: (host) => {},", js_string(&import.name), import_stub(interop, handles)?);
+            match modules.iter_mut().find(|(name, _)| *name == import.module) {
+                Some((_, stubs)) => stubs.push(stub),
+                None => modules.push((&import.module, vec![stub])),
+            }
+        }
+        if modules.is_empty() {
+            return Ok(false);
+        }
+
+        out.push_str("function missingImport(module, name) {\n");
+        out.push_str("  return () => {\n");
+        out.push_str("    throw new Error(`missing host import ${module}.${name}`);\n");
+        out.push_str("  };\n");
+        out.push_str("}\n\n");
+        out.push_str("const importStubs = {\n");
+        for (name, stubs) in modules {
+            let _ = writeln!(out, "  {}: {{", js_string(name));
+            for stub in stubs {
+                let _ = writeln!(out, "{}", stub);
+            }
+            out.push_str("  },\n");
+        }
+        out.push_str("};\n\n");
+        Ok(true)
+    }
+
+    fn generate_instantiation(&self, out: &mut String, threads: bool, watch_wakes: bool) {
+        out.push_str("function finishInit(instance) {\n");
+        out.push_str("  wasm = instance.exports;\n");
+        let _ = writeln!(
+            out,
+            "  if (typeof wasm.{0} === \"function\") wasm.{0}();",
+            CALL_CTORS_EXPORT
+        );
+        if watch_wakes && self.has_alternatives() {
+            out.push_str("  if (threadMemory) watchWakes();\n");
+        } else if watch_wakes {
+            out.push_str("  watchWakes();\n");
+        }
+        out.push_str("  return wasm;\n");
+        out.push_str("}\n\n");
+
+        let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+        let _ = writeln!(out, "{}function initSync(bytes, userImports) {{", export);
+        out.push_str("  currentUserImports = userImports;\n");
+        out.push_str("  const module = bytes instanceof WebAssembly.Module ? bytes : new WebAssembly.Module(bytes);\n");
+        if threads {
+            out.push_str(self.prepare_threads());
+        }
+        out.push_str("  return finishInit(new WebAssembly.Instance(module, buildImports(userImports)));\n");
+        out.push_str("}\n\n");
+
+        if self.format == GlueFormat::EsModule {
+            let wasm_file = match &self.fallback {
+                _ if !self.variants.is_empty() => "pickVariant()".to_string(),
+                Some(fallback) if threads => {
+                    format!("sharedMemory ? {} : {}", js_string(&self.wasm_file), js_string(fallback))
+                }
+                _ => js_string(&self.wasm_file),
+            };
+            let _ = writeln!(
+                out,
+                "export async function init(source = new URL({}, import.meta.url), userImports) {{",
+                wasm_file
+            );
+            out.push_str("  currentUserImports = userImports;\n");
+            out.push_str("  if (typeof source === \"string\" || source instanceof URL) source = fetch(source);\n");
+            out.push_str("  source = await source;\n");
+            out.push_str("  const bytes = source instanceof Response ? await source.arrayBuffer() : source;\n");
+            if threads {
+                // Workers instantiate the same compiled module
+                out.push_str("  const module = await WebAssembly.compile(bytes);\n");
+                out.push_str(self.prepare_threads());
+                out.push_str("  return finishInit(await WebAssembly.instantiate(module, buildImports(userImports)));\n");
+            } else {
+                out.push_str("  const { instance } = await WebAssembly.instantiate(bytes, buildImports(userImports));\n");
+                out.push_str("  return finishInit(instance);\n");
+            }
+            out.push_str("}\n\n");
+        }
+    }
+
+    /// Loading and linking of the secondary module of a split module
+    ///
+    /// The secondary gets the primary's imports, its memory, and its
+    /// exports for the calls back into it.
+    fn generate_secondary_loader(&self, out: &mut String) {
+        let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+        let wasm_file = match &self.secondary {
+            Some(wasm_file) => js_string(wasm_file),
+            None => js_string(&secondary_file_name(&self.wasm_file)),
+        };
+        out.push_str("let secondary;\n");
+        out.push_str("let secondaryLoading;\n\n");
+        out.push_str("function secondaryImports() {\n");
+        out.push_str("  const imports = buildImports(currentUserImports);\n");
+        let _ = writeln!(out, "  imports[{}] = exports();", js_string(PRIMARY_IMPORT_MODULE));
+        let _ = writeln!(
+            out,
+            "  imports[{0}] = Object.assign({{}}, imports[{0}], {{ {1}: exports().memory }});",
+            js_string(MEMORY_IMPORT_MODULE),
+            js_string(MEMORY_IMPORT)
+        );
+        out.push_str("  return imports;\n");
+        out.push_str("}\n\n");
+
+        let _ = writeln!(out, "{}function loadSecondarySync(bytes) {{", export);
+        out.push_str("  const module = bytes instanceof WebAssembly.Module ? bytes : new WebAssembly.Module(bytes);\n");
+        out.push_str("  secondary = new WebAssembly.Instance(module, secondaryImports()).exports;\n");
+        out.push_str("  return secondary;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function secondaryFunction(name) {\n");
+        out.push_str("  if (!secondary) {\n");
+        if self.format == GlueFormat::EsModule {
+            out.push_str("    throw new Error(`${name} is in the secondary module; await loadSecondary() first`);\n");
+        } else {
+            let _ = writeln!(out, "    const path = require(\"path\").join(__dirname, {});", wasm_file);
+            out.push_str("    loadSecondarySync(require(\"fs\").readFileSync(path));\n");
+        }
+        out.push_str("  }\n");
+        out.push_str("  return secondary[name];\n");
+        out.push_str("}\n\n");
+
+        if self.format == GlueFormat::EsModule {
+            let _ = writeln!(
+                out,
+                "export function loadSecondary(source = new URL({}, import.meta.url)) {{",
+                wasm_file
+            );
+            out.push_str("  secondaryLoading ??= (async () => {\n");
+            out.push_str("    if (typeof source === \"string\" || source instanceof URL) source = fetch(source);\n");
+            out.push_str("    source = await source;\n");
+            out.push_str("    const bytes = source instanceof Response ? await source.arrayBuffer() : source;\n");
+            out.push_str("    const { instance } = await WebAssembly.instantiate(bytes, secondaryImports());\n");
+            out.push_str("    secondary = instance.exports;\n");
+            out.push_str("    return secondary;\n");
+            out.push_str("  })();\n");
+            out.push_str("  return secondaryLoading;\n");
+            out.push_str("}\n\n");
+        }
+    }
+
+    /// `loadPlugin` and `loadPluginSync`, linking side modules into the instance
+    ///
+    /// Follows the dynamic linking ABI of `backend::dylink`: the plugin's
+    /// `dylink.0` section says how much memory and table to reserve, `GOT`
+    /// globals are set once it is instantiated, and its relocations and
+    /// constructors run before it is returned.
+    fn generate_plugin_loader(&self, out: &mut String) {
+        let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+        out.push_str("const plugins = [];\n");
+        out.push_str("let pluginTable;\n\n");
+
+        out.push_str("function readDylink(module) {\n");
+        let _ = writeln!(
+            out,
+            "  const [section] = WebAssembly.Module.customSections(module, {});",
+            js_string(DYLINK_SECTION)
+        );
+        let _ = writeln!(
+            out,
+            "  if (!section) throw new Error(\"not a shared module: no {} section\");",
+            DYLINK_SECTION
+        );
+        out.push_str("  const bytes = new Uint8Array(section);\n");
+        out.push_str("  let offset = 0;\n");
+        out.push_str("  const leb = () => {\n");
+        out.push_str("    let value = 0, shift = 0, byte;\n");
+        out.push_str("    do {\n");
+        out.push_str("      byte = bytes[offset++];\n");
+        out.push_str("      value |= (byte & 0x7f) << shift;\n");
+        out.push_str("      shift += 7;\n");
+        out.push_str("    } while (byte & 0x80);\n");
+        out.push_str("    return value >>> 0;\n");
+        out.push_str("  };\n");
+        out.push_str("  const info = { memorySize: 0, memoryAlign: 0, tableSize: 0, tableAlign: 0, needed: [] };\n");
+        out.push_str("  while (offset < bytes.length) {\n");
+        out.push_str("    const id = bytes[offset++];\n");
+        out.push_str("    const size = leb();\n");
+        out.push_str("    const end = offset + size;\n");
+        out.push_str("    if (id === 1) {\n");
+        out.push_str("      info.memorySize = leb();\n");
+        out.push_str("      info.memoryAlign = leb();\n");
+        out.push_str("      info.tableSize = leb();\n");
+        out.push_str("      info.tableAlign = leb();\n");
+        out.push_str("    } else if (id === 2) {\n");
+        out.push_str("      for (let count = leb(); count > 0; count--) {\n");
+        out.push_str("        const length = leb();\n");
+        out.push_str("        info.needed.push(new TextDecoder().decode(bytes.subarray(offset, offset + length)));\n");
+        out.push_str("        offset += length;\n");
+        out.push_str("      }\n");
+        out.push_str("    }\n");
+        out.push_str("    offset = end;\n");
+        out.push_str("  }\n");
+        out.push_str("  return info;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function reservePluginMemory(size, align) {\n");
+        out.push_str("  const main = exports();\n");
+        let _ = writeln!(out, "  if (typeof main.{} === \"function\") {{", ALLOC_EXPORT);
+        let _ = writeln!(out, "    const ptr = main.{}(size, align) >>> 0;", ALLOC_EXPORT);
+        out.push_str("    if (ptr === 0) throw new Error(\"out of memory loading a plugin\");\n");
+        out.push_str("    return ptr;\n");
+        out.push_str("  }\n");
+        out.push_str("  return main.memory.grow(Math.ceil(size / 65536)) * 65536;\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function pluginExport(name) {\n");
+        // Plugins allocate from the module's heap
+        let _ = writeln!(
+            out,
+            "  const aliases = {{ {}: {}, {}: {} }};",
+            js_string(HOST_ALLOC_IMPORT),
+            js_string(ALLOC_EXPORT),
+            js_string(HOST_FREE_IMPORT),
+            js_string(FREE_EXPORT)
+        );
+        out.push_str("  const main = exports();\n");
+        out.push_str("  if (name in main) return main[name];\n");
+        out.push_str("  if (aliases[name] in main) return main[aliases[name]];\n");
+        out.push_str("  return plugins.find((plugin) => name in plugin.exports)?.exports[name];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("function linkPlugin(module, pluginImports = {}, name) {\n");
+        out.push_str("  const info = readDylink(module);\n");
+        out.push_str("  for (const needed of info.needed) {\n");
+        out.push_str("    if (!plugins.some((plugin) => plugin.name === needed)) {\n");
+        out.push_str("      throw new Error(`plugin needs ${needed}; load it first`);\n");
+        out.push_str("    }\n");
+        out.push_str("  }\n");
+        out.push_str("  const memoryAlign = 2 ** info.memoryAlign;\n");
+        out.push_str("  const memoryBase = info.memorySize && reservePluginMemory(info.memorySize, memoryAlign);\n");
+        let _ = writeln!(
+            out,
+            "  pluginTable ??= exports().{} ?? new WebAssembly.Table({{ element: \"anyfunc\", initial: 0 }});",
+            TABLE_IMPORT
+        );
+        out.push_str("  const tableAlign = 2 ** info.tableAlign;\n");
+        out.push_str("  const tableBase = Math.ceil(pluginTable.length / tableAlign) * tableAlign;\n");
+        out.push_str("  pluginTable.grow(tableBase - pluginTable.length + info.tableSize);\n");
+        out.push_str("  const base = buildImports(currentUserImports);\n");
+        out.push_str("  const imports = {};\n");
+        out.push_str("  const got = [];\n");
+        out.push_str("  for (const { module: from, name: field } of WebAssembly.Module.imports(module)) {\n");
+        out.push_str("    const members = (imports[from] ??= {});\n");
+        let _ = writeln!(
+            out,
+            "    if (from === {} || from === {}) {{",
+            js_string(GOT_MEM_MODULE),
+            js_string(GOT_FUNC_MODULE)
+        );
+        out.push_str("      members[field] = new WebAssembly.Global({ value: \"i32\", mutable: true }, 0);\n");
+        out.push_str("      got.push([from, field, members[field]]);\n");
+        let _ = writeln!(out, "    }} else if (from !== {}) {{", js_string(MEMORY_IMPORT_MODULE));
+        out.push_str("      members[field] = base[from]?.[field];\n");
+        let _ = writeln!(out, "    }} else if (field === {}) {{", js_string(MEMORY_IMPORT));
+        out.push_str("      members[field] = exports().memory;\n");
+        let _ = writeln!(out, "    }} else if (field === {}) {{", js_string(TABLE_IMPORT));
+        out.push_str("      members[field] = pluginTable;\n");
+        let _ = writeln!(out, "    }} else if (field === {}) {{", js_string(MEMORY_BASE_IMPORT));
+        out.push_str("      members[field] = new WebAssembly.Global({ value: \"i32\" }, memoryBase);\n");
+        let _ = writeln!(out, "    }} else if (field === {}) {{", js_string(TABLE_BASE_IMPORT));
+        out.push_str("      members[field] = new WebAssembly.Global({ value: \"i32\" }, tableBase);\n");
+        // Each plugin gets a stack of its own
+        let _ = writeln!(out, "    }} else if (field === {}) {{", js_string(STACK_POINTER_GLOBAL));
+        let _ = writeln!(
+            out,
+            "      const stack = reservePluginMemory({0}, 16) + {0};",
+            DEFAULT_STACK_SIZE
+        );
+        out.push_str("      members[field] = new WebAssembly.Global({ value: \"i32\", mutable: true }, stack);\n");
+        out.push_str("    } else {\n");
+        out.push_str("      members[field] = pluginImports[field] ?? pluginExport(field) ?? base[from]?.[field];\n");
+        out.push_str("      if (members[field] === undefined) {\n");
+        out.push_str("        throw new Error(`plugin import ${from}.${field} is not defined`);\n");
+        out.push_str("      }\n");
+        out.push_str("    }\n");
+        out.push_str("  }\n");
+        out.push_str("  const own = new WebAssembly.Instance(module, imports).exports;\n");
+        out.push_str("  for (const [from, field, global] of got) {\n");
+        out.push_str("    const value = own[field] ?? pluginExport(field);\n");
+        let _ = writeln!(out, "    if (from === {}) {{", js_string(GOT_MEM_MODULE));
+        out.push_str("      if (!(value instanceof WebAssembly.Global)) throw new Error(`${field} is not defined`);\n");
+        // Addresses the plugin exports are relative to where its memory went
+        out.push_str("      global.value = (field in own ? memoryBase : 0) + value.value;\n");
+        out.push_str("    } else {\n");
+        out.push_str("      if (typeof value !== \"function\") throw new Error(`${field} is not defined`);\n");
+        out.push_str("      global.value = pluginTable.grow(1, value);\n");
+        out.push_str("    }\n");
+        out.push_str("  }\n");
+        let _ = writeln!(
+            out,
+            "  for (const relocate of [{}, {}]) own[relocate]?.();",
+            js_string(APPLY_RELOCS_EXPORT),
+            js_string(APPLY_DATA_RELOCS_EXPORT)
+        );
+        let _ = writeln!(out, "  own.{}?.();", CALL_CTORS_EXPORT);
+        out.push_str("  plugins.push({ name, exports: own });\n");
+        out.push_str("  return own;\n");
+        out.push_str("}\n\n");
+
+        let _ = writeln!(out, "{}function loadPluginSync(bytes, pluginImports, name) {{", export);
+        out.push_str("  const module = bytes instanceof WebAssembly.Module ? bytes : new WebAssembly.Module(bytes);\n");
+        out.push_str("  return linkPlugin(module, pluginImports, name);\n");
+        out.push_str("}\n\n");
+
+        if self.format == GlueFormat::EsModule {
+            // Plugins fetched by URL are named after their file, as `dylink.0` names the ones needed
+            out.push_str("export async function loadPlugin(source, pluginImports) {\n");
+            out.push_str("  const url = typeof source === \"string\" || source instanceof URL;\n");
+            out.push_str("  const name = url ? String(source).split(\"/\").pop() : undefined;\n");
+            out.push_str("  if (url) source = fetch(source);\n");
+            out.push_str("  source = await source;\n");
+            out.push_str("  const bytes = source instanceof Response ? await source.arrayBuffer() : source;\n");
+            out.push_str("  return linkPlugin(await WebAssembly.compile(bytes), pluginImports, name);\n");
+            out.push_str("}\n\n");
+        }
+    }
+
+    /// `hotSwap`, replacing the instance the wrappers call into
+    ///
+    /// Memory is copied after instantiation, so a start function's writes
+    /// are overwritten like the constructors' would have been. A split
+    /// module's secondary is linked to the old instance, so it is dropped
+    /// and must be loaded again.
+    fn generate_hot_swap(&self, out: &mut String, split: bool) {
+        out.push_str("/**\n");
+        out.push_str(" * Replaces the instance with one of a rebuilt module\n");
+        out.push_str(" * @param {Response | Promise<Response> | BufferSource} source\n");
+        out.push_str(" * @param {boolean} preserveMemory Keep memory and exported globals rather than initializing\n");
+        out.push_str(" */\n");
+        out.push_str("export async function hotSwap(source, preserveMemory) {\n");
+        out.push_str("  const previous = exports();\n");
+        if split {
+            out.push_str("  secondary = secondaryLoading = undefined;\n");
+        }
+        out.push_str("  source = await source;\n");
+        out.push_str("  const bytes = source instanceof Response ? await source.arrayBuffer() : source;\n");
+        out.push_str("  const imports = buildImports(currentUserImports);\n");
+        out.push_str("  const { instance } = await WebAssembly.instantiate(bytes, imports);\n");
+        out.push_str("  const next = instance.exports;\n");
+        out.push_str("  if (!preserveMemory || !previous.memory || !next.memory) return finishInit(instance);\n");
+        out.push_str("  const grow = previous.memory.buffer.byteLength - next.memory.buffer.byteLength;\n");
+        out.push_str("  if (grow > 0) next.memory.grow(grow / 65536);\n");
+        out.push_str("  new Uint8Array(next.memory.buffer).set(new Uint8Array(previous.memory.buffer));\n");
+        out.push_str("  for (const [name, value] of Object.entries(previous)) {\n");
+        out.push_str("    if (!(value instanceof WebAssembly.Global)) continue;\n");
+        // Removed or immutable globals throw, and new ones keep their value
+        out.push_str("    try { next[name].value = value.value; } catch {}\n");
+        out.push_str("  }\n");
+        out.push_str("  wasm = next;\n");
+        out.push_str("  return wasm;\n");
+        out.push_str("}\n\n");
+    }
+
+    /// Statement setting up threads once the module is compiled
+    ///
+    /// With a fallback or variants, only modules importing the shared
+    /// memory get threads, so the glue runs any of them.
+    fn prepare_threads(&self) -> &'static str {
+        if self.has_alternatives() {
+            "  if (importsMemory(module)) prepareThreads(module, bytes, userImports);\n"
+        } else {
+            "  prepareThreads(module, bytes, userImports);\n"
+        }
+    }
+
+    fn generate_wrappers(&self, out: &mut String, module: &WasmModule) -> Result<(), BackendError> {
+        let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+        let handles = module.uses_externref_table();
+
+        for (name, index) in exported_functions(module) {
+            let function = module.functions.get(index as usize).ok_or_else(|| {
+                BackendError::CompilationFailed(format!("export {} of unknown function {}", name, index))
+            })?;
+            if let Some(interop) = module.interop_signature(index).filter(|sig| sig.needs_marshaling()) {
+                self.generate_marshaling_wrapper(out, name, &function.signature, interop, handles);
+                continue;
+            }
+            let params: Vec<String> = (0..function.signature.params.len())
+                .map(|i| format!("arg{}", i))
+                .collect();
+
+            out.push_str("/**\n");
+            for (param, ty) in params.iter().zip(&function.signature.params) {
+                let _ = writeln!(out, " * @param {{{}}} {}", jsdoc_type(ty), param);
+            }
+            let returns = function.signature.returns.as_ref().map_or("void", jsdoc_type);
+            let _ = writeln!(out, " * @returns {{{}}}", returns);
+            out.push_str(" */\n");
+
+            let _ = writeln!(out, "{}function {}({}) {{", export, js_identifier(name), params.join(", "));
+            let args: Vec<String> = params.iter()
+                .zip(&function.signature.params)
+                .map(|(param, ty)| pass_value(param, ty, handles))
+                .collect();
+            let call = match (&function.signature.returns, module.promise_lowering) {
+                (Some(Type::Promise(_)), PromiseLowering::Callback) => {
+                    format!("takePromise(exports()[{}]({}))", js_string(name), args.join(", "))
+                }
+                (Some(Type::Promise(_)), PromiseLowering::Jspi) => {
+                    format!("promising({})({})", js_string(name), args.join(", "))
+                }
+                // Ownership of a returned handle moves back to JavaScript
+                (Some(Type::ExternRef(_)), _) if handles => {
+                    format!("takeExternRef(exports()[{}]({}))", js_string(name), args.join(", "))
+                }
+                _ => format!("exports()[{}]({})", js_string(name), args.join(", ")),
+            };
+            let _ = writeln!(out, "  return {};", call);
+            out.push_str("}\n\n");
+        }
+        Ok(())
+    }
+
+    /// Wrapper that marshals strings, slices, and vectors through memory
+    fn generate_marshaling_wrapper(
+        &self,
+        out: &mut String,
+        name: &str,
+        signature: &Signature,
+        interop: &InteropSignature,
+        handles: bool,
+    ) {
+        let export = if self.format == GlueFormat::EsModule { "export " } else { "" };
+        let params: Vec<String> = (0..interop.params.len()).map(|i| format!("arg{}", i)).collect();
+
+        // Source-level types, skipping the return area and lengths
+        let mut lowered = signature.params.iter().skip(usize::from(interop.has_return_area()));
+        out.push_str("/**\n");
+        for (param, kind) in params.iter().zip(&interop.params) {
+            let ty = lowered.next().map_or("any", jsdoc_type);
+            let ty = match kind {
+                InteropType::Value | InteropType::ExternRef => ty,
+                InteropType::Str => "string",
+                InteropType::Slice(element) | InteropType::SliceMut(element) | InteropType::Vec(element) => {
+                    element.typed_array()
+                }
+            };
+            if kind.is_memory_pair() {
+                lowered.next();
+            }
+            let _ = writeln!(out, " * @param {{{}}} {}", ty, param);
+        }
+        let returns = match interop.returns {
+            InteropType::Str => "string",
+            InteropType::Vec(element) => element.typed_array(),
+            _ => signature.returns.as_ref().map_or("void", jsdoc_type),
+        };
+        let _ = writeln!(out, " * @returns {{{}}}", returns);
+        out.push_str(" */\n");
+
+        let _ = writeln!(out, "{}function {}({}) {{", export, js_identifier(name), params.join(", "));
+        out.push_str("  const allocations = [];\n");
+        out.push_str("  try {\n");
+        let mut args = Vec::new();
+        if interop.has_return_area() {
+            out.push_str("    const retptr = allocate(8, 4, allocations);\n");
+            args.push("retptr".to_string());
+        }
+        let mut copy_backs = Vec::new();
+        let mut lowered = signature.params.iter().skip(usize::from(interop.has_return_area()));
+        for (i, (param, kind)) in params.iter().zip(&interop.params).enumerate() {
+            let ty = lowered.next();
+            if kind.is_memory_pair() {
+                lowered.next();
+            }
+            let pass = match kind {
+                InteropType::Value => {
+                    args.push(ty.map_or_else(|| param.clone(), |ty| pass_value(param, ty, handles)));
+                    continue;
+                }
+                InteropType::ExternRef => {
+                    args.push(pass_value(param, &Type::ExternRef(String::new()), handles));
+                    continue;
+                }
+                InteropType::Str => format!("passString({}, allocations)", param),
+                InteropType::Slice(element) => format!("passArray({}, {}, allocations)", param, element.typed_array()),
+                InteropType::SliceMut(element) => {
+                    copy_backs.push(format!("copyBack({0}, ptr{1}, len{1}, {2})", param, i, element.typed_array()));
+                    format!("passArray({}, {}, allocations)", param, element.typed_array())
+                }
+                // Ownership moves to the callee, so the buffer is not released
+                InteropType::Vec(element) => format!("passArray({}, {}, null)", param, element.typed_array()),
+            };
+            let _ = writeln!(out, "    const [ptr{0}, len{0}] = {1};", i, pass);
+            args.push(format!("ptr{}", i));
+            args.push(format!("len{}", i));
+        }
+
+        let mut call = format!("exports()[{}]({})", js_string(name), args.join(", "));
+        if interop.returns == InteropType::ExternRef && handles {
+            call = format!("takeExternRef({})", call);
+        }
+        if interop.has_return_area() || copy_backs.is_empty() {
+            let prefix = if interop.has_return_area() { "" } else { "return " };
+            let _ = writeln!(out, "    {}{};", prefix, call);
+        } else {
+            let _ = writeln!(out, "    const result = {};", call);
+        }
+        for copy_back in &copy_backs {
+            let _ = writeln!(out, "    {};", copy_back);
+        }
+        match interop.returns {
+            InteropType::Str => out.push_str("    return takeString(retptr);\n"),
+            InteropType::Vec(element) => {
+                let _ = writeln!(out, "    return takeArray(retptr, {});", element.typed_array());
+            }
+            _ if !copy_backs.is_empty() => out.push_str("    return result;\n"),
+            _ => {}
+        }
+        out.push_str("  } finally {\n");
+        out.push_str("    release(allocations);\n");
+        out.push_str("  }\n");
+        out.push_str("}\n\n");
+    }
+
+    fn generate_commonjs_footer(&self, out: &mut String, module: &WasmModule) {
+        let wasm_file = if self.variants.is_empty() { js_string(&self.wasm_file) } else { "pickVariant()".to_string() };
+        let _ = writeln!(
+            out,
+            "initSync(require(\"fs\").readFileSync(require(\"path\").join(__dirname, {})));\n",
+            wasm_file
+        );
+        let mut names = vec!["initSync".to_string()];
+        if is_split(module) {
+            names.push("loadSecondarySync".to_string());
+        }
+        if self.plugins {
+            names.push("loadPluginSync".to_string());
+        }
+        if module.uses_closures() {
+            names.push("closure".to_string());
+        }
+        if self.debug && module.uses_externref_table() {
+            names.push("externrefLeaks".to_string());
+        }
+        if self.emscripten.is_some() {
+            names.push("callMain".to_string());
+        }
+        names.extend(exported_functions(module).map(|(name, _)| js_identifier(name)));
+        let _ = writeln!(out, "module.exports = {{ {} }};", names.join(", "));
+    }
+}
+
+/// Smallest module validating only where `proposal` is supported
+///
+/// Each has one function using an instruction or type the proposal adds.
+pub(crate) fn feature_probe(proposal: Proposal) -> Vec<u8> {
+    const HEADER: [u8; 8] = [0, 97, 115, 109, 1, 0, 0, 0];
+    // Type and function sections of one `() -> ()` function
+    const VOID: [u8; 10] = [1, 4, 1, 96, 0, 0, 3, 2, 1, 0];
+    let (types, rest): (&[u8], &[u8]) = match proposal {
+        // ref.null func; drop
+        Proposal::ReferenceTypes => (&VOID, &[10, 7, 1, 5, 0, 208, 112, 26, 11]),
+        // A function returning two i32s
+        Proposal::MultiValue => {
+            (&[1, 6, 1, 96, 0, 2, 127, 127, 3, 2, 1, 0], &[10, 8, 1, 6, 0, 65, 0, 65, 0, 11])
+        }
+        // i32.atomic.load of a shared memory
+        Proposal::Threads => (&VOID, &[5, 4, 1, 3, 1, 1, 10, 11, 1, 9, 0, 65, 0, 254, 16, 2, 0, 26, 11]),
+        // throw of an empty tag
+        Proposal::ExceptionHandling => (&VOID, &[13, 3, 1, 0, 0, 10, 6, 1, 4, 0, 8, 0, 11]),
+        // i32.extend8_s
+        Proposal::SignExtension => (&VOID, &[10, 8, 1, 6, 0, 65, 0, 192, 26, 11]),
+        // i32.trunc_sat_f32_s
+        Proposal::SaturatingFloatToInt => (&VOID, &[10, 12, 1, 10, 0, 67, 0, 0, 0, 0, 252, 0, 26, 11]),
+        // memory.copy
+        Proposal::BulkMemory => (&VOID, &[5, 3, 1, 0, 1, 10, 14, 1, 12, 0, 65, 0, 65, 0, 65, 0, 252, 10, 0, 0, 11]),
+        // i8x16.splat returning a v128
+        Proposal::Simd => (&[1, 5, 1, 96, 0, 1, 123, 3, 2, 1, 0], &[10, 8, 1, 6, 0, 65, 0, 253, 15, 11]),
+    };
+    [&HEADER[..], types, rest].concat()
+}
+
+/// Function exports that get a typed wrapper
+pub(crate) fn exported_functions(module: &WasmModule) -> impl Iterator<Item = (&str, u32)> {
+    module.exports.iter().filter_map(|export| match export.kind {
+        ExportKind::Function(index) if !export.name.starts_with("__") => Some((export.name.as_str(), index)),
+        _ => None,
+    })
+}
+
+/// Checks whether the module was split, calling cold functions in a secondary module
+fn is_split(module: &WasmModule) -> bool {
+    module.imports.iter().any(|import| import.module == SECONDARY_IMPORT_MODULE)
+}
+
+/// Checks whether any exported function or import marshals values through memory
+fn needs_marshaling(module: &WasmModule) -> bool {
+    exported_functions(module)
+        .any(|(_, index)| module.interop_signature(index).is_some_and(InteropSignature::needs_marshaling))
+        || module.import_interop_signatures.values().any(InteropSignature::needs_marshaling)
+}
+
+/// JavaScript import calling `host` with values read from memory
+///
+/// With `handles`, objects are passed as externref table handles as in
+/// `import_body`.
+fn import_stub(interop: &InteropSignature, handles: bool) -> Result<String, BackendError> {
+    let mut params = Vec::new();
+    if interop.has_return_area() {
+        params.push("retptr".to_string());
+    }
+    let mut values = Vec::new();
+    for param in &interop.params {
+        let ptr = format!("arg{}", params.len());
+        match param {
+            InteropType::Value => values.push(ptr.clone()),
+            InteropType::ExternRef => values.push(borrow_value(&ptr, &Type::ExternRef(String::new()), handles)),
+            InteropType::Str => values.push(format!("readString({}, arg{})", ptr, params.len() + 1)),
+            InteropType::Slice(element) | InteropType::SliceMut(element) => values.push(format!(
+                "new {}(exports().memory.buffer, {}, arg{})",
+                element.typed_array(),
+                ptr,
+                params.len() + 1
+            )),
+            InteropType::Vec(_) => return Err(BackendError::Unsupported("vectors moved into imports".to_string())),
+        }
+        params.push(ptr);
+        if param.is_memory_pair() {
+            params.push(format!("arg{}", params.len()));
+        }
+    }
+
+    let call = format!("host({})", values.join(", "));
+    let body = match interop.returns {
+        InteropType::Value => call,
+        InteropType::ExternRef if handles => format!("rootExternRef({})", call),
+        InteropType::ExternRef => call,
+        InteropType::Str => format!("returnString(retptr, {})", call),
+        InteropType::Vec(element) => format!("returnArray(retptr, {}, {})", call, element.typed_array()),
+        InteropType::Slice(_) | InteropType::SliceMut(_) => {
+            return Err(BackendError::Unsupported("borrowed results of imports".to_string()));
+        }
+    };
+    Ok(format!("({}) => {}", params.join(", "), body))
+}
+
+/// Checks whether the module spawns threads on Workers
+fn uses_threads(module: &WasmModule) -> bool {
+    module.shared_memory || module.exports.iter().any(|export| export.name == THREAD_MAIN_EXPORT)
+}
+
+/// Checks whether the glue needs promise helpers
+fn uses_promises(module: &WasmModule) -> bool {
+    module.uses_promises()
+        || module.imports.iter().any(|import| import.module == JS_IMPORT_MODULE && import.name == PROMISE_NEW_IMPORT)
+}
+
+/// Checks whether the module runs tasks on `wasm::threading::async_rt`
+fn uses_tasks(module: &WasmModule) -> bool {
+    [SCHEDULE_RUN_IMPORT, SET_TIMEOUT_IMPORT].iter().any(|name| module.find_import(JS_IMPORT_MODULE, name).is_some())
+}
+
+/// JavaScript implementation of a `wasmrust` import
+///
+/// `js_call:`, `js_get:`, and `js_set:` imports act on their first
+/// argument; `js_global:` and `js_new:` imports call or construct what
+/// their dotted path names on `globalThis`. With `handles`, externref receivers and arguments are table handles
+/// borrowed for the call, and returned objects are rooted for Rust to own.
+fn import_body(import: &Import, lowering: PromiseLowering, handles: bool) -> Result<String, BackendError> {
+    if let Some((_, body)) = RUNTIME_IMPORTS.iter().find(|(name, _)| *name == import.name) {
+        return Ok((*body).to_string());
+    }
+
+    let signature = import.signature();
+    let root = |value: String| match &signature.returns {
+        Some(Type::ExternRef(_)) if handles => format!("rootExternRef({})", value),
+        _ => value,
+    };
+
+    let global = import.name.strip_prefix("js_global:").map(|path| (path, ""));
+    if let Some((path, new)) = global.or_else(|| import.name.strip_prefix("js_new:").map(|class| (class, "new "))) {
+        let params: Vec<String> = (0..signature.params.len()).map(|i| format!("arg{}", i)).collect();
+        let values: Vec<String> = params.iter()
+            .zip(&signature.params)
+            .map(|(arg, ty)| borrow_value(arg, ty, handles))
+            .collect();
+        let callee = path.split('.')
+            .fold("globalThis".to_string(), |object, name| format!("{}[{}]", object, js_string(name)));
+        return Ok(format!("({}) => {}", params.join(", "), root(format!("{}{}({})", new, callee, values.join(", ")))));
+    }
+
+    let args: Vec<String> = (1..signature.params.len())
+        .map(|i| format!("arg{}", i))
+        .collect();
+    let params = std::iter::once("target".to_string())
+        .chain(args.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let target = match signature.params.first() {
+        Some(ty) => borrow_value("target", ty, handles),
+        None => "target".to_string(),
+    };
+    let values: Vec<String> = args.iter()
+        .zip(signature.params.iter().skip(1))
+        .map(|(arg, ty)| borrow_value(arg, ty, handles))
+        .collect();
+
+    if let Some(method) = import.name.strip_prefix("js_call:") {
+        let call = format!("{}[{}]({})", target, js_string(method), values.join(", "));
+        Ok(match (&signature.returns, lowering) {
+            (Some(Type::Promise(_)), PromiseLowering::Callback) => {
+                format!("({}) => registerPromise({})", params, call)
+            }
+            // The wasm stack suspends until the promise settles
+            (Some(Type::Promise(_)), PromiseLowering::Jspi) => {
+                format!("new WebAssembly.Suspending(({}) => {})", params, call)
+            }
+            _ => format!("({}) => {}", params, root(call)),
+        })
+    } else if let Some(field) = import.name.strip_prefix("js_get:") {
+        Ok(format!("({}) => {}", params, root(format!("{}[{}]", target, js_string(field)))))
+    } else if let Some(field) = import.name.strip_prefix("js_set:") {
+        let value = values.first().map_or("undefined", String::as_str);
+        Ok(format!("({}) => {{ {}[{}] = {}; }}", params, target, js_string(field), value))
+    } else {
+        Err(BackendError::Unsupported(format!(
+            "no JavaScript implementation for import {}::{}",
+            import.module, import.name
+        )))
+    }
+}
+
+/// Reads an import argument, resolving borrowed externref handles
+fn borrow_value(name: &str, ty: &Type, handles: bool) -> String {
+    match ty {
+        Type::ExternRef(_) if handles => format!("getExternRef({})", name),
+        _ => name.to_string(),
+    }
+}
+
+/// Passes an export argument, rooting objects whose ownership moves to Rust
+fn pass_value(name: &str, ty: &Type, handles: bool) -> String {
+    match ty {
+        Type::ExternRef(_) if handles => format!("rootExternRef({})", name),
+        _ => name.to_string(),
+    }
+}
+
+/// JSDoc type for a WasmIR type
+fn jsdoc_type(ty: &Type) -> &'static str {
+    match ty {
+        Type::I32 | Type::F32 | Type::F64 | Type::Pointer(_) => "number",
+        Type::I64 | Type::I128 => "bigint",
+        Type::FuncRef => "Function",
+        Type::Linear { inner_type } | Type::Capability { inner_type, .. } => jsdoc_type(inner_type),
+        Type::Void => "void",
+        Type::Promise(_) => "Promise",
+        _ => "any",
+    }
+}
+
+/// Quotes a string as a JavaScript string literal
+pub(crate) fn js_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c if (c as u32) < 0x20 || c == '\u{2028}' || c == '\u{2029}' => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Turns an export name into a valid JavaScript identifier
+pub(crate) fn js_identifier(name: &str) -> String {
+    let mut ident: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '$' { c } else { '_' })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) || is_reserved(&ident) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+fn is_reserved(ident: &str) -> bool {
+    matches!(
+        ident,
+        "break" | "case" | "catch" | "class" | "const" | "continue" | "debugger" | "default"
+            | "delete" | "do" | "else" | "export" | "extends" | "finally" | "for" | "function"
+            | "if" | "import" | "in" | "instanceof" | "new" | "return" | "super" | "switch"
+            | "this" | "throw" | "try" | "typeof" | "var" | "void" | "while" | "with" | "yield"
+            | "let" | "static" | "enum" | "await" | "init" | "initSync" | "exports"
+            | "closure" | "externrefLeaks" | "hotSwap" | "callMain"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{Constant, ElementType, Instruction, MemoryType, Operand, Signature, Terminator, WasmIR};
+
+    fn interop_module() -> WasmModule {
+        let mut function = WasmIR::new("describe".to_string(), Signature {
+            params: vec![Type::ExternRef("Element".to_string()), Type::I32],
+            returns: Some(Type::I32),
+        });
+        function.add_basic_block(vec![
+            Instruction::JSMethodCall {
+                object: Operand::Local(0),
+                method: "setAttribute".to_string(),
+                args: vec![Operand::Local(1)],
+                return_type: None,
+            },
+            Instruction::ExternRefLoad {
+                externref: Operand::Local(0),
+                field: "childCount".to_string(),
+                field_type: Type::I32,
+            },
+        ], Terminator::Return { value: Some(Operand::StackValue(0)) });
+
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("describe", index);
+        module.declare_js_imports().unwrap();
+        module
+    }
+
+    #[test]
+    fn test_es_module_glue() {
+        let glue = JsGlueGenerator::new("app.wasm").generate(&interop_module()).unwrap();
+
+        assert!(glue.contains("\"js_call:setAttribute\": (target, arg1) => target[\"setAttribute\"](arg1)"));
+        assert!(glue.contains("\"js_get:childCount\": (target) => target[\"childCount\"]"));
+        assert!(glue.contains("export async function init(source = new URL(\"app.wasm\", import.meta.url)"));
+        assert!(glue.contains(" * @param {any} arg0\n * @param {number} arg1\n * @returns {number}"));
+        assert!(glue.contains("export function describe(arg0, arg1) {"));
+        assert!(glue.contains(CALL_CTORS_EXPORT));
+    }
+
+    #[test]
+    fn test_hot_reload_glue_swaps_instances() {
+        let glue = JsGlueGenerator::new("app.wasm").generate(&interop_module()).unwrap();
+        assert!(!glue.contains("hotSwap"));
+
+        let glue = JsGlueGenerator::new("app.wasm").hot_reload(true).generate(&interop_module()).unwrap();
+        assert!(glue.contains("export async function hotSwap(source, preserveMemory) {"));
+        assert!(glue.contains("const imports = buildImports(currentUserImports);"));
+        assert!(glue.contains("if (!preserveMemory || !previous.memory || !next.memory) return finishInit(instance);"));
+        assert_eq!(js_identifier("hotSwap"), "_hotSwap");
+
+        let glue = JsGlueGenerator::new("app.wasm")
+            .format(GlueFormat::CommonJs)
+            .hot_reload(true)
+            .generate(&interop_module())
+            .unwrap();
+        assert!(!glue.contains("hotSwap"));
+    }
+
+    #[test]
+    fn test_commonjs_glue() {
+        let glue = JsGlueGenerator::new("app.wasm")
+            .format(GlueFormat::CommonJs)
+            .generate(&interop_module())
+            .unwrap();
+
+        assert!(!glue.contains("export "));
+        assert!(glue.contains("module.exports = { initSync, describe };"));
+        assert_eq!(GlueFormat::CommonJs.extension(), "js");
+    }
+
+    #[test]
+    fn test_string_wrapper_marshals_utf8() {
+        let mut module = WasmModule::new();
+        let greet = module.add_function(WasmIR::new("greet".to_string(), Signature {
+            params: vec![Type::I32, Type::I32, Type::I32, Type::F64],
+            returns: None,
+        }));
+        module.export_function("greet", greet);
+        module.set_interop_signature(greet, InteropSignature {
+            params: vec![InteropType::Str, InteropType::Value],
+            returns: InteropType::Str,
+        });
+
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains("function passString(value, allocations) {"));
+        assert!(glue.contains(" * @param {string} arg0\n * @param {number} arg1\n * @returns {string}"));
+        assert!(glue.contains("    const [ptr0, len0] = passString(arg0, allocations);\n"));
+        assert!(glue.contains("    exports()[\"greet\"](retptr, ptr0, len0, arg1);\n    return takeString(retptr);"));
+
+        // Modules without string exports get no encoder
+        assert!(!JsGlueGenerator::new("app.wasm").generate(&interop_module()).unwrap().contains("TextEncoder"));
+    }
+
+    #[test]
+    fn test_slice_wrapper_copies_back_and_moves_vectors() {
+        let mut module = WasmModule::new();
+        let fill = module.add_function(WasmIR::new("fill".to_string(), Signature {
+            params: vec![Type::I32; 5],
+            returns: Some(Type::I32),
+        }));
+        module.export_function("fill", fill);
+        module.set_interop_signature(fill, InteropSignature {
+            params: vec![InteropType::SliceMut(ElementType::F32), InteropType::Vec(ElementType::U8)],
+            returns: InteropType::Value,
+        });
+
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains(" * @param {Float32Array} arg0\n * @param {Uint8Array} arg1\n * @returns {number}"));
+        assert!(glue.contains("    const [ptr0, len0] = passArray(arg0, Float32Array, allocations);\n"));
+        assert!(glue.contains("    const [ptr1, len1] = passArray(arg1, Uint8Array, null);\n"));
+        assert!(glue.contains(
+            "    const result = exports()[\"fill\"](ptr0, len0, ptr1, len1);\n    copyBack(arg0, ptr0, len0, Float32Array);\n    return result;"
+        ));
+    }
+
+    #[test]
+    fn test_import_stubs_read_arguments_from_memory() {
+        let mut module = interop_module();
+        let log = module.add_import("console", "log", Signature { params: vec![Type::I32; 3], returns: None });
+        module.set_import_interop_signature(log, InteropSignature {
+            params: vec![InteropType::Str, InteropType::Value],
+            returns: InteropType::Value,
+        });
+        let pixels = module.add_import("canvas", "pixels", Signature { params: vec![Type::I32; 3], returns: None });
+        module.set_import_interop_signature(pixels, InteropSignature {
+            params: vec![InteropType::SliceMut(ElementType::U32)],
+            returns: InteropType::Vec(ElementType::F64),
+        });
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+
+        assert!(glue.contains("\n    \"log\": (host) => (arg0, arg1, arg2) => host(readString(arg0, arg1), arg2),\n"));
+        assert!(glue.contains(
+            "(host) => (retptr, arg1, arg2) => returnArray(retptr, host(new Uint32Array(exports().memory.buffer, arg1, arg2)), Float64Array),"
+        ));
+        assert!(glue.contains("members[name] = stub(members[name] || missingImport(module, name));"));
+        assert!(glue.contains("function returnString(retptr, value) {"));
+
+        module.set_import_interop_signature(log, InteropSignature {
+            params: vec![InteropType::Vec(ElementType::U8)],
+            returns: InteropType::Value,
+        });
+        assert!(matches!(JsGlueGenerator::new("app.wasm").generate(&module), Err(BackendError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_closure_imports_invoke_through_function_table() {
+        let mut module = WasmModule::new();
+        module.add_import(JS_IMPORT_MODULE, CLOSURE_NEW_IMPORT, Signature {
+            params: vec![Type::I32; 3],
+            returns: Some(Type::I32),
+        });
+        module.add_import(JS_IMPORT_MODULE, "__wasmrust_closure_release", Signature {
+            params: vec![Type::I32],
+            returns: None,
+        });
+
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains("\"__wasmrust_closure_new\": (invoke, data, drop) => newClosure(invoke, data, drop),"));
+        assert!(glue.contains("return exports().__indirect_function_table.get(invoke)(data, ...args);"));
+        assert!(glue.contains("exports().__indirect_function_table.get(entry.drop)(entry.data);"));
+        assert!(glue.contains("export function closure(handle) {"));
+
+        let glue = JsGlueGenerator::new("app.wasm").format(GlueFormat::CommonJs).generate(&module).unwrap();
+        assert!(glue.contains("module.exports = { initSync, closure };"));
+
+        // Modules without closures get no closure table
+        assert!(!JsGlueGenerator::new("app.wasm").generate(&interop_module()).unwrap().contains("closures"));
+    }
+
+    #[test]
+    fn test_externref_table_roots_and_releases_handles() {
+        let node = Type::ExternRef("Node".to_string());
+        let mut module = WasmModule::new();
+        module.add_import(JS_IMPORT_MODULE, EXTERNREF_CLONE_IMPORT, Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        module.add_import(JS_IMPORT_MODULE, EXTERNREF_RELEASE_IMPORT, Signature {
+            params: vec![Type::I32],
+            returns: None,
+        });
+        module.add_import(JS_IMPORT_MODULE, Import::js_method_name("appendChild"), Signature {
+            params: vec![node.clone(), node.clone()],
+            returns: Some(node.clone()),
+        });
+        let index = module.add_function(WasmIR::new("adopt".to_string(), Signature {
+            params: vec![node.clone()],
+            returns: Some(node),
+        }));
+        module.export_function("adopt", index);
+
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains("\"__wasmrust_externref_release\": (handle) => unrootExternRef(handle),"));
+        assert!(glue.contains(
+            "(target, arg1) => rootExternRef(getExternRef(target)[\"appendChild\"](getExternRef(arg1)))"
+        ));
+        assert!(glue.contains("return takeExternRef(exports()[\"adopt\"](rootExternRef(arg0)));"));
+        assert!(!glue.contains("externrefLeaks"));
+
+        let glue = JsGlueGenerator::new("app.wasm").debug(true).format(GlueFormat::CommonJs).generate(&module).unwrap();
+        assert!(glue.contains("throw new Error(`externref ${handle} released twice or never rooted`);"));
+        assert!(glue.contains("module.exports = { initSync, externrefLeaks, adopt };"));
+    }
+
+    #[test]
+    fn test_interop_imports_of_globals_pass_objects_as_handles() {
+        let mut module = WasmModule::new();
+        let log = module.add_import(JS_IMPORT_MODULE, Import::js_global_name("console.log"), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: None,
+        });
+        let message = InteropSignature { params: vec![InteropType::Str], ..Default::default() };
+        module.set_import_interop_signature(log, message);
+        let map = module.add_import(JS_IMPORT_MODULE, Import::js_new_name("Map"), Signature {
+            params: vec![],
+            returns: Some(Type::I32),
+        });
+        module.set_import_interop_signature(map, InteropSignature { params: vec![], returns: InteropType::ExternRef });
+        let set = module.add_import(JS_IMPORT_MODULE, Import::js_method_name("set"), Signature {
+            params: vec![Type::I32; 4],
+            returns: None,
+        });
+        module.set_import_interop_signature(set, InteropSignature {
+            params: vec![InteropType::ExternRef, InteropType::Str, InteropType::ExternRef],
+            returns: InteropType::Value,
+        });
+        let index = module.add_function(WasmIR::new("size".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        }));
+        module.export_function("size", index);
+        module.set_interop_signature(index, InteropSignature {
+            params: vec![InteropType::ExternRef],
+            returns: InteropType::ExternRef,
+        });
+
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains(
+            "\"js_global:console.log\": ((host) => (arg0, arg1) => host(readString(arg0, arg1)))\
+             ((arg0) => globalThis[\"console\"][\"log\"](arg0)),"
+        ));
+        assert!(glue.contains(
+            "\"js_new:Map\": ((host) => () => rootExternRef(host()))(() => new globalThis[\"Map\"]()),"
+        ));
+        assert!(glue.contains(
+            "((host) => (arg0, arg1, arg2, arg3) => host(getExternRef(arg0), readString(arg1, arg2), \
+             getExternRef(arg3)))((target, arg1, arg2) => target[\"set\"](arg1, arg2)),"
+        ));
+        assert!(glue.contains("function unrootExternRef(handle) {"));
+    }
+
+    fn async_module(lowering: PromiseLowering) -> WasmModule {
+        let mut module = WasmModule::new();
+        module.promise_lowering = lowering;
+        module.add_import(JS_IMPORT_MODULE, Import::js_method_name("fetch"), Signature {
+            params: vec![Type::ExternRef("Window".to_string()), Type::I32],
+            returns: Some(Type::Promise(Box::new(Type::F64))),
+        });
+        let load = module.add_function(WasmIR::new("load".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::Promise(Box::new(Type::F64))),
+        }));
+        module.export_function("load", load);
+        module
+    }
+
+    #[test]
+    fn test_callback_promise_lowering() {
+        let glue = JsGlueGenerator::new("app.wasm").generate(&async_module(PromiseLowering::Callback)).unwrap();
+        assert!(glue.contains("\"js_call:fetch\": (target, arg1) => registerPromise(target[\"fetch\"](arg1)),"));
+        assert!(glue.contains("exports().__wasmrust_promise_settled(handle, fulfilled, value);"));
+        assert!(glue.contains(" * @returns {Promise}\n */\nexport function load(arg0) {\n  return takePromise(exports()[\"load\"](arg0));"));
+        assert!(!glue.contains("WebAssembly.promising"));
+    }
+
+    #[test]
+    fn test_jspi_promise_lowering() {
+        let glue = JsGlueGenerator::new("app.wasm").generate(&async_module(PromiseLowering::Jspi)).unwrap();
+        assert!(glue.contains(
+            "\"js_call:fetch\": new WebAssembly.Suspending((target, arg1) => target[\"fetch\"](arg1)),"
+        ));
+        assert!(glue.contains("  return promising(\"load\")(arg0);"));
+        assert!(!glue.contains("registerPromise"));
+    }
+
+    #[test]
+    fn test_unknown_runtime_import_is_rejected() {
+        let mut module = WasmModule::new();
+        module.add_import(JS_IMPORT_MODULE, "mystery", Signature { params: vec![], returns: None });
+        assert!(matches!(
+            JsGlueGenerator::new("app.wasm").generate(&module),
+            Err(BackendError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_panic_hook_reports_compiled_messages() {
+        let mut function = WasmIR::new("fail".to_string(), Signature { params: vec![], returns: None });
+        function.add_basic_block(vec![], Terminator::Panic {
+            message: Some(Operand::Constant(Constant::String("index out of bounds".to_string()))),
+        });
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("fail", index);
+        assert!(!JsGlueGenerator::new("app.wasm").generate(&module).unwrap().contains("panicMessages"));
+
+        module.declare_panic_hook();
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains("const panicMessages = [\"index out of bounds\"];"));
+        assert!(glue.contains(
+            "\"__wasmrust_panic\": (id) => { throw new Error(panicMessages[id] ?? \"wasm module panicked\"); },"
+        ));
+    }
+
+    #[test]
+    fn test_threads_start_workers_on_shared_memory() {
+        let mut main = WasmIR::new(THREAD_MAIN_EXPORT.to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: None,
+        });
+        main.add_basic_block(vec![], Terminator::Return { value: None });
+        let mut module = interop_module();
+        let index = module.add_function(main);
+        module.export_function(THREAD_MAIN_EXPORT, index);
+
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains("    \"wasi\": { \"thread-spawn\": spawnThread },\n    \"env\": { \"memory\": threadMemory },\n"));
+        assert!(glue.contains("  prepareThreads(module, bytes, userImports);\n  return finishInit(await WebAssembly.instantiate(module, buildImports(userImports)));"));
+        assert!(glue.contains("      wasm.wasi_thread_start(data.id, data.startArg);\n    } catch (error) {\n      failThread(data.startArg);"));
+        assert!(glue.contains("  const idle = idleWorkers.pop();\n  if (idle) {\n    idle.postMessage({ id, startArg });"));
+        assert!(!glue.contains("export function __wasmrust_thread_main"));
+
+        let commonjs = JsGlueGenerator::new("app.wasm").format(GlueFormat::CommonJs).generate(&module);
+        assert!(matches!(commonjs, Err(BackendError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_threads_fall_back_without_shared_memory() {
+        let mut module = interop_module();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: Some(16) });
+        module.shared_memory = true;
+        module.add_import(JS_IMPORT_MODULE, SCHEDULE_RUN_IMPORT, Signature { params: vec![], returns: None });
+
+        let glue = JsGlueGenerator::new("app.wasm").fallback("app.single.wasm").generate(&module).unwrap();
+        assert!(glue.contains("const sharedMemory = typeof SharedArrayBuffer === \"function\" && globalThis.crossOriginIsolated !== false;"));
+        assert!(glue.contains(
+            "export async function init(source = new URL(sharedMemory ? \"app.wasm\" : \"app.single.wasm\", import.meta.url), userImports) {"
+        ));
+        assert!(glue.contains("  if (importsMemory(module)) prepareThreads(module, bytes, userImports);\n  return finishInit(new WebAssembly.Instance("));
+        assert!(glue.contains("function spawnThread(startArg) {\n  if (!threadMemory) return -1;\n"));
+        assert!(glue.contains("  if (threadMemory) watchWakes();\n"));
+
+        // Modules without threads have a single variant
+        module.shared_memory = false;
+        let glue = JsGlueGenerator::new("app.wasm").fallback("app.single.wasm").generate(&module).unwrap();
+        assert!(!glue.contains("app.single.wasm"));
+    }
+
+    #[test]
+    fn test_variants_load_the_first_the_host_supports() {
+        let mut module = interop_module();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: Some(16) });
+        module.shared_memory = true;
+
+        let glue = JsGlueGenerator::new("app.wasm")
+            .variant("app.threads-simd.wasm", [Proposal::Threads, Proposal::Simd])
+            .variant("app.wasm", [])
+            .generate(&module)
+            .unwrap();
+        assert!(glue.contains("  \"threads\": [0, 97, 115, 109, 1, 0, 0, 0, 1, 4, 1, 96, 0, 0, 3, 2, 1, 0, 5, 4, "));
+        assert!(!glue.contains("  \"bulk-memory\": ["));
+        assert!(glue.contains("  { file: \"app.threads-simd.wasm\", features: [\"threads\", \"simd\"] },\n"));
+        assert!(glue.contains("  { file: \"app.wasm\", features: [] },\n];"));
+        assert!(glue.contains("export async function init(source = new URL(pickVariant(), import.meta.url), "));
+        assert!(glue.contains("  if (importsMemory(module)) prepareThreads(module, bytes, userImports);\n"));
+
+        module.shared_memory = false;
+        let glue = JsGlueGenerator::new("app.wasm")
+            .format(GlueFormat::CommonJs)
+            .variant("app.simd.wasm", [Proposal::Simd])
+            .variant("app.wasm", [])
+            .generate(&module)
+            .unwrap();
+        assert!(glue.contains("readFileSync(require(\"path\").join(__dirname, pickVariant())));"));
+    }
+
+    #[test]
+    fn test_split_modules_load_cold_functions_on_demand() {
+        let mut module = interop_module();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let signature = Signature { params: vec![Type::I32], returns: None };
+        module.add_import(SECONDARY_IMPORT_MODULE, "report", signature);
+
+        let glue = JsGlueGenerator::new("app.wasm").hot_reload(true).generate(&module).unwrap();
+        assert!(glue.contains("    \"wasmrust:secondary\": {\n      \"report\": (...args) => "));
+        assert!(glue.contains("secondaryFunction(\"report\")(...args),\n"));
+        assert!(glue.contains("  imports[\"wasmrust:primary\"] = exports();\n"));
+        let memory = "  imports[\"env\"] = Object.assign({}, imports[\"env\"], { \"memory\": exports().memory });";
+        assert!(glue.contains(memory));
+        assert!(glue.contains("export function loadSecondary(source = new URL(\"app.cold.wasm\", import.meta.url))"));
+        assert!(glue.contains("export function loadSecondarySync(bytes) {\n"));
+        assert!(glue.contains("await loadSecondary() first"));
+        assert!(glue.contains("  secondary = secondaryLoading = undefined;\n"));
+
+        let glue = JsGlueGenerator::new("app.wasm")
+            .format(GlueFormat::CommonJs)
+            .secondary("lazy.wasm")
+            .generate(&module)
+            .unwrap();
+        assert!(glue.contains("    const path = require(\"path\").join(__dirname, \"lazy.wasm\");\n"));
+        assert!(glue.contains("module.exports = { initSync, loadSecondarySync, "));
+        assert!(!glue.contains("loadSecondary("));
+
+        let error = JsGlueGenerator::new("app.wasm").fallback("app.mvp.wasm").generate(&module).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported: a split module cannot have a fallback or variants");
+    }
+
+    #[test]
+    fn test_plugins_link_side_modules_into_the_instance() {
+        let glue = JsGlueGenerator::new("app.wasm").plugins(true).generate(&interop_module()).unwrap();
+        assert!(glue.contains("  const [section] = WebAssembly.Module.customSections(module, \"dylink.0\");\n"));
+        assert!(glue.contains("    const ptr = main.__wasm_alloc(size, align) >>> 0;\n"));
+        assert!(glue.contains("{ \"__wasmrust_alloc\": \"__wasm_alloc\", \"__wasmrust_free\": \"__wasm_free\" }"));
+        assert!(glue.contains("    } else if (field === \"__memory_base\") {\n"));
+        assert!(glue.contains("      const stack = reservePluginMemory(65536, 16) + 65536;\n"));
+        assert!(glue.contains("      global.value = pluginTable.grow(1, value);\n"));
+        assert!(glue.contains("[\"__wasm_apply_relocs\", \"__wasm_apply_data_relocs\"]) own[relocate]?.();"));
+        assert!(glue.contains("  own.__wasm_call_ctors?.();\n  plugins.push({ name, exports: own });\n"));
+        assert!(glue.contains("export async function loadPlugin(source, pluginImports) {\n"));
+        assert!(glue.contains("export function loadPluginSync(bytes, pluginImports, name) {\n"));
+        assert!(!JsGlueGenerator::new("app.wasm").generate(&interop_module()).unwrap().contains("loadPlugin"));
+
+        let commonjs = JsGlueGenerator::new("app.wasm").format(GlueFormat::CommonJs).plugins(true);
+        let glue = commonjs.generate(&interop_module()).unwrap();
+        assert!(glue.contains("module.exports = { initSync, loadPluginSync, "));
+        assert!(!glue.contains("loadPlugin("));
+    }
+
+    #[test]
+    fn test_emscripten_glue_implements_syscalls_and_em_js() {
+        let mut module = WasmModule::new();
+        let void = Signature { params: vec![], returns: None };
+        for (from, name) in [
+            ("wasi_snapshot_preview1", "fd_write"),
+            ("env", "emscripten_resize_heap"),
+            ("env", "__syscall_openat"),
+            ("env", "draw"),
+            ("env", "host_log"),
+        ] {
+            module.add_import(from, name, void.clone());
+        }
+        let mut main = WasmIR::new("main".to_string(), void);
+        main.add_basic_block(vec![], Terminator::Return { value: None });
+        let main = module.add_function(main);
+        module.export_function("main", main);
+        emscripten::wrap_main(&mut module);
+
+        let draw = EmJsFunction::parse("draw", "(int x)<::>{ f(x); }").unwrap();
+        let glue = JsGlueGenerator::new("app.wasm").emscripten(vec![draw]).generate(&module).unwrap();
+        assert!(glue.contains("    \"wasi_snapshot_preview1\": {\n      \"fd_write\": fdWrite,\n    },\n"));
+        assert!(glue.contains("      \"emscripten_resize_heap\": resizeHeap,\n"));
+        assert!(glue.contains("      \"__syscall_openat\": () => -ENOSYS,\n"));
+        let draw = "      \"draw\": (...args) => { updateMemoryViews(); return emJs[\"draw\"](...args); },\n";
+        assert!(glue.contains(draw));
+        assert!(glue.contains("const emJs = {\n  \"draw\"(x) { f(x); },\n};\n"));
+        assert!(!glue.contains("\"host_log\""));
+        assert!(glue.contains("export function callMain(args = []) {\n"));
+        assert!(glue.contains("    return exports().main(argv ? strings.length : 0, argv) ?? 0;\n"));
+        assert!(glue.contains("    if (error instanceof ExitStatus) return error.status;\n"));
+
+        let glue = JsGlueGenerator::new("app.wasm").format(GlueFormat::CommonJs).emscripten(vec![]);
+        let glue = glue.generate(&module).unwrap();
+        assert!(glue.contains("module.exports = { initSync, callMain, main };"));
+        assert!(!glue.contains("emJs") && !glue.contains("HEAPU8"));
+    }
+
+    #[test]
+    fn test_feature_probes_need_their_proposal() {
+        use wasmparser::{Validator, WasmFeatures};
+
+        for proposal in Proposal::ALL {
+            let probe = feature_probe(proposal);
+            assert!(Validator::new_with_features(WasmFeatures::all()).validate_all(&probe).is_ok(), "{:?}", proposal);
+            // An MVP engine rejects them
+            let mvp = WasmFeatures::FLOATS;
+            assert!(Validator::new_with_features(mvp).validate_all(&probe).is_err(), "{:?}", proposal);
+        }
+    }
+
+    #[test]
+    fn test_async_runtime_imports_schedule_microtasks_and_timers() {
+        let mut module = interop_module();
+        module.add_import(JS_IMPORT_MODULE, SCHEDULE_RUN_IMPORT, Signature { params: vec![], returns: None });
+        module.add_import(JS_IMPORT_MODULE, SET_TIMEOUT_IMPORT, Signature { params: vec![Type::I32, Type::I32], returns: None });
+        module.add_import(JS_IMPORT_MODULE, CLEAR_TIMEOUT_IMPORT, Signature { params: vec![Type::I32], returns: None });
+
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains("      \"__wasmrust_schedule_run\": () => scheduleRun(),\n"));
+        assert!(glue.contains("      \"__wasmrust_set_timeout\": (id, millis) => setTimer(id, millis),\n"));
+        assert!(glue.contains("  queueMicrotask(() => {\n    runScheduled = false;\n    exports().__wasmrust_run_tasks();\n  });"));
+        assert!(glue.contains("    timers.delete(id);\n    exports().__wasmrust_timer_fired(id);\n  }, millis));"));
+        assert!(!glue.contains("  watchWakes();\n"));
+
+        // Threads wake the main thread through the executor's wake signal
+        module.shared_memory = true;
+        let glue = JsGlueGenerator::new("app.wasm").generate(&module).unwrap();
+        assert!(glue.contains("wasm.__wasm_call_ctors();\n  watchWakes();\n  return wasm;"));
+    }
+
+    #[test]
+    fn test_js_identifier_and_string_escaping() {
+        assert_eq!(js_identifier("do-work"), "do_work");
+        assert_eq!(js_identifier("1st"), "_1st");
+        assert_eq!(js_identifier("delete"), "_delete");
+        assert_eq!(js_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+    }
+}
@@ -0,0 +1,409 @@
+//! Linking with pre-built static libraries
+//!
+//! `Linker` links a module's relocatable object, see `backend::object`,
+//! with static libraries other compilers built, such as a C library from
+//! `clang --target=wasm32 -c`, by running wasm-ld. A library is an object
+//! (`.o`, or a `.wasm` holding a `linking` section) or an `ar` archive of
+//! them (`.a`); a linked module cannot be linked again.
+//!
+//! Before linking, `Linker::resolve` reads the libraries' symbol tables and
+//! matches the module's imports against what they define: those become
+//! calls into the library, and the rest stay imports for the host, as do
+//! the symbols libraries need that neither another library nor the module's
+//! exports define, such as a C library's system calls. The linked module
+//! exports `__wasm_call_ctors` when anything has constructors, for the host
+//! to call as with `InitStrategy::ExportedCallCtors`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use wasm::wasmir::WasmModule;
+use wasmparser::{KnownCustom, Linking, Parser, Payload, SymbolFlags, SymbolInfo, TypeRef};
+
+/// Linker run unless another is configured, found on `PATH`
+pub const DEFAULT_LINKER: &str = "wasm-ld";
+
+/// Linker exports the module's constructors under
+const CALL_CTORS: &str = "__wasm_call_ctors";
+
+/// Magic starting `ar` archives
+const ARCHIVE_MAGIC: &[u8] = b"!<arch>\n";
+
+/// Size of an archive member's header
+const MEMBER_HEADER_SIZE: usize = 60;
+
+/// Links in progress, telling apart the scratch directories of concurrent links
+static LINKS: AtomicU32 = AtomicU32::new(0);
+
+/// Reasons a module cannot be linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// A library or scratch file could not be read or written
+    Io(PathBuf, String),
+    /// A library is not an object or an archive of objects
+    NotAnObject(String),
+    /// A library's sections do not parse
+    Malformed(String, String),
+    /// The linker could not be run or failed, with what it printed
+    Linker(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            LinkError::NotAnObject(library) => {
+                write!(f, "{} is not a relocatable wasm object or an archive of them", library)
+            }
+            LinkError::Malformed(library, reason) => write!(f, "malformed library {}: {}", library, reason),
+            LinkError::Linker(output) => write!(f, "linking failed: {}", output),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Symbols a static library defines and needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Library {
+    pub path: PathBuf,
+    /// Symbols other objects may link against
+    pub defined: BTreeSet<String>,
+    /// Symbols the library needs from other objects or the host
+    pub undefined: BTreeSet<String>,
+}
+
+impl Library {
+    /// Reads the object or archive at `path`
+    pub fn load(path: &Path) -> Result<Self, LinkError> {
+        let bytes = std::fs::read(path).map_err(|error| io_error(path, error))?;
+        Self::parse(path, &bytes)
+    }
+
+    /// Reads the symbol tables of an object or of each object of an archive
+    pub fn parse(path: &Path, bytes: &[u8]) -> Result<Self, LinkError> {
+        let mut library = Self { path: path.to_path_buf(), defined: BTreeSet::new(), undefined: BTreeSet::new() };
+        let name = path.display().to_string();
+        if !bytes.starts_with(ARCHIVE_MAGIC) {
+            library.read_object(&name, bytes)?;
+            return Ok(library);
+        }
+        let members = archive_members(bytes).map_err(|reason| LinkError::Malformed(name.clone(), reason))?;
+        for (member, bytes) in members {
+            library.read_object(&format!("{}({})", name, member), bytes)?;
+        }
+        // Members resolve each other's symbols
+        let defined = library.defined.clone();
+        library.undefined.retain(|symbol| !defined.contains(symbol));
+        Ok(library)
+    }
+
+    fn read_object(&mut self, name: &str, bytes: &[u8]) -> Result<(), LinkError> {
+        let malformed =
+            |error: wasmparser::BinaryReaderError| LinkError::Malformed(name.to_string(), error.to_string());
+        if !bytes.starts_with(b"\0asm") {
+            return Err(LinkError::NotAnObject(name.to_string()));
+        }
+        let mut functions = Vec::new();
+        let mut globals = Vec::new();
+        let mut linked = false;
+        for payload in Parser::new(0).parse_all(bytes) {
+            match payload.map_err(malformed)? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import.map_err(malformed)?;
+                        match import.ty {
+                            TypeRef::Func(_) => functions.push(import.name.to_string()),
+                            TypeRef::Global(_) => globals.push(import.name.to_string()),
+                            _ => {}
+                        }
+                    }
+                }
+                Payload::CustomSection(reader) => {
+                    let KnownCustom::Linking(linking) = reader.as_known() else { continue };
+                    linked = true;
+                    for subsection in linking.subsections() {
+                        let Linking::SymbolTable(symbols) = subsection.map_err(malformed)? else { continue };
+                        for symbol in symbols {
+                            self.add_symbol(symbol.map_err(malformed)?, &functions, &globals);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !linked {
+            return Err(LinkError::NotAnObject(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Adds a symbol the object shares with others; local ones are its own
+    fn add_symbol(&mut self, symbol: SymbolInfo, functions: &[String], globals: &[String]) {
+        let (flags, name, imports, index) = match symbol {
+            SymbolInfo::Func { flags, index, name } => (flags, name, functions, index),
+            SymbolInfo::Global { flags, index, name } => (flags, name, globals, index),
+            SymbolInfo::Data { flags, name, .. } => (flags, Some(name), globals, u32::MAX),
+            _ => return,
+        };
+        if flags.contains(SymbolFlags::BINDING_LOCAL) {
+            return;
+        }
+        // Undefined symbols without an explicit name are named by their import
+        let Some(name) = name.or_else(|| imports.get(index as usize).map(String::as_str)) else { return };
+        if flags.contains(SymbolFlags::UNDEFINED) {
+            self.undefined.insert(name.to_string());
+        } else {
+            self.defined.insert(name.to_string());
+        }
+    }
+}
+
+/// Where the imports of a module come from once linked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolution {
+    /// Imports a library defines, with the first library defining each
+    pub linked: BTreeMap<String, PathBuf>,
+    /// Imports left to the host, the module's and the libraries'
+    pub host: BTreeSet<String>,
+}
+
+/// Links objects of modules with static libraries by running wasm-ld
+#[derive(Debug, Clone)]
+pub struct Linker {
+    program: PathBuf,
+    libraries: Vec<Library>,
+    stack_size: Option<u32>,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self { program: PathBuf::from(DEFAULT_LINKER), libraries: Vec::new(), stack_size: None }
+    }
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the linker to run; `rust-lld` and `lld` are run as wasm-ld
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    pub fn library(mut self, library: Library) -> Self {
+        self.libraries.push(library);
+        self
+    }
+
+    /// Sets the bytes the linker reserves for the shadow stack
+    pub fn stack_size(mut self, bytes: u32) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    pub fn libraries(&self) -> &[Library] {
+        &self.libraries
+    }
+
+    /// Matches the module's imports, and those of the libraries, against the symbols the others define
+    pub fn resolve(&self, module: &WasmModule) -> Resolution {
+        let mut resolution = Resolution::default();
+        let defined = |symbol: &String| {
+            module.exports.iter().any(|export| &export.name == symbol)
+                || self.libraries.iter().any(|library| library.defined.contains(symbol))
+        };
+        for library in &self.libraries {
+            resolution.host.extend(library.undefined.iter().filter(|symbol| !defined(symbol)).cloned());
+        }
+        for import in &module.imports {
+            match self.libraries.iter().find(|library| library.defined.contains(&import.name)) {
+                Some(library) => {
+                    resolution.linked.insert(import.name.clone(), library.path.clone());
+                }
+                None => {
+                    resolution.host.insert(import.name.clone());
+                }
+            }
+        }
+        resolution
+    }
+
+    /// Links `object`, compiled from `module`, with the libraries, returning the linked module
+    pub fn link(&self, module: &WasmModule, object: &[u8]) -> Result<Vec<u8>, LinkError> {
+        let link = LINKS.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("wasmrust-link-{}-{}", std::process::id(), link));
+        std::fs::create_dir_all(&dir).map_err(|error| io_error(&dir, error))?;
+        let result = self.link_in(&dir, module, object);
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    fn link_in(&self, dir: &Path, module: &WasmModule, object: &[u8]) -> Result<Vec<u8>, LinkError> {
+        let (input, host, output) = (dir.join("module.o"), dir.join("host.syms"), dir.join("linked.wasm"));
+        std::fs::write(&input, object).map_err(|error| io_error(&input, error))?;
+        let host_symbols: Vec<String> = self.resolve(module).host.into_iter().collect();
+        std::fs::write(&host, host_symbols.join("\n")).map_err(|error| io_error(&host, error))?;
+
+        let mut command = Command::new(&self.program);
+        let stem = self.program.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        if stem == "rust-lld" || stem == "lld" {
+            command.args(["-flavor", "wasm"]);
+        }
+        command.arg("--no-entry")
+            .arg(format!("--export-if-defined={}", CALL_CTORS))
+            .arg(format!("--allow-undefined-file={}", host.display()));
+        if let Some(bytes) = self.stack_size {
+            command.arg("-z").arg(format!("stack-size={}", bytes));
+        }
+        command.arg("-o").arg(&output).arg(&input);
+        command.args(self.libraries.iter().map(|library| &library.path));
+        let result = command.output()
+            .map_err(|error| LinkError::Linker(format!("cannot run {}: {}", self.program.display(), error)))?;
+        if !result.status.success() {
+            return Err(LinkError::Linker(String::from_utf8_lossy(&result.stderr).trim().to_string()));
+        }
+        std::fs::read(&output).map_err(|error| io_error(&output, error))
+    }
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> LinkError {
+    LinkError::Io(path.to_path_buf(), error.to_string())
+}
+
+/// Names and contents of the members of an `ar` archive, less its symbol
+/// and name tables, in GNU or BSD layout
+fn archive_members(bytes: &[u8]) -> Result<Vec<(String, &[u8])>, String> {
+    let mut members = Vec::new();
+    let mut long_names: &[u8] = &[];
+    let mut position = ARCHIVE_MAGIC.len();
+    while position < bytes.len() {
+        let header = bytes.get(position..position + MEMBER_HEADER_SIZE)
+            .ok_or_else(|| format!("member header at {} is cut off", position))?;
+        let field = |range: std::ops::Range<usize>| String::from_utf8_lossy(&header[range]).trim_end().to_string();
+        let size: usize = field(48..58).parse().map_err(|_| format!("member at {} has no size", position))?;
+        let start = position + MEMBER_HEADER_SIZE;
+        let mut data = bytes.get(start..start + size)
+            .ok_or_else(|| format!("member at {} is cut off", position))?;
+        // Members start at even offsets
+        position = start + size + size % 2;
+
+        let name = field(0..16);
+        let name = if let Some(length) = name.strip_prefix("#1/") {
+            // BSD: the name leads the data
+            let length: usize = length.parse().map_err(|_| format!("bad member name {}", name))?;
+            let (name, rest) = data.split_at(length.min(data.len()));
+            data = rest;
+            String::from_utf8_lossy(name).trim_end_matches('\0').to_string()
+        } else if name == "//" {
+            long_names = data;
+            continue;
+        } else if let Some(offset) = name.strip_prefix('/').and_then(|offset| offset.parse::<usize>().ok()) {
+            // GNU: the name is in the long name table
+            let rest = long_names.get(offset..).ok_or_else(|| format!("bad long name offset {}", offset))?;
+            let end = rest.iter().position(|&byte| byte == b'\n').unwrap_or(rest.len());
+            String::from_utf8_lossy(&rest[..end]).trim_end_matches('/').to_string()
+        } else {
+            name.trim_end_matches('/').to_string()
+        };
+        if name.is_empty() || name == "SYM64" || name.starts_with("__.SYMDEF") {
+            continue;
+        }
+        members.push((name, data));
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::WasmCodegen;
+    use wasm::wasmir::{Instruction, MemoryType, Signature, Terminator, WasmIR};
+
+    /// Module exporting `export` that calls the imports `imports`
+    fn module(export: &str, imports: &[&str]) -> WasmModule {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let calls = imports.iter()
+            .map(|&name| {
+                let import = module.add_import("env", name, Signature { params: vec![], returns: None });
+                Instruction::CallImport { import, args: vec![] }
+            })
+            .collect();
+        let mut function = WasmIR::new(format!("{}_impl", export), Signature { params: vec![], returns: None });
+        function.add_basic_block(calls, Terminator::Return { value: None });
+        let index = module.add_function(function);
+        module.export_function(export, index);
+        module
+    }
+
+    fn object(export: &str, imports: &[&str]) -> Vec<u8> {
+        WasmCodegen::new().compile_object(&module(export, imports)).unwrap()
+    }
+
+    /// GNU `ar` archive of `members`, with an empty symbol table and the
+    /// second member's name in the long name table
+    fn archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let header =
+            |name: &str, size: usize| format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 644, size);
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        let mut add = |name: &str, data: &[u8]| {
+            bytes.extend_from_slice(header(name, data.len()).as_bytes());
+            bytes.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                bytes.push(b'\n');
+            }
+        };
+        add("/", &[0, 0, 0, 0]);
+        let long_names = format!("{}/\n", members[1].0);
+        add("//", long_names.as_bytes());
+        add(&format!("{}/", members[0].0), members[0].1);
+        add("/0", members[1].1);
+        bytes
+    }
+
+    #[test]
+    fn test_reads_symbols_of_objects() {
+        let library = Library::parse(Path::new("puts.o"), &object("puts", &["write"])).unwrap();
+        assert_eq!(library.defined, BTreeSet::from(["puts".to_string()]));
+        assert_eq!(library.undefined, BTreeSet::from(["write".to_string()]));
+
+        let linked = WasmCodegen::new().compile(&module("puts", &[])).unwrap();
+        assert_eq!(
+            Library::parse(Path::new("puts.wasm"), &linked).unwrap_err(),
+            LinkError::NotAnObject("puts.wasm".to_string())
+        );
+        assert!(matches!(Library::load(Path::new("/nonexistent/libc.a")), Err(LinkError::Io(..))));
+    }
+
+    #[test]
+    fn test_reads_archive_members() {
+        let (puts, write) = (object("puts", &["write"]), object("write", &["syscall"]));
+        let bytes = archive(&[("puts.o", &puts), ("a-long-member-name.o", &write)]);
+        let library = Library::parse(Path::new("libc.a"), &bytes).unwrap();
+        assert_eq!(library.defined, BTreeSet::from(["puts".to_string(), "write".to_string()]));
+        assert_eq!(library.undefined, BTreeSet::from(["syscall".to_string()]));
+
+        let members: Vec<String> = archive_members(&bytes).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(members, ["puts.o", "a-long-member-name.o"]);
+        assert!(archive_members(&bytes[..bytes.len() - 10]).is_err());
+    }
+
+    #[test]
+    fn test_resolves_imports_against_libraries() {
+        // libc needs `write` from the host and `main` from the module
+        let libc = Library::parse(Path::new("libc.a"), &object("puts", &["write", "main"])).unwrap();
+        let libm = Library::parse(Path::new("libm.a"), &object("sqrt", &["puts"])).unwrap();
+        let linker = Linker::new().library(libc).library(libm);
+        let resolution = linker.resolve(&module("main", &["puts", "sqrt", "now"]));
+        assert_eq!(resolution.linked, BTreeMap::from([
+            ("puts".to_string(), PathBuf::from("libc.a")),
+            ("sqrt".to_string(), PathBuf::from("libm.a")),
+        ]));
+        assert_eq!(resolution.host, BTreeSet::from(["now".to_string(), "write".to_string()]));
+    }
+}
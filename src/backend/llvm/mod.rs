@@ -4,9 +4,11 @@
 //! optimized for release builds with full optimization pipeline.
 
 use crate::backend::{Backend, BackendError, CompilationResult, BackendCapabilities};
+use crate::backend::target_features::TargetFeatures;
 use crate::wasmir::WasmIR;
 use rustc_target::spec::Target;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use wasm::wasmir::features::FeatureSet;
 
 /// WasmRust LLVM Backend
 /// 
@@ -18,6 +20,8 @@ pub struct WasmRustLLVMBackend {
     optimization_flags: LLVMOptimizationFlags,
     /// PGO profile data
     pgo_data: Option<Vec<u8>>,
+    /// Proposals the target engine enables, given to LLVM as target features
+    features: FeatureSet,
 }
 
 /// LLVM-specific optimization flags
@@ -59,9 +63,15 @@ impl WasmRustLLVMBackend {
             target,
             optimization_flags,
             pgo_data: None,
+            features: FeatureSet::default(),
         })
     }
 
+    /// Sets the proposals the target engine enables
+    pub fn set_features(&mut self, features: FeatureSet) {
+        self.features = features;
+    }
+
     /// Compiles WasmIR to machine code using LLVM
     pub fn compile(
         &mut self,
@@ -75,10 +85,10 @@ impl WasmRustLLVMBackend {
         let llvm_ir = self.wasmir_to_llvm_ir(wasmir)?;
         
         // Optimize LLVM IR
-        let optimized_llvm_ir = self.optimize_llvm_ir(llvm_ir, profile)?;
+        let optimized_llvm_ir = self.optimize_llvm_ir(&llvm_ir, profile)?;
         
         // Generate machine code
-        let machine_code = self.llvm_ir_to_machine_code(optimized_llvm_ir)?;
+        let machine_code = self.llvm_ir_to_machine_code(&optimized_llvm_ir)?;
         
         // Generate relocations and symbols
         let (symbols, relocations) = self.generate_relocations(wasmir, &machine_code)?;
@@ -91,11 +101,22 @@ impl WasmRustLLVMBackend {
                 target: self.target.arch.clone(),
                 optimization_level: self.get_optimization_level(profile),
                 build_profile: profile,
-                timestamp: std::time::SystemTime::now(),
+                timestamp: crate::backend::CompilationMetadata::timestamp(false),
             },
         })
     }
 
+    /// Optimized LLVM IR of a function, as `compile` hands it to codegen
+    pub fn emit_llvm_ir(
+        &mut self,
+        wasmir: &WasmIR,
+        profile: crate::backend::BuildProfile,
+    ) -> Result<String, BackendError> {
+        self.apply_llvm_optimizations(wasmir)?;
+        let llvm_ir = self.wasmir_to_llvm_ir(wasmir)?;
+        self.optimize_llvm_ir(&llvm_ir, profile)
+    }
+
     /// Loads PGO profile data
     pub fn load_pgo_profile(&mut self, profile_path: &str) -> Result<(), BackendError> {
         use std::fs;
@@ -173,6 +194,8 @@ impl WasmRustLLVMBackend {
 
     /// Converts WasmIR to LLVM IR
     fn wasmir_to_llvm_ir(&self, wasmir: &WasmIR) -> Result<String, BackendError> {
+        self.features.check_function(wasmir)
+            .map_err(|violation| BackendError::Unsupported(violation.to_string()))?;
         // Implementation of WasmIR to LLVM IR conversion
         // This would map WasmIR instructions to LLVM IR
         
@@ -183,12 +206,16 @@ impl WasmRustLLVMBackend {
             target triple = \"wasm32-unknown-unknown\"\n\
             \n\
             ; Function: {}\n\
-            define void @{}() {{\n\
+            define void @{}() #0 {{\n\
             ; WasmIR to LLVM IR conversion\n\
             ret void\n\
-            }}\n",
+            }}\n\
+            \n\
+            attributes #0 = {{ \"target-features\"=\"{}\" }}\n",
+            wasmir.name.replace('-', "_"),
+            wasmir.name,
             wasmir.name.replace('-', "_"),
-            wasmir.name.replace('-', "_")
+            TargetFeatures::new(self.features)
         );
         
         Ok(llvm_ir)
@@ -202,8 +229,7 @@ impl WasmRustLLVMBackend {
         // For now, return slightly optimized LLVM IR
         let optimized_llvm_ir = format!(
             "{}\n\
-            ; Optimized for profile: {:?}\n\
-            {}",
+            ; Optimized for profile: {:?}\n",
             llvm_ir,
             profile
         );
@@ -228,8 +254,8 @@ impl WasmRustLLVMBackend {
     }
 
     /// Generates relocations and symbols
-    fn generate_relocations(&self, wasmir: &WasmIR, machine_code: &[u8]) -> Result<(HashMap<String, u64>, Vec<crate::backend::Relocation>), BackendError> {
-        let mut symbols = HashMap::new();
+    fn generate_relocations(&self, wasmir: &WasmIR, machine_code: &[u8]) -> Result<(BTreeMap<String, u64>, Vec<crate::backend::Relocation>), BackendError> {
+        let mut symbols = BTreeMap::new();
         let mut relocations = Vec::new();
         
         // Add function symbols
@@ -3,25 +3,64 @@
 //! This module provides different codegen backends for WasmRust,
 //! each optimized for different use cases and host environments.
 
+pub mod bench_gen;
+pub mod budget;
+pub mod abi_check;
+pub mod canonical;
+pub mod capabilities;
+pub mod codegen;
+pub mod conformance;
 pub mod cranelift;
+pub mod demangle;
+pub mod dump;
+pub mod dylink;
+pub mod emscripten;
+pub mod freestanding;
+pub mod interface;
+pub mod interpreter;
+pub mod js_glue;
+pub mod link;
 pub mod llvm;
+pub mod object;
+pub mod pgo;
+pub mod preinit;
+pub mod registry;
+pub mod sanitizer;
+pub mod single_threaded;
+pub mod size_report;
+pub mod split;
+pub mod startup_bench;
+pub mod target_features;
 
 use crate::wasmir::WasmIR;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
 
 /// Backend compilation result
 #[derive(Debug)]
 pub struct CompilationResult {
     /// Compiled machine code
     pub code: Vec<u8>,
-    /// Symbol table for linking
-    pub symbols: HashMap<String, u64>,
+    /// Symbol table for linking, sorted so it is emitted the same way each build
+    pub symbols: BTreeMap<String, u64>,
     /// Relocation information
     pub relocations: Vec<Relocation>,
     /// Compilation metadata
     pub metadata: CompilationMetadata,
 }
 
+impl CompilationResult {
+    /// Renames Rust symbols in the symbol table and relocations to their demangled paths
+    pub fn demangle_symbols(&mut self) {
+        self.symbols = std::mem::take(&mut self.symbols).into_iter()
+            .map(|(symbol, offset)| (demangle::demangle_or_raw(&symbol), offset))
+            .collect();
+        for relocation in &mut self.relocations {
+            relocation.symbol = demangle::demangle_or_raw(&relocation.symbol);
+        }
+    }
+}
+
 /// Relocation information for linking
 #[derive(Debug, Clone)]
 pub struct Relocation {
@@ -60,7 +99,25 @@ pub struct CompilationMetadata {
     /// Build profile used
     pub build_profile: BuildProfile,
     /// Compilation timestamp
-    pub timestamp: std::time::SystemTime,
+    pub timestamp: SystemTime,
+}
+
+impl CompilationMetadata {
+    /// Environment variable reproducible-build tools set to the time a build should claim, in Unix seconds
+    pub const SOURCE_DATE_EPOCH: &'static str = "SOURCE_DATE_EPOCH";
+
+    /// Timestamp to record for a compilation
+    ///
+    /// `SOURCE_DATE_EPOCH` wins when set. Otherwise a deterministic build
+    /// claims the Unix epoch, and any other the current time.
+    pub fn timestamp(deterministic: bool) -> SystemTime {
+        let source_date = std::env::var(Self::SOURCE_DATE_EPOCH).ok().and_then(|secs| secs.trim().parse().ok());
+        match source_date {
+            Some(secs) => SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            None if deterministic => SystemTime::UNIX_EPOCH,
+            None => SystemTime::now(),
+        }
+    }
 }
 
 /// Optimization levels for compilation
@@ -89,6 +146,28 @@ pub enum BuildProfile {
     Release,
 }
 
+impl BuildProfile {
+    /// Environment variable naming the profile of a codegen backend that
+    /// rustc loads, which has no other way to receive it
+    pub const ENV: &'static str = "WASMRUST_BUILD_PROFILE";
+
+    /// Name used in `ENV`
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildProfile::Freestanding => "freestanding",
+            BuildProfile::Development => "dev",
+            BuildProfile::Release => "release",
+        }
+    }
+
+    /// Parses a profile name
+    pub fn from_name(name: &str) -> Option<Self> {
+        [BuildProfile::Freestanding, BuildProfile::Development, BuildProfile::Release]
+            .into_iter()
+            .find(|profile| profile.name() == name)
+    }
+}
+
 /// Backend trait for different codegen implementations
 pub trait Backend {
     /// Compiles WasmIR to machine code
@@ -99,13 +178,20 @@ pub trait Backend {
     
     /// Gets backend capabilities
     fn capabilities(&self) -> BackendCapabilities;
+
+    /// What the backend did since it was created or reset, if it counts
+    fn stats(&self) -> Option<&cranelift::CompilationStats> {
+        None
+    }
     
     /// Resets backend state
     fn reset(&mut self);
 }
 
 /// Backend capabilities
-#[derive(Debug, Clone)]
+///
+/// As a requirement, the default asks for nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct BackendCapabilities {
     /// Supports thin monomorphization
     pub thin_monomorphization: bool,
@@ -121,6 +207,29 @@ pub struct BackendCapabilities {
     pub linear_types: bool,
 }
 
+impl BackendCapabilities {
+    /// Each capability by name, with whether it is set
+    pub fn flags(&self) -> [(&'static str, bool); 6] {
+        [
+            ("thin-monomorphization", self.thin_monomorphization),
+            ("streaming-layout", self.streaming_layout),
+            ("pgo", self.pgo_support),
+            ("component-model", self.component_model),
+            ("wasm-optimizations", self.wasm_optimizations),
+            ("linear-types", self.linear_types),
+        ]
+    }
+
+    /// Names of the capabilities `required` sets and these lack
+    pub fn missing(&self, required: &BackendCapabilities) -> Vec<&'static str> {
+        required.flags().into_iter()
+            .zip(self.flags())
+            .filter(|&((_, wanted), (_, has))| wanted && !has)
+            .map(|((name, _), _)| name)
+            .collect()
+    }
+}
+
 /// Backend errors
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BackendError {
@@ -156,71 +265,52 @@ impl std::error::Error for BackendError {}
 /// Backend factory for creating appropriate backend
 pub struct BackendFactory;
 
+/// Backend `BackendFactory::create_backend` chose, with why
+pub struct BackendSelection {
+    pub backend: Box<dyn Backend>,
+    pub report: SelectionReport,
+}
+
+/// How a backend was chosen
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelectionReport {
+    pub chosen: &'static str,
+    /// Requested capabilities the chosen backend lacks
+    pub missing: Vec<&'static str>,
+    /// Backends tried before or instead of it, with why each was not chosen
+    pub passed_over: Vec<(&'static str, String)>,
+}
+
+impl std::fmt::Display for SelectionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "using {}", self.chosen)?;
+        if !self.missing.is_empty() {
+            write!(f, ", which lacks {}", self.missing.join(", "))?;
+        }
+        for (name, reason) in &self.passed_over {
+            write!(f, "; passed over {}: {}", name, reason)?;
+        }
+        Ok(())
+    }
+}
+
 impl BackendFactory {
-    /// Creates a backend for the specified target and profile
+    /// Creates the backend best meeting `required` for the target and profile
+    ///
+    /// Chooses among the built-in backends and those registered with
+    /// `BackendRegistry::register`; see `BackendRegistry::select`. The
+    /// interpreter emits no code, so it is never chosen here.
     pub fn create_backend(
         target: &str,
         profile: BuildProfile,
-    ) -> Result<Box<dyn Backend>, BackendError> {
-        match profile {
-            BuildProfile::Development => {
-                // Use Cranelift for fast development builds
-                let cranelift_backend = crate::backend::cranelift::WasmRustCraneliftBackend::new(
-                    rustc_target::spec::Target {
-                        arch: target.to_string(),
-                        ..Default::default()
-                    }
-                )?;
-                Ok(Box::new(cranelift_backend))
-            }
-            BuildProfile::Release => {
-                // Use LLVM for optimized release builds
-                #[cfg(feature = "llvm-backend")]
-                {
-                    let llvm_backend = crate::backend::llvm::WasmRustLLVMBackend::new(
-                        rustc_target::spec::Target {
-                            arch: target.to_string(),
-                            ..Default::default()
-                        }
-                    )?;
-                    return Ok(Box::new(llvm_backend));
-                }
-                
-                #[cfg(not(feature = "llvm-backend"))]
-                {
-                    // Fallback to Cranelift if LLVM not available
-                    let cranelift_backend = crate::backend::cranelift::WasmRustCraneliftBackend::new(
-                        rustc_target::spec::Target {
-                            arch: target.to_string(),
-                            ..Default::default()
-                        }
-                    )?;
-                    Ok(Box::new(cranelift_backend))
-                }
-            }
-            BuildProfile::Freestanding => {
-                // Use Cranelift for freestanding builds (minimal overhead)
-                let cranelift_backend = crate::backend::cranelift::WasmRustCraneliftBackend::new(
-                    rustc_target::spec::Target {
-                        arch: target.to_string(),
-                        ..Default::default()
-                    }
-                )?;
-                Ok(Box::new(cranelift_backend))
-            }
-        }
+        required: &BackendCapabilities,
+    ) -> Result<BackendSelection, BackendError> {
+        registry::BackendRegistry::global().select(target, profile, required)
     }
 
     /// Lists available backends
     pub fn available_backends() -> Vec<&'static str> {
-        let mut backends = vec!["cranelift"];
-        
-        #[cfg(feature = "llvm-backend")]
-        {
-            backends.push("llvm");
-        }
-        
-        backends
+        registry::BackendRegistry::global().names()
     }
 
     /// Gets recommended backend for target and profile
@@ -264,14 +354,36 @@ mod tests {
 
     #[test]
     fn test_backend_factory_creation() {
-        let backend = BackendFactory::create_backend("wasm32", BuildProfile::Development);
+        let required = BackendCapabilities::default();
+        let backend = BackendFactory::create_backend("wasm32", BuildProfile::Development, &required);
         assert!(backend.is_ok());
     }
 
+    #[test]
+    fn test_capability_negotiation_reports_missing() {
+        let required = BackendCapabilities { pgo_support: true, linear_types: true, ..BackendCapabilities::default() };
+        let interpreter = interpreter::InterpreterBackend::new().capabilities();
+        assert_eq!(interpreter.missing(&required), ["pgo"]);
+        assert!(interpreter.missing(&BackendCapabilities::default()).is_empty());
+
+        let report = SelectionReport {
+            chosen: "interpreter",
+            missing: vec!["pgo"],
+            passed_over: vec![("cranelift", "Unsupported target: wasm16".to_string())],
+        };
+        assert_eq!(
+            report.to_string(),
+            "using interpreter, which lacks pgo; passed over cranelift: Unsupported target: wasm16"
+        );
+        let registry = registry::BackendRegistry::builtin();
+        assert_eq!(registry.candidates(BuildProfile::Development).last().unwrap().name, "interpreter");
+    }
+
     #[test]
     fn test_available_backends() {
         let backends = BackendFactory::available_backends();
         assert!(backends.contains(&"cranelift"));
+        assert!(!backends.contains(&"interpreter"));
         
         #[cfg(feature = "llvm-backend")]
         {
@@ -291,17 +403,25 @@ mod tests {
         assert_eq!(recommended, Some("cranelift"));
     }
 
+    #[test]
+    fn test_profile_names_round_trip() {
+        for profile in [BuildProfile::Freestanding, BuildProfile::Development, BuildProfile::Release] {
+            assert_eq!(BuildProfile::from_name(profile.name()), Some(profile));
+        }
+        assert_eq!(BuildProfile::from_name("debug"), None);
+    }
+
     #[test]
     fn test_compilation_result() {
         let result = CompilationResult {
             code: vec![0x01, 0x02, 0x03],
-            symbols: HashMap::new(),
+            symbols: BTreeMap::new(),
             relocations: Vec::new(),
             metadata: CompilationMetadata {
                 target: "wasm32".to_string(),
                 optimization_level: OptimizationLevel::Standard,
                 build_profile: BuildProfile::Release,
-                timestamp: std::time::SystemTime::UNIX_EPOCH,
+                timestamp: SystemTime::UNIX_EPOCH,
             },
         };
         
@@ -312,6 +432,37 @@ mod tests {
         assert_eq!(result.metadata.build_profile, BuildProfile::Release);
     }
 
+    #[test]
+    fn test_demangles_symbols_and_relocations() {
+        let mut result = CompilationResult {
+            code: Vec::new(),
+            symbols: BTreeMap::from([("_ZN3app4main17h0123456789abcdefE".to_string(), 0), ("memcpy".to_string(), 8)]),
+            relocations: vec![Relocation {
+                kind: RelocationKind::FunctionCall,
+                offset: 4,
+                symbol: "_RNvCs1234_3app6helper".to_string(),
+                addend: 0,
+            }],
+            metadata: CompilationMetadata {
+                target: "wasm32".to_string(),
+                optimization_level: OptimizationLevel::None,
+                build_profile: BuildProfile::Development,
+                timestamp: SystemTime::UNIX_EPOCH,
+            },
+        };
+        result.demangle_symbols();
+        assert_eq!(result.symbols, BTreeMap::from([("app::main".to_string(), 0), ("memcpy".to_string(), 8)]));
+        assert_eq!(result.relocations[0].symbol, "app::helper");
+    }
+
+    #[test]
+    fn test_deterministic_timestamps() {
+        if std::env::var_os(CompilationMetadata::SOURCE_DATE_EPOCH).is_none() {
+            assert_eq!(CompilationMetadata::timestamp(true), SystemTime::UNIX_EPOCH);
+            assert!(CompilationMetadata::timestamp(false) > SystemTime::UNIX_EPOCH);
+        }
+    }
+
     #[test]
     fn test_relocation() {
         let relocation = Relocation {
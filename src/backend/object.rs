@@ -0,0 +1,307 @@
+//! Relocatable object files for external linkers
+//!
+//! `WasmCodegen::compile_object` writes a module as wasm-ld links it next
+//! to C and C++ objects, following the tool conventions of LLVM: memory is
+//! imported as `env.__linear_memory` and the shadow stack pointer as
+//! `env.__stack_pointer`, shared with every other object. Every call and
+//! global access records a `Relocation` against a symbol, and its index is
+//! padded to five bytes so the linker can patch it in place. A `linking`
+//! section lists the symbols and constructors, and a `reloc.CODE` section
+//! the relocations.
+//!
+//! Imports are undefined symbols, resolved by the linker against the other
+//! objects or left to the host. Exported functions are global symbols under
+//! their export name, which the linked module exports too. Every other
+//! function and global is local to the object. Constructors, then the start
+//! function, run from the linker's `__wasm_call_ctors`.
+//!
+//! Code addresses linear memory by constant addresses, which the linker
+//! cannot move, so modules with data segments, memory regions, the bundled
+//! allocator, or thread locals are rejected, as are unwinding panics, whose
+//! tag would need a symbol. Stack frames go unchecked, because the limit of
+//! the linked stack is unknown to a single object.
+
+use crate::backend::codegen::{write_i64, write_u32};
+use crate::backend::{BackendError, Relocation, RelocationKind};
+use std::collections::HashMap;
+
+/// Name of the custom section holding the symbol table
+pub const LINKING_SECTION: &str = "linking";
+
+/// Version of the `linking` section layout
+pub const LINKING_VERSION: u32 = 2;
+
+/// Prefix of custom sections holding the relocations of a section
+pub const RELOC_SECTION_PREFIX: &str = "reloc.";
+
+/// Field objects import their memory under
+pub const LINEAR_MEMORY_IMPORT: &str = "__linear_memory";
+
+/// Priority under which the start function runs, after every constructor
+pub const START_PRIORITY: u32 = u32::MAX;
+
+/// Symbol is visible only within the object
+pub const SYMBOL_BINDING_LOCAL: u32 = 0x02;
+
+/// Symbol refers to an import
+pub const SYMBOL_UNDEFINED: u32 = 0x10;
+
+/// Linked module exports the symbol
+pub const SYMBOL_EXPORTED: u32 = 0x20;
+
+/// `linking` subsection listing the constructors
+const SUBSECTION_INIT_FUNCS: u8 = 6;
+
+/// `linking` subsection listing the symbols
+const SUBSECTION_SYMBOL_TABLE: u8 = 8;
+
+/// Padded width of relocated LEB128 fields
+const PADDED_LEB_BYTES: usize = 5;
+
+/// Kinds of symbols an object defines or references
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function = 0,
+    Global = 2,
+}
+
+/// Entry of the symbol table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    /// Name other objects link against, unique within the object
+    pub name: String,
+    /// Function or global index
+    pub index: u32,
+    /// `SYMBOL_*` flags
+    pub flags: u32,
+}
+
+impl Symbol {
+    pub fn is_undefined(&self) -> bool {
+        self.flags & SYMBOL_UNDEFINED != 0
+    }
+}
+
+/// Symbols of an object, in symbol index order
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    indices: HashMap<String, u32>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a symbol, returning its name, suffixed with `.N` if another symbol took it
+    ///
+    /// Undefined symbols take their name from the import, so only defined
+    /// ones should need the suffix.
+    pub fn add(&mut self, kind: SymbolKind, name: &str, index: u32, flags: u32) -> String {
+        let mut unique = name.to_string();
+        let mut suffix = 1;
+        while self.indices.contains_key(&unique) {
+            unique = format!("{}.{}", name, suffix);
+            suffix += 1;
+        }
+        self.indices.insert(unique.clone(), self.symbols.len() as u32);
+        self.symbols.push(Symbol { kind, name: unique.clone(), index, flags });
+        unique
+    }
+
+    /// Symbol index of `name`
+    pub fn index_of(&self, name: &str) -> Option<u32> {
+        self.indices.get(name).copied()
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+}
+
+/// Relocation type of the tool conventions for a `RelocationKind`
+///
+/// Memory relocations need data symbols, which objects do not define yet.
+pub fn relocation_type(kind: RelocationKind) -> u8 {
+    match kind {
+        // R_WASM_FUNCTION_INDEX_LEB
+        RelocationKind::FunctionCall => 0,
+        // R_WASM_MEMORY_ADDR_LEB
+        RelocationKind::DataAccess => 3,
+        // R_WASM_MEMORY_ADDR_I32
+        RelocationKind::Absolute => 5,
+        // R_WASM_GLOBAL_INDEX_LEB
+        RelocationKind::GlobalAccess => 7,
+        // R_WASM_MEMORY_ADDR_REL_SLEB
+        RelocationKind::Relative => 11,
+    }
+}
+
+/// Writes an unsigned LEB128 integer padded to the width relocations patch
+pub fn write_padded_u32(buf: &mut Vec<u8>, mut value: u32) {
+    for byte in 0..PADDED_LEB_BYTES {
+        let more = byte + 1 < PADDED_LEB_BYTES;
+        buf.push((value & 0x7F) as u8 | if more { 0x80 } else { 0 });
+        value >>= 7;
+    }
+}
+
+/// Number of sections in `binary`, which holds whole sections after the header
+pub fn section_count(binary: &[u8]) -> u32 {
+    let mut count = 0;
+    let mut position = 8;
+    while position < binary.len() {
+        let (size, read) = read_u32(&binary[position + 1..]);
+        position += 1 + read + size as usize;
+        count += 1;
+    }
+    count
+}
+
+/// Writes the `linking` section listing `symbols` and the constructors,
+/// as a priority and the name of their symbol
+pub fn write_linking_section(
+    output: &mut Vec<u8>,
+    symbols: &SymbolTable,
+    init_functions: &[(u32, String)],
+) -> Result<(), BackendError> {
+    let mut content = Vec::new();
+    write_name(&mut content, LINKING_SECTION);
+    write_u32(&mut content, LINKING_VERSION);
+
+    let mut table = Vec::new();
+    write_u32(&mut table, symbols.symbols.len() as u32);
+    for symbol in &symbols.symbols {
+        table.push(symbol.kind as u8);
+        write_u32(&mut table, symbol.flags);
+        write_u32(&mut table, symbol.index);
+        if !symbol.is_undefined() {
+            write_name(&mut table, &symbol.name);
+        }
+    }
+    write_subsection(&mut content, SUBSECTION_SYMBOL_TABLE, &table);
+
+    if !init_functions.is_empty() {
+        let mut inits = Vec::new();
+        write_u32(&mut inits, init_functions.len() as u32);
+        for (priority, name) in init_functions {
+            write_u32(&mut inits, *priority);
+            write_u32(&mut inits, symbol_index(symbols, name)?);
+        }
+        write_subsection(&mut content, SUBSECTION_INIT_FUNCS, &inits);
+    }
+    write_custom_section(output, &content);
+    Ok(())
+}
+
+/// Writes the `reloc.<target>` section of the section at `section_index`
+///
+/// Offsets are relative to the start of that section's content.
+pub fn write_reloc_section(
+    output: &mut Vec<u8>,
+    target: &str,
+    section_index: u32,
+    relocations: &[Relocation],
+    symbols: &SymbolTable,
+) -> Result<(), BackendError> {
+    let mut content = Vec::new();
+    write_name(&mut content, &format!("{}{}", RELOC_SECTION_PREFIX, target));
+    write_u32(&mut content, section_index);
+    write_u32(&mut content, relocations.len() as u32);
+    for relocation in relocations {
+        let ty = relocation_type(relocation.kind);
+        content.push(ty);
+        write_u32(&mut content, relocation.offset);
+        write_u32(&mut content, symbol_index(symbols, &relocation.symbol)?);
+        if matches!(relocation.kind, RelocationKind::DataAccess | RelocationKind::Absolute | RelocationKind::Relative) {
+            write_i64(&mut content, relocation.addend);
+        }
+    }
+    write_custom_section(output, &content);
+    Ok(())
+}
+
+fn symbol_index(symbols: &SymbolTable, name: &str) -> Result<u32, BackendError> {
+    symbols.index_of(name)
+        .ok_or_else(|| BackendError::CompilationFailed(format!("relocation against undefined symbol {}", name)))
+}
+
+fn write_subsection(output: &mut Vec<u8>, id: u8, content: &[u8]) {
+    output.push(id);
+    write_u32(output, content.len() as u32);
+    output.extend_from_slice(content);
+}
+
+fn write_custom_section(output: &mut Vec<u8>, content: &[u8]) {
+    output.push(0);
+    write_u32(output, content.len() as u32);
+    output.extend_from_slice(content);
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    write_u32(buf, name.len() as u32);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+/// Reads an unsigned LEB128 integer, returning it and the bytes it took
+fn read_u32(bytes: &[u8]) -> (u32, usize) {
+    let mut value = 0u32;
+    for (i, &byte) in bytes.iter().enumerate().take(PADDED_LEB_BYTES) {
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (value, i + 1);
+        }
+    }
+    (value, bytes.len().min(PADDED_LEB_BYTES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_leb_keeps_value() {
+        let mut padded = Vec::new();
+        write_padded_u32(&mut padded, 3);
+        assert_eq!(padded, vec![0x83, 0x80, 0x80, 0x80, 0x00]);
+        assert_eq!(read_u32(&padded), (3, 5));
+
+        let mut padded = Vec::new();
+        write_padded_u32(&mut padded, u32::MAX);
+        assert_eq!(read_u32(&padded), (u32::MAX, 5));
+    }
+
+    #[test]
+    fn test_symbol_names_stay_unique() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.add(SymbolKind::Function, "helper", 0, SYMBOL_BINDING_LOCAL), "helper");
+        assert_eq!(symbols.add(SymbolKind::Function, "helper", 1, SYMBOL_BINDING_LOCAL), "helper.1");
+        assert_eq!(symbols.add(SymbolKind::Global, "helper", 0, SYMBOL_BINDING_LOCAL), "helper.2");
+        assert_eq!(symbols.index_of("helper.1"), Some(1));
+        assert_eq!(symbols.index_of("missing"), None);
+    }
+
+    #[test]
+    fn test_reloc_section_names_symbols_by_index() {
+        let mut symbols = SymbolTable::new();
+        symbols.add(SymbolKind::Function, "puts", 0, SYMBOL_UNDEFINED);
+        symbols.add(SymbolKind::Global, "__stack_pointer", 0, SYMBOL_UNDEFINED);
+        let relocation = |kind, offset, symbol: &str| {
+            Relocation { kind, offset, symbol: symbol.to_string(), addend: 0 }
+        };
+        let relocations = [
+            relocation(RelocationKind::GlobalAccess, 7, "__stack_pointer"),
+            relocation(RelocationKind::FunctionCall, 20, "puts"),
+        ];
+        let mut output = Vec::new();
+        write_reloc_section(&mut output, "CODE", 4, &relocations, &symbols).unwrap();
+        let name = b"\x0areloc.CODE";
+        assert_eq!(&output[2..2 + name.len()], name);
+        assert_eq!(&output[2 + name.len()..], &[4, 2, 7, 7, 1, 0, 20, 0]);
+
+        let unknown = relocation(RelocationKind::FunctionCall, 0, "printf");
+        assert!(write_reloc_section(&mut Vec::new(), "CODE", 4, &[unknown], &symbols).is_err());
+    }
+}
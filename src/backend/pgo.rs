@@ -0,0 +1,256 @@
+//! Profiles for profile-guided optimization
+//!
+//! An instrumented run counts how often each function is entered and how
+//! often each caller calls each callee. `PgoProfile` keys those counts by
+//! function name rather than index, so a profile keeps applying to rebuilds
+//! that add or reorder functions. `StreamingLayoutOptimizer` orders
+//! functions by their counts, the indirect call optimizer caches the call
+//! sites the profile finds hot, and `split` moves the functions it never
+//! saw entered to the secondary module.
+//!
+//! Profiles come in two formats, told apart by their first character. JSON,
+//! as `to_json` writes it:
+//!
+//! ```json
+//! {"format": "wasmrust-pgo", "version": 1,
+//!  "functions": {"main": 1, "parse": 120},
+//!  "calls": [{"caller": "main", "callee": "parse", "count": 120}]}
+//! ```
+//!
+//! or raw counters, as an instrumented module's host dumps them: one count
+//! per line, followed by a function name or by a caller and a callee, all
+//! separated by tabs. Blank lines and lines starting with `#` are skipped.
+//! Counts listed twice add up, so the counters of several runs concatenate
+//! into one profile.
+
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// `format` field of JSON profiles
+pub const PROFILE_FORMAT: &str = "wasmrust-pgo";
+
+/// Version of the JSON layout
+pub const PROFILE_VERSION: u64 = 1;
+
+/// Share of all profiled calls above which a call is hot
+pub const HOT_CALL_SHARE: f64 = 0.01;
+
+/// Counts of an instrumented run, by function name
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PgoProfile {
+    /// Times each function was entered
+    pub functions: BTreeMap<String, u64>,
+    /// Times each caller called each callee, by `(caller, callee)`
+    pub calls: BTreeMap<(String, String), u64>,
+}
+
+/// Reasons a profile cannot be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgoError {
+    /// The file could not be read
+    Io(PathBuf, String),
+    /// The JSON does not parse or is not laid out as a profile
+    Json(String),
+    /// The JSON is of a version this compiler does not read
+    Version(u64),
+    /// A line of raw counters is not a count followed by one or two names
+    Counter { line: usize, text: String },
+}
+
+impl fmt::Display for PgoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgoError::Io(path, error) => write!(f, "cannot read profile {}: {}", path.display(), error),
+            PgoError::Json(reason) => write!(f, "malformed profile: {}", reason),
+            PgoError::Version(version) => {
+                write!(f, "profile version {} is not supported; expected {}", version, PROFILE_VERSION)
+            }
+            PgoError::Counter { line, text } => {
+                write!(f, "line {} of the profile is not `<count>\\t<function>[\\t<callee>]`: {}", line, text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PgoError {}
+
+impl PgoProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a profile in either format from `path`
+    pub fn load(path: &Path) -> Result<Self, PgoError> {
+        let text = std::fs::read_to_string(path).map_err(|error| PgoError::Io(path.to_path_buf(), error.to_string()))?;
+        Self::parse(&text)
+    }
+
+    /// Parses a profile, as JSON if it starts with `{` and as raw counters otherwise
+    pub fn parse(text: &str) -> Result<Self, PgoError> {
+        if text.trim_start().starts_with('{') {
+            let value: Value = serde_json::from_str(text).map_err(|error| PgoError::Json(error.to_string()))?;
+            Self::from_json(&value)
+        } else {
+            Self::from_counters(text)
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, PgoError> {
+        let malformed = |reason: &str| PgoError::Json(reason.to_string());
+        if value["format"].as_str() != Some(PROFILE_FORMAT) {
+            return Err(PgoError::Json(format!("`format` is not \"{}\"", PROFILE_FORMAT)));
+        }
+        match value["version"].as_u64() {
+            Some(PROFILE_VERSION) => {}
+            Some(version) => return Err(PgoError::Version(version)),
+            None => return Err(malformed("`version` is not a number")),
+        }
+        let mut profile = Self::new();
+        if let Some(functions) = value.get("functions") {
+            for (name, count) in functions.as_object().ok_or_else(|| malformed("`functions` is not an object"))? {
+                let count = count.as_u64().ok_or_else(|| malformed("a function count is not a number"))?;
+                profile.record_function(name, count);
+            }
+        }
+        if let Some(calls) = value.get("calls") {
+            for call in calls.as_array().ok_or_else(|| malformed("`calls` is not an array"))? {
+                match (call["caller"].as_str(), call["callee"].as_str(), call["count"].as_u64()) {
+                    (Some(caller), Some(callee), Some(count)) => profile.record_call(caller, callee, count),
+                    _ => return Err(malformed("a call lacks a `caller`, `callee`, or `count`")),
+                }
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn from_counters(text: &str) -> Result<Self, PgoError> {
+        let mut profile = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let count = fields[0].trim().parse::<u64>().ok();
+            match (count, &fields[1..]) {
+                (Some(count), [function]) if !function.is_empty() => profile.record_function(function, count),
+                (Some(count), [caller, callee]) if !caller.is_empty() && !callee.is_empty() => {
+                    profile.record_call(caller, callee, count)
+                }
+                _ => return Err(PgoError::Counter { line: index + 1, text: line.to_string() }),
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let calls: Vec<Value> = self.calls.iter()
+            .map(|((caller, callee), count)| json!({ "caller": caller, "callee": callee, "count": count }))
+            .collect();
+        json!({ "format": PROFILE_FORMAT, "version": PROFILE_VERSION, "functions": self.functions, "calls": calls })
+    }
+
+    /// Adds `count` entries of `function`
+    pub fn record_function(&mut self, function: &str, count: u64) {
+        let total = self.functions.entry(function.to_string()).or_default();
+        *total = total.saturating_add(count);
+    }
+
+    /// Adds `count` calls of `callee` from `caller`
+    pub fn record_call(&mut self, caller: &str, callee: &str, count: u64) {
+        let total = self.calls.entry((caller.to_string(), callee.to_string())).or_default();
+        *total = total.saturating_add(count);
+    }
+
+    /// Times `function` was entered; 0 for functions the profile never saw
+    pub fn function_count(&self, function: &str) -> u64 {
+        self.functions.get(function).copied().unwrap_or(0)
+    }
+
+    /// Times `caller` called `callee`
+    pub fn call_count(&self, caller: &str, callee: &str) -> u64 {
+        self.calls.get(&(caller.to_string(), callee.to_string())).copied().unwrap_or(0)
+    }
+
+    /// Entries of `function` relative to the most entered one, from 0 to 1
+    pub fn hotness(&self, function: &str) -> f64 {
+        match self.functions.values().max() {
+            Some(&max) if max > 0 => self.function_count(function) as f64 / max as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// Checks whether the call makes up at least `HOT_CALL_SHARE` of all profiled calls
+    pub fn is_hot_call(&self, caller: &str, callee: &str) -> bool {
+        let total: u64 = self.calls.values().sum();
+        let count = self.call_count(caller, callee);
+        count > 0 && count as f64 >= total as f64 * HOT_CALL_SHARE
+    }
+
+    /// Calls each function makes, or where the profile has no calls from
+    /// it, its entries, saturated to `u32`
+    pub fn call_frequencies(&self) -> HashMap<String, u32> {
+        let mut frequencies: HashMap<String, u64> = HashMap::new();
+        for ((caller, _), count) in &self.calls {
+            let total = frequencies.entry(caller.clone()).or_default();
+            *total = total.saturating_add(*count);
+        }
+        for (function, count) in &self.functions {
+            frequencies.entry(function.clone()).or_insert(*count);
+        }
+        frequencies.into_iter().map(|(function, count)| (function, count.min(u32::MAX as u64) as u32)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_and_json_read_alike() {
+        let counters = "# run 1\n1\tmain\n120\tparse\n120\tmain\tparse\n\n# run 2\n80\tparse\n";
+        let profile = PgoProfile::parse(counters).unwrap();
+        assert_eq!(profile.function_count("parse"), 200);
+        assert_eq!(profile.function_count("render"), 0);
+        assert_eq!(profile.call_count("main", "parse"), 120);
+
+        let json = profile.to_json().to_string();
+        assert_eq!(PgoProfile::parse(&json).unwrap(), profile);
+        assert!(matches!(PgoProfile::load(Path::new("/nonexistent.profile")), Err(PgoError::Io(..))));
+    }
+
+    #[test]
+    fn test_malformed_profiles_are_rejected() {
+        assert_eq!(
+            PgoProfile::parse("1\tmain\nmany\tparse\n").unwrap_err(),
+            PgoError::Counter { line: 2, text: "many\tparse".to_string() }
+        );
+        assert_eq!(PgoProfile::parse("1\tmain\tparse\textra").unwrap_err(), PgoError::Counter {
+            line: 1,
+            text: "1\tmain\tparse\textra".to_string()
+        });
+        assert_eq!(
+            PgoProfile::parse(r#"{"format": "wasmrust-pgo", "version": 2}"#).unwrap_err(),
+            PgoError::Version(2)
+        );
+        assert!(matches!(PgoProfile::parse(r#"{"version": 1}"#), Err(PgoError::Json(_))));
+        assert!(matches!(PgoProfile::parse("{"), Err(PgoError::Json(_))));
+    }
+
+    #[test]
+    fn test_hotness_and_hot_calls() {
+        let mut profile = PgoProfile::new();
+        profile.record_function("main", 1);
+        profile.record_function("parse", 200);
+        profile.record_call("main", "parse", 200);
+        profile.record_call("main", "report", 1);
+        assert_eq!(profile.hotness("parse"), 1.0);
+        assert_eq!(profile.hotness("main"), 0.005);
+        assert!(profile.is_hot_call("main", "parse"));
+        assert!(!profile.is_hot_call("main", "report"));
+        assert!(!profile.is_hot_call("parse", "main"));
+        assert_eq!(profile.call_frequencies(), HashMap::from([("main".to_string(), 201), ("parse".to_string(), 200)]));
+    }
+}
@@ -0,0 +1,214 @@
+//! Pre-initialization of modules at build time
+//!
+//! Like Wizer, `pre_initialize` instantiates a module in the interpreter,
+//! runs its constructors and start function, and bakes the memory and
+//! globals they leave into the module: memory as data segments, globals as
+//! their initializers. The module it returns starts in that state without
+//! running any initializer, so instantiating it costs no more than copying
+//! its data.
+//!
+//! Initializers run without a host, so one calling an import fails the
+//! build rather than baking in what the build machine's host returned.
+//! Modules with shared memory are not pre-initialized: every thread's
+//! instance would copy the snapshot over memory the others already use.
+
+use crate::backend::interpreter::{value_constant, Interpreter, InterpreterError};
+use crate::host::runtime::Value;
+use crate::host::HostFunctions;
+use wasm::wasmir::{DataSegment, MemoryType, WasmModule};
+
+/// Size of a linear memory page
+const PAGE_SIZE: usize = 65536;
+
+/// Zero bytes between two runs of data that still end the first segment
+///
+/// A segment costs a few bytes of header, so shorter gaps are cheaper kept.
+const SEGMENT_GAP: usize = 8;
+
+/// Linear memory and globals of an instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Pages of memory
+    pub pages: u32,
+    /// Bytes of memory that are not zero
+    pub segments: Vec<DataSegment>,
+    /// Global values; `None` for initializers the interpreter cannot represent
+    pub globals: Vec<Option<Value>>,
+}
+
+impl Snapshot {
+    /// Captures `memory`, keeping runs of bytes that are not zero
+    pub fn new(memory: &[u8], globals: &[Option<Value>]) -> Self {
+        Self { pages: (memory.len() / PAGE_SIZE) as u32, segments: segments(memory), globals: globals.to_vec() }
+    }
+
+    /// Returns a copy of `module` starting in the state of the snapshot,
+    /// with no initializers left to run
+    pub fn bake(&self, module: &WasmModule) -> WasmModule {
+        let mut baked = module.clone();
+        if let Some(memory) = module.memory {
+            baked.memory = Some(MemoryType { min_pages: self.pages.max(memory.min_pages), ..memory });
+        }
+        baked.data_segments = self.segments.clone();
+        for (global, value) in baked.globals.iter_mut().zip(&self.globals) {
+            if let Some(value) = value {
+                global.init = value_constant(*value);
+            }
+        }
+        baked.constructors.clear();
+        baked.start_function = None;
+        baked
+    }
+}
+
+/// Reasons a module cannot be pre-initialized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreinitError {
+    /// Threads share the module's memory
+    SharedMemory,
+    /// An initializer trapped, called an import, or used something the interpreter does not run
+    Initializer(InterpreterError),
+}
+
+impl std::fmt::Display for PreinitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreinitError::SharedMemory => write!(f, "cannot pre-initialize a module with shared memory"),
+            PreinitError::Initializer(error) => write!(f, "pre-initialization failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for PreinitError {}
+
+/// Checks whether the module has initializers to run
+pub fn needs_pre_initialization(module: &WasmModule) -> bool {
+    !module.constructors.is_empty() || module.start_function.is_some()
+}
+
+/// Runs the module's initializers and returns the module starting in the state they leave
+pub fn pre_initialize(module: &WasmModule) -> Result<WasmModule, PreinitError> {
+    if module.shared_memory {
+        return Err(PreinitError::SharedMemory);
+    }
+    let host = HostFunctions::new();
+    let mut interpreter = Interpreter::new(module, &host);
+    interpreter.initialize().map_err(PreinitError::Initializer)?;
+    Ok(interpreter.snapshot().bake(module))
+}
+
+/// Runs of bytes that are not zero, apart by more than `SEGMENT_GAP` zeros
+fn segments(memory: &[u8]) -> Vec<DataSegment> {
+    let mut segments: Vec<DataSegment> = Vec::new();
+    let mut address = 0;
+    while let Some(start) = memory[address..].iter().position(|&byte| byte != 0).map(|start| address + start) {
+        let end = memory[start..].iter().position(|&byte| byte == 0).map_or(memory.len(), |end| start + end);
+        match segments.last_mut() {
+            Some(last) if start - last.end() as usize <= SEGMENT_GAP => {
+                last.bytes.extend_from_slice(&memory[last.end() as usize..end]);
+            }
+            _ => segments.push(DataSegment { offset: start as u32, bytes: memory[start..end].to_vec() }),
+        }
+        address = end;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::{Constant, Instruction, Operand, Signature, Terminator, Type, WasmIR};
+
+    /// Stores 7 at 16 and sets a counter global to 3 in a constructor,
+    /// and exports `get`, returning their sum
+    fn module() -> WasmModule {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let counter = module.add_global("counter", Type::I32, true, Constant::I32(0));
+        let mut init = WasmIR::new("init".to_string(), Signature { params: vec![], returns: None });
+        init.add_basic_block(
+            vec![
+                Instruction::MemoryStore {
+                    address: Operand::Constant(Constant::I32(16)),
+                    value: Operand::Constant(Constant::I32(7)),
+                    ty: Type::I32,
+                    align: None,
+                    offset: 0,
+                },
+                Instruction::GlobalSet { index: counter, value: Operand::Constant(Constant::I32(3)) },
+            ],
+            Terminator::Return { value: None },
+        );
+        let init = module.add_function(init);
+        module.add_constructor(init, 0);
+        let mut get = WasmIR::new("get".to_string(), Signature { params: vec![], returns: Some(Type::I32) });
+        get.add_basic_block(
+            vec![
+                Instruction::MemoryLoad {
+                    address: Operand::Constant(Constant::I32(16)),
+                    ty: Type::I32,
+                    align: None,
+                    offset: 0,
+                },
+                Instruction::BinaryOp {
+                    op: wasm::wasmir::BinaryOp::Add,
+                    left: Operand::StackValue(0),
+                    right: Operand::Global(counter),
+                },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let get = module.add_function(get);
+        module.export_function("get", get);
+        module
+    }
+
+    #[test]
+    fn test_bakes_what_initializers_leave() {
+        let module = module();
+        assert!(needs_pre_initialization(&module));
+        let baked = pre_initialize(&module).unwrap();
+        assert!(!needs_pre_initialization(&baked));
+        assert!(baked.validate().is_ok());
+        assert_eq!(baked.data_segments, vec![DataSegment { offset: 16, bytes: vec![7] }]);
+        assert_eq!(baked.globals[0].init, Constant::I32(3));
+
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&baked, &host);
+        assert_eq!(interpreter.invoke("get", &[]), Ok(Some(Value::I32(10))));
+
+        let mut shared = module.clone();
+        shared.shared_memory = true;
+        assert_eq!(pre_initialize(&shared).unwrap_err(), PreinitError::SharedMemory);
+    }
+
+    #[test]
+    fn test_snapshots_restore_instances() {
+        let module = module();
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+        interpreter.initialize().unwrap();
+        let snapshot = interpreter.snapshot();
+        assert_eq!(snapshot.pages, 1);
+
+        let mut restored = Interpreter::new(&module, &host);
+        restored.restore(&snapshot);
+        assert_eq!(restored.memory(), interpreter.memory());
+        assert_eq!(restored.invoke("get", &[]), Ok(Some(Value::I32(10))));
+    }
+
+    #[test]
+    fn test_segments_merge_short_gaps() {
+        let mut memory = vec![0; 64];
+        memory[2] = 1;
+        memory[4..6].copy_from_slice(&[2, 3]);
+        memory[40] = 4;
+        memory[63] = 5;
+        assert_eq!(segments(&memory), vec![
+            DataSegment { offset: 2, bytes: vec![1, 0, 2, 3] },
+            DataSegment { offset: 40, bytes: vec![4] },
+            DataSegment { offset: 63, bytes: vec![5] },
+        ]);
+        assert!(segments(&[0; 16]).is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Backends the factory chooses among, built in or registered at runtime
+//!
+//! Each `BackendEntry` names a backend, declares its capabilities, and
+//! ranks it by a priority that may differ per profile. Third parties add
+//! backends, such as a singlepass or a GC-aware one, with
+//! `BackendRegistry::register`, and `BackendFactory::create_backend` then
+//! weighs them with the built-in ones. A backend is only constructed once
+//! it is chosen.
+
+use super::cranelift::WasmRustCraneliftBackend;
+use super::{Backend, BackendCapabilities, BackendError, BackendSelection, BuildProfile, SelectionReport};
+use std::fmt;
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
+
+/// Constructs a backend for a target architecture
+pub type BackendConstructor = Arc<dyn Fn(&str) -> Result<Box<dyn Backend>, BackendError> + Send + Sync>;
+
+/// Priority of Cranelift, the default backend of every profile
+pub const CRANELIFT_PRIORITY: i32 = 100;
+
+/// Backends registered at runtime, after the built-in ones
+static REGISTERED: RwLock<Vec<BackendEntry>> = RwLock::new(Vec::new());
+
+/// Backend the factory may choose
+#[derive(Clone)]
+pub struct BackendEntry {
+    pub name: &'static str,
+    pub capabilities: BackendCapabilities,
+    /// Higher is tried first, registration order breaking ties
+    pub priority: i32,
+    profile_priorities: Vec<(BuildProfile, i32)>,
+    /// Emits no code, so it is only chosen by name or when no backend that does is registered
+    pub fallback: bool,
+    constructor: BackendConstructor,
+}
+
+impl BackendEntry {
+    pub fn new<F>(name: &'static str, capabilities: BackendCapabilities, constructor: F) -> Self
+    where
+        F: Fn(&str) -> Result<Box<dyn Backend>, BackendError> + Send + Sync + 'static,
+    {
+        Self {
+            name,
+            capabilities,
+            priority: 0,
+            profile_priorities: Vec::new(),
+            fallback: false,
+            constructor: Arc::new(constructor),
+        }
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Overrides the priority for one profile
+    pub fn profile_priority(mut self, profile: BuildProfile, priority: i32) -> Self {
+        self.profile_priorities.retain(|&(other, _)| other != profile);
+        self.profile_priorities.push((profile, priority));
+        self
+    }
+
+    pub fn fallback(mut self) -> Self {
+        self.fallback = true;
+        self
+    }
+
+    pub fn priority_for(&self, profile: BuildProfile) -> i32 {
+        self.profile_priorities.iter()
+            .find(|&&(other, _)| other == profile)
+            .map_or(self.priority, |&(_, priority)| priority)
+    }
+
+    pub fn construct(&self, target: &str) -> Result<Box<dyn Backend>, BackendError> {
+        (self.constructor)(target)
+    }
+}
+
+impl fmt::Debug for BackendEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackendEntry")
+            .field("name", &self.name)
+            .field("capabilities", &self.capabilities)
+            .field("priority", &self.priority)
+            .field("profile_priorities", &self.profile_priorities)
+            .field("fallback", &self.fallback)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Backends by name
+#[derive(Debug, Clone, Default)]
+pub struct BackendRegistry {
+    entries: Vec<BackendEntry>,
+}
+
+impl BackendRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cranelift, LLVM when built in, and the interpreter as the fallback
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.entries.push(
+            BackendEntry::new("cranelift", WasmRustCraneliftBackend::CAPABILITIES, |arch| {
+                // It emits wasm32 modules, whatever the host
+                if arch != "wasm32" {
+                    return Err(BackendError::UnsupportedTarget(arch.to_string()));
+                }
+                Ok(Box::new(WasmRustCraneliftBackend::new()?))
+            })
+            .priority(CRANELIFT_PRIORITY),
+        );
+        #[cfg(feature = "llvm-backend")]
+        registry.entries.push(
+            BackendEntry::new("llvm", BackendCapabilities {
+                thin_monomorphization: true,
+                streaming_layout: true,
+                pgo_support: true,
+                component_model: true,
+                wasm_optimizations: true,
+                linear_types: true,
+            }, |arch| {
+                let target = rustc_target::spec::Target { arch: arch.to_string(), ..Default::default() };
+                Ok(Box::new(crate::backend::llvm::WasmRustLLVMBackend::new(target)?))
+            })
+            .priority(CRANELIFT_PRIORITY / 2)
+            // LLVM optimizes best
+            .profile_priority(BuildProfile::Release, CRANELIFT_PRIORITY * 2),
+        );
+        let interpreter = super::interpreter::InterpreterBackend::new();
+        registry.entries.push(
+            BackendEntry::new("interpreter", interpreter.capabilities(), |_| {
+                Ok(Box::new(super::interpreter::InterpreterBackend::new()))
+            })
+            .fallback(),
+        );
+        registry
+    }
+
+    /// The built-in backends and those registered so far
+    pub fn global() -> Self {
+        let mut registry = Self::builtin();
+        let registered = REGISTERED.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        registry.entries.extend(registered.iter().cloned());
+        registry
+    }
+
+    /// Makes a backend available to `BackendFactory`
+    pub fn register(entry: BackendEntry) -> Result<(), BackendError> {
+        let mut registered = registered();
+        if Self::builtin().get(entry.name).is_some() || registered.iter().any(|other| other.name == entry.name) {
+            return Err(BackendError::Unsupported(format!("backend `{}` is already registered", entry.name)));
+        }
+        registered.push(entry);
+        Ok(())
+    }
+
+    /// Removes a registered backend, returning whether there was one
+    pub fn unregister(name: &str) -> bool {
+        let mut registered = registered();
+        let count = registered.len();
+        registered.retain(|entry| entry.name != name);
+        registered.len() != count
+    }
+
+    /// Adds a backend to this registry only
+    pub fn add(&mut self, entry: BackendEntry) -> Result<(), BackendError> {
+        if self.get(entry.name).is_some() {
+            return Err(BackendError::Unsupported(format!("backend `{}` is already registered", entry.name)));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BackendEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Backends that emit code, in registration order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().filter(|entry| !entry.fallback).map(|entry| entry.name).collect()
+    }
+
+    /// Backends to try for a profile, most preferred first
+    pub fn candidates(&self, profile: BuildProfile) -> Vec<&BackendEntry> {
+        let mut candidates: Vec<_> = self.entries.iter().collect();
+        candidates.sort_by_key(|entry| (entry.fallback, std::cmp::Reverse(entry.priority_for(profile))));
+        candidates
+    }
+
+    /// Constructs the backend best meeting `required`
+    ///
+    /// The most preferred backend declaring every required capability
+    /// wins; if none does, the one lacking the fewest does, and the report
+    /// names what it lacks. A backend that cannot be constructed for the
+    /// target is passed over for the next; if none can be, the error of the
+    /// first one tried is returned.
+    ///
+    /// Fallback backends are left out unless every backend is one, so a
+    /// target no code-emitting backend supports fails rather than giving an
+    /// empty module. Construct a fallback by name with `get` to use it.
+    pub fn select(
+        &self,
+        target: &str,
+        profile: BuildProfile,
+        required: &BackendCapabilities,
+    ) -> Result<BackendSelection, BackendError> {
+        let mut candidates = self.candidates(profile);
+        if candidates.iter().any(|entry| !entry.fallback) {
+            candidates.retain(|entry| !entry.fallback);
+        }
+        let missing: Vec<_> = candidates.iter().map(|entry| entry.capabilities.missing(required)).collect();
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        order.sort_by_key(|&index| missing[index].len());
+
+        let mut failed: Vec<(usize, BackendError)> = Vec::new();
+        for index in order {
+            let backend = match candidates[index].construct(target) {
+                Ok(backend) => backend,
+                Err(err) => {
+                    failed.push((index, err));
+                    continue;
+                }
+            };
+            let passed_over = (0..candidates.len())
+                .filter_map(|other| match failed.iter().find(|(failed, _)| *failed == other) {
+                    Some((_, err)) => Some((candidates[other].name, err.to_string())),
+                    None if other < index && !missing[other].is_empty() => {
+                        Some((candidates[other].name, format!("lacks {}", missing[other].join(", "))))
+                    }
+                    None => None,
+                })
+                .collect();
+            let report = SelectionReport {
+                chosen: candidates[index].name,
+                missing: missing[index].clone(),
+                passed_over,
+            };
+            return Ok(BackendSelection { backend, report });
+        }
+        match failed.into_iter().next() {
+            Some((_, err)) => Err(err),
+            None => Err(BackendError::UnsupportedTarget(format!("no backend is registered for {}", target))),
+        }
+    }
+}
+
+fn registered() -> RwLockWriteGuard<'static, Vec<BackendEntry>> {
+    // A panic while registering leaves the entries intact
+    REGISTERED.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::interpreter::InterpreterBackend;
+
+    fn entry(name: &'static str, capabilities: BackendCapabilities) -> BackendEntry {
+        BackendEntry::new(name, capabilities, |_| Ok(Box::new(InterpreterBackend::new())))
+    }
+
+    #[test]
+    fn test_selects_by_priority_and_capabilities() {
+        let gc = BackendCapabilities { linear_types: true, component_model: true, ..BackendCapabilities::default() };
+        let mut registry = BackendRegistry::new();
+        registry.add(entry("interpreter", BackendCapabilities::default()).fallback()).unwrap();
+        registry.add(entry("singlepass", BackendCapabilities::default()).priority(10)).unwrap();
+        registry.add(entry("gc", gc.clone()).priority(5).profile_priority(BuildProfile::Release, 20)).unwrap();
+        registry.add(BackendEntry::new("broken", gc.clone(), |arch| {
+            Err(BackendError::UnsupportedTarget(arch.to_string()))
+        }).priority(30)).unwrap();
+        assert!(registry.add(entry("gc", gc)).is_err());
+
+        let names = |profile| registry.candidates(profile).iter().map(|entry| entry.name).collect::<Vec<_>>();
+        assert_eq!(names(BuildProfile::Development), ["broken", "singlepass", "gc", "interpreter"]);
+        assert_eq!(names(BuildProfile::Release), ["broken", "gc", "singlepass", "interpreter"]);
+        assert_eq!(registry.names(), ["singlepass", "gc", "broken"]);
+
+        let nothing = BackendCapabilities::default();
+        let report = registry.select("wasm32", BuildProfile::Development, &nothing).unwrap().report;
+        assert_eq!(report.to_string(), "using singlepass; passed over broken: Unsupported target: wasm32");
+
+        let component = BackendCapabilities { component_model: true, pgo_support: true, ..nothing };
+        let report = registry.select("wasm32", BuildProfile::Development, &component).unwrap().report;
+        assert_eq!(
+            report.to_string(),
+            "using gc, which lacks pgo; passed over broken: Unsupported target: wasm32; \
+             passed over singlepass: lacks pgo, component-model"
+        );
+    }
+
+    #[test]
+    fn test_fallbacks_are_not_chosen_over_constructor_errors() {
+        let mut registry = BackendRegistry::new();
+        registry.add(entry("interpreter", BackendCapabilities::default()).fallback()).unwrap();
+        let selection = registry.select("wasm32", BuildProfile::Development, &BackendCapabilities::default());
+        assert_eq!(selection.unwrap().report.chosen, "interpreter");
+
+        registry.add(BackendEntry::new("broken", BackendCapabilities::default(), |arch| {
+            Err(BackendError::UnsupportedTarget(arch.to_string()))
+        })).unwrap();
+        let selection = registry.select("wasm32", BuildProfile::Development, &BackendCapabilities::default());
+        assert_eq!(selection.err(), Some(BackendError::UnsupportedTarget("wasm32".to_string())));
+        assert!(registry.get("interpreter").unwrap().construct("wasm32").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_targets_other_than_wasm32() {
+        let registry = BackendRegistry::builtin();
+        let nothing = BackendCapabilities::default();
+        assert_eq!(registry.select("wasm32", BuildProfile::Development, &nothing).unwrap().report.chosen, "cranelift");
+        for target in ["wasm64", "x86_64"] {
+            let selection = registry.select(target, BuildProfile::Development, &nothing);
+            assert_eq!(selection.err(), Some(BackendError::UnsupportedTarget(target.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_registers_globally() {
+        // Below every built-in, so other tests keep their backends
+        let plugin = entry("test-registers-globally", BackendCapabilities::default()).priority(i32::MIN);
+        BackendRegistry::register(plugin.clone()).unwrap();
+        assert!(BackendRegistry::register(plugin).is_err());
+        assert!(BackendRegistry::register(entry("interpreter", BackendCapabilities::default())).is_err());
+        let global = BackendRegistry::global();
+        let candidates = global.candidates(BuildProfile::Development);
+        assert_eq!(candidates[candidates.len() - 2].name, "test-registers-globally");
+        assert!(global.names().contains(&"test-registers-globally"));
+
+        assert!(BackendRegistry::unregister("test-registers-globally"));
+        assert!(BackendRegistry::global().get("test-registers-globally").is_none());
+        assert!(!BackendRegistry::unregister("test-registers-globally"));
+    }
+}
@@ -0,0 +1,522 @@
+//! Memory sanitizer, a wasm analogue of AddressSanitizer
+//!
+//! `SanitizerPass` instruments a module so that every `MemoryLoad` and
+//! `MemoryStore` is checked before it runs and every `MemoryAlloc` and
+//! `MemoryFree` is recorded. The checks are calls to functions imported
+//! from `SANITIZER_IMPORT_MODULE`, which `SanitizerReport::install` adds to
+//! the host functions of a run. They keep shadow state outside linear
+//! memory: blocks freed stay poisoned until allocated again, so accesses
+//! through dangling pointers trap, as do accesses past the end of memory or
+//! of their block and second frees of a block. The trap message names
+//! the kind of error and the instruction, with the source location of the
+//! local holding the address when the frontend annotated it.
+//!
+//! Allocator functions are added after the pass, so their own accesses to
+//! the heap are not checked.
+
+use crate::host::runtime::{Value, ValueType};
+use crate::host::HostFunctions;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use wasm::wasmir::{Constant, Instruction, Operand, Signature, SourceLocation, Type, WasmIR, WasmModule};
+
+/// Import module of the sanitizer's checks
+pub const SANITIZER_IMPORT_MODULE: &str = "wasmrust_sanitizer";
+
+/// `access(pages, address, site)`, before each load and store
+pub const ACCESS_IMPORT: &str = "access";
+
+/// `alloc(address, size, site)`, after each allocation
+pub const ALLOC_IMPORT: &str = "alloc";
+
+/// `free(address, site)`, before each free
+pub const FREE_IMPORT: &str = "free";
+
+/// Size of a linear memory page
+const PAGE_SIZE: u64 = 65536;
+
+/// What an instrumented instruction does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Load { bytes: u32, offset: u32 },
+    Store { bytes: u32, offset: u32 },
+    Alloc,
+    Free,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Load { bytes, .. } => write!(f, "load of {} bytes", bytes),
+            Operation::Store { bytes, .. } => write!(f, "store of {} bytes", bytes),
+            Operation::Alloc => write!(f, "allocation"),
+            Operation::Free => write!(f, "free"),
+        }
+    }
+}
+
+/// Instrumented instruction, which trap messages name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Site {
+    pub function: String,
+    pub block: usize,
+    /// Index of the instruction in its block, before instrumenting
+    pub instruction: usize,
+    pub operation: Operation,
+    /// Where the local holding the address was declared, if annotated
+    pub location: Option<SourceLocation>,
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Site { function, block, instruction, .. } = self;
+        write!(f, "{} in `{}` (block {}, instruction {})", self.operation, function, block, instruction)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}:{}:{}", location.file, location.line, location.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of running the sanitizer pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SanitizerReport {
+    /// Instrumented instructions, indexed by the site the checks pass
+    pub sites: Vec<Site>,
+}
+
+impl SanitizerReport {
+    /// Adds the functions checking the instrumented module to `host`
+    ///
+    /// The checks share one shadow state across the instances linked to
+    /// the functions, so a fresh instance reallocating a block unpoisons it.
+    pub fn install(&self, host: HostFunctions) -> HostFunctions {
+        let shadow = Arc::new(Mutex::new(Shadow { sites: self.sites.clone(), ..Shadow::default() }));
+        let (access, alloc, free) = (shadow.clone(), shadow.clone(), shadow);
+        let i32s = |count| vec![ValueType::I32; count];
+        host.func(SANITIZER_IMPORT_MODULE, ACCESS_IMPORT, i32s(3), None, move |args| {
+            let [pages, address, site] = integers(args)?;
+            lock(&access).access(pages, address, site)?;
+            Ok(None)
+        })
+        .func(SANITIZER_IMPORT_MODULE, ALLOC_IMPORT, i32s(3), None, move |args| {
+            let [address, size, site] = integers(args)?;
+            lock(&alloc).alloc(address, size, site);
+            Ok(None)
+        })
+        .func(SANITIZER_IMPORT_MODULE, FREE_IMPORT, i32s(2), None, move |args| {
+            let [address, site] = integers(args)?;
+            lock(&free).free(address, site)?;
+            Ok(None)
+        })
+    }
+}
+
+/// Instruments loads, stores, allocations, and frees
+#[derive(Debug, Default)]
+pub struct SanitizerPass {
+    report: SanitizerReport,
+}
+
+/// Indices of the sanitizer's imports
+struct Imports {
+    access: u32,
+    alloc: u32,
+    free: u32,
+}
+
+/// Locals holding operands across the inserted calls
+#[derive(Default)]
+struct Temporaries {
+    address: Option<u32>,
+    size: Option<u32>,
+    values: Vec<(Type, u32)>,
+}
+
+impl SanitizerPass {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the pass already ran over a module
+    pub fn is_instrumented(module: &WasmModule) -> bool {
+        module.find_import(SANITIZER_IMPORT_MODULE, ACCESS_IMPORT).is_some()
+    }
+
+    /// Runs the pass over a module, leaving one already instrumented alone
+    pub fn run(mut self, module: &mut WasmModule) -> SanitizerReport {
+        if Self::is_instrumented(module) {
+            return self.report;
+        }
+        let signature = |params| Signature { params: vec![Type::I32; params], returns: None };
+        let imports = Imports {
+            access: module.add_import(SANITIZER_IMPORT_MODULE, ACCESS_IMPORT, signature(3)),
+            alloc: module.add_import(SANITIZER_IMPORT_MODULE, ALLOC_IMPORT, signature(3)),
+            free: module.add_import(SANITIZER_IMPORT_MODULE, FREE_IMPORT, signature(2)),
+        };
+        for function in &mut module.functions {
+            self.instrument(function, &imports);
+        }
+        self.report
+    }
+
+    fn instrument(&mut self, function: &mut WasmIR, imports: &Imports) {
+        let instrumented = function.all_instructions().any(|instruction| matches!(
+            instruction,
+            Instruction::MemoryLoad { .. }
+                | Instruction::MemoryStore { .. }
+                | Instruction::MemoryAlloc { .. }
+                | Instruction::MemoryFree { .. }
+        ));
+        if !instrumented {
+            return;
+        }
+
+        // Temporaries are allocated after the parameters
+        while function.locals.len() < function.signature.params.len() {
+            let ty = function.signature.params[function.locals.len()].clone();
+            function.locals.push(ty);
+        }
+
+        let mut temporaries = Temporaries::default();
+        for block in 0..function.basic_blocks.len() {
+            let instructions = std::mem::take(&mut function.basic_blocks[block].instructions);
+            let mut rewritten = Vec::with_capacity(instructions.len());
+            for (index, instruction) in instructions.into_iter().enumerate() {
+                let mut site = |operation, address: Option<&Operand>| {
+                    self.report.sites.push(Site {
+                        function: function.name.clone(),
+                        block,
+                        instruction: index,
+                        operation,
+                        location: address.and_then(|address| location(function, address)),
+                    });
+                    int(self.report.sites.len() as i32 - 1)
+                };
+                match instruction {
+                    Instruction::MemoryLoad { address, ty, align, offset } => {
+                        let site = site(Operation::Load { bytes: bytes(&ty), offset }, Some(&address));
+                        let address = spill(function, &mut rewritten, address, |f| temporaries.address(f));
+                        check_access(&mut rewritten, imports, &address, site);
+                        rewritten.push(Instruction::MemoryLoad { address, ty, align, offset });
+                    }
+                    Instruction::MemoryStore { address, value, ty, align, offset } => {
+                        let site = site(Operation::Store { bytes: bytes(&ty), offset }, Some(&address));
+                        // The value is above the address on the stack
+                        let value = spill(function, &mut rewritten, value, |f| temporaries.value(f, &ty));
+                        let address = spill(function, &mut rewritten, address, |f| temporaries.address(f));
+                        check_access(&mut rewritten, imports, &address, site);
+                        rewritten.push(Instruction::MemoryStore { address, value, ty, align, offset });
+                    }
+                    Instruction::MemoryAlloc { size, align } => {
+                        let site = site(Operation::Alloc, None);
+                        let size = spill(function, &mut rewritten, size, |f| temporaries.size(f));
+                        let address = temporaries.address(function);
+                        rewritten.push(Instruction::MemoryAlloc { size: size.clone(), align });
+                        rewritten.push(Instruction::LocalSet { index: address, value: Operand::StackValue(0) });
+                        rewritten.push(Instruction::CallImport {
+                            import: imports.alloc,
+                            args: vec![Operand::Local(address), size, site],
+                        });
+                        rewritten.push(Instruction::LocalGet { index: address });
+                    }
+                    Instruction::MemoryFree { address } => {
+                        let site = site(Operation::Free, Some(&address));
+                        let address = spill(function, &mut rewritten, address, |f| temporaries.address(f));
+                        let args = vec![address.clone(), site];
+                        rewritten.push(Instruction::CallImport { import: imports.free, args });
+                        rewritten.push(Instruction::MemoryFree { address });
+                    }
+                    other => rewritten.push(other),
+                }
+            }
+            function.basic_blocks[block].instructions = rewritten;
+        }
+    }
+}
+
+impl Temporaries {
+    fn address(&mut self, function: &mut WasmIR) -> u32 {
+        *self.address.get_or_insert_with(|| function.add_local(Type::I32))
+    }
+
+    fn size(&mut self, function: &mut WasmIR) -> u32 {
+        *self.size.get_or_insert_with(|| function.add_local(Type::I32))
+    }
+
+    fn value(&mut self, function: &mut WasmIR, ty: &Type) -> u32 {
+        if let Some(&(_, local)) = self.values.iter().find(|(other, _)| other == ty) {
+            return local;
+        }
+        let local = function.add_local(ty.clone());
+        self.values.push((ty.clone(), local));
+        local
+    }
+}
+
+/// Moves an operand taken from the stack into a local, which the inserted
+/// call and the instruction can both read
+fn spill(
+    function: &mut WasmIR,
+    rewritten: &mut Vec<Instruction>,
+    operand: Operand,
+    temporary: impl FnOnce(&mut WasmIR) -> u32,
+) -> Operand {
+    if !is_stack_value(&operand) {
+        return operand;
+    }
+    let index = temporary(function);
+    rewritten.push(Instruction::LocalSet { index, value: operand });
+    Operand::Local(index)
+}
+
+fn check_access(rewritten: &mut Vec<Instruction>, imports: &Imports, address: &Operand, site: Operand) {
+    rewritten.push(Instruction::MemorySize);
+    rewritten.push(Instruction::CallImport {
+        import: imports.access,
+        args: vec![Operand::StackValue(0), address.clone(), site],
+    });
+}
+
+fn is_stack_value(operand: &Operand) -> bool {
+    match operand {
+        Operand::StackValue(_) => true,
+        Operand::MemoryAddress(inner) => is_stack_value(inner),
+        _ => false,
+    }
+}
+
+/// Where the local an address is read from was declared
+fn location(function: &WasmIR, address: &Operand) -> Option<SourceLocation> {
+    match address {
+        Operand::Local(index) => function.ownership_annotations.iter()
+            .find(|annotation| annotation.variable == *index)
+            .map(|annotation| annotation.source_location.clone()),
+        Operand::MemoryAddress(inner) => location(function, inner),
+        _ => None,
+    }
+}
+
+/// Bytes a load or store of a type accesses
+fn bytes(ty: &Type) -> u32 {
+    match ty {
+        Type::I64 | Type::F64 => 8,
+        Type::I128 => 16,
+        _ => 4,
+    }
+}
+
+fn int(value: i32) -> Operand {
+    Operand::Constant(Constant::I32(value))
+}
+
+fn integers<const N: usize>(args: &[Value]) -> Result<[u32; N], String> {
+    let mut integers = [0; N];
+    for (integer, arg) in integers.iter_mut().zip(args) {
+        match arg {
+            Value::I32(value) => *integer = *value as u32,
+            other => return Err(format!("sanitizer check passed {}, expected i32", other.ty().name())),
+        }
+    }
+    Ok(integers)
+}
+
+fn lock(shadow: &Mutex<Shadow>) -> std::sync::MutexGuard<'_, Shadow> {
+    // A trapping check leaves the state consistent
+    shadow.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Blocks of the heap as the instrumented module allocated and freed them
+#[derive(Debug, Default)]
+struct Shadow {
+    sites: Vec<Site>,
+    /// Size of each live block, by address, with the site allocating it
+    live: BTreeMap<u64, (u64, u32)>,
+    /// Size of each poisoned block, by address, with the site freeing it
+    freed: BTreeMap<u64, (u64, u32)>,
+}
+
+impl Shadow {
+    fn access(&self, pages: u32, address: u32, site: u32) -> Result<(), String> {
+        let at = self.describe(site);
+        let (bytes, offset) = match self.sites.get(site as usize).map(|site| site.operation) {
+            Some(Operation::Load { bytes, offset } | Operation::Store { bytes, offset }) => (bytes, offset),
+            _ => return Err(format!("sanitizer: access checked for {}", at)),
+        };
+        let start = u64::from(address) + u64::from(offset);
+        let end = start + u64::from(bytes);
+        if end > u64::from(pages) * PAGE_SIZE {
+            return Err(format!("out-of-bounds at {:#x}: {}; memory has {} pages", start, at, pages));
+        }
+        if let Some((&block, &(size, freed_by))) = self.freed.range(..end).next_back() {
+            if block + size > start {
+                return Err(format!(
+                    "heap-use-after-free at {:#x}: {}; the {}-byte block at {:#x} was freed by {}",
+                    start, at, size, block, self.describe(freed_by)
+                ));
+            }
+        }
+        if let Some((&block, &(size, allocated_by))) = self.live.range(..=start).next_back() {
+            if start < block + size && end > block + size {
+                return Err(format!(
+                    "heap-buffer-overflow at {:#x}: {}; the {}-byte block at {:#x} is from {}",
+                    start, at, size, block, self.describe(allocated_by)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn alloc(&mut self, address: u32, size: u32, site: u32) {
+        // A failed allocation returns null and allocates nothing
+        if address == 0 {
+            return;
+        }
+        let (address, size) = (u64::from(address), u64::from(size));
+        let reused: Vec<u64> = self.freed.range(..address + size.max(1))
+            .filter(|&(&block, &(freed, _))| block + freed > address)
+            .map(|(&block, _)| block)
+            .collect();
+        for block in reused {
+            self.freed.remove(&block);
+        }
+        self.live.insert(address, (size, site));
+    }
+
+    /// Poisons a live block
+    ///
+    /// Blocks the host allocated through the exported allocator are not
+    /// tracked, so freeing one is allowed.
+    fn free(&mut self, address: u32, site: u32) -> Result<(), String> {
+        let address = u64::from(address);
+        if let Some(&(_, freed_by)) = self.freed.get(&address) {
+            return Err(format!(
+                "double-free at {:#x}: {}; the block was already freed by {}",
+                address, self.describe(site), self.describe(freed_by)
+            ));
+        }
+        if let Some((size, _)) = self.live.remove(&address) {
+            self.freed.insert(address, (size, site));
+        }
+        Ok(())
+    }
+
+    fn describe(&self, site: u32) -> String {
+        self.sites.get(site as usize).map_or_else(|| format!("unknown site {}", site), Site::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::interpreter::{Interpreter, InterpreterError};
+    use wasm::memory::allocator::{lower_allocations, AllocatorStrategy};
+    use wasm::wasmir::{MemoryType, OwnershipAnnotation, OwnershipState, Terminator};
+
+    fn load(address: Operand, offset: u32) -> Instruction {
+        Instruction::MemoryLoad { address, ty: Type::I32, align: None, offset }
+    }
+
+    fn store(address: Operand, value: Operand, offset: u32) -> Instruction {
+        Instruction::MemoryStore { address, value, ty: Type::I32, align: None, offset }
+    }
+
+    /// Exports a function of one `i32` taking an 8-byte block into local 1
+    fn module(name: &str, body: Vec<Instruction>) -> WasmModule {
+        let signature = Signature { params: vec![Type::I32], returns: Some(Type::I32) };
+        let mut function = WasmIR::new(name.to_string(), signature);
+        function.locals = vec![Type::I32; 2];
+        function.ownership_annotations.push(OwnershipAnnotation {
+            variable: 1,
+            state: OwnershipState::Owned,
+            source_location: SourceLocation { file: "src/lib.rs".to_string(), line: 3, column: 9 },
+        });
+        let mut instructions = vec![
+            Instruction::MemoryAlloc { size: int(8), align: Some(4) },
+            Instruction::LocalSet { index: 1, value: Operand::StackValue(0) },
+        ];
+        instructions.extend(body);
+        function.add_basic_block(instructions, Terminator::Return { value: Some(Operand::StackValue(0)) });
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let index = module.add_function(function);
+        module.export_function(name, index);
+        module
+    }
+
+    fn run(module: &WasmModule, report: &SanitizerReport, arg: i32) -> Result<Option<Value>, InterpreterError> {
+        let module = lower_allocations(module, AllocatorStrategy::Bundled).unwrap();
+        let host = report.install(HostFunctions::new());
+        let name = module.functions[0].name.clone();
+        Interpreter::new(&module, &host).invoke(&name, &[Value::I32(arg)])
+    }
+
+    fn sanitize(mut module: WasmModule, arg: i32) -> Result<Option<Value>, InterpreterError> {
+        let report = SanitizerPass::new().run(&mut module);
+        run(&module, &report, arg)
+    }
+
+    fn trap(result: Result<Option<Value>, InterpreterError>) -> String {
+        match result {
+            Err(InterpreterError::Trap(message)) => message,
+            other => panic!("expected a trap, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_traps_use_after_free_with_location() {
+        let block = Operand::Local(1);
+        let valid = vec![store(block.clone(), Operand::Local(0), 4), load(block.clone(), 4)];
+        assert_eq!(sanitize(module("valid", valid), 7).unwrap(), Some(Value::I32(7)));
+
+        let dangling = vec![
+            store(block.clone(), Operand::Local(0), 0),
+            Instruction::MemoryFree { address: block.clone() },
+            load(block, 0),
+        ];
+        let message = trap(sanitize(module("dangling", dangling), 7));
+        assert!(message.starts_with("heap-use-after-free at 0x"), "{}", message);
+        assert!(message.contains(
+            ": load of 4 bytes in `dangling` (block 0, instruction 4) at src/lib.rs:3:9; the 8-byte block at 0x"
+        ), "{}", message);
+        assert!(message.ends_with("was freed by free in `dangling` (block 0, instruction 3) at src/lib.rs:3:9"));
+    }
+
+    #[test]
+    fn test_traps_overflow_out_of_bounds_and_double_free() {
+        let block = Operand::Local(1);
+        let overflow = trap(sanitize(module("overflow", vec![store(block.clone(), Operand::Local(0), 6)]), 0));
+        assert!(overflow.starts_with("heap-buffer-overflow at 0x"), "{}", overflow);
+        assert!(overflow.ends_with("is from allocation in `overflow` (block 0, instruction 0)"), "{}", overflow);
+
+        let wild = trap(sanitize(module("wild", vec![load(int(-4), 0)]), 0));
+        // The heap took a second page
+        assert_eq!(
+            wild,
+            "out-of-bounds at 0xfffffffc: load of 4 bytes in `wild` (block 0, instruction 2); memory has 2 pages"
+        );
+
+        let free = Instruction::MemoryFree { address: block };
+        let double = trap(sanitize(module("double", vec![free.clone(), free, load(int(0), 0)]), 0));
+        assert!(double.starts_with("double-free at 0x"), "{}", double);
+        assert!(double.contains(": free in `double` (block 0, instruction 3) at src/lib.rs:3:9; the block was"));
+    }
+
+    #[test]
+    fn test_spills_stack_operands() {
+        // The address and the stored value both come from the stack
+        let body = vec![
+            Instruction::LocalGet { index: 1 },
+            Instruction::LocalGet { index: 0 },
+            store(Operand::StackValue(0), Operand::StackValue(1), 0),
+            load(Operand::Local(1), 0),
+        ];
+        let mut module = module("stacked", body);
+        let report = SanitizerPass::new().run(&mut module);
+        assert_eq!(report.sites.len(), 3);
+        assert!(SanitizerPass::new().run(&mut module).sites.is_empty());
+        assert_eq!(module.functions[0].locals.len(), 4);
+        assert_eq!(run(&module, &report, 9).unwrap(), Some(Value::I32(9)));
+    }
+}
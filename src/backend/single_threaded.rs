@@ -0,0 +1,550 @@
+//! Single-threaded atomics elision for WasmRust
+//!
+//! Thread-safe libraries bring atomic read-modify-write operations and
+//! locks into modules that never run on more than one thread. When
+//! no function in a module requires `Capability::Threading`, this pass
+//! rewrites every atomic operation into the equivalent plain load, compute,
+//! and store sequence and drops memory orderings, which makes fences no-ops.
+//! Lock acquisition built from compare-exchange collapses the same way.
+//! Futex waits, which `std::sync` and `wasm::threading::sync` block in,
+//! return at once when the value has changed or the wait would time out,
+//! and trap where they would block forever; notifications wake nobody.
+//!
+//! `force` also rewrites modules that do require threading, producing the
+//! fallback variant for hosts without shared memory, such as pages that
+//! are not cross-origin isolated. Its memory is no longer shared, the
+//! thread entry exports are dropped, and spawning a thread fails since
+//! the glue gives it nowhere to run.
+
+use wasm::threading::runtime::{THREAD_MAIN_EXPORT, THREAD_START_EXPORT};
+use wasm::wasmir::{
+    AtomicOp, BasicBlock, BinaryOp, BlockId, Capability, Constant, Instruction, Operand, Terminator, Type,
+    WasmIR, WasmModule,
+};
+
+/// Why the rewrite preserves behavior, recorded with every applied report
+const PROOF_NOTE: &str = "No function requires Capability::Threading, so the module executes on a \
+single agent. Without a concurrent observer an atomic read-modify-write is indistinguishable from \
+a load followed by a store of the computed value, compare-exchange reduces to a conditional store, \
+memory orderings constrain nothing, so fences are no-ops, and no other agent can change a waited-on \
+value or be woken by a notification.";
+
+/// Why the forced rewrite preserves behavior on the hosts that load it
+const FALLBACK_PROOF_NOTE: &str = "This is the fallback variant, which hosts only load when shared \
+memory is unavailable. Its memory is not shared and its thread spawns fail, so the module executes \
+on a single agent, where atomics reduce to plain operations as in the single-threaded case.";
+
+/// Result of running the single-threaded pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SingleThreadedReport {
+    /// Whether the module qualified for the rewrite
+    pub applied: bool,
+    /// Atomic read-modify-write operations turned into plain sequences
+    pub rewritten_atomics: usize,
+    /// Compare-exchange operations turned into conditional stores
+    pub rewritten_compare_exchanges: usize,
+    /// Futex waits turned into a comparison, or a trap where they would block forever
+    pub rewritten_waits: usize,
+    /// Futex notifications turned into waking nobody
+    pub rewritten_notifies: usize,
+    /// Atomic operations left untouched, with the reason
+    pub skipped: Vec<String>,
+    /// Justification for the rewrite
+    pub proof_note: String,
+}
+
+/// Whole-module transform replacing atomics with plain operations
+#[derive(Debug, Default)]
+pub struct SingleThreadedPass {
+    force: bool,
+    report: SingleThreadedReport,
+}
+
+impl SingleThreadedPass {
+    /// Creates a new pass
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rewrites modules requiring threading too, unsharing their memory
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Returns true if any function requires threading
+    pub fn requires_threading(module: &WasmModule) -> bool {
+        module.functions.iter()
+            .any(|function| function.capabilities.contains(&Capability::Threading))
+    }
+
+    /// Runs the pass over a module
+    pub fn run(mut self, module: &mut WasmModule) -> SingleThreadedReport {
+        let threaded = Self::requires_threading(module);
+        if threaded && !self.force {
+            return self.report;
+        }
+
+        self.report.applied = true;
+        let fallback = self.force && (threaded || module.shared_memory);
+        self.report.proof_note = if fallback { FALLBACK_PROOF_NOTE } else { PROOF_NOTE }.to_string();
+        if self.force {
+            module.shared_memory = false;
+            module.exports.retain(|export| export.name != THREAD_MAIN_EXPORT && export.name != THREAD_START_EXPORT);
+        }
+
+        for function in &mut module.functions {
+            let skipped_before = self.report.skipped.len();
+            self.rewrite_function(function);
+            if self.report.skipped.len() == skipped_before {
+                function.capabilities.retain(|capability| {
+                    !matches!(capability, Capability::AtomicMemory | Capability::Threading)
+                });
+            }
+        }
+
+        self.report
+    }
+
+    fn rewrite_function(&mut self, function: &mut WasmIR) {
+        let has_atomics = function.all_instructions().any(|instruction| matches!(
+            instruction,
+            Instruction::AtomicOp { .. }
+                | Instruction::CompareExchange { .. }
+                | Instruction::AtomicWait { .. }
+                | Instruction::AtomicNotify { .. }
+        ));
+        if !has_atomics {
+            return;
+        }
+
+        // Temporaries are allocated after the parameters
+        while function.locals.len() < function.signature.params.len() {
+            let ty = function.signature.params[function.locals.len()].clone();
+            function.locals.push(ty);
+        }
+
+        // Blocks appended by splitting are processed in turn
+        let mut index = 0;
+        while index < function.basic_blocks.len() {
+            self.rewrite_block(function, index);
+            index += 1;
+        }
+    }
+
+    fn rewrite_block(&mut self, function: &mut WasmIR, index: usize) {
+        let instructions = std::mem::take(&mut function.basic_blocks[index].instructions);
+        let mut rewritten = Vec::with_capacity(instructions.len());
+        let mut remaining = instructions.into_iter();
+
+        while let Some(instruction) = remaining.next() {
+            match instruction {
+                Instruction::AtomicOp { op, address, value, order } => {
+                    match self.rewrite_atomic(function, &mut rewritten, op, &address, &value) {
+                        Ok(()) => self.report.rewritten_atomics += 1,
+                        Err(reason) => {
+                            self.skip(function, reason);
+                            rewritten.push(Instruction::AtomicOp { op, address, value, order });
+                        }
+                    }
+                }
+                Instruction::CompareExchange { address, expected, new_value, order } => {
+                    let ty = match self.value_type(function, &[&expected, &new_value]) {
+                        Ok(ty) => ty,
+                        Err(reason) => {
+                            self.skip(function, reason);
+                            rewritten.push(Instruction::CompareExchange { address, expected, new_value, order });
+                            continue;
+                        }
+                    };
+                    self.report.rewritten_compare_exchanges += 1;
+                    let address = self.stash_address(function, &mut rewritten, address);
+                    let tail: Vec<Instruction> = remaining.collect();
+                    self.split_compare_exchange(function, index, rewritten, tail, address, expected, new_value, ty);
+                    return;
+                }
+                Instruction::AtomicWait { address, expected, timeout, ty } => {
+                    // Only a constant timeout tells whether the wait could ever end
+                    let check = match &timeout {
+                        Operand::Constant(Constant::I64(nanos)) => self.value_type(function, &[&expected]).map(|_| *nanos >= 0),
+                        _ => Err("wait with a run-time timeout".to_string()),
+                    };
+                    let timed = match check {
+                        Ok(timed) => timed,
+                        Err(reason) => {
+                            self.skip(function, reason);
+                            rewritten.push(Instruction::AtomicWait { address, expected, timeout, ty });
+                            continue;
+                        }
+                    };
+                    self.report.rewritten_waits += 1;
+                    let address = self.stash_address(function, &mut rewritten, address);
+                    if timed {
+                        self.rewrite_timed_wait(function, &mut rewritten, address, expected, ty);
+                    } else {
+                        let tail: Vec<Instruction> = remaining.collect();
+                        self.split_wait(function, index, rewritten, tail, address, expected, ty);
+                        return;
+                    }
+                }
+                Instruction::AtomicNotify { address, count } => {
+                    self.report.rewritten_notifies += 1;
+                    // Pop stack-resident operands, topmost first
+                    for operand in [count, address] {
+                        if !is_pure(&operand) {
+                            let scratch = function.add_local(Type::I32);
+                            rewritten.push(Instruction::LocalSet { index: scratch, value: operand });
+                        }
+                    }
+                    let woken = function.add_local(Type::I32);
+                    rewritten.push(Instruction::LocalSet { index: woken, value: Operand::Constant(Constant::I32(0)) });
+                    rewritten.push(Instruction::LocalGet { index: woken });
+                }
+                other => rewritten.push(other),
+            }
+        }
+
+        function.basic_blocks[index].instructions = rewritten;
+    }
+
+    /// Emits `old = load; store(op(old, value)); push old`
+    fn rewrite_atomic(
+        &mut self,
+        function: &mut WasmIR,
+        out: &mut Vec<Instruction>,
+        op: AtomicOp,
+        address: &Operand,
+        value: &Operand,
+    ) -> Result<(), String> {
+        let ty = self.value_type(function, &[value])?;
+        let address = self.stash_address(function, out, address.clone());
+        let old = function.add_local(ty.clone());
+
+        out.push(Instruction::MemoryLoad { address: address.clone(), ty: ty.clone(), align: None, offset: 0 });
+        out.push(Instruction::LocalSet { index: old, value: Operand::StackValue(0) });
+
+        let stored = match binary_op(op) {
+            Some(op) => {
+                let new = function.add_local(ty.clone());
+                out.push(Instruction::BinaryOp { op, left: Operand::Local(old), right: value.clone() });
+                out.push(Instruction::LocalSet { index: new, value: Operand::StackValue(0) });
+                Operand::Local(new)
+            }
+            None => value.clone(),
+        };
+
+        out.push(Instruction::MemoryStore { address, value: stored, ty, align: None, offset: 0 });
+        out.push(Instruction::LocalGet { index: old });
+        Ok(())
+    }
+
+    /// Splits the block so the store only happens when `old == expected`
+    ///
+    /// ```text
+    /// B: pre; old = load; cond = old == expected; br cond S J
+    /// S: store new_value; jump J
+    /// J: push old; post; <original terminator>
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    fn split_compare_exchange(
+        &mut self,
+        function: &mut WasmIR,
+        index: usize,
+        mut head: Vec<Instruction>,
+        tail: Vec<Instruction>,
+        address: Operand,
+        expected: Operand,
+        new_value: Operand,
+        ty: Type,
+    ) {
+        let old = function.add_local(ty.clone());
+        let cond = function.add_local(Type::I32);
+
+        head.push(Instruction::MemoryLoad { address: address.clone(), ty: ty.clone(), align: None, offset: 0 });
+        head.push(Instruction::LocalSet { index: old, value: Operand::StackValue(0) });
+        head.push(Instruction::BinaryOp { op: BinaryOp::Eq, left: Operand::Local(old), right: expected });
+        head.push(Instruction::LocalSet { index: cond, value: Operand::StackValue(0) });
+
+        let store_block = BlockId(function.basic_blocks.len());
+        let join_block = BlockId(function.basic_blocks.len() + 1);
+
+        let terminator = std::mem::replace(
+            &mut function.basic_blocks[index].terminator,
+            Terminator::Branch { condition: Operand::Local(cond), then_block: store_block, else_block: join_block },
+        );
+        function.basic_blocks[index].instructions = head;
+
+        function.basic_blocks.push(BasicBlock {
+            id: store_block,
+            instructions: vec![Instruction::MemoryStore { address, value: new_value, ty, align: None, offset: 0 }],
+            terminator: Terminator::Jump { target: join_block },
+        });
+
+        let mut join = vec![Instruction::LocalGet { index: old }];
+        join.extend(tail);
+        function.basic_blocks.push(BasicBlock { id: join_block, instructions: join, terminator });
+    }
+
+    /// Emits `old = load; push 2 - (old != expected)`, the result of a wait
+    /// that either finds the value changed or times out
+    fn rewrite_timed_wait(
+        &mut self,
+        function: &mut WasmIR,
+        out: &mut Vec<Instruction>,
+        address: Operand,
+        expected: Operand,
+        ty: Type,
+    ) {
+        let old = function.add_local(ty.clone());
+        let differs = function.add_local(Type::I32);
+        out.push(Instruction::MemoryLoad { address, ty, align: None, offset: 0 });
+        out.push(Instruction::LocalSet { index: old, value: Operand::StackValue(0) });
+        out.push(Instruction::BinaryOp { op: BinaryOp::Ne, left: Operand::Local(old), right: expected });
+        out.push(Instruction::LocalSet { index: differs, value: Operand::StackValue(0) });
+        out.push(Instruction::BinaryOp {
+            op: BinaryOp::Sub,
+            left: Operand::Constant(Constant::I32(2)),
+            right: Operand::Local(differs),
+        });
+    }
+
+    /// Splits the block so a wait that would block forever traps
+    ///
+    /// ```text
+    /// B: pre; old = load; cond = old == expected; br cond D J
+    /// D: panic
+    /// J: push old != expected; post; <original terminator>
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    fn split_wait(
+        &mut self,
+        function: &mut WasmIR,
+        index: usize,
+        mut head: Vec<Instruction>,
+        tail: Vec<Instruction>,
+        address: Operand,
+        expected: Operand,
+        ty: Type,
+    ) {
+        let old = function.add_local(ty.clone());
+        let cond = function.add_local(Type::I32);
+
+        head.push(Instruction::MemoryLoad { address, ty, align: None, offset: 0 });
+        head.push(Instruction::LocalSet { index: old, value: Operand::StackValue(0) });
+        head.push(Instruction::BinaryOp { op: BinaryOp::Eq, left: Operand::Local(old), right: expected.clone() });
+        head.push(Instruction::LocalSet { index: cond, value: Operand::StackValue(0) });
+
+        let deadlock_block = BlockId(function.basic_blocks.len());
+        let join_block = BlockId(function.basic_blocks.len() + 1);
+
+        let terminator = std::mem::replace(
+            &mut function.basic_blocks[index].terminator,
+            Terminator::Branch { condition: Operand::Local(cond), then_block: deadlock_block, else_block: join_block },
+        );
+        function.basic_blocks[index].instructions = head;
+
+        function.basic_blocks.push(BasicBlock {
+            id: deadlock_block,
+            instructions: Vec::new(),
+            terminator: Terminator::Panic {
+                message: Some(Operand::Constant(Constant::String("deadlock: wait on the only thread".to_string()))),
+            },
+        });
+
+        let mut join = vec![Instruction::BinaryOp { op: BinaryOp::Ne, left: Operand::Local(old), right: expected }];
+        join.extend(tail);
+        function.basic_blocks.push(BasicBlock { id: join_block, instructions: join, terminator });
+    }
+
+    /// Moves a stack-resident address into a local so it can be reused
+    fn stash_address(&mut self, function: &mut WasmIR, out: &mut Vec<Instruction>, address: Operand) -> Operand {
+        if is_pure(&address) {
+            return address;
+        }
+        let local = function.add_local(Type::I32);
+        out.push(Instruction::LocalSet { index: local, value: address });
+        Operand::Local(local)
+    }
+
+    /// Determines the integer type of an atomic operation from its value operands
+    fn value_type(&self, function: &WasmIR, operands: &[&Operand]) -> Result<Type, String> {
+        if operands.iter().any(|operand| matches!(operand, Operand::StackValue(_))) {
+            return Err("value operand comes from the stack".to_string());
+        }
+        match operands.iter().find_map(|operand| function.operand_type(operand)) {
+            Some(Type::I32) => Ok(Type::I32),
+            Some(Type::I64) => Ok(Type::I64),
+            Some(other) => Err(format!("non-integer atomic of type {:?}", other)),
+            None => Err("untyped value operand".to_string()),
+        }
+    }
+
+    fn skip(&mut self, function: &WasmIR, reason: String) {
+        self.report.skipped.push(format!("{}: {}", function.name, reason));
+    }
+}
+
+fn binary_op(op: AtomicOp) -> Option<BinaryOp> {
+    match op {
+        AtomicOp::Add => Some(BinaryOp::Add),
+        AtomicOp::Sub => Some(BinaryOp::Sub),
+        AtomicOp::And => Some(BinaryOp::And),
+        AtomicOp::Or => Some(BinaryOp::Or),
+        AtomicOp::Xor => Some(BinaryOp::Xor),
+        AtomicOp::Exchange => None,
+    }
+}
+
+/// Operands that can be evaluated more than once without side effects
+fn is_pure(operand: &Operand) -> bool {
+    match operand {
+        Operand::Local(_) | Operand::Constant(_) | Operand::Global(_) => true,
+        Operand::MemoryAddress(inner) => is_pure(inner),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::interpreter::{Interpreter, InterpreterError};
+    use crate::host::runtime::Value;
+    use crate::host::HostFunctions;
+    use wasm::wasmir::{MemoryOrder, MemoryType, Signature};
+
+    fn counter_module(capabilities: Vec<Capability>) -> WasmModule {
+        let mut function = WasmIR::new("bump".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        function.capabilities = capabilities;
+        function.add_basic_block(vec![
+            Instruction::AtomicOp {
+                op: AtomicOp::Add,
+                address: Operand::Local(0),
+                value: Operand::Constant(Constant::I32(1)),
+                order: MemoryOrder::SeqCst,
+            },
+        ], Terminator::Return { value: Some(Operand::StackValue(0)) });
+
+        let mut module = WasmModule::new();
+        module.add_function(function);
+        module
+    }
+
+    #[test]
+    fn test_atomic_add_becomes_plain_sequence() {
+        let mut module = counter_module(vec![Capability::AtomicMemory]);
+        let report = SingleThreadedPass::new().run(&mut module);
+
+        assert!(report.applied);
+        assert_eq!(report.rewritten_atomics, 1);
+        assert!(report.proof_note.contains("Capability::Threading"));
+
+        let function = &module.functions[0];
+        assert!(function.capabilities.is_empty());
+        assert!(!function.all_instructions().any(|i| matches!(i, Instruction::AtomicOp { .. })));
+        assert!(matches!(function.basic_blocks[0].instructions.last(), Some(Instruction::LocalGet { .. })));
+        assert!(module.validate().is_ok());
+    }
+
+    #[test]
+    fn test_threaded_modules_are_untouched() {
+        let mut module = counter_module(vec![Capability::Threading]);
+        let report = SingleThreadedPass::new().run(&mut module);
+
+        assert!(!report.applied);
+        assert!(module.functions[0].all_instructions().any(|i| matches!(i, Instruction::AtomicOp { .. })));
+    }
+
+    #[test]
+    fn test_forced_rewrite_builds_the_fallback_variant() {
+        let mut module = counter_module(vec![Capability::Threading, Capability::AtomicMemory]);
+        module.set_memory(MemoryType { min_pages: 1, max_pages: Some(16) });
+        module.shared_memory = true;
+        module.export_function(THREAD_MAIN_EXPORT, 0);
+        let report = SingleThreadedPass::new().force(true).run(&mut module);
+
+        assert!(report.applied);
+        assert_eq!(report.rewritten_atomics, 1);
+        assert_eq!(report.proof_note, FALLBACK_PROOF_NOTE);
+        assert!(!module.shared_memory);
+        assert!(!module.exports.iter().any(|export| export.name == THREAD_MAIN_EXPORT));
+        assert!(module.functions[0].capabilities.is_empty());
+        assert!(module.validate().is_ok());
+    }
+
+    #[test]
+    fn test_compare_exchange_splits_block() {
+        let mut function = WasmIR::new("try_lock".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        function.add_basic_block(vec![
+            Instruction::CompareExchange {
+                address: Operand::Local(0),
+                expected: Operand::Constant(Constant::I32(0)),
+                new_value: Operand::Constant(Constant::I32(1)),
+                order: MemoryOrder::Acquire,
+            },
+        ], Terminator::Return { value: Some(Operand::StackValue(0)) });
+        let mut module = WasmModule::new();
+        module.add_function(function);
+
+        let report = SingleThreadedPass::new().run(&mut module);
+        assert_eq!(report.rewritten_compare_exchanges, 1);
+
+        let function = &module.functions[0];
+        assert_eq!(function.basic_blocks.len(), 3);
+        assert!(matches!(function.basic_blocks[0].terminator, Terminator::Branch { .. }));
+        assert!(matches!(function.basic_blocks[2].terminator, Terminator::Return { .. }));
+        assert!(function.validate().is_ok());
+    }
+
+    #[test]
+    fn test_futex_operations_run_on_the_only_thread() {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        for (name, expected, timeout) in [("park", 0, -1), ("park_changed", 5, -1), ("park_for", 0, 10)] {
+            let mut function = WasmIR::new(name.to_string(), Signature { params: vec![Type::I32], returns: Some(Type::I32) });
+            function.add_local(Type::I32);
+            function.add_local(Type::I32);
+            function.add_basic_block(vec![
+                Instruction::AtomicNotify { address: Operand::Local(0), count: Operand::Constant(Constant::I32(1)) },
+                Instruction::LocalSet { index: 1, value: Operand::StackValue(0) },
+                Instruction::AtomicWait {
+                    address: Operand::Local(0),
+                    expected: Operand::Constant(Constant::I32(expected)),
+                    timeout: Operand::Constant(Constant::I64(timeout)),
+                    ty: Type::I32,
+                },
+            ], Terminator::Return { value: Some(Operand::StackValue(0)) });
+            let index = module.add_function(function);
+            module.export_function(name, index);
+        }
+
+        let report = SingleThreadedPass::new().run(&mut module);
+        assert_eq!((report.rewritten_waits, report.rewritten_notifies), (3, 3));
+        assert!(report.skipped.is_empty());
+        assert!(module.validate().is_ok());
+
+        // The wait reports a changed value or a timeout, or traps
+        let host = HostFunctions::new();
+        let mut interpreter = Interpreter::new(&module, &host);
+        assert!(matches!(interpreter.invoke("park", &[Value::I32(8)]), Err(InterpreterError::Trap(_))));
+        assert_eq!(interpreter.invoke("park_changed", &[Value::I32(8)]), Ok(Some(Value::I32(1))));
+        assert_eq!(interpreter.invoke("park_for", &[Value::I32(8)]), Ok(Some(Value::I32(2))));
+    }
+
+    #[test]
+    fn test_untyped_atomics_are_reported() {
+        let mut module = counter_module(vec![]);
+        if let Instruction::AtomicOp { value, .. } = &mut module.functions[0].basic_blocks[0].instructions[0] {
+            *value = Operand::StackValue(0);
+        }
+
+        let report = SingleThreadedPass::new().run(&mut module);
+        assert_eq!(report.rewritten_atomics, 0);
+        assert_eq!(report.skipped.len(), 1);
+    }
+}
@@ -0,0 +1,377 @@
+//! Size report of a compiled module, like twiggy or cargo-bloat
+//!
+//! `SizeReport::new` attributes every byte of a binary to its sections,
+//! and the code section's bytes to the functions they define. Functions
+//! are named from the `name` section codegen writes with `debug_info`,
+//! or from another build's with `names_from`; without either they show
+//! as `func[N]`. `rustc_lowering` names functions by path, crate first,
+//! so the first path segment is the crate a function is counted under;
+//! mangled symbols are counted under theirs once `demangle`d.
+//!
+//! Sizes include each item's framing: a section's id and length, and a
+//! function body's length prefix.
+
+use crate::backend::demangle::demangle_or_raw;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use wasmparser::{BinaryReaderError, KnownCustom, Name, Parser, Payload};
+
+/// Bytes of the magic number and version preceding the sections
+const HEADER_SIZE: usize = 8;
+
+/// Crate of functions whose names have no path
+const UNKNOWN_CRATE: &str = "<unknown>";
+
+/// How a report is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+impl SizeFormat {
+    pub const ALL: [SizeFormat; 2] = [SizeFormat::Table, SizeFormat::Json];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SizeFormat::Table => "table",
+            SizeFormat::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+}
+
+/// Bytes of one section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionSize {
+    /// Standard section name, or a custom section's own
+    pub name: String,
+    pub size: usize,
+}
+
+/// Bytes of one function body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSize {
+    /// Function index, counting imported functions
+    pub index: u32,
+    pub name: String,
+    pub size: usize,
+}
+
+/// Bytes of the functions of one crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrateSize {
+    pub name: String,
+    pub size: usize,
+    pub functions: usize,
+}
+
+/// Where the bytes of a binary go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeReport {
+    pub total: usize,
+    /// In binary order, starting with the header
+    pub sections: Vec<SectionSize>,
+    /// Largest first
+    pub functions: Vec<FunctionSize>,
+    /// Largest first
+    pub crates: Vec<CrateSize>,
+}
+
+impl SizeReport {
+    /// Attributes the bytes of `binary`
+    pub fn new(binary: &[u8]) -> Result<Self, BinaryReaderError> {
+        let mut sections = vec![SectionSize { name: "header".to_string(), size: HEADER_SIZE.min(binary.len()) }];
+        let mut functions = Vec::new();
+        let mut imported_functions = 0;
+        for payload in Parser::new(0).parse_all(binary) {
+            let payload = payload?;
+            match &payload {
+                Payload::ImportSection(reader) => {
+                    for import in reader.clone() {
+                        if matches!(import?.ty, wasmparser::TypeRef::Func(_)) {
+                            imported_functions += 1;
+                        }
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    let range = body.range();
+                    let index = imported_functions + functions.len() as u32;
+                    let size = range.len() + leb_len(range.len());
+                    functions.push(FunctionSize { index, name: format!("func[{}]", index), size });
+                }
+                _ => {}
+            }
+            if let Some((id, range)) = payload.as_section() {
+                let name = match &payload {
+                    Payload::CustomSection(reader) => reader.name().to_string(),
+                    _ => section_name(id).to_string(),
+                };
+                // The content's range leaves out the id and the length
+                sections.push(SectionSize { name, size: 1 + leb_len(range.len()) + range.len() });
+            }
+        }
+
+        let mut report = Self { total: binary.len(), sections, functions, crates: Vec::new() };
+        report.name_functions(&function_names(binary)?);
+        Ok(report)
+    }
+
+    /// Names functions from the `name` section of another build of the module
+    ///
+    /// The name section does not change function bodies, so a build with
+    /// `debug_info` names the functions of one without.
+    pub fn names_from(mut self, named: &[u8]) -> Result<Self, BinaryReaderError> {
+        self.name_functions(&function_names(named)?);
+        Ok(self)
+    }
+
+    /// Names Rust functions by their demangled paths, counting them under
+    /// the crate those start with
+    pub fn demangle(mut self) -> Self {
+        let names = self.functions.iter().map(|function| (function.index, demangle_or_raw(&function.name))).collect();
+        self.name_functions(&names);
+        self
+    }
+
+    fn name_functions(&mut self, names: &HashMap<u32, String>) {
+        for function in &mut self.functions {
+            if let Some(name) = names.get(&function.index) {
+                function.name = name.clone();
+            }
+        }
+        self.functions.sort_by(|a, b| b.size.cmp(&a.size).then(a.index.cmp(&b.index)));
+
+        let mut crates: Vec<CrateSize> = Vec::new();
+        for function in &self.functions {
+            let name = crate_of(&function.name);
+            match crates.iter_mut().find(|krate| krate.name == name) {
+                Some(krate) => {
+                    krate.size += function.size;
+                    krate.functions += 1;
+                }
+                None => crates.push(CrateSize { name: name.to_string(), size: function.size, functions: 1 }),
+            }
+        }
+        crates.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        self.crates = crates;
+    }
+
+    /// Renders the report, listing at most `limit` functions
+    pub fn render(&self, format: SizeFormat, limit: Option<usize>) -> String {
+        let limit = limit.unwrap_or(usize::MAX);
+        match format {
+            SizeFormat::Table => {
+                let mut out = String::new();
+                let _ = self.write_table(&mut out, limit);
+                out
+            }
+            SizeFormat::Json => {
+                let sizes = |name: &str, size: usize| json!({ "name": name, "size": size });
+                let report = json!({
+                    "total": self.total,
+                    "sections": self.sections.iter().map(|s| sizes(&s.name, s.size)).collect::<Vec<_>>(),
+                    "crates": self.crates.iter()
+                        .map(|c| json!({ "name": c.name, "size": c.size, "functions": c.functions }))
+                        .collect::<Vec<_>>(),
+                    "functions": self.functions.iter()
+                        .take(limit)
+                        .map(|f| json!({ "index": f.index, "name": f.name, "size": f.size }))
+                        .collect::<Vec<_>>(),
+                });
+                format!("{}\n", report)
+            }
+        }
+    }
+
+    fn write_table(&self, out: &mut String, limit: usize) -> fmt::Result {
+        let percent = |size: usize| match self.total {
+            0 => 0.0,
+            total => size as f64 * 100.0 / total as f64,
+        };
+        let mut rows = |title: &str, items: &mut dyn Iterator<Item = (usize, &str)>| -> fmt::Result {
+            writeln!(out, "{:>10} {:>7}  {}", "Bytes", "%", title)?;
+            for (size, name) in items {
+                writeln!(out, "{:>10} {:>6.2}%  {}", size, percent(size), name)?;
+            }
+            writeln!(out)
+        };
+        rows("Section", &mut self.sections.iter().map(|s| (s.size, s.name.as_str())))?;
+        rows("Crate", &mut self.crates.iter().map(|c| (c.size, c.name.as_str())))?;
+        rows("Function", &mut self.functions.iter().take(limit).map(|f| (f.size, f.name.as_str())))?;
+        if self.functions.len() > limit {
+            let rest = &self.functions[limit..];
+            let size = rest.iter().map(|f| f.size).sum();
+            writeln!(out, "{:>10} {:>6.2}%  ... and {} more functions\n", size, percent(size), rest.len())?;
+        }
+        writeln!(out, "{:>10} {:>6.2}%  Total", self.total, 100.0)
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(SizeFormat::Table, None))
+    }
+}
+
+/// Function names of a binary's `name` section, by function index
+fn function_names(binary: &[u8]) -> Result<HashMap<u32, String>, BinaryReaderError> {
+    let mut names = HashMap::new();
+    for payload in Parser::new(0).parse_all(binary) {
+        let Payload::CustomSection(reader) = payload? else { continue };
+        let KnownCustom::Name(reader) = reader.as_known() else { continue };
+        for subsection in reader {
+            let Name::Function(map) = subsection? else { continue };
+            for naming in map {
+                let naming = naming?;
+                names.insert(naming.index, naming.name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Crate a function is counted under, the first segment of its path
+fn crate_of(name: &str) -> &str {
+    match name.split_once("::") {
+        // `<T as Trait>::method` paths start at the type
+        Some((krate, _)) if !krate.is_empty() && !krate.contains(['<', ' ']) => krate,
+        _ => UNKNOWN_CRATE,
+    }
+}
+
+fn section_name(id: u8) -> &'static str {
+    match id {
+        1 => "type",
+        2 => "import",
+        3 => "function",
+        4 => "table",
+        5 => "memory",
+        6 => "global",
+        7 => "export",
+        8 => "start",
+        9 => "element",
+        10 => "code",
+        11 => "data",
+        12 => "datacount",
+        13 => "tag",
+        _ => "unknown",
+    }
+}
+
+/// Bytes of `value` as an unsigned LEB128
+fn leb_len(mut value: usize) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::WasmCodegen;
+    use wasm::wasmir::{Constant, Instruction, Operand, Signature, Terminator, Type, WasmIR, WasmModule};
+
+    /// Module with `app::small`, a larger `util::sum`, and an import
+    fn module() -> WasmModule {
+        let mut module = WasmModule::new();
+        module.add_import("env", "log", Signature { params: vec![Type::I32], returns: None });
+        let mut small = WasmIR::new("app::small".to_string(), Signature { params: vec![], returns: None });
+        small.add_basic_block(vec![], Terminator::Return { value: None });
+        let mut sum = WasmIR::new("util::sum".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        let adds = (0..8).map(|i| Instruction::BinaryOp {
+            op: wasm::wasmir::BinaryOp::Add,
+            left: Operand::Local(0),
+            right: Operand::Constant(Constant::I32(i)),
+        });
+        sum.add_basic_block(adds.collect(), Terminator::Return { value: Some(Operand::Local(0)) });
+        let small = module.add_function(small);
+        let sum = module.add_function(sum);
+        module.export_function("small", small);
+        module.export_function("sum", sum);
+        module
+    }
+
+    #[test]
+    fn test_attributes_every_byte() {
+        let binary = WasmCodegen::new().debug_info(true).compile(&module()).unwrap();
+        let report = SizeReport::new(&binary).unwrap();
+
+        assert_eq!(report.total, binary.len());
+        assert_eq!(report.sections.iter().map(|section| section.size).sum::<usize>(), binary.len());
+        let names: Vec<_> = report.sections.iter().map(|section| section.name.as_str()).collect();
+        assert!(names.starts_with(&["header", "type", "import"]) && names.contains(&"name"), "{:?}", names);
+
+        let code = report.sections.iter().find(|section| section.name == "code").unwrap().size;
+        let functions: usize = report.functions.iter().map(|function| function.size).sum();
+        let content = leb_len(report.functions.len()) + functions;
+        assert_eq!(code, 1 + leb_len(content) + content);
+        let functions: Vec<_> = report.functions.iter().map(|f| (f.index, f.name.as_str())).collect();
+        assert_eq!(functions, [(2, "util::sum"), (1, "app::small")]);
+        let crates: Vec<_> = report.crates.iter().map(|c| (c.name.as_str(), c.functions)).collect();
+        assert_eq!(crates, [("util", 1), ("app", 1)]);
+    }
+
+    #[test]
+    fn test_names_come_from_a_debug_build() {
+        let stripped = WasmCodegen::new().compile(&module()).unwrap();
+        let named = WasmCodegen::new().debug_info(true).compile(&module()).unwrap();
+        let report = SizeReport::new(&stripped).unwrap();
+        assert_eq!(report.functions[0].name, "func[2]");
+        assert_eq!(report.crates[0].name, UNKNOWN_CRATE);
+
+        let report = report.names_from(&named).unwrap();
+        assert_eq!(report.functions[0].name, "util::sum");
+        assert!(!report.sections.iter().any(|section| section.name == "name"));
+        assert_eq!(crate_of("<u32 as core::fmt::Debug>::fmt"), UNKNOWN_CRATE);
+        assert_eq!(crate_of("core::ptr::drop_in_place::<alloc::string::String>"), "core");
+    }
+
+    #[test]
+    fn test_demangles_rust_symbols() {
+        let mut module = module();
+        module.functions[0].name = "_ZN3app5small17h0123456789abcdefE".to_string();
+        module.functions[1].name = "_RNvNtCs1234_4util4math3sum".to_string();
+        let binary = WasmCodegen::new().debug_info(true).compile(&module).unwrap();
+        let report = SizeReport::new(&binary).unwrap();
+        assert_eq!(report.crates[0].name, UNKNOWN_CRATE);
+
+        let report = report.demangle();
+        let functions: Vec<_> = report.functions.iter().map(|f| (f.index, f.name.as_str())).collect();
+        assert_eq!(functions, [(2, "util::math::sum"), (1, "app::small")]);
+        let crates: Vec<_> = report.crates.iter().map(|c| (c.name.as_str(), c.functions)).collect();
+        assert_eq!(crates, [("util", 1), ("app", 1)]);
+
+        let demangled = WasmCodegen::new().debug_info(true).demangle(true).compile(&module).unwrap();
+        assert_eq!(SizeReport::new(&demangled).unwrap().functions, report.functions);
+    }
+
+    #[test]
+    fn test_renders_tables_and_json() {
+        let binary = WasmCodegen::new().debug_info(true).compile(&module()).unwrap();
+        let report = SizeReport::new(&binary).unwrap();
+
+        let table = report.render(SizeFormat::Table, Some(1));
+        assert!(table.contains("     Bytes       %  Function\n"), "{}", table);
+        assert!(table.contains("%  util::sum\n") && !table.contains("app::small"), "{}", table);
+        assert!(table.contains("... and 1 more functions"), "{}", table);
+        assert!(table.ends_with(&format!("{:>10} 100.00%  Total\n", binary.len())), "{}", table);
+
+        let json: serde_json::Value = serde_json::from_str(&report.render(SizeFormat::Json, None)).unwrap();
+        assert_eq!(json["total"], binary.len());
+        assert_eq!(json["functions"][1]["name"], "app::small");
+        assert_eq!(json["crates"][0]["name"], "util");
+        assert_eq!(SizeFormat::from_name("json"), Some(SizeFormat::Json));
+    }
+}
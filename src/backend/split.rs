@@ -0,0 +1,322 @@
+//! Code splitting of cold functions into a secondary module
+//!
+//! `split_module` moves the bodies of cold functions, those marked
+//! `#[cold]` or never called in a profile, into a secondary module the JS
+//! glue loads on demand, so the primary module downloads and compiles
+//! sooner. A moved function keeps its index in the primary as a
+//! trampoline calling the secondary's export through a
+//! `SECONDARY_IMPORT_MODULE` import, which the glue forwards once the
+//! secondary is loaded. The secondary imports the primary's memory, the
+//! host imports the moved functions call, and the functions left in the
+//! primary they call, which the primary exports with `SPLIT_EXPORT_PREFIX`
+//! from `PRIMARY_IMPORT_MODULE`.
+//!
+//! Only self-contained functions move. Ones touching globals, thread
+//! locals, the shadow stack, the heap allocator, tables, or JS objects, or
+//! panicking, depend on state of the primary instance and stay, as do
+//! initializers. Threaded modules are not split, since Workers only
+//! instantiate the primary.
+
+use crate::backend::pgo::PgoProfile;
+use wasm::threading::runtime::THREAD_MAIN_EXPORT;
+use wasm::wasmir::{Constant, Instruction, Operand, Terminator, Type, WasmIR, WasmModule};
+
+/// Import module of the trampolines into the secondary module
+pub const SECONDARY_IMPORT_MODULE: &str = "wasmrust:secondary";
+
+/// Import module of the secondary's calls back into the primary
+pub const PRIMARY_IMPORT_MODULE: &str = "wasmrust:primary";
+
+/// Prefix of the primary's exports of functions the secondary calls,
+/// followed by the function index
+pub const SPLIT_EXPORT_PREFIX: &str = "__wasmrust_split_";
+
+/// A module split in two by `split_module`
+#[derive(Debug, Clone)]
+pub struct SplitModules {
+    /// Module loaded first, with trampolines in place of the moved functions
+    pub primary: WasmModule,
+    /// Module holding the moved functions, loaded on demand
+    pub secondary: WasmModule,
+    /// Primary indices of the moved functions, in the order of the secondary
+    pub moved: Vec<u32>,
+}
+
+/// The secondary's file next to `wasm_file`, as in `app.cold.wasm`
+pub fn secondary_file_name(wasm_file: &str) -> String {
+    match wasm_file.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{}.cold.{}", stem, extension),
+        _ => format!("{}.cold", wasm_file),
+    }
+}
+
+/// Functions marked cold, and with a `profile`, those it never saw entered
+pub fn cold_functions(module: &WasmModule, profile: Option<&PgoProfile>) -> Vec<u32> {
+    let mut cold = module.cold_functions.clone();
+    if let Some(profile) = profile {
+        for (index, function) in module.functions.iter().enumerate() {
+            let index = index as u32;
+            if profile.function_count(&function.name) == 0 && !cold.contains(&index) {
+                cold.push(index);
+            }
+        }
+    }
+    cold
+}
+
+/// Moves the functions of `cold` that can leave the module into a secondary one
+///
+/// Returns `None` if none can, or the module is threaded.
+pub fn split_module(module: &WasmModule, cold: &[u32]) -> Option<SplitModules> {
+    let threaded = module.shared_memory || module.exports.iter().any(|export| export.name == THREAD_MAIN_EXPORT);
+    if threaded || module.import_memory {
+        return None;
+    }
+    let initializers: Vec<u32> = module.start_function.iter()
+        .copied()
+        .chain(module.constructors.iter().map(|ctor| ctor.function))
+        .collect();
+    let mut moved: Vec<u32> = Vec::new();
+    for &index in cold {
+        let movable = module.functions.get(index as usize).is_some_and(is_movable);
+        if movable && !initializers.contains(&index) && !moved.contains(&index) {
+            moved.push(index);
+        }
+    }
+    if moved.is_empty() {
+        return None;
+    }
+
+    let mut primary = module.clone();
+    primary.cold_functions.clear();
+    let mut secondary = WasmModule::new();
+    secondary.memory = module.memory;
+    secondary.import_memory = module.memory.is_some();
+    secondary.memory_regions = module.memory_regions.clone();
+    secondary.promise_lowering = module.promise_lowering;
+
+    let mut names: Vec<String> = Vec::with_capacity(moved.len());
+    for &index in &moved {
+        let name = &module.functions[index as usize].name;
+        names.push(match names.contains(name) {
+            true => format!("{}#{}", name, index),
+            false => name.clone(),
+        });
+    }
+
+    for (&index, name) in moved.iter().zip(&names) {
+        let mut function = module.functions[index as usize].clone();
+        let instructions = function.basic_blocks.iter_mut().flat_map(|block| block.instructions.iter_mut());
+        for instruction in instructions {
+            let callee = match instruction {
+                Instruction::Call { func_ref, .. } => match moved.iter().position(|&other| other == *func_ref) {
+                    Some(position) => {
+                        *func_ref = position as u32;
+                        continue;
+                    }
+                    None => *func_ref,
+                },
+                Instruction::CallImport { import, .. } => {
+                    let host = &module.imports[*import as usize];
+                    *import = secondary.add_import(host.module.clone(), host.name.clone(), host.signature().clone());
+                    continue;
+                }
+                _ => continue,
+            };
+            // A function left in the primary, called through its export
+            let export = format!("{}{}", SPLIT_EXPORT_PREFIX, callee);
+            if primary.exports.iter().all(|existing| existing.name != export) {
+                primary.export_function(export.clone(), callee);
+            }
+            let signature = module.functions[callee as usize].signature.clone();
+            let import = secondary.add_import(PRIMARY_IMPORT_MODULE, export, signature);
+            if let Instruction::Call { args, .. } = instruction {
+                let args = std::mem::take(args);
+                *instruction = Instruction::CallImport { import, args };
+            }
+        }
+        let at = secondary.add_function(function);
+        secondary.export_function(name.clone(), at);
+
+        let signature = module.functions[index as usize].signature.clone();
+        let import = primary.add_import(SECONDARY_IMPORT_MODULE, name.clone(), signature);
+        primary.functions[index as usize] = trampoline(&module.functions[index as usize], import);
+    }
+    Some(SplitModules { primary, secondary, moved })
+}
+
+/// Body forwarding the parameters of `function` to `import`
+fn trampoline(function: &WasmIR, import: u32) -> WasmIR {
+    let mut trampoline = WasmIR::new(function.name.clone(), function.signature.clone());
+    trampoline.locals = function.signature.params.clone();
+    let args = (0..function.signature.params.len() as u32).map(Operand::Local).collect();
+    let value = function.signature.returns.as_ref().map(|_| Operand::StackValue(0));
+    trampoline.add_basic_block(vec![Instruction::CallImport { import, args }], Terminator::Return { value });
+    trampoline
+}
+
+/// Checks whether a function depends on nothing of its instance but memory and imports
+fn is_movable(function: &WasmIR) -> bool {
+    let numeric = |ty: &Type| matches!(ty, Type::I32 | Type::I64 | Type::F32 | Type::F64);
+    let host_value = |ty: &Type| matches!(ty, Type::ExternRef(_) | Type::FuncRef | Type::Promise(_));
+    function.frame.is_none()
+        && function.signature.params.iter().all(numeric)
+        && function.signature.returns.iter().all(numeric)
+        && !function.locals.iter().any(host_value)
+        && function.basic_blocks.iter().all(|block| {
+            block.instructions.iter().all(is_movable_instruction) && is_movable_terminator(&block.terminator)
+        })
+}
+
+fn is_movable_instruction(instruction: &Instruction) -> bool {
+    let operands: Vec<&Operand> = match instruction {
+        Instruction::LocalGet { .. }
+        | Instruction::Jump { .. }
+        | Instruction::MemorySize
+        | Instruction::CapabilityCheck { .. }
+        | Instruction::Nop => vec![],
+        Instruction::LocalSet { value, .. }
+        | Instruction::UnaryOp { value, .. }
+        | Instruction::Convert { value, .. }
+        | Instruction::LinearOp { value, .. } => vec![value],
+        Instruction::BinaryOp { left, right, .. } => vec![left, right],
+        Instruction::Call { args, .. } | Instruction::CallImport { args, .. } => args.iter().collect(),
+        Instruction::Return { value } => value.iter().collect(),
+        Instruction::Branch { condition, .. } => vec![condition],
+        Instruction::Switch { value, .. } => vec![value],
+        Instruction::MemoryLoad { address, .. } => vec![address],
+        Instruction::MemoryStore { address, value, .. } | Instruction::AtomicOp { address, value, .. } => {
+            vec![address, value]
+        }
+        Instruction::MemoryGrow { pages } => vec![pages],
+        Instruction::CompareExchange { address, expected, new_value, .. } => vec![address, expected, new_value],
+        Instruction::AtomicWait { address, expected, timeout, .. } => vec![address, expected, timeout],
+        Instruction::AtomicNotify { address, count } => vec![address, count],
+        _ => return false,
+    };
+    operands.into_iter().all(is_movable_operand)
+}
+
+fn is_movable_terminator(terminator: &Terminator) -> bool {
+    match terminator {
+        Terminator::Return { value } => value.iter().all(is_movable_operand),
+        Terminator::Branch { condition, .. } => is_movable_operand(condition),
+        Terminator::Switch { value, targets, .. } => {
+            is_movable_operand(value) && targets.iter().all(|(case, _)| is_movable_operand(case))
+        }
+        Terminator::Jump { .. } | Terminator::Unreachable => true,
+        Terminator::Panic { .. } => false,
+    }
+}
+
+fn is_movable_operand(operand: &Operand) -> bool {
+    match operand {
+        Operand::Local(_) | Operand::StackValue(_) => true,
+        Operand::Constant(constant) => !matches!(constant, Constant::String(_)),
+        Operand::MemoryAddress(inner) => is_movable_operand(inner),
+        Operand::Global(_) | Operand::FunctionRef(_) | Operand::ExternRef(_) | Operand::FuncRef(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::WasmCodegen;
+    use wasm::wasmir::{Export, ExportKind, MemoryType, Signature};
+    use wasmparser::{Validator, WasmFeatures};
+
+    /// `main` calls the cold `report`, which stores its argument and
+    /// returns it through `helper`
+    fn module() -> WasmModule {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        let signature = Signature { params: vec![Type::I32], returns: Some(Type::I32) };
+        let mut helper = WasmIR::new("helper".to_string(), signature.clone());
+        helper.add_local(Type::I32);
+        helper.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Local(0)) });
+        let helper = module.add_function(helper);
+        let mut report = WasmIR::new("report".to_string(), signature.clone());
+        report.add_local(Type::I32);
+        report.add_basic_block(
+            vec![
+                Instruction::MemoryStore {
+                    address: Operand::Constant(Constant::I32(16)),
+                    value: Operand::Local(0),
+                    ty: Type::I32,
+                    align: None,
+                    offset: 0,
+                },
+                Instruction::Call { func_ref: helper, args: vec![Operand::Local(0)] },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let report = module.add_function(report);
+        module.mark_cold(report);
+        let mut main = WasmIR::new("main".to_string(), signature);
+        main.add_local(Type::I32);
+        main.add_basic_block(
+            vec![Instruction::Call { func_ref: report, args: vec![Operand::Local(0)] }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let main = module.add_function(main);
+        module.export_function("main", main);
+        module
+    }
+
+    #[test]
+    fn test_cold_functions_move_behind_trampolines() {
+        let module = module();
+        let split = split_module(&module, &cold_functions(&module, None)).unwrap();
+        assert_eq!(split.moved, vec![1]);
+
+        let primary = &split.primary;
+        assert_eq!(primary.functions.len(), 3);
+        assert_eq!(primary.imports[0].module, SECONDARY_IMPORT_MODULE);
+        assert_eq!(primary.imports[0].name, "report");
+        let trampoline = &primary.functions[1].basic_blocks[0].instructions;
+        assert!(matches!(trampoline[..], [Instruction::CallImport { import: 0, .. }]));
+        let export = Export { name: format!("{}0", SPLIT_EXPORT_PREFIX), kind: ExportKind::Function(0) };
+        assert!(primary.exports.contains(&export));
+
+        let secondary = &split.secondary;
+        assert!(secondary.imports_memory());
+        assert_eq!(secondary.imports[0].module, PRIMARY_IMPORT_MODULE);
+        assert_eq!(secondary.imports[0].name, export.name);
+        let call = &secondary.functions[0].basic_blocks[0].instructions[1];
+        assert!(matches!(call, Instruction::CallImport { import: 0, .. }));
+        assert_eq!(secondary.exports, vec![Export { name: "report".to_string(), kind: ExportKind::Function(0) }]);
+
+        for module in [primary, secondary] {
+            assert_eq!(module.validate(), Ok(()));
+            let binary = WasmCodegen::new().compile(module).unwrap();
+            Validator::new_with_features(WasmFeatures::all()).validate_all(&binary).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_functions_bound_to_the_instance_stay() {
+        let mut module = module();
+        let init = module.add_function(WasmIR::new("init".to_string(), Signature { params: vec![], returns: None }));
+        module.functions[init as usize].add_basic_block(vec![], Terminator::Return { value: None });
+        module.add_constructor(init, 0);
+        assert_eq!(split_module(&module, &[1, init]).unwrap().moved, vec![1]);
+
+        let counter = module.add_global("counter", Type::I32, true, Constant::I32(0));
+        let set = Instruction::GlobalSet { index: counter, value: Operand::Constant(Constant::I32(1)) };
+        module.functions[1].basic_blocks[0].instructions.push(set);
+        assert!(split_module(&module, &[1, init]).is_none());
+
+        let mut threaded = self::module();
+        threaded.shared_memory = true;
+        assert!(split_module(&threaded, &[1]).is_none());
+    }
+
+    #[test]
+    fn test_profiles_find_functions_never_called() {
+        let module = module();
+        let profile = PgoProfile::parse("3\tmain\n0\thelper\n").unwrap();
+        assert_eq!(cold_functions(&module, Some(&profile)), vec![1, 0]);
+        assert_eq!(secondary_file_name("app.wasm"), "app.cold.wasm");
+        assert_eq!(secondary_file_name("app"), "app.cold");
+    }
+}
@@ -0,0 +1,458 @@
+//! Instantiation benchmarks
+//!
+//! Startup latency is measured along four paths: a cold compile and
+//! instantiate, instantiating an already compiled module, restoring a
+//! pre-initialized snapshot, and taking an instance from a pool. Embedded
+//! runtimes such as wasmtime implement `InstantiationRunner` and are timed
+//! by `StartupBenchmark::run`; `browser_driver` emits an ES module timing
+//! the paths a browser has through the JS glue, for headless Chrome.
+//!
+//! Results are `StartupRecord`s, one JSON object per line. A CI job keeps
+//! the previous run's records as a baseline and fails on the regressions
+//! `StartupThresholds::check` reports.
+
+use crate::backend::bench_gen::summarize;
+use crate::backend::js_glue::js_string;
+use crate::backend::BackendError;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Instant;
+
+/// Schema identifier of startup records
+pub const STARTUP_SCHEMA: &str = "wasmrust.startup/1";
+
+/// Way an instance is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InstantiationPath {
+    /// Compiling the module bytes and instantiating the result
+    Cold,
+    /// Instantiating a module compiled or deserialized ahead of time
+    CachedCompile,
+    /// Restoring memory and globals captured after initialization
+    Snapshot,
+    /// Taking a pre-allocated instance slot from a pool
+    Pooled,
+}
+
+impl InstantiationPath {
+    pub const ALL: [InstantiationPath; 4] = [
+        InstantiationPath::Cold,
+        InstantiationPath::CachedCompile,
+        InstantiationPath::Snapshot,
+        InstantiationPath::Pooled,
+    ];
+
+    /// Name used in records
+    pub fn name(self) -> &'static str {
+        match self {
+            InstantiationPath::Cold => "cold",
+            InstantiationPath::CachedCompile => "cached",
+            InstantiationPath::Snapshot => "snapshot",
+            InstantiationPath::Pooled => "pooled",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|path| path.name() == name)
+    }
+}
+
+/// Measured instantiation latency of one path on one runtime
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupRecord {
+    pub path: InstantiationPath,
+    /// Runtime instantiated in, such as `wasmtime` or `chrome`
+    pub runtime: String,
+    pub iterations: usize,
+    pub mean_ns: u64,
+    pub median_ns: u64,
+    pub p99_ns: u64,
+}
+
+impl StartupRecord {
+    fn from_samples(path: InstantiationPath, runtime: &str, samples: Vec<u64>) -> Self {
+        let iterations = samples.len();
+        let (mean_ns, median_ns, p99_ns) = summarize(samples);
+        Self { path, runtime: runtime.to_string(), iterations, mean_ns, median_ns, p99_ns }
+    }
+
+    /// Renders the record as a JSON line in `STARTUP_SCHEMA`
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"schema\":{},\"path\":{},\"runtime\":{},\"iterations\":{},\"mean_ns\":{},\"median_ns\":{},\"p99_ns\":{}}}",
+            js_string(STARTUP_SCHEMA),
+            js_string(self.path.name()),
+            js_string(&self.runtime),
+            self.iterations,
+            self.mean_ns,
+            self.median_ns,
+            self.p99_ns,
+        )
+    }
+
+    /// Parses records written by `to_json` or the browser driver, one per line
+    ///
+    /// Blank lines and lines in other schemas are ignored.
+    pub fn parse_lines(text: &str) -> Result<Vec<Self>, BackendError> {
+        let mut records = Vec::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields = json_fields(line)
+                .ok_or_else(|| BackendError::CompilationFailed(format!("invalid startup record: {}", line)))?;
+            if fields.get("schema").map(String::as_str) != Some(STARTUP_SCHEMA) {
+                continue;
+            }
+            let text = |key: &str| {
+                fields.get(key)
+                    .ok_or_else(|| BackendError::CompilationFailed(format!("startup record without {}: {}", key, line)))
+            };
+            let number = |key: &str| {
+                text(key)?.parse::<u64>()
+                    .map_err(|_| BackendError::CompilationFailed(format!("invalid {} in startup record: {}", key, line)))
+            };
+            let path = text("path")?;
+            records.push(StartupRecord {
+                path: InstantiationPath::from_name(path)
+                    .ok_or_else(|| BackendError::CompilationFailed(format!("unknown instantiation path {}", path)))?,
+                runtime: text("runtime")?.clone(),
+                iterations: number("iterations")? as usize,
+                mean_ns: number("mean_ns")?,
+                median_ns: number("median_ns")?,
+                p99_ns: number("p99_ns")?,
+            });
+        }
+        Ok(records)
+    }
+}
+
+/// Instantiates a module in an embedded runtime
+pub trait InstantiationRunner {
+    /// Runtime name recorded with the results
+    fn runtime(&self) -> &str;
+
+    /// Whether the runtime has a path, such as a pooling allocator
+    fn supports(&self, path: InstantiationPath) -> bool;
+
+    /// Untimed setup for a path: compiling the module for later reuse,
+    /// capturing the snapshot, or reserving the pool
+    fn prepare(&mut self, path: InstantiationPath, wasm: &[u8]) -> Result<(), BackendError>;
+
+    /// Creates and drops one instance along a path
+    fn instantiate(&mut self, path: InstantiationPath, wasm: &[u8]) -> Result<(), BackendError>;
+}
+
+/// Times instantiation along each path
+#[derive(Debug, Clone)]
+pub struct StartupBenchmark {
+    paths: Vec<InstantiationPath>,
+    warmup: usize,
+    iterations: usize,
+}
+
+impl Default for StartupBenchmark {
+    fn default() -> Self {
+        Self { paths: InstantiationPath::ALL.to_vec(), warmup: 5, iterations: 50 }
+    }
+}
+
+impl StartupBenchmark {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts the measured paths
+    pub fn paths(mut self, paths: &[InstantiationPath]) -> Self {
+        self.paths = paths.to_vec();
+        self
+    }
+
+    /// Sets the unmeasured and measured instantiations per path
+    pub fn iterations(mut self, warmup: usize, iterations: usize) -> Self {
+        self.warmup = warmup;
+        self.iterations = iterations.max(1);
+        self
+    }
+
+    /// Times every path the runner supports
+    pub fn run<R: InstantiationRunner>(&self, runner: &mut R, wasm: &[u8]) -> Result<Vec<StartupRecord>, BackendError> {
+        let mut records = Vec::new();
+        for &path in &self.paths {
+            if !runner.supports(path) {
+                continue;
+            }
+            runner.prepare(path, wasm)?;
+            for _ in 0..self.warmup {
+                runner.instantiate(path, wasm)?;
+            }
+            let mut samples = Vec::with_capacity(self.iterations);
+            for _ in 0..self.iterations {
+                let start = Instant::now();
+                runner.instantiate(path, wasm)?;
+                samples.push(start.elapsed().as_nanos() as u64);
+            }
+            records.push(StartupRecord::from_samples(path, runner.runtime(), samples));
+        }
+        Ok(records)
+    }
+
+    /// Generates the browser driver, measuring the cold and cached paths
+    ///
+    /// Browsers have neither instance snapshots nor pooling allocators, so
+    /// those paths are only measured in embedded runtimes. The module
+    /// imports the glue from `glue_file` and exports
+    /// `runStartupBenchmarks(runtime)`, which fetches `wasm_file` once,
+    /// logs each record as a JSON line, and resolves to the records.
+    pub fn browser_driver(&self, glue_file: &str, wasm_file: &str) -> String {
+        let mut out = String::new();
+        out.push_str("// Generated by WasmRust. Do not edit.\n\n");
+        let _ = writeln!(out, "import * as glue from {};\n", js_string(glue_file));
+        let _ = writeln!(out, "const WARMUP = {};", self.warmup);
+        let _ = writeln!(out, "const ITERATIONS = {};\n", self.iterations);
+
+        out.push_str("function rank(sorted, p) {\n");
+        out.push_str("  return sorted[Math.max(Math.ceil(sorted.length * p / 100) - 1, 0)];\n");
+        out.push_str("}\n\n");
+
+        out.push_str("export async function runStartupBenchmarks(runtime = \"chrome\") {\n");
+        let _ = writeln!(
+            out,
+            "  const bytes = await (await fetch(new URL({}, import.meta.url))).arrayBuffer();",
+            js_string(wasm_file)
+        );
+        out.push_str("  const compiled = await WebAssembly.compile(bytes);\n");
+        out.push_str("  const paths = [\n");
+        for path in &self.paths {
+            match path {
+                InstantiationPath::Cold => {
+                    out.push_str("    [\"cold\", async () => glue.initSync(await WebAssembly.compile(bytes))],\n");
+                }
+                InstantiationPath::CachedCompile => {
+                    out.push_str("    [\"cached\", async () => glue.initSync(compiled)],\n");
+                }
+                InstantiationPath::Snapshot | InstantiationPath::Pooled => {}
+            }
+        }
+        out.push_str("  ];\n");
+        out.push_str("  const records = [];\n");
+        out.push_str("  for (const [path, instantiate] of paths) {\n");
+        out.push_str("    for (let i = 0; i < WARMUP; i++) await instantiate();\n");
+        out.push_str("    const samples = [];\n");
+        out.push_str("    for (let i = 0; i < ITERATIONS; i++) {\n");
+        out.push_str("      const start = performance.now();\n");
+        out.push_str("      await instantiate();\n");
+        out.push_str("      samples.push(Math.floor((performance.now() - start) * 1e6));\n");
+        out.push_str("    }\n");
+        out.push_str("    samples.sort((a, b) => a - b);\n");
+        out.push_str("    const record = {\n");
+        let _ = writeln!(out, "      schema: {},", js_string(STARTUP_SCHEMA));
+        out.push_str("      path,\n");
+        out.push_str("      runtime,\n");
+        out.push_str("      iterations: ITERATIONS,\n");
+        out.push_str("      mean_ns: Math.floor(samples.reduce((sum, sample) => sum + sample, 0) / samples.length),\n");
+        out.push_str("      median_ns: rank(samples, 50),\n");
+        out.push_str("      p99_ns: rank(samples, 99),\n");
+        out.push_str("    };\n");
+        out.push_str("    console.log(JSON.stringify(record));\n");
+        out.push_str("    records.push(record);\n");
+        out.push_str("  }\n");
+        out.push_str("  return records;\n");
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Median latency that exceeded its limit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupRegression {
+    pub path: InstantiationPath,
+    pub runtime: String,
+    pub median_ns: u64,
+    pub limit_ns: u64,
+}
+
+impl std::fmt::Display for StartupRegression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} instantiation on {} took {}ns, over the {}ns limit",
+            self.path.name(),
+            self.runtime,
+            self.median_ns,
+            self.limit_ns
+        )
+    }
+}
+
+/// Regression limits on median instantiation latency
+#[derive(Debug, Clone)]
+pub struct StartupThresholds {
+    tolerance_percent: u64,
+    budgets: BTreeMap<InstantiationPath, u64>,
+}
+
+impl Default for StartupThresholds {
+    fn default() -> Self {
+        Self { tolerance_percent: 10, budgets: BTreeMap::new() }
+    }
+}
+
+impl StartupThresholds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how much slower than the baseline a path may get
+    pub fn tolerance_percent(mut self, percent: u64) -> Self {
+        self.tolerance_percent = percent;
+        self
+    }
+
+    /// Sets an absolute limit for a path on every runtime
+    pub fn budget(mut self, path: InstantiationPath, max_ns: u64) -> Self {
+        self.budgets.insert(path, max_ns);
+        self
+    }
+
+    /// Compares records against the baseline's and the budgets
+    ///
+    /// Paths missing from the baseline are only held to their budget.
+    pub fn check(&self, records: &[StartupRecord], baseline: &[StartupRecord]) -> Vec<StartupRegression> {
+        records.iter()
+            .filter_map(|record| {
+                let previous = baseline.iter()
+                    .find(|previous| previous.path == record.path && previous.runtime == record.runtime)
+                    .map(|previous| previous.median_ns.saturating_mul(100 + self.tolerance_percent) / 100);
+                let limit_ns = match (previous, self.budgets.get(&record.path)) {
+                    (Some(previous), Some(&budget)) => previous.min(budget),
+                    (Some(limit), None) | (None, Some(&limit)) => limit,
+                    (None, None) => return None,
+                };
+                (record.median_ns > limit_ns).then(|| StartupRegression {
+                    path: record.path,
+                    runtime: record.runtime.clone(),
+                    median_ns: record.median_ns,
+                    limit_ns,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Fields of a flat JSON object whose values are strings or integers
+fn json_fields(line: &str) -> Option<BTreeMap<String, String>> {
+    let mut rest = line.strip_prefix('{')?.strip_suffix('}')?.trim();
+    let mut fields = BTreeMap::new();
+    while !rest.is_empty() {
+        let (key, after) = json_string(rest)?;
+        let after = after.trim_start().strip_prefix(':')?.trim_start();
+        let (value, after) = if after.starts_with('"') {
+            json_string(after)?
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (after[..end].trim().to_string(), &after[end..])
+        };
+        fields.insert(key, value);
+        rest = after.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Some(fields)
+}
+
+/// Splits a leading JSON string without escapes other than `\"` and `\\`
+fn json_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[i + 2..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Mock {
+        prepared: Vec<InstantiationPath>,
+        instances: usize,
+    }
+
+    impl InstantiationRunner for Mock {
+        fn runtime(&self) -> &str {
+            "wasmtime"
+        }
+
+        fn supports(&self, path: InstantiationPath) -> bool {
+            path != InstantiationPath::Snapshot
+        }
+
+        fn prepare(&mut self, path: InstantiationPath, wasm: &[u8]) -> Result<(), BackendError> {
+            assert_eq!(wasm, b"\0asm");
+            self.prepared.push(path);
+            Ok(())
+        }
+
+        fn instantiate(&mut self, _: InstantiationPath, _: &[u8]) -> Result<(), BackendError> {
+            self.instances += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_run_times_supported_paths() {
+        let mut runner = Mock { prepared: Vec::new(), instances: 0 };
+        let records = StartupBenchmark::new().iterations(2, 8).run(&mut runner, b"\0asm").unwrap();
+        let paths = [InstantiationPath::Cold, InstantiationPath::CachedCompile, InstantiationPath::Pooled];
+        assert_eq!(runner.prepared, paths);
+        assert_eq!(runner.instances, 3 * 10);
+        assert_eq!(records.iter().map(|record| record.path).collect::<Vec<_>>(), paths);
+        assert!(records.iter().all(|record| record.iterations == 8 && record.median_ns <= record.p99_ns));
+
+        let lines: Vec<String> = records.iter().map(StartupRecord::to_json).collect();
+        assert!(lines[0].starts_with("{\"schema\":\"wasmrust.startup/1\",\"path\":\"cold\",\"runtime\":\"wasmtime\",\"iterations\":8,"));
+        let text = format!("{}\n\n{{\"schema\":\"wasmrust.performance/1\",\"export\":\"f\"}}\n", lines.join("\n"));
+        assert_eq!(StartupRecord::parse_lines(&text).unwrap(), records);
+        assert!(StartupRecord::parse_lines("{\"schema\":\"wasmrust.startup/1\",\"path\":\"warm\"}").is_err());
+    }
+
+    #[test]
+    fn test_thresholds_flag_regressions() {
+        let record = |path, runtime: &str, median_ns| StartupRecord {
+            path,
+            runtime: runtime.to_string(),
+            iterations: 10,
+            mean_ns: median_ns,
+            median_ns,
+            p99_ns: median_ns,
+        };
+        let baseline = [record(InstantiationPath::Cold, "wasmtime", 1000), record(InstantiationPath::Cold, "chrome", 5000)];
+        let current = [
+            record(InstantiationPath::Cold, "wasmtime", 1150),
+            record(InstantiationPath::Cold, "chrome", 5400),
+            record(InstantiationPath::Pooled, "wasmtime", 90),
+            record(InstantiationPath::CachedCompile, "wasmtime", 1_000_000),
+        ];
+        let thresholds = StartupThresholds::new().budget(InstantiationPath::Pooled, 50);
+        let regressions = thresholds.check(&current, &baseline);
+        assert_eq!(regressions.iter().map(|r| (r.path, r.limit_ns)).collect::<Vec<_>>(), [
+            (InstantiationPath::Cold, 1100),
+            (InstantiationPath::Pooled, 50),
+        ]);
+        assert_eq!(regressions[0].to_string(), "cold instantiation on wasmtime took 1150ns, over the 1100ns limit");
+        assert!(thresholds.tolerance_percent(20).check(&current[..2], &baseline).is_empty());
+    }
+
+    #[test]
+    fn test_browser_driver_measures_cold_and_cached() {
+        let driver = StartupBenchmark::new().iterations(1, 20).browser_driver("./app.mjs", "app.wasm");
+        assert!(driver.contains("import * as glue from \"./app.mjs\";"));
+        assert!(driver.contains("const ITERATIONS = 20;"));
+        assert!(driver.contains("await fetch(new URL(\"app.wasm\", import.meta.url))"));
+        assert!(driver.contains("    [\"cold\", async () => glue.initSync(await WebAssembly.compile(bytes))],\n    [\"cached\", async () => glue.initSync(compiled)],\n  ];"));
+        assert!(driver.contains("schema: \"wasmrust.startup/1\""));
+        assert!(!driver.contains("snapshot"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Proposals a build targets, in rustc's `-C target-feature` spelling
+//!
+//! `TargetFeatures` starts from the WebAssembly 2.0 `FeatureSet` and takes
+//! `+feature` and `-feature` items, as in `+simd128,+atomics,-sign-ext`.
+//! Backends check modules against the resulting set, rustc sees it as
+//! `cfg(target_feature)`, and codegen records it in the `target_features`
+//! custom section that wasm-ld and wasm-opt read, like LLVM does.
+
+use std::fmt;
+use wasm::wasmir::features::{FeatureSet, Proposal};
+
+/// Custom section listing the features a module was compiled with
+pub const TARGET_FEATURES_SECTION: &str = "target_features";
+
+/// Proposals enabled and disabled for a build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TargetFeatures {
+    set: FeatureSet,
+}
+
+impl TargetFeatures {
+    pub fn new(set: FeatureSet) -> Self {
+        Self { set }
+    }
+
+    /// Applies comma-separated `+feature` and `-feature` items in order
+    pub fn apply(mut self, spec: &str) -> Result<Self, TargetFeatureError> {
+        for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (enable, name) = match (item.strip_prefix('+'), item.strip_prefix('-')) {
+                (Some(name), _) => (true, name),
+                (_, Some(name)) => (false, name),
+                _ => return Err(TargetFeatureError::MissingSign(item.to_string())),
+            };
+            let proposal = Proposal::from_target_feature(name)
+                .ok_or_else(|| TargetFeatureError::Unknown(name.to_string()))?;
+            self = if enable { self.enable(proposal) } else { self.disable(proposal) };
+        }
+        Ok(self)
+    }
+
+    pub fn enable(self, proposal: Proposal) -> Self {
+        Self { set: self.set.with(proposal) }
+    }
+
+    pub fn disable(self, proposal: Proposal) -> Self {
+        Self { set: self.set.without(proposal) }
+    }
+
+    pub fn contains(self, proposal: Proposal) -> bool {
+        self.set.contains(proposal)
+    }
+
+    /// The proposals backends check modules against
+    pub fn set(self) -> FeatureSet {
+        self.set
+    }
+
+    /// Enabled proposals, in `Proposal::ALL` order
+    pub fn enabled(self) -> impl Iterator<Item = Proposal> {
+        Proposal::ALL.into_iter().filter(move |&proposal| self.contains(proposal))
+    }
+}
+
+/// Every proposal with its sign, as rustc takes them in `-C target-feature`
+impl fmt::Display for TargetFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, proposal) in Proposal::ALL.into_iter().enumerate() {
+            let sign = if self.contains(proposal) { '+' } else { '-' };
+            write!(f, "{}{}{}", if index == 0 { "" } else { "," }, sign, proposal.target_feature())?;
+        }
+        Ok(())
+    }
+}
+
+/// Item of a `-C target-feature` list that does not parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetFeatureError {
+    /// Neither `+` nor `-` says whether to enable the feature
+    MissingSign(String),
+    /// The feature names no proposal the backends know
+    Unknown(String),
+}
+
+impl fmt::Display for TargetFeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetFeatureError::MissingSign(item) => {
+                write!(f, "target feature `{}` needs `+` to enable it or `-` to disable it", item)
+            }
+            TargetFeatureError::Unknown(name) => {
+                let names: Vec<_> = Proposal::ALL.iter().map(|proposal| proposal.target_feature()).collect();
+                write!(f, "unknown target feature `{}`; expected one of {}", name, names.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for TargetFeatureError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applies_items_in_order() {
+        let features = TargetFeatures::default().apply("+simd128,+atomics,-sign-ext").unwrap();
+        assert!(features.contains(Proposal::Simd) && features.contains(Proposal::Threads));
+        assert!(!features.contains(Proposal::SignExtension));
+        assert!(features.contains(Proposal::BulkMemory));
+        assert!(!TargetFeatures::default().apply("+simd128,-simd128").unwrap().contains(Proposal::Simd));
+        assert_eq!(TargetFeatures::default().apply("").unwrap(), TargetFeatures::default());
+
+        assert_eq!(
+            TargetFeatures::default().apply("+tail-call").unwrap_err().to_string(),
+            "unknown target feature `tail-call`; expected one of reference-types, multivalue, atomics, \
+             exception-handling, sign-ext, nontrapping-fptoint, bulk-memory, simd128"
+        );
+        assert_eq!(
+            TargetFeatures::default().apply("simd128"),
+            Err(TargetFeatureError::MissingSign("simd128".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_displays_as_rustc_target_features() {
+        let features = TargetFeatures::new(FeatureSet::mvp()).enable(Proposal::Simd).enable(Proposal::BulkMemory);
+        assert_eq!(
+            features.to_string(),
+            "-reference-types,-multivalue,-atomics,-exception-handling,-sign-ext,-nontrapping-fptoint,\
+             +bulk-memory,+simd128"
+        );
+        assert_eq!(TargetFeatures::default().apply(&features.to_string()).unwrap(), features);
+        assert_eq!(features.enabled().collect::<Vec<_>>(), [Proposal::BulkMemory, Proposal::Simd]);
+    }
+}
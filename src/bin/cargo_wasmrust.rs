@@ -0,0 +1,45 @@
+//! `cargo wasmrust`, the Cargo subcommand building packages with WasmRust
+//!
+//! See `wasmrust_compiler::driver::cargo` for the verbs and profiles.
+
+use std::env;
+use std::process;
+
+use wasmrust_compiler::driver::cargo::{self, CargoCommand};
+use wasmrust_compiler::driver;
+
+fn main() -> process::ExitCode {
+    // Cargo passes the subcommand's name first
+    let args: Vec<String> = env::args().skip(1).skip_while(|arg| arg == "wasmrust").collect();
+    let options = match cargo::parse_args(&args) {
+        Ok(CargoCommand::Help) => {
+            print!("{}", cargo::USAGE);
+            return process::ExitCode::SUCCESS;
+        }
+        Ok(CargoCommand::Version) => {
+            println!("cargo-wasmrust {}", wasmrust_compiler::VERSION);
+            return process::ExitCode::SUCCESS;
+        }
+        Ok(CargoCommand::Run(options)) => options,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprint!("\n{}", cargo::USAGE);
+            return process::ExitCode::FAILURE;
+        }
+    };
+
+    if options.watch {
+        // Watching only ends in an error
+        let err = cargo::watch(&options).unwrap_err();
+        driver::print_error(&err, options.error_format);
+        return process::ExitCode::FAILURE;
+    }
+    match cargo::run(&options) {
+        Ok(true) => process::ExitCode::SUCCESS,
+        Ok(false) => process::ExitCode::FAILURE,
+        Err(err) => {
+            driver::print_error(&err, options.error_format);
+            process::ExitCode::FAILURE
+        }
+    }
+}
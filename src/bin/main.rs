@@ -5,8 +5,16 @@
 //! with WASM-specific optimizations.
 
 use std::env;
+use std::path::Path;
 use std::process;
 
+use wasmrust_compiler::backend::abi_check::HostInterface;
+use wasmrust_compiler::backend::emscripten::read_em_js;
+use wasmrust_compiler::backend::interface::read_interface;
+use wasmrust_compiler::backend::js_glue::{GlueFormat, JsGlueGenerator};
+use wasmrust_compiler::scaffold::{ProjectGenerator, Template};
+use wasm::wasmir::features::Proposal;
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() -> process::ExitCode {
@@ -26,6 +34,16 @@ fn main() -> process::ExitCode {
             print_usage();
             process::ExitCode::SUCCESS
         }
+        "inspect" => match args.get(2) {
+            Some(path) => inspect(path),
+            None => {
+                eprintln!("usage: wasmrust inspect <component.wasm>");
+                process::ExitCode::FAILURE
+            }
+        },
+        "new" => new_project(&args[2..]),
+        "glue" => glue(&args[2..]),
+        "check-abi" => check_abi(&args[2..]),
         _ => {
             // For now, just indicate that compilation is not yet implemented
             eprintln!("WasmRust compiler is under development");
@@ -35,11 +53,221 @@ fn main() -> process::ExitCode {
     }
 }
 
+/// Prints a component's worlds, imports, exports, modules, and adapter issues
+fn inspect(path: &str) -> process::ExitCode {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("error: cannot read {}: {}", path, err);
+            return process::ExitCode::FAILURE;
+        }
+    };
+    match wasm::component::inspect(&bytes) {
+        Ok(info) => {
+            print!("{}", info);
+            if info.is_valid() {
+                process::ExitCode::SUCCESS
+            } else {
+                process::ExitCode::FAILURE
+            }
+        }
+        Err(err) => {
+            eprintln!("error: {}: {}", path, err);
+            process::ExitCode::FAILURE
+        }
+    }
+}
+
+/// Generates a project from a template into a new directory
+fn new_project(args: &[String]) -> process::ExitCode {
+    let usage = || {
+        eprintln!("usage: wasmrust new <name> [--template <template>] [--wasm-path <dir>]");
+        process::ExitCode::FAILURE
+    };
+    let mut name = None;
+    let mut template = Template::WebApp;
+    let mut wasm_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--template" => {
+                let Some(value) = args.next() else { return usage() };
+                let Some(t) = Template::from_name(value) else {
+                    let names: Vec<_> = Template::ALL.iter().map(|t| t.name()).collect();
+                    eprintln!("error: unknown template `{}`; expected one of {}", value, names.join(", "));
+                    return process::ExitCode::FAILURE;
+                };
+                template = t;
+            }
+            "--wasm-path" => {
+                let Some(path) = args.next() else { return usage() };
+                wasm_path = Some(path.clone());
+            }
+            other if name.is_none() && !other.starts_with('-') => name = Some(other.to_string()),
+            _ => return usage(),
+        }
+    }
+    let Some(name) = name else { return usage() };
+
+    let mut generator = ProjectGenerator::new(name.clone(), template);
+    if let Some(path) = wasm_path {
+        generator = generator.wasm_path(path);
+    }
+    match generator.write(Path::new(&name)) {
+        Ok(files) => {
+            println!("Created {} project `{}` ({} files)", template.name(), name, files.len());
+            process::ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::ExitCode::FAILURE
+        }
+    }
+}
+
+/// Writes the JS glue for a core module built by cargo next to it
+fn glue(args: &[String]) -> process::ExitCode {
+    let usage = || {
+        eprintln!(
+            "usage: wasmrust glue <module.wasm> [--format esm|cjs] [--fallback <single-threaded.wasm>] \
+             [--variant <file.wasm>=<features>]... [--emscripten]"
+        );
+        process::ExitCode::FAILURE
+    };
+    let mut input = None;
+    let mut format = GlueFormat::default();
+    let mut fallback = None;
+    let mut variants = Vec::new();
+    let mut emscripten = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let Some(f) = args.next().and_then(|value| GlueFormat::from_name(value)) else { return usage() };
+                format = f;
+            }
+            "--fallback" => {
+                let Some(file) = args.next() else { return usage() };
+                fallback = Some(file.clone());
+            }
+            // Features in `-C target-feature` spelling, such as `app.simd.wasm=simd128,bulk-memory`
+            "--variant" => {
+                let Some((file, features)) = args.next().and_then(|value| value.split_once('=')) else {
+                    return usage();
+                };
+                let features: Option<Vec<_>> = features.split(',')
+                    .filter(|name| !name.is_empty())
+                    .map(Proposal::from_target_feature)
+                    .collect();
+                let Some(features) = features else { return usage() };
+                variants.push((file.to_string(), features));
+            }
+            // For modules built for wasm32-unknown-emscripten
+            "--emscripten" => emscripten = true,
+            other if input.is_none() && !other.starts_with('-') => input = Some(Path::new(other)),
+            _ => return usage(),
+        }
+    }
+    let Some(input) = input else { return usage() };
+    let Some(wasm_file) = input.file_name().map(|name| name.to_string_lossy().into_owned()) else { return usage() };
+
+    let output = input.with_extension(format.extension());
+    let glue = std::fs::read(input)
+        .map_err(|err| format!("cannot read {}: {}", input.display(), err))
+        .and_then(|bytes| {
+            let module = read_interface(&bytes).map_err(|err| format!("{}: {}", input.display(), err))?;
+            let em_js = match emscripten {
+                true => Some(read_em_js(&bytes).map_err(|err| format!("{}: {}", input.display(), err))?),
+                false => None,
+            };
+            Ok((module, em_js))
+        })
+        .and_then(|(module, em_js)| {
+            let mut generator = JsGlueGenerator::new(wasm_file).format(format);
+            if let Some(em_js) = em_js {
+                generator = generator.emscripten(em_js);
+            }
+            if let Some(fallback) = fallback {
+                generator = generator.fallback(fallback);
+            }
+            for (file, features) in variants {
+                generator = generator.variant(file, features);
+            }
+            generator.generate(&module).map_err(|err| err.to_string())
+        })
+        .and_then(|glue| std::fs::write(&output, glue).map_err(|err| format!("cannot write {}: {}", output.display(), err)));
+    match glue {
+        Ok(()) => {
+            println!("Wrote {}", output.display());
+            process::ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::ExitCode::FAILURE
+        }
+    }
+}
+
+/// Diffs a module's imports and exports against its host's interface
+fn check_abi(args: &[String]) -> process::ExitCode {
+    let usage = || {
+        eprintln!("usage: wasmrust check-abi <module.wasm> <host.wit|host.json> [--world <name>]");
+        process::ExitCode::FAILURE
+    };
+    let mut paths = Vec::new();
+    let mut world = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--world" => {
+                let Some(name) = args.next() else { return usage() };
+                world = Some(name.clone());
+            }
+            other if paths.len() < 2 && !other.starts_with('-') => paths.push(Path::new(other)),
+            _ => return usage(),
+        }
+    }
+    let [module, host] = paths[..] else { return usage() };
+
+    let mismatches = std::fs::read(module)
+        .map_err(|err| format!("cannot read {}: {}", module.display(), err))
+        .and_then(|bytes| {
+            let interface = HostInterface::load(host, world.as_deref()).map_err(|err| err.to_string())?;
+            interface.check(&bytes).map_err(|err| format!("{}: {}", module.display(), err))
+        });
+    match mismatches {
+        Ok(mismatches) if mismatches.is_empty() => {
+            println!("{} matches {}", module.display(), host.display());
+            process::ExitCode::SUCCESS
+        }
+        Ok(mismatches) => {
+            for mismatch in &mismatches {
+                eprintln!("error: {}", mismatch);
+            }
+            let count = match mismatches.len() {
+                1 => "1 mismatch".to_string(),
+                count => format!("{} mismatches", count),
+            };
+            eprintln!("{} does not match {}: {}", module.display(), host.display(), count);
+            process::ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::ExitCode::FAILURE
+        }
+    }
+}
+
 fn print_usage() {
     println!("WasmRust - Rust-to-WebAssembly Compiler");
     println!();
     println!("Usage:");
     println!("  wasmrust [OPTIONS] <input>");
+    println!("  wasmrust inspect <component.wasm>");
+    println!("  wasmrust new <name> [--template web-app|component-plugin|wasi-cli|worker-pool]");
+    println!("  wasmrust glue <module.wasm> [--format esm|cjs] [--fallback <single-threaded.wasm>]");
+    println!("                [--variant <file.wasm>=<features>]... [--emscripten]");
+    println!("  wasmrust check-abi <module.wasm> <host.wit|host.json> [--world <name>]");
     println!();
     println!("Options:");
     println!("  -V, --version     Print version information");
@@ -51,6 +279,7 @@ fn print_usage() {
     println!("Examples:");
     println!("  wasmrust --emit ir my_crate.rs");
     println!("  wasmrust --optimize --backend llvm my_crate.rs");
+    println!("  wasmrust new my-plugin --template component-plugin");
 }
 
 #[cfg(test)]
@@ -0,0 +1,40 @@
+//! `wasm-rustc`, the WasmRust compiler driver
+//!
+//! Compiles a crate to WebAssembly with the flags described in
+//! `wasmrust_compiler::driver`.
+
+use std::env;
+use std::process;
+
+use wasmrust_compiler::driver::{self, Command};
+
+fn main() -> process::ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match driver::parse_args(&args) {
+        Ok(Command::Help) => {
+            print!("{}", driver::USAGE);
+            return process::ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("wasm-rustc {}", wasmrust_compiler::VERSION);
+            return process::ExitCode::SUCCESS;
+        }
+        Ok(Command::Compile(options)) => *options,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprint!("\n{}", driver::USAGE);
+            return process::ExitCode::FAILURE;
+        }
+    };
+
+    if options.watch {
+        driver::watch(&options);
+    }
+    match driver::compile(&options) {
+        Ok(_) => process::ExitCode::SUCCESS,
+        Err(err) => {
+            driver::print_error(&err, options.error_format);
+            process::ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,558 @@
+//! Compiler diagnostics
+//!
+//! Errors and warnings carry a stable code, a primary span, labelled
+//! secondary spans, notes, and suggested fixes. `Renderer` prints them the
+//! way rustc does, quoting the source lines of files it was given the text
+//! of and falling back to bare locations otherwise. Editors and CI read
+//! them as JSON lines in `DIAGNOSTIC_SCHEMA` instead.
+
+use crate::backend::js_glue::js_string;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use wasm::wasmir::SourceLocation;
+
+/// Schema tag of diagnostics emitted as JSON
+pub const DIAGNOSTIC_SCHEMA: &str = "wasmrust.diagnostic/1";
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        }
+    }
+}
+
+/// Stable diagnostic code, printed as `WR0001`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Code(pub u16);
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WR{:04}", self.0)
+    }
+}
+
+/// Codes emitted by the compiler
+///
+/// Codes are never reused once released, so tools and documentation can
+/// key on them.
+pub mod codes {
+    use super::Code;
+
+    /// A MIR place names a local the function does not declare
+    pub const UNKNOWN_LOCAL: Code = Code(1);
+    /// A MIR terminator targets a basic block the function does not have
+    pub const INVALID_BLOCK_TARGET: Code = Code(2);
+    /// A slice or vector crossing the host boundary has an unsupported element type
+    pub const UNSUPPORTED_ELEMENT_TYPE: Code = Code(3);
+    /// More than one function is marked as the start function
+    pub const MULTIPLE_START_FUNCTIONS: Code = Code(4);
+    /// Lowering produced WasmIR that fails validation
+    pub const INVALID_LOWERING: Code = Code(5);
+    /// Size of a function's code, reported as a note
+    pub const CODE_SIZE: Code = Code(6);
+    /// A linear value is used or moved after it was moved
+    pub const USE_OF_MOVED_VALUE: Code = Code(7);
+    /// An externref, funcref, or owned resource handle is dropped without being consumed
+    pub const UNCONSUMED_LINEAR_VALUE: Code = Code(8);
+    /// A MIR construct has no WasmIR lowering yet
+    pub const UNSUPPORTED_MIR: Code = Code(9);
+    /// A projection does not apply to the type of the place it projects
+    pub const INVALID_PROJECTION: Code = Code(10);
+    /// A cast between types that `as` does not convert between
+    pub const INVALID_CAST: Code = Code(11);
+}
+
+/// Source location with an optional label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub location: SourceLocation,
+    pub label: Option<String>,
+    /// Whether this is the location the diagnostic is about, rather than a
+    /// related one
+    pub primary: bool,
+}
+
+/// Suggested fix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub message: String,
+    /// Code to write at the primary span, when the fix is mechanical
+    pub replacement: Option<String>,
+}
+
+/// Diagnostic reported by the compiler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<Code>,
+    pub message: String,
+    /// At most one primary span, then secondary spans
+    pub spans: Vec<Span>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            spans: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn error(code: Code, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message).code(code)
+    }
+
+    pub fn warning(code: Code, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message).code(code)
+    }
+
+    pub fn code(mut self, code: Code) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Sets the location the diagnostic is about
+    pub fn primary(mut self, location: SourceLocation, label: impl Into<String>) -> Self {
+        self.spans.retain(|span| !span.primary);
+        self.spans.insert(0, span(location, label.into(), true));
+        self
+    }
+
+    /// Adds a related location
+    pub fn secondary(mut self, location: SourceLocation, label: impl Into<String>) -> Self {
+        self.spans.push(span(location, label.into(), false));
+        self
+    }
+
+    pub fn primary_span(&self) -> Option<&Span> {
+        self.spans.iter().find(|span| span.primary)
+    }
+
+    pub fn secondary_spans(&self) -> impl Iterator<Item = &Span> {
+        self.spans.iter().filter(|span| !span.primary)
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Adds a suggested fix described in prose
+    pub fn help(mut self, message: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion { message: message.into(), replacement: None });
+        self
+    }
+
+    /// Adds a suggested fix replacing the code at the primary span
+    pub fn suggest(mut self, message: impl Into<String>, replacement: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion { message: message.into(), replacement: Some(replacement.into()) });
+        self
+    }
+
+    /// Note recording how many bytes of code a function compiled to
+    pub fn code_size(function: &str, bytes: usize) -> Self {
+        Self::new(Severity::Note, format!("`{}` compiles to {} bytes of code", function, bytes))
+            .code(codes::CODE_SIZE)
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+fn span(location: SourceLocation, label: String, primary: bool) -> Span {
+    Span { location, label: (!label.is_empty()).then_some(label), primary }
+}
+
+/// One-line form, `file:line:column: error[WR0001]: message`
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(primary) = self.primary_span() {
+            let location = &primary.location;
+            write!(f, "{}:{}:{}: ", location.file, location.line, location.column)?;
+        }
+        write!(f, "{}", self.severity.name())?;
+        if let Some(code) = self.code {
+            write!(f, "[{}]", code)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Diagnostics collected over a compilation
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.items.push(diagnostic);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(Diagnostic::is_error)
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.items.iter().filter(|d| d.is_error()).count()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn as_slice(&self) -> &[Diagnostic] {
+        &self.items
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+/// How diagnostics are printed, as selected by `--message-format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+    /// Rendered with source snippets
+    #[default]
+    Human,
+    /// One `file:line:column: error[WR0001]: message` line each
+    Short,
+    /// One JSON object per line in `DIAGNOSTIC_SCHEMA`
+    Json,
+}
+
+impl MessageFormat {
+    pub const ALL: [MessageFormat; 3] = [MessageFormat::Human, MessageFormat::Short, MessageFormat::Json];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageFormat::Human => "human",
+            MessageFormat::Short => "short",
+            MessageFormat::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+}
+
+/// Renders diagnostics in the style of rustc
+///
+/// ```text
+/// error[WR0004]: multiple start functions
+///  --> src/lib.rs:9:1
+///   |
+/// 3 | fn init() {}
+///   | - first start function
+/// ...
+/// 9 | fn main() {}
+///   | ^ second start function
+///   |
+///   = help: mark one of them as a constructor instead
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Renderer {
+    sources: HashMap<String, String>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Provides the text of a file, so spans in it quote their lines
+    pub fn with_source(mut self, file: impl Into<String>, text: impl Into<String>) -> Self {
+        self.sources.insert(file.into(), text.into());
+        self
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        out.push_str(diagnostic.severity.name());
+        if let Some(code) = diagnostic.code {
+            out.push_str(&format!("[{}]", code));
+        }
+        out.push_str(&format!(": {}\n", diagnostic.message));
+
+        // Spans grouped by file, the primary span's file first
+        let mut files: Vec<(&str, Vec<&Span>)> = Vec::new();
+        for span in &diagnostic.spans {
+            let file = span.location.file.as_str();
+            match files.iter_mut().find(|(name, _)| *name == file) {
+                Some((_, group)) => group.push(span),
+                None => files.push((file, vec![span])),
+            }
+        }
+        let width = diagnostic.spans.iter()
+            .map(|span| span.location.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(width);
+
+        for (index, (file, group)) in files.iter_mut().enumerate() {
+            group.sort_by_key(|span| (span.location.line, span.location.column));
+            let anchor = group.iter().find(|span| span.primary).unwrap_or(&group[0]);
+            let arrow = if index == 0 && anchor.primary { "-->" } else { ":::" };
+            out.push_str(&format!(
+                "{} {} {}:{}:{}\n",
+                &pad[1..], arrow, file, anchor.location.line, anchor.location.column
+            ));
+
+            let Some(source) = self.sources.get(*file) else { continue };
+            let lines: Vec<&str> = source.lines().collect();
+            out.push_str(&format!("{} |\n", pad));
+            let mut previous: Option<u32> = None;
+            for span in group.iter() {
+                let line = span.location.line;
+                let Some(text) = (line as usize).checked_sub(1).and_then(|i| lines.get(i)) else { continue };
+                if previous.is_some_and(|p| line > p + 1) {
+                    out.push_str("...\n");
+                }
+                if previous != Some(line) {
+                    out.push_str(&format!("{:>width$} | {}\n", line, text, width = width));
+                }
+                previous = Some(line);
+
+                // Keep tabs so the marker lines up under the column
+                let indent: String = text.chars()
+                    .take(span.location.column.saturating_sub(1) as usize)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let marker = if span.primary { '^' } else { '-' };
+                let label = span.label.as_deref().map(|label| format!(" {}", label)).unwrap_or_default();
+                out.push_str(&format!("{} | {}{}{}\n", pad, indent, marker, label));
+            }
+        }
+
+        if !diagnostic.notes.is_empty() || !diagnostic.suggestions.is_empty() {
+            if !files.is_empty() {
+                out.push_str(&format!("{} |\n", pad));
+            }
+            for note in &diagnostic.notes {
+                out.push_str(&format!("{} = note: {}\n", pad, note));
+            }
+            for suggestion in &diagnostic.suggestions {
+                match &suggestion.replacement {
+                    Some(replacement) => {
+                        out.push_str(&format!("{} = help: {}: `{}`\n", pad, suggestion.message, replacement));
+                    }
+                    None => out.push_str(&format!("{} = help: {}\n", pad, suggestion.message)),
+                }
+            }
+        }
+        out
+    }
+
+    /// Renders every diagnostic, followed by a summary line if any is an error
+    pub fn render_all(&self, diagnostics: &Diagnostics) -> String {
+        let mut out: Vec<String> = diagnostics.iter().map(|d| self.render(d)).collect();
+        match diagnostics.error_count() {
+            0 => {}
+            1 => out.push("error: aborting due to 1 previous error\n".to_string()),
+            count => out.push(format!("error: aborting due to {} previous errors\n", count)),
+        }
+        out.join("\n")
+    }
+
+    /// Renders a diagnostic as a JSON line in `DIAGNOSTIC_SCHEMA`
+    ///
+    /// The `rendered` field holds the human-readable form, for tools that
+    /// show it verbatim.
+    pub fn to_json(&self, diagnostic: &Diagnostic) -> String {
+        let optional = |value: Option<&str>| value.map(js_string).unwrap_or_else(|| "null".to_string());
+        let spans: Vec<String> = diagnostic.spans.iter()
+            .map(|span| format!(
+                "{{\"file\":{},\"line\":{},\"column\":{},\"label\":{},\"primary\":{}}}",
+                js_string(&span.location.file),
+                span.location.line,
+                span.location.column,
+                optional(span.label.as_deref()),
+                span.primary,
+            ))
+            .collect();
+        let notes: Vec<String> = diagnostic.notes.iter().map(|note| js_string(note)).collect();
+        let suggestions: Vec<String> = diagnostic.suggestions.iter()
+            .map(|suggestion| format!(
+                "{{\"message\":{},\"replacement\":{}}}",
+                js_string(&suggestion.message),
+                optional(suggestion.replacement.as_deref()),
+            ))
+            .collect();
+        format!(
+            "{{\"schema\":{},\"severity\":{},\"code\":{},\"message\":{},\"spans\":[{}],\"notes\":[{}],\"suggestions\":[{}],\"rendered\":{}}}",
+            js_string(DIAGNOSTIC_SCHEMA),
+            js_string(diagnostic.severity.name()),
+            optional(diagnostic.code.map(|code| code.to_string()).as_deref()),
+            js_string(&diagnostic.message),
+            spans.join(","),
+            notes.join(","),
+            suggestions.join(","),
+            js_string(&self.render(diagnostic)),
+        )
+    }
+
+    /// Writes every diagnostic in `format`
+    ///
+    /// Human and short output end with the abort summary when there are
+    /// errors; JSON output is exactly one line per diagnostic.
+    pub fn emit(&self, out: &mut impl Write, format: MessageFormat, diagnostics: &Diagnostics) -> io::Result<()> {
+        match format {
+            MessageFormat::Human => out.write_all(self.render_all(diagnostics).as_bytes()),
+            MessageFormat::Short => {
+                for diagnostic in diagnostics {
+                    writeln!(out, "{}", diagnostic)?;
+                }
+                match diagnostics.error_count() {
+                    0 => Ok(()),
+                    1 => writeln!(out, "error: aborting due to 1 previous error"),
+                    count => writeln!(out, "error: aborting due to {} previous errors", count),
+                }
+            }
+            MessageFormat::Json => {
+                for diagnostic in diagnostics {
+                    writeln!(out, "{}", self.to_json(diagnostic))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(file: &str, line: u32, column: u32) -> SourceLocation {
+        SourceLocation { file: file.to_string(), line, column }
+    }
+
+    #[test]
+    fn test_renders_spans_with_source() {
+        let diagnostic = Diagnostic::error(codes::MULTIPLE_START_FUNCTIONS, "multiple start functions")
+            .primary(at("src/lib.rs", 10, 5), "second start function")
+            .secondary(at("src/lib.rs", 2, 1), "first start function")
+            .note("a module has at most one start function")
+            .help("mark one of them as a constructor instead");
+        let source = "#[start]\nfn init() {}\n\n\n\n\n\n\n#[start]\n\tfn main() {}\n";
+        let rendered = Renderer::new().with_source("src/lib.rs", source).render(&diagnostic);
+        assert_eq!(rendered, [
+            "error[WR0004]: multiple start functions",
+            "  --> src/lib.rs:10:5",
+            "   |",
+            " 2 | fn init() {}",
+            "   | - first start function",
+            "...",
+            "10 | \tfn main() {}",
+            "   | \t   ^ second start function",
+            "   |",
+            "   = note: a module has at most one start function",
+            "   = help: mark one of them as a constructor instead",
+            "",
+        ].join("\n"));
+        assert_eq!(diagnostic.to_string(), "src/lib.rs:10:5: error[WR0004]: multiple start functions");
+    }
+
+    #[test]
+    fn test_renders_without_source_and_counts_errors() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push(
+            Diagnostic::error(codes::UNKNOWN_LOCAL, "unknown local _7")
+                .primary(at("a.rs", 3, 9), "in this function")
+                .secondary(at("b.rs", 1, 1), "")
+                .suggest("declare it", "let _7: i32;"),
+        );
+        diagnostics.push(Diagnostic::new(Severity::Warning, "unused import"));
+        assert!(diagnostics.has_errors());
+        assert_eq!(diagnostics.error_count(), 1);
+
+        assert_eq!(Renderer::new().render_all(&diagnostics), [
+            "error[WR0001]: unknown local _7",
+            " --> a.rs:3:9",
+            " ::: b.rs:1:1",
+            "  |",
+            "  = help: declare it: `let _7: i32;`",
+            "",
+            "warning: unused import",
+            "",
+            "error: aborting due to 1 previous error",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_emits_json_lines() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push(
+            Diagnostic::error(codes::UNKNOWN_LOCAL, "unknown local \"_7\"")
+                .primary(at("a.rs", 3, 9), "")
+                .suggest("declare it", "let _7: i32;"),
+        );
+        diagnostics.push(Diagnostic::code_size("main", 42).note("after optimization"));
+
+        let mut out = Vec::new();
+        Renderer::new().emit(&mut out, MessageFormat::Json, &diagnostics).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], concat!(
+            "{\"schema\":\"wasmrust.diagnostic/1\",\"severity\":\"error\",\"code\":\"WR0001\",",
+            "\"message\":\"unknown local \\\"_7\\\"\",",
+            "\"spans\":[{\"file\":\"a.rs\",\"line\":3,\"column\":9,\"label\":null,\"primary\":true}],",
+            "\"notes\":[],\"suggestions\":[{\"message\":\"declare it\",\"replacement\":\"let _7: i32;\"}],",
+            "\"rendered\":\"error[WR0001]: unknown local \\\"_7\\\"\\n --> a.rs:3:9\\n  |\\n  = help: declare it: `let _7: i32;`\\n\"}",
+        ));
+        assert!(lines[1].contains("\"severity\":\"note\",\"code\":\"WR0006\",\"message\":\"`main` compiles to 42 bytes of code\""));
+        assert!(lines[1].contains("\"spans\":[],\"notes\":[\"after optimization\"]"));
+
+        let mut out = Vec::new();
+        Renderer::new().emit(&mut out, MessageFormat::from_name("short").unwrap(), &diagnostics).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), [
+            "a.rs:3:9: error[WR0001]: unknown local \"_7\"",
+            "note[WR0006]: `main` compiles to 42 bytes of code",
+            "error: aborting due to 1 previous error",
+            "",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,952 @@
+//! `cargo wasmrust`, building Cargo packages with WasmRust
+//!
+//! ```text
+//! cargo wasmrust build|run|test|serve [--release | --profile <name>] [-p <package>] [--bin <name>] [-- <test filters>]
+//! ```
+//!
+//! Cargo resolves the package graph through `cargo metadata`, and
+//! `BuildPlan` turns it into one rustc lowering per crate, dependencies
+//! first. Each library writes its metadata to `deps/` for the crates
+//! depending on it, and its module to `<crate>.wasm`, both under
+//! `target/wasmrust/<profile>/`. `run` then runs the package's binary on
+//! the in-process runtime and `test` its `#[test]` functions. With
+//! `--watch`, the verb runs again as sources change, skipping the crates
+//! whose fingerprints are the same as at their last build. `--timings`
+//! writes a trace of each build to `timings.json` in the output directory;
+//! see `timings`. `serve` builds
+//! like `build`, writing hot-reloading JavaScript glue next to each
+//! module, and watches while `serve::DevServer` serves the output.
+//!
+//! The `dev` and `test` Cargo profiles build with `BuildProfile::Development`,
+//! `release` and `bench` with `BuildProfile::Release`. Custom profiles
+//! name theirs in the workspace manifest:
+//!
+//! ```toml
+//! [workspace.metadata.wasmrust.profiles]
+//! embedded = "freestanding"
+//! ```
+//!
+//! A profile's `[workspace.metadata.wasmrust.budgets.<profile>]` table
+//! holds its modules to a `budget::Budget`, recording their sizes in
+//! `size-history.jsonl` next to them unless it names another file.
+//!
+//! Build scripts and proc macros need a host build, which is not done yet,
+//! so packages using either are rejected.
+
+use super::serve::{DevServer, DEFAULT_ADDRESS};
+use super::watch::{self, IncrementalCache, Watcher};
+use super::{lower_crate, notify_dev_server, print_error, write_timings, DriverError};
+use crate::backend::budget::{Budget, BudgetAction, SizeHistory, SizeRecord};
+use crate::backend::codegen::InitStrategy;
+use crate::backend::cranelift::CompilationStats;
+use crate::backend::js_glue::JsGlueGenerator;
+use crate::backend::size_report::{SizeFormat, SizeReport};
+use crate::backend::{BuildProfile, OptimizationLevel};
+use crate::diagnostics::MessageFormat;
+use crate::host::HostFunctions;
+use crate::timings::{self, Phase};
+use crate::{CompilerConfig, WasmRustFrontend};
+use cargo_metadata::{DependencyKind, Metadata, MetadataCommand, Package, PackageId, Target};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Directory under Cargo's target directory that artifacts go to
+pub const TARGET_SUBDIR: &str = "wasmrust";
+
+/// Size history of a budgeted profile, in its output directory unless the budget names one
+pub const SIZE_HISTORY: &str = "size-history.jsonl";
+
+/// Trace `--timings` writes to the output directory
+pub const TIMINGS: &str = "timings.json";
+
+/// Help printed by `--help`
+pub const USAGE: &str = "\
+Usage: cargo wasmrust <build|run|test|serve> [OPTIONS] [-- <test filters>]
+
+Options:
+    -r, --release              Build with the release profile
+        --profile <name>       Build with the named Cargo profile [default: dev, or test for `test`]
+    -p, --package <name>       Package to build [default: the workspace's default members]
+        --bin <name>           Binary to run, when the package has several
+        --manifest-path <path> Path to Cargo.toml
+        --error-format <fmt>   Diagnostic output: human, short, json [default: human]
+    -w, --watch                Run again whenever the packages' sources change
+        --notify <url>         With --watch, POST the rebuilt artifacts' paths to a dev server
+        --address <addr>       Address `serve` listens on [default: 127.0.0.1:8000]
+        --size-report[=<fmt>]  Print the size of each module built: table, json [default: table]
+        --capabilities[=<fmt>] Print the host capabilities each module built needs: table, json [default: table]
+        --timings              Write a Chrome trace of each build's phases to timings.json
+        --deterministic        Leave timestamps and machine paths out, for reproducible builds
+        --sanitize             Check memory accesses and frees as `run` and `test` execute
+    -h, --help                 Print this help message
+    -V, --version              Print version information
+";
+
+/// What `cargo wasmrust` does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verb {
+    Build,
+    Run,
+    Test,
+    /// Build, watch, and serve the output with hot reloading
+    Serve,
+}
+
+impl Verb {
+    pub const ALL: [Verb; 4] = [Verb::Build, Verb::Run, Verb::Test, Verb::Serve];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Verb::Build => "build",
+            Verb::Run => "run",
+            Verb::Test => "test",
+            Verb::Serve => "serve",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|verb| verb.name() == name)
+    }
+}
+
+/// What the command line asks for
+#[derive(Debug, Clone)]
+pub enum CargoCommand {
+    Help,
+    Version,
+    Run(CargoOptions),
+}
+
+/// Options of a `cargo wasmrust` invocation
+#[derive(Debug, Clone)]
+pub struct CargoOptions {
+    pub verb: Verb,
+    /// Cargo profile to build with
+    pub profile: String,
+    pub package: Option<String>,
+    pub bin: Option<String>,
+    pub manifest_path: Option<PathBuf>,
+    pub error_format: MessageFormat,
+    /// Arguments after `--`, which filter the tests to run
+    pub filters: Vec<String>,
+    pub watch: bool,
+    /// Dev server `--notify` tells of rebuilds
+    pub notify: Option<String>,
+    /// Address `serve` listens on
+    pub address: String,
+    /// Format `--size-report` prints in
+    pub size_report: Option<SizeFormat>,
+    /// Format `--capabilities` prints each module's manifest in
+    pub capabilities: Option<SizeFormat>,
+    /// Whether `--timings` profiles each build
+    pub timings: bool,
+    /// Whether `--deterministic` makes builds reproducible
+    pub deterministic: bool,
+    /// Whether `--sanitize` instruments what `run` and `test` execute
+    pub sanitize: bool,
+}
+
+/// Parses the arguments cargo passes after `cargo wasmrust`
+pub fn parse_args(args: &[String]) -> Result<CargoCommand, DriverError> {
+    let usage = |msg: String| DriverError::Usage(msg);
+    let mut verb = None;
+    let mut profile = None;
+    let mut package = None;
+    let mut bin = None;
+    let mut manifest_path = None;
+    let mut error_format = MessageFormat::Human;
+    let mut filters = Vec::new();
+    let mut watch = false;
+    let mut notify = None;
+    let mut address = DEFAULT_ADDRESS.to_string();
+    let mut size_report = None;
+    let mut capabilities = None;
+    let mut timings = false;
+    let mut deterministic = false;
+    let mut sanitize = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline.clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| usage(format!("`{}` needs a value", flag)))
+        };
+        match flag {
+            "-h" | "--help" => return Ok(CargoCommand::Help),
+            "-V" | "--version" => return Ok(CargoCommand::Version),
+            "-r" | "--release" => profile = Some("release".to_string()),
+            "--profile" => profile = Some(value()?),
+            "-p" | "--package" => package = Some(value()?),
+            "--bin" => bin = Some(value()?),
+            "--manifest-path" => manifest_path = Some(PathBuf::from(value()?)),
+            "--error-format" => {
+                let name = value()?;
+                error_format = MessageFormat::from_name(&name)
+                    .ok_or_else(|| usage(format!("unknown error format `{}`; expected human, short, or json", name)))?;
+            }
+            "-w" | "--watch" => watch = true,
+            "--notify" => notify = Some(super::notify_url(value()?)?),
+            "--address" => address = value()?,
+            "--size-report" => size_report = Some(super::size_format(inline.as_deref())?),
+            "--capabilities" => capabilities = Some(super::capabilities_format(inline.as_deref())?),
+            "--timings" => timings = true,
+            "--deterministic" => deterministic = true,
+            "--sanitize" => sanitize = true,
+            "--" => filters.extend(args.by_ref().cloned()),
+            other if verb.is_none() && !other.starts_with('-') => {
+                verb = Some(Verb::from_name(other).ok_or_else(|| {
+                    usage(format!("unknown command `{}`; expected build, run, test, or serve", other))
+                })?);
+            }
+            other => return Err(usage(format!("unexpected argument `{}`", other))),
+        }
+    }
+
+    let verb = verb.ok_or_else(|| usage("no command given; expected build, run, test, or serve".to_string()))?;
+    let watch = watch || verb == Verb::Serve;
+    let profile = profile.unwrap_or_else(|| if verb == Verb::Test { "test" } else { "dev" }.to_string());
+    if notify.is_some() && !watch {
+        return Err(usage("`--notify` needs `--watch`".to_string()));
+    }
+    if sanitize && !matches!(verb, Verb::Run | Verb::Test) {
+        return Err(usage(format!("`--sanitize` applies to run and test, not {}", verb.name())));
+    }
+    Ok(CargoCommand::Run(CargoOptions {
+        verb,
+        profile,
+        package,
+        bin,
+        manifest_path,
+        error_format,
+        filters,
+        watch,
+        notify,
+        address,
+        size_report,
+        capabilities,
+        timings,
+        deterministic,
+        sanitize,
+    }))
+}
+
+/// Compiler configuration of a Cargo profile
+///
+/// Custom profiles are looked up in `workspace_metadata`.
+pub fn profile_config(profile: &str, workspace_metadata: &serde_json::Value) -> Result<CompilerConfig, DriverError> {
+    let build_profile = match profile {
+        "dev" | "test" => BuildProfile::Development,
+        "release" | "bench" => BuildProfile::Release,
+        custom => workspace_metadata[TARGET_SUBDIR]["profiles"][custom]
+            .as_str()
+            .and_then(BuildProfile::from_name)
+            .ok_or_else(|| {
+                DriverError::Usage(format!(
+                    "profile `{}` needs a build profile in [workspace.metadata.wasmrust.profiles]",
+                    custom
+                ))
+            })?,
+    };
+    let (optimization_level, debug_info) = match build_profile {
+        BuildProfile::Development => (OptimizationLevel::None, true),
+        BuildProfile::Release | BuildProfile::Freestanding => (OptimizationLevel::Aggressive, false),
+    };
+    Ok(CompilerConfig { optimization_level, build_profile, debug_info, ..CompilerConfig::default() })
+}
+
+/// Budget of a Cargo profile, from `workspace_metadata`
+///
+/// Relative history paths are relative to the workspace `root`; without
+/// one, a budgeted profile records its sizes under `out_dir`.
+pub fn profile_budget(
+    profile: &str,
+    workspace_metadata: &serde_json::Value,
+    root: &Path,
+    out_dir: &Path,
+) -> Result<Budget, DriverError> {
+    let table = &workspace_metadata[TARGET_SUBDIR]["budgets"][profile];
+    if table.is_null() {
+        return Ok(Budget::default());
+    }
+    let mut budget = Budget::from_metadata(table, root).map_err(|msg| {
+        DriverError::Usage(format!("invalid [workspace.metadata.wasmrust.budgets.{}]: {}", profile, msg))
+    })?;
+    budget.history.get_or_insert_with(|| out_dir.join(SIZE_HISTORY));
+    Ok(budget)
+}
+
+/// How a crate is compiled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitKind {
+    /// Library, whose metadata dependent crates are checked against
+    Lib,
+    Bin,
+    /// Library or binary compiled with `--test`
+    Test,
+}
+
+/// One rustc lowering of a `BuildPlan`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub package: String,
+    /// Crate name as rustc knows it
+    pub crate_name: String,
+    pub src_path: PathBuf,
+    pub kind: UnitKind,
+    /// Whether the module is written out, rather than only its metadata
+    pub root: bool,
+    /// rustc flags naming the crate, its edition, features, and dependencies
+    pub rustc_args: Vec<String>,
+}
+
+impl Unit {
+    /// Name of the unit in an `IncrementalCache`
+    pub fn key(&self) -> String {
+        format!("{} {} {:?}", self.package, self.crate_name, self.kind)
+    }
+}
+
+/// Crates to lower for a verb, in dependency order
+#[derive(Debug, Clone)]
+pub struct BuildPlan {
+    pub config: CompilerConfig,
+    /// What the profile's root modules are held to
+    pub budget: Budget,
+    /// `target/wasmrust/<profile>`
+    pub out_dir: PathBuf,
+    pub units: Vec<Unit>,
+}
+
+impl BuildPlan {
+    /// Plans the crates `options` asks for
+    ///
+    /// The selected packages build their library and binaries, run picks
+    /// one binary, and test compiles each with `--test` after the
+    /// development dependencies.
+    pub fn new(metadata: &Metadata, options: &CargoOptions) -> Result<Self, DriverError> {
+        let mut config = profile_config(&options.profile, &metadata.workspace_metadata)?;
+        if options.verb == Verb::Serve {
+            // The glue runs constructors once it can serve their imports
+            config.init_strategy = InitStrategy::ExportedCallCtors;
+        }
+        config.deterministic = options.deterministic;
+        config.sanitize = options.sanitize;
+        let out_dir = metadata.target_directory.as_std_path().join(TARGET_SUBDIR).join(profile_dir(&options.profile));
+        let roots: Vec<&Package> = match &options.package {
+            Some(name) => vec![metadata.packages.iter()
+                .find(|package| metadata.workspace_members.contains(&package.id) && package.name == *name)
+                .ok_or_else(|| DriverError::Usage(format!("package `{}` is not a workspace member", name)))?],
+            None => metadata.workspace_default_packages(),
+        };
+
+        let root = metadata.workspace_root.as_std_path();
+        let budget = profile_budget(&options.profile, &metadata.workspace_metadata, root, &out_dir)?;
+        let mut plan = Self { config, budget, out_dir, units: Vec::new() };
+        let mut planned = HashSet::new();
+        for root in &roots {
+            plan.add_libraries(metadata, &root.id, options.verb == Verb::Test, &mut planned)?;
+        }
+        for root in roots {
+            let lib = root.targets.iter().find(|target| target.is_lib());
+            let bins: Vec<&Target> = root.targets.iter().filter(|target| target.is_bin()).collect();
+            match options.verb {
+                Verb::Build | Verb::Serve => {
+                    let own_lib = plan.units.iter_mut()
+                        .find(|unit| unit.package == root.name && unit.kind == UnitKind::Lib);
+                    if let Some(unit) = own_lib {
+                        unit.root = true;
+                    }
+                    for bin in bins {
+                        plan.units.push(plan.unit(metadata, root, bin, UnitKind::Bin, lib));
+                    }
+                }
+                Verb::Run => {
+                    if bins.is_empty() {
+                        return Err(DriverError::Usage(format!("package `{}` has no binaries", root.name)));
+                    }
+                    let bin = match (&options.bin, bins.as_slice()) {
+                        (Some(name), _) => bins.iter().find(|bin| bin.name == *name).copied(),
+                        (None, [bin]) => Some(*bin),
+                        (None, _) => None,
+                    };
+                    let names: Vec<_> = bins.iter().map(|bin| bin.name.as_str()).collect();
+                    let bin = bin.ok_or_else(|| {
+                        DriverError::Usage(format!("pick a binary of `{}` with --bin: {}", root.name, names.join(", ")))
+                    })?;
+                    plan.units.push(plan.unit(metadata, root, bin, UnitKind::Bin, lib));
+                }
+                Verb::Test => {
+                    for target in lib.into_iter().chain(bins).filter(|target| target.test) {
+                        let own_lib = if target.is_lib() { None } else { lib };
+                        plan.units.push(plan.unit(metadata, root, target, UnitKind::Test, own_lib));
+                    }
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Directory of library metadata
+    pub fn deps_dir(&self) -> PathBuf {
+        self.out_dir.join("deps")
+    }
+
+    /// Path a root unit's module is written to
+    pub fn artifact(&self, unit: &Unit) -> PathBuf {
+        self.out_dir.join(format!("{}.wasm", unit.crate_name))
+    }
+
+    /// Path the JavaScript glue of a root unit's module is written to by `serve`
+    pub fn glue(&self, unit: &Unit) -> PathBuf {
+        self.out_dir.join(format!("{}.mjs", unit.crate_name))
+    }
+
+    /// Fingerprint of each unit, in order
+    ///
+    /// A unit's covers the sources next to its root file, its flags, the
+    /// configuration, and the fingerprints of the libraries it is given
+    /// with `--extern`, so editing a library rebuilds its dependents too.
+    pub fn fingerprints(&self) -> Vec<u64> {
+        let config = format!("{:?}", self.config);
+        let mut libs: HashMap<PathBuf, String> = HashMap::new();
+        let mut fingerprints = Vec::new();
+        for unit in &self.units {
+            let dir = unit.src_path.parent().unwrap_or(&unit.src_path);
+            let deps = unit.rustc_args.iter()
+                .filter_map(|arg| arg.strip_prefix("--extern=")?.split_once('='))
+                .filter_map(|(_, path)| libs.get(Path::new(path)));
+            let mut parts: Vec<&str> = vec![&config, if unit.root { "root" } else { "metadata" }];
+            parts.extend(unit.rustc_args.iter().map(String::as_str));
+            parts.extend(deps.map(String::as_str));
+            let fingerprint = watch::fingerprint(&[dir], &parts);
+            if unit.kind == UnitKind::Lib {
+                let rmeta = self.deps_dir().join(format!("lib{}.rmeta", unit.crate_name));
+                libs.insert(rmeta, fingerprint.to_string());
+            }
+            fingerprints.push(fingerprint);
+        }
+        fingerprints
+    }
+
+    /// Files whose changes can change the plan or its units
+    pub fn watch_roots(&self, metadata: &Metadata) -> Vec<PathBuf> {
+        let mut roots = vec![metadata.workspace_root.as_std_path().join("Cargo.toml")];
+        for unit in &self.units {
+            if let Some(package) = metadata.packages.iter().find(|package| package.name == unit.package) {
+                roots.push(package.manifest_path.clone().into_std_path_buf());
+            }
+            roots.push(unit.src_path.parent().unwrap_or(&unit.src_path).to_path_buf());
+        }
+        roots.sort();
+        roots.dedup();
+        roots
+    }
+
+    /// Plans the libraries `id` depends on, then its own
+    fn add_libraries(
+        &mut self,
+        metadata: &Metadata,
+        id: &PackageId,
+        dev: bool,
+        planned: &mut HashSet<PackageId>,
+    ) -> Result<(), DriverError> {
+        if !planned.insert(id.clone()) {
+            return Ok(());
+        }
+        for dep in dependencies(metadata, id, dev) {
+            self.add_libraries(metadata, &dep.pkg, false, planned)?;
+        }
+        let package = &metadata[id];
+        if let Some(target) = package.targets.iter().find(|target| target.is_custom_build() || target.is_proc_macro()) {
+            let what = if target.is_proc_macro() { "is a proc macro" } else { "has a build script" };
+            return Err(DriverError::Unsupported(format!(
+                "package `{}` {}, which cargo wasmrust cannot build yet",
+                package.name, what
+            )));
+        }
+        if let Some(lib) = package.targets.iter().find(|target| target.is_lib()) {
+            let unit = self.unit(metadata, package, lib, UnitKind::Lib, None);
+            self.units.push(unit);
+        }
+        Ok(())
+    }
+
+    /// Unit compiling `target`, which sees `own_lib` of its package too
+    fn unit(
+        &self,
+        metadata: &Metadata,
+        package: &Package,
+        target: &Target,
+        kind: UnitKind,
+        own_lib: Option<&Target>,
+    ) -> Unit {
+        let crate_name = target.name.replace('-', "_");
+        let deps = self.deps_dir();
+        let mut rustc_args = vec![
+            format!("--crate-name={}", crate_name),
+            format!("--edition={}", target.edition.as_str()),
+            format!("-Ldependency={}", deps.display()),
+        ];
+        match kind {
+            UnitKind::Lib => rustc_args.extend([
+                "--crate-type=lib".to_string(),
+                "--emit=metadata".to_string(),
+                format!("--out-dir={}", deps.display()),
+            ]),
+            UnitKind::Bin => rustc_args.push("--crate-type=bin".to_string()),
+            UnitKind::Test => rustc_args.push("--test".to_string()),
+        }
+        for feature in node(metadata, &package.id).map(|node| node.features.as_slice()).unwrap_or_default() {
+            rustc_args.push(format!("--cfg=feature=\"{}\"", feature));
+        }
+        if let Some(lib) = own_lib {
+            let name = lib.name.replace('-', "_");
+            rustc_args.push(format!("--extern={}={}", name, deps.join(format!("lib{}.rmeta", name)).display()));
+        }
+        for dep in dependencies(metadata, &package.id, kind == UnitKind::Test) {
+            let Some(lib) = metadata[&dep.pkg].targets.iter().find(|target| target.is_lib()) else { continue };
+            let file = deps.join(format!("lib{}.rmeta", lib.name.replace('-', "_")));
+            rustc_args.push(format!("--extern={}={}", dep.name, file.display()));
+        }
+        if self.config.deterministic {
+            // Targets' sources are absolute, so the workspace is remapped rather than the working directory
+            rustc_args.push(format!("--remap-path-prefix={}=.", metadata.workspace_root));
+        }
+        Unit {
+            package: package.name.clone(),
+            crate_name,
+            src_path: target.src_path.clone().into_std_path_buf(),
+            kind,
+            root: kind != UnitKind::Lib,
+            rustc_args,
+        }
+    }
+}
+
+/// Normal dependencies of a package, and its development ones with `dev`
+fn dependencies<'a>(metadata: &'a Metadata, id: &PackageId, dev: bool) -> Vec<&'a cargo_metadata::NodeDep> {
+    let Some(node) = node(metadata, id) else { return Vec::new() };
+    node.deps.iter()
+        .filter(|dep| {
+            dep.dep_kinds.iter().any(|info| match info.kind {
+                DependencyKind::Normal => true,
+                DependencyKind::Development => dev,
+                _ => false,
+            })
+        })
+        .collect()
+}
+
+/// Resolved dependencies and features of a package
+fn node<'a>(metadata: &'a Metadata, id: &PackageId) -> Option<&'a cargo_metadata::Node> {
+    metadata.resolve.as_ref()?.nodes.iter().find(|node| node.id == *id)
+}
+
+/// Directory of a profile's artifacts, named the way Cargo names its own
+fn profile_dir(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        other => other,
+    }
+}
+
+/// Runs the verb, returning whether the run or every test succeeded
+///
+/// Progress goes to stderr like Cargo's, and reports to stdout.
+pub fn run(options: &CargoOptions) -> Result<bool, DriverError> {
+    let (metadata, plan) = plan(options)?;
+    let outcome = build(options, &metadata, &plan, &mut IncrementalCache::new())?;
+    Ok(outcome.succeeded)
+}
+
+/// Runs the verb, then again each time the packages' sources change
+///
+/// The workspace is planned anew each time, so manifest edits are picked
+/// up, but only crates whose fingerprint changed are lowered again.
+/// Errors are reported and wait for the sources to change like successes;
+/// only a dev server failing to start ends the watch.
+pub fn watch(options: &CargoOptions) -> Result<Infallible, DriverError> {
+    let mut watcher = Watcher::new(Vec::new());
+    let mut cache = IncrementalCache::new();
+    let mut server = None;
+    loop {
+        match plan(options) {
+            Ok((metadata, plan)) => {
+                watcher.set_roots(plan.watch_roots(&metadata));
+                let result = build(options, &metadata, &plan, &mut cache);
+                if options.verb == Verb::Serve && server.is_none() {
+                    let dev = DevServer::bind(&plan.out_dir, &options.address)
+                        .map_err(|err| DriverError::Serve(options.address.clone(), err))?;
+                    eprintln!("     Serving {} at {}", plan.out_dir.display(), dev.url());
+                    server = Some(dev);
+                }
+                match result {
+                    Ok(outcome) if !outcome.artifacts.is_empty() => {
+                        if let Some(server) = &server {
+                            server.rebuilt(&outcome.artifacts);
+                        }
+                        notify_dev_server(options.notify.as_deref(), &outcome.artifacts);
+                    }
+                    Ok(_) => {}
+                    Err(err) => print_error(&err, options.error_format),
+                }
+            }
+            Err(err) => {
+                print_error(&err, options.error_format);
+                // Without a plan, wait for the manifest to be fixed
+                if watcher.roots().is_empty() {
+                    let manifest = options.manifest_path.clone().unwrap_or_else(|| PathBuf::from("Cargo.toml"));
+                    watcher.set_roots(vec![manifest]);
+                }
+            }
+        }
+        eprintln!("    Watching for changes");
+        watcher.wait();
+    }
+}
+
+/// What running a plan came to
+#[derive(Debug, Clone, Default)]
+pub struct BuildOutcome {
+    /// Whether the run or every test succeeded
+    pub succeeded: bool,
+    /// Modules written by `build` and `serve`, and the glue `serve` writes
+    pub artifacts: Vec<PathBuf>,
+}
+
+/// Reads the workspace and plans the verb in it
+fn plan(options: &CargoOptions) -> Result<(Metadata, BuildPlan), DriverError> {
+    let mut command = MetadataCommand::new();
+    if let Some(path) = &options.manifest_path {
+        command.manifest_path(path);
+    }
+    let metadata = command.exec().map_err(|err| DriverError::Metadata(err.to_string()))?;
+    let plan = BuildPlan::new(&metadata, options)?;
+    Ok((metadata, plan))
+}
+
+/// Lowers the units of a plan not fresh in `cache`, and runs, tests, or writes the root ones
+///
+/// With `--timings`, the trace is written even when the build fails.
+fn build(
+    options: &CargoOptions,
+    metadata: &Metadata,
+    plan: &BuildPlan,
+    cache: &mut IncrementalCache,
+) -> Result<BuildOutcome, DriverError> {
+    if !options.timings {
+        return build_units(options, metadata, plan, cache);
+    }
+    timings::start();
+    let result = build_units(options, metadata, plan, cache);
+    write_timings(&plan.out_dir.join(TIMINGS))?;
+    result
+}
+
+fn build_units(
+    options: &CargoOptions,
+    metadata: &Metadata,
+    plan: &BuildPlan,
+    cache: &mut IncrementalCache,
+) -> Result<BuildOutcome, DriverError> {
+    fs::create_dir_all(plan.deps_dir()).map_err(|err| DriverError::Io(plan.deps_dir(), err))?;
+
+    let mut frontend = WasmRustFrontend::new(plan.config.clone()).map_err(|err| DriverError::Emit(err.to_string()))?;
+    let mut outcome = BuildOutcome { succeeded: true, artifacts: Vec::new() };
+    for (unit, fingerprint) in plan.units.iter().zip(plan.fingerprints()) {
+        let key = unit.key();
+        if cache.is_fresh(&key, fingerprint) {
+            eprintln!("       Fresh {}", unit.crate_name);
+            continue;
+        }
+        eprintln!("   Compiling {} ({})", unit.crate_name, relative(&unit.src_path, metadata));
+        let start = Instant::now();
+        let _unit = timings::span(Phase::Driver, format_args!("{} ({:?})", unit.crate_name, unit.kind));
+        let lowered = lower_crate(&unit.src_path, &plan.config, options.error_format, &unit.rustc_args)?;
+        if unit.root {
+            match (options.verb, unit.kind) {
+                (Verb::Run, UnitKind::Bin) => {
+                    eprintln!("     Running {}", unit.crate_name);
+                    let report = frontend.run_module(&lowered.module, &[], &HostFunctions::new())
+                        .map_err(|err| DriverError::Emit(err.to_string()))?;
+                    print!("{}", report);
+                    outcome.succeeded &= report.succeeded();
+                }
+                (Verb::Test, _) => {
+                    let selected = |name: &str| {
+                        options.filters.is_empty()
+                            || options.filters.iter().any(|filter| name.contains(filter.as_str()))
+                    };
+                    let tests: Vec<_> = lowered.tests.into_iter().filter(|test| selected(&test.name)).collect();
+                    eprintln!("     Running {} tests", unit.crate_name);
+                    let report = frontend.run_tests(&lowered.module, &tests, &HostFunctions::new())
+                        .map_err(|err| DriverError::Emit(err.to_string()))?;
+                    print!("{}", report);
+                    outcome.succeeded &= report.succeeded();
+                }
+                _ => {
+                    let binary = frontend.compile_module(&lowered.module)
+                        .map_err(|err| DriverError::Emit(err.to_string()))?;
+                    if let Some(format) = options.size_report {
+                        let report = super::size_report(&plan.config, &lowered.module, &binary)?;
+                        print!("{}", report.render(format, None));
+                    }
+                    if let Some(format) = options.capabilities {
+                        print!("{}", super::capabilities(&binary, format)?);
+                    }
+                    if !plan.budget.is_empty() {
+                        let stats = CompilationStats {
+                            functions_compiled: lowered.module.functions.len(),
+                            compilation_time_ms: start.elapsed().as_millis() as u64,
+                            wasm_size: binary.len(),
+                            ..CompilationStats::default()
+                        };
+                        check_budget(&plan.budget, &unit.crate_name, &stats, &binary)?;
+                    }
+                    let path = plan.artifact(unit);
+                    fs::write(&path, binary).map_err(|err| DriverError::Io(path.clone(), err))?;
+                    if options.verb == Verb::Serve {
+                        let file = path.file_name().unwrap_or_default().to_string_lossy();
+                        let glue = JsGlueGenerator::new(file)
+                            .debug(plan.config.debug_info)
+                            .hot_reload(true)
+                            .generate(&lowered.module)
+                            .map_err(|err| DriverError::Emit(err.to_string()))?;
+                        let glue_path = plan.glue(unit);
+                        fs::write(&glue_path, glue).map_err(|err| DriverError::Io(glue_path.clone(), err))?;
+                        outcome.artifacts.push(glue_path);
+                    }
+                    outcome.artifacts.push(path);
+                }
+            }
+        }
+        cache.record(&key, fingerprint);
+    }
+    if matches!(options.verb, Verb::Build | Verb::Serve) {
+        eprintln!("    Finished `{}` profile into {}", options.profile, plan.out_dir.display());
+    }
+    Ok(outcome)
+}
+
+/// Checks a root module against the profile's budget, then records its size
+///
+/// Exceeding a `warn` budget prints warnings; a `deny` one fails the
+/// build, which is then not recorded.
+fn check_budget(budget: &Budget, module: &str, stats: &CompilationStats, binary: &[u8]) -> Result<(), DriverError> {
+    let report = SizeReport::new(binary).map_err(|err| DriverError::Emit(format!("cannot read the module: {}", err)))?;
+    let history = budget.history.as_ref().map(SizeHistory::new);
+    let io_error = |history: &SizeHistory, err| DriverError::Io(history.path().to_path_buf(), err);
+    let previous = match &history {
+        Some(history) => history.last(module).map_err(|err| io_error(history, err))?,
+        None => None,
+    };
+    let violations = budget.check(module, stats, &report, previous.as_ref());
+    if budget.action == BudgetAction::Deny && !violations.is_empty() {
+        return Err(DriverError::Budget(violations));
+    }
+    for violation in &violations {
+        eprintln!("warning: {}", violation);
+    }
+    if let Some(history) = &history {
+        history.append(&SizeRecord::new(module, stats, &report)).map_err(|err| io_error(history, err))?;
+    }
+    Ok(())
+}
+
+/// Path of a source file relative to the workspace root, for progress lines
+fn relative<'a>(path: &'a Path, metadata: &Metadata) -> std::path::Display<'a> {
+    path.strip_prefix(metadata.workspace_root.as_std_path()).unwrap_or(path).display()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Workspace of a binary package `app` depending on the library `util-lib`
+    fn workspace(name: &str) -> (PathBuf, Metadata) {
+        let root = std::env::temp_dir().join(format!("cargo-wasmrust-{}-{}", name, std::process::id()));
+        let files = [
+            ("Cargo.toml", r#"
+                [workspace]
+                members = ["app", "util"]
+
+                [workspace.metadata.wasmrust.profiles]
+                tiny = "freestanding"
+
+                [workspace.metadata.wasmrust.budgets.tiny]
+                max-wasm-size = 4096
+                action = "deny"
+            "#),
+            ("app/Cargo.toml", r#"
+                [package]
+                name = "app"
+                version = "0.1.0"
+                edition = "2021"
+
+                [dependencies]
+                util = { package = "util-lib", path = "../util", features = ["fast"] }
+            "#),
+            ("app/src/lib.rs", ""),
+            ("app/src/main.rs", "fn main() {}\n"),
+            ("util/Cargo.toml", r#"
+                [package]
+                name = "util-lib"
+                version = "0.1.0"
+                edition = "2018"
+
+                [features]
+                fast = []
+            "#),
+            ("util/src/lib.rs", ""),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let metadata = MetadataCommand::new().manifest_path(root.join("Cargo.toml")).exec().unwrap();
+        (root, metadata)
+    }
+
+    fn options(args: &[&str]) -> CargoOptions {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match parse_args(&args).unwrap() {
+            CargoCommand::Run(options) => options,
+            command => panic!("expected a verb, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_cargo_profiles_map_to_build_profiles() {
+        let options = options(&["test", "-p", "app", "--", "adds"]);
+        assert_eq!((options.verb, options.profile.as_str()), (Verb::Test, "test"));
+        assert_eq!(options.filters, ["adds"]);
+        assert_eq!(self::options(&["build", "--release"]).profile, "release");
+        let serve = self::options(&["serve", "--address", "0.0.0.0:3000", "--size-report=json"]);
+        assert_eq!(serve.size_report, Some(SizeFormat::Json));
+        assert_eq!(self::options(&["build", "--capabilities"]).capabilities, Some(SizeFormat::Table));
+        assert!(!serve.timings && self::options(&["build", "--timings"]).timings);
+        assert!(serve.watch);
+        assert_eq!((serve.profile.as_str(), serve.address.as_str()), ("dev", "0.0.0.0:3000"));
+        assert!(self::options(&["test", "--sanitize"]).sanitize && !serve.sanitize);
+        assert!(parse_args(&["build".to_string(), "--sanitize".to_string()]).is_err());
+
+        let metadata = serde_json::json!({ "wasmrust": { "profiles": { "tiny": "freestanding" } } });
+        let config = profile_config("release", &metadata).unwrap();
+        assert_eq!(config.build_profile, BuildProfile::Release);
+        assert_eq!(config.optimization_level, OptimizationLevel::Aggressive);
+        assert!(profile_config("test", &metadata).unwrap().debug_info);
+        assert_eq!(profile_config("tiny", &metadata).unwrap().build_profile, BuildProfile::Freestanding);
+        assert_eq!(
+            profile_config("huge", &metadata).unwrap_err().to_string(),
+            "profile `huge` needs a build profile in [workspace.metadata.wasmrust.profiles]"
+        );
+
+        let metadata = serde_json::json!({ "wasmrust": { "budgets": {
+            "release": { "max-size-growth": 5, "history": "sizes.jsonl" },
+            "dev": { "max-wasm-size": "big" },
+        } } });
+        let (root, out_dir) = (Path::new("/ws"), Path::new("/ws/target/wasmrust/release"));
+        let budget = profile_budget("release", &metadata, root, out_dir).unwrap();
+        assert_eq!(budget.max_size_growth, Some(5.0));
+        assert_eq!(budget.history, Some(root.join("sizes.jsonl")));
+        assert!(profile_budget("test", &metadata, root, out_dir).unwrap().is_empty());
+        assert_eq!(
+            profile_budget("dev", &metadata, root, out_dir).unwrap_err().to_string(),
+            "invalid [workspace.metadata.wasmrust.budgets.dev]: `max-wasm-size` must be a byte count"
+        );
+    }
+
+    #[test]
+    fn test_plans_crates_in_dependency_order() {
+        let (root, metadata) = workspace("plan");
+        let target = metadata.target_directory.as_std_path().join("wasmrust");
+
+        let plan = BuildPlan::new(&metadata, &options(&["build", "-p", "app", "--profile", "tiny"])).unwrap();
+        let units: Vec<_> = plan.units.iter().map(|unit| (unit.crate_name.as_str(), unit.kind, unit.root)).collect();
+        assert_eq!(units, [
+            ("util_lib", UnitKind::Lib, false),
+            ("app", UnitKind::Lib, true),
+            ("app", UnitKind::Bin, true),
+        ]);
+        assert_eq!(plan.config.build_profile, BuildProfile::Freestanding);
+        assert_eq!((plan.budget.max_wasm_size, plan.budget.action), (Some(4096), BudgetAction::Deny));
+        assert_eq!(plan.budget.history, Some(target.join("tiny").join(SIZE_HISTORY)));
+        assert_eq!(plan.artifact(&plan.units[1]), target.join("tiny/app.wasm"));
+
+        let deps = target.join("tiny/deps");
+        assert_eq!(plan.units[0].rustc_args, [
+            "--crate-name=util_lib".to_string(),
+            "--edition=2018".to_string(),
+            format!("-Ldependency={}", deps.display()),
+            "--crate-type=lib".to_string(),
+            "--emit=metadata".to_string(),
+            format!("--out-dir={}", deps.display()),
+            "--cfg=feature=\"fast\"".to_string(),
+        ]);
+        let bin = &plan.units[2].rustc_args;
+        assert!(bin.contains(&format!("--extern=app={}", deps.join("libapp.rmeta").display())));
+        assert!(bin.contains(&format!("--extern=util={}", deps.join("libutil_lib.rmeta").display())));
+
+        let plan = BuildPlan::new(&metadata, &options(&["test", "-p", "app"])).unwrap();
+        let units: Vec<_> = plan.units.iter().map(|unit| (unit.crate_name.as_str(), unit.kind)).collect();
+        assert_eq!(units, [
+            ("util_lib", UnitKind::Lib),
+            ("app", UnitKind::Lib),
+            ("app", UnitKind::Test),
+            ("app", UnitKind::Test),
+        ]);
+        assert_eq!(plan.out_dir, target.join("debug"));
+        assert!(!plan.units[2].rustc_args.iter().any(|arg| arg.starts_with("--extern=app=")));
+
+        let error = BuildPlan::new(&metadata, &options(&["run", "-p", "util-lib"])).unwrap_err();
+        assert_eq!(error.to_string(), "package `util-lib` has no binaries");
+
+        let plan = BuildPlan::new(&metadata, &options(&["build", "-p", "app", "--deterministic"])).unwrap();
+        assert!(plan.config.deterministic);
+        let remap = format!("--remap-path-prefix={}=.", metadata.workspace_root);
+        assert!(plan.units.iter().all(|unit| unit.rustc_args.last() == Some(&remap)));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprints_follow_dependencies() {
+        let (root, metadata) = workspace("fingerprints");
+        let options = options(&["build", "-p", "app", "-w"]);
+        assert!(options.watch);
+        let plan = BuildPlan::new(&metadata, &options).unwrap();
+        assert_eq!(plan.watch_roots(&metadata), [
+            root.join("Cargo.toml"),
+            root.join("app/Cargo.toml"),
+            root.join("app/src"),
+            root.join("util/Cargo.toml"),
+            root.join("util/src"),
+        ]);
+        let before = plan.fingerprints();
+        assert_eq!(plan.fingerprints(), before);
+
+        // An edit to the library rebuilds everything depending on it
+        fs::write(root.join("util/src/lib.rs"), "pub fn fast() {}\n").unwrap();
+        let after = plan.fingerprints();
+        assert!(before.iter().zip(&after).all(|(before, after)| before != after));
+
+        fs::write(root.join("util/src/lib.rs"), "").unwrap();
+        fs::write(root.join("app/src/main.rs"), "fn main() { app::run() }\n").unwrap();
+        let after = plan.fingerprints();
+        assert_eq!(after[0], before[0]);
+        assert_ne!(after[2], before[2]);
+
+        let release = BuildPlan::new(&metadata, &self::options(&["build", "-p", "app", "--release"])).unwrap();
+        assert_ne!(release.fingerprints()[0], before[0]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,1069 @@
+//! `wasm-rustc`, the compiler's command-line driver
+//!
+//! Flags mirror `CompilerConfig` in rustc's spelling, so a crate compiles
+//! without Rust driver code:
+//!
+//! ```text
+//! wasm-rustc --profile release -O -C target-feature=+simd128 --emit=wasm,wat -o out/app.wasm src/lib.rs
+//! ```
+//!
+//! Rust sources are lowered by rustc itself, run in-process when the
+//! compiler is built with the `rustc-mir` feature; see `rustc`. Lowering
+//! errors go to stderr through `diagnostics::Renderer` in the
+//! `--error-format` asked for, which rustc uses for its own errors too.
+//! Each `--emit` kind is written to `-o`, or next to it with its own
+//! extension when several are emitted; `obj` is a relocatable object for
+//! wasm-ld, see `backend::object`. With `--emit-filter <glob>`, the
+//! intermediate kinds are written one file per matching function into a
+//! `.dump` directory instead, WasmIR once per pass that changed it; see
+//! `backend::dump`. `-l` links the module with static libraries, such as
+//! C compiled by clang, resolving the imports they define; see
+//! `backend::link`. `--shared` makes the wasm a side module, which a main
+//! module's glue loads as a plugin; see `backend::dylink`. `--watch`
+//! compiles again whenever the crate's sources change; see `watch`. `--size-report` prints where
+//! the module's bytes go; see `backend::size_report`, and `--capabilities`
+//! what it needs from its host; see `backend::capabilities`. `--timings` writes a
+//! trace of where the compiler spent its time next to the output; see
+//! `timings`. `--deterministic` makes the output byte-identical across
+//! runs and machines: rustc sees paths relative to the working directory,
+//! and timestamps come from `SOURCE_DATE_EPOCH` or are the Unix epoch.
+
+pub mod cargo;
+#[cfg(feature = "rustc-mir")]
+pub mod rustc;
+pub mod serve;
+pub mod watch;
+
+use crate::backend::budget::BudgetViolation;
+use crate::backend::capabilities::{from_list, CapabilityManifest, CapabilityPolicy};
+use crate::backend::cranelift::WasmRustCraneliftBackend;
+use crate::backend::dump::{file_stem, FunctionFilter, PassDump};
+use crate::backend::pgo::PgoProfile;
+use crate::backend::size_report::{SizeFormat, SizeReport};
+use crate::backend::split::secondary_file_name;
+use crate::backend::{BackendFactory, BuildProfile, OptimizationLevel};
+use crate::diagnostics::{Diagnostics, MessageFormat, Renderer};
+use crate::host::test_runner::TestCase;
+use crate::target_spec::TargetSpec;
+use crate::timings;
+use crate::{CompilerConfig, WasmRustFrontend};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use wasm::wasmir::{Capability, WasmIR, WasmModule};
+use watch::{IncrementalCache, Watcher};
+
+/// Help printed by `--help`
+pub const USAGE: &str = "\
+Usage: wasm-rustc [OPTIONS] <input.rs>
+
+Options:
+    -o <path>                  Write the output to <path>
+        --emit <kinds>         Comma-separated artifacts: wasm, wat, obj, wasmir, clif, llvm-ir [default: wasm]
+        --emit-filter <glob>   Write wasmir after each pass, clif, and llvm-ir per function matching <glob>
+        --profile <profile>    Build profile: dev, release, freestanding [default: dev]
+        --backend <backend>    Codegen backend: cranelift, or llvm when built with it
+    -O                         Optimize, same as --opt-level=2
+        --opt-level <level>    Optimization level: 0, 1, 2, 3 [default: 0]
+    -g, --debug-info           Name functions in a `name` section
+        --demangle             Show Rust symbols demangled in the `name` section and size reports
+    -C target-feature=<list>   Enable or disable proposals, as in +simd128,+atomics,-sign-ext
+    -C stack-size=<bytes>      Shadow stack size, rounded up to whole pages [default: 65536]
+    -C stack-check=<yes|no>    Trap when the shadow stack overflows [default: yes for dev, no otherwise]
+    -C region-check=<yes|no>   Trap on accesses outside their memory region [default: yes for dev, no otherwise]
+    -C profile-use=<path>      Order functions and pick hot calls by a profile, as JSON or raw counters
+    -C linker=<path>           Linker -l libraries are linked with [default: wasm-ld]
+    -l [static=]<name>         Link lib<name>.a, <name>.o, or <name>.wasm, resolving imports it defines
+    -L [<kind>=]<dir>          Add <dir> to the directories -l searches, before the working directory
+        --size-report[=<fmt>]  Print the module's size by section, crate, and function: table, json [default: table]
+        --capabilities[=<fmt>] Print the host capabilities the module needs: table, json [default: table]
+        --allow-capabilities <list>
+                               Fail if the module needs a capability not listed, as in js-interop,memory-region:heap
+        --deny-capabilities <list>
+                               Fail if the module needs a capability listed, as in threading,atomic-memory
+        --timings              Write a Chrome trace of the compiler's phases to <output>.timings.json
+        --deterministic        Leave timestamps and machine paths out, for reproducible builds
+        --pre-initialize       Run constructors at build time and bake the memory they leave into the module
+        --split-cold           Move #[cold] functions to <output>.cold.wasm, loaded on demand
+        --shared               Emit the wasm as a side module, loaded as a plugin by a main module's glue
+        --target <target>      Target triple or spec file, as rustc takes [default: wasm32-unknown-unknown]
+        --error-format <fmt>   Diagnostic output: human, short, json [default: human]
+    -w, --watch                Compile again whenever the crate's sources change
+        --notify <url>         With --watch, POST the rebuilt artifacts' paths to a dev server
+    -h, --help                 Print this help message
+    -V, --version              Print version information
+";
+
+/// Artifact written by `--emit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitKind {
+    /// Binary module
+    Wasm,
+    /// Binary module in the text format
+    Wat,
+    /// Relocatable object for wasm-ld; see `backend::object`
+    Obj,
+    /// Lowered module before codegen
+    WasmIr,
+    /// Cranelift IR of each function, from the Cranelift backend
+    Clif,
+    /// LLVM IR of each function, from the LLVM backend
+    LlvmIr,
+}
+
+impl EmitKind {
+    pub const ALL: [EmitKind; 6] =
+        [EmitKind::Wasm, EmitKind::Wat, EmitKind::Obj, EmitKind::WasmIr, EmitKind::Clif, EmitKind::LlvmIr];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EmitKind::Wasm => "wasm",
+            EmitKind::Wat => "wat",
+            EmitKind::Obj => "obj",
+            EmitKind::WasmIr => "wasmir",
+            EmitKind::Clif => "clif",
+            EmitKind::LlvmIr => "llvm-ir",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Extension of the file the artifact is written to
+    pub fn extension(self) -> &'static str {
+        match self {
+            EmitKind::Wasm => "wasm",
+            EmitKind::Wat => "wat",
+            EmitKind::Obj => "o",
+            EmitKind::WasmIr => "wasmir",
+            EmitKind::Clif => "clif",
+            EmitKind::LlvmIr => "ll",
+        }
+    }
+
+    /// Whether `--emit-filter` writes the kind per function
+    pub fn is_intermediate(self) -> bool {
+        matches!(self, EmitKind::WasmIr | EmitKind::Clif | EmitKind::LlvmIr)
+    }
+}
+
+/// What the command line asks for
+#[derive(Debug, Clone)]
+pub enum Command {
+    Help,
+    Version,
+    Compile(Box<Options>),
+}
+
+/// Options of a compilation
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Root source file of the crate
+    pub input: PathBuf,
+    pub config: CompilerConfig,
+    /// Backend named by `--backend`, or the one recommended for the profile
+    pub backend: &'static str,
+    /// Artifacts to write, in order and without duplicates
+    pub emit: Vec<EmitKind>,
+    /// Path given with `-o`
+    pub output: Option<PathBuf>,
+    pub error_format: MessageFormat,
+    pub watch: bool,
+    /// Dev server `--notify` tells of rebuilds
+    pub notify: Option<String>,
+    /// Format `--size-report` prints in
+    pub size_report: Option<SizeFormat>,
+    /// Format `--capabilities` prints the module's manifest in
+    pub capabilities: Option<SizeFormat>,
+    /// Whether `--timings` profiles the compilation
+    pub timings: bool,
+    /// Functions `--emit-filter` writes intermediate artifacts of
+    pub emit_filter: Option<FunctionFilter>,
+    /// Whether `--shared` makes the wasm a side module
+    pub shared: bool,
+}
+
+impl Options {
+    /// Path `kind` is written to
+    ///
+    /// `-o` names the file when one kind is emitted and gives the stem of
+    /// each otherwise; without it, files are named after the input.
+    pub fn output_path(&self, kind: EmitKind) -> PathBuf {
+        match &self.output {
+            Some(output) if self.emit.len() == 1 => output.clone(),
+            Some(output) => output.with_extension(kind.extension()),
+            None => {
+                let stem = self.input.file_stem().unwrap_or(self.input.as_os_str());
+                Path::new(stem).with_extension(kind.extension())
+            }
+        }
+    }
+
+    /// Path `--timings` writes its trace to, named after the first artifact
+    pub fn timings_path(&self) -> PathBuf {
+        self.output_path(self.emit[0]).with_extension(TIMINGS_EXTENSION)
+    }
+
+    /// Directory `--emit-filter` writes per-function artifacts to, named after the first artifact
+    pub fn dump_dir(&self) -> PathBuf {
+        self.output_path(self.emit[0]).with_extension("dump")
+    }
+}
+
+/// Extension of the trace `--timings` writes
+pub const TIMINGS_EXTENSION: &str = "timings.json";
+
+/// Module lowered from a crate
+#[derive(Debug, Clone)]
+pub struct LoweredCrate {
+    pub module: WasmModule,
+    /// `#[test]` functions of a crate compiled with `--test`
+    pub tests: Vec<TestCase>,
+}
+
+/// Errors of a `wasm-rustc` invocation
+#[derive(Debug)]
+pub enum DriverError {
+    /// The command line does not parse
+    Usage(String),
+    /// rustc or the lowering rejected the crate
+    ///
+    /// rustc prints its own errors, so the diagnostics are only the
+    /// lowering's and may be empty.
+    Lowering(Diagnostics),
+    /// The compiler cannot do what was asked, as built
+    Unsupported(String),
+    /// `cargo metadata` failed
+    Metadata(String),
+    /// Codegen or printing an artifact failed
+    Emit(String),
+    /// An artifact could not be written
+    Io(PathBuf, io::Error),
+    /// The dev server could not listen on the address
+    Serve(String, io::Error),
+    /// A module exceeded a `deny` budget
+    Budget(Vec<BudgetViolation>),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::Usage(msg) => write!(f, "{}", msg),
+            DriverError::Lowering(diagnostics) => match diagnostics.error_count() {
+                1 => write!(f, "could not compile due to 1 previous error"),
+                count => write!(f, "could not compile due to {} previous errors", count),
+            },
+            DriverError::Unsupported(msg) => write!(f, "{}", msg),
+            DriverError::Metadata(msg) => write!(f, "cannot read the Cargo workspace: {}", msg),
+            DriverError::Emit(msg) => write!(f, "{}", msg),
+            DriverError::Io(path, err) => write!(f, "cannot write {}: {}", path.display(), err),
+            DriverError::Serve(address, err) => write!(f, "cannot serve on {}: {}", address, err),
+            DriverError::Budget(violations) => {
+                let violations: Vec<String> = violations.iter().map(ToString::to_string).collect();
+                write!(f, "{}", violations.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for DriverError {}
+
+/// Parses the arguments following the program name
+///
+/// Flags taking a value accept it as the next argument or after `=`.
+pub fn parse_args(args: &[String]) -> Result<Command, DriverError> {
+    let usage = |msg: String| DriverError::Usage(msg);
+    let mut input = None;
+    let mut config = CompilerConfig {
+        optimization_level: OptimizationLevel::None,
+        debug_info: false,
+        ..CompilerConfig::default()
+    };
+    let mut backend = None;
+    let mut emit = Vec::new();
+    let mut output = None;
+    let mut error_format = MessageFormat::Human;
+    let mut watch = false;
+    let mut notify = None;
+    let mut size_report = None;
+    let mut capabilities = None;
+    let mut allowed = None;
+    let mut denied = Vec::new();
+    let mut timings = false;
+    let mut emit_filter = None;
+    let mut codegen_options = Vec::new();
+    let mut libraries = Vec::new();
+    let mut search_dirs = Vec::new();
+    let mut shared = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline.clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| usage(format!("`{}` needs a value", flag)))
+        };
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-o" => output = Some(PathBuf::from(value()?)),
+            "-O" => config.optimization_level = OptimizationLevel::Standard,
+            "-g" | "--debug-info" => config.debug_info = true,
+            "--demangle" => config.demangle = true,
+            "--opt-level" => {
+                config.optimization_level = match value()?.as_str() {
+                    "0" => OptimizationLevel::None,
+                    "1" => OptimizationLevel::Basic,
+                    "2" => OptimizationLevel::Standard,
+                    "3" => OptimizationLevel::Aggressive,
+                    other => {
+                        return Err(usage(format!("unknown optimization level `{}`; expected 0, 1, 2, or 3", other)))
+                    }
+                }
+            }
+            "--profile" => {
+                let name = value()?;
+                config.build_profile = BuildProfile::from_name(&name).ok_or_else(|| {
+                    usage(format!("unknown profile `{}`; expected dev, release, or freestanding", name))
+                })?;
+            }
+            "--backend" => {
+                let name = value()?;
+                let available = BackendFactory::available_backends();
+                let Some(&found) = available.iter().find(|&&backend| backend == name) else {
+                    return Err(usage(format!("unknown backend `{}`; expected one of {}", name, available.join(", "))));
+                };
+                backend = Some(found);
+            }
+            "--emit" => {
+                for name in value()?.split(',') {
+                    let kind = EmitKind::from_name(name).ok_or_else(|| {
+                        let names: Vec<_> = EmitKind::ALL.iter().map(|kind| kind.name()).collect();
+                        usage(format!("unknown emit kind `{}`; expected one of {}", name, names.join(", ")))
+                    })?;
+                    if !emit.contains(&kind) {
+                        emit.push(kind);
+                    }
+                }
+            }
+            "--target" => config.target = value()?,
+            "--error-format" => {
+                let name = value()?;
+                error_format = MessageFormat::from_name(&name)
+                    .ok_or_else(|| usage(format!("unknown error format `{}`; expected human, short, or json", name)))?;
+            }
+            "-w" | "--watch" => watch = true,
+            "--notify" => notify = Some(notify_url(value()?)?),
+            "--size-report" => size_report = Some(size_format(inline.as_deref())?),
+            "--capabilities" => capabilities = Some(capabilities_format(inline.as_deref())?),
+            "--allow-capabilities" => allowed = Some(capability_list(&value()?)?),
+            "--deny-capabilities" => denied.extend(capability_list(&value()?)?),
+            "--timings" => timings = true,
+            "--deterministic" => config.deterministic = true,
+            "--pre-initialize" => config.pre_initialize = true,
+            "--split-cold" => config.split_cold = true,
+            "--shared" => shared = true,
+            "--emit-filter" => emit_filter = Some(FunctionFilter::new(value()?)),
+            "-C" => codegen_options.push(value()?),
+            other if other.starts_with("-C") => codegen_options.push(other[2..].to_string()),
+            "-l" => libraries.push(value()?),
+            other if other.starts_with("-l") => libraries.push(other[2..].to_string()),
+            "-L" => search_dirs.push(search_dir(&value()?)),
+            other if other.starts_with("-L") => search_dirs.push(search_dir(&other[2..])),
+            other if other.starts_with('-') => return Err(usage(format!("unknown flag `{}`", other))),
+            other if input.is_none() => input = Some(PathBuf::from(other)),
+            other => return Err(usage(format!("unexpected argument `{}`; only one input is accepted", other))),
+        }
+    }
+
+    // The target's features come first, for `-C target-feature` to amend
+    TargetSpec::resolve(&config.target).map_err(|err| usage(err.to_string()))?.configure(&mut config);
+    for option in &codegen_options {
+        codegen_option(&mut config, option)?;
+    }
+    let policy = allowed.map_or_else(CapabilityPolicy::default, CapabilityPolicy::allow_only);
+    config.capability_policy = denied.into_iter().fold(policy, CapabilityPolicy::deny);
+    for library in &libraries {
+        config.link_libraries.push(find_library(library, &search_dirs)?);
+    }
+    let input = input.ok_or_else(|| usage("no input file".to_string()))?;
+    if emit.is_empty() {
+        emit.push(EmitKind::Wasm);
+    }
+    let backend = backend
+        .or_else(|| BackendFactory::recommend_backend("wasm32", config.build_profile))
+        .unwrap_or("cranelift");
+    if emit.contains(&EmitKind::LlvmIr) && backend != "llvm" {
+        return Err(usage("`--emit=llvm-ir` needs `--backend llvm`".to_string()));
+    }
+    if notify.is_some() && !watch {
+        return Err(usage("`--notify` needs `--watch`".to_string()));
+    }
+    if config.split_cold && !config.link_libraries.is_empty() {
+        return Err(usage("`--split-cold` cannot be used with `-l`".to_string()));
+    }
+    if shared && (config.split_cold || !config.link_libraries.is_empty()) {
+        return Err(usage("`--shared` cannot be used with `--split-cold` or `-l`".to_string()));
+    }
+    if emit_filter.is_some() && !emit.iter().any(|kind| kind.is_intermediate()) {
+        return Err(usage("`--emit-filter` needs `--emit` of wasmir, clif, or llvm-ir".to_string()));
+    }
+    Ok(Command::Compile(Box::new(Options {
+        input,
+        config,
+        backend,
+        emit,
+        output,
+        error_format,
+        watch,
+        notify,
+        size_report,
+        capabilities,
+        timings,
+        emit_filter,
+        shared,
+    })))
+}
+
+/// Format of `--size-report`, which takes its value only after `=`
+fn size_format(name: Option<&str>) -> Result<SizeFormat, DriverError> {
+    let Some(name) = name else { return Ok(SizeFormat::Table) };
+    SizeFormat::from_name(name)
+        .ok_or_else(|| DriverError::Usage(format!("unknown size report format `{}`; expected table or json", name)))
+}
+
+/// Format of `--capabilities`, which like `--size-report` takes its value only after `=`
+fn capabilities_format(name: Option<&str>) -> Result<SizeFormat, DriverError> {
+    let Some(name) = name else { return Ok(SizeFormat::Table) };
+    SizeFormat::from_name(name)
+        .ok_or_else(|| DriverError::Usage(format!("unknown capabilities format `{}`; expected table or json", name)))
+}
+
+/// Directory of `-L`, less rustc's `<kind>=` prefix
+fn search_dir(value: &str) -> PathBuf {
+    let kinds = ["native", "dependency", "crate", "framework", "all"];
+    match value.split_once('=') {
+        Some((kind, dir)) if kinds.contains(&kind) => PathBuf::from(dir),
+        _ => PathBuf::from(value),
+    }
+}
+
+/// Finds the file of a `-l` library in the `-L` directories, then the working directory
+///
+/// Wasm has no shared libraries, so only `static` libraries link.
+fn find_library(library: &str, search_dirs: &[PathBuf]) -> Result<PathBuf, DriverError> {
+    let name = match library.split_once('=') {
+        Some(("static", name)) => name,
+        Some((kind, _)) => {
+            return Err(DriverError::Usage(format!("cannot link `{}`: only static libraries link into wasm", kind)))
+        }
+        None => library,
+    };
+    let files = [format!("lib{}.a", name), format!("{}.o", name), format!("{}.wasm", name)];
+    search_dirs.iter()
+        .map(PathBuf::as_path)
+        .chain([Path::new(".")])
+        .flat_map(|dir| files.iter().map(move |file| dir.join(file)))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            DriverError::Usage(format!(
+                "cannot find library `{}`; looked for {} in the -L directories and the working directory",
+                name,
+                files.join(", ")
+            ))
+        })
+}
+
+/// Capabilities of `--allow-capabilities` or `--deny-capabilities`
+fn capability_list(list: &str) -> Result<Vec<Capability>, DriverError> {
+    from_list(list).map_err(DriverError::Usage)
+}
+
+/// Applies a `-C` codegen option: `target-feature`, `stack-size`, `stack-check`, `region-check`,
+/// `profile-use`, or `linker`
+///
+/// Like rustc, several `-C target-feature` lists apply in order.
+fn codegen_option(config: &mut CompilerConfig, option: &str) -> Result<(), DriverError> {
+    match option.split_once('=') {
+        Some(("target-feature", spec)) => {
+            config.target_features =
+                config.target_features.apply(spec).map_err(|err| DriverError::Usage(err.to_string()))?;
+            Ok(())
+        }
+        Some(("stack-size", bytes)) => {
+            config.stack_size = bytes.parse().map_err(|_| {
+                DriverError::Usage(format!("stack size `{}` is not a number of bytes", bytes))
+            })?;
+            Ok(())
+        }
+        Some(("stack-check", enabled)) => {
+            config.stack_check = Some(yes_or_no("stack check", enabled)?);
+            Ok(())
+        }
+        Some(("region-check", enabled)) => {
+            config.region_checks = Some(yes_or_no("region check", enabled)?);
+            Ok(())
+        }
+        Some(("profile-use", path)) => {
+            config.pgo = Some(PgoProfile::load(Path::new(path)).map_err(|err| DriverError::Usage(err.to_string()))?);
+            Ok(())
+        }
+        Some(("linker", path)) => {
+            config.linker = Some(PathBuf::from(path));
+            Ok(())
+        }
+        _ => Err(DriverError::Usage(format!(
+            "unknown codegen option `{}`; expected target-feature=<list>, stack-size=<bytes>, \
+             stack-check=<yes|no>, region-check=<yes|no>, profile-use=<path>, or linker=<path>",
+            option
+        ))),
+    }
+}
+
+/// Parses rustc's spellings of a boolean codegen option
+fn yes_or_no(option: &str, value: &str) -> Result<bool, DriverError> {
+    match value {
+        "y" | "yes" | "on" => Ok(true),
+        "n" | "no" | "off" => Ok(false),
+        other => Err(DriverError::Usage(format!("{} `{}` is not yes or no", option, other))),
+    }
+}
+
+/// Checks a `--notify` URL
+fn notify_url(url: String) -> Result<String, DriverError> {
+    match watch::split_url(&url) {
+        Some(_) => Ok(url),
+        None => Err(DriverError::Usage(format!("`--notify` needs an http:// URL, not `{}`", url))),
+    }
+}
+
+/// Compiles the input and writes every artifact, returning their paths
+///
+/// With `--timings`, the trace is written even when compiling fails.
+pub fn compile(options: &Options) -> Result<Vec<PathBuf>, DriverError> {
+    if options.timings {
+        timings::start();
+    }
+    let args = ["--crate-type=cdylib".to_string()];
+    let result = lower_crate(&options.input, &options.config, options.error_format, &args)
+        .and_then(|lowered| emit(options, &lowered.module));
+    if options.timings {
+        write_timings(&options.timings_path())?;
+    }
+    result
+}
+
+/// Writes the spans recorded since `timings::start` as a Chrome trace, and sums them up on stderr
+pub fn write_timings(path: &Path) -> Result<(), DriverError> {
+    let Some(timings) = timings::finish() else { return Ok(()) };
+    fs::write(path, timings.chrome_trace()).map_err(|err| DriverError::Io(path.to_path_buf(), err))?;
+    eprintln!("     Timings {}", path.display());
+    eprint!("{}", timings);
+    Ok(())
+}
+
+/// Compiles the input, then again each time its crate's sources change
+///
+/// The crate is taken to be the input's directory tree. Errors are
+/// reported and wait for the sources to change like successes, and a
+/// change that leaves the sources as they were last compiled is skipped.
+pub fn watch(options: &Options) -> ! {
+    let dir = match options.input.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut watcher = Watcher::new(vec![dir.to_path_buf()]);
+    let mut cache = IncrementalCache::new();
+    let key = options.input.display().to_string();
+    let settings = format!("{:?} {} {:?}", options.config, options.backend, options.emit);
+    loop {
+        let fingerprint = watch::fingerprint(&[dir], &[&settings]);
+        if !cache.is_fresh(&key, fingerprint) {
+            eprintln!("   Compiling {}", options.input.display());
+            match compile(options) {
+                Ok(written) => {
+                    cache.record(&key, fingerprint);
+                    notify_dev_server(options.notify.as_deref(), &written);
+                }
+                Err(err) => print_error(&err, options.error_format),
+            }
+        }
+        eprintln!("    Watching {} for changes", dir.display());
+        watcher.wait();
+    }
+}
+
+/// Tells the dev server at `url`, if any, of rebuilt artifacts
+///
+/// A dev server that is not up yet only makes for a warning.
+pub fn notify_dev_server(url: Option<&str>, artifacts: &[PathBuf]) {
+    let Some(url) = url else { return };
+    if let Err(err) = watch::notify(url, artifacts) {
+        eprintln!("warning: cannot notify {}: {}", url, err);
+    }
+}
+
+/// Lowers the crate rooted at `input` with rustc, given further rustc flags
+#[cfg(feature = "rustc-mir")]
+pub fn lower_crate(
+    input: &Path,
+    config: &CompilerConfig,
+    error_format: MessageFormat,
+    args: &[String],
+) -> Result<LoweredCrate, DriverError> {
+    let _rustc = timings::span(timings::Phase::Frontend, format_args!("rustc {}", input.display()));
+    rustc::lower_crate(input, config, error_format, args).map_err(DriverError::Lowering)
+}
+
+#[cfg(not(feature = "rustc-mir"))]
+pub fn lower_crate(_: &Path, _: &CompilerConfig, _: MessageFormat, _: &[String]) -> Result<LoweredCrate, DriverError> {
+    Err(DriverError::Unsupported(
+        "compiling Rust sources needs the compiler built with the `rustc-mir` feature".to_string(),
+    ))
+}
+
+/// Writes every artifact of a lowered module, returning their paths
+///
+/// With `--split-cold`, the module's cold functions are written next to
+/// the wasm as its `split::secondary_file_name`, and the other artifacts
+/// are of the primary. The size report and capabilities, if asked for, go
+/// to stdout. With `-l`, the wasm is the module linked with the libraries,
+/// and with `--shared`, a side module.
+pub fn emit(options: &Options, module: &WasmModule) -> Result<Vec<PathBuf>, DriverError> {
+    // WasmIR per pass comes out of compiling the module
+    let dump = match &options.emit_filter {
+        Some(filter) if options.emit.contains(&EmitKind::WasmIr) => Some(PassDump::new(filter.clone())),
+        _ => None,
+    };
+    let needs_binary = options.emit.iter().any(|&kind| matches!(kind, EmitKind::Wasm | EmitKind::Wat));
+    let reports = options.size_report.is_some() || options.capabilities.is_some();
+    let (binary, secondary) = match needs_binary || reports || dump.is_some() {
+        true => {
+            let mut frontend = WasmRustFrontend::new(options.config.clone())
+                .map_err(|err| DriverError::Emit(err.to_string()))?;
+            if let Some(dump) = &dump {
+                frontend.dump_passes(dump.clone());
+            }
+            let binaries = if options.shared {
+                frontend.compile_shared(module).map(|binary| (binary, None))
+            } else if !options.config.link_libraries.is_empty() {
+                frontend.compile_linked(module).map(|binary| (binary, None))
+            } else {
+                frontend.compile_split(module)
+            };
+            binaries.map_err(|err| DriverError::Emit(err.to_string()))?
+        }
+        false => (Vec::new(), None),
+    };
+
+    let mut written = Vec::new();
+    for &kind in &options.emit {
+        if let Some(filter) = options.emit_filter.as_ref().filter(|_| kind.is_intermediate()) {
+            written.extend(emit_functions(options, kind, module, filter, dump.as_ref())?);
+            continue;
+        }
+        let functions: Vec<&WasmIR> = module.functions.iter().collect();
+        let contents = match kind {
+            EmitKind::Wasm => binary.clone(),
+            EmitKind::Wat => wasmprinter::print_bytes(&binary)
+                .map_err(|err| DriverError::Emit(format!("cannot print the module: {}", err)))?
+                .into_bytes(),
+            EmitKind::Obj => object(options, module)?,
+            EmitKind::WasmIr => format!("{:#?}\n", module).into_bytes(),
+            EmitKind::Clif => clif(options, &functions)?.concat().into_bytes(),
+            EmitKind::LlvmIr => llvm_ir(options, &functions)?.concat().into_bytes(),
+        };
+        let path = options.output_path(kind);
+        fs::write(&path, contents).map_err(|err| DriverError::Io(path.clone(), err))?;
+        written.push(path);
+        if let (EmitKind::Wasm, Some(secondary)) = (kind, &secondary) {
+            let path = secondary_path(&options.output_path(kind));
+            fs::write(&path, secondary).map_err(|err| DriverError::Io(path.clone(), err))?;
+            written.push(path);
+        }
+    }
+    if let Some(format) = options.size_report {
+        print!("{}", size_report(&options.config, module, &binary)?.render(format, None));
+    }
+    if let Some(format) = options.capabilities {
+        print!("{}", capabilities(&binary, format)?);
+    }
+    Ok(written)
+}
+
+/// Path of the secondary module split off the module at `wasm`
+fn secondary_path(wasm: &Path) -> PathBuf {
+    wasm.with_file_name(secondary_file_name(&wasm.file_name().unwrap_or_default().to_string_lossy()))
+}
+
+/// Writes `kind` of each function `filter` picks to the dump directory, returning the files
+///
+/// WasmIR comes from `dump`, one file per pass that changed the function.
+fn emit_functions(
+    options: &Options,
+    kind: EmitKind,
+    module: &WasmModule,
+    filter: &FunctionFilter,
+    dump: Option<&PassDump>,
+) -> Result<Vec<PathBuf>, DriverError> {
+    let functions: Vec<&WasmIR> = module.functions.iter().filter(|function| filter.matches(&function.name)).collect();
+    let per_function = |texts: Vec<String>| -> Vec<(String, String)> {
+        functions.iter()
+            .zip(texts)
+            .map(|(function, text)| (format!("{}.{}", file_stem(&function.name), kind.extension()), text))
+            .collect()
+    };
+    let files = match kind {
+        EmitKind::WasmIr => dump.map(PassDump::take).unwrap_or_default()
+            .into_iter()
+            .map(|snapshot| (snapshot.file_name(), snapshot.wasmir))
+            .collect(),
+        EmitKind::Clif => per_function(clif(options, &functions)?),
+        EmitKind::LlvmIr => per_function(llvm_ir(options, &functions)?),
+        EmitKind::Wasm | EmitKind::Wat | EmitKind::Obj => unreachable!("{} is written whole", kind.name()),
+    };
+    if files.is_empty() {
+        eprintln!("warning: `--emit-filter {}` matches no function", filter.pattern());
+        return Ok(Vec::new());
+    }
+
+    let dir = options.dump_dir();
+    fs::create_dir_all(&dir).map_err(|err| DriverError::Io(dir.clone(), err))?;
+    let mut written = Vec::new();
+    for (name, contents) in files {
+        let path = dir.join(name);
+        fs::write(&path, contents).map_err(|err| DriverError::Io(path.clone(), err))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Relocatable object of the whole module, which is not split
+fn object(options: &Options, module: &WasmModule) -> Result<Vec<u8>, DriverError> {
+    let mut frontend = WasmRustFrontend::new(options.config.clone()).map_err(|err| DriverError::Emit(err.to_string()))?;
+    frontend.compile_object(module).map_err(|err| DriverError::Emit(err.to_string()))
+}
+
+/// Cranelift IR of each function, each headed by a comment naming it
+fn clif(options: &Options, functions: &[&WasmIR]) -> Result<Vec<String>, DriverError> {
+    let mut backend = WasmRustCraneliftBackend::new().map_err(|err| DriverError::Emit(err.to_string()))?;
+    backend.set_features(options.config.target_features.set());
+    functions.iter()
+        .map(|function| {
+            let clif = backend.emit_clif(function)
+                .map_err(|err| DriverError::Emit(format!("{}: {}", function.name, err)))?;
+            Ok(format!("; {}\n{}\n", function.name, clif))
+        })
+        .collect()
+}
+
+/// Size report of `binary`, compiled from `module` with `config`
+///
+/// Without debug info, functions are named from a build with it.
+pub fn size_report(config: &CompilerConfig, module: &WasmModule, binary: &[u8]) -> Result<SizeReport, DriverError> {
+    let invalid = |err: wasmparser::BinaryReaderError| DriverError::Emit(format!("cannot read the module: {}", err));
+    let report = SizeReport::new(binary).map_err(invalid)?;
+    if config.debug_info {
+        return Ok(report);
+    }
+    let named = WasmRustFrontend::new(CompilerConfig { debug_info: true, ..config.clone() })
+        .and_then(|mut frontend| frontend.compile_module(module))
+        .map_err(|err| DriverError::Emit(err.to_string()))?;
+    report.names_from(&named).map_err(invalid)
+}
+
+/// Manifest of the host capabilities `binary` needs, rendered in `format`
+pub fn capabilities(binary: &[u8], format: SizeFormat) -> Result<String, DriverError> {
+    let manifest = CapabilityManifest::from_binary(binary)
+        .map_err(|err| DriverError::Emit(err.to_string()))?
+        .unwrap_or_default();
+    Ok(match format {
+        SizeFormat::Table => manifest.to_string(),
+        SizeFormat::Json => format!("{}\n", manifest.to_json()),
+    })
+}
+
+/// LLVM IR of each function
+#[cfg(feature = "llvm-backend")]
+fn llvm_ir(options: &Options, functions: &[&WasmIR]) -> Result<Vec<String>, DriverError> {
+    use crate::backend::llvm::WasmRustLLVMBackend;
+
+    let target = rustc_target::spec::Target { arch: options.config.target.clone(), ..Default::default() };
+    let mut backend = WasmRustLLVMBackend::new(target).map_err(|err| DriverError::Emit(err.to_string()))?;
+    backend.set_features(options.config.target_features.set());
+    functions.iter()
+        .map(|function| backend.emit_llvm_ir(function, options.config.build_profile))
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|err| DriverError::Emit(err.to_string()))
+}
+
+#[cfg(not(feature = "llvm-backend"))]
+fn llvm_ir(_: &Options, _: &[&WasmIR]) -> Result<Vec<String>, DriverError> {
+    Err(DriverError::Unsupported("`--emit=llvm-ir` needs the `llvm-backend` feature".to_string()))
+}
+
+/// Prints an error of a compilation to stderr
+pub fn print_error(err: &DriverError, format: MessageFormat) {
+    match err {
+        DriverError::Lowering(diagnostics) => {
+            // rustc already reported the errors that left no diagnostics
+            if !diagnostics.is_empty() {
+                let _ = report(&mut io::stderr(), format, diagnostics);
+            }
+        }
+        DriverError::Budget(violations) => {
+            for violation in violations {
+                eprintln!("error: {}", violation);
+            }
+        }
+        err => eprintln!("error: {}", err),
+    }
+}
+
+/// Writes lowering diagnostics in `format`, quoting the files they point into
+pub fn report(out: &mut impl io::Write, format: MessageFormat, diagnostics: &Diagnostics) -> io::Result<()> {
+    let mut renderer = Renderer::new();
+    let mut files: Vec<&str> = diagnostics.iter()
+        .flat_map(|diagnostic| &diagnostic.spans)
+        .map(|span| span.location.file.as_str())
+        .collect();
+    files.sort_unstable();
+    files.dedup();
+    for file in files {
+        if let Ok(text) = fs::read_to_string(file) {
+            renderer = renderer.with_source(file, text);
+        }
+    }
+    renderer.emit(out, format, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::features::Proposal;
+    use wasm::wasmir::{Signature, Terminator, WasmIR};
+
+    fn parse(args: &[&str]) -> Result<Options, DriverError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match parse_args(&args)? {
+            Command::Compile(options) => Ok(*options),
+            command => panic!("expected a compilation, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_flags_map_to_the_config() {
+        let options = parse(&[
+            "--profile", "release", "-O", "--emit=wasm,wat,wasm", "-g", "--error-format=json", "-o", "out/app.wasm",
+            "src/lib.rs",
+        ])
+        .unwrap();
+        assert_eq!(options.config.build_profile, BuildProfile::Release);
+        assert_eq!(options.config.optimization_level, OptimizationLevel::Standard);
+        assert!(options.config.debug_info);
+        assert_eq!(options.emit, [EmitKind::Wasm, EmitKind::Wat]);
+        assert_eq!(options.error_format, MessageFormat::Json);
+        assert_eq!(options.backend, "cranelift");
+        assert_eq!(options.output_path(EmitKind::Wat), PathBuf::from("out/app.wat"));
+
+        let options = parse(&["--opt-level", "3", "src/lib.rs"]).unwrap();
+        assert_eq!(options.config.optimization_level, OptimizationLevel::Aggressive);
+        assert!(!options.config.debug_info);
+        assert_eq!(options.output_path(EmitKind::Wasm), PathBuf::from("lib.wasm"));
+        assert!(matches!(parse_args(&["-V".to_string()]), Ok(Command::Version)));
+
+        let options = parse(&["-w", "--notify=http://localhost:8000/__reload", "--size-report", "src/lib.rs"]).unwrap();
+        assert!(options.watch);
+        assert_eq!(options.size_report, Some(SizeFormat::Table));
+        assert_eq!(parse(&["--size-report=json", "a.rs"]).unwrap().size_report, Some(SizeFormat::Json));
+        assert_eq!(parse(&["--capabilities=json", "a.rs"]).unwrap().capabilities, Some(SizeFormat::Json));
+        assert_eq!(options.capabilities, None);
+        assert!(options.config.capability_policy.allows_all());
+        let args = ["--allow-capabilities", "js-interop,threading", "--deny-capabilities=threading", "a.rs"];
+        let policy = parse(&args).unwrap().config.capability_policy;
+        assert!(policy.permits(&Capability::JsInterop));
+        assert!(!policy.permits(&Capability::Threading) && !policy.permits(&Capability::ComponentModel));
+        assert_eq!(options.notify.as_deref(), Some("http://localhost:8000/__reload"));
+
+        let options = parse(&["--timings", "--emit=wat,wasm", "-o", "out/app.wasm", "src/lib.rs"]).unwrap();
+        assert!(options.timings);
+        assert_eq!(options.timings_path(), PathBuf::from("out/app.timings.json"));
+        assert!(!options.config.deterministic);
+        assert!(parse(&["--deterministic", "src/lib.rs"]).unwrap().config.deterministic);
+        assert!(parse(&["--pre-initialize", "src/lib.rs"]).unwrap().config.pre_initialize);
+        assert!(parse(&["--split-cold", "src/lib.rs"]).unwrap().config.split_cold);
+        assert!(parse(&["--shared", "src/lib.rs"]).unwrap().shared);
+        assert!(parse(&["--demangle", "src/lib.rs"]).unwrap().config.demangle);
+
+        let options = parse(&["-C", "target-feature=+simd128,+atomics", "-Ctarget-feature=-atomics", "a.rs"]).unwrap();
+        assert!(options.config.target_features.contains(Proposal::Simd));
+        assert!(!options.config.target_features.contains(Proposal::Threads));
+
+        let options = parse(&["--profile", "freestanding", "-C", "stack-size=4096", "a.rs"]).unwrap();
+        assert_eq!(options.config.build_profile, BuildProfile::Freestanding);
+        assert_eq!(options.config.stack_size, 4096);
+        assert!(!options.config.checks_stack());
+        assert!(parse(&["--profile", "release", "-C", "stack-check=yes", "a.rs"]).unwrap().config.checks_stack());
+        assert_eq!(parse(&["-Cstack-check=off", "a.rs"]).unwrap().config.stack_check, Some(false));
+        assert_eq!(parse(&["-Cregion-check=n", "a.rs"]).unwrap().config.region_checks, Some(false));
+
+        let dir = std::env::temp_dir().join(format!("wasmrust-driver-target-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let spec = dir.join("wasm32-plugin.json");
+        let json = r#"{"llvm-target": "wasm32-unknown-unknown", "arch": "wasm32", "features": "+simd128"}"#;
+        fs::write(&spec, json).unwrap();
+        let target = format!("--target={}", spec.display());
+        let options = parse(&["-Ctarget-feature=+atomics", &target, "a.rs"]).unwrap();
+        assert!(options.config.target_features.contains(Proposal::Simd));
+        assert!(options.config.target_features.contains(Proposal::Threads));
+
+        let profile = dir.join("app.profile");
+        fs::write(&profile, "7\tmain\n").unwrap();
+        let options = parse(&[&format!("-Cprofile-use={}", profile.display()), "a.rs"]).unwrap();
+        assert_eq!(options.config.pgo.unwrap().function_count("main"), 7);
+
+        fs::write(dir.join("libc.a"), b"").unwrap();
+        fs::write(dir.join("sqlite.o"), b"").unwrap();
+        let search = format!("-Lnative={}", dir.display());
+        let options = parse(&["-L", &search[2..], "-lc", "-l", "static=sqlite", "-Clinker=rust-lld", "a.rs"]).unwrap();
+        assert_eq!(options.config.link_libraries, [dir.join("libc.a"), dir.join("sqlite.o")]);
+        assert_eq!(options.config.linker, Some(PathBuf::from("rust-lld")));
+        let error = parse(&[&search, "-lc", "--split-cold", "a.rs"]).unwrap_err().to_string();
+        assert_eq!(error, "`--split-cold` cannot be used with `-l`");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_bad_flags() {
+        let error = |args: &[&str]| parse(args).unwrap_err().to_string();
+        assert_eq!(
+            error(&["--emit=exe", "a.rs"]),
+            "unknown emit kind `exe`; expected one of wasm, wat, obj, wasmir, clif, llvm-ir"
+        );
+        assert_eq!(
+            error(&["--profile", "fast", "a.rs"]),
+            "unknown profile `fast`; expected dev, release, or freestanding"
+        );
+        assert_eq!(error(&["a.rs", "-o"]), "`-o` needs a value");
+        assert_eq!(error(&["a.rs", "b.rs"]), "unexpected argument `b.rs`; only one input is accepted");
+        assert_eq!(error(&["--emit=llvm-ir", "a.rs"]), "`--emit=llvm-ir` needs `--backend llvm`");
+        assert_eq!(error(&["-O"]), "no input file");
+        assert_eq!(
+            error(&["--target", "wasm64-unknown-unknown", "a.rs"]),
+            "unknown target `wasm64-unknown-unknown`; expected one of wasm32-unknown-unknown, \
+             wasm32-unknown-emscripten or a target spec file"
+        );
+        assert_eq!(error(&["--notify", "http://localhost:8000", "a.rs"]), "`--notify` needs `--watch`");
+        assert_eq!(
+            error(&["--emit-filter", "main", "a.rs"]),
+            "`--emit-filter` needs `--emit` of wasmir, clif, or llvm-ir"
+        );
+        assert_eq!(
+            error(&["-C", "opt-level=3", "a.rs"]),
+            "unknown codegen option `opt-level=3`; expected target-feature=<list>, stack-size=<bytes>, \
+             stack-check=<yes|no>, region-check=<yes|no>, profile-use=<path>, or linker=<path>"
+        );
+        assert_eq!(error(&["-Cstack-size=64k", "a.rs"]), "stack size `64k` is not a number of bytes");
+        assert_eq!(
+            error(&["-lwasm-rust-missing", "a.rs"]),
+            "cannot find library `wasm-rust-missing`; looked for libwasm-rust-missing.a, wasm-rust-missing.o, \
+             wasm-rust-missing.wasm in the -L directories and the working directory"
+        );
+        assert_eq!(error(&["-l", "dylib=z", "a.rs"]), "cannot link `dylib`: only static libraries link into wasm");
+        assert_eq!(
+            error(&["--shared", "--split-cold", "a.rs"]),
+            "`--shared` cannot be used with `--split-cold` or `-l`"
+        );
+        assert_eq!(error(&["-Cstack-check=maybe", "a.rs"]), "stack check `maybe` is not yes or no");
+        assert_eq!(error(&["-Cregion-check=1", "a.rs"]), "region check `1` is not yes or no");
+        assert_eq!(
+            error(&["-Ctarget-feature=simd128", "a.rs"]),
+            "target feature `simd128` needs `+` to enable it or `-` to disable it"
+        );
+        assert_eq!(
+            error(&["--size-report=csv", "a.rs"]),
+            "unknown size report format `csv`; expected table or json"
+        );
+        assert_eq!(
+            error(&["--capabilities=yaml", "a.rs"]),
+            "unknown capabilities format `yaml`; expected table or json"
+        );
+        assert!(error(&["--deny-capabilities", "network", "a.rs"]).starts_with("unknown capability `network`"));
+        assert_eq!(
+            error(&["-w", "--notify", "ws://localhost:8000", "a.rs"]),
+            "`--notify` needs an http:// URL, not `ws://localhost:8000`"
+        );
+    }
+
+    #[test]
+    fn test_emits_each_artifact() {
+        let dir = std::env::temp_dir().join(format!("wasm-rustc-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("answer.wasm");
+        let args = ["--emit", "wasm,wat,obj,wasmir", "-g", "-o", output.to_str().unwrap(), "answer.rs"];
+        let options = parse(&args).unwrap();
+
+        let mut module = WasmModule::new();
+        let mut function = WasmIR::new("answer".to_string(), Signature { params: vec![], returns: None });
+        function.add_basic_block(vec![], Terminator::Return { value: None });
+        let index = module.add_function(function);
+        module.export_function("answer".to_string(), index);
+
+        let written = emit(&options, &module).unwrap();
+        let extensions = ["wasm", "wat", "o", "wasmir"];
+        assert_eq!(written, extensions.map(|extension| dir.join("answer").with_extension(extension)));
+        assert!(fs::read(&written[0]).unwrap().starts_with(b"\0asm"));
+        let wat = fs::read_to_string(&written[1]).unwrap();
+        assert!(wat.contains("(func $answer") && wat.contains("(export \"answer\""), "{}", wat);
+        let object = fs::read(&written[2]).unwrap();
+        assert!(object.windows(7).any(|window| window == b"linking"));
+        assert!(fs::read_to_string(&written[3]).unwrap().contains("name: \"answer\""));
+        let binary = fs::read(&written[0]).unwrap();
+        assert_eq!(capabilities(&binary, SizeFormat::Table).unwrap(), "no host capabilities required\n");
+        assert_eq!(capabilities(&binary, SizeFormat::Json).unwrap(), "{\"capabilities\":[],\"version\":1}\n");
+
+        // Filtered, WasmIR goes per function and pass
+        let mut other = WasmIR::new("other".to_string(), Signature { params: vec![], returns: None });
+        other.add_basic_block(vec![], Terminator::Return { value: None });
+        module.add_function(other);
+        let args = ["--emit=wasm,wasmir", "--emit-filter", "ans*", "-o", output.to_str().unwrap(), "answer.rs"];
+        let options = parse(&args).unwrap();
+        let written = emit(&options, &module).unwrap();
+        assert_eq!(written, [dir.join("answer.wasm"), dir.join("answer.dump/answer.00-input.wasmir")]);
+        assert!(fs::read_to_string(&written[1]).unwrap().contains("name: \"answer\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_deterministic_builds_are_byte_identical() {
+        let mut module = WasmModule::new();
+        for name in ["app::main", "app::parse::<u8>", "core::panicking::panic"] {
+            let mut function = WasmIR::new(name.to_string(), Signature { params: vec![], returns: None });
+            function.add_basic_block(vec![], Terminator::Return { value: None });
+            let index = module.add_function(function);
+            module.export_function(name.replace("::", "_"), index);
+        }
+
+        // Each build stands in for another machine, with its own directory and output name
+        let builds: Vec<Vec<u8>> = (0..2)
+            .map(|build| {
+                let dir = std::env::temp_dir().join(format!("wasm-rustc-{}-build{}", std::process::id(), build));
+                fs::create_dir_all(&dir).unwrap();
+                let output = dir.join(format!("app{}.wasm", build));
+                let options = parse(&["--deterministic", "-g", "-o", output.to_str().unwrap(), "src/main.rs"]).unwrap();
+                let written = emit(&options, &module).unwrap();
+                let binary = fs::read(&written[0]).unwrap();
+                fs::remove_dir_all(&dir).unwrap();
+                binary
+            })
+            .collect();
+        assert!(builds[0].starts_with(b"\0asm"));
+        assert_eq!(builds[0], builds[1]);
+    }
+}
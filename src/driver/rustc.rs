@@ -0,0 +1,115 @@
+//! Lowering a crate with rustc, run in-process
+//!
+//! rustc parses, type-checks, borrow-checks, and optimizes the crate as
+//! usual, with the flags `BodyLowering` expects. `Lower` then lowers
+//! `crate_functions` into one module after analysis, exporting the
+//! `#[no_mangle]` and `#[export_name]` ones and a binary's entry point as
+//! `main`. Under `--test` it leaves out the harness's entry point and
+//! describes the crate's `#[test]` functions instead. rustc stops there
+//! unless it was asked for `--emit=metadata`, which dependent crates are
+//! checked against.
+
+use super::LoweredCrate;
+use crate::backend::cranelift::rustc_lowering::{crate_functions, export_name, test_functions, BodyLowering};
+use crate::backend::OptimizationLevel;
+use crate::diagnostics::{Diagnostics, MessageFormat};
+use crate::CompilerConfig;
+use rustc_driver::{Callbacks, Compilation, RunCompiler};
+use rustc_interface::interface::Compiler;
+use rustc_interface::Queries;
+use std::path::Path;
+
+/// Flags `BodyLowering` needs rustc to compile with
+const LOWERING_FLAGS: &[&str] = &["-Cpanic=abort", "-Coverflow-checks=off"];
+
+/// Lowers the crate rooted at `input`
+///
+/// `args` are further rustc flags, such as the crate type and `--extern`s.
+/// rustc prints its own errors in `error_format`; `Err` carries the
+/// lowering's diagnostics, which are empty when rustc failed.
+pub fn lower_crate(
+    input: &Path,
+    config: &CompilerConfig,
+    error_format: MessageFormat,
+    args: &[String],
+) -> Result<LoweredCrate, Diagnostics> {
+    let mut rustc_args = vec![
+        "rustc".to_string(),
+        input.display().to_string(),
+        format!("--target={}", config.target),
+        format!("--error-format={}", error_format.name()),
+        format!("-Copt-level={}", opt_level(config.optimization_level)),
+        format!("-Ctarget-feature={}", config.target_features),
+    ];
+    rustc_args.extend(LOWERING_FLAGS.iter().map(|flag| flag.to_string()));
+    if config.debug_info {
+        rustc_args.push("-g".to_string());
+    }
+    if config.deterministic {
+        // Paths rustc embeds, such as those of panic locations, name the
+        // sources as they are in the checkout rather than on this machine
+        if let Ok(cwd) = std::env::current_dir() {
+            rustc_args.push(format!("--remap-path-prefix={}=.", cwd.display()));
+        }
+    }
+    rustc_args.extend_from_slice(args);
+
+    let mut lower = Lower { emit_metadata: args.iter().any(|arg| arg == "--emit=metadata"), result: None };
+    let status = RunCompiler::new(&rustc_args, &mut lower).run();
+    match (status, lower.result) {
+        (Ok(()), Some(result)) => result,
+        _ => Err(Diagnostics::new()),
+    }
+}
+
+/// rustc's `-C opt-level` for an optimization level
+fn opt_level(level: OptimizationLevel) -> &'static str {
+    match level {
+        OptimizationLevel::None => "0",
+        OptimizationLevel::Basic => "1",
+        OptimizationLevel::Standard => "2",
+        OptimizationLevel::Aggressive | OptimizationLevel::PGO => "3",
+    }
+}
+
+/// Lowers the crate once rustc has analyzed it
+struct Lower {
+    /// Whether rustc goes on to write the crate's metadata
+    emit_metadata: bool,
+    result: Option<Result<LoweredCrate, Diagnostics>>,
+}
+
+impl Callbacks for Lower {
+    fn after_analysis<'tcx>(&mut self, _compiler: &Compiler, queries: &'tcx Queries<'tcx>) -> Compilation {
+        queries.global_ctxt().unwrap().enter(|tcx| {
+            let test = tcx.sess.opts.test;
+            let entry = tcx.entry_fn(()).map(|(entry, _)| entry);
+            // The test harness's `main` calls into libtest instead
+            let items: Vec<_> = crate_functions(tcx).into_iter().filter(|&item| !test || entry != Some(item)).collect();
+            let mut lowering = BodyLowering::new(tcx);
+            self.result = Some(match lowering.lower_module(&items) {
+                Ok(mut module) => {
+                    for (index, &item) in items.iter().enumerate() {
+                        let name = match entry == Some(item) {
+                            true => Some("main".to_string()),
+                            false => export_name(tcx, item),
+                        };
+                        if let Some(name) = name {
+                            module.export_function(name, index as u32);
+                        }
+                    }
+                    let tests = match test {
+                        true => lowering.test_cases(&test_functions(tcx)),
+                        false => Vec::new(),
+                    };
+                    Ok(LoweredCrate { module, tests })
+                }
+                Err(_) => Err(lowering.diagnostics().clone()),
+            });
+        });
+        match self.emit_metadata {
+            true => Compilation::Continue,
+            false => Compilation::Stop,
+        }
+    }
+}
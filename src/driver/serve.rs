@@ -0,0 +1,441 @@
+//! Dev server with hot reloading
+//!
+//! `DevServer` serves a directory of build output: the modules, their
+//! JavaScript glue, and an `index.html`, generated to initialize the
+//! first module when the directory has none. HTML pages get the reload
+//! client injected, which listens to `EVENTS_PATH` as Server-Sent Events.
+//!
+//! After a rebuild, `DevServer::rebuilt` compares each new module with
+//! the one served before. When only a module changed, pages swap it in
+//! through the glue's `hotSwap`, keeping linear memory if the sections
+//! laying it out are unchanged (see `same_memory_layout`); when the glue
+//! changed too, its exports may have, so pages reload. Watchers running
+//! in another process POST the rebuilt paths to `RELOAD_PATH`, the way
+//! `watch::notify` does.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Address `cargo wasmrust serve` listens on by default
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8000";
+
+/// Path of the reload client's event stream
+pub const EVENTS_PATH: &str = "/__wasmrust/events";
+
+/// Path watchers POST rebuilt artifacts to
+pub const RELOAD_PATH: &str = "/__wasmrust/reload";
+
+/// Path of the reload client script
+pub const CLIENT_PATH: &str = "/__wasmrust/client.js";
+
+/// Section ids whose contents place data in linear memory: imports,
+/// memories, globals, elements, data, and the data count
+const LAYOUT_SECTIONS: &[u8] = &[2, 5, 6, 9, 11, 12];
+
+/// Script pages load the reload client with
+const CLIENT_TAG: &str = "<script type=\"module\" src=\"/__wasmrust/client.js\"></script>\n";
+
+/// Reload client, swapping modules in or reloading the page
+const CLIENT: &str = r#"// Generated by WasmRust. Do not edit.
+const events = new EventSource("/__wasmrust/events");
+events.addEventListener("reload", () => location.reload());
+events.addEventListener("hot", async (event) => {
+  const { module, glue, preserveMemory } = JSON.parse(event.data);
+  try {
+    const bindings = await import(glue);
+    if (typeof bindings.hotSwap !== "function") return location.reload();
+    await bindings.hotSwap(fetch(`${module}?t=${Date.now()}`), preserveMemory);
+    console.info(`[wasmrust] swapped in ${module}${preserveMemory ? ", keeping its memory" : ""}`);
+    dispatchEvent(new CustomEvent("wasmrust:hotswap", { detail: { module, preserveMemory } }));
+  } catch (err) {
+    console.error("[wasmrust] hot swap failed, reloading", err);
+    location.reload();
+  }
+});
+"#;
+
+/// What pages do about a rebuild
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update {
+    /// Swap in the module at the server path `module` through `glue`
+    Hot { module: String, glue: String, preserve_memory: bool },
+    /// Reload the page
+    Reload,
+}
+
+impl Update {
+    /// The update as a Server-Sent Event
+    pub fn event(&self) -> String {
+        match self {
+            Update::Hot { module, glue, preserve_memory } => format!(
+                "event: hot\ndata: {{\"module\":\"{}\",\"glue\":\"{}\",\"preserveMemory\":{}}}\n\n",
+                module, glue, preserve_memory
+            ),
+            Update::Reload => "event: reload\ndata: {}\n\n".to_string(),
+        }
+    }
+}
+
+/// Whether two binaries lay out linear memory the same way
+///
+/// Only then does memory copied from an instance of `old` mean the same
+/// to an instance of `new`: statics sit at the same addresses, and the
+/// function table, which closures index, holds the same functions.
+pub fn same_memory_layout(old: &[u8], new: &[u8]) -> bool {
+    match (layout_sections(old), layout_sections(new)) {
+        (Some(old), Some(new)) => old == new,
+        _ => false,
+    }
+}
+
+/// Contents of the `LAYOUT_SECTIONS` of a binary, in order
+fn layout_sections(binary: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut sections = Vec::new();
+    for payload in wasmparser::Parser::new(0).parse_all(binary) {
+        if let Some((id, range)) = payload.ok()?.as_section() {
+            if LAYOUT_SECTIONS.contains(&id) {
+                sections.push((id, &binary[range]));
+            }
+        }
+    }
+    Some(sections)
+}
+
+/// Modules and glue as pages last got them, and the pages listening
+#[derive(Debug, Default)]
+struct State {
+    served: HashMap<PathBuf, Vec<u8>>,
+    clients: Vec<TcpStream>,
+}
+
+/// Serves a directory of build output on a background thread
+#[derive(Debug, Clone)]
+pub struct DevServer {
+    root: PathBuf,
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl DevServer {
+    /// Listens on `address` and serves `root`, taking its files as served
+    pub fn bind(root: impl Into<PathBuf>, address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let server = Self {
+            root: root.into(),
+            address: listener.local_addr()?,
+            state: Arc::new(Mutex::new(State::default())),
+        };
+        let artifacts: Vec<PathBuf> = fs::read_dir(&server.root)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| is_artifact(path)).collect())
+            .unwrap_or_default();
+        server.remember(&artifacts);
+
+        let handler = server.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    let _ = handler.handle(stream);
+                });
+            }
+        });
+        Ok(server)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// URL of the served `index.html`
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.address)
+    }
+
+    /// Tells the listening pages of rebuilt artifacts, returning what they were told
+    ///
+    /// Artifacts outside the served directory, and modules and glue
+    /// rebuilt byte for byte, change nothing.
+    pub fn rebuilt(&self, artifacts: &[PathBuf]) -> Vec<Update> {
+        let updates = self.updates(artifacts);
+        self.remember(artifacts);
+        let mut state = self.state.lock().unwrap();
+        for update in &updates {
+            let event = update.event();
+            // Pages that went away are forgotten
+            state.clients.retain_mut(|client| client.write_all(event.as_bytes()).and_then(|()| client.flush()).is_ok());
+        }
+        updates
+    }
+
+    /// What rebuilding `artifacts` calls for
+    fn updates(&self, artifacts: &[PathBuf]) -> Vec<Update> {
+        let state = self.state.lock().unwrap();
+        let changed: Vec<(&PathBuf, Vec<u8>)> = artifacts.iter()
+            .filter(|path| path.parent() == Some(self.root.as_path()) && is_artifact(path))
+            .filter_map(|path| Some((path, fs::read(path).ok()?)))
+            .filter(|(path, contents)| state.served.get(*path) != Some(contents))
+            .collect();
+        if changed.iter().any(|(path, _)| path.extension().is_some_and(|ext| ext != "wasm")) {
+            return vec![Update::Reload];
+        }
+        changed.into_iter()
+            .map(|(path, contents)| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let glue = Path::new(name.as_ref()).with_extension("mjs");
+                Update::Hot {
+                    module: format!("/{}", name),
+                    glue: format!("/{}", glue.display()),
+                    preserve_memory: state.served.get(path).is_some_and(|old| same_memory_layout(old, &contents)),
+                }
+            })
+            .collect()
+    }
+
+    /// Records the artifacts as pages will now get them
+    fn remember(&self, artifacts: &[PathBuf]) {
+        let mut state = self.state.lock().unwrap();
+        for path in artifacts.iter().filter(|path| is_artifact(path)) {
+            if let Ok(contents) = fs::read(path) {
+                state.served.insert(path.clone(), contents);
+            }
+        }
+    }
+
+    /// Answers one request
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let path = target.split('?').next().unwrap_or_default();
+
+        match (method, path) {
+            ("GET", EVENTS_PATH) => {
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n")?;
+                stream.write_all(b"Cache-Control: no-store\r\n\r\n")?;
+                stream.flush()?;
+                self.state.lock().unwrap().clients.push(stream);
+                Ok(())
+            }
+            ("GET", CLIENT_PATH) => respond(&mut stream, "200 OK", "text/javascript", CLIENT.as_bytes()),
+            ("POST", RELOAD_PATH) => {
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body)?;
+                let artifacts: Vec<PathBuf> = String::from_utf8_lossy(&body).lines().map(PathBuf::from).collect();
+                self.rebuilt(&artifacts);
+                respond(&mut stream, "204 No Content", "text/plain", b"")
+            }
+            ("GET", path) => match self.file(path) {
+                Some((content_type, contents)) => respond(&mut stream, "200 OK", content_type, &contents),
+                None => respond(&mut stream, "404 Not Found", "text/plain", b"not found\n"),
+            },
+            _ => respond(&mut stream, "405 Method Not Allowed", "text/plain", b"method not allowed\n"),
+        }
+    }
+
+    /// Content type and contents of the file at a request path
+    fn file(&self, path: &str) -> Option<(&'static str, Vec<u8>)> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative.components().any(|component| !matches!(component, Component::Normal(_))) {
+            return None;
+        }
+        let relative = match relative.as_os_str().is_empty() {
+            true => Path::new("index.html"),
+            false => relative,
+        };
+        let file = self.root.join(relative);
+        let content_type = match file.extension().and_then(|ext| ext.to_str()) {
+            Some("html") => "text/html; charset=utf-8",
+            Some("wasm") => "application/wasm",
+            Some("mjs" | "js") => "text/javascript",
+            Some("json" | "map") => "application/json",
+            Some("css") => "text/css",
+            _ => "application/octet-stream",
+        };
+        match fs::read(&file) {
+            Ok(contents) if content_type.starts_with("text/html") => {
+                Some((content_type, inject_client(&String::from_utf8_lossy(&contents)).into_bytes()))
+            }
+            Ok(contents) => Some((content_type, contents)),
+            Err(_) if relative == Path::new("index.html") => Some((content_type, self.index().into_bytes())),
+            Err(_) => None,
+        }
+    }
+
+    /// Page initializing the first module with glue, for a directory without one
+    fn index(&self) -> String {
+        let mut glue: Vec<String> = fs::read_dir(&self.root)
+            .map(|entries| {
+                entries.flatten()
+                    .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "mjs"))
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        glue.sort();
+        let mut page = String::from("<!doctype html>\n<html>\n<head><meta charset=\"utf-8\"></head>\n<body>\n");
+        if let Some(glue) = glue.first() {
+            page.push_str("<script type=\"module\">\n");
+            page.push_str(&format!("import * as bindings from \"./{}\";\n", glue));
+            // The module's exports, for the console
+            page.push_str("await bindings.init();\nglobalThis.wasmrust = bindings;\n</script>\n");
+        }
+        page.push_str("</body>\n</html>\n");
+        inject_client(&page)
+    }
+}
+
+/// Whether pages are told of a rebuilt file: modules and their glue
+fn is_artifact(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "wasm" || ext == "mjs")
+}
+
+/// Adds the reload client to a page, before `</body>` when it has one
+fn inject_client(page: &str) -> String {
+    let mut page = page.to_string();
+    match page.rfind("</body>") {
+        Some(end) => page.insert_str(end, CLIENT_TAG),
+        None => page.push_str(CLIENT_TAG),
+    }
+    page
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::WasmCodegen;
+    use wasm::wasmir::{Constant, MemoryType, Operand, Signature, Terminator, Type, WasmIR, WasmModule};
+
+    /// Binary of a module with memory exporting `answer`, returning `value`
+    fn binary(value: i32, counter: Option<i32>) -> Vec<u8> {
+        let mut module = WasmModule::new();
+        module.set_memory(MemoryType { min_pages: 1, max_pages: None });
+        if let Some(init) = counter {
+            module.add_global("counter", Type::I32, true, Constant::I32(init));
+        }
+        let mut function = WasmIR::new("answer".to_string(), Signature { params: vec![], returns: Some(Type::I32) });
+        function.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(Constant::I32(value))) });
+        let index = module.add_function(function);
+        module.export_function("answer", index);
+        WasmCodegen::new().compile(&module).unwrap()
+    }
+
+    fn get(server: &DevServer, path: &str) -> String {
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn served_dir(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("wasmrust-serve-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("app.wasm"), binary(1, None)).unwrap();
+        fs::write(root.join("app.mjs"), "export async function init() {}\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_memory_layout_decides_preservation() {
+        assert!(same_memory_layout(&binary(1, None), &binary(2, None)));
+        assert!(!same_memory_layout(&binary(1, Some(0)), &binary(1, Some(7))));
+        assert!(!same_memory_layout(&binary(1, None), b"not wasm"));
+    }
+
+    #[test]
+    fn test_serves_output_with_the_reload_client() {
+        let root = served_dir("files");
+        let server = DevServer::bind(&root, "127.0.0.1:0").unwrap();
+
+        let index = get(&server, "/");
+        assert!(index.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/html"), "{}", index);
+        assert!(index.contains("import * as bindings from \"./app.mjs\";"), "{}", index);
+        assert!(index.contains(&format!("{}</body>", CLIENT_TAG)), "{}", index);
+        assert!(get(&server, "/app.wasm?t=1").contains("Content-Type: application/wasm"));
+        assert!(get(&server, CLIENT_PATH).contains("new EventSource(\"/__wasmrust/events\")"));
+        assert!(get(&server, "/../app.wasm").starts_with("HTTP/1.1 404"));
+
+        fs::write(root.join("index.html"), "<html><body><canvas></canvas></body></html>").unwrap();
+        let index = get(&server, "/index.html");
+        assert!(index.ends_with(&format!("<canvas></canvas>{}</body></html>", CLIENT_TAG)), "{}", index);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_rebuilds_swap_modules_or_reload_pages() {
+        let root = served_dir("events");
+        let server = DevServer::bind(&root, "127.0.0.1:0").unwrap();
+        let mut events = TcpStream::connect(server.address()).unwrap();
+        write!(events, "GET {} HTTP/1.1\r\n\r\n", EVENTS_PATH).unwrap();
+        let mut events = BufReader::new(events);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            events.read_line(&mut line).unwrap();
+        }
+        let artifacts = [root.join("app.wasm"), root.join("app.mjs")];
+
+        // Only code changed
+        fs::write(root.join("app.wasm"), binary(2, None)).unwrap();
+        let hot = Update::Hot { module: "/app.wasm".to_string(), glue: "/app.mjs".to_string(), preserve_memory: true };
+        assert_eq!(server.rebuilt(&artifacts), std::slice::from_ref(&hot));
+        let mut event = String::new();
+        events.read_line(&mut event).unwrap();
+        events.read_line(&mut event).unwrap();
+        assert_eq!(
+            event,
+            "event: hot\ndata: {\"module\":\"/app.wasm\",\"glue\":\"/app.mjs\",\"preserveMemory\":true}\n"
+        );
+
+        assert!(server.rebuilt(&artifacts).is_empty());
+        fs::write(root.join("app.wasm"), binary(2, Some(0))).unwrap();
+        let Update::Hot { module, glue, .. } = hot else { unreachable!() };
+        assert_eq!(server.rebuilt(&artifacts), [Update::Hot { module, glue, preserve_memory: false }]);
+
+        // A watcher in another process, after the glue changed
+        fs::write(root.join("app.mjs"), "export async function init() {}\nexport function answer() {}\n").unwrap();
+        let mut post = TcpStream::connect(server.address()).unwrap();
+        let body = format!("{}\n", artifacts[1].display());
+        write!(post, "POST {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", RELOAD_PATH, body.len(), body).unwrap();
+        let mut response = String::new();
+        post.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+        let mut event = String::new();
+        while !event.starts_with("event: reload") {
+            event.clear();
+            events.read_line(&mut event).unwrap();
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+//! Watch mode, rebuilding as sources change
+//!
+//! `Watcher` polls the crates' source directories, so it needs no
+//! platform file-notification API and sees edits made over network
+//! mounts too. A rebuild then skips every crate whose fingerprint, the
+//! hash of its sources, rustc flags, configuration, and dependencies'
+//! fingerprints, is the one `IncrementalCache` recorded for its last
+//! successful build. Once artifacts are written, `notify` tells a dev
+//! server about them so the browser can reload.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often `Watcher` rescans its roots
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Directories never watched besides hidden ones like `.git`
+const IGNORED_DIRS: &[&str] = &["target"];
+
+/// Modification time and length a file was seen with
+type Stamp = (Option<SystemTime>, u64);
+
+/// Watches the Rust sources and manifests under a set of roots
+#[derive(Debug, Clone)]
+pub struct Watcher {
+    roots: Vec<PathBuf>,
+    stamps: BTreeMap<PathBuf, Stamp>,
+}
+
+impl Watcher {
+    /// Watches `roots`, taking the files as they are now as unchanged
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        let mut watcher = Self { roots, stamps: BTreeMap::new() };
+        watcher.stamps = watcher.scan();
+        watcher
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Watches `roots` instead, keeping what is known of files under both
+    pub fn set_roots(&mut self, roots: Vec<PathBuf>) {
+        self.roots = roots;
+        let stamps = self.scan();
+        self.stamps.retain(|path, _| stamps.contains_key(path));
+        for (path, stamp) in stamps {
+            self.stamps.entry(path).or_insert(stamp);
+        }
+    }
+
+    /// Files modified, added, or removed since the last call, sorted
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let stamps = self.scan();
+        let mut changed: Vec<PathBuf> = stamps.iter()
+            .filter(|(path, stamp)| self.stamps.get(*path) != Some(stamp))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(self.stamps.keys().filter(|path| !stamps.contains_key(*path)).cloned());
+        changed.sort();
+        self.stamps = stamps;
+        changed
+    }
+
+    /// Blocks until files change, then until they stop changing
+    ///
+    /// Waiting out one quiet `POLL_INTERVAL` coalesces an editor's
+    /// several writes, or a `git checkout`, into one rebuild.
+    pub fn wait(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let more = self.changed();
+            if more.is_empty() && !changed.is_empty() {
+                changed.sort();
+                changed.dedup();
+                return changed;
+            }
+            changed.extend(more);
+        }
+    }
+
+    fn scan(&self) -> BTreeMap<PathBuf, Stamp> {
+        let mut stamps = BTreeMap::new();
+        for root in &self.roots {
+            scan(root, &mut stamps);
+        }
+        stamps
+    }
+}
+
+/// Stamps the watched files at or under `path`
+fn scan(path: &Path, stamps: &mut BTreeMap<PathBuf, Stamp>) {
+    let Ok(meta) = fs::metadata(path) else { return };
+    if meta.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else { return };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let is_dir = entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false);
+            if is_dir && (name.starts_with('.') || IGNORED_DIRS.contains(&name.as_ref())) {
+                continue;
+            }
+            scan(&entry.path(), stamps);
+        }
+    } else if is_watched(path) {
+        stamps.insert(path.to_path_buf(), (meta.modified().ok(), meta.len()));
+    }
+}
+
+/// Whether a change to the file can change a build
+fn is_watched(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "rs") || path.file_name().is_some_and(|name| name == "Cargo.toml")
+}
+
+/// Hashes the contents of the watched files under `dirs`, and `parts`
+///
+/// Hashing contents rather than modification times keeps a crate fresh
+/// when its files were only saved again or switched back to.
+pub fn fingerprint(dirs: &[&Path], parts: &[&str]) -> u64 {
+    let mut stamps = BTreeMap::new();
+    for dir in dirs {
+        scan(dir, &mut stamps);
+    }
+    let mut hasher = DefaultHasher::new();
+    for path in stamps.keys() {
+        path.hash(&mut hasher);
+        fs::read(path).unwrap_or_default().hash(&mut hasher);
+    }
+    for part in parts {
+        part.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Fingerprints of the crates built successfully, by a key naming each
+#[derive(Debug, Clone, Default)]
+pub struct IncrementalCache {
+    fingerprints: HashMap<String, u64>,
+}
+
+impl IncrementalCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `key` was last built from `fingerprint`
+    pub fn is_fresh(&self, key: &str, fingerprint: u64) -> bool {
+        self.fingerprints.get(key) == Some(&fingerprint)
+    }
+
+    /// Records a successful build of `key`
+    pub fn record(&mut self, key: &str, fingerprint: u64) {
+        self.fingerprints.insert(key.to_string(), fingerprint);
+    }
+
+    /// Forgets `key`, so it builds again
+    pub fn invalidate(&mut self, key: &str) {
+        self.fingerprints.remove(key);
+    }
+}
+
+/// Host and path of an `http://` URL, the only scheme `notify` speaks
+pub fn split_url(url: &str) -> Option<(&str, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    (!host.is_empty()).then_some((host, path))
+}
+
+/// POSTs the paths of rebuilt artifacts to a dev server, one per line
+pub fn notify(url: &str, artifacts: &[PathBuf]) -> io::Result<()> {
+    let (host, path) = split_url(url)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("`{}` is not an http:// URL", url)))?;
+    let address = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+    let body: String = artifacts.iter().map(|artifact| format!("{}\n", artifact.display())).collect();
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.lines().next().unwrap_or_default();
+    match status.split(' ').nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("dev server answered `{}`", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_watcher_reports_changed_sources() {
+        let root = std::env::temp_dir().join(format!("wasmrust-watch-{}", std::process::id()));
+        for dir in ["src", "target", ".git"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("Cargo.toml"), "[package]\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn a() {}\n").unwrap();
+        fs::write(root.join("src/util.rs"), "").unwrap();
+        let mut watcher = Watcher::new(vec![root.clone()]);
+        let before = fingerprint(&[&root], &["dev"]);
+        assert!(watcher.changed().is_empty());
+
+        fs::write(root.join("src/lib.rs"), "pub fn a() { b() }\n").unwrap();
+        fs::write(root.join("src/new.rs"), "").unwrap();
+        fs::remove_file(root.join("src/util.rs")).unwrap();
+        fs::write(root.join("README.md"), "").unwrap();
+        fs::write(root.join("target/out.rs"), "").unwrap();
+        fs::write(root.join(".git/HEAD.rs"), "").unwrap();
+        assert_eq!(watcher.changed(), [root.join("src/lib.rs"), root.join("src/new.rs"), root.join("src/util.rs")]);
+        assert!(watcher.changed().is_empty());
+        assert_ne!(fingerprint(&[&root], &["dev"]), before);
+
+        // Contents, not modification times, decide freshness
+        fs::write(root.join("src/lib.rs"), "pub fn a() {}\n").unwrap();
+        fs::remove_file(root.join("src/new.rs")).unwrap();
+        fs::write(root.join("src/util.rs"), "").unwrap();
+        assert_eq!(fingerprint(&[&root], &["dev"]), before);
+        assert_ne!(fingerprint(&[&root], &["release"]), before);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_cache_keeps_successful_builds() {
+        let mut cache = IncrementalCache::new();
+        assert!(!cache.is_fresh("app", 1));
+        cache.record("app", 1);
+        assert!(cache.is_fresh("app", 1));
+        assert!(!cache.is_fresh("app", 2));
+        cache.invalidate("app");
+        assert!(!cache.is_fresh("app", 1));
+    }
+
+    #[test]
+    fn test_notify_posts_artifacts() {
+        assert_eq!(split_url("http://localhost:8000/__reload"), Some(("localhost:8000", "/__reload")));
+        assert_eq!(split_url("http://localhost"), Some(("localhost", "/")));
+        assert_eq!(split_url("https://localhost/"), None);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/__reload", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !String::from_utf8_lossy(&request).ends_with("app.wasm\n") {
+                let read = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        notify(&url, &[PathBuf::from("out/app.wasm")]).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /__reload HTTP/1.1\r\n"), "{}", request);
+        assert!(request.ends_with("\r\n\r\nout/app.wasm\n"), "{}", request);
+    }
+}
@@ -0,0 +1,171 @@
+//! Host functions provided to compiled modules
+//!
+//! Embedders outside the browser supply imports as Rust closures registered
+//! under a `(module, name)` pair. The same set declares the imports on a
+//! `WasmModule` before compilation and is handed to `runtime::load`, which
+//! links the closures into the instance.
+
+use super::runtime::{RuntimeError, Value, ValueType};
+use std::sync::Arc;
+use wasm::wasmir::{Signature, WasmModule};
+
+/// Closure implementing a host function
+///
+/// Returns the result, if the function has one, or a message that traps
+/// the calling instance.
+pub type HostCallback = dyn Fn(&[Value]) -> Result<Option<Value>, String> + Send + Sync;
+
+/// Host function registered under an import name
+#[derive(Clone)]
+pub struct HostFunction {
+    pub module: String,
+    pub name: String,
+    pub params: Vec<ValueType>,
+    pub result: Option<ValueType>,
+    callback: Arc<HostCallback>,
+}
+
+impl HostFunction {
+    /// WasmIR signature of the import
+    pub fn signature(&self) -> Signature {
+        Signature {
+            params: self.params.iter().map(|ty| ty.wasmir_type()).collect(),
+            returns: self.result.map(ValueType::wasmir_type),
+        }
+    }
+
+    /// Calls the closure, checking its result has the declared type
+    pub fn call(&self, args: &[Value]) -> Result<Option<Value>, String> {
+        let result = (self.callback)(args)?;
+        match (result, self.result) {
+            (None, None) => Ok(None),
+            (Some(value), Some(ty)) if value.ty() == ty => Ok(Some(value)),
+            (value, expected) => Err(format!(
+                "host function {}::{} returned {}, expected {}",
+                self.module,
+                self.name,
+                value.map_or("nothing", |value| value.ty().name()),
+                expected.map_or("nothing", ValueType::name)
+            )),
+        }
+    }
+}
+
+impl std::fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("module", &self.module)
+            .field("name", &self.name)
+            .field("params", &self.params)
+            .field("result", &self.result)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Set of host functions for one kind of embedding
+#[derive(Debug, Clone, Default)]
+pub struct HostFunctions {
+    functions: Vec<HostFunction>,
+}
+
+impl HostFunctions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a closure, replacing any function with the same name
+    pub fn func(
+        mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        params: impl IntoIterator<Item = ValueType>,
+        result: Option<ValueType>,
+        callback: impl Fn(&[Value]) -> Result<Option<Value>, String> + Send + Sync + 'static,
+    ) -> Self {
+        let function = HostFunction {
+            module: module.into(),
+            name: name.into(),
+            params: params.into_iter().collect(),
+            result,
+            callback: Arc::new(callback),
+        };
+        self.functions.retain(|f| (&f.module, &f.name) != (&function.module, &function.name));
+        self.functions.push(function);
+        self
+    }
+
+    pub fn get(&self, module: &str, name: &str) -> Option<&HostFunction> {
+        self.functions.iter().find(|f| f.module == module && f.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &HostFunction> {
+        self.functions.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// Declares every function as an import of `module`
+    ///
+    /// Imports the module already declares must have the same signature.
+    pub fn declare_imports(&self, module: &mut WasmModule) -> Result<(), RuntimeError> {
+        for function in &self.functions {
+            let signature = function.signature();
+            if let Some(index) = module.find_import(&function.module, &function.name) {
+                if module.imports[index as usize].signature() != &signature {
+                    return Err(RuntimeError::Import(format!(
+                        "{}::{} is already imported with a different signature",
+                        function.module, function.name
+                    )));
+                }
+            }
+            module.add_import(function.module.clone(), function.name.clone(), signature);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::Type;
+
+    fn host() -> HostFunctions {
+        HostFunctions::new()
+            .func("env", "double", [ValueType::I32], Some(ValueType::I32), |args| match args {
+                [Value::I32(v)] => Ok(Some(Value::I32(v * 2))),
+                _ => Err("bad arguments".to_string()),
+            })
+            .func("env", "log", [ValueType::I32], None, |_| Ok(None))
+            .func("env", "wrong", [], Some(ValueType::F64), |_| Ok(Some(Value::I32(0))))
+    }
+
+    #[test]
+    fn test_declares_imports() {
+        let mut module = WasmModule::new();
+        host().declare_imports(&mut module).unwrap();
+        assert_eq!(module.imports.len(), 3);
+        let index = module.find_import("env", "double").unwrap();
+        assert_eq!(
+            module.imports[index as usize].signature(),
+            &Signature { params: vec![Type::I32], returns: Some(Type::I32) }
+        );
+        host().declare_imports(&mut module).unwrap();
+        assert_eq!(module.imports.len(), 3);
+
+        let mut conflicting = WasmModule::new();
+        conflicting.add_import("env", "log", Signature { params: vec![], returns: None });
+        assert!(matches!(host().declare_imports(&mut conflicting), Err(RuntimeError::Import(_))));
+    }
+
+    #[test]
+    fn test_calls_check_result_types() {
+        let host = host();
+        assert_eq!(host.get("env", "double").unwrap().call(&[Value::I32(21)]), Ok(Some(Value::I32(42))));
+        assert_eq!(
+            host.get("env", "wrong").unwrap().call(&[]),
+            Err("host function env::wrong returned i32, expected f64".to_string())
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! Host-side support for WasmRust compilation output
+//!
+//! Guest-side host integration (JS interop, closures, promises) lives in
+//! the `wasm` crate; this module is for embedders running what the
+//! compiler produced.
+
+pub mod functions;
+pub mod runtime;
+pub mod test_runner;
+
+pub use functions::HostFunctions;
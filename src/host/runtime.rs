@@ -0,0 +1,676 @@
+//! In-process execution of compiled modules
+//!
+//! A `Runtime` instantiates WebAssembly binaries inside the current process
+//! and an `Instance` invokes their exported functions, so library users and
+//! the registry test pipeline can run what they just compiled without
+//! shelling out to a runtime binary. Engines are optional dependencies:
+//! `Wasmtime` with the `wasmtime` feature and `Wasmer` with `wasmer`.
+//!
+//! Imports are satisfied from a `HostFunctions` set. Only core modules can
+//! be instantiated; components and native code, such as the LLVM backend's,
+//! are rejected up front. With the `wasi-runtime` feature, `Wasmtime` also
+//! runs WASI preview 1 commands, such as test binaries, to completion.
+
+use super::functions::HostFunctions;
+use crate::backend::codegen::CALL_CTORS_EXPORT;
+use crate::backend::CompilationResult;
+use wasm::wasmir::Type;
+
+/// `\0asm` magic number
+const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
+
+/// Binary format version of core modules
+const CORE_VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+/// Numeric value passed to or returned from an export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    pub fn ty(self) -> ValueType {
+        match self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::I32(v) => write!(f, "{}", v),
+            Value::I64(v) => write!(f, "{}", v),
+            Value::F32(v) => write!(f, "{}", v),
+            Value::F64(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Type of a `Value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValueType {
+    /// Name in the text format
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+        }
+    }
+
+    pub fn wasmir_type(self) -> Type {
+        match self {
+            ValueType::I32 => Type::I32,
+            ValueType::I64 => Type::I64,
+            ValueType::F32 => Type::F32,
+            ValueType::F64 => Type::F64,
+        }
+    }
+}
+
+/// Runtime errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// The code is not a core WebAssembly module
+    NotWasm(String),
+    /// The engine rejected the module
+    Compile(String),
+    /// The module could not be instantiated
+    Instantiate(String),
+    /// An import is missing or conflicts with a host function
+    Import(String),
+    /// No exported function has the name
+    MissingExport(String),
+    /// The export takes or returns values `Value` cannot hold
+    UnsupportedValue(String),
+    /// Execution trapped or the arguments did not match
+    Trap(String),
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::NotWasm(msg) => write!(f, "Not a core module: {}", msg),
+            RuntimeError::Compile(msg) => write!(f, "Compilation failed: {}", msg),
+            RuntimeError::Instantiate(msg) => write!(f, "Instantiation failed: {}", msg),
+            RuntimeError::Import(msg) => write!(f, "Import error: {}", msg),
+            RuntimeError::MissingExport(name) => write!(f, "Missing export: {}", name),
+            RuntimeError::UnsupportedValue(msg) => write!(f, "Unsupported value: {}", msg),
+            RuntimeError::Trap(msg) => write!(f, "Trap: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// WebAssembly engine embedded in the process
+pub trait Runtime {
+    /// Engine name
+    fn name(&self) -> &'static str;
+
+    /// Compiles and instantiates a core module, linking its imports to
+    /// host functions
+    fn instantiate(&self, wasm: &[u8], host: &HostFunctions) -> Result<Box<dyn Instance>, RuntimeError>;
+}
+
+/// Instantiated module
+pub trait Instance {
+    /// Names of the exported functions
+    fn exports(&self) -> Vec<String>;
+
+    /// Calls an exported function
+    fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, RuntimeError>;
+}
+
+/// Instantiates a compilation result
+pub fn load(
+    runtime: &dyn Runtime,
+    result: &CompilationResult,
+    host: &HostFunctions,
+) -> Result<Box<dyn Instance>, RuntimeError> {
+    check_core_module(&result.code)?;
+    runtime.instantiate(&result.code, host)
+}
+
+/// Gets the names of the runtimes compiled in, preferred first
+pub fn available_runtimes() -> Vec<&'static str> {
+    [("wasmtime", cfg!(feature = "wasmtime")), ("wasmer", cfg!(feature = "wasmer"))]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
+}
+
+/// Creates a runtime by name, if it was compiled in
+pub fn create_runtime(name: &str) -> Option<Box<dyn Runtime>> {
+    match name {
+        #[cfg(feature = "wasmtime")]
+        "wasmtime" => Some(Box::new(Wasmtime::new())),
+        #[cfg(feature = "wasmer")]
+        "wasmer" => Some(Box::new(Wasmer)),
+        _ => None,
+    }
+}
+
+/// Creates the preferred compiled-in runtime
+pub fn default_runtime() -> Option<Box<dyn Runtime>> {
+    available_runtimes().first().and_then(|name| create_runtime(name))
+}
+
+/// Call of an exported function
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub export: String,
+    pub args: Vec<Value>,
+}
+
+impl Invocation {
+    pub fn new(export: impl Into<String>, args: Vec<Value>) -> Self {
+        Self { export: export.into(), args }
+    }
+}
+
+impl std::fmt::Display for Invocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<String> = self.args.iter().map(Value::to_string).collect();
+        write!(f, "{}({})", self.export, args.join(", "))
+    }
+}
+
+/// Outcome of each invocation of a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunReport {
+    /// Engine the module ran on
+    pub runtime: &'static str,
+    pub results: Vec<(Invocation, Result<Vec<Value>, RuntimeError>)>,
+}
+
+impl RunReport {
+    /// Checks that no invocation trapped
+    pub fn succeeded(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+impl std::fmt::Display for RunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (invocation, result) in &self.results {
+            match result {
+                Ok(values) if values.is_empty() => writeln!(f, "{}", invocation)?,
+                Ok(values) => {
+                    let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                    writeln!(f, "{} -> {}", invocation, values.join(", "))?
+                }
+                Err(err) => writeln!(f, "{} failed: {}", invocation, err)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Instantiates a compilation result and makes each invocation in order
+///
+/// Runs the module's exported constructors first, if it has any. Without
+/// invocations, calls `main` with no arguments. A trapping invocation is
+/// recorded and the remaining ones still run.
+pub fn run(
+    runtime: &dyn Runtime,
+    result: &CompilationResult,
+    host: &HostFunctions,
+    invocations: &[Invocation],
+) -> Result<RunReport, RuntimeError> {
+    let mut instance = load(runtime, result, host)?;
+    let exports = instance.exports();
+    if exports.iter().any(|export| export == CALL_CTORS_EXPORT) {
+        instance.invoke(CALL_CTORS_EXPORT, &[])?;
+    }
+    let main = [Invocation::new("main", Vec::new())];
+    let invocations = if invocations.is_empty() { &main[..] } else { invocations };
+    if let Some(missing) = invocations.iter().find(|call| !exports.contains(&call.export)) {
+        return Err(RuntimeError::MissingExport(missing.export.clone()));
+    }
+
+    let results = invocations.iter()
+        .map(|call| (call.clone(), instance.invoke(&call.export, &call.args)))
+        .collect();
+    Ok(RunReport { runtime: runtime.name(), results })
+}
+
+fn check_core_module(code: &[u8]) -> Result<(), RuntimeError> {
+    if code.len() < 8 || code[..4] != WASM_MAGIC {
+        return Err(RuntimeError::NotWasm("missing `\\0asm` header".to_string()));
+    }
+    if code[4..8] != CORE_VERSION {
+        return Err(RuntimeError::NotWasm(format!("unsupported binary version {:02x?}", &code[4..8])));
+    }
+    Ok(())
+}
+
+/// Wasmtime engine
+#[cfg(feature = "wasmtime")]
+pub struct Wasmtime {
+    engine: wasmtime::Engine,
+}
+
+#[cfg(feature = "wasmtime")]
+impl Wasmtime {
+    pub fn new() -> Self {
+        Self { engine: wasmtime::Engine::default() }
+    }
+
+    /// Uses an engine configured by the caller
+    pub fn with_engine(engine: wasmtime::Engine) -> Self {
+        Self { engine }
+    }
+}
+
+/// Output of a WASI command run to completion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+#[cfg(feature = "wasi-runtime")]
+impl Wasmtime {
+    /// Runs a WASI preview 1 command's `_start`, capturing its output
+    ///
+    /// `args` are the command's arguments after its name. The command sees
+    /// no environment and no preopened directories.
+    pub fn run_command(&self, wasm: &[u8], args: &[String]) -> Result<CommandOutput, RuntimeError> {
+        use wasmtime_wasi::p1::{self, WasiP1Ctx};
+        use wasmtime_wasi::p2::pipe::MemoryOutputPipe;
+
+        /// Output kept per stream before the command blocks writing
+        const CAPTURE_LIMIT: usize = 16 << 20;
+
+        check_core_module(wasm)?;
+        let module = wasmtime::Module::new(&self.engine, wasm)
+            .map_err(|e| RuntimeError::Compile(e.to_string()))?;
+        let mut linker = wasmtime::Linker::new(&self.engine);
+        p1::add_to_linker_sync(&mut linker, |ctx: &mut WasiP1Ctx| ctx)
+            .map_err(|e| RuntimeError::Import(e.to_string()))?;
+
+        let (stdout, stderr) = (MemoryOutputPipe::new(CAPTURE_LIMIT), MemoryOutputPipe::new(CAPTURE_LIMIT));
+        let ctx = wasmtime_wasi::WasiCtxBuilder::new()
+            .arg("main.wasm")
+            .args(args)
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build_p1();
+        let mut store = wasmtime::Store::new(&self.engine, ctx);
+        let instance = linker.instantiate(&mut store, &module)
+            .map_err(|e| RuntimeError::Instantiate(e.to_string()))?;
+        let start = instance.get_typed_func::<(), ()>(&mut store, "_start")
+            .map_err(|_| RuntimeError::MissingExport("_start".to_string()))?;
+        let exit_code = match start.call(&mut store, ()) {
+            Ok(()) => 0,
+            Err(e) => match e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                Some(exit) => exit.0,
+                None => return Err(RuntimeError::Trap(format!("{:#}", e))),
+            },
+        };
+        drop(store);
+        Ok(CommandOutput {
+            exit_code,
+            stdout: stdout.contents().to_vec(),
+            stderr: stderr.contents().to_vec(),
+        })
+    }
+}
+
+#[cfg(feature = "wasmtime")]
+impl Default for Wasmtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "wasmtime")]
+impl Runtime for Wasmtime {
+    fn name(&self) -> &'static str {
+        "wasmtime"
+    }
+
+    fn instantiate(&self, wasm: &[u8], host: &HostFunctions) -> Result<Box<dyn Instance>, RuntimeError> {
+        let module = wasmtime::Module::new(&self.engine, wasm)
+            .map_err(|e| RuntimeError::Compile(e.to_string()))?;
+        let mut linker = wasmtime::Linker::new(&self.engine);
+        for function in host.iter() {
+            let ty = wasmtime::FuncType::new(
+                &self.engine,
+                function.params.iter().map(|&ty| wasmtime_type(ty)),
+                function.result.map(wasmtime_type),
+            );
+            let (module, name) = (function.module.clone(), function.name.clone());
+            let function = function.clone();
+            linker.func_new(&module, &name, ty, move |_, params, results| {
+                let args = params.iter().map(from_wasmtime).collect::<Result<Vec<_>, _>>()?;
+                if let Some(value) = function.call(&args).map_err(wasmtime::Error::msg)? {
+                    results[0] = to_wasmtime(value);
+                }
+                Ok(())
+            })
+            .map_err(|e| RuntimeError::Import(e.to_string()))?;
+        }
+        let mut store = wasmtime::Store::new(&self.engine, ());
+        let instance = linker.instantiate(&mut store, &module)
+            .map_err(|e| RuntimeError::Instantiate(e.to_string()))?;
+        let exports = module.exports()
+            .filter(|export| matches!(export.ty(), wasmtime::ExternType::Func(_)))
+            .map(|export| export.name().to_string())
+            .collect();
+        Ok(Box::new(WasmtimeInstance { store, instance, exports }))
+    }
+}
+
+#[cfg(feature = "wasmtime")]
+struct WasmtimeInstance {
+    store: wasmtime::Store<()>,
+    instance: wasmtime::Instance,
+    exports: Vec<String>,
+}
+
+#[cfg(feature = "wasmtime")]
+impl Instance for WasmtimeInstance {
+    fn exports(&self) -> Vec<String> {
+        self.exports.clone()
+    }
+
+    fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, RuntimeError> {
+        use wasmtime::Val;
+
+        let func = self.instance.get_func(&mut self.store, name)
+            .ok_or_else(|| RuntimeError::MissingExport(name.to_string()))?;
+        let params: Vec<Val> = args.iter().map(|&arg| to_wasmtime(arg)).collect();
+        let mut results = vec![Val::I32(0); func.ty(&self.store).results().len()];
+        func.call(&mut self.store, &params, &mut results)
+            .map_err(|e| RuntimeError::Trap(format!("{:#}", e)))?;
+        results.iter()
+            .map(|result| from_wasmtime(result).map_err(|e| RuntimeError::UnsupportedValue(format!("{} {}", name, e))))
+            .collect()
+    }
+}
+
+#[cfg(feature = "wasmtime")]
+fn wasmtime_type(ty: ValueType) -> wasmtime::ValType {
+    match ty {
+        ValueType::I32 => wasmtime::ValType::I32,
+        ValueType::I64 => wasmtime::ValType::I64,
+        ValueType::F32 => wasmtime::ValType::F32,
+        ValueType::F64 => wasmtime::ValType::F64,
+    }
+}
+
+#[cfg(feature = "wasmtime")]
+fn to_wasmtime(value: Value) -> wasmtime::Val {
+    match value {
+        Value::I32(v) => wasmtime::Val::I32(v),
+        Value::I64(v) => wasmtime::Val::I64(v),
+        Value::F32(v) => wasmtime::Val::F32(v.to_bits()),
+        Value::F64(v) => wasmtime::Val::F64(v.to_bits()),
+    }
+}
+
+#[cfg(feature = "wasmtime")]
+fn from_wasmtime(value: &wasmtime::Val) -> Result<Value, wasmtime::Error> {
+    match value {
+        wasmtime::Val::I32(v) => Ok(Value::I32(*v)),
+        wasmtime::Val::I64(v) => Ok(Value::I64(*v)),
+        wasmtime::Val::F32(bits) => Ok(Value::F32(f32::from_bits(*bits))),
+        wasmtime::Val::F64(bits) => Ok(Value::F64(f64::from_bits(*bits))),
+        other => Err(wasmtime::Error::msg(format!("uses unsupported value {:?}", other))),
+    }
+}
+
+/// Wasmer engine with its default compiler
+#[cfg(feature = "wasmer")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wasmer;
+
+#[cfg(feature = "wasmer")]
+impl Runtime for Wasmer {
+    fn name(&self) -> &'static str {
+        "wasmer"
+    }
+
+    fn instantiate(&self, wasm: &[u8], host: &HostFunctions) -> Result<Box<dyn Instance>, RuntimeError> {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(&store, wasm)
+            .map_err(|e| RuntimeError::Compile(e.to_string()))?;
+        let mut imports = wasmer::Imports::new();
+        for function in host.iter() {
+            let ty = wasmer::FunctionType::new(
+                function.params.iter().map(|&ty| wasmer_type(ty)).collect::<Vec<_>>(),
+                function.result.map(wasmer_type).into_iter().collect::<Vec<_>>(),
+            );
+            let (module, name) = (function.module.clone(), function.name.clone());
+            let function = function.clone();
+            let func = wasmer::Function::new(&mut store, ty, move |params| {
+                let args = params.iter()
+                    .map(from_wasmer)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(wasmer::RuntimeError::new)?;
+                let result = function.call(&args).map_err(wasmer::RuntimeError::new)?;
+                Ok(result.map(to_wasmer).into_iter().collect())
+            });
+            imports.define(&module, &name, func);
+        }
+        let instance = wasmer::Instance::new(&mut store, &module, &imports)
+            .map_err(|e| RuntimeError::Instantiate(e.to_string()))?;
+        let exports = module.exports()
+            .filter(|export| matches!(export.ty(), wasmer::ExternType::Function(_)))
+            .map(|export| export.name().to_string())
+            .collect();
+        Ok(Box::new(WasmerInstance { store, instance, exports }))
+    }
+}
+
+#[cfg(feature = "wasmer")]
+struct WasmerInstance {
+    store: wasmer::Store,
+    instance: wasmer::Instance,
+    exports: Vec<String>,
+}
+
+#[cfg(feature = "wasmer")]
+impl Instance for WasmerInstance {
+    fn exports(&self) -> Vec<String> {
+        self.exports.clone()
+    }
+
+    fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, RuntimeError> {
+        let func = self.instance.exports.get_function(name)
+            .map_err(|_| RuntimeError::MissingExport(name.to_string()))?;
+        let params: Vec<wasmer::Value> = args.iter().map(|&arg| to_wasmer(arg)).collect();
+        let results = func.call(&mut self.store, &params)
+            .map_err(|e| RuntimeError::Trap(e.to_string()))?;
+        results.iter()
+            .map(|result| from_wasmer(result).map_err(|e| RuntimeError::UnsupportedValue(format!("{} {}", name, e))))
+            .collect()
+    }
+}
+
+#[cfg(feature = "wasmer")]
+fn wasmer_type(ty: ValueType) -> wasmer::Type {
+    match ty {
+        ValueType::I32 => wasmer::Type::I32,
+        ValueType::I64 => wasmer::Type::I64,
+        ValueType::F32 => wasmer::Type::F32,
+        ValueType::F64 => wasmer::Type::F64,
+    }
+}
+
+#[cfg(feature = "wasmer")]
+fn to_wasmer(value: Value) -> wasmer::Value {
+    match value {
+        Value::I32(v) => wasmer::Value::I32(v),
+        Value::I64(v) => wasmer::Value::I64(v),
+        Value::F32(v) => wasmer::Value::F32(v),
+        Value::F64(v) => wasmer::Value::F64(v),
+    }
+}
+
+#[cfg(feature = "wasmer")]
+fn from_wasmer(value: &wasmer::Value) -> Result<Value, String> {
+    match value {
+        wasmer::Value::I32(v) => Ok(Value::I32(*v)),
+        wasmer::Value::I64(v) => Ok(Value::I64(*v)),
+        wasmer::Value::F32(v) => Ok(Value::F32(*v)),
+        wasmer::Value::F64(v) => Ok(Value::F64(*v)),
+        other => Err(format!("uses unsupported value {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{BuildProfile, CompilationMetadata, OptimizationLevel};
+    use std::collections::BTreeMap;
+
+    fn result(code: Vec<u8>) -> CompilationResult {
+        CompilationResult {
+            code,
+            symbols: BTreeMap::new(),
+            relocations: Vec::new(),
+            metadata: CompilationMetadata {
+                target: crate::DEFAULT_TARGET.to_string(),
+                optimization_level: OptimizationLevel::None,
+                build_profile: BuildProfile::Development,
+                timestamp: std::time::SystemTime::now(),
+            },
+        }
+    }
+
+    /// Runtime that must never be reached
+    struct Unreachable;
+
+    impl Runtime for Unreachable {
+        fn name(&self) -> &'static str {
+            "unreachable"
+        }
+
+        fn instantiate(&self, _: &[u8], _: &HostFunctions) -> Result<Box<dyn Instance>, RuntimeError> {
+            panic!("invalid code reached the runtime")
+        }
+    }
+
+    #[test]
+    fn test_rejects_non_core_modules() {
+        let native = result(vec![0x55, 0x48, 0x89, 0xe5, 0xc3, 0x00, 0x00, 0x00]);
+        assert!(matches!(load(&Unreachable, &native, &HostFunctions::new()), Err(RuntimeError::NotWasm(_))));
+
+        let component = result(vec![0x00, 0x61, 0x73, 0x6d, 0x0d, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            load(&Unreachable, &component, &HostFunctions::new()).err().unwrap().to_string(),
+            "Not a core module: unsupported binary version [0d, 00, 01, 00]"
+        );
+    }
+
+    #[test]
+    fn test_run_report_lists_each_invocation() {
+        let report = RunReport {
+            runtime: "wasmtime",
+            results: vec![
+                (Invocation::new("main", vec![]), Ok(vec![])),
+                (Invocation::new("add", vec![Value::I32(2), Value::F64(0.5)]), Ok(vec![Value::I64(42)])),
+                (Invocation::new("fail", vec![]), Err(RuntimeError::Trap("unreachable".to_string()))),
+            ],
+        };
+        assert!(!report.succeeded());
+        assert_eq!(report.to_string(), "main()\nadd(2, 0.5) -> 42\nfail() failed: Trap: unreachable\n");
+    }
+
+    #[test]
+    fn test_runs_compiled_functions_with_host_imports() {
+        use crate::backend::codegen::WasmCodegen;
+        use wasm::wasmir::{BinaryOp, Instruction, Operand, Signature, Terminator, WasmIR, WasmModule};
+
+        let host = HostFunctions::new()
+            .func("env", "double", [ValueType::I32], Some(ValueType::I32), |args| match args {
+                [Value::I32(v)] => Ok(Some(Value::I32(v * 2))),
+                _ => Err("bad arguments".to_string()),
+            })
+            .func("env", "fail", [], None, |_| Err("host failure".to_string()));
+        let mut module = WasmModule::new();
+        host.declare_imports(&mut module).unwrap();
+
+        let mut add = WasmIR::new("add".to_string(), Signature {
+            params: vec![Type::I32, Type::I32],
+            returns: Some(Type::I32),
+        });
+        add.add_basic_block(
+            vec![Instruction::BinaryOp {
+                op: BinaryOp::Add,
+                left: Operand::Local(0),
+                right: Operand::Local(1),
+            }],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let mut quadruple = WasmIR::new("quadruple".to_string(), Signature {
+            params: vec![Type::I32],
+            returns: Some(Type::I32),
+        });
+        let double = module.find_import("env", "double").unwrap();
+        let twice = quadruple.add_local(Type::I32);
+        quadruple.add_basic_block(
+            vec![
+                Instruction::CallImport { import: double, args: vec![Operand::Local(0)] },
+                Instruction::LocalSet { index: twice, value: Operand::StackValue(0) },
+                Instruction::CallImport { import: double, args: vec![Operand::Local(twice)] },
+            ],
+            Terminator::Return { value: Some(Operand::StackValue(0)) },
+        );
+        let mut fail = WasmIR::new("fail".to_string(), Signature { params: vec![], returns: None });
+        fail.add_basic_block(
+            vec![Instruction::CallImport { import: module.find_import("env", "fail").unwrap(), args: vec![] }],
+            Terminator::Return { value: None },
+        );
+        for function in [add, quadruple, fail] {
+            let name = function.name.clone();
+            let index = module.add_function(function);
+            module.export_function(name, index);
+        }
+        let code = WasmCodegen::new().compile(&module).unwrap();
+
+        for name in available_runtimes() {
+            let runtime = create_runtime(name).unwrap();
+            assert!(matches!(
+                load(runtime.as_ref(), &result(code.clone()), &HostFunctions::new()),
+                Err(RuntimeError::Instantiate(_))
+            ));
+            let mut instance = load(runtime.as_ref(), &result(code.clone()), &host).unwrap();
+            assert_eq!(instance.exports(), vec!["add".to_string(), "quadruple".to_string(), "fail".to_string()]);
+            assert_eq!(instance.invoke("add", &[Value::I32(2), Value::I32(40)]), Ok(vec![Value::I32(42)]), "{}", name);
+            assert_eq!(instance.invoke("quadruple", &[Value::I32(5)]), Ok(vec![Value::I32(20)]), "{}", name);
+            assert!(matches!(instance.invoke("fail", &[]), Err(RuntimeError::Trap(msg)) if msg.contains("host failure")));
+            assert_eq!(instance.invoke("sub", &[]), Err(RuntimeError::MissingExport("sub".to_string())));
+
+            let report = run(runtime.as_ref(), &result(code.clone()), &host, &[
+                Invocation::new("fail", vec![]),
+                Invocation::new("quadruple", vec![Value::I32(3)]),
+            ]).unwrap();
+            assert_eq!((report.runtime, report.results[1].1.clone()), (name, Ok(vec![Value::I32(12)])));
+            assert!(!report.succeeded());
+            assert_eq!(
+                run(runtime.as_ref(), &result(code.clone()), &host, &[]),
+                Err(RuntimeError::MissingExport("main".to_string()))
+            );
+        }
+    }
+}
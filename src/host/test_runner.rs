@@ -0,0 +1,319 @@
+//! `#[test]` functions run inside compiled modules
+//!
+//! In test mode the frontend lowers a crate's `#[test]` functions with
+//! everything they call into one module. `export_tests` exports each test
+//! as `TEST_EXPORT_PREFIX` followed by its path and declares the panic
+//! hook, and `run_tests` calls every export on a fresh instance of the
+//! compiled module, since a panic traps with memory in whatever state it
+//! was left. The hook tells which panic failed a test, so the report
+//! carries its message the way libtest prints it.
+//!
+//! A test passes when its export returns and fails when it traps, the
+//! other way round for `#[should_panic]` tests, whose expected message must
+//! be part of the panic's. `#[ignore]`d tests are reported without running.
+
+use super::functions::HostFunctions;
+use super::runtime::{load, Runtime, RuntimeError, Value, ValueType};
+use crate::backend::codegen::CALL_CTORS_EXPORT;
+use crate::backend::CompilationResult;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use wasm::wasmir::{WasmModule, JS_IMPORT_MODULE, PANIC_IMPORT};
+
+/// Prefix of the export running a test
+pub const TEST_EXPORT_PREFIX: &str = "__wasmrust_test_";
+
+/// Whether a test is expected to panic
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ShouldPanic {
+    #[default]
+    No,
+    Yes,
+    /// Panics with a message containing the string
+    YesWithMessage(String),
+}
+
+/// `#[test]` function of a lowered module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    /// Path of the test within its crate, such as `tests::adds`
+    pub name: String,
+    /// Index of the test function in the module
+    pub function: u32,
+    pub should_panic: ShouldPanic,
+    pub ignored: bool,
+}
+
+impl TestCase {
+    pub fn new(name: impl Into<String>, function: u32) -> Self {
+        Self { name: name.into(), function, should_panic: ShouldPanic::No, ignored: false }
+    }
+
+    pub fn should_panic(mut self, should_panic: ShouldPanic) -> Self {
+        self.should_panic = should_panic;
+        self
+    }
+
+    pub fn ignored(mut self, ignored: bool) -> Self {
+        self.ignored = ignored;
+        self
+    }
+
+    /// Name of the export running the test
+    pub fn export_name(&self) -> String {
+        format!("{}{}", TEST_EXPORT_PREFIX, self.name)
+    }
+}
+
+/// Result of one test
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    /// Failed with the panic message or the reason
+    Failed(String),
+    Ignored,
+}
+
+/// Outcome of every test of a run, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestReport {
+    /// Engine the tests ran on
+    pub runtime: &'static str,
+    pub results: Vec<(String, TestOutcome)>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.count(|outcome| *outcome == TestOutcome::Passed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, TestOutcome::Failed(_)))
+    }
+
+    pub fn ignored(&self) -> usize {
+        self.count(|outcome| *outcome == TestOutcome::Ignored)
+    }
+
+    /// Checks that no test failed
+    pub fn succeeded(&self) -> bool {
+        self.failed() == 0
+    }
+
+    fn count(&self, matches: impl Fn(&TestOutcome) -> bool) -> usize {
+        self.results.iter().filter(|(_, outcome)| matches(outcome)).count()
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = if self.results.len() == 1 { "" } else { "s" };
+        writeln!(f, "running {} test{} on {}", self.results.len(), plural, self.runtime)?;
+        for (name, outcome) in &self.results {
+            let status = match outcome {
+                TestOutcome::Passed => "ok",
+                TestOutcome::Failed(_) => "FAILED",
+                TestOutcome::Ignored => "ignored",
+            };
+            writeln!(f, "test {} ... {}", name, status)?;
+        }
+        if !self.succeeded() {
+            writeln!(f, "\nfailures:")?;
+            for (name, outcome) in &self.results {
+                if let TestOutcome::Failed(message) = outcome {
+                    writeln!(f, "    {}: {}", name, message)?;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "\ntest result: {}. {} passed; {} failed; {} ignored",
+            if self.succeeded() { "ok" } else { "FAILED" },
+            self.passed(),
+            self.failed(),
+            self.ignored()
+        )
+    }
+}
+
+/// Exports every test and declares the panic hook
+///
+/// Tests must take no arguments and return nothing.
+pub fn export_tests(module: &mut WasmModule, tests: &[TestCase]) -> Result<(), RuntimeError> {
+    for test in tests {
+        let function = module.functions.get(test.function as usize)
+            .ok_or_else(|| RuntimeError::MissingExport(test.export_name()))?;
+        if !function.signature.params.is_empty() || function.signature.returns.is_some() {
+            return Err(RuntimeError::UnsupportedValue(format!(
+                "test {} must take no arguments and return nothing",
+                test.name
+            )));
+        }
+        module.export_function(test.export_name(), test.function);
+    }
+    if module.find_import(JS_IMPORT_MODULE, PANIC_IMPORT).is_none() {
+        module.declare_panic_hook();
+    }
+    Ok(())
+}
+
+/// Runs the tests of a module compiled after `export_tests`
+///
+/// `module` is the lowered module, whose panic messages the hook's
+/// indices refer to. `host` must not provide the panic hook itself.
+pub fn run_tests(
+    runtime: &dyn Runtime,
+    result: &CompilationResult,
+    module: &WasmModule,
+    host: &HostFunctions,
+    tests: &[TestCase],
+) -> Result<TestReport, RuntimeError> {
+    let panicked = Arc::new(Mutex::new(None));
+    let hook = Arc::clone(&panicked);
+    let host = host.clone().func(JS_IMPORT_MODULE, PANIC_IMPORT, [ValueType::I32], None, move |args| {
+        if let [Value::I32(id)] = args {
+            *hook.lock().unwrap() = Some(*id);
+        }
+        Ok(None)
+    });
+    let messages = module.panic_messages();
+
+    let mut results = Vec::with_capacity(tests.len());
+    for test in tests {
+        if test.ignored {
+            results.push((test.name.clone(), TestOutcome::Ignored));
+            continue;
+        }
+        *panicked.lock().unwrap() = None;
+        let mut instance = load(runtime, result, &host)?;
+        if instance.exports().iter().any(|export| export == CALL_CTORS_EXPORT) {
+            instance.invoke(CALL_CTORS_EXPORT, &[])?;
+        }
+        let panic = match instance.invoke(&test.export_name(), &[]) {
+            Ok(_) => None,
+            Err(RuntimeError::Trap(trap)) => {
+                let message = panicked.lock().unwrap().and_then(|id| messages.get(id as usize).copied());
+                Some(message.map_or(trap, str::to_string))
+            }
+            Err(err) => return Err(err),
+        };
+        let outcome = match (&test.should_panic, panic) {
+            (ShouldPanic::No, None) => TestOutcome::Passed,
+            (ShouldPanic::No, Some(message)) => TestOutcome::Failed(message),
+            (_, None) => TestOutcome::Failed("test did not panic as expected".to_string()),
+            (ShouldPanic::YesWithMessage(expected), Some(message)) if !message.contains(expected.as_str()) => {
+                TestOutcome::Failed(format!("panic did not contain expected string `{}`: {}", expected, message))
+            }
+            (_, Some(_)) => TestOutcome::Passed,
+        };
+        results.push((test.name.clone(), outcome));
+    }
+    Ok(TestReport { runtime: runtime.name(), results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::codegen::WasmCodegen;
+    use crate::backend::{BuildProfile, CompilationMetadata, OptimizationLevel};
+    use crate::host::runtime::{available_runtimes, create_runtime};
+    use std::collections::BTreeMap;
+    use wasm::wasmir::{Constant, Operand, Signature, Terminator, Type, WasmIR};
+
+    fn test_module() -> (WasmModule, Vec<TestCase>) {
+        let mut module = WasmModule::new();
+        let mut tests = Vec::new();
+        for (name, panic) in [("adds", None), ("overflows", Some("attempt to add with overflow")), ("rejects", None)] {
+            let mut function = WasmIR::new(name.to_string(), Signature { params: vec![], returns: None });
+            let terminator = match panic {
+                Some(message) => Terminator::Panic { message: Some(Operand::Constant(Constant::String(message.to_string()))) },
+                None => Terminator::Return { value: None },
+            };
+            function.add_basic_block(vec![], terminator);
+            let index = module.add_function(function);
+            tests.push(TestCase::new(format!("tests::{}", name), index));
+        }
+        (module, tests)
+    }
+
+    #[test]
+    fn test_exports_tests_with_the_panic_hook() {
+        let (mut module, tests) = test_module();
+        export_tests(&mut module, &tests).unwrap();
+        let names: Vec<_> = module.exports.iter().map(|export| export.name.as_str()).collect();
+        assert_eq!(names, ["__wasmrust_test_tests::adds", "__wasmrust_test_tests::overflows", "__wasmrust_test_tests::rejects"]);
+        assert!(module.find_import(JS_IMPORT_MODULE, PANIC_IMPORT).is_some());
+
+        let mut function = WasmIR::new("takes".to_string(), Signature { params: vec![Type::I32], returns: None });
+        function.add_basic_block(vec![], Terminator::Return { value: None });
+        let index = module.add_function(function);
+        let error = export_tests(&mut module, &[TestCase::new("takes", index)]).unwrap_err();
+        assert_eq!(error, RuntimeError::UnsupportedValue("test takes must take no arguments and return nothing".to_string()));
+    }
+
+    #[test]
+    fn test_report_reads_like_libtest() {
+        let report = TestReport {
+            runtime: "wasmtime",
+            results: vec![
+                ("tests::adds".to_string(), TestOutcome::Passed),
+                ("tests::overflows".to_string(), TestOutcome::Failed("attempt to add with overflow".to_string())),
+                ("tests::slow".to_string(), TestOutcome::Ignored),
+            ],
+        };
+        assert!(!report.succeeded());
+        assert_eq!(
+            report.to_string(),
+            "running 3 tests on wasmtime\n\
+             test tests::adds ... ok\n\
+             test tests::overflows ... FAILED\n\
+             test tests::slow ... ignored\n\
+             \n\
+             failures:\n    tests::overflows: attempt to add with overflow\n\
+             \n\
+             test result: FAILED. 1 passed; 1 failed; 1 ignored\n"
+        );
+    }
+
+    #[test]
+    fn test_runs_each_test_on_a_fresh_instance() {
+        let (mut module, tests) = test_module();
+        let (overflows, rejects) = (tests[1].function, tests[2].function);
+        let tests = vec![
+            tests[0].clone(),
+            tests[1].clone(),
+            TestCase::new("expects_overflow", overflows).should_panic(ShouldPanic::YesWithMessage("overflow".to_string())),
+            TestCase::new("expects_underflow", overflows).should_panic(ShouldPanic::YesWithMessage("underflow".to_string())),
+            TestCase::new("expects_panic", rejects).should_panic(ShouldPanic::Yes),
+            TestCase::new("slow", rejects).ignored(true),
+        ];
+        export_tests(&mut module, &tests).unwrap();
+        let result = CompilationResult {
+            code: WasmCodegen::new().compile(&module).unwrap(),
+            symbols: BTreeMap::new(),
+            relocations: Vec::new(),
+            metadata: CompilationMetadata {
+                target: crate::DEFAULT_TARGET.to_string(),
+                optimization_level: OptimizationLevel::None,
+                build_profile: BuildProfile::Development,
+                timestamp: std::time::SystemTime::now(),
+            },
+        };
+
+        for name in available_runtimes() {
+            let runtime = create_runtime(name).unwrap();
+            let report = run_tests(runtime.as_ref(), &result, &module, &HostFunctions::new(), &tests).unwrap();
+            let outcomes: Vec<_> = report.results.iter().map(|(_, outcome)| outcome.clone()).collect();
+            assert_eq!(outcomes, [
+                TestOutcome::Passed,
+                TestOutcome::Failed("attempt to add with overflow".to_string()),
+                TestOutcome::Passed,
+                TestOutcome::Failed(
+                    "panic did not contain expected string `underflow`: attempt to add with overflow".to_string()
+                ),
+                TestOutcome::Failed("test did not panic as expected".to_string()),
+                TestOutcome::Ignored,
+            ]);
+        }
+    }
+}
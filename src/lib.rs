@@ -27,11 +27,37 @@ pub mod component;
 pub mod host;
 pub mod backend;
 pub mod wasmir;
+pub mod scaffold;
+pub mod diagnostics;
+pub mod driver;
+pub mod target_spec;
+pub mod timings;
 
 use backend::BackendFactory;
+use backend::capabilities::CapabilityPolicy;
+use backend::codegen::{InitStrategy, PanicStrategy, WasmCodegen};
+use backend::dump::PassDump;
+use backend::freestanding::FreestandingPass;
+use backend::sanitizer::SanitizerPass;
+use backend::js_glue::{GlueFormat, JsGlueGenerator};
+use backend::link::{Library, Linker};
+use backend::pgo::PgoProfile;
+use backend::single_threaded::SingleThreadedPass;
+use backend::split::{self, SplitModules};
+use backend::target_features::TargetFeatures;
+use host::runtime::{Invocation, RunReport};
+use host::test_runner::{TestCase, TestReport};
+use host::HostFunctions;
+use wasm::host::ts_bindings::{TsBindingsGenerator, TsModuleKind};
+use wasm::memory::allocator::AllocatorStrategy;
+use wasm::memory::stack::DEFAULT_STACK_SIZE;
+use wasm::wasmir::WasmModule;
 use wasmir::WasmIR;
 use rustc_middle::mir::Body;
 use rustc_target::spec::Target;
+use target_spec::TargetSpec;
+use timings::Phase;
+use std::path::PathBuf;
 
 /// WasmRust compiler version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -45,6 +71,12 @@ pub struct WasmRustCompiler {
     backend_factory: BackendFactory,
     /// Current target
     target: Target,
+    /// Where `compile_module` records the WasmIR after each pass
+    dump: Option<PassDump>,
+    /// Whether `compile_mir` and `compile_wasmir` demangle their symbols
+    demangle: bool,
+    /// What the backends of `compile_mir` and `compile_wasmir` did, summed
+    stats: backend::cranelift::CompilationStats,
 }
 
 impl WasmRustCompiler {
@@ -53,9 +85,31 @@ impl WasmRustCompiler {
         Self {
             backend_factory: BackendFactory,
             target,
+            dump: None,
+            demangle: false,
+            stats: backend::cranelift::CompilationStats::default(),
         }
     }
 
+    /// Records the WasmIR of the functions `dump` picks after each pass of `compile_module`
+    pub fn dump_passes(&mut self, dump: PassDump) {
+        self.dump = Some(dump);
+    }
+
+    /// Sets whether later compilations of functions demangle their symbol tables and relocations
+    pub fn demangle_symbols(&mut self, enabled: bool) {
+        self.demangle = enabled;
+    }
+
+    /// Statistics of every function `compile_mir` and `compile_wasmir` compiled
+    ///
+    /// Each compilation gets a backend of its own, so the counts are summed
+    /// here. Cache hits include functions other compilers in the process
+    /// compiled first, as their backends share a codegen cache.
+    pub fn stats(&self) -> &backend::cranelift::CompilationStats {
+        &self.stats
+    }
+
     /// Compiles a Rust MIR body to WASM using appropriate backend
     pub fn compile_mir(
         &mut self,
@@ -69,10 +123,19 @@ impl WasmRustCompiler {
         let mut backend = BackendFactory::create_backend(
             &self.target.arch,
             build_profile,
-        )?;
+            &backend::BackendCapabilities::default(),
+        )?
+        .backend;
         
         // Compile WasmIR to machine code
-        let result = backend.compile(&wasmir, build_profile)?;
+        let result = backend.compile(&wasmir, build_profile);
+        if let Some(stats) = backend.stats() {
+            self.stats.merge(stats);
+        }
+        let mut result = result?;
+        if self.demangle {
+            result.demangle_symbols();
+        }
         
         Ok(result)
     }
@@ -86,9 +149,134 @@ impl WasmRustCompiler {
         let mut backend = BackendFactory::create_backend(
             &self.target.arch,
             build_profile,
-        )?;
+            &backend::BackendCapabilities::default(),
+        )?
+        .backend;
         
-        backend.compile(wasmir, build_profile)
+        let result = backend.compile(wasmir, build_profile);
+        if let Some(stats) = backend.stats() {
+            self.stats.merge(stats);
+        }
+        let mut result = result?;
+        if self.demangle {
+            result.demangle_symbols();
+        }
+        Ok(result)
+    }
+
+    /// Compiles a WasmIR module, including its start function and constructors
+    ///
+    /// Modules that never require threading have their atomics lowered to
+    /// plain operations first, and functions that compute a constant are
+    /// evaluated by the interpreter and replaced by their result. Without
+    /// `atomics`, threaded modules are lowered too, giving the fallback
+    /// variant for hosts without shared memory. With `debug_info` the
+    /// binary names its functions. The binary is checked against and
+    /// records the `target_features`, less atomics when they are lowered.
+    /// Freestanding builds drop panic messages and are rejected if they
+    /// need the standard library or runtime; see `backend::freestanding`.
+    /// The binary records the host capabilities it needs, and is rejected
+    /// if `capability_policy` forbids any; see `backend::capabilities`.
+    /// With `pre_initialize`, the constructors and start function run at
+    /// build time and the binary starts in the state they leave.
+    /// Imports move to the import module of the target's spec, and an
+    /// Emscripten target's `main` takes `argc` and `argv`; see
+    /// `backend::emscripten::wrap_main`.
+    pub fn compile_module(
+        &mut self,
+        module: &WasmModule,
+        config: &CompilerConfig,
+    ) -> Result<Vec<u8>, backend::BackendError> {
+        self.compile(module, config, WasmCodegen::compile)
+    }
+
+    /// Compiles a WasmIR module as `compile_module` does, but to a
+    /// relocatable object for wasm-ld; see `backend::object`
+    pub fn compile_object(
+        &mut self,
+        module: &WasmModule,
+        config: &CompilerConfig,
+    ) -> Result<Vec<u8>, backend::BackendError> {
+        self.compile(module, config, WasmCodegen::compile_object)
+    }
+
+    /// Compiles a WasmIR module as `compile_module` does, but to a side
+    /// module loaded as a plugin; see `backend::dylink`
+    pub fn compile_shared(
+        &mut self,
+        module: &WasmModule,
+        config: &CompilerConfig,
+    ) -> Result<Vec<u8>, backend::BackendError> {
+        self.compile(module, config, WasmCodegen::compile_shared)
+    }
+
+    /// Runs the passes of `compile_module`, then emits the module with `emit`
+    fn compile(
+        &mut self,
+        module: &WasmModule,
+        config: &CompilerConfig,
+        emit: fn(&WasmCodegen, &WasmModule) -> Result<Vec<u8>, backend::BackendError>,
+    ) -> Result<Vec<u8>, backend::BackendError> {
+        let _codegen = timings::span(Phase::Codegen, "compile module");
+        let spec = TargetSpec::resolve(&config.target)
+            .map_err(|err| backend::BackendError::UnsupportedTarget(err.to_string()))?;
+        let record = |pass: &str, module: &WasmModule| {
+            if let Some(dump) = &self.dump {
+                dump.record(pass, module);
+            }
+        };
+        record(backend::dump::INPUT_PASS, module);
+        let mut module = module.clone();
+        spec.apply_import_module(&mut module);
+        if spec.is_emscripten() {
+            backend::emscripten::wrap_main(&mut module);
+        }
+        if config.sanitize {
+            // First, so every access the source makes is checked
+            let _pass = timings::span(Phase::Pass, "sanitizer");
+            SanitizerPass::new().run(&mut module);
+            record("sanitizer", &module);
+        }
+        {
+            let _pass = timings::span(Phase::Pass, "single-threaded");
+            SingleThreadedPass::new().force(!config.atomics).run(&mut module);
+            record("single-threaded", &module);
+        }
+        {
+            let _pass = timings::span(Phase::Pass, "fold constants");
+            backend::interpreter::fold_constants(&mut module);
+            record("fold constants", &module);
+        }
+        let freestanding = config.build_profile == backend::BuildProfile::Freestanding;
+        if freestanding {
+            let _pass = timings::span(Phase::Pass, "freestanding");
+            FreestandingPass::new().run(&mut module);
+            record("freestanding", &module);
+        }
+
+        let features = match config.atomics {
+            true => config.target_features,
+            false => config.target_features.disable(wasm::wasmir::features::Proposal::Threads),
+        };
+        let mut codegen = WasmCodegen::new()
+            .features(features.set())
+            .record_features(true)
+            .record_capabilities(true)
+            .capability_policy(config.capability_policy.clone())
+            .init_strategy(config.init_strategy)
+            .panic_strategy(config.panic_strategy)
+            .allocator(config.allocator)
+            .stack_size(config.stack_size)
+            .stack_check(config.checks_stack())
+            .region_checks(config.checks_regions())
+            .pre_initialize(config.pre_initialize)
+            .debug_info(config.debug_info)
+            .demangle(config.demangle)
+            .freestanding(freestanding);
+        if let Some(dump) = &self.dump {
+            codegen = codegen.dump_passes(dump.clone());
+        }
+        emit(&codegen, &module)
     }
 
     /// Converts Rust MIR to WasmIR
@@ -109,9 +297,9 @@ impl WasmRustCompiler {
         }
     }
 
-    /// Gets supported targets
+    /// Gets the built-in targets; others come from target spec files
     pub fn supported_targets() -> Vec<&'static str> {
-        vec!["wasm32-unknown-unknown", "wasm32-unknown-emscripten"]
+        TargetSpec::BUILTIN.to_vec()
     }
 
     /// Gets available backends
@@ -119,9 +307,9 @@ impl WasmRustCompiler {
         BackendFactory::available_backends()
     }
 
-    /// Validates target support
+    /// Validates target support, of a built-in target or a spec file
     pub fn is_target_supported(target: &str) -> bool {
-        Self::supported_targets().contains(&target)
+        TargetSpec::resolve(target).is_ok()
     }
 
     /// Gets recommended backend for target and profile
@@ -144,10 +332,51 @@ pub struct CompilerConfig {
     pub target: String,
     /// Enable debug information
     pub debug_info: bool,
+    /// Whether symbol tables, the `name` section, and size reports show Rust
+    /// symbols demangled; see `backend::demangle`
+    pub demangle: bool,
     /// Enable LTO (Link Time Optimization)
     pub lto: bool,
-    /// Enable PGO (Profile Guided Optimization)
-    pub pgo: Option<String>,
+    /// Profile of an instrumented run guiding function order, hot calls,
+    /// and, with `split_cold`, which functions are cold
+    pub pgo: Option<PgoProfile>,
+    /// How constructors and the start function are run
+    pub init_strategy: InitStrategy,
+    /// Whether panics trap or unwind
+    pub panic_strategy: PanicStrategy,
+    /// Where heap allocations get their memory from
+    pub allocator: AllocatorStrategy,
+    /// Bytes reserved for the shadow stack of address-taken locals
+    pub stack_size: u32,
+    /// Whether prologues trap when the shadow stack overflows, by default in development builds only
+    pub stack_check: Option<bool>,
+    /// Whether accesses through region-typed locals trap outside their memory region, by default in
+    /// development builds only
+    pub region_checks: Option<bool>,
+    /// Module system of the generated JavaScript glue
+    pub glue_format: GlueFormat,
+    /// Whether threaded modules keep atomics and shared memory
+    pub atomics: bool,
+    /// Proposals the target engine enables, as `-C target-feature` sets them
+    pub target_features: TargetFeatures,
+    /// Whether builds are reproducible, with no timestamps or machine paths in their output
+    pub deterministic: bool,
+    /// Further builds of each module, loaded by the glue where the host supports them
+    pub variants: Vec<BuildVariant>,
+    /// Whether loads, stores, and frees are checked at run time; see `backend::sanitizer`
+    pub sanitize: bool,
+    /// Host capabilities modules may need; see `backend::capabilities`
+    pub capability_policy: CapabilityPolicy,
+    /// Whether initializers run at build time, their result baked into the module; see `backend::preinit`
+    pub pre_initialize: bool,
+    /// Whether cold functions move to a secondary module loaded on demand; see `backend::split`
+    pub split_cold: bool,
+    /// Static libraries `compile_linked` links modules with, as objects or archives; see `backend::link`
+    pub link_libraries: Vec<PathBuf>,
+    /// Linker `compile_linked` runs, `link::DEFAULT_LINKER` unless set
+    pub linker: Option<PathBuf>,
+    /// Whether the glue loads side modules as plugins; see `backend::dylink`
+    pub plugins: bool,
 }
 
 impl Default for CompilerConfig {
@@ -157,8 +386,99 @@ impl Default for CompilerConfig {
             build_profile: backend::BuildProfile::Development,
             target: DEFAULT_TARGET.to_string(),
             debug_info: true,
+            demangle: false,
             lto: false,
             pgo: None,
+            init_strategy: InitStrategy::StartSection,
+            panic_strategy: PanicStrategy::Abort,
+            allocator: AllocatorStrategy::Bundled,
+            stack_size: DEFAULT_STACK_SIZE,
+            stack_check: None,
+            region_checks: None,
+            glue_format: GlueFormat::EsModule,
+            atomics: true,
+            target_features: TargetFeatures::default(),
+            deterministic: false,
+            variants: Vec::new(),
+            sanitize: false,
+            capability_policy: CapabilityPolicy::default(),
+            pre_initialize: false,
+            split_cold: false,
+            link_libraries: Vec::new(),
+            linker: None,
+            plugins: false,
+        }
+    }
+}
+
+impl CompilerConfig {
+    /// Whether prologues check the stack limit, as set or as the profile defaults to
+    ///
+    /// Like Cargo's overflow checks, the check is on in development builds
+    /// and off in release and freestanding ones.
+    pub fn checks_stack(&self) -> bool {
+        self.stack_check.unwrap_or(self.build_profile == backend::BuildProfile::Development)
+    }
+
+    /// Whether accesses to memory regions are checked at run time, as set or as the profile defaults to
+    ///
+    /// Accesses at constant addresses are checked while compiling either
+    /// way; see `wasm::memory::regions`.
+    pub fn checks_regions(&self) -> bool {
+        self.region_checks.unwrap_or(self.build_profile == backend::BuildProfile::Development)
+    }
+}
+
+/// Binary of each variant, from `WasmRustFrontend::compile_variants`
+pub type VariantBinaries = Vec<(BuildVariant, Vec<u8>)>;
+
+/// Primary and, if any function moved, secondary binary, from `WasmRustFrontend::compile_split`
+pub type SplitBinaries = (Vec<u8>, Option<Vec<u8>>);
+
+/// One build of a multi-target matrix, such as with SIMD or without atomics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildVariant {
+    /// Goes into the file name, as in `app.simd.wasm`
+    pub name: String,
+    /// Target triple; `compile_variants` rejects the unsupported wasm64 ones
+    pub target: String,
+    /// Proposals the variant is built with, and the glue checks the host for
+    pub target_features: TargetFeatures,
+}
+
+impl BuildVariant {
+    /// Creates a variant for the default target
+    pub fn new(name: impl Into<String>, target_features: TargetFeatures) -> Self {
+        Self { name: name.into(), target: DEFAULT_TARGET.to_string(), target_features }
+    }
+
+    /// Sets the target triple
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    /// The variant's file next to `wasm_file`, with its name before the extension
+    pub fn file_name(&self, wasm_file: &str) -> String {
+        match wasm_file.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => format!("{}.{}.{}", stem, self.name, extension),
+            _ => format!("{}.{}", wasm_file, self.name),
+        }
+    }
+
+    /// `config` building this variant, keeping atomics only if it enables threads
+    pub fn config(&self, config: &CompilerConfig) -> CompilerConfig {
+        let atomics = config.atomics && self.target_features.contains(wasm::wasmir::features::Proposal::Threads);
+        let target_features = match atomics {
+            true => self.target_features,
+            false => self.target_features.disable(wasm::wasmir::features::Proposal::Threads),
+        };
+        CompilerConfig {
+            target: self.target.clone(),
+            target_features,
+            atomics,
+            variants: Vec::new(),
+            ..config.clone()
         }
     }
 }
@@ -177,10 +497,9 @@ impl WasmRustFrontend {
             ..Default::default()
         };
         
-        Ok(Self {
-            compiler: WasmRustCompiler::new(target),
-            config,
-        })
+        let mut compiler = WasmRustCompiler::new(target);
+        compiler.demangle_symbols(config.demangle);
+        Ok(Self { compiler, config })
     }
 
     /// Compiles a crate to WASM
@@ -203,8 +522,237 @@ impl WasmRustFrontend {
         Err("File compilation not yet implemented".into())
     }
 
+    /// Records the WasmIR of the functions `dump` picks after each pass of later compilations
+    pub fn dump_passes(&mut self, dump: PassDump) {
+        self.compiler.dump_passes(dump);
+    }
+
+    /// Compiles a lowered module to a WASM binary
+    pub fn compile_module(
+        &mut self,
+        module: &WasmModule,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let binary = self.compiler.compile_module(module, &self.config)?;
+        Ok(binary)
+    }
+
+    /// Compiles a lowered module to a relocatable object, to link with
+    /// wasm-ld next to C and C++ objects
+    pub fn compile_object(
+        &mut self,
+        module: &WasmModule,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let object = self.compiler.compile_object(module, &self.config)?;
+        Ok(object)
+    }
+
+    /// Compiles a lowered module to a side module, which the glue of a main
+    /// module built with `CompilerConfig::plugins` loads with `loadPlugin`
+    pub fn compile_shared(
+        &mut self,
+        module: &WasmModule,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let shared = self.compiler.compile_shared(module, &self.config)?;
+        Ok(shared)
+    }
+
+    /// Compiles a lowered module and links it with `CompilerConfig::link_libraries`
+    ///
+    /// Imports the libraries define are resolved against them, and the rest
+    /// stay imports for the host; see `backend::link`. The module is linked
+    /// as an object, so it must meet `compile_object`'s restrictions.
+    pub fn compile_linked(
+        &mut self,
+        module: &WasmModule,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut linker = Linker::new().stack_size(self.config.stack_size);
+        if let Some(program) = &self.config.linker {
+            linker = linker.program(program);
+        }
+        for path in &self.config.link_libraries {
+            linker = linker.library(Library::load(path)?);
+        }
+        let object = self.compile_object(module)?;
+        let binary = linker.link(module, &object)?;
+        Ok(binary)
+    }
+
+    /// Compiles the variant of a module for hosts without shared memory
+    ///
+    /// Its atomics are plain operations and its memory is not shared, so
+    /// it runs where `SharedArrayBuffer` is unavailable. Ship it next to
+    /// the `compile_module` binary and name it in `generate_js_glue`; the
+    /// glue loads it when the page is not cross-origin isolated.
+    pub fn compile_fallback(
+        &mut self,
+        module: &WasmModule,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let binary = self.compiler.compile_module(module, &CompilerConfig { atomics: false, ..self.config.clone() })?;
+        Ok(binary)
+    }
+
+    /// Compiles every variant of `CompilerConfig::variants`, in order
+    ///
+    /// Write each binary to the variant's `file_name` and generate the glue
+    /// with `generate_js_glue`, which loads the first one the host runs.
+    pub fn compile_variants(&mut self, module: &WasmModule) -> Result<VariantBinaries, Box<dyn std::error::Error>> {
+        let mut binaries = Vec::with_capacity(self.config.variants.len());
+        for variant in &self.config.variants {
+            if !WasmRustCompiler::is_target_supported(&variant.target) {
+                return Err(format!("variant `{}`: unsupported target {}", variant.name, variant.target).into());
+            }
+            let binary = self.compiler.compile_module(module, &variant.config(&self.config))
+                .map_err(|e| format!("variant `{}`: {}", variant.name, e))?;
+            binaries.push((variant.clone(), binary));
+        }
+        Ok(binaries)
+    }
+
+    /// Compiles a module, moving its cold functions to a secondary binary
+    ///
+    /// With `CompilerConfig::split_cold`, the functions marked cold that
+    /// can leave the module are compiled into the secondary, to be shipped
+    /// as the primary's `split::secondary_file_name`; the glue from
+    /// `generate_js_glue` loads it on demand. Otherwise, or if none can,
+    /// there is no secondary.
+    pub fn compile_split(&mut self, module: &WasmModule) -> Result<SplitBinaries, Box<dyn std::error::Error>> {
+        let Some(split) = self.split(module) else {
+            return Ok((self.compile_module(module)?, None));
+        };
+        let primary = self.compiler.compile_module(&split.primary, &self.config)?;
+        let secondary = self.compiler.compile_module(&split.secondary, &self.config)
+            .map_err(|e| format!("secondary module: {}", e))?;
+        Ok((primary, Some(secondary)))
+    }
+
+    /// Splits a module as `compile_split` does, if `CompilerConfig::split_cold` is set
+    fn split(&self, module: &WasmModule) -> Option<SplitModules> {
+        if !self.config.split_cold {
+            return None;
+        }
+        split::split_module(module, &split::cold_functions(module, self.config.pgo.as_ref()))
+    }
+
+    /// Compiles a module and runs it in-process
+    ///
+    /// The module goes through the same binary emitter as `compile_module`,
+    /// which the development profile's Cranelift backend also emits
+    /// through; Cranelift compiles it again as the embedded engine's JIT,
+    /// for wasmtime. The binary stays
+    /// in memory, imports are declared from and linked to `host`, and
+    /// without invocations `main` runs. With `CompilerConfig::sanitize`,
+    /// memory errors trap with a message saying where they happened.
+    pub fn run_module(
+        &mut self,
+        module: &WasmModule,
+        invocations: &[Invocation],
+        host: &HostFunctions,
+    ) -> Result<RunReport, Box<dyn std::error::Error>> {
+        let runtime = host::runtime::default_runtime()
+            .ok_or("no in-process runtime; enable the `wasmtime` or `wasmer` feature")?;
+        let mut module = module.clone();
+        let host = self.sanitize(&mut module, host);
+        host.declare_imports(&mut module)?;
+        let result = self.compile_in_memory(&module)?;
+        let report = host::runtime::run(runtime.as_ref(), &result, &host, invocations)?;
+        Ok(report)
+    }
+
+    /// Compiles a module in test mode and runs its `#[test]` functions in-process
+    ///
+    /// Each test is exported for the runner, which calls it on a fresh
+    /// instance like `run_module` and reports its outcome like libtest.
+    pub fn run_tests(
+        &mut self,
+        module: &WasmModule,
+        tests: &[TestCase],
+        host: &HostFunctions,
+    ) -> Result<TestReport, Box<dyn std::error::Error>> {
+        let runtime = host::runtime::default_runtime()
+            .ok_or("no in-process runtime; enable the `wasmtime` or `wasmer` feature")?;
+        let mut module = module.clone();
+        host::test_runner::export_tests(&mut module, tests)?;
+        let host = self.sanitize(&mut module, host);
+        host.declare_imports(&mut module)?;
+        let result = self.compile_in_memory(&module)?;
+        let report = host::test_runner::run_tests(runtime.as_ref(), &result, &module, &host, tests)?;
+        Ok(report)
+    }
+
+    /// Instruments a module to run when `CompilerConfig::sanitize` is set,
+    /// adding the sanitizer's checks to the host functions
+    fn sanitize(&self, module: &mut WasmModule, host: &HostFunctions) -> HostFunctions {
+        if !self.config.sanitize {
+            return host.clone();
+        }
+        SanitizerPass::new().run(module).install(host.clone())
+    }
+
+    /// Compiles a module for an in-process runtime, keeping the binary in memory
+    fn compile_in_memory(&mut self, module: &WasmModule) -> Result<backend::CompilationResult, backend::BackendError> {
+        let code = self.compiler.compile_module(module, &self.config)?;
+        Ok(backend::CompilationResult {
+            code,
+            symbols: std::collections::BTreeMap::new(),
+            relocations: Vec::new(),
+            metadata: backend::CompilationMetadata {
+                target: self.config.target.clone(),
+                optimization_level: backend::OptimizationLevel::None,
+                build_profile: self.config.build_profile,
+                timestamp: backend::CompilationMetadata::timestamp(self.config.deterministic),
+            },
+        })
+    }
+
+    /// Generates the JavaScript glue module for a compiled `.wasm` file
+    ///
+    /// With a `fallback` file from `compile_fallback`, the glue of a
+    /// threaded module loads it on hosts without shared memory. With
+    /// `variants` configured, it instead loads the first variant whose
+    /// features the host supports, from the variant's `file_name`. With
+    /// `split_cold`, it loads the cold functions `compile_split` moved from
+    /// the file named by `split::secondary_file_name`.
+    pub fn generate_js_glue(
+        &self,
+        module: &WasmModule,
+        wasm_file: &str,
+        fallback: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut generator = JsGlueGenerator::new(wasm_file).format(self.config.glue_format);
+        if let Some(fallback) = fallback {
+            generator = generator.fallback(fallback);
+        }
+        for variant in &self.config.variants {
+            let features = variant.config(&self.config).target_features;
+            generator = generator.variant(variant.file_name(wasm_file), features.enabled());
+        }
+        generator = generator.plugins(self.config.plugins);
+        let glue = match self.split(module) {
+            Some(split) => generator.generate(&split.primary)?,
+            None => generator.generate(module)?,
+        };
+        Ok(glue)
+    }
+
+    /// Generates TypeScript declarations matching the JavaScript glue
+    pub fn generate_ts_bindings(
+        &self,
+        module: &WasmModule,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let kind = match self.config.glue_format {
+            GlueFormat::EsModule => TsModuleKind::EsModule,
+            GlueFormat::CommonJs => TsModuleKind::CommonJs,
+        };
+        let declarations = TsBindingsGenerator::new()
+            .module_kind(kind)
+            .generate(module)
+            .map_err(|e| e.to_string())?;
+        Ok(declarations)
+    }
+
     /// Updates compiler configuration
     pub fn update_config(&mut self, config: CompilerConfig) {
+        self.compiler.demangle_symbols(config.demangle);
         self.config = config;
     }
 
@@ -249,6 +797,28 @@ mod tests {
         assert!(compiler.available_backends().contains(&"cranelift"));
     }
 
+    #[test]
+    fn test_compilers_share_compiled_functions() {
+        use wasm::wasmir::{Constant, Operand, Signature, Terminator, Type, WasmIR};
+
+        let target = rustc_target::spec::Target { arch: "wasm32".to_string(), ..Default::default() };
+        // Named for this test alone, so no other test caches it first
+        let name = "test_compilers_share_compiled_functions".to_string();
+        let mut function = WasmIR::new(name, Signature { params: vec![], returns: Some(Type::I32) });
+        function.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(Constant::I32(7))) });
+
+        let mut first = WasmRustCompiler::new(target.clone());
+        first.compile_wasmir(&function, backend::BuildProfile::Development).unwrap();
+        assert_eq!((first.stats().cache_hits, first.stats().cache_misses), (0, 1));
+
+        let mut second = WasmRustCompiler::new(target);
+        for _ in 0..2 {
+            second.compile_wasmir(&function, backend::BuildProfile::Development).unwrap();
+        }
+        assert_eq!((second.stats().cache_hits, second.stats().cache_misses), (2, 0));
+        assert_eq!(second.stats().functions_compiled, 2);
+    }
+
     #[test]
     fn test_target_support() {
         assert!(WasmRustCompiler::is_target_supported("wasm32-unknown-unknown"));
@@ -279,6 +849,76 @@ mod tests {
         assert!(frontend.validate_config().is_err());
     }
 
+    #[test]
+    fn test_run_module() {
+        use wasm::wasmir::{Constant, Operand, Signature, Terminator, Type, WasmIR};
+
+        let mut module = WasmModule::new();
+        let mut main = WasmIR::new("main".to_string(), Signature { params: vec![], returns: Some(Type::I32) });
+        main.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Constant(Constant::I32(7))) });
+        let index = module.add_function(main);
+        module.export_function("main", index);
+
+        let mut frontend = WasmRustFrontend::new(CompilerConfig::default()).unwrap();
+        let report = frontend.run_module(&module, &[], &HostFunctions::new());
+        if host::runtime::available_runtimes().is_empty() {
+            assert!(report.is_err());
+        } else {
+            assert_eq!(report.unwrap().to_string(), "main() -> 7\n");
+        }
+    }
+
+    #[test]
+    fn test_run_tests() {
+        use host::test_runner::TestOutcome;
+        use wasm::wasmir::{Constant, Operand, Signature, Terminator, WasmIR};
+
+        let mut module = WasmModule::new();
+        let mut fails = WasmIR::new("fails".to_string(), Signature { params: vec![], returns: None });
+        let message = Operand::Constant(Constant::String("assertion failed: 1 + 1 == 3".to_string()));
+        fails.add_basic_block(vec![], Terminator::Panic { message: Some(message) });
+        let index = module.add_function(fails);
+
+        let mut frontend = WasmRustFrontend::new(CompilerConfig::default()).unwrap();
+        let report = frontend.run_tests(&module, &[TestCase::new("tests::fails", index)], &HostFunctions::new());
+        if host::runtime::available_runtimes().is_empty() {
+            assert!(report.is_err());
+        } else {
+            let report = report.unwrap();
+            assert_eq!(report.results[0].1, TestOutcome::Failed("assertion failed: 1 + 1 == 3".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_compile_variants() {
+        use wasm::wasmir::features::Proposal;
+        use wasm::wasmir::{Signature, Terminator, WasmIR};
+
+        let mut module = WasmModule::new();
+        let mut main = WasmIR::new("main".to_string(), Signature { params: vec![], returns: None });
+        main.add_basic_block(vec![], Terminator::Return { value: None });
+        let index = module.add_function(main);
+        module.export_function("main", index);
+
+        let simd = BuildVariant::new("simd", TargetFeatures::default().enable(Proposal::Simd));
+        let baseline = BuildVariant::new("baseline", TargetFeatures::default());
+        assert_eq!(simd.file_name("out/app.wasm"), "out/app.simd.wasm");
+        assert!(!simd.config(&CompilerConfig::default()).atomics);
+
+        let config = CompilerConfig { variants: vec![simd.clone(), baseline], ..CompilerConfig::default() };
+        let mut frontend = WasmRustFrontend::new(config).unwrap();
+        let binaries = frontend.compile_variants(&module).unwrap();
+        assert_eq!(binaries.len(), 2);
+        assert_eq!(binaries[0].0, simd);
+        let glue = frontend.generate_js_glue(&module, "app.wasm", None).unwrap();
+        assert!(glue.contains("{ file: \"app.simd.wasm\", features: ["));
+
+        let wasm64 = BuildVariant::new("wasm64", TargetFeatures::default()).target("wasm64-unknown-unknown");
+        frontend.update_config(CompilerConfig { variants: vec![wasm64], ..CompilerConfig::default() });
+        let error = frontend.compile_variants(&module).unwrap_err();
+        assert_eq!(error.to_string(), "variant `wasm64`: unsupported target wasm64-unknown-unknown");
+    }
+
     #[test]
     fn test_version() {
         assert!(!VERSION.is_empty());
@@ -298,7 +938,23 @@ mod tests {
         assert_eq!(config.build_profile, backend::BuildProfile::Development);
         assert_eq!(config.target, DEFAULT_TARGET);
         assert!(config.debug_info);
+        assert!(!config.demangle);
         assert!(!config.lto);
         assert!(config.pgo.is_none());
+        assert_eq!(config.init_strategy, InitStrategy::StartSection);
+        assert_eq!(config.panic_strategy, PanicStrategy::Abort);
+        assert_eq!(config.allocator, AllocatorStrategy::Bundled);
+        assert_eq!(config.stack_size, DEFAULT_STACK_SIZE);
+        assert!(config.checks_stack());
+        let release = CompilerConfig { build_profile: backend::BuildProfile::Release, ..CompilerConfig::default() };
+        assert!(!release.checks_stack());
+        assert!(config.checks_regions() && !release.checks_regions());
+        assert!(CompilerConfig { region_checks: Some(true), ..release.clone() }.checks_regions());
+        assert!(CompilerConfig { stack_check: Some(true), ..release }.checks_stack());
+        assert_eq!(config.glue_format, GlueFormat::EsModule);
+        assert_eq!(config.target_features, TargetFeatures::default());
+        assert!(!config.split_cold);
+        assert!(config.link_libraries.is_empty() && config.linker.is_none());
+        assert!(!config.plugins);
     }
 }
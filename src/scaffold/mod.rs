@@ -0,0 +1,371 @@
+//! Project templates for `wasmrust new`
+//!
+//! Each template is a set of files under `scaffold/templates`, embedded at
+//! build time and rendered by substituting `{{placeholder}}`s. Rendered
+//! projects are validated before anything is written, so a template that
+//! would produce a broken project fails here rather than in a user's tree.
+//!
+//! Every project depends on the `wasm` crate. Browser projects load their
+//! module through the glue `wasmrust glue` writes next to the `.wasm` file.
+
+use crate::backend::js_glue::GlueFormat;
+use std::path::Path;
+
+/// Version of the `wasm` crate generated projects depend on
+pub const WASM_CRATE_VERSION: &str = "0.1";
+
+/// Project template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+    /// Browser module loaded from a page through the JS glue, with a dev
+    /// server
+    WebApp,
+    /// Component implementing a WIT world, with bindings from a build script
+    ComponentPlugin,
+    /// WASI command run and tested under wasmtime
+    WasiCli,
+    /// Browser module whose threads run on Web Workers started by the glue
+    WorkerPool,
+}
+
+impl Template {
+    pub const ALL: [Template; 4] = [
+        Template::WebApp,
+        Template::ComponentPlugin,
+        Template::WasiCli,
+        Template::WorkerPool,
+    ];
+
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Template::WebApp => "web-app",
+            Template::ComponentPlugin => "component-plugin",
+            Template::WasiCli => "wasi-cli",
+            Template::WorkerPool => "worker-pool",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|template| template.name() == name)
+    }
+
+    /// Target the project builds for
+    pub fn target(self) -> &'static str {
+        match self {
+            Template::WebApp | Template::WorkerPool => "wasm32-unknown-unknown",
+            Template::ComponentPlugin | Template::WasiCli => "wasm32-wasip1",
+        }
+    }
+
+    /// Template files as (destination, contents) pairs
+    fn files(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Template::WebApp => &[
+                ("Cargo.toml", include_str!("templates/web-app/Cargo.toml.tmpl")),
+                (".cargo/config.toml", include_str!("templates/web-app/cargo-config.toml")),
+                (".gitignore", include_str!("templates/gitignore")),
+                ("src/lib.rs", include_str!("templates/web-app/src/lib.rs")),
+                ("index.html", include_str!("templates/web-app/index.html")),
+                ("main.js", include_str!("templates/web-app/main.js")),
+                ("serve.mjs", include_str!("templates/serve.mjs")),
+                ("package.json", include_str!("templates/web-app/package.json")),
+            ],
+            Template::ComponentPlugin => &[
+                ("Cargo.toml", include_str!("templates/component-plugin/Cargo.toml.tmpl")),
+                (".cargo/config.toml", include_str!("templates/component-plugin/cargo-config.toml")),
+                (".gitignore", include_str!("templates/gitignore")),
+                ("build.rs", include_str!("templates/component-plugin/build.rs")),
+                ("src/lib.rs", include_str!("templates/component-plugin/src/lib.rs")),
+                ("wit/world.wit", include_str!("templates/component-plugin/wit/world.wit")),
+            ],
+            Template::WasiCli => &[
+                ("Cargo.toml", include_str!("templates/wasi-cli/Cargo.toml.tmpl")),
+                (".cargo/config.toml", include_str!("templates/wasi-cli/cargo-config.toml")),
+                (".gitignore", include_str!("templates/gitignore")),
+                ("src/main.rs", include_str!("templates/wasi-cli/src/main.rs")),
+            ],
+            Template::WorkerPool => &[
+                ("Cargo.toml", include_str!("templates/worker-pool/Cargo.toml.tmpl")),
+                (".cargo/config.toml", include_str!("templates/worker-pool/cargo-config.toml")),
+                (".gitignore", include_str!("templates/gitignore")),
+                ("src/lib.rs", include_str!("templates/worker-pool/src/lib.rs")),
+                ("index.html", include_str!("templates/worker-pool/index.html")),
+                ("main.js", include_str!("templates/worker-pool/main.js")),
+                ("worker.js", include_str!("templates/worker-pool/worker.js")),
+                ("serve.mjs", include_str!("templates/serve.mjs")),
+                ("package.json", include_str!("templates/worker-pool/package.json")),
+            ],
+        }
+    }
+}
+
+/// Scaffolding errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScaffoldError {
+    /// The project name is not a usable crate and WIT package name
+    InvalidName(String),
+    /// A template file uses a placeholder with no value
+    UnknownPlaceholder { file: String, placeholder: String },
+    /// A rendered project is missing a file or setting it needs
+    InvalidProject { file: String, reason: String },
+    /// Writing the project failed
+    Io(String),
+}
+
+impl std::fmt::Display for ScaffoldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScaffoldError::InvalidName(name) => write!(
+                f,
+                "Invalid project name `{}`: use lowercase words of letters and digits, each starting with a letter, joined by `-` or `_`",
+                name
+            ),
+            ScaffoldError::UnknownPlaceholder { file, placeholder } => {
+                write!(f, "Unknown placeholder `{{{{{}}}}}` in {}", placeholder, file)
+            }
+            ScaffoldError::InvalidProject { file, reason } => write!(f, "Invalid project: {}: {}", file, reason),
+            ScaffoldError::Io(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ScaffoldError {}
+
+/// File of a rendered project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectFile {
+    /// Path relative to the project root
+    pub path: String,
+    pub contents: String,
+}
+
+/// Renders a template into a project
+#[derive(Debug, Clone)]
+pub struct ProjectGenerator {
+    name: String,
+    template: Template,
+    wasm_path: Option<String>,
+}
+
+impl ProjectGenerator {
+    /// Creates a generator for a project named `name`
+    pub fn new(name: impl Into<String>, template: Template) -> Self {
+        Self { name: name.into(), template, wasm_path: None }
+    }
+
+    /// Depends on a local checkout of the `wasm` crate instead of the registry
+    pub fn wasm_path(mut self, path: impl Into<String>) -> Self {
+        self.wasm_path = Some(path.into());
+        self
+    }
+
+    /// Renders and validates the project's files
+    pub fn render(&self) -> Result<Vec<ProjectFile>, ScaffoldError> {
+        if !is_valid_name(&self.name) {
+            return Err(ScaffoldError::InvalidName(self.name.clone()));
+        }
+        let wasm_dependency = match &self.wasm_path {
+            Some(path) => format!("{{ path = {:?} }}", path),
+            None => format!("{:?}", WASM_CRATE_VERSION),
+        };
+        let values = [
+            ("name", self.name.clone()),
+            ("crate_name", self.name.replace('-', "_")),
+            ("wit_name", self.name.replace('_', "-")),
+            ("wasm_dependency", wasm_dependency),
+        ];
+
+        let files = self.template.files().iter()
+            .map(|(path, source)| {
+                Ok(ProjectFile { path: path.to_string(), contents: substitute(path, source, &values)? })
+            })
+            .collect::<Result<Vec<_>, ScaffoldError>>()?;
+        validate(self.template, &self.name, &files)?;
+        Ok(files)
+    }
+
+    /// Renders the project into `dir`, which must not exist yet
+    pub fn write(&self, dir: &Path) -> Result<Vec<ProjectFile>, ScaffoldError> {
+        let files = self.render()?;
+        if dir.exists() {
+            return Err(ScaffoldError::Io(format!("{} already exists", dir.display())));
+        }
+        for file in &files {
+            let path = dir.join(&file.path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| ScaffoldError::Io(format!("{}: {}", parent.display(), e)))?;
+            }
+            std::fs::write(&path, &file.contents)
+                .map_err(|e| ScaffoldError::Io(format!("{}: {}", path.display(), e)))?;
+        }
+        Ok(files)
+    }
+}
+
+/// Checks a name works as a crate name, a JS identifier fragment, and a
+/// WIT package name once `_` becomes `-`
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.split(['-', '_']).all(|word| {
+            word.starts_with(|c: char| c.is_ascii_lowercase())
+                && word.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        })
+}
+
+/// Replaces every `{{placeholder}}` in a template file
+fn substitute(file: &str, source: &str, values: &[(&str, String)]) -> Result<String, ScaffoldError> {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| ScaffoldError::UnknownPlaceholder {
+            file: file.to_string(),
+            placeholder: after.lines().next().unwrap_or_default().to_string(),
+        })?;
+        let placeholder = &after[..end];
+        let value = values.iter()
+            .find(|(name, _)| *name == placeholder)
+            .ok_or_else(|| ScaffoldError::UnknownPlaceholder {
+                file: file.to_string(),
+                placeholder: placeholder.to_string(),
+            })?;
+        out.push_str(&value.1);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Checks a rendered project has what building, serving, and testing it need
+fn validate(template: Template, name: &str, files: &[ProjectFile]) -> Result<(), ScaffoldError> {
+    let invalid = |file: &str, reason: String| ScaffoldError::InvalidProject { file: file.to_string(), reason };
+    let find = |path: &str| files.iter().find(|file| file.path == path);
+
+    let manifest = find("Cargo.toml").ok_or_else(|| invalid("Cargo.toml", "missing".to_string()))?;
+    if !manifest.contents.contains(&format!("name = \"{}\"", name)) {
+        return Err(invalid("Cargo.toml", "package name does not match the project".to_string()));
+    }
+    if !manifest.contents.lines().any(|line| line.starts_with("wasm = ")) {
+        return Err(invalid("Cargo.toml", "does not depend on the wasm crate".to_string()));
+    }
+    if !manifest.contents.contains("[package.metadata.wasm]") {
+        return Err(invalid("Cargo.toml", "no [package.metadata.wasm] section".to_string()));
+    }
+    if let Some(glue) = toml_string(&manifest.contents, "glue") {
+        if GlueFormat::from_name(&glue).is_none() {
+            return Err(invalid("Cargo.toml", format!("unknown glue format `{}`", glue)));
+        }
+    }
+
+    let entry = if manifest.contents.contains("[lib]") { "src/lib.rs" } else { "src/main.rs" };
+    if find(entry).is_none() {
+        return Err(invalid(entry, "missing".to_string()));
+    }
+    let config = find(".cargo/config.toml").ok_or_else(|| invalid(".cargo/config.toml", "missing".to_string()))?;
+    if !config.contents.contains(template.target()) {
+        return Err(invalid(".cargo/config.toml", format!("does not build for {}", template.target())));
+    }
+    if find("package.json").is_some() && find("serve.mjs").is_none() {
+        return Err(invalid("serve.mjs", "missing for the `dev` script".to_string()));
+    }
+
+    let glue_file = format!("{}.{}", name.replace('-', "_"), GlueFormat::EsModule.extension());
+    for file in files.iter().filter(|file| file.path.ends_with(".js")) {
+        if file.contents.contains("/release/") && !file.contents.contains(&glue_file) {
+            return Err(invalid(&file.path, format!("does not load the glue {}", glue_file)));
+        }
+    }
+    Ok(())
+}
+
+/// Gets a string value of a top-level `key = "value"` line
+fn toml_string(source: &str, key: &str) -> Option<String> {
+    source.lines().find_map(|line| {
+        let (k, v) = line.split_once('=')?;
+        (k.trim() == key).then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_template_renders_valid_project() {
+        for template in Template::ALL {
+            let files = ProjectGenerator::new("my-app", template).render().unwrap();
+            for file in &files {
+                assert!(!file.contents.contains("{{"), "{} in {} left a placeholder", file.path, template.name());
+            }
+            assert_eq!(Template::from_name(template.name()), Some(template));
+        }
+
+        let plugin = ProjectGenerator::new("my_app", Template::ComponentPlugin)
+            .wasm_path("../crates/wasm")
+            .render()
+            .unwrap();
+        let find = |path: &str| plugin.iter().find(|file| file.path == path).unwrap();
+        assert!(find("Cargo.toml").contents.contains("wasm = { path = \"../crates/wasm\" }"));
+        assert!(find("wit/world.wit").contents.starts_with("package my-app:plugin;"));
+    }
+
+    #[test]
+    fn test_rejects_invalid_names_and_placeholders() {
+        for name in ["", "App", "my app", "2fast", "my-2nd", "a--b"] {
+            assert_eq!(
+                ProjectGenerator::new(name, Template::WebApp).render(),
+                Err(ScaffoldError::InvalidName(name.to_string()))
+            );
+        }
+        assert_eq!(
+            substitute("main.js", "{{name}} {{version}}", &[("name", "x".to_string())]),
+            Err(ScaffoldError::UnknownPlaceholder { file: "main.js".to_string(), placeholder: "version".to_string() })
+        );
+
+        let files = ProjectGenerator::new("my-app", Template::WorkerPool).render().unwrap();
+        let broken = |path: &str, from: &str, to: &str| {
+            let files: Vec<_> = files.iter()
+                .map(|file| match file.path == path {
+                    true => ProjectFile { contents: file.contents.replace(from, to), ..file.clone() },
+                    false => file.clone(),
+                })
+                .collect();
+            validate(Template::WorkerPool, "my-app", &files).unwrap_err().to_string()
+        };
+        assert_eq!(broken("Cargo.toml", "wasm = ", "# wasm = "), "Invalid project: Cargo.toml: does not depend on the wasm crate");
+        assert_eq!(broken("worker.js", "my_app.mjs", "my_app.wasm"), "Invalid project: worker.js: does not load the glue my_app.mjs");
+    }
+
+    /// Generates every template's project and checks it, tests included
+    ///
+    /// The component plugin's build script also runs, generating its
+    /// bindings.
+    #[test]
+    #[ignore = "needs the wasm32-unknown-unknown and wasm32-wasip1 targets installed"]
+    fn test_generated_projects_build() {
+        let wasm = Path::new(env!("CARGO_MANIFEST_DIR")).join("../crates/wasm");
+        let root = std::env::temp_dir().join(format!("wasmrust-scaffold-{}", std::process::id()));
+        for template in Template::ALL {
+            let dir = root.join(template.name());
+            ProjectGenerator::new("scaffold-check", template)
+                .wasm_path(wasm.to_string_lossy())
+                .write(&dir)
+                .unwrap();
+            let output = std::process::Command::new(env!("CARGO"))
+                .args(["check", "--all-targets", "--target", template.target()])
+                .current_dir(&dir)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{} failed to check:\n{}",
+                template.name(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,18 @@
+//! Generates the component bindings from `wit/world.wit`
+
+use std::{env, fs, path::Path};
+
+use wasm::component::guest_bindings::GuestBindings;
+use wasm::component::wit::WitPackage;
+
+fn main() {
+    println!("cargo:rerun-if-changed=wit");
+    let source = fs::read_to_string("wit/world.wit").expect("cannot read wit/world.wit");
+    let package = WitPackage::parse(&source).unwrap_or_else(|e| panic!("wit/world.wit: {}", e));
+    let bindings = GuestBindings::new(&package, "plugin")
+        .implementor("crate::Plugin")
+        .generate()
+        .unwrap_or_else(|e| panic!("cannot generate bindings: {}", e));
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("bindings.rs");
+    fs::write(out, bindings).expect("cannot write bindings");
+}
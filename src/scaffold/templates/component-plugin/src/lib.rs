@@ -0,0 +1,24 @@
+//! {{name}}: component implementing the `plugin` world
+
+include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+/// Implementation of the world's exports
+pub struct Plugin;
+
+impl exports::handler::Guest for Plugin {
+    fn handle(input: String) -> String {
+        input.to_uppercase()
+    }
+}
+
+// The bindings only link in a component, so the tests run under wasmtime
+#[cfg(all(test, target_family = "wasm"))]
+mod tests {
+    use super::*;
+    use exports::handler::Guest;
+
+    #[test]
+    fn test_handle() {
+        assert_eq!(Plugin::handle("hello".to_string()), "HELLO");
+    }
+}
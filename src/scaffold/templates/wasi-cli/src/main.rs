@@ -0,0 +1,34 @@
+//! {{name}}: command-line tool run under a WASI runtime
+
+use std::env;
+use std::process::ExitCode;
+
+fn greeting(name: Option<&str>) -> String {
+    format!("Hello, {}!", name.unwrap_or("world"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [flag] if flag == "--host" => {
+            println!("{:?} host, wasm {}", wasm::host::detect_host_profile(), wasm::runtime_version());
+        }
+        [] | [_] => println!("{}", greeting(args.first().map(String::as_str))),
+        _ => {
+            eprintln!("usage: {{name}} [name | --host]");
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_greeting() {
+        assert_eq!(greeting(None), "Hello, world!");
+        assert_eq!(greeting(Some("wasm")), "Hello, wasm!");
+    }
+}
@@ -0,0 +1,6 @@
+import { init, add, threading_available } from "./target/wasm32-unknown-unknown/release/{{crate_name}}.mjs";
+
+await init();
+
+document.getElementById("output").textContent =
+  `2 + 40 = ${add(2, 40)}; threads ${threading_available() ? "available" : "unavailable"}`;
@@ -0,0 +1,23 @@
+//! {{name}}: exports called from `main.js` through the generated glue
+
+/// Adds two numbers
+#[no_mangle]
+pub extern "C" fn add(a: i32, b: i32) -> i32 {
+    a.wrapping_add(b)
+}
+
+/// Whether the host can run threads, as the `wasm` crate detects it
+#[no_mangle]
+pub extern "C" fn threading_available() -> i32 {
+    wasm::has_capability("threading") as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        assert_eq!(add(2, 40), 42);
+    }
+}
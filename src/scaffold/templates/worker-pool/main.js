@@ -0,0 +1,28 @@
+/** Sends jobs to the Worker running the module */
+class Module {
+  constructor() {
+    this.worker = new Worker(new URL("./worker.js", import.meta.url), { type: "module" });
+    this.pending = new Map();
+    this.nextId = 0;
+    this.worker.onmessage = ({ data: { id, result } }) => {
+      this.pending.get(id)(result);
+      this.pending.delete(id);
+    };
+  }
+
+  countPrimes(limit) {
+    const id = this.nextId++;
+    return new Promise((resolve) => {
+      this.pending.set(id, resolve);
+      this.worker.postMessage({ id, limit });
+    });
+  }
+}
+
+const module = new Module();
+const limits = [100_000, 200_000, 300_000, 400_000];
+const counts = await Promise.all(limits.map((limit) => module.countPrimes(limit)));
+
+document.getElementById("output").textContent = limits
+  .map((limit, i) => `primes below ${limit}: ${counts[i]}`)
+  .join("\n");
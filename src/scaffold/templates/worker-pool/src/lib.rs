@@ -0,0 +1,40 @@
+//! {{name}}: prime counting split across threads
+//!
+//! `count_primes` splits its range over `wasm::threading::scope` threads.
+//! When the module is compiled with shared-memory atomics, each thread runs
+//! on a Web Worker started by the generated glue; otherwise they run one
+//! after another on the calling thread. The module itself runs in
+//! `worker.js`, since a page's main thread may not block on a join.
+
+use wasm::threading::scope;
+
+/// Threads each count takes
+const THREADS: u32 = 4;
+
+/// Counts the primes below `limit`
+#[no_mangle]
+pub extern "C" fn count_primes(limit: u32) -> u32 {
+    let chunk = limit.div_ceil(THREADS).max(1);
+    scope(|s| {
+        let handles: Vec<_> = (0..limit)
+            .step_by(chunk as usize)
+            .map(|start| s.spawn(move || (start..limit.min(start + chunk)).filter(|&n| is_prime(n)).count() as u32))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap_or(0)).sum()
+    })
+}
+
+fn is_prime(n: u32) -> bool {
+    n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| n % d != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_primes() {
+        assert_eq!(count_primes(2), 0);
+        assert_eq!(count_primes(30), 10);
+    }
+}
@@ -0,0 +1,10 @@
+// Runs the module off the page's main thread, which may not block on the
+// threads `count_primes` joins. The glue starts those threads' Workers.
+import { init, count_primes } from "./target/wasm32-unknown-unknown/release/{{crate_name}}.mjs";
+
+const ready = init();
+
+self.onmessage = async ({ data: { id, limit } }) => {
+  await ready;
+  self.postMessage({ id, result: count_primes(limit) });
+};
@@ -0,0 +1,247 @@
+//! Target specifications, built in or loaded from JSON like rustc's
+//!
+//! `--target` takes the name of a built-in target, a path to a `.json`
+//! file, or the name of a `<name>.json` file in one of the directories of
+//! `RUST_TARGET_PATH`. The file is the one rustc takes for the same
+//! target, so it may hold keys only rustc reads; of the rest, `arch`,
+//! `os`, `target-pointer-width`, `features`, and `panic-strategy` are
+//! rustc's, and `import-module` names the module that imports Rust
+//! declares without `#[link(wasm_import_module)]` come from, in place of
+//! `env`.
+//!
+//! Targets whose `os` is `emscripten` follow Emscripten's ABI; see
+//! `backend::emscripten`.
+
+use crate::backend::codegen::PanicStrategy;
+use crate::backend::target_features::TargetFeatures;
+use crate::CompilerConfig;
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use wasm::wasmir::WasmModule;
+
+/// Directories searched for `<target>.json`, separated like `PATH`
+pub const TARGET_PATH_ENV: &str = "RUST_TARGET_PATH";
+
+/// Module rustc imports undecorated foreign functions from
+pub const DEFAULT_IMPORT_MODULE: &str = "env";
+
+/// `os` of targets following Emscripten's ABI
+pub const EMSCRIPTEN_OS: &str = "emscripten";
+
+/// `os` of targets without one, as rustc defaults it
+const NO_OS: &str = "none";
+
+/// What the compiler needs to know about a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSpec {
+    /// Name of the target, the `llvm-target` of a file
+    pub llvm_target: String,
+    pub arch: String,
+    pub os: String,
+    pub pointer_width: u32,
+    /// Proposals enabled before any `-C target-feature`
+    pub features: TargetFeatures,
+    pub panic_strategy: PanicStrategy,
+    pub import_module: String,
+}
+
+impl TargetSpec {
+    /// Names of the built-in targets
+    pub const BUILTIN: [&'static str; 2] = ["wasm32-unknown-unknown", "wasm32-unknown-emscripten"];
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        Self::BUILTIN.contains(&name).then(|| TargetSpec {
+            llvm_target: name.to_string(),
+            arch: "wasm32".to_string(),
+            os: name.split('-').nth(2).unwrap_or(NO_OS).to_string(),
+            pointer_width: 32,
+            features: TargetFeatures::default(),
+            panic_strategy: PanicStrategy::Abort,
+            import_module: DEFAULT_IMPORT_MODULE.to_string(),
+        })
+    }
+
+    /// Finds the spec `--target` names
+    pub fn resolve(target: &str) -> Result<Self, TargetSpecError> {
+        if let Some(spec) = Self::builtin(target) {
+            return Ok(spec);
+        }
+        if target.ends_with(".json") {
+            return Self::load(Path::new(target));
+        }
+        let file = format!("{}.json", target);
+        let search = std::env::var_os(TARGET_PATH_ENV).unwrap_or_default();
+        match std::env::split_paths(&search).map(|dir| dir.join(&file)).find(|path| path.is_file()) {
+            Some(path) => Self::load(&path),
+            None => Err(TargetSpecError::Unknown(target.to_string())),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, TargetSpecError> {
+        let json = std::fs::read_to_string(path);
+        Self::from_json(&json.map_err(|err| TargetSpecError::Io(path.to_path_buf(), err.to_string()))?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, TargetSpecError> {
+        let value: Value = serde_json::from_str(json).map_err(|err| TargetSpecError::Json(err.to_string()))?;
+        let invalid = |key: &str, expected: &str| TargetSpecError::Invalid(format!("`{}` must be {}", key, expected));
+        let string = |key: &str| value.get(key).map(|value| value.as_str().ok_or_else(|| invalid(key, "a string")));
+
+        let llvm_target = string("llvm-target").ok_or_else(|| invalid("llvm-target", "given"))??.to_string();
+        let arch = string("arch").ok_or_else(|| invalid("arch", "given"))??.to_string();
+        if arch != "wasm32" && arch != "wasm64" {
+            return Err(invalid("arch", "wasm32 or wasm64"));
+        }
+        let os = string("os").transpose()?.unwrap_or(NO_OS).to_string();
+        // rustc has taken the width as a string and as a number
+        let pointer_width = match value.get("target-pointer-width") {
+            None if arch == "wasm64" => 64,
+            None => 32,
+            Some(Value::String(width)) => width.parse().map_err(|_| invalid("target-pointer-width", "32 or 64"))?,
+            Some(width) => width.as_u64().ok_or_else(|| invalid("target-pointer-width", "32 or 64"))? as u32,
+        };
+        match (arch.as_str(), pointer_width) {
+            ("wasm32", 32) => {}
+            ("wasm64", 64) => {
+                return Err(TargetSpecError::Unsupported("64-bit pointers need memory64".to_string()));
+            }
+            _ => return Err(invalid("target-pointer-width", &format!("the width of {}", arch))),
+        }
+        let features = TargetFeatures::default()
+            .apply(string("features").transpose()?.unwrap_or_default())
+            .map_err(|err| TargetSpecError::Invalid(err.to_string()))?;
+        let panic_strategy = match string("panic-strategy").transpose()? {
+            None | Some("abort") => PanicStrategy::Abort,
+            Some("unwind") => PanicStrategy::Unwind,
+            Some(_) => return Err(invalid("panic-strategy", "abort or unwind")),
+        };
+        let import_module = string("import-module").transpose()?.unwrap_or(DEFAULT_IMPORT_MODULE).to_string();
+        Ok(TargetSpec { llvm_target, arch, os, pointer_width, features, panic_strategy, import_module })
+    }
+
+    /// Whether modules follow Emscripten's ABI, with its `main` and system calls
+    pub fn is_emscripten(&self) -> bool {
+        self.os == EMSCRIPTEN_OS
+    }
+
+    /// Makes the target's features and panic strategy those of `config`
+    ///
+    /// `-C target-feature` lists apply after, as with rustc.
+    pub fn configure(&self, config: &mut CompilerConfig) {
+        config.target_features = self.features;
+        config.panic_strategy = self.panic_strategy;
+    }
+
+    /// Moves the module's imports from `DEFAULT_IMPORT_MODULE` to `import_module`
+    pub fn apply_import_module(&self, module: &mut WasmModule) {
+        if self.import_module == DEFAULT_IMPORT_MODULE {
+            return;
+        }
+        for import in module.imports.iter_mut().filter(|import| import.module == DEFAULT_IMPORT_MODULE) {
+            import.module = self.import_module.clone();
+        }
+    }
+}
+
+/// Target that cannot be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetSpecError {
+    /// Neither built in nor a file in `RUST_TARGET_PATH`
+    Unknown(String),
+    /// The spec file cannot be read
+    Io(PathBuf, String),
+    /// The spec file is not JSON
+    Json(String),
+    /// A key of the spec is missing or has a wrong value
+    Invalid(String),
+    /// The spec is valid but asks for what the backends cannot do
+    Unsupported(String),
+}
+
+impl fmt::Display for TargetSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetSpecError::Unknown(target) => write!(
+                f,
+                "unknown target `{}`; expected one of {} or a target spec file",
+                target,
+                TargetSpec::BUILTIN.join(", ")
+            ),
+            TargetSpecError::Io(path, err) => write!(f, "cannot read target spec {}: {}", path.display(), err),
+            TargetSpecError::Json(err) => write!(f, "invalid target spec: {}", err),
+            TargetSpecError::Invalid(msg) => write!(f, "invalid target spec: {}", msg),
+            TargetSpecError::Unsupported(msg) => write!(f, "unsupported target spec: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TargetSpecError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::wasmir::features::Proposal;
+    use wasm::wasmir::{Signature, Type};
+
+    #[test]
+    fn test_reads_rustc_spec_keys() {
+        let spec = TargetSpec::from_json(
+            r#"{
+                "llvm-target": "wasm32-unknown-unknown",
+                "arch": "wasm32",
+                "target-pointer-width": "32",
+                "data-layout": "e-m:e-p:32:32-i64:64-n32:64-S128",
+                "features": "+simd128,-sign-ext",
+                "panic-strategy": "unwind",
+                "import-module": "host"
+            }"#,
+        )
+        .unwrap();
+        assert!(spec.features.contains(Proposal::Simd) && !spec.features.contains(Proposal::SignExtension));
+        assert_eq!(spec.panic_strategy, PanicStrategy::Unwind);
+        assert_eq!(spec.os, "none");
+
+        let mut module = WasmModule::new();
+        module.add_import("env", "log", Signature { params: vec![Type::I32], returns: None });
+        module.add_import("wasmrust", "__wasmrust_panic", Signature { params: vec![Type::I32], returns: None });
+        spec.apply_import_module(&mut module);
+        let modules: Vec<_> = module.imports.iter().map(|import| import.module.as_str()).collect();
+        assert_eq!(modules, ["host", "wasmrust"]);
+    }
+
+    #[test]
+    fn test_rejects_unusable_specs() {
+        let error = |json: &str| TargetSpec::from_json(json).unwrap_err().to_string();
+        assert_eq!(
+            error(r#"{"llvm-target": "wasm64-unknown-unknown", "arch": "wasm64"}"#),
+            "unsupported target spec: 64-bit pointers need memory64"
+        );
+        assert_eq!(
+            error(r#"{"llvm-target": "x", "arch": "wasm32", "target-pointer-width": 64}"#),
+            "invalid target spec: `target-pointer-width` must be the width of wasm32"
+        );
+        assert_eq!(error(r#"{"arch": "x86_64"}"#), "invalid target spec: `llvm-target` must be given");
+        assert_eq!(
+            TargetSpec::resolve("wasm32-none").unwrap_err().to_string(),
+            "unknown target `wasm32-none`; expected one of wasm32-unknown-unknown, wasm32-unknown-emscripten \
+             or a target spec file"
+        );
+    }
+
+    #[test]
+    fn test_resolves_spec_files() {
+        let dir = std::env::temp_dir().join(format!("wasmrust-target-spec-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wasm32-plugin.json");
+        std::fs::write(&path, r#"{"llvm-target": "wasm32-unknown-unknown", "arch": "wasm32"}"#).unwrap();
+
+        let spec = TargetSpec::resolve(path.to_str().unwrap()).unwrap();
+        assert_eq!(spec.import_module, DEFAULT_IMPORT_MODULE);
+        assert_eq!(spec.pointer_width, 32);
+        assert!(!spec.is_emscripten());
+        assert_eq!(TargetSpec::resolve("wasm32-unknown-unknown").unwrap().llvm_target, "wasm32-unknown-unknown");
+        assert!(TargetSpec::resolve("wasm32-unknown-emscripten").unwrap().is_emscripten());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+//! Self-profiling of the compiler, written out by `--timings`
+//!
+//! `span` times the work until the returned guard is dropped. Nothing is
+//! recorded unless `start` was called, so instrumented code only pays an
+//! atomic load when profiling is off. Spans of every thread go to the one
+//! recording, tagged with their thread, since rustc runs the lowering on
+//! a thread of its own. `finish` hands back the spans, and
+//! `Timings::chrome_trace` writes them in the Trace Event format that
+//! Perfetto, chrome://tracing, and speedscope show as a flame graph.
+
+use serde_json::json;
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stage of the compiler a span belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// rustc parsing and analyzing the crate
+    Frontend,
+    /// MIR to WasmIR
+    Lowering,
+    /// A pass over WasmIR or Cranelift IR
+    Pass,
+    /// Codegen of a module, containing its passes and encoding
+    Codegen,
+    /// Writing a section or function body of the binary
+    Encode,
+    /// Work of `cargo wasmrust` around the compiler
+    Driver,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] =
+        [Phase::Frontend, Phase::Lowering, Phase::Pass, Phase::Codegen, Phase::Encode, Phase::Driver];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Frontend => "frontend",
+            Phase::Lowering => "lowering",
+            Phase::Pass => "pass",
+            Phase::Codegen => "codegen",
+            Phase::Encode => "encode",
+            Phase::Driver => "driver",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|phase| phase.name() == name)
+    }
+}
+
+/// A finished span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedSpan {
+    pub phase: Phase,
+    pub name: String,
+    /// Since `start`
+    pub start: Duration,
+    pub duration: Duration,
+    /// Spans open around this one on its thread
+    pub depth: usize,
+    /// Numbered in the order threads first opened a span
+    pub thread: u64,
+}
+
+/// Spans recorded since `start`
+struct Recording {
+    epoch: Instant,
+    /// Tells spans opened before a restart from the new recording's
+    generation: u64,
+    spans: Vec<TimedSpan>,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+static IS_RECORDING: AtomicBool = AtomicBool::new(false);
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static THREAD: u64 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Starts recording spans, dropping any recorded so far
+pub fn start() {
+    let generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+    *lock() = Some(Recording { epoch: Instant::now(), generation, spans: Vec::new() });
+    IS_RECORDING.store(true, Ordering::Release);
+}
+
+/// Stops recording, returning the spans if `start` was called
+pub fn finish() -> Option<Timings> {
+    IS_RECORDING.store(false, Ordering::Release);
+    let mut spans = lock().take()?.spans;
+    // Spans finish innermost first
+    spans.sort_by_key(|span| (span.start, span.thread, span.depth));
+    Some(Timings { spans })
+}
+
+/// Whether spans are being recorded
+pub fn is_recording() -> bool {
+    IS_RECORDING.load(Ordering::Acquire)
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Recording>> {
+    // A panic while holding the lock leaves the spans intact
+    RECORDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Times the work until the returned guard is dropped
+///
+/// `name` is only formatted while recording, so `format_args!` keeps
+/// spans of every function cheap.
+pub fn span(phase: Phase, name: impl Display) -> Span {
+    if !is_recording() {
+        return Span { open: None };
+    }
+    let Some((epoch, generation)) = lock().as_ref().map(|recording| (recording.epoch, recording.generation)) else {
+        return Span { open: None };
+    };
+    let depth = DEPTH.with(|depth| depth.replace(depth.get() + 1));
+    let open = OpenSpan { phase, name: name.to_string(), start: epoch.elapsed(), depth, generation };
+    Span { open: Some(open) }
+}
+
+/// Guard of an unfinished span
+#[must_use = "the span ends when the guard is dropped"]
+pub struct Span {
+    open: Option<OpenSpan>,
+}
+
+struct OpenSpan {
+    phase: Phase,
+    name: String,
+    start: Duration,
+    depth: usize,
+    generation: u64,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(open) = self.open.take() else { return };
+        DEPTH.with(|depth| depth.set(open.depth));
+        let mut recording = lock();
+        // A span outliving its recording is dropped with it
+        let Some(recording) = recording.as_mut().filter(|recording| recording.generation == open.generation) else {
+            return;
+        };
+        let duration = recording.epoch.elapsed().saturating_sub(open.start);
+        recording.spans.push(TimedSpan {
+            phase: open.phase,
+            name: open.name,
+            start: open.start,
+            duration,
+            depth: open.depth,
+            thread: THREAD.with(|thread| *thread),
+        });
+    }
+}
+
+/// Spans of one recording, in the order they started
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub spans: Vec<TimedSpan>,
+}
+
+impl Timings {
+    /// Time spent in spans of `phase`, not counting spans nested in others of it
+    pub fn total(&self, phase: Phase) -> Duration {
+        let mut total = Duration::ZERO;
+        let mut covered_until = Duration::ZERO;
+        for span in self.spans.iter().filter(|span| span.phase == phase) {
+            let end = span.start + span.duration;
+            if end > covered_until {
+                total += end - span.start.max(covered_until);
+                covered_until = end;
+            }
+        }
+        total
+    }
+
+    /// The spans as a Trace Event Format document of complete events
+    pub fn chrome_trace(&self) -> String {
+        let events: Vec<_> = self.spans.iter()
+            .map(|span| {
+                json!({
+                    "name": span.name,
+                    "cat": span.phase.name(),
+                    "ph": "X",
+                    "ts": span.start.as_micros() as u64,
+                    "dur": span.duration.as_micros() as u64,
+                    "pid": 1,
+                    "tid": span.thread,
+                })
+            })
+            .collect();
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string()
+    }
+}
+
+/// Time per phase, like the summary of rustc's `-Z time-passes`
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for phase in Phase::ALL {
+            let total = self.total(phase);
+            if !total.is_zero() {
+                writeln!(f, "{:>10}  {:>9.3}ms", phase.name(), total.as_secs_f64() * 1000.0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_spans_nest_and_record_only_while_started() {
+        drop(span(Phase::Codegen, "before"));
+        assert!(!is_recording());
+
+        start();
+        {
+            let _codegen = span(Phase::Codegen, "codegen");
+            for name in ["a", "b"] {
+                let _function = span(Phase::Encode, format_args!("encode {}", name));
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        thread::spawn(|| drop(span(Phase::Lowering, "elsewhere"))).join().unwrap();
+        let mut timings = finish().unwrap();
+        assert!(finish().is_none());
+
+        // Tests compiling modules on other threads meanwhile add spans too
+        let own = THREAD.with(|thread| *thread);
+        let other = timings.spans.iter().find(|span| span.name == "elsewhere").unwrap().thread;
+        assert_ne!(other, own);
+        timings.spans.retain(|span| span.thread == own);
+        let spans: Vec<_> = timings.spans.iter().map(|span| (span.name.as_str(), span.depth)).collect();
+        assert_eq!(spans, [("codegen", 0), ("encode a", 1), ("encode b", 1)]);
+        assert!(timings.spans[0].duration >= timings.spans[1].duration + timings.spans[2].duration);
+        assert!(timings.total(Phase::Encode) >= Duration::from_millis(2));
+        assert_eq!(timings.total(Phase::Lowering), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_chrome_trace_has_complete_events() {
+        let span = |name: &str, start, duration, depth| TimedSpan {
+            phase: Phase::Pass,
+            name: name.to_string(),
+            start: Duration::from_micros(start),
+            duration: Duration::from_micros(duration),
+            depth,
+            thread: 1,
+        };
+        let timings = Timings { spans: vec![span("fold constants", 10, 90, 0), span("fold main", 20, 30, 1)] };
+        let trace: serde_json::Value = serde_json::from_str(&timings.chrome_trace()).unwrap();
+        assert_eq!(trace["traceEvents"][0], json!({
+            "name": "fold constants", "cat": "pass", "ph": "X", "ts": 10, "dur": 90, "pid": 1, "tid": 1,
+        }));
+        assert_eq!(trace["traceEvents"][1]["ts"], 20);
+        // Nested spans of a phase count once
+        assert_eq!(timings.total(Phase::Pass), Duration::from_micros(90));
+        assert_eq!(timings.to_string(), "      pass      0.090ms\n");
+    }
+}
@@ -0,0 +1,32 @@
+//! `#[wasm::bench]` exports
+//!
+//! Runs benchmarks through their exports the way a driver does, then reads
+//! the samples back one at a time.
+
+use wasm::bench::{sample, Bencher};
+
+fn fibonacci(n: u32) -> u64 {
+    match n {
+        0 | 1 => u64::from(n),
+        n => fibonacci(n - 1) + fibonacci(n - 2),
+    }
+}
+
+#[wasm::bench]
+fn fibonacci_20(bencher: &mut Bencher) {
+    bencher.iter(|| fibonacci(std::hint::black_box(20)));
+}
+
+#[wasm::bench]
+fn untimed(_: &mut Bencher) {}
+
+#[test]
+fn test_exports_run_benchmarks_and_keep_samples() {
+    assert_eq!(__wasm_bench_fibonacci_20(2, 8), 8);
+    let samples: Vec<u64> = (0..8).map(|index| sample(index).unwrap()).collect();
+    assert!(samples.iter().all(|&ns| ns > 0));
+    assert_eq!(sample(8), None);
+
+    assert_eq!(__wasm_bench_untimed(2, 8), 0);
+    assert_eq!(sample(0), None);
+}
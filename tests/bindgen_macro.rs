@@ -0,0 +1,147 @@
+//! `#[wasm::bindgen]` on wasm-bindgen style declarations
+//!
+//! Off wasm the imports link against C symbols named after the `wasmrust`
+//! imports, which these tests define to record the calls the glue would
+//! make on JavaScript objects.
+
+use std::sync::Mutex;
+
+use wasm::host::bindgen::prelude::*;
+use wasm::host::bindgen::{handle_of, take_object};
+use wasm::host::import::read_imports;
+use wasm::wasmir::{InteropSignature, InteropType};
+
+#[wasm_bindgen]
+extern "C" {
+    pub type Node;
+
+    #[wasm_bindgen(extends = Node)]
+    pub type Element;
+
+    #[wasm_bindgen(constructor)]
+    fn new() -> Element;
+
+    #[wasm_bindgen(static_method_of = Element, js_name = count)]
+    fn element_count() -> u32;
+
+    #[wasm_bindgen(method, js_name = appendChild)]
+    fn append_child(this: &Node, child: &Node) -> Node;
+
+    #[wasm_bindgen(method, getter = tagName)]
+    fn tag_name(this: &Element) -> String;
+
+    #[wasm_bindgen(method, setter = textContent)]
+    fn set_text_content(this: &Element, text: &str);
+
+    #[wasm_bindgen(js_namespace = console)]
+    fn log(message: &str);
+}
+
+#[wasm_bindgen(js_name = adoptNode)]
+pub fn adopt(node: &Element) -> Node {
+    node.append_child(node)
+}
+
+static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn record(call: String) {
+    CALLS.lock().unwrap().push(call);
+}
+
+unsafe fn text<'a>(ptr: *const u8, len: usize) -> &'a str {
+    std::str::from_utf8(std::slice::from_raw_parts(ptr, len)).unwrap()
+}
+
+#[export_name = "js_new:Element"]
+extern "C" fn host_new_element() -> u32 {
+    record("new Element".to_string());
+    7
+}
+
+#[export_name = "js_global:Element.count"]
+extern "C" fn host_element_count() -> u32 {
+    3
+}
+
+#[export_name = "js_call:appendChild"]
+extern "C" fn host_append_child(this: u32, child: u32) -> u32 {
+    record(format!("{}.appendChild({})", this, child));
+    child + 1
+}
+
+#[export_name = "js_get:tagName"]
+unsafe extern "C" fn host_tag_name(retptr: *mut usize, this: u32) {
+    let value = format!("DIV{}", this).into_bytes().into_boxed_slice();
+    let len = value.len();
+    retptr.write(Box::into_raw(value) as *mut u8 as usize);
+    retptr.add(1).write(len);
+}
+
+#[export_name = "js_set:textContent"]
+unsafe extern "C" fn host_set_text_content(this: u32, ptr: *const u8, len: usize) {
+    record(format!("{}.textContent = {}", this, text(ptr, len)));
+}
+
+#[export_name = "js_global:console.log"]
+unsafe extern "C" fn host_log(ptr: *const u8, len: usize) {
+    record(format!("console.log({})", text(ptr, len)));
+}
+
+#[test]
+fn test_declarations_call_the_glue_imports() {
+    let element = Element::new();
+    assert_eq!(Element::element_count(), 3);
+    element.set_text_content("hello");
+    assert_eq!(element.tag_name(), "DIV7");
+    let child = element.append_child(&element);
+    assert_eq!(handle_of(&child), 8);
+    log("done");
+
+    // SAFETY: handle 9 stands for an object the glue rooted for the export
+    let adopted = unsafe { __wasm_export_adopt(9) };
+    assert_eq!(adopted, 10);
+    let node: Node = element.unchecked_into();
+    assert_eq!(handle_of(&node), 7);
+
+    assert_eq!(*CALLS.lock().unwrap(), [
+        "new Element",
+        "7.textContent = hello",
+        "7.appendChild(7)",
+        "console.log(done)",
+        "9.appendChild(9)",
+    ]);
+    // SAFETY: the handle is rooted for this value alone
+    let value: JsValue = unsafe { take_object(11) };
+    assert_eq!(handle_of(AsRef::<JsValue>::as_ref(&value)), 11);
+}
+
+#[test]
+fn test_records_pass_objects_as_handles() {
+    let mut section = Vec::new();
+    for record in [
+        &__WASM_IMPORT___WASM_BINDGEN_ELEMENT_NEW[..],
+        &__WASM_IMPORT___WASM_BINDGEN_NODE_APPEND_CHILD,
+        &__WASM_IMPORT___WASM_BINDGEN_ELEMENT_SET_TEXT_CONTENT,
+        &__WASM_IMPORT_LOG,
+    ] {
+        section.extend_from_slice(record);
+    }
+    let imports = read_imports(&section).unwrap();
+
+    let names: Vec<_> = imports.iter().map(|import| (import.module.as_str(), import.name.as_str())).collect();
+    assert_eq!(names, [
+        ("wasmrust", "js_new:Element"),
+        ("wasmrust", "js_call:appendChild"),
+        ("wasmrust", "js_set:textContent"),
+        ("wasmrust", "js_global:console.log"),
+    ]);
+    assert_eq!(imports[1].signature, InteropSignature {
+        params: vec![InteropType::ExternRef, InteropType::ExternRef],
+        returns: InteropType::ExternRef,
+    });
+    assert_eq!(imports[2].signature.params, [InteropType::ExternRef, InteropType::Str]);
+    assert_eq!(wasm::host::export::read_exports(&__WASM_EXPORT_ADOPT).unwrap(), [(
+        "adoptNode".to_string(),
+        InteropSignature { params: vec![InteropType::ExternRef], returns: InteropType::ExternRef },
+    )]);
+}
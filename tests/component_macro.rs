@@ -0,0 +1,40 @@
+//! `#[wasm::component]` entry points
+//!
+//! Off wasm, pointers do not fit the `i32`s of the canonical ABI, so these
+//! tests call the entry points taking scalars only.
+
+#[wasm::component(interface = "../../tests/wit/greeter.wit#greeter")]
+struct Plugin;
+
+impl Greeter for Plugin {
+    fn greet(name: String, times: u8) -> String {
+        format!("hello {}", name).repeat(times as usize)
+    }
+
+    fn score(weight: Option<f64>, penalty: Result<(), u32>) -> f32 {
+        let score = weight.unwrap_or(1.0) as f32;
+        match penalty {
+            Ok(()) => score,
+            Err(penalty) => score - penalty as f32,
+        }
+    }
+
+    fn count(values: Vec<u16>) -> u32 {
+        values.len() as u32
+    }
+}
+
+#[test]
+fn test_entry_points_lift_flat_arguments() {
+    // SAFETY: the arguments are flattened `option<f64>` and `result<_, u32>`
+    unsafe {
+        assert_eq!(__export_greeter_score(1, 2.5, 1, 2), 0.5);
+        assert_eq!(__export_greeter_score(0, 0.0, 0, 0), 1.0);
+    }
+}
+
+#[test]
+fn test_trait_follows_the_interface() {
+    assert_eq!(Plugin::greet("wasm".into(), 2), "hello wasmhello wasm");
+    assert_eq!(<Plugin as Greeter>::count(vec![1, 2, 3]), 3);
+}
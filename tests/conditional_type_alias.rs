@@ -0,0 +1,19 @@
+//! `wasm::conditional_type_alias!` per-target representations
+
+wasm::conditional_type_alias! {
+    /// Length of a host-side buffer
+    pub Length: wasm => u32, native => u64;
+    Pair<T>: native => (T, T), wasm => [T; 2];
+}
+
+#[test]
+fn test_aliases_follow_the_target() {
+    let length: Length = 7;
+    let pair: Pair<u8> = Default::default();
+    if cfg!(target_arch = "wasm32") {
+        assert_eq!(core::mem::size_of_val(&length), 4);
+    } else {
+        assert_eq!(core::mem::size_of_val(&length), 8);
+        assert_eq!(core::any::type_name_of_val(&pair), "(u8, u8)");
+    }
+}
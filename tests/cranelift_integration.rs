@@ -4,7 +4,7 @@
 //! Cranelift backend can compile real Rust code to functional WASM output.
 
 use wasm::wasmir::{WasmIR, Signature, Type, Instruction, Terminator, Operand, BinaryOp};
-use wasm::backend::{BackendFactory, BuildProfile, CompilationResult};
+use wasm::backend::{BackendCapabilities, BackendFactory, BuildProfile, CompilationResult};
 use wasm::backend::cranelift::{WasmRustCraneliftBackend, CompilationStats};
 use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
 use std::time::Instant;
@@ -359,14 +359,14 @@ mod tests {
             };
             
             let target = "wasm32";
-            let backend = BackendFactory::create_backend(target, profile);
+            let backend = BackendFactory::create_backend(target, profile, &BackendCapabilities::default());
             
             // Should always be able to create a backend
             if backend.is_err() {
                 return TestResult::failed();
             }
             
-            let backend = backend.unwrap();
+            let backend = backend.unwrap().backend;
             
             // Check capabilities
             let capabilities = backend.capabilities();
@@ -0,0 +1,79 @@
+//! `#[wasm::export]` shims and metadata
+//!
+//! Calls the generated `extern "C"` shims the way the JS glue does, and
+//! checks that each export's record decodes to its interop signature.
+
+use wasm::host::export::{encode_export, lift_vec, read_exports};
+use wasm::wasmir::{ElementType, InteropSignature, InteropType};
+
+#[wasm::export]
+fn add(left: i32, right: u8, negate: bool) -> i64 {
+    let sum = i64::from(left) + i64::from(right);
+    if negate {
+        -sum
+    } else {
+        sum
+    }
+}
+
+#[wasm::export(name = "greet")]
+fn greeting(name: &str, times: u32) -> String {
+    format!("hello {}", name).repeat(times as usize)
+}
+
+#[wasm::export]
+fn scale(values: &mut [f32], factor: f32) {
+    values.iter_mut().for_each(|value| *value *= factor);
+}
+
+#[wasm::export]
+fn sorted(mut values: Vec<i16>, bytes: &[u8]) -> Vec<i16> {
+    values.extend(bytes.iter().map(|&byte| i16::from(byte)));
+    values.sort_unstable();
+    values
+}
+
+#[test]
+fn test_shims_lift_arguments_and_lower_results() {
+    let name = "wasm";
+    let mut values = [1.0f32, 2.5];
+    let unsorted = vec![9i16, -3].into_boxed_slice();
+    let mut area = [0usize; 2];
+    // SAFETY: every pointer comes from a live buffer of the given length,
+    // and the vector's buffer is given up as a `Vec` argument is
+    unsafe {
+        assert_eq!(__wasm_export_add(-4, 7, 1), -3);
+
+        __wasm_export_greeting(area.as_mut_ptr(), name.as_ptr(), name.len(), 2);
+        let greeting = String::from_utf8(lift_vec(area[0] as *mut u8, area[1])).unwrap();
+        assert_eq!(greeting, "hello wasmhello wasm");
+
+        __wasm_export_scale(values.as_mut_ptr(), values.len(), 2.0);
+        assert_eq!(values, [2.0, 5.0]);
+
+        let len = unsorted.len();
+        let ptr = Box::into_raw(unsorted) as *mut i16;
+        __wasm_export_sorted(area.as_mut_ptr(), ptr, len, [4u8].as_ptr(), 1);
+        assert_eq!(lift_vec(area[0] as *mut i16, area[1]), [-3, 4, 9]);
+    }
+}
+
+#[test]
+fn test_records_describe_interop_signatures() {
+    let mut section = Vec::new();
+    for record in [&__WASM_EXPORT_ADD[..], &__WASM_EXPORT_GREETING, &__WASM_EXPORT_SCALE, &__WASM_EXPORT_SORTED] {
+        section.extend_from_slice(record);
+    }
+    let exports = read_exports(&section).unwrap();
+
+    let greet = InteropSignature { params: vec![InteropType::Str, InteropType::Value], returns: InteropType::Str };
+    assert_eq!(__WASM_EXPORT_GREETING[..], encode_export("greet", &greet)[..]);
+    let names: Vec<_> = exports.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["add", "greet", "scale", "sorted"]);
+    assert_eq!(exports[0].1, InteropSignature { params: vec![InteropType::Value; 3], returns: InteropType::Value });
+    assert_eq!(exports[2].1.params, [InteropType::SliceMut(ElementType::F32), InteropType::Value]);
+    let sorted = &exports[3].1;
+    assert_eq!(sorted.params, [InteropType::Vec(ElementType::I16), InteropType::Slice(ElementType::U8)]);
+    assert_eq!(sorted.returns, InteropType::Vec(ElementType::I16));
+    assert_eq!(sorted.lowered_param_count(), 5);
+}
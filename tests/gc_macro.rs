@@ -0,0 +1,90 @@
+//! `#[wasm::gc]` on the linear-memory heap
+//!
+//! Structs and enums managed by the macro are traced through every field
+//! that may hold a `Gc`, so a rooted object keeps the whole graph it
+//! reaches alive.
+
+use std::cell::{Cell, RefCell};
+use wasm::memory::gc::{Gc, GcHeap};
+
+#[wasm::gc]
+struct Leaf(u32);
+
+#[wasm::gc]
+struct Tree {
+    label: String,
+    left: Cell<Option<Gc<Tree>>>,
+    leaves: RefCell<Vec<Gc<Leaf>>>,
+}
+
+#[wasm::gc]
+enum Expr<T: 'static> {
+    Literal(T),
+    Neg(Gc<Expr<T>>),
+    Add { left: Gc<Expr<T>>, right: Gc<Expr<T>>, depth: u32 },
+    Hole,
+}
+
+#[wasm::gc]
+struct Pair<A, B>(A, B, (u8, &'static str));
+
+fn eval(expr: &Expr<Gc<Leaf>>) -> i64 {
+    match expr {
+        Expr::Literal(leaf) => i64::from(leaf.0),
+        Expr::Neg(inner) => -eval(inner),
+        Expr::Add { left, right, .. } => eval(left) + eval(right),
+        Expr::Hole => 0,
+    }
+}
+
+fn tree(heap: &mut GcHeap, label: &str) -> wasm::memory::gc::Root<Tree> {
+    heap.alloc(Tree { label: label.to_string(), left: Cell::new(None), leaves: RefCell::new(Vec::new()) })
+}
+
+#[test]
+fn test_macro_traces_every_field() {
+    let mut heap = GcHeap::new();
+    let root = tree(&mut heap, "root");
+    let child = tree(&mut heap, "child");
+    root.left.set(Some(child.gc()));
+    drop(child);
+    for value in 0..3 {
+        let leaf = heap.alloc(Leaf(value));
+        root.left.get().unwrap().leaves.borrow_mut().push(leaf.gc());
+    }
+    let garbage = heap.alloc(Leaf(99));
+    drop(garbage);
+
+    assert_eq!(heap.collect(), 1);
+    let child = root.left.get().unwrap();
+    assert_eq!(child.label, "child");
+    assert_eq!(child.leaves.borrow().iter().map(|leaf| leaf.0).collect::<Vec<_>>(), [0, 1, 2]);
+
+    drop(root);
+    assert_eq!(heap.collect(), 5);
+    assert_eq!(heap.stats().live_objects, 0);
+}
+
+#[test]
+fn test_macro_traces_enum_variants_and_generic_fields() {
+    let mut heap = GcHeap::new();
+    let one = heap.alloc(Leaf(1));
+    let two = heap.alloc(Leaf(2));
+    let left = heap.alloc(Expr::Literal(one.gc()));
+    let right = heap.alloc(Expr::Literal(two.gc()));
+    let neg = heap.alloc(Expr::Neg(right.gc()));
+    let sum = heap.alloc(Expr::Add { left: left.gc(), right: neg.gc(), depth: 2 });
+    let seven = heap.alloc(Leaf(7)).gc();
+    let pair = heap.alloc(Pair(sum.gc(), Some(seven), (0, "pair")));
+    drop((one, two, left, right, neg, sum));
+    heap.alloc(Expr::<Gc<Leaf>>::Hole);
+
+    // Only the hole is garbage
+    assert_eq!(heap.collect(), 1);
+    assert_eq!(eval(&pair.0), -1);
+    assert_eq!(pair.1.unwrap().0, 7);
+    assert_eq!(pair.2, (0, "pair"));
+
+    drop(pair);
+    assert_eq!(heap.collect(), 8);
+}
@@ -0,0 +1,92 @@
+//! `#[wasm::import]` wrappers and metadata
+//!
+//! Off wasm the imports link against C symbols, which these tests define
+//! the way the JS glue's stubs implement them.
+
+use wasm::host::import::read_imports;
+use wasm::wasmir::{ElementType, InteropSignature, InteropType};
+
+#[wasm::import("env", "wasmrust_test_weigh")]
+fn weigh(values: &[f64], scale: u8, negate: bool) -> f64;
+
+#[wasm::import("env", "wasmrust_test_shout")]
+fn shout(message: &str) -> String;
+
+#[wasm::import("canvas", "wasmrust_test_fill")]
+fn fill(pixels: &mut [u32], color: u32) -> bool;
+
+#[wasm::import("env")]
+fn wasmrust_test_squares(count: u32) -> Vec<i64>;
+
+#[no_mangle]
+unsafe extern "C" fn wasmrust_test_weigh(ptr: *const f64, len: usize, scale: u32, negate: u32) -> f64 {
+    let sum: f64 = std::slice::from_raw_parts(ptr, len).iter().sum();
+    let weighed = sum * f64::from(scale);
+    if negate != 0 {
+        -weighed
+    } else {
+        weighed
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn wasmrust_test_shout(retptr: *mut usize, ptr: *const u8, len: usize) {
+    let message = std::str::from_utf8(std::slice::from_raw_parts(ptr, len)).unwrap();
+    give(retptr, message.to_uppercase().into_bytes());
+}
+
+#[no_mangle]
+unsafe extern "C" fn wasmrust_test_fill(ptr: *mut u32, len: usize, color: u32) -> u32 {
+    std::slice::from_raw_parts_mut(ptr, len).fill(color);
+    u32::from(len > 0)
+}
+
+#[export_name = "wasmrust_test_squares"]
+unsafe extern "C" fn host_squares(retptr: *mut usize, count: u32) {
+    give(retptr, (0..i64::from(count)).map(|n| n * n).collect());
+}
+
+/// Hands a buffer to the module as the host's allocator would
+unsafe fn give<T>(retptr: *mut usize, value: Vec<T>) {
+    let value = value.into_boxed_slice();
+    let len = value.len();
+    retptr.write(Box::into_raw(value) as *mut T as usize);
+    retptr.add(1).write(len);
+}
+
+#[test]
+fn test_wrappers_lower_arguments_and_take_results() {
+    assert_eq!(weigh(&[1.5, 2.5], 3, true), -12.0);
+    assert_eq!(shout("hello"), "HELLO");
+    let mut pixels = [0u32; 3];
+    assert!(fill(&mut pixels, 0xff00ff));
+    assert_eq!(pixels, [0xff00ff; 3]);
+    assert!(!fill(&mut [], 1));
+    assert_eq!(wasmrust_test_squares(4), [0, 1, 4, 9]);
+}
+
+#[test]
+fn test_records_describe_imports() {
+    let mut section = Vec::new();
+    for record in [&__WASM_IMPORT_WEIGH[..], &__WASM_IMPORT_SHOUT, &__WASM_IMPORT_FILL, &__WASM_IMPORT_WASMRUST_TEST_SQUARES]
+    {
+        section.extend_from_slice(record);
+    }
+    let imports = read_imports(&section).unwrap();
+
+    let names: Vec<_> = imports.iter().map(|import| (import.module.as_str(), import.name.as_str())).collect();
+    assert_eq!(names, [
+        ("env", "wasmrust_test_weigh"),
+        ("env", "wasmrust_test_shout"),
+        ("canvas", "wasmrust_test_fill"),
+        ("env", "wasmrust_test_squares"),
+    ]);
+    assert_eq!(imports[0].signature, InteropSignature {
+        params: vec![InteropType::Slice(ElementType::F64), InteropType::Value, InteropType::Value],
+        returns: InteropType::Value,
+    });
+    assert_eq!(imports[1].signature, InteropSignature { params: vec![InteropType::Str], returns: InteropType::Str });
+    assert_eq!(imports[2].signature.params[0], InteropType::SliceMut(ElementType::U32));
+    assert_eq!(imports[3].signature.returns, InteropType::Vec(ElementType::I64));
+    assert_eq!(imports[3].signature.lowered_param_count(), 2);
+}
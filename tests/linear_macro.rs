@@ -0,0 +1,50 @@
+//! `#[wasm::linear]` single use
+//!
+//! Linear values are used up through `consume` or by moving them on;
+//! dropping one trips its drop bomb in debug builds.
+
+use wasm::linear::Linear;
+
+#[wasm::linear]
+#[derive(Clone, Debug, PartialEq)]
+struct Canvas {
+    handle: u32,
+    label: String,
+}
+
+#[wasm::linear]
+struct Token(u64);
+
+#[wasm::linear]
+struct Session<T> {
+    id: u32,
+    state: T,
+}
+
+fn assert_linear<T: Linear>() {}
+
+/// Consuming method of the kind linear types define on top of `consume`
+impl Canvas {
+    fn into_label(self) -> String {
+        self.consume().1
+    }
+}
+
+#[test]
+fn test_consume_uses_values_up() {
+    assert_linear::<Canvas>();
+    assert_linear::<Session<Vec<u8>>>();
+    let canvas = Canvas { handle: 3, label: "main".to_string() };
+    assert_eq!(format!("{:?}", canvas), "Canvas { handle: 3, label: \"main\" }");
+    assert_eq!(canvas.into_label(), "main");
+    assert_eq!(Token(7).consume(), 7);
+    let (id, state) = Session { id: 1, state: vec![1u8, 2] }.consume();
+    assert_eq!((id, state), (1, vec![1, 2]));
+}
+
+#[test]
+#[cfg_attr(debug_assertions, should_panic(expected = "was dropped without being consumed"))]
+fn test_implicit_drop_panics_in_debug() {
+    let token = Token(1);
+    drop(token);
+}
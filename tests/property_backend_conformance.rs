@@ -0,0 +1,194 @@
+//! Property-based cross-backend conformance tests
+//!
+//! Generates random WasmIR functions and arguments, runs them on the
+//! interpreter and on every in-process runtime compiled in, and checks that
+//! all engines agree on each result and on which calls trap.
+//!
+//! Property: Backend Conformance
+//! Without the `wasmtime` or `wasmer` feature only the interpreter runs, so
+//! the property reduces to the interpreter executing every generated call.
+//! The codegen backends (Cranelift, and LLVM with `llvm-backend`) run in a
+//! separate property, since they need a runtime to load what they emit.
+
+use proptest::prelude::*;
+use wasm::wasmir::{
+    BinaryOp, BlockId, Constant, Instruction, Operand, Signature, Terminator, Type, UnaryOp, WasmIR, WasmModule,
+};
+use wasmrust_compiler::backend::conformance::ConformanceSuite;
+use wasmrust_compiler::host::runtime::{Invocation, Value};
+
+/// Numeric type of a generated function
+#[derive(Debug, Clone, Copy)]
+enum NumType {
+    I32,
+    I64,
+    F64,
+}
+
+impl NumType {
+    fn wasmir_type(self) -> Type {
+        match self {
+            NumType::I32 => Type::I32,
+            NumType::I64 => Type::I64,
+            NumType::F64 => Type::F64,
+        }
+    }
+
+    fn binary_ops(self) -> Vec<BinaryOp> {
+        use BinaryOp::*;
+        match self {
+            // Comparisons yield i32, so they only keep i32 chains well typed
+            NumType::I32 => vec![Add, Sub, Mul, Div, Mod, And, Or, Xor, Shl, Shr, Sar, Eq, Ne, Lt, Le, Gt, Ge],
+            NumType::I64 => vec![Add, Sub, Mul, Div, Mod, And, Or, Xor, Shl, Shr, Sar],
+            NumType::F64 => vec![Add, Sub, Mul, Div],
+        }
+    }
+
+    fn unary_ops(self) -> Vec<UnaryOp> {
+        use UnaryOp::*;
+        match self {
+            NumType::I32 | NumType::I64 => vec![Neg, Not, Clz, Ctz, Popcnt],
+            NumType::F64 => vec![Neg],
+        }
+    }
+
+    fn value(self) -> BoxedStrategy<Value> {
+        let edge_i32 = prop_oneof![Just(0), Just(-1), Just(1), Just(i32::MIN), Just(i32::MAX), Just(31), Just(32)];
+        let edge_i64 = prop_oneof![Just(0), Just(-1), Just(1), Just(i64::MIN), Just(i64::MAX), Just(63), Just(64)];
+        match self {
+            NumType::I32 => prop_oneof![edge_i32, any::<i32>()].prop_map(Value::I32).boxed(),
+            NumType::I64 => prop_oneof![edge_i64, any::<i64>()].prop_map(Value::I64).boxed(),
+            NumType::F64 => prop_oneof![Just(0.0), Just(-0.0), Just(f64::INFINITY), any::<f64>()]
+                .prop_map(Value::F64)
+                .boxed(),
+        }
+    }
+}
+
+/// Operand folded into the running value
+#[derive(Debug, Clone)]
+enum Source {
+    Param(u32),
+    Constant(Value),
+}
+
+impl Source {
+    fn operand(&self) -> Operand {
+        match self {
+            Source::Param(index) => Operand::Local(*index),
+            Source::Constant(Value::I32(v)) => Operand::Constant(Constant::I32(*v)),
+            Source::Constant(Value::I64(v)) => Operand::Constant(Constant::I64(*v)),
+            Source::Constant(Value::F32(v)) => Operand::Constant(Constant::F32(*v)),
+            Source::Constant(Value::F64(v)) => Operand::Constant(Constant::F64(*v)),
+        }
+    }
+}
+
+/// Step applied to the value on top of the stack
+#[derive(Debug, Clone)]
+enum Step {
+    Binary(BinaryOp, Source),
+    Unary(UnaryOp),
+}
+
+/// Generated function and the calls made to it
+#[derive(Debug, Clone)]
+struct Program {
+    ty: NumType,
+    steps: Vec<Step>,
+    /// Whether the result picks which parameter to return
+    branch: bool,
+    calls: Vec<(Value, Value)>,
+}
+
+impl Program {
+    fn module(&self) -> WasmModule {
+        let ty = self.ty.wasmir_type();
+        let mut function = WasmIR::new("f".to_string(), Signature {
+            params: vec![ty.clone(), ty.clone()],
+            returns: Some(ty.clone()),
+        });
+        function.locals = vec![ty.clone(), ty];
+        let mut body = vec![Instruction::LocalGet { index: 0 }];
+        for step in &self.steps {
+            body.push(match step {
+                Step::Binary(op, source) => {
+                    Instruction::BinaryOp { op: *op, left: Operand::StackValue(0), right: source.operand() }
+                }
+                Step::Unary(op) => Instruction::UnaryOp { op: *op, value: Operand::StackValue(0) },
+            });
+        }
+        if self.branch {
+            function.add_basic_block(body, Terminator::Branch {
+                condition: Operand::StackValue(0),
+                then_block: BlockId(1),
+                else_block: BlockId(2),
+            });
+            function.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Local(0)) });
+            function.add_basic_block(vec![], Terminator::Return { value: Some(Operand::Local(1)) });
+        } else {
+            function.add_basic_block(body, Terminator::Return { value: Some(Operand::StackValue(0)) });
+        }
+
+        let mut module = WasmModule::new();
+        let index = module.add_function(function);
+        module.export_function("f", index);
+        module
+    }
+
+    fn calls(&self) -> Vec<Invocation> {
+        self.calls.iter().map(|(a, b)| Invocation::new("f", vec![*a, *b])).collect()
+    }
+}
+
+fn step(ty: NumType) -> impl Strategy<Value = Step> {
+    let source = prop_oneof![(0u32..2).prop_map(Source::Param), ty.value().prop_map(Source::Constant)];
+    prop_oneof![
+        3 => (proptest::sample::select(ty.binary_ops()), source).prop_map(|(op, source)| Step::Binary(op, source)),
+        1 => proptest::sample::select(ty.unary_ops()).prop_map(Step::Unary),
+    ]
+}
+
+fn program() -> impl Strategy<Value = Program> {
+    prop_oneof![Just(NumType::I32), Just(NumType::I64), Just(NumType::F64)].prop_flat_map(|ty| {
+        (
+            proptest::collection::vec(step(ty), 0..12),
+            // Float conditions are not valid branch operands
+            any::<bool>().prop_map(move |branch| branch && !matches!(ty, NumType::F64)),
+            proptest::collection::vec((ty.value(), ty.value()), 1..8),
+        )
+            .prop_map(move |(steps, branch, calls)| Program { ty, steps, branch, calls })
+    })
+}
+
+proptest! {
+    #[test]
+    fn prop_backends_agree_on_generated_functions(program in program()) {
+        let report = ConformanceSuite::with_available_runtimes().run(&program.module(), &program.calls());
+        prop_assert!(report.failures.is_empty(), "{:?}\n{}", program, report);
+        prop_assert!(report.is_conformant(), "{:?}\n{}", program, report);
+    }
+
+    #[test]
+    #[cfg_attr(feature = "llvm-backend", ignore = "the LLVM backend emits native code, which no runtime can load")]
+    fn prop_codegen_backends_agree_on_generated_functions(program in program()) {
+        let report = ConformanceSuite::with_available_backends().run(&program.module(), &program.calls());
+        prop_assert!(report.failures.is_empty(), "{:?}\n{}", program, report);
+        prop_assert!(report.is_conformant(), "{:?}\n{}", program, report);
+    }
+}
+
+#[test]
+fn test_shift_amounts_wrap_on_every_engine() {
+    let program = Program {
+        ty: NumType::I32,
+        steps: vec![
+            Step::Binary(BinaryOp::Shl, Source::Param(1)),
+            Step::Binary(BinaryOp::Sar, Source::Constant(Value::I32(33))),
+        ],
+        branch: false,
+        calls: vec![(Value::I32(-3), Value::I32(34)), (Value::I32(i32::MIN), Value::I32(-1))],
+    };
+    let report = ConformanceSuite::with_available_runtimes().run(&program.module(), &program.calls());
+    assert!(report.is_conformant(), "{}", report);
+}
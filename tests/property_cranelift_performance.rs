@@ -6,7 +6,7 @@
 //! Property 3: Cranelift Performance Advantage
 //! Validates: Requirements 2.2
 
-use wasm::backend::{BackendFactory, BuildProfile, CompilationResult};
+use wasm::backend::{BackendCapabilities, BackendFactory, BuildProfile, CompilationResult};
 use wasm::backend::cranelift::WasmRustCraneliftBackend;
 use wasm::wasmir::{WasmIR, Signature, Type, Instruction, Terminator, Operand, BinaryOp};
 use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
@@ -816,7 +816,7 @@ mod tests {
         complexity: &FunctionComplexity,
         profile: BuildProfile,
     ) -> Result<(Duration, usize), Box<dyn std::error::Error>> {
-        let mut backend = BackendFactory::create_backend("wasm32", profile)?;
+        let mut backend = BackendFactory::create_backend("wasm32", profile, &BackendCapabilities::default())?.backend;
         let func = create_function_with_complexity(
             &mut backend.as_any().downcast_mut::<WasmRustCraneliftBackend>()
                 .ok_or("Backend is not Cranelift")?,
@@ -0,0 +1,94 @@
+//! Self-hosting smoke target
+//!
+//! Compiles the `wasm` crate's own pure-Rust modules (the WasmIR module and
+//! the component model tooling) to `wasm32-wasip1` through the WasmRust
+//! codegen backend with the Freestanding profile, with default features off
+//! so nothing links `std` beyond the test harness, and runs their unit tests
+//! on the embedded wasmtime runtime. Real code exercises far more of the
+//! lowering than hand-written fixtures.
+//!
+//! The target needs the backend built as a rustc codegen backend library,
+//! named by `WASMRUST_CODEGEN_BACKEND` and passed to `-Zcodegen-backend`.
+//! It is ignored by default; run it with `--ignored` once the library is
+//! built, and it fails if the variable is missing.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use wasmrust_compiler::backend::BuildProfile;
+use wasmrust_compiler::host::runtime::Wasmtime;
+
+const TARGET: &str = "wasm32-wasip1";
+
+/// Test filters selecting the self-hosted modules
+const MODULES: &[&str] = &["wasmir::", "component::"];
+
+/// Tests that fail on the host as well, so say nothing about the backend
+const KNOWN_FAILURES: &[&str] = &["wasmir::tests::test_instruction_count"];
+
+/// Paths of the test executables in cargo's JSON messages
+fn test_executables(messages: &str) -> Vec<PathBuf> {
+    const KEY: &str = "\"executable\":\"";
+    messages.lines()
+        .filter(|line| line.contains("\"reason\":\"compiler-artifact\""))
+        .filter_map(|line| {
+            let start = line.find(KEY)? + KEY.len();
+            let end = start + line[start..].find('"')?;
+            Some(PathBuf::from(&line[start..end]))
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "needs the codegen backend library named by WASMRUST_CODEGEN_BACKEND"]
+fn self_hosted_unit_tests_pass() {
+    let backend = env::var_os("WASMRUST_CODEGEN_BACKEND")
+        .expect("WASMRUST_CODEGEN_BACKEND must name the codegen backend library");
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../crates/wasm/Cargo.toml");
+    // A separate target directory keeps the nested build off the outer build's lock
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("self-hosting");
+
+    let build = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .args(["test", "--lib", "--no-run", "--no-default-features", "--message-format=json", "--target", TARGET])
+        .arg("--manifest-path").arg(&manifest)
+        .arg("--target-dir").arg(&target_dir)
+        .env("RUSTFLAGS", format!("-Zcodegen-backend={}", backend.to_string_lossy()))
+        .env(BuildProfile::ENV, BuildProfile::Freestanding.name())
+        .output()
+        .expect("failed to run cargo");
+    let messages = String::from_utf8_lossy(&build.stdout);
+    assert!(build.status.success(), "self-hosted build failed:\n{}", String::from_utf8_lossy(&build.stderr));
+    let executables = test_executables(&messages);
+    assert!(!executables.is_empty(), "no test executable was built:\n{}", messages);
+
+    let mut args: Vec<String> = MODULES.iter().map(|module| module.to_string()).collect();
+    for test in KNOWN_FAILURES {
+        args.extend(["--skip".to_string(), test.to_string()]);
+    }
+    let runtime = Wasmtime::new();
+    for executable in executables {
+        let wasm = fs::read(&executable).expect("failed to read the test executable");
+        let output = runtime.run_command(&wasm, &args)
+            .unwrap_or_else(|e| panic!("{} did not run: {}", executable.display(), e));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(
+            output.exit_code, 0,
+            "self-hosted tests failed:\n{}\n{}",
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(stdout.contains("test result: ok"), "no tests ran:\n{}", stdout);
+    }
+}
+
+#[test]
+fn test_finds_executables_in_cargo_messages() {
+    let messages = [
+        r#"{"reason":"compiler-artifact","target":{"name":"wasm"},"executable":null}"#,
+        r#"{"reason":"compiler-artifact","target":{"name":"wasm"},"executable":"/t/wasm-1a2b.wasm"}"#,
+        r#"{"reason":"build-finished","success":true}"#,
+    ]
+    .join("\n");
+    assert_eq!(test_executables(&messages), vec![PathBuf::from("/t/wasm-1a2b.wasm")]);
+}